@@ -1,11 +1,13 @@
 extern crate cc;
 extern crate pkg_config;
+extern crate wayland_scanner;
 
 use std::{env, fs, io::Write, path::Path, process::Command};
 
 fn main() {
     dump_git_version();
     build_wayland_glib_interface();
+    generate_protocols();
 }
 
 /// Writes the current git hash to a file that is read by Way Cooler
@@ -71,3 +73,21 @@ fn build_wayland_glib_interface() {
         .file("src/wayland_glib_interface.c")
         .compile("wayland_glib_interface");
 }
+
+/// Generates the client side of the protocols that aren't in
+/// wayland-protocols.
+fn generate_protocols() {
+    let out_dir = env::var("OUT_DIR").expect("Could not find out directory!");
+    for name in &[
+        "color-management-v1",
+        "virtual-keyboard-unstable-v1",
+        "wlr-layer-shell-unstable-v1"
+    ] {
+        let protocol = format!("../protocols/{}.xml", name);
+        wayland_scanner::generate_code(
+            &protocol,
+            Path::new(&out_dir).join(format!("{}_client_api.rs", name)),
+            wayland_scanner::Side::Client
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! Drawins and the regions in them as accessible objects, so screen readers
+//! can say what a bar shows, like "Battery, 45 percent".
+//!
+//! `drawin.accessible = { role = "status_bar", label = "Main bar" }` makes
+//! a drawin accessible, and `drawin:set_accessible_regions` declares the
+//! parts of it, each a role, a label, an optional value and where it is on
+//! the drawin. Drawins tell this module when they move, are shown or are
+//! hidden, and when they're removed.
+//!
+//! With the `accessibility` feature the objects are exported over AT-SPI by
+//! `atspi`, which doesn't touch a bus until a drawin is accessible.
+//! Without it Lua can still set them, they're just not exported.
+
+#[cfg(feature = "accessibility")]
+pub mod atspi;
+mod role;
+#[cfg_attr(not(feature = "accessibility"), allow(dead_code))]
+mod tree;
+
+use std::cell::RefCell;
+
+use rlua::{self, prelude::LuaInteger, Table, Value};
+
+use crate::area::{self, Area, Origin, Size};
+
+pub use self::role::Role;
+pub use self::tree::{Accessible, Region};
+
+use self::tree::{Event, Tree};
+
+thread_local! {
+    static TREE: RefCell<Tree> = RefCell::new(Tree::default());
+}
+
+/// Makes the drawin `id` accessible, changes what it is, or makes it
+/// inaccessible with `None`.
+pub fn set_accessible(id: usize, accessible: Option<Accessible>, geometry: Area, showing: bool) {
+    let events = TREE.with(|tree| {
+        tree.borrow_mut()
+            .set_accessible(id, accessible, geometry, showing)
+    });
+    changed(events)
+}
+
+pub fn accessible(id: usize) -> Option<Accessible> {
+    TREE.with(|tree| tree.borrow().accessible(id).cloned())
+}
+
+/// Replaces the regions of the drawin `id`, false if it isn't accessible.
+pub fn set_regions(id: usize, regions: Vec<Region>) -> bool {
+    match TREE.with(|tree| tree.borrow_mut().set_regions(id, regions)) {
+        Some(events) => {
+            changed(events);
+            true
+        },
+        None => false
+    }
+}
+
+/// The drawin `id` moved, was resized, or was shown or hidden.
+pub fn moved(id: usize, geometry: Area, showing: bool) {
+    let events = TREE.with(|tree| tree.borrow_mut().moved(id, geometry, showing));
+    changed(events)
+}
+
+/// The drawin `id` is gone.
+pub fn removed(id: usize) {
+    set_accessible(id, None, Area::default(), false)
+}
+
+fn changed(events: Vec<Event>) {
+    if events.is_empty() {
+        return;
+    }
+    #[cfg(feature = "accessibility")]
+    atspi::publish(&events);
+}
+
+/// Reads `{ role = "status_bar", label = "Main bar" }`, with errors
+/// starting with `context`.
+pub fn accessible_from_lua(table: &Table, context: &str) -> rlua::Result<Accessible> {
+    Ok(Accessible {
+        role: role_from_lua(table, context)?,
+        label: label_from_lua(table, context)?
+    })
+}
+
+pub fn accessible_to_lua<'lua>(
+    lua: rlua::Context<'lua>,
+    accessible: &Accessible
+) -> rlua::Result<Table<'lua>> {
+    let table = lua.create_table()?;
+    table.set("role", accessible.role.name())?;
+    table.set("label", accessible.label.as_str())?;
+    Ok(table)
+}
+
+/// Reads `{ role = "progress_bar", label = "Battery", value = "45 percent",
+/// x = 1700, y = 0, width = 100, height = 30 }`, where the area is on the
+/// drawin, with errors starting with `context`.
+pub fn region_from_lua(table: &Table, context: &str) -> rlua::Result<Region> {
+    let value = match table.get::<_, Value>("value")? {
+        Value::Nil => None,
+        Value::String(value) => Some(value.to_str()?.to_string()),
+        _ => return Err(error(context, "value must be a string or nil"))
+    };
+    let integer = |name: &str| -> rlua::Result<LuaInteger> {
+        match table.get::<_, Value>(name)? {
+            Value::Integer(value) => Ok(value),
+            Value::Number(value) if value.fract() == 0.0 && value.abs() < 2f64.powi(53) => {
+                Ok(value as LuaInteger)
+            },
+            _ => Err(error(context, &format!("{} must be an integer", name)))
+        }
+    };
+    let coordinate = |name: &str| {
+        area::checked_coordinate(integer(name)?).map_err(|err| error(context, &format!("{}: {}", name, err)))
+    };
+    let length = |name: &str| {
+        area::checked_length(integer(name)?).map_err(|err| error(context, &format!("{}: {}", name, err)))
+    };
+    Ok(Region {
+        role: role_from_lua(table, context)?,
+        label: label_from_lua(table, context)?,
+        value,
+        area: Area {
+            origin: Origin {
+                x: coordinate("x")?,
+                y: coordinate("y")?
+            },
+            size: Size {
+                width: length("width")?,
+                height: length("height")?
+            }
+        }
+    })
+}
+
+fn role_from_lua(table: &Table, context: &str) -> rlua::Result<Role> {
+    let role = match table.get::<_, Value>("role")? {
+        Value::String(name) => name.to_str().ok().and_then(Role::from_name),
+        _ => None
+    };
+    role.ok_or_else(|| {
+        let names = Role::NAMES
+            .iter()
+            .map(|name| format!("{:?}", name))
+            .collect::<Vec<_>>();
+        error(context, &format!("role must be one of {}", names.join(", ")))
+    })
+}
+
+fn label_from_lua(table: &Table, context: &str) -> rlua::Result<String> {
+    match table.get::<_, Value>("label")? {
+        Value::String(label) => Ok(label.to_str()?.to_string()),
+        _ => Err(error(context, "label must be a string"))
+    }
+}
+
+fn error(context: &str, message: &str) -> rlua::Error {
+    rlua::Error::RuntimeError(format!("{}: {}", context, message))
+}
@@ -0,0 +1,928 @@
+//! Exports the accessible objects over AT-SPI, the accessibility protocol
+//! screen readers like Orca use, on the accessibility bus.
+//!
+//! Nothing is sent until a drawin is made accessible. Then the client
+//! connects to the accessibility bus, whose address is in
+//! `AT_SPI_BUS_ADDRESS` or given by `org.a11y.Bus` on the session bus, and
+//! embeds itself into the registry, which makes it a child of the desktop
+//! screen readers walk. Without a bus nothing more is tried. Without a
+//! registry the client waits for one to appear. Once embedded, it answers
+//! what the registry and screen readers ask and tells them what changed.
+//!
+//! Only what's needed to announce objects is answered: `Accessible`,
+//! `Component`, `Value`, `Application` and their properties. Nothing can be
+//! focused or activated yet.
+
+use std::{cell::RefCell, collections::HashMap, env};
+
+use ::dbus::{
+    arg::{RefArg, TypeMismatchError, Variant},
+    Message, MessageType, Path
+};
+
+use super::tree::{Event, Object, Property, Tree, ROOT_PATH};
+use super::TREE;
+use crate::area::{Area, Origin};
+use crate::dbus::{self, Bus};
+
+const REGISTRY: &str = "org.a11y.atspi.Registry";
+const ACCESSIBLE: &str = "org.a11y.atspi.Accessible";
+const APPLICATION: &str = "org.a11y.atspi.Application";
+const COMPONENT: &str = "org.a11y.atspi.Component";
+const VALUE: &str = "org.a11y.atspi.Value";
+const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+const EVENTS: &str = "org.a11y.atspi.Event.Object";
+
+/// Where a reference to no object points.
+const NULL_PATH: &str = "/org/a11y/atspi/null";
+
+/// How long to wait for the session bus to say where the accessibility bus
+/// is.
+const ADDRESS_TIMEOUT_MS: i32 = 2_000;
+
+/// `ATSPI_ROLE_APPLICATION`.
+const ROLE_APPLICATION: u32 = 75;
+
+/// The `AtspiStateType`s an object can be in.
+const STATE_DEFUNCT: u32 = 6;
+const STATE_ENABLED: u32 = 8;
+const STATE_SENSITIVE: u32 = 24;
+const STATE_SHOWING: u32 = 25;
+const STATE_VISIBLE: u32 = 30;
+
+/// `ATSPI_LAYER_WIDGET` and `ATSPI_LAYER_WINDOW`.
+const LAYER_WIDGET: u32 = 3;
+const LAYER_WINDOW: u32 = 7;
+
+/// The coordinate types of `Component`, what extents are relative to.
+const COORDS_SCREEN: u32 = 0;
+const COORDS_WINDOW: u32 = 1;
+
+/// Values are read as a number from 0 to 100 from the start of their text,
+/// like "45 percent".
+const MAXIMUM_VALUE: f64 = 100.0;
+
+thread_local! {
+    static EXPORT: RefCell<Export> = RefCell::new(Export::default());
+}
+
+/// An object on the bus: a bus name and a path.
+type Reference = (String, Path<'static>);
+
+#[derive(Debug, Clone, PartialEq)]
+enum State {
+    /// Nothing was tried yet, or the bus went away.
+    Stopped,
+    /// There's no accessibility bus, so no screen reader.
+    Unavailable,
+    /// The bus is there but the registry isn't.
+    WaitingForRegistry,
+    /// Embedding, with the serial of the call.
+    Embedding(u32),
+    /// The application is a child of the desktop of the registry.
+    Embedded { desktop: Reference }
+}
+
+#[derive(Debug)]
+struct Export {
+    state: State,
+    /// The unique name of the connection to the accessibility bus.
+    name: String,
+    /// The id the registry gave the application.
+    id: i32
+}
+
+impl Default for Export {
+    fn default() -> Self {
+        Export {
+            state: State::Stopped,
+            name: String::new(),
+            id: 0
+        }
+    }
+}
+
+/// Where the accessibility bus is, if there is one.
+pub fn bus_address() -> Result<String, ::dbus::Error> {
+    if let Ok(address) = env::var("AT_SPI_BUS_ADDRESS") {
+        if !address.is_empty() {
+            return Ok(address);
+        }
+    }
+    dbus::with_connection(Bus::Session, |connection| {
+        let has_owner = Message::new_method_call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "NameHasOwner"
+        )
+        .unwrap()
+        .append1("org.a11y.Bus");
+        let running: bool = connection
+            .send_with_reply_and_block(has_owner, ADDRESS_TIMEOUT_MS)?
+            .read1()
+            .map_err(mismatch)?;
+        if !running {
+            return Err(::dbus::Error::new_custom(
+                "org.freedesktop.DBus.Error.ServiceUnknown",
+                "org.a11y.Bus isn't running"
+            ));
+        }
+        let get_address =
+            Message::new_method_call("org.a11y.Bus", "/org/a11y/bus", "org.a11y.Bus", "GetAddress").unwrap();
+        connection
+            .send_with_reply_and_block(get_address, ADDRESS_TIMEOUT_MS)?
+            .read1()
+            .map_err(mismatch)
+    })?
+}
+
+fn mismatch(err: TypeMismatchError) -> ::dbus::Error {
+    ::dbus::Error::new_custom("org.freedesktop.DBus.Error.InvalidArgs", &err.to_string())
+}
+
+/// Tells screen readers what changed, connecting first if nothing was
+/// tried yet.
+pub fn publish(events: &[Event]) {
+    let signals = EXPORT.with(|export| {
+        let mut export = export.borrow_mut();
+        match export.state {
+            State::Stopped => {
+                start(&mut export);
+                Vec::new()
+            },
+            State::Embedded { .. } => TREE.with(|tree| {
+                let view = View {
+                    tree: &tree.borrow(),
+                    export: &export
+                };
+                events.iter().flat_map(|event| view.signals(event)).collect()
+            }),
+            _ => Vec::new()
+        }
+    });
+    for signal in signals {
+        send(signal);
+    }
+}
+
+/// Handles what arrived on the accessibility bus, waiting up to
+/// `timeout_ms` for something to arrive.
+pub fn dispatch(timeout_ms: u32) {
+    for message in dbus::receive(Bus::Accessibility, timeout_ms) {
+        match message.msg_type() {
+            MessageType::MethodCall => {
+                let reply = answer(&message);
+                if !message.get_no_reply() {
+                    send(reply);
+                }
+            },
+            MessageType::MethodReturn | MessageType::Error => embedded(message),
+            MessageType::Signal => handle_signal(&message),
+            MessageType::Invalid => {}
+        }
+    }
+}
+
+/// Connects to the accessibility bus and embeds the application into the
+/// registry.
+fn start(export: &mut Export) {
+    let rule = format!(
+        "type='signal',sender='org.freedesktop.DBus',interface='org.freedesktop.DBus',\
+         member='NameOwnerChanged',arg0='{}'",
+        REGISTRY
+    );
+    let connected = dbus::with_connection(Bus::Accessibility, |connection| {
+        connection.add_match(&rule)?;
+        Ok(connection.unique_name())
+    });
+    match connected.and_then(|connected| connected) {
+        Ok(name) => {
+            export.name = name;
+            embed(export);
+        },
+        Err(err) => {
+            info!(
+                "Not exporting accessible drawins, there's no accessibility bus: {}",
+                err.message().unwrap_or("")
+            );
+            export.state = State::Unavailable;
+        }
+    }
+}
+
+fn embed(export: &mut Export) {
+    let root = (export.name.clone(), Path::new(ROOT_PATH).unwrap());
+    let message = Message::new_method_call(REGISTRY, ROOT_PATH, "org.a11y.atspi.Socket", "Embed")
+        .unwrap()
+        .append1(root);
+    // Starting the registry is up to the desktop.
+    message.set_auto_start(false);
+    export.state = match dbus::with_connection(Bus::Accessibility, |connection| connection.send(message)) {
+        Ok(Ok(serial)) => State::Embedding(serial),
+        _ => {
+            warn!("Could not embed the accessible drawins into the registry");
+            State::Unavailable
+        }
+    };
+}
+
+/// Handles the reply of the registry to `embed`.
+fn embedded(mut reply: Message) {
+    EXPORT.with(|export| {
+        let mut export = export.borrow_mut();
+        match export.state {
+            State::Embedding(serial) if reply.get_reply_serial() == Some(serial) => {},
+            _ => return
+        }
+        export.state = match reply.as_result() {
+            Ok(reply) => match reply.read1::<(String, Path)>() {
+                Ok((name, path)) => {
+                    info!("Exporting accessible drawins to {}", name);
+                    State::Embedded {
+                        desktop: (name, path.into_static())
+                    }
+                },
+                Err(err) => {
+                    warn!("The registry embedded the accessible drawins oddly: {}", err);
+                    State::Unavailable
+                }
+            },
+            Err(err) => {
+                info!(
+                    "Waiting for an accessibility registry: {}",
+                    err.message().unwrap_or("")
+                );
+                State::WaitingForRegistry
+            }
+        };
+    })
+}
+
+fn handle_signal(message: &Message) {
+    let (_, _, interface, member) = message.headers();
+    match (
+        interface.unwrap_or_default().as_str(),
+        member.unwrap_or_default().as_str()
+    ) {
+        ("org.freedesktop.DBus.Local", "Disconnected") => {
+            warn!("Disconnected from the accessibility bus");
+            dbus::disconnect(Bus::Accessibility);
+            EXPORT.with(|export| export.borrow_mut().state = State::Stopped);
+        },
+        ("org.freedesktop.DBus", "NameOwnerChanged") => {
+            let owner = match message.read3::<&str, &str, &str>() {
+                Ok((name, _, owner)) if name == REGISTRY => owner,
+                _ => return
+            };
+            EXPORT.with(|export| {
+                let mut export = export.borrow_mut();
+                // A registry that restarted forgot the application.
+                if !owner.is_empty() && export.state != State::Unavailable {
+                    embed(&mut export);
+                }
+            });
+        },
+        _ => {}
+    }
+}
+
+fn send(message: Message) {
+    match dbus::with_connection(Bus::Accessibility, |connection| connection.send(message)) {
+        Ok(Ok(_)) => {},
+        _ => warn!("Could not send a message on the accessibility bus")
+    }
+}
+
+/// Answers a method call to an accessible object.
+fn answer(call: &Message) -> Message {
+    let (_, path, interface, member) = call.headers();
+    let (interface, member) = (interface.unwrap_or_default(), member.unwrap_or_default());
+    let object = path.as_ref().and_then(|path| Object::from_path(path));
+    let answered = EXPORT.with(|export| {
+        let mut export = export.borrow_mut();
+        TREE.with(|tree| {
+            let tree = tree.borrow();
+            let object = match object.filter(|&object| tree.exists(object)) {
+                Some(object) => object,
+                None => {
+                    return Err((
+                        "org.freedesktop.DBus.Error.UnknownObject",
+                        format!("no accessible object at {}", path.unwrap_or_default())
+                    ))
+                },
+            };
+            if (interface.as_str(), member.as_str()) == (PROPERTIES, "Set") {
+                return set_property(&mut export, call, object);
+            }
+            let view = View {
+                tree: &tree,
+                export: &export
+            };
+            view.answer(call, object, &interface, &member)
+        })
+    });
+    answered.unwrap_or_else(|(name, message)| Message::new_error(call, name, &message).unwrap())
+}
+
+/// Why a method call failed: the name of the error and a message.
+type Failure = (&'static str, String);
+
+fn invalid_args(err: TypeMismatchError) -> Failure {
+    ("org.freedesktop.DBus.Error.InvalidArgs", err.to_string())
+}
+
+/// Only the id of the application can be set, by the registry.
+fn set_property(export: &mut Export, call: &Message, object: Object) -> Result<Message, Failure> {
+    let (interface, name, value) = call
+        .read3::<&str, &str, Variant<Box<RefArg>>>()
+        .map_err(invalid_args)?;
+    match (object, interface, name, value.0.as_i64()) {
+        (Object::Root, APPLICATION, "Id", Some(id)) => {
+            export.id = id as i32;
+            Ok(call.method_return())
+        },
+        _ => Err((
+            "org.freedesktop.DBus.Error.PropertyReadOnly",
+            format!("{}.{} can't be set", interface, name)
+        ))
+    }
+}
+
+/// The accessible objects as they're exported.
+struct View<'a> {
+    tree: &'a Tree,
+    export: &'a Export
+}
+
+impl<'a> View<'a> {
+    fn reference(&self, object: Object) -> Reference {
+        (self.export.name.clone(), Path::new(object.path()).unwrap())
+    }
+
+    fn null() -> Reference {
+        (String::new(), Path::new(NULL_PATH).unwrap())
+    }
+
+    fn parent(&self, object: Object) -> Reference {
+        match (self.tree.parent(object), &self.export.state) {
+            (Some(parent), _) => self.reference(parent),
+            (None, State::Embedded { desktop }) => desktop.clone(),
+            (None, _) => View::null()
+        }
+    }
+
+    fn interfaces(&self, object: Object) -> Vec<&'static str> {
+        match object {
+            Object::Root => vec![ACCESSIBLE, APPLICATION],
+            Object::Drawin(_) => vec![ACCESSIBLE, COMPONENT],
+            Object::Region(..) if self.tree.value(object).is_some() => vec![ACCESSIBLE, COMPONENT, VALUE],
+            Object::Region(..) => vec![ACCESSIBLE, COMPONENT]
+        }
+    }
+
+    fn name(&self, object: Object) -> String {
+        match object {
+            Object::Root => "way-cooler".into(),
+            object => self.tree.name(object).unwrap_or_default().into()
+        }
+    }
+
+    fn role(&self, object: Object) -> u32 {
+        self.tree
+            .role(object)
+            .map_or(ROLE_APPLICATION, |role| role.atspi())
+    }
+
+    fn role_name(&self, object: Object) -> String {
+        self.tree
+            .role(object)
+            .map_or("application".into(), |role| role.name().replace('_', " "))
+    }
+
+    /// The value of a region as a number, see `MAXIMUM_VALUE`.
+    fn current_value(&self, object: Object) -> f64 {
+        let text = self.tree.value(object).unwrap_or_default().trim_start();
+        let end = text
+            .find(|c: char| !c.is_ascii_digit() && c != '.' && c != '-')
+            .unwrap_or_else(|| text.len());
+        text[..end].parse().unwrap_or(0.0)
+    }
+
+    /// The states, as a bit set of two words.
+    fn states(&self, object: Object) -> Vec<u32> {
+        let mut states = vec![0u32, 0];
+        let mut set = |state: u32| states[(state / 32) as usize] |= 1 << (state % 32);
+        if !self.tree.exists(object) {
+            set(STATE_DEFUNCT);
+            return states;
+        }
+        set(STATE_ENABLED);
+        set(STATE_SENSITIVE);
+        if self.tree.showing(object) {
+            set(STATE_SHOWING);
+            set(STATE_VISIBLE);
+        }
+        states
+    }
+
+    /// Where an object is, relative to the screen or to its drawin.
+    fn extents(&self, object: Object, coords: u32) -> Area {
+        let extents = self.tree.extents(object).unwrap_or_default();
+        match object {
+            // The application isn't anywhere, so the parent of a drawin is
+            // the screen.
+            Object::Drawin(_) if coords == COORDS_WINDOW => extents.with_origin(Origin::default()),
+            Object::Region(id, _) if coords != COORDS_SCREEN => {
+                let origin = self.tree.extents(Object::Drawin(id)).unwrap_or_default().origin;
+                extents.translate(Origin {
+                    x: -origin.x,
+                    y: -origin.y
+                })
+            },
+            _ => extents
+        }
+    }
+
+    fn property(&self, object: Object, interface: &str, name: &str) -> Option<Box<RefArg>> {
+        if !self.interfaces(object).contains(&interface) {
+            return None;
+        }
+        let value: Box<RefArg> = match (interface, name) {
+            (ACCESSIBLE, "Name") => Box::new(self.name(object)),
+            (ACCESSIBLE, "Description") => Box::new(self.tree.value(object).unwrap_or_default().to_string()),
+            (ACCESSIBLE, "Parent") => Box::new(self.parent(object)),
+            (ACCESSIBLE, "ChildCount") => Box::new(self.tree.children(object).len() as i32),
+            (ACCESSIBLE, "Locale") => Box::new(String::new()),
+            (ACCESSIBLE, "AccessibleId") => {
+                Box::new(object.path()[ROOT_PATH.len() - "root".len()..].to_string())
+            },
+            (APPLICATION, "ToolkitName") => Box::new("way-cooler".to_string()),
+            (APPLICATION, "Version") => Box::new(env!("CARGO_PKG_VERSION").to_string()),
+            (APPLICATION, "AtspiVersion") => Box::new("2.1".to_string()),
+            (APPLICATION, "Id") => Box::new(self.export.id),
+            (VALUE, "MinimumValue") => Box::new(0.0),
+            (VALUE, "MaximumValue") => Box::new(MAXIMUM_VALUE),
+            (VALUE, "MinimumIncrement") => Box::new(0.0),
+            (VALUE, "CurrentValue") => Box::new(self.current_value(object)),
+            (VALUE, "Text") => Box::new(self.tree.value(object).unwrap_or_default().to_string()),
+            _ => return None
+        };
+        Some(value)
+    }
+
+    fn properties(&self, object: Object, interface: &str) -> HashMap<String, Variant<Box<RefArg>>> {
+        let names: &[&str] = match interface {
+            ACCESSIBLE => &[
+                "Name",
+                "Description",
+                "Parent",
+                "ChildCount",
+                "Locale",
+                "AccessibleId"
+            ],
+            APPLICATION => &["ToolkitName", "Version", "AtspiVersion", "Id"],
+            VALUE => &[
+                "MinimumValue",
+                "MaximumValue",
+                "MinimumIncrement",
+                "CurrentValue",
+                "Text"
+            ],
+            _ => &[]
+        };
+        names
+            .iter()
+            .filter_map(|name| {
+                self.property(object, interface, name)
+                    .map(|value| (name.to_string(), Variant(value)))
+            })
+            .collect()
+    }
+
+    fn answer(
+        &self,
+        call: &Message,
+        object: Object,
+        interface: &str,
+        member: &str
+    ) -> Result<Message, Failure> {
+        let reply = call.method_return();
+        if interface != PROPERTIES && !self.interfaces(object).contains(&interface) {
+            return Err((
+                "org.freedesktop.DBus.Error.UnknownMethod",
+                format!("{} doesn't implement {}", object.path(), interface)
+            ));
+        }
+        let children = self.tree.children(object);
+        Ok(match (interface, member) {
+            (PROPERTIES, "Get") => {
+                let (interface, name) = call.read2::<&str, &str>().map_err(invalid_args)?;
+                match self.property(object, interface, name) {
+                    Some(value) => reply.append1(Variant(value)),
+                    None => {
+                        return Err((
+                            "org.freedesktop.DBus.Error.UnknownProperty",
+                            format!("{} has no property {}.{}", object.path(), interface, name)
+                        ))
+                    },
+                }
+            },
+            (PROPERTIES, "GetAll") => {
+                let interface = call.read1::<&str>().map_err(invalid_args)?;
+                reply.append1(self.properties(object, interface))
+            },
+            (ACCESSIBLE, "GetChildAtIndex") => {
+                let index = call.read1::<i32>().map_err(invalid_args)?;
+                let child = children.get(index as usize).filter(|_| index >= 0);
+                reply.append1(child.map_or_else(View::null, |&child| self.reference(child)))
+            },
+            (ACCESSIBLE, "GetChildren") => reply.append1(
+                children
+                    .iter()
+                    .map(|&child| self.reference(child))
+                    .collect::<Vec<_>>()
+            ),
+            (ACCESSIBLE, "GetIndexInParent") => {
+                reply.append1(self.tree.index_in_parent(object).map_or(-1, |index| index as i32))
+            },
+            (ACCESSIBLE, "GetRelationSet") => reply.append1(Vec::<(u32, Vec<Reference>)>::new()),
+            (ACCESSIBLE, "GetRole") => reply.append1(self.role(object)),
+            (ACCESSIBLE, "GetRoleName") | (ACCESSIBLE, "GetLocalizedRoleName") => {
+                reply.append1(self.role_name(object))
+            },
+            (ACCESSIBLE, "GetState") => reply.append1(self.states(object)),
+            (ACCESSIBLE, "GetAttributes") => reply.append1(HashMap::<String, String>::new()),
+            (ACCESSIBLE, "GetApplication") => reply.append1(self.reference(Object::Root)),
+            (ACCESSIBLE, "GetInterfaces") => reply.append1(self.interfaces(object)),
+            (COMPONENT, "Contains") => {
+                let (x, y, coords) = call.read3::<i32, i32, u32>().map_err(invalid_args)?;
+                reply.append1(self.extents(object, coords).contains(Origin { x, y }))
+            },
+            (COMPONENT, "GetAccessibleAtPoint") => {
+                let (x, y, coords) = call.read3::<i32, i32, u32>().map_err(invalid_args)?;
+                let child = children
+                    .iter()
+                    .find(|&&child| self.extents(child, coords).contains(Origin { x, y }));
+                reply.append1(child.map_or_else(View::null, |&child| self.reference(child)))
+            },
+            (COMPONENT, "GetExtents") => {
+                let coords = call.read1::<u32>().map_err(invalid_args)?;
+                let Area { origin, size } = self.extents(object, coords);
+                reply.append1((origin.x, origin.y, size.width as i32, size.height as i32))
+            },
+            (COMPONENT, "GetPosition") => {
+                let coords = call.read1::<u32>().map_err(invalid_args)?;
+                let Origin { x, y } = self.extents(object, coords).origin;
+                reply.append2(x, y)
+            },
+            (COMPONENT, "GetSize") => {
+                let size = self.extents(object, COORDS_SCREEN).size;
+                reply.append2(size.width as i32, size.height as i32)
+            },
+            (COMPONENT, "GetLayer") => match object {
+                Object::Drawin(_) => reply.append1(LAYER_WINDOW),
+                _ => reply.append1(LAYER_WIDGET)
+            },
+            (COMPONENT, "GetMDIZOrder") => reply.append1(-1i16),
+            (COMPONENT, "GrabFocus") => reply.append1(false),
+            (COMPONENT, "GetAlpha") => reply.append1(1.0),
+            _ => {
+                return Err((
+                    "org.freedesktop.DBus.Error.UnknownMethod",
+                    format!("{}.{} isn't supported", interface, member)
+                ))
+            },
+        })
+    }
+
+    /// What screen readers are told about an event.
+    fn signals(&self, event: &Event) -> Vec<Message> {
+        match *event {
+            Event::ChildAdded { parent, index, child } => {
+                vec![signal(
+                    parent,
+                    "ChildrenChanged",
+                    "add",
+                    index as i32,
+                    self.reference(child)
+                )]
+            },
+            Event::ChildRemoved { parent, index, child } => {
+                vec![signal(
+                    parent,
+                    "ChildrenChanged",
+                    "remove",
+                    index as i32,
+                    self.reference(child)
+                )]
+            },
+            Event::Changed { object, property } => match property {
+                Property::Name => vec![signal(
+                    object,
+                    "PropertyChange",
+                    "accessible-name",
+                    0,
+                    self.name(object)
+                )],
+                Property::Role => vec![signal(
+                    object,
+                    "PropertyChange",
+                    "accessible-role",
+                    0,
+                    self.role(object)
+                )],
+                Property::Value => vec![
+                    signal(
+                        object,
+                        "PropertyChange",
+                        "accessible-value",
+                        0,
+                        self.current_value(object)
+                    ),
+                    signal(
+                        object,
+                        "PropertyChange",
+                        "accessible-description",
+                        0,
+                        self.tree.value(object).unwrap_or_default().to_string()
+                    ),
+                ]
+            },
+            Event::Moved { object, extents } => {
+                let Area { origin, size } = extents;
+                let extents = (origin.x, origin.y, size.width as i32, size.height as i32);
+                vec![signal(object, "BoundsChanged", "", 0, extents)]
+            },
+            Event::Showing { object, showing } => vec![
+                signal(object, "StateChanged", "showing", showing as i32, 0),
+                signal(object, "StateChanged", "visible", showing as i32, 0),
+            ],
+            Event::Defunct { object } => vec![signal(object, "StateChanged", "defunct", 1, 0)]
+        }
+    }
+}
+
+/// An event of `object`, as `org.a11y.atspi.Event.Object` signals are:
+/// a detail, two numbers, a value and properties no one reads.
+fn signal<V: RefArg + 'static>(object: Object, member: &str, detail: &str, detail1: i32, any: V) -> Message {
+    let any: Box<RefArg> = Box::new(any);
+    Message::new_signal(object.path(), EVENTS, member)
+        .unwrap()
+        .append3(detail, detail1, 0i32)
+        .append2(Variant(any), HashMap::<String, Variant<Box<RefArg>>>::new())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dbus::test_bus::{connect, TestBus};
+    use crate::objects::{drawable, drawin, screen::SCREENS_HANDLE};
+    use rlua::Lua;
+    use std::{
+        collections::VecDeque,
+        rc::Rc,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            mpsc, Arc
+        },
+        thread,
+        time::Duration
+    };
+
+    /// A signal the registry received: its path, member, detail and first
+    /// number.
+    type Received = (String, String, String, i32);
+
+    /// Owns the name of the registry, embeds whoever asks and sends what
+    /// the application sends it on `received`. The calls sent on `queries`
+    /// are made and their replies sent back on `replies`.
+    fn serve_registry(
+        address: String,
+        embedded: mpsc::Sender<String>,
+        received: mpsc::Sender<Received>,
+        queries: mpsc::Receiver<Message>,
+        replies: mpsc::Sender<Result<Message, ::dbus::Error>>,
+        stop: Arc<AtomicBool>
+    ) {
+        let connection = connect(&address);
+        let queue = Rc::new(RefCell::new(VecDeque::new()));
+        let arrived = queue.clone();
+        connection.replace_message_callback(Some(Box::new(move |_, message| {
+            arrived.borrow_mut().push_back(message);
+            true
+        })));
+        connection.register_name(REGISTRY, 0).unwrap();
+        connection
+            .add_match(&format!("type='signal',interface='{}'", EVENTS))
+            .unwrap();
+        while !stop.load(Ordering::SeqCst) {
+            connection.incoming(20).for_each(drop);
+            let messages: Vec<Message> = queue.borrow_mut().drain(..).collect();
+            for message in messages {
+                let (message_type, path, interface, member) = message.headers();
+                let member = member.unwrap_or_default();
+                match message_type {
+                    MessageType::MethodCall if member == "Embed" => {
+                        let (name, _) = message.read1::<(String, Path)>().unwrap();
+                        let desktop = (
+                            connection.unique_name(),
+                            Path::new("/org/a11y/atspi/accessible/root").unwrap()
+                        );
+                        connection.send(message.method_return().append1(desktop)).unwrap();
+                        embedded.send(name).unwrap();
+                    },
+                    MessageType::Signal if interface.as_ref().map(String::as_str) == Some(EVENTS) => {
+                        let (detail, detail1) = message.read2::<String, i32>().unwrap();
+                        received
+                            .send((path.unwrap_or_default(), member, detail, detail1))
+                            .unwrap();
+                    },
+                    _ => {}
+                }
+            }
+            while let Ok(query) = queries.try_recv() {
+                replies
+                    .send(connection.send_with_reply_and_block(query, 5000))
+                    .unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn accessibility_atspi_export() -> rlua::Result<()> {
+        let bus = match TestBus::start() {
+            Some(bus) => bus,
+            None => {
+                eprintln!("dbus-daemon can't be run, skipping");
+                return Ok(());
+            }
+        };
+        env::set_var("AT_SPI_BUS_ADDRESS", &bus.address);
+        let stop = Arc::new(AtomicBool::new(false));
+        let (embedded_send, embedded) = mpsc::channel();
+        let (received_send, received) = mpsc::channel();
+        let (queries, queries_recv) = mpsc::channel();
+        let (replies_send, replies) = mpsc::channel();
+        let registry = {
+            let (address, stop) = (bus.address.clone(), stop.clone());
+            thread::spawn(move || {
+                serve_registry(
+                    address,
+                    embedded_send,
+                    received_send,
+                    queries_recv,
+                    replies_send,
+                    stop
+                )
+            })
+        };
+        let timeout = Duration::from_secs(5);
+        let state = || EXPORT.with(|export| export.borrow().state.clone());
+        // Answers to queries are only sent while the bus is read.
+        let try_query = |call: Message| -> Result<Message, ::dbus::Error> {
+            queries.send(call).unwrap();
+            for _ in 0..100 {
+                dispatch(50);
+                if let Ok(reply) = replies.try_recv() {
+                    return reply;
+                }
+            }
+            panic!("no reply came in time");
+        };
+        let query = |call: Message| try_query(call).unwrap();
+        let wait_for = |path: &str, member: &str, detail: &str| -> i32 {
+            loop {
+                let (from, name, what, detail1) = received.recv_timeout(timeout).unwrap();
+                if (from.as_str(), name.as_str(), what.as_str()) == (path, member, detail) {
+                    return detail1;
+                }
+            }
+        };
+
+        let lua = Lua::new();
+        let result = lua.context(|lua| {
+            drawable::init(lua)?;
+            drawin::init(lua)?;
+            lua.set_named_registry_value(SCREENS_HANDLE, lua.create_table()?)?;
+            lua.load(
+                r#"
+bar = drawin{ x = 0, y = 1050, width = 1920, height = 30 }
+assert(bar.accessible == nil)
+assert(not pcall(bar.set_accessible_regions, bar, {}))
+assert(not pcall(function() bar.accessible = { role = "bar", label = "Main bar" } end))
+"#
+            )
+            .exec()?;
+            // Nothing is sent before a drawin is accessible.
+            assert_eq!(state(), State::Stopped);
+            lua.load(r#"bar.accessible = { role = "status_bar", label = "Main bar" }"#)
+                .exec()?;
+            let app = embedded.recv_timeout(timeout).unwrap();
+            for _ in 0..100 {
+                if let State::Embedded { .. } = state() {
+                    break;
+                }
+                dispatch(50);
+            }
+            assert_eq!(
+                lua.load("return bar.accessible.role .. ':' .. bar.accessible.label")
+                    .eval::<String>()?,
+                "status_bar:Main bar"
+            );
+            let drawin = Object::Drawin(lua.load("return bar.id").eval()?);
+            let battery = match drawin {
+                Object::Drawin(id) => Object::Region(id, 1),
+                _ => unreachable!()
+            };
+            let call = |object: Object, interface: &str, member: &str| {
+                Message::new_method_call(app.as_str(), object.path(), interface, member).unwrap()
+            };
+            let children = |object: Object| -> Vec<String> {
+                query(call(object, ACCESSIBLE, "GetChildren"))
+                    .read1::<Vec<(String, Path)>>()
+                    .unwrap()
+                    .into_iter()
+                    .map(|(name, path)| {
+                        assert_eq!(name, app);
+                        path.to_string()
+                    })
+                    .collect()
+            };
+            let extents = |object: Object, coords: u32| {
+                query(call(object, COMPONENT, "GetExtents").append1(coords))
+                    .read1::<(i32, i32, i32, i32)>()
+                    .unwrap()
+            };
+            let current_value = || {
+                query(call(battery, PROPERTIES, "Get").append2(VALUE, "CurrentValue"))
+                    .read1::<Variant<f64>>()
+                    .unwrap()
+                    .0
+            };
+            assert_eq!(children(Object::Root), vec![drawin.path()]);
+            assert_eq!(extents(drawin, COORDS_SCREEN), (0, 1050, 1920, 30));
+
+            lua.load(
+                r#"
+local ok, err = pcall(bar.set_accessible_regions, bar, { { role = "label", label = "Clock" } })
+assert(not ok and tostring(err):find("region 1: x must be an integer", 1, true))
+bar:set_accessible_regions({
+    { role = "label", label = "Clock", value = "12:00", x = 1800, y = 0, width = 120, height = 30 },
+    { role = "progress_bar", label = "Battery", value = "45 percent", x = 1700, y = 0, width = 100, height = 30 }
+})
+"#
+            )
+            .exec()?;
+            assert_eq!(wait_for(&drawin.path(), "ChildrenChanged", "add"), 0);
+            assert_eq!(wait_for(&drawin.path(), "ChildrenChanged", "add"), 1);
+            assert_eq!(children(drawin).len(), 2);
+            assert_eq!(children(drawin)[1], battery.path());
+            assert_eq!(query(call(battery, ACCESSIBLE, "GetRole")).read1::<u32>().unwrap(), 42);
+            let name = query(call(battery, PROPERTIES, "Get").append2(ACCESSIBLE, "Name"));
+            assert_eq!(name.read1::<Variant<String>>().unwrap().0, "Battery");
+            let parent = query(call(drawin, PROPERTIES, "Get").append2(ACCESSIBLE, "Parent"));
+            assert_eq!(&*parent.read1::<Variant<(String, Path)>>().unwrap().0 .1, ROOT_PATH);
+            assert_eq!(extents(battery, COORDS_SCREEN), (1700, 1050, 100, 30));
+            assert_eq!(extents(battery, COORDS_WINDOW), (1700, 0, 100, 30));
+            assert_eq!(current_value(), 45.0);
+
+            // Moving the drawin moves its regions.
+            lua.load("bar.y = 0").exec()?;
+            wait_for(&battery.path(), "BoundsChanged", "");
+            assert_eq!(extents(battery, COORDS_SCREEN), (1700, 0, 100, 30));
+            assert_eq!(extents(drawin, COORDS_SCREEN), (0, 0, 1920, 30));
+
+            // A new value is announced.
+            lua.load(
+                r#"
+bar:set_accessible_regions({
+    { role = "label", label = "Clock", value = "12:00", x = 1800, y = 0, width = 120, height = 30 },
+    { role = "progress_bar", label = "Battery", value = "44 percent", x = 1700, y = 0, width = 100, height = 30 }
+})
+"#
+            )
+            .exec()?;
+            wait_for(&battery.path(), "PropertyChange", "accessible-value");
+            assert_eq!(current_value(), 44.0);
+
+            // Objects that are gone are torn down.
+            lua.load("bar.accessible = nil").exec()?;
+            assert_eq!(wait_for(ROOT_PATH, "ChildrenChanged", "remove"), 0);
+            assert_eq!(wait_for(&battery.path(), "StateChanged", "defunct"), 1);
+            let unknown = try_query(call(battery, ACCESSIBLE, "GetRole")).unwrap_err();
+            assert_eq!(
+                unknown.name(),
+                Some("org.freedesktop.DBus.Error.UnknownObject")
+            );
+            assert!(children(Object::Root).is_empty());
+            Ok(())
+        });
+        stop.store(true, Ordering::SeqCst);
+        registry.join().unwrap();
+        dbus::disconnect(Bus::Accessibility);
+        EXPORT.with(|export| *export.borrow_mut() = Export::default());
+        env::remove_var("AT_SPI_BUS_ADDRESS");
+        result
+    }
+}
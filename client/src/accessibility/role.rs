@@ -0,0 +1,105 @@
+//! The roles Lua can give accessible objects, which tell screen readers
+//! what they are.
+
+/// A role, named like AT-SPI's but in snake case, e.g. `"status_bar"`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Role {
+    Filler,
+    Heading,
+    Icon,
+    Image,
+    Label,
+    List,
+    ListItem,
+    MenuBar,
+    Panel,
+    ProgressBar,
+    PushButton,
+    Separator,
+    Slider,
+    StatusBar,
+    Text,
+    ToggleButton,
+    ToolBar,
+    Unknown
+}
+
+impl Role {
+    pub const NAMES: &'static [&'static str] = &[
+        "filler",
+        "heading",
+        "icon",
+        "image",
+        "label",
+        "list",
+        "list_item",
+        "menu_bar",
+        "panel",
+        "progress_bar",
+        "push_button",
+        "separator",
+        "slider",
+        "status_bar",
+        "text",
+        "toggle_button",
+        "tool_bar",
+        "unknown"
+    ];
+
+    const ALL: &'static [Role] = &[
+        Role::Filler,
+        Role::Heading,
+        Role::Icon,
+        Role::Image,
+        Role::Label,
+        Role::List,
+        Role::ListItem,
+        Role::MenuBar,
+        Role::Panel,
+        Role::ProgressBar,
+        Role::PushButton,
+        Role::Separator,
+        Role::Slider,
+        Role::StatusBar,
+        Role::Text,
+        Role::ToggleButton,
+        Role::ToolBar,
+        Role::Unknown
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Role::NAMES
+            .iter()
+            .position(|known| *known == name)
+            .map(|index| Role::ALL[index])
+    }
+
+    pub fn name(self) -> &'static str {
+        Role::NAMES[Role::ALL.iter().position(|role| *role == self).unwrap()]
+    }
+
+    /// The number of the role in AT-SPI's `AtspiRole`.
+    #[cfg_attr(not(feature = "accessibility"), allow(dead_code))]
+    pub fn atspi(self) -> u32 {
+        match self {
+            Role::Filler => 20,
+            Role::Heading => 83,
+            Role::Icon => 26,
+            Role::Image => 27,
+            Role::Label => 29,
+            Role::List => 31,
+            Role::ListItem => 32,
+            Role::MenuBar => 34,
+            Role::Panel => 39,
+            Role::ProgressBar => 42,
+            Role::PushButton => 43,
+            Role::Separator => 50,
+            Role::Slider => 51,
+            Role::StatusBar => 54,
+            Role::Text => 61,
+            Role::ToggleButton => 62,
+            Role::ToolBar => 63,
+            Role::Unknown => 67
+        }
+    }
+}
@@ -0,0 +1,510 @@
+//! The accessible objects: the application, the drawins Lua made accessible
+//! and the regions Lua declared in them. Changing them returns what screen
+//! readers are told about it.
+
+use super::role::Role;
+use crate::area::Area;
+
+/// Where the application is on the accessibility bus.
+pub const ROOT_PATH: &str = "/org/a11y/atspi/accessible/root";
+
+const PATH_PREFIX: &str = "/org/a11y/atspi/accessible/drawin";
+
+/// An accessible object.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Object {
+    /// The application, whose children are the accessible drawins.
+    Root,
+    /// A drawin, by its id.
+    Drawin(usize),
+    /// A region of a drawin, by the id of the drawin and its index.
+    Region(usize, usize)
+}
+
+impl Object {
+    pub fn path(self) -> String {
+        match self {
+            Object::Root => ROOT_PATH.into(),
+            Object::Drawin(id) => format!("{}{}", PATH_PREFIX, id),
+            Object::Region(id, index) => format!("{}{}/{}", PATH_PREFIX, id, index)
+        }
+    }
+
+    pub fn from_path(path: &str) -> Option<Self> {
+        if path == ROOT_PATH {
+            return Some(Object::Root);
+        }
+        if !path.starts_with(PATH_PREFIX) {
+            return None;
+        }
+        let mut parts = path[PATH_PREFIX.len()..].splitn(2, '/');
+        let id = parts.next()?.parse().ok()?;
+        match parts.next() {
+            Some(index) => Some(Object::Region(id, index.parse().ok()?)),
+            None => Some(Object::Drawin(id))
+        }
+    }
+}
+
+/// What a drawin is, from `drawin.accessible`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Accessible {
+    pub role: Role,
+    pub label: String
+}
+
+/// A part of a drawin, from `drawin:set_accessible_regions`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Region {
+    pub role: Role,
+    pub label: String,
+    /// What it shows, like "45 percent", if it changes.
+    pub value: Option<String>,
+    /// Where it is on the drawin.
+    pub area: Area
+}
+
+/// A property screen readers are told about when it changes.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Property {
+    Name,
+    Role,
+    Value
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Event {
+    ChildAdded {
+        parent: Object,
+        index: usize,
+        child: Object
+    },
+    ChildRemoved {
+        parent: Object,
+        index: usize,
+        child: Object
+    },
+    /// The property changed, its new value is in the tree.
+    Changed {
+        object: Object,
+        property: Property
+    },
+    /// Where the object is on the screen changed.
+    Moved {
+        object: Object,
+        extents: Area
+    },
+    Showing {
+        object: Object,
+        showing: bool
+    },
+    /// The object is gone.
+    Defunct {
+        object: Object
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    id: usize,
+    accessible: Accessible,
+    geometry: Area,
+    showing: bool,
+    regions: Vec<Region>
+}
+
+#[derive(Debug, Default)]
+pub struct Tree {
+    /// The accessible drawins, in the order they were made accessible.
+    nodes: Vec<Node>
+}
+
+impl Tree {
+    fn position(&self, id: usize) -> Option<usize> {
+        self.nodes.iter().position(|node| node.id == id)
+    }
+
+    fn node(&self, id: usize) -> Option<&Node> {
+        self.nodes.iter().find(|node| node.id == id)
+    }
+
+    fn region(&self, id: usize, index: usize) -> Option<(&Node, &Region)> {
+        let node = self.node(id)?;
+        node.regions.get(index).map(|region| (node, region))
+    }
+
+    pub fn accessible(&self, id: usize) -> Option<&Accessible> {
+        self.node(id).map(|node| &node.accessible)
+    }
+
+    /// Makes a drawin accessible, changes what it is, or makes it
+    /// inaccessible again with `None`, which forgets its regions.
+    pub fn set_accessible(
+        &mut self,
+        id: usize,
+        accessible: Option<Accessible>,
+        geometry: Area,
+        showing: bool
+    ) -> Vec<Event> {
+        let object = Object::Drawin(id);
+        match (self.position(id), accessible) {
+            (None, None) => Vec::new(),
+            (None, Some(accessible)) => {
+                self.nodes.push(Node {
+                    id,
+                    accessible,
+                    geometry,
+                    showing,
+                    regions: Vec::new()
+                });
+                vec![Event::ChildAdded {
+                    parent: Object::Root,
+                    index: self.nodes.len() - 1,
+                    child: object
+                }]
+            },
+            (Some(index), None) => {
+                let node = self.nodes.remove(index);
+                let mut events = vec![Event::ChildRemoved {
+                    parent: Object::Root,
+                    index,
+                    child: object
+                }];
+                events.extend((0..node.regions.len()).map(|index| Event::Defunct {
+                    object: Object::Region(id, index)
+                }));
+                events.push(Event::Defunct { object });
+                events
+            },
+            (Some(index), Some(accessible)) => {
+                let node = &mut self.nodes[index];
+                let mut events = Vec::new();
+                if node.accessible.role != accessible.role {
+                    events.push(Event::Changed {
+                        object,
+                        property: Property::Role
+                    });
+                }
+                if node.accessible.label != accessible.label {
+                    events.push(Event::Changed {
+                        object,
+                        property: Property::Name
+                    });
+                }
+                node.accessible = accessible;
+                events
+            }
+        }
+    }
+
+    /// Replaces the regions of an accessible drawin, or returns `None` if it
+    /// isn't accessible.
+    ///
+    /// A region at the same index with the same role is the same object, so
+    /// a new label or value is announced as a change of it.
+    pub fn set_regions(&mut self, id: usize, regions: Vec<Region>) -> Option<Vec<Event>> {
+        let index = self.position(id)?;
+        let node = &mut self.nodes[index];
+        let origin = node.geometry.origin;
+        let parent = Object::Drawin(id);
+        let kept = node
+            .regions
+            .iter()
+            .zip(&regions)
+            .take_while(|(old, new)| old.role == new.role)
+            .count();
+        let mut events = Vec::new();
+        for (index, (old, new)) in node.regions.iter().zip(&regions).take(kept).enumerate() {
+            let object = Object::Region(id, index);
+            if old.label != new.label {
+                events.push(Event::Changed {
+                    object,
+                    property: Property::Name
+                });
+            }
+            if old.value != new.value {
+                events.push(Event::Changed {
+                    object,
+                    property: Property::Value
+                });
+            }
+            if old.area != new.area {
+                events.push(Event::Moved {
+                    object,
+                    extents: new.area.translate(origin)
+                });
+            }
+        }
+        // The rest are removed from the last, so the indices stay right.
+        for index in (kept..node.regions.len()).rev() {
+            let child = Object::Region(id, index);
+            events.push(Event::ChildRemoved { parent, index, child });
+            events.push(Event::Defunct { object: child });
+        }
+        for index in kept..regions.len() {
+            let child = Object::Region(id, index);
+            events.push(Event::ChildAdded { parent, index, child });
+        }
+        node.regions = regions;
+        Some(events)
+    }
+
+    /// Updates where a drawin is and whether it's shown, if it's
+    /// accessible.
+    pub fn moved(&mut self, id: usize, geometry: Area, showing: bool) -> Vec<Event> {
+        let node = match self.position(id) {
+            Some(index) => &mut self.nodes[index],
+            None => return Vec::new()
+        };
+        let mut events = Vec::new();
+        if node.geometry != geometry {
+            let origin_moved = node.geometry.origin != geometry.origin;
+            node.geometry = geometry;
+            events.push(Event::Moved {
+                object: Object::Drawin(id),
+                extents: geometry
+            });
+            // Regions only move with the drawin's origin.
+            if origin_moved {
+                for (index, region) in node.regions.iter().enumerate() {
+                    events.push(Event::Moved {
+                        object: Object::Region(id, index),
+                        extents: region.area.translate(geometry.origin)
+                    });
+                }
+            }
+        }
+        if node.showing != showing {
+            node.showing = showing;
+            events.push(Event::Showing {
+                object: Object::Drawin(id),
+                showing
+            });
+        }
+        events
+    }
+
+    pub fn exists(&self, object: Object) -> bool {
+        match object {
+            Object::Root => true,
+            Object::Drawin(id) => self.node(id).is_some(),
+            Object::Region(id, index) => self.region(id, index).is_some()
+        }
+    }
+
+    pub fn children(&self, object: Object) -> Vec<Object> {
+        match object {
+            Object::Root => self.nodes.iter().map(|node| Object::Drawin(node.id)).collect(),
+            Object::Drawin(id) => self.node(id).map_or_else(Vec::new, |node| {
+                (0..node.regions.len())
+                    .map(|index| Object::Region(id, index))
+                    .collect()
+            }),
+            Object::Region(..) => Vec::new()
+        }
+    }
+
+    /// The parent of an object, `None` for the application, whose parent is
+    /// the desktop of the registry.
+    pub fn parent(&self, object: Object) -> Option<Object> {
+        match object {
+            Object::Root => None,
+            Object::Drawin(_) => Some(Object::Root),
+            Object::Region(id, _) => Some(Object::Drawin(id))
+        }
+    }
+
+    pub fn index_in_parent(&self, object: Object) -> Option<usize> {
+        match object {
+            Object::Root => None,
+            Object::Drawin(id) => self.position(id),
+            Object::Region(id, index) => self.region(id, index).map(|_| index)
+        }
+    }
+
+    /// The role of a drawin or region.
+    pub fn role(&self, object: Object) -> Option<Role> {
+        match object {
+            Object::Root => None,
+            Object::Drawin(id) => self.node(id).map(|node| node.accessible.role),
+            Object::Region(id, index) => self.region(id, index).map(|(_, region)| region.role)
+        }
+    }
+
+    /// The label of a drawin or region.
+    pub fn name(&self, object: Object) -> Option<&str> {
+        match object {
+            Object::Root => None,
+            Object::Drawin(id) => self.node(id).map(|node| node.accessible.label.as_str()),
+            Object::Region(id, index) => self.region(id, index).map(|(_, region)| region.label.as_str())
+        }
+    }
+
+    pub fn value(&self, object: Object) -> Option<&str> {
+        match object {
+            Object::Region(id, index) => self
+                .region(id, index)
+                .and_then(|(_, region)| region.value.as_ref().map(String::as_str)),
+            _ => None
+        }
+    }
+
+    /// Where a drawin or region is on the screen.
+    pub fn extents(&self, object: Object) -> Option<Area> {
+        match object {
+            Object::Root => None,
+            Object::Drawin(id) => self.node(id).map(|node| node.geometry),
+            Object::Region(id, index) => self
+                .region(id, index)
+                .map(|(node, region)| region.area.translate(node.geometry.origin))
+        }
+    }
+
+    /// Whether a drawin or region is shown, which the regions are when
+    /// their drawin is.
+    pub fn showing(&self, object: Object) -> bool {
+        match object {
+            Object::Root => true,
+            Object::Drawin(id) | Object::Region(id, _) => self.node(id).map_or(false, |node| node.showing)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::area::{Origin, Size};
+
+    fn area(x: i32, y: i32, width: u32, height: u32) -> Area {
+        Area {
+            origin: Origin { x, y },
+            size: Size { width, height }
+        }
+    }
+
+    fn region(role: Role, label: &str, value: Option<&str>, area: Area) -> Region {
+        Region {
+            role,
+            label: label.into(),
+            value: value.map(String::from),
+            area
+        }
+    }
+
+    #[test]
+    fn accessibility_tree_changes() {
+        let mut tree = Tree::default();
+        let bar = Accessible {
+            role: Role::StatusBar,
+            label: "Main bar".into()
+        };
+        assert!(tree.set_regions(1, Vec::new()).is_none());
+        assert_eq!(
+            tree.set_accessible(1, Some(bar.clone()), area(0, 1050, 1920, 30), true),
+            vec![Event::ChildAdded {
+                parent: Object::Root,
+                index: 0,
+                child: Object::Drawin(1)
+            }]
+        );
+        assert!(tree
+            .set_accessible(1, Some(bar), area(0, 0, 1, 1), true)
+            .is_empty());
+        let clock = region(Role::Label, "Clock", Some("12:00"), area(1800, 0, 120, 30));
+        let battery = region(
+            Role::ProgressBar,
+            "Battery",
+            Some("45 percent"),
+            area(1700, 0, 100, 30)
+        );
+        let events = tree.set_regions(1, vec![clock.clone(), battery.clone()]).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            tree.children(Object::Drawin(1)),
+            vec![Object::Region(1, 0), Object::Region(1, 1)]
+        );
+        assert_eq!(
+            tree.extents(Object::Region(1, 1)),
+            Some(area(1700, 1050, 100, 30))
+        );
+        assert_eq!(tree.value(Object::Region(1, 1)), Some("45 percent"));
+
+        // The same regions with a new value are changed, not replaced.
+        let lower = region(Role::ProgressBar, "Battery", Some("44 percent"), battery.area);
+        assert_eq!(
+            tree.set_regions(1, vec![clock.clone(), lower.clone()]).unwrap(),
+            vec![Event::Changed {
+                object: Object::Region(1, 1),
+                property: Property::Value
+            }]
+        );
+        // A region of another role is another object.
+        let button = region(Role::PushButton, "Menu", None, area(0, 0, 30, 30));
+        assert_eq!(
+            tree.set_regions(1, vec![button, lower]).unwrap(),
+            vec![
+                Event::ChildRemoved {
+                    parent: Object::Drawin(1),
+                    index: 1,
+                    child: Object::Region(1, 1)
+                },
+                Event::Defunct {
+                    object: Object::Region(1, 1)
+                },
+                Event::ChildRemoved {
+                    parent: Object::Drawin(1),
+                    index: 0,
+                    child: Object::Region(1, 0)
+                },
+                Event::Defunct {
+                    object: Object::Region(1, 0)
+                },
+                Event::ChildAdded {
+                    parent: Object::Drawin(1),
+                    index: 0,
+                    child: Object::Region(1, 0)
+                },
+                Event::ChildAdded {
+                    parent: Object::Drawin(1),
+                    index: 1,
+                    child: Object::Region(1, 1)
+                }
+            ]
+        );
+
+        // Moving the drawin moves its regions.
+        let events = tree.moved(1, area(0, 0, 1920, 30), false);
+        assert_eq!(
+            events,
+            vec![
+                Event::Moved {
+                    object: Object::Drawin(1),
+                    extents: area(0, 0, 1920, 30)
+                },
+                Event::Moved {
+                    object: Object::Region(1, 0),
+                    extents: area(0, 0, 30, 30)
+                },
+                Event::Moved {
+                    object: Object::Region(1, 1),
+                    extents: area(1700, 0, 100, 30)
+                },
+                Event::Showing {
+                    object: Object::Drawin(1),
+                    showing: false
+                }
+            ]
+        );
+        assert!(tree.moved(2, area(0, 0, 1, 1), true).is_empty());
+
+        let events = tree.set_accessible(1, None, area(0, 0, 1, 1), true);
+        assert_eq!(events.len(), 4);
+        assert!(tree.children(Object::Root).is_empty() && !tree.exists(Object::Region(1, 0)));
+
+        for object in &[Object::Root, Object::Drawin(3), Object::Region(3, 12)] {
+            assert_eq!(Object::from_path(&object.path()), Some(*object));
+        }
+        assert_eq!(Object::from_path("/org/a11y/atspi/accessible/drawinx"), None);
+        assert_eq!(Object::from_path("/org/a11y/atspi/registry"), None);
+    }
+}
@@ -0,0 +1,80 @@
+//! Counts the heap allocations of each thread, so the work done every frame
+//! can be checked for allocating, see `objects::drawable::frame`.
+//!
+//! This is the global allocator of the client. Counting is a thread local
+//! increment in front of the system allocator, so it's cheap enough to
+//! always be on, and the counts of a running client can be compared with
+//! what the tests expect.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell
+};
+
+thread_local! {
+    /// How many times this thread allocated or reallocated.
+    static ALLOCATIONS: Cell<u64> = Cell::new(0);
+}
+
+/// The system allocator, counting allocations.
+pub struct Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+fn count() {
+    // The counter is gone while the thread exits.
+    let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+}
+
+/// How many times this thread allocated so far.
+pub fn allocations() -> u64 {
+    ALLOCATIONS.with(Cell::get)
+}
+
+/// Runs `f`, returning what it returned and how many times it allocated.
+pub fn counted<T, F: FnOnce() -> T>(f: F) -> (T, u64) {
+    let before = allocations();
+    let result = f();
+    (result, allocations() - before)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn allocations_counted_per_thread() {
+        let (_, none) = counted(|| 1 + 1);
+        assert_eq!(none, 0);
+        let (vec, one) = counted(|| Vec::<u8>::with_capacity(16));
+        assert_eq!(one, 1);
+        // Growing reallocates, other threads don't count here.
+        let (_, more) = counted(|| {
+            let mut vec = vec;
+            vec.extend_from_slice(&[0; 64]);
+            std::thread::spawn(|| vec![0u8; 1024]).join().unwrap();
+        });
+        assert!(more >= 2, "{}", more);
+        let (_, other) = counted(|| std::thread::spawn(|| allocations()).join().unwrap());
+        assert!(other < 100);
+    }
+}
@@ -1,4 +1,21 @@
 //! Utility methods and structures
+//!
+//! The arithmetic on areas is kept here, in pure functions, so that every
+//! caller gets the same rounding and the same protection from overflow.
+//! Coordinates and lengths from outside, e.g. Lua, go through
+//! `checked_coordinate` and `checked_length` first, which keeps them small
+//! enough that none of these functions can overflow.
+
+#[cfg(test)]
+pub mod arbitrary;
+
+use std::fmt;
+
+use enumflags2::BitFlags;
+
+/// The largest coordinate or length of an area, far beyond any output but
+/// small enough that adding a few of them can't overflow an `i32`.
+pub const MAX_COORDINATE: i32 = 1 << 24;
 
 #[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Ord, PartialOrd)]
 /// Generic geometry-like struct. Contains an origin (x, y) point and bounds
@@ -20,6 +37,262 @@ impl Area {
     pub fn with_origin(self, origin: Origin) -> Self {
         Area { origin, ..self }
     }
+
+    /// Moves the area by `by`.
+    pub fn translate(self, by: Origin) -> Self {
+        Area {
+            origin: Origin {
+                x: self.origin.x + by.x,
+                y: self.origin.y + by.y
+            },
+            ..self
+        }
+    }
+
+    /// The part of the area that is also in `other`, if any.
+    pub fn intersection(self, other: Area) -> Option<Area> {
+        let left = self.origin.x.max(other.origin.x);
+        let top = self.origin.y.max(other.origin.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+        if left >= right || top >= bottom {
+            return None;
+        }
+        Some(Area {
+            origin: Origin { x: left, y: top },
+            size: Size {
+                width: (right - left) as u32,
+                height: (bottom - top) as u32
+            }
+        })
+    }
+
+    /// The part of the area within `bounds`, relative to the area, if some
+    /// but not all of it is.
+    pub fn visible_within(self, bounds: Area) -> Option<Area> {
+        let visible = self.intersection(bounds)?;
+        if visible == self {
+            return None;
+        }
+        Some(visible.translate(Origin {
+            x: -self.origin.x,
+            y: -self.origin.y
+        }))
+    }
+
+    /// The parts of the area that aren't in `other`, as up to four
+    /// rectangles that don't overlap: the rows above and below `other`,
+    /// then the columns to its left and right.
+    pub fn subtract(self, other: Area) -> Vec<Area> {
+        let overlap = match self.intersection(other) {
+            Some(overlap) => overlap,
+            None if self.size.width == 0 || self.size.height == 0 => return Vec::new(),
+            None => return vec![self]
+        };
+        let band = |x: i32, y: i32, right: i32, bottom: i32| {
+            if x < right && y < bottom {
+                Some(Area {
+                    origin: Origin { x, y },
+                    size: Size {
+                        width: (right - x) as u32,
+                        height: (bottom - y) as u32
+                    }
+                })
+            } else {
+                None
+            }
+        };
+        let (top, bottom) = (overlap.origin.y, overlap.bottom());
+        vec![
+            band(self.origin.x, self.origin.y, self.right(), top),
+            band(self.origin.x, bottom, self.right(), self.bottom()),
+            band(self.origin.x, top, overlap.origin.x, bottom),
+            band(overlap.right(), top, self.right(), bottom),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    /// Whether the two areas overlap. An empty area overlaps nothing.
+    pub fn intersects(self, other: Area) -> bool {
+        self.intersection(other).is_some()
+    }
+
+    /// How many pixels of the area are also in `other`.
+    pub fn overlap(self, other: Area) -> u64 {
+        self.intersection(other).map_or(0, |overlap| {
+            u64::from(overlap.size.width) * u64::from(overlap.size.height)
+        })
+    }
+
+    /// Whether `point` is in the area.
+    pub fn contains(self, point: Origin) -> bool {
+        point.x >= self.origin.x &&
+            point.y >= self.origin.y &&
+            point.x < self.right() &&
+            point.y < self.bottom()
+    }
+
+    /// Moves the area as little as possible to put it inside `bounds`.
+    ///
+    /// An area longer than `bounds` is aligned with its top or left edge.
+    pub fn clamp_within(self, bounds: Area) -> Area {
+        let clamp = |start: i32, length: u32, bounds_start: i32, bounds_length: u32| {
+            start
+                .min(bounds_start + bounds_length as i32 - length as i32)
+                .max(bounds_start)
+        };
+        Area {
+            origin: Origin {
+                x: clamp(self.origin.x, self.size.width, bounds.origin.x, bounds.size.width),
+                y: clamp(
+                    self.origin.y,
+                    self.size.height,
+                    bounds.origin.y,
+                    bounds.size.height
+                )
+            },
+            ..self
+        }
+    }
+
+    /// The area shrunk to the size of `bounds` where it's larger, and
+    /// moved as little as possible to put it inside.
+    pub fn fit_within(self, bounds: Area) -> Area {
+        let size = Size {
+            width: self.size.width.min(bounds.size.width),
+            height: self.size.height.min(bounds.size.height)
+        };
+        self.with_size(size).clamp_within(bounds)
+    }
+
+    /// The edges of `output` the area touches or reaches past.
+    pub fn anchor_edges_for(self, output: Area) -> AnchorSet {
+        let mut edges = AnchorSet::empty();
+        if self.origin.y <= output.origin.y {
+            edges.insert(AnchorEdge::Top);
+        }
+        if self.bottom() >= output.bottom() {
+            edges.insert(AnchorEdge::Bottom);
+        }
+        if self.origin.x <= output.origin.x {
+            edges.insert(AnchorEdge::Left);
+        }
+        if self.right() >= output.right() {
+            edges.insert(AnchorEdge::Right);
+        }
+        edges
+    }
+
+    /// The distance of each edge of the area from the same edge of `outer`,
+    /// which is negative where the area reaches past it.
+    pub fn margin_within(self, outer: Area) -> Margin {
+        Margin {
+            top: self.origin.y - outer.origin.y,
+            right: outer.right() - self.right(),
+            bottom: outer.bottom() - self.bottom(),
+            left: self.origin.x - outer.origin.x
+        }
+    }
+
+    /// The area without the space `margin` reserves along its edges, e.g.
+    /// the workarea of a screen with struts.
+    ///
+    /// Negative margins reserve nothing, and an area with more reserved
+    /// than it has is left empty at the edge that reserved the most.
+    pub fn inset(self, margin: Margin) -> Area {
+        let inset = |start: i32, length: u32, before: i32, after: i32| {
+            let before = (before.max(0) as u32).min(length);
+            let after = (after.max(0) as u32).min(length - before);
+            (start + before as i32, length - before - after)
+        };
+        let (x, width) = inset(self.origin.x, self.size.width, margin.left, margin.right);
+        let (y, height) = inset(self.origin.y, self.size.height, margin.top, margin.bottom);
+        Area {
+            origin: Origin { x, y },
+            size: Size { width, height }
+        }
+    }
+
+    /// The area scaled by `factor`, which must be positive.
+    ///
+    /// The edges are rounded outwards, so the result covers every pixel
+    /// the exactly scaled area touches.
+    pub fn scale(self, factor: f64) -> Area {
+        let scaled = |value: i32, round: fn(f64) -> f64| {
+            let value = round(value as f64 * factor);
+            // Casting an out of range float isn't defined.
+            value.max(-MAX_COORDINATE as f64).min(MAX_COORDINATE as f64) as i32
+        };
+        let (left, top) = (
+            scaled(self.origin.x, f64::floor),
+            scaled(self.origin.y, f64::floor)
+        );
+        let (right, bottom) = (scaled(self.right(), f64::ceil), scaled(self.bottom(), f64::ceil));
+        Area {
+            origin: Origin { x: left, y: top },
+            size: Size {
+                width: (right - left) as u32,
+                height: (bottom - top) as u32
+            }
+        }
+    }
+
+    /// Where the area of a buffer of size `within` is once `transform` is
+    /// applied to the buffer.
+    pub fn transform(self, transform: Transform, within: Size) -> Area {
+        let mut area = self;
+        let mut bounds = within;
+        if transform.flipped {
+            area.origin.x = bounds.width as i32 - area.right();
+        }
+        for _ in 0..transform.turns {
+            // A quarter turn counter-clockwise moves the right edge to the
+            // top.
+            area = Area {
+                origin: Origin {
+                    x: area.origin.y,
+                    y: bounds.width as i32 - area.right()
+                },
+                size: Size {
+                    width: area.size.height,
+                    height: area.size.width
+                }
+            };
+            bounds = bounds.transform(Transform::QUARTER_TURN);
+        }
+        area
+    }
+
+    pub fn right(self) -> i32 {
+        self.origin.x + self.size.width as i32
+    }
+
+    pub fn bottom(self) -> i32 {
+        self.origin.y + self.size.height as i32
+    }
+}
+
+/// The property signals for the parts of the geometry that changed from
+/// `old` to `new`, followed by `property::geometry` if any of them did.
+pub fn geometry_signals(old: Area, new: Area) -> Vec<&'static str> {
+    if old == new {
+        return Vec::new();
+    }
+    let changes = [
+        ("property::x", old.origin.x != new.origin.x),
+        ("property::y", old.origin.y != new.origin.y),
+        ("property::width", old.size.width != new.size.width),
+        ("property::height", old.size.height != new.size.height)
+    ];
+    let mut signals: Vec<_> = changes
+        .iter()
+        .filter(|&&(_, changed)| changed)
+        .map(|&(signal, _)| signal)
+        .collect();
+    signals.push("property::geometry");
+    signals
 }
 
 #[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
@@ -43,6 +316,20 @@ pub struct Size {
     pub height: u32
 }
 
+impl Size {
+    /// The size of a buffer of this size once `transform` is applied to it.
+    pub fn transform(self, transform: Transform) -> Size {
+        if transform.turns % 2 == 0 {
+            self
+        } else {
+            Size {
+                width: self.height,
+                height: self.width
+            }
+        }
+    }
+}
+
 impl Into<Area> for Size {
     fn into(self) -> Area {
         Area {
@@ -51,3 +338,386 @@ impl Into<Area> for Size {
         }
     }
 }
+
+/// Space around the edges of something, e.g. the distance of a surface from
+/// the edges of the output it is on.
+///
+/// Values can be negative, which places the surface partially outside of
+/// the edge (e.g. for sliding a bar out of view).
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct Margin {
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+    pub left: i32
+}
+
+/// An edge of an output an area can be anchored to.
+#[derive(enumflags2_derive::EnumFlags, Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AnchorEdge {
+    Top = 1 << 0,
+    Bottom = 1 << 1,
+    Left = 1 << 2,
+    Right = 1 << 3
+}
+
+pub type AnchorSet = BitFlags<AnchorEdge>;
+
+/// How the content of an output is rotated and flipped, like
+/// `wl_output.transform`: flipped around the vertical axis first, then
+/// turned counter-clockwise.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct Transform {
+    /// The number of quarter turns, from 0 to 3.
+    pub turns: u8,
+    pub flipped: bool
+}
+
+impl Transform {
+    pub const QUARTER_TURN: Transform = Transform {
+        turns: 1,
+        flipped: false
+    };
+
+    /// The transform with the value of a `wl_output.transform`.
+    pub fn from_raw(raw: u32) -> Transform {
+        Transform {
+            turns: (raw & 0b11) as u8,
+            flipped: raw & 0b100 != 0
+        }
+    }
+}
+
+/// A coordinate or length that can't be part of an area.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct OutOfRange {
+    pub value: i64,
+    pub min: i64,
+    pub max: i64
+}
+
+impl fmt::Display for OutOfRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "expected an integer from {} to {}, got {}",
+            self.min, self.max, self.value
+        )
+    }
+}
+
+/// Checks that `value` can be the coordinate of an area.
+pub fn checked_coordinate(value: i64) -> Result<i32, OutOfRange> {
+    checked(value, -MAX_COORDINATE as i64).map(|value| value as i32)
+}
+
+/// Checks that `value` can be the width or height of an area.
+pub fn checked_length(value: i64) -> Result<u32, OutOfRange> {
+    checked(value, 0).map(|value| value as u32)
+}
+
+fn checked(value: i64, min: i64) -> Result<i64, OutOfRange> {
+    let max = MAX_COORDINATE as i64;
+    if value < min || value > max {
+        Err(OutOfRange { value, min, max })
+    } else {
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::arbitrary::{for_all, Arbitrary};
+    use super::*;
+
+    /// Whether `inner` is within `outer`, edges included.
+    fn within(inner: Area, outer: Area) -> bool {
+        inner.origin.x >= outer.origin.x &&
+            inner.origin.y >= outer.origin.y &&
+            inner.right() <= outer.right() &&
+            inner.bottom() <= outer.bottom()
+    }
+
+    fn pixels(area: Area) -> u64 {
+        area.size.width as u64 * area.size.height as u64
+    }
+
+    fn two_areas(arbitrary: &mut Arbitrary) -> (Area, Area) {
+        (arbitrary.area(), arbitrary.area())
+    }
+
+    #[test]
+    fn area_visible_within() {
+        let output = Area {
+            origin: Origin { x: 100, y: 0 },
+            size: Size {
+                width: 200,
+                height: 100
+            }
+        };
+        let popup = |x, y| Area {
+            origin: Origin { x, y },
+            size: Size {
+                width: 50,
+                height: 40
+            }
+        };
+        assert_eq!(popup(120, 10).visible_within(output), None);
+        assert_eq!(popup(500, 10).visible_within(output), None);
+        // Sliding in from the left edge, and past the bottom.
+        assert_eq!(
+            popup(80, 10).visible_within(output),
+            Some(Area {
+                origin: Origin { x: 20, y: 0 },
+                size: Size {
+                    width: 30,
+                    height: 40
+                }
+            })
+        );
+        assert_eq!(
+            popup(120, 70).visible_within(output),
+            Some(Area {
+                origin: Origin { x: 0, y: 0 },
+                size: Size {
+                    width: 50,
+                    height: 30
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn area_geometry_signals() {
+        let old = Area {
+            origin: Origin { x: 1, y: 2 },
+            size: Size { width: 3, height: 4 }
+        };
+        assert!(geometry_signals(old, old).is_empty());
+        let moved = old.with_origin(Origin { x: 5, y: 2 });
+        assert_eq!(
+            geometry_signals(old, moved),
+            vec!["property::x", "property::geometry"]
+        );
+        let resized = old.with_size(Size { width: 6, height: 7 });
+        assert_eq!(
+            geometry_signals(old, resized),
+            vec!["property::width", "property::height", "property::geometry"]
+        );
+    }
+
+    #[test]
+    fn area_intersection() {
+        for_all(two_areas, |(a, b)| match a.intersection(b) {
+            Some(overlap) => {
+                a.intersects(b) &&
+                    b.intersects(a) &&
+                    within(overlap, a) &&
+                    within(overlap, b) &&
+                    pixels(overlap) > 0
+            },
+            None => !a.intersects(b) && b.intersection(a).is_none()
+        });
+    }
+
+    #[test]
+    fn area_overlap() {
+        for_all(two_areas, |(a, b)| {
+            a.overlap(b) == b.overlap(a) &&
+                a.overlap(b) <= pixels(a) &&
+                a.overlap(b) == a.intersection(b).map(pixels).unwrap_or(0)
+        });
+    }
+
+    #[test]
+    fn area_subtract() {
+        for_all(two_areas, |(a, b)| {
+            let rest = a.subtract(b);
+            let overlap = a.intersection(b).map(pixels).unwrap_or(0);
+            rest.iter()
+                .all(|&piece| within(piece, a) && !piece.intersects(b) && pixels(piece) > 0) &&
+                rest.iter().map(|&piece| pixels(piece)).sum::<u64>() + overlap == pixels(a)
+        });
+    }
+
+    #[test]
+    fn area_clamp_within() {
+        for_all(two_areas, |(area, bounds)| {
+            let clamped = area.clamp_within(bounds);
+            let fits = area.size.width <= bounds.size.width && area.size.height <= bounds.size.height;
+            clamped.size == area.size &&
+                clamped.clamp_within(bounds) == clamped &&
+                (!fits || within(clamped, bounds)) &&
+                (!within(area, bounds) || clamped == area)
+        });
+        // Too long for the bounds, it sticks out past the right and bottom.
+        let bounds = Area {
+            origin: Origin { x: 10, y: 10 },
+            size: Size {
+                width: 100,
+                height: 100
+            }
+        };
+        let long = Area {
+            origin: Origin { x: -50, y: 500 },
+            size: Size {
+                width: 300,
+                height: 20
+            }
+        };
+        assert_eq!(long.clamp_within(bounds).origin, Origin { x: 10, y: 90 });
+    }
+
+    #[test]
+    fn area_fit_within() {
+        for_all(two_areas, |(area, bounds)| {
+            let fitted = area.fit_within(bounds);
+            within(fitted, bounds) && (!within(area, bounds) || fitted == area)
+        });
+    }
+
+    #[test]
+    fn area_anchor_edges() {
+        for_all(two_areas, |(area, output)| {
+            let edges = area.anchor_edges_for(output);
+            let margin = area.margin_within(output);
+            edges.contains(AnchorEdge::Top) == (margin.top <= 0) &&
+                edges.contains(AnchorEdge::Bottom) == (margin.bottom <= 0) &&
+                edges.contains(AnchorEdge::Left) == (margin.left <= 0) &&
+                edges.contains(AnchorEdge::Right) == (margin.right <= 0) &&
+                output.anchor_edges_for(output).is_all()
+        });
+        // A bar as wide as its output, clamped onto it, is on both sides.
+        for_all(two_areas, |(area, output)| {
+            let bar = area.with_size(Size {
+                width: output.size.width,
+                height: area.size.height.min(output.size.height)
+            });
+            let edges = bar.clamp_within(output).anchor_edges_for(output);
+            edges.contains(AnchorEdge::Left | AnchorEdge::Right)
+        });
+    }
+
+    #[test]
+    fn area_inset() {
+        let areas = |arbitrary: &mut Arbitrary| (arbitrary.area(), arbitrary.margin());
+        for_all(areas, |(area, margin)| {
+            let workarea = area.inset(margin);
+            within(workarea, area) && workarea.inset(Margin::default()) == workarea
+        });
+        // Margins that fit are taken off exactly.
+        for_all(areas, |(area, margin)| {
+            let fitting = |value: i32, length: u32| (value.max(0) as u32).min(length / 2);
+            let margin = Margin {
+                top: fitting(margin.top, area.size.height) as i32,
+                right: fitting(margin.right, area.size.width) as i32,
+                bottom: fitting(margin.bottom, area.size.height) as i32,
+                left: fitting(margin.left, area.size.width) as i32
+            };
+            let workarea = area.inset(margin);
+            workarea.margin_within(area) == margin
+        });
+    }
+
+    #[test]
+    fn area_scale_round_trip() {
+        let areas = |arbitrary: &mut Arbitrary| {
+            let area = Area {
+                origin: Origin {
+                    x: arbitrary.small(),
+                    y: arbitrary.small()
+                },
+                size: Size {
+                    width: arbitrary.small().abs() as u32,
+                    height: arbitrary.small().abs() as u32
+                }
+            };
+            (area, arbitrary.factor())
+        };
+        for_all(areas, |(area, factor)| {
+            let scaled = area.scale(factor);
+            let back = scaled.scale(1.0 / factor);
+            let near = |a: i32, b: i32| (a - b).abs() <= 1;
+            // Rounded outwards, the scaled area covers the exact one.
+            scaled.origin.x as f64 <= area.origin.x as f64 * factor &&
+                scaled.right() as f64 >= area.right() as f64 * factor &&
+                near(back.origin.x, area.origin.x) &&
+                near(back.origin.y, area.origin.y) &&
+                near(back.right(), area.right()) &&
+                near(back.bottom(), area.bottom()) &&
+                area.scale(1.0) == area
+        });
+    }
+
+    #[test]
+    fn area_transform() {
+        let areas = |arbitrary: &mut Arbitrary| {
+            let within = arbitrary.area().size;
+            let area = arbitrary.area().clamp_within(within.into());
+            (area, within, arbitrary.transform())
+        };
+        for_all(areas, |(area, size, transform)| {
+            let transformed = area.transform(transform, size);
+            let fits = area.size.width <= size.width && area.size.height <= size.height;
+            let turned = (0..4).fold((area, size), |(area, size), _| {
+                (
+                    area.transform(Transform::QUARTER_TURN, size),
+                    size.transform(Transform::QUARTER_TURN)
+                )
+            });
+            let flip = Transform {
+                turns: 0,
+                flipped: true
+            };
+            transformed.size == area.size.transform(transform) &&
+                (!fits || within(transformed, size.transform(transform).into())) &&
+                turned == (area, size) &&
+                area.transform(flip, size).transform(flip, size) == area
+        });
+        let output = Size {
+            width: 1920,
+            height: 1080
+        };
+        let top_bar = Area {
+            origin: Origin { x: 0, y: 0 },
+            size: Size {
+                width: 1920,
+                height: 30
+            }
+        };
+        // Turned counter-clockwise, the top of the output is on the left.
+        let turned = top_bar.transform(Transform::from_raw(1), output);
+        assert_eq!(turned.origin, Origin { x: 0, y: 0 });
+        assert_eq!(
+            turned.size,
+            Size {
+                width: 30,
+                height: 1920
+            }
+        );
+        assert_eq!(
+            output.transform(Transform::from_raw(7)),
+            Size {
+                width: 1080,
+                height: 1920
+            }
+        );
+    }
+
+    #[test]
+    fn area_checked() {
+        for_all(
+            |arbitrary| arbitrary.range(-(1 << 40), 1 << 40),
+            |value| {
+                let in_range = value.abs() <= MAX_COORDINATE as i64;
+                checked_coordinate(value).is_ok() == in_range &&
+                    checked_length(value).is_ok() == (in_range && value >= 0)
+            }
+        );
+        assert_eq!(
+            checked_length(-1).unwrap_err().to_string(),
+            "expected an integer from 0 to 16777216, got -1"
+        );
+    }
+}
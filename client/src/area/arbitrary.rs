@@ -0,0 +1,122 @@
+//! Arbitrary geometry for property tests.
+//!
+//! The values come from a seeded generator, so a failing case is the same
+//! on every run. Values at the limits, and zero, are picked more often than
+//! they would be by chance, since that's where the bugs are.
+
+use std::fmt::Debug;
+
+use super::{Area, Margin, Origin, Size, Transform, MAX_COORDINATE};
+
+/// The number of cases each property is checked with.
+pub const CASES: usize = 2000;
+
+pub struct Arbitrary {
+    state: u64
+}
+
+impl Arbitrary {
+    pub fn new(seed: u64) -> Self {
+        // xorshift gets stuck at 0.
+        Arbitrary {
+            state: seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1
+        }
+    }
+
+    /// The next value of the xorshift64* generator.
+    pub fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// An integer from `min` to `max`, both included.
+    pub fn range(&mut self, min: i64, max: i64) -> i64 {
+        match self.next() % 16 {
+            0 => min,
+            1 => max,
+            2 if min <= 0 && 0 <= max => 0,
+            _ => min + (self.next() % (max - min + 1) as u64) as i64
+        }
+    }
+
+    pub fn coordinate(&mut self) -> i32 {
+        self.range(-MAX_COORDINATE as i64, MAX_COORDINATE as i64) as i32
+    }
+
+    pub fn length(&mut self) -> u32 {
+        self.range(0, MAX_COORDINATE as i64) as u32
+    }
+
+    /// A coordinate or length of the size outputs have, where rounding
+    /// matters more than overflow.
+    pub fn small(&mut self) -> i32 {
+        self.range(-4096, 4096) as i32
+    }
+
+    pub fn area(&mut self) -> Area {
+        if self.next() % 2 == 0 {
+            Area {
+                origin: Origin {
+                    x: self.coordinate(),
+                    y: self.coordinate()
+                },
+                size: Size {
+                    width: self.length(),
+                    height: self.length()
+                }
+            }
+        } else {
+            Area {
+                origin: Origin {
+                    x: self.small(),
+                    y: self.small()
+                },
+                size: Size {
+                    width: self.small().abs() as u32,
+                    height: self.small().abs() as u32
+                }
+            }
+        }
+    }
+
+    pub fn margin(&mut self) -> Margin {
+        Margin {
+            top: self.coordinate(),
+            right: self.coordinate(),
+            bottom: self.coordinate(),
+            left: self.coordinate()
+        }
+    }
+
+    pub fn transform(&mut self) -> Transform {
+        Transform::from_raw(self.next() as u32 % 8)
+    }
+
+    /// A factor between 1 and 4, like the scale of an output, or an
+    /// awkward fraction.
+    pub fn factor(&mut self) -> f64 {
+        match self.next() % 4 {
+            0 => (1 + self.next() % 4) as f64,
+            _ => 1.0 + (self.next() % 3000) as f64 / 1000.0
+        }
+    }
+}
+
+/// Checks `property` with `CASES` values made by `generate`, panicking with
+/// the first value it doesn't hold for.
+pub fn for_all<T, G, P>(generate: G, property: P)
+where
+    T: Debug + Clone,
+    G: Fn(&mut Arbitrary) -> T,
+    P: Fn(T) -> bool
+{
+    let mut arbitrary = Arbitrary::new(CASES as u64);
+    for case in 0..CASES {
+        let value = generate(&mut arbitrary);
+        if !property(value.clone()) {
+            panic!("property doesn't hold for case {}: {:?}", case, value);
+        }
+    }
+}
@@ -19,11 +19,29 @@ use xcb::{
     xkb, Connection
 };
 
+use crate::clock;
 use crate::common::{
+    connection,
+    schema::STRICT_ARGUMENTS,
     signal,
     xproperty::{XProperty, XPropertyType, PROPERTIES}
 };
+use crate::control;
+use crate::crash;
+use crate::event_trace;
+use crate::json;
+use crate::l10n;
+use crate::leaks;
 use crate::lua::NEXT_LUA;
+use crate::memory_pressure;
+use crate::objects::{drawable, drawin};
+use crate::picker;
+use crate::resume;
+use crate::scheduler;
+use crate::self_test;
+use crate::spawn_lines;
+use crate::svg;
+use crate::text_extents;
 use crate::XCB_CONNECTION_HANDLE;
 
 // TODO FIXME
@@ -90,6 +108,8 @@ pub fn init<'lua>(lua: rlua::Context<'lua>) -> rlua::Result<()> {
     global_string.set("wlen", lua.create_function(wlen)?)?;
     // TODO Add the rest of fixups
 
+    clock::start();
+
     let awesome = lua.create_userdata(AwesomeState::default())?;
     awesome.set_user_value(awesome_table)?;
     globals.set("awesome", awesome)
@@ -121,9 +141,73 @@ fn method_setup<'lua>(lua: rlua::Context<'lua>, awesome_table: &Table<'lua>) ->
     awesome_table.set("restart", lua.create_function(restart)?)?;
     awesome_table.set("load_image", lua.create_function(load_image)?)?;
     awesome_table.set("pixbuf_to_surface", lua.create_function(pixbuf_to_surface)?)?;
+    awesome_table.set("load_svg", lua.create_function(svg::load_svg)?)?;
+    awesome_table.set("svg_cache_stats", lua.create_function(svg::svg_cache_stats)?)?;
+    awesome_table.set("json_decode", lua.create_function(json::json_decode)?)?;
+    awesome_table.set("json_encode", lua.create_function(json::json_encode)?)?;
+    awesome_table.set("json_array", lua.create_function(json::json_array)?)?;
+    awesome_table.set("json_object", lua.create_function(json::json_object)?)?;
+    awesome_table.set("json_null", json::null(lua)?)?;
+    awesome_table.set("translate", lua.create_function(l10n::translate)?)?;
     awesome_table.set("sync", lua.create_function(sync)?)?;
+    awesome_table.set("self_test", lua.create_function(self_test::self_test)?)?;
+    awesome_table.set("text_extents", lua.create_function(text_extents::text_extents)?)?;
+    awesome_table.set(
+        "text_extents_multi",
+        lua.create_function(text_extents::text_extents_multi)?
+    )?;
     awesome_table.set("exec", lua.create_function(exec)?)?;
-    awesome_table.set("spawn", lua.create_function(crate::objects::dummy)?)?;
+    awesome_table.set("spawn", lua.create_function(spawn)?)?;
+    awesome_table.set(
+        "spawn_with_line_callback",
+        lua.create_function(spawn_lines::spawn_with_line_callback)?
+    )?;
+    awesome_table.set(
+        "spawn_with_input",
+        lua.create_function(spawn_lines::spawn_with_input)?
+    )?;
+    awesome_table.set("idle_add", lua.create_function(scheduler::idle_add)?)?;
+    awesome_table.set("defer", lua.create_function(scheduler::defer_lua)?)?;
+    awesome_table.set(
+        "set_frame_budget",
+        lua.create_function(scheduler::set_frame_budget)?
+    )?;
+    awesome_table.set(
+        "scheduler_stats",
+        lua.create_function(scheduler::scheduler_stats)?
+    )?;
+    awesome_table.set(
+        "invalidate_snapshots",
+        lua.create_function(drawable::invalidate_snapshots)?
+    )?;
+    awesome_table.set("now", lua.create_function(clock::lua_now)?)?;
+    awesome_table.set(
+        "connection_stats",
+        lua.create_function(connection::connection_stats)?
+    )?;
+    awesome_table.set("leak_report", lua.create_function(leaks::leak_report)?)?;
+    awesome_table.set("sweep_leaks", lua.create_function(leaks::sweep_leaks)?)?;
+    awesome_table.set("set_leak_sweep", lua.create_function(leaks::set_leak_sweep)?)?;
+    awesome_table.set(
+        "memory_usage",
+        lua.create_function(memory_pressure::memory_usage)?
+    )?;
+    awesome_table.set(
+        "set_memory_pressure",
+        lua.create_function(memory_pressure::set_memory_pressure)?
+    )?;
+    awesome_table.set("record_events", lua.create_function(event_trace::record_events)?)?;
+    awesome_table.set(
+        "stop_recording_events",
+        lua.create_function(event_trace::stop_recording_events)?
+    )?;
+    awesome_table.set("control_socket", lua.create_function(control::control_socket)?)?;
+    awesome_table.set("pick_geometry", lua.create_function(picker::pick_geometry)?)?;
+    awesome_table.set("resume_safely", lua.create_function(resume::resume_safely)?)?;
+    awesome_table.set(
+        "last_crash_report",
+        lua.create_function(crash::last_crash_report)?
+    )?;
     awesome_table.set("kill", lua.create_function(kill)?)?;
     awesome_table.set("quit", lua.create_function(quit)?)
 }
@@ -132,6 +216,7 @@ fn property_setup<'lua>(_: rlua::Context<'lua>, awesome_table: &Table<'lua>) ->
     // TODO Do properly
     awesome_table.set("version", "0")?;
     awesome_table.set("themes_path", "/usr/share/awesome/themes")?;
+    awesome_table.set(STRICT_ARGUMENTS, false)?;
     awesome_table.set("conffile", "")
 }
 
@@ -247,8 +332,11 @@ fn systray<'lua>(_: rlua::Context<'lua>, _: ()) -> rlua::Result<(u32, Value<'lua
 }
 
 /// Restart Awesome by restarting the Lua thread
-fn restart<'lua>(_: rlua::Context<'lua>, _: ()) -> rlua::Result<()> {
+fn restart<'lua>(lua: rlua::Context<'lua>, _: ()) -> rlua::Result<()> {
     info!("Lua thread restarting");
+    if let Err(err) = drawin::save_persisted_content(lua) {
+        warn!("Could not save the content of drawins: {}", err);
+    }
     NEXT_LUA.with(|next_lua| {
         next_lua.set(true);
     });
@@ -260,26 +348,14 @@ fn restart<'lua>(_: rlua::Context<'lua>, _: ()) -> rlua::Result<()> {
 fn load_image<'lua>(lua: rlua::Context<'lua>, file_path: String) -> rlua::Result<Value<'lua>> {
     let pixbuf = Pixbuf::new_from_file(file_path.as_str())
         .map_err(|err| rlua::Error::RuntimeError(format!("{}", err)))?;
-    let surface = load_surface_from_pixbuf(pixbuf);
-    // UGH, I wanted to do to_glib_full, but that isn't defined apparently
-    // So now I have to ignore the lifetime completely and just forget about the
-    // surface.
-    let surface_ptr = surface.to_glib_none().0;
-    ::std::mem::forget(surface);
-    LightUserData(surface_ptr as _).to_lua(lua)
+    surface_to_lua(lua, load_surface_from_pixbuf(pixbuf))
 }
 
 /// Convert a pixbuf to a cairo image surface.
 /// Returns either a cairo surface as light user data, nil and an error message
 fn pixbuf_to_surface<'lua>(lua: rlua::Context<'lua>, pixbuf: LightUserData) -> rlua::Result<Value<'lua>> {
     let pixbuf = unsafe { Pixbuf::from_glib_none(pixbuf.0 as *const _) };
-    let surface = load_surface_from_pixbuf(pixbuf);
-    // UGH, I wanted to do to_glib_full, but that isn't defined apparently
-    // So now I have to ignore the lifetime completely and just forget about the
-    // surface.
-    let surface_ptr = surface.to_glib_none().0;
-    ::std::mem::forget(surface);
-    LightUserData(surface_ptr as _).to_lua(lua)
+    surface_to_lua(lua, load_surface_from_pixbuf(pixbuf))
 }
 
 fn exec(_: rlua::Context<'_>, command: String) -> rlua::Result<()> {
@@ -297,6 +373,99 @@ fn exec(_: rlua::Context<'_>, command: String) -> rlua::Result<()> {
     Ok(())
 }
 
+/// The arguments of a command given to one of the spawn functions, either
+/// as a string split like a shell would, see `split_command`, or as a table
+/// of arguments.
+pub fn command_argv(command: Value) -> rlua::Result<Vec<String>> {
+    match command {
+        Value::String(command) => split_command(command.to_str()?)
+            .map_err(|err| rlua::Error::RuntimeError(format!("spawn: {}", err))),
+        Value::Table(command) => command.sequence_values().collect(),
+        _ => Err(rlua::Error::RuntimeError("spawn: invalid command".into()))
+    }
+}
+
+/// Splits a command into its arguments like a shell would, as awesome does,
+/// without expanding anything.
+///
+/// Arguments are separated by whitespace. Single quotes keep everything up
+/// to the next single quote. Double quotes keep everything up to the next
+/// unescaped double quote, where a backslash escapes a double quote, a
+/// backslash, a dollar sign or a backtick. Outside of quotes a backslash
+/// keeps the next character. A backslash before a newline drops both.
+fn split_command(command: &str) -> Result<Vec<String>, &'static str> {
+    let mut argv = Vec::new();
+    // The argument being read, if one started.
+    let mut arg: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => argv.extend(arg.take()),
+            '\'' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => return Err("unterminated single quote")
+                    }
+                }
+            },
+            '"' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ '"') | Some(c @ '\\') | Some(c @ '$') | Some(c @ '`') => arg.push(c),
+                            Some('\n') => {},
+                            Some(c) => {
+                                arg.push('\\');
+                                arg.push(c)
+                            },
+                            None => return Err("unterminated double quote")
+                        },
+                        Some(c) => arg.push(c),
+                        None => return Err("unterminated double quote")
+                    }
+                }
+            },
+            '\\' => match chars.next() {
+                Some('\n') => {},
+                Some(c) => arg.get_or_insert_with(String::new).push(c),
+                None => return Err("backslash at the end of the command")
+            },
+            c => arg.get_or_insert_with(String::new).push(c)
+        }
+    }
+    argv.extend(arg);
+    Ok(argv)
+}
+
+/// Spawns a command without a shell, returning its PID.
+///
+/// The command can either be a string, which is split like a shell would,
+/// or a table of arguments. On failure the error message is returned
+/// instead.
+fn spawn<'lua>(lua: rlua::Context<'lua>, command: Value<'lua>) -> rlua::Result<Value<'lua>> {
+    let argv = command_argv(command)?;
+    let (program, args) = match argv.split_first() {
+        Some(split) => split,
+        None => return "spawn: empty command".to_lua(lua)
+    };
+    trace!("spawn: {:?}", argv);
+    let mut child = match Command::new(program).args(args).stdin(Stdio::null()).spawn() {
+        Ok(child) => child,
+        Err(err) => return format!("{}", err).to_lua(lua)
+    };
+    let pid = child.id();
+    thread::Builder::new()
+        .name(program.clone())
+        .spawn(move || child.wait())
+        .expect("Unable to spawn thread");
+    pid.to_lua(lua)
+}
+
 /// Kills a PID with the given signal
 ///
 /// Returns false if it could not send the signal to that process
@@ -304,6 +473,23 @@ fn kill(_: rlua::Context<'_>, (pid, sig): (libc::pid_t, libc::c_int)) -> rlua::R
     Ok(nix::sys::signal::kill(pid, sig).is_ok())
 }
 
+/// Gives a reference to the surface to Lua, as light user data.
+pub fn surface_to_lua<'lua>(lua: rlua::Context<'lua>, surface: ImageSurface) -> rlua::Result<Value<'lua>> {
+    // UGH, I wanted to do to_glib_full, but that isn't defined apparently
+    // So now I have to ignore the lifetime completely and just forget about the
+    // surface.
+    let surface_ptr = surface.to_glib_none().0;
+    ::std::mem::forget(surface);
+    LightUserData(surface_ptr as _).to_lua(lua)
+}
+
+/// The size icons should be loaded at, 0 if the config didn't say.
+pub fn preferred_icon_size(lua: rlua::Context<'_>) -> rlua::Result<u32> {
+    let awesome_state = lua.globals().get::<_, AnyUserData>("awesome")?;
+    let awesome_state = awesome_state.borrow::<AwesomeState>()?;
+    Ok(awesome_state.preferred_icon_size)
+}
+
 fn set_preferred_icon_size(lua: rlua::Context<'_>, val: u32) -> rlua::Result<()> {
     let awesome_state = lua.globals().get::<_, AnyUserData>("awesome")?;
     let mut awesome_state = awesome_state.borrow_mut::<AwesomeState>()?;
@@ -423,3 +609,25 @@ pub fn load_surface_from_pixbuf(pixbuf: Pixbuf) -> ImageSurface {
 pub fn wlen<'lua>(_: rlua::Context<'lua>, cmd: String) -> rlua::Result<Value<'lua>> {
     Ok(Value::Integer(cmd.chars().count() as i64))
 }
+
+#[cfg(test)]
+mod test {
+    use super::split_command;
+
+    #[test]
+    fn split_command_quotes() {
+        assert_eq!(split_command("  xterm  -e top ").unwrap(), ["xterm", "-e", "top"]);
+        assert_eq!(split_command("sh -c 'a b'").unwrap(), ["sh", "-c", "a b"]);
+        assert_eq!(
+            split_command(r#"notify-send "it's \"done\"" \$HOME a\ b ''"#).unwrap(),
+            ["notify-send", "it's \"done\"", "$HOME", "a b", ""]
+        );
+        // Quotes only end a part of an argument.
+        assert_eq!(split_command(r#"--name="a b"'c'd"#).unwrap(), ["--name=a bcd"]);
+        assert_eq!(split_command(r#""\n""#).unwrap(), ["\\n"]);
+        assert_eq!(split_command("").unwrap(), Vec::<String>::new());
+        assert!(split_command("sh -c 'a b").is_err());
+        assert!(split_command(r#"echo "a"#).is_err());
+        assert!(split_command("echo \\").is_err());
+    }
+}
@@ -0,0 +1,86 @@
+//! A read-only view of the drawins for Rust code linked into the client,
+//! e.g. compositor-side code that wants to know where the bars are without
+//! scraping the Wayland protocol or the logs.
+//!
+//! Only built with the `client-api` feature.
+//!
+//! # Consistency
+//!
+//! The drawins are published after every callback into the event loop has
+//! returned, so a snapshot is always taken between Lua callbacks: it never
+//! shows a drawin in the middle of being changed by Lua. Several changes
+//! made by one callback are seen as one.
+//!
+//! Everything happens on the client's thread. Subscribers are called there
+//! with the events of a publication, after [`drawins`] was updated, so they
+//! can call it to look at the other drawins.
+//!
+//! # Stability
+//!
+//! The types are versioned by [`API_VERSION`]. New fields and variants are
+//! only added in minor versions, which is why they are `#[non_exhaustive]`.
+//! Anything else is a major version.
+
+mod registry;
+
+use std::cell::RefCell;
+
+use crate::objects::drawin::{Drawin, DRAWINS_HANDLE};
+
+use self::registry::Registry;
+pub use self::registry::{DrawinEvent, DrawinSnapshot, Layer};
+
+/// The (major, minor) version of this API.
+pub const API_VERSION: (u32, u32) = (1, 0);
+
+thread_local! {
+    static REGISTRY: RefCell<Registry> = RefCell::new(Registry::default());
+    static SUBSCRIBERS: RefCell<Vec<fn(DrawinEvent)>> = RefCell::new(Vec::new());
+}
+
+/// The drawins as of the last time they were published.
+pub fn drawins() -> Vec<DrawinSnapshot> {
+    REGISTRY.with(|registry| registry.borrow().drawins().to_vec())
+}
+
+/// Calls `callback` with every drawin that is added, changed or removed
+/// from now on.
+///
+/// The drawins that already exist are not replayed, use [`drawins`] for
+/// them.
+pub fn subscribe(callback: fn(DrawinEvent)) {
+    SUBSCRIBERS.with(|subscribers| subscribers.borrow_mut().push(callback))
+}
+
+/// Publishes the current state of the drawins, notifying the subscribers
+/// of what changed.
+///
+/// Called after every callback into the event loop.
+pub fn publish(lua: rlua::Context) {
+    let drawins = match snapshots(lua) {
+        Ok(drawins) => drawins,
+        Err(err) => {
+            warn!("Could not take a snapshot of the drawins: {}", err);
+            return;
+        }
+    };
+    let events = REGISTRY.with(|registry| registry.borrow_mut().publish(drawins));
+    if events.is_empty() {
+        return;
+    }
+    // Subscribers may subscribe others, so don't hold on to the list.
+    let subscribers = SUBSCRIBERS.with(|subscribers| subscribers.borrow().clone());
+    for event in events {
+        for subscriber in &subscribers {
+            subscriber(event.clone());
+        }
+    }
+}
+
+fn snapshots(lua: rlua::Context) -> rlua::Result<Vec<DrawinSnapshot>> {
+    // Before the libraries are set up there are no drawins.
+    match lua.named_registry_value::<str, Option<Vec<Drawin>>>(DRAWINS_HANDLE)? {
+        Some(drawins) => drawins.iter().map(|drawin| drawin.snapshot(lua)).collect(),
+        None => Ok(Vec::new())
+    }
+}
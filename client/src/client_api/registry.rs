@@ -0,0 +1,173 @@
+//! The snapshots of the drawins as they were last published, and the
+//! events that bring a subscriber from one publication to the next.
+
+use crate::area::{Area, Margin};
+
+/// The layer shell layer a drawin is shown on.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Layer {
+    Background,
+    Bottom,
+    Top,
+    Overlay
+}
+
+/// The state of a drawin at the time it was published.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct DrawinSnapshot {
+    /// The id of the drawin, which is never reused while the client runs.
+    pub id: usize,
+    pub geometry: Area,
+    /// The space the drawin reserves on the edges of outputs it owns.
+    pub struts: Margin,
+    pub layer: Layer,
+    /// The name of the output the drawin is on, if it's on any.
+    pub output: Option<String>,
+    pub visible: bool,
+    /// Whether the drawin has been shown with content painted by Lua.
+    pub first_paint: bool
+}
+
+/// A change between two publications of the drawins.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum DrawinEvent {
+    Added(DrawinSnapshot),
+    Changed(DrawinSnapshot),
+    /// The drawin with this id doesn't exist anymore.
+    Removed(usize)
+}
+
+#[derive(Debug, Default)]
+pub struct Registry {
+    drawins: Vec<DrawinSnapshot>
+}
+
+impl Registry {
+    pub fn drawins(&self) -> &[DrawinSnapshot] {
+        &self.drawins
+    }
+
+    /// Replaces the published drawins with `drawins`, returning the events
+    /// describing the difference.
+    ///
+    /// Removals come first, then additions and changes in the order of
+    /// `drawins`.
+    pub fn publish(&mut self, drawins: Vec<DrawinSnapshot>) -> Vec<DrawinEvent> {
+        let mut events: Vec<DrawinEvent> = self
+            .drawins
+            .iter()
+            .filter(|old| drawins.iter().all(|new| new.id != old.id))
+            .map(|old| DrawinEvent::Removed(old.id))
+            .collect();
+        for new in &drawins {
+            match self.drawins.iter().find(|old| old.id == new.id) {
+                None => events.push(DrawinEvent::Added(new.clone())),
+                Some(old) if old != new => events.push(DrawinEvent::Changed(new.clone())),
+                Some(_) => {}
+            }
+        }
+        self.drawins = drawins;
+        events
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::area::Size;
+
+    fn drawin(id: usize) -> DrawinSnapshot {
+        DrawinSnapshot {
+            id,
+            geometry: Area::default(),
+            struts: Margin::default(),
+            layer: Layer::Top,
+            output: None,
+            visible: false,
+            first_paint: false
+        }
+    }
+
+    #[test]
+    fn registry_publish_diffs() {
+        let mut registry = Registry::default();
+        assert_eq!(registry.publish(vec![]), vec![]);
+        assert_eq!(
+            registry.publish(vec![drawin(1), drawin(2)]),
+            vec![DrawinEvent::Added(drawin(1)), DrawinEvent::Added(drawin(2)),]
+        );
+        // Publishing the same state again is silent.
+        assert_eq!(registry.publish(vec![drawin(1), drawin(2)]), vec![]);
+        let visible = DrawinSnapshot {
+            visible: true,
+            ..drawin(2)
+        };
+        assert_eq!(
+            registry.publish(vec![drawin(3), visible.clone()]),
+            vec![
+                DrawinEvent::Removed(1),
+                DrawinEvent::Added(drawin(3)),
+                DrawinEvent::Changed(visible.clone()),
+            ]
+        );
+        assert_eq!(registry.drawins(), &[drawin(3), visible][..]);
+    }
+
+    /// The lifecycle of a bar: created by the config, shown, painted by Lua,
+    /// hidden and then gone.
+    #[test]
+    fn registry_bar_lifecycle() {
+        let mut registry = Registry::default();
+        let created = DrawinSnapshot {
+            geometry: Area {
+                size: Size {
+                    width: 1920,
+                    height: 20
+                },
+                ..Area::default()
+            },
+            ..drawin(7)
+        };
+        let shown = DrawinSnapshot {
+            visible: true,
+            output: Some("DP-1".into()),
+            struts: Margin {
+                top: 20,
+                ..Margin::default()
+            },
+            ..created.clone()
+        };
+        let painted = DrawinSnapshot {
+            first_paint: true,
+            ..shown.clone()
+        };
+        let hidden = DrawinSnapshot {
+            visible: false,
+            struts: Margin::default(),
+            ..painted.clone()
+        };
+        let events: Vec<_> = vec![
+            vec![created.clone()],
+            vec![shown.clone()],
+            vec![painted.clone()],
+            vec![hidden.clone()],
+            vec![],
+        ]
+        .into_iter()
+        .flat_map(|drawins| registry.publish(drawins))
+        .collect();
+        assert_eq!(
+            events,
+            vec![
+                DrawinEvent::Added(created),
+                DrawinEvent::Changed(shown),
+                DrawinEvent::Changed(painted),
+                DrawinEvent::Changed(hidden),
+                DrawinEvent::Removed(7),
+            ]
+        );
+    }
+}
@@ -0,0 +1,251 @@
+//! The time given to Lua: milliseconds since the client started, on the
+//! monotonic clock, as a float.
+//!
+//! `awesome.now()` has microsecond precision. The compositor timestamps
+//! events in milliseconds with an undefined base in a 32 bit integer, which
+//! wraps around after about 49 days. Every compositor we know of uses the
+//! monotonic clock for them, which is checked with the first event: if its
+//! time is close to the monotonic clock, event times are unwrapped against
+//! the monotonic clock, so they are right no matter how long there wasn't
+//! an event. Otherwise the first event is taken to have happened when it
+//! was received, and later ones are unwrapped against the one before, which
+//! is right as long as there are no 24 days without an event.
+//!
+//! Event times never go backwards, an event that claims to be older than
+//! the one before it is given the time of that one.
+
+use std::cell::RefCell;
+
+/// How far the time of the first event may be from the monotonic clock for
+/// events to be taken to be on it, in milliseconds. Events can wait in the
+/// queue for a while when Lua is busy.
+const SAME_CLOCK_TOLERANCE: u64 = 10_000;
+
+/// Half of the range of the timestamps of events.
+const HALF_WRAP: u64 = 1 << 31;
+
+thread_local! {
+    static CLOCK: RefCell<Clock> = RefCell::new(Clock::new(monotonic()));
+}
+
+/// The clock of event timestamps.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Domain {
+    /// The monotonic clock, in milliseconds.
+    Monotonic,
+    /// Some other clock.
+    Foreign {
+        /// What to add to the time of an event for the monotonic clock.
+        offset: i64,
+        /// The unwrapped time of the last event.
+        last: u64
+    }
+}
+
+/// Converts times to milliseconds since the clock was made.
+#[derive(Debug)]
+pub struct Clock {
+    /// The monotonic clock when the clock was made, in microseconds.
+    startup: u64,
+    /// The clock of events, known once there was one.
+    domain: Option<Domain>,
+    /// The time of the last event.
+    last: f64
+}
+
+impl Clock {
+    /// A clock that starts at `startup`, the monotonic clock in
+    /// microseconds.
+    pub fn new(startup: u64) -> Self {
+        Clock {
+            startup,
+            domain: None,
+            last: 0.0
+        }
+    }
+
+    /// The time at `monotonic`, the monotonic clock in microseconds.
+    pub fn at(&self, monotonic: u64) -> f64 {
+        monotonic.saturating_sub(self.startup) as f64 / 1000.0
+    }
+
+    /// The time of an event timestamped with `time`, received at
+    /// `monotonic`, the monotonic clock in microseconds.
+    pub fn event_time(&mut self, time: u32, monotonic: u64) -> f64 {
+        let now = monotonic / 1000;
+        let domain = *self.domain.get_or_insert_with(|| {
+            let unwrapped = unwrap(time, now);
+            if unwrapped.max(now) - unwrapped.min(now) <= SAME_CLOCK_TOLERANCE {
+                Domain::Monotonic
+            } else {
+                Domain::Foreign {
+                    offset: now as i64 - u64::from(time) as i64,
+                    last: u64::from(time)
+                }
+            }
+        });
+        let millis = match domain {
+            Domain::Monotonic => unwrap(time, now) as i64,
+            Domain::Foreign { offset, last } => {
+                let unwrapped = unwrap(time, last);
+                self.domain = Some(Domain::Foreign {
+                    offset,
+                    last: unwrapped
+                });
+                unwrapped as i64 + offset
+            }
+        };
+        let since_startup =
+            (millis - (self.startup / 1000) as i64) as f64 - (self.startup % 1000) as f64 / 1000.0;
+        self.last = self.last.max(since_startup);
+        self.last
+    }
+}
+
+/// The time `time` wrapped around from, closest to `reference`.
+fn unwrap(time: u32, reference: u64) -> u64 {
+    let time = (reference & !0xffff_ffff) | u64::from(time);
+    if time > reference + HALF_WRAP && time > 0xffff_ffff {
+        time - (1 << 32)
+    } else if time + HALF_WRAP < reference {
+        time + (1 << 32)
+    } else {
+        time
+    }
+}
+
+/// The monotonic clock, in microseconds.
+pub fn monotonic() -> u64 {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0
+    };
+    // Can't fail with a valid clock and pointer.
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    now.tv_sec as u64 * 1_000_000 + now.tv_nsec as u64 / 1000
+}
+
+/// Milliseconds since the client started.
+pub fn now() -> f64 {
+    CLOCK.with(|clock| clock.borrow().at(monotonic()))
+}
+
+/// The time of an event the compositor timestamped with `time`, in
+/// milliseconds since the client started.
+pub fn event_time(time: u32) -> f64 {
+    CLOCK.with(|clock| clock.borrow_mut().event_time(time, monotonic()))
+}
+
+/// A timestamp for the compositor, on the monotonic clock like its own.
+pub fn timestamp() -> u32 {
+    (monotonic() / 1000) as u32
+}
+
+/// Starts the clock, so times are from when the client started rather
+/// than from when they were first needed.
+pub fn start() {
+    CLOCK.with(|_| ())
+}
+
+/// `awesome.now()`, the time in milliseconds since the client started.
+pub fn lua_now(_: rlua::Context, _: ()) -> rlua::Result<f64> {
+    Ok(now())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const WRAP: u64 = 1 << 32;
+    const DAY: u64 = 24 * 60 * 60 * 1000;
+
+    /// A clock started `before` milliseconds before the monotonic clock
+    /// wraps around in 32 bits.
+    fn clock_before_wrap(wraps: u64, before: u64) -> (Clock, u64) {
+        let startup = (wraps * WRAP - before) * 1000;
+        (Clock::new(startup), startup)
+    }
+
+    #[test]
+    fn clock_unwrap() {
+        assert_eq!(unwrap(5, 10), 5);
+        assert_eq!(unwrap(0xffff_fff0, 10), 0xffff_fff0);
+        assert_eq!(unwrap(0xffff_fff0, WRAP + 10), 0xffff_fff0);
+        assert_eq!(unwrap(10, WRAP - 10), WRAP + 10);
+        assert_eq!(unwrap(10, 3 * WRAP + 10), 3 * WRAP + 10);
+    }
+
+    #[test]
+    fn clock_monotonic_across_wrap() {
+        let (mut clock, startup) = clock_before_wrap(3, 5000);
+        let mut last = -1.0;
+        for step in 0..100u64 {
+            let millis = startup / 1000 + step * 100;
+            // Received a little late, as events are.
+            let time = clock.event_time(millis as u32, (millis + 3) * 1000);
+            assert!(time > last, "{} went back to {}", last, time);
+            assert_eq!(time, (step * 100) as f64);
+            last = time;
+        }
+    }
+
+    #[test]
+    fn clock_long_idle() {
+        // Longer than the timestamps can count without an event, which the
+        // monotonic clock gets right.
+        let (mut clock, startup) = clock_before_wrap(1, 1000);
+        let start = startup / 1000;
+        assert_eq!(clock.event_time(start as u32, startup), 0.0);
+        let later = start + 60 * DAY;
+        assert_eq!(
+            clock.event_time(later as u32, later * 1000),
+            (later - start) as f64
+        );
+    }
+
+    #[test]
+    fn clock_foreign_domain() {
+        let (mut clock, startup) = clock_before_wrap(2, 0);
+        let start = startup / 1000;
+        // Timestamps that are a day from the monotonic clock and wrap
+        // around after a second.
+        let mut last = -1.0;
+        for step in 0..30u64 {
+            let sent = WRAP - 1000 + step * 100;
+            let time = clock.event_time(sent as u32, (start + DAY + 5 + step * 100) * 1000);
+            assert!(time > last, "{} went back to {}", last, time);
+            assert_eq!(time, (DAY + 5 + step * 100) as f64);
+            last = time;
+        }
+        // Out of order, which doesn't make time go backwards.
+        assert_eq!(
+            clock.event_time((WRAP - 2000) as u32, (start + DAY + 3000) * 1000),
+            last
+        );
+    }
+
+    #[test]
+    fn clock_frame_times_match_now() {
+        // Frame callbacks are timestamped with the monotonic clock by the
+        // compositor, in whole milliseconds.
+        let startup = monotonic();
+        let mut clock = Clock::new(startup);
+        for _ in 0..5 {
+            let before = clock.at(monotonic());
+            let time = clock.event_time(timestamp(), monotonic());
+            let after = clock.at(monotonic());
+            assert!(
+                time >= before - 1.0 && time <= after + 1.0,
+                "{} not in {}..{}",
+                time,
+                before,
+                after
+            );
+            std::thread::sleep(std::time::Duration::from_millis(3));
+        }
+        let frame = clock.event_time(timestamp(), monotonic());
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let next = clock.event_time(timestamp(), monotonic());
+        assert!((next - frame - 20.0).abs() < 10.0, "{} after {}", next, frame);
+    }
+}
@@ -3,7 +3,8 @@
 use std::{convert::From, marker::PhantomData, sync::Arc};
 
 use rlua::{
-    self, AnyUserData, FromLua, Function, MetaMethod, Table, ToLua, UserData, UserDataMethods, Value
+    self, AnyUserData, FromLua, FromLuaMulti, Function, MetaMethod, Table, ToLua, ToLuaMulti, UserData,
+    UserDataMethods, Value
 };
 
 use super::{
@@ -83,6 +84,158 @@ impl<'lua, S: ObjectStateType> ClassBuilder<'lua, S> {
     }
 }
 
+/// Defines a class with less boilerplate than the `ClassBuilder`, from
+/// plain Rust functions.
+///
+/// Property setters emit `property::<name>` on the object after every
+/// assignment, including the one from the constructor arguments, unless
+/// `without_property_signals` was called. The methods of objects are added
+/// to every object the class allocates.
+///
+/// The objects get `__index`, `__newindex` and `__tostring` from
+/// `object::default_add_methods` and can be compared by the id of their
+/// state with `add_eq_by_id`, both in their `UserData` implementation.
+/// When an object is collected its state is dropped, so `Drop` is the
+/// `__gc` hook. Anything else can be done on the builder with `raw`.
+pub struct ClassDef<'lua, S: ObjectStateType> {
+    lua: rlua::Context<'lua>,
+    name: String,
+    builder: ClassBuilder<'lua, S>,
+    object_methods: Table<'lua>,
+    property_signals: bool
+}
+
+impl<'lua, S: ObjectStateType> ClassDef<'lua, S> {
+    pub fn new(lua: rlua::Context<'lua>, name: &str) -> rlua::Result<Self> {
+        Ok(ClassDef {
+            lua,
+            name: name.into(),
+            builder: Class::builder(lua, name, None)?,
+            object_methods: lua.create_table()?,
+            property_signals: true
+        })
+    }
+
+    /// Makes calling the class, e.g. `drawin{ visible = true }`, create an
+    /// object. The constructor usually applies the arguments with
+    /// `ObjectBuilder::handle_constructor_argument_with_schema`.
+    pub fn constructor<F>(self, constructor: F) -> rlua::Result<Self>
+    where
+        F: 'static + Send + Fn(rlua::Context<'lua>, Table<'lua>) -> rlua::Result<Object<'lua, S>>
+    {
+        self.class_method("__call", constructor)
+    }
+
+    /// A function of the class itself, e.g. `drawin.edge_claims()`.
+    pub fn class_method<A, R, F>(self, name: &str, method: F) -> rlua::Result<Self>
+    where
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        F: 'static + Send + Fn(rlua::Context<'lua>, A) -> rlua::Result<R>
+    {
+        let method = self.lua.create_function(method)?;
+        self.raw(|builder| builder.method(name.into(), method))
+    }
+
+    /// A method of every object of the class, e.g. `drawin:geometry()`.
+    pub fn object_method<A, R, F>(self, name: &str, method: F) -> rlua::Result<Self>
+    where
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        F: 'static + Send + Fn(rlua::Context<'lua>, A) -> rlua::Result<R>
+    {
+        self.object_methods.set(name, self.lua.create_function(method)?)?;
+        Ok(self)
+    }
+
+    /// A property that can be read and assigned.
+    pub fn property<G, R, T, V>(self, name: &str, getter: G, setter: T) -> rlua::Result<Self>
+    where
+        G: 'static + Send + Fn(rlua::Context<'lua>, Object<'lua, S>) -> rlua::Result<R>,
+        R: ToLuaMulti<'lua>,
+        T: 'static + Send + Fn(rlua::Context<'lua>, (Object<'lua, S>, V)) -> rlua::Result<()>,
+        V: FromLua<'lua>
+    {
+        let setter = if self.property_signals {
+            let signal = format!("property::{}", name);
+            self.lua.create_function(
+                move |lua: rlua::Context<'lua>, (object, value): (Object<'lua, S>, V)| {
+                    setter(lua, (object.clone(), value))?;
+                    Object::emit_signal(lua, &object, &signal, Value::Nil)
+                }
+            )?
+        } else {
+            self.lua.create_function(setter)?
+        };
+        let property = Property::new(
+            name.into(),
+            Some(setter.clone()),
+            Some(self.lua.create_function(getter)?),
+            Some(setter)
+        );
+        self.raw(|builder| builder.property(property))
+    }
+
+    /// A property that can only be read.
+    pub fn read_only<G, R>(self, name: &str, getter: G) -> rlua::Result<Self>
+    where
+        G: 'static + Send + Fn(rlua::Context<'lua>, Object<'lua, S>) -> rlua::Result<R>,
+        R: ToLuaMulti<'lua>
+    {
+        let property = Property::new(name.into(), None, Some(self.lua.create_function(getter)?), None);
+        self.raw(|builder| builder.property(property))
+    }
+
+    /// Stops the properties defined after this from emitting
+    /// `property::<name>` when they are assigned.
+    pub fn without_property_signals(mut self) -> Self {
+        self.property_signals = false;
+        self
+    }
+
+    /// Changes the class with the underlying builder.
+    pub fn raw<F>(mut self, change: F) -> rlua::Result<Self>
+    where
+        F: FnOnce(ClassBuilder<'lua, S>) -> rlua::Result<ClassBuilder<'lua, S>>
+    {
+        self.builder = change(self.builder)?;
+        Ok(self)
+    }
+
+    /// Builds the class and makes it a global under its name.
+    pub fn save(self) -> rlua::Result<Class<'lua, S>> {
+        let name = self.name.clone();
+        self.raw(|builder| builder.save_class(&name))?.build()
+    }
+
+    pub fn build(self) -> rlua::Result<Class<'lua, S>> {
+        let class = self.builder.build()?;
+        // Raw, since the class may have a `__newindex` of its own by now.
+        class
+            .class
+            .get_user_value::<Table>()?
+            .raw_set("object_methods", self.object_methods)?;
+        Ok(class)
+    }
+}
+
+/// Makes `==` compare objects by an id of their state, instead of by which
+/// userdata they are.
+pub fn add_eq_by_id<'lua, S, M, K>(methods: &mut M, id: fn(&S) -> K)
+where
+    S: ObjectStateType,
+    M: UserDataMethods<'lua, S>,
+    K: 'static + PartialEq
+{
+    methods.add_meta_function(
+        MetaMethod::Eq,
+        move |_, (a, b): (AnyUserData, AnyUserData)| match (a.borrow::<S>(), b.borrow::<S>()) {
+            (Ok(a), Ok(b)) => Ok(id(&a) == id(&b)),
+            _ => Ok(false)
+        }
+    );
+}
+
 impl<'lua, S: ObjectStateType> ToLua<'lua> for Class<'lua, S> {
     fn to_lua(self, lua: rlua::Context<'lua>) -> rlua::Result<Value<'lua>> {
         self.class.to_lua(lua)
@@ -101,8 +254,7 @@ impl<S: ObjectStateType> Default for ClassState<S> {
 impl<S: ObjectStateType> UserData for ClassState<S> {
     fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
         methods.add_meta_function(MetaMethod::Index, class_index);
-        // TODO Class new index?
-        methods.add_meta_function(MetaMethod::NewIndex, object::default_newindex::<S>);
+        methods.add_meta_function(MetaMethod::NewIndex, class_newindex::<S>);
         fn call<'lua>(
             lua: rlua::Context<'lua>,
             (class, args): (AnyUserData<'lua>, rlua::MultiValue<'lua>)
@@ -162,6 +314,11 @@ impl<'lua, S: ObjectStateType> Class<'lua, S> {
         })
     }
 
+    /// The methods `ClassDef::object_method` added for the objects.
+    pub fn object_methods(&self) -> rlua::Result<Option<Table<'lua>>> {
+        self.class.get_user_value::<Table>()?.raw_get("object_methods")
+    }
+
     pub fn checker(&self) -> rlua::Result<Option<Checker<S>>> {
         self.class
             .borrow::<ClassState<S>>()
@@ -222,6 +379,21 @@ pub fn class_setup<'lua, S: ObjectStateType>(
     })
 }
 
+/// Assignment to a field of the class itself, e.g. `client.focus = c`.
+///
+/// Classes can handle this by defining a `__newindex` method.
+fn class_newindex<'lua, S: ObjectStateType>(
+    lua: rlua::Context<'lua>,
+    (class, index, val): (AnyUserData<'lua>, String, Value<'lua>)
+) -> rlua::Result<Value<'lua>> {
+    let table = class.get_user_value::<Table>()?;
+    let meta = table.get_metatable().expect("class had no meta table");
+    match meta.raw_get("__newindex")? {
+        Value::Function(function) => function.call((class, index, val)),
+        _ => object::default_newindex::<S>(lua, (class.into(), index, val))
+    }
+}
+
 fn class_index<'lua>(
     _: rlua::Context<'lua>,
     (class, index): (AnyUserData<'lua>, Value<'lua>)
@@ -0,0 +1,78 @@
+//! Colors as they are given by Lua, e.g. in a theme.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Color {
+    pub red: f64,
+    pub green: f64,
+    pub blue: f64,
+    pub alpha: f64
+}
+
+/// Transparent black.
+impl Default for Color {
+    fn default() -> Self {
+        Color {
+            red: 0.0,
+            green: 0.0,
+            blue: 0.0,
+            alpha: 0.0
+        }
+    }
+}
+
+impl Color {
+    /// The color as a pixel of a Cairo ARGB32 surface, which is premultiplied
+    /// and stored in native endian order.
+    pub fn to_argb32(self) -> [u8; 4] {
+        let channel = |value: f64| (value * self.alpha * 255.0).round() as u8;
+        let pixel = u32::from(channel(self.blue)) |
+            u32::from(channel(self.green)) << 8 |
+            u32::from(channel(self.red)) << 16 |
+            ((self.alpha * 255.0).round() as u32) << 24;
+        pixel.to_ne_bytes()
+    }
+}
+
+/// Parses a color like "#rgb", "#rrggbb" or "#rrggbbaa".
+pub fn parse_color(color: &str) -> Option<Color> {
+    let hex = color.trim().trim_start_matches('#');
+    let channel = |i: usize, len: usize| -> Option<f64> {
+        let digits = hex.get(i * len..(i + 1) * len)?;
+        let value = u8::from_str_radix(digits, 16).ok()?;
+        let value = if len == 1 { value * 0x11 } else { value };
+        Some(f64::from(value) / 255.0)
+    };
+    let len = match hex.len() {
+        3 => 1,
+        6 | 8 => 2,
+        _ => return None
+    };
+    Some(Color {
+        red: channel(0, len)?,
+        green: channel(1, len)?,
+        blue: channel(2, len)?,
+        alpha: if hex.len() == 8 { channel(3, len)? } else { 1.0 }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn color_parsing() {
+        let red = Color {
+            red: 1.0,
+            green: 0.0,
+            blue: 0.0,
+            alpha: 1.0
+        };
+        assert_eq!(parse_color("#ff0000"), Some(red));
+        assert_eq!(parse_color("#f00"), Some(red));
+        assert_eq!(parse_color("#ff000000").map(|color| color.alpha), Some(0.0));
+        assert_eq!(parse_color("red"), None);
+        assert_eq!(red.to_argb32(), 0xffff_0000u32.to_ne_bytes());
+        let half_blue = parse_color("#0000ff80").unwrap();
+        assert_eq!(half_blue.to_argb32(), 0x8000_0080u32.to_ne_bytes());
+    }
+}
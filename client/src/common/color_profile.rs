@@ -0,0 +1,186 @@
+//! The color profiles drawins are painted in, and the transfer functions
+//! between their values and linear light.
+//!
+//! Cairo paints in sRGB, the only profile so far. The effects mix colors in
+//! linear light with the tables of the transfer function of the profile,
+//! see `drawable::effects`, and a compositor that supports color management
+//! is told the profile of the surface, see `wayland_obj::color_management`.
+//! Without it surfaces are untagged, which compositors take to be sRGB.
+//!
+//! Showing HDR content, e.g. in PQ or scRGB, takes:
+//!
+//! - a `Profile` for it, whose name `drawin.color_profile` then takes,
+//! - a `TransferFunction` for its encoding, which the effects use without
+//!   further changes. scRGB is linear with values past 1, so `Tables` has
+//!   to stop clamping to 1 for it,
+//! - buffers with more than 8 bits per channel, since Cairo surfaces and
+//!   `Tables` only have 8, and
+//! - the named primaries and transfer function the profile is described to
+//!   the compositor with, in `color_management::description`, which also
+//!   has to set the luminances for PQ.
+
+/// The steps linear light is quantized to when it's encoded again.
+const LINEAR_STEPS: usize = 4096;
+
+lazy_static! {
+    static ref SRGB: Tables = Tables::new(TransferFunction::Srgb);
+}
+
+/// What the values of a drawin's pixels mean.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Profile {
+    /// The BT.709 primaries with the sRGB transfer function, which is what
+    /// Cairo paints.
+    Srgb
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile::Srgb
+    }
+}
+
+impl Profile {
+    pub const NAMES: &'static [&'static str] = &["srgb"];
+
+    pub fn from_name(name: &str) -> Option<Profile> {
+        match name {
+            "srgb" => Some(Profile::Srgb),
+            _ => None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::Srgb => "srgb"
+        }
+    }
+
+    /// How the values of the profile encode light.
+    pub fn transfer_function(self) -> TransferFunction {
+        match self {
+            Profile::Srgb => TransferFunction::Srgb
+        }
+    }
+}
+
+/// How the values of a color channel encode light.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TransferFunction {
+    /// The piecewise sRGB curve, a gamma of about 2.2.
+    Srgb
+}
+
+impl Default for TransferFunction {
+    fn default() -> Self {
+        TransferFunction::Srgb
+    }
+}
+
+impl TransferFunction {
+    /// The linear light of an encoded `value`, both from 0 to 1.
+    pub fn to_linear(self, value: f32) -> f32 {
+        match self {
+            TransferFunction::Srgb => {
+                if value <= 0.040_45 {
+                    value / 12.92
+                } else {
+                    ((value + 0.055) / 1.055).powf(2.4)
+                }
+            },
+        }
+    }
+
+    /// The encoded value of `linear` light, both from 0 to 1.
+    pub fn from_linear(self, linear: f32) -> f32 {
+        match self {
+            TransferFunction::Srgb => {
+                if linear <= 0.003_130_8 {
+                    linear * 12.92
+                } else {
+                    1.055 * linear.powf(1.0 / 2.4) - 0.055
+                }
+            },
+        }
+    }
+
+    /// The function for 8 bit channels, computed the first time it's used.
+    pub fn tables(self) -> &'static Tables {
+        match self {
+            TransferFunction::Srgb => &SRGB
+        }
+    }
+}
+
+/// A transfer function looked up for 8 bit channels.
+pub struct Tables {
+    to_linear: [f32; 256],
+    from_linear: Vec<u8>
+}
+
+impl Tables {
+    fn new(function: TransferFunction) -> Tables {
+        let mut to_linear = [0.0; 256];
+        for (value, linear) in to_linear.iter_mut().enumerate() {
+            *linear = function.to_linear(value as f32 / 255.0);
+        }
+        let from_linear = (0..=LINEAR_STEPS)
+            .map(|step| {
+                let value = function.from_linear(step as f32 / LINEAR_STEPS as f32);
+                (value * 255.0).round() as u8
+            })
+            .collect();
+        Tables {
+            to_linear,
+            from_linear
+        }
+    }
+
+    /// The linear light of a channel, from 0 to 1.
+    pub fn to_linear(&self, value: u8) -> f32 {
+        self.to_linear[value as usize]
+    }
+
+    /// The channel for `linear` light, which is clamped from 0 to 1.
+    pub fn from_linear(&self, linear: f32) -> u8 {
+        let step = (linear.max(0.0).min(1.0) * LINEAR_STEPS as f32).round() as usize;
+        self.from_linear[step]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn color_profile_names() {
+        for name in Profile::NAMES {
+            assert_eq!(Profile::from_name(name).map(Profile::name), Some(*name));
+        }
+        assert_eq!(Profile::from_name("pq"), None);
+        assert_eq!(Profile::default().transfer_function(), TransferFunction::Srgb);
+    }
+
+    #[test]
+    fn color_profile_srgb_round_trip() {
+        let srgb = TransferFunction::Srgb;
+        // The ends, both sides of the linear segment, and mid grey, which
+        // is about a fifth of the light.
+        assert_eq!(srgb.to_linear(0.0), 0.0);
+        assert!((srgb.to_linear(1.0) - 1.0).abs() < 1e-6);
+        assert!((srgb.to_linear(0.04) - 0.04 / 12.92).abs() < 1e-7);
+        assert!((srgb.to_linear(0.5) - 0.214_041).abs() < 1e-5);
+        for step in 0..=1000 {
+            let value = step as f32 / 1000.0;
+            let back = srgb.from_linear(srgb.to_linear(value));
+            assert!((back - value).abs() < 1e-5, "{} came back as {}", value, back);
+        }
+        // Every 8 bit channel survives the tables.
+        let tables = srgb.tables();
+        for value in 0..=255 {
+            assert_eq!(tables.from_linear(tables.to_linear(value)), value);
+        }
+        assert_eq!(tables.from_linear(-0.5), 0);
+        assert_eq!(tables.from_linear(2.0), 255);
+    }
+}
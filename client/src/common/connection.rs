@@ -0,0 +1,358 @@
+//! Scoped signal connections, which disconnect their handler when they're
+//! collected, so a handler can't outlive what it's for.
+//!
+//! `object:connect_signal_scoped(name, func)` returns a connection, which
+//! keeps the handler connected as long as it's referenced or until
+//! `connection:disconnect()`. A group from `object:connection_group()`
+//! holds many connections, of any object, and disconnects them all at once
+//! or when it's collected, so a widget can keep its connections in a group
+//! that lives as long as it does:
+//!
+//! ```lua
+//! self._connections = d:connection_group()
+//! self._connections:connect_signal("property::geometry", function() self:relayout() end)
+//! self._connections:add(screen:connect_signal_scoped("removed", function() self:hide() end))
+//! ```
+//!
+//! A handler that references its own connection keeps it from being
+//! collected. Lua can't be called while something is collected, so a
+//! collected connection only marks its handler, which is skipped and then
+//! removed the next time its signal is emitted or connected to.
+
+use std::{cell::RefCell, collections::HashSet};
+
+use rlua::{self, AnyUserData, Function, Table, UserData, UserDataMethods, Value};
+
+use super::signal;
+
+/// Weak table of the objects with signals, for `awesome.connection_stats`.
+const SIGNAL_OWNERS: &str = "__signal_owners";
+
+thread_local! {
+    /// The ids of the handlers whose connection was collected.
+    static COLLECTED: RefCell<HashSet<usize>> = RefCell::new(HashSet::new());
+}
+
+/// A handler connected to a signal until this is collected or disconnected.
+///
+/// The user value is the signal table of the object.
+pub struct Connection {
+    id: usize,
+    name: String,
+    connected: bool
+}
+
+/// Connections that are disconnected together.
+///
+/// The user value is a table with the object the group was made for and
+/// its connections.
+pub struct ConnectionGroup;
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if self.connected {
+            // The client may be exiting, after the set was dropped.
+            let _ = COLLECTED.try_with(|collected| collected.borrow_mut().insert(self.id));
+        }
+    }
+}
+
+impl UserData for Connection {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_function("disconnect", disconnect);
+        methods.add_method("is_connected", |_, connection, ()| Ok(connection.connected));
+    }
+}
+
+impl UserData for ConnectionGroup {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_function("connect_signal", group_connect_signal);
+        methods.add_function("add", group_add);
+        methods.add_function("disconnect", group_disconnect);
+        methods.add_function("count", group_count);
+    }
+}
+
+/// Whether the connection of any handler was collected.
+pub fn any_collected() -> bool {
+    COLLECTED.with(|collected| !collected.borrow().is_empty())
+}
+
+/// Whether the connection of the handler was collected.
+pub fn is_collected(id: usize) -> bool {
+    COLLECTED.with(|collected| collected.borrow().contains(&id))
+}
+
+/// Forgets that the connection of the handler was collected, returning
+/// whether it was, once the handler is removed.
+pub fn take_collected(id: usize) -> bool {
+    COLLECTED.with(|collected| collected.borrow_mut().remove(&id))
+}
+
+/// Remembers that `object` has the signals, for
+/// `awesome.connection_stats`, without keeping it alive.
+pub fn track<'lua>(lua: rlua::Context<'lua>, object: Value<'lua>, signals: Table<'lua>) -> rlua::Result<()> {
+    let owners = match lua.named_registry_value::<str, Option<Table>>(SIGNAL_OWNERS)? {
+        Some(owners) => owners,
+        None => {
+            let owners = lua.create_table()?;
+            let meta = lua.create_table()?;
+            meta.set("__mode", "k")?;
+            owners.set_metatable(Some(meta));
+            lua.set_named_registry_value(SIGNAL_OWNERS, owners.clone())?;
+            owners
+        }
+    };
+    owners.set(object, signals)
+}
+
+/// Connects `func` to the signal in `signals` for as long as the returned
+/// connection lives.
+pub fn connect_scoped<'lua>(
+    lua: rlua::Context<'lua>,
+    signals: Table<'lua>,
+    name: String,
+    func: Function<'lua>
+) -> rlua::Result<AnyUserData<'lua>> {
+    let id = signal::connect_signal(lua, signals.clone(), &name, func)?;
+    let connection = lua.create_userdata(Connection {
+        id,
+        name,
+        connected: true
+    })?;
+    connection.set_user_value(signals)?;
+    Ok(connection)
+}
+
+/// A group that connects to the signals of `object`, which are in
+/// `signals`.
+pub fn group<'lua>(
+    lua: rlua::Context<'lua>,
+    object: Value<'lua>,
+    signals: Table<'lua>
+) -> rlua::Result<AnyUserData<'lua>> {
+    let group = lua.create_userdata(ConnectionGroup)?;
+    let contents = lua.create_table()?;
+    contents.set("object", object)?;
+    contents.set("signals", signals)?;
+    contents.set("connections", lua.create_table()?)?;
+    group.set_user_value(contents)?;
+    Ok(group)
+}
+
+/// `connection:disconnect()`, which returns whether it was connected.
+fn disconnect(_: rlua::Context, connection: AnyUserData) -> rlua::Result<bool> {
+    let (id, name) = {
+        let mut state = connection.borrow_mut::<Connection>()?;
+        if !state.connected {
+            return Ok(false);
+        }
+        state.connected = false;
+        (state.id, state.name.clone())
+    };
+    signal::disconnect_handler(connection.get_user_value::<Table>()?, &name, id)
+}
+
+/// `group:connect_signal(name, func)`, which connects to the object of the
+/// group and returns the connection.
+fn group_connect_signal<'lua>(
+    lua: rlua::Context<'lua>,
+    (group, name, func): (AnyUserData<'lua>, String, Function<'lua>)
+) -> rlua::Result<AnyUserData<'lua>> {
+    let signals = group.get_user_value::<Table>()?.get::<_, Table>("signals")?;
+    let connection = connect_scoped(lua, signals, name, func)?;
+    group_add(lua, (group, connection.clone()))?;
+    Ok(connection)
+}
+
+/// `group:add(connection)`.
+fn group_add<'lua>(
+    _: rlua::Context<'lua>,
+    (group, connection): (AnyUserData<'lua>, AnyUserData<'lua>)
+) -> rlua::Result<()> {
+    if !connection.is::<Connection>() {
+        return Err(rlua::Error::RuntimeError(
+            "connection group: can only add connections".into()
+        ));
+    }
+    let connections = group.get_user_value::<Table>()?.get::<_, Table>("connections")?;
+    connections.set(connections.raw_len() + 1, connection)
+}
+
+/// `group:disconnect()`, which disconnects every connection of the group
+/// and returns how many were connected. The group can be used again.
+fn group_disconnect<'lua>(lua: rlua::Context<'lua>, group: AnyUserData<'lua>) -> rlua::Result<usize> {
+    let contents = group.get_user_value::<Table>()?;
+    let connections = contents.get::<_, Table>("connections")?;
+    contents.set("connections", lua.create_table()?)?;
+    let mut disconnected = 0;
+    for connection in connections.sequence_values::<AnyUserData>() {
+        if disconnect(lua, connection?)? {
+            disconnected += 1;
+        }
+    }
+    Ok(disconnected)
+}
+
+/// `group:count()`, how many connections of the group are connected.
+fn group_count(_: rlua::Context, group: AnyUserData) -> rlua::Result<usize> {
+    let connections = group.get_user_value::<Table>()?.get::<_, Table>("connections")?;
+    let mut count = 0;
+    for connection in connections.sequence_values::<AnyUserData>() {
+        if connection?.borrow::<Connection>()?.connected {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// `awesome.connection_stats()`, the handlers connected to each object
+/// that has any, most first: `{ object = o, total = 3, signals = {
+/// ["property::geometry"] = 2, ... } }`. The global signals have no
+/// `object`.
+pub fn connection_stats(lua: rlua::Context, _: ()) -> rlua::Result<Vec<Table>> {
+    let mut owners = vec![(
+        Value::Nil,
+        lua.named_registry_value::<str, Table>(crate::GLOBAL_SIGNALS)?
+    )];
+    if let Some(tracked) = lua.named_registry_value::<str, Option<Table>>(SIGNAL_OWNERS)? {
+        for pair in tracked.pairs::<Value, Table>() {
+            owners.push(pair?);
+        }
+    }
+    let mut stats = Vec::new();
+    for (object, signals) in owners {
+        let counts = signal::handler_counts(signals)?;
+        let total: usize = counts.iter().map(|&(_, count)| count).sum();
+        if total == 0 {
+            continue;
+        }
+        let by_name = lua.create_table()?;
+        for (name, count) in counts {
+            by_name.set(name, count)?;
+        }
+        let entry = lua.create_table()?;
+        entry.set("object", object)?;
+        entry.set("total", total)?;
+        entry.set("signals", by_name)?;
+        stats.push((total, entry));
+    }
+    stats.sort_by(|(a, _), (b, _)| b.cmp(a));
+    Ok(stats.into_iter().map(|(_, entry)| entry).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use rlua::{self, Lua};
+
+    use crate::objects::timer;
+
+    fn setup(lua: rlua::Context) -> rlua::Result<()> {
+        lua.set_named_registry_value(crate::GLOBAL_SIGNALS, lua.create_table()?)?;
+        timer::init(lua)?;
+        lua.globals()
+            .set("connection_stats", lua.create_function(super::connection_stats)?)?;
+        Ok(())
+    }
+
+    #[test]
+    fn connection_collected() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            setup(lua)?;
+            lua.load(
+                r#"
+local t = timer{ timeout = 10 }
+local calls = {}
+local kept = t:connect_signal_scoped("start", function() calls[#calls + 1] = "kept" end)
+t:connect_signal_scoped("start", function() calls[#calls + 1] = "dropped" end)
+t:connect_signal("start", function() calls[#calls + 1] = "plain" end)
+collectgarbage()
+collectgarbage()
+t:emit_signal("start")
+assert(table.concat(calls, " ") == "kept plain", table.concat(calls, " "))
+local stats = connection_stats()
+assert(#stats == 1 and stats[1].object == t and stats[1].total == 2)
+assert(stats[1].signals.start == 2)
+assert(kept:is_connected() and kept:disconnect() and not kept:is_connected())
+assert(not kept:disconnect())
+calls = {}
+t:emit_signal("start")
+assert(table.concat(calls, " ") == "plain")
+                "#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn connection_group_during_emission() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            setup(lua)?;
+            lua.load(
+                r#"
+local t = timer{ timeout = 10 }
+local other = timer{ timeout = 10 }
+local group = t:connection_group()
+local calls = {}
+group:connect_signal("start", function()
+    calls[#calls + 1] = "first"
+    assert(group:disconnect() == 3)
+end)
+group:connect_signal("start", function() calls[#calls + 1] = "second" end)
+group:add(other:connect_signal_scoped("start", function() calls[#calls + 1] = "other" end))
+t:connect_signal("start", function() calls[#calls + 1] = "plain" end)
+assert(group:count() == 3)
+t:emit_signal("start")
+assert(table.concat(calls, " ") == "first plain", table.concat(calls, " "))
+other:emit_signal("start")
+assert(group:count() == 0 and #calls == 2)
+-- The group can be used again.
+group:connect_signal("start", function() calls[#calls + 1] = "again" end)
+t:emit_signal("start")
+assert(table.concat(calls, " ") == "first plain plain again", table.concat(calls, " "))
+assert(not pcall(group.add, group, t))
+                "#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn connection_order() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            setup(lua)?;
+            lua.load(
+                r#"
+local t = timer{ timeout = 10 }
+local calls = {}
+local function handler(name)
+    return function() calls[#calls + 1] = name end
+end
+local a = t:connect_signal_scoped("start", handler("a"))
+local b = t:connect_signal_scoped("start", handler("b"))
+t:connect_signal("start", handler("c"))
+local d = t:connect_signal_scoped("start", handler("d"))
+t:emit_signal("start")
+assert(table.concat(calls, "") == "abcd")
+b:disconnect()
+calls = {}
+local e = t:connect_signal_scoped("start", function()
+    calls[#calls + 1] = "e"
+    -- Connected during the emission, called from the next one.
+    t:connect_signal("start", handler("f"))
+end)
+t:emit_signal("start")
+assert(table.concat(calls, "") == "acde", table.concat(calls, ""))
+a:disconnect()
+calls = {}
+t:emit_signal("start")
+assert(table.concat(calls, "") == "cdef", table.concat(calls, ""))
+                "#
+            )
+            .exec()
+        })
+    }
+}
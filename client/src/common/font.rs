@@ -0,0 +1,41 @@
+//! Fonts as they are given by Lua, e.g. in a theme.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Font {
+    pub family: String,
+    pub size: f64
+}
+
+/// Parses a font description like "Monospace 10".
+///
+/// Without a size, `default_size` is used.
+pub fn parse_font(font: &str, default_size: f64) -> Font {
+    let font = font.trim();
+    match font.rfind(' ').map(|space| font.split_at(space)) {
+        Some((family, size)) if size.trim().parse::<f64>().is_ok() => Font {
+            family: family.trim().into(),
+            size: size.trim().parse().unwrap()
+        },
+        _ => Font {
+            family: font.into(),
+            size: default_size
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn font_parsing() {
+        assert_eq!(
+            parse_font("DejaVu Sans Mono 11", 10.0),
+            Font {
+                family: "DejaVu Sans Mono".into(),
+                size: 11.0
+            }
+        );
+        assert_eq!(parse_font("Monospace", 10.0).size, 10.0);
+    }
+}
@@ -1,5 +1,11 @@
 pub mod class;
+pub mod color;
+pub mod color_profile;
+pub mod connection;
+pub mod font;
 pub mod object;
 pub mod property;
+pub mod schema;
 pub mod signal;
+pub mod text;
 pub mod xproperty;
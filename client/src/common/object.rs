@@ -7,7 +7,7 @@ use rlua::{
     Value
 };
 
-use super::{class::Class, property::Property, signal};
+use super::{class::Class, connection, property::Property, schema::Schema, signal};
 
 /// The ObjectStateType trait is used to constrain the generic data types in the Object and Class structs.
 /// They can be transferred to and from Lua user data and force type checking
@@ -92,6 +92,30 @@ impl<'lua, S: ObjectStateType> ObjectBuilder<'lua, S> {
         Ok(self)
     }
 
+    /// Like `handle_constructor_argument`, but the arguments are checked
+    /// against the schema and applied in the order it defines, instead of
+    /// the order of the table.
+    pub fn handle_constructor_argument_with_schema(
+        self,
+        args: Table<'lua>,
+        schema: &Schema
+    ) -> rlua::Result<Self> {
+        let meta = self.object.get_metatable()?.expect("Object had no meta table");
+        let class = meta.get::<_, AnyUserData>("__class")?;
+        let class_table = class.get_user_value::<Table>()?;
+        let props = class_table.get::<_, Vec<Property>>("properties")?;
+        for (key, value) in schema.order(self.lua, args)? {
+            let new = props
+                .iter()
+                .find(|prop| prop.name == key)
+                .and_then(|prop| prop.cb_new.as_ref());
+            if let Some(new) = new {
+                let _: () = new.bind(self.object.clone())?.call(value)?;
+            }
+        }
+        Ok(self)
+    }
+
     pub fn build(self) -> Object<'lua, S> {
         self.object
     }
@@ -163,15 +187,34 @@ impl<'lua, S: ObjectStateType> Object<'lua, S> {
         let data_table = lua.create_table()?;
         wrapper_table.set("data", data_table)?;
         let meta = lua.create_table()?;
+        if let Some(methods) = class.object_methods()? {
+            for entry in methods.pairs::<Value, Value>() {
+                let (key, value) = entry?;
+                meta.set(key, value)?;
+            }
+        }
         meta.set("__class", class)?;
         meta.set("properties", Vec::<Property>::new().to_lua(lua)?)?;
-        meta.set("signals", lua.create_table()?)?;
+        let signals = lua.create_table()?;
+        meta.set("signals", signals.clone())?;
         meta.set(
             "connect_signal",
             lua.create_function(|ctx, (obj, name, func): (_, String, _)| {
                 Self::connect_signal(ctx, &obj, name.as_ref(), func)
             })?
         )?;
+        meta.set(
+            "connect_signal_scoped",
+            lua.create_function(|ctx, (obj, name, func): (Self, String, Function)| {
+                connection::connect_scoped(ctx, obj.signals()?, name, func)
+            })?
+        )?;
+        meta.set(
+            "connection_group",
+            lua.create_function(|ctx, obj: Self| {
+                connection::group(ctx, obj.clone().to_lua(ctx)?, obj.signals()?)
+            })?
+        )?;
         meta.set(
             "disconnect_signal",
             lua.create_function(|ctx, (obj, name): (_, String)| {
@@ -188,6 +231,7 @@ impl<'lua, S: ObjectStateType> Object<'lua, S> {
         meta.set("__tostring", lua.create_function(default_tostring::<S>)?)?;
         wrapper_table.set_metatable(Some(meta));
         obj.set_user_value(wrapper_table)?;
+        connection::track(lua, Value::UserData(obj.clone()), signals)?;
         // TODO Emit new signal event
         let object = Object {
             obj,
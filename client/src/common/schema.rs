@@ -0,0 +1,272 @@
+//! Validation of the values given to the properties of an object.
+//!
+//! The plain rlua conversions report errors like "error converting Lua
+//! string to integer" without saying which property was wrong, and anything
+//! converts to a boolean. A `Schema` lists the properties of a class and
+//! what they expect, so values can be checked (and coerced where that's
+//! unambiguous) with errors that name the property.
+
+use std::{cell::RefCell, collections::HashSet};
+
+use rlua::{self, FromLua, Table, Value};
+
+/// Field of the global `awesome` table that makes unknown constructor
+/// arguments an error instead of a warning.
+pub const STRICT_ARGUMENTS: &str = "strict_arguments";
+
+thread_local! {
+    /// The (class, message) pairs that have already been warned about.
+    static WARNED: RefCell<HashSet<(&'static str, String)>> = RefCell::new(HashSet::new());
+}
+
+/// The type of value a property expects.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Kind {
+    /// An integer, or a string or float that is one.
+    Integer,
+    /// A number, or a string that is one.
+    Number,
+    /// A boolean. 0 and 1 are accepted too, but are deprecated.
+    Boolean,
+    String,
+    /// A boolean or one of the strings.
+    BooleanOr(&'static [&'static str]),
+    /// One of the strings.
+    OneOf(&'static [&'static str]),
+    /// A list of strings.
+    Strings,
+    /// A table, whose fields the setter checks.
+    Table,
+    /// A function, e.g. a handler.
+    Function,
+    /// An object of the class, or its index, e.g. `screen[1]`. The setter
+    /// checks its class.
+    Object(&'static str),
+    /// A cairo surface, or a table of rectangles. The setter reads them.
+    Shape
+}
+
+/// The order in which constructor arguments are applied.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Phase {
+    /// What kind of object it is and how it's backed.
+    Backend,
+    Geometry,
+    Appearance,
+    /// Applied last, so an object is only shown once it's set up.
+    Visibility
+}
+
+/// A property of a class.
+#[derive(Debug)]
+pub struct Key {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub phase: Phase
+}
+
+/// The properties of a class.
+#[derive(Debug)]
+pub struct Schema {
+    pub class: &'static str,
+    pub keys: &'static [Key]
+}
+
+impl Kind {
+    fn expected(self) -> String {
+        match self {
+            Kind::Integer => "an integer".into(),
+            Kind::Number => "a number".into(),
+            Kind::Boolean => "a boolean".into(),
+            Kind::String => "a string".into(),
+            Kind::BooleanOr(choices) => match choices.len() {
+                1 => format!("a boolean or {}", quote(choices)),
+                _ => format!("a boolean or one of {}", quote(choices))
+            },
+            Kind::OneOf(choices) => format!("one of {}", quote(choices)),
+            Kind::Strings => "a list of strings".into(),
+            Kind::Table => "a table".into(),
+            Kind::Function => "a function".into(),
+            Kind::Object(class) => format!("a {} or its index", class),
+            Kind::Shape => "a cairo surface or a list of rectangles".into()
+        }
+    }
+}
+
+/// Lists the choices of a `Kind`, e.g. `"top", "bottom"`.
+fn quote(choices: &[&str]) -> String {
+    let choices: Vec<String> = choices.iter().map(|choice| format!("\"{}\"", choice)).collect();
+    choices.join(", ")
+}
+
+impl Schema {
+    pub fn key(&self, name: &str) -> Option<&Key> {
+        self.keys.iter().find(|key| key.name == name)
+    }
+
+    /// Checks that `value` is valid for the property `name` and converts it.
+    pub fn check<'lua, T: FromLua<'lua>>(
+        &self,
+        lua: rlua::Context<'lua>,
+        name: &str,
+        value: Value<'lua>
+    ) -> rlua::Result<T> {
+        let key = self.key(name).ok_or_else(|| {
+            rlua::Error::RuntimeError(format!("{}: unknown property \"{}\"", self.class, name))
+        })?;
+        T::from_lua(self.coerce(key, value)?, lua)
+    }
+
+    /// Lists the constructor arguments in the order they should be applied:
+    /// by phase, then in the order of the schema.
+    ///
+    /// Unknown keys are left out with a warning, or are an error if
+    /// `awesome.strict_arguments` is set. Keys that aren't strings are
+    /// ignored.
+    pub fn order<'lua>(
+        &self,
+        lua: rlua::Context<'lua>,
+        args: Table<'lua>
+    ) -> rlua::Result<Vec<(&'static str, Value<'lua>)>> {
+        let mut ordered = Vec::new();
+        for pair in args.pairs::<Value, Value>() {
+            let (name, value) = match pair? {
+                (Value::String(name), value) => (name, value),
+                _ => continue
+            };
+            let name = name.to_str()?;
+            match self.keys.iter().position(|key| key.name == name) {
+                Some(index) => ordered.push((index, value)),
+                None if strict_arguments(lua)? => {
+                    return Err(rlua::Error::RuntimeError(format!(
+                        "{}: unknown property \"{}\"",
+                        self.class, name
+                    )))
+                },
+                None => self.warn_once(format!("ignoring unknown property \"{}\"", name))
+            }
+        }
+        ordered.sort_by_key(|&(index, _)| (self.keys[index].phase, index));
+        Ok(ordered
+            .into_iter()
+            .map(|(index, value)| (self.keys[index].name, value))
+            .collect())
+    }
+
+    fn coerce<'lua>(&self, key: &Key, value: Value<'lua>) -> rlua::Result<Value<'lua>> {
+        let coerced = match (key.kind, &value) {
+            (Kind::Integer, Value::Integer(_)) | (Kind::Object(_), Value::Integer(_)) => Some(value.clone()),
+            (Kind::Integer, &Value::Number(n)) | (Kind::Object(_), &Value::Number(n)) => {
+                float_integer(n).map(Value::Integer)
+            },
+            (Kind::Integer, Value::String(string)) => parse_integer(string).map(Value::Integer),
+            (Kind::Object(_), Value::UserData(_)) => Some(value.clone()),
+            (Kind::Number, &Value::Integer(n)) => Some(Value::Number(n as f64)),
+            (Kind::Number, Value::Number(_)) => Some(value.clone()),
+            (Kind::Number, Value::String(string)) => string
+                .to_str()
+                .ok()
+                .and_then(|string| string.trim().parse::<f64>().ok())
+                .map(Value::Number),
+            (Kind::String, Value::String(_)) => Some(value.clone()),
+            (Kind::BooleanOr(choices), Value::String(string)) |
+            (Kind::OneOf(choices), Value::String(string)) => choices
+                .iter()
+                .find(|choice| string.as_bytes() == choice.as_bytes())
+                .map(|_| value.clone()),
+            (Kind::Strings, Value::Table(table)) => {
+                let strings = table.clone().sequence_values::<Value>().all(|value| match value {
+                    Ok(Value::String(_)) => true,
+                    _ => false
+                });
+                if strings {
+                    Some(value.clone())
+                } else {
+                    None
+                }
+            },
+            (Kind::Table, Value::Table(_)) => Some(value.clone()),
+            (Kind::Function, Value::Function(_)) => Some(value.clone()),
+            (Kind::Shape, Value::LightUserData(_)) | (Kind::Shape, Value::Table(_)) => Some(value.clone()),
+            (Kind::Boolean, Value::Boolean(_)) | (Kind::BooleanOr(_), Value::Boolean(_)) => {
+                Some(value.clone())
+            },
+            (Kind::Boolean, _) | (Kind::BooleanOr(_), _) => as_flag(&value).map(|flag| {
+                self.warn_once(format!(
+                    "using a number for \"{}\" is deprecated, use a boolean instead",
+                    key.name
+                ));
+                Value::Boolean(flag)
+            }),
+            _ => None
+        };
+        coerced.ok_or_else(|| {
+            rlua::Error::RuntimeError(format!(
+                "{}.{}: expected {}, got {}",
+                self.class,
+                key.name,
+                key.kind.expected(),
+                describe(&value)
+            ))
+        })
+    }
+
+    fn warn_once(&self, message: String) {
+        let first = WARNED.with(|warned| warned.borrow_mut().insert((self.class, message.clone())));
+        if first {
+            warn!("{}: {}", self.class, message);
+        }
+    }
+}
+
+fn strict_arguments(lua: rlua::Context) -> rlua::Result<bool> {
+    match lua.globals().get::<_, Value>("awesome")? {
+        Value::Table(awesome) => Ok(awesome.get::<_, Option<bool>>(STRICT_ARGUMENTS)?.unwrap_or(false)),
+        _ => Ok(false)
+    }
+}
+
+fn parse_integer(string: &rlua::String) -> Option<i64> {
+    let string = string.to_str().ok()?.trim();
+    string
+        .parse::<i64>()
+        .ok()
+        .or_else(|| string.parse::<f64>().ok().and_then(float_integer))
+}
+
+/// The integer a float is, if it's a whole number an `i64` can hold.
+fn float_integer(n: f64) -> Option<i64> {
+    // Casting a float out of the range of the integer isn't defined.
+    if n.fract() == 0.0 && n >= i64::min_value() as f64 && n < i64::max_value() as f64 {
+        Some(n as i64)
+    } else {
+        None
+    }
+}
+
+/// The boolean 0 or 1 stand for.
+fn as_flag(value: &Value) -> Option<bool> {
+    match *value {
+        Value::Integer(0) => Some(false),
+        Value::Integer(1) => Some(true),
+        Value::Number(n) if n == 0.0 => Some(false),
+        Value::Number(n) if n == 1.0 => Some(true),
+        _ => None
+    }
+}
+
+/// The value as it's named in error messages, e.g. `string "abc"`.
+pub fn describe(value: &Value) -> String {
+    match *value {
+        Value::Nil => "nil".into(),
+        Value::Boolean(b) => format!("boolean {}", b),
+        Value::Integer(n) => format!("number {}", n),
+        Value::Number(n) => format!("number {}", n),
+        Value::String(ref string) => format!("string \"{}\"", String::from_utf8_lossy(string.as_bytes())),
+        Value::Table(_) => "table".into(),
+        Value::Function(_) => "function".into(),
+        Value::Thread(_) => "thread".into(),
+        Value::LightUserData(_) | Value::UserData(_) => "userdata".into(),
+        Value::Error(_) => "error".into()
+    }
+}
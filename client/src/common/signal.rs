@@ -3,11 +3,21 @@
 //!
 //! Signals are stored with the object in its metatable,
 //! the methods defined here are just to make it easier to use.
+//!
+//! Every handler is connected with an id, which is how it's disconnected
+//! on its own, see `connection`. The handlers are called in the order they
+//! were connected. A handler disconnected while the signal is emitted isn't
+//! called anymore, and one connected isn't called until the next emission.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use rlua::{self, Function, Table, ToLuaMulti, Value};
 
+use crate::common::connection;
 use crate::GLOBAL_SIGNALS;
 
+static NEXT_HANDLER_ID: AtomicUsize = AtomicUsize::new(1);
+
 /// Connects functions to a signal. Creates a new entry in the table if it
 /// doesn't exist.
 pub fn connect_signals<'lua>(
@@ -16,29 +26,132 @@ pub fn connect_signals<'lua>(
     name: &str,
     funcs: &[Function<'lua>]
 ) -> rlua::Result<()> {
-    if let Ok(Value::Table(table)) = signals.get::<_, Value>(name) {
-        let mut length = table.len()? + 1;
-        for func in funcs {
-            table.set(length, func.clone())?;
-            length += 1;
-        }
-        Ok(())
-    } else {
-        let table = lua.create_table()?;
-        for (index, func) in funcs.into_iter().enumerate() {
-            table.set(index + 1, func.clone())?;
-        }
-        signals.set(name, table)
+    for func in funcs {
+        connect_signal(lua, signals.clone(), name, func.clone())?;
     }
+    Ok(())
 }
 
+/// Connects a function to a signal, returning the id it can be
+/// disconnected with.
+pub fn connect_signal<'lua>(
+    lua: rlua::Context<'lua>,
+    signals: Table<'lua>,
+    name: &str,
+    func: Function<'lua>
+) -> rlua::Result<usize> {
+    let handlers = match signals.get::<_, Value>(name)? {
+        Value::Table(handlers) => handlers,
+        _ => {
+            let handlers = lua.create_table()?;
+            signals.set(name, handlers.clone())?;
+            handlers
+        }
+    };
+    prune(&handlers)?;
+    let id = NEXT_HANDLER_ID.fetch_add(1, Ordering::Relaxed);
+    let handler = lua.create_table()?;
+    handler.set("id", id)?;
+    handler.set("func", func)?;
+    handlers.set(handlers.raw_len() + 1, handler)?;
+    Ok(id)
+}
+
+/// Disconnects every function from a signal.
 pub fn disconnect_signals(_: rlua::Context, signals: Table, name: &str) -> rlua::Result<()> {
+    if let Value::Table(handlers) = signals.get::<_, Value>(name)? {
+        for handler in handlers.sequence_values::<Table>() {
+            handler?.set("func", Value::Nil)?;
+        }
+    }
     signals.set(name, Value::Nil)
 }
 
+/// Disconnects the handler with the id from a signal, returning whether it
+/// was connected.
+pub fn disconnect_handler(signals: Table, name: &str, id: usize) -> rlua::Result<bool> {
+    let handlers = match signals.get::<_, Value>(name)? {
+        Value::Table(handlers) => handlers,
+        _ => return Ok(false)
+    };
+    let mut found = false;
+    retain(&handlers, |handler| {
+        if handler.get::<_, usize>("id")? != id {
+            return Ok(true);
+        }
+        // An emission that already started skips it.
+        handler.set("func", Value::Nil)?;
+        found = true;
+        Ok(false)
+    })?;
+    Ok(found)
+}
+
+/// The functions connected to a signal, in the order they were connected.
+pub fn handlers<'lua>(signals: Table<'lua>, name: &str) -> rlua::Result<Vec<Function<'lua>>> {
+    let handlers = match signals.get::<_, Value>(name)? {
+        Value::Table(handlers) => handlers,
+        _ => return Ok(Vec::new())
+    };
+    prune(&handlers)?;
+    let mut funcs = Vec::new();
+    for handler in handlers.sequence_values::<Table>() {
+        funcs.extend(handler?.get::<_, Option<Function>>("func")?);
+    }
+    Ok(funcs)
+}
+
+/// How many functions are connected to each signal that has any.
+pub fn handler_counts(signals: Table) -> rlua::Result<Vec<(String, usize)>> {
+    let mut counts = Vec::new();
+    for pair in signals.pairs::<Value, Value>() {
+        if let (Value::String(name), Value::Table(handlers)) = pair? {
+            prune(&handlers)?;
+            let count = handlers.raw_len() as usize;
+            if count > 0 {
+                counts.push((name.to_str()?.to_string(), count));
+            }
+        }
+    }
+    counts.sort();
+    Ok(counts)
+}
+
+/// Removes the handlers whose scoped connection was collected.
+fn prune(handlers: &Table) -> rlua::Result<()> {
+    if !connection::any_collected() {
+        return Ok(());
+    }
+    retain(handlers, |handler| {
+        Ok(!connection::take_collected(handler.get::<_, usize>("id")?))
+    })
+}
+
+/// Keeps the handlers `keep` returns true for, in order.
+fn retain<'lua, F>(handlers: &Table<'lua>, mut keep: F) -> rlua::Result<()>
+where
+    F: FnMut(&Table<'lua>) -> rlua::Result<bool>
+{
+    let all = handlers
+        .clone()
+        .sequence_values::<Table>()
+        .collect::<rlua::Result<Vec<_>>>()?;
+    let mut kept = 0;
+    for handler in &all {
+        if keep(handler)? {
+            kept += 1;
+            handlers.set(kept, handler.clone())?;
+        }
+    }
+    for index in kept + 1..=all.len() {
+        handlers.set(index, Value::Nil)?;
+    }
+    Ok(())
+}
+
 /// Evaluate the functions associated with a signal.
 pub fn emit_signals<'lua, A>(
-    _: rlua::Context<'lua>,
+    lua: rlua::Context<'lua>,
     signals: Table<'lua>,
     name: &str,
     args: A
@@ -46,19 +159,46 @@ pub fn emit_signals<'lua, A>(
 where
     A: ToLuaMulti<'lua> + Clone
 {
-    if let Ok(Value::Table(table)) = signals.get::<_, Value>(name.clone()) {
-        for entry in table.pairs::<Value, Function>() {
-            if let Ok((_, func)) = entry {
-                match func.call(args.clone()) {
-                    Ok(()) => {},
-                    Err(e) => {
-                        error!("Error while emitting signal {}: {}", name, e);
-                    }
-                };
-            }
+    emit_signals_with_errors(lua, signals, name, args)?;
+    Ok(())
+}
+
+/// Evaluate the functions associated with a signal, returning how many were
+/// called and the errors they raised.
+pub fn emit_signals_with_errors<'lua, A>(
+    _: rlua::Context<'lua>,
+    signals: Table<'lua>,
+    name: &str,
+    args: A
+) -> rlua::Result<(usize, Vec<rlua::Error>)>
+where
+    A: ToLuaMulti<'lua> + Clone
+{
+    let (mut called, mut errors) = (0, Vec::new());
+    let handlers = match signals.get::<_, Value>(name)? {
+        Value::Table(handlers) => handlers,
+        _ => return Ok((called, errors))
+    };
+    prune(&handlers)?;
+    // The handlers can connect and disconnect others while they run.
+    let handlers = handlers
+        .sequence_values::<Table>()
+        .collect::<rlua::Result<Vec<_>>>()?;
+    for handler in handlers {
+        let func = match handler.get::<_, Option<Function>>("func")? {
+            Some(func) => func,
+            None => continue
+        };
+        if connection::is_collected(handler.get::<_, usize>("id")?) {
+            continue;
+        }
+        called += 1;
+        if let Err(e) = func.call::<_, ()>(args.clone()) {
+            error!("Error while emitting signal {}: {}", name, e);
+            errors.push(e);
         }
     }
-    Ok(())
+    Ok((called, errors))
 }
 
 /// Connect the function to the named signal in the global signal list.
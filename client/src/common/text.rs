@@ -0,0 +1,266 @@
+//! Measuring and drawing text with cairo, so that Lua can lay out text
+//! before painting it.
+//!
+//! Text is always measured and drawn with the same font setup, so a
+//! measurement never disagrees with what is drawn.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    env, fs,
+    path::PathBuf,
+    time::SystemTime
+};
+
+use cairo::{Context, FontSlant, FontWeight};
+
+use super::font::Font;
+
+/// The longest text that is measured, in bytes. Longer text is refused
+/// instead of stalling the event loop.
+pub const MAX_TEXT_LEN: usize = 4096;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TextExtents {
+    /// The width of the ink of the text.
+    pub width: f64,
+    /// The height of a line in the font.
+    pub height: f64,
+    pub ascent: f64,
+    pub descent: f64,
+    /// How far the next text would be drawn.
+    pub x_advance: f64
+}
+
+pub fn select_font(cr: &Context, font: &Font) {
+    cr.select_font_face(&font.family, FontSlant::Normal, FontWeight::Normal);
+    cr.set_font_size(font.size);
+}
+
+/// Measures `text` in the font selected in `cr`.
+pub fn measure(cr: &Context, text: &str) -> Result<TextExtents, String> {
+    if text.len() > MAX_TEXT_LEN {
+        return Err(format!(
+            "text of {} bytes is longer than the limit of {}",
+            text.len(),
+            MAX_TEXT_LEN
+        ));
+    }
+    let font = cr.font_extents();
+    let (width, x_advance) = if text.is_empty() {
+        (0.0, 0.0)
+    } else {
+        let extents = cr.text_extents(text);
+        (extents.width, extents.x_advance)
+    };
+    Ok(TextExtents {
+        width,
+        height: font.height,
+        ascent: font.ascent,
+        descent: font.descent,
+        x_advance
+    })
+}
+
+/// Draws `text` in the font selected in `cr`, with its baseline starting at
+/// the current point.
+pub fn draw_text(cr: &Context, text: &str) {
+    cr.show_text(text);
+}
+
+/// The most recently used measurements.
+#[derive(Debug)]
+pub struct TextCache {
+    capacity: usize,
+    extents: HashMap<(String, u64, String), TextExtents>,
+    /// The keys of `extents`, least recently used first.
+    order: VecDeque<(String, u64, String)>
+}
+
+impl TextCache {
+    pub fn new(capacity: usize) -> Self {
+        TextCache {
+            capacity,
+            extents: HashMap::new(),
+            order: VecDeque::new()
+        }
+    }
+
+    pub fn get(&mut self, font: &Font, text: &str) -> Option<TextExtents> {
+        let key = cache_key(font, text);
+        let extents = *self.extents.get(&key)?;
+        self.touch(&key);
+        Some(extents)
+    }
+
+    /// Remembers a measurement, forgetting the least recently used one if
+    /// the cache is full.
+    pub fn insert(&mut self, font: &Font, text: &str, extents: TextExtents) {
+        let key = cache_key(font, text);
+        if self.extents.insert(key.clone(), extents).is_some() {
+            self.touch(&key);
+            return;
+        }
+        self.order.push_back(key);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.extents.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.extents.clear();
+        self.order.clear();
+    }
+
+    fn touch(&mut self, key: &(String, u64, String)) {
+        if let Some(index) = self.order.iter().position(|other| other == key) {
+            let key = self.order.remove(index).unwrap();
+            self.order.push_back(key);
+        }
+    }
+}
+
+fn cache_key(font: &Font, text: &str) -> (String, u64, String) {
+    (font.family.clone(), font.size.to_bits(), text.into())
+}
+
+/// The modification times of the fontconfig configuration, which change
+/// when fonts that measurements depend on might have changed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FontconfigStamp(Vec<(PathBuf, Option<SystemTime>)>);
+
+impl FontconfigStamp {
+    /// Stats the configuration files fontconfig reads by default.
+    pub fn read() -> Self {
+        let mut paths = Vec::new();
+        match env::var("FONTCONFIG_FILE") {
+            Ok(file) => paths.push(PathBuf::from(file)),
+            Err(_) => {
+                paths.push(PathBuf::from("/etc/fonts/fonts.conf"));
+                paths.push(PathBuf::from("/etc/fonts/conf.d"));
+            }
+        }
+        let config_home = env::var("XDG_CONFIG_HOME").ok().map(PathBuf::from).or_else(|| {
+            env::var("HOME")
+                .ok()
+                .map(|home| PathBuf::from(home).join(".config"))
+        });
+        if let Some(config_home) = config_home {
+            paths.push(config_home.join("fontconfig/fonts.conf"));
+            paths.push(config_home.join("fontconfig/conf.d"));
+        }
+        FontconfigStamp::of(paths)
+    }
+
+    pub fn of(paths: Vec<PathBuf>) -> Self {
+        FontconfigStamp(
+            paths
+                .into_iter()
+                .map(|path| {
+                    let modified = fs::metadata(&path).and_then(|meta| meta.modified()).ok();
+                    (path, modified)
+                })
+                .collect()
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use cairo::{Format, ImageSurface};
+
+    fn font(family: &str, size: f64) -> Font {
+        Font {
+            family: family.into(),
+            size
+        }
+    }
+
+    fn extents(x_advance: f64) -> TextExtents {
+        TextExtents {
+            x_advance,
+            ..TextExtents::default()
+        }
+    }
+
+    #[test]
+    fn text_cache_lru() {
+        let mut cache = TextCache::new(2);
+        let mono = font("Monospace", 10.0);
+        cache.insert(&mono, "a", extents(1.0));
+        cache.insert(&mono, "b", extents(2.0));
+        assert_eq!(cache.get(&mono, "a"), Some(extents(1.0)));
+        // "b" is the least recently used now.
+        cache.insert(&mono, "c", extents(3.0));
+        assert_eq!(cache.get(&mono, "b"), None);
+        assert_eq!(cache.get(&mono, "a"), Some(extents(1.0)));
+        assert_eq!(cache.get(&font("Monospace", 11.0), "a"), None);
+        cache.clear();
+        assert_eq!(cache.get(&mono, "c"), None);
+    }
+
+    #[test]
+    fn text_fontconfig_stamp() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = dir.path().join("fonts.conf");
+        let stamp = FontconfigStamp::of(vec![conf.clone()]);
+        assert_eq!(stamp, FontconfigStamp::of(vec![conf.clone()]));
+        fs::write(&conf, b"<fontconfig/>").unwrap();
+        assert_ne!(stamp, FontconfigStamp::of(vec![conf]));
+    }
+
+    /// The horizontal span of the pixels that were drawn on.
+    fn inked_width(surface: &mut ImageSurface) -> f64 {
+        let width = surface.get_width() as usize;
+        let stride = surface.get_stride() as usize;
+        let data = surface.get_data().unwrap();
+        let inked: Vec<usize> = (0..width)
+            .filter(|x| data.chunks(stride).any(|row| row[x * 4 + 3] != 0))
+            .collect();
+        match (inked.first(), inked.last()) {
+            (Some(first), Some(last)) => (last - first + 1) as f64,
+            _ => 0.0
+        }
+    }
+
+    #[test]
+    fn text_measure_then_draw() {
+        for family in &["Sans", "Serif", "Monospace"] {
+            for size in &[8.0, 11.0, 16.0, 24.0] {
+                let mut surface = ImageSurface::create(Format::ARgb32, 1024, 64).unwrap();
+                let cr = Context::new(&surface);
+                select_font(&cr, &font(family, *size));
+                let measured = measure(&cr, "12:34 Wed, Mar 5").unwrap();
+                cr.move_to(8.0, measured.ascent);
+                draw_text(&cr, "12:34 Wed, Mar 5");
+                drop(cr);
+                let rendered = inked_width(&mut surface);
+                assert!(
+                    (rendered - measured.width).abs() <= 1.0,
+                    "{} {}: measured {}, rendered {}",
+                    family,
+                    size,
+                    measured.width,
+                    rendered
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn text_measure_edge_cases() {
+        let surface = ImageSurface::create(Format::ARgb32, 1, 1).unwrap();
+        let cr = Context::new(&surface);
+        select_font(&cr, &font("Sans", 12.0));
+        let empty = measure(&cr, "").unwrap();
+        assert_eq!((empty.width, empty.x_advance), (0.0, 0.0));
+        assert!(empty.height > 0.0 && empty.ascent > 0.0);
+        // Combining marks on their own are measured, whatever they look like.
+        assert!(measure(&cr, "\u{301}\u{308}").is_ok());
+        assert!(measure(&cr, &"a".repeat(MAX_TEXT_LEN)).is_ok());
+        assert!(measure(&cr, &"a".repeat(MAX_TEXT_LEN + 1)).is_err());
+    }
+}
@@ -0,0 +1,581 @@
+//! `awesome.control_socket`, which lets other programs own drawins, e.g. a
+//! widget rendered by a sandboxed process that can crash without taking the
+//! bars with it.
+//!
+//! A program connects to the Unix socket, whose path is also in
+//! `WAY_COOLER_CONTROL_SOCKET` for the programs Lua spawns, and claims
+//! drawins with the commands of `protocol`. It's sent what happens to them
+//! as events, like their input and where they are, see
+//! `objects::drawin::owned`. A program that reads its events too slowly
+//! misses some, see `socket`, it can't block the main loop.
+//!
+//! Commands are run on the Lua thread from the main loop at timer
+//! priority, like the callbacks of `spawn_lines`.
+
+mod protocol;
+mod socket;
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    env,
+    os::unix::io::RawFd,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender}
+    },
+    time::{Duration, Instant}
+};
+
+use glib::Continue;
+use nix::unistd;
+use rlua::{self, Table, Value};
+
+use crate::area::Size;
+use crate::json::Json;
+use crate::objects::drawin::{
+    owned::{self, OwnerError},
+    DrawinId
+};
+use crate::scheduler::{self, Priority};
+
+use self::protocol::{Command, Request};
+use self::socket::{Message, Outbox};
+
+/// Where programs spawned by Lua find the socket.
+pub const SOCKET_ENV: &str = "WAY_COOLER_CONTROL_SOCKET";
+
+/// How long the drawins of a lost connection are kept, by default.
+const DEFAULT_GRACE_SECONDS: f64 = 5.0;
+
+/// Whether the Lua thread has been woken up to handle the messages.
+static WOKEN: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static CHANNEL: RefCell<Channel> = RefCell::new(Channel::new());
+    static CONNECTIONS: RefCell<HashMap<u64, Connection>> = RefCell::new(HashMap::new());
+    /// Where the socket is, once Lua asked for it.
+    static SOCKET: RefCell<Option<PathBuf>> = RefCell::new(None);
+    static GRACE: Cell<Duration> = Cell::new(Duration::from_millis((DEFAULT_GRACE_SECONDS * 1000.0) as u64));
+}
+
+struct Channel {
+    sender: Sender<Message>,
+    receiver: Receiver<Message>
+}
+
+impl Channel {
+    fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Channel { sender, receiver }
+    }
+}
+
+struct Connection {
+    /// What the program called itself with "hello".
+    name: String,
+    outbox: Outbox
+}
+
+/// `awesome.control_socket(path, options)`, which listens for programs on
+/// `path` and returns it, or nil and the error.
+///
+/// The socket is `way-cooler-control-<pid>` in `XDG_RUNTIME_DIR` without a
+/// path, and is only created once. The options are `grace`, how many
+/// seconds the drawins of a program are kept after it disconnected (5 by
+/// default).
+pub fn control_socket<'lua>(
+    lua: rlua::Context<'lua>,
+    (path, options): (Option<String>, Option<Table<'lua>>)
+) -> rlua::Result<(Value<'lua>, Value<'lua>)> {
+    if let Some(options) = options {
+        if let Some(grace) = options.get::<_, Option<f64>>("grace")? {
+            if !grace.is_finite() || grace < 0.0 {
+                return Err(rlua::Error::RuntimeError(format!(
+                    "awesome.control_socket: the grace period must be a positive number of seconds, got {}",
+                    grace
+                )));
+            }
+            GRACE.with(|period| period.set(Duration::from_millis((grace * 1000.0) as u64)));
+        }
+    }
+    if let Some(listening) = SOCKET.with(|socket| socket.borrow().clone()) {
+        return Ok((
+            Value::String(lua.create_string(&listening.to_string_lossy()[..])?),
+            Value::Nil
+        ));
+    }
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let runtime = env::var_os("XDG_RUNTIME_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(env::temp_dir);
+            runtime.join(format!("way-cooler-control-{}", std::process::id()))
+        }
+    };
+    let sender = CHANNEL.with(|channel| channel.borrow().sender.clone());
+    if let Err(err) = socket::listen(&path, sender, wake) {
+        let message = format!("could not listen on {}: {}", path.display(), err);
+        return Ok((Value::Nil, Value::String(lua.create_string(&message)?)));
+    }
+    env::set_var(SOCKET_ENV, &path);
+    SOCKET.with(|socket| *socket.borrow_mut() = Some(path.clone()));
+    Ok((
+        Value::String(lua.create_string(&path.to_string_lossy()[..])?),
+        Value::Nil
+    ))
+}
+
+/// Sends `event` to `connection`, unless it's gone. It's dropped if the
+/// program doesn't keep up.
+pub fn send(connection: u64, event: Json) {
+    CONNECTIONS.with(|connections| {
+        if let Some(connection) = connections.borrow_mut().get_mut(&connection) {
+            connection.outbox.send(&event);
+        }
+    })
+}
+
+/// Wakes up the Lua thread to handle the messages, unless it already was.
+fn wake() {
+    if !WOKEN.swap(true, Ordering::SeqCst) {
+        glib::idle_add(|| {
+            scheduler::defer(Priority::Timer, dispatch);
+            Continue(false)
+        });
+    }
+}
+
+/// Handles every message that was sent.
+fn dispatch(lua: rlua::Context) -> rlua::Result<()> {
+    WOKEN.store(false, Ordering::SeqCst);
+    let messages: Vec<_> = CHANNEL.with(|channel| channel.borrow().receiver.try_iter().collect());
+    for message in messages {
+        if let Err(err) = handle(lua, message) {
+            warn!("Could not handle a control message: {}", err);
+        }
+    }
+    Ok(())
+}
+
+fn handle(lua: rlua::Context, message: Message) -> rlua::Result<()> {
+    match message {
+        Message::Connected(id, outbox) => {
+            let connection = Connection {
+                name: format!("connection {}", id),
+                outbox
+            };
+            CONNECTIONS.with(|connections| connections.borrow_mut().insert(id, connection));
+            Ok(())
+        },
+        Message::Line(id, line, fds) => {
+            let reply = match protocol::parse(&line) {
+                Ok(request) => run(lua, id, request, fds)?,
+                Err(err) => {
+                    close(fds);
+                    protocol::reply(err.id.clone(), Err(("protocol", err.message)))
+                }
+            };
+            send(id, reply);
+            Ok(())
+        },
+        Message::Disconnected(id) => {
+            CONNECTIONS.with(|connections| connections.borrow_mut().remove(&id));
+            let grace = GRACE.with(Cell::get);
+            owned::orphan(lua, id, Instant::now() + grace)?;
+            glib::timeout_add(grace.as_millis() as u32 + 1, || {
+                scheduler::defer(Priority::Timer, |lua| expire(lua, Instant::now()).map(|_| ()));
+                Continue(false)
+            });
+            Ok(())
+        }
+    }
+}
+
+/// Removes the drawins of lost connections whose grace period is over by
+/// `now`, returning their ids.
+pub fn expire(lua: rlua::Context, now: Instant) -> rlua::Result<Vec<usize>> {
+    owned::expire(lua, now)
+}
+
+/// Runs a command, returning the reply. The file descriptors are closed
+/// unless the command took them.
+fn run(lua: rlua::Context, connection: u64, request: Request, mut fds: Vec<RawFd>) -> rlua::Result<Json> {
+    let Request { id, command } = request;
+    let fd = if command.takes_fd() && fds.len() == 1 {
+        fds.pop()
+    } else {
+        None
+    };
+    close(fds);
+    let result = execute(lua, connection, command, fd);
+    if let Some(fd) = fd {
+        // The compositor has its own copy by now.
+        let _ = unistd::close(fd);
+    }
+    Ok(protocol::reply(id, result?))
+}
+
+type Outcome = Result<Vec<(String, Json)>, (&'static str, String)>;
+
+fn execute(
+    lua: rlua::Context,
+    connection: u64,
+    command: Command,
+    fd: Option<RawFd>
+) -> rlua::Result<Outcome> {
+    let name = CONNECTIONS.with(|connections| {
+        connections
+            .borrow()
+            .get(&connection)
+            .map(|connection| connection.name.clone())
+            .unwrap_or_default()
+    });
+    let owned_drawin = |drawin| owned::owned(lua, connection, drawin);
+    let lua_error = |err: rlua::Error| Err(("lua", err.to_string()));
+    let owner_error = |err: OwnerError| Err((err.kind(), err.to_string()));
+    Ok(match command {
+        Command::Hello { name } => {
+            CONNECTIONS.with(|connections| {
+                if let Some(connection) = connections.borrow_mut().get_mut(&connection) {
+                    connection.name = name;
+                }
+            });
+            Ok(Vec::new())
+        },
+        Command::Claim { properties } => {
+            let properties = match properties.into_lua(lua)? {
+                Value::Table(properties) => properties,
+                _ => lua.create_table()?
+            };
+            match owned::claim(lua, connection, name, properties) {
+                Ok((drawin, token)) => {
+                    let DrawinId(id) = drawin.id()?;
+                    Ok(vec![
+                        ("drawin".into(), Json::Integer(id as i64)),
+                        ("token".into(), Json::String(token)),
+                    ])
+                },
+                Err(err) => lua_error(err)
+            }
+        },
+        Command::Adopt { drawin, token } => match owned::adopt(lua, connection, name, drawin, &token)? {
+            Ok(_) => Ok(Vec::new()),
+            Err(err) => owner_error(err)
+        },
+        Command::Set {
+            drawin,
+            property,
+            value
+        } => match owned_drawin(drawin)? {
+            Ok(drawin) => match owned::set(lua, drawin, property, value.into_lua(lua)?) {
+                Ok(()) => Ok(Vec::new()),
+                Err(err) => lua_error(err)
+            },
+            Err(err) => owner_error(err)
+        },
+        Command::Get { drawin, property } => match owned_drawin(drawin)? {
+            Ok(drawin) => match owned::get(lua, drawin, &property) {
+                Ok(value) => match Json::from_lua(value) {
+                    Ok(value) => Ok(vec![("value".into(), value)]),
+                    Err(err) => Err(("unencodable", err.to_string()))
+                },
+                Err(err) => lua_error(err)
+            },
+            Err(err) => owner_error(err)
+        },
+        Command::ImportShm {
+            drawin,
+            width,
+            height,
+            stride,
+            offset
+        } => match (owned_drawin(drawin)?, fd) {
+            (Ok(drawin), Some(fd)) => {
+                let size = Size { width, height };
+                match owned::import(lua, drawin, fd, size, stride, offset)? {
+                    Ok(buffer) => Ok(vec![("buffer".into(), Json::Integer(buffer as i64))]),
+                    Err(err) => Err((err.kind(), err.to_string()))
+                }
+            },
+            (Ok(_), None) => Err((
+                "protocol",
+                "import-shm needs exactly one file descriptor sent with it".into()
+            )),
+            (Err(err), _) => owner_error(err)
+        },
+        Command::Commit { drawin, buffer } => match owned_drawin(drawin)? {
+            Ok(drawin) => match owned::commit(lua, drawin, buffer)? {
+                Ok(()) => Ok(Vec::new()),
+                Err(err) => Err((err.kind(), err.to_string()))
+            },
+            Err(err) => owner_error(err)
+        },
+        Command::DestroyBuffer { drawin, buffer } => match owned_drawin(drawin)? {
+            Ok(drawin) => {
+                if owned::destroy_buffer(lua, &drawin, buffer)? {
+                    Ok(Vec::new())
+                } else {
+                    Err((
+                        "destroyed",
+                        format!("drawin {} has no buffer {}", drawin.id()?.0, buffer)
+                    ))
+                }
+            },
+            Err(err) => owner_error(err)
+        },
+        Command::Release { drawin } => match owned_drawin(drawin)? {
+            Ok(drawin) => owned::release(lua, drawin).map(|_| Vec::new()).or_else(lua_error),
+            Err(err) => owner_error(err)
+        }
+    })
+}
+
+fn close(fds: Vec<RawFd>) {
+    for fd in fds {
+        let _ = unistd::close(fd);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        env,
+        fs::File,
+        io::{BufRead, BufReader, Lines, Write},
+        os::unix::{io::AsRawFd, net::UnixStream},
+        process::Command
+    };
+
+    use nix::sys::{
+        socket::{sendmsg, ControlMessage, MsgFlags},
+        uio::IoVec
+    };
+    use rlua::Lua;
+
+    use super::*;
+    use crate::common::signal;
+    use crate::objects::{
+        drawable,
+        drawin::{self, Drawin, DRAWINS_HANDLE},
+        screen::SCREENS_HANDLE
+    };
+
+    /// Set in the helper process, to the path of the socket.
+    const CHILD_SOCKET: &str = "WAY_COOLER_CONTROL_TEST_SOCKET";
+
+    fn no_wake() {}
+
+    /// One end of a connection, as a program sees it.
+    struct Program {
+        stream: UnixStream,
+        lines: Lines<BufReader<UnixStream>>
+    }
+
+    impl Program {
+        /// Sends `command` with the file descriptors and returns the reply,
+        /// skipping the events sent before it.
+        fn request(&mut self, command: &str, fds: &[i32]) -> Json {
+            let line = format!("{}\n", command);
+            let cmsgs = [ControlMessage::ScmRights(fds)];
+            let cmsgs = if fds.is_empty() { &[][..] } else { &cmsgs[..] };
+            let iov = [IoVec::from_slice(line.as_bytes())];
+            sendmsg(self.stream.as_raw_fd(), &iov, cmsgs, MsgFlags::empty(), None).unwrap();
+            self.next(|json| json.get("reply").is_some())
+        }
+
+        /// The next line `wanted` is true for.
+        fn next<F: Fn(&Json) -> bool>(&mut self, wanted: F) -> Json {
+            loop {
+                let line = self.lines.next().expect("the connection was closed").unwrap();
+                let json = Json::parse(line.as_bytes()).unwrap();
+                if wanted(&json) {
+                    return json;
+                }
+            }
+        }
+    }
+
+    fn error_kind(reply: &Json) -> Option<&Json> {
+        reply.get("error").and_then(|error| error.get("kind"))
+    }
+
+    /// Claims a drawin and renders frames into it, then sets a property
+    /// once it was clicked, and exits without releasing it.
+    fn helper(path: &str) {
+        let stream = UnixStream::connect(path).unwrap();
+        let lines = BufReader::new(stream.try_clone().unwrap()).lines();
+        let mut program = Program { stream, lines };
+        let ok = Some(&Json::Bool(true));
+        let hello = program.request(r#"{"id":1,"command":"hello","name":"dashboard"}"#, &[]);
+        assert_eq!(hello.get("ok"), ok);
+        let claim = program.request(
+            r#"{"id":2,"command":"claim-drawin","properties":{"x":10,"y":20,"width":64,"height":32}}"#,
+            &[]
+        );
+        assert_eq!(claim.get("reply"), Some(&Json::Integer(2)));
+        let drawin = match claim.get("drawin") {
+            Some(&Json::Integer(drawin)) => drawin,
+            _ => panic!("no drawin in {}", claim)
+        };
+        for frame in 0..2u8 {
+            let mut file: File = tempfile::tempfile().unwrap();
+            file.write_all(&vec![frame; 64 * 32 * 4]).unwrap();
+            let import = format!(
+                r#"{{"id":3,"command":"import-shm","drawin":{},"width":64,"height":32,"stride":256}}"#,
+                drawin
+            );
+            // The file passed the checks, but there's no compositor to
+            // show it.
+            let reply = program.request(&import, &[file.as_raw_fd()]);
+            assert_eq!(
+                error_kind(&reply),
+                Some(&Json::String("import".into())),
+                "{}",
+                reply
+            );
+            let reply = program.request(&import, &[]);
+            assert_eq!(
+                error_kind(&reply),
+                Some(&Json::String("protocol".into())),
+                "{}",
+                reply
+            );
+        }
+        let commit = format!(r#"{{"id":4,"command":"commit","drawin":{},"buffer":99}}"#, drawin);
+        let reply = program.request(&commit, &[]);
+        assert_eq!(
+            error_kind(&reply),
+            Some(&Json::String("destroyed".into())),
+            "{}",
+            reply
+        );
+        let reply = program.request(r#"{"id":5,"command":"release-drawin","drawin":1000000}"#, &[]);
+        assert_eq!(
+            error_kind(&reply),
+            Some(&Json::String("unknown_drawin".into())),
+            "{}",
+            reply
+        );
+
+        let click = program.next(|json| json.get("event") == Some(&Json::String("button::press".into())));
+        assert_eq!(click.get("drawin"), Some(&Json::Integer(drawin)));
+        assert_eq!(
+            click.get("args").map(Json::to_string),
+            Some("[5,6,1,{},0]".to_string())
+        );
+        let set = format!(
+            r#"{{"id":6,"command":"set","drawin":{},"property":"ontop","value":true}}"#,
+            drawin
+        );
+        assert_eq!(program.request(&set, &[]).get("ok"), ok);
+        // Gone without a word, like a crash.
+        unsafe { nix::libc::_exit(0) }
+    }
+
+    /// Handles messages until `last` was.
+    fn pump<F: Fn(&Message) -> bool>(
+        lua: rlua::Context,
+        receiver: &Receiver<Message>,
+        last: F
+    ) -> rlua::Result<()> {
+        loop {
+            let message = receiver
+                .recv_timeout(Duration::from_secs(30))
+                .expect("the helper went quiet");
+            let done = last(&message);
+            handle(lua, message)?;
+            if done {
+                return Ok(());
+            }
+        }
+    }
+
+    #[test]
+    fn control_helper_owns_drawin() -> rlua::Result<()> {
+        if let Some(path) = env::var_os(CHILD_SOCKET) {
+            helper(&path.to_string_lossy());
+            unreachable!("the helper didn't exit");
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("control");
+        let (sender, receiver) = mpsc::channel();
+        socket::listen(&path, sender, no_wake).unwrap();
+        let mut child = Command::new(env::current_exe().unwrap())
+            .args(&[
+                "control::test::control_helper_owns_drawin",
+                "--exact",
+                "--test-threads=1"
+            ])
+            .env(CHILD_SOCKET, &path)
+            .spawn()
+            .unwrap();
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            drawin::init(lua)?;
+            lua.set_named_registry_value(SCREENS_HANDLE, lua.create_table()?)?;
+            let line = |message: &Message, text: &str| match message {
+                Message::Line(_, line, _) => line.contains(text),
+                _ => false
+            };
+            pump(lua, &receiver, |message| line(message, r#""id":5"#))?;
+            let drawins = lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)?;
+            assert_eq!(drawins.len(), 1);
+            lua.globals().set("owned", drawins[0].clone())?;
+            lua.load(
+                r#"
+local d = owned
+assert(d.owner == "dashboard", tostring(d.owner))
+assert(d.width == 64 and d.height == 32)
+-- Lua may move it.
+d.x = 100
+assert(d.x == 100)
+local ok, err = pcall(function() d.width = 10 end)
+assert(not ok and tostring(err):find('owned by "dashboard"'), tostring(err))
+ok, err = pcall(d.geometry, d, { height = 10 })
+assert(not ok and tostring(err):find("not set height"), tostring(err))
+ok, err = pcall(d.remove, d)
+assert(not ok and tostring(err):find("remove it with force = true"), tostring(err))
+ok, err = pcall(d.import_shm, d, { fd = 0, width = 1, height = 1, stride = 4 })
+assert(not ok and tostring(err):find("with force = true"), tostring(err))
+-- Fields of its own are Lua's.
+d.group = "widgets"
+reasons = {}
+d:connect_signal("request::remove", function(_, reason) reasons[#reasons + 1] = reason end)
+                "#
+            )
+            .exec()?;
+            // A click, with the arguments the pointer handler gives it.
+            let args = (drawins[0].clone(), 5, 6, 1, lua.create_table()?, 0);
+            signal::emit_signals(lua, drawins[0].signals()?, "button::press", args)?;
+            pump(lua, &receiver, |message| match message {
+                Message::Disconnected(_) => true,
+                _ => false
+            })?;
+            let status = child.wait().unwrap();
+            assert!(status.success(), "the helper failed: {}", status);
+            lua.load(
+                r#"
+assert(owned.ontop)
+assert(owned.owner == "dashboard")
+                "#
+            )
+            .exec()?;
+            // Kept for the grace period, in case the helper comes back.
+            assert_eq!(expire(lua, Instant::now())?, Vec::<usize>::new());
+            let id = lua.load("return owned.id").eval::<usize>()?;
+            let later = Instant::now() + GRACE.with(Cell::get) + Duration::from_secs(1);
+            assert_eq!(expire(lua, later)?, vec![id]);
+            lua.load(
+                r#"
+assert(drawin.by_id(owned.id) == nil)
+assert(#reasons == 1 and reasons[1] == "disconnected")
+                "#
+            )
+            .exec()
+        })
+    }
+}
@@ -0,0 +1,254 @@
+//! The commands programs send on the control socket, a JSON object per
+//! line like `{"id":1,"command":"claim-drawin","properties":{"width":300}}`.
+//!
+//! The `id` is optional, any JSON value, and is given back as the `reply`
+//! of the answer, which is `{"reply":1,"ok":true,...}` or `{"reply":1,
+//! "error":{"kind":"...","message":"..."}}`. Events have an `event` field
+//! instead, see `objects::drawin::owned`.
+
+use std::fmt;
+
+use crate::json::Json;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub id: Option<Json>,
+    pub command: Command
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Names the program in `drawin.owner`.
+    Hello {
+        name: String
+    },
+    /// Creates a drawin the connection owns.
+    Claim {
+        properties: Json
+    },
+    /// Takes over a drawin whose owner was lost.
+    Adopt {
+        drawin: usize,
+        token: String
+    },
+    Set {
+        drawin: usize,
+        property: String,
+        value: Json
+    },
+    Get {
+        drawin: usize,
+        property: String
+    },
+    /// Imports the buffer in the file descriptor sent with the line.
+    ImportShm {
+        drawin: usize,
+        width: u32,
+        height: u32,
+        stride: i64,
+        offset: i64
+    },
+    Commit {
+        drawin: usize,
+        buffer: usize
+    },
+    DestroyBuffer {
+        drawin: usize,
+        buffer: usize
+    },
+    /// Removes a drawin the connection owns.
+    Release {
+        drawin: usize
+    }
+}
+
+impl Command {
+    /// Whether the command comes with a file descriptor.
+    pub fn takes_fd(&self) -> bool {
+        match self {
+            Command::ImportShm { .. } => true,
+            _ => false
+        }
+    }
+}
+
+/// Why a line isn't a command, with its id if it had one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtocolError {
+    pub id: Option<Json>,
+    pub message: String
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+pub fn parse(line: &str) -> Result<Request, ProtocolError> {
+    let json = Json::parse(line.as_bytes()).map_err(|err| ProtocolError {
+        id: None,
+        message: err.to_string()
+    })?;
+    let id = json.get("id").cloned();
+    let fail = |message: String| ProtocolError {
+        id: id.clone(),
+        message
+    };
+    let name = match (&json, json.get("command")) {
+        (Json::Object(_), Some(Json::String(name))) => name.clone(),
+        (Json::Object(_), _) => return Err(fail("expected a command".into())),
+        _ => return Err(fail("expected an object".into()))
+    };
+    let fields = Fields { json: &json, fail };
+    let command = match name.as_str() {
+        "hello" => Command::Hello {
+            name: fields.string("name")?
+        },
+        "claim-drawin" => Command::Claim {
+            properties: match json.get("properties") {
+                Some(properties @ Json::Object(_)) => properties.clone(),
+                None => Json::Object(Vec::new()),
+                Some(_) => return Err((fields.fail)("properties must be an object".into()))
+            }
+        },
+        "adopt-drawin" => Command::Adopt {
+            drawin: fields.id("drawin")?,
+            token: fields.string("token")?
+        },
+        "set" => Command::Set {
+            drawin: fields.id("drawin")?,
+            property: fields.string("property")?,
+            value: json.get("value").cloned().unwrap_or(Json::Null)
+        },
+        "get" => Command::Get {
+            drawin: fields.id("drawin")?,
+            property: fields.string("property")?
+        },
+        "import-shm" => Command::ImportShm {
+            drawin: fields.id("drawin")?,
+            width: fields.integer("width", 1, i64::from(u32::max_value()))? as u32,
+            height: fields.integer("height", 1, i64::from(u32::max_value()))? as u32,
+            stride: fields.integer("stride", 1, i64::from(i32::max_value()))?,
+            offset: match json.get("offset") {
+                None => 0,
+                Some(_) => fields.integer("offset", 0, i64::from(i32::max_value()))?
+            }
+        },
+        "commit" => Command::Commit {
+            drawin: fields.id("drawin")?,
+            buffer: fields.id("buffer")?
+        },
+        "destroy-buffer" => Command::DestroyBuffer {
+            drawin: fields.id("drawin")?,
+            buffer: fields.id("buffer")?
+        },
+        "release-drawin" => Command::Release {
+            drawin: fields.id("drawin")?
+        },
+        name => return Err((fields.fail)(format!("unknown command {:?}", name)))
+    };
+    Ok(Request { id, command })
+}
+
+/// The reply to the command with `id`, with `fields` added if it worked.
+pub fn reply(id: Option<Json>, result: Result<Vec<(String, Json)>, (&str, String)>) -> Json {
+    let mut members = vec![("reply".to_string(), id.unwrap_or(Json::Null))];
+    match result {
+        Ok(fields) => {
+            members.push(("ok".into(), Json::Bool(true)));
+            members.extend(fields);
+        },
+        Err((kind, message)) => members.push((
+            "error".into(),
+            Json::Object(vec![
+                ("kind".into(), Json::String(kind.into())),
+                ("message".into(), Json::String(message)),
+            ])
+        ))
+    }
+    Json::Object(members)
+}
+
+struct Fields<'a, F> {
+    json: &'a Json,
+    fail: F
+}
+
+impl<'a, F: Fn(String) -> ProtocolError> Fields<'a, F> {
+    fn string(&self, name: &str) -> Result<String, ProtocolError> {
+        match self.json.get(name) {
+            Some(Json::String(value)) => Ok(value.clone()),
+            _ => Err((self.fail)(format!("{} must be a string", name)))
+        }
+    }
+
+    fn integer(&self, name: &str, min: i64, max: i64) -> Result<i64, ProtocolError> {
+        match self.json.get(name) {
+            Some(&Json::Integer(value)) if value >= min && value <= max => Ok(value),
+            _ => Err((self.fail)(format!(
+                "{} must be an integer from {} to {}",
+                name, min, max
+            )))
+        }
+    }
+
+    fn id(&self, name: &str) -> Result<usize, ProtocolError> {
+        self.integer(name, 1, i64::max_value()).map(|id| id as usize)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn control_protocol_commands() {
+        assert_eq!(
+            parse(r#"{"id":"a","command":"set","drawin":3,"property":"width","value":200}"#),
+            Ok(Request {
+                id: Some(Json::String("a".into())),
+                command: Command::Set {
+                    drawin: 3,
+                    property: "width".into(),
+                    value: Json::Integer(200)
+                }
+            })
+        );
+        assert_eq!(
+            parse(r#"{"command":"import-shm","drawin":3,"width":64,"height":32,"stride":256}"#)
+                .map(|r| r.command),
+            Ok(Command::ImportShm {
+                drawin: 3,
+                width: 64,
+                height: 32,
+                stride: 256,
+                offset: 0
+            })
+        );
+        assert_eq!(
+            parse(r#"{"command":"claim-drawin"}"#).map(|r| r.command),
+            Ok(Command::Claim {
+                properties: Json::Object(Vec::new())
+            })
+        );
+        let error = |line| parse(line).unwrap_err();
+        assert_eq!(error("[1]").message, "expected an object");
+        assert_eq!(error("{").id, None);
+        assert_eq!(
+            error(r#"{"id":7,"command":"commit","drawin":0,"buffer":1}"#),
+            ProtocolError {
+                id: Some(Json::Integer(7)),
+                message: format!("drawin must be an integer from 1 to {}", i64::max_value())
+            }
+        );
+        assert_eq!(
+            error(r#"{"command":"paint"}"#).message,
+            r#"unknown command "paint""#
+        );
+        assert_eq!(
+            reply(Some(Json::Integer(2)), Err(("busy", "not yet".into()))).to_string(),
+            r#"{"reply":2,"error":{"kind":"busy","message":"not yet"}}"#
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! The Unix socket of `control`, read and written on threads of their own
+//! so a program that's slow or stuck can't block the main loop.
+//!
+//! Every connection has a thread reading its lines, with the file
+//! descriptors sent along with them, and one writing what the Lua thread
+//! put in its outbox. The outbox holds a fixed number of lines; what
+//! doesn't fit is dropped, and the program is told how many lines it
+//! missed before the next one that fits.
+
+use std::{
+    fs, io,
+    io::Write,
+    mem,
+    os::unix::{
+        io::{AsRawFd, RawFd},
+        net::{UnixListener, UnixStream}
+    },
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, Sender, SyncSender, TrySendError}
+    },
+    thread
+};
+
+use nix::{
+    errno::Errno,
+    sys::{
+        socket::{recvmsg, CmsgSpace, ControlMessage, MsgFlags},
+        uio::IoVec
+    },
+    unistd
+};
+
+use crate::json::Json;
+
+/// How many lines an outbox holds.
+pub const OUTBOX_SIZE: usize = 256;
+
+/// The longest line a program may send.
+const MAX_LINE: usize = 1 << 20;
+
+/// How many file descriptors are received with one read.
+const MAX_FDS: usize = 8;
+
+static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(1);
+
+/// Something a connection did, as it's sent to the Lua thread.
+#[derive(Debug)]
+pub enum Message {
+    Connected(u64, Outbox),
+    /// A line without its newline, and the file descriptors that came with
+    /// it, which the Lua thread has to close.
+    Line(u64, String, Vec<RawFd>),
+    Disconnected(u64)
+}
+
+/// Where the Lua thread puts the lines for a connection.
+#[derive(Debug)]
+pub struct Outbox {
+    sender: SyncSender<String>,
+    /// How many lines were dropped since one was sent.
+    dropped: u64
+}
+
+impl Outbox {
+    /// An outbox holding `size` lines, and what the writer reads them from.
+    pub fn new(size: usize) -> (Outbox, Receiver<String>) {
+        let (sender, receiver) = mpsc::sync_channel(size);
+        (Outbox { sender, dropped: 0 }, receiver)
+    }
+
+    /// Queues `json` as a line, or drops it if the outbox is full. Returns
+    /// whether it was queued.
+    pub fn send(&mut self, json: &Json) -> bool {
+        if self.dropped > 0 {
+            let dropped = Json::Object(vec![
+                ("event".into(), Json::String("dropped".into())),
+                ("count".into(), Json::Integer(self.dropped as i64)),
+            ]);
+            if !self.try_send(dropped.to_string()) {
+                return false;
+            }
+            self.dropped = 0;
+        }
+        self.try_send(json.to_string())
+    }
+
+    fn try_send(&mut self, line: String) -> bool {
+        match self.sender.try_send(line) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                false
+            },
+            // The connection is gone, nobody misses anything.
+            Err(TrySendError::Disconnected(_)) => false
+        }
+    }
+}
+
+/// Listens on `path`, sending what every connection does to the Lua thread.
+///
+/// A socket left behind by a client that's gone is replaced. `wake` is
+/// called after every message, from the thread that sent it.
+pub fn listen(path: &Path, sender: Sender<Message>, wake: fn()) -> io::Result<()> {
+    let listener = match UnixListener::bind(path) {
+        Ok(listener) => listener,
+        Err(ref err) if err.kind() == io::ErrorKind::AddrInUse && UnixStream::connect(path).is_err() => {
+            fs::remove_file(path)?;
+            UnixListener::bind(path)?
+        },
+        Err(err) => return Err(err)
+    };
+    thread::Builder::new().name("control".into()).spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(err) = accept(stream, sender.clone(), wake) {
+                        warn!("Could not accept a control connection: {}", err);
+                    }
+                },
+                Err(err) => warn!("Could not accept a control connection: {}", err)
+            }
+        }
+    })?;
+    Ok(())
+}
+
+fn accept(stream: UnixStream, sender: Sender<Message>, wake: fn()) -> io::Result<()> {
+    let connection = NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed);
+    let writer = stream.try_clone()?;
+    let (outbox, lines) = Outbox::new(OUTBOX_SIZE);
+    thread::Builder::new()
+        .name(format!("control {} writer", connection))
+        .spawn(move || write_lines(writer, lines))?;
+    if sender.send(Message::Connected(connection, outbox)).is_err() {
+        return Ok(());
+    }
+    wake();
+    thread::Builder::new()
+        .name(format!("control {} reader", connection))
+        .spawn(move || read_lines(stream, connection, sender, wake))?;
+    Ok(())
+}
+
+/// Writes the lines of an outbox until it's dropped or the connection is
+/// lost.
+fn write_lines(mut stream: UnixStream, lines: Receiver<String>) {
+    for mut line in lines {
+        line.push('\n');
+        if stream.write_all(line.as_bytes()).is_err() {
+            break;
+        }
+    }
+}
+
+fn read_lines(stream: UnixStream, connection: u64, sender: Sender<Message>, wake: fn()) {
+    let mut buffer = vec![0; 4096];
+    let mut line = Vec::new();
+    let mut fds = Vec::new();
+    loop {
+        let read = {
+            let iov = [IoVec::from_mut_slice(&mut buffer)];
+            let mut space: CmsgSpace<[RawFd; MAX_FDS]> = CmsgSpace::new();
+            match recvmsg(stream.as_raw_fd(), &iov, Some(&mut space), MsgFlags::empty()) {
+                Ok(message) => {
+                    for cmsg in message.cmsgs() {
+                        if let ControlMessage::ScmRights(received) = cmsg {
+                            fds.extend_from_slice(received);
+                        }
+                    }
+                    message.bytes
+                },
+                Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                Err(_) => 0
+            }
+        };
+        if read == 0 {
+            break;
+        }
+        for &byte in &buffer[..read] {
+            if byte != b'\n' {
+                line.push(byte);
+                continue;
+            }
+            let text = String::from_utf8_lossy(&line).into_owned();
+            line.clear();
+            let fds = mem::replace(&mut fds, Vec::new());
+            if sender.send(Message::Line(connection, text, fds)).is_err() {
+                return;
+            }
+            wake();
+        }
+        if line.len() > MAX_LINE {
+            warn!(
+                "Control connection {} sent a line longer than {} bytes",
+                connection, MAX_LINE
+            );
+            break;
+        }
+    }
+    for fd in fds {
+        let _ = unistd::close(fd);
+    }
+    if sender.send(Message::Disconnected(connection)).is_ok() {
+        wake();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn control_outbox_drops_and_flags() {
+        let (mut outbox, lines) = Outbox::new(2);
+        let event = |n| Json::Object(vec![("n".into(), Json::Integer(n))]);
+        assert!(outbox.send(&event(1)));
+        assert!(outbox.send(&event(2)));
+        // Nobody reads, so these don't fit.
+        assert!(!outbox.send(&event(3)));
+        assert!(!outbox.send(&event(4)));
+        assert_eq!(lines.try_recv().unwrap(), r#"{"n":1}"#);
+        // The flag fits, the line after it doesn't.
+        assert!(!outbox.send(&event(5)));
+        assert_eq!(lines.try_recv().unwrap(), r#"{"n":2}"#);
+        assert_eq!(lines.try_recv().unwrap(), r#"{"event":"dropped","count":2}"#);
+        assert!(outbox.send(&event(6)));
+        let rest: Vec<_> = lines.try_iter().collect();
+        assert_eq!(
+            rest,
+            vec![
+                r#"{"event":"dropped","count":1}"#.to_string(),
+                r#"{"n":6}"#.into()
+            ]
+        );
+        drop(lines);
+        assert!(!outbox.send(&event(7)));
+        assert_eq!(outbox.dropped, 0);
+    }
+}
@@ -0,0 +1,450 @@
+//! Crash reports: what the client was doing when it panicked or the
+//! compositor cut it off with a protocol error, kept for
+//! `awesome.last_crash_report()` after the restart.
+//!
+//! Nothing about the client can be trusted once it crashed, so the report
+//! is written carefully:
+//!
+//! * into a buffer allocated at startup, and never past its capacity,
+//! * by sections that only read what they can borrow without waiting, and
+//!   only on the main thread, whose state they know,
+//! * with plain `open(2)` and `write(2)` to paths worked out at startup,
+//!   after every section, so a section that crashes again still leaves the
+//!   ones before it,
+//! * once: a crash while writing the report doesn't start another one.
+//!
+//! Nothing calls into Lua or Wayland. Reports go to
+//! `$XDG_RUNTIME_DIR/way-cooler`, and to `$XDG_STATE_HOME/way-cooler` so
+//! they survive a reboot. The last few are kept, the newest as
+//! `crash.report`.
+
+mod report;
+
+use std::{
+    cell::RefCell,
+    env,
+    ffi::{CStr, CString},
+    fmt::{self, Write},
+    fs,
+    io::Read,
+    os::unix::ffi::OsStringExt,
+    panic::{self, PanicInfo},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex
+    },
+    thread::{self, ThreadId}
+};
+
+use rlua::{self, Table};
+
+pub use self::report::{Escaped, Report};
+use self::report::{ReportWriter, END, HEADER};
+use crate::clock;
+
+/// The most a report can be, what doesn't fit is left out.
+pub const MAX_REPORT_BYTES: usize = 64 * 1024;
+
+/// How many reports are kept in each directory.
+pub const KEPT_REPORTS: usize = 3;
+
+const REPORT_NAME: &str = "crash.report";
+
+/// Writes a part of a report, a line per entry. It mustn't allocate, block
+/// or call into Lua or Wayland, see `Escaped` for writing text that might
+/// have newlines.
+pub type Section = fn(&mut dyn Write) -> fmt::Result;
+
+lazy_static! {
+    static ref RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+}
+
+/// Set once a report is being written.
+static WRITING: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// The report of the crash that ended the last session, if it did.
+    static LAST_REPORT: RefCell<Option<(PathBuf, Report)>> = RefCell::new(None);
+}
+
+/// Everything needed to write a report, set up in advance.
+struct Recorder {
+    /// Where the report is written, with room for all of it.
+    buffer: Vec<u8>,
+    paths: Vec<CString>,
+    /// The file descriptors of `paths` while a report is written.
+    fds: Vec<libc::c_int>,
+    sections: Vec<(&'static str, Section)>,
+    main_thread: ThreadId,
+    pid: u32,
+    /// The monotonic clock when the client started, in microseconds.
+    startup: u64
+}
+
+/// Sets up crash reports, and reads the report of the last session if it
+/// crashed.
+pub fn install() {
+    let sections: [(&'static str, Section); 5] = [
+        ("scheduler", crate::scheduler::write_stats),
+        ("input_trace", crate::objects::drawin::write_input_trace),
+        ("leaks", crate::leaks::write_totals),
+        ("buffers", crate::objects::drawable::write_buffer_stats),
+        ("dbus", crate::dbus::write_stats)
+    ];
+    install_in(report_dirs(), &sections);
+}
+
+/// Sets up crash reports written to `dirs` with the sections, and reads
+/// the newest report in them.
+pub fn install_in(dirs: Vec<PathBuf>, sections: &[(&'static str, Section)]) {
+    let mut last = None;
+    let mut paths = Vec::new();
+    for dir in dirs {
+        if let Err(err) = fs::create_dir_all(&dir) {
+            warn!("Crash reports won't be written to {}: {}", dir.display(), err);
+            continue;
+        }
+        if last.is_none() {
+            last = read_report(&dir.join(REPORT_NAME));
+        }
+        rotate(&dir);
+        if let Ok(path) = CString::new(dir.join(REPORT_NAME).into_os_string().into_vec()) {
+            paths.push(path);
+        }
+    }
+    if let Some((ref path, _)) = last {
+        warn!("The last session crashed, see {}", path.display());
+    }
+    LAST_REPORT.with(|report| *report.borrow_mut() = last);
+    let fds = Vec::with_capacity(paths.len());
+    *RECORDER.lock().unwrap() = Some(Recorder {
+        buffer: Vec::with_capacity(MAX_REPORT_BYTES),
+        paths,
+        fds,
+        sections: sections.to_vec(),
+        main_thread: thread::current().id(),
+        pid: std::process::id(),
+        startup: clock::monotonic() - (clock::now() * 1000.0) as u64
+    });
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        report_panic(info);
+        previous(info)
+    }));
+}
+
+fn report_dirs() -> Vec<PathBuf> {
+    let runtime = env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir);
+    let state = env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")));
+    let mut dirs = vec![runtime.join("way-cooler")];
+    dirs.extend(state.map(|state| state.join("way-cooler")));
+    dirs
+}
+
+fn read_report(path: &Path) -> Option<(PathBuf, Report)> {
+    let mut text = String::new();
+    fs::File::open(path)
+        .and_then(|file| file.take(MAX_REPORT_BYTES as u64).read_to_string(&mut text))
+        .ok()?;
+    match Report::parse(&text) {
+        Ok(report) => Some((path.into(), report)),
+        Err(err) => {
+            warn!("Could not read the crash report {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+/// Makes room for a new report, renaming `crash.report` to
+/// `crash.report.1` and so on, and dropping the oldest.
+fn rotate(dir: &Path) {
+    let name = |n: usize| match n {
+        0 => dir.join(REPORT_NAME),
+        n => dir.join(format!("{}.{}", REPORT_NAME, n))
+    };
+    for n in (1..KEPT_REPORTS).rev() {
+        let _ = fs::rename(name(n - 1), name(n));
+    }
+}
+
+fn report_panic(info: &PanicInfo) {
+    let payload = info.payload();
+    let message = payload
+        .downcast_ref::<&str>()
+        .cloned()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("(not a string)");
+    write_report("panic", &|out| {
+        writeln!(out, "message: {}", Escaped(message))?;
+        if let Some(location) = info.location() {
+            writeln!(
+                out,
+                "location: {}:{}:{}",
+                Escaped(location.file()),
+                location.line(),
+                location.column()
+            )?;
+        }
+        Ok(())
+    });
+}
+
+/// Called by the main loop when the compositor disconnected us for a
+/// protocol error, with the object it was about.
+///
+/// # Safety
+///
+/// `interface` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn awesome_protocol_error(code: u32, interface: *const libc::c_char, id: u32) {
+    let interface = if interface.is_null() {
+        "(unknown)"
+    } else {
+        CStr::from_ptr(interface).to_str().unwrap_or("(not UTF-8)")
+    };
+    write_report("protocol error", &|out| {
+        writeln!(out, "message: error {} on {}@{}", code, Escaped(interface), id)
+    });
+}
+
+fn write_report(reason: &str, details: &dyn Fn(&mut dyn Write) -> fmt::Result) {
+    if WRITING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let mut recorder = match RECORDER.try_lock() {
+        Ok(recorder) => recorder,
+        Err(_) => return
+    };
+    let recorder = match recorder.as_mut() {
+        Some(recorder) => recorder,
+        None => return
+    };
+    let Recorder {
+        buffer,
+        paths,
+        fds,
+        sections,
+        main_thread,
+        pid,
+        startup
+    } = recorder;
+    fds.clear();
+    for path in paths.iter() {
+        let fd = unsafe {
+            libc::open(
+                path.as_ptr(),
+                libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC | libc::O_CLOEXEC,
+                0o600
+            )
+        };
+        if fd >= 0 && fds.len() < fds.capacity() {
+            fds.push(fd);
+        }
+    }
+    buffer.clear();
+    let mut flushed = 0;
+    let mut out = ReportWriter::new(buffer);
+    let thread = thread::current();
+    let _ = (|| -> fmt::Result {
+        writeln!(out, "{}", HEADER)?;
+        writeln!(out, "reason: {}", reason)?;
+        writeln!(out, "version: {}", env!("CARGO_PKG_VERSION"))?;
+        if !crate::GIT_VERSION.is_empty() {
+            writeln!(out, "commit: {}", Escaped(crate::GIT_VERSION.trim()))?;
+        }
+        writeln!(out, "pid: {}", pid)?;
+        let uptime = clock::monotonic().saturating_sub(*startup) as f64 / 1000.0;
+        writeln!(out, "uptime: {:.3}", uptime)?;
+        writeln!(out, "thread: {}", Escaped(thread.name().unwrap_or("(unnamed)")))?;
+        details(&mut out)
+    })();
+    flush(fds, &out, &mut flushed);
+    for &(name, section) in sections.iter() {
+        if writeln!(out, "[{}]", name).is_err() {
+            break;
+        }
+        if thread.id() != *main_thread {
+            let _ = writeln!(out, "(left out, the crash wasn't on the main thread)");
+        } else if section(&mut out).is_err() && out.is_truncated() {
+            break;
+        }
+        flush(fds, &out, &mut flushed);
+    }
+    let _ = writeln!(out, "{}", END);
+    flush(fds, &out, &mut flushed);
+    for &fd in fds.iter() {
+        unsafe { libc::close(fd) };
+    }
+}
+
+/// Writes what was added to the report since the last flush.
+fn flush(fds: &[libc::c_int], out: &ReportWriter, flushed: &mut usize) {
+    let new = &out.written()[*flushed..];
+    for &fd in fds {
+        let mut written = 0;
+        while written < new.len() {
+            let result = unsafe {
+                libc::write(
+                    fd,
+                    new[written..].as_ptr() as *const libc::c_void,
+                    new.len() - written
+                )
+            };
+            if result <= 0 {
+                break;
+            }
+            written += result as usize;
+        }
+    }
+    *flushed = out.len();
+}
+
+/// `awesome.last_crash_report()`, the report of the crash that ended the
+/// last session, or nil if it didn't crash.
+///
+/// The report is a table with its fields, like `reason`, `message` and
+/// `uptime`, the lines of each section by name, e.g. `input_trace`, `path`
+/// of the file and `truncated` if not all of it was written.
+pub fn last_crash_report<'lua>(lua: rlua::Context<'lua>, _: ()) -> rlua::Result<Option<Table<'lua>>> {
+    LAST_REPORT.with(|report| {
+        let report = report.borrow();
+        let (path, report) = match report.as_ref() {
+            Some(report) => report,
+            None => return Ok(None)
+        };
+        let table = lua.create_table()?;
+        for (key, value) in &report.fields {
+            table.set(key.as_str(), value.as_str())?;
+        }
+        for (name, entries) in &report.sections {
+            table.set(name.as_str(), entries.clone())?;
+        }
+        table.set("path", path.to_string_lossy().as_ref())?;
+        table.set("truncated", report.truncated)?;
+        Ok(Some(table))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::process::Command;
+
+    /// Set in the process that crashes, to the directory of its report.
+    const CHILD_DIR: &str = "WAY_COOLER_CRASH_TEST_DIR";
+
+    thread_local! {
+        static EVENTS: RefCell<Vec<&'static str>> = RefCell::new(Vec::with_capacity(8));
+    }
+
+    fn write_events(out: &mut dyn Write) -> fmt::Result {
+        EVENTS.with(|events| {
+            for event in events.borrow().iter() {
+                writeln!(out, "{}", Escaped(event))?;
+            }
+            Ok(())
+        })
+    }
+
+    fn crash_again(_: &mut dyn Write) -> fmt::Result {
+        panic!("crashed while writing the report")
+    }
+
+    /// Runs `test` again in a process of its own, which crashes, and reads
+    /// back the report of the crash.
+    fn crash_in_child(test: &str, setup: fn(PathBuf)) -> (Report, PathBuf) {
+        if let Some(dir) = env::var_os(CHILD_DIR) {
+            setup(PathBuf::from(dir));
+            unreachable!("the child didn't crash");
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let status = Command::new(env::current_exe().unwrap())
+            .args(&[test, "--exact", "--test-threads=1", "--nocapture"])
+            .env(CHILD_DIR, dir.path())
+            .status()
+            .unwrap();
+        assert!(!status.success());
+        // Next startup: the report is read back and rotated.
+        let (path, report) = read_report(&dir.path().join(REPORT_NAME)).expect("no report was written");
+        rotate(dir.path());
+        assert!(!path.exists());
+        assert!(dir.path().join("crash.report.1").exists());
+        (report, dir.into_path())
+    }
+
+    #[test]
+    fn crash_report_of_panic() {
+        let (report, dir) = crash_in_child("crash::test::crash_report_of_panic", |dir| {
+            install_in(vec![dir], &[("events", write_events)]);
+            EVENTS.with(|events| {
+                let mut events = events.borrow_mut();
+                events.push("pressed button 1");
+                events.push("error in handler:\ntraceback");
+            });
+            let code = 42;
+            panic!("controlled crash {}", code);
+        });
+        assert_eq!(report.field("reason"), Some("panic"));
+        assert_eq!(report.field("message"), Some("controlled crash 42"));
+        assert!(report.field("location").unwrap().starts_with("src/crash.rs:"));
+        // The test runs single threaded, on the main thread.
+        assert_eq!(report.field("thread"), Some("main"));
+        assert_eq!(
+            report.section("events"),
+            Some(
+                &[
+                    "pressed button 1".to_string(),
+                    "error in handler:\ntraceback".into()
+                ][..]
+            )
+        );
+        assert!(!report.truncated);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn crash_report_crash_while_writing() {
+        let (report, dir) = crash_in_child("crash::test::crash_report_crash_while_writing", |dir| {
+            install_in(
+                vec![dir],
+                &[
+                    ("events", write_events),
+                    ("again", crash_again),
+                    ("never", write_events)
+                ]
+            );
+            EVENTS.with(|events| events.borrow_mut().push("last event"));
+            panic!("first crash");
+        });
+        // The second crash aborts, leaving what was written before it.
+        assert_eq!(report.field("message"), Some("first crash"));
+        assert_eq!(report.section("events"), Some(&["last event".to_string()][..]));
+        assert_eq!(report.section("never"), None);
+        assert!(report.truncated);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn crash_report_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        for n in 0..5 {
+            rotate(dir.path());
+            fs::write(
+                dir.path().join(REPORT_NAME),
+                format!("{}\ncrash: {}\n", HEADER, n)
+            )
+            .unwrap();
+        }
+        let crash = |name: &str| read_report(&dir.path().join(name)).map(|(_, report)| report);
+        assert_eq!(crash(REPORT_NAME).unwrap().field("crash"), Some("4"));
+        assert_eq!(crash("crash.report.1").unwrap().field("crash"), Some("3"));
+        assert_eq!(crash("crash.report.2").unwrap().field("crash"), Some("2"));
+        assert!(!dir.path().join("crash.report.3").exists());
+    }
+}
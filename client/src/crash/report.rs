@@ -0,0 +1,215 @@
+//! The format of crash reports, and writing them without allocating.
+//!
+//! A report is text: a header line, `key: value` lines about the crash,
+//! then sections that start with `[name]` and have a line per entry, and
+//! `[end]` once everything was written. Newlines and backslashes in values
+//! and entries are escaped, so every line is one of them.
+
+use std::fmt::{self, Write};
+
+/// The first line of a report.
+pub const HEADER: &str = "way-cooler crash report 1";
+
+/// The line after the last section.
+pub const END: &str = "[end]";
+
+/// Writes into a buffer up to its capacity, so that it never allocates.
+/// What doesn't fit is left out.
+pub struct ReportWriter<'a> {
+    buffer: &'a mut Vec<u8>,
+    truncated: bool
+}
+
+impl<'a> ReportWriter<'a> {
+    pub fn new(buffer: &'a mut Vec<u8>) -> Self {
+        ReportWriter {
+            buffer,
+            truncated: false
+        }
+    }
+
+    /// Whether something didn't fit.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn written(&self) -> &[u8] {
+        self.buffer
+    }
+}
+
+impl<'a> Write for ReportWriter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = self.buffer.capacity() - self.buffer.len();
+        if s.len() <= room {
+            self.buffer.extend_from_slice(s.as_bytes());
+            return Ok(());
+        }
+        let mut end = room;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.buffer.extend_from_slice(s[..end].as_bytes());
+        self.truncated = true;
+        Err(fmt::Error)
+    }
+}
+
+/// Shows a value with its newlines and backslashes escaped, so it fits on
+/// a line of a report.
+pub struct Escaped<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for Escaped<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(Escaping(f), "{}", self.0)
+    }
+}
+
+struct Escaping<'a, 'b>(&'a mut fmt::Formatter<'b>);
+
+impl<'a, 'b> Write for Escaping<'a, 'b> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut rest = s;
+        while let Some(index) = rest.find(|c| c == '\n' || c == '\\') {
+            self.0.write_str(&rest[..index])?;
+            self.0.write_str(if rest.as_bytes()[index] == b'\n' {
+                "\\n"
+            } else {
+                "\\\\"
+            })?;
+            rest = &rest[index + 1..];
+        }
+        self.0.write_str(rest)
+    }
+}
+
+fn unescape(line: &str) -> String {
+    let mut unescaped = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                chars.next();
+                unescaped.push('\n');
+            },
+            ('\\', Some('\\')) => {
+                chars.next();
+                unescaped.push('\\');
+            },
+            (c, _) => unescaped.push(c)
+        }
+    }
+    unescaped
+}
+
+/// A report read back.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    /// What the report says about the crash, in order.
+    pub fields: Vec<(String, String)>,
+    /// The entries of each section, in order.
+    pub sections: Vec<(String, Vec<String>)>,
+    /// Set if the report ends before `[end]`, because it didn't fit or the
+    /// client died while writing it.
+    pub truncated: bool
+}
+
+impl Report {
+    pub fn parse(text: &str) -> Result<Report, String> {
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return Err("not a crash report".into());
+        }
+        let mut report = Report {
+            truncated: true,
+            ..Report::default()
+        };
+        let mut lines = lines.peekable();
+        while let Some(line) = lines.next() {
+            if line == END {
+                report.truncated = false;
+                break;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                report.sections.push((line[1..line.len() - 1].into(), Vec::new()));
+            } else if let Some((_, entries)) = report.sections.last_mut() {
+                entries.push(unescape(line));
+            } else if let Some(colon) = line.find(": ") {
+                report
+                    .fields
+                    .push((line[..colon].into(), unescape(&line[colon + 2..])));
+            } else if lines.peek().is_some() {
+                return Err(format!("malformed line \"{}\"", line));
+            }
+        }
+        Ok(report)
+    }
+
+    #[cfg(test)]
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[cfg(test)]
+    pub fn section(&self, name: &str) -> Option<&[String]> {
+        self.sections
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, entries)| entries.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn crash_report_round_trip() {
+        let mut buffer = Vec::with_capacity(1024);
+        let mut out = ReportWriter::new(&mut buffer);
+        writeln!(out, "{}", HEADER).unwrap();
+        writeln!(out, "message: {}", Escaped("two\nlines \\ and: a colon")).unwrap();
+        writeln!(out, "[events]").unwrap();
+        writeln!(out, "{}", Escaped("traceback:\n\tinit.lua:3")).unwrap();
+        writeln!(out, "{}", Escaped(42)).unwrap();
+        writeln!(out, "[empty]").unwrap();
+        writeln!(out, "{}", END).unwrap();
+        assert!(!out.is_truncated());
+        let report = Report::parse(std::str::from_utf8(&buffer).unwrap()).unwrap();
+        assert_eq!(report.field("message"), Some("two\nlines \\ and: a colon"));
+        assert_eq!(
+            report.section("events"),
+            Some(&["traceback:\n\tinit.lua:3".to_string(), "42".to_string()][..])
+        );
+        assert_eq!(report.section("empty"), Some(&[][..]));
+        assert!(!report.truncated);
+        assert!(Report::parse("something else\n").is_err());
+    }
+
+    #[test]
+    fn crash_report_capacity() {
+        let mut buffer = Vec::with_capacity(40);
+        let pointer = buffer.as_ptr();
+        let mut out = ReportWriter::new(&mut buffer);
+        writeln!(out, "{}", HEADER).unwrap();
+        assert!(write!(out, "message: {}", "é".repeat(20)).is_err());
+        assert!(out.is_truncated());
+        // Cut at a character, in the buffer it was given.
+        assert_eq!(out.len(), 39);
+        assert_eq!(out.written().as_ptr(), pointer);
+        let report = Report::parse(std::str::from_utf8(&buffer).unwrap()).unwrap();
+        assert_eq!(report.field("message"), Some("éé"));
+        assert!(report.truncated);
+        // The last line may have been cut anywhere.
+        let report = Report::parse(&format!("{}\npid: 1\n[events]\nfirst\nsecond cut", HEADER)).unwrap();
+        assert_eq!(report.section("events").unwrap().len(), 2);
+        assert!(Report::parse(&format!("{}\nno colon\npid: 1", HEADER)).is_err());
+    }
+}
@@ -1,229 +1,621 @@
-//! Sets up the dbus interface for Awesome.
+//! The `dbus` Lua module, compatible with awesome's, which configs use to
+//! listen to media players, UPower and NetworkManager.
+//!
+//! A bus is connected to the first time the config uses it. Signals are
+//! given to the function connected to their interface with
+//! `dbus.connect_signal`, as `(data, args...)` where `data` says what the
+//! message is and where it came from, but only when one of the match rules
+//! the config added matches them. Lua isn't woken for the others. Method
+//! calls to the names the config owns are answered with what the function
+//! returns, pairs of a type and a value. See `marshal` for how values are
+//! converted.
+//!
+//! `dbus.call_method` calls a method, either blocking until the reply or
+//! with a callback that's given it.
 
-use std::{cell::RefCell, os::unix::io::RawFd, slice, thread::LocalKey};
+mod marshal;
+mod rules;
+mod signature;
+#[cfg(test)]
+pub mod test_bus;
 
-use dbus::{BusType, Connection, Message, MessageItem, MessageType};
-use rlua::{self, Error::RuntimeError, MultiValue, Table, ToLua, ToLuaMulti, Value};
+use std::{
+    cell::RefCell,
+    collections::{HashSet, VecDeque},
+    fmt,
+    rc::Rc,
+    thread::LocalKey
+};
 
+use dbus::{
+    arg::{ArgType, IterAppend},
+    BusType, Connection, Message, MessageType, Path, RequestNameReply
+};
+use glib::Continue;
+use rlua::{self, Error::RuntimeError, Function, MultiValue, Table, ToLuaMulti, Value};
+
+use self::marshal::{Arg, MarshalError};
+use self::rules::{Headers, MatchRule};
 use crate::common::signal;
 use crate::lua::LUA;
 
-/// A connection to a D-Bus we store globally that, when destroyed, will destroy
-/// the other connection as well.
-type GlobalConnection = RefCell<Option<DBusConnection>>;
+const SIGNALS_NAME: &str = "signals";
+
+/// Handle to the table of the callbacks of method calls waiting for their
+/// reply, keyed by the bus and the serial of the call.
+const CALLS_HANDLE: &str = "__dbus_calls";
 
-const SIGNALS_NAME: &'static str = "signals";
+/// How long a method call waits for its reply, as long as libdbus waits.
+const CALL_TIMEOUT_MS: u32 = 25_000;
+
+/// The interface of the messages libdbus sends about the connection
+/// itself.
+const LOCAL_INTERFACE: &str = "org.freedesktop.DBus.Local";
 
 thread_local! {
-    static SESSION_BUS: GlobalConnection = RefCell::new(None);
-    static SYSTEM_BUS:  GlobalConnection = RefCell::new(None);
+    static SESSION_BUS: RefCell<Option<BusConnection>> = RefCell::new(None);
+    static SYSTEM_BUS: RefCell<Option<BusConnection>> = RefCell::new(None);
+    #[cfg(feature = "accessibility")]
+    static ACCESSIBILITY_BUS: RefCell<Option<BusConnection>> = RefCell::new(None);
+    /// The interfaces a function is connected to, so signals on others
+    /// don't wake Lua.
+    static INTERFACES: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
 }
 
-/// Called from `wayland_glib_interface.c` whenever a request is sent to the
-/// current session dbus file descriptor.
-///
-/// This will kick off the special handling code in `dbus.rs`.
+/// Called from `wayland_glib_interface.c` whenever a bus has something to
+/// read, with its index.
 #[no_mangle]
-pub extern "C" fn dbus_session_refresh(_: libc::c_void) -> bool {
-    SESSION_BUS.with(|session_bus| {
-        let session_bus = session_bus.borrow();
-        let session_bus = session_bus.as_ref().unwrap();
-        session_bus.replace_message_callback(Some(Box::new(handle_msg_session)));
-        let _ = session_bus.incoming(0).collect::<Vec<_>>();
-    });
+pub extern "C" fn dbus_refresh(bus: *mut libc::c_void) -> bool {
+    if let Some(bus) = Bus::from_index(bus as libc::c_int) {
+        dispatch_from_loop(bus);
+    }
     true
 }
 
-/// Called from `wayland_glib_interface.c` whenever a request is sent to the
-/// system dbus file descriptor.
-///
-/// This will kick off the special handling code in `dbus.rs`.
-#[no_mangle]
-pub extern "C" fn dbus_system_refresh(_: libc::c_void) -> bool {
-    SYSTEM_BUS.with(|system_bus| {
-        let system_bus = system_bus.borrow();
-        let system_bus = system_bus.as_ref().unwrap();
-        system_bus.replace_message_callback(Some(Box::new(handle_msg_system)));
-        let _ = system_bus.incoming(0).collect::<Vec<_>>();
-    });
-    true
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Bus {
+    Session,
+    System,
+    /// The bus screen readers listen on, only used by `accessibility`.
+    #[cfg(feature = "accessibility")]
+    Accessibility
 }
 
-struct DBusConnection {
-    connection: Connection
+impl Bus {
+    #[cfg(not(feature = "accessibility"))]
+    const ALL: &'static [Bus] = &[Bus::Session, Bus::System];
+    #[cfg(feature = "accessibility")]
+    const ALL: &'static [Bus] = &[Bus::Session, Bus::System, Bus::Accessibility];
+
+    fn from_name(name: &str) -> rlua::Result<Self> {
+        match name {
+            "session" => Ok(Bus::Session),
+            "system" => Ok(Bus::System),
+            name => Err(RuntimeError(format!(
+                "unknown bus {:?}, expected \"session\" or \"system\"",
+                name
+            )))
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Bus::Session => "session",
+            Bus::System => "system",
+            #[cfg(feature = "accessibility")]
+            Bus::Accessibility => "accessibility"
+        }
+    }
+
+    /// How the bus is known to `wayland_glib_interface.c`.
+    fn index(self) -> libc::c_int {
+        match self {
+            Bus::Session => 0,
+            Bus::System => 1,
+            #[cfg(feature = "accessibility")]
+            Bus::Accessibility => 2
+        }
+    }
+
+    fn from_index(index: libc::c_int) -> Option<Self> {
+        Bus::ALL.iter().cloned().find(|bus| bus.index() == index)
+    }
+
+    fn key(self) -> &'static LocalKey<RefCell<Option<BusConnection>>> {
+        match self {
+            Bus::Session => &SESSION_BUS,
+            Bus::System => &SYSTEM_BUS,
+            #[cfg(feature = "accessibility")]
+            Bus::Accessibility => &ACCESSIBILITY_BUS
+        }
+    }
+
+    fn with<R, F>(self, func: F) -> R
+    where
+        F: FnOnce(&mut Option<BusConnection>) -> R
+    {
+        self.key().with(|connection| func(&mut connection.borrow_mut()))
+    }
 }
 
-impl std::ops::Deref for DBusConnection {
-    type Target = Connection;
+/// A connection to a bus, and what the config asked of it.
+struct BusConnection {
+    bus: Bus,
+    connection: Connection,
+    /// What was received, handled once libdbus is done reading.
+    queue: Rc<RefCell<VecDeque<Message>>>,
+    /// The match rules the config added, as it gave them.
+    rules: Vec<(String, MatchRule)>,
+    /// The serials of the method calls whose callback waits for the reply.
+    calls: HashSet<u32>,
+    delivered: u64,
+    ignored: u64
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.connection
+impl BusConnection {
+    fn open(bus: Bus) -> Result<Self, dbus::Error> {
+        let connection = match bus {
+            Bus::Session => Connection::get_private(BusType::Session)?,
+            Bus::System => Connection::get_private(BusType::System)?,
+            #[cfg(feature = "accessibility")]
+            Bus::Accessibility => {
+                let connection = Connection::open_private(&crate::accessibility::atspi::bus_address()?)?;
+                connection.register()?;
+                connection
+            }
+        };
+        Ok(BusConnection::new(bus, connection))
+    }
+
+    fn new(bus: Bus, connection: Connection) -> Self {
+        let queue = Rc::new(RefCell::new(VecDeque::new()));
+        let received = queue.clone();
+        connection.replace_message_callback(Some(Box::new(move |_, message| {
+            // libdbus answers pings on its own.
+            if message.msg_type() == MessageType::MethodCall &&
+                message
+                    .interface()
+                    .map_or(false, |interface| &*interface == "org.freedesktop.DBus.Peer")
+            {
+                return false;
+            }
+            received.borrow_mut().push_back(message);
+            true
+        })));
+        if let Some(watch) = connection.watch_fds().into_iter().find(|watch| watch.readable()) {
+            unsafe { crate::add_dbus_to_glib(watch.fd(), bus.index()) }
+        }
+        info!(
+            "Connected to the {} bus as {}",
+            bus.name(),
+            connection.unique_name()
+        );
+        BusConnection {
+            bus,
+            connection,
+            queue,
+            rules: Vec::new(),
+            calls: HashSet::new(),
+            delivered: 0,
+            ignored: 0
+        }
+    }
+
+    /// Whether a signal is one the config listens to.
+    fn wants(&self, message: &Message) -> bool {
+        let destination = message.destination();
+        if destination.as_ref().map(|name| &**name) == Some(self.connection.unique_name().as_str()) {
+            return true;
+        }
+        let (_, path, interface, member) = message.headers();
+        let sender = message.sender();
+        let headers = Headers {
+            message_type: "signal",
+            sender: sender.as_ref().map(|name| &**name),
+            interface: interface.as_ref().map(String::as_str),
+            member: member.as_ref().map(String::as_str),
+            path: path.as_ref().map(String::as_str),
+            destination: destination.as_ref().map(|name| &**name)
+        };
+        let args = string_args(message);
+        self.rules.iter().any(|(_, rule)| {
+            rule.matches(&headers, |index| {
+                args.get(index).and_then(|arg| arg.as_ref().map(String::as_str))
+            })
+        })
     }
 }
 
-impl Drop for DBusConnection {
+impl Drop for BusConnection {
     fn drop(&mut self) {
-        unsafe {
-            crate::remove_dbus_from_glib();
+        unsafe { crate::remove_dbus_from_glib(self.bus.index()) }
+    }
+}
+
+/// The top level arguments of a message that are strings or object paths,
+/// which match rules can match.
+fn string_args(message: &Message) -> Vec<Option<String>> {
+    let mut iter = message.iter_init();
+    let mut args = Vec::new();
+    loop {
+        match iter.arg_type() {
+            ArgType::Invalid => break,
+            ArgType::String => args.push(iter.get::<String>()),
+            ArgType::ObjectPath => args.push(iter.get::<Path>().map(|path| path.to_string())),
+            _ => args.push(None)
         }
-        // Need to close both of them, no idea which one was just destroyed,
-        // so try to destroy both of them.
-        SESSION_BUS
-            .try_with(|session_bus| {
-                if let Ok(mut bus) = session_bus.try_borrow_mut() {
-                    *bus = None
-                }
-            })
-            .ok();
-        SYSTEM_BUS
-            .try_with(|system_bus| {
-                if let Ok(mut bus) = system_bus.try_borrow_mut() {
-                    *bus = None
-                }
-            })
-            .ok();
+        iter.next();
+    }
+    args
+}
+
+/// Does `func` with the connection to `bus`, connecting to it first if
+/// there is none.
+fn connected<R, F>(bus: Bus, func: F) -> Result<R, dbus::Error>
+where
+    F: FnOnce(&mut BusConnection) -> R
+{
+    bus.with(|connection| {
+        if connection.is_none() {
+            *connection = Some(BusConnection::open(bus)?);
+        }
+        Ok(func(connection.as_mut().unwrap()))
+    })
+}
+
+/// Does `func` with the connection to `bus`, connecting to it first if
+/// there is none, for the modules using a bus on their own.
+#[cfg_attr(not(feature = "accessibility"), allow(dead_code))]
+pub fn with_connection<R, F>(bus: Bus, func: F) -> Result<R, dbus::Error>
+where
+    F: FnOnce(&Connection) -> R
+{
+    connected(bus, |connection| func(&connection.connection))
+}
+
+/// Drops the connection to `bus`, if there is one.
+#[cfg_attr(not(feature = "accessibility"), allow(dead_code))]
+pub fn disconnect(bus: Bus) {
+    bus.with(|connection| *connection = None);
+}
+
+/// Why something couldn't be done on a bus, given to Lua as a table like
+/// `{ kind = "dbus", name = "org.freedesktop.DBus.Error.ServiceUnknown",
+/// message = "..." }`.
+enum CallError {
+    /// The bus or the other end said no.
+    Bus {
+        name: String,
+        message: String
+    },
+    Marshal(MarshalError),
+    /// No reply came in time.
+    Timeout
+}
+
+impl CallError {
+    fn to_lua<'lua>(&self, lua: rlua::Context<'lua>) -> rlua::Result<Table<'lua>> {
+        let table = lua.create_table()?;
+        let kind = match self {
+            CallError::Bus { name, .. } => {
+                table.set("name", name.as_str())?;
+                "dbus"
+            },
+            CallError::Marshal(err) => err.kind(),
+            CallError::Timeout => "timeout"
+        };
+        table.set("kind", kind)?;
+        table.set("message", self.to_string())?;
+        Ok(table)
+    }
+
+    /// `nil, err`, how errors are returned to Lua.
+    fn to_lua_multi<'lua>(&self, lua: rlua::Context<'lua>) -> rlua::Result<MultiValue<'lua>> {
+        (Value::Nil, self.to_lua(lua)?).to_lua_multi(lua)
     }
 }
 
-fn handle_msg_session(bus: &Connection, msg: Message) -> bool {
-    handle_msg(bus, BusType::Session, msg)
+impl From<dbus::Error> for CallError {
+    fn from(err: dbus::Error) -> Self {
+        CallError::Bus {
+            name: err.name().unwrap_or("org.freedesktop.DBus.Error.Failed").into(),
+            message: err.message().unwrap_or("").into()
+        }
+    }
 }
 
-fn handle_msg_system(bus: &Connection, msg: Message) -> bool {
-    handle_msg(bus, BusType::System, msg)
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CallError::Bus { message, .. } => write!(f, "{}", message),
+            CallError::Marshal(err) => write!(f, "{}", err),
+            CallError::Timeout => write!(f, "no reply came in {} seconds", CALL_TIMEOUT_MS / 1000)
+        }
+    }
 }
 
-fn handle_msg(bus: &Connection, bus_type: BusType, msg: Message) -> bool {
-    if msg.msg_type() == MessageType::Signal {
-        let (_, _, interface, member) = msg.headers();
-        match (interface.unwrap().as_str(), member.unwrap().as_str()) {
-            // TODO Drop them
-            ("org.freedesktop.DBus.Local", "Disconnected") => return false,
-            _ => {}
+/// Handles what arrived on `bus` from the main loop.
+fn dispatch_from_loop(bus: Bus) {
+    #[cfg(feature = "accessibility")]
+    {
+        if bus == Bus::Accessibility {
+            return crate::accessibility::atspi::dispatch(0);
         }
     }
-    let reply = LUA
-        .with(|lua| {
-            let lua = lua.borrow();
-            lua.context(|lua_ctx| process_request(bus_type, lua_ctx, &msg))
-        })
-        .unwrap_or_else(|err| {
-            crate::lua::log_error(err);
-            None
-        });
-    if let Some(reply) = reply {
-        bus.send(reply).expect("Could not send D-Bus reply");
+    LUA.with(|lua| {
+        let lua = lua.borrow();
+        lua.context(|lua| dispatch(lua, bus, 0))
+    })
+    .unwrap_or_else(crate::lua::log_error);
+}
+
+/// Reads what arrived on `bus`, waiting up to `timeout_ms` for something to
+/// arrive, and handles it.
+fn dispatch(lua: rlua::Context, bus: Bus, timeout_ms: u32) -> rlua::Result<()> {
+    for message in receive(bus, timeout_ms) {
+        let handled = match message.msg_type() {
+            MessageType::Signal => handle_signal(lua, bus, &message),
+            MessageType::MethodCall => handle_method_call(lua, bus, &message),
+            MessageType::MethodReturn | MessageType::Error => handle_reply(lua, bus, message),
+            MessageType::Invalid => Ok(())
+        };
+        handled.unwrap_or_else(crate::lua::log_error);
     }
-    true
+    Ok(())
 }
 
-/// Gives the D-Bus message to Lua for processing the reply.
-fn process_request(bus_type: BusType, lua: rlua::Context, msg: &Message) -> rlua::Result<Option<Message>> {
-    let message_metadata = lua.create_table()?;
-    let msg_type = match msg.msg_type() {
+/// Reads what arrived on `bus`, waiting up to `timeout_ms` for something to
+/// arrive.
+pub fn receive(bus: Bus, timeout_ms: u32) -> Vec<Message> {
+    bus.with(|connection| match connection {
+        Some(connection) => {
+            connection.connection.incoming(timeout_ms).for_each(drop);
+            connection.queue.borrow_mut().drain(..).collect()
+        },
+        None => Vec::new()
+    })
+}
+
+/// Handles what arrived while a method call blocked, once the main loop
+/// is back.
+fn dispatch_later(bus: Bus) {
+    glib::idle_add(move || {
+        dispatch_from_loop(bus);
+        Continue(false)
+    });
+}
+
+/// What a message is and where it came from, for the functions given it.
+fn message_data<'lua>(lua: rlua::Context<'lua>, bus: Bus, message: &Message) -> rlua::Result<Table<'lua>> {
+    let data = lua.create_table()?;
+    let message_type = match message.msg_type() {
         MessageType::Signal => "signal",
         MessageType::MethodCall => "method_call",
         MessageType::MethodReturn => "method_return",
         MessageType::Error => "error",
         MessageType::Invalid => "unknown"
     };
-    message_metadata.set("type", msg_type)?;
-    let (_type, path, interface, member) = msg.headers();
-    let (path, interface, member) = (
-        path.unwrap_or_else(|| "".into()),
-        interface.unwrap_or_else(|| "".into()),
-        member.unwrap_or_else(|| "".into())
-    );
-    message_metadata.set("interface", interface.clone())?;
-    message_metadata.set("path", path)?;
-    message_metadata.set("member", member)?;
-    if let Some(sender) = msg.sender() {
-        message_metadata.set("sender", sender.to_string())?;
-    }
-    let bus_type_str = match bus_type {
-        BusType::Session => "session",
-        BusType::System => "system",
-        _ => unimplemented!()
+    data.set("type", message_type)?;
+    let (_, path, interface, member) = message.headers();
+    data.set("interface", interface.unwrap_or_default())?;
+    data.set("path", path.unwrap_or_default())?;
+    data.set("member", member.unwrap_or_default())?;
+    if let Some(sender) = message.sender() {
+        data.set("sender", sender.to_string())?;
+    }
+    data.set("bus", bus.name())?;
+    Ok(data)
+}
+
+/// `(data, args...)`, what functions connected to an interface are given.
+fn handler_args<'lua>(
+    lua: rlua::Context<'lua>,
+    bus: Bus,
+    message: &Message,
+    args: &[Arg]
+) -> rlua::Result<MultiValue<'lua>> {
+    let mut values = Vec::with_capacity(args.len() + 1);
+    values.push(Value::Table(message_data(lua, bus, message)?));
+    for arg in args {
+        values.push(arg.to_lua(lua)?);
+    }
+    Ok(MultiValue::from_vec(values))
+}
+
+fn signals_table(lua: rlua::Context) -> rlua::Result<Table> {
+    lua.globals().get::<_, Table>("dbus")?.get(SIGNALS_NAME)
+}
+
+fn handle_signal(lua: rlua::Context, bus: Bus, message: &Message) -> rlua::Result<()> {
+    let (_, _, interface, member) = message.headers();
+    let (interface, member) = (interface.unwrap_or_default(), member.unwrap_or_default());
+    if interface == LOCAL_INTERFACE && member == "Disconnected" {
+        warn!("Disconnected from the {} bus", bus.name());
+        bus.with(|connection| *connection = None);
+        return Ok(());
+    }
+    let connected = INTERFACES.with(|interfaces| interfaces.borrow().contains(&interface));
+    let wanted = bus.with(|connection| match connection {
+        Some(connection) => {
+            let wanted = connected && connection.wants(message);
+            if wanted {
+                connection.delivered += 1;
+            } else {
+                connection.ignored += 1;
+            }
+            wanted
+        },
+        None => false
+    });
+    if !wanted {
+        return Ok(());
+    }
+    let args = match marshal::read_message(message) {
+        Ok(args) => args,
+        Err(err) => {
+            warn!("Dropped the D-Bus signal {}.{}: {}", interface, member, err);
+            return Ok(());
+        }
+    };
+    let args = handler_args(lua, bus, message, &args)?;
+    signal::emit_signals(lua, signals_table(lua)?, &interface, args)
+}
+
+/// Answers a method call to a name the config owns with what the function
+/// connected to its interface returns.
+fn handle_method_call(lua: rlua::Context, bus: Bus, message: &Message) -> rlua::Result<()> {
+    let interface = message.headers().2.unwrap_or_default();
+    let args = match marshal::read_message(message) {
+        Ok(args) => args,
+        Err(err) => {
+            return reply_error(
+                bus,
+                message,
+                "org.freedesktop.DBus.Error.InvalidArgs",
+                &err.to_string()
+            )
+        },
+    };
+    let signals = signals_table(lua)?;
+    if message.get_no_reply() {
+        let args = handler_args(lua, bus, message, &args)?;
+        return signal::emit_signals(lua, signals, &interface, args);
+    }
+    // Only one function can answer.
+    let handler = match signal::handlers(signals, &interface)?.into_iter().next() {
+        Some(handler) => handler,
+        None => {
+            let error = format!("nothing answers method calls to {}", interface);
+            return reply_error(bus, message, "org.freedesktop.DBus.Error.UnknownMethod", &error);
+        }
     };
-    message_metadata.set("bus", bus_type_str)?;
-    let lua_message = dbus_to_lua_value(lua, msg.get_items().as_slice())?;
-    let dbus_table = lua
-        .globals()
-        .get::<_, Table>("dbus")
-        .expect("Could not get dbus table");
-    let signals = dbus_table.get(SIGNALS_NAME).expect("Could not get signals table");
-    if msg.get_no_reply() {
-        signal::emit_signals(lua, signals, &interface, lua_message)?;
-        return Ok(None);
-    }
-    if let Ok(Value::Table(sig)) = signals.get(interface) {
-        // There can only be ONE handler to send reply
-        let func: rlua::Function = sig.get(1)?;
-        let res: MultiValue = func.call((message_metadata, lua_message))?;
-        if res.len() % 2 != 0 {
+    let results = match handler.call::<_, MultiValue>(handler_args(lua, bus, message, &args)?) {
+        Ok(results) => results,
+        Err(err) => {
+            reply_error(
+                bus,
+                message,
+                "org.freedesktop.DBus.Error.Failed",
+                &err.to_string()
+            )?;
+            return Err(err);
+        }
+    };
+    match marshal::from_pairs(results) {
+        Ok(results) => {
+            let mut reply = message.method_return();
+            append(&mut reply, &results);
+            send(bus, reply)
+        },
+        Err(err) => {
             warn!(
-                "Your D-Bus signal handling method returned \
-                 wrong number of arguments"
+                "The answer to a D-Bus method call on {} is invalid: {}",
+                interface, err
             );
-            return Ok(None);
-        }
-        let types = res.iter().step_by(2);
-        let values = res.iter().skip(1).step_by(2);
-        let mut reply = msg.method_return();
-        for (type_, value) in types.zip(values) {
-            match lua_value_to_dbus(lua, type_.clone(), value.clone()) {
-                Ok(v) => {
-                    reply = reply.append(v);
-                },
-                Err(err) => {
-                    warn!(
-                        "Your D-Bus signal handling method returned \
-                         bad data"
-                    );
-                    crate::lua::log_error(err);
-                    return Ok(None);
-                }
-            }
+            reply_error(
+                bus,
+                message,
+                "org.freedesktop.DBus.Error.Failed",
+                &err.to_string()
+            )
         }
-        Ok(Some(reply))
-    } else {
-        Ok(None)
     }
 }
 
-/// Set up the connections to the session bus and system bus.
-///
-/// The message handling is all done within the `dbus` module,
-/// it's up to the caller of this function to register the DBus
-/// file descriptors with glib so that we can awaken and deal
-/// with dbus events when necessary.
-pub fn connect() -> Result<(RawFd, RawFd), dbus::Error> {
-    let session_con = Connection::get_private(BusType::Session)?;
-    let system_con = Connection::get_private(BusType::System)?;
-    let session_fds = session_con.watch_fds();
-    let system_fds = system_con.watch_fds();
-    assert_eq!(session_fds.len(), 1, "Only one fd per dbus connection");
-    assert_eq!(system_fds.len(), 1, "Only one fd per dbus connection");
-
-    SESSION_BUS.with(|session_bus| {
-        let mut session_bus = session_bus.borrow_mut();
-        *session_bus = Some(DBusConnection {
-            connection: session_con
-        });
-    });
-    SYSTEM_BUS.with(|system_bus| {
-        let mut system_bus = system_bus.borrow_mut();
-        *system_bus = Some(DBusConnection {
-            connection: system_con
-        });
+fn reply_error(bus: Bus, message: &Message, name: &str, error: &str) -> rlua::Result<()> {
+    match Message::new_error(message, name, error) {
+        Some(reply) => send(bus, reply),
+        None => Ok(())
+    }
+}
+
+fn send(bus: Bus, message: Message) -> rlua::Result<()> {
+    connected(bus, |connection| connection.connection.send(message))
+        .map_err(|err| RuntimeError(CallError::from(err).to_string()))?
+        .map(|_| ())
+        .map_err(|_| RuntimeError(format!("could not send a message on the {} bus", bus.name())))
+}
+
+fn append(message: &mut Message, args: &[Arg]) {
+    let mut iter = IterAppend::new(message);
+    for arg in args {
+        arg.append(&mut iter);
+    }
+}
+
+/// What a method call returned, or `nil, err`.
+fn reply_values<'lua>(lua: rlua::Context<'lua>, mut reply: Message) -> rlua::Result<MultiValue<'lua>> {
+    if let Err(err) = reply.as_result() {
+        return CallError::from(err).to_lua_multi(lua);
+    }
+    match marshal::read_message(&reply) {
+        Ok(args) => args
+            .iter()
+            .map(|arg| arg.to_lua(lua))
+            .collect::<rlua::Result<Vec<_>>>()
+            .map(MultiValue::from_vec),
+        Err(err) => CallError::Marshal(err).to_lua_multi(lua)
+    }
+}
+
+/// Gives the reply to a method call to its callback.
+fn handle_reply(lua: rlua::Context, bus: Bus, reply: Message) -> rlua::Result<()> {
+    let serial = match reply.get_reply_serial() {
+        Some(serial) => serial,
+        None => return Ok(())
+    };
+    let waiting = bus.with(|connection| {
+        connection
+            .as_mut()
+            .map_or(false, |connection| connection.calls.remove(&serial))
     });
+    if !waiting {
+        return Ok(());
+    }
+    let callback = take_callback(lua, bus, serial)?;
+    if let Some(callback) = callback {
+        callback.call::<_, ()>(reply_values(lua, reply)?)?;
+    }
+    Ok(())
+}
+
+fn call_key(bus: Bus, serial: u32) -> String {
+    format!("{}:{}", bus.name(), serial)
+}
 
-    Ok((session_fds[0].fd(), system_fds[0].fd()))
+fn take_callback(lua: rlua::Context, bus: Bus, serial: u32) -> rlua::Result<Option<Function>> {
+    let calls = lua.named_registry_value::<str, Table>(CALLS_HANDLE)?;
+    let key = call_key(bus, serial);
+    let callback = calls.get::<_, Option<Function>>(key.as_str())?;
+    calls.set(key, Value::Nil)?;
+    Ok(callback)
 }
 
-/// Set up the DBus object in Lua so that the user libs can interact with
-/// Awesome via DBus.
+/// Gives up on the reply to a method call.
+fn expire_call(bus: Bus, serial: u32) {
+    let waiting = bus.with(|connection| {
+        connection
+            .as_mut()
+            .map_or(false, |connection| connection.calls.remove(&serial))
+    });
+    if !waiting {
+        return;
+    }
+    LUA.with(|lua| {
+        let lua = lua.borrow();
+        lua.context(|lua| {
+            if let Some(callback) = take_callback(lua, bus, serial)? {
+                callback.call::<_, ()>(CallError::Timeout.to_lua_multi(lua)?)?;
+            }
+            Ok(())
+        })
+    })
+    .unwrap_or_else(crate::lua::log_error);
+}
+
+/// Set up the `dbus` table in Lua, awesome's module for D-Bus.
 pub fn lua_init(lua: rlua::Context) -> rlua::Result<()> {
+    lua.set_named_registry_value(CALLS_HANDLE, lua.create_table()?)?;
     let dbus_table = lua.create_table()?;
     dbus_table.set(SIGNALS_NAME, lua.create_table()?)?;
     dbus_table.set("request_name", lua.create_function(request_name)?)?;
@@ -233,240 +625,206 @@ pub fn lua_init(lua: rlua::Context) -> rlua::Result<()> {
     dbus_table.set("connect_signal", lua.create_function(connect_signal)?)?;
     dbus_table.set("disconnect_signal", lua.create_function(disconnect_signal)?)?;
     dbus_table.set("emit_signal", lua.create_function(emit_signal)?)?;
+    dbus_table.set("call_method", lua.create_function(call_method)?)?;
     dbus_table.set("__index", lua.create_function(index)?)?;
     dbus_table.set("__newindex", lua.create_function(newindex)?)?;
     lua.globals().set("dbus", dbus_table)?;
     Ok(())
 }
 
-fn get_bus_by_name<'bus>(bus_name: &str) -> rlua::Result<&'bus LocalKey<GlobalConnection>> {
-    match bus_name {
-        "session" => Ok(&SESSION_BUS),
-        "system" => Ok(&SYSTEM_BUS),
-        v => Err(RuntimeError(format!("Unknown bus type {}", v)))
+/// `dbus.request_name(bus, name)`, whether the config now owns the name, or
+/// `nil, err`.
+fn request_name<'lua>(
+    lua: rlua::Context<'lua>,
+    (bus, name): (String, String)
+) -> rlua::Result<MultiValue<'lua>> {
+    let bus = Bus::from_name(&bus)?;
+    match connected(bus, |connection| connection.connection.register_name(&name, 0)) {
+        Ok(Ok(reply)) => {
+            let owner = reply == RequestNameReply::PrimaryOwner || reply == RequestNameReply::AlreadyOwner;
+            owner.to_lua_multi(lua)
+        },
+        Ok(Err(err)) | Err(err) => CallError::from(err).to_lua_multi(lua)
     }
 }
 
-fn dbus_to_lua_value<'lua>(
+/// `dbus.release_name(bus, name)`, whether the config owned the name, or
+/// `nil, err`.
+fn release_name<'lua>(
     lua: rlua::Context<'lua>,
-    msg_items: &[MessageItem]
+    (bus, name): (String, String)
 ) -> rlua::Result<MultiValue<'lua>> {
-    let mut res = Vec::new();
-    for msg_item in msg_items {
-        use dbus::MessageItem::*;
-        match msg_item {
-            Variant(sub_msg_item) => res.extend(dbus_to_lua_value(lua, slice::from_ref(sub_msg_item))?),
-            DictEntry(key, value) => {
-                res.extend(dbus_to_lua_value(lua, slice::from_ref(key))?);
-                res.extend(dbus_to_lua_value(lua, slice::from_ref(value))?);
-            },
-            Struct(fields) => {
-                let struct_table = lua.create_table()?;
-                let fields = dbus_to_lua_value(lua, fields.as_slice())?;
-                for (index, field) in fields.into_iter().enumerate() {
-                    struct_table.set(index + 1, field)?;
-                }
-                res.push(struct_table.to_lua(lua)?);
-            },
-            Array(array) => {
-                let array_table = lua.create_table()?;
-                match array.get(0) {
-                    None => {},
-                    Some(DictEntry(..)) => {
-                        let sub_res = dbus_to_lua_value(lua, array)?;
-                        assert!(sub_res.len() % 2 == 0);
-                        let keys = sub_res.iter().step_by(2);
-                        let values = sub_res.iter().skip(1).step_by(2);
-                        for (key, value) in keys.zip(values) {
-                            array_table.set(key.clone(), value.clone())?;
-                        }
-                    },
-                    Some(_) => {
-                        let sub_res = dbus_to_lua_value(lua, array)?;
-                        for (index, value) in sub_res.into_iter().enumerate() {
-                            array_table.set(index + 1, value)?
-                        }
-                    }
-                }
-                res.push(array_table.to_lua(lua)?);
-            },
-            Bool(v) => res.push(v.to_lua(lua)?),
-            Byte(v) => res.push(v.to_lua(lua)?),
-            Int16(v) => res.push(v.to_lua(lua)?),
-            UInt16(v) => res.push(v.to_lua(lua)?),
-            Int32(v) => res.push(v.to_lua(lua)?),
-            UInt32(v) => res.push(v.to_lua(lua)?),
-            Int64(v) => res.push(v.to_lua(lua)?),
-            UInt64(v) => res.push(v.to_lua(lua)?),
-            Double(v) => res.push(v.to_lua(lua)?),
-            Str(v) => res.push(v.clone().to_lua(lua)?),
-            _ => res.push(Value::Nil)
-        }
+    let bus = Bus::from_name(&bus)?;
+    match connected(bus, |connection| connection.connection.release_name(&name)) {
+        Ok(Ok(reply)) => (reply == dbus::ReleaseNameReply::Released).to_lua_multi(lua),
+        Ok(Err(err)) | Err(err) => CallError::from(err).to_lua_multi(lua)
     }
-    Ok(MultiValue::from_vec(res))
-}
-
-/// Converts an `rlua::Value` into a `dbus::MessageItem`.
-fn lua_value_to_dbus(lua: rlua::Context, type_: Value, value: Value) -> rlua::Result<MessageItem> {
-    use dbus::arg::ArgType;
-    let type_ = match type_ {
-        Value::String(s) => s.to_str()?.to_string(),
-        _ => return Err(RuntimeError("D-Bus type name was not a string".into()))
-    };
-    let is_ascii = type_.chars().next().map(|c| char::is_ascii(&c)).unwrap_or(false);
-    if type_.len() > 1 || !is_ascii {
-        return Err(RuntimeError(format!("{} is an invalid type name", type_)));
-    }
-    let type_ = ArgType::from_i32(type_.chars().next().unwrap() as i32)
-        .map_err(|_| RuntimeError(format!("{} is an invalid type name", type_)))?;
-    match (type_, value) {
-        (ArgType::Array, Value::Table(value)) => {
-            let size = value.len()?;
-            if size % 2 != 0 {
-                return Err(RuntimeError(
-                    "your D-Bus signal handling method returned \
-                     wrong number of arguments"
-                        .into()
-                ));
-            }
-            let types = value.clone().sequence_values().step_by(2);
-            let values = value.clone().sequence_values().skip(1).step_by(2);
-            let mut list = Vec::with_capacity(size as usize);
-            for (type_, value) in types.zip(values) {
-                list.push(lua_value_to_dbus(lua, type_?, value?)?)
-            }
-            MessageItem::new_array(list).map_err(|_| RuntimeError("Empty list is invalid".into()))
-        },
-        (ArgType::Boolean, Value::Boolean(value)) => Ok(value.into()),
-        (ArgType::String, Value::String(value)) => Ok(value.to_str()?.into()),
-        (ArgType::Byte, Value::Integer(value)) |
-        (ArgType::Int16, Value::Integer(value)) |
-        (ArgType::UInt16, Value::Integer(value)) |
-        (ArgType::Int32, Value::Integer(value)) |
-        (ArgType::UInt32, Value::Integer(value)) |
-        (ArgType::Int64, Value::Integer(value)) |
-        (ArgType::UInt64, Value::Integer(value)) |
-        (ArgType::Double, Value::Integer(value)) => Ok(value.into()),
-        (ArgType::Byte, Value::Number(value)) |
-        (ArgType::Int16, Value::Number(value)) |
-        (ArgType::UInt16, Value::Number(value)) |
-        (ArgType::Int32, Value::Number(value)) |
-        (ArgType::UInt32, Value::Number(value)) |
-        (ArgType::Int64, Value::Number(value)) |
-        (ArgType::UInt64, Value::Number(value)) |
-        (ArgType::Double, Value::Number(value)) => Ok(value.into()),
-        (type_, value) => Err(RuntimeError(format!(
-            "Invalid type {:?} or value {:?}",
-            type_, value
-        )))
-    }
-}
-
-fn request_name(_: rlua::Context, (bus, name): (String, String)) -> rlua::Result<bool> {
-    let bus = get_bus_by_name(bus.as_str())?;
-    bus.with(|bus| {
-        let bus = bus.borrow();
-        let bus = bus.as_ref().unwrap();
-        bus.register_name(name.as_str(), 0)
-            .expect(&format!("Could not register name {}", name.as_str()));
-    });
-    Ok(true)
-}
-
-fn release_name(_: rlua::Context, (bus, name): (String, String)) -> rlua::Result<bool> {
-    let bus = get_bus_by_name(bus.as_str())?;
-    bus.with(|bus| {
-        let bus = bus.borrow();
-        let bus = bus.as_ref().unwrap();
-        bus.release_name(name.as_str())
-            .expect(&format!("Could not release name {}", name.as_str()));
-    });
-    Ok(true)
 }
 
-fn add_match(_: rlua::Context, (bus, name): (String, String)) -> rlua::Result<()> {
-    let bus = get_bus_by_name(bus.as_str())?;
-    bus.with(|bus| {
-        let bus = bus.borrow();
-        let bus = bus.as_ref().unwrap();
-        bus.add_match(name.as_str())
-            .map_err(|err| RuntimeError(format!("{}", err)))
-    })?;
-    Ok(())
+/// `dbus.add_match(bus, rule)`, which has the bus send the signals the rule
+/// matches.
+fn add_match(_: rlua::Context, (bus, rule): (String, String)) -> rlua::Result<()> {
+    let bus = Bus::from_name(&bus)?;
+    let parsed = MatchRule::parse(&rule)
+        .map_err(|err| RuntimeError(format!("dbus.add_match: invalid rule {:?}, {}", rule, err)))?;
+    connected(bus, |connection| {
+        connection.connection.add_match(&rule)?;
+        connection.rules.push((rule, parsed));
+        Ok(())
+    })
+    .and_then(|added| added)
+    .map_err(|err: dbus::Error| RuntimeError(format!("dbus.add_match: {}", CallError::from(err))))
 }
 
-fn remove_match(_: rlua::Context, (bus, name): (String, String)) -> rlua::Result<()> {
-    let bus = get_bus_by_name(bus.as_str())?;
-    bus.with(|bus| {
-        let bus = bus.borrow();
-        let bus = bus.as_ref().unwrap();
-        bus.remove_match(name.as_str())
-            .map_err(|err| RuntimeError(format!("{}", err)))
-    })?;
-    Ok(())
+/// `dbus.remove_match(bus, rule)`, for a rule added with the same string.
+fn remove_match(_: rlua::Context, (bus, rule): (String, String)) -> rlua::Result<()> {
+    let bus = Bus::from_name(&bus)?;
+    connected(bus, |connection| {
+        connection.connection.remove_match(&rule)?;
+        if let Some(index) = connection.rules.iter().position(|(added, _)| *added == rule) {
+            connection.rules.remove(index);
+        }
+        Ok(())
+    })
+    .and_then(|removed| removed)
+    .map_err(|err: dbus::Error| RuntimeError(format!("dbus.remove_match: {}", CallError::from(err))))
 }
 
+/// `dbus.connect_signal(interface, func)`. Only one function can be
+/// connected to an interface, otherwise this returns `nil, err`.
 fn connect_signal<'lua>(
     lua: rlua::Context<'lua>,
-    (name, func): (String, rlua::Function<'lua>)
+    (interface, func): (String, Function<'lua>)
 ) -> rlua::Result<MultiValue<'lua>> {
-    let signals = lua
-        .globals()
-        .get::<_, Table>("dbus")
-        .unwrap()
-        .get::<_, Table>(SIGNALS_NAME)
-        .unwrap();
-    if signals.get(name.as_str())? {
-        let error_msg = format!("Cannot add signal {} on D-Bus, already existing", name.as_str());
+    let signals = signals_table(lua)?;
+    if !signal::handlers(signals.clone(), &interface)?.is_empty() {
+        let error_msg = format!("Cannot add signal {} on D-Bus, already existing", interface);
         warn!("{}", error_msg);
-        (rlua::Nil, error_msg).to_lua_multi(lua)
-    } else {
-        signal::connect_signals(lua, signals, &name, &[func])?;
-        (true.to_lua_multi(lua))
+        return (Value::Nil, error_msg).to_lua_multi(lua);
     }
+    signal::connect_signals(lua, signals, &interface, &[func])?;
+    INTERFACES.with(|interfaces| interfaces.borrow_mut().insert(interface));
+    true.to_lua_multi(lua)
 }
 
-fn disconnect_signal(lua: rlua::Context, (name, _func): (String, rlua::Function)) -> rlua::Result<()> {
-    let signals: Table = lua
-        .globals()
-        .get::<_, Table>("dbus")
-        .unwrap()
-        .get(SIGNALS_NAME)
-        .unwrap();
-    signal::disconnect_signals(lua, signals, &name)
+fn disconnect_signal(lua: rlua::Context, (interface, _func): (String, Function)) -> rlua::Result<()> {
+    INTERFACES.with(|interfaces| interfaces.borrow_mut().remove(&interface));
+    signal::disconnect_signals(lua, signals_table(lua)?, &interface)
 }
 
+/// `dbus.emit_signal(bus, path, interface, member, type, value, ...)`,
+/// whether the signal was sent, or `false, err`.
 fn emit_signal<'lua>(
     lua: rlua::Context<'lua>,
-    (bus, path, interface, name, args): (String, String, String, String, MultiValue)
-) -> rlua::Result<Value<'lua>> {
-    if args.len() % 2 != 0 {
-        let error_msg = "your D-Bus signal emitting metod has wrong number of arguments";
-        warn!("{}", error_msg);
-        return false.to_lua(lua);
-    }
-    let types = args.iter().step_by(2);
-    let values = args.iter().skip(1).step_by(2);
-    let args = types
-        .zip(values)
-        .map(|v| lua_value_to_dbus(lua, v.0.clone(), v.1.clone()))
-        .collect::<rlua::Result<Vec<MessageItem>>>()?;
-    let bus = get_bus_by_name(bus.as_str())?;
-    bus.with::<_, rlua::Result<_>>(|bus| {
-        let bus = bus.borrow();
-        let bus = bus.as_ref().unwrap();
-        let mut msg = Message::new_signal(path, interface, name).map_err(|_| {
-            RuntimeError(
-                "your D-Bus signal emitting \
-                 method had bad argument type"
-                    .into()
-            )
-        })?;
-        msg.append_items(&args);
-        bus.send(msg)
-            .map_err(|_| RuntimeError("Could not send D-Bus message".into()))
-    })?;
-    true.to_lua(lua)
+    (bus, path, interface, member, args): (String, String, String, String, MultiValue<'lua>)
+) -> rlua::Result<MultiValue<'lua>> {
+    let bus = Bus::from_name(&bus)?;
+    let args = match marshal::from_pairs(args) {
+        Ok(args) => args,
+        Err(err) => {
+            warn!("dbus.emit_signal: {}", err);
+            return (false, err.to_string()).to_lua_multi(lua);
+        }
+    };
+    let mut message = Message::new_signal(path, interface, member).map_err(RuntimeError)?;
+    append(&mut message, &args);
+    send(bus, message)?;
+    true.to_lua_multi(lua)
+}
+
+/// `dbus.call_method(bus, destination, path, interface, method, type,
+/// value, ..., [callback])`.
+///
+/// Without a callback it blocks until the reply and returns what the method
+/// returned, or `nil, err`. With one it returns true and the callback is
+/// given that once the reply is there.
+fn call_method<'lua>(
+    lua: rlua::Context<'lua>,
+    (bus, destination, path, interface, method, args): (
+        String,
+        String,
+        String,
+        String,
+        String,
+        MultiValue<'lua>
+    )
+) -> rlua::Result<MultiValue<'lua>> {
+    let bus = Bus::from_name(&bus)?;
+    let mut args = args.into_vec();
+    let callback = match args.last() {
+        Some(Value::Function(callback)) => Some(callback.clone()),
+        _ => None
+    };
+    if callback.is_some() {
+        args.pop();
+    }
+    let args = marshal::from_pairs(MultiValue::from_vec(args))
+        .map_err(|err| RuntimeError(format!("dbus.call_method: {}", err)))?;
+    let mut message = Message::new_method_call(destination, path, interface, method)
+        .map_err(|err| RuntimeError(format!("dbus.call_method: {}", err)))?;
+    append(&mut message, &args);
+    let callback = match callback {
+        Some(callback) => callback,
+        None => {
+            let reply = connected(bus, |connection| {
+                connection
+                    .connection
+                    .send_with_reply_and_block(message, CALL_TIMEOUT_MS as i32)
+            });
+            dispatch_later(bus);
+            return match reply {
+                Ok(Ok(reply)) => reply_values(lua, reply),
+                Ok(Err(err)) | Err(err) => CallError::from(err).to_lua_multi(lua)
+            };
+        }
+    };
+    let sent = connected(bus, |connection| {
+        let serial = connection.connection.send(message).ok()?;
+        connection.calls.insert(serial);
+        Some(serial)
+    });
+    let serial = match sent {
+        Ok(Some(serial)) => serial,
+        Ok(None) => {
+            let error = format!("could not send a message on the {} bus", bus.name());
+            return Err(RuntimeError(error));
+        },
+        Err(err) => return CallError::from(err).to_lua_multi(lua)
+    };
+    let calls = lua.named_registry_value::<str, Table>(CALLS_HANDLE)?;
+    calls.set(call_key(bus, serial), callback)?;
+    glib::timeout_add(CALL_TIMEOUT_MS, move || {
+        expire_call(bus, serial);
+        Continue(false)
+    });
+    true.to_lua_multi(lua)
+}
+
+/// Writes the connections to the buses, for crash reports.
+pub fn write_stats(out: &mut dyn fmt::Write) -> fmt::Result {
+    for &bus in Bus::ALL {
+        bus.key()
+            .try_with(|connection| {
+                let connection = match connection.try_borrow() {
+                    Ok(connection) => connection,
+                    Err(_) => return writeln!(out, "{}: (in use)", bus.name())
+                };
+                match connection.as_ref() {
+                    Some(connection) => writeln!(
+                        out,
+                        "{}: {} rules, {} calls waiting, {} signals delivered, {} ignored",
+                        bus.name(),
+                        connection.rules.len(),
+                        connection.calls.len(),
+                        connection.delivered,
+                        connection.ignored
+                    ),
+                    None => writeln!(out, "{}: not connected", bus.name())
+                }
+            })
+            .unwrap_or(Ok(()))?;
+    }
+    Ok(())
 }
 
 // TODO This is the default class index/newindex, move there
@@ -478,3 +836,212 @@ fn index<'lua>(lua: rlua::Context<'lua>, args: Value<'lua>) -> rlua::Result<()>
 fn newindex<'lua>(lua: rlua::Context<'lua>, args: Value<'lua>) -> rlua::Result<()> {
     signal::global_emit_signal(lua, ("debug::newindex::miss".into(), args))
 }
+
+#[cfg(test)]
+mod test {
+    use super::test_bus::{connect, TestBus};
+    use super::*;
+    use rlua::Lua;
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            mpsc, Arc
+        },
+        thread
+    };
+
+    /// Owns `org.test.Peer` and answers `Echo` with its arguments, `Fail`
+    /// with an error and `Relay` with what `org.test.Client` answers to
+    /// `Hello`.
+    fn serve_peer(address: String, ready: mpsc::Sender<()>, stop: Arc<AtomicBool>) {
+        let connection = connect(&address);
+        let queue = Rc::new(RefCell::new(VecDeque::new()));
+        let received = queue.clone();
+        connection.replace_message_callback(Some(Box::new(move |_, message| {
+            received.borrow_mut().push_back(message);
+            true
+        })));
+        connection.register_name("org.test.Peer", 0).unwrap();
+        ready.send(()).unwrap();
+        while !stop.load(Ordering::SeqCst) {
+            connection.incoming(50).for_each(drop);
+            let calls: Vec<Message> = queue
+                .borrow_mut()
+                .drain(..)
+                .filter(|message| message.msg_type() == MessageType::MethodCall)
+                .collect();
+            for call in calls {
+                let reply = match call.headers().3.as_ref().map(String::as_str) {
+                    Some("Echo") => {
+                        let mut reply = call.method_return();
+                        reply.append_items(&call.get_items());
+                        reply
+                    },
+                    Some("Fail") => Message::new_error(&call, "org.test.Error.Failed", "it failed").unwrap(),
+                    Some("Relay") => {
+                        let hello =
+                            Message::new_method_call("org.test.Client", "/", "org.test.Client", "Hello")
+                                .unwrap()
+                                .append1("peer");
+                        match connection.send_with_reply_and_block(hello, 5000) {
+                            Ok(answer) => {
+                                let mut reply = call.method_return();
+                                reply.append_items(&answer.get_items());
+                                reply
+                            },
+                            Err(err) => {
+                                Message::new_error(&call, err.name().unwrap(), err.message().unwrap_or(""))
+                                    .unwrap()
+                            },
+                        }
+                    },
+                    _ => continue
+                };
+                connection.send(reply).unwrap();
+            }
+        }
+    }
+
+    fn counters() -> (u64, u64) {
+        Bus::Session.with(|connection| {
+            let connection = connection.as_ref().unwrap();
+            (connection.delivered, connection.ignored)
+        })
+    }
+
+    #[test]
+    fn dbus_private_bus() -> rlua::Result<()> {
+        let bus = match TestBus::start() {
+            Some(bus) => bus,
+            None => {
+                eprintln!("dbus-daemon can't be run, skipping");
+                return Ok(());
+            }
+        };
+        Bus::Session.with(|connection| *connection = Some(BusConnection::new(Bus::Session, bus.connect())));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (ready, is_ready) = mpsc::channel();
+        let peer = {
+            let (address, stop) = (bus.address.clone(), stop.clone());
+            thread::spawn(move || serve_peer(address, ready, stop))
+        };
+        is_ready.recv().unwrap();
+        let emitter = bus.connect();
+        let lua = Lua::new();
+        let result = lua.context(|lua| {
+            lua_init(lua)?;
+            let pump = |done: &dyn Fn() -> rlua::Result<bool>| -> rlua::Result<()> {
+                for _ in 0..50 {
+                    dispatch(lua, Bus::Session, 100)?;
+                    if done()? {
+                        return Ok(());
+                    }
+                }
+                Err(RuntimeError("nothing arrived in time".into()))
+            };
+            let lua_is_true = |condition: &'static str| move || lua.load(condition).eval::<bool>();
+            lua.load(
+                r#"
+local function has_owner(name)
+    return dbus.call_method("session", "org.freedesktop.DBus", "/org/freedesktop/DBus",
+                            "org.freedesktop.DBus", "NameHasOwner", "s", name)
+end
+assert(dbus.request_name("session", "org.test.Client") == true)
+assert(dbus.request_name("session", "org.test.Client") == true)
+assert(dbus.request_name("session", "org.test.Peer") == false)
+assert(has_owner("org.test.Client"))
+assert(dbus.release_name("session", "org.test.Client") == true)
+assert(dbus.release_name("session", "org.test.Client") == false)
+assert(not has_owner("org.test.Client"))
+assert(not pcall(dbus.request_name, "sessions", "org.test.Client"))
+
+received = {}
+assert(dbus.connect_signal("org.test.Player", function(data, ...)
+    table.insert(received, { data = data, args = { ... } })
+end))
+local ok, err = dbus.connect_signal("org.test.Player", function() end)
+assert(ok == nil and err)
+dbus.add_match("session", "type='signal',interface='org.test.Player'")
+dbus.add_match("session", "type='signal',interface='org.test.Other'")
+assert(not pcall(dbus.add_match, "session", "colour='red'"))
+assert(dbus.emit_signal("session", "/org/test", "org.test.Player", "Changed", "s", "playing", "i", 3,
+                        "as", { "a", "b" }, "a{sv}", { Volume = 0.5 }, "v", "x"))
+ok, err = dbus.emit_signal("session", "/org/test", "org.test.Player", "Changed", "s")
+assert(ok == false and err)
+                "#
+            )
+            .exec()?;
+            let (_, ignored) = counters();
+            emitter
+                .send(Message::new_signal("/org/test", "org.test.Other", "Changed").unwrap())
+                .unwrap();
+            emitter
+                .send(Message::new_signal("/org/test", "org.test.Player", "Stopped").unwrap())
+                .unwrap();
+            pump(&lua_is_true("#received == 2"))?;
+            pump(&|| Ok(counters().1 > ignored))?;
+            assert_eq!(counters().0, 2);
+            lua.load(
+                r#"
+local changed, stopped
+for _, signal in ipairs(received) do
+    if signal.data.member == "Changed" then changed = signal else stopped = signal end
+end
+local data = changed.data
+assert(data.type == "signal" and data.interface == "org.test.Player" and data.path == "/org/test")
+assert(data.bus == "session" and data.sender ~= stopped.data.sender)
+local args = changed.args
+assert(args[1] == "playing" and math.type(args[2]) == "integer" and args[2] == 3)
+assert(args[3][1] == "a" and args[3][2] == "b" and args[4].Volume == 0.5 and args[5] == "x")
+assert(#stopped.args == 0)
+
+local names, counts, n = dbus.call_method("session", "org.test.Peer", "/", "org.test.Peer", "Echo",
+                                          "as", { "a", "b" }, "a{si}", { x = 1 }, "u", 7)
+assert(names[2] == "b" and counts.x == 1 and n == 7)
+ok, err = dbus.call_method("session", "org.test.Peer", "/", "org.test.Peer", "Fail")
+assert(ok == nil and err.kind == "dbus" and err.name == "org.test.Error.Failed" and err.message == "it failed")
+ok, err = dbus.call_method("session", "org.test.Missing", "/", "org.test.Missing", "Echo")
+assert(ok == nil and err.name == "org.freedesktop.DBus.Error.ServiceUnknown")
+assert(not pcall(dbus.call_method, "session", "org.test.Peer", "/", "org.test.Peer", "Echo", "h", 1))
+assert(not pcall(dbus.call_method, "bus", "org.test.Peer", "/", "org.test.Peer", "Echo"))
+assert(dbus.call_method("session", "org.test.Peer", "/", "org.test.Peer", "Echo", "s", "later",
+                        function(...) echoed = { ... } end))
+
+assert(dbus.request_name("session", "org.test.Client"))
+assert(dbus.connect_signal("org.test.Client", function(data, name)
+    hello = data
+    return "s", "hello " .. name
+end))
+assert(dbus.call_method("session", "org.test.Peer", "/", "org.test.Peer", "Relay",
+                        function(...) relayed = { ... } end))
+                "#
+            )
+            .exec()?;
+            pump(&lua_is_true("echoed ~= nil and relayed ~= nil"))?;
+            lua.load(
+                r#"
+assert(echoed[1] == "later")
+assert(relayed[1] == "hello peer" and hello.type == "method_call" and hello.member == "Hello")
+dbus.disconnect_signal("org.test.Player", function() end)
+                "#
+            )
+            .exec()?;
+            let (delivered, ignored) = counters();
+            emitter
+                .send(Message::new_signal("/org/test", "org.test.Player", "Stopped").unwrap())
+                .unwrap();
+            pump(&|| Ok(counters().1 > ignored))?;
+            assert_eq!(counters().0, delivered);
+            let mut stats = String::new();
+            write_stats(&mut stats).unwrap();
+            assert!(stats.starts_with("session: 2 rules, 0 calls waiting, 2 signals delivered"));
+            assert!(stats.contains("\nsystem: not connected\n"));
+            Ok(())
+        });
+        stop.store(true, Ordering::SeqCst);
+        peer.join().unwrap();
+        Bus::Session.with(|connection| *connection = None);
+        INTERFACES.with(|interfaces| interfaces.borrow_mut().clear());
+        result
+    }
+}
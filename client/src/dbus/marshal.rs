@@ -0,0 +1,789 @@
+//! Converting between Lua values and D-Bus arguments, the way awesome does.
+//!
+//! Values are sent as the type their signature names:
+//!
+//! * `b` from a boolean, `y n q i u x t` from whole numbers in their range,
+//!   `d` from any number,
+//! * `s` from a string or a number, `o` and `g` from strings that are valid
+//!   object paths and signatures,
+//! * `aT` from a sequence, `a{KV}` from a table's pairs, `(…)` from a
+//!   sequence with a value for every field,
+//! * `v` from a boolean, number or string, sent as `b`, `i` or `x`, `d` or
+//!   `s`, or from `{ signature = "…", value = … }` for anything else.
+//!
+//! awesome's `a`, a sequence of type and value pairs of the same type, is
+//! understood too. What's received is given to Lua the same way back:
+//! arrays and structs as sequences, dicts as tables, variants unwrapped and
+//! 64 bit unsigned integers too big for Lua as floats.
+//!
+//! File descriptors (`h`) can't be sent or received yet.
+
+use std::{convert::TryFrom, error::Error, fmt};
+
+use dbus::{
+    arg::{ArgType, Iter, IterAppend},
+    Message, Path, Signature
+};
+use rlua::{self, MultiValue, Table, Value};
+
+use super::signature::{Basic, Type};
+
+/// An argument, checked against its type, that's sent or was received.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arg {
+    Boolean(bool),
+    Byte(u8),
+    Int16(i16),
+    UInt16(u16),
+    Int32(i32),
+    UInt32(u32),
+    Int64(i64),
+    UInt64(u64),
+    Double(f64),
+    String(String),
+    ObjectPath(String),
+    Signature(String),
+    /// The type of the elements and the elements.
+    Array(Type, Vec<Arg>),
+    /// The types of the keys and values and the entries.
+    Dict(Basic, Type, Vec<(Arg, Arg)>),
+    Struct(Vec<Arg>),
+    Variant(Box<Arg>)
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum MarshalError {
+    /// A value that isn't of the type it's sent as, at the path.
+    Invalid { path: String, message: String },
+    /// A type that can't be sent or received yet, at the path.
+    Unsupported { path: String, what: &'static str }
+}
+
+impl MarshalError {
+    fn invalid<S: Into<String>>(message: S) -> Self {
+        MarshalError::Invalid {
+            path: String::new(),
+            message: message.into()
+        }
+    }
+
+    /// Puts the path of the value under the part of its container it's in.
+    fn within(self, part: &dyn fmt::Display) -> Self {
+        match self {
+            MarshalError::Invalid { path, message } => MarshalError::Invalid {
+                path: format!("{}{}", part, path),
+                message
+            },
+            MarshalError::Unsupported { path, what } => MarshalError::Unsupported {
+                path: format!("{}{}", part, path),
+                what
+            }
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MarshalError::Invalid { .. } => "invalid",
+            MarshalError::Unsupported { .. } => "unsupported"
+        }
+    }
+}
+
+impl fmt::Display for MarshalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MarshalError::Invalid { path, message } => write!(f, "{}: {}", path, message),
+            MarshalError::Unsupported { path, what } => write!(f, "{}: {} aren't supported yet", path, what)
+        }
+    }
+}
+
+impl Error for MarshalError {}
+
+impl From<MarshalError> for rlua::Error {
+    fn from(err: MarshalError) -> Self {
+        rlua::Error::RuntimeError(err.to_string())
+    }
+}
+
+struct Index(usize);
+
+impl fmt::Display for Index {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}]", self.0)
+    }
+}
+
+struct Argument(usize);
+
+impl fmt::Display for Argument {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "argument {}", self.0)
+    }
+}
+
+/// Converts awesome's list of type and value pairs, like `"s", "hello",
+/// "as", { "a", "b" }`, to arguments.
+pub fn from_pairs(values: MultiValue) -> Result<Vec<Arg>, MarshalError> {
+    let values = values.into_vec();
+    if values.len() % 2 != 0 {
+        return Err(MarshalError::invalid(
+            "expected pairs of a type and a value, got an odd number of values"
+        )
+        .within(&"arguments"));
+    }
+    values
+        .chunks(2)
+        .enumerate()
+        .map(|(index, pair)| {
+            from_pair(pair[0].clone(), pair[1].clone()).map_err(|err| err.within(&Argument(index + 1)))
+        })
+        .collect()
+}
+
+fn from_pair(signature: Value, value: Value) -> Result<Arg, MarshalError> {
+    let signature = match signature {
+        Value::String(signature) => signature
+            .to_str()
+            .map(String::from)
+            .map_err(|_| MarshalError::invalid("the type isn't valid UTF-8"))?,
+        other => {
+            return Err(MarshalError::invalid(format!(
+                "expected a type like \"s\", got {}",
+                type_name(&other)
+            )))
+        },
+    };
+    if signature == "a" {
+        return from_awesome_array(value);
+    }
+    let ty = Type::parse(&signature)
+        .map_err(|err| MarshalError::invalid(format!("the type {:?} is invalid, {}", signature, err)))?;
+    Arg::from_lua(&ty, value)
+}
+
+/// awesome's arrays, a sequence of type and value pairs.
+fn from_awesome_array(value: Value) -> Result<Arg, MarshalError> {
+    let table = match value {
+        Value::Table(table) => table,
+        other => return Err(expected("a table of type and value pairs", &other))
+    };
+    let values = table
+        .sequence_values::<Value>()
+        .collect::<rlua::Result<Vec<_>>>()
+        .map_err(|err| MarshalError::invalid(err.to_string()))?;
+    if values.is_empty() {
+        return Err(MarshalError::invalid(
+            "an empty array needs its element type, like \"as\""
+        ));
+    }
+    if values.len() % 2 != 0 {
+        return Err(MarshalError::invalid(
+            "expected pairs of a type and a value, got an odd number of values"
+        ));
+    }
+    let mut elements = Vec::with_capacity(values.len() / 2);
+    for (index, pair) in values.chunks(2).enumerate() {
+        let element =
+            from_pair(pair[0].clone(), pair[1].clone()).map_err(|err| err.within(&Index(index + 1)))?;
+        if let Some(first) = elements.first().map(Arg::ty) {
+            if element.ty() != first {
+                return Err(MarshalError::invalid(format!(
+                    "the elements are {} and {}, an array's are all of one type",
+                    first.signature(),
+                    element.ty().signature()
+                ))
+                .within(&Index(index + 1)));
+            }
+        }
+        elements.push(element);
+    }
+    Ok(Arg::Array(elements[0].ty(), elements))
+}
+
+/// The arguments of a message.
+pub fn read_message(message: &Message) -> Result<Vec<Arg>, MarshalError> {
+    let mut iter = message.iter_init();
+    let mut args = Vec::new();
+    while iter.arg_type() != ArgType::Invalid {
+        args.push(Arg::read(&mut iter).map_err(|err| err.within(&Argument(args.len() + 1)))?);
+        iter.next();
+    }
+    Ok(args)
+}
+
+impl Arg {
+    pub fn ty(&self) -> Type {
+        let basic = match self {
+            Arg::Boolean(_) => Basic::Boolean,
+            Arg::Byte(_) => Basic::Byte,
+            Arg::Int16(_) => Basic::Int16,
+            Arg::UInt16(_) => Basic::UInt16,
+            Arg::Int32(_) => Basic::Int32,
+            Arg::UInt32(_) => Basic::UInt32,
+            Arg::Int64(_) => Basic::Int64,
+            Arg::UInt64(_) => Basic::UInt64,
+            Arg::Double(_) => Basic::Double,
+            Arg::String(_) => Basic::String,
+            Arg::ObjectPath(_) => Basic::ObjectPath,
+            Arg::Signature(_) => Basic::Signature,
+            Arg::Array(element, _) => return Type::Array(Box::new(element.clone())),
+            Arg::Dict(key, value, _) => return Type::Dict(*key, Box::new(value.clone())),
+            Arg::Struct(fields) => return Type::Struct(fields.iter().map(Arg::ty).collect()),
+            Arg::Variant(_) => return Type::Variant
+        };
+        Type::Basic(basic)
+    }
+
+    /// Converts `value` to an argument of type `ty`.
+    pub fn from_lua(ty: &Type, value: Value) -> Result<Self, MarshalError> {
+        match ty {
+            Type::Basic(basic) => from_lua_basic(*basic, value),
+            Type::Array(element) => {
+                let table = match value {
+                    Value::Table(table) => table,
+                    other => return Err(expected("a sequence", &other))
+                };
+                let elements = sequence(table)?
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, value)| {
+                        Arg::from_lua(element, value).map_err(|err| err.within(&Index(index + 1)))
+                    })
+                    .collect::<Result<_, _>>()?;
+                Ok(Arg::Array((**element).clone(), elements))
+            },
+            Type::Dict(key, value_type) => {
+                let table = match value {
+                    Value::Table(table) => table,
+                    other => return Err(expected("a table", &other))
+                };
+                let mut entries = Vec::new();
+                for pair in table.pairs::<Value, Value>() {
+                    let (key_value, value) = pair.map_err(|err| MarshalError::invalid(err.to_string()))?;
+                    let part = lua_key_part(&key_value);
+                    let key_arg = from_lua_basic(*key, key_value).map_err(|err| err.within(&part))?;
+                    let value_arg = Arg::from_lua(value_type, value).map_err(|err| err.within(&part))?;
+                    entries.push((key_arg, value_arg));
+                }
+                Ok(Arg::Dict(*key, (**value_type).clone(), entries))
+            },
+            Type::Struct(fields) => {
+                let table = match value {
+                    Value::Table(table) => table,
+                    other => return Err(expected("a sequence", &other))
+                };
+                let values = sequence(table)?;
+                if values.len() != fields.len() {
+                    return Err(MarshalError::invalid(format!(
+                        "expected {} fields for {}, got {}",
+                        fields.len(),
+                        ty.signature(),
+                        values.len()
+                    )));
+                }
+                fields
+                    .iter()
+                    .zip(values)
+                    .enumerate()
+                    .map(|(index, (field, value))| {
+                        Arg::from_lua(field, value).map_err(|err| err.within(&Index(index + 1)))
+                    })
+                    .collect::<Result<_, _>>()
+                    .map(Arg::Struct)
+            },
+            Type::Variant => from_lua_variant(value).map(|arg| Arg::Variant(Box::new(arg)))
+        }
+    }
+
+    /// Reads the argument `iter` is at.
+    fn read(iter: &mut Iter) -> Result<Self, MarshalError> {
+        Ok(match iter.arg_type() {
+            ArgType::Boolean => Arg::Boolean(iter.get().unwrap_or_default()),
+            ArgType::Byte => Arg::Byte(iter.get().unwrap_or_default()),
+            ArgType::Int16 => Arg::Int16(iter.get().unwrap_or_default()),
+            ArgType::UInt16 => Arg::UInt16(iter.get().unwrap_or_default()),
+            ArgType::Int32 => Arg::Int32(iter.get().unwrap_or_default()),
+            ArgType::UInt32 => Arg::UInt32(iter.get().unwrap_or_default()),
+            ArgType::Int64 => Arg::Int64(iter.get().unwrap_or_default()),
+            ArgType::UInt64 => Arg::UInt64(iter.get().unwrap_or_default()),
+            ArgType::Double => Arg::Double(iter.get().unwrap_or_default()),
+            ArgType::String => Arg::String(iter.get().unwrap_or_default()),
+            ArgType::ObjectPath => Arg::ObjectPath(
+                iter.get::<Path>()
+                    .map(|path| path.to_string())
+                    .unwrap_or_default()
+            ),
+            ArgType::Signature => Arg::Signature(
+                iter.get::<Signature>()
+                    .map(|signature| signature.to_string())
+                    .unwrap_or_default()
+            ),
+            ArgType::Array => {
+                let ty = Type::parse(&iter.signature()).map_err(|err| {
+                    MarshalError::invalid(format!("received an invalid signature, {}", err))
+                })?;
+                let mut elements = iter.recurse(ArgType::Array).unwrap();
+                match ty {
+                    Type::Dict(key, value) => {
+                        let mut entries = Vec::new();
+                        while elements.arg_type() == ArgType::DictEntry {
+                            let mut entry = elements.recurse(ArgType::DictEntry).unwrap();
+                            let key = Arg::read(&mut entry)?;
+                            entry.next();
+                            let part = key_part(&key);
+                            entries.push((key, Arg::read(&mut entry).map_err(|err| err.within(&part))?));
+                            elements.next();
+                        }
+                        Arg::Dict(key, *value, entries)
+                    },
+                    Type::Array(element) => {
+                        let mut items = Vec::new();
+                        while elements.arg_type() != ArgType::Invalid {
+                            items.push(
+                                Arg::read(&mut elements)
+                                    .map_err(|err| err.within(&Index(items.len() + 1)))?
+                            );
+                            elements.next();
+                        }
+                        Arg::Array(*element, items)
+                    },
+                    _ => return Err(MarshalError::invalid("received an array that isn't one"))
+                }
+            },
+            ArgType::Struct => {
+                let mut fields = iter.recurse(ArgType::Struct).unwrap();
+                let mut items = Vec::new();
+                while fields.arg_type() != ArgType::Invalid {
+                    items.push(Arg::read(&mut fields).map_err(|err| err.within(&Index(items.len() + 1)))?);
+                    fields.next();
+                }
+                Arg::Struct(items)
+            },
+            ArgType::Variant => {
+                Arg::Variant(Box::new(Arg::read(&mut iter.recurse(ArgType::Variant).unwrap())?))
+            },
+            ArgType::UnixFd => {
+                return Err(MarshalError::Unsupported {
+                    path: String::new(),
+                    what: "file descriptors"
+                })
+            },
+            ArgType::DictEntry | ArgType::Invalid => {
+                return Err(MarshalError::invalid("received a dict entry outside of a dict"))
+            },
+        })
+    }
+
+    pub fn append(&self, iter: &mut IterAppend) {
+        match self {
+            Arg::Boolean(value) => iter.append(*value),
+            Arg::Byte(value) => iter.append(*value),
+            Arg::Int16(value) => iter.append(*value),
+            Arg::UInt16(value) => iter.append(*value),
+            Arg::Int32(value) => iter.append(*value),
+            Arg::UInt32(value) => iter.append(*value),
+            Arg::Int64(value) => iter.append(*value),
+            Arg::UInt64(value) => iter.append(*value),
+            Arg::Double(value) => iter.append(*value),
+            Arg::String(value) => iter.append(value.as_str()),
+            // Both were checked when they were converted.
+            Arg::ObjectPath(path) => iter.append(Path::new(path.as_str()).unwrap()),
+            Arg::Signature(signature) => iter.append(Signature::new(signature.as_str()).unwrap()),
+            Arg::Array(element, items) => iter.append_array(&dbus_signature(element), |iter| {
+                for item in items {
+                    item.append(iter);
+                }
+            }),
+            Arg::Dict(key, value, entries) => iter.append_dict(
+                &dbus_signature(&Type::Basic(*key)),
+                &dbus_signature(value),
+                |iter| {
+                    for (key, value) in entries {
+                        iter.append_dict_entry(|iter| {
+                            key.append(iter);
+                            value.append(iter);
+                        });
+                    }
+                }
+            ),
+            Arg::Struct(fields) => iter.append_struct(|iter| {
+                for field in fields {
+                    field.append(iter);
+                }
+            }),
+            Arg::Variant(inner) => {
+                iter.append_variant(&dbus_signature(&inner.ty()), |iter| inner.append(iter))
+            },
+        }
+    }
+
+    fn to_integer(self) -> Option<i128> {
+        Some(match self {
+            Arg::Byte(n) => n.into(),
+            Arg::Int16(n) => n.into(),
+            Arg::UInt16(n) => n.into(),
+            Arg::Int32(n) => n.into(),
+            Arg::UInt32(n) => n.into(),
+            Arg::Int64(n) => n.into(),
+            Arg::UInt64(n) => n.into(),
+            _ => return None
+        })
+    }
+
+    pub fn to_lua<'lua>(&self, lua: rlua::Context<'lua>) -> rlua::Result<Value<'lua>> {
+        Ok(match self {
+            Arg::Boolean(value) => Value::Boolean(*value),
+            Arg::Byte(value) => Value::Integer(i64::from(*value)),
+            Arg::Int16(value) => Value::Integer(i64::from(*value)),
+            Arg::UInt16(value) => Value::Integer(i64::from(*value)),
+            Arg::Int32(value) => Value::Integer(i64::from(*value)),
+            Arg::UInt32(value) => Value::Integer(i64::from(*value)),
+            Arg::Int64(value) => Value::Integer(*value),
+            Arg::UInt64(value) => match i64::try_from(*value) {
+                Ok(value) => Value::Integer(value),
+                Err(_) => Value::Number(*value as f64)
+            },
+            Arg::Double(value) => Value::Number(*value),
+            Arg::String(value) | Arg::ObjectPath(value) | Arg::Signature(value) => {
+                Value::String(lua.create_string(value)?)
+            },
+            Arg::Array(_, items) | Arg::Struct(items) => {
+                let table = lua.create_table()?;
+                for (index, item) in items.iter().enumerate() {
+                    table.set(index + 1, item.to_lua(lua)?)?;
+                }
+                Value::Table(table)
+            },
+            Arg::Dict(_, _, entries) => {
+                let table = lua.create_table()?;
+                for (key, value) in entries {
+                    match key.to_lua(lua)? {
+                        Value::Number(key) if key.is_nan() => continue,
+                        key => table.set(key, value.to_lua(lua)?)?
+                    }
+                }
+                Value::Table(table)
+            },
+            Arg::Variant(inner) => inner.to_lua(lua)?
+        })
+    }
+}
+
+fn from_lua_basic(basic: Basic, value: Value) -> Result<Arg, MarshalError> {
+    let out_of_range = |value: &dyn fmt::Display| {
+        MarshalError::invalid(format!("{} is out of the range of {}", value, basic.code()))
+    };
+    macro_rules! integer {
+        ($variant:ident, $type:ty) => {
+            match whole_number(&value) {
+                Some(Ok(n)) => <$type>::try_from(n)
+                    .map(Arg::$variant)
+                    .map_err(|_| out_of_range(&n)),
+                Some(Err(n)) => Err(MarshalError::invalid(format!("{} isn't a whole number", n))),
+                None => Err(expected("an integer", &value))
+            }
+        };
+    }
+    match basic {
+        Basic::Boolean => match value {
+            Value::Boolean(value) => Ok(Arg::Boolean(value)),
+            other => Err(expected("a boolean", &other))
+        },
+        Basic::Byte => integer!(Byte, u8),
+        Basic::Int16 => integer!(Int16, i16),
+        Basic::UInt16 => integer!(UInt16, u16),
+        Basic::Int32 => integer!(Int32, i32),
+        Basic::UInt32 => integer!(UInt32, u32),
+        Basic::Int64 => integer!(Int64, i64),
+        Basic::UInt64 => match value {
+            Value::Number(n) if n >= 0.0 && n < 18_446_744_073_709_551_616.0 && n.fract() == 0.0 => {
+                Ok(Arg::UInt64(n as u64))
+            },
+            _ => integer!(UInt64, u64)
+        },
+        Basic::Double => match value {
+            Value::Integer(n) => Ok(Arg::Double(n as f64)),
+            Value::Number(n) => Ok(Arg::Double(n)),
+            other => Err(expected("a number", &other))
+        },
+        Basic::String => match value {
+            Value::Integer(n) => Ok(Arg::String(n.to_string())),
+            Value::Number(n) => Ok(Arg::String(n.to_string())),
+            other => string(other).map(Arg::String)
+        },
+        Basic::ObjectPath => {
+            let path = string(value)?;
+            Path::new(path.as_str())
+                .map_err(|_| MarshalError::invalid(format!("{:?} isn't an object path", path)))?;
+            Ok(Arg::ObjectPath(path))
+        },
+        Basic::Signature => {
+            let signature = string(value)?;
+            Signature::new(signature.as_str())
+                .map_err(|_| MarshalError::invalid(format!("{:?} isn't a signature", signature)))?;
+            Ok(Arg::Signature(signature))
+        },
+        Basic::UnixFd => Err(MarshalError::Unsupported {
+            path: String::new(),
+            what: "file descriptors"
+        })
+    }
+}
+
+/// What a variant holding `value` is sent as, when it doesn't say.
+fn from_lua_variant(value: Value) -> Result<Arg, MarshalError> {
+    match value {
+        Value::Boolean(value) => Ok(Arg::Boolean(value)),
+        Value::Integer(n) => Ok(i32::try_from(n).map(Arg::Int32).unwrap_or(Arg::Int64(n))),
+        Value::Number(n) => Ok(Arg::Double(n)),
+        Value::String(_) => from_lua_basic(Basic::String, value),
+        Value::Table(table) => {
+            let signature = match table.get::<_, Value>("signature") {
+                Ok(Value::String(signature)) => signature
+                    .to_str()
+                    .map(String::from)
+                    .map_err(|_| MarshalError::invalid("the signature isn't valid UTF-8"))?,
+                _ => {
+                    return Err(MarshalError::invalid(
+                        "a table in a variant needs its type, as { signature = \"…\", value = … }"
+                    ))
+                },
+            };
+            let ty = Type::parse(&signature).map_err(|err| {
+                MarshalError::invalid(format!("the type {:?} is invalid, {}", signature, err))
+            })?;
+            let value = table
+                .get::<_, Value>("value")
+                .map_err(|err| MarshalError::invalid(err.to_string()))?;
+            Arg::from_lua(&ty, value).map_err(|err| err.within(&".value"))
+        },
+        other => Err(expected("a boolean, number, string or table", &other))
+    }
+}
+
+/// The whole number `value` is, or the number that isn't whole.
+fn whole_number(value: &Value) -> Option<Result<i64, f64>> {
+    match *value {
+        Value::Integer(n) => Some(Ok(n)),
+        Value::Number(n)
+            if n.fract() == 0.0 && n >= -9_223_372_036_854_775_808.0 && n < 9_223_372_036_854_775_808.0 =>
+        {
+            Some(Ok(n as i64))
+        },
+        Value::Number(n) => Some(Err(n)),
+        _ => None
+    }
+}
+
+fn string(value: Value) -> Result<String, MarshalError> {
+    let string = match value {
+        Value::String(string) => string
+            .to_str()
+            .map(String::from)
+            .map_err(|_| MarshalError::invalid("the string isn't valid UTF-8"))?,
+        other => return Err(expected("a string", &other))
+    };
+    if string.contains('\0') {
+        return Err(MarshalError::invalid("the string contains a NUL byte"));
+    }
+    Ok(string)
+}
+
+fn sequence(table: Table) -> Result<Vec<Value>, MarshalError> {
+    table
+        .sequence_values::<Value>()
+        .collect::<rlua::Result<Vec<_>>>()
+        .map_err(|err| MarshalError::invalid(err.to_string()))
+}
+
+/// Where a dict's value is, by its key.
+fn key_part(key: &Arg) -> String {
+    match key {
+        Arg::String(key) | Arg::ObjectPath(key) | Arg::Signature(key) => format!("[{:?}]", key),
+        Arg::Boolean(key) => format!("[{}]", key),
+        Arg::Double(key) => format!("[{}]", key),
+        key => match key.clone().to_integer() {
+            Some(key) => format!("[{}]", key),
+            None => "[?]".into()
+        }
+    }
+}
+
+fn lua_key_part(key: &Value) -> String {
+    match key {
+        Value::String(key) => format!("[{:?}]", key.to_str().unwrap_or("?")),
+        Value::Integer(key) => format!("[{}]", key),
+        Value::Number(key) => format!("[{}]", key),
+        Value::Boolean(key) => format!("[{}]", key),
+        other => format!("[{}]", type_name(other))
+    }
+}
+
+fn expected(what: &str, value: &Value) -> MarshalError {
+    MarshalError::invalid(format!("expected {}, got {}", what, type_name(value)))
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Nil => "nil",
+        Value::Boolean(_) => "boolean",
+        Value::Integer(_) | Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Table(_) => "table",
+        Value::Function(_) => "function",
+        Value::Thread(_) => "thread",
+        Value::LightUserData(_) | Value::UserData(_) => "userdata",
+        Value::Error(_) => "error"
+    }
+}
+
+fn dbus_signature(ty: &Type) -> Signature<'static> {
+    Signature::new(ty.signature()).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rlua::Lua;
+
+    #[test]
+    fn dbus_marshal_lua_values() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            let pairs = |code: &str| -> rlua::Result<Result<Vec<Arg>, MarshalError>> {
+                Ok(from_pairs(lua.load(code).eval::<MultiValue>()?))
+            };
+            let args = pairs(
+                r#"return "s", "hi", "y", 255, "d", 1, "as", { "a", "b" }, "a", { "i", 1, "i", 2 },
+                    "(ob)", { "/org/a", true }, "a{sv}", { volume = 0.5 }, "v", 2^40"#
+            )?
+            .unwrap();
+            assert_eq!(
+                args,
+                vec![
+                    Arg::String("hi".into()),
+                    Arg::Byte(255),
+                    Arg::Double(1.0),
+                    Arg::Array(
+                        Type::Basic(Basic::String),
+                        vec![Arg::String("a".into()), Arg::String("b".into())]
+                    ),
+                    Arg::Array(Type::Basic(Basic::Int32), vec![Arg::Int32(1), Arg::Int32(2)]),
+                    Arg::Struct(vec![Arg::ObjectPath("/org/a".into()), Arg::Boolean(true)]),
+                    Arg::Dict(
+                        Basic::String,
+                        Type::Variant,
+                        vec![(
+                            Arg::String("volume".into()),
+                            Arg::Variant(Box::new(Arg::Double(0.5)))
+                        )]
+                    ),
+                    Arg::Variant(Box::new(Arg::Double(1_099_511_627_776.0)))
+                ]
+            );
+            // Variants holding tables say what they are.
+            let args =
+                pairs(r#"return "v", { signature = "at", value = { 1, 2^63 } }, "v", 1 << 40"#)?.unwrap();
+            assert_eq!(
+                args,
+                vec![
+                    Arg::Variant(Box::new(Arg::Array(
+                        Type::Basic(Basic::UInt64),
+                        vec![Arg::UInt64(1), Arg::UInt64(1 << 63)]
+                    ))),
+                    Arg::Variant(Box::new(Arg::Int64(1 << 40)))
+                ]
+            );
+            let error = |code: &str| pairs(code).map(|args| args.unwrap_err().to_string());
+            assert_eq!(
+                error(r#"return "s", "a", "y""#)?,
+                "arguments: expected pairs of a type and a value, got an odd number of values"
+            );
+            assert_eq!(
+                error(r#"return "y", 256"#)?,
+                "argument 1: 256 is out of the range of y"
+            );
+            assert_eq!(
+                error(r#"return "i", 1.5"#)?,
+                "argument 1: 1.5 isn't a whole number"
+            );
+            assert_eq!(
+                error(r#"return "s", "", "as", { "a", 2, {} }"#)?,
+                "argument 2[3]: expected a string, got table"
+            );
+            assert_eq!(
+                error(r#"return "a{si}", { a = "x" }"#)?,
+                "argument 1[\"a\"]: expected an integer, got string"
+            );
+            assert_eq!(
+                error(r#"return "a", { "s", "a", "i", 1 }"#)?,
+                "argument 1[2]: the elements are s and i, an array's are all of one type"
+            );
+            assert_eq!(
+                error(r#"return "a", {}"#)?,
+                "argument 1: an empty array needs its element type, like \"as\""
+            );
+            assert_eq!(
+                error(r#"return "o", "not/a/path""#)?,
+                "argument 1: \"not/a/path\" isn't an object path"
+            );
+            assert_eq!(
+                error(r#"return "a{vs}", {}"#)?,
+                "argument 1: the type \"a{vs}\" is invalid, the dict entry at byte 1 doesn't have a basic key"
+            );
+            assert_eq!(
+                error(r#"return "v", { 1, 2 }"#)?,
+                "argument 1: a table in a variant needs its type, as { signature = \"…\", value = … }"
+            );
+            assert_eq!(
+                error(r#"return "(is)", { 1 }"#)?,
+                "argument 1: expected 2 fields for (is), got 1"
+            );
+            let unsupported = pairs(r#"return "s", "", "ah", { 0 }"#)?.unwrap_err();
+            assert_eq!(unsupported.kind(), "unsupported");
+            assert_eq!(
+                unsupported.to_string(),
+                "argument 2[1]: file descriptors aren't supported yet"
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn dbus_marshal_round_trip() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            let args = from_pairs(
+                lua.load(
+                    r#"return "as", {}, "a{sa{sv}}", { player = { Volume = 0.5, Title = "x" } },
+                        "a(iv)", { { 1, true } }, "t", 2^63, "g", "a{sv}""#
+                )
+                .eval::<MultiValue>()?
+            )
+            .unwrap();
+            let mut message = Message::new_signal("/org/test", "org.test.Marshal", "Changed").unwrap();
+            {
+                let mut iter = IterAppend::new(&mut message);
+                for arg in &args {
+                    arg.append(&mut iter);
+                }
+            }
+            assert_eq!(read_message(&message).unwrap(), args);
+            let globals = lua.globals();
+            for (index, arg) in args.iter().enumerate() {
+                globals.set(format!("arg{}", index + 1), arg.to_lua(lua)?)?;
+            }
+            lua.load(
+                r#"
+assert(next(arg1) == nil)
+assert(arg2.player.Volume == 0.5 and arg2.player.Title == "x")
+assert(arg3[1][1] == 1 and arg3[1][2] == true)
+assert(arg4 == 2^63 and math.type(arg4) == "float")
+assert(arg5 == "a{sv}")
+                "#
+            )
+            .exec()
+        })
+    }
+}
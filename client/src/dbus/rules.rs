@@ -0,0 +1,307 @@
+//! Match rules like `type='signal',interface='org.mpris.MediaPlayer2.Player'`,
+//! parsed so a signal is only given to Lua when a rule the config added
+//! matches it.
+//!
+//! The bus already only sends signals some rule matches, but the rules of
+//! every part of the config share one connection, and a rule that was
+//! removed can still have signals on their way.
+
+use std::{error::Error, fmt};
+
+/// A parsed match rule. Every key it has must match.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct MatchRule {
+    pub message_type: Option<String>,
+    pub sender: Option<String>,
+    pub interface: Option<String>,
+    pub member: Option<String>,
+    pub path: Option<String>,
+    pub path_namespace: Option<String>,
+    pub destination: Option<String>,
+    /// The string arguments that must be equal to a value, by position.
+    pub args: Vec<(usize, String)>,
+    /// The string or object path arguments that must be a path under or
+    /// above a value, by position.
+    pub arg_paths: Vec<(usize, String)>,
+    pub arg0_namespace: Option<String>
+}
+
+/// What a match rule is checked against.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Headers<'a> {
+    pub message_type: &'a str,
+    pub sender: Option<&'a str>,
+    pub interface: Option<&'a str>,
+    pub member: Option<&'a str>,
+    pub path: Option<&'a str>,
+    pub destination: Option<&'a str>
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum RuleError {
+    /// A key without a value, at this byte offset.
+    Malformed {
+        offset: usize
+    },
+    /// A quote that isn't closed.
+    Unterminated,
+    UnknownKey(String),
+    Duplicate(String),
+    /// A value that can't be what the key is, like a `type` other than a
+    /// message type.
+    Invalid {
+        key: String,
+        value: String
+    }
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuleError::Malformed { offset } => write!(f, "expected key='value' at byte {}", offset),
+            RuleError::Unterminated => write!(f, "a quote isn't closed"),
+            RuleError::UnknownKey(key) => write!(f, "{} isn't a key of match rules", key),
+            RuleError::Duplicate(key) => write!(f, "{} is given more than once", key),
+            RuleError::Invalid { key, value } => write!(f, "{:?} isn't a valid {}", value, key)
+        }
+    }
+}
+
+impl Error for RuleError {}
+
+/// The highest argument a rule can match, as the D-Bus specification
+/// allows.
+const MAX_ARG: usize = 63;
+
+impl MatchRule {
+    pub fn parse(rule: &str) -> Result<Self, RuleError> {
+        let mut parsed = MatchRule::default();
+        let mut seen: Vec<String> = Vec::new();
+        for (offset, key, value) in split(rule)? {
+            if seen.contains(&key) {
+                return Err(RuleError::Duplicate(key));
+            }
+            seen.push(key.clone());
+            let invalid = RuleError::Invalid {
+                key: key.clone(),
+                value: value.clone()
+            };
+            match key.as_str() {
+                "type" => match value.as_str() {
+                    "signal" | "method_call" | "method_return" | "error" => parsed.message_type = Some(value),
+                    _ => return Err(invalid)
+                },
+                "sender" => parsed.sender = Some(value),
+                "interface" => parsed.interface = Some(value),
+                "member" => parsed.member = Some(value),
+                "path" => parsed.path = Some(value),
+                "path_namespace" => parsed.path_namespace = Some(value),
+                "destination" => parsed.destination = Some(value),
+                "arg0namespace" => parsed.arg0_namespace = Some(value),
+                // Whether the connection wants to see messages that
+                // aren't for it doesn't change which match.
+                "eavesdrop" => match value.as_str() {
+                    "true" | "false" => {},
+                    _ => return Err(invalid)
+                },
+                _ if key.starts_with("arg") => {
+                    let (index, is_path) = match key[3..].find(|c: char| !c.is_ascii_digit()) {
+                        Some(end) if &key[3 + end..] == "path" => (&key[3..3 + end], true),
+                        Some(_) => return Err(RuleError::UnknownKey(key.clone())),
+                        None => (&key[3..], false)
+                    };
+                    let index = match index.parse::<usize>() {
+                        Ok(index) if index <= MAX_ARG => index,
+                        _ => return Err(RuleError::UnknownKey(key.clone()))
+                    };
+                    if is_path {
+                        parsed.arg_paths.push((index, value));
+                    } else {
+                        parsed.args.push((index, value));
+                    }
+                },
+                _ if key.is_empty() => return Err(RuleError::Malformed { offset }),
+                _ => return Err(RuleError::UnknownKey(key.clone()))
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Whether the rule matches a message with the headers, whose string
+    /// argument at a position is given by `arg`.
+    ///
+    /// A `sender` that's a well known name rather than a unique one can't
+    /// be checked here, the bus checked it before sending the message.
+    pub fn matches<'a, F>(&self, headers: &Headers, arg: F) -> bool
+    where
+        F: Fn(usize) -> Option<&'a str>
+    {
+        fn equal(rule: &Option<String>, header: Option<&str>) -> bool {
+            rule.as_ref().map_or(true, |rule| Some(rule.as_str()) == header)
+        }
+        let sender = match self.sender.as_ref() {
+            Some(sender) if sender.starts_with(':') => Some(sender.as_str()) == headers.sender,
+            _ => true
+        };
+        let path_namespace = match (self.path_namespace.as_ref(), headers.path) {
+            (Some(namespace), Some(path)) => is_path_under(path, namespace),
+            (Some(_), None) => false,
+            (None, _) => true
+        };
+        let arg0_namespace = match self.arg0_namespace.as_ref() {
+            Some(namespace) => arg(0).map_or(false, |arg| {
+                arg == namespace ||
+                    (arg.starts_with(namespace.as_str()) && arg[namespace.len()..].starts_with('.'))
+            }),
+            None => true
+        };
+        equal(&self.message_type, Some(headers.message_type)) &&
+            sender &&
+            equal(&self.interface, headers.interface) &&
+            equal(&self.member, headers.member) &&
+            equal(&self.path, headers.path) &&
+            path_namespace &&
+            equal(&self.destination, headers.destination) &&
+            arg0_namespace &&
+            self.args
+                .iter()
+                .all(|(index, value)| arg(*index) == Some(value.as_str())) &&
+            self.arg_paths.iter().all(|(index, value)| {
+                arg(*index).map_or(false, |arg| {
+                    arg == value ||
+                        (value.ends_with('/') && arg.starts_with(value.as_str())) ||
+                        (arg.ends_with('/') && value.starts_with(arg))
+                })
+            })
+    }
+}
+
+/// Whether `path` is `namespace` or a path under it.
+fn is_path_under(path: &str, namespace: &str) -> bool {
+    namespace == "/" ||
+        path == namespace ||
+        (path.starts_with(namespace) && path[namespace.len()..].starts_with('/'))
+}
+
+/// Splits a rule into its keys and values, with the offset of each key.
+///
+/// Values are quoted with `'`, and outside of quotes `\'` is a quote, as
+/// the D-Bus specification escapes them.
+fn split(rule: &str) -> Result<Vec<(usize, String, String)>, RuleError> {
+    let mut pairs = Vec::new();
+    let mut chars = rule.char_indices().peekable();
+    while chars.peek().is_some() {
+        while chars.peek().map_or(false, |&(_, c)| c.is_whitespace()) {
+            chars.next();
+        }
+        let offset = match chars.peek() {
+            Some(&(offset, _)) => offset,
+            None => break
+        };
+        let mut key = String::new();
+        loop {
+            match chars.next() {
+                Some((_, '=')) => break,
+                Some((_, c)) => key.push(c),
+                None => return Err(RuleError::Malformed { offset })
+            }
+        }
+        let mut value = String::new();
+        let mut quoted = false;
+        loop {
+            match chars.next() {
+                Some((_, '\'')) => quoted = !quoted,
+                Some((_, '\\')) if !quoted && chars.peek().map(|&(_, c)| c) == Some('\'') => {
+                    chars.next();
+                    value.push('\'');
+                },
+                Some((_, ',')) if !quoted => break,
+                Some((_, c)) => value.push(c),
+                None if quoted => return Err(RuleError::Unterminated),
+                None => break
+            }
+        }
+        pairs.push((offset, key.trim().to_string(), value));
+    }
+    Ok(pairs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dbus_match_rules() {
+        let rule = MatchRule::parse(
+            "type='signal', interface='org.mpris.MediaPlayer2.Player',path_namespace='/org/mpris',\
+             arg0='It'\\''s',arg1path='/a/'"
+        )
+        .unwrap();
+        assert_eq!(rule.message_type.as_ref().unwrap(), "signal");
+        assert_eq!(rule.args, [(0, "It's".to_string())]);
+        let headers = Headers {
+            message_type: "signal",
+            sender: Some(":1.5"),
+            interface: Some("org.mpris.MediaPlayer2.Player"),
+            member: Some("Seeked"),
+            path: Some("/org/mpris/MediaPlayer2"),
+            destination: None
+        };
+        let args = ["It's", "/a/b"];
+        let arg = |index: usize| args.get(index).cloned();
+        assert!(rule.matches(&headers, arg));
+        assert!(!rule.matches(&headers, |index| ["Its", "/a/b"].get(index).cloned()));
+        assert!(!rule.matches(&headers, |index| ["It's", "/b"].get(index).cloned()));
+        assert!(!rule.matches(
+            &Headers {
+                path: Some("/org/mprisx"),
+                ..headers
+            },
+            arg
+        ));
+        assert!(!rule.matches(
+            &Headers {
+                message_type: "method_call",
+                ..headers
+            },
+            arg
+        ));
+        // Well known senders were checked by the bus.
+        let rule = MatchRule::parse("sender='org.freedesktop.UPower'").unwrap();
+        assert!(rule.matches(&headers, arg));
+        assert!(!MatchRule::parse("sender=':1.6'").unwrap().matches(&headers, arg));
+        assert!(MatchRule::parse("").unwrap().matches(&headers, arg));
+        let rule = MatchRule::parse("arg0namespace='org.mpris'").unwrap();
+        assert!(rule.matches(&headers, |_| Some("org.mpris.MediaPlayer2.vlc")));
+        assert!(!rule.matches(&headers, |_| Some("org.mprisx")));
+
+        assert_eq!(
+            MatchRule::parse("type='x'").map(|_| ()),
+            Err(RuleError::Invalid {
+                key: "type".into(),
+                value: "x".into()
+            })
+        );
+        assert_eq!(MatchRule::parse("member='a"), Err(RuleError::Unterminated));
+        assert_eq!(
+            MatchRule::parse("type='signal',member"),
+            Err(RuleError::Malformed { offset: 14 })
+        );
+        assert_eq!(
+            MatchRule::parse("colour='red'"),
+            Err(RuleError::UnknownKey("colour".into()))
+        );
+        assert_eq!(
+            MatchRule::parse("arg64='a'"),
+            Err(RuleError::UnknownKey("arg64".into()))
+        );
+        assert_eq!(
+            MatchRule::parse("arg1x='a'"),
+            Err(RuleError::UnknownKey("arg1x".into()))
+        );
+        assert_eq!(
+            MatchRule::parse("member='a',member='b'"),
+            Err(RuleError::Duplicate("member".into()))
+        );
+    }
+}
@@ -0,0 +1,281 @@
+//! D-Bus type signatures like `a{sv}`, which say what a Lua value is sent
+//! as.
+
+use std::{error::Error, fmt};
+
+/// How deep containers can be nested, as the D-Bus specification allows.
+const MAX_DEPTH: usize = 32;
+
+/// A type that isn't a container.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Basic {
+    Boolean,
+    Byte,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Int64,
+    UInt64,
+    Double,
+    String,
+    ObjectPath,
+    Signature,
+    UnixFd
+}
+
+impl Basic {
+    fn from_code(code: char) -> Option<Self> {
+        Some(match code {
+            'b' => Basic::Boolean,
+            'y' => Basic::Byte,
+            'n' => Basic::Int16,
+            'q' => Basic::UInt16,
+            'i' => Basic::Int32,
+            'u' => Basic::UInt32,
+            'x' => Basic::Int64,
+            't' => Basic::UInt64,
+            'd' => Basic::Double,
+            's' => Basic::String,
+            'o' => Basic::ObjectPath,
+            'g' => Basic::Signature,
+            'h' => Basic::UnixFd,
+            _ => return None
+        })
+    }
+
+    pub fn code(self) -> char {
+        match self {
+            Basic::Boolean => 'b',
+            Basic::Byte => 'y',
+            Basic::Int16 => 'n',
+            Basic::UInt16 => 'q',
+            Basic::Int32 => 'i',
+            Basic::UInt32 => 'u',
+            Basic::Int64 => 'x',
+            Basic::UInt64 => 't',
+            Basic::Double => 'd',
+            Basic::String => 's',
+            Basic::ObjectPath => 'o',
+            Basic::Signature => 'g',
+            Basic::UnixFd => 'h'
+        }
+    }
+}
+
+/// A single complete type.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Type {
+    Basic(Basic),
+    Array(Box<Type>),
+    /// An array of dict entries, whose keys are always basic.
+    Dict(Basic, Box<Type>),
+    Struct(Vec<Type>),
+    Variant
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SignatureError {
+    Empty,
+    /// A character that can't be where it is, at this byte offset.
+    Unexpected {
+        offset: usize,
+        found: char
+    },
+    /// A container isn't closed.
+    Unterminated,
+    /// More than one complete type, the second at this byte offset.
+    Trailing {
+        offset: usize
+    },
+    /// A dict entry whose key isn't a basic type.
+    DictKey {
+        offset: usize
+    },
+    TooDeep
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignatureError::Empty => write!(f, "the signature is empty"),
+            SignatureError::Unexpected { offset, found } => {
+                write!(f, "unexpected {:?} at byte {}", found, offset)
+            },
+            SignatureError::Unterminated => write!(f, "a container isn't closed"),
+            SignatureError::Trailing { offset } => {
+                write!(f, "more than one complete type, the second at byte {}", offset)
+            },
+            SignatureError::DictKey { offset } => {
+                write!(f, "the dict entry at byte {} doesn't have a basic key", offset)
+            },
+            SignatureError::TooDeep => write!(f, "nested deeper than {} containers", MAX_DEPTH)
+        }
+    }
+}
+
+impl Error for SignatureError {}
+
+impl Type {
+    /// Parses a signature of exactly one complete type.
+    pub fn parse(signature: &str) -> Result<Self, SignatureError> {
+        let mut parser = Parser {
+            chars: signature.char_indices().peekable(),
+            depth: 0
+        };
+        let ty = parser.complete_type()?;
+        match parser.chars.next() {
+            Some((offset, _)) => Err(SignatureError::Trailing { offset }),
+            None => Ok(ty)
+        }
+    }
+
+    pub fn signature(&self) -> String {
+        let mut out = String::new();
+        self.write_signature(&mut out);
+        out
+    }
+
+    fn write_signature(&self, out: &mut String) {
+        match self {
+            Type::Basic(basic) => out.push(basic.code()),
+            Type::Array(element) => {
+                out.push('a');
+                element.write_signature(out);
+            },
+            Type::Dict(key, value) => {
+                out.push_str("a{");
+                out.push(key.code());
+                value.write_signature(out);
+                out.push('}');
+            },
+            Type::Struct(fields) => {
+                out.push('(');
+                for field in fields {
+                    field.write_signature(out);
+                }
+                out.push(')');
+            },
+            Type::Variant => out.push('v')
+        }
+    }
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    depth: usize
+}
+
+impl<'a> Parser<'a> {
+    fn complete_type(&mut self) -> Result<Type, SignatureError> {
+        let (offset, code) = match self.chars.next() {
+            Some(next) => next,
+            None if self.depth == 0 => return Err(SignatureError::Empty),
+            None => return Err(SignatureError::Unterminated)
+        };
+        if let Some(basic) = Basic::from_code(code) {
+            return Ok(Type::Basic(basic));
+        }
+        match code {
+            'v' => Ok(Type::Variant),
+            'a' => self.nested(|parser| {
+                if parser.chars.peek().map(|&(_, c)| c) != Some('{') {
+                    return Ok(Type::Array(Box::new(parser.complete_type()?)));
+                }
+                let (entry, _) = parser.chars.next().unwrap();
+                let key = match parser.complete_type()? {
+                    Type::Basic(key) => key,
+                    _ => return Err(SignatureError::DictKey { offset: entry })
+                };
+                let value = parser.complete_type()?;
+                parser.close('}')?;
+                Ok(Type::Dict(key, Box::new(value)))
+            }),
+            '(' => self.nested(|parser| {
+                let mut fields = Vec::new();
+                loop {
+                    match parser.chars.peek() {
+                        Some(&(_, ')')) if !fields.is_empty() => {
+                            parser.chars.next();
+                            return Ok(Type::Struct(fields));
+                        },
+                        None => return Err(SignatureError::Unterminated),
+                        _ => fields.push(parser.complete_type()?)
+                    }
+                }
+            }),
+            found => Err(SignatureError::Unexpected { offset, found })
+        }
+    }
+
+    fn nested<F>(&mut self, parse: F) -> Result<Type, SignatureError>
+    where
+        F: FnOnce(&mut Self) -> Result<Type, SignatureError>
+    {
+        if self.depth == MAX_DEPTH {
+            return Err(SignatureError::TooDeep);
+        }
+        self.depth += 1;
+        let ty = parse(self);
+        self.depth -= 1;
+        ty
+    }
+
+    fn close(&mut self, close: char) -> Result<(), SignatureError> {
+        match self.chars.next() {
+            Some((_, c)) if c == close => Ok(()),
+            Some((offset, found)) => Err(SignatureError::Unexpected { offset, found }),
+            None => Err(SignatureError::Unterminated)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dbus_signatures() {
+        for signature in &["s", "as", "a{sv}", "a{oa{sa{sv}}}", "(iasv)", "aay", "a(ss)"] {
+            assert_eq!(&Type::parse(signature).unwrap().signature(), signature);
+        }
+        assert_eq!(
+            Type::parse("a{s(ib)}"),
+            Ok(Type::Dict(
+                Basic::String,
+                Box::new(Type::Struct(vec![
+                    Type::Basic(Basic::Int32),
+                    Type::Basic(Basic::Boolean)
+                ]))
+            ))
+        );
+        assert_eq!(Type::parse(""), Err(SignatureError::Empty));
+        assert_eq!(Type::parse("ss"), Err(SignatureError::Trailing { offset: 1 }));
+        assert_eq!(Type::parse("a"), Err(SignatureError::Unterminated));
+        assert_eq!(Type::parse("(is"), Err(SignatureError::Unterminated));
+        assert_eq!(
+            Type::parse("()"),
+            Err(SignatureError::Unexpected {
+                offset: 1,
+                found: ')'
+            })
+        );
+        assert_eq!(Type::parse("a{vs}"), Err(SignatureError::DictKey { offset: 1 }));
+        assert_eq!(
+            Type::parse("a{sss}"),
+            Err(SignatureError::Unexpected {
+                offset: 4,
+                found: 's'
+            })
+        );
+        assert_eq!(
+            Type::parse("{sv}"),
+            Err(SignatureError::Unexpected {
+                offset: 0,
+                found: '{'
+            })
+        );
+        assert_eq!(Type::parse(&"a".repeat(33)), Err(SignatureError::TooDeep));
+        assert_eq!(Type::parse(&format!("{}s", "a".repeat(32))).map(|_| ()), Ok(()));
+    }
+}
@@ -0,0 +1,68 @@
+//! A bus of a test's own, so tests don't depend on or disturb the buses of
+//! the machine running them.
+
+use std::{
+    fs,
+    io::{BufRead, BufReader},
+    process::{Child, Command, Stdio}
+};
+
+use dbus::Connection;
+
+/// A `dbus-daemon` stopped when dropped.
+pub struct TestBus {
+    daemon: Child,
+    pub address: String,
+    _dir: tempfile::TempDir
+}
+
+impl TestBus {
+    /// Starts the daemon, `None` if it can't be run.
+    pub fn start() -> Option<Self> {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("bus.conf");
+        fs::write(
+            &config,
+            format!(
+                "<busconfig><type>session</type><listen>unix:path={}</listen><auth>EXTERNAL</auth>\
+                 <policy context=\"default\"><allow send_destination=\"*\"/>\
+                 <allow receive_sender=\"*\"/><allow own=\"*\"/></policy></busconfig>",
+                dir.path().join("bus").display()
+            )
+        )
+        .unwrap();
+        let mut daemon = Command::new("dbus-daemon")
+            .arg(format!("--config-file={}", config.display()))
+            .args(&["--print-address", "--nofork"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+        Some(TestBus {
+            daemon,
+            address: address.trim().into(),
+            _dir: dir
+        })
+    }
+
+    pub fn connect(&self) -> Connection {
+        connect(&self.address)
+    }
+}
+
+impl Drop for TestBus {
+    fn drop(&mut self) {
+        self.daemon.kill().ok();
+        self.daemon.wait().ok();
+    }
+}
+
+pub fn connect(address: &str) -> Connection {
+    let connection = Connection::open_private(address).unwrap();
+    connection.register().unwrap();
+    connection
+}
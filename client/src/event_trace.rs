@@ -0,0 +1,472 @@
+//! Recording what the compositor sends the client, and the requests the
+//! client makes in return, so a session can be replayed later.
+//!
+//! `awesome.record_events(path, { input = false, redact = true })` starts
+//! writing a trace to `path`: configures and closes of layer surfaces,
+//! output geometry, modes and scales, buffer releases, surfaces entering and
+//! leaving outputs and the requests made on layer surfaces, and pointer
+//! events if `input` is true. Nothing is recorded until it's called, and
+//! the hooks only check a thread local when it isn't. Pixels are never
+//! recorded, only the size of buffers, and with `redact` strings, like the
+//! make of outputs, are replaced with their hash.
+//! `awesome.stop_recording_events()` finishes the trace.
+//!
+//! In the tests `replay` plays a trace to a model of the client and finds
+//! the first request that differs from the recording, see `format` for the
+//! file.
+
+// Traces are only read back by the replay, which is in the tests.
+#[cfg_attr(not(test), allow(dead_code))]
+pub mod format;
+#[cfg(test)]
+mod remap;
+#[cfg(test)]
+pub mod replay;
+
+use std::{
+    cell::RefCell,
+    fs::File,
+    io::{self, BufWriter},
+    path::Path
+};
+
+use rlua::{self, Table, ToLua, Value};
+use wayland_client::{Interface, Proxy};
+
+use crate::clock;
+
+pub use self::format::{Arg, Direction, ObjectRef};
+use self::format::{Encoder, Flags, Record};
+
+thread_local! {
+    static RECORDER: RefCell<Option<Recorder>> = RefCell::new(None);
+}
+
+/// What a recording includes.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct Options {
+    /// Whether to record pointer events, which can tell what the user did.
+    pub input: bool,
+    /// Whether to replace strings with their hash.
+    pub redact: bool
+}
+
+/// What a finished recording wrote.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Summary {
+    pub records: u64,
+    pub bytes: u64
+}
+
+struct Recorder {
+    encoder: Encoder<BufWriter<File>>,
+    options: Options,
+    /// The monotonic clock when recording started, in microseconds.
+    started: u64,
+    records: u64
+}
+
+/// Starts recording to `path`, finishing the recording that's running.
+pub fn start(path: &Path, options: Options) -> io::Result<()> {
+    let file = File::create(path)?;
+    let flags = Flags {
+        input: options.input,
+        redacted: options.redact
+    };
+    let recorder = Recorder {
+        encoder: Encoder::new(BufWriter::new(file), flags)?,
+        options,
+        started: clock::monotonic(),
+        records: 0
+    };
+    if let Some(Err(err)) = stop() {
+        warn!("Could not finish the last event trace: {}", err);
+    }
+    RECORDER.with(|cell| *cell.borrow_mut() = Some(recorder));
+    Ok(())
+}
+
+/// Finishes the recording, `None` if there isn't one.
+pub fn stop() -> Option<io::Result<Summary>> {
+    let mut recorder = RECORDER.with(|cell| cell.borrow_mut().take())?;
+    Some(recorder.encoder.flush().map(|()| Summary {
+        records: recorder.records,
+        bytes: recorder.encoder.written()
+    }))
+}
+
+pub fn is_recording() -> bool {
+    RECORDER.with(|cell| cell.borrow().is_some())
+}
+
+/// The interface and id of `proxy`.
+pub fn object<I: Interface>(proxy: &Proxy<I>) -> ObjectRef {
+    ObjectRef {
+        interface: I::NAME.into(),
+        id: proxy.id()
+    }
+}
+
+/// Records the event `message` on `proxy`. `args` is only called while
+/// recording.
+pub fn event<I: Interface, F: FnOnce() -> Vec<Arg>>(proxy: &Proxy<I>, message: &str, args: F) {
+    if is_recording() {
+        record(Direction::Event, object(proxy), message, args(), false)
+    }
+}
+
+/// Records the event `message` on the `I` with `id`, for events that
+/// destroy their object, whose proxy has no id anymore when they're
+/// handled.
+pub fn destructor_event<I: Interface, F: FnOnce() -> Vec<Arg>>(id: u32, message: &str, args: F) {
+    if is_recording() {
+        let object = ObjectRef {
+            interface: I::NAME.into(),
+            id
+        };
+        record(Direction::Event, object, message, args(), false)
+    }
+}
+
+/// Records the input event `message` on `proxy`, if the recording
+/// includes input.
+pub fn input_event<I: Interface, F: FnOnce() -> Vec<Arg>>(proxy: &Proxy<I>, message: &str, args: F) {
+    if is_recording() {
+        record(Direction::Event, object(proxy), message, args(), true)
+    }
+}
+
+/// Records the request `message` on `proxy`.
+pub fn request<I: Interface, F: FnOnce() -> Vec<Arg>>(proxy: &Proxy<I>, message: &str, args: F) {
+    if is_recording() {
+        record(Direction::Request, object(proxy), message, args(), false)
+    }
+}
+
+/// Appends a record, stopping the recording if it can't be written.
+fn record(direction: Direction, object: ObjectRef, message: &str, args: Vec<Arg>, input: bool) {
+    let failed = RECORDER.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        let recorder = match cell.as_mut() {
+            Some(recorder) => recorder,
+            None => return None
+        };
+        if input && !recorder.options.input {
+            return None;
+        }
+        let redact = recorder.options.redact;
+        let args = args
+            .into_iter()
+            .map(|arg| match arg {
+                Arg::Str(string) if redact => Arg::Redacted(format::redact(&string)),
+                arg => arg
+            })
+            .collect();
+        let record = Record {
+            time: clock::monotonic().saturating_sub(recorder.started),
+            direction,
+            object,
+            message: message.into(),
+            args
+        };
+        match recorder.encoder.record(&record) {
+            Ok(()) => {
+                recorder.records += 1;
+                None
+            },
+            Err(err) => {
+                *cell = None;
+                Some(err)
+            }
+        }
+    });
+    if let Some(err) = failed {
+        warn!(
+            "Stopped recording events, the trace could not be written: {}",
+            err
+        );
+    }
+}
+
+/// `awesome.record_events(path, { input = false, redact = true })`, starts
+/// recording a trace to `path`, true or nil and an error.
+pub fn record_events<'lua>(
+    lua: rlua::Context<'lua>,
+    (path, options): (String, Option<Table<'lua>>)
+) -> rlua::Result<(Value<'lua>, Value<'lua>)> {
+    let mut parsed = Options {
+        input: false,
+        redact: true
+    };
+    if let Some(options) = options {
+        parsed.input = options.get::<_, Option<bool>>("input")?.unwrap_or(parsed.input);
+        parsed.redact = options.get::<_, Option<bool>>("redact")?.unwrap_or(parsed.redact);
+    }
+    match start(Path::new(&path), parsed) {
+        Ok(()) => Ok((Value::Boolean(true), Value::Nil)),
+        Err(err) => Ok((Value::Nil, format!("{}: {}", path, err).to_lua(lua)?))
+    }
+}
+
+/// `awesome.stop_recording_events()`, finishes the trace and returns
+/// `{ records = n, bytes = n }`, nil if nothing was being recorded, or nil
+/// and an error.
+pub fn stop_recording_events(lua: rlua::Context, _: ()) -> rlua::Result<(Value, Value)> {
+    match stop() {
+        Some(Ok(summary)) => {
+            let table = lua.create_table()?;
+            table.set("records", summary.records)?;
+            table.set("bytes", summary.bytes)?;
+            Ok((Value::Table(table), Value::Nil))
+        },
+        Some(Err(err)) => Ok((Value::Nil, err.to_string().to_lua(lua)?)),
+        None => Ok((Value::Nil, Value::Nil))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::format::{decode, Trace};
+    use super::replay::{replay, Target, Timing};
+    use super::*;
+
+    use std::{fs, time::Instant};
+
+    fn object(interface: &str, id: u32) -> ObjectRef {
+        ObjectRef {
+            interface: interface.into(),
+            id
+        }
+    }
+
+    /// Draws one layer surface like a drawin does: a buffer the size of
+    /// every configure, destroyed when the compositor releases it.
+    struct Model {
+        next_id: u32,
+        surface: ObjectRef,
+        layer: ObjectRef,
+        pool: ObjectRef,
+        buffers: Vec<ObjectRef>,
+        requests: Vec<Record>
+    }
+
+    impl Model {
+        /// A model whose objects start at `base`, which the globals it uses
+        /// are just below.
+        fn new(base: u32) -> Self {
+            let mut model = Model {
+                next_id: base,
+                surface: object("wl_surface", 0),
+                layer: object("zwlr_layer_surface_v1", 0),
+                pool: object("wl_shm_pool", 0),
+                buffers: Vec::new(),
+                requests: Vec::new()
+            };
+            model.surface = model.create(
+                object("wl_compositor", base - 3),
+                "create_surface",
+                "wl_surface",
+                vec![]
+            );
+            let surface = Arg::Object(Some(model.surface.clone()));
+            let args = vec![
+                surface,
+                Arg::Object(None),
+                Arg::Uint(2),
+                Arg::Str("way-cooler".into()),
+            ];
+            let shell = object("zwlr_layer_shell_v1", base - 2);
+            model.layer = model.create(shell, "get_layer_surface", "zwlr_layer_surface_v1", args);
+            let layer = model.layer.clone();
+            model.request(&layer, "set_size", vec![Arg::Uint(100), Arg::Uint(30)]);
+            let surface = model.surface.clone();
+            model.request(&surface, "commit", vec![]);
+            model.pool = model.create(object("wl_shm", base - 1), "create_pool", "wl_shm_pool", vec![]);
+            model
+        }
+
+        fn request(&mut self, object: &ObjectRef, message: &str, args: Vec<Arg>) {
+            self.requests.push(Record {
+                time: 0,
+                direction: Direction::Request,
+                object: object.clone(),
+                message: message.into(),
+                args
+            })
+        }
+
+        fn create(&mut self, on: ObjectRef, message: &str, interface: &str, mut args: Vec<Arg>) -> ObjectRef {
+            let created = object(interface, self.next_id);
+            self.next_id += 1;
+            args.insert(0, Arg::NewId(created.clone()));
+            self.request(&on, message, args);
+            created
+        }
+    }
+
+    impl Target for Model {
+        fn deliver(&mut self, event: &Record) {
+            let (layer, surface) = (self.layer.clone(), self.surface.clone());
+            match (event.message.as_str(), event.args.as_slice()) {
+                ("configure", [serial, Arg::Uint(width), Arg::Uint(height)]) => {
+                    self.request(&layer, "ack_configure", vec![serial.clone()]);
+                    let (width, height) = (*width as i64, *height as i64);
+                    let args = vec![Arg::Int(width), Arg::Int(height), Arg::Int(width * 4)];
+                    let buffer = self.create(self.pool.clone(), "create_buffer", "wl_buffer", args);
+                    self.buffers.push(buffer.clone());
+                    self.request(&surface, "attach", vec![Arg::Object(Some(buffer))]);
+                    self.request(&surface, "damage", vec![Arg::Int(width), Arg::Int(height)]);
+                    self.request(&surface, "commit", vec![]);
+                },
+                ("release", []) => {
+                    self.buffers.retain(|buffer| *buffer != event.object);
+                    self.request(&event.object, "destroy", vec![]);
+                },
+                ("closed", []) => {
+                    self.request(&layer, "destroy", vec![]);
+                    self.request(&surface, "destroy", vec![]);
+                },
+                _ => {}
+            }
+        }
+
+        fn requests(&mut self) -> Vec<Record> {
+            self.requests.drain(..).collect()
+        }
+    }
+
+    /// Records `model` against a scripted compositor, through the recorder.
+    fn record_session(path: &Path, options: Options, mut model: Model) -> Trace {
+        let record_requests = |model: &mut Model| {
+            for request in model.requests() {
+                record(
+                    Direction::Request,
+                    request.object,
+                    &request.message,
+                    request.args,
+                    false
+                );
+            }
+        };
+        start(path, options).unwrap();
+        record_requests(&mut model);
+        let layer = model.layer.clone();
+        let configure = |serial, width| {
+            (
+                layer.clone(),
+                "configure",
+                vec![Arg::Uint(serial), Arg::Uint(width), Arg::Uint(30)]
+            )
+        };
+        let mut script = vec![configure(1, 100), configure(2, 200), configure(3, 150)];
+        script.push((layer.clone(), "closed", vec![]));
+        for (index, (object, message, args)) in script.into_iter().enumerate() {
+            std::thread::sleep(std::time::Duration::from_millis(5));
+            // The compositor is done with the old buffer once a new one was
+            // committed.
+            if index >= 2 {
+                let buffer = model.buffers[0].clone();
+                record(Direction::Event, buffer.clone(), "release", vec![], false);
+                model.deliver(&Record {
+                    time: 0,
+                    direction: Direction::Event,
+                    object: buffer,
+                    message: "release".into(),
+                    args: vec![]
+                });
+                record_requests(&mut model);
+            }
+            record(Direction::Event, object.clone(), message, args.clone(), false);
+            // Pointer events aren't recorded without consent.
+            record(
+                Direction::Event,
+                object.clone(),
+                "motion",
+                vec![Arg::Fixed(1.5)],
+                true
+            );
+            model.deliver(&Record {
+                time: 0,
+                direction: Direction::Event,
+                object,
+                message: message.into(),
+                args
+            });
+            record_requests(&mut model);
+        }
+        let summary = stop().unwrap().unwrap();
+        assert!(stop().is_none());
+        let bytes = fs::read(path).unwrap();
+        assert_eq!(summary.bytes, bytes.len() as u64);
+        let trace = decode(&bytes).unwrap();
+        assert_eq!(summary.records, trace.records.len() as u64);
+        trace
+    }
+
+    #[test]
+    fn event_trace_replays_recorded_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.trace");
+        let options = Options {
+            input: false,
+            redact: true
+        };
+        let trace = record_session(&path, options, Model::new(10));
+        assert!(trace.records.iter().all(|record| record.message != "motion"));
+        assert!(trace
+            .records
+            .iter()
+            .flat_map(|record| &record.args)
+            .all(|arg| if let Arg::Str(_) = arg { false } else { true }));
+        let events = trace
+            .records
+            .iter()
+            .filter(|record| record.direction == Direction::Event)
+            .count();
+        assert_eq!(events, 6);
+
+        // The replay has other ids, and runs as fast as it can or as the
+        // session did.
+        assert_eq!(replay(&trace, &mut Model::new(300), Timing::FastForward), Ok(()));
+        let started = Instant::now();
+        assert_eq!(replay(&trace, &mut Model::new(60), Timing::Original), Ok(()));
+        let last = trace.records.last().unwrap().time - trace.records[0].time;
+        assert!(started.elapsed().as_micros() as u64 >= last);
+
+        // A client that draws the second configure wrong diverges on the
+        // buffer it creates for it.
+        let second = trace
+            .records
+            .iter()
+            .enumerate()
+            .filter(|(_, record)| record.message == "configure")
+            .map(|(index, _)| index)
+            .nth(1)
+            .unwrap();
+        let mut mutated = trace.clone();
+        mutated.records[second].args[1] = Arg::Uint(201);
+        let divergence = replay(&mutated, &mut Model::new(300), Timing::FastForward).unwrap_err();
+        assert_eq!(divergence.index, second + 2);
+        assert_eq!(divergence.after, Some(second));
+        assert_eq!(divergence.reason, "argument 2 differs");
+        assert_eq!(
+            divergence.to_string(),
+            format!(
+                "record {}: argument 2 differs, \
+                 expected wl_shm_pool@12.create_buffer(new wl_buffer@14, 200, 30, 800), \
+                 got wl_shm_pool@302.create_buffer(new wl_buffer@304, 201, 30, 804), \
+                 after event {}: zwlr_layer_surface_v1@11.configure(2, 201, 30)",
+                second + 2,
+                second
+            )
+        );
+
+        // A client that stops drawing diverges where it would have.
+        let mut lazy = trace.clone();
+        lazy.records.truncate(second + 1);
+        lazy.records.push(trace.records[second + 1].clone());
+        let extra = replay(&lazy, &mut Model::new(300), Timing::FastForward).unwrap_err();
+        assert_eq!(extra.index, second + 2);
+        assert!(extra.expected.is_none());
+    }
+}
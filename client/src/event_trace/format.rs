@@ -0,0 +1,501 @@
+//! The records of a trace and the file they're kept in.
+//!
+//! A trace starts with `MAGIC`, the version and its flags. Every entry after
+//! that starts with a tag: either a name, which gets the next number, or an
+//! event or request. A message is the time since the one before it in
+//! microseconds, the interface and id of its object, its name and its
+//! arguments. Interfaces and message names are given by number, so each is
+//! only written once. Numbers are LEB128 varints, signed ones zigzagged
+//! first, so most of them take a byte or two.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, Write}
+};
+
+/// What every trace starts with.
+pub const MAGIC: &[u8; 8] = b"WCTRACE\0";
+
+/// The version of the format written.
+pub const VERSION: u8 = 1;
+
+const FLAG_INPUT: u8 = 1;
+const FLAG_REDACTED: u8 = 2;
+
+const TAG_NAME: u8 = 0;
+const TAG_EVENT: u8 = 1;
+const TAG_REQUEST: u8 = 2;
+
+const ARG_INT: u8 = 0;
+const ARG_UINT: u8 = 1;
+const ARG_FIXED: u8 = 2;
+const ARG_STR: u8 = 3;
+const ARG_REDACTED: u8 = 4;
+const ARG_NULL: u8 = 5;
+const ARG_OBJECT: u8 = 6;
+const ARG_NEW_ID: u8 = 7;
+
+/// What a trace was recorded with.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct Flags {
+    /// Whether input events were recorded.
+    pub input: bool,
+    /// Whether strings were replaced with their hash.
+    pub redacted: bool
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Direction {
+    /// From the compositor to the client.
+    Event,
+    /// From the client to the compositor.
+    Request
+}
+
+/// A Wayland object, by the interface and id it had in the session.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ObjectRef {
+    pub interface: String,
+    pub id: u32
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arg {
+    Int(i64),
+    Uint(u64),
+    Fixed(f64),
+    Str(String),
+    /// A string that was left out, by its hash, so equal strings still
+    /// compare equal.
+    Redacted(u32),
+    /// An object, or `None` for a null one.
+    Object(Option<ObjectRef>),
+    /// An object the message creates.
+    NewId(ObjectRef)
+}
+
+/// A message of a trace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// Microseconds since the trace started.
+    pub time: u64,
+    pub direction: Direction,
+    pub object: ObjectRef,
+    pub message: String,
+    pub args: Vec<Arg>
+}
+
+/// A decoded trace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace {
+    pub flags: Flags,
+    pub records: Vec<Record>
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum FormatErrorKind {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    UnknownTag(u8),
+    UnknownName(u64),
+    BadString,
+    OutOfRange
+}
+
+/// Why a trace couldn't be read, and at which byte.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FormatError {
+    pub offset: usize,
+    pub kind: FormatErrorKind
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::FormatErrorKind::*;
+        match self.kind {
+            BadMagic => write!(f, "not an event trace")?,
+            UnsupportedVersion(version) => write!(f, "unsupported trace version {}", version)?,
+            Truncated => write!(f, "the trace is truncated")?,
+            UnknownTag(tag) => write!(f, "unknown tag {}", tag)?,
+            UnknownName(index) => write!(f, "name {} was never defined", index)?,
+            BadString => write!(f, "a string is not UTF-8")?,
+            OutOfRange => write!(f, "a number is out of range")?
+        }
+        write!(f, " at byte {}", self.offset)
+    }
+}
+
+/// The hash a redacted string is replaced with, 32 bit FNV-1a.
+pub fn redact(string: &str) -> u32 {
+    string.bytes().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Writes records to a trace.
+pub struct Encoder<W: Write> {
+    out: W,
+    names: HashMap<String, u64>,
+    last_time: u64,
+    /// How many bytes were written.
+    written: u64
+}
+
+impl<W: Write> Encoder<W> {
+    /// Starts a trace in `out`.
+    pub fn new(mut out: W, flags: Flags) -> io::Result<Self> {
+        let mut header = MAGIC.to_vec();
+        header.push(VERSION);
+        header
+            .push(if flags.input { FLAG_INPUT } else { 0 } | if flags.redacted { FLAG_REDACTED } else { 0 });
+        out.write_all(&header)?;
+        Ok(Encoder {
+            out,
+            names: HashMap::new(),
+            last_time: 0,
+            written: header.len() as u64
+        })
+    }
+
+    /// Appends `record`. Records must be appended in the order of their
+    /// times, a record older than the one before is written with its time.
+    pub fn record(&mut self, record: &Record) -> io::Result<()> {
+        let interface = self.name(&record.object.interface);
+        let message = self.name(&record.message);
+        let mut bytes = Vec::new();
+        bytes.push(match record.direction {
+            Direction::Event => TAG_EVENT,
+            Direction::Request => TAG_REQUEST
+        });
+        let time = record.time.max(self.last_time);
+        write_varint(&mut bytes, time - self.last_time);
+        self.last_time = time;
+        write_varint(&mut bytes, interface);
+        write_varint(&mut bytes, u64::from(record.object.id));
+        write_varint(&mut bytes, message);
+        write_varint(&mut bytes, record.args.len() as u64);
+        for arg in &record.args {
+            match arg {
+                Arg::Int(value) => {
+                    bytes.push(ARG_INT);
+                    write_varint(&mut bytes, zigzag(*value));
+                },
+                Arg::Uint(value) => {
+                    bytes.push(ARG_UINT);
+                    write_varint(&mut bytes, *value);
+                },
+                Arg::Fixed(value) => {
+                    bytes.push(ARG_FIXED);
+                    bytes.extend_from_slice(&value.to_bits().to_le_bytes());
+                },
+                Arg::Str(value) => {
+                    bytes.push(ARG_STR);
+                    write_string(&mut bytes, value);
+                },
+                Arg::Redacted(hash) => {
+                    bytes.push(ARG_REDACTED);
+                    write_varint(&mut bytes, u64::from(*hash));
+                },
+                Arg::Object(None) => bytes.push(ARG_NULL),
+                Arg::Object(Some(object)) | Arg::NewId(object) => {
+                    let interface = self.name(&object.interface);
+                    bytes.push(if let Arg::NewId(_) = arg {
+                        ARG_NEW_ID
+                    } else {
+                        ARG_OBJECT
+                    });
+                    write_varint(&mut bytes, interface);
+                    write_varint(&mut bytes, u64::from(object.id));
+                }
+            }
+        }
+        self.out.write_all(&bytes)?;
+        self.written += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// How many bytes were written so far.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    /// The number of `name`, writing its definition if it's new. Records
+    /// are built before they're written, so the definitions of the names
+    /// they use come first.
+    fn name(&mut self, name: &str) -> u64 {
+        if let Some(index) = self.names.get(name) {
+            return *index;
+        }
+        let index = self.names.len() as u64;
+        self.names.insert(name.to_string(), index);
+        let mut definition = vec![TAG_NAME];
+        write_string(&mut definition, name);
+        // A failed write shows up when the record is written.
+        if self.out.write_all(&definition).is_ok() {
+            self.written += definition.len() as u64;
+        }
+        index
+    }
+}
+
+/// Reads a whole trace.
+pub fn decode(bytes: &[u8]) -> Result<Trace, FormatError> {
+    let mut reader = Reader { bytes, offset: 0 };
+    if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return Err(FormatError {
+            offset: 0,
+            kind: FormatErrorKind::BadMagic
+        });
+    }
+    let version = reader.byte()?;
+    if version != VERSION {
+        return Err(reader.error(FormatErrorKind::UnsupportedVersion(version)));
+    }
+    let flags = reader.byte()?;
+    let flags = Flags {
+        input: flags & FLAG_INPUT != 0,
+        redacted: flags & FLAG_REDACTED != 0
+    };
+    let mut names = Vec::new();
+    let mut records = Vec::new();
+    let mut time = 0u64;
+    while reader.offset < bytes.len() {
+        let direction = match reader.byte()? {
+            TAG_NAME => {
+                names.push(reader.string()?);
+                continue;
+            },
+            TAG_EVENT => Direction::Event,
+            TAG_REQUEST => Direction::Request,
+            tag => return Err(reader.error(FormatErrorKind::UnknownTag(tag)))
+        };
+        time = time
+            .checked_add(reader.varint()?)
+            .ok_or_else(|| reader.error(FormatErrorKind::OutOfRange))?;
+        let object = reader.object(&names)?;
+        let message = reader.name(&names)?;
+        let count = reader.varint()?;
+        let mut args = Vec::new();
+        for _ in 0..count {
+            args.push(match reader.byte()? {
+                ARG_INT => Arg::Int(unzigzag(reader.varint()?)),
+                ARG_UINT => Arg::Uint(reader.varint()?),
+                ARG_FIXED => {
+                    let mut bits = [0; 8];
+                    bits.copy_from_slice(reader.take(8)?);
+                    Arg::Fixed(f64::from_bits(u64::from_le_bytes(bits)))
+                },
+                ARG_STR => Arg::Str(reader.string()?),
+                ARG_REDACTED => Arg::Redacted(reader.u32()?),
+                ARG_NULL => Arg::Object(None),
+                ARG_OBJECT => Arg::Object(Some(reader.object(&names)?)),
+                ARG_NEW_ID => Arg::NewId(reader.object(&names)?),
+                tag => return Err(reader.error(FormatErrorKind::UnknownTag(tag)))
+            });
+        }
+        records.push(Record {
+            time,
+            direction,
+            object,
+            message,
+            args
+        });
+    }
+    Ok(Trace { flags, records })
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize
+}
+
+impl<'a> Reader<'a> {
+    fn error(&self, kind: FormatErrorKind) -> FormatError {
+        FormatError {
+            offset: self.offset,
+            kind
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], FormatError> {
+        if self.bytes.len() - self.offset < len {
+            return Err(self.error(FormatErrorKind::Truncated));
+        }
+        let bytes = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, FormatError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<u64, FormatError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(self.error(FormatErrorKind::OutOfRange))
+    }
+
+    fn u32(&mut self) -> Result<u32, FormatError> {
+        let value = self.varint()?;
+        if value > u64::from(u32::max_value()) {
+            return Err(self.error(FormatErrorKind::OutOfRange));
+        }
+        Ok(value as u32)
+    }
+
+    fn string(&mut self) -> Result<String, FormatError> {
+        let len = self.varint()?;
+        if len > (self.bytes.len() - self.offset) as u64 {
+            return Err(self.error(FormatErrorKind::Truncated));
+        }
+        let start = self.offset;
+        let bytes = self.take(len as usize)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| FormatError {
+            offset: start,
+            kind: FormatErrorKind::BadString
+        })
+    }
+
+    fn name(&mut self, names: &[String]) -> Result<String, FormatError> {
+        let index = self.varint()?;
+        names
+            .get(index as usize)
+            .cloned()
+            .ok_or_else(|| self.error(FormatErrorKind::UnknownName(index)))
+    }
+
+    fn object(&mut self, names: &[String]) -> Result<ObjectRef, FormatError> {
+        Ok(ObjectRef {
+            interface: self.name(names)?,
+            id: self.u32()?
+        })
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn write_string(out: &mut Vec<u8>, string: &str) {
+    write_varint(out, string.len() as u64);
+    out.extend_from_slice(string.as_bytes());
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn object(interface: &str, id: u32) -> ObjectRef {
+        ObjectRef {
+            interface: interface.into(),
+            id
+        }
+    }
+
+    #[test]
+    fn event_trace_format_round_trip() {
+        let records = vec![
+            Record {
+                time: 5,
+                direction: Direction::Request,
+                object: object("zwlr_layer_shell_v1", 4),
+                message: "get_layer_surface".into(),
+                args: vec![
+                    Arg::NewId(object("zwlr_layer_surface_v1", 9)),
+                    Arg::Object(Some(object("wl_surface", 8))),
+                    Arg::Object(None),
+                    Arg::Uint(2),
+                    Arg::Str("way-cooler".into()),
+                ]
+            },
+            Record {
+                time: 300_000,
+                direction: Direction::Event,
+                object: object("zwlr_layer_surface_v1", 9),
+                message: "configure".into(),
+                args: vec![
+                    Arg::Uint(1 << 40),
+                    Arg::Int(-1),
+                    Arg::Int(i64::min_value()),
+                    Arg::Fixed(12.5),
+                ]
+            },
+            Record {
+                time: 300_000,
+                direction: Direction::Event,
+                object: object("wl_output", 3),
+                message: "geometry".into(),
+                args: vec![Arg::Redacted(redact("Dell")), Arg::Str(String::new())]
+            },
+        ];
+        let mut encoder = Encoder::new(
+            Vec::new(),
+            Flags {
+                input: false,
+                redacted: true
+            }
+        )
+        .unwrap();
+        for record in &records {
+            encoder.record(record).unwrap();
+        }
+        let written = encoder.written();
+        let bytes = encoder.into_inner();
+        assert_eq!(written, bytes.len() as u64);
+        let trace = decode(&bytes).unwrap();
+        assert_eq!(trace.records, records);
+        assert!(trace.flags.redacted && !trace.flags.input);
+        assert_eq!(redact("Dell"), redact("Dell"));
+        assert_ne!(redact("Dell"), redact("LG"));
+        // Names are only written once.
+        let count = |needle: &[u8]| {
+            bytes
+                .windows(needle.len())
+                .filter(|window| *window == needle)
+                .count()
+        };
+        assert_eq!(count(b"zwlr_layer_surface_v1"), 1);
+
+        for len in MAGIC.len() + 2..bytes.len() {
+            if let Err(err) = decode(&bytes[..len]) {
+                assert_eq!(err.kind, FormatErrorKind::Truncated, "at {}", len);
+            }
+        }
+        assert_eq!(decode(b"PNG").unwrap_err().kind, FormatErrorKind::BadMagic);
+        let mut future = bytes.clone();
+        future[MAGIC.len()] = VERSION + 1;
+        assert_eq!(
+            decode(&future).unwrap_err().to_string(),
+            format!("unsupported trace version {} at byte 9", VERSION + 1)
+        );
+    }
+}
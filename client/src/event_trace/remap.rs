@@ -0,0 +1,181 @@
+//! Pairs the objects of a recording with the objects of its replay.
+//!
+//! A replay creates its own objects, which get other ids than they had in
+//! the recording. Objects are paired when the replay makes the request that
+//! created them in the recording, or when a request of the replay first
+//! uses one, and the pair is dropped when the recording destroys it, as its
+//! id can be used again after that.
+
+use std::collections::HashMap;
+
+use super::format::{redact, Arg, ObjectRef, Record};
+
+#[derive(Debug, Default)]
+pub struct Remap {
+    /// From the recording to the replay.
+    forward: HashMap<ObjectRef, ObjectRef>,
+    /// From the replay to the recording.
+    backward: HashMap<ObjectRef, ObjectRef>
+}
+
+impl Remap {
+    /// Pairs `recorded` with `replayed`, unless either is paired with
+    /// another object or they're of different interfaces.
+    pub fn pair(&mut self, recorded: &ObjectRef, replayed: &ObjectRef) -> bool {
+        if recorded.interface != replayed.interface {
+            return false;
+        }
+        match (self.forward.get(recorded), self.backward.get(replayed)) {
+            (Some(paired), _) => paired == replayed,
+            (None, Some(_)) => false,
+            (None, None) => {
+                self.forward.insert(recorded.clone(), replayed.clone());
+                self.backward.insert(replayed.clone(), recorded.clone());
+                true
+            }
+        }
+    }
+
+    /// Drops the pair of `recorded`.
+    pub fn forget(&mut self, recorded: &ObjectRef) {
+        if let Some(replayed) = self.forward.remove(recorded) {
+            self.backward.remove(&replayed);
+        }
+    }
+
+    /// The object of the replay `recorded` is paired with.
+    pub fn replayed(&self, recorded: &ObjectRef) -> Option<&ObjectRef> {
+        self.forward.get(recorded)
+    }
+
+    /// The recorded `event` with the objects of the replay, or the first of
+    /// its objects that isn't paired.
+    pub fn event(&self, event: &Record) -> Result<Record, ObjectRef> {
+        let translate = |object: &ObjectRef| self.replayed(object).cloned().ok_or_else(|| object.clone());
+        let mut args = Vec::with_capacity(event.args.len());
+        for arg in &event.args {
+            args.push(match arg {
+                Arg::Object(Some(object)) => Arg::Object(Some(translate(object)?)),
+                // Objects the compositor creates keep their ids, the replay
+                // is expected to give them the recorded ones.
+                arg => arg.clone()
+            });
+        }
+        Ok(Record {
+            object: translate(&event.object)?,
+            args,
+            ..event.clone()
+        })
+    }
+
+    /// Checks that `replayed` is the request `recorded`, pairing the objects
+    /// it uses and creates, or why it isn't.
+    pub fn request(&mut self, recorded: &Record, replayed: &Record) -> Result<(), String> {
+        if recorded.object.interface != replayed.object.interface || recorded.message != replayed.message {
+            return Err(format!(
+                "expected a {}.{} request",
+                recorded.object.interface, recorded.message
+            ));
+        }
+        if !self.pair(&recorded.object, &replayed.object) {
+            return Err(format!("the request is on another {}", recorded.object.interface));
+        }
+        if recorded.args.len() != replayed.args.len() {
+            return Err(format!(
+                "expected {} arguments, got {}",
+                recorded.args.len(),
+                replayed.args.len()
+            ));
+        }
+        for (index, (expected, found)) in recorded.args.iter().zip(&replayed.args).enumerate() {
+            let same = match (expected, found) {
+                (Arg::Object(Some(expected)), Arg::Object(Some(found))) |
+                (Arg::NewId(expected), Arg::NewId(found)) => self.pair(expected, found),
+                // A redacted recording is replayed with the real strings.
+                (Arg::Redacted(hash), Arg::Str(found)) => redact(found) == *hash,
+                (expected, found) => expected == found
+            };
+            if !same {
+                return Err(format!("argument {} differs", index + 1));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::format::Direction;
+    use super::*;
+
+    fn object(interface: &str, id: u32) -> ObjectRef {
+        ObjectRef {
+            interface: interface.into(),
+            id
+        }
+    }
+
+    fn request(object: ObjectRef, message: &str, args: Vec<Arg>) -> Record {
+        Record {
+            time: 0,
+            direction: Direction::Request,
+            object,
+            message: message.into(),
+            args
+        }
+    }
+
+    #[test]
+    fn event_trace_remap_pairs_objects() {
+        let mut remap = Remap::default();
+        let shell = (
+            object("zwlr_layer_shell_v1", 5),
+            object("zwlr_layer_shell_v1", 12)
+        );
+        let surface = (object("wl_surface", 8), object("wl_surface", 40));
+        let layer = (
+            object("zwlr_layer_surface_v1", 9),
+            object("zwlr_layer_surface_v1", 41)
+        );
+        let create = |shell: &ObjectRef, surface: &ObjectRef, layer: &ObjectRef| {
+            request(
+                shell.clone(),
+                "get_layer_surface",
+                vec![Arg::NewId(layer.clone()), Arg::Object(Some(surface.clone()))]
+            )
+        };
+        assert_eq!(
+            remap.request(
+                &create(&shell.0, &surface.0, &layer.0),
+                &create(&shell.1, &surface.1, &layer.1)
+            ),
+            Ok(())
+        );
+        assert_eq!(remap.replayed(&layer.0), Some(&layer.1));
+
+        let configure = Record {
+            direction: Direction::Event,
+            ..request(layer.0.clone(), "configure", vec![Arg::Uint(1), Arg::Uint(100)])
+        };
+        assert_eq!(remap.event(&configure).unwrap().object, layer.1);
+
+        // Once paired an object can't stand in for another one.
+        let other = object("wl_surface", 41);
+        let attach = |surface: &ObjectRef| request(surface.clone(), "commit", vec![]);
+        assert_eq!(
+            remap.request(&attach(&surface.0), &attach(&other)),
+            Err("the request is on another wl_surface".into())
+        );
+        assert_eq!(
+            remap.request(
+                &request(layer.0.clone(), "set_size", vec![Arg::Uint(100), Arg::Uint(30)]),
+                &request(layer.1.clone(), "set_size", vec![Arg::Uint(100), Arg::Uint(31)])
+            ),
+            Err("argument 2 differs".into())
+        );
+
+        remap.forget(&layer.0);
+        assert_eq!(remap.event(&configure), Err(layer.0.clone()));
+        assert!(remap.pair(&layer.0, &object("zwlr_layer_surface_v1", 41)));
+    }
+}
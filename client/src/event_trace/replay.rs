@@ -0,0 +1,177 @@
+//! Plays the events of a trace to a target and checks that the requests it
+//! makes in return are the recorded ones.
+//!
+//! The requests made after an event are expected before the next event is
+//! delivered, in the order they were recorded in. The first request that
+//! differs, is missing or wasn't recorded is the divergence, reported with
+//! the last event delivered before it, which is usually what the target
+//! handled differently.
+
+use std::{
+    collections::VecDeque,
+    fmt, thread,
+    time::{Duration, Instant}
+};
+
+use super::format::{Arg, Direction, Record, Trace};
+use super::remap::Remap;
+
+/// What a trace is replayed to, e.g. a model of the client.
+pub trait Target {
+    /// Handles `event`, which is on the objects of the replay.
+    fn deliver(&mut self, event: &Record);
+
+    /// The requests made since this was last called, in order.
+    fn requests(&mut self) -> Vec<Record>;
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Timing {
+    /// Events are delivered as far apart as they were recorded.
+    Original,
+    /// Events are delivered as fast as the target handles them.
+    FastForward
+}
+
+/// Where a replay first differed from its recording.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// The index of the record that differed, the length of the trace when
+    /// the replay made requests after the recording ended.
+    pub index: usize,
+    /// The recorded request, unless the replay made one that wasn't.
+    pub expected: Option<Record>,
+    /// The request of the replay, unless it didn't make one.
+    pub found: Option<Record>,
+    /// The index of the last event delivered before the divergence.
+    pub after: Option<usize>,
+    /// The recorded event, if there is one.
+    pub event: Option<Record>,
+    pub reason: String
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "record {}: {}", self.index, self.reason)?;
+        if let Some(expected) = &self.expected {
+            write!(f, ", expected {}", Message(expected))?;
+        }
+        match (&self.expected, &self.found) {
+            (_, Some(found)) => write!(f, ", got {}", Message(found))?,
+            (Some(_), None) => write!(f, ", got nothing")?,
+            (None, None) => {}
+        }
+        match (self.after, &self.event) {
+            (Some(index), Some(event)) => write!(f, ", after event {}: {}", index, Message(event)),
+            _ => write!(f, ", before the first event")
+        }
+    }
+}
+
+/// Formats a record like `zwlr_layer_surface_v1@9.set_size(100, 30)`.
+pub struct Message<'a>(pub &'a Record);
+
+impl<'a> fmt::Display for Message<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let record = self.0;
+        write!(
+            f,
+            "{}@{}.{}(",
+            record.object.interface, record.object.id, record.message
+        )?;
+        for (index, arg) in record.args.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            match arg {
+                Arg::Int(value) => write!(f, "{}", value)?,
+                Arg::Uint(value) => write!(f, "{}", value)?,
+                Arg::Fixed(value) => write!(f, "{}", value)?,
+                Arg::Str(value) => write!(f, "{:?}", value)?,
+                Arg::Redacted(hash) => write!(f, "<redacted {:08x}>", hash)?,
+                Arg::Object(None) => write!(f, "null")?,
+                Arg::Object(Some(object)) => write!(f, "{}@{}", object.interface, object.id)?,
+                Arg::NewId(object) => write!(f, "new {}@{}", object.interface, object.id)?
+            }
+        }
+        write!(f, ")")
+    }
+}
+
+/// Replays `trace` to `target`, which has made the requests it makes
+/// before the first event by now.
+pub fn replay<T: Target>(trace: &Trace, target: &mut T, timing: Timing) -> Result<(), Divergence> {
+    let records = &trace.records;
+    let mut remap = Remap::default();
+    let mut pending = VecDeque::from(target.requests());
+    let mut after = None;
+    let started = Instant::now();
+    let first = records.first().map(|record| record.time).unwrap_or(0);
+    let divergence =
+        |index, expected: Option<&Record>, found, after: Option<usize>, reason: String| Divergence {
+            index,
+            expected: expected.cloned(),
+            found,
+            after,
+            event: after.map(|index| records[index].clone()),
+            reason
+        };
+    for (index, record) in records.iter().enumerate() {
+        match record.direction {
+            Direction::Request => {
+                if pending.is_empty() {
+                    pending.extend(target.requests());
+                }
+                let found = match pending.pop_front() {
+                    Some(found) => found,
+                    None => {
+                        let reason = "the replay made no request".to_string();
+                        return Err(divergence(index, Some(record), None, after, reason));
+                    }
+                };
+                if let Err(reason) = remap.request(record, &found) {
+                    return Err(divergence(index, Some(record), Some(found), after, reason));
+                }
+                if record.message == "destroy" {
+                    remap.forget(&record.object);
+                }
+            },
+            Direction::Event => {
+                pending.extend(target.requests());
+                if let Some(found) = pending.pop_front() {
+                    let reason = "the replay made a request that wasn't recorded".to_string();
+                    return Err(divergence(index, None, Some(found), after, reason));
+                }
+                let event = remap.event(record).map_err(|object| {
+                    let reason = format!(
+                        "the event is on {}@{}, which the replay didn't create",
+                        object.interface, object.id
+                    );
+                    divergence(index, None, None, after, reason)
+                })?;
+                if timing == Timing::Original {
+                    let due = started + Duration::from_micros(record.time - first);
+                    let now = Instant::now();
+                    if due > now {
+                        thread::sleep(due - now);
+                    }
+                }
+                target.deliver(&event);
+                // The callback is gone once it's done, its id can be used
+                // again.
+                if record.object.interface == "wl_callback" {
+                    remap.forget(&record.object);
+                }
+                after = Some(index);
+            }
+        }
+    }
+    pending.extend(target.requests());
+    match pending.pop_front() {
+        Some(found) => {
+            let reason = "the replay made a request after the recording ended".to_string();
+            Err(divergence(records.len(), None, Some(found), after, reason))
+        },
+        None => Ok(())
+    }
+}
@@ -0,0 +1,626 @@
+//! `awesome.json_decode` and `awesome.json_encode`, for the data widgets get
+//! from web APIs and command line tools.
+//!
+//! Arrays and objects are both tables in Lua, so decoded ones have a
+//! metatable saying which they were, and `awesome.json_array` and
+//! `awesome.json_object` mark tables for encoding the same way. That keeps
+//! `[]` and `{}` apart when data is read and written back. JSON's null is
+//! `awesome.json_null`, since a table can't hold nil.
+//!
+//! Integers and floats are kept apart too: `1` is decoded to a Lua integer
+//! and `1.0` to a float, and each is encoded like it was decoded.
+
+mod parse;
+mod write;
+
+use std::fmt;
+
+use rlua::{self, MultiValue, Table, Value};
+
+use self::parse::{parse, Options, MAX_DEPTH};
+use self::write::{write, Format};
+
+/// The field of a metatable that says what a table is encoded as: "array",
+/// "object" or "null".
+const MARKER: &str = "__json";
+
+/// The registry value holding `awesome.json_null`.
+const NULL_HANDLE: &str = "__json_null";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    /// Always finite.
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    /// The members in the order they were read, duplicate keys and all.
+    Object(Vec<(String, Json)>)
+}
+
+impl Json {
+    /// Parses `input` with the default limits.
+    pub fn parse(input: &[u8]) -> Result<Json, JsonError> {
+        parse(input, &Options::default())
+    }
+
+    /// The member `key` of an object, the last one if it's there more than
+    /// once, like Lua sees it.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members
+                .iter()
+                .rev()
+                .find(|(name, _)| name == key)
+                .map(|(_, member)| member),
+            _ => None
+        }
+    }
+
+    /// The value like `awesome.json_decode` returns it, except that null is
+    /// nil.
+    pub fn into_lua(self, lua: rlua::Context) -> rlua::Result<Value> {
+        if self == Json::Null {
+            return Ok(Value::Nil);
+        }
+        let markers = Markers {
+            null: null(lua)?,
+            array: marker(lua, "array")?,
+            object: marker(lua, "object")?
+        };
+        to_lua(lua, self, &markers)
+    }
+
+    /// A Lua value encoded like `awesome.json_encode` does.
+    pub fn from_lua(value: Value) -> Result<Json, JsonError> {
+        from_lua(value, 0, Options::default().max_depth)
+    }
+}
+
+/// The value as compact JSON text, on one line.
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&write(self, &Format::default()))
+    }
+}
+
+/// Where in the input an error is, counting lines and columns from 1 and
+/// bytes from 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize
+}
+
+impl Position {
+    /// The position of `offset`, of which all of `input` before it has to
+    /// be UTF-8. The column counts characters, not bytes.
+    fn at(input: &[u8], offset: usize) -> Position {
+        let before = &input[..offset];
+        let line_start = before
+            .iter()
+            .rposition(|&byte| byte == b'\n')
+            .map_or(0, |i| i + 1);
+        Position {
+            offset,
+            line: before.iter().filter(|&&byte| byte == b'\n').count() + 1,
+            column: before[line_start..]
+                .iter()
+                .filter(|&&byte| byte & 0xc0 != 0x80)
+                .count() +
+                1
+        }
+    }
+}
+
+/// Why a value couldn't be decoded or encoded.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonError {
+    /// The input isn't JSON.
+    Syntax { message: String, position: Position },
+    /// The input isn't UTF-8.
+    Utf8(Position),
+    /// Arrays and objects are nested deeper than allowed.
+    TooDeep { limit: usize, position: Position },
+    /// The input is bigger than allowed.
+    TooLarge { size: usize, limit: usize },
+    /// A value can't be written as JSON, at a path like `$.items[3]` that
+    /// indexes tables like Lua does.
+    Unencodable { path: String, message: String }
+}
+
+impl JsonError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            JsonError::Syntax { .. } => "syntax",
+            JsonError::Utf8(_) => "utf8",
+            JsonError::TooDeep { .. } => "too_deep",
+            JsonError::TooLarge { .. } => "too_large",
+            JsonError::Unencodable { .. } => "unencodable"
+        }
+    }
+
+    fn position(&self) -> Option<Position> {
+        match *self {
+            JsonError::Syntax { position, .. } |
+            JsonError::Utf8(position) |
+            JsonError::TooDeep { position, .. } => Some(position),
+            _ => None
+        }
+    }
+
+    /// Puts the path of an unencodable value under the key or index of the
+    /// table it's in.
+    fn within(self, key: &Key) -> Self {
+        match self {
+            JsonError::Unencodable { path, message } => JsonError::Unencodable {
+                path: format!("{}{}", key, path),
+                message
+            },
+            err => err
+        }
+    }
+
+    /// The error as a table like `{ kind = "syntax", message = "...", offset
+    /// = 12, line = 2, column = 5 }`. The offset is of the byte the error is
+    /// at, counting from 1 like `string.sub` does, and only errors in the
+    /// input have one. Unencodable values have the `path` to them instead.
+    pub fn to_lua<'lua>(&self, lua: rlua::Context<'lua>) -> rlua::Result<Table<'lua>> {
+        let table = lua.create_table()?;
+        table.set("kind", self.kind())?;
+        table.set("message", self.to_string())?;
+        if let Some(position) = self.position() {
+            table.set("offset", position.offset + 1)?;
+            table.set("line", position.line)?;
+            table.set("column", position.column)?;
+        }
+        if let JsonError::Unencodable { path, .. } = self {
+            table.set("path", format!("${}", path))?;
+        }
+        Ok(table)
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(Position { line, column, .. }) = self.position() {
+            write!(f, "{}:{}: ", line, column)?;
+        }
+        match self {
+            JsonError::Syntax { message, .. } => write!(f, "{}", message),
+            JsonError::Utf8(position) => write!(f, "invalid UTF-8 at byte {}", position.offset + 1),
+            JsonError::TooDeep { limit, .. } => write!(f, "nested deeper than {} levels", limit),
+            JsonError::TooLarge { size, limit } => {
+                write!(f, "the input is {} bytes, more than the limit of {}", size, limit)
+            },
+            JsonError::Unencodable { path, message } => write!(f, "${}: {}", path, message)
+        }
+    }
+}
+
+/// A key of a table being encoded, for the path of an unencodable value.
+enum Key {
+    Index(i64),
+    Name(String)
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Key::Index(i) => write!(f, "[{}]", i),
+            Key::Name(name) => {
+                let mut chars = name.chars();
+                let identifier = chars
+                    .next()
+                    .map_or(false, |c| c == '_' || c.is_ascii_alphabetic()) &&
+                    chars.all(|c| c == '_' || c.is_ascii_alphanumeric());
+                if identifier {
+                    write!(f, ".{}", name)
+                } else {
+                    write!(f, "[{:?}]", name)
+                }
+            }
+        }
+    }
+}
+
+/// The metatable that marks tables as `kind`.
+fn marker<'lua>(lua: rlua::Context<'lua>, kind: &str) -> rlua::Result<Table<'lua>> {
+    let name = format!("__json_{}_marker", kind);
+    if let Some(metatable) = lua.named_registry_value::<str, Option<Table>>(&name)? {
+        return Ok(metatable);
+    }
+    let metatable = lua.create_table()?;
+    metatable.set(MARKER, kind)?;
+    lua.set_named_registry_value(&name, metatable.clone())?;
+    Ok(metatable)
+}
+
+/// What the metatable of `table` marks it as, if anything.
+fn marked(table: &Table) -> Option<String> {
+    table
+        .get_metatable()
+        .and_then(|metatable| metatable.raw_get::<_, Option<String>>(MARKER).ok())
+        .and_then(|kind| kind)
+}
+
+/// `awesome.json_null`, which can't be changed.
+pub fn null(lua: rlua::Context) -> rlua::Result<Table> {
+    if let Some(null) = lua.named_registry_value::<str, Option<Table>>(NULL_HANDLE)? {
+        return Ok(null);
+    }
+    let metatable = marker(lua, "null")?;
+    metatable.set("__tostring", lua.create_function(|_, _: Value| Ok("null"))?)?;
+    metatable.set(
+        "__newindex",
+        lua.create_function(|_, _: MultiValue| -> rlua::Result<()> {
+            Err(rlua::Error::RuntimeError(
+                "awesome.json_null can't be changed".into()
+            ))
+        })?
+    )?;
+    metatable.set("__metatable", false)?;
+    let null = lua.create_table()?;
+    null.set_metatable(Some(metatable));
+    lua.set_named_registry_value(NULL_HANDLE, null.clone())?;
+    Ok(null)
+}
+
+/// `awesome.json_array(table)`, which marks the table, or a new one, to be
+/// encoded as an array and returns it.
+pub fn json_array<'lua>(lua: rlua::Context<'lua>, table: Option<Table<'lua>>) -> rlua::Result<Table<'lua>> {
+    mark(lua, table, "array")
+}
+
+/// `awesome.json_object(table)`, which marks the table, or a new one, to be
+/// encoded as an object and returns it.
+pub fn json_object<'lua>(lua: rlua::Context<'lua>, table: Option<Table<'lua>>) -> rlua::Result<Table<'lua>> {
+    mark(lua, table, "object")
+}
+
+fn mark<'lua>(lua: rlua::Context<'lua>, table: Option<Table<'lua>>, kind: &str) -> rlua::Result<Table<'lua>> {
+    let table = match table {
+        Some(table) => table,
+        None => lua.create_table()?
+    };
+    table.set_metatable(Some(marker(lua, kind)?));
+    Ok(table)
+}
+
+/// `awesome.json_decode(string, options)`, which returns the value or nil
+/// and the error.
+///
+/// The options are `max_size`, how many bytes the string may have (16 MiB
+/// by default), `max_depth`, how deep arrays and objects may be nested
+/// (256 by default, at most 1024), and `big_integers_as_strings`, to get
+/// integers beyond 2^53 as strings instead of integers or floats.
+pub fn json_decode<'lua>(
+    lua: rlua::Context<'lua>,
+    (input, options): (rlua::String<'lua>, Option<Table<'lua>>)
+) -> rlua::Result<(Value<'lua>, Value<'lua>)> {
+    let options = decode_options(options)?;
+    match parse(input.as_bytes(), &options) {
+        Ok(json) => {
+            let markers = Markers {
+                null: null(lua)?,
+                array: marker(lua, "array")?,
+                object: marker(lua, "object")?
+            };
+            Ok((to_lua(lua, json, &markers)?, Value::Nil))
+        },
+        Err(err) => Ok((Value::Nil, Value::Table(err.to_lua(lua)?)))
+    }
+}
+
+/// `awesome.json_encode(value, options)`, which returns the JSON text or
+/// nil and the error.
+///
+/// Tables are encoded as arrays when they're marked as one or their keys
+/// are exactly 1 to n, and as objects otherwise, with integer keys written
+/// as strings. An empty table that isn't marked is an object. The options
+/// are `pretty`, to indent by two spaces, `sort_keys`, and `max_depth`,
+/// which also catches tables that contain themselves.
+pub fn json_encode<'lua>(
+    lua: rlua::Context<'lua>,
+    (value, options): (Value<'lua>, Option<Table<'lua>>)
+) -> rlua::Result<(Value<'lua>, Value<'lua>)> {
+    let mut format = Format::default();
+    let mut limit = Options::default().max_depth;
+    if let Some(options) = options {
+        format.pretty = options.get::<_, Option<bool>>("pretty")?.unwrap_or(false);
+        format.sort_keys = options.get::<_, Option<bool>>("sort_keys")?.unwrap_or(false);
+        limit = max_depth(&options, "awesome.json_encode")?.unwrap_or(limit);
+    }
+    match from_lua(value, 0, limit) {
+        Ok(json) => Ok((
+            Value::String(lua.create_string(&write(&json, &format))?),
+            Value::Nil
+        )),
+        Err(err) => Ok((Value::Nil, Value::Table(err.to_lua(lua)?)))
+    }
+}
+
+fn decode_options(options: Option<Table>) -> rlua::Result<Options> {
+    let mut decoded = Options::default();
+    let options = match options {
+        Some(options) => options,
+        None => return Ok(decoded)
+    };
+    if let Some(max_size) = options.get::<_, Option<usize>>("max_size")? {
+        decoded.max_size = max_size;
+    }
+    if let Some(max_depth) = max_depth(&options, "awesome.json_decode")? {
+        decoded.max_depth = max_depth;
+    }
+    decoded.big_integers_as_strings = options
+        .get::<_, Option<bool>>("big_integers_as_strings")?
+        .unwrap_or(false);
+    Ok(decoded)
+}
+
+fn max_depth(options: &Table, function: &str) -> rlua::Result<Option<usize>> {
+    match options.get::<_, Option<usize>>("max_depth")? {
+        Some(depth) if depth == 0 || depth > MAX_DEPTH => Err(rlua::Error::RuntimeError(format!(
+            "{}: the depth limit must be from 1 to {}, got {}",
+            function, MAX_DEPTH, depth
+        ))),
+        depth => Ok(depth)
+    }
+}
+
+struct Markers<'lua> {
+    null: Table<'lua>,
+    array: Table<'lua>,
+    object: Table<'lua>
+}
+
+fn to_lua<'lua>(lua: rlua::Context<'lua>, json: Json, markers: &Markers<'lua>) -> rlua::Result<Value<'lua>> {
+    Ok(match json {
+        Json::Null => Value::Table(markers.null.clone()),
+        Json::Bool(b) => Value::Boolean(b),
+        Json::Integer(n) => Value::Integer(n),
+        Json::Number(n) => Value::Number(n),
+        Json::String(string) => Value::String(lua.create_string(&string)?),
+        Json::Array(items) => {
+            let table = lua.create_table()?;
+            for (i, item) in items.into_iter().enumerate() {
+                table.raw_set(i + 1, to_lua(lua, item, markers)?)?;
+            }
+            table.set_metatable(Some(markers.array.clone()));
+            Value::Table(table)
+        },
+        Json::Object(members) => {
+            let table = lua.create_table()?;
+            for (key, member) in members {
+                table.raw_set(key, to_lua(lua, member, markers)?)?;
+            }
+            table.set_metatable(Some(markers.object.clone()));
+            Value::Table(table)
+        }
+    })
+}
+
+fn unencodable(message: String) -> JsonError {
+    JsonError::Unencodable {
+        path: String::new(),
+        message
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Nil => "nil",
+        Value::Boolean(_) => "boolean",
+        Value::LightUserData(_) | Value::UserData(_) => "userdata",
+        Value::Integer(_) | Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Table(_) => "table",
+        Value::Function(_) => "function",
+        Value::Thread(_) => "thread",
+        Value::Error(_) => "error"
+    }
+}
+
+fn from_lua(value: Value, depth: usize, limit: usize) -> Result<Json, JsonError> {
+    match value {
+        Value::Nil => Ok(Json::Null),
+        Value::Boolean(b) => Ok(Json::Bool(b)),
+        Value::Integer(n) => Ok(Json::Integer(n)),
+        Value::Number(n) if n.is_finite() => Ok(Json::Number(n)),
+        Value::Number(n) => Err(unencodable(format!("can't encode {}", n))),
+        Value::String(string) => string
+            .to_str()
+            .map(|string| Json::String(string.into()))
+            .map_err(|_| unencodable("the string isn't UTF-8".into())),
+        Value::Table(table) => table_from_lua(table, depth, limit),
+        value => Err(unencodable(format!("can't encode a {}", type_name(&value))))
+    }
+}
+
+fn table_from_lua(table: Table, depth: usize, limit: usize) -> Result<Json, JsonError> {
+    let kind = marked(&table);
+    if kind.as_ref().map(String::as_str) == Some("null") {
+        return Ok(Json::Null);
+    }
+    if depth >= limit {
+        return Err(unencodable(format!(
+            "nested deeper than {} levels, does a table contain itself?",
+            limit
+        )));
+    }
+    let lua_error = |err: rlua::Error| unencodable(err.to_string());
+    let item = |key: Key, value: Value| from_lua(value, depth + 1, limit).map_err(|err| err.within(&key));
+    if kind.as_ref().map(String::as_str) == Some("array") {
+        let mut items = Vec::new();
+        for i in 1..=table.raw_len() {
+            items.push(item(Key::Index(i), table.raw_get(i).map_err(lua_error)?)?);
+        }
+        return Ok(Json::Array(items));
+    }
+    let mut pairs = Vec::new();
+    for pair in table.pairs::<Value, Value>() {
+        pairs.push(pair.map_err(lua_error)?);
+    }
+    let length = pairs.len() as i64;
+    let sequence = pairs.iter().all(|(key, _)| match *key {
+        Value::Integer(i) => i >= 1 && i <= length,
+        _ => false
+    });
+    if kind.is_none() && !pairs.is_empty() && sequence {
+        pairs.sort_by_key(|(key, _)| match *key {
+            Value::Integer(i) => i,
+            _ => 0
+        });
+        let mut items = Vec::new();
+        for (i, (_, value)) in (1..).zip(pairs) {
+            items.push(item(Key::Index(i), value)?);
+        }
+        return Ok(Json::Array(items));
+    }
+    let mut members = Vec::new();
+    for (key, value) in pairs {
+        let key = match key {
+            Value::Integer(i) => i.to_string(),
+            Value::String(ref string) => match string.to_str() {
+                Ok(string) => string.to_owned(),
+                Err(_) => return Err(unencodable("a key isn't UTF-8".into()))
+            },
+            key => return Err(unencodable(format!("can't use a {} as a key", type_name(&key))))
+        };
+        let value = item(Key::Name(key.clone()), value)?;
+        members.push((key, value));
+    }
+    Ok(Json::Object(members))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rlua::{Function, Lua};
+
+    fn setup(lua: rlua::Context) -> rlua::Result<()> {
+        let awesome = lua.create_table()?;
+        awesome.set("json_decode", lua.create_function(json_decode)?)?;
+        awesome.set("json_encode", lua.create_function(json_encode)?)?;
+        awesome.set("json_array", lua.create_function(json_array)?)?;
+        awesome.set("json_object", lua.create_function(json_object)?)?;
+        awesome.set("json_null", null(lua)?)?;
+        lua.globals().set("awesome", awesome)
+    }
+
+    #[test]
+    fn json_round_trips_through_lua() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            setup(lua)?;
+            lua.load(
+                r#"
+local text = '{"b":{},"a":[],"c":null,"d":[1,2.0,"é\\n"],"big":9007199254740993}'
+local data = assert(awesome.json_decode(text))
+assert(getmetatable(data.a).__json == "array" and getmetatable(data.b).__json == "object")
+assert(data.c == awesome.json_null and tostring(data.c) == "null")
+assert(math.type(data.d[1]) == "integer" and math.type(data.d[2]) == "float")
+assert(data.d[3] == "é\n" and data.big == 9007199254740993)
+local sorted = '{"a":[],"b":{},"big":9007199254740993,"c":null,"d":[1,2.0,"é\\n"]}'
+assert(awesome.json_encode(data, { sort_keys = true }) == sorted)
+-- Integers beyond 2^53 can be kept exactly for code that only has floats.
+local big = awesome.json_decode("[9007199254740993, 123456789012345678901, 5]", { big_integers_as_strings = true })
+assert(big[1] == "9007199254740993" and big[2] == "123456789012345678901" and big[3] == 5)
+assert(math.type(awesome.json_decode("123456789012345678901")) == "float")
+-- Unmarked tables are arrays when their keys are 1 to n.
+assert(awesome.json_encode({}) == "{}")
+assert(awesome.json_encode(awesome.json_array()) == "[]")
+assert(awesome.json_encode({ 3, 2, 1 }) == "[3,2,1]")
+assert(awesome.json_encode({ [1] = true, [3] = false }, { sort_keys = true }) == '{"1":true,"3":false}')
+assert(awesome.json_encode(awesome.json_object{ "x" }) == '{"1":"x"}')
+assert(awesome.json_encode(awesome.json_array{ 1, { x = awesome.json_null } }, { pretty = true }) ==
+    '[\n  1,\n  {\n    "x": null\n  }\n]')
+assert(awesome.json_decode("null") == awesome.json_null)
+assert(not pcall(function() awesome.json_null.x = 1 end))
+assert(not pcall(setmetatable, awesome.json_null, {}))
+                "#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn json_errors_in_lua() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            setup(lua)?;
+            lua.load(
+                r#"
+local data, err = awesome.json_decode('[1, "\xff"]')
+assert(data == nil and err.kind == "utf8" and err.offset == 6, err.message)
+assert(err.line == 1 and err.column == 6 and err.message == "1:6: invalid UTF-8 at byte 6", err.message)
+local _, err = awesome.json_decode('{\n  "a": tru\n}')
+assert(err.kind == "syntax" and err.line == 2 and err.column == 8 and err.offset == 10, err.message)
+local _, err = awesome.json_decode("[1, 2]", { max_size = 4 })
+assert(err.kind == "too_large" and err.offset == nil, err.message)
+local _, err = awesome.json_decode("[[[]]]", { max_depth = 2 })
+assert(err.kind == "too_deep" and err.offset == 3, err.message)
+local _, err = awesome.json_encode({ a = { 1, print } })
+assert(err.kind == "unencodable" and err.path == "$.a[2]", err.path)
+assert(err.message == "$.a[2]: can't encode a function", err.message)
+local _, err = awesome.json_encode({ ["a key"] = 0 / 0 })
+assert(err.path == '$["a key"]', err.path)
+local cycle = {}
+cycle.self = cycle
+local _, err = awesome.json_encode(cycle, { max_depth = 10 })
+assert(err.kind == "unencodable" and err.message:find("contain itself"), err.message)
+local _, err = awesome.json_encode({ [true] = 1 })
+assert(err.message == "$: can't use a boolean as a key", err.message)
+assert(not pcall(awesome.json_decode, "[]", { max_depth = 0 }))
+assert(not pcall(awesome.json_encode, {}, { max_depth = 1025 }))
+                "#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn json_fuzz_lua_entry_point() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            setup(lua)?;
+            let awesome: Table = lua.globals().get("awesome")?;
+            let decode: Function = awesome.get("json_decode")?;
+            let encode: Function = awesome.get("json_encode")?;
+            let alphabet: &[u8] = b"[]{},:\"\\u0123456789.eE+-tfnrl ad\n\xc3\xa9\xff\x01";
+            let mut state = 0x853c_49e6_748f_ea9b_u64;
+            let mut next = move || {
+                state = state.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+                (state >> 33) as usize
+            };
+            let mut decoded = 0;
+            for _ in 0..20_000 {
+                let length = next() % 12;
+                let bytes: Vec<u8> = (0..length).map(|_| alphabet[next() % alphabet.len()]).collect();
+                let input = lua.create_string(&bytes)?;
+                let (value, err) = decode.call::<_, (Value, Value)>(input)?;
+                match (value, err) {
+                    (Value::Nil, Value::Table(err)) => {
+                        assert!(err.get::<_, String>("kind").is_ok());
+                    },
+                    (value, Value::Nil) => {
+                        // Whatever decodes encodes to the same value again.
+                        decoded += 1;
+                        let options = lua.create_table()?;
+                        options.set("sort_keys", true)?;
+                        let (text, _) = encode.call::<_, (String, Value)>((value, options.clone()))?;
+                        let (again, _) = decode.call::<_, (Value, Value)>(text.clone())?;
+                        let (text_again, _) = encode.call::<_, (String, Value)>((again, options))?;
+                        assert_eq!(text, text_again);
+                    },
+                    _ => panic!("json_decode returned a value and an error for {:?}", bytes)
+                }
+            }
+            assert!(decoded > 0);
+            Ok(())
+        })
+    }
+}
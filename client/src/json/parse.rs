@@ -0,0 +1,609 @@
+//! A strict parser for RFC 8259 JSON.
+//!
+//! The input has to be UTF-8, which is checked before anything else so the
+//! offset of the first bad byte can be reported. Strings can't contain
+//! unpaired surrogates, since they couldn't be UTF-8 either, and numbers
+//! have to fit in a double.
+
+use std::str;
+
+use super::{Json, JsonError, Position};
+
+/// Integers further from 0 than this can't all be told apart as doubles.
+const MAX_SAFE_INTEGER: i64 = 1 << 53;
+
+/// How deep anything is nested at most, whatever the options say, so the
+/// parser doesn't run out of stack.
+pub const MAX_DEPTH: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Options {
+    /// How many bytes the input may have.
+    pub max_size: usize,
+    /// How deep arrays and objects may be nested.
+    pub max_depth: usize,
+    /// Whether integers that don't fit in 53 bits are kept as strings, for
+    /// data meant for programs that only have doubles.
+    pub big_integers_as_strings: bool
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            max_size: 16 << 20,
+            max_depth: 256,
+            big_integers_as_strings: false
+        }
+    }
+}
+
+pub fn parse(input: &[u8], options: &Options) -> Result<Json, JsonError> {
+    if input.len() > options.max_size {
+        return Err(JsonError::TooLarge {
+            size: input.len(),
+            limit: options.max_size
+        });
+    }
+    if let Err(err) = str::from_utf8(input) {
+        return Err(JsonError::Utf8(Position::at(input, err.valid_up_to())));
+    }
+    let mut parser = Parser {
+        input,
+        offset: 0,
+        depth: 0,
+        options
+    };
+    parser.skip_whitespace();
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.offset < input.len() {
+        return Err(parser.error("unexpected data after the value"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    offset: usize,
+    depth: usize,
+    options: &'a Options
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> JsonError {
+        JsonError::Syntax {
+            message: message.into(),
+            position: Position::at(self.input, self.offset)
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.offset).cloned()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.offset += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8, message: &str) -> Result<(), JsonError> {
+        if self.peek() != Some(byte) {
+            return Err(self.error(message));
+        }
+        self.offset += 1;
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Json, JsonError> {
+        match self.peek() {
+            Some(b'{') => self.nested(Parser::object),
+            Some(b'[') => self.nested(Parser::array),
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b'-') | Some(b'0'..=b'9') => self.number(),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(_) => Err(self.error("expected a value")),
+            None => Err(self.error("expected a value, got the end of the input"))
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Json, JsonError>) -> Result<Json, JsonError> {
+        let limit = self.options.max_depth.min(MAX_DEPTH);
+        if self.depth >= limit {
+            return Err(JsonError::TooDeep {
+                limit,
+                position: Position::at(self.input, self.offset)
+            });
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn literal(&mut self, literal: &str, value: Json) -> Result<Json, JsonError> {
+        if !self.input[self.offset..].starts_with(literal.as_bytes()) {
+            return Err(self.error("expected a value"));
+        }
+        self.offset += literal.len();
+        Ok(value)
+    }
+
+    fn array(&mut self) -> Result<Json, JsonError> {
+        self.offset += 1;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.offset += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            self.skip_whitespace();
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.offset += 1,
+                Some(b']') => {
+                    self.offset += 1;
+                    return Ok(Json::Array(items));
+                },
+                _ => return Err(self.error("expected ',' or ']'"))
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, JsonError> {
+        self.offset += 1;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.offset += 1;
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a string key"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(b':', "expected ':' after the key")?;
+            self.skip_whitespace();
+            members.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.offset += 1,
+                Some(b'}') => {
+                    self.offset += 1;
+                    return Ok(Json::Object(members));
+                },
+                _ => return Err(self.error("expected ',' or '}'"))
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.offset += 1;
+        let mut bytes = Vec::new();
+        loop {
+            // Runs of plain characters are copied at once, the input is
+            // known to be UTF-8.
+            let start = self.offset;
+            while let Some(byte) = self.peek() {
+                if byte == b'"' || byte == b'\\' || byte < 0x20 {
+                    break;
+                }
+                self.offset += 1;
+            }
+            bytes.extend_from_slice(&self.input[start..self.offset]);
+            match self.peek() {
+                Some(b'"') => {
+                    self.offset += 1;
+                    break;
+                },
+                Some(b'\\') => {
+                    let c = self.escape()?;
+                    let mut buffer = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                },
+                Some(_) => return Err(self.error("control characters must be escaped in strings")),
+                None => return Err(self.error("unterminated string"))
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8 in string"))
+    }
+
+    fn escape(&mut self) -> Result<char, JsonError> {
+        let escape = self.offset;
+        self.offset += 1;
+        let c = match self.peek() {
+            Some(b'"') => '"',
+            Some(b'\\') => '\\',
+            Some(b'/') => '/',
+            Some(b'b') => '\u{8}',
+            Some(b'f') => '\u{c}',
+            Some(b'n') => '\n',
+            Some(b'r') => '\r',
+            Some(b't') => '\t',
+            Some(b'u') => {
+                self.offset += 1;
+                let unit = self.hex()?;
+                let code = match unit {
+                    0xd800..=0xdbff => {
+                        if !self.input[self.offset..].starts_with(b"\\u") {
+                            self.offset = escape;
+                            return Err(self.error("unpaired surrogate in string"));
+                        }
+                        self.offset += 2;
+                        let low = self.hex()?;
+                        if low < 0xdc00 || low > 0xdfff {
+                            self.offset = escape;
+                            return Err(self.error("unpaired surrogate in string"));
+                        }
+                        0x10000 + ((unit - 0xd800) << 10) + (low - 0xdc00)
+                    },
+                    0xdc00..=0xdfff => {
+                        self.offset = escape;
+                        return Err(self.error("unpaired surrogate in string"));
+                    },
+                    unit => unit
+                };
+                // Every code point that isn't a surrogate is a char.
+                return std::char::from_u32(code).ok_or_else(|| self.error("invalid escape"));
+            },
+            _ => return Err(self.error("invalid escape"))
+        };
+        self.offset += 1;
+        Ok(c)
+    }
+
+    /// The four hex digits of a `\u` escape.
+    fn hex(&mut self) -> Result<u32, JsonError> {
+        let mut unit = 0;
+        for _ in 0..4 {
+            let digit = match self.peek() {
+                Some(byte @ b'0'..=b'9') => byte - b'0',
+                Some(byte @ b'a'..=b'f') => byte - b'a' + 10,
+                Some(byte @ b'A'..=b'F') => byte - b'A' + 10,
+                _ => return Err(self.error("expected four hex digits after \\u"))
+            };
+            unit = unit * 16 + u32::from(digit);
+            self.offset += 1;
+        }
+        Ok(unit)
+    }
+
+    fn number(&mut self) -> Result<Json, JsonError> {
+        let start = self.offset;
+        if self.peek() == Some(b'-') {
+            self.offset += 1;
+        }
+        match self.peek() {
+            Some(b'0') => self.offset += 1,
+            Some(b'1'..=b'9') => self.digits(),
+            _ => return Err(self.error("expected a digit"))
+        }
+        let mut integer = true;
+        if self.peek() == Some(b'.') {
+            integer = false;
+            self.offset += 1;
+            if !self.peek().map(|byte| byte.is_ascii_digit()).unwrap_or(false) {
+                return Err(self.error("expected a digit after the decimal point"));
+            }
+            self.digits();
+        }
+        if let Some(b'e') | Some(b'E') = self.peek() {
+            integer = false;
+            self.offset += 1;
+            if let Some(b'+') | Some(b'-') = self.peek() {
+                self.offset += 1;
+            }
+            if !self.peek().map(|byte| byte.is_ascii_digit()).unwrap_or(false) {
+                return Err(self.error("expected a digit in the exponent"));
+            }
+            self.digits();
+        }
+        // Only ASCII was consumed.
+        let text = str::from_utf8(&self.input[start..self.offset]).unwrap_or_default();
+        let big = self.options.big_integers_as_strings;
+        if integer {
+            match text.parse::<i64>() {
+                Ok(n) if big && (n > MAX_SAFE_INTEGER || n < -MAX_SAFE_INTEGER) => {
+                    return Ok(Json::String(text.into()))
+                },
+                Ok(n) => return Ok(Json::Integer(n)),
+                Err(_) if big => return Ok(Json::String(text.into())),
+                Err(_) => {}
+            }
+        }
+        match text.parse::<f64>() {
+            Ok(n) if n.is_finite() => Ok(Json::Number(n)),
+            _ => {
+                self.offset = start;
+                Err(self.error("number out of range"))
+            }
+        }
+    }
+
+    fn digits(&mut self) {
+        while self.peek().map(|byte| byte.is_ascii_digit()).unwrap_or(false) {
+            self.offset += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::json::write::{write, Format};
+
+    fn parse_str(input: &str) -> Result<Json, JsonError> {
+        parse(input.as_bytes(), &Options::default())
+    }
+
+    /// Cases from the JSON test suite (github.com/nst/JSONTestSuite), by
+    /// their name there. `y_` have to be accepted, `n_` rejected, and `i_`
+    /// are up to the parser, which rejects all of these.
+    const FIXTURES: &[(&str, &[u8])] = &[
+        ("y_array_empty", b"[]"),
+        ("y_array_heterogeneous", b"[null, 1, \"1\", {}]"),
+        ("y_number_0e1", b"[0e1]"),
+        ("y_number_real_capital_e", b"[1E22]"),
+        ("y_number_negative_zero", b"[-0]"),
+        ("y_number_very_big_negative_int", b"[-237462374673276894279832749832423479823246327846]"),
+        ("y_object_duplicated_key", b"{\"a\":\"b\",\"a\":\"c\"}"),
+        ("y_object_empty_key", b"{\"\":0}"),
+        ("y_string_accepted_surrogate_pair", b"[\"\\uD801\\udc37\"]"),
+        ("y_string_escaped_noncharacter", b"[\"\\uFFFF\"]"),
+        ("y_string_null_escape", b"[\"\\u0000\"]"),
+        ("y_string_unicode_U+FFFE_nonchar", b"[\"\\uFFFE\"]"),
+        ("y_string_utf8", b"[\"\xe2\x82\xac\xf0\x9d\x84\x9e\"]"),
+        ("y_string_u+2029_par_sep", b"[\"\xe2\x80\xa9\"]"),
+        ("y_structure_lonely_null", b"null"),
+        ("y_structure_whitespace_array", b" [] "),
+        ("y_structure_trailing_newline", b"[\"a\"]\n"),
+        ("n_array_extra_comma", b"[\"\",]"),
+        ("n_array_unclosed", b"[\"\""),
+        ("n_incomplete_true", b"[tru]"),
+        ("n_number_-01", b"[-01]"),
+        ("n_number_.2e-3", b"[.2e-3]"),
+        ("n_number_2.e3", b"[2.e3]"),
+        ("n_number_+1", b"[+1]"),
+        ("n_number_NaN", b"[NaN]"),
+        ("n_number_infinity", b"[Infinity]"),
+        ("n_number_hex_1_digit", b"[0x1]"),
+        ("n_object_trailing_comma", b"{\"id\":0,}"),
+        ("n_object_single_quote", b"{'a':0}"),
+        ("n_object_non_string_key", b"{1:1}"),
+        ("n_single_space", b" "),
+        ("n_string_escape_x", b"[\"\\x00\"]"),
+        ("n_string_incomplete_surrogate", b"[\"\\uD834\\uDd\"]"),
+        ("n_string_invalid_utf8_after_escape", b"[\"\\\xe5\"]"),
+        ("n_string_lone_utf8_continuation_byte", b"[\"\x81\"]"),
+        ("n_string_unescaped_tab", b"[\"\t\"]"),
+        ("n_string_unescaped_newline", b"[\"new\nline\"]"),
+        ("n_structure_no_data", b""),
+        ("n_structure_trailing_#", b"{\"a\":\"b\"}#{}"),
+        ("n_structure_UTF8_BOM_no_data", b"\xef\xbb\xbf"),
+        ("n_structure_open_array_object", b"[{\"\":[{\"\":"),
+        ("i_number_huge_exp", b"[0.4e00669999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999969999999006]"),
+        ("i_number_neg_int_huge_exp", b"[-1e+9999]"),
+        ("i_string_1st_surrogate_but_2nd_missing", b"[\"\\uDADA\"]"),
+        ("i_string_lone_second_surrogate", b"[\"\\uDFAA\"]"),
+        ("i_string_invalid_utf-8", b"[\"\xff\"]"),
+        ("i_string_UTF-16LE_with_BOM", b"\xff\xfe[\x00\"\x00\xe9\x00\"\x00]\x00"),
+    ];
+
+    #[test]
+    fn json_test_suite_fixtures() {
+        for &(name, input) in FIXTURES {
+            let result = parse(input, &Options::default());
+            match &name[..2] {
+                "y_" => assert!(result.is_ok(), "{} was rejected: {:?}", name, result),
+                _ => assert!(result.is_err(), "{} was accepted: {:?}", name, result)
+            }
+        }
+        let deep = vec![b'['; 100_000];
+        match parse(&deep, &Options::default()) {
+            Err(JsonError::TooDeep { limit: 256, .. }) => {},
+            result => panic!("n_structure_100000_opening_arrays: {:?}", result)
+        }
+    }
+
+    #[test]
+    fn json_parse_values() {
+        assert_eq!(
+            parse_str(r#"{"a": [1, -2.5, true, null], "b": "x\u00e9\ud834\udd1e\n"}"#),
+            Ok(Json::Object(vec![
+                (
+                    "a".into(),
+                    Json::Array(vec![
+                        Json::Integer(1),
+                        Json::Number(-2.5),
+                        Json::Bool(true),
+                        Json::Null
+                    ])
+                ),
+                ("b".into(), Json::String("x\u{e9}\u{1d11e}\n".into()))
+            ]))
+        );
+        // Integers stay integers, anything with a fraction or an exponent
+        // is a number.
+        assert_eq!(parse_str("1.0"), Ok(Json::Number(1.0)));
+        assert_eq!(parse_str("1e2"), Ok(Json::Number(100.0)));
+        assert_eq!(parse_str("-0"), Ok(Json::Integer(0)));
+        assert_eq!(
+            parse_str("9223372036854775807"),
+            Ok(Json::Integer(i64::max_value()))
+        );
+        assert_eq!(
+            parse_str("9223372036854775808"),
+            Ok(Json::Number(9223372036854775808.0))
+        );
+        let big = Options {
+            big_integers_as_strings: true,
+            ..Options::default()
+        };
+        let parse_big = |input: &str| parse(input.as_bytes(), &big);
+        assert_eq!(parse_big("9007199254740992"), Ok(Json::Integer(1 << 53)));
+        assert_eq!(
+            parse_big("-9007199254740993"),
+            Ok(Json::String("-9007199254740993".into()))
+        );
+        assert_eq!(
+            parse_big("123456789012345678901234567890"),
+            Ok(Json::String("123456789012345678901234567890".into()))
+        );
+        assert_eq!(parse_big("1.5e300"), Ok(Json::Number(1.5e300)));
+    }
+
+    #[test]
+    fn json_parse_errors() {
+        let position = |offset, line, column| Position { offset, line, column };
+        assert_eq!(
+            parse(b"{\"a\": \"\xc3\xa9\xff\"}", &Options::default()),
+            Err(JsonError::Utf8(position(9, 1, 9)))
+        );
+        assert_eq!(
+            parse_str("[1,\n  2,\n  x]"),
+            Err(JsonError::Syntax {
+                message: "expected a value".into(),
+                position: position(11, 3, 3)
+            })
+        );
+        assert_eq!(
+            parse_str("[\"\\ud800x\"]"),
+            Err(JsonError::Syntax {
+                message: "unpaired surrogate in string".into(),
+                position: position(2, 1, 3)
+            })
+        );
+        let small = Options {
+            max_size: 4,
+            max_depth: 2,
+            ..Options::default()
+        };
+        assert_eq!(
+            parse(b"[1, 2]", &small),
+            Err(JsonError::TooLarge { size: 6, limit: 4 })
+        );
+        assert_eq!(
+            parse(b"[[[]]]", &Options { max_size: 8, ..small }),
+            Err(JsonError::TooDeep {
+                limit: 2,
+                position: position(2, 1, 3)
+            })
+        );
+    }
+
+    /// A xorshift generator, so the fuzzing is the same on every run.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    fn random_json(rng: &mut Rng, depth: usize) -> Json {
+        let kinds = if depth == 0 { 6 } else { 8 };
+        match rng.below(kinds) {
+            0 => Json::Null,
+            1 => Json::Bool(rng.below(2) == 0),
+            2 => Json::Integer(rng.next() as i64 >> rng.below(64)),
+            3 => {
+                let n = f64::from_bits(rng.next());
+                Json::Number(if n.is_finite() { n } else { 0.5 })
+            },
+            4 | 5 => {
+                let alphabet = [
+                    'a',
+                    '"',
+                    '\\',
+                    '\n',
+                    '\u{1}',
+                    '\u{e9}',
+                    '\u{1d11e}',
+                    '/',
+                    '\u{7f}'
+                ];
+                let length = rng.below(6);
+                Json::String((0..length).map(|_| alphabet[rng.below(alphabet.len())]).collect())
+            },
+            6 => Json::Array((0..rng.below(4)).map(|_| random_json(rng, depth - 1)).collect()),
+            _ => Json::Object(
+                (0..rng.below(4))
+                    .map(|i| (format!("k{}", i), random_json(rng, depth - 1)))
+                    .collect()
+            )
+        }
+    }
+
+    #[test]
+    fn json_round_trips() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..2000 {
+            let json = random_json(&mut rng, 4);
+            for &pretty in &[false, true] {
+                let text = write(
+                    &json,
+                    &Format {
+                        pretty,
+                        sort_keys: false
+                    }
+                );
+                assert_eq!(parse_str(&text).as_ref(), Ok(&json), "{}", text);
+            }
+        }
+    }
+
+    #[test]
+    fn json_fuzz_without_panics() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        let seeds: &[&[u8]] = &[
+            b"{\"a\": [1, 2.5e-3, \"\\ud834\\udd1e\", null, true], \"b\": {}}",
+            b"[[[[\"\xc3\xa9\"]]]]",
+            b"-0.0e+00"
+        ];
+        let tokens: &[&[u8]] = &[
+            b"[", b"]", b"{", b"}", b",", b":", b"\"", b"\\u", b"d8", b"1e", b"-", b"null", b"\xff"
+        ];
+        let small = Options {
+            max_depth: 8,
+            ..Options::default()
+        };
+        for _ in 0..5000 {
+            let mut input = seeds[rng.below(seeds.len())].to_vec();
+            for _ in 0..rng.below(4) + 1 {
+                let at = rng.below(input.len() + 1);
+                match rng.below(4) {
+                    0 => {
+                        input.truncate(at);
+                    },
+                    1 if at < input.len() => input[at] = rng.next() as u8,
+                    2 => {
+                        let token = tokens[rng.below(tokens.len())];
+                        input.splice(at..at, token.iter().cloned());
+                    },
+                    _ => input.insert(at, rng.next() as u8)
+                }
+            }
+            for options in &[Options::default(), small] {
+                // Whatever is accepted is written out and read back the same.
+                if let Ok(json) = parse(&input, options) {
+                    let text = write(&json, &Format::default());
+                    assert_eq!(parse(text.as_bytes(), &Options::default()), Ok(json));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! Writes JSON text, either compact or indented by two spaces.
+
+use std::fmt::Write;
+
+use super::Json;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Format {
+    /// Whether every value of an array or object goes on a line of its own.
+    pub pretty: bool,
+    /// Whether the members of objects are sorted by key, so the same data
+    /// is always written the same.
+    pub sort_keys: bool
+}
+
+pub fn write(json: &Json, format: &Format) -> String {
+    let mut out = String::new();
+    value(&mut out, json, format, 0);
+    out
+}
+
+fn value(out: &mut String, json: &Json, format: &Format, depth: usize) {
+    match json {
+        Json::Null => out.push_str("null"),
+        Json::Bool(true) => out.push_str("true"),
+        Json::Bool(false) => out.push_str("false"),
+        Json::Integer(n) => {
+            let _ = write!(out, "{}", n);
+        },
+        Json::Number(n) => number(out, *n),
+        Json::String(string) => quote(out, string),
+        Json::Array(items) => {
+            if items.is_empty() {
+                out.push_str("[]");
+                return;
+            }
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                indent(out, format, depth + 1);
+                value(out, item, format, depth + 1);
+            }
+            indent(out, format, depth);
+            out.push(']');
+        },
+        Json::Object(members) => {
+            if members.is_empty() {
+                out.push_str("{}");
+                return;
+            }
+            let mut members: Vec<_> = members.iter().collect();
+            if format.sort_keys {
+                members.sort_by(|a, b| a.0.cmp(&b.0));
+            }
+            out.push('{');
+            for (i, (key, member)) in members.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                indent(out, format, depth + 1);
+                quote(out, key);
+                out.push_str(if format.pretty { ": " } else { ":" });
+                value(out, member, format, depth + 1);
+            }
+            indent(out, format, depth);
+            out.push('}');
+        }
+    }
+}
+
+fn indent(out: &mut String, format: &Format, depth: usize) {
+    if format.pretty {
+        out.push('\n');
+        for _ in 0..depth {
+            out.push_str("  ");
+        }
+    }
+}
+
+/// Writes a finite number so it's read back as the same double, and as a
+/// float rather than an integer.
+fn number(out: &mut String, n: f64) {
+    let _ = if n == 0.0 || (n.abs() >= 1e-5 && n.abs() < 1e16) {
+        // Always has a decimal point, like "3.0".
+        write!(out, "{:?}", n)
+    } else {
+        // Always has an exponent, like "1e300".
+        write!(out, "{:e}", n)
+    };
+}
+
+fn quote(out: &mut String, string: &str) {
+    out.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            },
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn json_write_format() {
+        let json = Json::Object(vec![
+            (
+                "b".into(),
+                Json::Array(vec![Json::Integer(1), Json::Number(1.0), Json::Array(vec![])])
+            ),
+            ("a".into(), Json::String("\"\\/\u{1}\u{e9}\n".into())),
+            ("c".into(), Json::Object(vec![])),
+        ]);
+        assert_eq!(
+            write(&json, &Format::default()),
+            r#"{"b":[1,1.0,[]],"a":"\"\\/\u0001é\n","c":{}}"#
+        );
+        assert_eq!(
+            write(&json, &Format {
+                pretty: true,
+                sort_keys: true
+            }),
+            "{\n  \"a\": \"\\\"\\\\/\\u0001é\\n\",\n  \"b\": [\n    1,\n    1.0,\n    []\n  ],\n  \"c\": {}\n}"
+        );
+        let numbers = [0.5, -0.0, 1e-7, 1.5e300, 123456789.125, 1e16];
+        let written: Vec<_> = numbers
+            .iter()
+            .map(|&n| write(&Json::Number(n), &Format::default()))
+            .collect();
+        assert_eq!(
+            written,
+            ["0.5", "-0.0", "1e-7", "1.5e300", "123456789.125", "1e16"]
+        );
+    }
+}
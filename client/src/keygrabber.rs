@@ -44,14 +44,12 @@ pub fn keygrabber_handle(mods: Vec<Keysym>, sym: Keysym, state: u32) -> rlua::Re
 }
 
 /// Check is the Lua callback function is set
-#[allow(dead_code)]
 pub fn is_keygrabber_set(lua: rlua::Context) -> bool {
     lua.named_registry_value::<str, Function>(KEYGRABBER_CALLBACK)
         .is_ok()
 }
 
 /// Call the Lua callback function for when a key is pressed.
-#[allow(dead_code)]
 pub fn call_keygrabber<'lua>(
     lua: rlua::Context<'lua>,
     (mods, key, event): (Table<'lua>, String, String)
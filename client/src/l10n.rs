@@ -0,0 +1,456 @@
+//! Translating the text the client draws itself, like the label of
+//! `awesome.pick_geometry` and the names of the self test steps, and
+//! `awesome.translate` for the text of the configuration.
+//!
+//! Messages come from catalogs named after their locale, like
+//! `pl.catalog`. The ones the client ships are built in, and a catalog in
+//! `$XDG_DATA_HOME/way-cooler/locale` or `$XDG_DATA_DIRS/way-cooler/locale`
+//! adds messages to them or adds a language of its own. The locale is read
+//! from the environment like gettext does, and a message that isn't in its
+//! catalog is looked up in less specific ones and then in English. A key
+//! that no catalog has is shown as it is.
+
+mod catalog;
+mod format;
+mod locale;
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    env, fs, io,
+    path::PathBuf
+};
+
+use rlua::{self, Table, Value};
+
+pub use self::format::{Arg, Args};
+
+use self::catalog::{Catalog, Message};
+use self::format::FormatError;
+use self::locale::Plural;
+
+/// The catalogs the client ships.
+const BUILT_IN: &[(&str, &str)] = &[
+    ("en", include_str!("../locale/en.catalog")),
+    ("de", include_str!("../locale/de.catalog")),
+    ("ja", include_str!("../locale/ja.catalog")),
+    ("pl", include_str!("../locale/pl.catalog")),
+    ("ru", include_str!("../locale/ru.catalog"))
+];
+
+thread_local! {
+    static TRANSLATOR: RefCell<Option<Translator>> = RefCell::new(None);
+    /// The translators for the locales Lua asked for by name.
+    static OTHERS: RefCell<HashMap<String, Translator>> = RefCell::new(HashMap::new());
+}
+
+#[derive(Debug, Default)]
+pub struct Translator {
+    /// The locale numbers are written for.
+    locale: String,
+    /// The catalogs of the fallback chain that exist, most preferred first.
+    catalogs: Vec<(String, Catalog)>,
+    /// The keys no catalog has, which have been logged.
+    missing: HashSet<String>
+}
+
+impl Translator {
+    /// Loads the catalogs of the fallback chain of `locales`, with the
+    /// catalogs in `dirs` replacing built in messages, and the catalogs of
+    /// earlier dirs replacing those of later ones.
+    pub fn load<S: AsRef<str>>(locales: &[S], dirs: &[PathBuf]) -> Self {
+        let chain = locale::fallback_chain(locales);
+        let mut catalogs = Vec::new();
+        for name in &chain {
+            let mut catalog = None;
+            if let Some((_, text)) = BUILT_IN.iter().find(|(built_in, _)| built_in == name) {
+                catalog = Some(parse(text, &format!("the built in {} catalog", name)));
+            }
+            for dir in dirs.iter().rev() {
+                let path = dir.join(format!("{}.catalog", name));
+                match fs::read_to_string(&path) {
+                    Ok(text) => catalog
+                        .get_or_insert_with(Catalog::default)
+                        .merge(parse(&text, &path.display().to_string())),
+                    Err(ref err) if err.kind() == io::ErrorKind::NotFound => {},
+                    Err(err) => warn!("Could not read {}: {}", path.display(), err)
+                }
+            }
+            if let Some(catalog) = catalog {
+                catalogs.push((name.clone(), catalog));
+            }
+        }
+        Translator {
+            locale: chain[0].clone(),
+            catalogs,
+            missing: HashSet::new()
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Translator::load(&locale::from_env(|name| env::var(name).ok()), &data_dirs())
+    }
+
+    /// The message of `key` with its placeholders filled in from `args`,
+    /// or `key` itself if no catalog has it.
+    pub fn translate(&mut self, key: &str, args: &Args) -> Result<String, FormatError> {
+        let separator = self.decimal_separator().to_string();
+        let found = self
+            .catalogs
+            .iter()
+            .find_map(|(name, catalog)| catalog.messages.get(key).map(|message| (name, message)));
+        let template = match found {
+            Some((_, Message::Text(template))) => template,
+            Some((name, Message::Plural(forms))) => {
+                let form = args
+                    .count()
+                    .map(|count| locale::plural(name, count))
+                    .unwrap_or(Plural::Other);
+                // Every plural message has an `other` form, that's checked
+                // when it's parsed.
+                forms.get(&form).unwrap_or_else(|| &forms[&Plural::Other])
+            },
+            None => {
+                if self.missing.insert(key.into()) {
+                    warn!("No catalog has a message for {}", key);
+                }
+                return Ok(key.into());
+            }
+        };
+        template.format(args, &separator)
+    }
+
+    /// What separates the whole part of a number from its fraction.
+    pub fn decimal_separator(&self) -> &str {
+        self.catalogs
+            .iter()
+            .find_map(|(_, catalog)| catalog.decimal_separator.as_ref())
+            .map(String::as_str)
+            .unwrap_or_else(|| locale::decimal_separator(&self.locale))
+    }
+}
+
+fn parse(text: &str, source: &str) -> Catalog {
+    let (catalog, errors) = Catalog::parse(text);
+    for error in errors {
+        warn!("Left out line {} of {}: {}", error.line, source, error.message);
+    }
+    catalog
+}
+
+/// The directories catalogs are read from, most preferred first.
+fn data_dirs() -> Vec<PathBuf> {
+    let data_home = env::var("XDG_DATA_HOME")
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var("HOME")
+                .ok()
+                .map(|home| PathBuf::from(home).join(".local/share"))
+        });
+    let mut dirs: Vec<PathBuf> = data_home.into_iter().collect();
+    dirs.extend(
+        env::var("XDG_DATA_DIRS")
+            .unwrap_or("/usr/local/share:/usr/share".into())
+            .split(':')
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+    );
+    dirs.into_iter()
+        .map(|dir| dir.join("way-cooler/locale"))
+        .collect()
+}
+
+/// Loads the catalogs of the locale of the environment.
+pub fn init() {
+    let translator = Translator::from_env();
+    info!(
+        "Translating to {}",
+        translator
+            .catalogs
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    TRANSLATOR.with(|cell| *cell.borrow_mut() = Some(translator));
+}
+
+/// The message of `key` for text the client draws itself.
+///
+/// A message that can't be formatted is logged and shown as its key, the
+/// arguments of the client's own messages are always given.
+pub fn message(key: &str, args: &Args) -> String {
+    TRANSLATOR.with(|cell| {
+        cell.borrow_mut()
+            .get_or_insert_with(Translator::from_env)
+            .translate(key, args)
+            .unwrap_or_else(|err| {
+                warn!("Could not format the message for {}: {}", key, err);
+                key.into()
+            })
+    })
+}
+
+/// `awesome.translate(key, args, locale)`, the message of `key` in the
+/// locale of the environment, or in `locale` if it's given, with the
+/// placeholders filled in from `args`.
+///
+/// The integer keys of `args` are the positional arguments, with `{0}`
+/// being `args[1]`, and the string keys are the named ones.
+pub fn translate<'lua>(
+    _: rlua::Context<'lua>,
+    (key, args, locale): (String, Option<Table<'lua>>, Option<String>)
+) -> rlua::Result<String> {
+    let args = match args {
+        Some(args) => args_from_lua(args)?,
+        None => Args::default()
+    };
+    let translated = match locale {
+        Some(locale) => OTHERS.with(|others| {
+            others
+                .borrow_mut()
+                .entry(locale.clone())
+                .or_insert_with(|| Translator::load(&[locale], &data_dirs()))
+                .translate(&key, &args)
+        }),
+        None => TRANSLATOR.with(|cell| {
+            cell.borrow_mut()
+                .get_or_insert_with(Translator::from_env)
+                .translate(&key, &args)
+        })
+    };
+    translated.map_err(|err| rlua::Error::RuntimeError(format!("could not translate {}: {}", key, err)))
+}
+
+fn args_from_lua(table: Table) -> rlua::Result<Args> {
+    let mut args = Args::default();
+    let mut positional = Vec::new();
+    for pair in table.pairs::<Value, Value>() {
+        let (key, value) = pair?;
+        let name = match &key {
+            Value::Integer(index) => index.to_string(),
+            Value::String(name) => name.to_str()?.to_string(),
+            _ => {
+                return Err(rlua::Error::RuntimeError(
+                    "the keys of the arguments must be positions or names".into()
+                ))
+            },
+        };
+        let arg = match value {
+            Value::Integer(n) => Arg::Integer(n),
+            Value::Number(n) => Arg::Number(n),
+            Value::String(text) => Arg::Text(text.to_str()?.into()),
+            Value::Boolean(b) => Arg::Text(b.to_string()),
+            _ => {
+                return Err(rlua::Error::RuntimeError(format!(
+                    "the argument {} must be a number, a string or a boolean",
+                    name
+                )))
+            },
+        };
+        match key {
+            Value::Integer(index) if index >= 1 => positional.push((index as usize, arg)),
+            Value::Integer(_) => {
+                return Err(rlua::Error::RuntimeError(format!(
+                    "the argument {} isn't a position, they start at 1",
+                    name
+                )))
+            },
+            _ => args.named.push((name, arg))
+        }
+    }
+    positional.sort_by_key(|&(index, _)| index);
+    for (expected, (index, arg)) in positional.into_iter().enumerate() {
+        if index != expected + 1 {
+            return Err(rlua::Error::RuntimeError(format!(
+                "the arguments skip position {}",
+                expected + 1
+            )));
+        }
+        args.positional.push(arg);
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::PathBuf};
+
+    use cairo::{Format, ImageSurface};
+    use rlua::{self, Lua};
+
+    use super::*;
+    use crate::common::{font::Font, text};
+
+    fn with_translator<F: FnOnce()>(translator: Translator, f: F) {
+        TRANSLATOR.with(|cell| *cell.borrow_mut() = Some(translator));
+        f();
+        TRANSLATOR.with(|cell| *cell.borrow_mut() = None);
+        OTHERS.with(|others| others.borrow_mut().clear());
+    }
+
+    fn catalog_dir(name: &str, catalogs: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("way-cooler-l10n-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (locale, text) in catalogs {
+            fs::write(dir.join(format!("{}.catalog", locale)), text).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn l10n_translate_fallback() {
+        let user = catalog_dir(
+            "user",
+            &[
+                (
+                    "de_AT",
+                    "self_test.layers = Schichten\nextra[one] = {0} Stück\nextra[other] = {0} Stücke\n"
+                ),
+                ("eo", "self_test.layers = Tavoloj\n@decimal_separator = ,\n")
+            ]
+        );
+        let system = catalog_dir(
+            "system",
+            &[("de_AT", "self_test.layers = Lagen\nonly.system = Ja\n")]
+        );
+        let dirs = [user.clone(), system.clone()];
+        let mut translator = Translator::load(&["de_AT.UTF-8@euro"], &dirs);
+        let names: Vec<&str> = translator
+            .catalogs
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(names, ["de_AT", "de", "en"]);
+        let none = Args::default();
+        let mut translate = |key: &str, args: &Args| translator.translate(key, args).unwrap();
+        // The user's catalog wins over the system's, which wins over the less
+        // specific built in one, which wins over English.
+        assert_eq!(translate("self_test.layers", &none), "Schichten");
+        assert_eq!(translate("only.system", &none), "Ja");
+        assert_eq!(translate("self_test.workarea", &none), "Arbeitsbereich");
+        let count = |n| Args::positional(vec![Arg::Integer(n)]);
+        assert_eq!(translate("extra", &count(1)), "1 Stück");
+        assert_eq!(translate("extra", &count(3)), "3 Stücke");
+        assert_eq!(
+            translate(
+                "picker.dimensions",
+                &Args::positional(vec![Arg::Integer(3), Arg::Integer(4)])
+            ),
+            "3×4"
+        );
+        // A key no catalog has is shown as it is, and only logged once.
+        assert_eq!(translate("no.such.key", &count(1)), "no.such.key");
+        assert_eq!(translate("no.such.key", &none), "no.such.key");
+        assert_eq!(translator.missing.len(), 1);
+        assert_eq!(translator.decimal_separator(), ",");
+        // A language the client doesn't ship can be added by the user.
+        let mut esperanto = Translator::load(&["eo"], &dirs);
+        assert_eq!(esperanto.translate("self_test.layers", &none).unwrap(), "Tavoloj");
+        assert_eq!(esperanto.translate("self_test.commit", &none).unwrap(), "Commit");
+        assert_eq!(esperanto.decimal_separator(), ",");
+        let mut english = Translator::load::<&str>(&[], &dirs);
+        assert_eq!(english.translate("self_test.layers", &none).unwrap(), "Layers");
+        assert_eq!(english.decimal_separator(), ".");
+        fs::remove_dir_all(user).unwrap();
+        fs::remove_dir_all(system).unwrap();
+    }
+
+    #[test]
+    fn l10n_translate_lua() {
+        let lua = Lua::new();
+        with_translator(Translator::load(&["ru_RU.UTF-8"], &[]), || {
+            lua.context(|ctx| {
+                let translate = ctx.create_function(translate).unwrap();
+                ctx.globals().set("translate", translate).unwrap();
+                let failed: Vec<String> = ctx
+                    .load(
+                        r#"
+                        local failed = {}
+                        for _, count in ipairs({1, 2, 5, 21, 112}) do
+                            table.insert(failed, translate("self_test.failed", {count = count}))
+                        end
+                        table.insert(failed, translate("self_test.failed", {count = 3}, "pl_PL"))
+                        table.insert(failed, translate("self_test.failed", {count = 3}, "C"))
+                        return failed
+                        "#
+                    )
+                    .eval()
+                    .unwrap();
+                assert_eq!(
+                    failed,
+                    [
+                        "1 шаг не пройден",
+                        "2 шага не пройдены",
+                        "5 шагов не пройдено",
+                        "21 шаг не пройден",
+                        "112 шагов не пройдено",
+                        "3 kroki nie powiodły się",
+                        "3 steps failed"
+                    ]
+                );
+                let number: String = ctx
+                    .load(r#"return translate("{0} / {1}", {0.5, "x"})"#)
+                    .eval()
+                    .unwrap();
+                // A key that isn't in any catalog isn't formatted.
+                assert_eq!(number, "{0} / {1}");
+                let errors: Vec<String> = ctx
+                    .load(
+                        r#"
+                        local errors = {}
+                        for _, args in ipairs({{}, {[2] = 1}, {[0] = 1}, {{}}, {[true] = 1}}) do
+                            local ok, err = pcall(translate, "self_test.failed", args)
+                            table.insert(errors, ok and "ok" or tostring(err))
+                        end
+                        return errors
+                        "#
+                    )
+                    .eval()
+                    .unwrap();
+                assert!(errors[0]
+                    .contains("could not translate self_test.failed: no argument was given for {count}"));
+                assert!(errors[1].contains("the arguments skip position 1"));
+                assert!(errors[2].contains("the argument 0 isn't a position"));
+                assert!(errors[3].contains("the argument 1 must be a number"));
+                assert!(errors[4].contains("must be positions or names"));
+            });
+        });
+    }
+
+    #[test]
+    fn l10n_renders_cjk_message() {
+        with_translator(Translator::load(&["ja_JP.UTF-8"], &[]), || {
+            let args = Args {
+                positional: Vec::new(),
+                named: vec![("count".into(), Arg::Integer(2))]
+            };
+            let failed = message("self_test.failed", &args);
+            assert_eq!(failed, "2 個のステップが失敗しました");
+            // The font of the text renderer falls back to one that has the
+            // glyphs, and a message that can't be formatted is shown as its
+            // key rather than not at all.
+            assert_eq!(message("self_test.failed", &Args::default()), "self_test.failed");
+            let mut surface = ImageSurface::create(Format::ARgb32, 400, 40).unwrap();
+            let cr = cairo::Context::new(&surface);
+            text::select_font(
+                &cr,
+                &Font {
+                    family: "Sans".into(),
+                    size: 16.0
+                }
+            );
+            let extents = text::measure(&cr, &failed).unwrap();
+            assert!(extents.x_advance > text::measure(&cr, "2").unwrap().x_advance);
+            cr.set_source_rgb(1.0, 1.0, 1.0);
+            cr.move_to(0.0, extents.ascent);
+            text::draw_text(&cr, &failed);
+            drop(cr);
+            let data = surface.get_data().unwrap();
+            assert!(data.iter().any(|&byte| byte != 0));
+        });
+    }
+}
@@ -0,0 +1,202 @@
+//! Message catalogs, one per locale, written as `key = message` lines.
+//!
+//! ```text
+//! # Comments start with a hash.
+//! picker.dimensions = {0}×{1}
+//! self_test.failed[one] = {count} step failed
+//! self_test.failed[other] = {count} steps failed
+//! @decimal_separator = ,
+//! ```
+//!
+//! A key with a plural form in brackets gives the message for numbers of
+//! that form, and every plural message needs an `other` form. Keys
+//! starting with `@` are settings of the locale rather than messages.
+
+use std::collections::HashMap;
+
+use super::{
+    format::{FormatError, Template},
+    locale::Plural
+};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Message {
+    Text(Template),
+    Plural(HashMap<Plural, Template>)
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Catalog {
+    pub messages: HashMap<String, Message>,
+    pub decimal_separator: Option<String>
+}
+
+/// A line of a catalog that was left out.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LineError {
+    /// Counting from 1.
+    pub line: usize,
+    pub message: String
+}
+
+impl Catalog {
+    /// Parses a catalog, leaving out the lines that are broken so one
+    /// mistake doesn't lose the whole language.
+    pub fn parse(text: &str) -> (Self, Vec<LineError>) {
+        let mut catalog = Catalog::default();
+        let mut errors = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Err(message) = catalog.parse_line(line) {
+                errors.push(LineError {
+                    line: line_number,
+                    message
+                });
+            }
+        }
+        let incomplete: Vec<String> = catalog
+            .messages
+            .iter()
+            .filter(|(_, message)| match message {
+                Message::Plural(forms) => !forms.contains_key(&Plural::Other),
+                Message::Text(_) => false
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in incomplete {
+            catalog.messages.remove(&key);
+            errors.push(LineError {
+                line: 0,
+                message: format!("{} has plural forms but no [other] form", key)
+            });
+        }
+        errors.sort_by_key(|error| error.line);
+        (catalog, errors)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        let equals = line
+            .find('=')
+            .ok_or_else(|| "expected key = message".to_string())?;
+        let (key, message) = (line[..equals].trim(), line[equals + 1..].trim());
+        if key == "@decimal_separator" {
+            self.decimal_separator = Some(message.into());
+            return Ok(());
+        }
+        let (key, form) = match key.find('[') {
+            Some(open) if key.ends_with(']') => {
+                let name = &key[open + 1..key.len() - 1];
+                let form = Plural::from_name(name).ok_or_else(|| {
+                    format!(
+                        "{} isn't a plural form, expected one of {}",
+                        name,
+                        Plural::NAMES.join(", ")
+                    )
+                })?;
+                (key[..open].trim_end(), Some(form))
+            },
+            _ => (key, None)
+        };
+        if key.is_empty() || key.contains(|c: char| c.is_whitespace() || c == '[' || c == ']') {
+            return Err(format!("{:?} isn't a key", key));
+        }
+        let template = Template::parse(&unescape(message)).map_err(|err: FormatError| err.to_string())?;
+        match (form, self.messages.get_mut(key)) {
+            (None, _) => {
+                self.messages.insert(key.into(), Message::Text(template));
+            },
+            (Some(form), Some(Message::Plural(forms))) => {
+                forms.insert(form, template);
+            },
+            (Some(form), _) => {
+                let mut forms = HashMap::new();
+                forms.insert(form, template);
+                self.messages.insert(key.into(), Message::Plural(forms));
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the messages and settings of `other`, which replace those this
+    /// already has.
+    pub fn merge(&mut self, other: Catalog) {
+        self.messages.extend(other.messages);
+        if other.decimal_separator.is_some() {
+            self.decimal_separator = other.decimal_separator;
+        }
+    }
+}
+
+/// Messages are trimmed, so leading and trailing spaces and line breaks are
+/// written as `\s` and `\n`.
+fn unescape(message: &str) -> String {
+    let mut out = String::with_capacity(message.len());
+    let mut chars = message.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => out.push('\n'),
+            ('\\', Some('s')) => out.push(' '),
+            ('\\', Some('\\')) => out.push('\\'),
+            _ => {
+                out.push(c);
+                continue;
+            }
+        }
+        chars.next();
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn l10n_catalog_parse() {
+        let (catalog, errors) = Catalog::parse(
+            "# header\n\
+             \n\
+             a = one {0}\n\
+             b[one] = {count} thing\n\
+             b[few] = {count} things\n\
+             b[other] = {count} things\n\
+             c[one] = missing other\n\
+             broken\n\
+             d[lots] = x\n\
+             e = {open\n\
+             f = \\s\\\\n\\n\n\
+             @decimal_separator = ,\n"
+        );
+        assert_eq!(catalog.decimal_separator.as_ref().map(String::as_str), Some(","));
+        assert_eq!(
+            catalog.messages.get("a"),
+            Some(&Message::Text(Template::parse("one {0}").unwrap()))
+        );
+        match catalog.messages.get("b") {
+            Some(Message::Plural(forms)) => assert_eq!(forms.len(), 3),
+            other => panic!("b is {:?}", other)
+        }
+        assert_eq!(
+            catalog.messages.get("f"),
+            Some(&Message::Text(Template::parse(" \\n\n").unwrap()))
+        );
+        let lines: Vec<usize> = errors.iter().map(|error| error.line).collect();
+        assert_eq!(lines, [0, 8, 9, 10]);
+        assert_eq!(errors[0].message, "c has plural forms but no [other] form");
+        assert_eq!(errors[1].message, "expected key = message");
+        assert!(errors[2].message.starts_with("lots isn't a plural form"));
+        assert_eq!(errors[3].message, "the { at byte 0 isn't closed");
+        let mut merged = catalog.clone();
+        merged.merge(Catalog::parse("a = replaced").0);
+        assert_eq!(merged.messages.len(), catalog.messages.len());
+        assert_eq!(
+            merged.messages.get("a"),
+            Some(&Message::Text(Template::parse("replaced").unwrap()))
+        );
+        assert_eq!(merged.decimal_separator, catalog.decimal_separator);
+    }
+}
@@ -0,0 +1,277 @@
+//! Messages with placeholders for arguments, like `{0} of {total}`.
+//!
+//! A placeholder is a position, counting from 0, or a name, and may ask for
+//! a number of decimals like `{load:.2}`. Braces are written twice to be
+//! shown, `{{` and `}}`.
+
+use std::{error::Error, fmt};
+
+/// A value given for a placeholder.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arg {
+    Integer(i64),
+    Number(f64),
+    Text(String)
+}
+
+impl Arg {
+    /// The number that chooses the plural form of a message.
+    pub fn as_number(&self) -> Option<f64> {
+        match *self {
+            Arg::Integer(n) => Some(n as f64),
+            Arg::Number(n) => Some(n),
+            Arg::Text(_) => None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Args {
+    pub positional: Vec<Arg>,
+    pub named: Vec<(String, Arg)>
+}
+
+impl Args {
+    pub fn positional(positional: Vec<Arg>) -> Self {
+        Args {
+            positional,
+            named: Vec::new()
+        }
+    }
+
+    pub fn get(&self, name: &Name) -> Option<&Arg> {
+        match name {
+            Name::Index(index) => self.positional.get(*index),
+            Name::Named(name) => self
+                .named
+                .iter()
+                .find(|(other, _)| other == name)
+                .map(|(_, arg)| arg)
+        }
+    }
+
+    /// The number the plural form is chosen by: the `count` argument, or
+    /// else the first one.
+    pub fn count(&self) -> Option<f64> {
+        self.get(&Name::Named("count".into()))
+            .or_else(|| self.positional.first())
+            .and_then(Arg::as_number)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Name {
+    Index(usize),
+    Named(String)
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Name::Index(index) => write!(f, "{{{}}}", index),
+            Name::Named(name) => write!(f, "{{{}}}", name)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Piece {
+    Text(String),
+    Placeholder { name: Name, decimals: Option<usize> }
+}
+
+/// A message whose placeholders have been checked.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Template(Vec<Piece>);
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum FormatError {
+    /// A `{` isn't closed, at this byte offset.
+    Unterminated { offset: usize },
+    /// A `}` wasn't opened and isn't doubled, at this byte offset.
+    Unopened { offset: usize },
+    /// What's between the braces at this byte offset isn't a placeholder.
+    Invalid { offset: usize, placeholder: String },
+    /// No argument was given for a placeholder.
+    Missing { name: Name }
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FormatError::Unterminated { offset } => write!(f, "the {{ at byte {} isn't closed", offset),
+            FormatError::Unopened { offset } => {
+                write!(
+                    f,
+                    "the }} at byte {} isn't opened, write }}}} to show one",
+                    offset
+                )
+            },
+            FormatError::Invalid { offset, placeholder } => write!(
+                f,
+                "{{{}}} at byte {} isn't a position or a name",
+                placeholder, offset
+            ),
+            FormatError::Missing { name } => write!(f, "no argument was given for {}", name)
+        }
+    }
+}
+
+impl Error for FormatError {}
+
+impl Template {
+    pub fn parse(message: &str) -> Result<Self, FormatError> {
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        let mut chars = message.char_indices().peekable();
+        while let Some((offset, c)) = chars.next() {
+            match c {
+                '{' if chars.peek().map(|&(_, c)| c) == Some('{') => {
+                    chars.next();
+                    text.push('{');
+                },
+                '}' if chars.peek().map(|&(_, c)| c) == Some('}') => {
+                    chars.next();
+                    text.push('}');
+                },
+                '}' => return Err(FormatError::Unopened { offset }),
+                '{' => {
+                    let end = match message[offset..].find('}') {
+                        Some(end) => offset + end,
+                        None => return Err(FormatError::Unterminated { offset })
+                    };
+                    let placeholder = &message[offset + 1..end];
+                    if !text.is_empty() {
+                        pieces.push(Piece::Text(std::mem::replace(&mut text, String::new())));
+                    }
+                    pieces.push(
+                        parse_placeholder(placeholder).ok_or_else(|| FormatError::Invalid {
+                            offset,
+                            placeholder: placeholder.into()
+                        })?
+                    );
+                    while chars.peek().map_or(false, |&(next, _)| next <= end) {
+                        chars.next();
+                    }
+                },
+                c => text.push(c)
+            }
+        }
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+        Ok(Template(pieces))
+    }
+
+    /// Fills in the placeholders, writing numbers with `decimal_separator`.
+    pub fn format(&self, args: &Args, decimal_separator: &str) -> Result<String, FormatError> {
+        let mut out = String::new();
+        for piece in &self.0 {
+            match piece {
+                Piece::Text(text) => out.push_str(text),
+                Piece::Placeholder { name, decimals } => {
+                    let arg = args
+                        .get(name)
+                        .ok_or_else(|| FormatError::Missing { name: name.clone() })?;
+                    out.push_str(&format_arg(arg, *decimals, decimal_separator));
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+fn parse_placeholder(placeholder: &str) -> Option<Piece> {
+    let (name, decimals) = match placeholder.find(':') {
+        Some(colon) => {
+            let spec = &placeholder[colon + 1..];
+            if !spec.starts_with('.') {
+                return None;
+            }
+            (
+                &placeholder[..colon],
+                Some(spec[1..].parse().ok().filter(|&n| n <= 20)?)
+            )
+        },
+        None => (placeholder, None)
+    };
+    let name = if !name.is_empty() && name.bytes().all(|b| b.is_ascii_digit()) {
+        Name::Index(name.parse().ok()?)
+    } else if name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') &&
+        name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        Name::Named(name.into())
+    } else {
+        return None;
+    };
+    Some(Piece::Placeholder { name, decimals })
+}
+
+/// Writes an argument, with numbers rounded to `decimals` if there are any.
+pub fn format_arg(arg: &Arg, decimals: Option<usize>, decimal_separator: &str) -> String {
+    let number = match (arg, decimals) {
+        (Arg::Text(text), _) => return text.clone(),
+        (Arg::Integer(n), None) => return n.to_string(),
+        (Arg::Integer(n), Some(decimals)) => format!("{:.*}", decimals, *n as f64),
+        (Arg::Number(n), Some(decimals)) => format!("{:.*}", decimals, n),
+        (Arg::Number(n), None) => n.to_string()
+    };
+    number.replacen('.', decimal_separator, 1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn named(named: &[(&str, Arg)]) -> Args {
+        Args {
+            positional: Vec::new(),
+            named: named
+                .iter()
+                .map(|(name, arg)| (name.to_string(), arg.clone()))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn l10n_placeholders() {
+        let format = |message: &str, args: &Args| Template::parse(message)?.format(args, ",");
+        let args = Args::positional(vec![Arg::Integer(3), Arg::Text("b".into())]);
+        assert_eq!(format("{1}{0} {{x}} {0}}}", &args), Ok("b3 {x} 3}".into()));
+        let args = named(&[("load", Arg::Number(0.456)), ("cpu", Arg::Integer(2))]);
+        assert_eq!(
+            format("{cpu}: {load:.2} / {load} / {cpu:.1}", &args),
+            Ok("2: 0,46 / 0,456 / 2,0".into())
+        );
+        assert_eq!(format("", &args), Ok("".into()));
+        // Broken messages are caught before they're formatted.
+        assert_eq!(
+            Template::parse("open {0"),
+            Err(FormatError::Unterminated { offset: 5 })
+        );
+        assert_eq!(Template::parse("a } b"), Err(FormatError::Unopened { offset: 2 }));
+        for broken in &["{}", "{-1}", "{a b}", "{0:2}", "{0:.x}", "{1x}"] {
+            match Template::parse(broken) {
+                Err(FormatError::Invalid { offset: 0, .. }) => {},
+                other => panic!("{} parsed as {:?}", broken, other)
+            }
+        }
+        let missing = format("{0} and {name}", &Args::positional(vec![Arg::Integer(1)]));
+        assert_eq!(
+            missing,
+            Err(FormatError::Missing {
+                name: Name::Named("name".into())
+            })
+        );
+        assert_eq!(
+            missing.unwrap_err().to_string(),
+            "no argument was given for {name}"
+        );
+        assert_eq!(
+            format("{2}", &args).unwrap_err().to_string(),
+            "no argument was given for {2}"
+        );
+        assert_eq!(args.count(), None);
+        assert_eq!(named(&[("count", Arg::Integer(5))]).count(), Some(5.0));
+    }
+}
@@ -0,0 +1,252 @@
+//! Locale names, and what the client needs to know about a language without
+//! a locale database: how its plurals are chosen and how it writes decimals.
+
+/// The locale whose catalog has every message, used when no other has it.
+pub const FALLBACK: &str = "en";
+
+/// Which of its locales the user wants messages in, most preferred first,
+/// from the environment variables gettext reads.
+///
+/// `LANGUAGE` can list several languages, but like gettext it's ignored
+/// when the locale is `C`, where messages are never translated.
+pub fn from_env<F>(var: F) -> Vec<String>
+where
+    F: Fn(&str) -> Option<String>
+{
+    let var = |name: &str| var(name).filter(|value| !value.is_empty());
+    let locale = var("LC_ALL")
+        .or_else(|| var("LC_MESSAGES"))
+        .or_else(|| var("LANG"))
+        .unwrap_or_else(|| "C".into());
+    if is_untranslated(&locale) {
+        return Vec::new();
+    }
+    match var("LANGUAGE") {
+        Some(languages) => languages
+            .split(':')
+            .filter(|language| !language.is_empty() && !is_untranslated(language))
+            .map(String::from)
+            .collect(),
+        None => vec![locale]
+    }
+}
+
+fn is_untranslated(locale: &str) -> bool {
+    locale == "C" || locale == "POSIX" || locale.starts_with("C.")
+}
+
+/// The catalogs messages are looked up in for `locales`, most preferred
+/// first.
+///
+/// Each locale is tried from its most to its least specific form, so
+/// `sr_RS.UTF-8@latin` is looked up in `sr_RS@latin`, `sr_RS`, `sr@latin`
+/// and `sr`, and the fallback comes last.
+pub fn fallback_chain<S: AsRef<str>>(locales: &[S]) -> Vec<String> {
+    let mut chain: Vec<String> = Vec::new();
+    let mut push = |name: String| {
+        if !chain.contains(&name) {
+            chain.push(name);
+        }
+    };
+    for locale in locales {
+        let Locale {
+            language,
+            territory,
+            modifier
+        } = Locale::parse(locale.as_ref());
+        if language.is_empty() {
+            continue;
+        }
+        if let (Some(territory), Some(modifier)) = (territory, modifier) {
+            push(format!("{}_{}@{}", language, territory, modifier));
+        }
+        if let Some(territory) = territory {
+            push(format!("{}_{}", language, territory));
+        }
+        if let Some(modifier) = modifier {
+            push(format!("{}@{}", language, modifier));
+        }
+        push(language.into());
+    }
+    push(FALLBACK.into());
+    chain
+}
+
+/// The parts of a locale name like `sr_RS.UTF-8@latin`. The codeset isn't
+/// kept, catalogs are always UTF-8.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Locale<'a> {
+    pub language: &'a str,
+    pub territory: Option<&'a str>,
+    pub modifier: Option<&'a str>
+}
+
+impl<'a> Locale<'a> {
+    pub fn parse(name: &'a str) -> Self {
+        let (name, modifier) = split_off(name, '@');
+        let (name, _codeset) = split_off(name, '.');
+        let (language, territory) = split_off(name, '_');
+        Locale {
+            language,
+            territory,
+            modifier
+        }
+    }
+}
+
+fn split_off(name: &str, separator: char) -> (&str, Option<&str>) {
+    match name.find(separator) {
+        Some(index) => (
+            &name[..index],
+            Some(&name[index + 1..]).filter(|rest| !rest.is_empty())
+        ),
+        None => (name, None)
+    }
+}
+
+/// The forms a message can have for the number it's about, as CLDR names
+/// them.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Plural {
+    Zero,
+    One,
+    Two,
+    Few,
+    Many,
+    Other
+}
+
+impl Plural {
+    pub const NAMES: [&'static str; 6] = ["zero", "one", "two", "few", "many", "other"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "zero" => Some(Plural::Zero),
+            "one" => Some(Plural::One),
+            "two" => Some(Plural::Two),
+            "few" => Some(Plural::Few),
+            "many" => Some(Plural::Many),
+            "other" => Some(Plural::Other),
+            _ => None
+        }
+    }
+}
+
+/// The plural form of the language of `locale` for `n`.
+///
+/// Only whole numbers have forms other than `other`, and languages that
+/// aren't known are assumed to be like English.
+pub fn plural(locale: &str, n: f64) -> Plural {
+    if n.fract() != 0.0 || !n.is_finite() {
+        return Plural::Other;
+    }
+    let n = n.abs() as u64;
+    let (ten, hundred) = (n % 10, n % 100);
+    let few = (2..=4).contains(&ten) && !(12..=14).contains(&hundred);
+    match Locale::parse(locale).language {
+        "ja" | "zh" | "ko" | "vi" | "th" | "id" | "ms" => Plural::Other,
+        "fr" | "pt" if n <= 1 => Plural::One,
+        "ru" | "uk" | "be" | "sr" | "hr" | "bs" => {
+            if ten == 1 && hundred != 11 {
+                Plural::One
+            } else if few {
+                Plural::Few
+            } else {
+                Plural::Many
+            }
+        },
+        "pl" => {
+            if n == 1 {
+                Plural::One
+            } else if few {
+                Plural::Few
+            } else {
+                Plural::Many
+            }
+        },
+        "cs" | "sk" => match n {
+            1 => Plural::One,
+            2..=4 => Plural::Few,
+            _ => Plural::Other
+        },
+        _ if n == 1 => Plural::One,
+        _ => Plural::Other
+    }
+}
+
+/// What separates the whole part of a number from its fraction in the
+/// language of `locale`. A catalog can say otherwise.
+pub fn decimal_separator(locale: &str) -> &'static str {
+    match Locale::parse(locale).language {
+        "de" | "fr" | "es" | "it" | "pt" | "nl" | "sv" | "da" | "nb" | "nn" | "fi" | "ru" | "uk" | "be" |
+        "pl" | "cs" | "sk" | "sr" | "hr" | "bs" | "tr" | "id" => ",",
+        _ => "."
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn env<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.to_string())
+        }
+    }
+
+    #[test]
+    fn l10n_fallback_chain() {
+        assert_eq!(
+            fallback_chain(&["sr_RS.UTF-8@latin"]),
+            ["sr_RS@latin", "sr_RS", "sr@latin", "sr", "en"]
+        );
+        assert_eq!(
+            fallback_chain(&["de_AT.UTF-8", "fr"]),
+            ["de_AT", "de", "fr", "en"]
+        );
+        assert_eq!(fallback_chain(&["en_GB"]), ["en_GB", "en"]);
+        assert_eq!(fallback_chain::<&str>(&[]), ["en"]);
+        // LC_ALL wins over the rest, and LANGUAGE lists several.
+        let vars = [("LANG", "ru_RU.UTF-8"), ("LC_MESSAGES", "pl_PL.UTF-8")];
+        assert_eq!(from_env(env(&vars)), ["pl_PL.UTF-8"]);
+        let vars = [("LC_ALL", "ja_JP.UTF-8"), ("LC_MESSAGES", "pl_PL"), ("LANG", "")];
+        assert_eq!(from_env(env(&vars)), ["ja_JP.UTF-8"]);
+        let vars = [("LANG", "de_DE.UTF-8"), ("LANGUAGE", "uk:ru::C")];
+        assert_eq!(from_env(env(&vars)), ["uk", "ru"]);
+        // The C locale is never translated.
+        let vars = [("LC_ALL", "C.UTF-8"), ("LANGUAGE", "de")];
+        assert!(from_env(env(&vars)).is_empty());
+        assert!(from_env(env(&[])).is_empty());
+    }
+
+    #[test]
+    fn l10n_plural_rules() {
+        let forms = |locale: &str, numbers: &[f64]| -> Vec<Plural> {
+            numbers.iter().map(|&n| plural(locale, n)).collect()
+        };
+        use self::Plural::*;
+        let numbers = [
+            0.0, 1.0, 2.0, 4.0, 5.0, 11.0, 12.0, 21.0, 22.0, 25.0, 101.0, 111.0, 1.5
+        ];
+        assert_eq!(
+            forms("ru_RU", &numbers),
+            [Many, One, Few, Few, Many, Many, Many, One, Few, Many, One, Many, Other]
+        );
+        assert_eq!(forms("uk", &numbers), forms("ru", &numbers));
+        assert_eq!(
+            forms("pl", &numbers),
+            [Many, One, Few, Few, Many, Many, Many, Many, Few, Many, Many, Many, Other]
+        );
+        assert_eq!(
+            forms("cs", &numbers),
+            [Other, One, Few, Few, Other, Other, Other, Other, Other, Other, Other, Other, Other]
+        );
+        assert_eq!(forms("en", &[0.0, 1.0, 2.0, -1.0]), [Other, One, Other, One]);
+        assert_eq!(forms("fr", &[0.0, 1.0, 2.0]), [One, One, Other]);
+        assert_eq!(forms("ja_JP", &[1.0, 2.0]), [Other, Other]);
+        assert_eq!(decimal_separator("de_DE"), ",");
+        assert_eq!(decimal_separator("ja"), ".");
+    }
+}
@@ -0,0 +1,483 @@
+//! Sweeps for state kept for Lua objects that Lua lost without destroying
+//! them, e.g. a hidden drawin a widget forgot to remove.
+//!
+//! Every entry of a registry that keeps something for Lua is tagged with
+//! its owner. An entry the client owns, like a shown drawin or a started
+//! timer, is always kept: it's there until the client is done with it.
+//! An entry Lua owns is only kept while Lua can still reach it from
+//! somewhere else than the registry. Entries that are left over from
+//! something that was destroyed, like the focus request of a removed
+//! drawin, are always reclaimed.
+//!
+//! Whether Lua can reach an entry is found by marking everything that can
+//! be reached from the Lua registry, its globals and the stacks of its
+//! threads, like the garbage collector does, but in Lua with the debug
+//! library, so nothing is collected or finalized on the way. The registry
+//! being swept only counts with the entries the client owns. What Rust
+//! code holds doesn't count. Weak references don't either.
+//!
+//! A sweep runs every few minutes, see `awesome.set_leak_sweep`, and
+//! `awesome.leak_report()` returns what the last one reclaimed, with when
+//! and, if tracebacks are on, where it was created.
+
+use std::{
+    cell::RefCell,
+    fmt::{self, Write}
+};
+
+use glib::{Continue, SourceId};
+use rlua::{self, Function, Table, Value};
+
+use crate::clock;
+use crate::objects::{drawin, timer};
+use crate::scheduler::{self, Priority};
+
+/// How many minutes there are between sweeps, unless Lua sets it.
+const DEFAULT_INTERVAL: f64 = 10.0;
+
+type Category = for<'lua> fn(rlua::Context<'lua>) -> rlua::Result<Vec<Finding>>;
+
+/// The registries swept, in the order they're swept in. Imported buffers
+/// keep their drawin alive, so they go first.
+const CATEGORIES: [(&str, Category); 4] = [
+    ("timers", timer::sweep),
+    ("imported_buffers", drawin::sweep_imports),
+    ("drawins", drawin::sweep_drawins),
+    ("drawin_state", drawin::sweep_drawin_state)
+];
+
+thread_local! {
+    static SWEEPER: RefCell<Sweeper> = RefCell::new(Sweeper::default());
+}
+
+/// Who an entry of a registry belongs to.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Owner {
+    /// Lua, so it's kept while Lua refers to it.
+    Lua,
+    /// The client, for the reason, so it's kept.
+    Client(&'static str)
+}
+
+/// When and where something was created.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Created {
+    /// See `clock::now`.
+    pub time: f64,
+    /// The Lua traceback, if tracebacks were on.
+    pub traceback: Option<String>
+}
+
+impl Created {
+    /// Now, and here if tracebacks are on.
+    pub fn now(lua: rlua::Context) -> Self {
+        let tracebacks = SWEEPER.with(|sweeper| sweeper.borrow().tracebacks);
+        Created {
+            time: clock::now(),
+            traceback: if tracebacks { traceback(lua) } else { None }
+        }
+    }
+}
+
+/// Something a sweep reclaimed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub category: &'static str,
+    /// The id of the object or entry in its category.
+    pub id: usize,
+    /// Unknown for what's left over from something destroyed.
+    pub created: Option<Created>
+}
+
+#[derive(Debug, Default)]
+struct Report {
+    time: f64,
+    findings: Vec<Finding>
+}
+
+#[derive(Debug, Default)]
+struct Sweeper {
+    source: Option<SourceId>,
+    tracebacks: bool,
+    last: Option<Report>,
+    /// How many entries of every category were reclaimed since the start.
+    totals: [u64; CATEGORIES.len()]
+}
+
+/// Starts sweeping every few minutes.
+pub fn init(_: rlua::Context) -> rlua::Result<()> {
+    schedule(DEFAULT_INTERVAL);
+    Ok(())
+}
+
+/// Sweeps every `minutes`, or never if it's 0.
+fn schedule(minutes: f64) {
+    let old = SWEEPER.with(|sweeper| sweeper.borrow_mut().source.take());
+    if let Some(source) = old {
+        glib::source_remove(source);
+    }
+    if minutes == 0.0 {
+        return;
+    }
+    let seconds = (minutes * 60.0).round().max(1.0) as u32;
+    let source = glib::timeout_add_seconds(seconds, || {
+        scheduler::defer(Priority::Idle, |lua| sweep(lua).map(|_| ()));
+        Continue(true)
+    });
+    SWEEPER.with(|sweeper| sweeper.borrow_mut().source = Some(source));
+}
+
+/// Sweeps every registry, returning what was reclaimed.
+pub fn sweep(lua: rlua::Context) -> rlua::Result<Vec<Finding>> {
+    let mut findings = Vec::new();
+    let mut counts = [0; CATEGORIES.len()];
+    for (index, &(name, category)) in CATEGORIES.iter().enumerate() {
+        let found = category(lua)?;
+        counts[index] = found.len();
+        if !found.is_empty() {
+            warn!("Reclaimed {} orphaned {}", found.len(), name);
+        }
+        findings.extend(found);
+    }
+    SWEEPER.with(|sweeper| {
+        let mut sweeper = sweeper.borrow_mut();
+        for (total, count) in sweeper.totals.iter_mut().zip(counts.iter()) {
+            *total += *count as u64;
+        }
+        sweeper.last = Some(Report {
+            time: clock::now(),
+            findings: findings.clone()
+        });
+    });
+    Ok(findings)
+}
+
+/// Which of `candidates`, entries of `registry`, Lua can reach from
+/// somewhere else than `registry`. Its other entries are reached.
+pub fn reachable<'lua>(
+    lua: rlua::Context<'lua>,
+    registry: Table<'lua>,
+    candidates: Vec<Value<'lua>>
+) -> rlua::Result<Vec<bool>> {
+    let count = candidates.len();
+    let candidates = lua.create_sequence_from(candidates)?;
+    let reached: Table = lua
+        .load(REACHABLE)
+        .set_name("leaks.lua")?
+        .call((registry, candidates))?;
+    (1..=count)
+        .map(|index| Ok(reached.raw_get::<_, Option<bool>>(index)?.unwrap_or(false)))
+        .collect()
+}
+
+/// Marks what can be reached, see `reachable`.
+const REACHABLE: &str = r#"
+local registry, candidates = ...
+if type(debug) ~= "table" then
+    -- Without the debug library nothing can be told apart.
+    local all = {}
+    for i = 1, #candidates do all[i] = true end
+    return all
+end
+local getinfo, getlocal, getmetatable = debug.getinfo, debug.getlocal, debug.getmetatable
+local getupvalue, getuservalue = debug.getupvalue, debug.getuservalue
+local current = coroutine.running()
+local this = getinfo(1, "f").func
+local index, reached, pending, ephemerons = {}, {}, {}, {}
+for i, candidate in ipairs(candidates) do
+    index[candidate] = i
+end
+local seen = { [registry] = true, [candidates] = true, [index] = true, [reached] = true }
+
+local function collectable(value)
+    local kind = type(value)
+    return kind == "table" or kind == "function" or kind == "userdata" or kind == "thread"
+end
+
+local function mark(value)
+    if collectable(value) and not seen[value] then
+        seen[value] = true
+        if index[value] then
+            reached[index[value]] = true
+        end
+        pending[#pending + 1] = value
+    end
+end
+
+-- The frames of this chunk and of what it calls are skipped.
+local function mark_stack(thread)
+    local current_thread = thread == current
+    local level = current_thread and 1 or 0
+    local skipping = current_thread
+    while true do
+        local info
+        if current_thread then info = getinfo(level, "f") else info = getinfo(thread, level, "f") end
+        if not info then
+            return
+        end
+        if skipping then
+            skipping = info.func ~= this
+            level = level + 1
+            goto continue
+        end
+        mark(info.func)
+        local i = 1
+        while true do
+            local name, value
+            if current_thread then name, value = getlocal(level, i) else name, value = getlocal(thread, level, i) end
+            if not name then
+                break
+            end
+            mark(value)
+            i = i + 1
+        end
+        level = level + 1
+        ::continue::
+    end
+end
+
+local function traverse(value)
+    local kind = type(value)
+    local meta = getmetatable(value)
+    mark(meta)
+    if kind == "table" then
+        local mode = type(meta) == "table" and rawget(meta, "__mode")
+        local weak_keys = type(mode) == "string" and mode:find("k") ~= nil
+        local weak_values = type(mode) == "string" and mode:find("v") ~= nil
+        for key, item in next, value do
+            if weak_keys and not weak_values then
+                -- Reached once the key is.
+                ephemerons[#ephemerons + 1] = { key, item }
+            else
+                if not weak_keys then mark(key) end
+                if not weak_values then mark(item) end
+            end
+        end
+    elseif kind == "function" then
+        local i = 1
+        while true do
+            local name, upvalue = getupvalue(value, i)
+            if name == nil then
+                break
+            end
+            mark(upvalue)
+            i = i + 1
+        end
+    elseif kind == "userdata" then
+        mark(getuservalue(value))
+    elseif kind == "thread" then
+        mark_stack(value)
+    end
+end
+
+for key, value in next, registry do
+    if not index[value] then
+        mark(key)
+        mark(value)
+    end
+end
+mark(debug.getregistry())
+mark(current)
+repeat
+    while #pending > 0 do
+        local value = pending[#pending]
+        pending[#pending] = nil
+        traverse(value)
+    end
+    local waiting = ephemerons
+    ephemerons = {}
+    for _, pair in ipairs(waiting) do
+        if not collectable(pair[1]) or seen[pair[1]] then
+            mark(pair[2])
+        else
+            ephemerons[#ephemerons + 1] = pair
+        end
+    end
+until #pending == 0
+return reached
+"#;
+
+/// `awesome.sweep_leaks()`, which sweeps now and returns the report.
+pub fn sweep_leaks(lua: rlua::Context, _: ()) -> rlua::Result<Value> {
+    sweep(lua)?;
+    leak_report(lua, ())
+}
+
+/// `awesome.set_leak_sweep(minutes, tracebacks)`, how often to sweep, never
+/// with 0, and whether to remember where things are created, which makes
+/// creating them slower.
+pub fn set_leak_sweep(_: rlua::Context, (minutes, tracebacks): (f64, Option<bool>)) -> rlua::Result<()> {
+    if !minutes.is_finite() || minutes < 0.0 {
+        return Err(rlua::Error::RuntimeError(format!(
+            "set_leak_sweep: the interval must be 0 or positive, got {}",
+            minutes
+        )));
+    }
+    if let Some(tracebacks) = tracebacks {
+        SWEEPER.with(|sweeper| sweeper.borrow_mut().tracebacks = tracebacks);
+    }
+    schedule(minutes);
+    Ok(())
+}
+
+/// `awesome.leak_report()`, what the last sweep reclaimed, or nil before
+/// the first one: `{ time = t, reclaimed = { drawins = 1, ... }, totals =
+/// { drawins = 3, ... }, findings = { { category = "drawins", id = 4,
+/// created = t, traceback = "..." }, ... } }`.
+pub fn leak_report(lua: rlua::Context, _: ()) -> rlua::Result<Value> {
+    SWEEPER.with(|sweeper| {
+        let sweeper = sweeper.borrow();
+        let report = match sweeper.last.as_ref() {
+            Some(report) => report,
+            None => return Ok(Value::Nil)
+        };
+        let table = lua.create_table()?;
+        table.set("time", report.time)?;
+        let reclaimed = lua.create_table()?;
+        let totals = lua.create_table()?;
+        for (&(name, _), &total) in CATEGORIES.iter().zip(sweeper.totals.iter()) {
+            let count = report
+                .findings
+                .iter()
+                .filter(|finding| finding.category == name)
+                .count();
+            reclaimed.set(name, count)?;
+            totals.set(name, total)?;
+        }
+        table.set("reclaimed", reclaimed)?;
+        table.set("totals", totals)?;
+        let findings = lua.create_table()?;
+        for (index, finding) in report.findings.iter().enumerate() {
+            let entry = lua.create_table()?;
+            entry.set("category", finding.category)?;
+            entry.set("id", finding.id)?;
+            if let Some(created) = finding.created.as_ref() {
+                entry.set("created", created.time)?;
+                entry.set("traceback", created.traceback.as_ref().map(String::as_str))?;
+            }
+            findings.set(index + 1, entry)?;
+        }
+        table.set("findings", findings)?;
+        Ok(Value::Table(table))
+    })
+}
+
+/// Writes how many entries of every category were reclaimed, for crash
+/// reports.
+pub fn write_totals(out: &mut dyn Write) -> fmt::Result {
+    SWEEPER
+        .try_with(|sweeper| {
+            let sweeper = match sweeper.try_borrow() {
+                Ok(sweeper) => sweeper,
+                Err(_) => return writeln!(out, "(in use)")
+            };
+            for (&(name, _), total) in CATEGORIES.iter().zip(sweeper.totals.iter()) {
+                writeln!(out, "{}: {}", name, total)?;
+            }
+            Ok(())
+        })
+        .unwrap_or(Ok(()))
+}
+
+fn traceback(lua: rlua::Context) -> Option<String> {
+    let debug = lua.globals().get::<_, Table>("debug").ok()?;
+    let traceback = debug.get::<_, Function>("traceback").ok()?;
+    traceback.call::<_, String>(()).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use rlua::{self, Lua};
+
+    use super::*;
+    use crate::objects::{drawable, drawin, screen::SCREENS_HANDLE, timer};
+
+    fn setup(lua: rlua::Context) -> rlua::Result<()> {
+        drawable::init(lua)?;
+        drawin::init(lua)?;
+        timer::init(lua)?;
+        lua.set_named_registry_value(SCREENS_HANDLE, lua.create_table()?)?;
+        let awesome = lua.create_table()?;
+        awesome.set("sweep_leaks", lua.create_function(sweep_leaks)?)?;
+        awesome.set("leak_report", lua.create_function(leak_report)?)?;
+        awesome.set("set_leak_sweep", lua.create_function(set_leak_sweep)?)?;
+        lua.globals().set("awesome", awesome)
+    }
+
+    #[test]
+    fn leaks_reachable() -> rlua::Result<()> {
+        let lua = unsafe { Lua::new_with_debug() };
+        lua.context(|lua| {
+            let (registry, candidates): (Table, Vec<Value>) = lua
+                .load(
+                    r#"
+local registry = {}
+for i = 1, 6 do registry[i] = {} end
+global = registry[1]
+local second = registry[2]
+keep_closure = function() return second end
+local weak = setmetatable({}, { __mode = "k" })
+-- Only reached through the value of a weak key that is reached.
+weak[global] = registry[3]
+weak[{}] = registry[4]
+weak_table = weak
+-- A cycle of candidates nothing else refers to.
+registry[5].other = registry[6]
+registry[6].other = registry[5]
+-- The entries the client owns are reached.
+registry[8] = {}
+registry[7] = { registry[8] }
+return registry, { registry[1], registry[2], registry[3], registry[4], registry[5], registry[6], registry[8] }
+"#
+                )
+                .eval()?;
+            let reached = reachable(lua, registry, candidates)?;
+            assert_eq!(reached, vec![true, true, true, false, false, false, true]);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn leaks_swept() -> rlua::Result<()> {
+        let lua = unsafe { Lua::new_with_debug() };
+        lua.context(|lua| {
+            setup(lua)?;
+            lua.load(
+                r#"
+assert(awesome.leak_report() == nil)
+awesome.set_leak_sweep(0, true)
+removed = {}
+local function track(d, name)
+    d:connect_signal("request::remove", function(_, reason) removed[#removed + 1] = name .. " " .. reason end)
+    return d
+end
+-- Healthy: referenced, hidden but shown by the client, or started.
+kept = track(drawin{}, "kept")
+track(drawin{ osk_auto = true }, "keyboard")
+track(drawin.apply_description{ id = "bar", width = 10, height = 10 }, "declared")
+local started = timer{ timeout = 60 }
+started:start()
+started = nil
+-- An orphan, which the handler doesn't keep alive.
+local function orphan()
+    track(drawin{}, "orphan")
+end
+orphan()
+local report = awesome.sweep_leaks()
+assert(report.reclaimed.drawins == 1, report.reclaimed.drawins)
+assert(report.reclaimed.timers == 0 and report.reclaimed.drawin_state == 0)
+assert(table.concat(removed, ", ") == "orphan orphaned", table.concat(removed, ", "))
+local finding = report.findings[1]
+assert(finding.category == "drawins" and finding.created ~= nil)
+assert(finding.traceback:find("orphan"), finding.traceback)
+-- Nothing more is found, but the totals stay.
+report = awesome.sweep_leaks()
+assert(#report.findings == 0 and report.totals.drawins == 1)
+assert(#drawin.reconcile{ { id = "bar", width = 10, height = 10 } }.created == 0)
+assert(#removed == 1)
+"#
+            )
+            .exec()
+        })
+    }
+}
@@ -212,8 +212,13 @@ fn init_libs(lua: rlua::Context, lib_paths: &[&str]) -> rlua::Result<()> {
     tag::init(lua)?;
     drawin::init(lua)?;
     drawable::init(lua)?;
+    timer::init(lua)?;
+    leaks::init(lua)?;
+    memory_pressure::init(lua)?;
     mousegrabber::init(lua)?;
     dbus::lua_init(lua)?;
+    lua_fns::init(lua)?;
+    menu::init(lua)?;
     Ok(())
 }
 
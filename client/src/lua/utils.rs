@@ -19,7 +19,6 @@ pub enum KeyboardModifiers {
 }
 
 /// Human readable versions of the standard modifier keys.
-#[allow(dead_code)]
 static MOD_NAMES: [&str; 8] = ["Shift", "Caps", "Control", "Alt", "Mod2", "Mod3", "Mod4", "Mod5"];
 /// Keycodes corresponding to various button events.
 static MOUSE_EVENTS: [evdev::Key; 5] = [BTN_LEFT, BTN_RIGHT, BTN_MIDDLE, BTN_SIDE, BTN_EXTRA];
@@ -51,7 +50,7 @@ pub fn mods_to_lua<'lua>(lua: rlua::Context<'lua>, mods: &[Keysym]) -> rlua::Res
             .into()
         );
     }
-    lua.create_table_from(mods_list.into_iter().enumerate())
+    lua.create_sequence_from(mods_list)
 }
 
 /// Convert a single number to a modifier list.
@@ -68,6 +67,17 @@ pub fn num_to_mods(modifiers: BitFlags<KeyboardModifiers>) -> Vec<Keysym> {
     res
 }
 
+/// Convert a single number to the names of the modifiers, e.g. for
+/// displaying a key binding.
+pub fn num_to_mod_names(modifiers: u32) -> Vec<&'static str> {
+    MOD_NAMES
+        .iter()
+        .enumerate()
+        .filter(|&(bit, _)| modifiers & (1 << bit) != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
 /// Convert a modifier list to a single number.
 #[allow(non_upper_case_globals)]
 pub fn mods_to_num(modifiers: Table) -> rlua::Result<BitFlags<KeyboardModifiers>> {
@@ -109,6 +119,16 @@ pub fn mods_to_rust(mods_table: Table) -> rlua::Result<Vec<Keysym>> {
     Ok(mods)
 }
 
+/// Convert a button code from Wayland to the number Lua uses for it, e.g. 1
+/// for the left button.
+pub fn button_to_lua(button: u32) -> Option<u32> {
+    const NUMBERS: [u32; 5] = [1, 3, 2, 8, 9];
+    MOUSE_EVENTS
+        .iter()
+        .position(|mouse_event| *mouse_event as u32 == button)
+        .map(|index| NUMBERS[index])
+}
+
 /// Convert a mouse event from Wayland to the representation Lua expcets
 // TODO Need a proper type for button_state
 pub fn mouse_events_to_lua(_: &Lua, button: u32, button_state: u32) -> rlua::Result<Vec<bool>> {
@@ -0,0 +1,52 @@
+//! Native versions of the `awful.client` helpers that need to know more
+//! about clients than Lua can see.
+
+use rlua::{self, Value};
+
+use crate::{
+    awesome,
+    objects::client::{self, Client}
+};
+
+pub fn init(lua: rlua::Context) -> rlua::Result<()> {
+    let module = lua.create_table()?;
+    module.set("get", lua.create_function(get)?)?;
+    module.set("icon_lookup", lua.create_function(icon_lookup)?)?;
+    module.set("visible", lua.create_function(visible)?)?;
+    super::extend_module(lua, "awful.client", module)
+}
+
+/// The clients on `screen`, or all of them, like `client.get`.
+fn get<'lua>(lua: rlua::Context<'lua>, screen: Value<'lua>) -> rlua::Result<Value<'lua>> {
+    let client_class = lua.globals().get::<_, Value>("client")?;
+    match super::index(lua, client_class, "get")? {
+        Value::Function(get) => get.call(screen),
+        _ => Err(rlua::Error::RuntimeError("client.get is not defined".into()))
+    }
+}
+
+/// Returns the icon of the application with the app id as a surface, or nil
+/// if it has none. The size defaults to `awesome.set_preferred_icon_size`.
+///
+/// This is for applications that aren't clients yet, e.g. in a launcher.
+fn icon_lookup<'lua>(
+    lua: rlua::Context<'lua>,
+    (app_id, size): (String, Option<u32>)
+) -> rlua::Result<Value<'lua>> {
+    let size = match size {
+        Some(size) => size,
+        None => awesome::preferred_icon_size(lua)?
+    };
+    match client::icon_lookup(&app_id, size) {
+        Some(icon) => awesome::surface_to_lua(lua, icon),
+        None => Ok(Value::Nil)
+    }
+}
+
+/// The clients that are sticky or on a selected tag, in the order they were
+/// managed.
+///
+/// Clients aren't assigned to screens yet, so the screen is ignored.
+fn visible<'lua>(lua: rlua::Context<'lua>, _screen: Value<'lua>) -> rlua::Result<Vec<Client<'lua>>> {
+    client::visible_clients(lua)
+}
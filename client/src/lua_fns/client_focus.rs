@@ -0,0 +1,359 @@
+//! The helpers to move the focus around, `client.focus.byidx(1)`.
+//!
+//! `client.focus` itself is the focused client, which has the helpers as
+//! well. It's nil while no client is focused, so they are in
+//! `awful.client.focus` too, like they are in Awesome.
+
+use rlua;
+
+use crate::area::Area;
+use crate::objects::{
+    client::{self, Client},
+    tag::{Tag, TAG_LIST}
+};
+
+pub fn init(lua: rlua::Context) -> rlua::Result<()> {
+    let focus = lua.create_table()?;
+    let filter = lua.create_table()?;
+    filter.set("currently_tagged", lua.create_function(currently_tagged)?)?;
+    focus.set("filter", filter)?;
+    focus.set("byidx", lua.create_function(byidx)?)?;
+    focus.set("bydirection", lua.create_function(bydirection)?)?;
+    lua.set_named_registry_value(client::FOCUS_HELPERS, focus.clone())?;
+    super::extend_module(lua, "awful.client.focus", focus)
+}
+
+/// The direction to move the focus in with `bydirection`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Direction {
+    Up,
+    Down,
+    Left,
+    Right
+}
+
+impl Direction {
+    fn from_str(direction: &str) -> rlua::Result<Self> {
+        match direction {
+            "up" => Ok(Direction::Up),
+            "down" => Ok(Direction::Down),
+            "left" => Ok(Direction::Left),
+            "right" => Ok(Direction::Right),
+            _ => Err(rlua::Error::RuntimeError(format!(
+                "invalid direction \"{}\", expected up, down, left or right",
+                direction
+            )))
+        }
+    }
+}
+
+/// Whether the client is on a selected tag.
+fn currently_tagged<'lua>(lua: rlua::Context<'lua>, client: Client<'lua>) -> rlua::Result<bool> {
+    is_currently_tagged(lua, &client)
+}
+
+/// Focuses the client `n` steps away from the focused client (or `client`
+/// if given) among the clients on the selected tags, wrapping around.
+///
+/// Returns the newly focused client.
+fn byidx<'lua>(
+    lua: rlua::Context<'lua>,
+    (n, client): (i64, Option<Client<'lua>>)
+) -> rlua::Result<Option<Client<'lua>>> {
+    let visible = client::visible_clients(lua)?;
+    if visible.is_empty() {
+        return Ok(None);
+    }
+    let current = match client {
+        Some(client) => Some(client),
+        None => client::focused(lua)?
+    };
+    let index = match current.and_then(|current| visible.iter().position(|c| *c == current)) {
+        Some(index) => (index as i64 + n).rem_euclid(visible.len() as i64) as usize,
+        None => 0
+    };
+    let target = visible[index].clone();
+    target.activate(lua)?;
+    Ok(Some(target))
+}
+
+/// Focuses the closest client in the given direction of the focused client
+/// (or `client` if given).
+///
+/// Returns the newly focused client, or nil if there was none in that
+/// direction.
+fn bydirection<'lua>(
+    lua: rlua::Context<'lua>,
+    (direction, client): (String, Option<Client<'lua>>)
+) -> rlua::Result<Option<Client<'lua>>> {
+    let direction = Direction::from_str(&direction)?;
+    let current = match client {
+        Some(client) => client,
+        None => match client::focused(lua)? {
+            Some(client) => client,
+            None => return Ok(None)
+        }
+    };
+    let from = current.get_geometry()?;
+    let mut best: Option<(f64, Client)> = None;
+    for candidate in client::visible_clients(lua)? {
+        if candidate == current {
+            continue;
+        }
+        let distance = match distance_in_direction(direction, from, candidate.get_geometry()?) {
+            Some(distance) => distance,
+            None => continue
+        };
+        if best.as_ref().map(|&(best, _)| distance < best).unwrap_or(true) {
+            best = Some((distance, candidate));
+        }
+    }
+    match best {
+        Some((_, target)) => {
+            target.activate(lua)?;
+            Ok(Some(target))
+        },
+        None => Ok(None)
+    }
+}
+
+fn is_currently_tagged<'lua>(lua: rlua::Context<'lua>, client: &Client<'lua>) -> rlua::Result<bool> {
+    for tag in lua.named_registry_value::<str, Vec<Tag>>(TAG_LIST)? {
+        if tag.selected()? && tag.client_index(client)?.is_some() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// How far away `to` is from `from` in the given direction, or `None` if
+/// it isn't in that direction at all.
+///
+/// This measures from the edge of `from` facing the direction to the
+/// opposite edge of `to`, the same way Awesome does.
+fn distance_in_direction(direction: Direction, from: Area, to: Area) -> Option<f64> {
+    let (mut from_x, mut from_y) = (from.origin.x as f64, from.origin.y as f64);
+    let (mut to_x, mut to_y) = (to.origin.x as f64, to.origin.y as f64);
+    match direction {
+        Direction::Up => to_y += to.size.height as f64,
+        Direction::Down => from_y += from.size.height as f64,
+        Direction::Left => to_x += to.size.width as f64,
+        Direction::Right => from_x += from.size.width as f64
+    }
+    let in_direction = match direction {
+        Direction::Up => to_y <= from_y,
+        Direction::Down => from_y <= to_y,
+        Direction::Left => to_x <= from_x,
+        Direction::Right => from_x <= to_x
+    };
+    if in_direction {
+        Some(((to_x - from_x).powi(2) + (to_y - from_y).powi(2)).sqrt())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rlua::{self, Lua};
+
+    use super::super::init;
+    use crate::objects::{
+        client::{self, Client},
+        tag
+    };
+    use crate::wayland_obj::{test_server::TestServer, LAYER_SHELL_MAX_VERSION};
+
+    const SETUP: &str = r#"
+local t = tag{ activated = true, selected = true }
+left = client{}
+left:geometry{ x = 0, y = 0, width = 100, height = 100 }
+right = client{}
+right:geometry{ x = 100, y = 0, width = 100, height = 100 }
+below = client{}
+below:geometry{ x = 0, y = 100, width = 100, height = 100 }
+hidden = client{}
+t:clients({ left, right, below })
+package.loaded["awful.client.focus"] = {}
+focus = require("awful.client.focus")
+"#;
+
+    fn setup(lua: rlua::Context) -> rlua::Result<()> {
+        tag::init(lua)?;
+        client::init(lua)?;
+        init(lua)?;
+        lua.load(SETUP).exec()?;
+        let globals = lua.globals();
+        let clients = ["left", "right", "below", "hidden"]
+            .iter()
+            .map(|name| globals.get::<_, Client>(*name))
+            .collect::<rlua::Result<Vec<_>>>()?;
+        lua.set_named_registry_value(client::CLIENTS_HANDLE, clients)
+    }
+
+    #[test]
+    fn focus_signals() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|ctx| {
+            setup(ctx)?;
+            ctx.load(
+                r#"
+local events = {}
+left:connect_signal("focus", function() table.insert(events, "focus left") end)
+left:connect_signal("unfocus", function() table.insert(events, "unfocus left") end)
+client.connect_signal("focus", function(c)
+    if c == right then table.insert(events, "focus right") end
+end)
+assert(client.focus == nil)
+client.focus = left
+assert(client.focus == left)
+client.focus = left
+right:activate()
+assert(client.focus == right)
+client.focus = nil
+assert(client.focus == nil)
+assert(#events == 3)
+assert(events[1] == "focus left")
+assert(events[2] == "unfocus left")
+assert(events[3] == "focus right")
+                "#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn focus_currently_tagged() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|ctx| {
+            setup(ctx)?;
+            ctx.load(
+                r#"
+assert(focus.filter.currently_tagged(left))
+assert(not focus.filter.currently_tagged(hidden))
+                "#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn focus_sticky() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|ctx| {
+            setup(ctx)?;
+            ctx.load(
+                r#"
+local changes = 0
+hidden:connect_signal("property::sticky", function() changes = changes + 1 end)
+assert(not hidden:isvisible())
+hidden.sticky = true
+hidden.sticky = true
+assert(hidden.sticky and hidden:isvisible())
+assert(not focus.filter.currently_tagged(hidden))
+assert(focus.byidx(-1, left) == hidden)
+-- It stays visible by moving to the selected tag.
+hidden.sticky = false
+assert(focus.filter.currently_tagged(hidden))
+assert(changes == 2)
+                "#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn focus_byidx() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|ctx| {
+            setup(ctx)?;
+            ctx.load(
+                r#"
+assert(focus.byidx(1) == left)
+assert(focus.byidx(1) == right)
+assert(focus.byidx(2) == left)
+assert(focus.byidx(-1) == below)
+assert(client.focus == below)
+                "#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn focus_bydirection() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|ctx| {
+            setup(ctx)?;
+            ctx.load(
+                r#"
+client.focus = left
+assert(focus.bydirection("right") == right)
+assert(focus.bydirection("right") == nil)
+assert(focus.bydirection("left") == left)
+assert(focus.bydirection("down") == below)
+assert(client.focus == below)
+assert(not pcall(focus.bydirection, "sideways"))
+                "#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn focus_helpers_of_client_focus() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|ctx| {
+            setup(ctx)?;
+            ctx.load(
+                r#"
+client.focus = left
+assert(client.focus.filter.currently_tagged(left))
+assert(not client.focus.filter.currently_tagged(hidden))
+assert(client.focus.byidx(1) == right)
+assert(client.focus.bydirection("left") == left)
+assert(client.focus.byidx(-1) == below)
+assert(client.focus == below)
+                "#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn focus_nil_releases_keyboard() -> rlua::Result<()> {
+        let mut server = TestServer::start(LAYER_SHELL_MAX_VERSION);
+        let lua = Lua::new();
+        lua.context(|ctx| {
+            setup(ctx)?;
+            let sent = |server: &mut TestServer| -> Vec<String> {
+                server.roundtrip();
+                server
+                    .take_requests()
+                    .iter()
+                    .filter(|request| request.interface.starts_with("zwlr_layer"))
+                    .map(ToString::to_string)
+                    .collect()
+            };
+            // The keyboard goes to a surface of ours on the top layer, once.
+            ctx.load("client.focus = left; client.focus = nil; client.focus = nil")
+                .exec()?;
+            let requests = sent(&mut server);
+            assert!(requests[0].starts_with("zwlr_layer_shell_v1.get_layer_surface("));
+            assert!(requests[0].ends_with(", 0, 2)"));
+            assert!(requests.contains(&"zwlr_layer_surface_v1.set_keyboard_interactivity(1)".to_string()));
+            assert_eq!(
+                requests
+                    .iter()
+                    .filter(|request| request.contains("get_layer_surface"))
+                    .count(),
+                1
+            );
+            ctx.load("assert(client.focus == nil)").exec()?;
+            // A focused client gets it back.
+            ctx.load("client.focus = right").exec()?;
+            assert_eq!(sent(&mut server), ["zwlr_layer_surface_v1.destroy()"]);
+            ctx.load("assert(client.focus == right)").exec()
+        })
+    }
+}
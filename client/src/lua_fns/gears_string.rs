@@ -0,0 +1,214 @@
+//! Native versions of the `gears.string` helpers widgets use to format
+//! their text.
+
+use rlua;
+
+pub fn init(lua: rlua::Context) -> rlua::Result<()> {
+    let string = lua.create_table()?;
+    string.set("split", lua.create_function(split)?)?;
+    string.set("startswith", lua.create_function(startswith)?)?;
+    string.set("endswith", lua.create_function(endswith)?)?;
+    string.set("trim", lua.create_function(trim)?)?;
+    string.set("format_size", lua.create_function(format_size)?)?;
+    string.set("xml_escape", lua.create_function(xml_escape)?)?;
+    string.set("xml_unescape", lua.create_function(xml_unescape)?)?;
+    string.set("linewrap", lua.create_function(linewrap)?)?;
+    super::extend_module(lua, "gears.string", string)
+}
+
+/// Splits the string on every occurrence of `sep`, which defaults to a
+/// newline. Empty pieces are kept.
+fn split(_: rlua::Context, (string, sep): (String, Option<String>)) -> rlua::Result<Vec<String>> {
+    let sep = sep.unwrap_or_else(|| "\n".into());
+    if sep.is_empty() {
+        return Err(rlua::Error::RuntimeError("separator can't be empty".into()));
+    }
+    Ok(string.split(sep.as_str()).map(String::from).collect())
+}
+
+fn startswith(_: rlua::Context, (string, prefix): (String, String)) -> rlua::Result<bool> {
+    Ok(string.starts_with(prefix.as_str()))
+}
+
+fn endswith(_: rlua::Context, (string, suffix): (String, String)) -> rlua::Result<bool> {
+    Ok(string.ends_with(suffix.as_str()))
+}
+
+/// Removes leading and trailing whitespace.
+fn trim(_: rlua::Context, string: String) -> rlua::Result<String> {
+    Ok(string.trim().into())
+}
+
+/// Formats a number of bytes with binary units, e.g. "1.2 MiB".
+fn format_size(_: rlua::Context, bytes: f64) -> rlua::Result<String> {
+    const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+    if bytes.abs() < 1024.0 {
+        return Ok(format!("{} B", bytes));
+    }
+    let mut size = bytes / 1024.0;
+    let mut unit = 0;
+    while size.abs() >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    Ok(format!("{:.1} {}", size, UNITS[unit]))
+}
+
+/// Escapes the characters that have a meaning in Pango markup.
+fn xml_escape(_: rlua::Context, string: String) -> rlua::Result<String> {
+    let mut escaped = String::with_capacity(string.len());
+    for c in string.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '\'' => escaped.push_str("&apos;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c)
+        }
+    }
+    Ok(escaped)
+}
+
+/// Replaces XML entities with the characters they stand for.
+///
+/// Unknown entities are left alone.
+fn xml_unescape(_: rlua::Context, string: String) -> rlua::Result<String> {
+    let mut unescaped = String::with_capacity(string.len());
+    let mut rest = string.as_str();
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest.find(';').and_then(|end| {
+            let c = match &rest[1..end] {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "apos" => Some('\''),
+                "quot" => Some('"'),
+                name if name.starts_with("#x") => u32::from_str_radix(&name[2..], 16)
+                    .ok()
+                    .and_then(std::char::from_u32),
+                name if name.starts_with('#') => name[1..].parse().ok().and_then(std::char::from_u32),
+                _ => None
+            };
+            c.map(|c| (c, end))
+        });
+        match entity {
+            Some((c, end)) => {
+                unescaped.push(c);
+                rest = &rest[end + 1..];
+            },
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    Ok(unescaped)
+}
+
+/// Breaks the string into lines at most `width` characters long, breaking
+/// at word boundaries.
+///
+/// Words longer than `width` are put on a line of their own. Existing line
+/// breaks are kept.
+fn linewrap(_: rlua::Context, (string, width): (String, usize)) -> rlua::Result<Vec<String>> {
+    if width == 0 {
+        return Err(rlua::Error::RuntimeError("width must be positive".into()));
+    }
+    let mut lines = Vec::new();
+    for paragraph in string.lines() {
+        let mut line = String::new();
+        let mut line_width = 0;
+        for word in paragraph.split_whitespace() {
+            let word_width = word.chars().count();
+            if line_width > 0 && line_width + 1 + word_width > width {
+                lines.push(line);
+                line = String::new();
+                line_width = 0;
+            }
+            if line_width > 0 {
+                line.push(' ');
+                line_width += 1;
+            }
+            line.push_str(word);
+            line_width += word_width;
+        }
+        lines.push(line);
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod test {
+    use rlua::{self, Lua};
+
+    use super::super::init;
+
+    fn run(code: &str) -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|ctx| {
+            init(ctx)?;
+            ctx.load(r#"package.loaded["gears.string"] = {}; gstring = require("gears.string")"#)
+                .exec()?;
+            ctx.load(code).exec()
+        })
+    }
+
+    #[test]
+    fn gears_string_split() -> rlua::Result<()> {
+        run(r#"
+local parts = gstring.split("a,b,,c", ",")
+assert(#parts == 4 and parts[1] == "a" and parts[3] == "" and parts[4] == "c")
+local lines = gstring.split("one\ntwo")
+assert(#lines == 2 and lines[2] == "two")
+assert(not pcall(gstring.split, "abc", ""))
+        "#)
+    }
+
+    #[test]
+    fn gears_string_affixes() -> rlua::Result<()> {
+        run(r#"
+assert(gstring.startswith("volume: 50%", "volume"))
+assert(not gstring.startswith("volume", "volume: 50%"))
+assert(gstring.endswith("volume: 50%", "%"))
+assert(not gstring.endswith("volume: 50%", "volume"))
+assert(gstring.trim("  \t padded \n") == "padded")
+        "#)
+    }
+
+    #[test]
+    fn gears_string_format_size() -> rlua::Result<()> {
+        run(r#"
+assert(gstring.format_size(512) == "512 B")
+assert(gstring.format_size(1024) == "1.0 KiB")
+assert(gstring.format_size(1258291) == "1.2 MiB")
+assert(gstring.format_size(5 * 1024 ^ 3) == "5.0 GiB")
+        "#)
+    }
+
+    #[test]
+    fn gears_string_xml() -> rlua::Result<()> {
+        run(r#"
+local escaped = gstring.xml_escape([[<b>"Tom" & 'Jerry'</b>]])
+assert(escaped == "&lt;b&gt;&quot;Tom&quot; &amp; &apos;Jerry&apos;&lt;/b&gt;")
+assert(gstring.xml_unescape(escaped) == [[<b>"Tom" & 'Jerry'</b>]])
+assert(gstring.xml_unescape("&#65;&#x42; &unknown; & done") == "AB &unknown; & done")
+        "#)
+    }
+
+    #[test]
+    fn gears_string_linewrap() -> rlua::Result<()> {
+        run(r#"
+local lines = gstring.linewrap("the quick brown fox jumps", 10)
+assert(#lines == 3)
+assert(lines[1] == "the quick")
+assert(lines[2] == "brown fox")
+assert(lines[3] == "jumps")
+lines = gstring.linewrap("short\nsupercalifragilistic word", 5)
+assert(lines[1] == "short" and lines[2] == "supercalifragilistic" and lines[3] == "word")
+        "#)
+    }
+}
@@ -0,0 +1,368 @@
+//! Native version of `awful.hotkeys_popup`, a cheat sheet of the global key
+//! bindings.
+//!
+//! Bindings are listed if they have a `description`, under the heading of
+//! their `group`. The sheet is themed with `beautiful.hotkeys_bg`,
+//! `beautiful.hotkeys_fg` and `beautiful.hotkeys_font`.
+
+mod sheet;
+
+use cairo::{Context, FontSlant, FontWeight};
+use glib::translate::FromGlibPtrNone;
+use rlua::{self, Function, LightUserData, Table, ToLua, Value};
+
+use crate::area::{Area, Origin, Size};
+use crate::common::{
+    color::{self, Color},
+    font::{self, Font}
+};
+use crate::objects::{
+    client::Client,
+    drawin::Drawin,
+    key::Key,
+    screen::{Screen, SCREENS_HANDLE}
+};
+use crate::root::ROOT_KEYS_HANDLE;
+
+use self::sheet::{Binding, Group, Layout, Style};
+
+/// Handle to the table with the popup's drawin and how far it's scrolled.
+const POPUP: &str = "__hotkeys_popup";
+/// How much of the screen the popup covers.
+const SCREEN_RATIO: f64 = 0.9;
+/// How far the arrow keys scroll.
+const SCROLL_STEP: f64 = 40.0;
+
+const DEFAULT_BG: Color = Color {
+    red: 0.13,
+    green: 0.13,
+    blue: 0.13,
+    alpha: 0.95
+};
+const DEFAULT_FG: Color = Color {
+    red: 1.0,
+    green: 1.0,
+    blue: 1.0,
+    alpha: 1.0
+};
+const DEFAULT_FONT: &str = "Monospace";
+const DEFAULT_FONT_SIZE: f64 = 10.0;
+
+struct Theme {
+    bg: Color,
+    fg: Color,
+    font: Font
+}
+
+pub fn init(lua: rlua::Context) -> rlua::Result<()> {
+    let popup = lua.create_table()?;
+    popup.set("show_help", lua.create_function(show_help)?)?;
+    popup.set("hide", lua.create_function(|lua, ()| hide(lua))?)?;
+    let widget = lua.create_table()?;
+    widget.set("new", lua.create_function(new_widget)?)?;
+    popup.set("widget", widget)?;
+    super::extend_module(lua, "awful.hotkeys_popup", popup)
+}
+
+/// Shows the cheat sheet on the screen `s`, or the screen of the client `c`.
+///
+/// Any key hides it again, except for the arrow keys, Page Up, Page Down,
+/// Home and End which scroll it.
+fn show_help<'lua>(
+    lua: rlua::Context<'lua>,
+    (client, screen): (Option<Client<'lua>>, Option<Screen<'lua>>)
+) -> rlua::Result<()> {
+    let screen = match screen {
+        Some(screen) => screen.state()?.geometry,
+        None => screen_of(lua, client)?
+    };
+    let size = Size {
+        width: (screen.size.width as f64 * SCREEN_RATIO) as u32,
+        height: (screen.size.height as f64 * SCREEN_RATIO) as u32
+    };
+    let geometry = Area {
+        origin: Origin {
+            x: screen.origin.x + (screen.size.width - size.width) as i32 / 2,
+            y: screen.origin.y + (screen.size.height - size.height) as i32 / 2
+        },
+        size
+    };
+    let popup = match lua.named_registry_value::<str, Option<Table>>(POPUP)? {
+        Some(popup) => popup,
+        None => {
+            let args = lua.create_table()?;
+            args.set("ontop", true)?;
+            let popup = lua.create_table()?;
+            popup.set("drawin", Drawin::new(lua, args)?)?;
+            popup.set("visible", false)?;
+            lua.set_named_registry_value(POPUP, popup.clone())?;
+            popup
+        }
+    };
+    if !popup.get::<_, bool>("visible")? {
+        let keygrabber = lua.globals().get::<_, Table>("keygrabber")?;
+        keygrabber
+            .get::<_, Function>("run")?
+            .call::<_, ()>(lua.create_function(handle_key)?)?;
+        popup.set("visible", true)?;
+    }
+    popup.set("scroll", 0.0)?;
+    let mut drawin = popup.get::<_, Drawin>("drawin")?;
+    let table = lua.create_table()?;
+    table.set("x", geometry.origin.x)?;
+    table.set("y", geometry.origin.y)?;
+    table.set("width", geometry.size.width)?;
+    table.set("height", geometry.size.height)?;
+    super::call_method(lua, drawin.clone().to_lua(lua)?, "geometry", table)?;
+    drawin.set_visible(lua, true)?;
+    render_popup(lua, &popup)
+}
+
+/// Hides the cheat sheet, if it is shown.
+fn hide(lua: rlua::Context) -> rlua::Result<()> {
+    let popup = match lua.named_registry_value::<str, Option<Table>>(POPUP)? {
+        Some(ref popup) if popup.get::<_, bool>("visible")? => popup.clone(),
+        _ => return Ok(())
+    };
+    popup.set("visible", false)?;
+    popup.get::<_, Drawin>("drawin")?.set_visible(lua, false)?;
+    let keygrabber = lua.globals().get::<_, Table>("keygrabber")?;
+    keygrabber.get::<_, Function>("stop")?.call(())
+}
+
+/// The keygrabber callback while the cheat sheet is shown.
+fn handle_key<'lua>(
+    lua: rlua::Context<'lua>,
+    (_, key, event): (Value<'lua>, String, String)
+) -> rlua::Result<()> {
+    if event != "press" {
+        return Ok(());
+    }
+    let popup = match lua.named_registry_value::<str, Option<Table>>(POPUP)? {
+        Some(popup) => popup,
+        None => return Ok(())
+    };
+    let scroll = popup.get::<_, f64>("scroll")?;
+    let page = popup.get::<_, Drawin>("drawin")?.get_geometry()?.size.height as f64;
+    let scroll = match key.as_str() {
+        "Up" => scroll - SCROLL_STEP,
+        "Down" => scroll + SCROLL_STEP,
+        "Page_Up" => scroll - page,
+        "Page_Down" => scroll + page,
+        "Home" => 0.0,
+        "End" => std::f64::MAX,
+        _ => return hide(lua)
+    };
+    popup.set("scroll", scroll)?;
+    render_popup(lua, &popup)
+}
+
+/// Draws the cheat sheet into the popup's drawin.
+fn render_popup<'lua>(lua: rlua::Context<'lua>, popup: &Table<'lua>) -> rlua::Result<()> {
+    let theme = theme(lua)?;
+    let groups = groups(lua)?;
+    let drawin = popup.get::<_, Drawin>("drawin")?;
+    let mut drawable = drawin.drawable()?;
+    let Size { width, height } = drawable.get_geometry()?.size;
+    {
+        let state = drawable.state()?;
+        let surface = match state.surface.as_ref() {
+            Some(surface) => surface,
+            None => return Ok(())
+        };
+        let cr = Context::new(surface);
+        let layout = layout(&cr, &theme, &groups, width as f64, height as f64);
+        let scroll = sheet::clamp_scroll(popup.get("scroll")?, layout.height, height as f64);
+        popup.set("scroll", scroll)?;
+        draw(&cr, &theme, &layout, width as f64, height as f64, scroll);
+    }
+    drawable.refresh(lua)
+}
+
+/// Makes a widget that shows the cheat sheet, for putting in a wibox.
+///
+/// It also has a `show_help` method, which opens the popup on the widget's
+/// screen.
+fn new_widget<'lua>(lua: rlua::Context<'lua>, args: Option<Table<'lua>>) -> rlua::Result<Table<'lua>> {
+    let require = lua.globals().get::<_, Function>("require")?;
+    let widget = match require.call::<_, Table>("wibox.widget.base") {
+        Ok(base) => base.get::<_, Function>("make_widget")?.call::<_, Table>(())?,
+        Err(_) => lua.create_table()?
+    };
+    let screen = match args {
+        Some(args) => args.get::<_, Value>("screen")?,
+        None => Value::Nil
+    };
+    widget.set("screen", screen)?;
+    widget.set("fit", lua.create_function(widget_fit)?)?;
+    widget.set("draw", lua.create_function(widget_draw)?)?;
+    widget.set(
+        "show_help",
+        lua.create_function(|lua, widget: Table| {
+            show_help(lua, (None, widget.get::<_, Option<Screen>>("screen")?))
+        })?
+    )?;
+    Ok(widget)
+}
+
+/// The widget takes all the space it's given.
+fn widget_fit<'lua>(
+    _: rlua::Context<'lua>,
+    (_, _, width, height): (Value<'lua>, Value<'lua>, f64, f64)
+) -> rlua::Result<(f64, f64)> {
+    Ok((width, height))
+}
+
+fn widget_draw<'lua>(
+    lua: rlua::Context<'lua>,
+    (_, _, cr, width, height): (Value<'lua>, Value<'lua>, Value<'lua>, f64, f64)
+) -> rlua::Result<()> {
+    // The context is an LGI object, which wraps the pointer to the cairo_t.
+    let cr = match super::index(lua, cr, "_native")? {
+        Value::LightUserData(LightUserData(ptr)) => unsafe { Context::from_glib_none(ptr as *mut _) },
+        _ => return Err(rlua::Error::RuntimeError("expected a cairo context".into()))
+    };
+    let theme = theme(lua)?;
+    let layout = layout(&cr, &theme, &groups(lua)?, width, height);
+    draw(&cr, &theme, &layout, width, height, 0.0);
+    Ok(())
+}
+
+/// The geometry of the screen the client is on, or of the first screen.
+fn screen_of<'lua>(lua: rlua::Context<'lua>, client: Option<Client<'lua>>) -> rlua::Result<Area> {
+    let screens = lua.named_registry_value::<str, Vec<Screen>>(SCREENS_HANDLE)?;
+    let geometries = screens
+        .iter()
+        .map(|screen| Ok(screen.state()?.geometry))
+        .collect::<rlua::Result<Vec<Area>>>()?;
+    let center = match client {
+        Some(client) => {
+            let Area { origin, size } = client.get_geometry()?;
+            Some((
+                origin.x + size.width as i32 / 2,
+                origin.y + size.height as i32 / 2
+            ))
+        },
+        None => None
+    };
+    let on_screen = |&(x, y): &(i32, i32)| {
+        geometries.iter().cloned().find(|screen| {
+            x >= screen.origin.x &&
+                y >= screen.origin.y &&
+                x < screen.origin.x + screen.size.width as i32 &&
+                y < screen.origin.y + screen.size.height as i32
+        })
+    };
+    center
+        .as_ref()
+        .and_then(on_screen)
+        .or_else(|| geometries.first().cloned())
+        .ok_or_else(|| rlua::Error::RuntimeError("there is no screen to show the hotkeys on".into()))
+}
+
+/// The global key bindings that have a description, grouped.
+fn groups(lua: rlua::Context) -> rlua::Result<Vec<Group>> {
+    let keys = match lua.named_registry_value::<str, Option<Table>>(ROOT_KEYS_HANDLE)? {
+        Some(keys) => keys,
+        None => return Ok(Vec::new())
+    };
+    let mut bindings = Vec::new();
+    for pair in keys.pairs::<Value, Key>() {
+        let (_, key) = pair?;
+        let description = key.description()?;
+        if description.is_empty() {
+            continue;
+        }
+        bindings.push((
+            key.group()?,
+            Binding {
+                keys: key.label()?,
+                description
+            }
+        ));
+    }
+    Ok(sheet::group(bindings))
+}
+
+fn theme(lua: rlua::Context) -> rlua::Result<Theme> {
+    let loaded = lua
+        .globals()
+        .get::<_, Table>("package")?
+        .get::<_, Table>("loaded")?;
+    let beautiful = loaded.get::<_, Option<Table>>("beautiful")?;
+    let value = |names: &[&str]| -> rlua::Result<Option<String>> {
+        if let Some(beautiful) = beautiful.as_ref() {
+            for name in names {
+                if let Value::String(value) = beautiful.get::<_, Value>(*name)? {
+                    return Ok(Some(value.to_str()?.into()));
+                }
+            }
+        }
+        Ok(None)
+    };
+    let color = |names: &[&str], default: Color| -> rlua::Result<Color> {
+        Ok(value(names)?
+            .and_then(|color| color::parse_color(&color))
+            .unwrap_or(default))
+    };
+    Ok(Theme {
+        bg: color(&["hotkeys_bg", "bg_normal"], DEFAULT_BG)?,
+        fg: color(&["hotkeys_fg", "fg_normal"], DEFAULT_FG)?,
+        font: font::parse_font(
+            &value(&["hotkeys_font", "font"])?.unwrap_or_else(|| DEFAULT_FONT.into()),
+            DEFAULT_FONT_SIZE
+        )
+    })
+}
+
+fn set_font(cr: &Context, theme: &Theme, style: Style) {
+    let weight = match style {
+        Style::Header | Style::Keys => FontWeight::Bold,
+        Style::Description => FontWeight::Normal
+    };
+    cr.select_font_face(&theme.font.family, FontSlant::Normal, weight);
+    cr.set_font_size(theme.font.size);
+}
+
+fn layout(cr: &Context, theme: &Theme, groups: &[Group], width: f64, height: f64) -> Layout {
+    set_font(cr, theme, Style::Description);
+    let line_height = cr.font_extents().height * 1.2;
+    let measure = |text: &str, style: Style| {
+        set_font(cr, theme, style);
+        cr.text_extents(text).x_advance
+    };
+    sheet::layout(groups, width, height, line_height, &measure)
+}
+
+fn draw(cr: &Context, theme: &Theme, layout: &Layout, width: f64, height: f64, scroll: f64) {
+    let Color {
+        red,
+        green,
+        blue,
+        alpha
+    } = theme.bg;
+    cr.save();
+    cr.rectangle(0.0, 0.0, width, height);
+    cr.clip();
+    cr.set_source_rgba(red, green, blue, alpha);
+    cr.paint();
+    cr.translate(0.0, -scroll);
+    let Color {
+        red,
+        green,
+        blue,
+        alpha
+    } = theme.fg;
+    for cell in &layout.cells {
+        set_font(cr, theme, cell.style);
+        // Descriptions are dimmed, so the keys stand out.
+        let alpha = match cell.style {
+            Style::Description => alpha * 0.75,
+            _ => alpha
+        };
+        cr.set_source_rgba(red, green, blue, alpha);
+        cr.move_to(cell.x, cell.y + cr.font_extents().ascent);
+        cr.show_text(&cell.text);
+    }
+    cr.restore();
+}
@@ -0,0 +1,240 @@
+//! The contents of the hotkeys cheat sheet: which bindings are listed,
+//! where they are placed and what they look like.
+//!
+//! Text is measured by the caller, so none of this needs a Cairo context.
+
+/// The group of bindings that didn't say which group they belong to.
+pub const DEFAULT_GROUP: &str = "Other";
+
+/// Space around the whole sheet.
+pub const MARGIN: f64 = 20.0;
+/// Space between two columns of groups.
+pub const COLUMN_GAP: f64 = 40.0;
+/// Space between the keys and the description of a binding.
+pub const KEY_GAP: f64 = 16.0;
+
+/// A key binding as it's listed on the sheet.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Binding {
+    pub keys: String,
+    pub description: String
+}
+
+/// The bindings listed under the same heading.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Group {
+    pub name: String,
+    pub bindings: Vec<Binding>
+}
+
+/// How a piece of text on the sheet is drawn.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Style {
+    Header,
+    Keys,
+    Description
+}
+
+/// A piece of text, placed with its top left corner at (`x`, `y`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cell {
+    pub x: f64,
+    pub y: f64,
+    pub text: String,
+    pub style: Style
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Layout {
+    pub cells: Vec<Cell>,
+    /// The height of all of the content, which can be more than is visible.
+    pub height: f64
+}
+
+/// Sorts the bindings into groups, ordered by name.
+///
+/// Bindings without a group go in `DEFAULT_GROUP`. Duplicates, e.g. the
+/// variants `awful.key` makes for the ignored modifiers, are listed once.
+pub fn group(bindings: Vec<(String, Binding)>) -> Vec<Group> {
+    let mut groups: Vec<Group> = Vec::new();
+    for (name, binding) in bindings {
+        let name = if name.is_empty() {
+            DEFAULT_GROUP.into()
+        } else {
+            name
+        };
+        let index = match groups.iter().position(|group| group.name == name) {
+            Some(index) => index,
+            None => {
+                groups.push(Group {
+                    name,
+                    bindings: Vec::new()
+                });
+                groups.len() - 1
+            }
+        };
+        let group = &mut groups[index];
+        if !group.bindings.contains(&binding) {
+            group.bindings.push(binding);
+        }
+    }
+    groups.sort_by(|a, b| a.name.cmp(&b.name));
+    groups
+}
+
+/// Places the groups in columns on a sheet `width` wide, where `height` of
+/// it is visible at a time.
+///
+/// Columns are filled from left to right. Once there's no room for another
+/// column the groups continue below, and the sheet has to be scrolled to
+/// see them.
+pub fn layout(
+    groups: &[Group],
+    width: f64,
+    height: f64,
+    line_height: f64,
+    measure: &dyn Fn(&str, Style) -> f64
+) -> Layout {
+    let mut layout = Layout::default();
+    let column_height = (height - 2.0 * MARGIN).max(line_height);
+    let (mut x, mut y) = (MARGIN, MARGIN);
+    let mut band_top = MARGIN;
+    let mut column_width: f64 = 0.0;
+    let mut bottom = MARGIN;
+    for group in groups {
+        let widest = |style: Style, text: &dyn Fn(&Binding) -> &str| {
+            group
+                .bindings
+                .iter()
+                .map(|binding| measure(text(binding), style))
+                .fold(0.0, f64::max)
+        };
+        let keys_width = widest(Style::Keys, &|binding| &binding.keys);
+        let description_width = widest(Style::Description, &|binding| &binding.description);
+        let block_width = measure(&group.name, Style::Header).max(keys_width + KEY_GAP + description_width);
+        let block_height = (group.bindings.len() + 1) as f64 * line_height;
+        if y > band_top && y + block_height > band_top + column_height {
+            x += column_width + COLUMN_GAP;
+            y = band_top;
+            column_width = 0.0;
+        }
+        if x > MARGIN && x + block_width > width - MARGIN {
+            band_top = bottom + line_height;
+            x = MARGIN;
+            y = band_top;
+            column_width = 0.0;
+        }
+        layout.cells.push(Cell {
+            x,
+            y,
+            text: group.name.clone(),
+            style: Style::Header
+        });
+        for (row, binding) in group.bindings.iter().enumerate() {
+            let row_y = y + (row + 1) as f64 * line_height;
+            layout.cells.push(Cell {
+                x,
+                y: row_y,
+                text: binding.keys.clone(),
+                style: Style::Keys
+            });
+            layout.cells.push(Cell {
+                x: x + keys_width + KEY_GAP,
+                y: row_y,
+                text: binding.description.clone(),
+                style: Style::Description
+            });
+        }
+        bottom = bottom.max(y + block_height);
+        y += block_height + line_height;
+        column_width = column_width.max(block_width);
+    }
+    layout.height = bottom + MARGIN;
+    layout
+}
+
+/// Keeps the scroll position within the content.
+pub fn clamp_scroll(scroll: f64, content_height: f64, visible_height: f64) -> f64 {
+    scroll.min(content_height - visible_height).max(0.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn binding(keys: &str, description: &str) -> Binding {
+        Binding {
+            keys: keys.into(),
+            description: description.into()
+        }
+    }
+
+    /// Every character is 10 pixels wide.
+    fn measure(text: &str, _: Style) -> f64 {
+        text.len() as f64 * 10.0
+    }
+
+    fn headers(layout: &Layout) -> Vec<(f64, f64)> {
+        layout
+            .cells
+            .iter()
+            .filter(|cell| cell.style == Style::Header)
+            .map(|cell| (cell.x, cell.y))
+            .collect()
+    }
+
+    #[test]
+    fn hotkeys_grouping() {
+        let groups = group(vec![
+            ("tag".into(), binding("Mod4+1", "view tag #1")),
+            ("".into(), binding("Mod4+x", "run prompt")),
+            ("client".into(), binding("Mod4+f", "fullscreen")),
+            ("tag".into(), binding("Mod4+1", "view tag #1")),
+            ("tag".into(), binding("Mod4+2", "view tag #2")),
+        ]);
+        let names: Vec<&str> = groups.iter().map(|group| group.name.as_str()).collect();
+        assert_eq!(names, vec![DEFAULT_GROUP, "client", "tag"]);
+        assert_eq!(
+            groups[2].bindings,
+            vec![binding("Mod4+1", "view tag #1"), binding("Mod4+2", "view tag #2")]
+        );
+    }
+
+    #[test]
+    fn hotkeys_layout_columns() {
+        let groups = group(vec![
+            ("a".into(), binding("Mod4+1", "one")),
+            ("a".into(), binding("Mod4+Shift+1", "one more")),
+            ("b".into(), binding("Mod4+2", "two")),
+        ]);
+        // Enough room to stack the groups.
+        let layout = layout(&groups, 1000.0, 1000.0, 20.0, &measure);
+        assert_eq!(headers(&layout), vec![(MARGIN, MARGIN), (MARGIN, MARGIN + 80.0)]);
+        assert_eq!(layout.height, MARGIN + 120.0 + MARGIN);
+        let description = &layout.cells[4];
+        assert_eq!(description.text, "one more");
+        assert_eq!(
+            (description.x, description.y),
+            (MARGIN + 120.0 + KEY_GAP, MARGIN + 40.0)
+        );
+        // Too short for both, so the second group gets its own column.
+        let layout = super::layout(&groups, 1000.0, 100.0, 20.0, &measure);
+        let column = MARGIN + 120.0 + KEY_GAP + 80.0 + COLUMN_GAP;
+        assert_eq!(headers(&layout), vec![(MARGIN, MARGIN), (column, MARGIN)]);
+    }
+
+    #[test]
+    fn hotkeys_layout_scrolling() {
+        let groups = group(vec![
+            ("a".into(), binding("Mod4+1", "one")),
+            ("b".into(), binding("Mod4+2", "two")),
+        ]);
+        // No room for a second column, so it continues below what's visible.
+        let layout = layout(&groups, 200.0, 60.0, 20.0, &measure);
+        assert_eq!(headers(&layout), vec![(MARGIN, MARGIN), (MARGIN, MARGIN + 60.0)]);
+        assert_eq!(layout.height, MARGIN + 100.0 + MARGIN);
+        assert_eq!(clamp_scroll(1000.0, layout.height, 60.0), 80.0);
+        assert_eq!(clamp_scroll(-5.0, layout.height, 60.0), 0.0);
+        assert_eq!(clamp_scroll(10.0, 50.0, 60.0), 0.0);
+    }
+}
@@ -0,0 +1,102 @@
+//! Native implementations of functions from the Awesome Lua libraries.
+//!
+//! The Awesome libraries (awful, gears, ...) are plain Lua modules that are
+//! loaded from the search path. Functions defined here are merged into those
+//! modules when they are `require`d, filling in anything the Lua module does
+//! not define itself. If the Lua module can't be loaded at all the native
+//! functions are used as the module instead.
+
+mod client;
+mod client_focus;
+mod gears_string;
+mod hotkeys_popup;
+mod rules;
+mod spawn;
+
+use rlua::{self, Function, Table, Value};
+
+/// Handle to the table of native module extensions, keyed by module name.
+const MODULE_EXTENSIONS: &str = "__module_extensions";
+/// Handle to the `require` function that was replaced by `require_extended`.
+const ORIGINAL_REQUIRE: &str = "__original_require";
+
+pub fn init(lua: rlua::Context) -> rlua::Result<()> {
+    let globals = lua.globals();
+    lua.set_named_registry_value(MODULE_EXTENSIONS, lua.create_table()?)?;
+    lua.set_named_registry_value(ORIGINAL_REQUIRE, globals.get::<_, Function>("require")?)?;
+    globals.set("require", lua.create_function(require_extended)?)?;
+    client::init(lua)?;
+    client_focus::init(lua)?;
+    gears_string::init(lua)?;
+    hotkeys_popup::init(lua)?;
+    rules::init(lua)?;
+    spawn::init(lua)?;
+    Ok(())
+}
+
+/// Registers the functions in `extension` to be merged into the Lua module
+/// with the given name whenever it is required.
+pub fn extend_module<'lua>(lua: rlua::Context<'lua>, name: &str, extension: Table<'lua>) -> rlua::Result<()> {
+    let extensions = lua.named_registry_value::<str, Table>(MODULE_EXTENSIONS)?;
+    extensions.set(name, extension)
+}
+
+/// Index a Lua value the way Lua would, respecting `__index` metamethods.
+///
+/// This is necessary for Awesome objects, whose methods and properties
+/// are only reachable through their metatables.
+pub fn index<'lua>(lua: rlua::Context<'lua>, obj: Value<'lua>, key: &str) -> rlua::Result<Value<'lua>> {
+    lua.load("local obj, key = ...; return obj[key]")
+        .set_name("index")?
+        .call((obj, key))
+}
+
+/// Calls the method `name` on an Awesome object, passing the object as the
+/// first argument like `obj:name(args)` would.
+pub fn call_method<'lua, A>(
+    lua: rlua::Context<'lua>,
+    obj: Value<'lua>,
+    name: &str,
+    args: A
+) -> rlua::Result<Value<'lua>>
+where
+    A: rlua::ToLua<'lua>
+{
+    match index(lua, obj.clone(), name)? {
+        Value::Function(method) => method.call((obj, args)),
+        _ => Err(rlua::Error::RuntimeError(format!(
+            "object has no method \"{}\"",
+            name
+        )))
+    }
+}
+
+/// Replacement for the global `require` that merges in native extensions.
+fn require_extended<'lua>(lua: rlua::Context<'lua>, name: String) -> rlua::Result<Value<'lua>> {
+    let require = lua.named_registry_value::<str, Function>(ORIGINAL_REQUIRE)?;
+    let extensions = lua.named_registry_value::<str, Table>(MODULE_EXTENSIONS)?;
+    let extension = match extensions.get::<_, Value>(name.as_str())? {
+        Value::Table(extension) => extension,
+        _ => return require.call(name)
+    };
+    let module = match require.call::<_, Value>(name.as_str()) {
+        Ok(Value::Table(module)) => module,
+        Ok(_) => return Ok(Value::Table(extension)),
+        Err(err) => {
+            warn!(
+                "Could not load Lua module {}, using native version: {}",
+                name, err
+            );
+            let package = lua.globals().get::<_, Table>("package")?;
+            package.get::<_, Table>("loaded")?.set(name, extension.clone())?;
+            return Ok(Value::Table(extension));
+        }
+    };
+    for pair in extension.pairs::<Value, Value>() {
+        let (key, value) = pair?;
+        if let Value::Nil = module.raw_get::<_, Value>(key.clone())? {
+            module.set(key, value)?;
+        }
+    }
+    Ok(Value::Table(module))
+}
@@ -0,0 +1,233 @@
+//! Native version of `awful.rules`, which sets properties of clients when
+//! they are managed, e.g. to move Firefox to a tag.
+//!
+//! A rule matches when every field of it matches the property of the client
+//! with that name. Strings are Lua patterns found with `string.find`, other
+//! values are compared.
+
+use rlua::{self, Function, Table, Value};
+
+use super::index;
+
+/// Handle to the native `apply`, to tell it apart from a Lua version.
+const NATIVE_APPLY: &str = "__rules_native_apply";
+
+pub fn init(lua: rlua::Context) -> rlua::Result<()> {
+    let apply = lua.create_function(apply)?;
+    lua.set_named_registry_value(NATIVE_APPLY, apply.clone())?;
+    let module = lua.create_table()?;
+    module.set("rules", lua.create_table()?)?;
+    module.set("match", lua.create_function(match_rule)?)?;
+    module.set("match_any", lua.create_function(match_any)?)?;
+    module.set("matches", lua.create_function(matches)?)?;
+    module.set("apply", apply)?;
+    super::extend_module(lua, "awful.rules", module)?;
+    match lua.globals().get::<_, Value>("client")? {
+        Value::Nil => Ok(()),
+        client_class => match index(lua, client_class, "connect_signal")? {
+            Value::Function(connect_signal) => {
+                connect_signal.call(("manage", lua.create_function(on_manage)?))
+            },
+            _ => Ok(())
+        }
+    }
+}
+
+/// Whether every field of `rule` matches the client.
+fn match_rule<'lua>(
+    lua: rlua::Context<'lua>,
+    (client, rule): (Value<'lua>, Option<Table<'lua>>)
+) -> rlua::Result<bool> {
+    let rule = match rule {
+        Some(rule) => rule,
+        None => return Ok(false)
+    };
+    for pair in rule.pairs::<String, Value>() {
+        let (field, wanted) = pair?;
+        let value = index(lua, client.clone(), &field)?;
+        if !value_matches(lua, value, wanted)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Whether any of the values listed for a field of `rule` matches the
+/// client.
+fn match_any<'lua>(
+    lua: rlua::Context<'lua>,
+    (client, rule): (Value<'lua>, Option<Table<'lua>>)
+) -> rlua::Result<bool> {
+    let rule = match rule {
+        Some(rule) => rule,
+        None => return Ok(false)
+    };
+    for pair in rule.pairs::<String, Table>() {
+        let (field, values) = pair?;
+        let value = index(lua, client.clone(), &field)?;
+        for wanted in values.sequence_values::<Value>() {
+            if value_matches(lua, value.clone(), wanted?)? {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Whether an entry of `awful.rules.rules` applies to the client: its
+/// `rule` or `rule_any` must match, and neither `except` nor `except_any`.
+fn matches<'lua>(
+    lua: rlua::Context<'lua>,
+    (client, entry): (Value<'lua>, Table<'lua>)
+) -> rlua::Result<bool> {
+    let included = match_rule(lua, (client.clone(), entry.get("rule")?))? ||
+        match_any(lua, (client.clone(), entry.get("rule_any")?))?;
+    Ok(included &&
+        !match_rule(lua, (client.clone(), entry.get("except")?))? &&
+        !match_any(lua, (client, entry.get("except_any")?))?)
+}
+
+/// Applies every entry of `awful.rules.rules` that matches the client, in
+/// order, setting its `properties` and calling its `callback`.
+fn apply<'lua>(lua: rlua::Context<'lua>, client: Value<'lua>) -> rlua::Result<()> {
+    let rules = match loaded_module(lua)? {
+        Some(module) => module.get::<_, Table>("rules")?,
+        None => return Ok(())
+    };
+    let set_property = lua
+        .load("local obj, key, value = ...; obj[key] = value")
+        .set_name("apply_rules")?
+        .into_function()?;
+    for entry in rules.sequence_values::<Table>() {
+        let entry = entry?;
+        if !matches(lua, (client.clone(), entry.clone()))? {
+            continue;
+        }
+        if let Some(properties) = entry.get::<_, Option<Table>>("properties")? {
+            for pair in properties.pairs::<Value, Value>() {
+                let (key, value) = pair?;
+                set_property.call::<_, ()>((client.clone(), key, value))?;
+            }
+        }
+        if let Some(callback) = entry.get::<_, Option<Function>>("callback")? {
+            callback.call::<_, ()>(client.clone())?;
+        }
+    }
+    Ok(())
+}
+
+/// Handler for the "manage" signal.
+///
+/// The rules only apply once `awful.rules` is required, and only when this
+/// is its `apply`: the Lua version applies them itself.
+fn on_manage<'lua>(lua: rlua::Context<'lua>, client: Value<'lua>) -> rlua::Result<()> {
+    let module = match loaded_module(lua)? {
+        Some(module) => module,
+        None => return Ok(())
+    };
+    let native = lua.named_registry_value::<str, Value>(NATIVE_APPLY)?;
+    if !equals(lua, module.get("apply")?, native)? {
+        return Ok(());
+    }
+    // One broken rule shouldn't stop the client from being managed.
+    if let Err(err) = apply(lua, client) {
+        warn!("Could not apply awful.rules: {}", err);
+    }
+    Ok(())
+}
+
+/// The `awful.rules` module, if it has been required.
+fn loaded_module(lua: rlua::Context) -> rlua::Result<Option<Table>> {
+    let package = lua.globals().get::<_, Table>("package")?;
+    package.get::<_, Table>("loaded")?.get("awful.rules")
+}
+
+/// Whether the value of a client property matches the value in a rule.
+fn value_matches<'lua>(
+    lua: rlua::Context<'lua>,
+    value: Value<'lua>,
+    wanted: Value<'lua>
+) -> rlua::Result<bool> {
+    match (value, wanted) {
+        (Value::String(value), Value::String(pattern)) => {
+            let find = lua
+                .globals()
+                .get::<_, Table>("string")?
+                .get::<_, Function>("find")?;
+            Ok(find.call::<_, Option<i64>>((value, pattern))?.is_some())
+        },
+        (value, wanted) => equals(lua, value, wanted)
+    }
+}
+
+fn equals<'lua>(lua: rlua::Context<'lua>, a: Value<'lua>, b: Value<'lua>) -> rlua::Result<bool> {
+    lua.load("local a, b = ...; return a == b")
+        .set_name("equals")?
+        .call((a, b))
+}
+
+#[cfg(test)]
+mod test {
+    use rlua::{self, Lua};
+
+    use super::super::init;
+
+    const FAKE_CLIENTS: &str = r#"
+package.loaded["awful.rules"] = {}
+rules = require("awful.rules")
+firefox = { class = "firefox", instance = "org.mozilla", name = "Mozilla Firefox", floating = false }
+vlc = { class = "vlc", instance = "vlc", name = "movie.mkv - VLC media player", floating = false }
+"#;
+
+    #[test]
+    fn rules_match() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|ctx| {
+            init(ctx)?;
+            ctx.load(FAKE_CLIENTS).exec()?;
+            ctx.load(
+                r#"
+assert(rules.match(firefox, { class = "firefox" }))
+assert(rules.match(firefox, { class = "fire", instance = "^org%." }))
+assert(not rules.match(firefox, { class = "firefox", name = "VLC" }))
+assert(rules.match(vlc, { name = "VLC media player$", floating = false }))
+assert(not rules.match(vlc, { floating = true }))
+assert(not rules.match(vlc, nil))
+assert(rules.match_any(vlc, { class = { "mpv", "vlc" } }))
+assert(not rules.match_any(firefox, { class = { "mpv", "vlc" } }))
+assert(rules.matches(firefox, { rule_any = { class = { "firefox" } } }))
+assert(not rules.matches(firefox, { rule = { class = "firefox" }, except = { name = "Mozilla" } }))
+assert(not rules.matches(firefox, {}))
+                "#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn rules_apply() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|ctx| {
+            init(ctx)?;
+            ctx.load(FAKE_CLIENTS).exec()?;
+            ctx.load(
+                r#"
+local called = {}
+rules.rules = {
+    { rule = {}, properties = { floating = false } },
+    { rule = { class = "vlc" }, properties = { floating = true } },
+    { rule = { class = "firefox" }, properties = { tag = "web" }, callback = function(c)
+        table.insert(called, c)
+    end },
+}
+rules.apply(vlc)
+rules.apply(firefox)
+assert(vlc.floating == true and vlc.tag == nil)
+assert(firefox.floating == false and firefox.tag == "web")
+assert(#called == 1 and called[1] == firefox)
+                "#
+            )
+            .exec()
+        })
+    }
+}
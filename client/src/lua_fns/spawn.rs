@@ -0,0 +1,288 @@
+//! Native versions of the `awful.spawn` helpers that need to look at the
+//! list of clients.
+
+use rlua::{self, Function, Table, Value};
+
+use super::{call_method, index};
+
+/// Handle to the list of rules `raise_or_spawn` waits to apply, one table
+/// with the `rules` and the `matcher` for each spawned command.
+const PENDING_RULES: &str = "__spawn_pending_rules";
+
+pub fn init(lua: rlua::Context) -> rlua::Result<()> {
+    let spawn = lua.create_table()?;
+    spawn.set("raise_or_spawn", lua.create_function(raise_or_spawn)?)?;
+    spawn.set("once", lua.create_function(once)?)?;
+    spawn.set("with_line_callback", lua.create_function(with_line_callback)?)?;
+    super::extend_module(lua, "awful.spawn", spawn)
+}
+
+/// Activates the first client `matcher` returns true for.
+///
+/// If no client matches then `cmd` is spawned instead and `rules` are applied
+/// to the first client matching `matcher` that is managed afterwards. The
+/// rules are only applied once, to that client.
+///
+/// Returns the activated client, or the result of `awesome.spawn`.
+fn raise_or_spawn<'lua>(
+    lua: rlua::Context<'lua>,
+    (cmd, rules, matcher): (Value<'lua>, Option<Table<'lua>>, Function<'lua>)
+) -> rlua::Result<Value<'lua>> {
+    if let Some(client) = find_client(lua, &matcher)? {
+        let args = lua.create_table()?;
+        args.set("context", "taskbar")?;
+        call_method(lua, client.clone(), "activate", args)?;
+        return Ok(client);
+    }
+    let awesome = lua.globals().get::<_, Value>("awesome")?;
+    let pid = match index(lua, awesome, "spawn")? {
+        Value::Function(spawn) => spawn.call::<_, Value>(cmd)?,
+        _ => return Err(rlua::Error::RuntimeError("awesome.spawn is not defined".into()))
+    };
+    if let Some(rules) = rules {
+        let pending = lua.create_table()?;
+        pending.set("rules", rules)?;
+        pending.set("matcher", matcher)?;
+        let pending_rules = pending_rules(lua)?;
+        pending_rules.set(pending_rules.raw_len() + 1, pending)?;
+    }
+    Ok(pid)
+}
+
+/// The rules waiting for their client to be managed.
+///
+/// The "manage" handler that applies them is connected when the list is
+/// made, so there's only ever one.
+fn pending_rules(lua: rlua::Context) -> rlua::Result<Table> {
+    if let Value::Table(pending_rules) = lua.named_registry_value::<str, Value>(PENDING_RULES)? {
+        return Ok(pending_rules);
+    }
+    let pending_rules = lua.create_table()?;
+    lua.set_named_registry_value(PENDING_RULES, pending_rules.clone())?;
+    let client_class = lua.globals().get::<_, Value>("client")?;
+    if let Value::Function(connect_signal) = index(lua, client_class, "connect_signal")? {
+        connect_signal.call::<_, ()>(("manage", lua.create_function(apply_rules)?))?;
+    }
+    Ok(pending_rules)
+}
+
+/// Like `raise_or_spawn`, but matches clients whose `app_id` is `class`.
+fn once<'lua>(
+    lua: rlua::Context<'lua>,
+    (cmd, class, rules): (Value<'lua>, String, Option<Table<'lua>>)
+) -> rlua::Result<Value<'lua>> {
+    let matcher = lua.create_function(match_app_id)?.bind(class)?;
+    raise_or_spawn(lua, (cmd, rules, matcher))
+}
+
+/// Spawns `cmd`, calling the `stdout`, `stderr` and `exit` functions of
+/// `callbacks` like `awesome.spawn_with_line_callback` does.
+fn with_line_callback<'lua>(
+    lua: rlua::Context<'lua>,
+    (cmd, callbacks): (Value<'lua>, Table<'lua>)
+) -> rlua::Result<Value<'lua>> {
+    let awesome = lua.globals().get::<_, Value>("awesome")?;
+    match index(lua, awesome, "spawn_with_line_callback")? {
+        Value::Function(spawn) => spawn.call((
+            cmd,
+            callbacks.get::<_, Value>("stdout")?,
+            callbacks.get::<_, Value>("stderr")?,
+            callbacks.get::<_, Value>("exit")?
+        )),
+        _ => Err(rlua::Error::RuntimeError(
+            "awesome.spawn_with_line_callback is not defined".into()
+        ))
+    }
+}
+
+/// Finds the first client in `awful.client.get()` that `matcher` returns
+/// true for.
+fn find_client<'lua>(
+    lua: rlua::Context<'lua>,
+    matcher: &Function<'lua>
+) -> rlua::Result<Option<Value<'lua>>> {
+    let require = lua.globals().get::<_, Function>("require")?;
+    let awful_client = require.call::<_, Value>("awful.client")?;
+    let clients = match index(lua, awful_client, "get")? {
+        Value::Function(get) => get.call::<_, Table>(())?,
+        _ => return Ok(None)
+    };
+    for client in clients.sequence_values::<Value>() {
+        let client = client?;
+        if matcher.call::<_, bool>(client.clone())? {
+            return Ok(Some(client));
+        }
+    }
+    Ok(None)
+}
+
+/// Handler for the "manage" signal connected by `pending_rules`.
+///
+/// Applies the first of the pending rules whose matcher matches the newly
+/// managed client, and forgets them.
+fn apply_rules<'lua>(lua: rlua::Context<'lua>, client: Value<'lua>) -> rlua::Result<()> {
+    let pending_rules = pending_rules(lua)?;
+    let len = pending_rules.raw_len();
+    let mut matched = None;
+    for index in 1..=len {
+        let pending = pending_rules.raw_get::<_, Table>(index)?;
+        if pending
+            .get::<_, Function>("matcher")?
+            .call::<_, bool>(client.clone())?
+        {
+            matched = Some((index, pending));
+            break;
+        }
+    }
+    let (index, pending) = match matched {
+        Some(matched) => matched,
+        None => return Ok(())
+    };
+    for index in index..len {
+        pending_rules.raw_set(index, pending_rules.raw_get::<_, Value>(index + 1)?)?;
+    }
+    pending_rules.raw_set(len, Value::Nil)?;
+    let set_property = lua
+        .load("local obj, key, value = ...; obj[key] = value")
+        .set_name("apply_rules")?
+        .into_function()?;
+    for pair in pending.get::<_, Table>("rules")?.pairs::<Value, Value>() {
+        let (key, value) = pair?;
+        set_property.call::<_, ()>((client.clone(), key, value))?;
+    }
+    Ok(())
+}
+
+fn match_app_id<'lua>(
+    lua: rlua::Context<'lua>,
+    (class, client): (String, Value<'lua>)
+) -> rlua::Result<bool> {
+    match index(lua, client, "app_id")? {
+        Value::String(app_id) => Ok(app_id.to_str()? == class),
+        _ => Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rlua::{self, Lua};
+
+    use super::super::init;
+
+    const FAKE_GLOBALS: &str = r#"
+spawned = {}
+activated = nil
+local clients = {
+    { app_id = "firefox", activate = function(c, args) activated = c; assert(args.context == "taskbar") end },
+    { app_id = "xterm", activate = function(c) activated = c end }
+}
+managers = {}
+client = {
+    get = function() return clients end,
+    connect_signal = function(name, func) table.insert(managers, { name = name, func = func }) end
+}
+awesome = {
+    spawn = function(cmd) table.insert(spawned, cmd); return 42 end,
+    spawn_with_line_callback = function(cmd, stdout, stderr, exit)
+        table.insert(spawned, cmd)
+        stdout("line")
+        exit("exit", 0)
+        return 43
+    end
+}
+package.loaded["awful.spawn"] = {}
+"#;
+
+    #[test]
+    fn raise_or_spawn_raises() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|ctx| {
+            init(ctx)?;
+            ctx.load(FAKE_GLOBALS).exec()?;
+            ctx.load(
+                r#"
+local spawn = require("awful.spawn")
+local c = spawn.raise_or_spawn("xterm", nil, function(c) return c.app_id == "xterm" end)
+assert(c == activated)
+assert(c.app_id == "xterm")
+assert(#spawned == 0)
+                "#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn once_spawns() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|ctx| {
+            init(ctx)?;
+            ctx.load(FAKE_GLOBALS).exec()?;
+            ctx.load(
+                r#"
+local spawn = require("awful.spawn")
+assert(spawn.once("chromium", "chromium") == 42)
+assert(spawned[1] == "chromium")
+assert(activated == nil)
+assert(spawn.once("firefox", "firefox").app_id == "firefox")
+assert(#spawned == 1)
+                "#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn raise_or_spawn_applies_rules_once() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|ctx| {
+            init(ctx)?;
+            ctx.load(FAKE_GLOBALS).exec()?;
+            ctx.load(
+                r#"
+local spawn = require("awful.spawn")
+spawn.once("chromium", "chromium", { floating = true })
+spawn.once("mpv", "mpv", { ontop = true })
+assert(#spawned == 2)
+-- One handler applies the rules of every command.
+assert(#managers == 1 and managers[1].name == "manage")
+local function manage(c) managers[1].func(c) end
+local first, second, player = { app_id = "chromium" }, { app_id = "chromium" }, { app_id = "mpv" }
+manage(player)
+assert(player.ontop and not player.floating)
+manage(first)
+assert(first.floating)
+-- The rules were applied already.
+manage(second)
+assert(second.floating == nil)
+spawn.once("mpv", "mpv", { sticky = true })
+assert(#managers == 1)
+                "#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn with_line_callback_passes_callbacks() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|ctx| {
+            init(ctx)?;
+            ctx.load(FAKE_GLOBALS).exec()?;
+            ctx.load(
+                r#"
+local spawn = require("awful.spawn")
+local lines, exited = {}, nil
+local pid = spawn.with_line_callback({ "sh", "-c", "df" }, {
+    stdout = function(line) table.insert(lines, line) end,
+    exit = function(reason, code) exited = reason .. " " .. code end
+})
+assert(pid == 43)
+assert(spawned[1][3] == "df")
+assert(lines[1] == "line" and exited == "exit 0")
+                "#
+            )
+            .exec()
+        })
+    }
+}
@@ -38,6 +38,8 @@
 
 use env_logger;
 #[macro_use]
+extern crate bitflags;
+#[macro_use]
 extern crate clap;
 #[macro_use]
 extern crate lazy_static;
@@ -46,17 +48,42 @@ extern crate log;
 
 #[macro_use]
 mod macros;
+mod accessibility;
+mod allocations;
 mod area;
 mod awesome;
+// Nothing in the client reads the API, it's for code linked with it.
+#[cfg(feature = "client-api")]
+#[allow(dead_code)]
+mod client_api;
+mod clock;
 mod common;
+mod control;
+mod crash;
 mod dbus;
+mod event_trace;
+mod json;
 mod keygrabber;
+mod l10n;
+mod leaks;
 mod lua;
+mod lua_fns;
+mod memory_pressure;
 mod mousegrabber;
 mod objects;
+mod picker;
+mod resume;
 mod root;
+mod scheduler;
+mod self_test;
+mod spawn_lines;
+mod svg;
+mod text_extents;
 mod wayland_obj;
 
+#[global_allocator]
+static ALLOCATOR: allocations::Counting = allocations::Counting;
+
 use std::{
     env,
     io::{self, Write},
@@ -73,17 +100,19 @@ use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet};
 use rlua::{LightUserData, Table};
 use wayland_client::{
     global_filter,
-    protocol::{wl_compositor, wl_output, wl_shm},
+    protocol::{wl_compositor, wl_output, wl_seat, wl_shm},
     sys::client::wl_display,
-    ConnectError, Display, EventQueue, GlobalError, GlobalManager
+    ConnectError, Display, EventQueue, GlobalError, GlobalEvent, GlobalImplementor, GlobalManager, Interface
 };
+use wayland_protocols::unstable::input_method::v1::client::zwp_input_method_v1;
+use wayland_protocols::wlr::unstable::foreign_toplevel::v1::client::zwlr_foreign_toplevel_manager_v1;
 use xcb::xkb;
 
 // So the C code can link to these Rust functions.
-pub use crate::dbus::{dbus_session_refresh, dbus_system_refresh};
+pub use crate::crash::awesome_protocol_error;
+pub use crate::dbus::dbus_refresh;
 
 use crate::lua::{LUA, NEXT_LUA};
-pub use wayland_protocols::xdg_shell::client::xdg_wm_base;
 
 const GIT_VERSION: &'static str = include_str!(concat!(env!("OUT_DIR"), "/git-version.txt"));
 pub const GLOBAL_SIGNALS: &'static str = "__awesome_global_signals";
@@ -91,13 +120,9 @@ pub const XCB_CONNECTION_HANDLE: &'static str = "__xcb_connection";
 
 #[link(name = "wayland_glib_interface", kind = "static")]
 extern "C" {
-    pub fn wayland_glib_interface_init(
-        display: *mut wl_display,
-        session_fd: RawFd,
-        system_fd: RawFd,
-        wayland_state: *mut libc::c_void
-    );
-    pub fn remove_dbus_from_glib();
+    pub fn wayland_glib_interface_init(display: *mut wl_display, wayland_state: *mut libc::c_void);
+    pub fn add_dbus_to_glib(fd: RawFd, bus: libc::c_int);
+    pub fn remove_dbus_from_glib(bus: libc::c_int);
 }
 
 /// The state passed into C to store it during the glib loop.
@@ -134,6 +159,8 @@ pub extern "C" fn awesome_refresh(wayland_state: *mut libc::c_void) {
             panic!("Could not restart Awesome");
         }
     });
+    #[cfg(feature = "client-api")]
+    LUA.with(|lua| lua.borrow().context(client_api::publish));
 }
 
 struct AwesomeVersion;
@@ -194,6 +221,7 @@ fn main() {
         )
         .get_matches();
     init_logs();
+    crash::install();
     let sig_action = SigAction::new(SigHandler::Handler(sig_handle), SaFlags::empty(), SigSet::empty());
     unsafe {
         signal::sigaction(signal::SIGINT, &sig_action).expect("Could not set SIGINT catcher");
@@ -229,10 +257,10 @@ fn main() {
         .values_of("lua lib search")
         .unwrap_or_default()
         .collect::<Vec<_>>();
+    l10n::init();
     lua::init_awesome_libraries(&lib_paths);
     let (display, event_queue, _globals) = init_wayland();
-    let (session_fd, system_fd) = dbus::connect().expect("Could not set up dbus connection");
-    init_glib(display, event_queue, session_fd, system_fd);
+    init_glib(display, event_queue);
     let config = matches.value_of("config");
     lua::run_awesome(&lib_paths, config);
 }
@@ -256,58 +284,140 @@ fn init_wayland() -> (Display, EventQueue, GlobalManager) {
         }
         exit(1);
     });
-    let globals = GlobalManager::new_with_cb(
-        &display,
-        global_filter!(
-            [
-                wl_output::WlOutput,
-                wayland_obj::WL_OUTPUT_VERSION,
-                wayland_obj::WlOutputManager {}
-            ],
-            [
-                wl_compositor::WlCompositor,
-                wayland_obj::WL_COMPOSITOR_VERSION,
-                wayland_obj::WlCompositorManager {}
-            ],
-            [
-                wl_shm::WlShm,
-                wayland_obj::WL_SHM_VERSION,
-                wayland_obj::WlShmManager {}
-            ]
-        )
+    let mut bind = global_filter!(
+        [
+            wl_output::WlOutput,
+            wayland_obj::WL_OUTPUT_VERSION,
+            wayland_obj::WlOutputManager {}
+        ],
+        [
+            wl_compositor::WlCompositor,
+            wayland_obj::WL_COMPOSITOR_VERSION,
+            wayland_obj::WlCompositorManager {}
+        ],
+        [
+            wl_shm::WlShm,
+            wayland_obj::WL_SHM_VERSION,
+            wayland_obj::WlShmManager {}
+        ],
+        [
+            wl_seat::WlSeat,
+            wayland_obj::WL_SEAT_VERSION,
+            wayland_obj::WlSeatManager {}
+        ],
+        [
+            zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1,
+            wayland_obj::FOREIGN_TOPLEVEL_MANAGER_VERSION,
+            wayland_obj::ForeignToplevelManager {}
+        ],
+        [
+            zwp_input_method_v1::ZwpInputMethodV1,
+            wayland_obj::INPUT_METHOD_VERSION,
+            wayland_obj::InputMethodManager {}
+        ],
+        [
+            wayland_obj::WpColorManagerV1,
+            wayland_obj::COLOR_MANAGER_VERSION,
+            wayland_obj::ColorManager {}
+        ],
+        [
+            wayland_obj::ZwpVirtualKeyboardManagerV1,
+            wayland_obj::VIRTUAL_KEYBOARD_MANAGER_VERSION,
+            wayland_obj::VirtualKeyboardManager {}
+        ],
+        [
+            wayland_obj::ZwpKeyboardShortcutsInhibitManagerV1,
+            wayland_obj::SHORTCUTS_INHIBIT_MANAGER_VERSION,
+            wayland_obj::ShortcutsInhibitManager {}
+        ],
+        [
+            wayland_obj::ZwlrInputInhibitManagerV1,
+            wayland_obj::INPUT_INHIBIT_MANAGER_VERSION,
+            wayland_obj::InputInhibitManager {}
+        ]
     );
+    let globals = GlobalManager::new_with_cb(&display, move |event, registry| {
+        // Outputs are told which global they are, so they can be removed
+        // with it.
+        match event {
+            GlobalEvent::New {
+                id, ref interface, ..
+            } if interface == wl_output::WlOutput::NAME => wayland_obj::binding_output(id),
+            GlobalEvent::Removed { id, ref interface } if interface == wl_output::WlOutput::NAME => {
+                wayland_obj::output_removed(id)
+            },
+            // Bound here rather than by the filter, which would bind newer
+            // versions than the client knows.
+            #[cfg(feature = "dmabuf")]
+            GlobalEvent::New {
+                id,
+                ref interface,
+                version
+            } if interface == wayland_obj::ZwpLinuxDmabufV1::NAME &&
+                version >= wayland_obj::LINUX_DMABUF_VERSION =>
+            {
+                let version = version.min(wayland_obj::LINUX_DMABUF_MAX_VERSION);
+                registry
+                    .bind::<wayland_obj::ZwpLinuxDmabufV1, _>(version, id, |new_proxy| {
+                        wayland_obj::LinuxDmabufManager {}.new_global(new_proxy)
+                    })
+                    .expect("wl_registry died unexpectedly");
+            },
+            _ => {}
+        }
+        bind(event, registry)
+    });
     event_queue.sync_roundtrip().unwrap();
 
-    globals
-        .instantiate_exact(wayland_obj::XDG_WM_BASE_VERSION, wayland_obj::xdg_shell_init)
-        .unwrap_or_else(|err| {
-            match err {
-                GlobalError::Missing => {
-                    error!(
-                        "Missing xdg_wm_base global (version {})",
-                        wayland_obj::XDG_WM_BASE_VERSION
-                    );
-                    error!("Your compositor doesn't support the xdg shell protocol");
-                    error!("This protocol is necessary for Awesome to function");
-                },
-                GlobalError::VersionTooLow(version) => {
-                    error!(
-                        "Got xdg_wm_base version {}, expected version {}",
-                        version,
-                        wayland_obj::XDG_WM_BASE_VERSION
-                    );
-                    error!(
-                        "Your compositor doesn't support version {} \
-                         of the xdg shell protocol",
-                        wayland_obj::XDG_WM_BASE_VERSION
-                    );
-                    error!("Ensure your compositor is up to date");
-                }
+    let layer_shell = globals.instantiate_range(
+        wayland_obj::LAYER_SHELL_VERSION,
+        wayland_obj::LAYER_SHELL_MAX_VERSION,
+        |new_proxy| wayland_obj::LayerShellManager {}.new_global(new_proxy)
+    );
+    if let Err(err) = layer_shell {
+        match err {
+            GlobalError::Missing => {
+                warn!(
+                    "Missing zwlr_layer_shell_v1 global (version {})",
+                    wayland_obj::LAYER_SHELL_VERSION
+                );
+                warn!("Your compositor doesn't support the layer shell protocol");
+            },
+            GlobalError::VersionTooLow(version) => {
+                warn!(
+                    "Got zwlr_layer_shell_v1 version {}, expected at least version {}",
+                    version,
+                    wayland_obj::LAYER_SHELL_VERSION
+                );
+                warn!("Ensure your compositor is up to date");
             }
-            exit(1);
-        });
+        }
+        // Drawins are shown as windows the compositor places instead.
+        globals
+            .instantiate_exact(wayland_obj::XDG_WM_BASE_VERSION, |new_proxy| {
+                wayland_obj::XdgWmBaseManager {}.new_global(new_proxy)
+            })
+            .unwrap_or_else(|_| {
+                error!(
+                    "Missing xdg_wm_base global (version {})",
+                    wayland_obj::XDG_WM_BASE_VERSION
+                );
+                error!(
+                    "Either the layer shell or the xdg shell protocol is necessary for Awesome to function"
+                );
+                exit(1);
+            });
+        warn!("Drawins are shown as xdg toplevels, which can't be placed or stacked");
+    }
 
     event_queue.sync_roundtrip().unwrap();
+    wayland_obj::set_globals(
+        globals
+            .list()
+            .into_iter()
+            .map(|(_, interface, version)| (interface, version))
+            .collect()
+    );
     (display, event_queue, globals)
 }
 
@@ -315,16 +425,11 @@ fn init_wayland() -> (Display, EventQueue, GlobalManager) {
 /// Wayland triggers an event.
 ///
 /// Note this doesn't actually start it yet, see `lua::run_awesome` for that.
-fn init_glib(display: Display, event_queue: EventQueue, session_fd: RawFd, system_fd: RawFd) {
+fn init_glib(display: Display, event_queue: EventQueue) {
     let mut wayland_state = WaylandState { display, event_queue };
     let display_ptr = wayland_state.display.get_display_ptr();
     unsafe {
-        wayland_glib_interface_init(
-            display_ptr,
-            session_fd,
-            system_fd,
-            &mut wayland_state as *mut _ as _
-        );
+        wayland_glib_interface_init(display_ptr, &mut wayland_state as *mut _ as _);
         ::std::mem::forget(wayland_state);
     }
 }
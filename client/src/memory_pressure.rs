@@ -0,0 +1,638 @@
+//! Tells Lua how much memory the client keeps, so widget libraries can free
+//! their caches before the session runs out.
+//!
+//! The memory the client keeps for drawins, their buffers, surfaces,
+//! variants and the vectors kept for the next frame, and the snapshots in
+//! the runtime directory are measured against a budget. The memory of the
+//! cgroup the client is in, or of the system if the cgroup isn't limited,
+//! is sampled too. The larger of the two fractions is the pressure, which
+//! is at one of three levels.
+//!
+//! The pressure is evaluated every few seconds, see
+//! `awesome.set_memory_pressure`, and soon after a buffer is allocated
+//! while the memory estimated since the last evaluation is past the
+//! budget. When the level changes `awesome::memory_pressure` is emitted
+//! with its name and what `awesome.memory_usage()` returns, then the
+//! `on_memory_pressure` handlers of the drawins are called. A level is only
+//! left for a lower one once the pressure is below its threshold by the
+//! hysteresis, so a pressure close to a threshold doesn't flap.
+//!
+//! At the critical level the client first sheds what it can get back
+//! without Lua: the buffers of hidden drawins, the vectors kept for the
+//! next frame, the least recently used snapshots and the variants that
+//! aren't shown. The signal has the usage after that.
+
+use std::{cell::RefCell, fs, ops::AddAssign, path::Path};
+
+use glib::{Continue, SourceId};
+use rlua::{self, Table};
+
+use crate::common::signal;
+use crate::objects::{drawable, drawin};
+use crate::scheduler::{self, Priority};
+use crate::GLOBAL_SIGNALS;
+
+/// How many seconds there are between evaluations, unless Lua sets it.
+const DEFAULT_INTERVAL: f64 = 30.0;
+
+/// The memory the client should keep, unless Lua sets it.
+const DEFAULT_BUDGET: u64 = 256 << 20;
+
+type Step = for<'lua> fn(rlua::Context<'lua>) -> rlua::Result<u64>;
+
+/// What's shed at the critical level, in the order it's shed in: what's
+/// cheapest to get back goes first.
+const STEPS: [(&str, Step); 4] = [
+    ("hidden_buffers", drawin::evict_hidden_buffers),
+    ("caches", drawin::drop_caches),
+    ("snapshots", drawable::shrink_snapshots),
+    ("variants", drawin::drop_inactive_variants)
+];
+
+thread_local! {
+    static MONITOR: RefCell<Monitor> = RefCell::new(Monitor::default());
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Level {
+    Normal,
+    Elevated,
+    Critical
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Level::Normal
+    }
+}
+
+impl Level {
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Normal => "normal",
+            Level::Elevated => "elevated",
+            Level::Critical => "critical"
+        }
+    }
+}
+
+/// The fractions of the budget the levels start at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Thresholds {
+    elevated: f64,
+    critical: f64,
+    /// How far below its threshold the pressure has to be to leave a level.
+    hysteresis: f64
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds {
+            elevated: 0.75,
+            critical: 0.9,
+            hysteresis: 0.05
+        }
+    }
+}
+
+impl Thresholds {
+    /// The level at `fraction` of the budget, coming from `current`.
+    fn level(&self, current: Level, fraction: f64) -> Level {
+        let rising = Self::at(fraction, self.elevated, self.critical);
+        if rising >= current {
+            return rising;
+        }
+        let falling = Self::at(
+            fraction,
+            self.elevated - self.hysteresis,
+            self.critical - self.hysteresis
+        );
+        falling.min(current)
+    }
+
+    fn at(fraction: f64, elevated: f64, critical: f64) -> Level {
+        if fraction >= critical {
+            Level::Critical
+        } else if fraction >= elevated {
+            Level::Elevated
+        } else {
+            Level::Normal
+        }
+    }
+}
+
+/// The memory the client keeps for drawins, in bytes.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct Memory {
+    pub buffers: u64,
+    pub surfaces: u64,
+    pub variants: u64,
+    /// The vectors kept for the next frame.
+    pub caches: u64
+}
+
+impl Memory {
+    pub fn total(&self) -> u64 {
+        self.buffers + self.surfaces + self.variants + self.caches
+    }
+}
+
+impl AddAssign for Memory {
+    fn add_assign(&mut self, other: Memory) {
+        self.buffers += other.buffers;
+        self.surfaces += other.surfaces;
+        self.variants += other.variants;
+        self.caches += other.caches;
+    }
+}
+
+/// The memory of the cgroup the client is in, or of the system.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct System {
+    source: &'static str,
+    used: u64,
+    limit: u64
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Usage {
+    drawins: Memory,
+    snapshots: u64,
+    system: Option<System>
+}
+
+impl Usage {
+    fn tracked(&self) -> u64 {
+        self.drawins.total() + self.snapshots
+    }
+
+    /// How close to the budget, or to the limit of the system, the memory
+    /// is, the larger of the two.
+    fn fraction(&self, budget: u64) -> f64 {
+        let tracked = self.tracked() as f64 / budget as f64;
+        let system = self
+            .system
+            .map(|system| system.used as f64 / system.limit as f64)
+            .unwrap_or(0.0);
+        tracked.max(system)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    /// Seconds, or 0 for never.
+    interval: f64,
+    budget: u64,
+    thresholds: Thresholds,
+    /// Whether the memory of the system is sampled.
+    system: bool
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            interval: DEFAULT_INTERVAL,
+            budget: DEFAULT_BUDGET,
+            thresholds: Thresholds::default(),
+            system: true
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Monitor {
+    source: Option<SourceId>,
+    settings: Settings,
+    level: Level,
+    /// The memory the drawins kept at the last evaluation, plus the buffers
+    /// allocated since, some of which replaced others.
+    estimate: u64,
+    /// Set while an evaluation is deferred.
+    queued: bool
+}
+
+/// Starts evaluating the pressure every few seconds.
+pub fn init(_: rlua::Context) -> rlua::Result<()> {
+    schedule(DEFAULT_INTERVAL);
+    Ok(())
+}
+
+/// Evaluates every `seconds`, or never if it's 0.
+fn schedule(seconds: f64) {
+    let old = MONITOR.with(|monitor| monitor.borrow_mut().source.take());
+    if let Some(source) = old {
+        glib::source_remove(source);
+    }
+    if seconds == 0.0 {
+        return;
+    }
+    let source = glib::timeout_add_seconds(seconds.round().max(1.0) as u32, || {
+        scheduler::defer(Priority::Idle, evaluate);
+        Continue(true)
+    });
+    MONITOR.with(|monitor| monitor.borrow_mut().source = Some(source));
+}
+
+/// Tells that a buffer of `bytes` was allocated, which evaluates the
+/// pressure soon if the memory estimated since the last evaluation is past
+/// the budget.
+pub fn allocated(bytes: u64) {
+    let queue = MONITOR
+        .try_with(|monitor| {
+            let mut monitor = monitor.borrow_mut();
+            monitor.estimate += bytes;
+            let queue = !monitor.queued && monitor.estimate > monitor.settings.budget;
+            monitor.queued |= queue;
+            queue
+        })
+        .unwrap_or(false);
+    if queue {
+        scheduler::defer(Priority::Idle, |lua| {
+            MONITOR.with(|monitor| monitor.borrow_mut().queued = false);
+            evaluate(lua)
+        });
+    }
+}
+
+/// Measures the memory and tells Lua if the level changed, shedding what
+/// the client can first at the critical level.
+pub fn evaluate(lua: rlua::Context) -> rlua::Result<()> {
+    let (settings, current) = MONITOR.with(|monitor| {
+        let monitor = monitor.borrow();
+        (monitor.settings, monitor.level)
+    });
+    let mut usage = measure(lua, settings.system)?;
+    let level = settings
+        .thresholds
+        .level(current, usage.fraction(settings.budget));
+    let mut shed = Vec::new();
+    if level == Level::Critical {
+        for &(name, step) in STEPS.iter() {
+            shed.push((name, step(lua)?));
+        }
+        usage = measure(lua, settings.system)?;
+    }
+    MONITOR.with(|monitor| {
+        let mut monitor = monitor.borrow_mut();
+        monitor.level = level;
+        monitor.estimate = usage.drawins.total();
+    });
+    if level == current {
+        return Ok(());
+    }
+    info!("Memory pressure is {}", level.name());
+    let table = usage_to_lua(lua, &usage, level, settings.budget)?;
+    if level == Level::Critical {
+        let steps = lua.create_table()?;
+        for (index, &(name, freed)) in shed.iter().enumerate() {
+            let step = lua.create_table()?;
+            step.set("step", name)?;
+            step.set("freed", freed)?;
+            steps.set(index + 1, step)?;
+        }
+        table.set("shed", steps)?;
+    }
+    let global_signals = lua.named_registry_value::<str, Table>(GLOBAL_SIGNALS)?;
+    signal::emit_signals(
+        lua,
+        global_signals,
+        "awesome::memory_pressure",
+        (level.name(), table)
+    )?;
+    drawin::memory_pressure(lua, level.name())
+}
+
+fn measure(lua: rlua::Context, system: bool) -> rlua::Result<Usage> {
+    Ok(Usage {
+        drawins: drawin::memory(lua)?,
+        snapshots: drawable::snapshot_usage(),
+        system: if system { sample_system() } else { None }
+    })
+}
+
+/// The memory of the cgroup the client is in if it's limited, otherwise
+/// of the system.
+fn sample_system() -> Option<System> {
+    sample_cgroup().or_else(|| {
+        let (used, limit) = parse_meminfo(&fs::read_to_string("/proc/meminfo").ok()?)?;
+        Some(System {
+            source: "meminfo",
+            used,
+            limit
+        })
+    })
+}
+
+/// The memory of the cgroup v2 the client is in, if it has a limit.
+fn sample_cgroup() -> Option<System> {
+    let cgroup = fs::read_to_string("/proc/self/cgroup").ok()?;
+    let dir = Path::new("/sys/fs/cgroup").join(parse_cgroup(&cgroup)?.trim_start_matches('/'));
+    let used = fs::read_to_string(dir.join("memory.current"))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    let limit = parse_limit(&fs::read_to_string(dir.join("memory.max")).ok()?)?;
+    Some(System {
+        source: "cgroup",
+        used,
+        limit
+    })
+}
+
+/// The path of the cgroup v2 in `/proc/self/cgroup`.
+fn parse_cgroup(cgroup: &str) -> Option<&str> {
+    cgroup
+        .lines()
+        .find(|line| line.starts_with("0::"))
+        .map(|line| &line["0::".len()..])
+}
+
+/// The limit in `memory.max`, which is "max" if there's none.
+fn parse_limit(max: &str) -> Option<u64> {
+    max.trim().parse().ok().filter(|&limit| limit > 0)
+}
+
+/// The memory in use and the memory there is in `/proc/meminfo`, in bytes.
+fn parse_meminfo(meminfo: &str) -> Option<(u64, u64)> {
+    let field = |name: &str| -> Option<u64> {
+        let line = meminfo.lines().find(|line| line.starts_with(name))?;
+        let kilobytes: u64 = line[name.len()..]
+            .trim()
+            .trim_end_matches("kB")
+            .trim()
+            .parse()
+            .ok()?;
+        Some(kilobytes * 1024)
+    };
+    let total = field("MemTotal:")?;
+    let available = field("MemAvailable:")?;
+    if total == 0 {
+        return None;
+    }
+    Some((total.saturating_sub(available), total))
+}
+
+fn usage_to_lua<'lua>(
+    lua: rlua::Context<'lua>,
+    usage: &Usage,
+    level: Level,
+    budget: u64
+) -> rlua::Result<Table<'lua>> {
+    let table = lua.create_table()?;
+    table.set("level", level.name())?;
+    table.set("budget", budget)?;
+    table.set("tracked", usage.tracked())?;
+    table.set("fraction", usage.fraction(budget))?;
+    table.set("buffers", usage.drawins.buffers)?;
+    table.set("surfaces", usage.drawins.surfaces)?;
+    table.set("variants", usage.drawins.variants)?;
+    table.set("caches", usage.drawins.caches)?;
+    table.set("snapshots", usage.snapshots)?;
+    if let Some(system) = usage.system {
+        let entry = lua.create_table()?;
+        entry.set("source", system.source)?;
+        entry.set("used", system.used)?;
+        entry.set("limit", system.limit)?;
+        table.set("system", entry)?;
+    }
+    Ok(table)
+}
+
+/// `awesome.memory_usage()`, the memory the client keeps now, in bytes, and
+/// the level of the last evaluation: `{ level = "normal", budget = n,
+/// tracked = n, fraction = f, buffers = n, surfaces = n, variants = n,
+/// caches = n, snapshots = n, system = { source = "cgroup", used = n,
+/// limit = n } }`. `awesome::memory_pressure` has the same table, with
+/// `shed = { { step = "hidden_buffers", freed = n }, ... }` at the
+/// critical level.
+pub fn memory_usage(lua: rlua::Context, _: ()) -> rlua::Result<Table> {
+    let (settings, level) = MONITOR.with(|monitor| {
+        let monitor = monitor.borrow();
+        (monitor.settings, monitor.level)
+    });
+    let usage = measure(lua, settings.system)?;
+    usage_to_lua(lua, &usage, level, settings.budget)
+}
+
+/// `awesome.set_memory_pressure{ interval = 30, budget = 256 * 1024 *
+/// 1024, elevated = 0.75, critical = 0.9, hysteresis = 0.05, system = true
+/// }`, where the interval is in seconds, never with 0, the levels start at
+/// fractions of the budget and `system` samples the memory of the cgroup
+/// or system too. Fields left out keep their value. The pressure is
+/// evaluated with the new settings right away.
+pub fn set_memory_pressure(lua: rlua::Context, args: Table) -> rlua::Result<()> {
+    use rlua::Error::RuntimeError;
+    let mut settings = MONITOR.with(|monitor| monitor.borrow().settings);
+    if let Some(interval) = args.get::<_, Option<f64>>("interval")? {
+        if !interval.is_finite() || interval < 0.0 {
+            return Err(RuntimeError(format!(
+                "set_memory_pressure: the interval must be 0 or positive, got {}",
+                interval
+            )));
+        }
+        settings.interval = interval;
+    }
+    if let Some(budget) = args.get::<_, Option<u64>>("budget")? {
+        if budget == 0 {
+            return Err(RuntimeError(
+                "set_memory_pressure: the budget must be positive".into()
+            ));
+        }
+        settings.budget = budget;
+    }
+    let thresholds = &mut settings.thresholds;
+    if let Some(elevated) = args.get::<_, Option<f64>>("elevated")? {
+        thresholds.elevated = elevated;
+    }
+    if let Some(critical) = args.get::<_, Option<f64>>("critical")? {
+        thresholds.critical = critical;
+    }
+    if let Some(hysteresis) = args.get::<_, Option<f64>>("hysteresis")? {
+        thresholds.hysteresis = hysteresis;
+    }
+    let ordered = 0.0 < thresholds.elevated && thresholds.elevated < thresholds.critical;
+    if !ordered || !(0.0 <= thresholds.hysteresis && thresholds.hysteresis < thresholds.elevated) {
+        return Err(RuntimeError(format!(
+            "set_memory_pressure: expected 0 < elevated < critical and 0 <= hysteresis < elevated, got \
+             {}, {} and {}",
+            thresholds.elevated, thresholds.critical, thresholds.hysteresis
+        )));
+    }
+    if let Some(system) = args.get::<_, Option<bool>>("system")? {
+        settings.system = system;
+    }
+    let reschedule = MONITOR.with(|monitor| {
+        let mut monitor = monitor.borrow_mut();
+        let reschedule = monitor.settings.interval != settings.interval;
+        monitor.settings = settings;
+        reschedule
+    });
+    if reschedule {
+        schedule(settings.interval);
+    }
+    evaluate(lua)
+}
+
+#[cfg(test)]
+mod test {
+    use rlua::{self, Lua};
+
+    use super::*;
+    use crate::area::{Area, Origin, Size};
+    use crate::objects::{
+        drawable,
+        drawin::{self, Drawin},
+        screen::SCREENS_HANDLE
+    };
+
+    fn setup(lua: rlua::Context) -> rlua::Result<()> {
+        drawable::init(lua)?;
+        drawin::init(lua)?;
+        lua.set_named_registry_value(SCREENS_HANDLE, lua.create_table()?)?;
+        lua.set_named_registry_value(GLOBAL_SIGNALS, lua.create_table()?)?;
+        let awesome = lua.create_table()?;
+        awesome.set(
+            "connect_signal",
+            lua.create_function(signal::global_connect_signal)?
+        )?;
+        awesome.set("memory_usage", lua.create_function(memory_usage)?)?;
+        awesome.set("set_memory_pressure", lua.create_function(set_memory_pressure)?)?;
+        lua.globals().set("awesome", awesome)
+    }
+
+    #[test]
+    fn memory_pressure_hysteresis() {
+        use self::Level::*;
+        let thresholds = Thresholds::default();
+        // Rising is right away, falling only below the hysteresis.
+        let steps = [
+            (0.5, Normal),
+            (0.76, Elevated),
+            (0.72, Elevated),
+            (0.69, Normal),
+            (0.95, Critical),
+            (0.86, Critical),
+            (0.84, Elevated),
+            (0.74, Elevated),
+            (0.91, Critical),
+            (0.1, Normal)
+        ];
+        let mut level = Normal;
+        for &(fraction, expected) in steps.iter() {
+            level = thresholds.level(level, fraction);
+            assert_eq!(level, expected, "at {}", fraction);
+        }
+        // Falling past both thresholds at once.
+        assert_eq!(thresholds.level(Critical, 0.72), Elevated);
+        assert_eq!(thresholds.level(Critical, 0.69), Normal);
+    }
+
+    #[test]
+    fn memory_pressure_system_sources() {
+        let meminfo = "MemTotal:       16318864 kB\nMemFree:         1214436 kB\nMemAvailable:    \
+                       8159432 kB\nBuffers:          412004 kB\n";
+        assert_eq!(
+            parse_meminfo(meminfo),
+            Some((8_159_432 * 1024, 16_318_864 * 1024))
+        );
+        assert_eq!(parse_meminfo("MemTotal: 100 kB\n"), None);
+        let cgroup = "1:name=systemd:/user.slice\n0::/user.slice/user-1000.slice/session-2.scope\n";
+        assert_eq!(
+            parse_cgroup(cgroup),
+            Some("/user.slice/user-1000.slice/session-2.scope")
+        );
+        assert_eq!(parse_cgroup("1:memory:/\n"), None);
+        assert_eq!(parse_limit("max\n"), None);
+        assert_eq!(parse_limit("1073741824\n"), Some(1 << 30));
+    }
+
+    #[test]
+    fn memory_pressure_levels_signaled() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            setup(lua)?;
+            let drawins: Vec<Drawin> = lua
+                .load(
+                    r#"
+awesome.set_memory_pressure{ interval = 0, system = false }
+signals, handled = {}, {}
+awesome.connect_signal("awesome::memory_pressure", function(level, usage)
+    signals[#signals + 1] = { level = level, usage = usage }
+end)
+local function track(d, name)
+    d.on_memory_pressure = function(level, of)
+        assert(of == d)
+        handled[#handled + 1] = name .. " " .. level
+    end
+    return d
+end
+-- The last one has no handler, and is skipped.
+drawins = { track(drawin{}, "bar"), track(drawin{}, "popup"), drawin{} }
+return drawins
+                    "#
+                )
+                .eval()?;
+            // Hidden drawins have no surface without a compositor.
+            for drawin in drawins {
+                let geometry = Area {
+                    origin: Origin::default(),
+                    size: Size {
+                        width: 100,
+                        height: 50
+                    }
+                };
+                drawin.drawable()?.set_geometry(lua, geometry)?;
+            }
+            lua.load(
+                r#"
+local hidden = drawins[2]
+hidden.drawable:begin_variant("scale:2")
+hidden.drawable:end_variant()
+local usage = awesome.memory_usage()
+assert(usage.level == "normal" and usage.system == nil)
+assert(usage.variants == 200 * 100 * 4, usage.variants)
+local variants = usage.variants
+local before = usage.tracked
+
+-- Everything that can be shed is, in order, before Lua is told.
+awesome.set_memory_pressure{ budget = before }
+assert(#signals == 1 and signals[1].level == "critical")
+usage = signals[1].usage
+assert(usage.level == "critical" and usage.variants == 0)
+assert(usage.tracked == before - variants, usage.tracked)
+local steps = {}
+for i, step in ipairs(usage.shed) do steps[i] = step.step end
+assert(table.concat(steps, " ") == "hidden_buffers caches snapshots variants", table.concat(steps, " "))
+assert(usage.shed[4].freed == variants)
+assert(table.concat(handled, ", ") == "bar critical, popup critical", table.concat(handled, ", "))
+assert(awesome.memory_usage().level == "critical")
+
+-- Signaled when the level changes, not in between.
+local tracked = usage.tracked
+local function at(fraction)
+    awesome.set_memory_pressure{ budget = math.floor(tracked / fraction) }
+    return #signals, signals[#signals].level
+end
+local count, level = at(0.3)
+assert(count == 2 and level == "normal" and signals[2].usage.shed == nil)
+count, level = at(0.8)
+assert(count == 3 and level == "elevated")
+count, level = at(0.72)
+assert(count == 3 and level == "elevated")
+count, level = at(0.95)
+assert(count == 4 and level == "critical")
+assert(signals[4].usage.shed[4].freed == 0)
+count, level = at(0.87)
+assert(count == 4 and level == "critical")
+count, level = at(0.6)
+assert(count == 5 and level == "normal")
+assert(#handled == 10, #handled)
+assert(not pcall(awesome.set_memory_pressure, { elevated = 0.95 }))
+assert(not pcall(awesome.set_memory_pressure, { budget = 0 }))
+"#
+            )
+            .exec()
+        })
+    }
+}
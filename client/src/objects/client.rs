@@ -1,31 +1,71 @@
 //! A client to the Wayland compositor. We control their position through tiling
 //! and other properties based on what kind of shell they are.
 
+mod icon_theme;
+
 use std::{
+    cell::RefCell,
+    collections::HashMap,
     default::Default,
     hash::{Hash, Hasher}
 };
 
-use rlua::{self, Table, UserData};
+use cairo::ImageSurface;
+use gdk_pixbuf::Pixbuf;
+use rlua::{self, AnyUserData, Table, ToLua, UserData, UserDataMethods, Value};
+use wayland_client::protocol::wl_shm;
 
+use crate::area::{Area, Origin, Size};
+use crate::awesome;
 use crate::common::{
     class::{self, Class, ClassBuilder},
-    object::Object
+    object::{self, Object, ObjectBuilder},
+    property::Property,
+    signal
 };
+use crate::objects::tag::{Tag, TAG_LIST};
+use crate::wayland_obj::{self, Anchor, Buffer, ForeignToplevel, KeyboardInteractivity, Layer, LayerSurface};
+
+use self::icon_theme::IconDirs;
+
+/// Handle to the list of managed clients.
+pub const CLIENTS_HANDLE: &'static str = "__clients";
+/// Handle to the client that has keyboard focus, if any.
+pub const FOCUSED_CLIENT: &'static str = "__focused_client";
+/// Handle to the functions `client.focus` has as well as the focused client,
+/// like `client.focus.byidx(1)`, see `lua_fns::client_focus`.
+pub const FOCUS_HELPERS: &'static str = "__client_focus_helpers";
+
+thread_local! {
+    /// The icons that were loaded, keyed by app id and size.
+    static ICONS: RefCell<HashMap<String, ImageSurface>> = RefCell::new(HashMap::new());
+    /// The surface that holds the keyboard focus while no client has it,
+    /// see `release_keyboard`.
+    static FOCUS_SINK: RefCell<Option<(LayerSurface, Buffer)>> = RefCell::new(None);
+}
 
-#[derive(Clone, Debug, Hash)]
+#[derive(Debug, Default)]
 pub struct ClientState {
-    // TODO Fill in
-    pub dummy: i32
+    /// The toplevel of the other Wayland client, if this was created
+    /// by the compositor advertising a new toplevel.
+    toplevel: Option<ForeignToplevel>,
+    name: String,
+    app_id: String,
+    /// The icon of the application, looked up by the app id.
+    icon: Option<ImageSurface>,
+    geometry: Area,
+    /// Whether the client is shown on every tag.
+    sticky: bool,
+    // Stacking hints, which can't be applied through the foreign toplevel
+    // protocol.
+    above: bool,
+    below: bool,
+    ontop: bool
 }
 
-pub type Client<'lua> = Object<'lua, ClientState>;
+unsafe impl Send for ClientState {}
 
-impl Default for ClientState {
-    fn default() -> Self {
-        ClientState { dummy: 0 }
-    }
-}
+pub type Client<'lua> = Object<'lua, ClientState>;
 
 impl<'lua> PartialEq for Client<'lua> {
     fn eq(&self, other: &Self) -> bool {
@@ -37,25 +77,314 @@ impl<'lua> Eq for Client<'lua> {}
 
 impl<'lua> Hash for Client<'lua> {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.state().unwrap().hash(state);
+        (&*self.state().unwrap() as *const ClientState).hash(state);
+    }
+}
+
+impl UserData for ClientState {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        object::default_add_methods(methods);
     }
 }
 
-// This is currently unused.
-// TODO: Figure out if this will be needed later.
 impl<'lua> Client<'lua> {
     pub fn new(lua: rlua::Context<'lua>, args: Table<'lua>) -> rlua::Result<Client<'lua>> {
         let class = class::class_setup(lua, "client")?;
-        Ok(Client::allocate(lua, class)?
+        Ok(object_setup(lua, Client::allocate(lua, class)?)?
             .handle_constructor_argument(args)?
             .build())
     }
+
+    pub fn get_name(&self) -> rlua::Result<String> {
+        Ok(self.state()?.name.clone())
+    }
+
+    pub fn get_geometry(&self) -> rlua::Result<Area> {
+        Ok(self.state()?.geometry)
+    }
+
+    pub fn set_geometry(&mut self, lua: rlua::Context<'lua>, geometry: Area) -> rlua::Result<()> {
+        {
+            let mut state = self.state_mut()?;
+            if state.geometry == geometry {
+                return Ok(());
+            }
+            state.geometry = geometry;
+        }
+        Object::emit_signal(lua, self, "property::geometry", Value::Nil)
+    }
+
+    /// Gives the client keyboard focus.
+    ///
+    /// The focus is updated right away, the compositor is asked to activate
+    /// the toplevel as well. If it decides to focus something else instead
+    /// `client.focus` is updated once it tells us.
+    pub fn activate(&self, lua: rlua::Context<'lua>) -> rlua::Result<()> {
+        if let Some(toplevel) = self.state()?.toplevel.as_ref() {
+            toplevel.activate();
+        }
+        set_focus(lua, Some(self.clone()))
+    }
+
+    pub fn is_sticky(&self) -> rlua::Result<bool> {
+        Ok(self.state()?.sticky)
+    }
+
+    /// Makes the client shown on every tag, or only on its own tags again.
+    ///
+    /// A client that isn't sticky anymore and isn't on any selected tag is
+    /// moved to the first selected tag, so it doesn't disappear.
+    pub fn set_sticky(&mut self, lua: rlua::Context<'lua>, sticky: bool) -> rlua::Result<()> {
+        {
+            let mut state = self.state_mut()?;
+            if state.sticky == sticky {
+                return Ok(());
+            }
+            state.sticky = sticky;
+        }
+        if !sticky && !is_visible(lua, self)? {
+            let tags = lua.named_registry_value::<str, Vec<Tag>>(TAG_LIST)?;
+            for mut tag in tags {
+                if tag.selected()? {
+                    tag.tag_client(self.clone())?;
+                    break;
+                }
+            }
+        }
+        Object::emit_signal(lua, self, "property::sticky", Value::Nil)
+    }
+
+    fn has_toplevel(&self, toplevel: &ForeignToplevel) -> rlua::Result<bool> {
+        Ok(self.state()?.toplevel.as_ref() == Some(toplevel))
+    }
+}
+
+/// Get all the clients that are managed, in the order they were managed.
+pub fn clients<'lua>(lua: rlua::Context<'lua>) -> rlua::Result<Vec<Client<'lua>>> {
+    lua.named_registry_value::<str, Vec<Client>>(CLIENTS_HANDLE)
 }
 
-impl UserData for ClientState {}
+/// Whether the client is sticky or on a selected tag.
+pub fn is_visible<'lua>(lua: rlua::Context<'lua>, client: &Client<'lua>) -> rlua::Result<bool> {
+    if client.is_sticky()? {
+        return Ok(true);
+    }
+    for tag in lua.named_registry_value::<str, Vec<Tag>>(TAG_LIST)? {
+        if tag.selected()? && tag.client_index(client)?.is_some() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// The clients that are sticky or on a selected tag, in the order they were
+/// managed.
+pub fn visible_clients<'lua>(lua: rlua::Context<'lua>) -> rlua::Result<Vec<Client<'lua>>> {
+    let mut visible = Vec::new();
+    for client in clients(lua)? {
+        if is_visible(lua, &client)? {
+            visible.push(client);
+        }
+    }
+    Ok(visible)
+}
+
+/// Get the client with keyboard focus, if any.
+pub fn focused<'lua>(lua: rlua::Context<'lua>) -> rlua::Result<Option<Client<'lua>>> {
+    lua.named_registry_value::<str, Option<Client>>(FOCUSED_CLIENT)
+}
+
+/// Changes the focused client, emitting "unfocus" on the previously focused
+/// client and "focus" on the new one.
+///
+/// This only changes what Lua sees, `client.focus = nil` takes the keyboard
+/// focus away from the clients with `release_keyboard`. Once a client has the
+/// focus again it's given back to the clients.
+pub fn set_focus<'lua>(lua: rlua::Context<'lua>, client: Option<Client<'lua>>) -> rlua::Result<()> {
+    if client.is_some() {
+        FOCUS_SINK.with(|sink| sink.borrow_mut().take());
+    }
+    let old = focused(lua)?;
+    if old == client {
+        return Ok(());
+    }
+    lua.set_named_registry_value(FOCUSED_CLIENT, client.clone())?;
+    if let Some(old) = old {
+        emit_client_signal(lua, &old, "unfocus")?;
+    }
+    if let Some(client) = client {
+        emit_client_signal(lua, &client, "focus")?;
+    }
+    Ok(())
+}
+
+/// Takes the keyboard focus away from every client.
+///
+/// Wayland has no way to just take it away from another client, so it's
+/// given to a transparent surface of ours on the top layer instead, which
+/// asks for all of it. Keys go to the keygrabber then, if there's one. A
+/// drawin that requests the focus after this still gets it.
+///
+/// Nothing happens without a layer shell.
+fn release_keyboard() {
+    if FOCUS_SINK.with(|sink| sink.borrow().is_some()) {
+        return;
+    }
+    // A toplevel standing in for the layer surface would be a window.
+    if !wayland_obj::has_layer_shell() {
+        warn!("Can't take the keyboard focus from the clients, there is no layer shell");
+        return;
+    }
+    let size = Size { width: 1, height: 1 };
+    let buffer = match wayland_obj::create_buffer(size, wl_shm::Format::Argb8888) {
+        Ok(buffer) => buffer,
+        Err(err) => {
+            warn!("Can't take the keyboard focus from the clients: {}", err);
+            return;
+        }
+    };
+    let layer_surface = match wayland_obj::create_layer_surface(None, Layer::Top, Anchor::Top | Anchor::Left)
+    {
+        Ok(layer_surface) => layer_surface,
+        Err(()) => {
+            warn!("Can't take the keyboard focus from the clients, there is no layer shell");
+            return;
+        }
+    };
+    layer_surface.set_size(size);
+    layer_surface.set_input_region(Some(&[]));
+    layer_surface.set_keyboard_interactivity(KeyboardInteractivity::Exclusive);
+    // The buffer is transparent, and attached once it's configured.
+    layer_surface.set_buffer(buffer.wl_buffer(), size, None);
+    layer_surface.commit();
+    FOCUS_SINK.with(|sink| *sink.borrow_mut() = Some((layer_surface, buffer)));
+}
+
+/// Updates the client for the toplevel, managing it if it's new.
+///
+/// Called once the compositor is done sending the state of a toplevel.
+pub fn update_client<'lua>(lua: rlua::Context<'lua>, toplevel: ForeignToplevel) -> rlua::Result<()> {
+    let (mut client, is_new) = match find_client(lua, &toplevel)? {
+        Some(client) => (client, false),
+        None => (manage_client(lua, toplevel.clone())?, true)
+    };
+    let (name, app_id) = (toplevel.title(), toplevel.app_id());
+    let (name_changed, app_id_changed) = {
+        let mut state = client.state_mut()?;
+        let changed = (state.name != name, state.app_id != app_id);
+        state.name = name;
+        state.app_id = app_id.clone();
+        changed
+    };
+    if name_changed {
+        Object::emit_signal(lua, &client, "property::name", Value::Nil)?;
+    }
+    if app_id_changed {
+        Object::emit_signal(lua, &client, "property::app_id", Value::Nil)?;
+        Object::emit_signal(lua, &client, "property::class", Value::Nil)?;
+        Object::emit_signal(lua, &client, "property::instance", Value::Nil)?;
+        let icon = icon_lookup(&app_id, awesome::preferred_icon_size(lua)?);
+        let icon_changed = {
+            let mut state = client.state_mut()?;
+            let changed = state.icon.is_some() || icon.is_some();
+            state.icon = icon;
+            changed
+        };
+        if icon_changed {
+            Object::emit_signal(lua, &client, "property::icon", Value::Nil)?;
+        }
+    }
+    // Rules match on the name and class, so a new client is only announced
+    // once they are known.
+    if is_new {
+        emit_class_signal(lua, "manage", client.clone())?;
+    }
+    let is_focused = focused(lua)?.as_ref() == Some(&client);
+    if toplevel.activated() && !is_focused {
+        set_focus(lua, Some(client))?;
+    } else if !toplevel.activated() && is_focused {
+        set_focus(lua, None)?;
+    }
+    Ok(())
+}
+
+/// Loads the icon of the application with `app_id` to be shown at `size`
+/// pixels, or at its largest size if `size` is 0.
+///
+/// Icons are only loaded once per size.
+pub fn icon_lookup(app_id: &str, size: u32) -> Option<ImageSurface> {
+    let key = format!("{}@{}", app_id, size);
+    if let Some(icon) = ICONS.with(|icons| icons.borrow().get(&key).cloned()) {
+        return Some(icon);
+    }
+    let path = IconDirs::from_env().find_icon(app_id, size)?;
+    let pixbuf = if size == 0 {
+        Pixbuf::new_from_file(&path)
+    } else {
+        Pixbuf::new_from_file_at_size(&path, size as i32, size as i32)
+    };
+    let icon = match pixbuf {
+        Ok(pixbuf) => awesome::load_surface_from_pixbuf(pixbuf),
+        Err(err) => {
+            warn!("Could not load icon {}: {}", path.display(), err);
+            return None;
+        }
+    };
+    ICONS.with(|icons| icons.borrow_mut().insert(key, icon.clone()));
+    Some(icon)
+}
+
+/// Removes the client for the toplevel from the list of clients.
+pub fn unmanage_client<'lua>(lua: rlua::Context<'lua>, toplevel: &ForeignToplevel) -> rlua::Result<()> {
+    let mut client = match find_client(lua, toplevel)? {
+        Some(client) => client,
+        None => return Ok(())
+    };
+    if focused(lua)?.as_ref() == Some(&client) {
+        set_focus(lua, None)?;
+    }
+    let clients: Vec<_> = clients(lua)?.into_iter().filter(|c| *c != client).collect();
+    lua.set_named_registry_value(CLIENTS_HANDLE, clients.to_lua(lua)?)?;
+    client.state_mut()?.toplevel = None;
+    emit_class_signal(lua, "unmanage", client)
+}
+
+/// Adds a client for the toplevel, which is announced by `update_client`.
+fn manage_client<'lua>(lua: rlua::Context<'lua>, toplevel: ForeignToplevel) -> rlua::Result<Client<'lua>> {
+    let mut client = Client::new(lua, lua.create_table()?)?;
+    client.state_mut()?.toplevel = Some(toplevel);
+    let mut clients = clients(lua)?;
+    clients.push(client.clone());
+    lua.set_named_registry_value(CLIENTS_HANDLE, clients.to_lua(lua)?)?;
+    Ok(client)
+}
+
+fn find_client<'lua>(
+    lua: rlua::Context<'lua>,
+    toplevel: &ForeignToplevel
+) -> rlua::Result<Option<Client<'lua>>> {
+    for client in clients(lua)? {
+        if client.has_toplevel(toplevel)? {
+            return Ok(Some(client));
+        }
+    }
+    Ok(None)
+}
+
+/// Emits the signal on both the client and the client class, like Awesome.
+fn emit_client_signal<'lua>(lua: rlua::Context<'lua>, client: &Client<'lua>, name: &str) -> rlua::Result<()> {
+    Object::emit_signal(lua, client, name, Value::Nil)?;
+    emit_class_signal(lua, name, client.clone())
+}
+
+fn emit_class_signal<'lua>(lua: rlua::Context<'lua>, name: &str, client: Client<'lua>) -> rlua::Result<()> {
+    let class = class::class_setup::<ClientState>(lua, "client")?;
+    signal::emit_signals(lua, class.signals()?, name, client)
+}
 
 pub fn init(lua: rlua::Context) -> rlua::Result<Class<ClientState>> {
-    method_setup(lua, Class::builder(lua, "client", None)?)?
+    lua.set_named_registry_value(CLIENTS_HANDLE, lua.create_table()?)?;
+    property_setup(lua, method_setup(lua, Class::builder(lua, "client", None)?)?)?
         .save_class("client")?
         .build()
 }
@@ -64,17 +393,257 @@ fn method_setup<'lua>(
     lua: rlua::Context<'lua>,
     builder: ClassBuilder<'lua, ClientState>
 ) -> rlua::Result<ClassBuilder<'lua, ClientState>> {
-    // TODO Do properly
-    use super::dummy;
     builder
-        .method("connect_signal".into(), lua.create_function(dummy)?)?
         .method(
             "__call".into(),
             lua.create_function(|lua, args: Table| Client::new(lua, args))?
         )?
-        .method("get".into(), lua.create_function(dummy_table)?)
+        .method("get".into(), lua.create_function(get_clients)?)?
+        .method("__index".into(), lua.create_function(class_index)?)?
+        .method("__newindex".into(), lua.create_function(class_newindex)?)
+}
+
+fn property_setup<'lua>(
+    lua: rlua::Context<'lua>,
+    builder: ClassBuilder<'lua, ClientState>
+) -> rlua::Result<ClassBuilder<'lua, ClientState>> {
+    builder
+        .property(Property::new(
+            "name".into(),
+            None,
+            Some(lua.create_function(get_name)?),
+            None
+        ))?
+        .property(Property::new(
+            "app_id".into(),
+            None,
+            Some(lua.create_function(get_app_id)?),
+            None
+        ))?
+        .property(Property::new(
+            "class".into(),
+            None,
+            Some(lua.create_function(get_class)?),
+            None
+        ))?
+        .property(Property::new(
+            "instance".into(),
+            None,
+            Some(lua.create_function(get_instance)?),
+            None
+        ))?
+        .property(Property::new(
+            "icon".into(),
+            None,
+            Some(lua.create_function(get_icon)?),
+            None
+        ))?
+        .property(Property::new(
+            "sticky".into(),
+            Some(lua.create_function(set_sticky)?),
+            Some(lua.create_function(get_sticky)?),
+            Some(lua.create_function(set_sticky)?)
+        ))?
+        .property(Property::new(
+            "above".into(),
+            Some(lua.create_function(|lua, args| set_stacking_hint(lua, args, "above"))?),
+            Some(lua.create_function(|_, client: Client| Ok(client.state()?.above))?),
+            Some(lua.create_function(|lua, args| set_stacking_hint(lua, args, "above"))?)
+        ))?
+        .property(Property::new(
+            "below".into(),
+            Some(lua.create_function(|lua, args| set_stacking_hint(lua, args, "below"))?),
+            Some(lua.create_function(|_, client: Client| Ok(client.state()?.below))?),
+            Some(lua.create_function(|lua, args| set_stacking_hint(lua, args, "below"))?)
+        ))?
+        .property(Property::new(
+            "ontop".into(),
+            Some(lua.create_function(|lua, args| set_stacking_hint(lua, args, "ontop"))?),
+            Some(lua.create_function(|_, client: Client| Ok(client.state()?.ontop))?),
+            Some(lua.create_function(|lua, args| set_stacking_hint(lua, args, "ontop"))?)
+        ))
+}
+
+fn object_setup<'lua>(
+    lua: rlua::Context<'lua>,
+    builder: ObjectBuilder<'lua, ClientState>
+) -> rlua::Result<ObjectBuilder<'lua, ClientState>> {
+    let table = lua.create_table()?;
+    table.set("activate", lua.create_function(activate)?)?;
+    table.set("geometry", lua.create_function(client_geometry)?)?;
+    table.set("icon_sizes", lua.create_function(icon_sizes)?)?;
+    table.set("isvisible", lua.create_function(isvisible)?)?;
+    // The focused client is `client.focus`, which has the helpers to move
+    // the focus as well.
+    if let Value::Table(helpers) = lua.named_registry_value::<str, Value>(FOCUS_HELPERS)? {
+        for pair in helpers.pairs::<Value, Value>() {
+            let (key, value) = pair?;
+            table.set(key, value)?;
+        }
+    }
+    builder.add_to_meta(table)
+}
+
+/// Index of the client class, which is how `client.focus` is read.
+fn class_index<'lua>(
+    lua: rlua::Context<'lua>,
+    (class, index): (AnyUserData<'lua>, Value<'lua>)
+) -> rlua::Result<Value<'lua>> {
+    if let Value::String(ref string) = index {
+        if string.to_str()? == "focus" {
+            return focused(lua)?.to_lua(lua);
+        }
+    }
+    let meta = class
+        .get_user_value::<Table>()?
+        .get_metatable()
+        .expect("client class had no metatable");
+    meta.raw_get(index)
+}
+
+/// New index of the client class, which is how `client.focus` is written.
+fn class_newindex<'lua>(
+    lua: rlua::Context<'lua>,
+    (class, index, val): (AnyUserData<'lua>, String, Value<'lua>)
+) -> rlua::Result<Value<'lua>> {
+    if index != "focus" {
+        return object::default_newindex::<ClientState>(lua, (class.into(), index, val));
+    }
+    match val {
+        Value::Nil => {
+            release_keyboard();
+            set_focus(lua, None)?
+        },
+        Value::UserData(ref obj) => Client::cast(obj.clone())?.activate(lua)?,
+        _ => {
+            return Err(rlua::Error::RuntimeError(
+                "client.focus must be a client or nil".into()
+            ))
+        },
+    }
+    Ok(Value::Nil)
+}
+
+fn get_clients<'lua>(lua: rlua::Context<'lua>, _: rlua::MultiValue<'lua>) -> rlua::Result<Vec<Client<'lua>>> {
+    clients(lua)
+}
+
+fn get_name<'lua>(_: rlua::Context<'lua>, client: Client<'lua>) -> rlua::Result<String> {
+    client.get_name()
 }
 
-fn dummy_table<'lua>(lua: rlua::Context<'lua>, _: rlua::Value) -> rlua::Result<Table<'lua>> {
-    Ok(lua.create_table()?)
+fn get_app_id<'lua>(_: rlua::Context<'lua>, client: Client<'lua>) -> rlua::Result<String> {
+    Ok(client.state()?.app_id.clone())
+}
+
+fn get_class<'lua>(_: rlua::Context<'lua>, client: Client<'lua>) -> rlua::Result<String> {
+    Ok(split_app_id(&client.state()?.app_id).0.into())
+}
+
+fn get_instance<'lua>(_: rlua::Context<'lua>, client: Client<'lua>) -> rlua::Result<String> {
+    Ok(split_app_id(&client.state()?.app_id).1.into())
+}
+
+/// Splits an app id into the class and instance that X11 clients have, so
+/// rules written for them keep working.
+///
+/// The class is the last part of a reverse domain name app id like
+/// "org.mozilla.firefox" and the instance is the rest. App ids without a
+/// dot are both.
+fn split_app_id(app_id: &str) -> (&str, &str) {
+    match app_id.rfind('.') {
+        Some(dot) => (&app_id[dot + 1..], &app_id[..dot]),
+        None => (app_id, app_id)
+    }
+}
+
+fn get_icon<'lua>(lua: rlua::Context<'lua>, client: Client<'lua>) -> rlua::Result<Value<'lua>> {
+    match client.state()?.icon.clone() {
+        Some(icon) => awesome::surface_to_lua(lua, icon),
+        None => Ok(Value::Nil)
+    }
+}
+
+/// The sizes the icon of the client is available in, smallest first.
+fn icon_sizes<'lua>(_: rlua::Context<'lua>, client: Client<'lua>) -> rlua::Result<Vec<u32>> {
+    Ok(IconDirs::from_env().icon_sizes(&client.state()?.app_id))
+}
+
+fn set_sticky<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut client, sticky): (Client<'lua>, bool)
+) -> rlua::Result<()> {
+    client.set_sticky(lua, sticky)
+}
+
+fn get_sticky<'lua>(_: rlua::Context<'lua>, client: Client<'lua>) -> rlua::Result<bool> {
+    client.is_sticky()
+}
+
+/// Sets `above`, `below` or `ontop`. They are only remembered, the
+/// compositor decides how toplevels are stacked.
+fn set_stacking_hint<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut client, value): (Client<'lua>, bool),
+    hint: &str
+) -> rlua::Result<()> {
+    {
+        let mut state = client.state_mut()?;
+        let field = match hint {
+            "above" => &mut state.above,
+            "below" => &mut state.below,
+            _ => &mut state.ontop
+        };
+        if *field == value {
+            return Ok(());
+        }
+        *field = value;
+    }
+    warn!(
+        "client.{} has no effect, the compositor controls the stacking of clients",
+        hint
+    );
+    Object::emit_signal(lua, &client, &format!("property::{}", hint), Value::Nil)
+}
+
+fn isvisible<'lua>(lua: rlua::Context<'lua>, client: Client<'lua>) -> rlua::Result<bool> {
+    is_visible(lua, &client)
+}
+
+fn activate<'lua>(
+    lua: rlua::Context<'lua>,
+    (client, _args): (Client<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    client.activate(lua)
+}
+
+fn client_geometry<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut client, geometry): (Client<'lua>, Option<Table<'lua>>)
+) -> rlua::Result<Table<'lua>> {
+    if let Some(geometry) = geometry {
+        let mut geo = client.get_geometry()?;
+        if let Some(x) = geometry.get::<_, Option<i32>>("x")? {
+            geo.origin.x = x;
+        }
+        if let Some(y) = geometry.get::<_, Option<i32>>("y")? {
+            geo.origin.y = y;
+        }
+        if let Some(width) = geometry.get::<_, Option<u32>>("width")? {
+            geo.size.width = width;
+        }
+        if let Some(height) = geometry.get::<_, Option<u32>>("height")? {
+            geo.size.height = height;
+        }
+        client.set_geometry(lua, geo)?;
+    }
+    let geometry = client.get_geometry()?;
+    let Origin { x, y } = geometry.origin;
+    let Size { width, height } = geometry.size;
+    let table = lua.create_table()?;
+    table.set("x", x)?;
+    table.set("y", y)?;
+    table.set("width", width)?;
+    table.set("height", height)?;
+    Ok(table)
 }
@@ -0,0 +1,240 @@
+//! Finding the icon of an application by its app id, following the
+//! freedesktop icon theme layout.
+//!
+//! Only the `hicolor` theme is searched, which every application installs
+//! its icon into, falling back to the unthemed pixmaps directories.
+
+use std::{env, fs, path::PathBuf};
+
+/// The theme applications are required to install their icons into.
+const FALLBACK_THEME: &str = "hicolor";
+
+/// Where icons are searched, in order of preference.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct IconDirs {
+    /// Directories containing icon themes, e.g. `/usr/share/icons`.
+    pub themes: Vec<PathBuf>,
+    /// Directories containing unthemed icons, e.g. `/usr/share/pixmaps`.
+    pub pixmaps: Vec<PathBuf>
+}
+
+impl IconDirs {
+    /// The directories from the XDG base directory environment variables.
+    pub fn from_env() -> Self {
+        let home = env::var("HOME").ok().map(PathBuf::from);
+        let data_home = env::var("XDG_DATA_HOME")
+            .ok()
+            .map(PathBuf::from)
+            .or_else(|| home.as_ref().map(|home| home.join(".local/share")));
+        let mut data_dirs: Vec<PathBuf> = data_home.into_iter().collect();
+        data_dirs.extend(
+            env::var("XDG_DATA_DIRS")
+                .unwrap_or("/usr/local/share:/usr/share".into())
+                .split(':')
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+        );
+        let mut themes: Vec<PathBuf> = home.map(|home| home.join(".icons")).into_iter().collect();
+        themes.extend(data_dirs.iter().map(|dir| dir.join("icons")));
+        IconDirs {
+            themes,
+            pixmaps: data_dirs.iter().map(|dir| dir.join("pixmaps")).collect()
+        }
+    }
+
+    /// Finds the file of the icon for `app_id` that is best shown at `size`
+    /// pixels.
+    ///
+    /// An icon of exactly that size is preferred, then a scalable one, then
+    /// the closest size, preferring larger icons. A `size` of 0 means the
+    /// largest icon.
+    pub fn find_icon(&self, app_id: &str, size: u32) -> Option<PathBuf> {
+        if !is_valid_app_id(app_id) {
+            return None;
+        }
+        let png = format!("{}.png", app_id);
+        let svg = format!("{}.svg", app_id);
+        let mut best: Option<(u32, PathBuf)> = None;
+        let mut scalable = None;
+        for (icon_size, dir) in self.app_dirs() {
+            match icon_size {
+                Some(icon_size) => {
+                    let path = dir.join(&png);
+                    if !path.is_file() {
+                        continue;
+                    }
+                    if icon_size == size {
+                        return Some(path);
+                    }
+                    let better = match best {
+                        None => true,
+                        Some((best_size, _)) => is_closer(icon_size, best_size, size)
+                    };
+                    if better {
+                        best = Some((icon_size, path));
+                    }
+                },
+                None if scalable.is_none() => {
+                    let path = dir.join(&svg);
+                    if path.is_file() {
+                        scalable = Some(path);
+                    }
+                },
+                None => {}
+            }
+        }
+        if size != 0 && scalable.is_some() {
+            return scalable;
+        }
+        if let Some((_, path)) = best {
+            return Some(path);
+        }
+        if scalable.is_some() {
+            return scalable;
+        }
+        self.pixmaps
+            .iter()
+            .flat_map(|dir| vec![dir.join(&png), dir.join(&svg)])
+            .find(|path| path.is_file())
+    }
+
+    /// The sizes the icon of `app_id` is available in, smallest first.
+    ///
+    /// Scalable and unthemed icons have no size and aren't listed.
+    pub fn icon_sizes(&self, app_id: &str) -> Vec<u32> {
+        if !is_valid_app_id(app_id) {
+            return Vec::new();
+        }
+        let png = format!("{}.png", app_id);
+        let mut sizes: Vec<u32> = self
+            .app_dirs()
+            .into_iter()
+            .filter_map(|(size, dir)| size.filter(|_| dir.join(&png).is_file()))
+            .collect();
+        sizes.sort();
+        sizes.dedup();
+        sizes
+    }
+
+    /// The application icon directories of the fallback theme, with their
+    /// size. Scalable directories have no size.
+    fn app_dirs(&self) -> Vec<(Option<u32>, PathBuf)> {
+        let mut dirs = Vec::new();
+        for theme in &self.themes {
+            let entries = match fs::read_dir(theme.join(FALLBACK_THEME)) {
+                Ok(entries) => entries,
+                Err(_) => continue
+            };
+            for entry in entries.filter_map(Result::ok) {
+                let name = entry.file_name();
+                let size = match name.to_str() {
+                    Some("scalable") => None,
+                    Some(name) => match parse_size(name) {
+                        Some(size) => Some(size),
+                        None => continue
+                    },
+                    None => continue
+                };
+                dirs.push((size, entry.path().join("apps")));
+            }
+        }
+        dirs
+    }
+}
+
+/// Parses the name of a theme directory like "48x48".
+fn parse_size(name: &str) -> Option<u32> {
+    let mut parts = name.splitn(2, 'x');
+    let width = parts.next()?.parse().ok()?;
+    let height: u32 = parts.next()?.parse().ok()?;
+    if width == height {
+        Some(width)
+    } else {
+        None
+    }
+}
+
+/// Whether an icon of `size` is a better match for `wanted` than one of
+/// `other`.
+fn is_closer(size: u32, other: u32, wanted: u32) -> bool {
+    if wanted == 0 {
+        return size > other;
+    }
+    let distance = |size: u32| (i64::from(size) - i64::from(wanted)).abs();
+    distance(size) < distance(other) || (distance(size) == distance(other) && size > other)
+}
+
+/// App ids come from other clients, so they mustn't be able to point
+/// outside of the icon directories.
+fn is_valid_app_id(app_id: &str) -> bool {
+    !app_id.is_empty() && !app_id.contains('/') && !app_id.starts_with('.')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+
+    fn install(themes: &Path, dir: &str, file: &str) {
+        let dir = themes.join("hicolor").join(dir).join("apps");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(file), b"").unwrap();
+    }
+
+    fn icon_dirs(root: &Path) -> IconDirs {
+        IconDirs {
+            themes: vec![root.join("icons")],
+            pixmaps: vec![root.join("pixmaps")]
+        }
+    }
+
+    #[test]
+    fn icon_theme_sizes() {
+        let root = tempfile::tempdir().unwrap();
+        let themes = root.path().join("icons");
+        install(&themes, "48x48", "firefox.png");
+        install(&themes, "16x16", "firefox.png");
+        install(&themes, "scalable", "firefox.svg");
+        install(&themes, "32x32", "other.png");
+        install(&themes, "24x32", "firefox.png");
+        let dirs = icon_dirs(root.path());
+        assert_eq!(dirs.icon_sizes("firefox"), vec![16, 48]);
+        assert_eq!(dirs.icon_sizes("missing"), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn icon_theme_lookup() {
+        let root = tempfile::tempdir().unwrap();
+        let themes = root.path().join("icons");
+        install(&themes, "16x16", "term.png");
+        install(&themes, "64x64", "term.png");
+        let dirs = icon_dirs(root.path());
+        let sized = |size: u32| themes.join(format!("hicolor/{0}x{0}/apps/term.png", size));
+        assert_eq!(dirs.find_icon("term", 16), Some(sized(16)));
+        // The closest size is used, preferring the larger one on a tie.
+        assert_eq!(dirs.find_icon("term", 24), Some(sized(16)));
+        assert_eq!(dirs.find_icon("term", 40), Some(sized(64)));
+        assert_eq!(dirs.find_icon("term", 0), Some(sized(64)));
+        // A scalable icon is better than one of the wrong size.
+        install(&themes, "scalable", "term.svg");
+        let scalable = themes.join("hicolor/scalable/apps/term.svg");
+        assert_eq!(dirs.find_icon("term", 24), Some(scalable));
+        assert_eq!(dirs.find_icon("term", 64), Some(sized(64)));
+        assert_eq!(dirs.find_icon("term", 0), Some(sized(64)));
+    }
+
+    #[test]
+    fn icon_theme_pixmaps() {
+        let root = tempfile::tempdir().unwrap();
+        let pixmaps = root.path().join("pixmaps");
+        fs::create_dir_all(&pixmaps).unwrap();
+        fs::write(pixmaps.join("xterm.svg"), b"").unwrap();
+        let dirs = icon_dirs(root.path());
+        assert_eq!(dirs.find_icon("xterm", 32), Some(pixmaps.join("xterm.svg")));
+        assert_eq!(dirs.find_icon("missing", 32), None);
+        // App ids can't escape the icon directories.
+        fs::write(root.path().join("secret.png"), b"").unwrap();
+        assert_eq!(dirs.find_icon("../secret", 32), None);
+        assert_eq!(dirs.find_icon("", 32), None);
+    }
+}
@@ -1,54 +1,207 @@
 //! A wrapper around a Cairo image surface.
+//!
+//! The surface is replaced whenever the drawable is resized, which leaves
+//! contexts Lua created for the old surface drawing nowhere. Every surface
+//! has a generation, so a cache can check that what it kept is current
+//! with one comparison per frame:
+//!
+//! ```lua
+//! local surface = cache.generation and drawable:surface_if_current(cache.generation)
+//! if not surface then
+//!     cache.surface, cache.generation = drawable:get_surface()
+//!     cache.cr = cairo.Context(cache.surface)
+//! end
+//! ```
+//!
+//! The input region of the surface can be set from the alpha of the
+//! content, which is kept cheap during animations, see `input_region`.
+//!
+//! The surface is allocated at the scale of the output the drawable is on,
+//! and is painted in logical pixels. Lua can paint the content again for
+//! outputs of other scales instead, see `variants`.
+//!
+//! Effects like a drop shadow can be applied to the content on its way to
+//! the buffer, see `effects`.
+//!
+//! Content much wider than the drawable, like a taskbar of hundreds of
+//! windows, can be scrolled and painted a range at a time, see `strip`.
+//!
+//! The buffer can be a dmabuf rather than shared memory, see `backend`, or
+//! the memory Lua paints into, see `mapped`.
 
-use std::{default::Default, fs::File, io::Write};
+mod backend;
+mod capacity;
+mod content_fit;
+mod damage;
+mod effects;
+mod frame;
+mod input_region;
+mod mapped;
+mod pixel_format;
+mod snapshot;
+mod strip;
+mod variants;
 
-use cairo::{Format, ImageSurface};
-use glib::translate::ToGlibPtr;
-use rlua::{self, LightUserData, Table, UserData, UserDataMethods, Value};
-use tempfile;
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering}
+};
+
+use cairo::{Context, Format, ImageSurface, Operator};
+use glib::{translate::ToGlibPtr, Continue};
+use rlua::{self, LightUserData, MultiValue, Table, ToLua, UserData, UserDataMethods, Value};
+use wayland_client::protocol::wl_buffer::WlBuffer;
 
-use crate::area::{Area, Origin, Size};
+use crate::allocations;
+use crate::area::{self, Area, Origin, Size};
+use crate::clock;
 use crate::common::{
-    class::{self, Class},
+    class::{self, Class, ClassDef},
+    color::Color,
+    color_profile::Profile,
     object::{self, Object},
-    property::Property
+    signal
 };
-use crate::wayland_obj::{self, XdgToplevel};
+use crate::memory_pressure::{self, Memory};
+use crate::objects::drawin::Drawin;
+use crate::scheduler::{self, Priority};
+use crate::svg::{self, Svg, SvgError, SvgHandle};
+use crate::wayland_obj::{self, MappedBuffer};
 
-#[derive(Debug)]
+pub use self::backend::write_buffer_stats;
+use self::backend::{Backend, DrawableBuffer};
+use self::capacity::Capacity;
+pub use self::content_fit::ContentFit;
+use self::content_fit::{fit_content, Image};
+use self::damage::DamageTree;
+pub use self::effects::{Effect, EffectStats};
+use self::effects::{Effects, Pixels};
+use self::frame::{FrameStats, Placement, Scratch, Source};
+pub use self::input_region::ScanStats;
+use self::input_region::{AlphaRegion, ScanSchedule};
+pub use self::pixel_format::PixelFormat;
+use self::snapshot::{Snapshot, SnapshotCache};
+use self::strip::{Strip, MAX_STRIP_WIDTH};
+use self::variants::{Variants, MAX_SCALE};
+
+#[derive(Debug, Default)]
 pub struct DrawableState {
-    temp_file: File,
-    wayland_shell: Option<XdgToplevel>,
     pub surface: Option<ImageSurface>,
+    /// Increased whenever `surface` is replaced.
+    surface_generation: u64,
+    /// The memory of `surface`, kept while the drawable shrinks.
+    capacity: Capacity,
+    buffer: Option<DrawableBuffer>,
+    /// The kind of buffer Lua asked for.
+    buffer_backend: Backend,
+    /// Why the buffer is in shared memory though Lua asked for a dmabuf,
+    /// or why the content is copied though it asked for a mapped buffer.
+    buffer_fallback: Option<String>,
+    /// The buffer `surface` is painted in, if Lua asked for a mapped one.
+    mapped: Option<MappedBuffer>,
+    /// Whether the last frame is shown from `mapped` rather than `buffer`.
+    mapped_shown: bool,
+    /// The pixel format of the surfaces and the buffer.
+    format: PixelFormat,
     geo: Area,
-    // TODO Use this to determine whether we draw this or not
-    refreshed: bool
+    /// Where in the surface the top left corner of the buffer is taken from.
+    ///
+    /// This allows the content to be scrolled without Lua repainting it.
+    content_offset: Origin,
+    /// The size the compositor granted the surface the drawable is shown
+    /// on, if it's known.
+    surface_size: Option<Size>,
+    /// The part of the buffer that's shown, at scale 1, while the drawin is
+    /// partly past the edges of its output, see `set_clip`.
+    clip: Option<Area>,
+    /// How the content is shown when it isn't the size of the surface.
+    content_fit: ContentFit,
+    /// The color around letterboxed or cropped content.
+    fill: Color,
+    /// What the values of the pixels mean.
+    color_profile: Profile,
+    /// Set if the buffer has content that can be shown on the surface.
+    presentable: bool,
+    /// Set once Lua refreshed the current surface, so there's content to
+    /// write when the buffer has to be written again without a refresh.
+    /// Every refresh writes the buffer, whether this is set or not.
+    refreshed: bool,
+    /// The snapshot to restore once the surface is allocated.
+    pending_snapshot: Option<String>,
+    /// The parts of the surface Lua repainted since the last refresh.
+    damage: DamageTree,
+    /// The content offset the buffer was last written at directly, which
+    /// is when damaged parts can be written on their own.
+    written_offset: Option<Origin>,
+    /// The vectors a refresh needs, kept for the next one. The parts of the
+    /// buffer changed by the last write are in its `rects`.
+    scratch: Scratch,
+    /// Whether the last write only changed `scratch.rects`, rather than all
+    /// of the buffer.
+    partial_write: bool,
+    /// The work done by refreshes, see `drawable:frame_stats()`.
+    frame_stats: FrameStats,
+    /// Finds the input region, while it's set from the alpha of the content.
+    alpha_region: Option<AlphaRegion>,
+    /// Pixels with an alpha above this take input.
+    alpha_threshold: u8,
+    /// Which frames of an animation the input region is scanned in.
+    scan_schedule: ScanSchedule,
+    /// The parts of the buffer that take input, or `None` if all of it does.
+    input_region: Option<Vec<Area>>,
+    /// Set when `input_region` changed since the drawin took it.
+    input_region_changed: bool,
+    /// The parts of the surface Lua lets take input, see `set_input_shape`.
+    input_shape: Option<Vec<Area>>,
+    /// The parts of the surface inside its shape, see `set_bounding_shape`.
+    bounding_shape: Option<Vec<Area>>,
+    /// The content painted for other scales.
+    variants: Variants,
+    /// The scale of the variant Lua paints into, or `None` for `surface`.
+    painting: Option<i32>,
+    /// The scale of the variant in the buffer, once there is one.
+    shown_scale: Option<i32>,
+    /// Applied to the content copied into the buffer, which they can grow.
+    effects: Effects,
+    /// How opaque the content is shown, `None` for fully.
+    opacity: Option<f64>,
+    /// The content wider than the drawable, if Lua set a virtual width.
+    strip: Option<Strip>,
+    /// Set while painting the invalid ranges of the strip is deferred.
+    fill_queued: bool,
+    /// Set while the drawable isn't shown, so refreshes don't write the
+    /// buffer, see `set_suspended`.
+    suspended: bool,
+    /// Set when a refresh waits for the compositor to be done with the last
+    /// frame, see `flush_throttled`.
+    throttled: bool
 }
 
-pub type Drawable<'lua> = Object<'lua, DrawableState>;
-
-impl Default for DrawableState {
-    fn default() -> Self {
-        let temp_file = tempfile::tempfile().expect("Could not make a temp file for the buffer");
-        DrawableState {
-            temp_file,
-            wayland_shell: None,
-            surface: None,
-            geo: Area::default(),
-            refreshed: false
-        }
+/// The buffer and the surfaces are released when a drawable is collected.
+impl Drop for DrawableState {
+    fn drop(&mut self) {
+        self.destroy();
     }
 }
 
+/// The drawables waiting for their content to settle, so its input region
+/// can be scanned, by the id of the wait.
+const SETTLING_HANDLE: &'static str = "__drawables_settling";
+
+static NEXT_SETTLE_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// How long the content has to stay the same for the frame it settled on
+/// to be scanned.
+const SETTLE_MILLIS: u32 = 100;
+
+pub type Drawable<'lua> = Object<'lua, DrawableState>;
+
 impl<'lua> Drawable<'lua> {
     pub fn new(lua: rlua::Context<'lua>) -> rlua::Result<Drawable> {
         let class = class::class_setup(lua, "drawable")?;
-        let builder = Drawable::allocate(lua, class)?;
-        // TODO Do properly
-        let table = lua.create_table()?;
-        table.set("geometry", lua.create_function(geometry)?)?;
-        table.set("refresh", lua.create_function(refresh)?)?;
-        Ok(builder.add_to_meta(table)?.build())
+        Ok(Drawable::allocate(lua, class)?.build())
     }
 
     pub fn get_geometry(&self) -> rlua::Result<Area> {
@@ -56,11 +209,19 @@ impl<'lua> Drawable<'lua> {
         Ok(drawable.geo)
     }
 
+    /// The surface Lua paints into, which is the surface of a variant
+    /// between `begin_variant` and `end_variant`.
+    ///
+    /// Lua gets a reference of its own: wibox wraps the pointer with
+    /// `cairo.Surface(surface, true)`, which takes the reference over and
+    /// drops it when the wrapper is collected. Dropping the surface here,
+    /// on a resize or once the drawable is destroyed, only drops ours, so
+    /// the pointer stays valid for as long as Lua holds it.
     pub fn get_surface(&self) -> rlua::Result<Value<'lua>> {
         let drawable = self.state()?;
-        Ok(match drawable.surface {
+        Ok(match drawable.painted_surface() {
             None => Value::Nil,
-            Some(ref image) => {
+            Some(image) => {
                 let stash = image.to_glib_none();
                 let ptr = stash.0;
                 // NOTE
@@ -76,110 +237,3040 @@ impl<'lua> Drawable<'lua> {
         })
     }
 
-    /// Sets the geometry, and allocates a new surface.
+    pub fn surface_generation(&self) -> rlua::Result<u64> {
+        Ok(self.state()?.surface_generation)
+    }
+
+    /// Get the Wayland buffer the contents of the drawable are copied into,
+    /// and its size in pixels.
+    ///
+    /// There's no buffer to show while the content doesn't fit the surface,
+    /// see `set_content_fit`.
+    pub fn wl_buffer(&self) -> rlua::Result<Option<(WlBuffer, Size)>> {
+        let drawable = self.state()?;
+        if !drawable.presentable {
+            return Ok(None);
+        }
+        if drawable.mapped_shown {
+            return Ok(drawable
+                .mapped
+                .as_ref()
+                .map(|mapped| (mapped.wl_buffer().clone(), mapped.size())));
+        }
+        Ok(drawable
+            .buffer
+            .as_ref()
+            .map(|buffer| (buffer.wl_buffer().clone(), buffer.size())))
+    }
+
+    /// Records that the buffer `wl_buffer` returned was attached, so a
+    /// mapped buffer isn't painted into again before the compositor
+    /// released it.
+    pub fn buffer_attached(&self) -> rlua::Result<()> {
+        let drawable = self.state()?;
+        if let (true, Some(mapped)) = (drawable.mapped_shown, drawable.mapped.as_ref()) {
+            mapped.attached();
+        }
+        Ok(())
+    }
+
+    /// Sets the size the compositor granted the surface the drawable is shown
+    /// on, which the buffer is allocated at.
+    ///
+    /// `None` means the buffer is the size of the drawable.
+    pub fn set_surface_size(&mut self, size: Option<Size>) -> rlua::Result<()> {
+        {
+            let mut drawable = self.state_mut()?;
+            drawable.surface_size = size;
+            if !drawable.refreshed {
+                return Ok(());
+            }
+            drawable.update_buffer()?;
+            drawable.rescan_input_region();
+        }
+        self.refresh_drawin()
+    }
+
+    /// Only puts `clip` of the buffer into it, in the coordinates of the
+    /// whole buffer at scale 1, or all of it for `None`.
+    ///
+    /// A drawin partly past the edges of its output is only shown where
+    /// it's on the output, some compositors don't allow a surface past
+    /// them. The geometry stays the whole of it, while the buffer damage,
+    /// the input region and the opaque region are in the coordinates of
+    /// the clipped buffer.
+    pub fn set_clip(&mut self, clip: Option<Area>) -> rlua::Result<()> {
+        let mut drawable = self.state_mut()?;
+        if drawable.clip == clip {
+            return Ok(());
+        }
+        drawable.clip = clip;
+        drawable.written_offset = None;
+        drawable.input_region_changed = true;
+        if drawable.refreshed {
+            drawable.update_buffer()?;
+        }
+        Ok(())
+    }
+
+    /// The part of the buffer that's shown, see `set_clip`.
+    pub fn clip(&self) -> rlua::Result<Option<Area>> {
+        Ok(self.state()?.clip)
+    }
+
+    /// Stops writing the buffer when the content is refreshed while the
+    /// drawable isn't shown. Once it's shown again the buffer is written
+    /// whole, with the content of the last refresh.
+    pub fn set_suspended(&mut self, suspended: bool) -> rlua::Result<()> {
+        let mut drawable = self.state_mut()?;
+        if drawable.suspended == suspended {
+            return Ok(());
+        }
+        drawable.suspended = suspended;
+        if suspended || !drawable.refreshed {
+            return Ok(());
+        }
+        // The damage of the skipped refreshes is in the buffer all at once.
+        drawable.written_offset = None;
+        drawable.update_buffer()?;
+        drawable.rescan_input_region();
+        Ok(())
+    }
+
+    pub fn get_content_fit(&self) -> rlua::Result<ContentFit> {
+        Ok(self.state()?.content_fit)
+    }
+
+    /// Sets how the content is shown when the drawable isn't the size of the
+    /// surface, and the color around it for letterboxing and cropping.
+    pub fn set_content_fit(&mut self, content_fit: ContentFit, fill: Color) -> rlua::Result<()> {
+        {
+            let mut drawable = self.state_mut()?;
+            drawable.content_fit = content_fit;
+            drawable.fill = fill;
+            if !drawable.refreshed {
+                return Ok(());
+            }
+            drawable.update_buffer()?;
+            drawable.rescan_input_region();
+        }
+        self.refresh_drawin()
+    }
+
+    pub fn get_color_profile(&self) -> rlua::Result<Profile> {
+        Ok(self.state()?.color_profile)
+    }
+
+    /// Sets what the values Lua paints mean, which the effects mix in.
+    pub fn set_color_profile(&mut self, color_profile: Profile) -> rlua::Result<()> {
+        {
+            let mut drawable = self.state_mut()?;
+            if drawable.color_profile == color_profile {
+                return Ok(());
+            }
+            drawable.color_profile = color_profile;
+            drawable
+                .effects
+                .set_transfer_function(color_profile.transfer_function());
+            if drawable.effects.is_empty() || !drawable.refreshed {
+                return Ok(());
+            }
+            drawable.written_offset = None;
+            drawable.update_buffer()?;
+        }
+        self.refresh_drawin()
+    }
+
+    /// Sets the geometry, and allocates a new surface if the size changed.
     pub fn set_geometry(&mut self, lua: rlua::Context<'lua>, geometry: Area) -> rlua::Result<()> {
+        let old = {
+            let mut drawable = self.state_mut()?;
+            std::mem::replace(&mut drawable.geo, geometry)
+        };
+        if old.size != geometry.size {
+            self.allocate_surface(lua)?;
+        }
+        // Only what changed is signalled, a move doesn't relayout wibox.
+        for signal in area::geometry_signals(old, geometry) {
+            Object::emit_signal(lua, self, signal, Value::Nil)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the surface with one of the size of the content, which is
+    /// as wide as the strip if there is one.
+    fn allocate_surface(&mut self, lua: rlua::Context<'lua>) -> rlua::Result<()> {
         use rlua::Error::RuntimeError;
         let obj_clone = self.clone();
         let mut drawable = self.state_mut()?;
-        let size_changed = drawable.geo != geometry;
-        drawable.geo = geometry;
-        if size_changed {
-            drawable.refreshed = false;
-            drawable.surface = None;
-            drawable.wayland_shell = Some(
-                wayland_obj::create_xdg_toplevel(None)
-                    .expect("Could not construct an xdg toplevel for a drawable")
-            );
-            let size: Size = geometry.size;
-
-            if size.width > 0 && size.height > 0 {
-                let temp_file = tempfile::tempfile().expect("Could not make new temp file");
-                temp_file
-                    .set_len(size.width as u64 * size.height as u64 * 4)
-                    .expect("Could not set file length");
-                drawable.surface = Some(
-                    ImageSurface::create(Format::ARgb32, size.width as i32, size.height as i32)
-                        .map_err(|err| RuntimeError(format!("Could not allocate {:?}", err)))?
-                );
-                {
-                    let shell = drawable.wayland_shell.as_mut().unwrap();
-                    shell.set_size(size);
-                    shell
-                        .set_surface(&temp_file, size)
-                        .map_err(|_| RuntimeError(format!("Could not set surface for drawable")))?;
+        // The buffer is kept, so the previous frame can be shown until
+        // the new surface is painted.
+        drawable.refreshed = false;
+        drawable.presentable = false;
+        if let Some(surface) = drawable.surface.take() {
+            capacity::retire(&surface);
+        }
+        drawable.surface_generation += 1;
+        drawable.variants.clear();
+        drawable.painting = None;
+        // A strip is painted at scale 1, like it can't have variants.
+        let scale = if drawable.strip.is_some() {
+            1
+        } else {
+            drawable.variants.output_scale()
+        };
+        drawable.variants.set_own_scale(scale);
+        let size = drawable.content_size();
+        let root = drawable.damage.root();
+        drawable.damage.resize(root, size);
+        if let Some(strip) = drawable.strip.as_mut() {
+            strip.invalidate(0, strip.width());
+        }
+
+        drawable.mapped = None;
+        drawable.mapped_shown = false;
+
+        if size.width > 0 && size.height > 0 {
+            let pixels = Size {
+                width: size.width * scale as u32,
+                height: size.height * scale as u32
+            };
+            // A strip is wider than the buffer, its content is copied.
+            let mapped = if drawable.buffer_backend == Backend::Mapped && drawable.strip.is_none() {
+                let format = drawable.format;
+                backend::allocate_mapped(pixels, format, &mut drawable.buffer_fallback)
+            } else {
+                None
+            };
+            let surface = match mapped {
+                Some((buffer, surface)) => {
+                    drawable.capacity.clear();
+                    drawable.mapped = Some(buffer);
+                    Ok(surface)
+                },
+                None => {
+                    let format = drawable.format.cairo();
+                    drawable.capacity.surface(format, pixels)
                 }
-                drawable.temp_file = temp_file;
-                Object::emit_signal(lua, &obj_clone, "property::surface".into(), Value::Nil)?;
+            };
+            let surface = match surface {
+                Ok(surface) => surface,
+                Err(err) => {
+                    // Nothing is shown rather than the previous frame,
+                    // which can't be replaced.
+                    drawable.buffer = None;
+                    drawable.written_offset = None;
+                    return Err(RuntimeError(format!("Could not allocate {:?}", err)));
+                }
+            };
+            if scale != 1 {
+                variants::set_device_scale(&surface, scale);
+            }
+            drawable.surface = Some(surface);
+            // Restored before Lua is told about the surface, so that
+            // anything Lua paints right away isn't overwritten.
+            let restored = match drawable.pending_snapshot.take() {
+                Some(key) => Some((key.clone(), drawable.restore_snapshot(&key)?)),
+                None => None
+            };
+            let strip = drawable.strip.is_some();
+            // Drop the borrow, Lua might access the drawable in the signal.
+            drop(drawable);
+            if let Some((key, restored)) = restored {
+                match restored {
+                    Ok(scaled) => {
+                        let result = restore_result(lua, scaled)?;
+                        Object::emit_signal(lua, &obj_clone, "snapshot::restored".into(), result)?
+                    },
+                    Err(err) => debug!("Not restoring snapshot \"{}\": {}", key, err)
+                }
+            }
+            Object::emit_signal(lua, &obj_clone, "property::surface".into(), Value::Nil)?;
+            if strip {
+                self.queue_fill(lua)?;
+            }
+        } else {
+            // Nothing is painted at this size, the previous frame isn't
+            // kept for it.
+            drawable.destroy();
+        }
+        Ok(())
+    }
+
+    /// Marks `rect` of the surface as repainted, so the next refresh only
+    /// copies and damages the repainted parts.
+    ///
+    /// Without any damage a refresh copies the whole surface.
+    pub fn add_damage(&mut self, rect: Area) -> rlua::Result<()> {
+        let mut drawable = self.state_mut()?;
+        let root = drawable.damage.root();
+        drawable.damage.add_damage(root, rect);
+        Ok(())
+    }
+
+    /// Puts the parts of the buffer that changed since this was last called
+    /// into `damage`, returning false if all of it might have.
+    ///
+    /// The drawable keeps the vector `damage` had for the next refresh, so
+    /// passing the same one every time doesn't allocate.
+    pub fn take_buffer_damage(&mut self, damage: &mut Vec<Area>) -> rlua::Result<bool> {
+        let mut drawable = self.state_mut()?;
+        let partial = std::mem::replace(&mut drawable.partial_write, false);
+        if partial {
+            std::mem::swap(&mut drawable.scratch.rects, damage);
+        }
+        Ok(partial)
+    }
+
+    pub fn frame_stats(&self) -> rlua::Result<FrameStats> {
+        Ok(self.state()?.frame_stats)
+    }
+
+    /// The kind of buffer the drawable has, if it has one, and why it isn't
+    /// the kind Lua asked for.
+    pub fn buffer_backend(&self) -> rlua::Result<(Option<Backend>, Option<String>)> {
+        let drawable = self.state()?;
+        if drawable.mapped_shown {
+            return Ok((Some(Backend::Mapped), None));
+        }
+        Ok((
+            drawable.buffer.as_ref().map(DrawableBuffer::backend),
+            drawable.buffer_fallback.clone()
+        ))
+    }
+
+    pub fn format(&self) -> rlua::Result<PixelFormat> {
+        Ok(self.state()?.format)
+    }
+
+    /// Sets the pixel format of the surface and the buffer. The surface is
+    /// allocated again, so Lua has to repaint, like after a resize.
+    ///
+    /// Without alpha the compositor doesn't blend the drawable, and the
+    /// shadows of effects and the opacity aren't shown.
+    pub fn set_format(&mut self, lua: rlua::Context<'lua>, format: PixelFormat) -> rlua::Result<()> {
+        use rlua::Error::RuntimeError;
+        if !wayland_obj::supports_format(format.shm()) {
+            return Err(RuntimeError(format!(
+                "drawable.format: the compositor doesn't support {}",
+                format.name()
+            )));
+        }
+        let allocate = {
+            let mut drawable = self.state_mut()?;
+            if drawable.format == format {
+                return Ok(());
             }
+            drawable.format = format;
+            drawable.surface.is_some()
+        };
+        if allocate {
+            self.allocate_surface(lua)?;
         }
         Ok(())
     }
 
+    /// Asks for the kind of buffer the content is copied into from the
+    /// next refresh on, which is tried again if it couldn't be had before.
+    ///
+    /// A mapped buffer is only allocated with the next surface, so it's
+    /// best asked for before the drawable gets its size.
+    pub fn set_buffer_backend(&mut self, backend: Backend) -> rlua::Result<()> {
+        let mut drawable = self.state_mut()?;
+        drawable.buffer_backend = backend;
+        drawable.buffer_fallback = None;
+        Ok(())
+    }
+
     /// Signals that the drawable's surface was updated.
-    pub fn refresh(&mut self) -> rlua::Result<()> {
+    ///
+    /// While the content keeps changing its input region is only scanned in
+    /// some frames, and in the frame the content settles on.
+    ///
+    /// While the compositor isn't done with the last frame of the drawin
+    /// the drawable is shown on, the refresh waits for it, and all the
+    /// refreshes until then are written as one.
+    ///
+    /// A drawable that isn't on a drawin, like a titlebar, is refreshed the
+    /// same, its buffer is written for whatever shows it. Before it has a
+    /// size there's nothing to write.
+    pub fn refresh(&mut self, lua: rlua::Context<'lua>) -> rlua::Result<()> {
+        if self.frame_pending()? {
+            let mut drawable = self.state_mut()?;
+            if !std::mem::replace(&mut drawable.throttled, true) {
+                drawable.frame_stats.throttled_frames += 1;
+            } else {
+                drawable.frame_stats.coalesced_frames += 1;
+            }
+            return Ok(());
+        }
+        let settle = {
+            let mut drawable = self.state_mut()?;
+            drawable.update_buffer()?;
+            if drawable.alpha_region.is_some() && drawable.scan_schedule.frame() {
+                drawable.scan_input_region();
+            }
+            drawable.scan_schedule.arm()
+        };
+        if let Some(frame) = settle {
+            settle_later(lua, self.clone(), frame)?;
+        }
+        self.refresh_drawin()
+    }
+
+    /// Does the refresh that waited for the last frame to be done, if
+    /// there's one.
+    pub fn flush_throttled(&mut self, lua: rlua::Context<'lua>) -> rlua::Result<()> {
+        if !std::mem::replace(&mut self.state_mut()?.throttled, false) {
+            return Ok(());
+        }
+        self.refresh(lua)
+    }
+
+    /// Paints `area` of the surface Lua paints into, or all of it, with
+    /// `color`, like Lua clearing it to the background before a redraw.
+    ///
+    /// The content has to be refreshed to be shown, with `refresh` right
+    /// away.
+    pub fn fill(
+        &mut self,
+        lua: rlua::Context<'lua>,
+        color: Color,
+        area: Option<Area>,
+        refresh: bool
+    ) -> rlua::Result<()> {
+        {
+            let mut drawable = self.state_mut()?;
+            let bounds = Area {
+                origin: Origin::default(),
+                size: drawable.content_size()
+            };
+            let surface = drawable.painted_surface().ok_or_else(|| {
+                rlua::Error::RuntimeError("drawable:fill(): the drawable has no surface yet".into())
+            })?;
+            // Cairo premultiplies the color, and clips to the surface.
+            let cr = Context::new(surface);
+            cr.set_operator(Operator::Source);
+            cr.set_source_rgba(color.red, color.green, color.blue, color.alpha);
+            let Area { origin, size } = area.unwrap_or(bounds);
+            cr.rectangle(
+                f64::from(origin.x),
+                f64::from(origin.y),
+                f64::from(size.width),
+                f64::from(size.height)
+            );
+            cr.fill();
+            if let Some(damage) = area.unwrap_or(bounds).intersection(bounds) {
+                let root = drawable.damage.root();
+                drawable.damage.add_damage(root, damage);
+            }
+            drawable.refreshed = false;
+        }
+        if refresh {
+            self.refresh(lua)?;
+        }
+        Ok(())
+    }
+
+    /// Sets whether only the pixels with an alpha above the threshold take
+    /// input, rather than the whole surface.
+    pub fn set_input_from_alpha(&mut self, from_alpha: bool) -> rlua::Result<()> {
+        {
+            let mut drawable = self.state_mut()?;
+            if from_alpha == drawable.alpha_region.is_some() {
+                return Ok(());
+            }
+            if from_alpha {
+                drawable.alpha_region = Some(AlphaRegion::new(drawable.alpha_threshold));
+                drawable.rescan_input_region();
+            } else {
+                drawable.alpha_region = None;
+                drawable.input_region = None;
+                drawable.input_region_changed = true;
+            }
+        }
+        self.refresh_drawin()
+    }
+
+    pub fn input_from_alpha(&self) -> rlua::Result<bool> {
+        Ok(self.state()?.alpha_region.is_some())
+    }
+
+    pub fn set_alpha_threshold(&mut self, threshold: u8) -> rlua::Result<()> {
+        {
+            let mut drawable = self.state_mut()?;
+            drawable.alpha_threshold = threshold;
+            match drawable.alpha_region.as_mut() {
+                Some(region) if region.threshold() != threshold => region.set_threshold(threshold),
+                _ => return Ok(())
+            }
+            drawable.rescan_input_region();
+        }
+        self.refresh_drawin()
+    }
+
+    pub fn alpha_threshold(&self) -> rlua::Result<u8> {
+        Ok(self.state()?.alpha_threshold)
+    }
+
+    /// Sets how many frames of an animation the input region is scanned
+    /// once in at most.
+    pub fn set_input_scan_frames(&mut self, frames: u32) -> rlua::Result<()> {
+        self.state_mut()?.scan_schedule.set_frames(frames);
+        Ok(())
+    }
+
+    pub fn input_scan_frames(&self) -> rlua::Result<u32> {
+        Ok(self.state()?.scan_schedule.frames())
+    }
+
+    /// What scanning the input region cost so far, if it's set from the
+    /// alpha of the content.
+    pub fn input_scan_stats(&self) -> rlua::Result<Option<ScanStats>> {
+        Ok(self.state()?.alpha_region.as_ref().map(AlphaRegion::stats))
+    }
+
+    /// Sets the parts of the surface that take input, in its coordinates,
+    /// or `None` for all of it. Input anywhere else goes through to what is
+    /// below, and with the input region from the alpha only the parts in
+    /// both take input.
+    pub fn set_input_shape(&mut self, shape: Option<Vec<Area>>) -> rlua::Result<()> {
+        {
+            let mut drawable = self.state_mut()?;
+            if drawable.input_shape == shape {
+                return Ok(());
+            }
+            drawable.input_shape = shape;
+            drawable.input_region_changed = true;
+        }
+        self.refresh_drawin()
+    }
+
+    pub fn input_shape(&self) -> rlua::Result<Option<Vec<Area>>> {
+        Ok(self.state()?.input_shape.clone())
+    }
+
+    /// Sets the shape of the surface, in its coordinates, or `None` for a
+    /// rectangle. Only the parts inside it take input, and the compositor
+    /// is told they're opaque.
+    pub fn set_bounding_shape(&mut self, shape: Option<Vec<Area>>) -> rlua::Result<()> {
+        {
+            let mut drawable = self.state_mut()?;
+            if drawable.bounding_shape == shape {
+                return Ok(());
+            }
+            drawable.bounding_shape = shape;
+            drawable.input_region_changed = true;
+        }
+        self.refresh_drawin()
+    }
+
+    pub fn bounding_shape(&self) -> rlua::Result<Option<Vec<Area>>> {
+        Ok(self.state()?.bounding_shape.clone())
+    }
+
+    /// The parts of the buffer that are opaque, which is the inside of the
+    /// shape unless the content is shown translucent.
+    pub fn opaque_region(&self) -> rlua::Result<Vec<Area>> {
+        let drawable = self.state()?;
+        let shape = match (drawable.bounding_shape.as_ref(), drawable.opacity) {
+            (Some(shape), None) => shape,
+            _ => return Ok(Vec::new())
+        };
+        let by = drawable.buffer_origin();
+        Ok(shape.iter().map(|rect| rect.translate(by)).collect())
+    }
+
+    /// The parts of the buffer that take input, or `None` if all of it does.
+    pub fn input_region(&self) -> rlua::Result<Option<Vec<Area>>> {
+        Ok(self.state()?.buffer_input_region())
+    }
+
+    /// The input region if it changed since this was last called.
+    pub fn take_input_region(&mut self) -> rlua::Result<Option<Option<Vec<Area>>>> {
         let mut drawable = self.state_mut()?;
-        let drawable = &mut *drawable;
-        if let Some(data) = drawable.surface.as_mut().map(get_data) {
-            drawable
-                .temp_file
-                .write(&*data)
-                .expect("Could not write data to buffer");
-            drawable.temp_file.flush().expect("Could not flush buffer");
-            drawable.refreshed = true;
+        if !std::mem::replace(&mut drawable.input_region_changed, false) {
+            return Ok(None);
+        }
+        Ok(Some(drawable.buffer_input_region()))
+    }
+
+    /// Moves the content of the drawable within its buffer.
+    ///
+    /// The pixel at (`dx`, `dy`) of the surface is shown in the top left
+    /// corner of the buffer. The content is re-copied from the surface, so
+    /// Lua does not need to repaint it.
+    pub fn set_content_offset(&mut self, offset: Origin) -> rlua::Result<()> {
+        self.state_mut()?.content_offset = offset;
+        self.update_shown()
+    }
+
+    /// Copies the content into the buffer again and shows it, if it was
+    /// shown before.
+    fn update_shown(&mut self) -> rlua::Result<()> {
+        {
+            let mut drawable = self.state_mut()?;
+            if !drawable.refreshed {
+                return Ok(());
+            }
+            drawable.update_buffer()?;
+            drawable.rescan_input_region();
         }
+        self.refresh_drawin()
+    }
+
+    /// Makes the content `width` wide, of which the part at the scroll
+    /// offset is shown, or as wide as the drawable again with `None`.
+    ///
+    /// The surface is replaced, and Lua is asked to paint the part in view
+    /// once the main loop runs.
+    pub fn set_virtual_width(&mut self, lua: rlua::Context<'lua>, width: Option<u32>) -> rlua::Result<()> {
+        if let Some(width) = width {
+            if width > MAX_STRIP_WIDTH {
+                return Err(rlua::Error::RuntimeError(format!(
+                    "drawable: a virtual width of {} is wider than the limit of {}",
+                    width, MAX_STRIP_WIDTH
+                )));
+            }
+        }
+        {
+            let mut drawable = self.state_mut()?;
+            drawable.strip = width.map(Strip::new);
+            drawable.content_offset.x = 0;
+        }
+        self.allocate_surface(lua)
+    }
+
+    pub fn virtual_width(&self) -> rlua::Result<Option<u32>> {
+        Ok(self.state()?.strip.as_ref().map(Strip::width))
+    }
+
+    /// Scrolls the strip to show the content from `x`, first asking Lua to
+    /// paint the ranges that come into view and aren't valid.
+    pub fn set_scroll_offset(&mut self, lua: rlua::Context<'lua>, x: i64) -> rlua::Result<()> {
+        let ranges = {
+            let mut drawable = self.state_mut()?;
+            let view = drawable.view_width();
+            let drawable = &mut *drawable;
+            let strip = drawable.strip.as_mut().ok_or_else(no_strip)?;
+            let x = strip.clamp_scroll(x, view);
+            drawable.content_offset.x = x as i32;
+            match drawable.surface {
+                Some(_) => strip.reveal(x, view),
+                None => Vec::new()
+            }
+        };
+        self.paint_ranges(lua, ranges)?;
+        self.update_shown()
+    }
+
+    /// Makes `width` of the strip from `x` be painted again, which Lua is
+    /// asked for once the main loop runs if it's in view.
+    pub fn invalidate_range(&mut self, lua: rlua::Context<'lua>, x: i64, width: u32) -> rlua::Result<()> {
+        self.state_mut()?
+            .strip
+            .as_mut()
+            .ok_or_else(no_strip)?
+            .invalidate(x, width);
+        self.queue_fill(lua)
+    }
+
+    /// The ranges of the strip that are painted, as `(x, width)`.
+    pub fn valid_ranges(&self) -> rlua::Result<Vec<(u32, u32)>> {
+        Ok(self.state()?.strip.as_ref().ok_or_else(no_strip)?.valid())
+    }
+
+    /// Paints the invalid ranges in view from the main loop, once for
+    /// however many times it's asked before.
+    fn queue_fill(&mut self, lua: rlua::Context<'lua>) -> rlua::Result<()> {
+        if std::mem::replace(&mut self.state_mut()?.fill_queued, true) {
+            return Ok(());
+        }
+        let key = lua.create_registry_value(self.clone())?;
+        scheduler::defer(Priority::Redraw, move |lua| {
+            let mut drawable = lua.registry_value::<Drawable>(&key)?;
+            lua.remove_registry_value(key)?;
+            drawable.state_mut()?.fill_queued = false;
+            drawable.fill_view(lua)
+        });
         Ok(())
     }
-}
 
-impl UserData for DrawableState {
-    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
-        object::default_add_methods(methods);
+    /// Asks Lua to paint the invalid ranges in view.
+    fn fill_view(&mut self, lua: rlua::Context<'lua>) -> rlua::Result<()> {
+        let ranges = {
+            let mut drawable = self.state_mut()?;
+            let view = drawable.view_width();
+            let x = drawable.content_offset.x.max(0) as u32;
+            let painted = drawable.surface.is_some();
+            match drawable.strip.as_mut() {
+                Some(strip) if painted => strip.reveal(x, view),
+                _ => return Ok(())
+            }
+        };
+        if ranges.is_empty() {
+            return Ok(());
+        }
+        self.paint_ranges(lua, ranges)?;
+        self.update_shown()
     }
-}
 
-pub fn init(lua: rlua::Context) -> rlua::Result<Class<DrawableState>> {
-    Class::<DrawableState>::builder(lua, "drawable", None)?
-        .method("geometry".into(), lua.create_function(geometry)?)?
-        .property(Property::new(
-            "surface".into(),
-            None,
-            Some(lua.create_function(get_surface)?),
-            None
-        ))?
-        .save_class("drawable")?
-        .build()
-}
+    /// Emits "request::paint_range" with the x and the width of every range,
+    /// which Lua paints into the surface.
+    fn paint_ranges(&mut self, lua: rlua::Context<'lua>, ranges: Vec<(u32, u32)>) -> rlua::Result<()> {
+        let height = self.state()?.geo.size.height;
+        for (x, width) in ranges {
+            let args = vec![self.clone().to_lua(lua)?, x.to_lua(lua)?, width.to_lua(lua)?];
+            signal::emit_signals(
+                lua,
+                self.signals()?,
+                "request::paint_range",
+                MultiValue::from_vec(args)
+            )?;
+            self.add_damage(Area {
+                origin: Origin { x: x as i32, y: 0 },
+                size: Size { width, height }
+            })?;
+        }
+        Ok(())
+    }
 
-fn get_surface<'lua>(_: rlua::Context<'lua>, drawable: Drawable<'lua>) -> rlua::Result<Value<'lua>> {
-    drawable.get_surface()
-}
+    /// Saves the content of the drawable under `key`, so it can be restored
+    /// after a restart.
+    pub fn save_snapshot(&mut self, key: &str) -> rlua::Result<Result<(), String>> {
+        check_snapshot_key(key)?;
+        let mut drawable = self.state_mut()?;
+        let geometry = drawable.geo;
+        let scale = drawable.variants.own_scale();
+        let surface = match drawable.surface.as_mut() {
+            Some(surface) => surface,
+            None => return Ok(Err("the drawable has no content".into()))
+        };
+        flush(surface);
+        let image = Image {
+            stride: surface.get_stride() as usize,
+            size: Size {
+                width: surface.get_width() as u32,
+                height: surface.get_height() as u32
+            },
+            data: get_data(surface)
+        };
+        let snapshot = Snapshot::new(image, geometry, scale);
+        Ok(SnapshotCache::session()
+            .save(key, &snapshot)
+            .map_err(|err| err.to_string()))
+    }
 
-fn geometry<'lua>(lua: rlua::Context<'lua>, drawable: Drawable<'lua>) -> rlua::Result<Table<'lua>> {
-    let geometry = drawable.get_geometry()?;
-    let Origin { x, y } = geometry.origin;
-    let Size { width, height } = geometry.size;
-    let table = lua.create_table()?;
-    table.set("x", x)?;
-    table.set("y", y)?;
-    table.set("width", width)?;
-    table.set("height", height)?;
-    Ok(table)
-}
+    /// Writes the content Lua painted to `path` as a PNG, returning why it
+    /// couldn't be written.
+    pub fn save_to_png(&self, path: &Path) -> rlua::Result<Result<(), String>> {
+        self.state()?.write_png(path)
+    }
 
-fn refresh<'lua>(_: rlua::Context<'lua>, mut drawable: Drawable<'lua>) -> rlua::Result<()> {
-    drawable.refresh()
-}
+    /// Shows the content saved under `key` until Lua paints, returning
+    /// whether it had to be scaled to the size of the drawable.
+    pub fn restore_snapshot(&mut self, key: &str) -> rlua::Result<Result<bool, String>> {
+        check_snapshot_key(key)?;
+        let restored = self.state_mut()?.restore_snapshot(key)?;
+        if restored.is_ok() {
+            self.refresh_drawin()?;
+        }
+        Ok(restored)
+    }
 
-/// Get the data associated with the ImageSurface.
-fn get_data(surface: &mut ImageSurface) -> &[u8] {
-    // NOTE This is safe to do because there's one thread.
-    //
-    // We know Lua is not modifying it because it's not running.
-    use cairo_sys;
-    use std::slice;
-    unsafe {
-        let len = surface.get_stride() as usize * surface.get_height() as usize;
-        let surface = surface.to_glib_none().0;
-        slice::from_raw_parts(cairo_sys::cairo_image_surface_get_data(surface as _), len)
+    /// Restores the snapshot under `key` as soon as there's a surface to
+    /// restore it into, unless Lua painted first.
+    pub fn restore_snapshot_on_allocation(&mut self, key: &str) -> rlua::Result<()> {
+        check_snapshot_key(key)?;
+        let has_surface = {
+            let drawable = self.state()?;
+            drawable.surface.is_some() && !drawable.refreshed
+        };
+        if has_surface {
+            if let Err(err) = self.restore_snapshot(key)? {
+                debug!("Not restoring snapshot \"{}\": {}", key, err);
+            }
+        } else {
+            self.state_mut()?.pending_snapshot = Some(key.into());
+        }
+        Ok(())
+    }
+
+    /// Draws `svg` rendered at the size of `area` into the surface, and
+    /// marks the area as damaged. It's shown once Lua refreshes.
+    pub fn draw_svg(&mut self, svg: &Svg, area: Area) -> rlua::Result<Result<(), SvgError>> {
+        {
+            let drawable = self.state()?;
+            let surface = match drawable.surface.as_ref() {
+                Some(surface) => surface,
+                None => return Ok(Err(SvgError::Render("the drawable has no surface yet".into())))
+            };
+            // Rendered at the scale of the surface, so it isn't blurred.
+            let scale = drawable.variants.own_scale();
+            let (width, height) = (area.size.width as i32 * scale, area.size.height as i32 * scale);
+            let rendering = match svg::render(svg, width, height) {
+                Ok(rendering) => rendering,
+                Err(err) => return Ok(Err(err))
+            };
+            variants::set_device_scale(&rendering, scale);
+            let cr = Context::new(surface);
+            cr.set_source_surface(&rendering, f64::from(area.origin.x), f64::from(area.origin.y));
+            cr.paint();
+        }
+        self.add_damage(area)?;
+        Ok(Ok(()))
+    }
+
+    /// Makes Lua paint the variant for `scale`, allocating its surface,
+    /// until `end_variant`. It's shown once Lua refreshes.
+    pub fn begin_variant(&mut self, scale: i32) -> rlua::Result<()> {
+        use rlua::Error::RuntimeError;
+        let mut drawable = self.state_mut()?;
+        let own_scale = drawable.variants.own_scale();
+        if drawable.strip.is_some() && scale != own_scale {
+            return Err(RuntimeError(
+                "drawable: variants can't be painted with a virtual width".into()
+            ));
+        }
+        drawable.painting = if scale == own_scale { None } else { Some(scale) };
+        let Size { width, height } = drawable.geo.size;
+        if scale == own_scale || width == 0 || height == 0 || drawable.variants.get(scale).is_some() {
+            return Ok(());
+        }
+        let surface = variants::create_surface(drawable.format.cairo(), width as i32, height as i32, scale)
+            .map_err(|err| RuntimeError(format!("Could not allocate {:?}", err)))?;
+        drawable.variants.insert(scale, surface);
+        Ok(())
+    }
+
+    /// Makes Lua paint the drawable's own surface again.
+    pub fn end_variant(&mut self) -> rlua::Result<()> {
+        self.state_mut()?.painting = None;
+        Ok(())
+    }
+
+    /// The scales there is content for.
+    pub fn variant_scales(&self) -> rlua::Result<Vec<i32>> {
+        Ok(self.state()?.variants.scales())
+    }
+
+    /// How many pixels of the buffer make a unit of the surface.
+    pub fn buffer_scale(&self) -> rlua::Result<i32> {
+        let drawable = self.state()?;
+        Ok(drawable
+            .shown_scale
+            .unwrap_or_else(|| drawable.variants.own_scale()))
+    }
+
+    /// Sets `scale`, the scale of the output the drawable is on.
+    ///
+    /// Unless Lua painted variants the surface is allocated again at that
+    /// scale, and "property::surface" asks Lua to paint it. Otherwise the
+    /// variant for `scale` is shown, or the nearest one, and `scale` is
+    /// returned if Lua should be told there's no variant for it, which only
+    /// happens once for each scale.
+    pub fn set_output_scale(&mut self, lua: rlua::Context<'lua>, scale: i32) -> rlua::Result<Option<i32>> {
+        let (missing, reallocate, changed) = {
+            let mut drawable = self.state_mut()?;
+            let follows = drawable.strip.is_none() && drawable.variants.is_empty();
+            let reallocate = follows && drawable.variants.own_scale() != scale && drawable.surface.is_some();
+            if follows {
+                // The surface is allocated at it below, or once it has a
+                // size.
+                drawable.variants.set_own_scale(scale);
+            }
+            let missing = drawable.variants.set_output_scale(scale);
+            let changed =
+                !reallocate && drawable.refreshed && Some(drawable.variants.shown()) != drawable.shown_scale;
+            if changed {
+                drawable.update_buffer()?;
+            }
+            (missing, reallocate, changed)
+        };
+        if reallocate {
+            // The previous frame is shown until Lua paints.
+            self.allocate_surface(lua)?;
+        }
+        if changed {
+            self.refresh_drawin()?;
+        }
+        Ok(missing)
+    }
+
+    /// The memory the drawable keeps, see `memory_pressure`.
+    pub fn memory(&self) -> rlua::Result<Memory> {
+        let drawable = self.state()?;
+        Ok(Memory {
+            buffers: drawable.buffer.as_ref().map(DrawableBuffer::bytes).unwrap_or(0),
+            surfaces: drawable
+                .surface
+                .as_ref()
+                .map(variants::surface_bytes)
+                .unwrap_or(0),
+            variants: drawable.variants.bytes(),
+            caches: drawable.scratch.bytes()
+        })
+    }
+
+    /// Drops the buffer, which is allocated again by the next refresh or
+    /// once the drawin is shown, returning the memory freed.
+    pub fn evict_buffer(&mut self) -> rlua::Result<u64> {
+        let mut drawable = self.state_mut()?;
+        let freed = match drawable.buffer.take() {
+            Some(buffer) => buffer.bytes(),
+            None => return Ok(0)
+        };
+        drawable.written_offset = None;
+        drawable.presentable = false;
+        Ok(freed)
+    }
+
+    /// Releases the surfaces and the buffer of a drawable that's never
+    /// shown again, returning the memory freed. See `DrawableState::destroy`.
+    pub fn destroy(&mut self) -> rlua::Result<u64> {
+        Ok(self.state_mut()?.destroy())
+    }
+
+    /// Gives back the memory kept for the next frame, returning how much.
+    pub fn drop_caches(&mut self) -> rlua::Result<u64> {
+        Ok(self.state_mut()?.scratch.shrink())
+    }
+
+    /// Drops the variants that aren't shown or being painted, returning
+    /// the memory freed.
+    pub fn drop_inactive_variants(&mut self) -> rlua::Result<u64> {
+        let mut drawable = self.state_mut()?;
+        let painting: Vec<i32> = drawable.painting.into_iter().collect();
+        Ok(drawable.variants.drop_inactive(&painting))
+    }
+
+    /// Sets the effects applied to the content, which is copied into the
+    /// buffer again without Lua repainting it.
+    pub fn set_effects(&mut self, effects: Vec<Effect>) -> rlua::Result<()> {
+        {
+            let mut drawable = self.state_mut()?;
+            if drawable.effects.list() == effects.as_slice() {
+                return Ok(());
+            }
+            drawable.effects.set(effects);
+            // The buffer has the content with the old effects.
+            drawable.written_offset = None;
+            drawable.input_region_changed = true;
+            if !drawable.refreshed {
+                return Ok(());
+            }
+            drawable.update_buffer()?;
+            drawable.rescan_input_region();
+        }
+        self.refresh_drawin()
+    }
+
+    pub fn opacity(&self) -> rlua::Result<f64> {
+        Ok(self.state()?.opacity.unwrap_or(1.0))
+    }
+
+    /// Shows the content `opacity` times as opaque, from 0 to 1, without it
+    /// being painted again. Returns whether it changed.
+    pub fn set_opacity(&mut self, opacity: f64) -> rlua::Result<bool> {
+        let opacity = if opacity < 1.0 {
+            Some(opacity.max(0.0))
+        } else {
+            None
+        };
+        {
+            let mut drawable = self.state_mut()?;
+            if drawable.opacity == opacity {
+                return Ok(false);
+            }
+            drawable.opacity = opacity;
+            // The buffer has the content at the old opacity.
+            drawable.written_offset = None;
+            drawable.input_region_changed = true;
+            if !drawable.refreshed {
+                return Ok(true);
+            }
+            drawable.update_buffer()?;
+            drawable.rescan_input_region();
+        }
+        self.refresh_drawin()?;
+        Ok(true)
+    }
+
+    pub fn effects(&self) -> rlua::Result<Vec<Effect>> {
+        Ok(self.state()?.effects.list().to_vec())
+    }
+
+    /// What applying each of the effects cost so far.
+    pub fn effect_stats(&self) -> rlua::Result<Vec<(Effect, EffectStats)>> {
+        Ok(self.state()?.effects.stats())
+    }
+
+    /// How far the effects grow the buffer past the content on every side.
+    pub fn effect_extent(&self) -> rlua::Result<u32> {
+        Ok(self.state()?.effects.extent())
+    }
+
+    /// Whether the drawin that owns this drawable, if any, waits for the
+    /// compositor to be done with its last frame.
+    fn frame_pending(&self) -> rlua::Result<bool> {
+        match self.get_associated_data::<Option<Drawin>>("drawin")? {
+            Some(drawin) => drawin.frame_pending(),
+            None => Ok(false)
+        }
+    }
+
+    /// Tells the drawin that owns this drawable, if any, that there's new
+    /// content to display.
+    fn refresh_drawin(&self) -> rlua::Result<()> {
+        if let Some(mut drawin) = self.get_associated_data::<Option<Drawin>>("drawin")? {
+            drawin.refresh_pixmap()?;
+        }
+        Ok(())
+    }
+}
+
+impl DrawableState {
+    /// Drops the surfaces, the buffer and the memory kept for the next
+    /// frame, returning how much memory that freed.
+    ///
+    /// Dropping the buffer destroys its wl_buffer and pool, so the
+    /// compositor unmaps the memory, and closes its file. The surfaces are
+    /// only freed once Lua dropped the references `get_surface` gave it.
+    /// The generation changes, so what Lua cached isn't current anymore.
+    pub fn destroy(&mut self) -> u64 {
+        let mut freed = self.scratch.shrink() + self.variants.bytes();
+        if let Some(buffer) = self.buffer.take() {
+            freed += buffer.bytes();
+        }
+        // The mapped buffer is the memory of the surface, which is counted
+        // with it.
+        self.mapped = None;
+        self.mapped_shown = false;
+        let kept = self.capacity.clear();
+        if let Some(surface) = self.surface.take() {
+            // A surface in kept memory is counted with it.
+            freed += if kept > 0 {
+                kept
+            } else {
+                variants::surface_bytes(&surface)
+            };
+            self.surface_generation += 1;
+        }
+        self.variants.clear();
+        self.painting = None;
+        self.shown_scale = None;
+        self.written_offset = None;
+        self.refreshed = false;
+        self.presentable = false;
+        freed
+    }
+
+    /// Writes the surface Lua paints into to `path` as a PNG, as it is
+    /// whether it was refreshed or not.
+    ///
+    /// The error is the one from writing the file, without a surface there
+    /// is nothing to write and that's a Lua error.
+    pub fn write_png(&self, path: &Path) -> rlua::Result<Result<(), String>> {
+        let surface = self.surface.as_ref().ok_or_else(|| {
+            rlua::Error::RuntimeError(
+                "drawable:save_to_png(): the drawable has no surface yet, it's allocated once it has a size"
+                    .into()
+            )
+        })?;
+        flush(surface);
+        let written = File::create(path)
+            .map_err(|err| err.to_string())
+            .and_then(|file| {
+                surface
+                    .write_to_png(&mut BufWriter::new(file))
+                    .map_err(|err| err.to_string())
+            });
+        Ok(written.map_err(|err| format!("Could not write {}: {}", path.display(), err)))
+    }
+
+    /// The size of the content Lua paints, which is as wide as the strip if
+    /// there is one.
+    fn content_size(&self) -> Size {
+        match self.strip.as_ref() {
+            Some(strip) => Size {
+                width: strip.width().max(self.geo.size.width),
+                height: self.geo.size.height
+            },
+            None => self.geo.size
+        }
+    }
+
+    /// How much of the width of the content is shown at a time.
+    fn view_width(&self) -> u32 {
+        self.content_surface_size()
+            .map(|size| size.width)
+            .unwrap_or(self.geo.size.width)
+    }
+
+    /// The surface Lua paints into.
+    fn painted_surface(&self) -> Option<&ImageSurface> {
+        match self.painting {
+            None => self.surface.as_ref(),
+            Some(scale) => self.variants.get(scale)
+        }
+    }
+
+    /// The size of the surface granted by the compositor that's left for
+    /// the content once the effects grew it, if it's known.
+    fn content_surface_size(&self) -> Option<Size> {
+        let extent = 2 * self.effects.extent();
+        self.surface_size.map(|Size { width, height }| Size {
+            width: width.saturating_sub(extent),
+            height: height.saturating_sub(extent)
+        })
+    }
+
+    /// The input region in the coordinates of the buffer, which is moved
+    /// past any shadow the effects grew it by.
+    ///
+    /// With a shadow and no input region of its own only the content takes
+    /// input, not the shadow around it.
+    fn buffer_input_region(&self) -> Option<Vec<Area>> {
+        let input_region = self.shaped_input_region();
+        let by = self.buffer_origin();
+        if by == Origin::default() {
+            return input_region;
+        }
+        match input_region.as_ref() {
+            Some(rects) => Some(rects.iter().map(|rect| rect.translate(by)).collect()),
+            None => {
+                let content: Area = self.content_surface_size().unwrap_or(self.geo.size).into();
+                Some(vec![content.translate(by)])
+            }
+        }
+    }
+
+    /// Where the top left corner of the content is in the buffer, past the
+    /// shadow of the effects and moved by the clip.
+    fn buffer_origin(&self) -> Origin {
+        let extent = self.effects.extent() as i32;
+        let clip = self.clip.map(|clip| clip.origin).unwrap_or_default();
+        Origin {
+            x: extent - clip.x,
+            y: extent - clip.y
+        }
+    }
+
+    /// The input region cut down to the shapes Lua set, if it set any.
+    ///
+    /// The shapes aren't clipped to the surface, the compositor does that
+    /// whenever it's resized.
+    fn shaped_input_region(&self) -> Option<Vec<Area>> {
+        let mut region = self.input_region.clone();
+        for shape in self.input_shape.iter().chain(self.bounding_shape.iter()) {
+            region = Some(match region {
+                Some(rects) => shape
+                    .iter()
+                    .flat_map(|rect| rects.iter().filter_map(move |within| rect.intersection(*within)))
+                    .collect(),
+                None => shape.clone()
+            });
+        }
+        region
+    }
+
+    /// The scale of the variant to show, and the size of the buffer it's
+    /// shown in.
+    fn shown_content(&self) -> Option<(i32, Size)> {
+        let scale = self.variants.shown();
+        let surface = if scale == self.variants.own_scale() {
+            self.surface.as_ref()?
+        } else {
+            self.variants.get(scale)?
+        };
+        let content_size = if self.strip.is_some() {
+            // Only the view of a strip is shown.
+            self.geo.size
+        } else {
+            Size {
+                width: surface.get_width() as u32,
+                height: surface.get_height() as u32
+            }
+        };
+        let size = self
+            .content_surface_size()
+            .map(|Size { width, height }| Size {
+                width: width * scale as u32,
+                height: height * scale as u32
+            })
+            .unwrap_or(content_size);
+        Some((scale, size))
+    }
+
+    fn restore_snapshot(&mut self, key: &str) -> rlua::Result<Result<bool, String>> {
+        let size = self.geo.size;
+        let scale = self.variants.own_scale();
+        let surface = match self.surface.as_mut() {
+            Some(surface) => surface,
+            None => return Ok(Err("the drawable has no surface yet".into()))
+        };
+        let snapshot = match SnapshotCache::session().load(key) {
+            Ok(Some(snapshot)) => snapshot,
+            Ok(None) => return Ok(Err("there is no such snapshot".into())),
+            Err(err) => return Ok(Err(err.to_string()))
+        };
+        let (pixels, scaled) = match snapshot.pixels_at(size) {
+            Ok(pixels) => pixels,
+            Err(err) => return Ok(Err(err))
+        };
+        // Snapshots are kept in logical pixels.
+        let (pixels, size) = if scale == 1 {
+            (pixels, size)
+        } else {
+            let to = Size {
+                width: size.width * scale as u32,
+                height: size.height * scale as u32
+            };
+            (snapshot::scale_bilinear(&pixels, size, to), to)
+        };
+        flush(surface);
+        let stride = surface.get_stride() as usize;
+        let row = size.width as usize * 4;
+        let data = get_data_mut(surface);
+        for (y, pixels) in pixels.chunks(row).enumerate() {
+            data[y * stride..y * stride + row].copy_from_slice(pixels);
+        }
+        mark_dirty(surface);
+        self.update_buffer()?;
+        self.rescan_input_region();
+        Ok(Ok(scaled))
+    }
+
+    /// Copies the contents of the surface into the Wayland buffer.
+    ///
+    /// If the surface isn't the size of the buffer the content is fitted
+    /// into it, or with `ContentFit::None` the buffer is left alone and
+    /// can't be shown until the sizes match.
+    fn update_buffer(&mut self) -> rlua::Result<()> {
+        if self.suspended {
+            // Written when it's shown again.
+            self.refreshed = true;
+            return Ok(());
+        }
+        // The content of a throttled refresh is in this write.
+        self.throttled = false;
+        let (written, allocations) = allocations::counted(|| self.write_frame());
+        if let Some((partial, bytes_copied)) = written? {
+            self.frame_stats.record(partial, allocations, bytes_copied);
+        }
+        Ok(())
+    }
+
+    /// Does the work of `update_buffer`, returning whether only the damage
+    /// was copied and how many bytes were, if the buffer was written.
+    fn write_frame(&mut self) -> rlua::Result<Option<(bool, usize)>> {
+        use rlua::Error::RuntimeError;
+        let (scale, size) = match self.shown_content() {
+            Some(shown) => shown,
+            None => return Ok(None)
+        };
+        if self.shown_scale != Some(scale) {
+            // The buffer has the content of another variant.
+            self.shown_scale = Some(scale);
+            self.written_offset = None;
+        }
+        if self.shows_mapped(scale, size) {
+            if self.mapped.as_ref().map_or(false, MappedBuffer::is_released) {
+                return Ok(Some(self.write_mapped()));
+            }
+            backend::fall_back(
+                &mut self.buffer_fallback,
+                "the compositor didn't release the mapped buffer before the next frame".into()
+            );
+            self.mapped = None;
+        }
+        self.mapped_shown = false;
+        // The offset and the damage are in the coordinates of `surface`,
+        // which a variant is `scale` times as large as.
+        let offset = Origin {
+            x: self.content_offset.x * scale,
+            y: self.content_offset.y * scale
+        };
+        let surface = if scale == self.variants.own_scale() {
+            self.surface.as_mut()
+        } else {
+            self.variants.get_mut(scale)
+        };
+        let surface = match surface {
+            Some(surface) => surface,
+            None => return Ok(None)
+        };
+        let content_size = Size {
+            width: surface.get_width() as u32,
+            height: surface.get_height() as u32
+        };
+        let stride = surface.get_stride() as usize;
+        let data = get_data(surface);
+        // A strip is shown through the window at the content offset.
+        let direct = size == content_size || self.strip.is_some();
+        let image = Image {
+            data,
+            stride,
+            size: content_size
+        };
+        let opacity = self.opacity;
+        let fitted = if direct && self.effects.is_empty() && opacity.is_none() {
+            None
+        } else if direct {
+            // The effects need the shown pixels on their own, which are
+            // cropped the way the buffer shows them.
+            fit_content(image, size, ContentFit::Crop, offset, [0; 4])
+        } else {
+            match fit_content(image, size, self.content_fit, offset, self.fill.to_argb32()) {
+                Some(pixels) => Some(pixels),
+                None => {
+                    self.refreshed = true;
+                    self.presentable = false;
+                    return Ok(None);
+                }
+            }
+        };
+        // The effects grow the buffer by their extent on every side.
+        let extent = self.effects.extent() as i32 * scale;
+        let effects = &mut self.effects;
+        let fitted = fitted.map(|data| {
+            let pixels = Pixels { data, size };
+            let mut pixels = if effects.is_empty() {
+                pixels
+            } else {
+                effects.apply(pixels, scale)
+            };
+            if let Some(opacity) = opacity {
+                effects::fade(&mut pixels.data, opacity);
+            }
+            pixels
+        });
+        let full_size = fitted.as_ref().map(|pixels| pixels.size).unwrap_or(size);
+        // Only the part of the buffer that's shown is in it, see `set_clip`.
+        let clip = self
+            .clip
+            .and_then(|clip| clip.scale(f64::from(scale)).intersection(full_size.into()))
+            .unwrap_or_else(|| full_size.into());
+        let buffer_size = clip.size;
+        let offset = Origin {
+            x: offset.x + clip.origin.x,
+            y: offset.y + clip.origin.y
+        };
+        if let Some(failure) = self.buffer.as_ref().and_then(DrawableBuffer::failure) {
+            backend::fall_back(&mut self.buffer_fallback, failure.into());
+            self.buffer = None;
+        }
+        let reallocate = match self.buffer.as_mut() {
+            Some(buffer)
+                if buffer.backend() != self.buffer_backend.copied_into() &&
+                    self.buffer_fallback.is_none() =>
+            {
+                true
+            },
+            Some(buffer) if buffer.format() != self.format.shm() => true,
+            // Shared memory is resized in the same file.
+            Some(buffer) if buffer.size() != buffer_size => match buffer.resize(buffer_size) {
+                Some(grown) => {
+                    memory_pressure::allocated(grown);
+                    self.written_offset = None;
+                    false
+                },
+                None => true
+            },
+            Some(_) => false,
+            None => true
+        };
+        if reallocate {
+            // Without a buffer of the right size there's nothing to show
+            // until the next write manages to allocate one.
+            self.buffer = None;
+            self.written_offset = None;
+            self.presentable = false;
+            let buffer = backend::allocate(
+                buffer_size,
+                self.format,
+                self.buffer_backend,
+                &mut self.buffer_fallback
+            )
+            .map_err(|err| RuntimeError(format!("Could not create buffer for drawable: {}", err)))?;
+            memory_pressure::allocated(buffer.bytes());
+            self.buffer = Some(buffer);
+        }
+        let buffer = self.buffer.as_mut().unwrap();
+        let root = self.damage.root();
+        let scratch = &mut self.scratch;
+        self.damage.take_damage_into(root, &mut scratch.damage);
+        let partial = direct && !scratch.damage.is_empty() && self.written_offset == Some(offset);
+        // The damage is in the coordinates of the surface, which is shifted
+        // by the content offset in the buffer, and grown by what the effects
+        // spread it by.
+        let placement = if partial {
+            Some(Placement {
+                scale,
+                offset,
+                extent
+            })
+        } else {
+            None
+        };
+        let source = match fitted.as_ref() {
+            Some(pixels) => Source {
+                data: &pixels.data,
+                stride: full_size.width as usize * 4,
+                offset: clip.origin
+            },
+            None => Source { data, stride, offset }
+        };
+        let bytes_copied = frame::copy(buffer, buffer_size, source, placement, scratch)
+            .map_err(|err| RuntimeError(format!("Could not write to buffer: {}", err)))?;
+        self.partial_write = partial;
+        let own_scale = f64::from(self.variants.own_scale());
+        if let Some(region) = self.alpha_region.as_mut() {
+            if partial {
+                for &rect in self.scratch.damage.iter() {
+                    region.damage(rect.scale(own_scale));
+                }
+            } else {
+                region.damage_all();
+            }
+        }
+        self.written_offset = if direct { Some(offset) } else { None };
+        self.refreshed = true;
+        self.presentable = true;
+        Ok(Some((partial, bytes_copied)))
+    }
+
+    /// Whether the frame can be shown from the mapped buffer Lua painted
+    /// it in, which is when the buffer shows the surface as it is.
+    fn shows_mapped(&self, scale: i32, size: Size) -> bool {
+        let mapped = match self.mapped.as_ref() {
+            Some(mapped) => mapped,
+            None => return false
+        };
+        self.buffer_backend == Backend::Mapped &&
+            self.buffer_fallback.is_none() &&
+            scale == self.variants.own_scale() &&
+            mapped.size() == size &&
+            self.content_offset == Origin::default() &&
+            self.clip.is_none() &&
+            self.effects.is_empty() &&
+            self.opacity.is_none()
+    }
+
+    /// Shows the frame from the mapped buffer, which nothing is copied
+    /// into. Only the damage changed if the last frame was shown from it
+    /// too.
+    fn write_mapped(&mut self) -> (bool, usize) {
+        if let Some(surface) = self.surface.as_ref() {
+            flush(surface);
+        }
+        let root = self.damage.root();
+        let scratch = &mut self.scratch;
+        self.damage.take_damage_into(root, &mut scratch.damage);
+        let partial = self.mapped_shown && !scratch.damage.is_empty();
+        let own_scale = f64::from(self.variants.own_scale());
+        scratch.rects.clear();
+        if partial {
+            // The buffer is the surface, at its scale.
+            scratch
+                .rects
+                .extend(scratch.damage.iter().map(|rect| rect.scale(own_scale)));
+        }
+        self.partial_write = partial;
+        if let Some(region) = self.alpha_region.as_mut() {
+            if partial {
+                for &rect in self.scratch.damage.iter() {
+                    region.damage(rect.scale(own_scale));
+                }
+            } else {
+                region.damage_all();
+            }
+        }
+        // The buffer content is copied into doesn't have this frame.
+        self.written_offset = None;
+        self.mapped_shown = true;
+        self.refreshed = true;
+        self.presentable = true;
+        (partial, 0)
+    }
+
+    /// Scans the content for the parts of the buffer that take input, if
+    /// they're found from its alpha.
+    fn scan_input_region(&mut self) {
+        if self.format.is_opaque() {
+            // The alpha of the pixels isn't defined, all of them are shown.
+            if self.input_region.is_some() {
+                self.input_region = None;
+                self.input_region_changed = true;
+            }
+            return;
+        }
+        // The surface is scanned in its own pixels, the region is in
+        // logical ones.
+        let scale = self.variants.own_scale();
+        let surface_size = self.content_surface_size().map(|Size { width, height }| Size {
+            width: width * scale as u32,
+            height: height * scale as u32
+        });
+        let offset = Origin {
+            x: self.content_offset.x * scale,
+            y: self.content_offset.y * scale
+        };
+        let region = match self.alpha_region.as_mut() {
+            Some(region) => region,
+            None => return
+        };
+        let surface = match self.surface.as_mut() {
+            Some(surface) => surface,
+            None => return
+        };
+        let content_size = Size {
+            width: surface.get_width() as u32,
+            height: surface.get_height() as u32
+        };
+        let size = surface_size.unwrap_or(content_size);
+        let image = Image {
+            stride: surface.get_stride() as usize,
+            data: get_data(surface),
+            size: content_size
+        };
+        let rects = if size == content_size {
+            input_region::to_buffer(region.scan(&image), offset, size)
+        } else {
+            // The fitted pixels are in the coordinates of the buffer.
+            let fill = self.fill.to_argb32();
+            let pixels = match fit_content(image, size, self.content_fit, offset, fill) {
+                Some(pixels) => pixels,
+                None => return
+            };
+            let image = Image {
+                data: &pixels,
+                stride: size.width as usize * 4,
+                size
+            };
+            region.damage_all();
+            region.scan(&image).to_vec()
+        };
+        let rects = if scale == 1 {
+            rects
+        } else {
+            let to_logical = 1.0 / f64::from(scale);
+            rects.iter().map(|rect| rect.scale(to_logical)).collect()
+        };
+        if self.input_region.as_ref() != Some(&rects) {
+            self.input_region = Some(rects);
+            self.input_region_changed = true;
+        }
+    }
+
+    /// Scans the input region after something other than an animation
+    /// frame changed the buffer.
+    fn rescan_input_region(&mut self) {
+        if self.alpha_region.is_some() {
+            self.scan_input_region();
+            self.scan_schedule.scanned();
+        }
+    }
+}
+
+impl UserData for DrawableState {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        object::default_add_methods(methods);
+    }
+}
+
+pub fn init(lua: rlua::Context) -> rlua::Result<Class<DrawableState>> {
+    lua.set_named_registry_value(SETTLING_HANDLE, lua.create_table()?)?;
+    ClassDef::new(lua, "drawable")?
+        .class_method("geometry", geometry)?
+        .read_only("surface", get_surface)?
+        .read_only("surface_generation", get_surface_generation)?
+        .object_method("geometry", geometry)?
+        .object_method("get_surface", get_surface_and_generation)?
+        .object_method("surface_if_current", surface_if_current)?
+        .object_method("refresh", refresh)?
+        .object_method("fill", fill)?
+        .object_method("set_content_offset", set_content_offset)?
+        .object_method("add_damage", add_damage)?
+        .object_method("save_snapshot", save_snapshot)?
+        .object_method("restore_snapshot", restore_snapshot)?
+        .object_method("save_to_png", save_to_png)?
+        .object_method("draw_svg", draw_svg)?
+        .object_method("begin_variant", begin_variant)?
+        .object_method("end_variant", end_variant)?
+        .object_method("variants", variants)?
+        .object_method("frame_stats", frame_stats)?
+        .property("buffer_backend", get_buffer_backend, set_buffer_backend)?
+        .property("format", get_format, set_format)?
+        .read_only("virtual_width", get_virtual_width)?
+        .read_only("scroll_offset", get_scroll_offset)?
+        .object_method("set_virtual_width", set_virtual_width)?
+        .object_method("set_scroll_offset", set_scroll_offset)?
+        .object_method("invalidate_range", invalidate_range)?
+        .object_method("valid_ranges", valid_ranges)?
+        .save()
+}
+
+fn get_surface<'lua>(_: rlua::Context<'lua>, drawable: Drawable<'lua>) -> rlua::Result<Value<'lua>> {
+    drawable.get_surface()
+}
+
+fn get_surface_generation<'lua>(_: rlua::Context<'lua>, drawable: Drawable<'lua>) -> rlua::Result<u64> {
+    drawable.surface_generation()
+}
+
+/// `drawable:get_surface()`, which returns the surface and its generation.
+fn get_surface_and_generation<'lua>(
+    _: rlua::Context<'lua>,
+    drawable: Drawable<'lua>
+) -> rlua::Result<(Value<'lua>, u64)> {
+    Ok((drawable.get_surface()?, drawable.surface_generation()?))
+}
+
+/// `drawable:surface_if_current(generation)`, which returns the surface
+/// if it's still of that generation and nil if it was replaced.
+fn surface_if_current<'lua>(
+    _: rlua::Context<'lua>,
+    (drawable, generation): (Drawable<'lua>, u64)
+) -> rlua::Result<Value<'lua>> {
+    if drawable.surface_generation()? != generation {
+        return Ok(Value::Nil);
+    }
+    drawable.get_surface()
+}
+
+/// `drawable:begin_variant("scale:2")`, which returns the surface of the
+/// variant and its generation like `get_surface`.
+fn begin_variant<'lua>(
+    _: rlua::Context<'lua>,
+    (mut drawable, key): (Drawable<'lua>, String)
+) -> rlua::Result<(Value<'lua>, u64)> {
+    let scale = Variants::parse_key(&key).ok_or_else(|| {
+        rlua::Error::RuntimeError(format!(
+            "drawable: invalid variant \"{}\", expected \"scale:\" and a scale from 1 to {}",
+            key, MAX_SCALE
+        ))
+    })?;
+    drawable.begin_variant(scale)?;
+    Ok((drawable.get_surface()?, drawable.surface_generation()?))
+}
+
+fn end_variant<'lua>(_: rlua::Context<'lua>, mut drawable: Drawable<'lua>) -> rlua::Result<()> {
+    drawable.end_variant()
+}
+
+/// `drawable:variants()`, the keys of the variants there is content for.
+fn variants<'lua>(_: rlua::Context<'lua>, drawable: Drawable<'lua>) -> rlua::Result<Vec<String>> {
+    Ok(drawable
+        .variant_scales()?
+        .into_iter()
+        .map(|scale| format!("scale:{}", scale))
+        .collect())
+}
+
+/// `drawable:frame_stats()`, the work done by refreshes so far and by the
+/// last one.
+fn frame_stats<'lua>(lua: rlua::Context<'lua>, drawable: Drawable<'lua>) -> rlua::Result<Table<'lua>> {
+    let stats = drawable.frame_stats()?;
+    let table = lua.create_table()?;
+    table.set("frames", stats.frames)?;
+    table.set("partial_frames", stats.partial_frames)?;
+    table.set("allocations", stats.allocations)?;
+    table.set("bytes_copied", stats.bytes_copied)?;
+    table.set("last_allocations", stats.last_allocations)?;
+    table.set("last_bytes_copied", stats.last_bytes_copied)?;
+    table.set("throttled_frames", stats.throttled_frames)?;
+    table.set("coalesced_frames", stats.coalesced_frames)?;
+    let (backend, fallback) = drawable.buffer_backend()?;
+    table.set("buffer_backend", backend.map(Backend::name))?;
+    table.set("buffer_fallback", fallback)?;
+    Ok(table)
+}
+
+fn get_buffer_backend<'lua>(_: rlua::Context<'lua>, drawable: Drawable<'lua>) -> rlua::Result<&'static str> {
+    Ok(drawable.state()?.buffer_backend.name())
+}
+
+/// `drawable.buffer_backend = "dmabuf"`, which falls back to `"shm"` on its
+/// own where a dmabuf can't be had.
+fn set_buffer_backend<'lua>(
+    _: rlua::Context<'lua>,
+    (mut drawable, backend): (Drawable<'lua>, String)
+) -> rlua::Result<()> {
+    let backend = Backend::from_name(&backend).ok_or_else(|| {
+        rlua::Error::RuntimeError(format!(
+            "drawable.buffer_backend: expected one of \"{}\", got \"{}\"",
+            Backend::NAMES.join("\", \""),
+            backend
+        ))
+    })?;
+    drawable.set_buffer_backend(backend)
+}
+
+fn get_format<'lua>(_: rlua::Context<'lua>, drawable: Drawable<'lua>) -> rlua::Result<&'static str> {
+    Ok(drawable.format()?.name())
+}
+
+/// `drawable.format = "rgb24"`, for content without alpha.
+fn set_format<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawable, format): (Drawable<'lua>, String)
+) -> rlua::Result<()> {
+    let format = PixelFormat::from_name(&format).ok_or_else(|| {
+        rlua::Error::RuntimeError(format!(
+            "drawable.format: expected one of \"{}\", got \"{}\"",
+            PixelFormat::NAMES.join("\", \""),
+            format
+        ))
+    })?;
+    drawable.set_format(lua, format)
+}
+
+fn get_virtual_width<'lua>(_: rlua::Context<'lua>, drawable: Drawable<'lua>) -> rlua::Result<Option<u32>> {
+    drawable.virtual_width()
+}
+
+fn get_scroll_offset<'lua>(_: rlua::Context<'lua>, drawable: Drawable<'lua>) -> rlua::Result<i32> {
+    Ok(drawable.state()?.content_offset.x)
+}
+
+/// `drawable:set_virtual_width(width)`, where nil or 0 makes the content as
+/// wide as the drawable again.
+fn set_virtual_width<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawable, width): (Drawable<'lua>, Option<u32>)
+) -> rlua::Result<()> {
+    drawable.set_virtual_width(lua, width.filter(|&width| width > 0))
+}
+
+fn set_scroll_offset<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawable, x): (Drawable<'lua>, i64)
+) -> rlua::Result<()> {
+    drawable.set_scroll_offset(lua, x)
+}
+
+fn invalidate_range<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawable, x, width): (Drawable<'lua>, i64, u32)
+) -> rlua::Result<()> {
+    drawable.invalidate_range(lua, x, width)
+}
+
+/// `drawable:valid_ranges()`, the ranges of the strip that are painted as
+/// tables like `{ x = 0, width = 100 }`.
+fn valid_ranges<'lua>(lua: rlua::Context<'lua>, drawable: Drawable<'lua>) -> rlua::Result<Vec<Table<'lua>>> {
+    drawable
+        .valid_ranges()?
+        .into_iter()
+        .map(|(x, width)| {
+            let table = lua.create_table()?;
+            table.set("x", x)?;
+            table.set("width", width)?;
+            Ok(table)
+        })
+        .collect()
+}
+
+fn no_strip() -> rlua::Error {
+    rlua::Error::RuntimeError("drawable: there's no virtual width, see set_virtual_width".into())
+}
+
+/// `drawable:geometry([geometry])`, which with a table like
+/// `{ x = 0, width = 100 }` first sets what's in it, like wibox does when
+/// its widgets changed size before the drawin did.
+fn geometry<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawable, geometry): (Drawable<'lua>, Option<Table<'lua>>)
+) -> rlua::Result<Table<'lua>> {
+    if let Some(geometry) = geometry {
+        let geometry = geometry_from_table("drawable:geometry()", geometry, drawable.get_geometry()?)?;
+        drawable.set_geometry(lua, geometry)?;
+    }
+    let geometry = drawable.get_geometry()?;
+    let Origin { x, y } = geometry.origin;
+    let Size { width, height } = geometry.size;
+    let table = lua.create_table()?;
+    table.set("x", x)?;
+    table.set("y", y)?;
+    table.set("width", width)?;
+    table.set("height", height)?;
+    Ok(table)
+}
+
+/// The geometry in a table like `{ x = 0, width = 100 }`, with what isn't
+/// in it taken from `current`, for the errors of `method`. An area is at
+/// least a pixel large.
+fn geometry_from_table(method: &str, table: Table, current: Area) -> rlua::Result<Area> {
+    use rlua::Error::RuntimeError;
+    let field = |name: &str| -> rlua::Result<Option<i64>> {
+        // Integral floats come from arithmetic like `width / 2`, and are
+        // clamped before they're cast.
+        match table.get::<_, Value>(name)? {
+            Value::Nil => Ok(None),
+            Value::Integer(value) => Ok(Some(value)),
+            Value::Number(value) if value.fract() == 0.0 => Ok(Some(
+                value.max(i64::min_value() as f64).min(i64::max_value() as f64) as i64
+            )),
+            _ => Err(RuntimeError(format!("{}: {} has to be an integer", method, name)))
+        }
+    };
+    let out_of_range =
+        |name: &str, err: area::OutOfRange| RuntimeError(format!("{}: {}: {}", method, name, err));
+    let coordinate = |name: &str, current: i32| -> rlua::Result<i32> {
+        field(name)?.map_or(Ok(current), |value| {
+            area::checked_coordinate(value).map_err(|err| out_of_range(name, err))
+        })
+    };
+    let length = |name: &str, current: u32| -> rlua::Result<u32> {
+        match field(name)? {
+            Some(value) if value < 1 => Err(RuntimeError(format!(
+                "{}: {} has to be at least 1, got {}",
+                method, name, value
+            ))),
+            Some(value) => area::checked_length(value).map_err(|err| out_of_range(name, err)),
+            None => Ok(current)
+        }
+    };
+    Ok(Area {
+        origin: Origin {
+            x: coordinate("x", current.origin.x)?,
+            y: coordinate("y", current.origin.y)?
+        },
+        size: Size {
+            width: length("width", current.size.width)?,
+            height: length("height", current.size.height)?
+        }
+    })
+}
+
+/// `drawable:refresh([x, y, width, height])`, which with a rectangle only
+/// copies and damages it, along with the damage added since the last
+/// refresh.
+fn refresh<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawable, x, y, width, height): (Drawable<'lua>, Option<i32>, Option<i32>, Option<u32>, Option<u32>)
+) -> rlua::Result<()> {
+    use rlua::Error::RuntimeError;
+    match (x, y, width, height) {
+        (Some(x), Some(y), Some(width), Some(height)) => drawable.add_damage(Area {
+            origin: Origin { x, y },
+            size: Size { width, height }
+        })?,
+        (None, None, None, None) => {},
+        _ => {
+            return Err(RuntimeError(
+                "drawable:refresh() takes either no rectangle or x, y, width and height".into()
+            ))
+        },
+    }
+    drawable.refresh(lua)
+}
+
+/// `drawable:fill(r, g, b, [a], [area], [refresh])`, which paints the
+/// area, a table like `{ x = 0, width = 100 }` that defaults to all of the
+/// surface, with the color and refreshes if `refresh` is true.
+fn fill<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawable, red, green, blue, alpha, area, refresh): (
+        Drawable<'lua>,
+        f64,
+        f64,
+        f64,
+        Option<f64>,
+        Option<Table<'lua>>,
+        Option<bool>
+    )
+) -> rlua::Result<()> {
+    let color = Color {
+        red,
+        green,
+        blue,
+        alpha: alpha.unwrap_or(1.0)
+    };
+    for &(name, value) in &[
+        ("r", color.red),
+        ("g", color.green),
+        ("b", color.blue),
+        ("a", color.alpha)
+    ] {
+        if !(value >= 0.0 && value <= 1.0) {
+            return Err(rlua::Error::RuntimeError(format!(
+                "drawable:fill(): {} has to be from 0 to 1, got {}",
+                name, value
+            )));
+        }
+    }
+    let area = match area {
+        Some(area) => {
+            let bounds = Area {
+                origin: Origin::default(),
+                size: drawable.state()?.content_size()
+            };
+            Some(geometry_from_table("drawable:fill()", area, bounds)?)
+        },
+        None => None
+    };
+    drawable.fill(lua, color, area, refresh.unwrap_or(false))
+}
+
+/// Checks whether the content of the drawable settled on `frame` once it
+/// had time to, keeping the drawable alive until then.
+fn settle_later<'lua>(lua: rlua::Context<'lua>, drawable: Drawable<'lua>, frame: u64) -> rlua::Result<()> {
+    let id = NEXT_SETTLE_ID.fetch_add(1, Ordering::Relaxed);
+    let settling = lua.named_registry_value::<str, Table>(SETTLING_HANDLE)?;
+    settling.set(id, drawable)?;
+    glib::timeout_add(SETTLE_MILLIS, move || {
+        scheduler::defer(Priority::Redraw, move |lua| settle(lua, id, frame));
+        Continue(false)
+    });
+    Ok(())
+}
+
+/// Scans the input region if the content settled on `frame`, or waits for
+/// it to settle on a later one.
+fn settle(lua: rlua::Context, id: usize, frame: u64) -> rlua::Result<()> {
+    let settling = lua.named_registry_value::<str, Table>(SETTLING_HANDLE)?;
+    let mut drawable = match settling.get::<_, Option<Drawable>>(id)? {
+        Some(drawable) => drawable,
+        None => return Ok(())
+    };
+    settling.set(id, Value::Nil)?;
+    let (scanned, settle) = {
+        let mut state = drawable.state_mut()?;
+        let scanned = state.scan_schedule.settle(frame);
+        if scanned {
+            state.scan_input_region();
+        }
+        (scanned, state.scan_schedule.arm())
+    };
+    if let Some(frame) = settle {
+        settle_later(lua, drawable.clone(), frame)?;
+    }
+    if scanned {
+        drawable.refresh_drawin()?;
+    }
+    Ok(())
+}
+
+fn set_content_offset<'lua>(
+    _: rlua::Context<'lua>,
+    (mut drawable, dx, dy): (Drawable<'lua>, i32, i32)
+) -> rlua::Result<()> {
+    drawable.set_content_offset(Origin { x: dx, y: dy })
+}
+
+fn add_damage<'lua>(
+    _: rlua::Context<'lua>,
+    (mut drawable, x, y, width, height): (Drawable<'lua>, i32, i32, u32, u32)
+) -> rlua::Result<()> {
+    drawable.add_damage(Area {
+        origin: Origin { x, y },
+        size: Size { width, height }
+    })
+}
+
+/// `drawable:save_snapshot(key)`, which returns true or nil and the reason
+/// the content couldn't be saved.
+fn save_snapshot<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawable, key): (Drawable<'lua>, String)
+) -> rlua::Result<(Value<'lua>, Value<'lua>)> {
+    match drawable.save_snapshot(&key)? {
+        Ok(()) => Ok((Value::Boolean(true), Value::Nil)),
+        Err(err) => Ok((Value::Nil, err.to_lua(lua)?))
+    }
+}
+
+/// `drawable:save_to_png(path)`, which returns true or nil and the reason
+/// the file couldn't be written.
+fn save_to_png<'lua>(
+    lua: rlua::Context<'lua>,
+    (drawable, path): (Drawable<'lua>, String)
+) -> rlua::Result<(Value<'lua>, Value<'lua>)> {
+    match drawable.save_to_png(Path::new(&path))? {
+        Ok(()) => Ok((Value::Boolean(true), Value::Nil)),
+        Err(err) => Ok((Value::Nil, err.to_lua(lua)?))
+    }
+}
+
+/// `drawable:draw_svg(handle, x, y, width, height)`, which returns true or
+/// nil and the error.
+fn draw_svg<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawable, handle, x, y, width, height): (Drawable<'lua>, SvgHandle, i32, i32, i32, i32)
+) -> rlua::Result<(Value<'lua>, Value<'lua>)> {
+    if width < 1 || height < 1 {
+        return Ok((
+            Value::Nil,
+            Value::Table(SvgError::Size { width, height }.to_lua(lua)?)
+        ));
+    }
+    let area = Area {
+        origin: Origin { x, y },
+        size: Size {
+            width: width as u32,
+            height: height as u32
+        }
+    };
+    match drawable.draw_svg(&handle.0, area)? {
+        Ok(()) => Ok((Value::Boolean(true), Value::Nil)),
+        Err(err) => Ok((Value::Nil, Value::Table(err.to_lua(lua)?)))
+    }
+}
+
+/// `drawable:restore_snapshot(key)`, which returns `{ is_stale = true,
+/// scaled = ... }` or nil and the reason nothing was restored.
+fn restore_snapshot<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawable, key): (Drawable<'lua>, String)
+) -> rlua::Result<(Value<'lua>, Value<'lua>)> {
+    match drawable.restore_snapshot(&key)? {
+        Ok(scaled) => Ok((restore_result(lua, scaled)?, Value::Nil)),
+        Err(err) => Ok((Value::Nil, err.to_lua(lua)?))
+    }
+}
+
+fn restore_result(lua: rlua::Context, scaled: bool) -> rlua::Result<Value> {
+    let result = lua.create_table()?;
+    result.set("is_stale", true)?;
+    result.set("scaled", scaled)?;
+    result.set("time", clock::now())?;
+    Ok(Value::Table(result))
+}
+
+fn check_snapshot_key(key: &str) -> rlua::Result<()> {
+    if snapshot::valid_key(key) {
+        Ok(())
+    } else {
+        Err(rlua::Error::RuntimeError(format!(
+            "drawable: invalid snapshot key \"{}\", expected letters, digits, '-', '_' and '.'",
+            key
+        )))
+    }
+}
+
+/// Invalidates every snapshot, e.g. after the theme changed.
+pub fn invalidate_snapshots(_: rlua::Context, _: ()) -> rlua::Result<()> {
+    SnapshotCache::session()
+        .invalidate()
+        .map_err(|err| rlua::Error::RuntimeError(format!("Could not invalidate snapshots: {}", err)))
+}
+
+/// The memory the snapshots of the session take, see `memory_pressure`.
+pub fn snapshot_usage() -> u64 {
+    SnapshotCache::session().usage().unwrap_or_else(|err| {
+        warn!("Could not read the snapshot index: {}", err);
+        0
+    })
+}
+
+/// Removes the least recently used snapshots until they take at most half
+/// of the cache limit, returning the memory freed.
+pub fn shrink_snapshots(_: rlua::Context) -> rlua::Result<u64> {
+    Ok(SnapshotCache::session()
+        .shrink(snapshot::MAX_CACHE_BYTES / 2)
+        .unwrap_or_else(|err| {
+            warn!("Could not remove snapshots: {}", err);
+            0
+        }))
+}
+
+/// The parts of `surface` that aren't fully transparent, like the shapes
+/// awesome takes from Lua.
+pub fn shape_of(surface: &ImageSurface) -> rlua::Result<Vec<Area>> {
+    let size = Size {
+        width: surface.get_width().max(0) as u32,
+        height: surface.get_height().max(0) as u32
+    };
+    // Shapes are usually A1, which is read like any other surface once
+    // it's painted into an ARGB32 one.
+    let mut pixels = ImageSurface::create(Format::ARgb32, size.width as i32, size.height as i32)
+        .map_err(|err| rlua::Error::RuntimeError(format!("Could not allocate {:?}", err)))?;
+    {
+        let cr = Context::new(&pixels);
+        cr.set_source_surface(surface, 0.0, 0.0);
+        cr.paint();
+    }
+    flush(&pixels);
+    let image = Image {
+        stride: pixels.get_stride() as usize,
+        data: get_data(&mut pixels),
+        size
+    };
+    Ok(AlphaRegion::new(0).scan(&image).to_vec())
+}
+
+/// Get the data associated with the ImageSurface.
+fn get_data(surface: &mut ImageSurface) -> &[u8] {
+    // NOTE This is safe to do because there's one thread.
+    //
+    // We know Lua is not modifying it because it's not running.
+    use cairo_sys;
+    use std::slice;
+    unsafe {
+        let len = surface.get_stride() as usize * surface.get_height() as usize;
+        let surface = surface.to_glib_none().0;
+        slice::from_raw_parts(cairo_sys::cairo_image_surface_get_data(surface as _), len)
+    }
+}
+
+/// Get the data associated with the ImageSurface, to write to it.
+fn get_data_mut(surface: &mut ImageSurface) -> &mut [u8] {
+    // NOTE This is safe for the same reasons as `get_data`.
+    use cairo_sys;
+    use std::slice;
+    unsafe {
+        let len = surface.get_stride() as usize * surface.get_height() as usize;
+        let surface = surface.to_glib_none().0;
+        slice::from_raw_parts_mut(cairo_sys::cairo_image_surface_get_data(surface as _), len)
+    }
+}
+
+/// Finishes any drawing cairo has pending on the surface, so its data can
+/// be read.
+fn flush(surface: &ImageSurface) {
+    unsafe { ::cairo_sys::cairo_surface_flush(surface.to_glib_none().0) }
+}
+
+/// Tells cairo the data of the surface was written to directly.
+fn mark_dirty(surface: &ImageSurface) {
+    unsafe { ::cairo_sys::cairo_surface_mark_dirty(surface.to_glib_none().0) }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{self, File};
+
+    use rlua::{self, Lua, Value};
+
+    use cairo::{Context, Format, ImageSurface};
+
+    use super::{
+        backend::{self, Backend},
+        flush, get_data, init, shape_of, Drawable
+    };
+    use crate::area::{Area, Origin, Size};
+    use crate::wayland_obj::{test_server::TestServer, LAYER_SHELL_MAX_VERSION};
+
+    fn resize<'lua>(lua: rlua::Context<'lua>, drawable: &mut Drawable<'lua>, width: u32) -> rlua::Result<()> {
+        drawable.set_geometry(
+            lua,
+            Area {
+                origin: Origin::default(),
+                size: Size { width, height: 20 }
+            }
+        )
+    }
+
+    #[test]
+    fn drawable_surface_generation() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            init(lua)?;
+            let mut drawable = Drawable::new(lua)?;
+            lua.globals().set("d", drawable.clone())?;
+            resize(lua, &mut drawable, 100)?;
+            lua.load(
+                r#"
+-- Like wibox, keep the surface to draw into it every frame.
+cache = { rebuilt = 0 }
+function draw()
+    local surface = cache.generation and d:surface_if_current(cache.generation)
+    if not surface then
+        cache.surface, cache.generation = d:get_surface()
+        cache.rebuilt = cache.rebuilt + 1
+    end
+    return cache.surface
+end
+assert(draw() ~= nil and draw() == cache.surface)
+assert(cache.rebuilt == 1 and cache.generation == d.surface_generation)
+                "#
+            )
+            .exec()?;
+            resize(lua, &mut drawable, 200)?;
+            lua.load(
+                r#"
+-- Drawing into the kept surface would go nowhere now.
+local stale = cache.surface
+assert(d:surface_if_current(cache.generation) == nil)
+assert(draw() ~= stale and draw() == d.surface)
+assert(cache.rebuilt == 2 and cache.generation == d.surface_generation)
+                "#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn drawable_scale_variants() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            init(lua)?;
+            let mut drawable = Drawable::new(lua)?;
+            lua.globals().set("d", drawable.clone())?;
+            resize(lua, &mut drawable, 100)?;
+            lua.load(
+                r#"
+local surface, generation = d:begin_variant("scale:2")
+assert(surface ~= nil and generation == d.surface_generation)
+assert(d.surface == surface)
+d:end_variant()
+assert(d.surface ~= surface)
+assert(not pcall(d.begin_variant, d, "scale:x"))
+assert(table.concat(d:variants(), " ") == "scale:1 scale:2")
+                "#
+            )
+            .exec()?;
+            // Variants are painted in the units of the drawable.
+            {
+                let mut state = drawable.state_mut()?;
+                let variant = state.variants.get_mut(2).unwrap();
+                {
+                    let cr = Context::new(variant);
+                    cr.rectangle(0.0, 0.0, 10.0, 10.0);
+                    cr.fill();
+                }
+                flush(variant);
+                let stride = variant.get_stride() as usize;
+                let data = get_data(variant);
+                assert_eq!(data[19 * stride + 19 * 4 + 3], 255);
+                assert_eq!(data[20 * stride + 20 * 4 + 3], 0);
+            }
+            let shown = |drawable: &Drawable| drawable.state().unwrap().shown_content().unwrap();
+            let size = |width, height| Size { width, height };
+            // Moved from a 1x output to a 2x one and back.
+            assert_eq!(drawable.set_output_scale(lua, 1)?, None);
+            assert_eq!(shown(&drawable), (1, size(100, 20)));
+            assert_eq!(drawable.set_output_scale(lua, 2)?, None);
+            assert_eq!(shown(&drawable), (2, size(200, 40)));
+            assert_eq!(drawable.set_output_scale(lua, 1)?, None);
+            assert_eq!(shown(&drawable), (1, size(100, 20)));
+            // On a 3x output the 2x variant is shown, at the right size,
+            // until Lua paints one.
+            assert_eq!(drawable.set_output_scale(lua, 3)?, Some(3));
+            assert_eq!(drawable.set_output_scale(lua, 3)?, None);
+            assert_eq!(shown(&drawable), (2, size(200, 40)));
+            drawable.set_surface_size(Some(size(120, 20)))?;
+            assert_eq!(shown(&drawable), (2, size(240, 40)));
+            // Resizing drops the variants, and without them the surface is
+            // allocated at the scale of the output.
+            drawable.set_surface_size(None)?;
+            resize(lua, &mut drawable, 50)?;
+            assert_eq!(drawable.variant_scales()?, vec![3]);
+            assert_eq!(drawable.set_output_scale(lua, 3)?, None);
+            assert_eq!(shown(&drawable), (3, size(150, 60)));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawable_follows_output_scale() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            init(lua)?;
+            let mut drawable = Drawable::new(lua)?;
+            lua.globals().set("d", drawable.clone())?;
+            resize(lua, &mut drawable, 100)?;
+            lua.load(
+                r#"
+allocated = 0
+d:connect_signal("property::surface", function() allocated = allocated + 1 end)
+                "#
+            )
+            .exec()?;
+            let pixels = |drawable: &Drawable| {
+                let state = drawable.state().unwrap();
+                let surface = state.surface.as_ref().unwrap();
+                (surface.get_width(), surface.get_height())
+            };
+            // Moved to a 2x output, the surface has twice the pixels and
+            // Lua is asked to paint it.
+            assert_eq!(drawable.set_output_scale(lua, 2)?, None);
+            assert_eq!(pixels(&drawable), (200, 40));
+            assert_eq!(drawable.variant_scales()?, vec![2]);
+            lua.load("assert(allocated == 1)").exec()?;
+            // It's painted in logical pixels.
+            {
+                let mut state = drawable.state_mut()?;
+                let surface = state.surface.as_mut().unwrap();
+                {
+                    let cr = Context::new(surface);
+                    cr.rectangle(0.0, 0.0, 10.0, 10.0);
+                    cr.fill();
+                }
+                flush(surface);
+                let stride = surface.get_stride() as usize;
+                let data = get_data(surface);
+                assert_eq!(data[19 * stride + 19 * 4 + 3], 255);
+                assert_eq!(data[20 * stride + 20 * 4 + 3], 0);
+            }
+            assert_eq!(
+                drawable.state()?.shown_content(),
+                Some((
+                    2,
+                    Size {
+                        width: 200,
+                        height: 40
+                    }
+                ))
+            );
+            // Staying on an output of the same scale keeps the surface,
+            // resizing keeps the scale.
+            assert_eq!(drawable.set_output_scale(lua, 2)?, None);
+            lua.load("assert(allocated == 1)").exec()?;
+            resize(lua, &mut drawable, 50)?;
+            assert_eq!(pixels(&drawable), (100, 40));
+            assert_eq!(drawable.set_output_scale(lua, 1)?, None);
+            assert_eq!(pixels(&drawable), (50, 20));
+            lua.load("assert(allocated == 3)").exec()?;
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawable_input_shape() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            init(lua)?;
+            let mut drawable = Drawable::new(lua)?;
+            resize(lua, &mut drawable, 100)?;
+            let rect = |x, width| Area {
+                origin: Origin { x, y: 0 },
+                size: Size { width, height: 20 }
+            };
+            assert_eq!(drawable.input_region()?, None);
+            drawable.set_input_shape(Some(vec![rect(10, 20), rect(90, 50)]))?;
+            assert_eq!(
+                drawable.take_input_region()?,
+                Some(Some(vec![rect(10, 20), rect(90, 50)]))
+            );
+            assert_eq!(drawable.take_input_region()?, None);
+            // The compositor clips the shape to the surface whenever it's
+            // resized, it doesn't have to be set again.
+            resize(lua, &mut drawable, 20)?;
+            assert_eq!(drawable.take_input_region()?, None);
+            drawable.set_input_shape(Some(Vec::new()))?;
+            assert_eq!(drawable.take_input_region()?, Some(Some(Vec::new())));
+            drawable.set_input_shape(None)?;
+            assert_eq!(drawable.take_input_region()?, Some(None));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawable_bounding_shape() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            init(lua)?;
+            let rect = |x, y, width, height| Area {
+                origin: Origin { x, y },
+                size: Size { width, height }
+            };
+            // A bar with the corners cut off, in a mask like awesome's.
+            let mask = ImageSurface::create(Format::A1, 10, 4).unwrap();
+            {
+                let cr = Context::new(&mask);
+                cr.rectangle(1.0, 0.0, 8.0, 4.0);
+                cr.rectangle(0.0, 1.0, 10.0, 2.0);
+                cr.fill();
+            }
+            let shape = shape_of(&mask)?;
+            assert_eq!(shape, vec![rect(1, 0, 8, 1), rect(0, 1, 10, 2), rect(1, 3, 8, 1)]);
+            let mut drawable = Drawable::new(lua)?;
+            resize(lua, &mut drawable, 10)?;
+            drawable.set_bounding_shape(Some(shape.clone()))?;
+            assert_eq!(drawable.opaque_region()?, shape);
+            // Only the parts in both shapes take input.
+            drawable.set_input_shape(Some(vec![rect(0, 0, 5, 4)]))?;
+            assert_eq!(
+                drawable.input_region()?,
+                Some(vec![rect(1, 0, 4, 1), rect(0, 1, 5, 2), rect(1, 3, 4, 1)])
+            );
+            // Translucent content isn't opaque inside the shape either.
+            drawable.set_opacity(0.5)?;
+            assert_eq!(drawable.opaque_region()?, Vec::new());
+            drawable.set_opacity(1.0)?;
+            drawable.set_bounding_shape(None)?;
+            assert_eq!(drawable.opaque_region()?, Vec::new());
+            assert_eq!(drawable.input_region()?, Some(vec![rect(0, 0, 5, 4)]));
+            Ok(())
+        })
+    }
+
+    /// Paints every column of `x..x + width` in a color of its own.
+    fn paint_columns(surface: &ImageSurface, x: u32, width: u32) {
+        let cr = Context::new(surface);
+        for column in x..x + width {
+            let (red, green) = (column % 256, column / 256);
+            cr.set_source_rgb(f64::from(red) / 255.0, f64::from(green) / 255.0, 0.0);
+            cr.rectangle(f64::from(column), 0.0, 1.0, f64::from(surface.get_height()));
+            cr.fill();
+        }
+    }
+
+    /// The pixels of the row at the top of `surface` from `x`.
+    fn row(surface: &mut ImageSurface, x: u32, width: u32) -> Vec<u8> {
+        let data = surface.get_data().unwrap();
+        data[x as usize * 4..(x + width) as usize * 4].to_vec()
+    }
+
+    #[test]
+    fn drawable_virtual_strip() -> rlua::Result<()> {
+        const VIEW: u32 = 100;
+        const STRIP: u32 = 10_000;
+        let lua = Lua::new();
+        lua.context(|lua| {
+            init(lua)?;
+            let mut drawable = Drawable::new(lua)?;
+            lua.globals().set("d", drawable.clone())?;
+            let painter = lua.create_function(|_, (drawable, x, width): (Drawable, u32, u32)| {
+                paint_columns(drawable.state()?.surface.as_ref().unwrap(), x, width);
+                Ok(())
+            })?;
+            lua.globals().set("paint_columns", painter)?;
+            lua.load(
+                r#"
+requests = {}
+d:connect_signal("request::paint_range", function(d, x, width)
+    table.insert(requests, x .. "+" .. width)
+    paint_columns(d, x, width)
+end)
+                "#
+            )
+            .exec()?;
+            resize(lua, &mut drawable, VIEW)?;
+            drawable.set_virtual_width(lua, Some(STRIP))?;
+            assert_eq!(drawable.virtual_width()?, Some(STRIP));
+            let requests = || -> rlua::Result<String> {
+                let requests = lua.load("local r = table.concat(requests, ' ') requests = {} return r");
+                requests.eval()
+            };
+            // Nothing is painted until the main loop runs.
+            assert_eq!(requests()?, "");
+            drawable.fill_view(lua)?;
+            assert_eq!(requests()?, "0+100");
+
+            let mut reference = ImageSurface::create(Format::ARgb32, STRIP as i32, 20).unwrap();
+            paint_columns(&reference, 0, STRIP);
+            let mut check = |drawable: &mut Drawable, x: u32| {
+                let mut state = drawable.state_mut().unwrap();
+                assert_eq!(state.content_offset.x, x as i32);
+                let shown = row(state.surface.as_mut().unwrap(), x, VIEW);
+                assert!(
+                    shown == row(&mut reference, x, VIEW),
+                    "differs from the reference at {}",
+                    x
+                );
+            };
+            check(&mut drawable, 0);
+            // Scrolled in steps, only what comes into view is painted.
+            let mut painted = VIEW;
+            for x in (70..STRIP + 70).step_by(70) {
+                drawable.set_scroll_offset(lua, i64::from(x))?;
+                let x = x.min(STRIP - VIEW);
+                let expected = match x + VIEW {
+                    end if end > painted => format!("{}+{}", painted, end - painted),
+                    _ => String::new()
+                };
+                assert_eq!(requests()?, expected);
+                painted = painted.max(x + VIEW);
+                check(&mut drawable, x);
+            }
+            assert_eq!(painted, STRIP);
+            // Back over painted content Lua isn't asked for anything.
+            for x in (0..STRIP).rev().step_by(333) {
+                drawable.set_scroll_offset(lua, i64::from(x))?;
+                check(&mut drawable, x.min(STRIP - VIEW));
+            }
+            drawable.set_scroll_offset(lua, -20)?;
+            check(&mut drawable, 0);
+            assert_eq!(requests()?, "");
+            lua.load(
+                r#"
+local ranges = d:valid_ranges()
+assert(#ranges == 1 and ranges[1].x == 0 and ranges[1].width == 10000)
+d:invalidate_range(5000, 10)
+d:invalidate_range(50, 10)
+assert(#d:valid_ranges() == 3)
+                "#
+            )
+            .exec()?;
+            // Only what's in view is painted again, the rest when it's shown.
+            drawable.fill_view(lua)?;
+            assert_eq!(requests()?, "50+10");
+            drawable.set_scroll_offset(lua, 4950)?;
+            assert_eq!(requests()?, "5000+10");
+            check(&mut drawable, 4950);
+            assert_eq!(drawable.valid_ranges()?, vec![(0, STRIP)]);
+            assert!(drawable.set_virtual_width(lua, Some(40_000)).is_err());
+            drawable.set_virtual_width(lua, None)?;
+            assert!(drawable.set_scroll_offset(lua, 10).is_err());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawable_set_geometry() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            init(lua)?;
+            let drawable = Drawable::new(lua)?;
+            lua.globals().set("d", drawable.clone())?;
+            lua.load(
+                r#"
+local geo = d:geometry({ x = 5, y = -3, width = 40, height = 20 })
+assert(geo.x == 5 and geo.y == -3 and geo.width == 40 and geo.height == 20)
+-- What isn't in the table is kept.
+geo = d:geometry({ width = 60 })
+assert(geo.x == 5 and geo.width == 60 and geo.height == 20)
+geo = d:geometry({ height = 60 / 2 })
+assert(geo.height == 30)
+for _, bad in ipairs({ { width = 0 }, { height = -5 }, { x = 1.5 }, { width = "wide" } }) do
+    assert(not pcall(function() d:geometry(bad) end))
+end
+geo = d:geometry()
+assert(geo.width == 60 and geo.height == 30)
+                "#
+            )
+            .exec()?;
+            let surface = drawable
+                .state()?
+                .surface
+                .as_ref()
+                .map(|surface| surface.get_width());
+            assert_eq!(surface, Some(60));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawable_save_to_png() -> rlua::Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let lua = Lua::new();
+        lua.context(|lua| {
+            init(lua)?;
+            let mut drawable = Drawable::new(lua)?;
+            lua.globals().set("d", drawable.clone())?;
+            lua.globals().set("dir", dir.path().to_str().unwrap())?;
+            // Without a surface there's nothing to write.
+            lua.load(r#"assert(not pcall(function() d:save_to_png(dir .. "/none.png") end))"#)
+                .exec()?;
+            drawable.set_geometry(
+                lua,
+                Area {
+                    origin: Origin { x: 0, y: 0 },
+                    size: Size { width: 3, height: 2 }
+                }
+            )?;
+            {
+                let state = drawable.state()?;
+                let cr = Context::new(state.surface.as_ref().unwrap());
+                cr.set_source_rgb(1.0, 0.0, 0.0);
+                cr.paint();
+            }
+            // Before any refresh, and where the file can't be written.
+            lua.load(
+                r#"
+assert(d:save_to_png(dir .. "/red.png") == true)
+local ok, err = d:save_to_png(dir .. "/missing/red.png")
+assert(ok == nil and err:find("missing/red.png", 1, true))
+                "#
+            )
+            .exec()?;
+            let mut file = File::open(dir.path().join("red.png")).unwrap();
+            let mut png = ImageSurface::create_from_png(&mut file).unwrap();
+            assert_eq!((png.get_width(), png.get_height()), (3, 2));
+            assert_eq!(&get_data(&mut png)[..4], &[0, 0, 0xff, 0xff]);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawable_fill() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            init(lua)?;
+            let mut drawable = Drawable::new(lua)?;
+            lua.globals().set("d", drawable.clone())?;
+            lua.load(r#"assert(not pcall(function() d:fill(0, 0, 0) end))"#)
+                .exec()?;
+            // Writing the buffer needs a compositor.
+            drawable.set_suspended(true)?;
+            drawable.set_geometry(
+                lua,
+                Area {
+                    origin: Origin { x: 0, y: 0 },
+                    size: Size { width: 4, height: 4 }
+                }
+            )?;
+            lua.load("d:fill(1, 0, 0, 0.5, nil, true)").exec()?;
+            assert!(drawable.state()?.refreshed);
+            lua.load(
+                r#"
+-- A band across the surface, clipped to it.
+d:fill(0, 0, 1, 1, { y = 3, height = 5 })
+for _, bad in ipairs({ { 2, 0, 0 }, { 0, 0, 0, -1 }, { 0 / 0, 0, 0 } }) do
+    assert(not pcall(function() d:fill(table.unpack(bad)) end))
+end
+assert(not pcall(function() d:fill(0, 0, 0, 1, { width = 0 }) end))
+                "#
+            )
+            .exec()?;
+            let mut state = drawable.state_mut()?;
+            assert!(!state.refreshed);
+            // A suspended refresh leaves the damage for when it's shown.
+            let damage = state.damage.damage(state.damage.root()).to_vec();
+            assert_eq!(
+                damage,
+                vec![Area {
+                    origin: Origin { x: 0, y: 0 },
+                    size: Size { width: 4, height: 4 }
+                }]
+            );
+            let surface = state.surface.as_mut().unwrap();
+            flush(surface);
+            let stride = surface.get_stride() as usize;
+            let data = get_data(surface);
+            // Premultiplied, and replacing what was there.
+            assert_eq!(&data[..4], &0x8080_0000u32.to_ne_bytes());
+            assert_eq!(&data[3 * stride..3 * stride + 4], &0xff00_00ffu32.to_ne_bytes());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawable_buffer_error() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            init(lua)?;
+            let drawable = Drawable::new(lua)?;
+            lua.globals().set("d", drawable.clone())?;
+            // There's no compositor to ask for a buffer in the tests.
+            lua.load(
+                r#"
+assert(d:geometry({ width = 20, height = 10 }).width == 20)
+local ok, err = pcall(function() d:refresh() end)
+assert(not ok and tostring(err):find("Could not create buffer for drawable: the compositor has no wl_shm", 1, true))
+                "#
+            )
+            .exec()?;
+            let state = drawable.state()?;
+            assert!(state.buffer.is_none() && !state.presentable);
+            // The content is kept for when a buffer can be had.
+            assert!(state.surface.is_some());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawable_destroy() -> rlua::Result<()> {
+        use cairo_sys::{cairo_surface_destroy, cairo_surface_get_reference_count, cairo_surface_t};
+        let lua = Lua::new();
+        lua.context(|lua| {
+            init(lua)?;
+            // The reference `get_surface` gives Lua outlives the drawable,
+            // and is then the only one.
+            let mut drawable = Drawable::new(lua)?;
+            resize(lua, &mut drawable, 10)?;
+            let surface = match drawable.get_surface()? {
+                Value::LightUserData(surface) => surface.0 as *mut cairo_surface_t,
+                other => panic!("expected a surface, got {:?}", other)
+            };
+            let generation = drawable.surface_generation()?;
+            assert_eq!(drawable.destroy()?, 10 * 20 * 4);
+            assert!(drawable.state()?.surface.is_none());
+            assert!(drawable.surface_generation()? > generation);
+            assert_eq!(unsafe { cairo_surface_get_reference_count(surface) }, 1);
+            unsafe { cairo_surface_destroy(surface) };
+            // A drawable shrunk to nothing keeps nothing.
+            resize(lua, &mut drawable, 10)?;
+            resize(lua, &mut drawable, 0)?;
+            assert_eq!(drawable.destroy()?, 0);
+            drop(drawable);
+            // Collected drawables release their surfaces.
+            let mut surfaces = Vec::new();
+            for _ in 0..10_000 {
+                let mut drawable = Drawable::new(lua)?;
+                resize(lua, &mut drawable, 10)?;
+                if let Value::LightUserData(surface) = drawable.get_surface()? {
+                    surfaces.push(surface.0 as *mut cairo_surface_t);
+                }
+            }
+            lua.load("collectgarbage()").exec()?;
+            assert_eq!(surfaces.len(), 10_000);
+            for surface in surfaces {
+                assert_eq!(unsafe { cairo_surface_get_reference_count(surface) }, 1);
+                unsafe { cairo_surface_destroy(surface) };
+            }
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawable_mapped_falls_back() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            init(lua)?;
+            let mut drawable = Drawable::new(lua)?;
+            lua.globals().set("d", drawable.clone())?;
+            lua.load(
+                r#"
+d.buffer_backend = "mapped"
+assert(d.buffer_backend == "mapped")
+                "#
+            )
+            .exec()?;
+            // Without wl_shm there's no mapped buffer, the surface is one of
+            // its own that's copied from.
+            resize(lua, &mut drawable, 10)?;
+            assert!(drawable.state()?.surface.is_some());
+            assert!(drawable.state()?.mapped.is_none());
+            let (backend, fallback) = drawable.buffer_backend()?;
+            assert_eq!(backend, None);
+            assert!(fallback.unwrap().starts_with("could not create a mapped buffer"));
+            assert_eq!(Backend::copied_into(Backend::Mapped), Backend::Shm);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawable_geometry_signals() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            init(lua)?;
+            let drawable = Drawable::new(lua)?;
+            lua.globals().set("d", drawable.clone())?;
+            lua.load(
+                r#"
+local signals = {}
+for _, name in ipairs{ "property::surface", "property::geometry", "property::x", "property::y",
+                       "property::width", "property::height" } do
+    d:connect_signal(name, function() table.insert(signals, name) end)
+end
+local function emitted(geo)
+    signals = {}
+    d:geometry(geo)
+    return table.concat(signals, " ")
+end
+assert(emitted({ x = 0, y = 0, width = 10, height = 20 }) ==
+    "property::surface property::width property::height property::geometry")
+assert(emitted({ x = 5 }) == "property::x property::geometry")
+assert(emitted({ width = 30 }) == "property::surface property::width property::geometry")
+assert(emitted({ x = 5, width = 30 }) == "")
+                "#
+            )
+            .exec()?;
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawable_refresh_standalone() -> rlua::Result<()> {
+        let mut server = TestServer::start(LAYER_SHELL_MAX_VERSION);
+        let lua = Lua::new();
+        lua.context(|lua| {
+            init(lua)?;
+            let mut drawable = Drawable::new(lua)?;
+            lua.globals().set("d", drawable.clone())?;
+            assert!(drawable
+                .get_associated_data::<Option<super::Drawin>>("drawin")?
+                .is_none());
+            // Without a geometry there's nothing to write yet.
+            lua.load("d:refresh()").exec()?;
+            assert!(drawable.wl_buffer()?.is_none());
+            server.roundtrip();
+            assert_eq!(server.take_requests(), []);
+            // Without a drawin the buffer is written, and not shown.
+            resize(lua, &mut drawable, 10)?;
+            lua.load("d:refresh()").exec()?;
+            let (_, size) = drawable.wl_buffer()?.expect("the buffer wasn't written");
+            assert_eq!(
+                size,
+                Size {
+                    width: 10,
+                    height: 20
+                }
+            );
+            server.roundtrip();
+            let sent: Vec<_> = server
+                .take_requests()
+                .iter()
+                .map(|request| format!("{}.{}", request.interface, request.name))
+                .collect();
+            assert_eq!(sent, ["wl_shm.create_pool", "wl_shm_pool.create_buffer"]);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawable_shrink_keeps_memory() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            init(lua)?;
+            let mut drawable = Drawable::new(lua)?;
+            let stride =
+                |drawable: &Drawable| drawable.state().unwrap().surface.as_ref().unwrap().get_stride();
+            resize(lua, &mut drawable, 100)?;
+            let generation = drawable.surface_generation()?;
+            // Shrinking is a new surface to Lua, in the same memory.
+            resize(lua, &mut drawable, 80)?;
+            assert!(drawable.surface_generation()? > generation);
+            assert_eq!(drawable.state()?.surface.as_ref().unwrap().get_width(), 80);
+            assert_eq!(stride(&drawable), 100 * 4);
+            // Growing allocates with headroom, which the next step fits in.
+            resize(lua, &mut drawable, 110)?;
+            assert_eq!(stride(&drawable), 137 * 4);
+            resize(lua, &mut drawable, 120)?;
+            assert_eq!(stride(&drawable), 137 * 4);
+            drawable.destroy()?;
+            assert_eq!(drawable.state_mut()?.capacity.clear(), 0);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawable_resizes_keep_fds_bounded() -> rlua::Result<()> {
+        let open_fds = || fs::read_dir("/proc/self/fd").unwrap().count();
+        let mut server = TestServer::start(LAYER_SHELL_MAX_VERSION);
+        let lua = Lua::new();
+        lua.context(|lua| {
+            init(lua)?;
+            let mut drawable = Drawable::new(lua)?;
+            lua.globals().set("d", drawable.clone())?;
+            resize(lua, &mut drawable, 10)?;
+            lua.load("d:refresh()").exec()?;
+            server.roundtrip();
+            let before = open_fds();
+            let created_pools = |server: &TestServer| {
+                server
+                    .take_requests()
+                    .iter()
+                    .filter(|request| request.name == "create_pool")
+                    .count()
+            };
+            let mut pools = 0;
+            // Like a clock widget toggling its size, which sometimes needs
+            // a new buffer.
+            for step in 0..1000 {
+                resize(lua, &mut drawable, if step % 2 == 0 { 1000 } else { 10 })?;
+                lua.load("d:refresh()").exec()?;
+                if step % 100 == 99 {
+                    server.roundtrip();
+                    pools += created_pools(&server);
+                }
+            }
+            assert!(pools > 0, "no buffer was allocated again");
+            // Both the client's files and the ones the server got are
+            // closed with the buffers. Other tests might have a few open.
+            let after = open_fds();
+            assert!(after < before + 64, "{} fds were open, then {}", before, after);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawable_clip() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            init(lua)?;
+            let mut drawable = Drawable::new(lua)?;
+            resize(lua, &mut drawable, 10)?;
+            assert_eq!(drawable.input_region()?, None);
+            // Sliding in from the left, the input region is moved with what's
+            // shown, and the geometry is kept.
+            let clip = Area {
+                origin: Origin { x: 6, y: 0 },
+                size: Size { width: 4, height: 20 }
+            };
+            drawable.set_clip(Some(clip))?;
+            assert_eq!(drawable.clip()?, Some(clip));
+            assert!(drawable.take_input_region()?.is_some());
+            assert_eq!(
+                drawable.input_region()?,
+                Some(vec![Area {
+                    origin: Origin { x: -6, y: 0 },
+                    size: Size {
+                        width: 10,
+                        height: 20
+                    }
+                }])
+            );
+            assert_eq!(
+                drawable.get_geometry()?.size,
+                Size {
+                    width: 10,
+                    height: 20
+                }
+            );
+            drawable.set_clip(None)?;
+            assert_eq!(drawable.input_region()?, None);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawable_flush_throttled() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            init(lua)?;
+            let mut drawable = Drawable::new(lua)?;
+            // Writing the buffer needs a compositor.
+            drawable.set_suspended(true)?;
+            drawable.set_geometry(
+                lua,
+                Area {
+                    origin: Origin { x: 0, y: 0 },
+                    size: Size {
+                        width: 10,
+                        height: 10
+                    }
+                }
+            )?;
+            // Without a frame pending nothing is held back, or flushed.
+            drawable.flush_throttled(lua)?;
+            assert!(!drawable.state()?.refreshed);
+            drawable.refresh(lua)?;
+            assert!(!drawable.state()?.throttled);
+            // The damage of the refreshes that waited is written at once.
+            drawable.state_mut()?.refreshed = false;
+            drawable.add_damage(Area {
+                origin: Origin { x: 0, y: 0 },
+                size: Size { width: 5, height: 5 }
+            })?;
+            drawable.state_mut()?.throttled = true;
+            drawable.flush_throttled(lua)?;
+            let state = drawable.state()?;
+            assert!(state.refreshed && !state.throttled);
+            assert!(!state.damage.damage(state.damage.root()).is_empty());
+            assert_eq!(state.frame_stats.throttled_frames, 0);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawable_refresh_rect() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            init(lua)?;
+            let mut drawable = Drawable::new(lua)?;
+            // Writing the buffer needs a compositor.
+            drawable.set_suspended(true)?;
+            drawable.set_geometry(
+                lua,
+                Area {
+                    origin: Origin { x: 0, y: 0 },
+                    size: Size {
+                        width: 100,
+                        height: 20
+                    }
+                }
+            )?;
+            lua.globals().set("d", drawable.clone())?;
+            lua.load(
+                r#"
+d:refresh(10, 0, 20, 20)
+d:refresh(20, 5, 20, 10)
+d:add_damage(90, 10, 50, 50)
+d:refresh()
+assert(not pcall(function() d:refresh(0, 0) end))
+assert(not pcall(function() d:refresh(0, 0, -1, 5) end))
+                "#
+            )
+            .exec()?;
+            // Overlapping rectangles are merged, and clipped to the surface.
+            let state = drawable.state()?;
+            let damage = state.damage.damage(state.damage.root());
+            let pixels = |rects: &[Area]| {
+                rects
+                    .iter()
+                    .map(|rect| rect.size.width * rect.size.height)
+                    .sum::<u32>()
+            };
+            assert_eq!(pixels(damage), 20 * 20 + 10 * 10 + 10 * 10);
+            for (i, rect) in damage.iter().enumerate() {
+                assert!(damage[i + 1..]
+                    .iter()
+                    .all(|other| rect.intersection(*other).is_none()));
+            }
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawable_format() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            init(lua)?;
+            let mut drawable = Drawable::new(lua)?;
+            drawable.set_geometry(
+                lua,
+                Area {
+                    origin: Origin { x: 0, y: 0 },
+                    size: Size {
+                        width: 40,
+                        height: 10
+                    }
+                }
+            )?;
+            drawable.set_input_from_alpha(true)?;
+            lua.globals().set("d", drawable.clone())?;
+            lua.load(
+                r#"
+assert(d.format == "argb32")
+local generation = d.surface_generation
+d.format = "rgb24"
+assert(d.format == "rgb24")
+-- Painted again in the new format.
+assert(d.surface_generation == generation + 1)
+d.format = "rgb24"
+assert(d.surface_generation == generation + 1)
+assert(not pcall(function() d.format = "a8" end))
+assert(d.format == "rgb24")
+                "#
+            )
+            .exec()?;
+            let state = drawable.state()?;
+            assert_eq!(
+                state.surface.as_ref().map(ImageSurface::get_format),
+                Some(Format::Rgb24)
+            );
+            drop(state);
+            // All of an opaque drawable takes input.
+            drawable.set_alpha_threshold(10)?;
+            assert_eq!(drawable.input_region()?, None);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawable_buffer_backend() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            init(lua)?;
+            let mut drawable = Drawable::new(lua)?;
+            lua.globals().set("d", drawable.clone())?;
+            lua.load(
+                r#"
+assert(d.buffer_backend == "shm")
+d.buffer_backend = "dmabuf"
+assert(d.buffer_backend == "dmabuf")
+assert(not pcall(function() d.buffer_backend = "vulkan" end))
+assert(d.buffer_backend == "dmabuf")
+local stats = d:frame_stats()
+assert(stats.buffer_backend == nil and stats.buffer_fallback == nil)
+                "#
+            )
+            .exec()?;
+            // Asking for a backend again tries it again after a fallback.
+            backend::fall_back(
+                &mut drawable.state_mut()?.buffer_fallback,
+                "no render node".into()
+            );
+            assert_eq!(drawable.buffer_backend()?, (None, Some("no render node".into())));
+            drawable.set_buffer_backend(Backend::Dmabuf)?;
+            assert_eq!(drawable.buffer_backend()?, (None, None));
+            Ok(())
+        })
     }
 }
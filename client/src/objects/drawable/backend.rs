@@ -0,0 +1,280 @@
+//! Which kind of buffer the content of a drawable is copied into.
+//!
+//! Buffers are in shared memory unless the drawable asks for a dmabuf with
+//! `drawable.buffer_backend = "dmabuf"`, which the compositor can use
+//! without copying it. When a dmabuf can't be had, because the client was
+//! built without the `dmabuf` feature, there is no GPU to allocate it on or
+//! the compositor couldn't import it, that drawable falls back to shared
+//! memory on its own. `drawable:frame_stats()` says which it has and why.
+//! Dmabufs are only allocated in the default pixel format.
+//!
+//! With `"mapped"` the content isn't copied at all when it can be shown as
+//! it is, Lua paints it into the buffer, see `mapped`.
+
+use std::{cell::RefCell, fmt, io};
+
+use wayland_client::protocol::{wl_buffer::WlBuffer, wl_shm};
+
+use cairo::ImageSurface;
+
+use super::{frame::Target, mapped, pixel_format::PixelFormat};
+use crate::area::{Area, Origin, Size};
+#[cfg(feature = "dmabuf")]
+use crate::wayland_obj::DmabufBuffer;
+use crate::wayland_obj::{self, Buffer, BufferError, MappedBuffer};
+
+thread_local! {
+    static STATS: RefCell<BackendStats> = RefCell::new(BackendStats::default());
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Backend {
+    Shm,
+    Dmabuf,
+    Mapped
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Backend::Shm
+    }
+}
+
+impl Backend {
+    pub const NAMES: &'static [&'static str] = &["shm", "dmabuf", "mapped"];
+
+    pub fn from_name(name: &str) -> Option<Backend> {
+        match name {
+            "shm" => Some(Backend::Shm),
+            "dmabuf" => Some(Backend::Dmabuf),
+            "mapped" => Some(Backend::Mapped),
+            _ => None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Shm => "shm",
+            Backend::Dmabuf => "dmabuf",
+            Backend::Mapped => "mapped"
+        }
+    }
+
+    /// The kind of buffer the content is copied into, which for a mapped
+    /// buffer is the one used when the content can't be shown as it is.
+    pub fn copied_into(self) -> Backend {
+        match self {
+            Backend::Mapped => Backend::Shm,
+            backend => backend
+        }
+    }
+}
+
+/// The buffers allocated by every drawable, for crash reports.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+struct BackendStats {
+    shm: u64,
+    dmabuf: u64,
+    mapped: u64,
+    fallbacks: u64,
+    last_fallback: Option<String>
+}
+
+#[derive(Debug)]
+pub enum DrawableBuffer {
+    Shm(Buffer),
+    #[cfg(feature = "dmabuf")]
+    Dmabuf(DmabufBuffer)
+}
+
+impl DrawableBuffer {
+    pub fn size(&self) -> Size {
+        match self {
+            DrawableBuffer::Shm(buffer) => buffer.size(),
+            #[cfg(feature = "dmabuf")]
+            DrawableBuffer::Dmabuf(buffer) => buffer.size()
+        }
+    }
+
+    /// The memory the buffer takes, at 4 bytes a pixel, or what its file
+    /// takes for shared memory.
+    pub fn bytes(&self) -> u64 {
+        match self {
+            DrawableBuffer::Shm(buffer) => buffer.capacity() as u64,
+            #[cfg(feature = "dmabuf")]
+            DrawableBuffer::Dmabuf(buffer) => {
+                let Size { width, height } = buffer.size();
+                u64::from(width) * u64::from(height) * 4
+            }
+        }
+    }
+
+    /// Makes the buffer `size` without allocating another, returning how
+    /// many bytes it grew by, or `None` if it has to be allocated again.
+    pub fn resize(&mut self, size: Size) -> Option<u64> {
+        match self {
+            DrawableBuffer::Shm(buffer) if buffer.can_resize(size) => {
+                buffer.resize(size).ok().map(|grown| grown as u64)
+            },
+            _ => None
+        }
+    }
+
+    pub fn wl_buffer(&self) -> &WlBuffer {
+        match self {
+            DrawableBuffer::Shm(buffer) => buffer.wl_buffer(),
+            #[cfg(feature = "dmabuf")]
+            DrawableBuffer::Dmabuf(buffer) => buffer.wl_buffer()
+        }
+    }
+
+    pub fn format(&self) -> wl_shm::Format {
+        match self {
+            DrawableBuffer::Shm(buffer) => buffer.format(),
+            #[cfg(feature = "dmabuf")]
+            DrawableBuffer::Dmabuf(_) => wl_shm::Format::Argb8888
+        }
+    }
+
+    pub fn backend(&self) -> Backend {
+        match self {
+            DrawableBuffer::Shm(_) => Backend::Shm,
+            #[cfg(feature = "dmabuf")]
+            DrawableBuffer::Dmabuf(_) => Backend::Dmabuf
+        }
+    }
+
+    /// Why the buffer can't be shown, if the compositor refused it.
+    pub fn failure(&self) -> Option<&'static str> {
+        match self {
+            DrawableBuffer::Shm(_) => None,
+            #[cfg(feature = "dmabuf")]
+            DrawableBuffer::Dmabuf(buffer) if buffer.failed() => {
+                Some("the compositor couldn't import the dmabuf")
+            },
+            #[cfg(feature = "dmabuf")]
+            DrawableBuffer::Dmabuf(_) => None
+        }
+    }
+}
+
+impl Target for DrawableBuffer {
+    fn write(&mut self, data: &[u8], stride: usize, offset: Origin) -> io::Result<()> {
+        match self {
+            DrawableBuffer::Shm(buffer) => buffer.write(data, stride, offset),
+            #[cfg(feature = "dmabuf")]
+            DrawableBuffer::Dmabuf(buffer) => buffer
+                .write(data, stride, offset)
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+        }
+    }
+
+    fn write_rects(&mut self, data: &[u8], stride: usize, offset: Origin, rects: &[Area]) -> io::Result<()> {
+        match self {
+            DrawableBuffer::Shm(buffer) => buffer.write_rects(data, stride, offset, rects),
+            #[cfg(feature = "dmabuf")]
+            DrawableBuffer::Dmabuf(buffer) => buffer
+                .write_rects(data, stride, offset, rects)
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+        }
+    }
+}
+
+/// Allocates a buffer of `size` and `format` with the backend the drawable
+/// wants.
+///
+/// Where a dmabuf can't be had `fallback` is set to why and the buffer is
+/// in shared memory instead. While `fallback` is set, a dmabuf isn't tried
+/// again.
+pub fn allocate(
+    size: Size,
+    format: PixelFormat,
+    wanted: Backend,
+    fallback: &mut Option<String>
+) -> Result<DrawableBuffer, BufferError> {
+    if wanted == Backend::Dmabuf && fallback.is_none() {
+        let dmabuf = if format == PixelFormat::default() {
+            allocate_dmabuf(size)
+        } else {
+            Err(format!("dmabufs can't be allocated as {}", format.name()))
+        };
+        match dmabuf {
+            Ok(buffer) => {
+                STATS.with(|stats| stats.borrow_mut().dmabuf += 1);
+                return Ok(buffer);
+            },
+            Err(reason) => fall_back(fallback, reason)
+        }
+    }
+    let buffer = wayland_obj::create_buffer(size, format.shm())?;
+    STATS.with(|stats| stats.borrow_mut().shm += 1);
+    Ok(DrawableBuffer::Shm(buffer))
+}
+
+/// Allocates a mapped buffer of `size` and `format` and a surface over it.
+///
+/// Where there's none `fallback` is set to why, and the content is painted
+/// into a surface of its own and copied. While `fallback` is set, a mapped
+/// buffer isn't tried again.
+pub fn allocate_mapped(
+    size: Size,
+    format: PixelFormat,
+    fallback: &mut Option<String>
+) -> Option<(MappedBuffer, ImageSurface)> {
+    if fallback.is_some() {
+        return None;
+    }
+    match mapped::allocate(size, format) {
+        Ok(mapped) => {
+            STATS.with(|stats| stats.borrow_mut().mapped += 1);
+            Some(mapped)
+        },
+        Err(reason) => {
+            fall_back(fallback, reason);
+            None
+        }
+    }
+}
+
+/// Records why a drawable that wanted a dmabuf or a mapped buffer has to
+/// do with copying into shared memory.
+pub fn fall_back(fallback: &mut Option<String>, reason: String) {
+    warn!("Copying into a shm buffer instead: {}", reason);
+    STATS.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        stats.fallbacks += 1;
+        stats.last_fallback = Some(reason.clone());
+    });
+    *fallback = Some(reason);
+}
+
+#[cfg(feature = "dmabuf")]
+fn allocate_dmabuf(size: Size) -> Result<DrawableBuffer, String> {
+    wayland_obj::create_dmabuf_buffer(size).map(DrawableBuffer::Dmabuf)
+}
+
+#[cfg(not(feature = "dmabuf"))]
+fn allocate_dmabuf(_: Size) -> Result<DrawableBuffer, String> {
+    Err("the client was built without the dmabuf feature".into())
+}
+
+/// Writes how many buffers of each backend were allocated, for crash
+/// reports.
+pub fn write_buffer_stats(out: &mut dyn fmt::Write) -> fmt::Result {
+    STATS
+        .try_with(|stats| {
+            let stats = match stats.try_borrow() {
+                Ok(stats) => stats,
+                Err(_) => return writeln!(out, "(in use)")
+            };
+            writeln!(out, "shm: {}", stats.shm)?;
+            writeln!(out, "dmabuf: {}", stats.dmabuf)?;
+            writeln!(out, "mapped: {}", stats.mapped)?;
+            writeln!(out, "fallbacks: {}", stats.fallbacks)?;
+            if let Some(reason) = stats.last_fallback.as_ref() {
+                writeln!(out, "last fallback: {}", reason)?;
+            }
+            Ok(())
+        })
+        .unwrap_or(Ok(()))
+}
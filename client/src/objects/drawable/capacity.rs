@@ -0,0 +1,196 @@
+//! The memory of the surface, kept while the drawable shrinks.
+//!
+//! A popup that grows and shrinks in an animation would allocate a surface
+//! for every size it goes through. Instead the surface Lua paints into is
+//! a view of memory that's kept: a smaller surface has the stride of the
+//! memory and only growing past it allocates more, with some headroom so
+//! the next few steps of the animation fit. A surface much smaller than
+//! the memory gets memory of its own, so a drawable that stays small
+//! doesn't hold on to what it took when it was large.
+//!
+//! The memory is cleared for every surface, which looks to Lua like a new
+//! one. The view that's replaced is finished, so contexts Lua kept for it
+//! draw nowhere rather than into the next surface.
+
+use std::os::raw::c_void;
+
+use cairo::{Context, Format, ImageSurface, Operator, Status};
+use cairo_sys::{self, cairo_surface_t, cairo_user_data_key_t};
+use glib::translate::ToGlibPtr;
+
+use super::variants;
+use crate::area::Size;
+
+/// The key of the memory a view keeps alive, only its address matters.
+static MEMORY_KEY: cairo_user_data_key_t = cairo_user_data_key_t { unused: 0 };
+
+/// Cairo doesn't take surfaces larger than this.
+const MAX_LENGTH: u32 = 32767;
+
+#[derive(Debug, Default)]
+pub struct Capacity {
+    /// The surface that has the memory, which is never given to Lua.
+    memory: Option<ImageSurface>
+}
+
+impl Capacity {
+    /// A cleared surface of `size` in the kept memory, which is allocated
+    /// again if it's too small, too large or in another format.
+    pub fn surface(&mut self, format: Format, size: Size) -> Result<ImageSurface, Status> {
+        let allocate = match self.memory.as_ref() {
+            Some(memory) => memory.get_format() != format || !keeps(memory, size),
+            None => true
+        };
+        let mut cleared = false;
+        if allocate {
+            // Only a drawable that's resized again is likely to be resized
+            // once more.
+            let capacity = if self.memory.is_some() {
+                headroom(size)
+            } else {
+                size
+            };
+            self.memory = None;
+            let memory = ImageSurface::create(format, capacity.width as i32, capacity.height as i32)?;
+            self.memory = Some(memory);
+            cleared = true;
+        }
+        let view = view(self.memory.as_ref().unwrap(), size)?;
+        if !cleared {
+            let cr = Context::new(&view);
+            cr.set_operator(Operator::Clear);
+            cr.paint();
+        }
+        Ok(view)
+    }
+
+    /// Drops the memory, returning how much that was. Views Lua still has
+    /// keep it until they're dropped too.
+    pub fn clear(&mut self) -> u64 {
+        self.memory
+            .take()
+            .map(|memory| variants::surface_bytes(&memory))
+            .unwrap_or(0)
+    }
+}
+
+/// Finishes a surface that's replaced, so drawing into it does nothing.
+pub fn retire(surface: &ImageSurface) {
+    unsafe { cairo_sys::cairo_surface_finish(surface.to_glib_none().0) }
+}
+
+/// Whether `memory` is large enough for a surface of `size`, which uses at
+/// least a quarter of it.
+fn keeps(memory: &ImageSurface, size: Size) -> bool {
+    let (width, height) = (memory.get_width() as u32, memory.get_height() as u32);
+    size.width <= width &&
+        size.height <= height &&
+        u64::from(size.width) * u64::from(size.height) * 4 >= u64::from(width) * u64::from(height)
+}
+
+/// The size of memory allocated for a surface of `size`, a quarter larger
+/// on each side.
+fn headroom(size: Size) -> Size {
+    let grow = |length: u32| (length + length / 4).min(MAX_LENGTH.max(length));
+    Size {
+        width: grow(size.width),
+        height: grow(size.height)
+    }
+}
+
+/// A surface of `size` over the top left of `memory`, which it keeps alive.
+fn view(memory: &ImageSurface, size: Size) -> Result<ImageSurface, Status> {
+    unsafe {
+        let memory: *mut cairo_surface_t = memory.to_glib_none().0;
+        let view = ImageSurface::from_raw_full(cairo_sys::cairo_image_surface_create_for_data(
+            cairo_sys::cairo_image_surface_get_data(memory),
+            cairo_sys::cairo_image_surface_get_format(memory),
+            size.width as i32,
+            size.height as i32,
+            cairo_sys::cairo_image_surface_get_stride(memory)
+        ))?;
+        let memory = cairo_sys::cairo_surface_reference(memory);
+        let status = cairo_sys::cairo_surface_set_user_data(
+            view.to_glib_none().0,
+            &MEMORY_KEY as *const _ as *mut _,
+            memory as *mut c_void,
+            Some(release_memory)
+        );
+        if status != Status::Success {
+            // Cairo didn't take the memory, which has to outlive the view.
+            drop(view);
+            release_memory(memory as *mut c_void);
+            return Err(status);
+        }
+        Ok(view)
+    }
+}
+
+/// Drops the reference to the memory a view had, once cairo destroys the
+/// view.
+unsafe extern "C" fn release_memory(memory: *mut c_void) {
+    cairo_sys::cairo_surface_destroy(memory as *mut cairo_surface_t);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn data(surface: &ImageSurface) -> *mut u8 {
+        unsafe { cairo_sys::cairo_image_surface_get_data(surface.to_glib_none().0) }
+    }
+
+    fn size(width: u32, height: u32) -> Size {
+        Size { width, height }
+    }
+
+    #[test]
+    fn capacity_keeps_memory_while_shrinking() {
+        let mut capacity = Capacity::default();
+        let large = capacity.surface(Format::ARgb32, size(100, 40)).unwrap();
+        let cr = Context::new(&large);
+        cr.set_source_rgb(1.0, 0.0, 0.0);
+        cr.paint();
+        let stride = large.get_stride();
+        retire(&large);
+        let small = capacity.surface(Format::ARgb32, size(80, 30)).unwrap();
+        // The same memory, cleared, and the old surface draws nowhere.
+        assert_eq!(data(&small), data(&large));
+        assert_eq!(
+            (small.get_width(), small.get_height(), small.get_stride()),
+            (80, 30, stride)
+        );
+        assert_eq!(cr.status(), Status::Success);
+        cr.paint();
+        drop(cr);
+        drop(large);
+        let mut small = small;
+        let cleared = small
+            .get_data()
+            .unwrap()
+            .chunks(stride as usize)
+            .all(|row| row[..80 * 4].iter().all(|&byte| byte == 0));
+        assert!(cleared);
+        // Growing past it allocates with headroom.
+        let grown = capacity.surface(Format::ARgb32, size(120, 40)).unwrap();
+        assert_ne!(data(&grown), data(&small));
+        assert_eq!(capacity.clear(), 150 * 4 * 50);
+        // The memory outlives the capacity while a view has it.
+        drop(small);
+        let mut grown = grown;
+        assert!(grown.get_data().is_ok());
+    }
+
+    #[test]
+    fn capacity_reallocates_much_smaller() {
+        let mut capacity = Capacity::default();
+        let large = capacity.surface(Format::ARgb32, size(100, 100)).unwrap();
+        let small = capacity.surface(Format::ARgb32, size(40, 40)).unwrap();
+        assert_ne!(data(&small), data(&large));
+        assert_eq!(small.get_stride(), 50 * 4);
+        // Another format doesn't share the memory either.
+        let rgb = capacity.surface(Format::Rgb24, size(40, 40)).unwrap();
+        assert_ne!(data(&rgb), data(&small));
+        assert_eq!(rgb.get_format(), Format::Rgb24);
+    }
+}
@@ -0,0 +1,220 @@
+//! What's shown when the content of a drawable isn't the size of the
+//! surface it's displayed on.
+//!
+//! That happens between the compositor granting a new size and Lua
+//! repainting at it, and when Lua deliberately paints at a fixed size.
+
+use crate::area::{Origin, Size};
+
+/// How content is fitted into a surface of a different size.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ContentFit {
+    /// Scale the content to the size of the surface.
+    Stretch,
+    /// Center the content, filling the rest of the surface with a color.
+    Letterbox,
+    /// Show the region of the content at the content offset, filling any
+    /// part of the surface it doesn't cover with a color.
+    Crop,
+    /// Don't show mismatched content, keep the previous frame until the
+    /// content is repainted at the right size.
+    None
+}
+
+impl Default for ContentFit {
+    fn default() -> Self {
+        ContentFit::None
+    }
+}
+
+impl ContentFit {
+    pub const NAMES: &'static [&'static str] = &["stretch", "letterbox", "crop", "none"];
+
+    pub fn from_name(name: &str) -> Option<ContentFit> {
+        match name {
+            "stretch" => Some(ContentFit::Stretch),
+            "letterbox" => Some(ContentFit::Letterbox),
+            "crop" => Some(ContentFit::Crop),
+            "none" => Some(ContentFit::None),
+            _ => None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ContentFit::Stretch => "stretch",
+            ContentFit::Letterbox => "letterbox",
+            ContentFit::Crop => "crop",
+            ContentFit::None => "none"
+        }
+    }
+}
+
+/// ARGB32 pixels, with rows `stride` bytes long.
+#[derive(Debug, Clone, Copy)]
+pub struct Image<'a> {
+    pub data: &'a [u8],
+    pub stride: usize,
+    pub size: Size
+}
+
+/// Fits `image` into a surface of `size`, returning the tightly packed
+/// pixels of the surface.
+///
+/// `offset` is the content offset of the drawable, which is where a cropped
+/// region starts. Pixels not covered by the content are set to `fill`.
+///
+/// Returns `None` if the content can't be shown with `ContentFit::None`.
+pub fn fit_content(
+    image: Image,
+    size: Size,
+    fit: ContentFit,
+    offset: Origin,
+    fill: [u8; 4]
+) -> Option<Vec<u8>> {
+    let (width, height) = (size.width as i64, size.height as i64);
+    let (src_width, src_height) = (image.size.width as i64, image.size.height as i64);
+    let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+    let position = match fit {
+        ContentFit::None if image.size != size => return None,
+        ContentFit::Stretch if src_width > 0 && src_height > 0 => {
+            for y in 0..height {
+                let src_y = (y * src_height / height) as usize;
+                for x in 0..width {
+                    let src = src_y * image.stride + (x * src_width / width) as usize * 4;
+                    pixels.extend_from_slice(&image.data[src..src + 4]);
+                }
+            }
+            return Some(pixels);
+        },
+        ContentFit::Letterbox => Origin {
+            x: ((width - src_width) / 2) as i32,
+            y: ((height - src_height) / 2) as i32
+        },
+        _ => Origin {
+            x: -offset.x,
+            y: -offset.y
+        }
+    };
+    for y in 0..height {
+        let src_y = y - position.y as i64;
+        for x in 0..width {
+            let src_x = x - position.x as i64;
+            if src_x < 0 || src_y < 0 || src_x >= src_width || src_y >= src_height {
+                pixels.extend_from_slice(&fill);
+            } else {
+                let src = src_y as usize * image.stride + src_x as usize * 4;
+                pixels.extend_from_slice(&image.data[src..src + 4]);
+            }
+        }
+    }
+    Some(pixels)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FILL: [u8; 4] = [9, 9, 9, 9];
+
+    /// A 2x2 image with a different color in each corner, with some padding
+    /// at the end of the rows like Cairo can add.
+    fn pattern() -> Vec<u8> {
+        vec![
+            1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0, //
+            3, 3, 3, 3, 4, 4, 4, 4, 0, 0, 0, 0,
+        ]
+    }
+
+    fn image(data: &[u8]) -> Image {
+        Image {
+            data,
+            stride: 12,
+            size: Size { width: 2, height: 2 }
+        }
+    }
+
+    /// The first byte of each pixel, row by row.
+    fn render(fit: ContentFit, size: Size, offset: Origin) -> Option<Vec<Vec<u8>>> {
+        let data = pattern();
+        let pixels = fit_content(image(&data), size, fit, offset, FILL)?;
+        Some(
+            pixels
+                .chunks(size.width as usize * 4)
+                .map(|row| row.chunks(4).map(|pixel| pixel[0]).collect())
+                .collect()
+        )
+    }
+
+    /// The size the compositor granted, which is larger than the content.
+    const GRANTED: Size = Size { width: 4, height: 4 };
+
+    #[test]
+    fn content_fit_stretch() {
+        assert_eq!(
+            render(ContentFit::Stretch, GRANTED, Origin::default()),
+            Some(vec![
+                vec![1, 1, 2, 2],
+                vec![1, 1, 2, 2],
+                vec![3, 3, 4, 4],
+                vec![3, 3, 4, 4],
+            ])
+        );
+    }
+
+    #[test]
+    fn content_fit_letterbox() {
+        assert_eq!(
+            render(ContentFit::Letterbox, GRANTED, Origin::default()),
+            Some(vec![
+                vec![9, 9, 9, 9],
+                vec![9, 1, 2, 9],
+                vec![9, 3, 4, 9],
+                vec![9, 9, 9, 9],
+            ])
+        );
+        // Content larger than the surface is centered too.
+        let size = Size { width: 1, height: 2 };
+        assert_eq!(
+            render(ContentFit::Letterbox, size, Origin::default()),
+            Some(vec![vec![1], vec![3]])
+        );
+    }
+
+    #[test]
+    fn content_fit_crop() {
+        assert_eq!(
+            render(ContentFit::Crop, GRANTED, Origin::default()),
+            Some(vec![
+                vec![1, 2, 9, 9],
+                vec![3, 4, 9, 9],
+                vec![9, 9, 9, 9],
+                vec![9, 9, 9, 9],
+            ])
+        );
+        // The content offset anchors the region that's shown.
+        let size = Size { width: 1, height: 1 };
+        assert_eq!(
+            render(ContentFit::Crop, size, Origin { x: 1, y: 1 }),
+            Some(vec![vec![4]])
+        );
+    }
+
+    #[test]
+    fn content_fit_none() {
+        assert_eq!(render(ContentFit::None, GRANTED, Origin::default()), None);
+        let size = Size { width: 2, height: 2 };
+        assert_eq!(
+            render(ContentFit::None, size, Origin::default()),
+            Some(vec![vec![1, 2], vec![3, 4]])
+        );
+    }
+
+    #[test]
+    fn content_fit_names() {
+        for name in ContentFit::NAMES {
+            assert_eq!(ContentFit::from_name(name).map(ContentFit::name), Some(*name));
+        }
+        assert_eq!(ContentFit::from_name("fill"), None);
+    }
+}
@@ -0,0 +1,331 @@
+//! Damage of drawables nested in each other, in the buffer coordinates of
+//! the surface that owns the damaged pixels.
+//!
+//! Every surface has a size and, when it's nested, an offset within its
+//! parent and a clip rectangle in its own coordinates. A surface shows the
+//! pixels within its bounds and its clip rectangle, as far as they are
+//! within its parent.
+//!
+//! A pixel of a parent is owned by the topmost child that shows it, where
+//! children added later are above those added earlier, and otherwise by the
+//! parent. Damage is only ever uploaded by the owner of the pixel, so no
+//! pixel is uploaded twice.
+
+use crate::area::{Area, Origin, Size};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct SurfaceId(usize);
+
+#[derive(Debug)]
+struct Node {
+    children: Vec<SurfaceId>,
+    /// Where the surface is in its parent.
+    offset: Origin,
+    size: Size,
+    clip: Option<Area>,
+    /// Rectangles in the buffer of the surface that don't overlap.
+    damage: Vec<Area>
+}
+
+impl Node {
+    fn new(offset: Origin, size: Size) -> Self {
+        Node {
+            children: Vec::new(),
+            offset,
+            size,
+            clip: None,
+            damage: Vec::new()
+        }
+    }
+
+    /// The part of the surface that is shown, in its own coordinates.
+    fn visible(&self) -> Option<Area> {
+        let bounds: Area = self.size.into();
+        match self.clip {
+            Some(clip) => bounds.intersection(clip),
+            None if self.size.width > 0 && self.size.height > 0 => Some(bounds),
+            None => None
+        }
+    }
+}
+
+/// A drawable and the drawables nested in it.
+#[derive(Debug)]
+pub struct DamageTree {
+    nodes: Vec<Node>
+}
+
+impl Default for DamageTree {
+    fn default() -> Self {
+        DamageTree::new(Size::default())
+    }
+}
+
+impl DamageTree {
+    pub fn new(size: Size) -> Self {
+        DamageTree {
+            nodes: vec![Node::new(Origin::default(), size)]
+        }
+    }
+
+    pub fn root(&self) -> SurfaceId {
+        SurfaceId(0)
+    }
+
+    /// Nests a surface of `size` at `offset` in `parent`, above the
+    /// surfaces nested in it before.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn add_child(&mut self, parent: SurfaceId, offset: Origin, size: Size) -> SurfaceId {
+        let id = SurfaceId(self.nodes.len());
+        self.nodes.push(Node::new(offset, size));
+        self.nodes[parent.0].children.push(id);
+        id
+    }
+
+    /// Sets the part of the surface that is shown, in its own coordinates.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn set_clip(&mut self, id: SurfaceId, clip: Option<Area>) {
+        self.nodes[id.0].clip = clip;
+    }
+
+    /// Moves a nested surface within its parent.
+    ///
+    /// Neither buffer changes, the compositor moves the content, so nothing
+    /// is damaged.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn move_to(&mut self, id: SurfaceId, offset: Origin) {
+        self.nodes[id.0].offset = offset;
+    }
+
+    /// Resizes the surface, which has no content yet at the new size.
+    pub fn resize(&mut self, id: SurfaceId, size: Size) {
+        let node = &mut self.nodes[id.0];
+        node.size = size;
+        node.damage.clear();
+    }
+
+    /// Damages `rect`, in the coordinates of the surface `id`.
+    ///
+    /// The damage is clipped to what the surface shows, and the parts that
+    /// nested surfaces show are damaged in those instead.
+    pub fn add_damage(&mut self, id: SurfaceId, rect: Area) {
+        let visible = match self.nodes[id.0]
+            .visible()
+            .and_then(|visible| visible.intersection(rect))
+        {
+            Some(visible) => visible,
+            None => return
+        };
+        let mut pieces = vec![visible];
+        let mut forwarded = Vec::new();
+        for &child in self.nodes[id.0].children.iter().rev() {
+            let node = &self.nodes[child.0];
+            let shown = match node.visible() {
+                Some(shown) => shown.translate(node.offset),
+                None => continue
+            };
+            let mut rest = Vec::new();
+            for piece in pieces {
+                match piece.intersection(shown) {
+                    Some(overlap) => {
+                        let back = Origin {
+                            x: -node.offset.x,
+                            y: -node.offset.y
+                        };
+                        forwarded.push((child, overlap.translate(back)));
+                        rest.extend(piece.subtract(overlap));
+                    },
+                    None => rest.push(piece)
+                }
+            }
+            pieces = rest;
+        }
+        for piece in pieces {
+            self.add_own_damage(id, piece);
+        }
+        for (child, rect) in forwarded {
+            self.add_damage(child, rect);
+        }
+    }
+
+    /// Adds the parts of `rect` that aren't damaged yet.
+    fn add_own_damage(&mut self, id: SurfaceId, rect: Area) {
+        let damage = &mut self.nodes[id.0].damage;
+        let mut pieces = vec![rect];
+        for &existing in damage.iter() {
+            pieces = pieces
+                .into_iter()
+                .flat_map(|piece| piece.subtract(existing))
+                .collect();
+        }
+        damage.extend(pieces);
+    }
+
+    /// The damaged rectangles of the surface, which don't overlap.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn damage(&self, id: SurfaceId) -> &[Area] {
+        &self.nodes[id.0].damage
+    }
+
+    /// Moves the damage of the surface into `damage`, which is cleared
+    /// first. The surface keeps the vector `damage` had, so taking the
+    /// damage every frame doesn't allocate once the vectors are large
+    /// enough.
+    pub fn take_damage_into(&mut self, id: SurfaceId, damage: &mut Vec<Area>) {
+        damage.clear();
+        std::mem::swap(&mut self.nodes[id.0].damage, damage);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rect(x: i32, y: i32, width: u32, height: u32) -> Area {
+        Area {
+            origin: Origin { x, y },
+            size: Size { width, height }
+        }
+    }
+
+    /// How many bytes of ARGB32 pixels uploading `rects` copies.
+    fn upload_bytes(rects: &[Area]) -> usize {
+        rects
+            .iter()
+            .map(|rect| rect.size.width as usize * rect.size.height as usize * 4)
+            .sum()
+    }
+
+    fn sorted(rects: &[Area]) -> Vec<Area> {
+        let mut rects = rects.to_vec();
+        rects.sort();
+        rects
+    }
+
+    /// A bar with a graph nested in it, which has a tooltip-like marker
+    /// nested in it.
+    fn nesting() -> (DamageTree, SurfaceId, SurfaceId, SurfaceId) {
+        let mut tree = DamageTree::new(Size {
+            width: 1000,
+            height: 30
+        });
+        let bar = tree.root();
+        let graph = tree.add_child(
+            bar,
+            Origin { x: 600, y: 0 },
+            Size {
+                width: 300,
+                height: 30
+            }
+        );
+        let marker = tree.add_child(
+            graph,
+            Origin { x: 50, y: 5 },
+            Size {
+                width: 100,
+                height: 20
+            }
+        );
+        (tree, bar, graph, marker)
+    }
+
+    #[test]
+    fn damage_straddling_nesting() {
+        let (mut tree, bar, graph, marker) = nesting();
+        tree.add_damage(bar, rect(550, 0, 200, 30));
+        assert_eq!(tree.damage(bar), &[rect(550, 0, 50, 30)]);
+        assert_eq!(
+            sorted(tree.damage(graph)),
+            vec![rect(0, 0, 150, 5), rect(0, 5, 50, 20), rect(0, 25, 150, 5),]
+        );
+        assert_eq!(tree.damage(marker), &[rect(0, 0, 100, 20)]);
+        // Every damaged pixel is uploaded once, by its owner.
+        let bytes: Vec<usize> = [bar, graph, marker]
+            .iter()
+            .map(|&id| upload_bytes(tree.damage(id)))
+            .collect();
+        assert_eq!(
+            bytes,
+            vec![50 * 30 * 4, (2 * 150 * 5 + 50 * 20) * 4, 100 * 20 * 4]
+        );
+        assert_eq!(bytes.iter().sum::<usize>(), 200 * 30 * 4);
+    }
+
+    #[test]
+    fn damage_translated_and_clipped() {
+        let (mut tree, bar, graph, marker) = nesting();
+        // Damage of the graph in its own coordinates, partly outside of it.
+        tree.add_damage(graph, rect(250, -10, 100, 20));
+        assert_eq!(tree.damage(graph), &[rect(250, 0, 50, 10)]);
+        assert_eq!(tree.damage(bar), &[]);
+        // Damage of the marker, partly below it.
+        tree.add_damage(marker, rect(90, 15, 20, 20));
+        assert_eq!(tree.damage(marker), &[rect(90, 15, 10, 5)]);
+        // Damage that was already there isn't added twice.
+        tree.add_damage(graph, rect(250, 0, 50, 10));
+        assert_eq!(upload_bytes(tree.damage(graph)), 50 * 10 * 4);
+        let mut taken = vec![rect(0, 0, 1, 1)];
+        tree.take_damage_into(graph, &mut taken);
+        assert_eq!(taken, vec![rect(250, 0, 50, 10)]);
+        assert_eq!(tree.damage(graph), &[]);
+    }
+
+    #[test]
+    fn damage_clip_and_stacking() {
+        let (mut tree, bar, graph, marker) = nesting();
+        // The graph only shows its left half, the bar owns the rest.
+        tree.set_clip(graph, Some(rect(0, 0, 150, 30)));
+        tree.add_damage(bar, rect(700, 0, 150, 30));
+        assert_eq!(tree.damage(bar), &[rect(750, 0, 100, 30)]);
+        assert_eq!(
+            upload_bytes(tree.damage(graph)) + upload_bytes(tree.damage(marker)),
+            50 * 30 * 4
+        );
+        // A child added later is above the earlier ones where they overlap.
+        let mut tree = DamageTree::new(Size {
+            width: 100,
+            height: 10
+        });
+        let root = tree.root();
+        let below = tree.add_child(
+            root,
+            Origin { x: 0, y: 0 },
+            Size {
+                width: 60,
+                height: 10
+            }
+        );
+        let above = tree.add_child(
+            root,
+            Origin { x: 40, y: 0 },
+            Size {
+                width: 60,
+                height: 10
+            }
+        );
+        tree.add_damage(root, rect(0, 0, 100, 10));
+        assert_eq!(tree.damage(root), &[]);
+        assert_eq!(tree.damage(below), &[rect(0, 0, 40, 10)]);
+        assert_eq!(tree.damage(above), &[rect(0, 0, 60, 10)]);
+    }
+
+    #[test]
+    fn damage_after_move() {
+        let (mut tree, bar, graph, marker) = nesting();
+        tree.move_to(graph, Origin { x: 0, y: 0 });
+        for &id in &[bar, graph, marker] {
+            assert_eq!(tree.damage(id), &[]);
+        }
+        tree.add_damage(bar, rect(550, 0, 200, 30));
+        assert_eq!(tree.damage(bar), &[rect(550, 0, 200, 30)]);
+        assert_eq!(tree.damage(graph), &[]);
+        tree.resize(
+            bar,
+            Size {
+                width: 10,
+                height: 10
+            }
+        );
+        assert_eq!(tree.damage(bar), &[]);
+    }
+}
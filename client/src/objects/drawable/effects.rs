@@ -0,0 +1,517 @@
+//! Effects applied to the content of a drawable on its way to the buffer.
+//!
+//! A drawin can have a list of built-in effects, which are applied in order
+//! to the pixels copied from the surface, so themes can shade a popup or
+//! dim a bar without repainting it in Lua every frame:
+//!
+//! - `"shadow:<radius>:<opacity>"` draws a black drop shadow around the
+//!   content, blurred over `radius` units. The buffer grows by the radius on
+//!   every side, which is the extent of the effect.
+//! - `"dim:<amount>"` darkens the content, `1` making it black.
+//! - `"desaturate:<amount>"` takes the color out of the content, `1` making
+//!   it grey.
+//!
+//! Colors are mixed in linear light, since mixing the sRGB values Cairo
+//! stores darkens and shifts them. The transfer function between the
+//! values and light is the one of the drawable's color profile.
+//!
+//! The surface is kept as Lua painted it, so changing the effects only
+//! applies them again.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::area::Size;
+use crate::common::color_profile::{Tables, TransferFunction};
+
+/// The largest shadow radius, in units of the surface.
+pub const MAX_SHADOW_RADIUS: u32 = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Effect {
+    Shadow { radius: u32, opacity: f32 },
+    Dim(f32),
+    Desaturate(f32)
+}
+
+impl Effect {
+    /// The effect described by `key`, like `"shadow:8:0.5"`.
+    pub fn parse(key: &str) -> Result<Effect, String> {
+        let mut parts = key.split(':');
+        let name = parts.next().unwrap_or_default();
+        let args: Vec<&str> = parts.collect();
+        let amount = |arg: &str| {
+            arg.parse::<f32>()
+                .ok()
+                .filter(|amount| (0.0..=1.0).contains(amount))
+        };
+        let effect = match (name, args.as_slice()) {
+            ("shadow", [radius, opacity]) => radius
+                .parse()
+                .ok()
+                .filter(|radius| *radius <= MAX_SHADOW_RADIUS)
+                .and_then(|radius| amount(opacity).map(|opacity| Effect::Shadow { radius, opacity })),
+            ("dim", [amount_]) => amount(amount_).map(Effect::Dim),
+            ("desaturate", [amount_]) => amount(amount_).map(Effect::Desaturate),
+            _ => None
+        };
+        effect.ok_or_else(|| {
+            format!(
+                "invalid effect \"{}\", expected \"shadow:<radius up to {}>:<opacity>\", \
+                 \"dim:<amount>\" or \"desaturate:<amount>\", with amounts from 0 to 1",
+                key, MAX_SHADOW_RADIUS
+            )
+        })
+    }
+
+    /// How far the effect grows the content on every side, in units of the
+    /// surface.
+    pub fn extent(self) -> u32 {
+        match self {
+            Effect::Shadow { radius, .. } => radius,
+            _ => 0
+        }
+    }
+}
+
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Effect::Shadow { radius, opacity } => write!(f, "shadow:{}:{}", radius, opacity),
+            Effect::Dim(amount) => write!(f, "dim:{}", amount),
+            Effect::Desaturate(amount) => write!(f, "desaturate:{}", amount)
+        }
+    }
+}
+
+/// What applying an effect cost so far.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct EffectStats {
+    pub runs: u64,
+    /// The pixels the effect produced, over all runs.
+    pub pixels: u64,
+    pub time: Duration
+}
+
+/// ARGB32 pixels, tightly packed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pixels {
+    pub data: Vec<u8>,
+    pub size: Size
+}
+
+/// The effects of a drawable, in the order they're applied.
+#[derive(Debug, Default)]
+pub struct Effects {
+    effects: Vec<Effect>,
+    stats: Vec<EffectStats>,
+    /// How the values of the pixels encode light.
+    transfer: TransferFunction
+}
+
+impl Effects {
+    pub fn set(&mut self, effects: Vec<Effect>) {
+        self.stats = vec![EffectStats::default(); effects.len()];
+        self.effects = effects;
+    }
+
+    pub fn set_transfer_function(&mut self, transfer: TransferFunction) {
+        self.transfer = transfer;
+    }
+
+    pub fn list(&self) -> &[Effect] {
+        &self.effects
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty()
+    }
+
+    /// How far the effects grow the content on every side, in units of the
+    /// surface.
+    pub fn extent(&self) -> u32 {
+        self.effects.iter().map(|effect| effect.extent()).sum()
+    }
+
+    /// The effects and what each cost so far.
+    pub fn stats(&self) -> Vec<(Effect, EffectStats)> {
+        self.effects
+            .iter()
+            .cloned()
+            .zip(self.stats.iter().cloned())
+            .collect()
+    }
+
+    /// Applies the effects to `pixels`, for content shown at `scale`.
+    pub fn apply(&mut self, mut pixels: Pixels, scale: i32) -> Pixels {
+        let tables = self.transfer.tables();
+        for (effect, stats) in self.effects.iter().zip(self.stats.iter_mut()) {
+            let start = Instant::now();
+            pixels = match *effect {
+                Effect::Shadow { radius, opacity } => shadow(&pixels, radius * scale as u32, opacity),
+                Effect::Dim(amount) => {
+                    let keep = 1.0 - amount;
+                    map_linear(&mut pixels.data, tables, |[r, g, b]| {
+                        [r * keep, g * keep, b * keep]
+                    });
+                    pixels
+                },
+                Effect::Desaturate(amount) => {
+                    map_linear(&mut pixels.data, tables, |[r, g, b]| {
+                        let luminance = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+                        let mix = |value: f32| value + (luminance - value) * amount;
+                        [mix(r), mix(g), mix(b)]
+                    });
+                    pixels
+                }
+            };
+            stats.runs += 1;
+            stats.pixels += u64::from(pixels.size.width) * u64::from(pixels.size.height);
+            stats.time += start.elapsed();
+        }
+        pixels
+    }
+}
+
+/// Makes the content `opacity` times as opaque, from 0 to 1. Cairo
+/// premultiplies the channels by the alpha, so all four are scaled.
+pub fn fade(data: &mut [u8], opacity: f64) {
+    let opacity = (opacity.max(0.0).min(1.0) * 255.0).round() as u32;
+    for byte in data.iter_mut() {
+        *byte = ((u32::from(*byte) * opacity + 127) / 255) as u8;
+    }
+}
+
+/// Replaces the color of every pixel with `f` of it in linear light,
+/// leaving the alpha alone.
+fn map_linear<F: Fn([f32; 3]) -> [f32; 3]>(data: &mut [u8], tables: &Tables, f: F) {
+    for pixel in data.chunks_exact_mut(4) {
+        let argb = u32::from_ne_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+        let alpha = argb >> 24;
+        if alpha == 0 {
+            continue;
+        }
+        // Cairo premultiplies the channels by the alpha.
+        let channel = |shift: u32| {
+            let premultiplied = (argb >> shift) & 0xff;
+            tables.to_linear(((premultiplied * 255 + alpha / 2) / alpha).min(255) as u8)
+        };
+        let mapped = f([channel(16), channel(8), channel(0)]);
+        let channel = |linear: f32| (u32::from(tables.from_linear(linear)) * alpha + 127) / 255;
+        let argb = alpha << 24 | channel(mapped[0]) << 16 | channel(mapped[1]) << 8 | channel(mapped[2]);
+        pixel.copy_from_slice(&argb.to_ne_bytes());
+    }
+}
+
+/// The content over a black shadow of its alpha blurred over `radius`
+/// pixels, in a buffer grown by `radius` on every side.
+fn shadow(pixels: &Pixels, radius: u32, opacity: f32) -> Pixels {
+    let Size { width, height } = pixels.size;
+    let size = Size {
+        width: width + 2 * radius,
+        height: height + 2 * radius
+    };
+    let (radius, src_width, out_width, out_height) = (
+        radius as usize,
+        width as usize,
+        size.width as usize,
+        size.height as usize
+    );
+    let mut alpha = vec![0.0f32; out_width * out_height];
+    for (y, row) in pixels.data.chunks_exact(src_width * 4).enumerate() {
+        let start = (y + radius) * out_width + radius;
+        for (alpha, pixel) in alpha[start..start + src_width]
+            .iter_mut()
+            .zip(row.chunks_exact(4))
+        {
+            let argb = u32::from_ne_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+            *alpha = (argb >> 24) as f32;
+        }
+    }
+    if radius > 0 {
+        let kernel = gaussian(radius);
+        let mut blurred = vec![0.0f32; alpha.len()];
+        blur_rows(&alpha, &mut blurred, out_width, &kernel);
+        blur_columns(&blurred, &mut alpha, out_width, &kernel);
+    }
+    let mut data = Vec::with_capacity(out_width * out_height * 4);
+    for shadow in alpha.iter() {
+        let argb = ((shadow * opacity).round().max(0.0).min(255.0) as u32) << 24;
+        data.extend_from_slice(&argb.to_ne_bytes());
+    }
+    // The content over the shadow, whose color is black.
+    for (y, row) in pixels.data.chunks_exact(src_width * 4).enumerate() {
+        let start = ((y + radius) * out_width + radius) * 4;
+        for (out, pixel) in data[start..start + src_width * 4]
+            .chunks_exact_mut(4)
+            .zip(row.chunks_exact(4))
+        {
+            let argb = u32::from_ne_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+            let shadow = u32::from_ne_bytes([out[0], out[1], out[2], out[3]]) >> 24;
+            let alpha = argb >> 24;
+            let alpha = alpha + (shadow * (255 - alpha) + 127) / 255;
+            out.copy_from_slice(&(argb & 0x00ff_ffff | alpha << 24).to_ne_bytes());
+        }
+    }
+    Pixels { data, size }
+}
+
+/// The weights of a normalized gaussian kernel reaching `radius` pixels to
+/// either side, which covers three standard deviations.
+fn gaussian(radius: usize) -> Vec<f32> {
+    let sigma = radius as f32 / 3.0;
+    let weights: Vec<f32> = (0..=2 * radius)
+        .map(|i| {
+            let x = i as f32 - radius as f32;
+            (-x * x / (2.0 * sigma * sigma)).exp()
+        })
+        .collect();
+    let sum: f32 = weights.iter().sum();
+    weights.into_iter().map(|weight| weight / sum).collect()
+}
+
+/// Blurs each row of `src`, which is `width` values long, with `kernel`.
+/// Values past the ends count as 0.
+fn blur_rows(src: &[f32], dest: &mut [f32], width: usize, kernel: &[f32]) {
+    let radius = kernel.len() / 2;
+    for (src, dest) in src.chunks_exact(width).zip(dest.chunks_exact_mut(width)) {
+        for value in dest.iter_mut() {
+            *value = 0.0;
+        }
+        for (i, &weight) in kernel.iter().enumerate() {
+            // Whole rows are shifted and added at once, which vectorizes.
+            if i >= radius && i - radius < width {
+                let shift = i - radius;
+                add_weighted(&mut dest[..width - shift], &src[shift..], weight);
+            } else if i < radius && radius - i < width {
+                let shift = radius - i;
+                add_weighted(&mut dest[shift..], &src[..width - shift], weight);
+            }
+        }
+    }
+}
+
+/// Blurs each column of `src`, which has rows `width` values long, with
+/// `kernel`. Values past the ends count as 0.
+fn blur_columns(src: &[f32], dest: &mut [f32], width: usize, kernel: &[f32]) {
+    let radius = kernel.len() / 2;
+    let height = src.len() / width;
+    for (y, dest) in dest.chunks_exact_mut(width).enumerate() {
+        for value in dest.iter_mut() {
+            *value = 0.0;
+        }
+        for (i, &weight) in kernel.iter().enumerate() {
+            let from = y + i;
+            if from >= radius && from - radius < height {
+                let start = (from - radius) * width;
+                add_weighted(dest, &src[start..start + width], weight);
+            }
+        }
+    }
+}
+
+fn add_weighted(dest: &mut [f32], src: &[f32], weight: f32) {
+    for (dest, src) in dest.iter_mut().zip(src) {
+        *dest += src * weight;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn pixel(a: u8, r: u8, g: u8, b: u8) -> [u8; 4] {
+        (u32::from(a) << 24 | u32::from(r) << 16 | u32::from(g) << 8 | u32::from(b)).to_ne_bytes()
+    }
+
+    fn image(width: u32, height: u32, fill: [u8; 4]) -> Pixels {
+        Pixels {
+            data: fill.repeat((width * height) as usize),
+            size: Size { width, height }
+        }
+    }
+
+    fn apply(keys: &[&str], pixels: Pixels) -> Pixels {
+        let mut effects = Effects::default();
+        effects.set(keys.iter().map(|key| Effect::parse(key).unwrap()).collect());
+        effects.apply(pixels, 1)
+    }
+
+    fn argb_at(pixels: &Pixels, x: u32, y: u32) -> u32 {
+        let i = ((y * pixels.size.width + x) * 4) as usize;
+        u32::from_ne_bytes([
+            pixels.data[i],
+            pixels.data[i + 1],
+            pixels.data[i + 2],
+            pixels.data[i + 3]
+        ])
+    }
+
+    fn alpha_at(pixels: &Pixels, x: u32, y: u32) -> u8 {
+        (argb_at(pixels, x, y) >> 24) as u8
+    }
+
+    #[test]
+    fn effects_fade() {
+        let white = 0xffff_ffff_u32.to_ne_bytes();
+        let half_red = 0x8080_0000_u32.to_ne_bytes();
+        let mut data = [white, half_red].concat();
+        fade(&mut data, 1.0);
+        assert_eq!(data, [white, half_red].concat());
+        fade(&mut data, 0.5);
+        let faded = |pixel: usize| {
+            u32::from_ne_bytes([
+                data[pixel * 4],
+                data[pixel * 4 + 1],
+                data[pixel * 4 + 2],
+                data[pixel * 4 + 3]
+            ])
+        };
+        assert_eq!(faded(0), 0x8080_8080);
+        // Still premultiplied, no channel is above the alpha.
+        assert_eq!(faded(1), 0x4040_0000);
+        fade(&mut data, 0.0);
+        assert!(data.iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn effects_parse() {
+        assert_eq!(
+            Effect::parse("shadow:8:0.5"),
+            Ok(Effect::Shadow {
+                radius: 8,
+                opacity: 0.5
+            })
+        );
+        assert_eq!(Effect::parse("dim:0.3"), Ok(Effect::Dim(0.3)));
+        assert_eq!(Effect::parse("desaturate:1"), Ok(Effect::Desaturate(1.0)));
+        for bad in &[
+            "shadow:8",
+            "shadow:65:0.5",
+            "shadow:-1:0.5",
+            "dim:1.5",
+            "dim",
+            "blur:4",
+            ""
+        ] {
+            assert!(Effect::parse(bad).is_err(), "{} was accepted", bad);
+        }
+        assert_eq!(Effect::parse("shadow:8:0.5").unwrap().to_string(), "shadow:8:0.5");
+    }
+
+    #[test]
+    fn effects_dim() {
+        // Half as much light as white, which is brighter than half of 255.
+        let dimmed = apply(&["dim:0.5"], image(2, 1, pixel(255, 255, 255, 255)));
+        assert_eq!(dimmed, image(2, 1, pixel(255, 188, 188, 188)));
+        // Premultiplied content keeps its alpha and stays premultiplied.
+        let dimmed = apply(&["dim:0.5"], image(1, 1, pixel(128, 128, 0, 64)));
+        assert_eq!(dimmed, image(1, 1, pixel(128, 94, 0, 46)));
+        let dimmed = apply(&["dim:1"], image(1, 1, pixel(255, 10, 200, 30)));
+        assert_eq!(dimmed, image(1, 1, pixel(255, 0, 0, 0)));
+        assert_eq!(apply(&["dim:0.5"], image(1, 1, [0; 4])), image(1, 1, [0; 4]));
+    }
+
+    #[test]
+    fn effects_desaturate() {
+        // Red has the luminance of a dark grey.
+        let grey = apply(&["desaturate:1"], image(1, 1, pixel(255, 255, 0, 0)));
+        assert_eq!(grey, image(1, 1, pixel(255, 127, 127, 127)));
+        let grey = apply(&["desaturate:1"], image(1, 1, pixel(255, 0, 255, 0)));
+        assert_eq!(grey, image(1, 1, pixel(255, 220, 220, 220)));
+        let unchanged = apply(&["desaturate:0"], image(1, 1, pixel(255, 12, 34, 56)));
+        assert_eq!(unchanged, image(1, 1, pixel(255, 12, 34, 56)));
+    }
+
+    #[test]
+    fn effects_shadow() {
+        let content = pixel(255, 40, 80, 120);
+        let shadowed = apply(&["shadow:3:1"], image(4, 2, content));
+        assert_eq!(shadowed.size, Size { width: 10, height: 8 });
+        // The content is unchanged in the middle.
+        for y in 3..5 {
+            for x in 3..7 {
+                let i = ((y * 10 + x) * 4) as usize;
+                assert_eq!(&shadowed.data[i..i + 4], &content);
+            }
+        }
+        // The shadow is black, fades out from the content and is
+        // symmetric.
+        for y in 0..8 {
+            for x in 0..10 {
+                if (3..7).contains(&x) && (3..5).contains(&y) {
+                    continue;
+                }
+                assert_eq!(argb_at(&shadowed, x, y) & 0x00ff_ffff, 0);
+                assert_eq!(alpha_at(&shadowed, x, y), alpha_at(&shadowed, 9 - x, 7 - y));
+            }
+        }
+        assert!(alpha_at(&shadowed, 2, 3) > alpha_at(&shadowed, 1, 3));
+        assert!(alpha_at(&shadowed, 1, 3) > alpha_at(&shadowed, 0, 3));
+        assert!(alpha_at(&shadowed, 0, 0) < alpha_at(&shadowed, 0, 3));
+        assert!(alpha_at(&shadowed, 2, 2) > 0);
+        // Half as opaque at half the opacity.
+        let faint = apply(&["shadow:3:0.5"], image(4, 2, content));
+        let half = (f32::from(alpha_at(&shadowed, 2, 3)) / 2.0).round() as i32;
+        assert!((i32::from(alpha_at(&faint, 2, 3)) - half).abs() <= 1);
+        // A shadow without a radius only darkens under translucent content.
+        let sharp = apply(&["shadow:0:1"], image(1, 1, pixel(128, 64, 0, 0)));
+        assert_eq!(sharp, image(1, 1, pixel(192, 64, 0, 0)));
+    }
+
+    #[test]
+    fn effects_stacked() {
+        let content = pixel(255, 255, 0, 0);
+        let stacked = apply(&["desaturate:1", "shadow:2:0.5", "dim:0.5"], image(2, 2, content));
+        assert_eq!(stacked.size, Size { width: 6, height: 6 });
+        // Desaturated, then dimmed.
+        let i = ((2 * 6 + 2) * 4) as usize;
+        assert_eq!(&stacked.data[i..i + 4], &pixel(255, 92, 92, 92));
+        // Dimming leaves the black shadow as it was.
+        let shadowed = apply(&["desaturate:1", "shadow:2:0.5"], image(2, 2, content));
+        assert_eq!(alpha_at(&stacked, 0, 2), alpha_at(&shadowed, 0, 2));
+        // The extents of stacked shadows add up.
+        let mut effects = Effects::default();
+        effects.set(vec![
+            Effect::parse("shadow:2:0.5").unwrap(),
+            Effect::parse("shadow:3:0.5").unwrap(),
+            Effect::Dim(0.1),
+        ]);
+        assert_eq!(effects.extent(), 5);
+        assert_eq!(
+            effects.apply(image(1, 1, content), 2).size,
+            Size {
+                width: 21,
+                height: 21
+            }
+        );
+        let stats = effects.stats();
+        assert_eq!(stats.len(), 3);
+        assert_eq!(stats[0].1.runs, 1);
+        assert_eq!(stats[0].1.pixels, 9 * 9);
+        assert_eq!(stats[2].1.pixels, 21 * 21);
+    }
+
+    /// The cost of an 8 pixel shadow around a 400x300 popup, which has to
+    /// fit in a frame at 60Hz. Unoptimized builds are bounded loosely, so it
+    /// catches the blur becoming quadratic rather than a slow machine.
+    #[test]
+    fn effects_shadow_cost() {
+        let mut effects = Effects::default();
+        effects.set(vec![Effect::parse("shadow:8:0.5").unwrap()]);
+        let content = image(400, 300, pixel(255, 30, 30, 30));
+        for _ in 0..3 {
+            effects.apply(content.clone(), 1);
+        }
+        let stats = effects.stats()[0].1;
+        assert_eq!(stats.runs, 3);
+        let bound = if cfg!(debug_assertions) { 1000 } else { 16 };
+        assert!(
+            stats.time / 3 < Duration::from_millis(bound),
+            "a frame took {:?}",
+            stats.time / 3
+        );
+    }
+}
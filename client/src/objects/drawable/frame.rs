@@ -0,0 +1,377 @@
+//! The part of a refresh that runs for every frame: copying what changed
+//! into the Wayland buffer.
+//!
+//! Animations run it many times a second, so once the sizes settled a
+//! frame that copies the damaged parts straight from the surface mustn't
+//! allocate, and copies only as many bytes as the damage covers. The
+//! vectors it needs are kept from one frame to the next. Fitting the
+//! content or applying effects makes pixels of their own every frame, so
+//! those frames allocate a few times and copy all of the buffer.
+//!
+//! The tests check this with the counting allocator, see `allocations`.
+//! The same counts are kept for every drawable, see `drawable:frame_stats()`,
+//! so a running client can be checked too.
+
+use std::io;
+
+use crate::area::{Area, Origin, Size};
+
+/// Where a frame is copied to.
+pub trait Target {
+    /// Copies all of `data` in, as `Buffer::write` does.
+    fn write(&mut self, data: &[u8], stride: usize, offset: Origin) -> io::Result<()>;
+
+    /// Copies `rects` of `data` in, as `Buffer::write_rects` does.
+    fn write_rects(&mut self, data: &[u8], stride: usize, offset: Origin, rects: &[Area]) -> io::Result<()>;
+}
+
+/// The pixels a frame is copied from, with rows `stride` bytes long. The
+/// pixel at `offset` ends up in the top left corner of the buffer.
+pub struct Source<'a> {
+    pub data: &'a [u8],
+    pub stride: usize,
+    pub offset: Origin
+}
+
+/// Where the damage of the surface is in the buffer: scaled up by the
+/// scale of the variant, moved by the content offset and grown on every
+/// side by how far the effects spread it.
+#[derive(Debug, Clone, Copy)]
+pub struct Placement {
+    pub scale: i32,
+    pub offset: Origin,
+    pub extent: i32
+}
+
+/// The vectors a frame needs, kept for the next one.
+#[derive(Debug, Default)]
+pub struct Scratch {
+    /// The damage of the surface, taken for the frame.
+    pub damage: Vec<Area>,
+    /// The parts of the buffer the frame wrote, if only the damage was.
+    pub rects: Vec<Area>
+}
+
+impl Scratch {
+    /// The memory the vectors keep.
+    pub fn bytes(&self) -> u64 {
+        ((self.damage.capacity() + self.rects.capacity()) * std::mem::size_of::<Area>()) as u64
+    }
+
+    /// Gives back the memory the vectors keep beyond what's in them,
+    /// returning how much, so the next frame allocates again.
+    pub fn shrink(&mut self) -> u64 {
+        let before = self.bytes();
+        self.damage.shrink_to_fit();
+        self.rects.shrink_to_fit();
+        before - self.bytes()
+    }
+}
+
+/// The work done by frames, which `drawable:frame_stats()` returns.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct FrameStats {
+    pub frames: u64,
+    /// The frames that only copied the damage.
+    pub partial_frames: u64,
+    pub allocations: u64,
+    pub bytes_copied: u64,
+    pub last_allocations: u64,
+    pub last_bytes_copied: u64,
+    /// The refreshes that waited for the compositor to be done with the
+    /// last frame.
+    pub throttled_frames: u64,
+    /// The refreshes written along with one that waited.
+    pub coalesced_frames: u64
+}
+
+impl FrameStats {
+    pub fn record(&mut self, partial: bool, allocations: u64, bytes_copied: usize) {
+        self.frames += 1;
+        self.partial_frames += partial as u64;
+        self.allocations += allocations;
+        self.bytes_copied += bytes_copied as u64;
+        self.last_allocations = allocations;
+        self.last_bytes_copied = bytes_copied as u64;
+    }
+}
+
+/// Copies a frame into `target`, a buffer `size` pixels large.
+///
+/// With a placement only the damage in `scratch` is copied, and the parts
+/// of the buffer written are left in `scratch.rects`. Otherwise all of it
+/// is. Returns how many bytes were copied.
+pub fn copy<T: Target>(
+    target: &mut T,
+    size: Size,
+    source: Source,
+    placement: Option<Placement>,
+    scratch: &mut Scratch
+) -> io::Result<usize> {
+    scratch.rects.clear();
+    let placement = match placement {
+        Some(placement) => placement,
+        None => {
+            target.write(source.data, source.stride, source.offset)?;
+            return Ok(size.width as usize * size.height as usize * 4);
+        }
+    };
+    let Placement {
+        scale,
+        offset,
+        extent
+    } = placement;
+    let bounds: Area = size.into();
+    let back = Origin {
+        x: -offset.x,
+        y: -offset.y
+    };
+    let grown = scratch.damage.iter().filter_map(|rect| {
+        let rect = rect.scale(f64::from(scale)).translate(back);
+        let grown = Area {
+            origin: rect.origin,
+            size: Size {
+                width: rect.size.width + 2 * extent as u32,
+                height: rect.size.height + 2 * extent as u32
+            }
+        };
+        grown.intersection(bounds)
+    });
+    scratch.rects.extend(grown);
+    target.write_rects(source.data, source.stride, source.offset, &scratch.rects)?;
+    Ok(scratch
+        .rects
+        .iter()
+        .map(|rect| rect.size.width as usize * rect.size.height as usize * 4)
+        .sum())
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::content_fit::{fit_content, ContentFit, Image};
+    use super::super::damage::DamageTree;
+    use super::super::effects::{Effect, Effects, Pixels};
+    use super::*;
+    use crate::allocations::counted;
+
+    /// A buffer in memory, which counts what's copied into it.
+    struct Memory {
+        size: Size,
+        data: Vec<u8>,
+        copied: usize
+    }
+
+    impl Memory {
+        fn new(size: Size) -> Self {
+            Memory {
+                size,
+                data: vec![0; size.width as usize * size.height as usize * 4],
+                copied: 0
+            }
+        }
+    }
+
+    impl Target for Memory {
+        fn write(&mut self, data: &[u8], stride: usize, offset: Origin) -> io::Result<()> {
+            let rect: Area = self.size.into();
+            self.write_rects(data, stride, offset, &[rect])
+        }
+
+        fn write_rects(
+            &mut self,
+            data: &[u8],
+            stride: usize,
+            offset: Origin,
+            rects: &[Area]
+        ) -> io::Result<()> {
+            let row = self.size.width as usize * 4;
+            for rect in rects {
+                for y in rect.origin.y..rect.origin.y + rect.size.height as i32 {
+                    let len = rect.size.width as usize * 4;
+                    let src = (y + offset.y) as usize * stride + (rect.origin.x + offset.x) as usize * 4;
+                    let dest = y as usize * row + rect.origin.x as usize * 4;
+                    self.data[dest..dest + len].copy_from_slice(&data[src..src + len]);
+                    self.copied += len;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    const SIZE: Size = Size {
+        width: 400,
+        height: 100
+    };
+
+    fn rect(x: i32, y: i32, width: u32, height: u32) -> Area {
+        Area {
+            origin: Origin { x, y },
+            size: Size { width, height }
+        }
+    }
+
+    /// A bar with a clock that ticks every frame.
+    struct Bar {
+        surface: Vec<u8>,
+        damage: DamageTree,
+        scratch: Scratch,
+        target: Memory,
+        stats: FrameStats
+    }
+
+    impl Bar {
+        fn new() -> Self {
+            Bar {
+                surface: vec![0; SIZE.width as usize * SIZE.height as usize * 4],
+                damage: DamageTree::new(SIZE),
+                scratch: Scratch::default(),
+                target: Memory::new(SIZE),
+                stats: FrameStats::default()
+            }
+        }
+
+        /// Paints the clock and copies the frame, like a refresh does.
+        fn frame(&mut self, tick: u8, extent: i32) -> usize {
+            let clock = rect(360, 40, 30, 12);
+            for y in 40..52 {
+                let start = (y * SIZE.width as usize + 360) * 4;
+                for byte in &mut self.surface[start..start + 30 * 4] {
+                    *byte = tick;
+                }
+            }
+            let root = self.damage.root();
+            self.damage.add_damage(root, clock);
+            let damage = &mut self.damage;
+            let scratch = &mut self.scratch;
+            let target = &mut self.target;
+            let surface = &self.surface;
+            let (copied, allocations) = counted(|| {
+                damage.take_damage_into(root, &mut scratch.damage);
+                let source = Source {
+                    data: surface,
+                    stride: SIZE.width as usize * 4,
+                    offset: Origin::default()
+                };
+                let placement = Placement {
+                    scale: 1,
+                    offset: Origin::default(),
+                    extent
+                };
+                copy(target, SIZE, source, Some(placement), scratch).unwrap()
+            });
+            self.stats.record(true, allocations, copied);
+            copied
+        }
+    }
+
+    #[test]
+    fn frame_steady_state_allocates_nothing() {
+        let mut bar = Bar::new();
+        // The first frames size the vectors that are kept.
+        for tick in 0..3 {
+            bar.frame(tick, 0);
+        }
+        let before = bar.stats;
+        for tick in 3..100 {
+            let copied = bar.frame(tick, 0);
+            assert_eq!(bar.stats.last_allocations, 0, "allocated in frame {}", tick);
+            // Exactly the damage, the clock, is copied.
+            assert_eq!(copied, 30 * 12 * 4);
+        }
+        assert_eq!(bar.stats.allocations, before.allocations);
+        assert_eq!(bar.stats.frames, 100);
+        assert_eq!(bar.target.copied as u64, bar.stats.bytes_copied);
+        assert_eq!(&bar.target.data[..], &bar.surface[..]);
+        // A shadow grows what's copied around the damage, and nothing else.
+        let copied = bar.frame(100, 4);
+        assert_eq!(bar.stats.last_allocations, 0);
+        assert!(copied <= (30 + 8) * (12 + 8) * 4, "{}", copied);
+    }
+
+    #[test]
+    fn frame_full_copy() {
+        let surface = vec![7u8; SIZE.width as usize * SIZE.height as usize * 4];
+        let mut target = Memory::new(SIZE);
+        let mut scratch = Scratch::default();
+        scratch.rects.push(rect(0, 0, 1, 1));
+        let source = Source {
+            data: &surface,
+            stride: SIZE.width as usize * 4,
+            offset: Origin::default()
+        };
+        let (copied, allocations) = counted(|| copy(&mut target, SIZE, source, None, &mut scratch).unwrap());
+        assert_eq!(allocations, 0);
+        assert_eq!(copied, surface.len());
+        assert!(scratch.rects.is_empty());
+    }
+
+    #[test]
+    fn frame_redrawn_at_same_size() {
+        let mut target = Memory::new(SIZE);
+        let mut scratch = Scratch::default();
+        // Lua painted the whole surface again, at the same size, without
+        // adding damage.
+        for &color in &[7u8, 9] {
+            let surface = vec![color; SIZE.width as usize * SIZE.height as usize * 4];
+            let source = Source {
+                data: &surface,
+                stride: SIZE.width as usize * 4,
+                offset: Origin::default()
+            };
+            copy(&mut target, SIZE, source, None, &mut scratch).unwrap();
+            assert_eq!(&target.data[..], &surface[..]);
+        }
+    }
+
+    /// Fitting the content and the effects make pixels of their own, which
+    /// is allowed a few allocations a frame, but no more.
+    #[test]
+    fn frame_conversion_and_effect_allocations() {
+        let content = Size {
+            width: 200,
+            height: 50
+        };
+        let surface = vec![0x80u8; content.width as usize * content.height as usize * 4];
+        let mut target = Memory::new(SIZE);
+        let mut scratch = Scratch::default();
+        let mut effects = Effects::default();
+        effects.set(vec![Effect::parse("dim:0.5").unwrap()]);
+        let mut frame = |effects: &mut Effects| {
+            counted(|| {
+                let image = Image {
+                    data: &surface,
+                    stride: content.width as usize * 4,
+                    size: content
+                };
+                let data =
+                    fit_content(image, SIZE, ContentFit::Letterbox, Origin::default(), [0; 4]).unwrap();
+                let mut pixels = Pixels { data, size: SIZE };
+                if !effects.is_empty() {
+                    pixels = effects.apply(pixels, 1);
+                }
+                let source = Source {
+                    data: &pixels.data,
+                    stride: SIZE.width as usize * 4,
+                    offset: Origin::default()
+                };
+                copy(&mut target, SIZE, source, None, &mut scratch).unwrap()
+            })
+        };
+        let mut plain = Effects::default();
+        frame(&mut plain);
+        let (copied, allocations) = frame(&mut plain);
+        assert_eq!(copied, SIZE.width as usize * SIZE.height as usize * 4);
+        assert!(allocations <= 1, "fitting allocated {} times", allocations);
+        frame(&mut effects);
+        let (_, allocations) = frame(&mut effects);
+        assert!(allocations <= 2, "dimming allocated {} times", allocations);
+        let mut shadow = Effects::default();
+        shadow.set(vec![Effect::parse("shadow:4:0.5").unwrap()]);
+        let pixels = Pixels {
+            data: vec![0xff; SIZE.width as usize * SIZE.height as usize * 4],
+            size: SIZE
+        };
+        let (_, allocations) = counted(|| shadow.apply(pixels, 1));
+        assert!(allocations <= 8, "the shadow allocated {} times", allocations);
+    }
+}
@@ -0,0 +1,464 @@
+//! The input region of a drawable, from the alpha of its content.
+//!
+//! Pixels more opaque than a threshold take input, the rest let it through
+//! to whatever is below. Finding them means reading the pixels, which an
+//! animation that repaints the whole surface every frame would otherwise do
+//! every frame, so:
+//!
+//! - Only the damaged rows are read, and a damaged row that hashes the same
+//!   as when it was last read keeps the runs it had.
+//! - The region is only rebuilt from the runs when a row's runs changed.
+//! - While the content keeps changing it's scanned at most once every few
+//!   frames, and the frame it settles on is always scanned.
+
+use std::convert::TryInto;
+use std::time::{Duration, Instant};
+
+use crate::area::{Area, Origin, Size};
+
+use super::content_fit::Image;
+
+/// How many frames of changing content are scanned at most once, unless
+/// configured otherwise.
+pub const DEFAULT_SCAN_FRAMES: u32 = 4;
+
+/// How much scanning cost, to see what the caching saves.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct ScanStats {
+    pub scans: u64,
+    /// The rows whose runs had to be found again.
+    pub rows_scanned: u64,
+    /// The damaged rows that were unchanged and kept their runs.
+    pub rows_reused: u64,
+    pub time: Duration
+}
+
+#[derive(Debug, Clone)]
+struct Row {
+    hash: u64,
+    /// The columns at which the pixels take input, as [start, end).
+    runs: Vec<(u32, u32)>
+}
+
+#[derive(Debug)]
+pub struct AlphaRegion {
+    /// Pixels take input if their alpha is above this.
+    threshold: u8,
+    size: Size,
+    /// The runs of the rows, `None` until the row is first scanned.
+    rows: Vec<Option<Row>>,
+    /// The rows damaged since the last scan.
+    damaged: Vec<bool>,
+    /// The region of the last scan, in the coordinates of the image.
+    rects: Vec<Area>,
+    stats: ScanStats
+}
+
+impl AlphaRegion {
+    pub fn new(threshold: u8) -> Self {
+        AlphaRegion {
+            threshold,
+            size: Size::default(),
+            rows: Vec::new(),
+            damaged: Vec::new(),
+            rects: Vec::new(),
+            stats: ScanStats::default()
+        }
+    }
+
+    pub fn threshold(&self) -> u8 {
+        self.threshold
+    }
+
+    /// Sets the threshold, which changes the runs of every row.
+    pub fn set_threshold(&mut self, threshold: u8) {
+        if threshold == self.threshold {
+            return;
+        }
+        self.threshold = threshold;
+        for row in self.rows.iter_mut() {
+            *row = None;
+        }
+        self.damage_all();
+    }
+
+    /// Damages the rows `rect` covers, in the coordinates of the image.
+    pub fn damage(&mut self, rect: Area) {
+        let height = self.damaged.len() as i64;
+        let top = (rect.origin.y as i64).max(0).min(height);
+        let bottom = (rect.origin.y as i64 + rect.size.height as i64)
+            .max(0)
+            .min(height);
+        for damaged in &mut self.damaged[top as usize..bottom as usize] {
+            *damaged = true;
+        }
+    }
+
+    pub fn damage_all(&mut self) {
+        for damaged in self.damaged.iter_mut() {
+            *damaged = true;
+        }
+    }
+
+    /// Reads the damaged rows of `image`, returning the region where its
+    /// pixels take input.
+    ///
+    /// An image of another size than the last one is read entirely.
+    pub fn scan(&mut self, image: &Image) -> &[Area] {
+        let start = Instant::now();
+        let Size { width, height } = image.size;
+        if image.size != self.size {
+            self.size = image.size;
+            self.rows = vec![None; height as usize];
+            self.damaged = vec![true; height as usize];
+        }
+        let row_len = width as usize * 4;
+        let mut changed = false;
+        for y in 0..height as usize {
+            if !std::mem::replace(&mut self.damaged[y], false) {
+                continue;
+            }
+            let pixels = &image.data[y * image.stride..y * image.stride + row_len];
+            let hash = row_hash(pixels);
+            match self.rows[y] {
+                Some(ref row) if row.hash == hash => {
+                    self.stats.rows_reused += 1;
+                    continue;
+                },
+                _ => {}
+            }
+            let runs = runs(pixels, self.threshold);
+            changed |= self.rows[y].as_ref().map(|row| row.runs != runs).unwrap_or(true);
+            self.rows[y] = Some(Row { hash, runs });
+            self.stats.rows_scanned += 1;
+        }
+        if changed {
+            self.rects = rectangles(&self.rows);
+        }
+        self.stats.scans += 1;
+        self.stats.time += start.elapsed();
+        &self.rects
+    }
+
+    pub fn stats(&self) -> ScanStats {
+        self.stats
+    }
+}
+
+/// Decides which frames of changing content are scanned.
+///
+/// The first frame that changes the content after it was scanned is
+/// scanned right away, after that one in every so many frames. Skipped
+/// frames leave a scan pending, which the caller checks for later with
+/// `arm` and `settle` so the last frame is scanned once the content stops
+/// changing.
+#[derive(Debug)]
+pub struct ScanSchedule {
+    /// Scan at most one in this many frames.
+    frames: u32,
+    /// The frames since the last scan.
+    since_scan: u32,
+    /// Set if a frame was skipped since the last scan.
+    pending: bool,
+    /// Counts the frames, so a check armed at one can tell whether the
+    /// content changed since.
+    frame: u64,
+    armed: bool
+}
+
+impl Default for ScanSchedule {
+    fn default() -> Self {
+        ScanSchedule::new(DEFAULT_SCAN_FRAMES)
+    }
+}
+
+impl ScanSchedule {
+    pub fn new(frames: u32) -> Self {
+        let frames = frames.max(1);
+        ScanSchedule {
+            frames,
+            since_scan: frames,
+            pending: false,
+            frame: 0,
+            armed: false
+        }
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    pub fn set_frames(&mut self, frames: u32) {
+        self.frames = frames.max(1);
+    }
+
+    /// A frame changed the content, returns whether to scan it.
+    pub fn frame(&mut self) -> bool {
+        self.frame += 1;
+        self.since_scan = self.since_scan.saturating_add(1);
+        if self.since_scan >= self.frames {
+            self.scanned();
+            true
+        } else {
+            self.pending = true;
+            false
+        }
+    }
+
+    /// Something other than a frame scanned the content.
+    pub fn scanned(&mut self) {
+        self.since_scan = 0;
+        self.pending = false;
+    }
+
+    /// The frame to pass to `settle` once the content may have settled, if
+    /// a scan is pending and no check is armed already.
+    pub fn arm(&mut self) -> Option<u64> {
+        if !self.pending || self.armed {
+            return None;
+        }
+        self.armed = true;
+        Some(self.frame)
+    }
+
+    /// Whether the content settled on the frame it was armed at, and has
+    /// to be scanned. If it didn't the check has to be armed again.
+    pub fn settle(&mut self, frame: u64) -> bool {
+        self.armed = false;
+        if !self.pending || frame != self.frame {
+            return false;
+        }
+        self.pending = false;
+        // The next change starts a new animation, which is scanned at once.
+        self.since_scan = self.frames;
+        true
+    }
+}
+
+/// Moves `rects` from the coordinates of an image shown at `offset` into
+/// those of a buffer of `size`.
+pub fn to_buffer(rects: &[Area], offset: Origin, size: Size) -> Vec<Area> {
+    let bounds: Area = size.into();
+    let back = Origin {
+        x: -offset.x,
+        y: -offset.y
+    };
+    rects
+        .iter()
+        .filter_map(|rect| rect.translate(back).intersection(bounds))
+        .collect()
+}
+
+/// An FNV-1a hash of the row, eight bytes at a time.
+fn row_hash(pixels: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    let words = pixels.chunks_exact(8);
+    let rest = words.remainder();
+    for word in words {
+        hash ^= u64::from_le_bytes(word.try_into().unwrap());
+        hash = hash.wrapping_mul(0x100_0000_01b3);
+    }
+    for &byte in rest {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100_0000_01b3);
+    }
+    hash
+}
+
+/// The runs of pixels with an alpha above `threshold`. The pixels are
+/// ARGB32, so the alpha is the last byte on little endian machines.
+fn runs(pixels: &[u8], threshold: u8) -> Vec<(u32, u32)> {
+    let mut runs = Vec::new();
+    let mut start = None;
+    for (x, pixel) in pixels.chunks_exact(4).enumerate() {
+        let x = x as u32;
+        match (pixel[3] > threshold, start) {
+            (true, None) => start = Some(x),
+            (false, Some(from)) => {
+                runs.push((from, x));
+                start = None
+            },
+            _ => {}
+        }
+    }
+    if let Some(from) = start {
+        runs.push((from, (pixels.len() / 4) as u32));
+    }
+    runs
+}
+
+/// Joins the runs of consecutive rows that have the same runs into
+/// rectangles.
+fn rectangles(rows: &[Option<Row>]) -> Vec<Area> {
+    let mut rects = Vec::new();
+    let mut band: Option<(usize, &[(u32, u32)])> = None;
+    let no_runs: &[(u32, u32)] = &[];
+    for (y, row) in rows
+        .iter()
+        .map(|row| row.as_ref().map_or(no_runs, |row| row.runs.as_slice()))
+        .enumerate()
+    {
+        match band {
+            Some((_, runs)) if runs == row => continue,
+            Some((top, runs)) => push_band(&mut rects, top, y, runs),
+            None => {}
+        }
+        band = Some((y, row));
+    }
+    if let Some((top, runs)) = band {
+        push_band(&mut rects, top, rows.len(), runs);
+    }
+    rects
+}
+
+fn push_band(rects: &mut Vec<Area>, top: usize, bottom: usize, runs: &[(u32, u32)]) {
+    rects.extend(runs.iter().map(|&(start, end)| Area {
+        origin: Origin {
+            x: start as i32,
+            y: top as i32
+        },
+        size: Size {
+            width: end - start,
+            height: (bottom - top) as u32
+        }
+    }));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const WIDTH: u32 = 100;
+    const HEIGHT: u32 = 40;
+
+    fn rect(x: i32, y: i32, width: u32, height: u32) -> Area {
+        Area {
+            origin: Origin { x, y },
+            size: Size { width, height }
+        }
+    }
+
+    fn whole() -> Area {
+        rect(0, 0, WIDTH, HEIGHT)
+    }
+
+    /// A rounded popup that fades in: a card of 60x20 pixels at `alpha`,
+    /// with a shadow too faint to take input around it.
+    fn popup(alpha: u8) -> Vec<u8> {
+        let mut pixels = vec![0u8; (WIDTH * HEIGHT * 4) as usize];
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let pixel_alpha = if x >= 20 && x < 80 && y >= 10 && y < 30 {
+                    alpha
+                } else if x >= 15 && x < 85 && y >= 5 && y < 35 {
+                    alpha / 64
+                } else {
+                    0
+                };
+                let i = ((y * WIDTH + x) * 4) as usize;
+                pixels[i..i + 4].copy_from_slice(&[pixel_alpha, pixel_alpha / 2, 0, pixel_alpha]);
+            }
+        }
+        pixels
+    }
+
+    fn image(pixels: &[u8]) -> Image {
+        Image {
+            data: pixels,
+            stride: WIDTH as usize * 4,
+            size: Size {
+                width: WIDTH,
+                height: HEIGHT
+            }
+        }
+    }
+
+    #[test]
+    fn input_region_runs() {
+        let mut region = AlphaRegion::new(0);
+        let pixels = popup(255);
+        assert_eq!(region.scan(&image(&pixels)), &[rect(15, 5, 70, 30)]);
+        // The shadow doesn't take input above a higher threshold, which
+        // invalidates every cached row.
+        region.set_threshold(10);
+        assert_eq!(region.scan(&image(&pixels)), &[rect(20, 10, 60, 20)]);
+        assert_eq!(region.stats().rows_scanned, 2 * HEIGHT as u64);
+        assert_eq!(region.stats().rows_reused, 0);
+        // Moved into a buffer with the content scrolled.
+        assert_eq!(
+            to_buffer(
+                &[rect(20, 10, 60, 20)],
+                Origin { x: 30, y: 0 },
+                Size {
+                    width: 40,
+                    height: 15
+                }
+            ),
+            vec![rect(0, 10, 40, 5)]
+        );
+    }
+
+    #[test]
+    fn input_region_fade() {
+        // Before: every frame of the fade scans the whole surface.
+        let frames: Vec<Vec<u8>> = (0..=255).step_by(5).map(popup).collect();
+        let before = frames.len() as u64 * HEIGHT as u64;
+        // After: the fade is scanned every few frames, and the rows outside
+        // of the popup keep their runs.
+        let mut region = AlphaRegion::new(128);
+        let mut schedule = ScanSchedule::new(4);
+        for pixels in frames.iter() {
+            region.damage_all();
+            if schedule.frame() {
+                region.scan(&image(pixels));
+            }
+        }
+        let frame = schedule.arm().expect("the last frame was skipped");
+        assert!(schedule.settle(frame));
+        let last = frames.last().unwrap();
+        assert_eq!(region.scan(&image(last)), &[rect(20, 10, 60, 20)]);
+        let stats = region.stats();
+        assert_eq!(stats.scans, 14);
+        assert_eq!(stats.rows_scanned + stats.rows_reused, 14 * HEIGHT as u64);
+        assert!(stats.rows_reused >= 13 * 10);
+        assert!(stats.rows_scanned * 4 < before);
+    }
+
+    #[test]
+    fn input_region_change_mid_animation() {
+        let mut region = AlphaRegion::new(0);
+        let mut schedule = ScanSchedule::new(3);
+        let mut pixels = popup(255);
+        assert!(schedule.frame());
+        region.scan(&image(&pixels));
+        // A row of the card turns transparent while the frames are skipped.
+        for x in 0..WIDTH as usize * 4 {
+            pixels[20 * WIDTH as usize * 4 + x] = 0;
+        }
+        region.damage(rect(0, 20, WIDTH, 1));
+        assert!(!schedule.frame());
+        assert!(!schedule.frame());
+        // The deadline comes before the content settles.
+        assert!(schedule.frame());
+        assert_eq!(
+            region.scan(&image(&pixels)),
+            &[rect(15, 5, 70, 15), rect(15, 21, 70, 14)]
+        );
+        assert_eq!(region.stats().rows_scanned, HEIGHT as u64 + 1);
+        // The content settles on a skipped frame, which the check armed
+        // before it finds.
+        assert!(!schedule.frame());
+        let early = schedule.arm().unwrap();
+        assert_eq!(schedule.arm(), None);
+        assert!(!schedule.frame());
+        assert!(!schedule.settle(early));
+        let frame = schedule.arm().unwrap();
+        assert!(schedule.settle(frame));
+        assert_eq!(schedule.arm(), None);
+        // The next change is scanned at once.
+        assert!(schedule.frame());
+        region.damage(whole());
+        region.scan(&image(&pixels));
+        assert_eq!(region.stats().rows_reused, HEIGHT as u64);
+    }
+}
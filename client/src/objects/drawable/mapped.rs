@@ -0,0 +1,127 @@
+//! Surfaces painted right into the buffer the compositor reads, with
+//! `drawable.buffer_backend = "mapped"`.
+//!
+//! The surface is over the memory of a shared memory buffer mapped into
+//! the client, so a refresh attaches that buffer rather than copying the
+//! content into another. That only works while the buffer shows the
+//! content as it is: at scale 1, at the size of the surface the drawable
+//! is shown on, without effects, opacity or a content offset. Otherwise
+//! the content is copied into a buffer of its own, as with `"shm"`, and
+//! strips always are.
+//!
+//! Nothing should be painted into the buffer while the compositor reads
+//! it. Compositors release shared memory once they uploaded it, which is
+//! long before the next frame since refreshes wait for frame callbacks,
+//! but one that keeps it until the next commit would show frames half
+//! painted. A refresh that finds the last frame not released yet falls
+//! back to copying, like a drawable that can't have a dmabuf.
+
+use std::{os::raw::c_void, rc::Rc};
+
+use cairo::{Format, ImageSurface, Status};
+use cairo_sys::{self, cairo_user_data_key_t};
+use glib::translate::ToGlibPtr;
+
+use super::pixel_format::PixelFormat;
+use crate::area::Size;
+use crate::wayland_obj::{self, MappedBuffer, Mapping};
+
+/// The key of the mapping a surface keeps alive, only its address matters.
+static MAPPING_KEY: cairo_user_data_key_t = cairo_user_data_key_t { unused: 0 };
+
+/// Allocates a mapped buffer of `size` and a surface over it, or says why
+/// there's none.
+pub fn allocate(size: Size, format: PixelFormat) -> Result<(MappedBuffer, ImageSurface), String> {
+    let buffer = wayland_obj::create_mapped_buffer(size, format.shm())
+        .map_err(|err| format!("could not create a mapped buffer: {}", err))?;
+    let surface = create_surface(buffer.mapping(), buffer.size(), buffer.stride(), format.cairo())
+        .map_err(|err| format!("could not create a surface over the mapped buffer: {:?}", err))?;
+    Ok((buffer, surface))
+}
+
+/// Creates a surface of `size` over `mapping`, whose rows are `stride`
+/// bytes long.
+///
+/// The surface keeps the memory mapped for as long as it lives, which can
+/// be longer than the buffer when Lua holds on to it.
+fn create_surface(
+    mapping: Rc<Mapping>,
+    size: Size,
+    stride: usize,
+    format: Format
+) -> Result<ImageSurface, Status> {
+    let Size { width, height } = size;
+    let mapping = Box::new(mapping);
+    unsafe {
+        let surface = ImageSurface::from_raw_full(cairo_sys::cairo_image_surface_create_for_data(
+            mapping.as_mut_ptr(),
+            format,
+            width as i32,
+            height as i32,
+            stride as i32
+        ))?;
+        let mapping = Box::into_raw(mapping);
+        let status = cairo_sys::cairo_surface_set_user_data(
+            surface.to_glib_none().0,
+            &MAPPING_KEY as *const _ as *mut _,
+            mapping as *mut c_void,
+            Some(release_mapping)
+        );
+        if status != Status::Success {
+            // Cairo didn't take the mapping, which has to outlive the
+            // surface.
+            drop(surface);
+            release_mapping(mapping as *mut c_void);
+            return Err(status);
+        }
+        Ok(surface)
+    }
+}
+
+/// Drops the reference to the mapping a surface had, once cairo destroys
+/// the surface.
+unsafe extern "C" fn release_mapping(mapping: *mut c_void) {
+    drop(Box::from_raw(mapping as *mut Rc<Mapping>));
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use cairo::{prelude::SurfaceExt, Context, Format};
+
+    use super::*;
+
+    #[test]
+    fn mapped_surface_paints_into_the_file() {
+        let size = Size { width: 4, height: 2 };
+        let mut file = tempfile::tempfile().unwrap();
+        file.set_len(4 * 2 * 4).unwrap();
+        let mapping = Rc::new(Mapping::new(&file, 4 * 2 * 4).unwrap());
+        let surface = create_surface(mapping.clone(), size, 4 * 4, Format::ARgb32).unwrap();
+        assert_eq!(Rc::strong_count(&mapping), 2);
+        let cr = Context::new(&surface);
+        cr.set_source_rgb(1.0, 0.0, 0.0);
+        cr.rectangle(2.0, 0.0, 2.0, 2.0);
+        cr.fill();
+        drop(cr);
+        surface.flush();
+        // What was painted is in the file, nothing was copied there.
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).unwrap();
+        let red = 0xffff_0000u32.to_ne_bytes();
+        for y in 0..2 {
+            for x in 0..4 {
+                let pixel = &data[(y * 4 + x) * 4..][..4];
+                assert_eq!(pixel, if x < 2 { &[0; 4][..] } else { &red[..] });
+            }
+        }
+        // The surface keeps the memory mapped until it's destroyed.
+        drop(file);
+        let weak = Rc::downgrade(&mapping);
+        drop(mapping);
+        assert!(weak.upgrade().is_some());
+        drop(surface);
+        assert!(weak.upgrade().is_none());
+    }
+}
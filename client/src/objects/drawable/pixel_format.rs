@@ -0,0 +1,81 @@
+//! The pixel format of the surface Lua paints into and of the buffer it's
+//! copied into, set with `drawable.format`.
+//!
+//! Both formats have 4 bytes a pixel in the same layout, so everything that
+//! reads the content works the same for both. An `rgb24` surface has no
+//! alpha, which lets the compositor skip blending an opaque drawable with
+//! what's below it.
+
+use cairo::Format;
+use wayland_client::protocol::wl_shm;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PixelFormat {
+    Argb32,
+    Rgb24
+}
+
+impl Default for PixelFormat {
+    fn default() -> Self {
+        PixelFormat::Argb32
+    }
+}
+
+impl PixelFormat {
+    pub const NAMES: &'static [&'static str] = &["argb32", "rgb24"];
+
+    pub fn from_name(name: &str) -> Option<PixelFormat> {
+        match name {
+            "argb32" => Some(PixelFormat::Argb32),
+            "rgb24" => Some(PixelFormat::Rgb24),
+            _ => None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PixelFormat::Argb32 => "argb32",
+            PixelFormat::Rgb24 => "rgb24"
+        }
+    }
+
+    /// The format of the cairo surface Lua paints into.
+    pub fn cairo(self) -> Format {
+        match self {
+            PixelFormat::Argb32 => Format::ARgb32,
+            PixelFormat::Rgb24 => Format::Rgb24
+        }
+    }
+
+    /// The format of the buffer the content is copied into.
+    pub fn shm(self) -> wl_shm::Format {
+        match self {
+            PixelFormat::Argb32 => wl_shm::Format::Argb8888,
+            PixelFormat::Rgb24 => wl_shm::Format::Xrgb8888
+        }
+    }
+
+    /// Whether every pixel is shown fully opaque.
+    pub fn is_opaque(self) -> bool {
+        self == PixelFormat::Rgb24
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pixel_format_names() {
+        for name in PixelFormat::NAMES {
+            assert_eq!(PixelFormat::from_name(name).map(PixelFormat::name), Some(*name));
+        }
+        assert_eq!(PixelFormat::from_name("a8"), None);
+        assert_eq!(PixelFormat::default().shm(), wl_shm::Format::Argb8888);
+        // The same layout, so the stride of the surface is the buffer's.
+        for format in &[PixelFormat::Argb32, PixelFormat::Rgb24] {
+            let surface = cairo::ImageSurface::create(format.cairo(), 100, 1).unwrap();
+            assert_eq!(surface.get_stride(), 400);
+        }
+    }
+}
@@ -0,0 +1,527 @@
+//! Snapshots of the content of drawables, saved to disk so that bars can
+//! show what they last showed right after a restart, while Lua is still
+//! fetching fresh data.
+//!
+//! Snapshots are kept in a directory per session, which is pruned to a
+//! total size by forgetting the least recently used ones.
+
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf}
+};
+
+use crate::area::{Area, Origin, Size};
+
+use super::content_fit::Image;
+
+/// How big the snapshots of a session can get in total, in bytes.
+pub const MAX_CACHE_BYTES: u64 = 32 << 20;
+
+/// How much bigger or smaller than the snapshot, relatively, a drawable can
+/// be for the snapshot to be scaled to it.
+pub const MAX_SCALE_DIFFERENCE: f64 = 0.25;
+
+const MAGIC: &[u8; 8] = b"WCSNAP1\n";
+const HEADER_LEN: usize = MAGIC.len() + 5 * 4;
+const EXTENSION: &str = "snap";
+/// The keys of the snapshots, least recently used first.
+const INDEX: &str = "index";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub geometry: Area,
+    /// The scale of the output the content was painted for.
+    pub scale: i32,
+    /// ARGB32 pixels at the size of the geometry, tightly packed.
+    pub pixels: Vec<u8>
+}
+
+impl Snapshot {
+    pub fn new(image: Image, geometry: Area, scale: i32) -> Self {
+        let row = image.size.width as usize * 4;
+        let mut pixels = Vec::with_capacity(row * image.size.height as usize);
+        for y in 0..image.size.height as usize {
+            let start = y * image.stride;
+            pixels.extend_from_slice(&image.data[start..start + row]);
+        }
+        if image.size != geometry.size {
+            // Content painted at a scale other than 1 is kept in logical
+            // pixels, like any other.
+            pixels = scale_bilinear(&pixels, image.size, geometry.size);
+        }
+        Snapshot {
+            geometry,
+            scale,
+            pixels
+        }
+    }
+
+    /// The snapshot as it's written to disk, with runs of the same pixel
+    /// stored once.
+    pub fn encode(&self) -> Vec<u8> {
+        let Area {
+            origin: Origin { x, y },
+            size: Size { width, height }
+        } = self.geometry;
+        let mut data = Vec::with_capacity(HEADER_LEN + self.pixels.len() / 4);
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&x.to_le_bytes());
+        data.extend_from_slice(&y.to_le_bytes());
+        data.extend_from_slice(&width.to_le_bytes());
+        data.extend_from_slice(&height.to_le_bytes());
+        data.extend_from_slice(&self.scale.to_le_bytes());
+        let mut pixels = self.pixels.chunks(4).peekable();
+        while let Some(pixel) = pixels.next() {
+            let mut run: u16 = 1;
+            while run < u16::max_value() && pixels.peek() == Some(&pixel) {
+                pixels.next();
+                run += 1;
+            }
+            data.extend_from_slice(&run.to_le_bytes());
+            data.extend_from_slice(pixel);
+        }
+        data
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, String> {
+        if data.len() < HEADER_LEN || &data[..MAGIC.len()] != MAGIC {
+            return Err("not a snapshot".into());
+        }
+        let field = |index: usize| {
+            let start = MAGIC.len() + index * 4;
+            [data[start], data[start + 1], data[start + 2], data[start + 3]]
+        };
+        let geometry = Area {
+            origin: Origin {
+                x: i32::from_le_bytes(field(0)),
+                y: i32::from_le_bytes(field(1))
+            },
+            size: Size {
+                width: u32::from_le_bytes(field(2)),
+                height: u32::from_le_bytes(field(3))
+            }
+        };
+        let scale = i32::from_le_bytes(field(4));
+        let len = geometry.size.width as usize * geometry.size.height as usize * 4;
+        let runs = &data[HEADER_LEN..];
+        if runs.len() % 6 != 0 {
+            return Err("truncated snapshot".into());
+        }
+        let mut pixels = Vec::with_capacity(len);
+        for run in runs.chunks(6) {
+            let count = u16::from_le_bytes([run[0], run[1]]) as usize;
+            if pixels.len() + count * 4 > len {
+                return Err("snapshot has more pixels than its size".into());
+            }
+            for _ in 0..count {
+                pixels.extend_from_slice(&run[2..]);
+            }
+        }
+        if pixels.len() != len {
+            return Err("snapshot has fewer pixels than its size".into());
+        }
+        Ok(Snapshot {
+            geometry,
+            scale,
+            pixels
+        })
+    }
+
+    /// The pixels of the snapshot at `size`, and whether they were scaled.
+    ///
+    /// The snapshot is only scaled if the size is close to its own, beyond
+    /// that stale content would look more wrong than no content.
+    pub fn pixels_at(&self, size: Size) -> Result<(Vec<u8>, bool), String> {
+        let own = self.geometry.size;
+        if own == size {
+            return Ok((self.pixels.clone(), false));
+        }
+        let close = |own: u32, other: u32| {
+            own > 0 && other > 0 && (other as f64 / own as f64 - 1.0).abs() <= MAX_SCALE_DIFFERENCE
+        };
+        if !close(own.width, size.width) || !close(own.height, size.height) {
+            return Err(format!(
+                "snapshot of {}x{} can't be scaled to {}x{}",
+                own.width, own.height, size.width, size.height
+            ));
+        }
+        Ok((scale_bilinear(&self.pixels, own, size), true))
+    }
+}
+
+/// Scales tightly packed ARGB32 pixels, interpolating between the four
+/// closest pixels of the source.
+///
+/// The pixels are premultiplied, so the channels are interpolated on their
+/// own.
+pub fn scale_bilinear(pixels: &[u8], from: Size, to: Size) -> Vec<u8> {
+    let (src_width, src_height) = (from.width as usize, from.height as usize);
+    let mut scaled = Vec::with_capacity(to.width as usize * to.height as usize * 4);
+    // Where the center of a pixel of the result is in the source.
+    let source = |position: u32, from: usize, to: u32| {
+        let center = (position as f64 + 0.5) * from as f64 / to as f64 - 0.5;
+        let center = center.max(0.0).min((from - 1) as f64);
+        let first = center.floor() as usize;
+        (first, (first + 1).min(from - 1), center - first as f64)
+    };
+    for y in 0..to.height {
+        let (top, bottom, fy) = source(y, src_height, to.height);
+        for x in 0..to.width {
+            let (left, right, fx) = source(x, src_width, to.width);
+            for channel in 0..4 {
+                let at = |x: usize, y: usize| pixels[(y * src_width + x) * 4 + channel] as f64;
+                let upper = at(left, top) * (1.0 - fx) + at(right, top) * fx;
+                let lower = at(left, bottom) * (1.0 - fx) + at(right, bottom) * fx;
+                scaled.push((upper * (1.0 - fy) + lower * fy).round() as u8);
+            }
+        }
+    }
+    scaled
+}
+
+/// Whether `key` can name a snapshot, which is also its file name.
+pub fn valid_key(key: &str) -> bool {
+    !key.is_empty() &&
+        key.len() <= 128 &&
+        key != INDEX &&
+        key.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.') &&
+        !key.starts_with('.')
+}
+
+/// The snapshots of a session, on disk.
+#[derive(Debug)]
+pub struct SnapshotCache {
+    dir: PathBuf,
+    limit: u64
+}
+
+impl SnapshotCache {
+    pub fn new(dir: PathBuf, limit: u64) -> Self {
+        SnapshotCache { dir, limit }
+    }
+
+    /// The cache in the runtime directory, which goes away with the
+    /// session.
+    pub fn session() -> Self {
+        let dir = env::var("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| env::temp_dir());
+        SnapshotCache::new(dir.join("way-cooler").join("snapshots"), MAX_CACHE_BYTES)
+    }
+
+    pub fn save(&self, key: &str, snapshot: &Snapshot) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        // Written to the side first, so a snapshot is never half written.
+        let path = self.path(key);
+        let partial = path.with_extension("partial");
+        File::create(&partial)?.write_all(&snapshot.encode())?;
+        fs::rename(&partial, &path)?;
+        self.touch(key)?;
+        self.prune(key)
+    }
+
+    /// Loads the snapshot, if there is one.
+    pub fn load(&self, key: &str) -> io::Result<Option<Snapshot>> {
+        let data = match fs::read(self.path(key)) {
+            Ok(data) => data,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err)
+        };
+        let snapshot =
+            Snapshot::decode(&data).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        self.touch(key)?;
+        Ok(Some(snapshot))
+    }
+
+    /// Forgets every snapshot, e.g. because the theme changed.
+    pub fn invalidate(&self) -> io::Result<()> {
+        match fs::remove_dir_all(&self.dir) {
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(key).with_extension(EXTENSION)
+    }
+
+    fn index(&self) -> io::Result<Vec<String>> {
+        match fs::read_to_string(self.dir.join(INDEX)) {
+            Ok(index) => Ok(index.lines().map(String::from).collect()),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err)
+        }
+    }
+
+    fn write_index(&self, keys: &[String]) -> io::Result<()> {
+        let mut index = keys.join("\n");
+        index.push('\n');
+        fs::write(self.dir.join(INDEX), index)
+    }
+
+    /// Makes `key` the most recently used snapshot.
+    fn touch(&self, key: &str) -> io::Result<()> {
+        let mut keys = self.index()?;
+        keys.retain(|other| other != key);
+        keys.push(key.into());
+        self.write_index(&keys)
+    }
+
+    /// The memory the snapshots take, the runtime directory being in
+    /// memory.
+    pub fn usage(&self) -> io::Result<u64> {
+        let keys = self.index()?;
+        Ok(keys.iter().map(|key| self.size(key)).sum())
+    }
+
+    /// Removes the least recently used snapshots until they take at most
+    /// `limit`, returning the bytes freed.
+    pub fn shrink(&self, limit: u64) -> io::Result<u64> {
+        let (keys, _, freed) = self.evict(limit, None)?;
+        if freed > 0 {
+            self.write_index(&keys)?;
+        }
+        Ok(freed)
+    }
+
+    /// Removes the least recently used snapshots until they fit in the
+    /// limit. `keep` is only removed if it doesn't fit on its own.
+    fn prune(&self, keep: &str) -> io::Result<()> {
+        let (mut keys, total, _) = self.evict(self.limit, Some(keep))?;
+        let result = if total > self.limit {
+            keys.retain(|key| key != keep);
+            remove(&self.path(keep))?;
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("snapshot is larger than the cache limit of {} bytes", self.limit)
+            ))
+        } else {
+            Ok(())
+        };
+        self.write_index(&keys)?;
+        result
+    }
+
+    /// Removes the least recently used snapshots other than `keep` until
+    /// they take at most `limit`, returning the keys left, the bytes they
+    /// take and the bytes freed.
+    fn evict(&self, limit: u64, keep: Option<&str>) -> io::Result<(Vec<String>, u64, u64)> {
+        let mut keys = self.index()?;
+        keys.retain(|key| self.path(key).exists());
+        let mut total: u64 = keys.iter().map(|key| self.size(key)).sum();
+        let mut freed = 0;
+        while total > limit {
+            let oldest = match keys.iter().position(|key| Some(key.as_str()) != keep) {
+                Some(oldest) => oldest,
+                None => break
+            };
+            let key = keys.remove(oldest);
+            let size = self.size(&key);
+            total -= size;
+            freed += size;
+            remove(&self.path(&key))?;
+        }
+        Ok((keys, total, freed))
+    }
+
+    fn size(&self, key: &str) -> u64 {
+        fs::metadata(self.path(key)).map(|meta| meta.len()).unwrap_or(0)
+    }
+}
+
+fn remove(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn area(width: u32, height: u32) -> Area {
+        Area {
+            origin: Origin { x: 10, y: -20 },
+            size: Size { width, height }
+        }
+    }
+
+    /// A bar with a dark background and some lighter text-like noise.
+    fn bar(width: u32, height: u32) -> Snapshot {
+        let mut pixels = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                if (x * 7 + y * 3) % 11 == 0 {
+                    pixels.extend_from_slice(&[(x % 256) as u8, (y % 256) as u8, 0x80, 0xff]);
+                } else {
+                    pixels.extend_from_slice(&[0x22, 0x22, 0x22, 0xff]);
+                }
+            }
+        }
+        Snapshot {
+            geometry: area(width, height),
+            scale: 1,
+            pixels
+        }
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let snapshot = bar(1920, 24);
+        let encoded = snapshot.encode();
+        assert!(encoded.len() < snapshot.pixels.len() / 2);
+        let decoded = Snapshot::decode(&encoded).unwrap();
+        assert_eq!(decoded, snapshot);
+        let (pixels, scaled) = decoded
+            .pixels_at(Size {
+                width: 1920,
+                height: 24
+            })
+            .unwrap();
+        assert_eq!((pixels, scaled), (snapshot.pixels, false));
+        // Longer runs than fit in one count.
+        let black = Snapshot {
+            geometry: area(400, 400),
+            scale: 2,
+            pixels: vec![0; 400 * 400 * 4]
+        };
+        assert_eq!(Snapshot::decode(&black.encode()).unwrap(), black);
+    }
+
+    #[test]
+    fn snapshot_from_image() {
+        // Rows padded to a stride of 12 bytes.
+        let data = [
+            1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0, 0, 9, 9, 9, 9, 8, 8, 8, 8, 0, 0, 0, 0
+        ];
+        let image = Image {
+            data: &data,
+            stride: 12,
+            size: Size { width: 2, height: 2 }
+        };
+        let snapshot = Snapshot::new(image, area(2, 2), 1);
+        assert_eq!(
+            snapshot.pixels,
+            vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 9, 9, 9, 8, 8, 8, 8]
+        );
+    }
+
+    #[test]
+    fn snapshot_decode_errors() {
+        let encoded = bar(8, 8).encode();
+        assert!(Snapshot::decode(b"").is_err());
+        assert!(Snapshot::decode(b"PNG\0\0\0\0\0garbage garbage garbage").is_err());
+        assert!(Snapshot::decode(&encoded[..encoded.len() - 1]).is_err());
+        assert!(Snapshot::decode(&encoded[..encoded.len() - 6]).is_err());
+        let mut extra = encoded.clone();
+        extra.extend_from_slice(&[1, 0, 0, 0, 0, 0]);
+        assert!(Snapshot::decode(&extra).is_err());
+    }
+
+    #[test]
+    fn snapshot_scaled() {
+        let snapshot = Snapshot {
+            geometry: area(4, 2),
+            scale: 1,
+            pixels: [
+                [0u8, 0, 0, 0xff],
+                [0x40, 0x40, 0x40, 0xff],
+                [0x80, 0x80, 0x80, 0xff],
+                [0xc0, 0xc0, 0xc0, 0xff]
+            ]
+            .iter()
+            .cycle()
+            .take(8)
+            .flatten()
+            .cloned()
+            .collect()
+        };
+        let (pixels, scaled) = snapshot.pixels_at(Size { width: 5, height: 2 }).unwrap();
+        assert!(scaled);
+        assert_eq!(pixels.len(), 5 * 2 * 4);
+        // A gradient stays a gradient, from end to end.
+        let row: Vec<u8> = pixels[..20].chunks(4).map(|pixel| pixel[0]).collect();
+        assert_eq!(row.first(), Some(&0));
+        assert_eq!(row.last(), Some(&0xc0));
+        assert!(row.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(pixels.chunks(4).all(|pixel| pixel[3] == 0xff));
+        // A solid bar stays solid.
+        let solid = Snapshot {
+            geometry: area(100, 20),
+            scale: 1,
+            pixels: [0x11, 0x22, 0x33, 0xff]
+                .iter()
+                .cycle()
+                .take(100 * 20 * 4)
+                .cloned()
+                .collect()
+        };
+        let (pixels, _) = solid
+            .pixels_at(Size {
+                width: 110,
+                height: 18
+            })
+            .unwrap();
+        assert!(pixels.chunks(4).all(|pixel| pixel == [0x11, 0x22, 0x33, 0xff]));
+        // Too different a size fails instead of smearing the content.
+        assert!(solid
+            .pixels_at(Size {
+                width: 200,
+                height: 20
+            })
+            .is_err());
+        assert!(solid
+            .pixels_at(Size {
+                width: 100,
+                height: 0
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn snapshot_keys() {
+        assert!(valid_key("drawin-3"));
+        assert!(valid_key("calendar_month.v2"));
+        for key in &["", "../etc/passwd", "a/b", ".hidden", "index", "spaced key"] {
+            assert!(!valid_key(key), "{}", key);
+        }
+    }
+
+    #[test]
+    fn snapshot_cache_pruning() {
+        let dir = tempfile::tempdir().unwrap();
+        let one = bar(64, 16);
+        let size = one.encode().len() as u64;
+        let cache = SnapshotCache::new(dir.path().join("snapshots"), size * 3);
+        assert_eq!(cache.load("a").unwrap(), None);
+        cache.save("a", &one).unwrap();
+        cache.save("b", &one).unwrap();
+        cache.save("c", &one).unwrap();
+        // "a" is the most recently used now, so "b" goes first.
+        assert_eq!(cache.load("a").unwrap(), Some(one.clone()));
+        cache.save("d", &one).unwrap();
+        assert_eq!(cache.load("b").unwrap(), None);
+        for key in &["a", "c", "d"] {
+            assert!(cache.load(key).unwrap().is_some(), "{}", key);
+        }
+        // Shrunk under memory pressure, least recently used first.
+        assert_eq!(cache.usage().unwrap(), size * 3);
+        assert_eq!(cache.shrink(size * 2).unwrap(), size);
+        assert_eq!(cache.shrink(size * 2).unwrap(), 0);
+        assert_eq!(cache.load("a").unwrap(), None);
+        assert_eq!(cache.usage().unwrap(), size * 2);
+        cache.save("a", &one).unwrap();
+        // A snapshot that can't fit on its own isn't kept.
+        assert!(cache.save("huge", &bar(1920, 24)).is_err());
+        assert_eq!(cache.load("huge").unwrap(), None);
+        cache.invalidate().unwrap();
+        assert_eq!(cache.load("a").unwrap(), None);
+        cache.invalidate().unwrap();
+        cache.save("a", &one).unwrap();
+        assert!(cache.load("a").unwrap().is_some());
+    }
+}
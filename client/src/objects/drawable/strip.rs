@@ -0,0 +1,193 @@
+//! Content wider than the drawable is shown on, which Lua paints a range
+//! at a time as it's scrolled into view.
+//!
+//! The surface is as wide as the whole strip, and the content offset picks
+//! the part of it that's shown. Only the ranges that were painted since
+//! they were last invalidated are valid, so scrolling over them copies the
+//! pixels that are already there without asking Lua for anything.
+//!
+//! Ranges are `(x, width)` pairs in the coordinates of the surface.
+
+/// The widest strip, which is as wide as Cairo makes image surfaces.
+pub const MAX_STRIP_WIDTH: u32 = 32767;
+
+/// Sorted ranges of x, which don't overlap or touch, as `(start, end)`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Intervals(Vec<(u32, u32)>);
+
+impl Intervals {
+    pub fn insert(&mut self, start: u32, end: u32) {
+        if start >= end {
+            return;
+        }
+        let (mut start, mut end) = (start, end);
+        // Merged with every range it overlaps or touches.
+        self.0.retain(|&(other_start, other_end)| {
+            if other_end < start || other_start > end {
+                return true;
+            }
+            start = start.min(other_start);
+            end = end.max(other_end);
+            false
+        });
+        let index = self
+            .0
+            .iter()
+            .position(|&(other, _)| other > start)
+            .unwrap_or(self.0.len());
+        self.0.insert(index, (start, end));
+    }
+
+    pub fn remove(&mut self, start: u32, end: u32) {
+        if start >= end {
+            return;
+        }
+        let mut kept = Vec::with_capacity(self.0.len() + 1);
+        for &(other_start, other_end) in &self.0 {
+            if other_end <= start || other_start >= end {
+                kept.push((other_start, other_end));
+                continue;
+            }
+            if other_start < start {
+                kept.push((other_start, start));
+            }
+            if other_end > end {
+                kept.push((end, other_end));
+            }
+        }
+        self.0 = kept;
+    }
+
+    /// The parts of `start..end` that aren't in any range.
+    pub fn gaps(&self, start: u32, end: u32) -> Vec<(u32, u32)> {
+        let mut gaps = Vec::new();
+        let mut from = start;
+        for &(other_start, other_end) in &self.0 {
+            if other_end <= from {
+                continue;
+            }
+            if other_start >= end {
+                break;
+            }
+            if other_start > from {
+                gaps.push((from, other_start));
+            }
+            from = other_end;
+        }
+        if from < end {
+            gaps.push((from, end));
+        }
+        gaps
+    }
+
+    pub fn ranges(&self) -> &[(u32, u32)] {
+        &self.0
+    }
+}
+
+#[derive(Debug)]
+pub struct Strip {
+    width: u32,
+    valid: Intervals
+}
+
+impl Strip {
+    pub fn new(width: u32) -> Self {
+        Strip {
+            width,
+            valid: Intervals::default()
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Where a view `view` wide starts when it's scrolled to `x`, which
+    /// keeps all of it on the strip.
+    pub fn clamp_scroll(&self, x: i64, view: u32) -> u32 {
+        let max = i64::from(self.width.saturating_sub(view));
+        x.max(0).min(max) as u32
+    }
+
+    /// The ranges of the view at `x` that have to be painted, which are
+    /// taken to be valid from now on.
+    pub fn reveal(&mut self, x: u32, view: u32) -> Vec<(u32, u32)> {
+        let end = x.saturating_add(view).min(self.width);
+        let gaps = self.valid.gaps(x, end);
+        for &(start, end) in &gaps {
+            self.valid.insert(start, end);
+        }
+        gaps.into_iter()
+            .map(|(start, end)| (start, end - start))
+            .collect()
+    }
+
+    /// Makes `width` from `x` be painted again the next time it's shown.
+    pub fn invalidate(&mut self, x: i64, width: u32) {
+        let strip_width = i64::from(self.width);
+        let clamp = |x: i64| x.max(0).min(strip_width) as u32;
+        self.valid.remove(clamp(x), clamp(x + i64::from(width)));
+    }
+
+    /// The ranges that are painted, as `(x, width)`.
+    pub fn valid(&self) -> Vec<(u32, u32)> {
+        self.valid
+            .ranges()
+            .iter()
+            .map(|&(start, end)| (start, end - start))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn intervals_merge_and_split() {
+        let mut intervals = Intervals::default();
+        intervals.insert(10, 20);
+        intervals.insert(40, 50);
+        intervals.insert(20, 30);
+        assert_eq!(intervals.ranges(), &[(10, 30), (40, 50)]);
+        assert_eq!(intervals.gaps(0, 60), vec![(0, 10), (30, 40), (50, 60)]);
+        assert_eq!(intervals.gaps(12, 45), vec![(30, 40)]);
+        assert_eq!(intervals.gaps(41, 49), vec![]);
+        intervals.insert(25, 45);
+        assert_eq!(intervals.ranges(), &[(10, 50)]);
+        intervals.remove(15, 20);
+        intervals.remove(45, 60);
+        assert_eq!(intervals.ranges(), &[(10, 15), (20, 45)]);
+        intervals.remove(0, 100);
+        assert_eq!(intervals.ranges(), &[]);
+        intervals.insert(5, 5);
+        assert_eq!(intervals.ranges(), &[]);
+    }
+
+    #[test]
+    fn strip_reveals_gaps() {
+        const VIEW: u32 = 1000;
+        let mut strip = Strip::new(10_000);
+        assert_eq!(strip.reveal(0, VIEW), vec![(0, 1000)]);
+        // Scrolled in steps, only what comes into view is painted.
+        assert_eq!(strip.reveal(300, VIEW), vec![(1000, 300)]);
+        assert_eq!(strip.reveal(700, VIEW), vec![(1300, 400)]);
+        // Back over painted content there's nothing to paint.
+        for x in (0..=700).rev().step_by(50) {
+            assert_eq!(strip.reveal(x, VIEW), vec![]);
+        }
+        // A jump far ahead, then back between the painted ranges.
+        let end = strip.clamp_scroll(20_000, VIEW);
+        assert_eq!(end, 9000);
+        assert_eq!(strip.reveal(end, VIEW), vec![(9000, 1000)]);
+        assert_eq!(strip.reveal(1500, VIEW), vec![(1700, 800)]);
+        assert_eq!(strip.valid(), vec![(0, 2500), (9000, 1000)]);
+        strip.invalidate(2000, 100);
+        strip.invalidate(-50, 100);
+        assert_eq!(strip.reveal(1500, VIEW), vec![(2000, 100)]);
+        assert_eq!(strip.reveal(0, VIEW), vec![(0, 50)]);
+        assert_eq!(strip.clamp_scroll(-5, VIEW), 0);
+        assert_eq!(Strip::new(500).clamp_scroll(100, VIEW), 0);
+    }
+}
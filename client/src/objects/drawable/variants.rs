@@ -0,0 +1,276 @@
+//! Content painted for outputs of other scales.
+//!
+//! The surface of a drawable is allocated at the scale of the output it's
+//! on, and allocated again when it moves to an output of another scale.
+//! Lua can instead paint variants of it for other scales, into surfaces as
+//! many times larger, with `drawable:begin_variant("scale:2")`, and then
+//! the surface stays at the scale it has. The device scale of every
+//! surface is its scale, so Lua paints them all in logical pixels and only
+//! the pixels are finer. The variant shown is the one for the scale of the
+//! output the drawable is on, or while there is none the nearest one,
+//! which the compositor scales the rest of the way: the buffer scale is
+//! always the scale of the variant, so the content is the right size even
+//! when it's soft.
+//!
+//! Lua is told once for each scale it has no variant for, so it can paint
+//! one when it's needed. Variants are the size the drawable was when they
+//! were painted, so they're dropped when it's resized.
+
+use cairo::{Format, ImageSurface, Status};
+use cairo_sys::cairo_surface_t;
+use glib::translate::ToGlibPtr;
+
+// Cairo has had this since 1.14, but cairo-rs doesn't bind it.
+extern "C" {
+    fn cairo_surface_set_device_scale(surface: *mut cairo_surface_t, x_scale: f64, y_scale: f64);
+}
+
+/// The largest scale a variant can be painted for.
+pub const MAX_SCALE: i32 = 8;
+
+#[derive(Debug)]
+pub struct Variants {
+    /// The variants and their scales, other than the drawable's own.
+    surfaces: Vec<(i32, ImageSurface)>,
+    /// The scale of the drawable's own surface.
+    own_scale: i32,
+    /// The scale of the output the drawable is on.
+    output_scale: i32,
+    /// The scales Lua was told there's no variant for.
+    requested: Vec<i32>
+}
+
+impl Default for Variants {
+    fn default() -> Self {
+        Variants {
+            surfaces: Vec::new(),
+            own_scale: 1,
+            output_scale: 1,
+            requested: Vec::new()
+        }
+    }
+}
+
+impl Variants {
+    /// The scale of a variant, from a key like `"scale:2"`.
+    pub fn parse_key(key: &str) -> Option<i32> {
+        const PREFIX: &str = "scale:";
+        if !key.starts_with(PREFIX) {
+            return None;
+        }
+        key[PREFIX.len()..]
+            .parse()
+            .ok()
+            .filter(|scale| (1..=MAX_SCALE).contains(scale))
+    }
+
+    /// The scales there is content for, including the drawable's own.
+    pub fn scales(&self) -> Vec<i32> {
+        let mut scales = vec![self.own_scale];
+        scales.extend(self.surfaces.iter().map(|&(scale, _)| scale));
+        scales.sort();
+        scales
+    }
+
+    /// Whether Lua painted any variants.
+    pub fn is_empty(&self) -> bool {
+        self.surfaces.is_empty()
+    }
+
+    pub fn own_scale(&self) -> i32 {
+        self.own_scale
+    }
+
+    /// Sets the scale of the drawable's own surface, once it's allocated
+    /// at that scale.
+    pub fn set_own_scale(&mut self, scale: i32) {
+        self.surfaces.retain(|&(of, _)| of != scale);
+        self.own_scale = scale;
+    }
+
+    /// The scale of the output the drawable is on.
+    pub fn output_scale(&self) -> i32 {
+        self.output_scale
+    }
+
+    pub fn get(&self, scale: i32) -> Option<&ImageSurface> {
+        self.surfaces
+            .iter()
+            .find(|&&(of, _)| of == scale)
+            .map(|(_, surface)| surface)
+    }
+
+    pub fn get_mut(&mut self, scale: i32) -> Option<&mut ImageSurface> {
+        self.surfaces
+            .iter_mut()
+            .find(|&&mut (of, _)| of == scale)
+            .map(|(_, surface)| surface)
+    }
+
+    pub fn insert(&mut self, scale: i32, surface: ImageSurface) {
+        self.surfaces.retain(|&(of, _)| of != scale);
+        self.surfaces.push((scale, surface));
+    }
+
+    /// Drops the variants, which Lua is told about again when they're
+    /// needed.
+    pub fn clear(&mut self) {
+        self.surfaces.clear();
+        self.requested.clear();
+    }
+
+    /// The memory the variants take.
+    pub fn bytes(&self) -> u64 {
+        self.surfaces
+            .iter()
+            .map(|(_, surface)| surface_bytes(surface))
+            .sum()
+    }
+
+    /// Drops the variants other than the one shown and those for the
+    /// scales in `keep`, returning the memory freed. Lua is told about
+    /// them again when they're needed.
+    pub fn drop_inactive(&mut self, keep: &[i32]) -> u64 {
+        let shown = self.shown();
+        let mut freed = 0;
+        let requested = &mut self.requested;
+        self.surfaces.retain(|(scale, surface)| {
+            if *scale == shown || keep.contains(scale) {
+                return true;
+            }
+            freed += surface_bytes(surface);
+            requested.retain(|requested| requested != scale);
+            false
+        });
+        freed
+    }
+
+    /// Sets the scale of the output the drawable is on, returning it if
+    /// Lua should be told there's no variant for it.
+    pub fn set_output_scale(&mut self, scale: i32) -> Option<i32> {
+        self.output_scale = scale;
+        if self.scales().contains(&scale) || self.requested.contains(&scale) {
+            return None;
+        }
+        self.requested.push(scale);
+        Some(scale)
+    }
+
+    /// The scale of the variant to show.
+    pub fn shown(&self) -> i32 {
+        nearest(&self.scales(), self.output_scale)
+    }
+}
+
+/// A surface for the variant for `scale` of content of `width` by
+/// `height`, which is painted in the units of the content.
+pub fn create_surface(format: Format, width: i32, height: i32, scale: i32) -> Result<ImageSurface, Status> {
+    let surface = ImageSurface::create(format, width * scale, height * scale)?;
+    set_device_scale(&surface, scale);
+    Ok(surface)
+}
+
+/// Makes what's painted into `surface` `scale` times as large, so it's
+/// painted in logical pixels.
+pub fn set_device_scale(surface: &ImageSurface, scale: i32) {
+    unsafe {
+        cairo_surface_set_device_scale(surface.to_glib_none().0, f64::from(scale), f64::from(scale));
+    }
+}
+
+/// The memory the pixels of `surface` take.
+pub fn surface_bytes(surface: &ImageSurface) -> u64 {
+    surface.get_stride() as u64 * surface.get_height() as u64
+}
+
+/// The scale in `available` closest to `wanted`, the larger of two that are
+/// as close, since content scaled down stays sharper.
+fn nearest(available: &[i32], wanted: i32) -> i32 {
+    available
+        .iter()
+        .cloned()
+        .min_by_key(|&scale| ((scale - wanted).abs(), -scale))
+        .unwrap_or(1)
+}
+
+#[cfg(test)]
+mod test {
+    use cairo::{Format, ImageSurface};
+
+    use super::*;
+
+    fn surface(scale: i32) -> ImageSurface {
+        ImageSurface::create(Format::ARgb32, 10 * scale, 4 * scale).unwrap()
+    }
+
+    #[test]
+    fn variants_nearest_scale() {
+        assert_eq!(Variants::parse_key("scale:2"), Some(2));
+        assert_eq!(Variants::parse_key("2"), None);
+        assert_eq!(Variants::parse_key("scale:0"), None);
+        assert_eq!(Variants::parse_key("scale:9"), None);
+        assert_eq!(Variants::parse_key("dark"), None);
+        assert_eq!(nearest(&[1], 3), 1);
+        assert_eq!(nearest(&[1, 2], 3), 2);
+        assert_eq!(nearest(&[1, 3], 2), 3);
+        assert_eq!(nearest(&[1, 4], 2), 1);
+    }
+
+    #[test]
+    fn variants_needed_once_per_scale() {
+        let mut variants = Variants::default();
+        assert_eq!(variants.set_output_scale(1), None);
+        // Migrated to a 2x output, shown at 1x until Lua paints for it.
+        assert_eq!(variants.set_output_scale(2), Some(2));
+        assert_eq!(variants.shown(), 1);
+        assert_eq!(variants.set_output_scale(1), None);
+        assert_eq!(variants.set_output_scale(2), None);
+        variants.insert(2, surface(2));
+        assert_eq!(variants.shown(), 2);
+        assert_eq!(variants.set_output_scale(1), None);
+        assert_eq!(variants.shown(), 1);
+        assert_eq!(variants.set_output_scale(3), Some(3));
+        assert_eq!(variants.shown(), 2);
+        assert_eq!(variants.set_output_scale(3), None);
+        // Resized: painted again for the output it's on.
+        variants.clear();
+        assert_eq!(variants.shown(), 1);
+        assert_eq!(variants.set_output_scale(3), Some(3));
+        assert_eq!(variants.set_output_scale(2), Some(2));
+    }
+
+    #[test]
+    fn variants_own_scale() {
+        let mut variants = Variants::default();
+        variants.set_own_scale(2);
+        // The drawable's own surface is painted for a 2x output.
+        assert_eq!(variants.set_output_scale(2), None);
+        assert_eq!(variants.scales(), vec![2]);
+        assert_eq!(variants.shown(), 2);
+        assert!(variants.is_empty());
+        variants.insert(1, surface(1));
+        assert_eq!(variants.set_output_scale(1), None);
+        assert_eq!(variants.shown(), 1);
+        assert_eq!(variants.scales(), vec![1, 2]);
+    }
+
+    #[test]
+    fn variants_inactive_dropped() {
+        let mut variants = Variants::default();
+        assert_eq!(variants.set_output_scale(2), Some(2));
+        variants.insert(2, surface(2));
+        variants.insert(3, surface(3));
+        variants.insert(4, surface(4));
+        assert_eq!(variants.bytes(), (80 * 8 + 120 * 12 + 160 * 16) as u64);
+        // The shown variant and the one being painted are kept.
+        assert_eq!(variants.drop_inactive(&[4]), 120 * 12);
+        assert_eq!(variants.scales(), vec![1, 2, 4]);
+        assert_eq!(variants.drop_inactive(&[]), 160 * 16);
+        assert_eq!(variants.scales(), vec![1, 2]);
+        // Lua is told again once a dropped scale is needed.
+        variants.insert(3, surface(3));
+        variants.drop_inactive(&[]);
+        assert_eq!(variants.set_output_scale(3), Some(3));
+        assert_eq!(variants.set_output_scale(2), None);
+    }
+}
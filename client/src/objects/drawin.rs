@@ -4,29 +4,205 @@
 // NOTE need to store the drawable in lua, because it's a reference to a
 // drawable a lua object
 
-use cairo::ImageSurface;
-use rlua::{self, prelude::LuaInteger, Table, ToLua, UserData, UserDataMethods};
+mod anchor;
+mod description;
+mod drag;
+mod edge_claims;
+mod focus;
+mod follow;
+mod import;
+mod inhibit;
+mod input_trace;
+mod keys;
+mod layer;
+mod migration;
+mod osk;
+pub mod owned;
+mod struts;
+mod window_type;
 
-use crate::area::{Area, Origin, Size};
+use std::{
+    cell::{Cell, RefCell},
+    convert::TryFrom,
+    fmt, mem,
+    os::unix::io::RawFd,
+    rc::Rc,
+    sync::atomic::{AtomicUsize, Ordering}
+};
+
+use cairo::{ImageSurface, Surface};
+use rlua::{
+    self, prelude::LuaInteger, AnyUserData, FromLua, Function, LightUserData, MetaMethod, MultiValue, Table,
+    ToLua, ToLuaMulti, UserData, UserDataMethods, Value
+};
+use wayland_client::protocol::wl_surface::WlSurface;
+use xkbcommon::xkb;
+
+use crate::accessibility;
+use crate::area::{self, AnchorEdge, AnchorSet, Area, Margin, Origin, Size};
+#[cfg(feature = "client-api")]
+use crate::client_api::{self, DrawinSnapshot};
+use crate::clock;
 use crate::common::{
-    class::{self, Class, ClassBuilder},
+    class::{self, Class, ClassDef},
+    color::{self, Color},
+    color_profile::Profile,
     object::{self, Object, ObjectBuilder},
-    property::Property
+    schema, signal
+};
+use crate::crash::Escaped;
+use crate::keygrabber;
+use crate::leaks::{self, Created, Finding, Owner as LeakOwner};
+use crate::lua;
+use crate::memory_pressure::Memory;
+use crate::objects::{
+    button::{Button, ButtonState},
+    drawable::{self, ContentFit, Drawable, Effect, PixelFormat},
+    screen::{Screen, SCREENS_HANDLE}
+};
+use crate::resume::{self, Kind, Removal};
+use crate::scheduler::{self, Priority};
+use crate::wayland_obj::{
+    self, ImportedBuffer, InputInhibitor, KeyboardEvent, KeyboardInteractivity, LayerSurface, Output,
+    PointerEvent, ShortcutsInhibitor, VirtualKeyboard
 };
-use crate::objects::drawable::Drawable;
+
+use self::anchor::Anchor;
+use self::description::{Applied, Description};
+use self::drag::{Corner, Drag, Drags, Mode as DragMode};
+#[cfg(feature = "client-api")]
+use self::edge_claims::Edge;
+use self::edge_claims::{Claim, EdgeClaims, Ownership, Slot};
+use self::focus::{Change, FocusStack, Priority as FocusPriority};
+use self::follow::{Constrain, Follow, Following, Follows};
+pub use self::import::sweep as sweep_imports;
+use self::import::{ImportError, Imports, Layout};
+use self::inhibit::{Action as InhibitAction, Inhibits};
+use self::input_trace::{Entry, InputTrace, Stage};
+use self::keys::DRAWIN_SCHEMA;
+use self::layer::Layer;
+use self::migration::{Action, Migration, OutputId, Policy};
+use self::osk::{Action as KeyAction, OskKeymap, Placement};
+use self::owned::Owner;
+use self::struts::{docked, Strut};
+use self::window_type::WindowType;
 
 pub const DRAWINS_HANDLE: &'static str = "__drawins";
 
+/// The key of the `on_memory_pressure` handler in the data of a drawin.
+const MEMORY_PRESSURE_HANDLER: &str = "__on_memory_pressure";
+/// The button bindings of a drawin, see `buttons`.
+const BUTTONS: &str = "__buttons";
+
+static NEXT_DRAWIN_ID: AtomicUsize = AtomicUsize::new(1);
+
+thread_local! {
+    /// The edges owned by drawins with `exclusive_edge_owner` set.
+    static EDGE_CLAIMS: RefCell<EdgeClaims> = RefCell::new(EdgeClaims::default());
+    /// The drawin the pointer is over, and where on it the pointer is.
+    static POINTER_FOCUS: Cell<Option<(DrawinId, f64, f64)>> = Cell::new(None);
+    /// Where the pointer was last seen over one of the drawins, across all
+    /// outputs.
+    static POINTER_POSITION: Cell<Option<(f64, f64)>> = Cell::new(None);
+    /// The drawin the compositor gave the keyboard focus to.
+    static KEYBOARD_FOCUS: Cell<Option<DrawinId>> = Cell::new(None);
+    /// The drawins that follow the pointer.
+    static FOLLOWS: RefCell<Follows> = RefCell::new(Follows::default());
+    /// The drawin that's moved or resized with the pointer.
+    static DRAGS: RefCell<Drags> = RefCell::new(Drags::default());
+    /// The recent input events of drawins that are traced.
+    static INPUT_TRACE: RefCell<InputTrace> = RefCell::new(InputTrace::default());
+    /// The requests of drawins for exclusive keyboard focus.
+    static FOCUS: RefCell<FocusStack> = RefCell::new(FocusStack::default());
+    /// The drawins that want the shortcuts of the compositor.
+    static INHIBITS: RefCell<Inhibits> = RefCell::new(Inhibits::default());
+    /// Sends all input to us while the screen is locked.
+    static INPUT_INHIBITOR: RefCell<Option<InputInhibitor>> = RefCell::new(None);
+    /// The keymap all on-screen keyboards type with.
+    static OSK_KEYMAP: RefCell<OskKeymap> = RefCell::new(OskKeymap::default());
+    /// The virtual keyboard on-screen keyboards type with, created when the
+    /// first key is sent.
+    static VIRTUAL_KEYBOARD: RefCell<Option<VirtualKeyboard>> = RefCell::new(None);
+}
+
+/// Identifies a drawin for the user, e.g. in error messages.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DrawinId(pub usize);
+
+impl Default for DrawinId {
+    fn default() -> Self {
+        DrawinId(NEXT_DRAWIN_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct DrawinState {
     // Note that the drawable is stored in Lua.
     // TODO WINDOW_OBJECT_HEADER??
-    ontop: bool,
+    id: DrawinId,
+    /// The layer the drawin is shown on, `ontop` being the overlay one.
+    layer: Layer,
+    /// What kind of window the drawin is, see `window_type`.
+    window_type: WindowType,
     visible: bool,
+    /// The cursor Lua set, empty for the default one.
     cursor: String,
+    letterbox_color: String,
     geometry: Area,
     geometry_dirty: bool,
-    surface: Option<ImageSurface>
+    edge_ownership: Ownership,
+    layer_surface: Option<LayerSurface>,
+    /// The output the layer surface was created on, if one was chosen.
+    output: Option<Output>,
+    migration: Migration,
+    /// The geometry of the screen the drawin was last placed on.
+    placed_on: Option<Area>,
+    /// The id of the screen the drawin is pinned to, see `set_screen`.
+    screen: Option<usize>,
+    /// Whether content painted by Lua has been shown.
+    painted: bool,
+    /// The opaque region the layer surface was given, see `shape_bounding`.
+    opaque_region: Vec<Area>,
+    /// Whether the content is saved on restart and shown again right after.
+    persist_content: bool,
+    /// Whether the drawin is an on-screen keyboard.
+    osk: bool,
+    /// Whether the drawin asks for the keyboard focus while it's shown, see
+    /// `set_keyboard_focus`.
+    keyboard_focus: bool,
+    /// Whether the on-screen keyboard is shown while a text field is active.
+    osk_auto: bool,
+    /// The id a declarative config knows the drawin by, see `description`.
+    declared_id: Option<String>,
+    /// The geometry Lua last placed the drawin at, which stays the same
+    /// when it follows its output.
+    placed: Area,
+    /// The buffers another program draws into, see `import`.
+    imports: Imports<ImportedBuffer>,
+    /// Inhibits the shortcuts of the compositor, see `inhibit`.
+    shortcuts_inhibitor: Option<ShortcutsInhibitor>,
+    /// When and where the drawin was created, see `leaks`.
+    created: Created,
+    /// The parts of the buffer the last refresh changed, kept so the next
+    /// one doesn't allocate.
+    buffer_damage: Vec<Area>,
+    /// When the drawin was removed, see `resume`.
+    removed: Option<Removal>,
+    /// The program that owns the drawin, see `owned`.
+    owner: Option<Owner>,
+    /// The space Lua asked the drawin to keep windows out of, see `struts`.
+    struts: Margin,
+    /// Whether the surfaces are resized to the geometry soon, see
+    /// `queue_update`.
+    update_queued: bool,
+    /// Whether the drawin is kept on its screen, see `set_clamp_to_output`.
+    clamp_to_output: bool,
+    /// Whether the drawin covers its screen, see `set_fullscreen`.
+    fullscreen: bool,
+    /// The geometry Lua asked for before the drawin was made fullscreen.
+    restored: Area,
+    /// The edges of its screen the drawin is anchored to, see `set_anchor`.
+    anchor: Anchor
 }
 
 unsafe impl Send for DrawinState {}
@@ -36,16 +212,32 @@ pub type Drawin<'lua> = Object<'lua, DrawinState>;
 impl UserData for DrawinState {
     fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
         object::default_add_methods(methods);
+        class::add_eq_by_id(methods, |state: &DrawinState| state.id);
+        methods.add_meta_function(MetaMethod::Index, object_index);
+        methods.add_meta_function(MetaMethod::NewIndex, object_newindex);
+        methods.add_meta_function(MetaMethod::ToString, drawin_tostring);
     }
 }
 
+/// Tells drawins apart when they're printed, e.g. "drawin: 0x... (window 3)".
+fn drawin_tostring<'lua>(lua: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<String> {
+    let DrawinId(id) = drawin.id()?;
+    Ok(format!(
+        "{} (window {})",
+        object::default_tostring(lua, drawin)?,
+        id
+    ))
+}
+
 impl<'lua> Drawin<'lua> {
-    fn new(lua: rlua::Context<'lua>, args: Table<'lua>) -> rlua::Result<Drawin<'lua>> {
+    pub fn new(lua: rlua::Context<'lua>, args: Table<'lua>) -> rlua::Result<Drawin<'lua>> {
         let class = class::class_setup(lua, "drawin")?;
         let mut drawins = lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)?;
-        let drawin = object_setup(lua, Drawin::allocate(lua, class)?)?
-            .handle_constructor_argument(args)?
+        let mut drawin = object_setup(lua, Drawin::allocate(lua, class)?)?
+            .handle_constructor_argument_with_schema(args, &DRAWIN_SCHEMA)?
             .build();
+        drawin.drawable()?.set_associated_data("drawin", drawin.clone())?;
+        drawin.state_mut()?.created = Created::now(lua);
         drawins.push(drawin.clone());
         lua.set_named_registry_value(DRAWINS_HANDLE, drawins.to_lua(lua)?)?;
         Ok(drawin)
@@ -54,257 +246,6112 @@ impl<'lua> Drawin<'lua> {
     /// Get the drawable associated with this drawin.
     ///
     /// It has the surface that is needed to render to the screen.
-    #[allow(dead_code)]
-    pub fn drawable(&mut self) -> rlua::Result<Drawable<'lua>> {
+    pub fn drawable(&self) -> rlua::Result<Drawable<'lua>> {
         self.get_associated_data::<Drawable>("drawable")
     }
 
     fn update_drawing(&mut self, lua: rlua::Context<'lua>) -> rlua::Result<()> {
-        let mut drawable: Drawable = self.get_associated_data("drawable")?;
+        let geometry = {
+            let mut state = self.state_mut()?;
+            // A hidden or empty drawin has no layer surface, the geometry is
+            // applied once it is shown.
+            let Size { width, height } = state.geometry.size;
+            if !state.geometry_dirty || !state.visible || width == 0 || height == 0 {
+                return Ok(());
+            }
+            state.geometry_dirty = false;
+            state.geometry
+        };
+        let shown = self.shown_geometry()?;
+        // An on-screen keyboard keeps windows above it on its screen, and a
+        // drawin with struts an edge of it.
+        let (osk, struts, dock, anchor) = {
+            let state = self.state()?;
+            (
+                state.osk,
+                state.struts,
+                state.window_type == WindowType::Dock,
+                state.anchor
+            )
+        };
+        // The layer surface is on the output of the screen the drawin is
+        // pinned to or mostly on, and placed relative to it.
+        let pinned = self.state()?.screen.is_some();
+        let (on, output) = match self.output_screen(lua, shown)? {
+            Some(screen) => {
+                let state = screen.state()?;
+                (Some(state.geometry), state.outputs.first().cloned())
+            },
+            None => (None, None)
+        };
+        {
+            let state = self.state()?;
+            // Layer surfaces can't move between outputs.
+            if state.layer_surface.is_some() && state.output != output {
+                drop(state);
+                return self.remap(lua);
+            }
+        }
+        let screen = if pinned || osk || dock || struts != Margin::default() {
+            on
+        } else {
+            None
+        };
+        let placement = match screen {
+            Some(screen) if osk => {
+                let placement = Placement::new(shown, screen);
+                Some((AnchorEdge::Bottom, placement.margin, placement.exclusive_zone))
+            },
+            Some(screen) => Strut::new(self.state()?.struts_on(screen), shown, screen)
+                .map(|strut| (strut.edge, strut.margin, strut.exclusive_zone)),
+            None => None
+        };
+        // Only the part of a drawin placed on its own that's on its output
+        // is shown, some compositors don't allow a surface past the edges.
+        let clip = match on {
+            Some(screen) if placement.is_none() && !anchor.is_anchored() && !self.state()?.fullscreen => {
+                shown.visible_within(screen)
+            },
+            _ => None
+        };
+        let mut drawable = self.drawable()?;
+        drawable.set_geometry(lua, geometry)?;
+        drawable.set_clip(clip)?;
         {
             let mut state = self.state_mut()?;
-            if state.geometry_dirty {
-                drawable.set_geometry(lua, state.geometry)?;
-                state.geometry_dirty = false;
+            if state.layer_surface.is_none() {
+                let layer_surface = create_shell(state.id, state.layer, output.as_ref(), anchor.edges)?;
+                state.output = output;
+                wayland_obj::tag_surface(&layer_surface.wl_surface(), drawable.get_color_profile()?);
+                let DrawinId(id) = state.id;
+                if FOCUS.with(|focus| focus.borrow().holder()) == Some(id) {
+                    layer_surface.set_keyboard_interactivity(KeyboardInteractivity::Exclusive);
+                }
+                if let Some(input_region) = drawable.input_region()? {
+                    layer_surface.set_input_region(Some(&input_region));
+                }
+                state.layer_surface = Some(layer_surface);
+            }
+            let layer_surface = state.layer_surface.as_ref().unwrap();
+            if state.fullscreen {
+                // The compositor chooses the size, which is the size of the
+                // output in every configure.
+                layer_surface.set_size(Size::default());
+                layer_surface.set_fullscreen();
+            } else {
+                layer_surface.set_size(clip.map_or(shown.size, |clip| clip.size));
+                match placement {
+                    Some((edge, margin, exclusive_zone)) => {
+                        layer_surface.set_edge_placement(edge, margin, exclusive_zone)
+                    },
+                    // The compositor keeps it along the edges when the output
+                    // changes.
+                    None if anchor.is_anchored() => {
+                        let extent = geometry.origin.x - shown.origin.x;
+                        layer_surface.set_anchors(anchor.edges, anchor.margin(extent))
+                    },
+                    None => {
+                        let origin = on.map(|screen| screen.origin).unwrap_or_default();
+                        let visible = clip.map(|clip| clip.origin).unwrap_or_default();
+                        layer_surface.set_position(Origin {
+                            x: shown.origin.x + visible.x - origin.x,
+                            y: shown.origin.y + visible.y - origin.y
+                        })
+                    }
+                }
+            }
+        }
+        // The variants for other scales were dropped if it was resized.
+        self.update_scale(lua)?;
+        self.refresh_pixmap()
+    }
+
+    /// The geometry of the layer surface, which is grown past the geometry
+    /// Lua set by the shadows of the effects.
+    fn shown_geometry(&self) -> rlua::Result<Area> {
+        let geometry = self.get_geometry()?;
+        let extent = self.drawable()?.effect_extent()?;
+        Ok(Area {
+            origin: Origin {
+                x: geometry.origin.x - extent as i32,
+                y: geometry.origin.y - extent as i32
+            },
+            size: Size {
+                width: geometry.size.width + 2 * extent,
+                height: geometry.size.height + 2 * extent
+            }
+        })
+    }
+
+    /// Where a point of the layer surface is on the content, which is
+    /// inside the shadows of the effects.
+    fn content_position(&self, x: f64, y: f64) -> rlua::Result<(f64, f64)> {
+        let drawable = self.drawable()?;
+        let extent = f64::from(drawable.effect_extent()?);
+        // A clipped layer surface only shows part of it.
+        let clip = drawable.clip()?.map(|clip| clip.origin).unwrap_or_default();
+        Ok((x + f64::from(clip.x) - extent, y + f64::from(clip.y) - extent))
+    }
+
+    /// Shows the content for the scale of the outputs the drawin is on,
+    /// and tells Lua when it has no variant for that scale.
+    fn update_scale(&mut self, lua: rlua::Context<'lua>) -> rlua::Result<()> {
+        let scale = self
+            .state()?
+            .layer_surface
+            .as_ref()
+            .and_then(LayerSurface::output_scale);
+        let scale = match scale {
+            Some(scale) => scale,
+            None => return Ok(())
+        };
+        if let Some(missing) = self.drawable()?.set_output_scale(lua, scale)? {
+            Object::emit_signal(lua, self, "drawin::scale_variant_needed", missing)?;
+        }
+        Ok(())
+    }
+
+    /// Attaches the drawable's buffer to the layer surface and commits it.
+    ///
+    /// Called by the drawable when its contents have changed.
+    pub fn refresh_pixmap(&mut self) -> rlua::Result<()> {
+        let mut drawable = self.drawable()?;
+        let wl_buffer = drawable.wl_buffer()?;
+        let mut damage = std::mem::replace(&mut self.state_mut()?.buffer_damage, Vec::new());
+        let partial = drawable.take_buffer_damage(&mut damage)?;
+        let input_region = drawable.take_input_region()?;
+        let opaque_region = drawable.opaque_region()?;
+        let mut state = self.state_mut()?;
+        let state = &mut *state;
+        let mut painted = state.painted;
+        // An imported buffer is shown instead until it's destroyed.
+        let imported = state.imports.attached().is_some();
+        if let Some(layer_surface) = state.layer_surface.as_ref() {
+            if let (Some((wl_buffer, size)), false) = (wl_buffer.as_ref(), imported) {
+                layer_surface.set_buffer_scale(drawable.buffer_scale()?);
+                layer_surface.set_buffer(wl_buffer, *size, if partial { Some(&damage[..]) } else { None });
+                drawable.buffer_attached()?;
+                layer_surface.request_frame();
+                painted = true;
+            }
+            if let Some(input_region) = input_region {
+                layer_surface.set_input_region(input_region.as_ref().map(Vec::as_slice));
             }
+            if opaque_region != state.opaque_region {
+                layer_surface.set_opaque_region(&opaque_region);
+                state.opaque_region = opaque_region;
+            }
+            layer_surface.commit();
         }
-        self.set_associated_data("drawable", drawable)?;
+        state.painted = painted;
+        state.buffer_damage = damage;
         Ok(())
     }
 
-    pub fn get_visible(&mut self) -> rlua::Result<bool> {
+    /// Whether the compositor isn't done with the last buffer committed to
+    /// the layer surface, so the drawable waits to write the next one.
+    pub fn frame_pending(&self) -> rlua::Result<bool> {
+        let state = self.state()?;
+        Ok(state
+            .layer_surface
+            .as_ref()
+            .map_or(false, LayerSurface::frame_pending))
+    }
+
+    pub fn get_visible(&self) -> rlua::Result<bool> {
         let drawin = self.state()?;
         Ok(drawin.visible)
     }
 
-    fn set_visible(&mut self, lua: rlua::Context<'lua>, val: bool) -> rlua::Result<()> {
+    /// Gives a shown drawin a new layer surface with the current buffer
+    /// attached, after something the layer surface was created with
+    /// changed.
+    fn remap(&mut self, lua: rlua::Context<'lua>) -> rlua::Result<()> {
+        if self.renew_surface(lua)? {
+            // The new layer surface is on top of its layer.
+            self.restack_above(lua)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the layer surface of a shown drawin, returning whether it
+    /// had one.
+    fn renew_surface(&mut self, lua: rlua::Context<'lua>) -> rlua::Result<bool> {
+        let (shown, inhibited) = {
+            let state = self.state()?;
+            (state.layer_surface.is_some(), state.shortcuts_inhibitor.is_some())
+        };
+        if !shown {
+            return Ok(false);
+        }
+        self.unmap()?;
+        self.map(lua)?;
+        // The inhibitor went with the old layer surface.
+        if inhibited {
+            let DrawinId(id) = self.id()?;
+            inhibit_actions(lua, vec![InhibitAction::Create(id)])?;
+        }
+        Ok(true)
+    }
+
+    /// Gives the shown drawins above this one on its layer new layer
+    /// surfaces, bottom first, so they are above it again.
+    ///
+    /// The compositor stacks the surfaces of a layer in the order they were
+    /// created, which is the only say we have in it.
+    fn restack_above(&self, lua: rlua::Context<'lua>) -> rlua::Result<()> {
+        let (id, layer) = {
+            let state = self.state()?;
+            (state.id, state.layer)
+        };
+        let drawins = lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)?;
+        let mut above = false;
+        for mut drawin in drawins {
+            if !above {
+                above = drawin.id()? == id;
+                continue;
+            }
+            if drawin.state()?.layer == layer {
+                drawin.renew_surface(lua)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The position of the drawin in the stacking order, from 0 at the
+    /// bottom.
+    fn stack_index(&self, lua: rlua::Context<'lua>) -> rlua::Result<usize> {
+        let id = self.id()?;
+        let drawins = lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)?;
+        for (index, drawin) in drawins.iter().enumerate() {
+            if drawin.id()? == id {
+                return Ok(index);
+            }
+        }
+        Err(rlua::Error::RuntimeError(format!(
+            "drawin#{} is not in the stacking order",
+            id.0
+        )))
+    }
+
+    /// Moves the drawin to the top of the stacking order, or the bottom,
+    /// and shows the drawins on its layer in the new order.
+    ///
+    /// Drawins on a higher layer are above it whatever their order.
+    pub fn restack(&mut self, lua: rlua::Context<'lua>, top: bool) -> rlua::Result<()> {
+        let from = self.stack_index(lua)?;
+        let mut drawins = lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)?;
+        let to = if top { drawins.len() - 1 } else { 0 };
+        if from == to {
+            return Ok(());
+        }
+        let drawin = drawins.remove(from);
+        drawins.insert(to, drawin);
+        lua.set_named_registry_value(DRAWINS_HANDLE, drawins.clone().to_lua(lua)?)?;
+        if top {
+            self.remap(lua)?;
+        } else {
+            self.restack_above(lua)?;
+        }
+        let moved = if from < to { from..=to } else { to..=from };
+        for drawin in &drawins[moved] {
+            Object::emit_signal(lua, drawin, "property::index", Value::Nil)?;
+        }
+        Ok(())
+    }
+
+    /// The screen the drawin is pinned to, if it's still there.
+    fn pinned_screen(&self, lua: rlua::Context<'lua>) -> rlua::Result<Option<Screen<'lua>>> {
+        let id = match self.state()?.screen {
+            Some(id) => id,
+            None => return Ok(None)
+        };
+        for screen in lua.named_registry_value::<str, Vec<Screen>>(SCREENS_HANDLE)? {
+            let found = {
+                let state = screen.state()?;
+                state.valid && state.id == id
+            };
+            if found {
+                return Ok(Some(screen));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The screen whose output the layer surface of the drawin at `geometry`
+    /// goes on: the one it's pinned to, or else the one with most of it.
+    fn output_screen(&self, lua: rlua::Context<'lua>, geometry: Area) -> rlua::Result<Option<Screen<'lua>>> {
+        if let Some(screen) = self.pinned_screen(lua)? {
+            return Ok(Some(screen));
+        }
+        let mut most = None;
+        let mut most_pixels = 0;
+        for screen in lua.named_registry_value::<str, Vec<Screen>>(SCREENS_HANDLE)? {
+            let pixels = {
+                let state = screen.state()?;
+                if !state.valid || state.outputs.is_empty() {
+                    continue;
+                }
+                state.geometry.overlap(geometry)
+            };
+            if pixels > most_pixels {
+                most = Some(screen);
+                most_pixels = pixels;
+            }
+        }
+        Ok(most)
+    }
+
+    /// The screen the drawin is pinned to, or else the first it's on.
+    pub fn get_screen(&self, lua: rlua::Context<'lua>) -> rlua::Result<Option<Screen<'lua>>> {
+        if let Some(screen) = self.pinned_screen(lua)? {
+            return Ok(Some(screen));
+        }
+        let geometry = self.get_geometry()?;
+        for screen in lua.named_registry_value::<str, Vec<Screen>>(SCREENS_HANDLE)? {
+            let on = {
+                let state = screen.state()?;
+                state.valid && geometry.intersects(state.geometry)
+            };
+            if on {
+                return Ok(Some(screen));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Pins the drawin to the output of `screen`, moving it there if it's
+    /// elsewhere, or lets the compositor choose the output again.
+    ///
+    /// The layer surface of a shown drawin is created again on the output.
+    pub fn set_screen(&mut self, lua: rlua::Context<'lua>, screen: Option<Screen<'lua>>) -> rlua::Result<()> {
+        let (id, to) = match screen {
+            Some(ref screen) => {
+                let state = screen.state()?;
+                (Some(state.id), Some(state.geometry))
+            },
+            None => (None, None)
+        };
+        if self.state()?.screen == id {
+            return Ok(());
+        }
+        let geometry = self.get_geometry()?;
+        let from = match self.get_screen(lua)? {
+            Some(screen) => screen.state()?.geometry,
+            // A drawin that wasn't on a screen goes to its origin.
+            None => Area {
+                origin: geometry.origin,
+                size: Size::default()
+            }
+        };
+        self.state_mut()?.screen = id;
+        if let Some(to) = to {
+            if !geometry.intersects(to) {
+                self.apply_geometry(lua, migration::relocate(geometry, from, to))?;
+                self.state_mut()?.placed_on = Some(to);
+            }
+        }
+        self.reanchor(lua)?;
+        self.remap(lua)?;
+        update_workareas(lua)?;
+        Object::emit_signal(lua, self, "property::screen", Value::Nil)
+    }
+
+    /// Shows the drawin on the layer of `window_type`, keeping the edge it's
+    /// along clear if it's a dock.
+    pub fn set_window_type(&mut self, lua: rlua::Context<'lua>, window_type: WindowType) -> rlua::Result<()> {
+        {
+            let mut state = self.state_mut()?;
+            if state.window_type == window_type {
+                return Ok(());
+            }
+            state.window_type = window_type;
+            state.geometry_dirty = true;
+        }
+        self.set_layer(lua, window_type.layer())?;
+        // Placed again if it wasn't shown on another layer.
+        self.update_drawing(lua)?;
+        update_workareas(lua)?;
+        Object::emit_signal(lua, self, "property::type", Value::Nil)
+    }
+
+    pub fn get_layer(&self) -> rlua::Result<Layer> {
+        Ok(self.state()?.layer)
+    }
+
+    /// Shows the drawin on `layer`, the overlay one being above fullscreen
+    /// windows. A shown drawin keeps its surface and what's shown on it,
+    /// and is on top of the drawins already on that layer.
+    pub fn set_layer(&mut self, lua: rlua::Context<'lua>, layer: Layer) -> rlua::Result<()> {
+        let old = std::mem::replace(&mut self.state_mut()?.layer, layer);
+        if old == layer {
+            return Ok(());
+        }
+        let moved = match self.state_mut()?.layer_surface.as_mut() {
+            Some(layer_surface) => Some(layer_surface.set_layer(layer.to_wayland())),
+            None => None
+        };
+        match moved {
+            Some(Ok(())) => self.restack_above(lua)?,
+            // It's shown on a new surface instead.
+            Some(Err(())) => self.remap(lua)?,
+            None => {}
+        }
+        Object::emit_signal(lua, self, "property::layer", Value::Nil)?;
+        if old.is_ontop() != layer.is_ontop() {
+            Object::emit_signal(lua, self, "property::ontop", Value::Nil)?;
+        }
+        Ok(())
+    }
+
+    /// Shows the drawin on a new layer surface, or hides it by destroying
+    /// its layer surface.
+    pub fn set_visible(&mut self, lua: rlua::Context<'lua>, val: bool) -> rlua::Result<()> {
+        if self.state()?.visible == val {
+            return Ok(());
+        }
+        let geometry = self.get_geometry()?;
+        self.claim_edges(lua, geometry, val)?;
         {
             let mut drawin = self.state_mut()?;
             drawin.visible = val;
         }
+        self.moved()?;
         if val {
-            self.map(lua)
+            self.map(lua)?;
+            // It keeps its place in the stacking order.
+            self.restack_above(lua)?;
+            if self.state()?.keyboard_focus {
+                let DrawinId(id) = self.id()?;
+                let changes = FOCUS.with(|focus| focus.borrow_mut().request(id, FocusPriority::Regular));
+                focus_changed(lua, changes)?;
+            }
         } else {
-            self.unmap()
+            self.unmap()?;
+            // A hidden drawin can't take key presses.
+            let DrawinId(id) = self.id()?;
+            let actions = INHIBITS.with(|inhibits| inhibits.borrow_mut().set_locked(id, false));
+            inhibit_actions(lua, actions)?;
+            let changes = FOCUS.with(|focus| focus.borrow_mut().release(id));
+            focus_changed(lua, changes)?;
         }
+        update_workareas(lua)?;
+        Object::emit_signal(lua, self, "property::visible", Value::Nil)
     }
 
+    /// Creates the layer surface of the drawin with the content Lua last
+    /// refreshed, which is attached once the compositor configured the
+    /// surface.
     fn map(&mut self, lua: rlua::Context<'lua>) -> rlua::Result<()> {
-        // TODO other things
+        // The layer surface was destroyed when it was unmapped.
+        self.state_mut()?.geometry_dirty = true;
+        self.drawable()?.set_suspended(false)?;
         self.update_drawing(lua)?;
         Ok(())
     }
 
     fn unmap(&mut self) -> rlua::Result<()> {
-        // TODO?
-        Ok(())
+        // Destroying the layer surface is the only way to hide it.
+        {
+            let mut state = self.state_mut()?;
+            // The inhibitor has to go before its surface.
+            state.shortcuts_inhibitor = None;
+            state.layer_surface = None;
+            state.output = None;
+            state.opaque_region.clear();
+            state.imports.detach();
+        }
+        // Nothing is shown until it's mapped again, so the content Lua
+        // paints meanwhile isn't written to the buffer. The next layer
+        // surface will be configured with a size of its own.
+        let mut drawable = self.drawable()?;
+        drawable.set_suspended(true)?;
+        drawable.set_surface_size(None)
     }
 
     pub fn get_geometry(&self) -> rlua::Result<Area> {
         Ok(self.state()?.geometry)
     }
 
-    fn resize(&mut self, lua: rlua::Context<'lua>, geometry: Area) -> rlua::Result<()> {
-        {
-            let mut state = self.state_mut()?;
-            let old_geometry = state.geometry;
-            state.geometry = geometry;
-            {
-                let Size {
-                    ref mut width,
-                    ref mut height
-                } = state.geometry.size;
-                if *width == 0 {
-                    *width = old_geometry.size.width;
-                }
-                if *height == 0 {
-                    *height = old_geometry.size.height
-                }
+    /// The geometry as Lua sees it, where the origin of an anchored drawin
+    /// is its distance from the anchored edges.
+    fn lua_geometry(&self) -> rlua::Result<Area> {
+        let state = self.state()?;
+        Ok(if state.anchor.is_anchored() {
+            state.geometry.with_origin(state.anchor.offset)
+        } else {
+            state.geometry
+        })
+    }
+
+    /// The geometry a change of a part of it starts from, which is what Lua
+    /// asked for rather than where a clamped drawin is.
+    fn requested_geometry(&self) -> rlua::Result<Area> {
+        let state = self.state()?;
+        if state.clamp_to_output && !state.anchor.is_anchored() {
+            return Ok(state.placed);
+        }
+        drop(state);
+        self.lua_geometry()
+    }
+
+    /// The geometry of the screen an anchored drawin is anchored within:
+    /// the screen it's pinned to or on, or else the first one.
+    fn anchor_screen(&self, lua: rlua::Context<'lua>) -> rlua::Result<Option<Area>> {
+        if let Some(screen) = self.get_screen(lua)? {
+            return Ok(Some(screen.state()?.geometry));
+        }
+        for screen in lua.named_registry_value::<str, Vec<Screen>>(SCREENS_HANDLE)? {
+            let state = screen.state()?;
+            if state.valid {
+                return Ok(Some(state.geometry));
             }
-            state.geometry_dirty = true;
-            // TODO emit signals
-            // TODO update screen workareas like in awesome? Might not be necessary
         }
-        self.update_drawing(lua)
+        Ok(None)
     }
-}
 
-pub fn init(lua: rlua::Context) -> rlua::Result<Class<DrawinState>> {
-    let drawins: Vec<Drawin> = Vec::new();
-    lua.set_named_registry_value(DRAWINS_HANDLE, drawins.to_lua(lua)?)?;
-    property_setup(lua, method_setup(lua, Class::builder(lua, "drawin", None)?)?)?
-        .save_class("drawin")?
-        .build()
-}
-
-fn method_setup<'lua>(
-    lua: rlua::Context<'lua>,
-    builder: ClassBuilder<'lua, DrawinState>
-) -> rlua::Result<ClassBuilder<'lua, DrawinState>> {
-    // TODO Do properly
-    builder
-           // TODO This should be adding properties, e.g like luaA_class_new
-           .method("__call".into(), lua.create_function(|lua, args: Table| Drawin::new(lua, args))?)
-}
-
-fn property_setup<'lua>(
-    lua: rlua::Context<'lua>,
-    builder: ClassBuilder<'lua, DrawinState>
-) -> rlua::Result<ClassBuilder<'lua, DrawinState>> {
-    builder
-        .property(Property::new(
-            "x".into(),
-            Some(lua.create_function(set_x)?),
-            Some(lua.create_function(get_x)?),
-            Some(lua.create_function(set_x)?)
-        ))?
-        .property(Property::new(
-            "y".into(),
-            Some(lua.create_function(set_y)?),
-            Some(lua.create_function(get_y)?),
-            Some(lua.create_function(set_y)?)
-        ))?
-        .property(Property::new(
-            "width".into(),
-            Some(lua.create_function(set_width)?),
-            Some(lua.create_function(get_width)?),
-            Some(lua.create_function(set_width)?)
-        ))?
-        .property(Property::new(
-            "height".into(),
-            Some(lua.create_function(set_height)?),
-            Some(lua.create_function(get_height)?),
-            Some(lua.create_function(set_height)?)
-        ))?
-        .property(Property::new(
-            "visible".into(),
-            Some(lua.create_function(set_visible)?),
-            Some(lua.create_function(get_visible)?),
-            Some(lua.create_function(set_visible)?)
-        ))
-}
+    /// Where the drawin is with `size` if it's anchored.
+    fn anchored_geometry(&self, lua: rlua::Context<'lua>, size: Size) -> rlua::Result<Option<Area>> {
+        let anchor = self.state()?.anchor;
+        if !anchor.is_anchored() {
+            return Ok(None);
+        }
+        Ok(self.anchor_screen(lua)?.map(|screen| anchor.place(size, screen)))
+    }
 
-fn object_setup<'lua>(
-    lua: rlua::Context<'lua>,
-    builder: ObjectBuilder<'lua, DrawinState>
-) -> rlua::Result<ObjectBuilder<'lua, DrawinState>> {
-    // TODO Do properly
-    let table = lua.create_table()?;
-    let drawable_table = Drawable::new(lua)?.to_lua(lua)?;
-    table.set("drawable", drawable_table)?;
-    table.set("geometry", lua.create_function(drawin_geometry)?)?;
-    table.set("struts", lua.create_function(drawin_struts)?)?;
-    table.set("buttons", lua.create_function(super::dummy)?)?;
-    builder.add_to_meta(table)
-}
+    /// Anchors the drawin to `edges` of its screen without moving it, after
+    /// which its origin is the distance from them. No edges place it at its
+    /// position again.
+    pub fn set_anchor(&mut self, lua: rlua::Context<'lua>, edges: AnchorSet) -> rlua::Result<()> {
+        if self.state()?.anchor.edges == edges {
+            return Ok(());
+        }
+        let DrawinId(id) = self.id()?;
+        if FOLLOWS.with(|follows| follows.borrow().is_following(id)) {
+            return Err(rlua::Error::external(Following { drawin: id }));
+        }
+        let geometry = self.get_geometry()?;
+        let anchor = match self.anchor_screen(lua)? {
+            Some(screen) => Anchor::at(edges, geometry, screen),
+            None => Anchor {
+                edges,
+                offset: geometry.origin
+            }
+        };
+        self.state_mut()?.anchor = anchor;
+        // Stretched between two edges, it might be resized.
+        let geometry = self.lua_geometry()?;
+        self.resize(lua, geometry)?;
+        Object::emit_signal(lua, self, "property::anchor", Value::Nil)
+    }
 
-fn set_visible<'lua>(
-    lua: rlua::Context<'lua>,
-    (mut drawin, visible): (Drawin<'lua>, bool)
-) -> rlua::Result<()> {
-    drawin.set_visible(lua, visible)
-    // TODO signal
-}
+    /// `geometry` shrunk and moved onto the screen the drawin is pinned to,
+    /// or else the one `geometry` is on, or the one the drawin is on.
+    fn clamped(&self, lua: rlua::Context<'lua>, geometry: Area) -> rlua::Result<Area> {
+        let mut screen = match self.pinned_screen(lua)? {
+            Some(screen) => Some(screen.state()?.geometry),
+            None => None
+        };
+        if screen.is_none() {
+            for other in lua.named_registry_value::<str, Vec<Screen>>(SCREENS_HANDLE)? {
+                let state = other.state()?;
+                if state.valid && geometry.intersects(state.geometry) {
+                    screen = Some(state.geometry);
+                    break;
+                }
+            }
+        }
+        if screen.is_none() {
+            screen = self.anchor_screen(lua)?;
+        }
+        Ok(screen.map_or(geometry, |screen| geometry.fit_within(screen)))
+    }
 
-fn get_visible<'lua>(_: rlua::Context<'lua>, mut drawin: Drawin<'lua>) -> rlua::Result<bool> {
-    drawin.get_visible()
-    // TODO signal
-}
+    /// Sets whether the drawin is kept on its screen, shrinking and moving
+    /// it where Lua places it past the edges. Unclamped, it goes back to
+    /// where Lua placed it.
+    pub fn set_clamp_to_output(&mut self, lua: rlua::Context<'lua>, clamp: bool) -> rlua::Result<()> {
+        if self.state()?.clamp_to_output == clamp {
+            return Ok(());
+        }
+        let geometry = if clamp {
+            self.lua_geometry()?
+        } else {
+            self.state()?.placed
+        };
+        self.state_mut()?.clamp_to_output = clamp;
+        if geometry.size.width > 0 && geometry.size.height > 0 {
+            self.resize(lua, geometry)?;
+        }
+        Object::emit_signal(lua, self, "property::clamp_to_output", Value::Nil)
+    }
 
-fn drawin_geometry<'lua>(
-    lua: rlua::Context<'lua>,
-    (mut drawin, geometry): (Drawin<'lua>, Option<Table<'lua>>)
-) -> rlua::Result<Table<'lua>> {
-    if let Some(geometry) = geometry {
-        let width = geometry.get::<_, u32>("width")?;
-        let height = geometry.get::<_, u32>("height")?;
-        let x = geometry.get::<_, i32>("x")?;
-        let y = geometry.get::<_, i32>("y")?;
-        if width > 0 && height > 0 {
-            let geo = Area {
-                origin: Origin { x, y },
-                size: Size { width, height }
+    /// Places an anchored or clamped drawin on its screen again after the
+    /// screen was resized, growing a clamped drawin back towards what Lua
+    /// asked for.
+    fn refit(&mut self, lua: rlua::Context<'lua>) -> rlua::Result<()> {
+        let (fullscreen, anchored, clamped, placed) = {
+            let state = self.state()?;
+            (
+                state.fullscreen,
+                state.anchor.is_anchored(),
+                state.clamp_to_output,
+                state.placed
+            )
+        };
+        if fullscreen {
+            return match self.anchor_screen(lua)? {
+                Some(screen) => self.cover(lua, screen),
+                None => Ok(())
             };
-            drawin.resize(lua, geo)?;
         }
+        if anchored {
+            return self.reanchor(lua);
+        }
+        if !clamped || placed.size.width == 0 || placed.size.height == 0 {
+            return Ok(());
+        }
+        let geometry = self.clamped(lua, placed)?;
+        if geometry != self.get_geometry()? {
+            self.apply_geometry(lua, geometry)?;
+        }
+        Ok(())
     }
-    let new_geo = drawin.get_geometry()?;
-    let Size { width, height } = new_geo.size;
-    let Origin { x, y } = new_geo.origin;
-    let res = lua.create_table()?;
-    res.set("x", x)?;
-    res.set("y", y)?;
-    res.set("height", height)?;
-    res.set("width", width)?;
-    Ok(res)
-}
 
-fn get_x<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<LuaInteger> {
-    let Origin { x, .. } = drawin.get_geometry()?.origin;
-    Ok(x as LuaInteger)
-}
+    /// Covers its whole screen with the drawin while `fullscreen` is set,
+    /// or puts it back where Lua placed it before.
+    pub fn set_fullscreen(&mut self, lua: rlua::Context<'lua>, fullscreen: bool) -> rlua::Result<()> {
+        if self.state()?.fullscreen == fullscreen {
+            return Ok(());
+        }
+        if fullscreen {
+            let restored = self.requested_geometry()?;
+            {
+                let mut state = self.state_mut()?;
+                state.fullscreen = true;
+                state.restored = restored;
+            }
+            if let Some(screen) = self.anchor_screen(lua)? {
+                self.cover(lua, screen)?;
+            }
+        } else {
+            let restored = {
+                let mut state = self.state_mut()?;
+                state.fullscreen = false;
+                state.restored
+            };
+            // Placed again, even where the geometry is the same.
+            self.state_mut()?.geometry_dirty = true;
+            if restored.size.width > 0 && restored.size.height > 0 {
+                self.resize(lua, restored)?;
+            } else {
+                self.queue_update()?;
+            }
+        }
+        Object::emit_signal(lua, self, "property::fullscreen", Value::Nil)
+    }
 
-fn set_x<'lua>(lua: rlua::Context<'lua>, (mut drawin, x): (Drawin<'lua>, LuaInteger)) -> rlua::Result<()> {
-    let mut geo = drawin.get_geometry()?;
-    geo.origin.x = x as i32;
-    drawin.resize(lua, geo)?;
-    Ok(())
-}
+    /// Gives a fullscreen drawin the geometry of `area`, e.g. its screen or
+    /// what the compositor configured its layer surface with.
+    fn cover(&mut self, lua: rlua::Context<'lua>, area: Area) -> rlua::Result<()> {
+        if area != self.get_geometry()? && area.size.width > 0 && area.size.height > 0 {
+            self.apply_geometry(lua, area)?;
+        }
+        Ok(())
+    }
 
-fn get_y<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<LuaInteger> {
-    let Origin { y, .. } = drawin.get_geometry()?.origin;
-    Ok(y as LuaInteger)
-}
+    /// Places an anchored drawin on its screen again, e.g. after the screen
+    /// was resized.
+    fn reanchor(&mut self, lua: rlua::Context<'lua>) -> rlua::Result<()> {
+        let geometry = self.get_geometry()?;
+        match self.anchored_geometry(lua, geometry.size)? {
+            Some(anchored) if anchored != geometry => self.apply_geometry(lua, anchored),
+            _ => Ok(())
+        }
+    }
 
-fn set_y<'lua>(lua: rlua::Context<'lua>, (mut drawin, y): (Drawin<'lua>, LuaInteger)) -> rlua::Result<()> {
-    let mut geo = drawin.get_geometry()?;
-    geo.origin.y = y as i32;
-    drawin.resize(lua, geo)?;
-    Ok(())
-}
+    /// Moves the drawin where Lua asked, making the output it's on its
+    /// preferred output.
+    ///
+    /// A drawin that follows the pointer can only be resized.
+    fn resize(&mut self, lua: rlua::Context<'lua>, geometry: Area) -> rlua::Result<()> {
+        let DrawinId(id) = self.id()?;
+        if self.state()?.fullscreen {
+            warn!(
+                "drawin#{} is fullscreen, its geometry is kept until drawin.fullscreen is false",
+                id
+            );
+            return Ok(());
+        }
+        let following = FOLLOWS.with(|follows| follows.borrow().is_following(id));
+        if following && geometry.origin != self.lua_geometry()?.origin {
+            return Err(rlua::Error::external(Following { drawin: id }));
+        }
+        let (anchored, clamped) = {
+            let state = self.state()?;
+            (state.anchor.is_anchored(), state.clamp_to_output)
+        };
+        // What isn't set keeps its size.
+        let current = self.get_geometry()?.size;
+        let requested = geometry.with_size(Size {
+            width: if geometry.size.width > 0 {
+                geometry.size.width
+            } else {
+                current.width
+            },
+            height: if geometry.size.height > 0 {
+                geometry.size.height
+            } else {
+                current.height
+            }
+        });
+        // The origin of an anchored drawin is its distance from the edges.
+        let geometry = if anchored {
+            self.state_mut()?.anchor.offset = geometry.origin;
+            self.anchored_geometry(lua, geometry.size)?.unwrap_or(geometry)
+        } else if clamped {
+            self.clamped(lua, requested)?
+        } else {
+            geometry
+        };
+        self.apply_geometry(lua, geometry)?;
+        let placed = output_at(lua, self.get_geometry()?)?;
+        // A clamped drawin grows back to what Lua asked for with its screen.
+        let lua_geometry = if clamped && !anchored {
+            requested
+        } else {
+            self.lua_geometry()?
+        };
+        let mut state = self.state_mut()?;
+        state.placed = lua_geometry;
+        state.placed_on = placed.as_ref().map(|(_, area)| *area);
+        state.migration.placed(placed.map(|(output, _)| output));
+        Ok(())
+    }
 
-fn get_width<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<LuaInteger> {
-    let Size { width, .. } = drawin.get_geometry()?.size;
-    Ok(width as LuaInteger)
-}
+    fn apply_geometry(&mut self, lua: rlua::Context<'lua>, mut geometry: Area) -> rlua::Result<()> {
+        let (old_geometry, visible) = {
+            let state = self.state()?;
+            (state.geometry, state.visible)
+        };
+        {
+            let Size {
+                ref mut width,
+                ref mut height
+            } = geometry.size;
+            if *width == 0 {
+                *width = old_geometry.size.width;
+            }
+            if *height == 0 {
+                *height = old_geometry.size.height
+            }
+        }
+        // Claim first, so the drawin doesn't move if it would take the edge
+        // of another drawin.
+        self.claim_edges(lua, geometry, visible)?;
+        {
+            let mut state = self.state_mut()?;
+            state.geometry = geometry;
+            state.geometry_dirty = true;
+        }
+        self.moved()?;
+        self.queue_update()?;
+        update_workareas(lua)?;
+        self.emit_geometry_signals(lua, old_geometry, geometry)
+    }
 
-fn set_width<'lua>(
-    lua: rlua::Context<'lua>,
-    (mut drawin, width): (Drawin<'lua>, LuaInteger)
-) -> rlua::Result<()> {
-    let mut geo = drawin.get_geometry()?;
-    if width > 0 {
-        geo.size.width = width as u32;
-        drawin.resize(lua, geo)?;
+    /// Resizes the surfaces of the drawin to its geometry once the Lua code
+    /// that is running is done, so setting `x`, `y`, `width` and `height`
+    /// one after the other allocates and commits only once.
+    fn queue_update(&mut self) -> rlua::Result<()> {
+        let id = {
+            let mut state = self.state_mut()?;
+            if state.update_queued {
+                return Ok(());
+            }
+            state.update_queued = true;
+            state.id
+        };
+        scheduler::defer(Priority::Redraw, move |lua| {
+            if let Some(mut drawin) = find_drawin(lua, id)? {
+                drawin.state_mut()?.update_queued = false;
+                if let Err(err) = drawin.update_drawing(lua) {
+                    warn!("Could not resize drawin#{}: {}", id.0, err);
+                }
+            }
+            Ok(())
+        });
+        Ok(())
     }
-    Ok(())
-}
 
-fn get_height<'lua>(_lua: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<LuaInteger> {
-    let Size { height, .. } = drawin.get_geometry()?.size;
-    Ok(height as LuaInteger)
-}
+    /// Tells Lua which parts of the geometry changed from `old` to `new`.
+    fn emit_geometry_signals(&self, lua: rlua::Context<'lua>, old: Area, new: Area) -> rlua::Result<()> {
+        for signal in area::geometry_signals(old, new) {
+            Object::emit_signal(lua, self, signal, Value::Nil)?;
+        }
+        Ok(())
+    }
 
-fn set_height<'lua>(
-    lua: rlua::Context<'lua>,
-    (mut drawin, height): (Drawin<'lua>, LuaInteger)
+    /// Moves the drawin to `origin` as it follows the pointer, which only
+    /// repositions its layer surface.
+    fn move_to(&mut self, lua: rlua::Context<'lua>, origin: Origin) -> rlua::Result<()> {
+        let geometry = {
+            let state = self.state()?;
+            if state.geometry.origin == origin {
+                return Ok(());
+            }
+            state.geometry.with_origin(origin)
+        };
+        let mut drawable = self.drawable()?;
+        drawable.set_geometry(lua, geometry)?;
+        let extent = drawable.effect_extent()? as i32;
+        {
+            let mut state = self.state_mut()?;
+            state.geometry = geometry;
+            state.placed = geometry;
+        }
+        // Moving past the edges of the output changes what's shown of it.
+        let shown = self.shown_geometry()?;
+        let clipped = match self.output_screen(lua, shown)? {
+            Some(screen) => shown.visible_within(screen.state()?.geometry).is_some(),
+            None => false
+        };
+        if clipped || drawable.clip()?.is_some() {
+            self.state_mut()?.geometry_dirty = true;
+            self.update_drawing(lua)?;
+            return self.moved();
+        }
+        {
+            let mut state = self.state_mut()?;
+            match state.layer_surface.as_ref() {
+                Some(layer_surface) => {
+                    layer_surface.set_position(Origin {
+                        x: origin.x - extent,
+                        y: origin.y - extent
+                    });
+                    layer_surface.commit();
+                },
+                // It's placed when it's shown.
+                None => state.geometry_dirty = true
+            }
+        }
+        self.moved()
+    }
+
+    /// Tells screen readers and the program that owns the drawin where it
+    /// is now.
+    fn moved(&self) -> rlua::Result<()> {
+        let state = self.state()?;
+        accessibility::moved(state.id.0, state.geometry, state.visible);
+        owned::moved(&state);
+        Ok(())
+    }
+
+    /// Hides the drawin and forgets it, so it isn't found by its surface
+    /// or by the id of its description anymore, and releases its buffer.
+    /// Lua can't use it after that, see `resume`.
+    pub fn remove(&mut self, lua: rlua::Context<'lua>) -> rlua::Result<()> {
+        if self.get_visible()? {
+            self.set_visible(lua, false)?;
+        }
+        let imports = {
+            let mut state = self.state_mut()?;
+            state.declared_id = None;
+            state.imports.drain()
+        };
+        import::destroyed(lua, imports)?;
+        let id = self.id()?;
+        accessibility::removed(id.0);
+        let actions = INHIBITS.with(|inhibits| inhibits.borrow_mut().remove(id.0));
+        inhibit_actions(lua, actions)?;
+        if FOLLOWS.with(|follows| follows.borrow_mut().stop(id.0)) {
+            Object::emit_signal(lua, self, "drawin::follow_stopped", Value::Nil)?;
+        }
+        if DRAGS.with(|drags| drags.borrow_mut().stop(id.0)).is_some() {
+            Object::emit_signal(lua, self, "drawin::drag_stopped", Value::Nil)?;
+        }
+        let mut drawins = Vec::new();
+        for drawin in lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)? {
+            if drawin.id()? != id {
+                drawins.push(drawin);
+            }
+        }
+        lua.set_named_registry_value(DRAWINS_HANDLE, drawins.to_lua(lua)?)?;
+        // Lua may keep the drawin around, but never shows it again.
+        self.drawable()?.destroy()?;
+        let mut state = self.state_mut()?;
+        owned::removed(&mut state);
+        if state.removed.is_none() {
+            state.removed = Some(resume::removed());
+        }
+        Ok(())
+    }
+
+    pub fn id(&self) -> rlua::Result<DrawinId> {
+        Ok(self.state()?.id)
+    }
+
+    /// Raises an error if Lua can't use the drawin anymore.
+    fn check_valid(&self) -> rlua::Result<()> {
+        let state = self.state()?;
+        let DrawinId(id) = state.id;
+        resume::check(state.removed, Kind::Destroyed, "drawin", id as u64)
+    }
+
+    /// Attaches the imported buffer to the layer surface and commits it.
+    fn commit_import(&mut self, id: usize) -> rlua::Result<Result<(), ImportError>> {
+        let mut state = self.state_mut()?;
+        let state = &mut *state;
+        let layer_surface = state.layer_surface.as_ref();
+        let buffer = match state.imports.commit(id, layer_surface.is_some()) {
+            Ok(buffer) => buffer,
+            Err(err) => return Ok(Err(err))
+        };
+        let layer_surface = layer_surface.unwrap();
+        // The program draws in the size of the surface, at any scale.
+        layer_surface.set_buffer_scale(1);
+        layer_surface.set_buffer(buffer.wl_buffer(), buffer.size(), None);
+        layer_surface.commit();
+        state.painted = true;
+        Ok(Ok(()))
+    }
+
+    /// Destroys the imported buffer, showing the drawable again if it was
+    /// shown instead.
+    fn remove_import(&mut self, id: usize) -> rlua::Result<()> {
+        let removed = self.state_mut()?.imports.remove(id);
+        match removed {
+            Some((_, true)) => self.refresh_pixmap(),
+            _ => Ok(())
+        }
+    }
+
+    /// The space along the edges of `screen` the drawin keeps windows out
+    /// of, which on-screen keyboards and drawins with struts do.
+    fn struts(&self, screen: Area) -> rlua::Result<Margin> {
+        let state = self.state()?;
+        let mut struts = Margin::default();
+        if !state.visible || !state.geometry.intersects(screen) {
+            return Ok(struts);
+        }
+        if state.osk {
+            struts.bottom = Placement::new(state.geometry, screen).reserved();
+        } else if let Some(strut) = Strut::new(state.struts_on(screen), state.geometry, screen) {
+            struts = strut.reserved();
+        }
+        Ok(struts)
+    }
+
+    /// Sets the space Lua asks the drawin to keep windows out of, which
+    /// places it along the edge it keeps clear, see `struts`.
+    pub fn set_struts(&mut self, lua: rlua::Context<'lua>, struts: Margin) -> rlua::Result<()> {
+        let old = {
+            let mut state = self.state_mut()?;
+            if state.struts == struts {
+                return Ok(());
+            }
+            std::mem::replace(&mut state.struts, struts)
+        };
+        match self.strut_zone(lua, old)? {
+            // The layer surface commits the zone itself, there's nothing
+            // to draw again.
+            Some(exclusive_zone) => {
+                let state = self.state()?;
+                state
+                    .layer_surface
+                    .as_ref()
+                    .unwrap()
+                    .set_exclusive_zone(exclusive_zone);
+            },
+            None => {
+                self.state_mut()?.geometry_dirty = true;
+                // Placed again and committed, if it's shown.
+                self.update_drawing(lua)?;
+            }
+        }
+        update_workareas(lua)?;
+        Object::emit_signal(lua, self, "property::struts", Value::Nil)
+    }
+
+    /// The exclusive zone of the struts the drawin has now, if it's shown
+    /// along the same edge and with the same margins as with the `old`
+    /// struts, so only the zone changes.
+    fn strut_zone(&self, lua: rlua::Context<'lua>, old: Margin) -> rlua::Result<Option<i32>> {
+        {
+            let state = self.state()?;
+            if state.layer_surface.is_none() || state.geometry_dirty || state.osk || state.fullscreen {
+                return Ok(None);
+            }
+        }
+        let shown = self.shown_geometry()?;
+        let screen = match self.output_screen(lua, shown)? {
+            Some(screen) => screen.state()?.geometry,
+            None => return Ok(None)
+        };
+        // Without struts on either side nothing's kept, or a dock keeps
+        // the edge it's along, see `struts_on`, so it's placed again.
+        let old = Strut::new(old, shown, screen);
+        let new = Strut::new(self.state()?.struts, shown, screen);
+        Ok(match (old, new) {
+            (Some(old), Some(new)) if old.edge == new.edge && old.margin == new.margin => {
+                Some(new.exclusive_zone)
+            },
+            _ => None
+        })
+    }
+
+    /// The state of the drawin as it's published to Rust code linked with
+    /// the client.
+    #[cfg(feature = "client-api")]
+    pub fn snapshot(&self, lua: rlua::Context<'lua>) -> rlua::Result<DrawinSnapshot> {
+        let state = self.state()?;
+        let DrawinId(id) = state.id;
+        let geometry = state.geometry;
+        let mut struts = Margin::default();
+        EDGE_CLAIMS.with(|claims| {
+            let claims = claims.borrow();
+            for claim in claims.claims().iter().filter(|claim| claim.drawin == id) {
+                let (strut, thickness) = match claim.slot.edge {
+                    Edge::Top => (&mut struts.top, geometry.size.height),
+                    Edge::Bottom => (&mut struts.bottom, geometry.size.height),
+                    Edge::Left => (&mut struts.left, geometry.size.width),
+                    Edge::Right => (&mut struts.right, geometry.size.width)
+                };
+                *strut = thickness as i32;
+            }
+        });
+        let mut output = None;
+        for screen in lua.named_registry_value::<str, Vec<Screen>>(SCREENS_HANDLE)? {
+            let screen = screen.state()?;
+            if geometry.intersects(screen.geometry) {
+                output = screen.outputs.first().map(|output| output.name());
+                break;
+            }
+        }
+        Ok(DrawinSnapshot {
+            id,
+            geometry,
+            struts,
+            layer: match state.layer {
+                Layer::Background => client_api::Layer::Background,
+                Layer::Bottom => client_api::Layer::Bottom,
+                Layer::Top => client_api::Layer::Top,
+                Layer::Overlay => client_api::Layer::Overlay
+            },
+            output,
+            visible: state.visible,
+            first_paint: state.painted
+        })
+    }
+
+    /// Claims the edges the drawin is placed on at `geometry`, if it owns
+    /// edges and is visible. Edges it no longer is on are released.
+    fn claim_edges(&mut self, lua: rlua::Context<'lua>, geometry: Area, visible: bool) -> rlua::Result<()> {
+        let (DrawinId(id), ownership) = {
+            let state = self.state()?;
+            (state.id, state.edge_ownership)
+        };
+        let (slots, ownership) = if visible && ownership != Ownership::None {
+            (edge_slots(lua, geometry)?, ownership)
+        } else {
+            (Vec::new(), Ownership::None)
+        };
+        let lost = EDGE_CLAIMS
+            .with(|claims| claims.borrow_mut().claim(id, &slots, ownership))
+            .map_err(|err| rlua::Error::RuntimeError(err.to_string()))?;
+        for claim in lost {
+            emit_edge_lost(lua, claim)?;
+        }
+        Ok(())
+    }
+
+    fn set_edge_ownership(&mut self, lua: rlua::Context<'lua>, ownership: Ownership) -> rlua::Result<()> {
+        let (old, geometry, visible) = {
+            let mut state = self.state_mut()?;
+            let old = state.edge_ownership;
+            state.edge_ownership = ownership;
+            (old, state.geometry, state.visible)
+        };
+        if let Err(err) = self.claim_edges(lua, geometry, visible) {
+            self.state_mut()?.edge_ownership = old;
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+/// The key the content of a drawin is saved under on restart.
+///
+/// Drawins are numbered in the order they are created, so a config that
+/// creates its bars in the same order finds its content again.
+fn snapshot_key(DrawinId(id): DrawinId) -> String {
+    format!("drawin-{}", id)
+}
+
+/// Saves the content of the drawins with `persist_content` set, before the
+/// client restarts.
+pub fn save_persisted_content(lua: rlua::Context) -> rlua::Result<()> {
+    for drawin in lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)? {
+        let (id, persist, painted) = {
+            let state = drawin.state()?;
+            (state.id, state.persist_content, state.painted)
+        };
+        if !persist || !painted {
+            continue;
+        }
+        if let Err(err) = drawin.drawable()?.save_snapshot(&snapshot_key(id))? {
+            warn!("Could not save the content of drawin#{}: {}", id.0, err);
+        }
+    }
+    Ok(())
+}
+
+/// Moves the drawins to the outputs their migration policy wants them on,
+/// after an output was connected or disconnected.
+pub fn outputs_changed(lua: rlua::Context) -> rlua::Result<()> {
+    let outputs = connected_outputs(lua)?;
+    let ids: Vec<OutputId> = outputs.iter().map(|(output, _)| output.clone()).collect();
+    for mut drawin in lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)? {
+        // A drawin whose screen went away is on any output again.
+        if drawin.state()?.screen.is_some() && drawin.pinned_screen(lua)?.is_none() {
+            drawin.state_mut()?.screen = None;
+            drawin.remap(lua)?;
+            Object::emit_signal(lua, &drawin, "property::screen", Value::Nil)?;
+        }
+        let actions = drawin.state_mut()?.migration.outputs_changed(&ids);
+        for action in actions {
+            match action {
+                Action::Move(to) => {
+                    let to = match outputs.iter().find(|(output, _)| *output == to) {
+                        Some((_, area)) => *area,
+                        None => continue
+                    };
+                    let (geometry, from) = {
+                        let state = drawin.state()?;
+                        // A drawin that wasn't on an output goes to its origin.
+                        let unplaced = Area {
+                            origin: state.geometry.origin,
+                            size: Size::default()
+                        };
+                        (state.geometry, state.placed_on.unwrap_or(unplaced))
+                    };
+                    drawin.apply_geometry(lua, migration::relocate(geometry, from, to))?;
+                    drawin.state_mut()?.placed_on = Some(to);
+                },
+                Action::Lost(output) => {
+                    Object::emit_signal(lua, &drawin, "drawin::output_lost", output.name)?
+                },
+                Action::Available(output) => {
+                    Object::emit_signal(lua, &drawin, "drawin::output_available", output.name)?
+                },
+            }
+        }
+        drawin.reanchor(lua)?;
+        // The scale of the output it's on might have changed.
+        drawin.update_scale(lua)?;
+    }
+    Ok(())
+}
+
+/// Places the anchored and clamped drawins on their screens again after a
+/// screen was resized, e.g. when the mode of its output changed.
+pub fn update_placement(lua: rlua::Context) -> rlua::Result<()> {
+    for mut drawin in lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)? {
+        drawin.refit(lua)?;
+    }
+    Ok(())
+}
+
+/// The outputs of the screens, with the geometry of their screen.
+fn connected_outputs(lua: rlua::Context) -> rlua::Result<Vec<(OutputId, Area)>> {
+    let mut outputs = Vec::new();
+    for screen in lua.named_registry_value::<str, Vec<Screen>>(SCREENS_HANDLE)? {
+        let state = screen.state()?;
+        if !state.valid {
+            continue;
+        }
+        for output in &state.outputs {
+            let id = OutputId {
+                name: output.name(),
+                make: output.make(),
+                model: output.model()
+            };
+            outputs.push((id, state.geometry));
+        }
+    }
+    Ok(outputs)
+}
+
+/// The output a drawin at `geometry` is on, with the geometry of its screen.
+fn output_at(lua: rlua::Context, geometry: Area) -> rlua::Result<Option<(OutputId, Area)>> {
+    Ok(connected_outputs(lua)?
+        .into_iter()
+        .find(|(_, area)| geometry.intersects(*area)))
+}
+
+/// Sets the workarea of each screen to what the drawins on it leave free.
+///
+/// The struts of the drawins on an edge add up, the way the compositor
+/// stacks the exclusive zones of the layer surfaces along it.
+pub fn update_workareas(lua: rlua::Context) -> rlua::Result<()> {
+    let drawins = lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)?;
+    for mut screen in lua.named_registry_value::<str, Vec<Screen>>(SCREENS_HANDLE)? {
+        let geometry = screen.state()?.geometry;
+        let mut struts = Margin::default();
+        for drawin in &drawins {
+            let reserved = drawin.struts(geometry)?;
+            struts.top += reserved.top;
+            struts.right += reserved.right;
+            struts.bottom += reserved.bottom;
+            struts.left += reserved.left;
+        }
+        screen.set_workarea(lua, geometry.inset(struts))?;
+    }
+    Ok(())
+}
+
+/// The larger of the margins on each edge.
+fn widest(one: Margin, other: Margin) -> Margin {
+    Margin {
+        top: one.top.max(other.top),
+        right: one.right.max(other.right),
+        bottom: one.bottom.max(other.bottom),
+        left: one.left.max(other.left)
+    }
+}
+
+/// Re-evaluates the edges the drawins own, e.g. after the outputs changed.
+///
+/// Edges that are owned by another drawin are skipped, since there's no one
+/// to report the conflict to.
+pub fn update_edge_claims(lua: rlua::Context) -> rlua::Result<()> {
+    for drawin in lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)? {
+        let (DrawinId(id), ownership, geometry, visible) = {
+            let state = drawin.state()?;
+            (state.id, state.edge_ownership, state.geometry, state.visible)
+        };
+        if ownership == Ownership::None || !visible {
+            continue;
+        }
+        let slots = edge_slots(lua, geometry)?;
+        let taken = EDGE_CLAIMS.with(|claims| claims.borrow_mut().reclaim(id, &slots));
+        for slot in taken {
+            warn!(
+                "drawin#{} can't claim the {} edge, it is owned by another drawin",
+                id,
+                slot.edge.name()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The slots a drawin placed at `geometry` is on.
+///
+/// A drawin spanning several screens can be on an edge of each of them.
+fn edge_slots(lua: rlua::Context, geometry: Area) -> rlua::Result<Vec<Slot>> {
+    let mut slots = Vec::new();
+    for screen in lua.named_registry_value::<str, Vec<Screen>>(SCREENS_HANDLE)? {
+        let state = screen.state()?;
+        if !geometry.intersects(state.geometry) {
+            continue;
+        }
+        if let Some(edge) = edge_claims::placed_edge(geometry, state.geometry) {
+            slots.push(Slot {
+                screen: state.id,
+                edge
+            });
+        }
+    }
+    Ok(slots)
+}
+
+fn find_screen<'lua>(lua: rlua::Context<'lua>, id: usize) -> rlua::Result<Option<Screen<'lua>>> {
+    for screen in lua.named_registry_value::<str, Vec<Screen>>(SCREENS_HANDLE)? {
+        if screen.state()?.id == id {
+            return Ok(Some(screen));
+        }
+    }
+    Ok(None)
+}
+
+fn find_drawin<'lua>(lua: rlua::Context<'lua>, id: DrawinId) -> rlua::Result<Option<Drawin<'lua>>> {
+    for drawin in lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)? {
+        if drawin.id()? == id {
+            return Ok(Some(drawin));
+        }
+    }
+    Ok(None)
+}
+
+/// Describes a claim for Lua, as `{ drawin = d, screen = s, edge = "top" }`.
+fn claim_table<'lua>(lua: rlua::Context<'lua>, claim: Claim) -> rlua::Result<Table<'lua>> {
+    let table = lua.create_table()?;
+    table.set("drawin", find_drawin(lua, DrawinId(claim.drawin))?)?;
+    table.set("screen", find_screen(lua, claim.slot.screen)?)?;
+    table.set("edge", claim.slot.edge.name())?;
+    table.set("time", clock::now())?;
+    Ok(table)
+}
+
+fn emit_edge_lost(lua: rlua::Context, claim: Claim) -> rlua::Result<()> {
+    if let Some(drawin) = find_drawin(lua, DrawinId(claim.drawin))? {
+        Object::emit_signal(lua, &drawin, "drawin::edge_lost", claim_table(lua, claim)?)?;
+    }
+    Ok(())
+}
+
+/// Emits the signals for what the pointer did on a drawin, like Awesome:
+/// "mouse::enter" and "mouse::leave" with a table of the position and the
+/// time, "mouse::move" with the position, and "button::press" and
+/// "button::release" with the position, the button and the modifiers.
+/// Every signal is also given the time of the event, in the milliseconds
+/// of `awesome.now()`.
+fn pointer_event(lua: rlua::Context, event: PointerEvent) -> rlua::Result<()> {
+    // Describing the event is only worth it if someone is looking.
+    let mut trace = if INPUT_TRACE.with(|trace| trace.borrow().is_enabled()) {
+        Some(Entry {
+            drawin: None,
+            time: event.time(),
+            stages: vec![Stage::Received(describe_pointer_event(&event))]
+        })
+    } else {
+        None
+    };
+    let result = dispatch_pointer_event(lua, event, &mut trace);
+    if let Some(entry) = trace {
+        INPUT_TRACE.with(|trace| trace.borrow_mut().record(entry));
+    }
+    result
+}
+
+fn dispatch_pointer_event(
+    lua: rlua::Context,
+    event: PointerEvent,
+    trace: &mut Option<Entry>
+) -> rlua::Result<()> {
+    let focus = POINTER_FOCUS.with(Cell::get);
+    let focused = match focus {
+        Some((id, _, _)) => find_drawin(lua, id)?,
+        None => None
+    };
+    match event {
+        PointerEvent::Enter { surface, x, y, time } => {
+            let surface_id = surface.as_ref().id();
+            let drawin = match drawin_of_surface(lua, &surface)? {
+                Some(drawin) => drawin,
+                None => {
+                    trace_stage(trace, || Stage::SurfaceMismatched { surface: surface_id });
+                    return Ok(());
+                }
+            };
+            let id = drawin.id()?;
+            trace_drawin(trace, id);
+            trace_stage(trace, || Stage::SurfaceMatched { surface: surface_id });
+            pointer_moved(&drawin, x, y, trace)?;
+            let (x, y) = drawin.content_position(x, y)?;
+            trace_stage(trace, || Stage::Translated { x, y });
+            POINTER_FOCUS.with(|focus| focus.set(Some((id, x, y))));
+            wayland_obj::set_cursor(drawin.state()?.cursor());
+            let crossing = crossing_table(lua, x, y, time)?;
+            emit_pointer_signal(lua, &drawin, "mouse::enter", crossing, trace)?;
+            emit_pointer_signal(lua, &drawin, "mouse::move", (x, y, time), trace)
+        },
+        PointerEvent::Leave { time, .. } => {
+            POINTER_FOCUS.with(|focus| focus.set(None));
+            let following = FOLLOWS.with(|follows| {
+                let mut follows = follows.borrow_mut();
+                follows.pointer_left();
+                !follows.is_empty()
+            });
+            if following {
+                trace_stage(trace, || Stage::FollowPaused);
+            }
+            match (focused, focus) {
+                (Some(drawin), Some((_, x, y))) => {
+                    trace_drawin(trace, drawin.id()?);
+                    trace_stage(trace, || Stage::Focused);
+                    // Where it was last seen on the drawin.
+                    let crossing = crossing_table(lua, x, y, time)?;
+                    emit_pointer_signal(lua, &drawin, "mouse::leave", crossing, trace)
+                },
+                _ => {
+                    trace_stage(trace, || Stage::NoFocus);
+                    Ok(())
+                }
+            }
+        },
+        PointerEvent::Motion { x, y, time } => match focused {
+            Some(drawin) => {
+                let id = drawin.id()?;
+                trace_drawin(trace, id);
+                trace_stage(trace, || Stage::Focused);
+                pointer_moved(&drawin, x, y, trace)?;
+                let (x, y) = drawin.content_position(x, y)?;
+                trace_stage(trace, || Stage::Translated { x, y });
+                POINTER_FOCUS.with(|focus| focus.set(Some((id, x, y))));
+                emit_pointer_signal(lua, &drawin, "mouse::move", (x, y, time), trace)
+            },
+            None => {
+                trace_stage(trace, || Stage::NoFocus);
+                Ok(())
+            }
+        },
+        PointerEvent::Button {
+            button,
+            pressed,
+            time
+        } => {
+            // Releasing the button ends a drag, wherever the pointer is.
+            if let (false, Some(button)) = (pressed, lua::button_to_lua(button)) {
+                drag_released(lua, button)?;
+            }
+            let (drawin, (_, x, y)) = match (focused, focus) {
+                (Some(drawin), Some(focus)) => (drawin, focus),
+                _ => {
+                    trace_stage(trace, || Stage::NoFocus);
+                    return Ok(());
+                }
+            };
+            trace_drawin(trace, drawin.id()?);
+            trace_stage(trace, || Stage::Focused);
+            trace_stage(trace, || Stage::Translated { x, y });
+            let code = button;
+            let button = match lua::button_to_lua(code) {
+                Some(button) => button,
+                None => {
+                    trace_stage(trace, || Stage::ButtonUnmapped { code });
+                    return Ok(());
+                }
+            };
+            trace_stage(trace, || Stage::ButtonMapped { button });
+            // TODO Pass the modifiers that are held down
+            let mods = lua.create_table()?;
+            let name = if pressed {
+                "button::press"
+            } else {
+                "button::release"
+            };
+            emit_pointer_signal(lua, &drawin, name, (x, y, button, mods, time), trace)?;
+            emit_button_bindings(lua, &drawin, button, pressed, trace)
+        }
+    }
+}
+
+/// The argument of "mouse::enter" and "mouse::leave", with where the
+/// pointer is on the drawin.
+fn crossing_table(lua: rlua::Context, x: f64, y: f64, time: f64) -> rlua::Result<Table> {
+    let table = lua.create_table()?;
+    table.set("x", x)?;
+    table.set("y", y)?;
+    table.set("time", time)?;
+    Ok(table)
+}
+
+/// Where the pointer was last seen over one of the drawins, across all
+/// outputs. The compositor doesn't say where it is elsewhere.
+pub fn pointer_position() -> Option<(f64, f64)> {
+    POINTER_POSITION.with(Cell::get)
+}
+
+/// Remembers that the pointer is at `x`, `y` on the layer surface of
+/// `drawin` and tells the drawins that follow the pointer, queueing them to
+/// be moved there at the end of the frame.
+fn pointer_moved(drawin: &Drawin, x: f64, y: f64, trace: &mut Option<Entry>) -> rlua::Result<()> {
+    let Origin { x: left, y: top } = drawin.shown_geometry()?.origin;
+    let (x, y) = (f64::from(left) + x, f64::from(top) + y);
+    POINTER_POSITION.with(|position| position.set(Some((x, y))));
+    let (queue, followers) = FOLLOWS.with(|follows| {
+        let mut follows = follows.borrow_mut();
+        (follows.pointer_moved((x, y)), follows.followers().count())
+    });
+    if followers > 0 {
+        trace_stage(trace, || Stage::Followed { x, y, followers });
+    }
+    if queue {
+        queue_follow();
+    }
+    if DRAGS.with(|drags| drags.borrow_mut().pointer_moved((x, y))) {
+        queue_drag();
+    }
+    Ok(())
+}
+
+fn queue_follow() {
+    scheduler::defer(Priority::Redraw, |lua| {
+        if let Err(err) = move_followers(lua) {
+            warn!("Could not move the drawins following the pointer: {}", err);
+        }
+        Ok(())
+    });
+}
+
+/// Moves the drawins that follow the pointer to where it was last seen.
+fn move_followers(lua: rlua::Context) -> rlua::Result<()> {
+    let ((x, y), followers) = match FOLLOWS.with(|follows| follows.borrow_mut().take_moves()) {
+        Some(moves) => moves,
+        None => return Ok(())
+    };
+    let pointer = Origin {
+        x: x.floor() as i32,
+        y: y.floor() as i32
+    };
+    let mut screens = Vec::new();
+    for screen in lua.named_registry_value::<str, Vec<Screen>>(SCREENS_HANDLE)? {
+        let state = screen.state()?;
+        if state.valid {
+            screens.push(state.geometry);
+        }
+    }
+    let output = screens
+        .iter()
+        .cloned()
+        .find(|screen| screen.contains(pointer))
+        .or_else(|| screens.first().cloned());
+    for (id, follow) in followers {
+        if let Some(mut drawin) = find_drawin(lua, DrawinId(id))? {
+            let size = drawin.get_geometry()?.size;
+            drawin.move_to(lua, follow::place(pointer, follow, size, output))?;
+        }
+    }
+    Ok(())
+}
+
+fn queue_drag() {
+    scheduler::defer(Priority::Redraw, |lua| {
+        if let Err(err) = move_dragged(lua) {
+            warn!("Could not move the drawin dragged with the pointer: {}", err);
+        }
+        Ok(())
+    });
+}
+
+/// Moves or resizes the dragged drawin for where the pointer went in the
+/// frame.
+fn move_dragged(lua: rlua::Context) -> rlua::Result<()> {
+    let (id, geometry) = match DRAGS.with(|drags| drags.borrow_mut().take_move()) {
+        Some(moved) => moved,
+        None => return Ok(())
+    };
+    match find_drawin(lua, DrawinId(id))? {
+        Some(mut drawin) => drawin.resize(lua, geometry),
+        None => Ok(())
+    }
+}
+
+/// Ends the drag `button` was held down for, moving the drawin to where the
+/// pointer was released.
+fn drag_released(lua: rlua::Context, button: u32) -> rlua::Result<()> {
+    let (drag, last) = match DRAGS.with(|drags| drags.borrow_mut().released(button)) {
+        Some(released) => released,
+        None => return Ok(())
+    };
+    if let Some(mut drawin) = find_drawin(lua, DrawinId(drag.drawin))? {
+        if let Some(geometry) = last {
+            drawin.resize(lua, geometry)?;
+        }
+        Object::emit_signal(lua, &drawin, "drawin::drag_stopped", Value::Nil)?;
+    }
+    Ok(())
+}
+
+fn describe_pointer_event(event: &PointerEvent) -> String {
+    match event {
+        PointerEvent::Enter { .. } => "enter".into(),
+        PointerEvent::Leave { .. } => "leave".into(),
+        PointerEvent::Motion { .. } => "motion".into(),
+        PointerEvent::Button { button, pressed, .. } => {
+            format!(
+                "button {} {}",
+                button,
+                if *pressed { "pressed" } else { "released" }
+            )
+        }
+    }
+}
+
+/// Adds a stage to the trace of the event, if it's traced.
+fn trace_stage<F: FnOnce() -> Stage>(trace: &mut Option<Entry>, stage: F) {
+    if let Some(entry) = trace.as_mut() {
+        entry.stages.push(stage());
+    }
+}
+
+fn trace_drawin(trace: &mut Option<Entry>, DrawinId(id): DrawinId) {
+    if let Some(entry) = trace.as_mut() {
+        entry.drawin = Some(id);
+    }
+}
+
+fn emit_pointer_signal<'lua, A>(
+    lua: rlua::Context<'lua>,
+    drawin: &Drawin<'lua>,
+    name: &'static str,
+    args: A,
+    trace: &mut Option<Entry>
+) -> rlua::Result<()>
+where
+    A: ToLuaMulti<'lua>
+{
+    let mut values = vec![drawin.clone().to_lua(lua)?];
+    values.extend(args.to_lua_multi(lua)?);
+    let (handlers, errors) =
+        signal::emit_signals_with_errors(lua, drawin.signals()?, name, MultiValue::from_vec(values))?;
+    trace_stage(trace, || Stage::Dispatched {
+        signal: name,
+        handlers
+    });
+    for err in errors {
+        trace_stage(trace, || Stage::HandlerErrored(err.to_string()));
+    }
+    Ok(())
+}
+
+/// Hands what the keyboard did to Lua: to the keygrabber while one runs,
+/// like awesome, else to the drawin with the focus, as "key::press" and
+/// "key::release" with the modifiers, the key and the time of the event.
+fn keyboard_event(lua: rlua::Context, event: KeyboardEvent) -> rlua::Result<()> {
+    let (keysym, modifiers, pressed, time) = match event {
+        KeyboardEvent::Enter { surface, .. } => {
+            let focus = match drawin_of_surface(lua, &surface)? {
+                Some(drawin) => Some(drawin.id()?),
+                None => None
+            };
+            KEYBOARD_FOCUS.with(|keyboard_focus| keyboard_focus.set(focus));
+            return Ok(());
+        },
+        KeyboardEvent::Leave { .. } => {
+            KEYBOARD_FOCUS.with(|keyboard_focus| keyboard_focus.set(None));
+            return Ok(());
+        },
+        KeyboardEvent::Key {
+            keysym,
+            modifiers,
+            pressed,
+            time
+        } => (keysym, modifiers, pressed, time)
+    };
+    // Escape puts a dragged drawin back where it was.
+    if pressed && keysym == xkb::keysyms::KEY_Escape {
+        if let Some(drag) = DRAGS.with(|drags| drags.borrow().drag()) {
+            DRAGS.with(|drags| drags.borrow_mut().stop(drag.drawin));
+            if let Some(mut drawin) = find_drawin(lua, DrawinId(drag.drawin))? {
+                drawin.resize(lua, drag.from)?;
+                Object::emit_signal(lua, &drawin, "drawin::drag_stopped", Value::Nil)?;
+            }
+            return Ok(());
+        }
+    }
+    let mods = lua::mods_to_lua(lua, &modifiers)?;
+    let key = xkb::keysym_get_name(keysym);
+    if keygrabber::is_keygrabber_set(lua) {
+        let event = if pressed { "press" } else { "release" };
+        return keygrabber::call_keygrabber(lua, (mods, key, event.into()));
+    }
+    let focused = match KEYBOARD_FOCUS.with(Cell::get) {
+        Some(id) => find_drawin(lua, id)?,
+        None => None
+    };
+    if let Some(drawin) = focused {
+        let name = if pressed { "key::press" } else { "key::release" };
+        let args = (drawin.clone(), mods, key, time).to_lua_multi(lua)?;
+        signal::emit_signals(lua, drawin.signals()?, name, args)?;
+    }
+    Ok(())
+}
+
+/// Emits "press" or "release" with the drawin on its button bindings for
+/// `button`, like awesome. The modifiers held down aren't known, so only
+/// the bindings without modifiers match.
+fn emit_button_bindings<'lua>(
+    lua: rlua::Context<'lua>,
+    drawin: &Drawin<'lua>,
+    button: u32,
+    pressed: bool,
+    trace: &mut Option<Entry>
+) -> rlua::Result<()> {
+    let bindings = match drawin.get_associated_data::<Option<Table>>(BUTTONS)? {
+        Some(bindings) => bindings
+            .sequence_values::<Button>()
+            .collect::<rlua::Result<Vec<_>>>()?,
+        None => return Ok(())
+    };
+    let name = if pressed { "press" } else { "release" };
+    for binding in bindings {
+        let matches = match binding.button()? {
+            Value::Integer(0) => true,
+            Value::Integer(code) => code == LuaInteger::from(button),
+            _ => false
+        };
+        if !matches || !binding.modifiers()?.is_empty() {
+            continue;
+        }
+        let args = MultiValue::from_vec(vec![binding.clone().to_lua(lua)?, drawin.clone().to_lua(lua)?]);
+        let (handlers, errors) = signal::emit_signals_with_errors(lua, binding.signals()?, name, args)?;
+        trace_stage(trace, || Stage::Dispatched {
+            signal: name,
+            handlers
+        });
+        for err in errors {
+            trace_stage(trace, || Stage::HandlerErrored(err.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// The drawin displayed with `wl_surface`, if any.
+fn drawin_of_surface<'lua>(
+    lua: rlua::Context<'lua>,
+    wl_surface: &WlSurface
+) -> rlua::Result<Option<Drawin<'lua>>> {
+    for drawin in lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)? {
+        let shown = match drawin.state()?.layer_surface.as_ref() {
+            Some(layer_surface) => layer_surface.has_surface(wl_surface),
+            None => false
+        };
+        if shown {
+            return Ok(Some(drawin));
+        }
+    }
+    Ok(None)
+}
+
+/// Asks the compositor for keyboard focus for the drawin that holds it now,
+/// and tells Lua.
+fn focus_changed(lua: rlua::Context, changes: Vec<Change>) -> rlua::Result<()> {
+    for change in changes {
+        let (id, interactive, signal) = match change {
+            Change::Revoked(id) => (id, false, "drawin::focus_revoked"),
+            Change::Granted(id) => (id, true, "drawin::focus_granted")
+        };
+        // The shortcuts are inhibited only while the drawin has the focus:
+        // the inhibitor goes before the focus does, and comes after it.
+        if !interactive {
+            let actions = INHIBITS.with(|inhibits| inhibits.borrow_mut().set_focused(id, false));
+            inhibit_actions(lua, actions)?;
+        }
+        let drawin = match find_drawin(lua, DrawinId(id))? {
+            Some(drawin) => drawin,
+            None => continue
+        };
+        if let Some(layer_surface) = drawin.state()?.layer_surface.as_ref() {
+            layer_surface.set_keyboard_interactivity(if interactive {
+                KeyboardInteractivity::Exclusive
+            } else {
+                KeyboardInteractivity::None
+            });
+            layer_surface.commit();
+        }
+        if interactive {
+            let actions = INHIBITS.with(|inhibits| inhibits.borrow_mut().set_focused(id, true));
+            inhibit_actions(lua, actions)?;
+        }
+        Object::emit_signal(lua, &drawin, signal, Value::Nil)?;
+    }
+    // A locked screen takes all input, if the compositor lets it.
+    let locked = FOCUS.with(|focus| focus.borrow().locked());
+    INPUT_INHIBITOR.with(|inhibitor| {
+        let mut inhibitor = inhibitor.borrow_mut();
+        if !locked {
+            *inhibitor = None;
+        } else if inhibitor.is_none() {
+            *inhibitor = wayland_obj::create_input_inhibitor();
+        }
+    });
+    Ok(())
+}
+
+/// Creates and destroys shortcut inhibitors and tells Lua about them.
+fn inhibit_actions(lua: rlua::Context, actions: Vec<InhibitAction>) -> rlua::Result<()> {
+    for action in actions {
+        let id = match action {
+            InhibitAction::Create(id) |
+            InhibitAction::Destroy(id) |
+            InhibitAction::Inhibited(id, _) |
+            InhibitAction::LockWeakened(id, _) => id
+        };
+        let mut drawin = match find_drawin(lua, DrawinId(id))? {
+            Some(drawin) => drawin,
+            None => continue
+        };
+        match action {
+            InhibitAction::Create(_) => {
+                let inhibitor = match drawin.state()?.layer_surface.as_ref() {
+                    Some(layer_surface) => wayland_obj::create_shortcuts_inhibitor(
+                        layer_surface,
+                        Rc::new(move |active| {
+                            scheduler::defer(Priority::Input, move |lua| {
+                                let actions =
+                                    INHIBITS.with(|inhibits| inhibits.borrow_mut().set_active(id, active));
+                                inhibit_actions(lua, actions)
+                            })
+                        })
+                    )
+                    .ok(),
+                    None => None
+                };
+                match inhibitor {
+                    Some(inhibitor) => drawin.state_mut()?.shortcuts_inhibitor = Some(inhibitor),
+                    None => {
+                        let actions = INHIBITS.with(|inhibits| inhibits.borrow_mut().unsupported(id));
+                        inhibit_actions(lua, actions)?;
+                    }
+                }
+            },
+            InhibitAction::Destroy(_) => drawin.state_mut()?.shortcuts_inhibitor = None,
+            InhibitAction::Inhibited(_, inhibited) => {
+                Object::emit_signal(lua, &drawin, "property::shortcuts_inhibited", inhibited)?
+            },
+            InhibitAction::LockWeakened(_, reason) => {
+                warn!(
+                    "The compositor's shortcuts are not inhibited for lock screen drawin#{} ({}), \
+                     they may get past the lock",
+                    id, reason
+                );
+                Object::emit_signal(lua, &drawin, "drawin::lock_weakened", reason)?
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Creates the layer surface that displays a drawin.
+fn create_shell(
+    id: DrawinId,
+    layer: Layer,
+    output: Option<&Output>,
+    edges: AnchorSet
+) -> rlua::Result<LayerSurface> {
+    let layer_surface = wayland_obj::create_layer_surface(
+        output.map(Output::wl_output),
+        layer.to_wayland(),
+        wayland_obj::anchor_of(edges)
+    )
+    .map_err(|_| rlua::Error::RuntimeError("Could not create layer surface for drawin".into()))?;
+    let wl_surface = layer_surface.wl_surface();
+    layer_surface.on_configure(Rc::new(move |size| {
+        let wl_surface = wl_surface.clone();
+        scheduler::defer(Priority::Redraw, move |lua| {
+            if let Err(err) = configured(lua, &wl_surface, size) {
+                warn!("Could not resize drawin#{}: {}", id.0, err);
+            }
+            Ok(())
+        })
+    }));
+    layer_surface.on_frame(Rc::new(move || {
+        scheduler::defer(Priority::Redraw, move |lua| {
+            if let Some(drawin) = find_drawin(lua, id)? {
+                if let Err(err) = drawin.drawable()?.flush_throttled(lua) {
+                    warn!("Could not refresh drawin#{}: {}", id.0, err);
+                }
+            }
+            Ok(())
+        })
+    }));
+    let wl_surface = layer_surface.wl_surface();
+    layer_surface.on_closed(Rc::new(move || {
+        let wl_surface = wl_surface.clone();
+        scheduler::defer(Priority::Redraw, move |lua| {
+            if let Err(err) = closed(lua, &wl_surface) {
+                warn!("Could not hide drawin#{}: {}", id.0, err);
+            }
+            Ok(())
+        })
+    }));
+    layer_surface.on_outputs_changed(Rc::new(move || {
+        scheduler::defer(Priority::Redraw, move |lua| {
+            if let Some(mut drawin) = find_drawin(lua, id)? {
+                if let Err(err) = drawin.update_scale(lua) {
+                    warn!("Could not rescale drawin#{}: {}", id.0, err);
+                }
+            }
+            Ok(())
+        })
+    }));
+    Ok(layer_surface)
+}
+
+/// Called when the compositor granted the layer surface of a drawin a new
+/// size.
+///
+/// A drawin hidden or shown again since has no or another layer surface,
+/// whose size this isn't.
+fn configured(lua: rlua::Context, wl_surface: &WlSurface, size: Size) -> rlua::Result<()> {
+    match drawin_of_surface(lua, wl_surface)? {
+        Some(mut drawin) => {
+            // It was configured again since, with a size this will be
+            // called with too.
+            let configured_size = drawin
+                .state()?
+                .layer_surface
+                .as_ref()
+                .and_then(LayerSurface::configured_size);
+            if configured_size != Some(size) {
+                return Ok(());
+            }
+            owned::configured(&drawin, size)?;
+            // A fullscreen drawin is as large as its output.
+            if drawin.state()?.fullscreen {
+                let origin = drawin.get_geometry()?.origin;
+                drawin.cover(lua, Area { origin, size })?;
+            }
+            let mut drawable = drawin.drawable()?;
+            // A clipped drawin is granted the part of it that's shown.
+            let size = match drawable.clip()? {
+                Some(clip) if clip.size == size => drawin.shown_geometry()?.size,
+                _ => size
+            };
+            drawable.set_surface_size(Some(size))
+        },
+        None => Ok(())
+    }
+}
+
+/// Called when the compositor closed the layer surface of a drawin, which
+/// hides the drawin after telling Lua with `drawin::closed`. Showing it
+/// again gives it a new layer surface.
+///
+/// A drawin hidden or shown again since has no or another layer surface.
+fn closed(lua: rlua::Context, wl_surface: &WlSurface) -> rlua::Result<()> {
+    if let Some(mut drawin) = drawin_of_surface(lua, wl_surface)? {
+        Object::emit_signal(lua, &drawin, "drawin::closed", Value::Nil)?;
+        drawin.set_visible(lua, false)?;
+    }
+    Ok(())
+}
+
+pub fn init(lua: rlua::Context) -> rlua::Result<Class<DrawinState>> {
+    let drawins: Vec<Drawin> = Vec::new();
+    lua.set_named_registry_value(DRAWINS_HANDLE, drawins.to_lua(lua)?)?;
+    wayland_obj::on_pointer_event(Rc::new(|event| {
+        scheduler::defer(Priority::Input, move |lua| {
+            if let Err(err) = pointer_event(lua, event) {
+                warn!("Could not handle pointer event: {}", err);
+            }
+            Ok(())
+        })
+    }));
+    wayland_obj::on_keyboard_event(Rc::new(|event| {
+        scheduler::defer(Priority::Input, move |lua| {
+            if let Err(err) = keyboard_event(lua, event) {
+                warn!("Could not handle keyboard event: {}", err);
+            }
+            Ok(())
+        })
+    }));
+    wayland_obj::on_buffer_release(Rc::new(|id| {
+        scheduler::defer(Priority::Redraw, move |lua| {
+            if let Err(err) = import::released(lua, id) {
+                warn!("Could not handle the release of an imported buffer: {}", err);
+            }
+            Ok(())
+        })
+    }));
+    wayland_obj::on_text_input(Rc::new(|active| {
+        scheduler::defer(Priority::Input, move |lua| {
+            if let Err(err) = text_input_changed(lua, active) {
+                warn!("Could not show or hide the on-screen keyboards: {}", err);
+            }
+            Ok(())
+        })
+    }));
+    // Lua doesn't expect property signals from drawins yet.
+    ClassDef::new(lua, "drawin")?
+        .without_property_signals()
+        .constructor(Drawin::new)?
+        .class_method("edge_claims", edge_claims)?
+        .class_method("input_trace", input_trace_all)?
+        .class_method("describe", describe)?
+        .class_method("apply_description", apply_description)?
+        .class_method("reconcile", reconcile)?
+        .class_method("get", get_drawins)?
+        .class_method("instances", instances)?
+        .class_method("by_id", by_id)?
+        .class_method("follow_stats", follow_stats)?
+        .class_method("__index", class_index)?
+        .class_method("__newindex", class_newindex)?
+        .property("anchor", get_anchor, set_anchor)?
+        .property("clamp_to_output", get_clamp_to_output, set_clamp_to_output)?
+        .property("fullscreen", get_fullscreen, set_fullscreen)?
+        .property("x", get_x, set_x)?
+        .property("y", get_y, set_y)?
+        .property("width", get_width, set_width)?
+        .property("height", get_height, set_height)?
+        .property("type", get_type, set_type)?
+        .property("ontop", get_ontop, set_ontop)?
+        .property("layer", get_layer, set_layer)?
+        .property("screen", get_screen, set_screen)?
+        .property("cursor", get_cursor, set_cursor)?
+        .property("content_fit", get_content_fit, set_content_fit)?
+        .property("format", get_format, set_format)?
+        .property("letterbox_color", get_letterbox_color, set_letterbox_color)?
+        .property("input_from_alpha", get_input_from_alpha, set_input_from_alpha)?
+        .property("alpha_threshold", get_alpha_threshold, set_alpha_threshold)?
+        .property("input_scan_frames", get_input_scan_frames, set_input_scan_frames)?
+        .property("input_passthrough", get_input_passthrough, set_input_passthrough)?
+        .property("shape_bounding", get_shape_bounding, set_shape_bounding)?
+        .property("effects", get_effects, set_effects)?
+        .property("color_profile", get_color_profile, set_color_profile)?
+        .property("opacity", get_opacity, set_opacity)?
+        .property("accessible", get_accessible, set_accessible)?
+        .property("visible", get_visible, set_visible)?
+        .read_only("id", get_id)?
+        .read_only("window", get_id)?
+        .read_only("index", get_index)?
+        .read_only("owner", get_owner)?
+        .property(
+            "exclusive_edge_owner",
+            get_exclusive_edge_owner,
+            set_exclusive_edge_owner
+        )?
+        .property("trace_input", get_trace_input, set_trace_input)?
+        .property("migration_policy", get_migration_policy, set_migration_policy)?
+        .property("preferred_output", get_preferred_output, set_preferred_output)?
+        .property("persist_content", get_persist_content, set_persist_content)?
+        .property("osk", get_osk, set_osk)?
+        .property("osk_auto", get_osk_auto, set_osk_auto)?
+        .property(
+            "on_memory_pressure",
+            get_on_memory_pressure,
+            set_on_memory_pressure
+        )?
+        .property("keyboard_focus", get_keyboard_focus, set_keyboard_focus)?
+        .read_only("has_focus", get_has_focus)?
+        .read_only("following", get_following)?
+        .read_only("dragging", get_dragging)?
+        .read_only("shortcuts_inhibited", get_shortcuts_inhibited)?
+        .object_method("geometry", drawin_geometry)?
+        .object_method("shown_geometry", drawin_shown_geometry)?
+        .object_method("struts", drawin_struts)?
+        .object_method("buttons", buttons)?
+        .object_method("input_trace", input_trace)?
+        .object_method("input_scan_stats", input_scan_stats)?
+        .object_method("set_input_region", set_input_region)?
+        .object_method("effect_stats", effect_stats)?
+        .object_method("import_shm", import_shm)?
+        .object_method("raise", raise)?
+        .object_method("lower", lower)?
+        .object_method("remove", remove)?
+        .object_method("request_focus", request_focus)?
+        .object_method("release_focus", release_focus)?
+        .object_method("inhibit_shortcuts", inhibit_shortcuts)?
+        .object_method("send_key", send_key)?
+        .object_method("send_text", send_text)?
+        .object_method("follow_pointer", follow_pointer)?
+        .object_method("unfollow_pointer", unfollow_pointer)?
+        .object_method("start_move", start_move)?
+        .object_method("start_resize", start_resize)?
+        .object_method("set_accessible_regions", set_accessible_regions)?
+        .save()
+}
+
+fn object_setup<'lua>(
+    lua: rlua::Context<'lua>,
+    builder: ObjectBuilder<'lua, DrawinState>
+) -> rlua::Result<ObjectBuilder<'lua, DrawinState>> {
+    let table = lua.create_table()?;
+    let mut drawable = Drawable::new(lua)?;
+    // It's hidden until it's made visible.
+    drawable.set_suspended(true)?;
+    table.set("drawable", drawable)?;
+    builder.add_to_meta(table)
+}
+
+fn set_visible<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawin, visible): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let visible = DRAWIN_SCHEMA.check(lua, "visible", visible)?;
+    drawin.set_visible(lua, visible)
+}
+
+fn get_visible<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<bool> {
+    drawin.get_visible()
+}
+
+/// `drawin.ontop = true` shows the drawin on the overlay layer, above
+/// windows and fullscreen ones, and `false` on the top layer again.
+fn set_ontop<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawin, ontop): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let ontop = DRAWIN_SCHEMA.check(lua, "ontop", ontop)?;
+    drawin.set_layer(lua, Layer::ontop(ontop))
+}
+
+fn get_ontop<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<bool> {
+    Ok(drawin.get_layer()?.is_ontop())
+}
+
+/// `drawin.layer = "bottom"`, the layer shell layer the drawin is shown
+/// on: "background", "bottom", "top" or "overlay".
+fn set_layer<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawin, layer): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let layer: String = DRAWIN_SCHEMA.check(lua, "layer", layer)?;
+    drawin.set_layer(lua, Layer::from_name(&layer).unwrap_or_default())
+}
+
+fn get_layer<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<&'static str> {
+    Ok(drawin.get_layer()?.name())
+}
+
+/// `drawin.anchor = { "top", "left", "right" }`, the edges of its screen
+/// the drawin is anchored to, from "top", "bottom", "left" and "right".
+/// `x` and `y` are then its distance from those edges, see `anchor`.
+fn set_anchor<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawin, anchor): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let names: Vec<String> = DRAWIN_SCHEMA.check(lua, "anchor", anchor)?;
+    let mut edges = AnchorSet::empty();
+    for name in names {
+        edges |= anchor::from_name(&name).ok_or_else(|| {
+            rlua::Error::RuntimeError(format!(
+                "drawin.anchor: expected edges from \"{}\", got \"{}\"",
+                anchor::NAMES.join("\", \""),
+                name
+            ))
+        })?;
+    }
+    drawin.set_anchor(lua, edges)
+}
+
+fn get_anchor<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<Vec<&'static str>> {
+    Ok(drawin.state()?.anchor.edges.iter().map(anchor::name).collect())
+}
+
+/// `drawin.clamp_to_output = true` keeps the drawin on its screen: a
+/// geometry past the edges is shrunk and moved onto it, and grows back when
+/// the screen does.
+fn set_clamp_to_output<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawin, clamp): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let clamp = DRAWIN_SCHEMA.check(lua, "clamp_to_output", clamp)?;
+    drawin.set_clamp_to_output(lua, clamp)
+}
+
+fn get_clamp_to_output<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<bool> {
+    Ok(drawin.state()?.clamp_to_output)
+}
+
+/// `drawin.fullscreen = true` makes the drawin cover its whole screen, e.g.
+/// for a lock screen, following the size of the output. Its geometry can't
+/// be set until it's false again, which puts it back where it was.
+fn set_fullscreen<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawin, fullscreen): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let fullscreen = DRAWIN_SCHEMA.check(lua, "fullscreen", fullscreen)?;
+    drawin.set_fullscreen(lua, fullscreen)
+}
+
+fn get_fullscreen<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<bool> {
+    Ok(drawin.state()?.fullscreen)
+}
+
+/// `drawin.type = "dock"`, what kind of window the drawin is, which
+/// chooses its layer, see `window_type`.
+fn set_type<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawin, window_type): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let window_type: String = DRAWIN_SCHEMA.check(lua, "type", window_type)?;
+    drawin.set_window_type(lua, WindowType::from_name(&window_type).unwrap_or_default())
+}
+
+fn get_type<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<&'static str> {
+    Ok(drawin.state()?.window_type.name())
+}
+
+/// `drawin.screen = s`, with a screen or its index, pins the drawin to the
+/// output of the screen. `nil` lets the compositor choose again.
+fn set_screen<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawin, screen): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let screen = match screen {
+        Value::Nil => None,
+        screen => match DRAWIN_SCHEMA.check(lua, "screen", screen)? {
+            Value::Integer(index) => {
+                let mut screens = lua.named_registry_value::<str, Vec<Screen>>(SCREENS_HANDLE)?;
+                if index < 1 || index as usize > screens.len() {
+                    return Err(rlua::Error::RuntimeError(format!(
+                        "drawin.screen: invalid screen number: {} (of {} existing)",
+                        index,
+                        screens.len()
+                    )));
+                }
+                Some(screens.swap_remove(index as usize - 1))
+            },
+            object => Some(
+                Screen::cast(AnyUserData::from_lua(object, lua)?.into()).map_err(|_| {
+                    rlua::Error::RuntimeError("drawin.screen: expected a screen or its index".into())
+                })?
+            )
+        }
+    };
+    drawin.set_screen(lua, screen)
+}
+
+fn get_screen<'lua>(lua: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<Option<Screen<'lua>>> {
+    drawin.get_screen(lua)
+}
+
+fn set_trace_input<'lua>(
+    lua: rlua::Context<'lua>,
+    (drawin, traced): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let traced = DRAWIN_SCHEMA.check(lua, "trace_input", traced)?;
+    let DrawinId(id) = drawin.id()?;
+    INPUT_TRACE.with(|trace| trace.borrow_mut().set_traced(id, traced));
+    Ok(())
+}
+
+fn get_trace_input<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<bool> {
+    let DrawinId(id) = drawin.id()?;
+    Ok(INPUT_TRACE.with(|trace| trace.borrow().is_traced(id)))
+}
+
+/// The recent input events of the drawin, oldest first, as tables like
+/// `{ drawin = id, time = 1234.0, stages = { "received enter", ... } }`.
+fn input_trace<'lua>(lua: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<Vec<Table<'lua>>> {
+    let DrawinId(id) = drawin.id()?;
+    let entries: Vec<Entry> = INPUT_TRACE.with(|trace| trace.borrow().entries_of(id).cloned().collect());
+    entries
+        .into_iter()
+        .map(|entry| trace_entry_table(lua, entry))
+        .collect()
+}
+
+/// All of the recent input events, including those that didn't reach a
+/// drawin.
+fn input_trace_all<'lua>(lua: rlua::Context<'lua>, _: Value<'lua>) -> rlua::Result<Vec<Table<'lua>>> {
+    let entries: Vec<Entry> = INPUT_TRACE.with(|trace| trace.borrow().entries().cloned().collect());
+    entries
+        .into_iter()
+        .map(|entry| trace_entry_table(lua, entry))
+        .collect()
+}
+
+/// `drawin.describe(d)`, the description of the drawin as a plain table.
+fn describe<'lua>(lua: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<Table<'lua>> {
+    description::describe(lua, &drawin)
+}
+
+/// `drawin.apply_description(desc)`, which creates or updates the drawin
+/// with the id of the description. Returns the drawin and the properties
+/// that were changed, which are all of them if it was created.
+fn apply_description<'lua>(
+    lua: rlua::Context<'lua>,
+    desc: Table<'lua>
+) -> rlua::Result<(Drawin<'lua>, Vec<&'static str>)> {
+    let desc = Description::parse(lua, desc)?;
+    let (drawin, applied) = description::apply(lua, &desc)?;
+    let changed = match applied {
+        Applied::Created => desc.keys(),
+        Applied::Updated(changed) => changed
+    };
+    Ok((drawin, changed))
+}
+
+/// `drawin.reconcile(descs)`, which applies the descriptions and removes
+/// the described drawins that aren't in them. Returns
+/// `{ created = ids, updated = ids, removed = ids }`.
+fn reconcile<'lua>(lua: rlua::Context<'lua>, descs: Vec<Table<'lua>>) -> rlua::Result<Table<'lua>> {
+    let (created, updated, removed) = description::reconcile(lua, descs)?;
+    let table = lua.create_table()?;
+    table.set("created", created)?;
+    table.set("updated", updated)?;
+    table.set("removed", removed)?;
+    Ok(table)
+}
+
+fn trace_entry_table<'lua>(lua: rlua::Context<'lua>, entry: Entry) -> rlua::Result<Table<'lua>> {
+    let table = lua.create_table()?;
+    table.set("drawin", entry.drawin)?;
+    table.set("time", entry.time)?;
+    let stages: Vec<String> = entry.stages.iter().map(ToString::to_string).collect();
+    table.set("stages", stages)?;
+    Ok(table)
+}
+
+impl DrawinState {
+    /// The struts the drawin keeps on `screen`, which for a dock without
+    /// any are the edge it's along.
+    fn struts_on(&self, screen: Area) -> Margin {
+        if self.window_type == WindowType::Dock && self.struts == Margin::default() {
+            docked(self.geometry, screen)
+        } else {
+            self.struts
+        }
+    }
+
+    /// The cursor shown while the pointer is over the drawin.
+    fn cursor(&self) -> &str {
+        if self.cursor.is_empty() {
+            wayland_obj::DEFAULT_CURSOR
+        } else {
+            &self.cursor
+        }
+    }
+
+    /// Who the drawin belongs to, see `leaks`. A hidden drawin is Lua's,
+    /// unless the client shows it by itself.
+    fn owner(&self) -> LeakOwner {
+        if self.owner.is_some() {
+            LeakOwner::Client("owned by another program")
+        } else if self.visible {
+            LeakOwner::Client("shown")
+        } else if self.declared_id.is_some() {
+            LeakOwner::Client("declared")
+        } else if self.osk_auto {
+            LeakOwner::Client("shown with text fields")
+        } else {
+            LeakOwner::Lua
+        }
+    }
+}
+
+/// Removes the drawins Lua lost without removing them, see `leaks`. They
+/// are told with "request::remove" and the reason "orphaned" first.
+pub fn sweep_drawins(lua: rlua::Context) -> rlua::Result<Vec<Finding>> {
+    let mut candidates = Vec::new();
+    for drawin in lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)? {
+        if drawin.state()?.owner() == LeakOwner::Lua {
+            candidates.push(drawin);
+        }
+    }
+    if candidates.is_empty() {
+        return Ok(Vec::new());
+    }
+    let values = candidates
+        .iter()
+        .map(|drawin| drawin.clone().to_lua(lua))
+        .collect::<rlua::Result<Vec<_>>>()?;
+    let registry = lua.named_registry_value::<str, Table>(DRAWINS_HANDLE)?;
+    let reached = leaks::reachable(lua, registry, values)?;
+    let mut findings = Vec::new();
+    for (mut drawin, reached) in candidates.into_iter().zip(reached) {
+        if reached {
+            continue;
+        }
+        let DrawinId(id) = drawin.id()?;
+        findings.push(Finding {
+            category: "drawins",
+            id,
+            created: Some(drawin.state()?.created.clone())
+        });
+        Object::emit_signal(lua, &drawin, "request::remove", "orphaned")?;
+        drawin.remove(lua)?;
+    }
+    Ok(findings)
+}
+
+/// Releases what the client keeps for drawins that were removed, like their
+/// focus requests, see `leaks`.
+pub fn sweep_drawin_state(lua: rlua::Context) -> rlua::Result<Vec<Finding>> {
+    let mut live = Vec::new();
+    for drawin in lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)? {
+        live.push(drawin.id()?.0);
+    }
+    let mut left = FOCUS.with(|focus| focus.borrow().drawins());
+    left.extend(INHIBITS.with(|inhibits| inhibits.borrow().drawins()));
+    left.extend(EDGE_CLAIMS.with(|claims| {
+        claims
+            .borrow()
+            .claims()
+            .iter()
+            .map(|claim| claim.drawin)
+            .collect::<Vec<_>>()
+    }));
+    left.extend(FOLLOWS.with(|follows| follows.borrow().followers().collect::<Vec<_>>()));
+    if let Some((DrawinId(id), _, _)) = POINTER_FOCUS.with(Cell::get) {
+        left.push(id);
+    }
+    left.retain(|id| !live.contains(id));
+    left.sort();
+    left.dedup();
+    let mut findings = Vec::new();
+    for id in left {
+        let changes = FOCUS.with(|focus| focus.borrow_mut().release(id));
+        focus_changed(lua, changes)?;
+        let actions = INHIBITS.with(|inhibits| inhibits.borrow_mut().remove(id));
+        inhibit_actions(lua, actions)?;
+        EDGE_CLAIMS.with(|claims| claims.borrow_mut().release(id));
+        FOLLOWS.with(|follows| follows.borrow_mut().stop(id));
+        DRAGS.with(|drags| drags.borrow_mut().stop(id));
+        if POINTER_FOCUS.with(Cell::get).map(|(focus, _, _)| focus) == Some(DrawinId(id)) {
+            POINTER_FOCUS.with(|focus| focus.set(None));
+        }
+        findings.push(Finding {
+            category: "drawin_state",
+            id,
+            created: None
+        });
+    }
+    Ok(findings)
+}
+
+/// The memory the drawins keep, see `memory_pressure`.
+pub fn memory(lua: rlua::Context) -> rlua::Result<Memory> {
+    let mut memory = Memory::default();
+    for drawin in lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)? {
+        memory += drawin.drawable()?.memory()?;
+        memory.caches += (drawin.state()?.buffer_damage.capacity() * mem::size_of::<Area>()) as u64;
+    }
+    Ok(memory)
+}
+
+/// Drops the buffers of the hidden drawins, returning the memory freed.
+pub fn evict_hidden_buffers(lua: rlua::Context) -> rlua::Result<u64> {
+    let mut freed = 0;
+    for drawin in lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)? {
+        if !drawin.get_visible()? {
+            freed += drawin.drawable()?.evict_buffer()?;
+        }
+    }
+    Ok(freed)
+}
+
+/// Gives back the memory the drawins keep for their next frame, returning
+/// how much.
+pub fn drop_caches(lua: rlua::Context) -> rlua::Result<u64> {
+    let mut freed = 0;
+    for mut drawin in lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)? {
+        freed += drawin.drawable()?.drop_caches()?;
+        let mut state = drawin.state_mut()?;
+        let before = state.buffer_damage.capacity();
+        state.buffer_damage.shrink_to_fit();
+        freed += ((before - state.buffer_damage.capacity()) * mem::size_of::<Area>()) as u64;
+    }
+    Ok(freed)
+}
+
+/// Drops the variants the drawins don't show, returning the memory freed.
+pub fn drop_inactive_variants(lua: rlua::Context) -> rlua::Result<u64> {
+    let mut freed = 0;
+    for drawin in lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)? {
+        freed += drawin.drawable()?.drop_inactive_variants()?;
+    }
+    Ok(freed)
+}
+
+/// Calls the `on_memory_pressure` handlers of the drawins with the name of
+/// the level, those of hidden drawins first, since what they keep isn't
+/// seen.
+pub fn memory_pressure(lua: rlua::Context, level: &str) -> rlua::Result<()> {
+    let mut handlers = Vec::new();
+    for drawin in lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)? {
+        if let Some(handler) = drawin.get_associated_data::<Option<Function>>(MEMORY_PRESSURE_HANDLER)? {
+            handlers.push((drawin.get_visible()?, drawin, handler));
+        }
+    }
+    handlers.sort_by_key(|&(visible, _, _)| visible);
+    for (_, drawin, handler) in handlers {
+        if let Err(err) = handler.call::<_, ()>((level, drawin)) {
+            warn!("drawin.on_memory_pressure failed: {}", err);
+        }
+    }
+    Ok(())
+}
+
+/// Writes the recent input events for a crash report, a line per event
+/// like `drawin=1 time=1234.000 received enter; surface-matched 3`.
+pub fn write_input_trace(out: &mut dyn fmt::Write) -> fmt::Result {
+    INPUT_TRACE
+        .try_with(|trace| {
+            let trace = match trace.try_borrow() {
+                Ok(trace) => trace,
+                Err(_) => return writeln!(out, "(in use)")
+            };
+            for entry in trace.entries() {
+                match entry.drawin {
+                    Some(drawin) => write!(out, "drawin={}", drawin)?,
+                    None => write!(out, "drawin=none")?
+                }
+                write!(out, " time={:.3}", entry.time)?;
+                for (index, stage) in entry.stages.iter().enumerate() {
+                    let separator = if index == 0 { " " } else { "; " };
+                    write!(out, "{}{}", separator, Escaped(stage))?;
+                }
+                writeln!(out)?;
+            }
+            Ok(())
+        })
+        .unwrap_or(Ok(()))
+}
+
+/// Index of the drawin class, which is how `drawin.trace_input_all` is read.
+fn class_index<'lua>(
+    lua: rlua::Context<'lua>,
+    (class, index): (AnyUserData<'lua>, Value<'lua>)
+) -> rlua::Result<Value<'lua>> {
+    if let Value::String(ref string) = index {
+        if string.to_str()? == "trace_input_all" {
+            return INPUT_TRACE.with(|trace| trace.borrow().all()).to_lua(lua);
+        }
+    }
+    let meta = class
+        .get_user_value::<Table>()?
+        .get_metatable()
+        .expect("drawin class had no metatable");
+    meta.raw_get(index)
+}
+
+/// Index of a drawin, which raises an error once it was removed. Whether
+/// it's valid and its id or window can still be read.
+fn object_index<'lua>(
+    lua: rlua::Context<'lua>,
+    (drawin, index): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<Value<'lua>> {
+    let removed = drawin.state()?.removed;
+    if let Value::String(ref string) = index {
+        match string.to_str()? {
+            "valid" => return Ok(Value::Boolean(resume::is_valid(removed))),
+            "id" | "window" => return get_id(lua, drawin)?.to_lua(lua),
+            _ => {}
+        }
+    }
+    drawin.check_valid()?;
+    object::default_index(lua, (drawin, index))
+}
+
+fn object_newindex<'lua>(
+    lua: rlua::Context<'lua>,
+    (drawin, index, val): (Drawin<'lua>, String, Value<'lua>)
+) -> rlua::Result<Value<'lua>> {
+    drawin.check_valid()?;
+    owned::check_lua_write(&drawin, &index)?;
+    object::default_newindex(lua, (drawin, index, val))
+}
+
+/// `drawin.get()`, the drawins that weren't removed, from the bottom of the
+/// stacking order.
+fn get_drawins<'lua>(lua: rlua::Context<'lua>, _: rlua::MultiValue<'lua>) -> rlua::Result<Vec<Drawin<'lua>>> {
+    let mut drawins = lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)?;
+    drawins.retain(|drawin| {
+        drawin
+            .state()
+            .map(|state| state.removed.is_none())
+            .unwrap_or(false)
+    });
+    Ok(drawins)
+}
+
+/// `drawin.instances()`, how many drawins `drawin.get()` returns.
+fn instances<'lua>(lua: rlua::Context<'lua>, args: rlua::MultiValue<'lua>) -> rlua::Result<usize> {
+    Ok(get_drawins(lua, args)?.len())
+}
+
+/// `drawin.by_id(id)`, the drawin with the id, or nil if it was removed.
+fn by_id<'lua>(lua: rlua::Context<'lua>, id: usize) -> rlua::Result<Option<Drawin<'lua>>> {
+    find_drawin(lua, DrawinId(id))
+}
+
+/// New index of the drawin class, which is how `drawin.trace_input_all` is
+/// written.
+fn class_newindex<'lua>(
+    lua: rlua::Context<'lua>,
+    (class, index, val): (AnyUserData<'lua>, String, Value<'lua>)
+) -> rlua::Result<Value<'lua>> {
+    if index != "trace_input_all" {
+        return object::default_newindex::<DrawinState>(lua, (class.into(), index, val));
+    }
+    let all = bool::from_lua(val, lua)?;
+    INPUT_TRACE.with(|trace| trace.borrow_mut().set_all(all));
+    Ok(Value::Nil)
+}
+
+/// `drawin.cursor = "hand1"`, the cursor shown while the pointer is over
+/// the drawin, named after a glyph of the X cursor font. Other names are
+/// taken to be "left_ptr".
+fn set_cursor<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawin, cursor): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let mut cursor: String = DRAWIN_SCHEMA.check(lua, "cursor", cursor)?;
+    if !wayland_obj::is_cursor_name(&cursor) {
+        warn!(
+            "drawin.cursor: unknown cursor \"{}\", using {}",
+            cursor,
+            wayland_obj::DEFAULT_CURSOR
+        );
+        cursor = wayland_obj::DEFAULT_CURSOR.into();
+    }
+    let id = {
+        let mut state = drawin.state_mut()?;
+        if state.cursor() == cursor {
+            return Ok(());
+        }
+        state.cursor = cursor;
+        state.id
+    };
+    // The pointer is already over it.
+    if POINTER_FOCUS.with(Cell::get).map(|(focus, _, _)| focus) == Some(id) {
+        wayland_obj::set_cursor(drawin.state()?.cursor());
+    }
+    Object::emit_signal(lua, &drawin, "property::cursor", Value::Nil)
+}
+
+fn get_cursor<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<String> {
+    Ok(drawin.state()?.cursor().into())
+}
+
+fn set_content_fit<'lua>(
+    lua: rlua::Context<'lua>,
+    (drawin, content_fit): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let content_fit: String = DRAWIN_SCHEMA.check(lua, "content_fit", content_fit)?;
+    let content_fit = ContentFit::from_name(&content_fit).unwrap_or_default();
+    let fill = letterbox_fill(&drawin.state()?.letterbox_color);
+    drawin.drawable()?.set_content_fit(content_fit, fill)
+}
+
+fn get_content_fit<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<&'static str> {
+    Ok(drawin.drawable()?.get_content_fit()?.name())
+}
+
+fn set_format<'lua>(
+    lua: rlua::Context<'lua>,
+    (drawin, format): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let format: String = DRAWIN_SCHEMA.check(lua, "format", format)?;
+    let format = PixelFormat::from_name(&format).unwrap_or_default();
+    drawin.drawable()?.set_format(lua, format)
+}
+
+fn get_format<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<&'static str> {
+    Ok(drawin.drawable()?.format()?.name())
+}
+
+fn set_letterbox_color<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawin, letterbox_color): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let letterbox_color: String = DRAWIN_SCHEMA.check(lua, "letterbox_color", letterbox_color)?;
+    if color::parse_color(&letterbox_color).is_none() {
+        return Err(rlua::Error::RuntimeError(format!(
+            "drawin.letterbox_color: expected a color like \"#rrggbb\", got \"{}\"",
+            letterbox_color
+        )));
+    }
+    let fill = letterbox_fill(&letterbox_color);
+    drawin.state_mut()?.letterbox_color = letterbox_color;
+    let mut drawable = drawin.drawable()?;
+    let content_fit = drawable.get_content_fit()?;
+    drawable.set_content_fit(content_fit, fill)
+}
+
+fn get_letterbox_color<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<String> {
+    Ok(drawin.state()?.letterbox_color.clone())
+}
+
+fn set_input_from_alpha<'lua>(
+    lua: rlua::Context<'lua>,
+    (drawin, from_alpha): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let from_alpha = DRAWIN_SCHEMA.check(lua, "input_from_alpha", from_alpha)?;
+    drawin.drawable()?.set_input_from_alpha(from_alpha)
+}
+
+fn get_input_from_alpha<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<bool> {
+    drawin.drawable()?.input_from_alpha()
+}
+
+/// `drawin:set_input_region{ { x = 0, y = 0, width = 100, height = 20 } }`,
+/// the parts of the drawin that take input, or all of it with `nil`. Input
+/// anywhere else goes through to what is below, so `{}` lets all of it
+/// through.
+fn set_input_region<'lua>(
+    lua: rlua::Context<'lua>,
+    (drawin, region): (Drawin<'lua>, Option<Vec<Table<'lua>>>)
+) -> rlua::Result<()> {
+    let shape = match region {
+        Some(rects) => Some(
+            rects
+                .into_iter()
+                .map(|rect| geometry_from_table(lua, rect, Area::default()))
+                .collect::<rlua::Result<Vec<_>>>()?
+        ),
+        None => None
+    };
+    drawin.drawable()?.set_input_shape(shape)
+}
+
+/// Whether all input goes through the drawin, a shortcut for
+/// `drawin:set_input_region{}`.
+fn set_input_passthrough<'lua>(
+    lua: rlua::Context<'lua>,
+    (drawin, passthrough): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let passthrough = DRAWIN_SCHEMA.check(lua, "input_passthrough", passthrough)?;
+    drawin
+        .drawable()?
+        .set_input_shape(if passthrough { Some(Vec::new()) } else { None })
+}
+
+fn get_input_passthrough<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<bool> {
+    Ok(drawin
+        .drawable()?
+        .input_shape()?
+        .map_or(false, |shape| shape.is_empty()))
+}
+
+/// `drawin.shape_bounding`, the shape of the drawin, as a cairo surface
+/// whose transparent pixels are outside it or as a list of rectangles like
+/// `drawin:set_input_region`. Input outside it goes through to what is
+/// below, and the compositor is told the inside is opaque. Reading it gives
+/// the rectangles.
+fn set_shape_bounding<'lua>(
+    lua: rlua::Context<'lua>,
+    (drawin, shape): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let shape = match shape {
+        Value::Nil => None,
+        shape => Some(shape_from_lua(
+            lua,
+            DRAWIN_SCHEMA.check(lua, "shape_bounding", shape)?
+        )?)
+    };
+    drawin.drawable()?.set_bounding_shape(shape)
+}
+
+fn get_shape_bounding<'lua>(lua: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<Value<'lua>> {
+    match drawin.drawable()?.bounding_shape()? {
+        Some(shape) => shape
+            .into_iter()
+            .map(|rect| area_table(lua, rect))
+            .collect::<rlua::Result<Vec<_>>>()?
+            .to_lua(lua),
+        None => Ok(Value::Nil)
+    }
+}
+
+/// The rectangles of a cairo surface, like awesome's shapes, or of a list
+/// of rectangles.
+fn shape_from_lua<'lua>(lua: rlua::Context<'lua>, shape: Value<'lua>) -> rlua::Result<Vec<Area>> {
+    match shape {
+        Value::LightUserData(LightUserData(ptr)) => {
+            // The surface of an lgi cairo surface, `surface._native`.
+            let surface = unsafe { Surface::from_raw_none(ptr as *mut cairo_sys::cairo_surface_t) };
+            let surface = ImageSurface::from(surface).map_err(|_| {
+                rlua::Error::RuntimeError("drawin.shape_bounding: expected an image surface".into())
+            })?;
+            drawable::shape_of(&surface)
+        },
+        shape => Vec::<Table>::from_lua(shape, lua)?
+            .into_iter()
+            .map(|rect| geometry_from_table(lua, rect, Area::default()))
+            .collect()
+    }
+}
+
+fn set_alpha_threshold<'lua>(
+    lua: rlua::Context<'lua>,
+    (drawin, threshold): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let threshold: i64 = DRAWIN_SCHEMA.check(lua, "alpha_threshold", threshold)?;
+    if threshold < 0 || threshold > 255 {
+        return Err(rlua::Error::RuntimeError(format!(
+            "drawin.alpha_threshold: expected an integer from 0 to 255, got {}",
+            threshold
+        )));
+    }
+    drawin.drawable()?.set_alpha_threshold(threshold as u8)
+}
+
+fn get_alpha_threshold<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<u8> {
+    drawin.drawable()?.alpha_threshold()
+}
+
+fn set_input_scan_frames<'lua>(
+    lua: rlua::Context<'lua>,
+    (drawin, frames): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let frames: i64 = DRAWIN_SCHEMA.check(lua, "input_scan_frames", frames)?;
+    if frames < 1 {
+        return Err(rlua::Error::RuntimeError(format!(
+            "drawin.input_scan_frames: expected a positive integer, got {}",
+            frames
+        )));
+    }
+    drawin
+        .drawable()?
+        .set_input_scan_frames(frames.min(u32::max_value() as i64) as u32)
+}
+
+fn get_input_scan_frames<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<u32> {
+    drawin.drawable()?.input_scan_frames()
+}
+
+/// `drawin:input_scan_stats()`, which returns what finding the input region
+/// from the alpha cost so far, or nil if it isn't.
+fn input_scan_stats<'lua>(lua: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<Value<'lua>> {
+    let stats = match drawin.drawable()?.input_scan_stats()? {
+        Some(stats) => stats,
+        None => return Ok(Value::Nil)
+    };
+    let table = lua.create_table()?;
+    table.set("scans", stats.scans)?;
+    table.set("rows_scanned", stats.rows_scanned)?;
+    table.set("rows_reused", stats.rows_reused)?;
+    table.set("scan_time", stats.time.as_secs_f64())?;
+    Ok(Value::Table(table))
+}
+
+/// `drawin.effects = { "shadow:8:0.5", "dim:0.3" }`, which are applied to
+/// the content in order.
+fn set_effects<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawin, effects): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let keys: Vec<String> = DRAWIN_SCHEMA.check(lua, "effects", effects)?;
+    let effects = keys
+        .iter()
+        .map(|key| Effect::parse(key))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| rlua::Error::RuntimeError(format!("drawin.effects: {}", err)))?;
+    let mut drawable = drawin.drawable()?;
+    let extent = drawable.effect_extent()?;
+    drawable.set_effects(effects)?;
+    if drawable.effect_extent()? != extent {
+        // The layer surface grows or shrinks around the content.
+        drawin.state_mut()?.geometry_dirty = true;
+        drawin.update_drawing(lua)?;
+    }
+    Ok(())
+}
+
+fn get_effects<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<Vec<String>> {
+    Ok(drawin
+        .drawable()?
+        .effects()?
+        .iter()
+        .map(Effect::to_string)
+        .collect())
+}
+
+/// `drawin.opacity = 0.8`, how opaque the drawin is shown, from 0 to 1.
+/// The content isn't painted again, it's copied to the buffer more
+/// transparent.
+fn set_opacity<'lua>(
+    lua: rlua::Context<'lua>,
+    (drawin, opacity): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let opacity: f64 = DRAWIN_SCHEMA.check(lua, "opacity", opacity)?;
+    if drawin.drawable()?.set_opacity(opacity.max(0.0).min(1.0))? {
+        Object::emit_signal(lua, &drawin, "property::opacity", Value::Nil)?;
+    }
+    Ok(())
+}
+
+fn get_opacity<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<f64> {
+    drawin.drawable()?.opacity()
+}
+
+/// `drawin.color_profile = "srgb"`, what the values painted into the
+/// drawin mean, which the compositor is told if it does color management.
+fn set_color_profile<'lua>(
+    lua: rlua::Context<'lua>,
+    (drawin, color_profile): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let color_profile: String = DRAWIN_SCHEMA.check(lua, "color_profile", color_profile)?;
+    let color_profile = Profile::from_name(&color_profile).unwrap_or_default();
+    let mut drawable = drawin.drawable()?;
+    if drawable.get_color_profile()? == color_profile {
+        return Ok(());
+    }
+    drawable.set_color_profile(color_profile)?;
+    if let Some(layer_surface) = drawin.state()?.layer_surface.as_ref() {
+        wayland_obj::tag_surface(&layer_surface.wl_surface(), color_profile);
+    }
+    Ok(())
+}
+
+fn get_color_profile<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<&'static str> {
+    Ok(drawin.drawable()?.get_color_profile()?.name())
+}
+
+/// `drawin.accessible = { role = "status_bar", label = "Main bar" }`, what
+/// screen readers are told the drawin is. `nil` makes it inaccessible
+/// again, which forgets its regions.
+fn set_accessible<'lua>(
+    lua: rlua::Context<'lua>,
+    (drawin, accessible): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let accessible = match accessible {
+        Value::Nil => None,
+        accessible => {
+            let table: Table = DRAWIN_SCHEMA.check(lua, "accessible", accessible)?;
+            Some(accessibility::accessible_from_lua(&table, "drawin.accessible")?)
+        }
+    };
+    let (DrawinId(id), geometry, visible) = {
+        let state = drawin.state()?;
+        (state.id, state.geometry, state.visible)
+    };
+    accessibility::set_accessible(id, accessible, geometry, visible);
+    Ok(())
+}
+
+fn get_accessible<'lua>(lua: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<Value<'lua>> {
+    match accessibility::accessible(drawin.id()?.0) {
+        Some(accessible) => accessibility::accessible_to_lua(lua, &accessible).map(Value::Table),
+        None => Ok(Value::Nil)
+    }
+}
+
+/// `drawin:set_accessible_regions({ { role = "progress_bar", label =
+/// "Battery", value = "45 percent", x = 1700, y = 0, width = 100, height =
+/// 30 }, ... })`, the parts of an accessible drawin, where the area is on
+/// the drawin. Regions that keep their place and role are updated, so a
+/// new value is announced rather than a new object.
+fn set_accessible_regions<'lua>(
+    _: rlua::Context<'lua>,
+    (drawin, regions): (Drawin<'lua>, Vec<Table<'lua>>)
+) -> rlua::Result<()> {
+    let regions = regions
+        .iter()
+        .enumerate()
+        .map(|(index, region)| {
+            let context = format!("drawin:set_accessible_regions: region {}", index + 1);
+            accessibility::region_from_lua(region, &context)
+        })
+        .collect::<rlua::Result<Vec<_>>>()?;
+    let DrawinId(id) = drawin.id()?;
+    if !accessibility::set_regions(id, regions) {
+        return Err(rlua::Error::RuntimeError(
+            "drawin:set_accessible_regions: the drawin isn't accessible, set drawin.accessible first".into()
+        ));
+    }
+    Ok(())
+}
+
+/// `drawin:effect_stats()`, what applying each effect cost so far, in the
+/// order they're applied.
+fn effect_stats<'lua>(lua: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<Vec<Table<'lua>>> {
+    let mut tables = Vec::new();
+    for (effect, stats) in drawin.drawable()?.effect_stats()? {
+        let table = lua.create_table()?;
+        table.set("effect", effect.to_string())?;
+        table.set("runs", stats.runs)?;
+        table.set("pixels", stats.pixels)?;
+        table.set("time", stats.time.as_secs_f64())?;
+        tables.push(table);
+    }
+    Ok(tables)
+}
+
+/// `drawin:import_shm{ fd = fd, width = w, height = h, stride = s, offset =
+/// o }`, which returns a handle of the imported buffer or nil and the
+/// error. The offset defaults to 0.
+fn import_shm<'lua>(
+    lua: rlua::Context<'lua>,
+    (drawin, args): (Drawin<'lua>, Table<'lua>)
+) -> rlua::Result<(Value<'lua>, Value<'lua>)> {
+    owned::check_force(&drawin, Some(&args), "import buffers to")?;
+    let imported = match import_layout(&args)? {
+        Ok(layout) => import_buffer(lua, drawin, layout)?,
+        Err(err) => Err(err)
+    };
+    match imported {
+        Ok(handle) => Ok((Value::UserData(handle), Value::Nil)),
+        Err(err) => Ok((Value::Nil, Value::Table(err.to_lua(lua)?)))
+    }
+}
+
+/// Imports the buffer at `layout` to the drawin, returning its handle.
+fn import_buffer<'lua>(
+    lua: rlua::Context<'lua>,
+    mut drawin: Drawin<'lua>,
+    layout: Layout
+) -> rlua::Result<Result<AnyUserData<'lua>, ImportError>> {
+    let id = import::next_id();
+    let buffer = match wayland_obj::import_buffer(layout.fd, layout.offset, layout.stride, layout.size, id) {
+        Ok(buffer) => buffer,
+        Err(()) => {
+            let err = ImportError::Import("the compositor could not be asked for a buffer".into());
+            return Ok(Err(err));
+        }
+    };
+    drawin.state_mut()?.imports.add(id, buffer);
+    Ok(Ok(import::register(lua, drawin, id)?))
+}
+
+fn import_layout(args: &Table) -> rlua::Result<Result<Layout, ImportError>> {
+    let mut fields = [0; 5];
+    let names = ["fd", "width", "height", "stride", "offset"];
+    for (field, name) in fields.iter_mut().zip(names.iter()) {
+        *field = match args.get::<_, Value>(*name)? {
+            Value::Integer(value) => value,
+            Value::Nil if *name == "offset" => 0,
+            value => {
+                return Ok(Err(ImportError::Invalid(format!(
+                    "expected an integer {}, got {}",
+                    name,
+                    schema::describe(&value)
+                ))))
+            },
+        };
+    }
+    let [fd, width, height, stride, offset] = fields;
+    let file_len = match nix::sys::stat::fstat(fd as RawFd) {
+        Ok(stat) if fd >= 0 => stat.st_size,
+        _ => return Ok(Err(ImportError::Invalid(format!("{} is not an open file", fd))))
+    };
+    Ok(Layout::new(fd, width, height, stride, offset, file_len))
+}
+
+/// The color around letterboxed content, which is transparent by default.
+fn letterbox_fill(letterbox_color: &str) -> Color {
+    color::parse_color(letterbox_color).unwrap_or_default()
+}
+
+/// `drawin:remove(options)`, which hides the drawin for good. A drawin
+/// another program owns is only removed with `force = true`, which tells
+/// the program.
+fn remove<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawin, options): (Drawin<'lua>, Option<Table<'lua>>)
+) -> rlua::Result<()> {
+    drawin.check_valid()?;
+    owned::check_force(&drawin, options.as_ref(), "remove")?;
+    drawin.remove(lua)
+}
+
+fn get_owner<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<Option<String>> {
+    let state = drawin.state()?;
+    Ok(owned::owner_name(&state))
+}
+
+/// `drawin.id`, also `drawin.window` for libraries that key tables by the
+/// X window of a wibox. No other drawin gets it, even once this one is gone.
+fn get_id<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<LuaInteger> {
+    let DrawinId(id) = drawin.id()?;
+    Ok(id as LuaInteger)
+}
+
+/// `drawin.index`, the position of the drawin in the stacking order, from
+/// 1 at the bottom. The layer comes first, the order only stacks drawins on
+/// the same layer.
+fn get_index<'lua>(lua: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<LuaInteger> {
+    Ok(drawin.stack_index(lua)? as LuaInteger + 1)
+}
+
+/// `drawin:raise()`, which shows the drawin above the others on its layer.
+fn raise<'lua>(lua: rlua::Context<'lua>, mut drawin: Drawin<'lua>) -> rlua::Result<()> {
+    drawin.restack(lua, true)
+}
+
+/// `drawin:lower()`, which shows the drawin below the others on its layer.
+fn lower<'lua>(lua: rlua::Context<'lua>, mut drawin: Drawin<'lua>) -> rlua::Result<()> {
+    drawin.restack(lua, false)
+}
+
+fn set_exclusive_edge_owner<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawin, val): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let ownership = match val {
+        Value::Nil => Ownership::None,
+        val => match DRAWIN_SCHEMA.check(lua, "exclusive_edge_owner", val)? {
+            Value::Boolean(false) => Ownership::None,
+            Value::Boolean(true) => Ownership::Exclusive,
+            _ => Ownership::Replace
+        }
+    };
+    drawin.set_edge_ownership(lua, ownership)
+}
+
+fn get_exclusive_edge_owner<'lua>(
+    lua: rlua::Context<'lua>,
+    drawin: Drawin<'lua>
+) -> rlua::Result<Value<'lua>> {
+    match drawin.state()?.edge_ownership {
+        Ownership::None => Ok(Value::Boolean(false)),
+        Ownership::Exclusive => Ok(Value::Boolean(true)),
+        Ownership::Replace => "replace".to_lua(lua)
+    }
+}
+
+fn set_migration_policy<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawin, policy): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let policy: String = DRAWIN_SCHEMA.check(lua, "migration_policy", policy)?;
+    // It takes effect when the outputs change next.
+    drawin.state_mut()?.migration.policy = Policy::from_name(&policy).unwrap_or_default();
+    Ok(())
+}
+
+fn get_migration_policy<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<&'static str> {
+    Ok(drawin.state()?.migration.policy.name())
+}
+
+/// Sets the output the drawin prefers to be on by name, which doesn't have
+/// to be connected. Nil forgets it.
+fn set_preferred_output<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawin, name): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let preferred = match name {
+        Value::Nil => None,
+        name => {
+            let name: String = DRAWIN_SCHEMA.check(lua, "preferred_output", name)?;
+            let connected = connected_outputs(lua)?
+                .into_iter()
+                .map(|(output, _)| output)
+                .find(|output| output.name == name);
+            Some(connected.unwrap_or(OutputId {
+                name,
+                ..OutputId::default()
+            }))
+        }
+    };
+    drawin.state_mut()?.migration.set_preferred(preferred);
+    Ok(())
+}
+
+fn get_preferred_output<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<Option<String>> {
+    Ok(drawin
+        .state()?
+        .migration
+        .preferred()
+        .map(|output| output.name.clone()))
+}
+
+fn set_persist_content<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawin, persist): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let persist = DRAWIN_SCHEMA.check(lua, "persist_content", persist)?;
+    let (id, restore) = {
+        let mut state = drawin.state_mut()?;
+        let restore = persist && !state.persist_content && !state.painted;
+        state.persist_content = persist;
+        (state.id, restore)
+    };
+    // The content saved before the restart is shown until Lua paints.
+    if restore {
+        drawin
+            .drawable()?
+            .restore_snapshot_on_allocation(&snapshot_key(id))?;
+    }
+    Ok(())
+}
+
+fn get_persist_content<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<bool> {
+    Ok(drawin.state()?.persist_content)
+}
+
+/// `drawin:request_focus(priority)`, where the priority is "regular", the
+/// default, "modal" or "lockscreen".
+fn request_focus<'lua>(
+    lua: rlua::Context<'lua>,
+    (drawin, priority): (Drawin<'lua>, Option<String>)
+) -> rlua::Result<()> {
+    let priority = match priority {
+        Some(name) => FocusPriority::from_name(&name).ok_or_else(|| {
+            rlua::Error::RuntimeError(format!(
+                "drawin.request_focus: expected one of \"{}\", got \"{}\"",
+                FocusPriority::NAMES.join("\", \""),
+                name
+            ))
+        })?,
+        None => FocusPriority::Regular
+    };
+    let (DrawinId(id), visible, osk) = {
+        let state = drawin.state()?;
+        (state.id, state.visible, state.osk)
+    };
+    if osk {
+        return Err(rlua::Error::RuntimeError(format!(
+            "drawin.request_focus: drawin#{} is an on-screen keyboard",
+            id
+        )));
+    }
+    if !visible {
+        return Err(rlua::Error::RuntimeError(format!(
+            "drawin.request_focus: drawin#{} is hidden",
+            id
+        )));
+    }
+    // A lock screen inhibits the shortcuts of the compositor as well.
+    let locked = priority == FocusPriority::Lockscreen;
+    let actions = INHIBITS.with(|inhibits| inhibits.borrow_mut().set_locked(id, locked));
+    inhibit_actions(lua, actions)?;
+    let changes = FOCUS.with(|focus| focus.borrow_mut().request(id, priority));
+    focus_changed(lua, changes)
+}
+
+/// `drawin.keyboard_focus = true`, which makes the drawin request the
+/// keyboard focus whenever it's shown, like a prompt. Setting it to false
+/// releases the focus.
+fn set_keyboard_focus<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawin, keyboard_focus): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let keyboard_focus = DRAWIN_SCHEMA.check(lua, "keyboard_focus", keyboard_focus)?;
+    if keyboard_focus == drawin.state()?.keyboard_focus {
+        return Ok(());
+    }
+    if keyboard_focus && drawin.get_visible()? {
+        request_focus(lua, (drawin.clone(), None))?;
+    } else if !keyboard_focus {
+        release_focus(lua, drawin.clone())?;
+    }
+    drawin.state_mut()?.keyboard_focus = keyboard_focus;
+    Object::emit_signal(lua, &drawin, "property::keyboard_focus", Value::Nil)
+}
+
+fn get_keyboard_focus<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<bool> {
+    Ok(drawin.state()?.keyboard_focus)
+}
+
+/// `drawin:release_focus()`, which gives the focus back to the drawin that
+/// had it before, or unlocks the screen.
+fn release_focus<'lua>(lua: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<()> {
+    let DrawinId(id) = drawin.id()?;
+    let actions = INHIBITS.with(|inhibits| inhibits.borrow_mut().set_locked(id, false));
+    inhibit_actions(lua, actions)?;
+    let changes = FOCUS.with(|focus| focus.borrow_mut().release(id));
+    focus_changed(lua, changes)
+}
+
+/// `drawin:inhibit_shortcuts(inhibit)`, which asks the compositor to send
+/// its shortcuts to the drawin while it has the focus. Every call with true
+/// is undone by one with false. Returns how many calls are left to undo.
+fn inhibit_shortcuts<'lua>(
+    lua: rlua::Context<'lua>,
+    (drawin, inhibit): (Drawin<'lua>, bool)
+) -> rlua::Result<u32> {
+    let DrawinId(id) = drawin.id()?;
+    let actions = INHIBITS.with(|inhibits| {
+        let mut inhibits = inhibits.borrow_mut();
+        if inhibit {
+            inhibits.request(id)
+        } else {
+            inhibits.unrequest(id)
+        }
+    });
+    inhibit_actions(lua, actions)?;
+    Ok(INHIBITS.with(|inhibits| inhibits.borrow().requests(id)))
+}
+
+fn get_shortcuts_inhibited<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<bool> {
+    let DrawinId(id) = drawin.id()?;
+    Ok(INHIBITS.with(|inhibits| inhibits.borrow().inhibited(id)))
+}
+
+/// Shows the on-screen keyboards with `osk_auto` set while a text field is
+/// active, and hides them when it isn't.
+fn text_input_changed(lua: rlua::Context, active: bool) -> rlua::Result<()> {
+    for mut drawin in lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)? {
+        let auto = {
+            let state = drawin.state()?;
+            state.osk && state.osk_auto
+        };
+        if auto && drawin.get_visible()? != active {
+            drawin.set_visible(lua, active)?;
+        }
+    }
+    Ok(())
+}
+
+fn set_osk<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawin, osk): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let osk = DRAWIN_SCHEMA.check(lua, "osk", osk)?;
+    let DrawinId(id) = {
+        let mut state = drawin.state_mut()?;
+        state.osk = osk;
+        // Placed again, at the bottom of its screen or where it is.
+        state.geometry_dirty = true;
+        state.id
+    };
+    if osk {
+        // The keyboard types into the window that has the focus, so it
+        // must never take it.
+        let changes = FOCUS.with(|focus| focus.borrow_mut().release(id));
+        focus_changed(lua, changes)?;
+        // It stays at the bottom of its screen.
+        if FOLLOWS.with(|follows| follows.borrow_mut().stop(id)) {
+            Object::emit_signal(lua, &drawin, "drawin::follow_stopped", Value::Nil)?;
+        }
+    }
+    drawin.update_drawing(lua)?;
+    update_workareas(lua)
+}
+
+fn get_osk<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<bool> {
+    Ok(drawin.state()?.osk)
+}
+
+fn set_osk_auto<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawin, osk_auto): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    drawin.state_mut()?.osk_auto = DRAWIN_SCHEMA.check(lua, "osk_auto", osk_auto)?;
+    Ok(())
+}
+
+fn get_osk_auto<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<bool> {
+    Ok(drawin.state()?.osk_auto)
+}
+
+/// `drawin.on_memory_pressure = function(level, drawin) ... end`, called
+/// with "normal", "elevated" or "critical" when the memory pressure
+/// changes, so the caches of its widgets can be freed, see
+/// `memory_pressure`. `nil` removes it.
+fn set_on_memory_pressure<'lua>(
+    lua: rlua::Context<'lua>,
+    (drawin, handler): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let handler: Option<Function> = match handler {
+        Value::Nil => None,
+        handler => Some(DRAWIN_SCHEMA.check(lua, "on_memory_pressure", handler)?)
+    };
+    drawin.set_associated_data(MEMORY_PRESSURE_HANDLER, handler)
+}
+
+/// `drawin:buttons(buttons)`, which sets the list of button bindings of the
+/// drawin, and returns it. They get "press" and "release" when the pointer
+/// button they're for is used on the drawin, see `emit_button_bindings`.
+fn buttons<'lua>(
+    lua: rlua::Context<'lua>,
+    (drawin, buttons): (Drawin<'lua>, Option<Table<'lua>>)
+) -> rlua::Result<Table<'lua>> {
+    if let Some(buttons) = buttons {
+        let bindings = lua.create_table()?;
+        for (index, binding) in buttons.sequence_values::<Value>().enumerate() {
+            match binding? {
+                Value::UserData(binding) if binding.is::<ButtonState>() => {
+                    bindings.set(index + 1, binding)?
+                },
+                binding => {
+                    return Err(rlua::Error::RuntimeError(format!(
+                        "drawin:buttons: expected a list of buttons, got {} at {}",
+                        schema::describe(&binding),
+                        index + 1
+                    )))
+                },
+            }
+        }
+        drawin.set_associated_data(BUTTONS, bindings)?;
+        Object::emit_signal(lua, &drawin, "property::buttons", Value::Nil)?;
+    }
+    match drawin.get_associated_data::<Option<Table>>(BUTTONS)? {
+        Some(bindings) => Ok(bindings),
+        None => lua.create_table()
+    }
+}
+
+fn get_on_memory_pressure<'lua>(
+    _: rlua::Context<'lua>,
+    drawin: Drawin<'lua>
+) -> rlua::Result<Option<Function<'lua>>> {
+    drawin.get_associated_data(MEMORY_PRESSURE_HANDLER)
+}
+
+/// `drawin:send_key(keysym, pressed)`, which presses or releases the key
+/// with the keysym name, e.g. "BackSpace", in the window with the focus.
+fn send_key<'lua>(
+    _: rlua::Context<'lua>,
+    (drawin, name, pressed): (Drawin<'lua>, String, bool)
+) -> rlua::Result<()> {
+    check_osk(&drawin, "send_key")?;
+    let keysym = match xkb::keysym_from_name(&name, xkb::KEYSYM_NO_FLAGS) {
+        xkb::keysyms::KEY_NoSymbol => {
+            return Err(rlua::Error::RuntimeError(format!(
+                "drawin.send_key: unknown keysym \"{}\"",
+                name
+            )))
+        },
+        keysym => keysym
+    };
+    type_keys("send_key", |keymap| keymap.key(keysym, pressed))
+}
+
+/// `drawin:send_text(text)`, which types the text in the window with the
+/// focus.
+fn send_text<'lua>(_: rlua::Context<'lua>, (drawin, text): (Drawin<'lua>, String)) -> rlua::Result<()> {
+    check_osk(&drawin, "send_text")?;
+    type_keys("send_text", |keymap| keymap.text(&text))
+}
+
+/// Only on-screen keyboards can type, other drawins get key presses.
+fn check_osk(drawin: &Drawin, method: &str) -> rlua::Result<()> {
+    let state = drawin.state()?;
+    if state.osk {
+        Ok(())
+    } else {
+        Err(rlua::Error::RuntimeError(format!(
+            "drawin.{}: drawin#{} is not an on-screen keyboard",
+            method, state.id.0
+        )))
+    }
+}
+
+/// Sends the keys to the virtual keyboard, creating it the first time.
+fn type_keys<F>(method: &str, keys: F) -> rlua::Result<()>
+where
+    F: FnOnce(&mut OskKeymap) -> Vec<KeyAction>
+{
+    VIRTUAL_KEYBOARD.with(|virtual_keyboard| {
+        let mut virtual_keyboard = virtual_keyboard.borrow_mut();
+        if virtual_keyboard.is_none() {
+            let created = wayland_obj::create_virtual_keyboard().map_err(|_| {
+                rlua::Error::RuntimeError(format!(
+                    "drawin.{}: the compositor has no virtual keyboard",
+                    method
+                ))
+            })?;
+            *virtual_keyboard = Some(created);
+        }
+        let virtual_keyboard = virtual_keyboard.as_ref().unwrap();
+        let actions = OSK_KEYMAP.with(|keymap| keys(&mut keymap.borrow_mut()));
+        for action in actions {
+            match action {
+                KeyAction::Keymap(keymap) => virtual_keyboard.set_keymap(&keymap).map_err(|err| {
+                    OSK_KEYMAP.with(|keymap| keymap.borrow_mut().resend());
+                    rlua::Error::RuntimeError(format!(
+                        "drawin.{}: could not send the keymap: {}",
+                        method, err
+                    ))
+                })?,
+                KeyAction::Key { keycode, pressed } => virtual_keyboard.key(keycode, pressed)
+            }
+        }
+        Ok(())
+    })
+}
+
+/// `drawin:follow_pointer{ offset_x = 16, offset_y = 16, constrain =
+/// "output" }`, which keeps the drawin at the offset from the pointer,
+/// and on the output the pointer is on unless `constrain` is "none".
+///
+/// Lua can still resize the drawin, but not move it, until
+/// `drawin:unfollow_pointer()`. We only see the pointer while it's over one
+/// of our own surfaces, elsewhere the drawin waits where it was last seen.
+fn follow_pointer<'lua>(
+    lua: rlua::Context<'lua>,
+    (drawin, options): (Drawin<'lua>, Option<Table<'lua>>)
+) -> rlua::Result<()> {
+    let invalid = |message: String| rlua::Error::RuntimeError(format!("drawin:follow_pointer: {}", message));
+    let DrawinId(id) = drawin.id()?;
+    if drawin.state()?.osk {
+        return Err(invalid("an on-screen keyboard can't follow the pointer".into()));
+    }
+    if drawin.state()?.anchor.is_anchored() {
+        return Err(invalid(
+            "an anchored drawin can't follow the pointer, set drawin.anchor to {} first".into()
+        ));
+    }
+    let mut follow = Follow::default();
+    if let Some(options) = options {
+        let offset = |name: &str| -> rlua::Result<i32> {
+            let offset = options.get::<_, Option<LuaInteger>>(name)?.unwrap_or(0);
+            area::checked_coordinate(offset).map_err(|err| invalid(format!("{}: {}", name, err)))
+        };
+        follow.offset = Origin {
+            x: offset("offset_x")?,
+            y: offset("offset_y")?
+        };
+        if let Some(name) = options.get::<_, Option<String>>("constrain")? {
+            follow.constrain = Constrain::from_name(&name).ok_or_else(|| {
+                invalid(format!(
+                    "expected constrain to be one of \"{}\", got \"{}\"",
+                    Constrain::NAMES.join("\", \""),
+                    name
+                ))
+            })?;
+        }
+    }
+    let (started, queue) = FOLLOWS.with(|follows| follows.borrow_mut().start(id, follow));
+    if queue {
+        queue_follow();
+    }
+    if started {
+        Object::emit_signal(lua, &drawin, "drawin::follow_started", Value::Nil)?;
+    }
+    Ok(())
+}
+
+fn unfollow_pointer<'lua>(lua: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<()> {
+    let DrawinId(id) = drawin.id()?;
+    if FOLLOWS.with(|follows| follows.borrow_mut().stop(id)) {
+        Object::emit_signal(lua, &drawin, "drawin::follow_stopped", Value::Nil)?;
+    }
+    Ok(())
+}
+
+/// `drawin.follow_stats()`, like `{ paused = false, moves = 120 }`: whether
+/// the pointer is off our surfaces, so the drawins following it wait where
+/// it was last seen, and how many frames moved them.
+fn follow_stats<'lua>(lua: rlua::Context<'lua>, _: Value<'lua>) -> rlua::Result<Table<'lua>> {
+    let (paused, moves) = FOLLOWS.with(|follows| {
+        let follows = follows.borrow();
+        (follows.is_paused(), follows.moves())
+    });
+    let stats = lua.create_table()?;
+    stats.set("paused", paused)?;
+    stats.set("moves", moves)?;
+    Ok(stats)
+}
+
+/// `drawin:start_move(button)` moves the drawin with the pointer until
+/// `button` is released, any button without one, like awesome's
+/// `awful.mouse.client.move`. It's called while the button is held down,
+/// e.g. from "button::press". Escape puts the drawin back where it was.
+fn start_move<'lua>(
+    lua: rlua::Context<'lua>,
+    (drawin, button): (Drawin<'lua>, Option<LuaInteger>)
+) -> rlua::Result<()> {
+    start_drag(lua, drawin, button, DragMode::Move, "start_move")
+}
+
+/// `drawin:start_resize(button, corner)` resizes the drawin by dragging
+/// `corner` with the pointer, the bottom right one without one, like
+/// `drawin:start_move`.
+fn start_resize<'lua>(
+    lua: rlua::Context<'lua>,
+    (drawin, button, corner): (Drawin<'lua>, Option<LuaInteger>, Option<String>)
+) -> rlua::Result<()> {
+    let corner = match corner {
+        Some(name) => Corner::from_name(&name).ok_or_else(|| {
+            rlua::Error::RuntimeError(format!(
+                "drawin:start_resize: expected corner to be one of \"{}\", got \"{}\"",
+                Corner::NAMES.join("\", \""),
+                name
+            ))
+        })?,
+        None => Corner::default()
+    };
+    start_drag(lua, drawin, button, DragMode::Resize(corner), "start_resize")
+}
+
+fn start_drag<'lua>(
+    lua: rlua::Context<'lua>,
+    drawin: Drawin<'lua>,
+    button: Option<LuaInteger>,
+    mode: DragMode,
+    method: &str
+) -> rlua::Result<()> {
+    let invalid = |message: &str| rlua::Error::RuntimeError(format!("drawin:{}: {}", method, message));
+    let DrawinId(id) = drawin.id()?;
+    if FOLLOWS.with(|follows| follows.borrow().is_following(id)) {
+        return Err(invalid("a drawin that follows the pointer can't be dragged"));
+    }
+    {
+        let state = drawin.state()?;
+        if state.anchor.is_anchored() {
+            return Err(invalid(
+                "an anchored drawin can't be dragged, set drawin.anchor to {} first"
+            ));
+        }
+        if state.fullscreen {
+            return Err(invalid("a fullscreen drawin can't be dragged"));
+        }
+    }
+    let button = match button {
+        None | Some(0) => None,
+        Some(button) => Some(u32::try_from(button).map_err(|_| invalid("expected button to be positive"))?)
+    };
+    let pointer = pointer_position().ok_or_else(|| invalid("the pointer wasn't seen over a drawin yet"))?;
+    let drag = Drag {
+        drawin: id,
+        mode,
+        button,
+        from: drawin.requested_geometry()?,
+        pointer
+    };
+    let replaced = DRAGS.with(|drags| drags.borrow_mut().start(drag));
+    if let Some(replaced) = replaced.filter(|replaced| replaced.drawin != id) {
+        if let Some(other) = find_drawin(lua, DrawinId(replaced.drawin))? {
+            Object::emit_signal(lua, &other, "drawin::drag_stopped", Value::Nil)?;
+        }
+    }
+    if replaced.map(|replaced| replaced.drawin) != Some(id) {
+        Object::emit_signal(lua, &drawin, "drawin::drag_started", Value::Nil)?;
+    }
+    Ok(())
+}
+
+/// `drawin.dragging`, whether the drawin is moved or resized with the
+/// pointer.
+fn get_dragging<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<bool> {
+    let DrawinId(id) = drawin.id()?;
+    Ok(DRAGS.with(|drags| drags.borrow().dragging()) == Some(id))
+}
+
+fn get_following<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<bool> {
+    let DrawinId(id) = drawin.id()?;
+    Ok(FOLLOWS.with(|follows| follows.borrow().is_following(id)))
+}
+
+fn get_has_focus<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<bool> {
+    let DrawinId(id) = drawin.id()?;
+    Ok(FOCUS.with(|focus| focus.borrow().holder()) == Some(id))
+}
+
+/// Lists the claimed edges, for debugging.
+fn edge_claims<'lua>(lua: rlua::Context<'lua>, _: Value<'lua>) -> rlua::Result<Vec<Table<'lua>>> {
+    let claims = EDGE_CLAIMS.with(|claims| claims.borrow().claims().to_vec());
+    claims.into_iter().map(|claim| claim_table(lua, claim)).collect()
+}
+
+fn drawin_geometry<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawin, geometry): (Drawin<'lua>, Option<Table<'lua>>)
+) -> rlua::Result<Table<'lua>> {
+    if let Some(geometry) = geometry {
+        let current = drawin.requested_geometry()?;
+        let geo = geometry_from_table(lua, geometry, current)?;
+        if geo.size.width != current.size.width {
+            owned::check_lua_write(&drawin, "width")?;
+        }
+        if geo.size.height != current.size.height {
+            owned::check_lua_write(&drawin, "height")?;
+        }
+        if geo.size.width > 0 && geo.size.height > 0 {
+            drawin.resize(lua, geo)?;
+            // Asked for explicitly, it's resized right away.
+            drawin.update_drawing(lua)?;
+        }
+    }
+    area_table(lua, drawin.lua_geometry()?)
+}
+
+/// `drawin:shown_geometry()`, the geometry of the drawin including the
+/// shadows of its effects.
+fn drawin_shown_geometry<'lua>(lua: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<Table<'lua>> {
+    area_table(lua, drawin.shown_geometry()?)
+}
+
+/// An area in a table like `drawin:geometry()` returns.
+fn area_table<'lua>(lua: rlua::Context<'lua>, area: Area) -> rlua::Result<Table<'lua>> {
+    let Area {
+        origin: Origin { x, y },
+        size: Size { width, height }
+    } = area;
+    let res = lua.create_table()?;
+    res.set("x", x)?;
+    res.set("y", y)?;
+    res.set("height", height)?;
+    res.set("width", width)?;
+    Ok(res)
+}
+
+/// The geometry in a table like `{ x = 10, width = 100 }`, with what isn't
+/// in it taken from `current`.
+fn geometry_from_table<'lua>(
+    lua: rlua::Context<'lua>,
+    table: Table<'lua>,
+    current: Area
+) -> rlua::Result<Area> {
+    let field = |name: &str| -> rlua::Result<Option<LuaInteger>> {
+        match table.get::<_, Value>(name)? {
+            Value::Nil => Ok(None),
+            value => DRAWIN_SCHEMA.check(lua, name, value).map(Some)
+        }
+    };
+    let coordinate = |name: &str, current: i32| -> rlua::Result<i32> {
+        field(name)?.map_or(Ok(current), |value| checked_coordinate(name, value))
+    };
+    let length = |name: &str, current: u32| -> rlua::Result<u32> {
+        field(name)?.map_or(Ok(current), |value| checked_length(name, value))
+    };
+    Ok(Area {
+        origin: Origin {
+            x: coordinate("x", current.origin.x)?,
+            y: coordinate("y", current.origin.y)?
+        },
+        size: Size {
+            width: length("width", current.size.width)?,
+            height: length("height", current.size.height)?
+        }
+    })
+}
+
+/// Checks a coordinate from Lua, which can be any integer.
+fn checked_coordinate(name: &str, value: LuaInteger) -> rlua::Result<i32> {
+    area::checked_coordinate(value)
+        .map_err(|err| rlua::Error::RuntimeError(format!("drawin.{}: {}", name, err)))
+}
+
+/// Checks a width or height from Lua, which can be any integer.
+fn checked_length(name: &str, value: LuaInteger) -> rlua::Result<u32> {
+    area::checked_length(value).map_err(|err| rlua::Error::RuntimeError(format!("drawin.{}: {}", name, err)))
+}
+
+fn get_x<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<LuaInteger> {
+    let Origin { x, .. } = drawin.lua_geometry()?.origin;
+    Ok(x as LuaInteger)
+}
+
+fn set_x<'lua>(lua: rlua::Context<'lua>, (mut drawin, x): (Drawin<'lua>, Value<'lua>)) -> rlua::Result<()> {
+    let x: LuaInteger = DRAWIN_SCHEMA.check(lua, "x", x)?;
+    let mut geo = drawin.requested_geometry()?;
+    geo.origin.x = checked_coordinate("x", x)?;
+    drawin.resize(lua, geo)?;
+    Ok(())
+}
+
+fn get_y<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<LuaInteger> {
+    let Origin { y, .. } = drawin.lua_geometry()?.origin;
+    Ok(y as LuaInteger)
+}
+
+fn set_y<'lua>(lua: rlua::Context<'lua>, (mut drawin, y): (Drawin<'lua>, Value<'lua>)) -> rlua::Result<()> {
+    let y: LuaInteger = DRAWIN_SCHEMA.check(lua, "y", y)?;
+    let mut geo = drawin.requested_geometry()?;
+    geo.origin.y = checked_coordinate("y", y)?;
+    drawin.resize(lua, geo)?;
+    Ok(())
+}
+
+fn get_width<'lua>(_: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<LuaInteger> {
+    let Size { width, .. } = drawin.get_geometry()?.size;
+    Ok(width as LuaInteger)
+}
+
+fn set_width<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawin, width): (Drawin<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let width: LuaInteger = DRAWIN_SCHEMA.check(lua, "width", width)?;
+    let width = checked_length("width", width)?;
+    let mut geo = drawin.requested_geometry()?;
+    if width > 0 {
+        geo.size.width = width;
+        drawin.resize(lua, geo)?;
+    }
+    Ok(())
+}
+
+fn get_height<'lua>(_lua: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<LuaInteger> {
+    let Size { height, .. } = drawin.get_geometry()?.size;
+    Ok(height as LuaInteger)
+}
+
+fn set_height<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawin, height): (Drawin<'lua>, Value<'lua>)
 ) -> rlua::Result<()> {
-    let mut geo = drawin.get_geometry()?;
+    let height: LuaInteger = DRAWIN_SCHEMA.check(lua, "height", height)?;
+    let height = checked_length("height", height)?;
+    let mut geo = drawin.requested_geometry()?;
     if height > 0 {
-        geo.size.height = height as u32;
+        geo.size.height = height;
         drawin.resize(lua, geo)?;
     }
     Ok(())
 }
 
-fn drawin_struts<'lua>(lua: rlua::Context<'lua>, _drawin: Drawin<'lua>) -> rlua::Result<Table<'lua>> {
-    // TODO: Implement this properly. Struts means this drawin reserves some space
-    // on the screen that it is visible on, shrinking the workarea in the
-    // specified directions.
+/// `drawin:struts{ left = 0, right = 0, top = 24, bottom = 0 }`, the space
+/// along the edges of its screen the drawin keeps windows out of, from the
+/// edge of the screen. Sides left out are 0. It returns the struts, with
+/// the space an on-screen keyboard keeps, with or without a table.
+fn drawin_struts<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut drawin, struts): (Drawin<'lua>, Option<Table<'lua>>)
+) -> rlua::Result<Table<'lua>> {
+    if let Some(struts) = struts {
+        let side = |name: &str| -> rlua::Result<i32> {
+            let strut = struts.get::<_, Option<i32>>(name)?.unwrap_or(0);
+            if strut < 0 {
+                return Err(rlua::Error::RuntimeError(format!(
+                    "drawin:struts: {} must be 0 or positive, got {}",
+                    name, strut
+                )));
+            }
+            Ok(strut)
+        };
+        let struts = Margin {
+            top: side("top")?,
+            right: side("right")?,
+            bottom: side("bottom")?,
+            left: side("left")?
+        };
+        drawin.set_struts(lua, struts)?;
+    }
+    let reserved = match output_at(lua, drawin.get_geometry()?)? {
+        Some((_, screen)) => drawin.struts(screen)?,
+        None => Margin::default()
+    };
+    let struts = widest(drawin.state()?.struts, reserved);
     let res = lua.create_table()?;
-    res.set("left", 0)?;
-    res.set("right", 0)?;
-    res.set("top", 0)?;
-    res.set("bottom", 0)?;
+    res.set("left", struts.left)?;
+    res.set("right", struts.right)?;
+    res.set("top", struts.top)?;
+    res.set("bottom", struts.bottom)?;
     Ok(res)
 }
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use rlua::{self, Lua, Table, Value};
+    use xkbcommon::xkb::keysyms;
+
+    use super::{
+        dispatch_pointer_event, drawin_geometry, init, keyboard_event, memory_pressure, move_dragged,
+        move_followers, sweep_drawin_state, text_input_changed, update_workareas, Drawin, DrawinId,
+        FocusPriority, Following, DRAGS, DRAWINS_HANDLE, FOCUS, FOLLOWS, INHIBITS, KEYBOARD_FOCUS,
+        POINTER_FOCUS, POINTER_POSITION
+    };
+    use crate::area::{
+        self,
+        arbitrary::{Arbitrary, CASES},
+        Area, Origin, Size
+    };
+    use crate::event_trace::{
+        self,
+        format::{Arg, Direction, ObjectRef, Record},
+        replay::{replay, Target, Timing}
+    };
+    use crate::keygrabber;
+    use crate::lua::LUA;
+    use crate::objects::{
+        button, drawable, mouse,
+        screen::{self, Screen, SCREENS_HANDLE}
+    };
+    use crate::scheduler;
+    use crate::wayland_obj::{
+        test_server::{Request, TestServer},
+        KeyboardEvent, PointerEvent, LAYER_SHELL_MAX_VERSION
+    };
+
+    /// Any value Lua code could put in a geometry table.
+    fn arbitrary_value<'lua>(
+        lua: rlua::Context<'lua>,
+        arbitrary: &mut Arbitrary
+    ) -> rlua::Result<Value<'lua>> {
+        Ok(match arbitrary.next() % 9 {
+            0 => Value::Nil,
+            1 => Value::Boolean(arbitrary.next() % 2 == 0),
+            2 => Value::Integer(arbitrary.next() as i64),
+            3 => Value::Integer(arbitrary.range(-(1 << 26), 1 << 26)),
+            // Including the infinities and NaNs.
+            4 => Value::Number(f64::from_bits(arbitrary.next())),
+            5 => Value::Number(arbitrary.range(-(1 << 26), 1 << 26) as f64 / 2.0),
+            6 => {
+                let number = match arbitrary.next() % 3 {
+                    0 => arbitrary.range(-(1 << 26), 1 << 26).to_string(),
+                    1 => format!(" {:e} ", f64::from_bits(arbitrary.next())),
+                    _ => "abc".into()
+                };
+                Value::String(lua.create_string(&number)?)
+            },
+            7 => Value::Table(lua.create_table()?),
+            _ => Value::Function(lua.create_function(|_, ()| Ok(()))?)
+        })
+    }
+
+    #[test]
+    fn drawin_osk_auto_show() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            lua.set_named_registry_value(SCREENS_HANDLE, lua.create_table()?)?;
+            lua.load(
+                r#"
+keyboard = drawin{ osk = true, osk_auto = true }
+manual = drawin{ osk = true }
+assert(not keyboard.visible and not manual.visible)
+assert(not pcall(keyboard.request_focus, keyboard))
+                "#
+            )
+            .exec()?;
+            // A text field was focused, then unfocused.
+            text_input_changed(lua, true)?;
+            lua.load("assert(keyboard.visible and not manual.visible)")
+                .exec()?;
+            text_input_changed(lua, false)?;
+            lua.load(
+                r#"
+assert(not keyboard.visible)
+keyboard.osk_auto = false
+                "#
+            )
+            .exec()?;
+            text_input_changed(lua, true)?;
+            lua.load("assert(not keyboard.visible)").exec()
+        })
+    }
+
+    #[test]
+    fn drawin_memory_pressure_hidden_first() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            lua.set_named_registry_value(SCREENS_HANDLE, lua.create_table()?)?;
+            let mut drawins: Vec<Drawin> = lua
+                .load(
+                    r#"
+told = {}
+local drawins = {}
+for _, name in ipairs{ "bar", "popup", "dock", "tooltip" } do
+    local d = drawin{}
+    d.on_memory_pressure = function(level, of)
+        assert(of == d)
+        told[#told + 1] = name .. " " .. level
+    end
+    drawins[#drawins + 1] = d
+end
+drawins[4].on_memory_pressure = nil
+assert(drawins[4].on_memory_pressure == nil and type(drawins[1].on_memory_pressure) == "function")
+return drawins
+                    "#
+                )
+                .eval()?;
+            // Shown without a compositor.
+            for index in &[0, 2] {
+                drawins[*index].state_mut()?.visible = true;
+            }
+            memory_pressure(lua, "elevated")?;
+            lua.load(r#"assert(table.concat(told, ", ") == "popup elevated, bar elevated, dock elevated")"#)
+                .exec()
+        })
+    }
+
+    #[test]
+    fn drawin_effects_grow_shown_geometry() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            lua.set_named_registry_value(SCREENS_HANDLE, lua.create_table()?)?;
+            lua.load(
+                r#"
+popup = drawin{ x = 100, y = 50, width = 40, height = 30, effects = { "shadow:8:0.5", "dim:0.3" } }
+local effects = popup.effects
+assert(#effects == 2 and effects[1] == "shadow:8:0.5" and effects[2] == "dim:0.3")
+-- Lua's geometry stays the content's, the shadow is around it.
+local geometry = popup:geometry()
+assert(geometry.x == 100 and geometry.width == 40)
+local shown = popup:shown_geometry()
+assert(shown.x == 92 and shown.y == 42 and shown.width == 56 and shown.height == 46)
+assert(not pcall(function() popup.effects = { "shadow:8" } end))
+assert(#popup.effects == 2)
+popup.effects = { "desaturate:1" }
+assert(popup:shown_geometry().width == 40)
+local stats = popup:effect_stats()
+assert(#stats == 1 and stats[1].effect == "desaturate:1" and stats[1].runs == 0)
+                "#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn drawin_geometry_from_arbitrary_tables() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            lua.set_named_registry_value(SCREENS_HANDLE, lua.create_table()?)?;
+            let drawin = Drawin::new(lua, lua.create_table()?)?;
+            let mut arbitrary = Arbitrary::new(CASES as u64);
+            for _ in 0..CASES {
+                let table = lua.create_table()?;
+                for key in &["x", "y", "width", "height", "visible"] {
+                    if arbitrary.next() % 4 != 0 {
+                        table.set(*key, arbitrary_value(lua, &mut arbitrary)?)?;
+                    }
+                }
+                let described = format!("{:?}", table.clone().pairs::<String, Value>().collect::<Vec<_>>());
+                // Bad values are errors that name the property, never panics.
+                match drawin_geometry(lua, (drawin.clone(), Some(table))) {
+                    Ok(_) => {},
+                    Err(rlua::Error::RuntimeError(ref err)) if err.starts_with("drawin.") => {},
+                    Err(err) => panic!("unexpected error for {}: {:?}", described, err)
+                }
+                let geometry = drawin.get_geometry()?;
+                let checked = |value: i32| area::checked_coordinate(value as i64).is_ok();
+                assert!(
+                    checked(geometry.origin.x) &&
+                        checked(geometry.origin.y) &&
+                        area::checked_length(geometry.size.width as i64).is_ok() &&
+                        area::checked_length(geometry.size.height as i64).is_ok(),
+                    "{:?} from {}",
+                    geometry,
+                    described
+                );
+            }
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawin_state_of_removed_drawins_swept() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            let drawin = Drawin::new(lua, lua.create_table()?)?;
+            let DrawinId(live) = drawin.id()?;
+            // Left behind by a drawin that is gone.
+            let removed = live + 100;
+            FOCUS.with(|focus| {
+                let mut focus = focus.borrow_mut();
+                focus.request(live, FocusPriority::Regular);
+                focus.request(removed, FocusPriority::Modal);
+            });
+            INHIBITS.with(|inhibits| inhibits.borrow_mut().request(removed));
+            let findings = sweep_drawin_state(lua)?;
+            assert_eq!(findings.len(), 1);
+            assert_eq!((findings[0].category, findings[0].id), ("drawin_state", removed));
+            // The focus went back to the drawin that waited for it.
+            assert_eq!(FOCUS.with(|focus| focus.borrow().holder()), Some(live));
+            assert!(!INHIBITS.with(|inhibits| inhibits.borrow().drawins().contains(&removed)));
+            assert!(sweep_drawin_state(lua)?.is_empty());
+            Ok(())
+        })
+    }
+    #[test]
+    fn drawin_ontop_layer() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            lua.load(
+                r#"
+local bar = drawin{ ontop = true }
+assert(bar.ontop and bar.layer == "overlay")
+local ontop, layer = 0, 0
+bar:connect_signal("property::ontop", function() ontop = ontop + 1 end)
+bar:connect_signal("property::layer", function() layer = layer + 1 end)
+bar.ontop = true
+assert(ontop == 0 and layer == 0)
+bar.layer = "bottom"
+assert(not bar.ontop and ontop == 1 and layer == 1)
+bar.layer = "background"
+assert(ontop == 1 and layer == 2)
+bar.ontop = false
+assert(bar.layer == "top" and ontop == 1 and layer == 3)
+assert(not pcall(function() bar.layer = "above" end))
+assert(bar.layer == "top")
+assert(drawin{ ontop = true, layer = "bottom" }.layer == "bottom")
+"#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn drawin_opacity() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            lua.load(
+                r#"
+local popup = drawin{}
+assert(popup.opacity == 1)
+local changes = 0
+popup:connect_signal("property::opacity", function() changes = changes + 1 end)
+popup.opacity = 0.8
+assert(popup.opacity == 0.8 and changes == 1)
+popup.opacity = "0.8"
+assert(changes == 1)
+-- Clamped from 0 to 1.
+popup.opacity = 2
+assert(popup.opacity == 1 and changes == 2)
+popup.opacity = -1
+assert(popup.opacity == 0 and changes == 3)
+assert(not pcall(function() popup.opacity = "half" end))
+assert(drawin{ opacity = 0.5 }.opacity == 0.5)
+"#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn drawin_cursor() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            lua.load(
+                r#"
+local bar = drawin{}
+assert(bar.cursor == "left_ptr")
+local changes = 0
+bar:connect_signal("property::cursor", function() changes = changes + 1 end)
+bar.cursor = "hand1"
+assert(bar.cursor == "hand1" and changes == 1)
+bar.cursor = "hand1"
+assert(changes == 1)
+-- Unknown cursors are the default one.
+bar.cursor = "pointer"
+assert(bar.cursor == "left_ptr" and changes == 2)
+assert(drawin{ cursor = "no_such_cursor" }.cursor == "left_ptr")
+assert(not pcall(function() bar.cursor = {} end))
+"#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn drawin_input_region() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            screen::init(lua)?;
+            lua.load(
+                r#"
+bar = drawin{ width = 100, height = 20, input_passthrough = true }
+assert(bar.input_passthrough)
+bar:set_input_region{ { x = 10, y = 0, width = 20, height = 20 } }
+assert(not bar.input_passthrough)
+"#
+            )
+            .exec()?;
+            let bar: Drawin = lua.globals().get("bar")?;
+            let rect = Area {
+                origin: Origin { x: 10, y: 0 },
+                size: Size {
+                    width: 20,
+                    height: 20
+                }
+            };
+            assert_eq!(bar.drawable()?.input_region()?, Some(vec![rect]));
+            lua.load(
+                r#"
+bar:set_input_region(nil)
+assert(not bar.input_passthrough)
+bar:set_input_region{}
+assert(bar.input_passthrough)
+assert(not pcall(bar.set_input_region, bar, { 1 }))
+assert(not pcall(bar.set_input_region, bar, { { width = -1 } }))
+assert(bar.shape_bounding == nil)
+bar.shape_bounding = { { x = 2, y = 0, width = 96, height = 20 } }
+local shape = bar.shape_bounding[1]
+assert(#bar.shape_bounding == 1 and shape.x == 2 and shape.width == 96 and shape.height == 20)
+bar.shape_bounding = nil
+assert(bar.shape_bounding == nil)
+"#
+            )
+            .exec()?;
+            assert_eq!(bar.drawable()?.input_region()?, Some(Vec::new()));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawin_buttons() -> rlua::Result<()> {
+        const BTN_LEFT: u32 = 0x110;
+        const BTN_RIGHT: u32 = 0x111;
+        let lua = Lua::new();
+        lua.context(|lua| {
+            button::init(lua)?;
+            drawable::init(lua)?;
+            init(lua)?;
+            lua.load(
+                r#"
+bar = drawin{}
+events = {}
+local function binding(name, args)
+    local binding = button(args)
+    for _, signal in ipairs{ "press", "release" } do
+        binding:connect_signal(signal, function(self, d)
+            assert(self == binding and d == bar)
+            table.insert(events, name .. " " .. signal)
+        end)
+    end
+    return binding
+end
+assert(#bar:buttons() == 0)
+local buttons = bar:buttons{
+    binding("left", { button = 1 }),
+    binding("right", { button = 3 }),
+    binding("shift left", { button = 1, modifiers = { "Shift" } }),
+    binding("any", { button = 0 })
+}
+assert(#buttons == 4 and #bar:buttons() == 4)
+assert(not pcall(bar.buttons, bar, { 1 }))
+assert(#bar:buttons() == 4)
+"#
+            )
+            .exec()?;
+            let bar = lua.globals().get::<_, Drawin>("bar")?.id()?;
+            POINTER_FOCUS.with(|focus| focus.set(Some((bar, 5.0, 5.0))));
+            let click = |button, pressed| {
+                let event = PointerEvent::Button {
+                    button,
+                    pressed,
+                    time: 0.0
+                };
+                dispatch_pointer_event(lua, event, &mut None)
+            };
+            click(BTN_LEFT, true)?;
+            click(BTN_LEFT, false)?;
+            click(BTN_RIGHT, true)?;
+            POINTER_FOCUS.with(|focus| focus.set(None));
+            lua.load(
+                r#"
+assert(table.concat(events, ", ") ==
+    "left press, any press, left release, any release, right press, any press", table.concat(events, ", "))
+events = {}
+bar:buttons{}
+assert(#bar:buttons() == 0)
+"#
+            )
+            .exec()?;
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawin_pointer_position() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            mouse::init(lua)?;
+            screen::init(lua)?;
+            let mut second = Screen::new(lua)?;
+            second.state_mut()?.geometry = Area {
+                origin: Origin { x: 1024, y: 0 },
+                size: Size {
+                    width: 1920,
+                    height: 1080
+                }
+            };
+            screen::add_screen(lua, second)?;
+            // The pointer wasn't seen yet.
+            POINTER_POSITION.with(|position| position.set(None));
+            lua.load(
+                r#"
+bar = drawin{ x = 1074, y = 20, width = 300, height = 40 }
+local coords = mouse.coords()
+assert(coords.x == 0 and coords.y == 0 and mouse.screen == screen[1])
+"#
+            )
+            .exec()?;
+            let bar = lua.globals().get::<_, Drawin>("bar")?.id()?;
+            POINTER_FOCUS.with(|focus| focus.set(Some((bar, 0.0, 0.0))));
+            let event = PointerEvent::Motion {
+                x: 10.5,
+                y: 4.0,
+                time: 0.0
+            };
+            dispatch_pointer_event(lua, event, &mut None)?;
+            lua.load(
+                r#"
+local coords = mouse.coords()
+assert(coords.x == 1084.5 and coords.y == 24 and mouse.screen == screen[2])
+-- The pointer stays where it is.
+coords = mouse.coords({ x = 5, y = 5 })
+assert(coords.x == 1084.5 and coords.y == 24)
+"#
+            )
+            .exec()?;
+            // The tests share the thread.
+            POINTER_FOCUS.with(|focus| focus.set(None));
+            POINTER_POSITION.with(|position| position.set(None));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawin_keyboard_focus() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            keygrabber::init(lua)?;
+            drawable::init(lua)?;
+            init(lua)?;
+            lua.load("bar = drawin{ keyboard_focus = true }").exec()?;
+            let mut bar: Drawin = lua.globals().get("bar")?;
+            lua.load("assert(bar.keyboard_focus and not bar.has_focus)")
+                .exec()?;
+            // Shown, without a compositor to show it.
+            bar.state_mut()?.visible = true;
+            lua.load(
+                r#"
+bar.keyboard_focus = false
+assert(not bar.has_focus)
+bar.keyboard_focus = true
+assert(bar.has_focus)
+keys = {}
+bar:connect_signal("key::press", function(d, mods, key)
+    assert(d == bar)
+    table.insert(keys, table.concat(mods, "+") .. " " .. key)
+end)
+"#
+            )
+            .exec()?;
+            let key = |pressed| {
+                let event = KeyboardEvent::Key {
+                    keysym: keysyms::KEY_a,
+                    modifiers: vec![keysyms::KEY_Control_L],
+                    pressed,
+                    time: 0.0
+                };
+                keyboard_event(lua, event)
+            };
+            // Without the focus of the compositor nothing is pressed.
+            key(true)?;
+            let id = bar.id()?;
+            KEYBOARD_FOCUS.with(|focus| focus.set(Some(id)));
+            key(true)?;
+            lua.load(
+                r#"
+assert(#keys == 1 and keys[1] == "Control a", keys[1])
+keygrabber.run(function(mods, key, event) grabbed = mods[1] .. " " .. key .. " " .. event end)
+"#
+            )
+            .exec()?;
+            key(false)?;
+            KEYBOARD_FOCUS.with(|focus| focus.set(None));
+            lua.load(
+                r#"
+assert(#keys == 1 and grabbed == "Control a release")
+keygrabber.stop()
+bar.keyboard_focus = false
+assert(not bar.has_focus)
+"#
+            )
+            .exec()?;
+            bar.state_mut()?.visible = false;
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawin_visible() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            screen::init(lua)?;
+            // Without a size it gets no layer surface when it's shown.
+            lua.load(
+                r#"
+local popup = drawin{}
+local changes = 0
+popup:connect_signal("property::visible", function() changes = changes + 1 end)
+popup.visible = false
+assert(changes == 0)
+popup.visible = true
+popup.visible = true
+assert(popup.visible and changes == 1)
+popup.visible = false
+popup.visible = true
+popup.visible = false
+assert(not popup.visible and changes == 4)
+"#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn drawin_closed_by_compositor() -> rlua::Result<()> {
+        let mut server = TestServer::start(LAYER_SHELL_MAX_VERSION);
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            screen::init(lua)?;
+            lua.load(
+                r#"
+bar = drawin{ x = 0, y = 0, width = 100, height = 20 }
+changes, closes = 0, 0
+bar:connect_signal("property::visible", function() changes = changes + 1 end)
+bar:connect_signal("drawin::closed", function() closes = closes + 1 end)
+bar.visible = true
+"#
+            )
+            .exec()?;
+            let bar: Drawin = lua.globals().get("bar")?;
+            let id = bar.state()?.layer_surface.as_ref().unwrap().id();
+            server.roundtrip();
+            server.configure(
+                id,
+                1,
+                Size {
+                    width: 100,
+                    height: 20
+                }
+            );
+            server.roundtrip();
+            scheduler::run_deferred(lua);
+            server.take_requests();
+            server.close(id);
+            server.roundtrip();
+            scheduler::run_deferred(lua);
+            assert!(bar.state()?.layer_surface.is_none());
+            lua.load("assert(not bar.visible and changes == 2 and closes == 1)")
+                .exec()?;
+            // The layer surface was destroyed once, when it was closed.
+            server.roundtrip();
+            let destroyed: Vec<_> = server
+                .take_requests()
+                .into_iter()
+                .filter(|request| request.name == "destroy")
+                .map(|request| request.interface)
+                .collect();
+            assert_eq!(destroyed, ["zwlr_layer_surface_v1", "wl_surface"]);
+            // Shown again it gets a surface of its own, which is drawn once
+            // it's configured.
+            lua.load("bar.visible = true").exec()?;
+            let reopened = bar.state()?.layer_surface.as_ref().unwrap().id();
+            server.roundtrip();
+            let created: Vec<_> = server
+                .take_requests()
+                .into_iter()
+                .filter(|request| request.name == "get_layer_surface")
+                .map(|request| request.args[0] as u32)
+                .collect();
+            assert_eq!(created, [reopened]);
+            server.configure(
+                reopened,
+                2,
+                Size {
+                    width: 100,
+                    height: 20
+                }
+            );
+            server.roundtrip();
+            scheduler::run_deferred(lua);
+            lua.load("bar.drawable:refresh()").exec()?;
+            server.roundtrip();
+            let frame = server.take_frames().pop().expect("the drawin wasn't drawn again");
+            assert_eq!(
+                frame.size,
+                Size {
+                    width: 100,
+                    height: 20
+                }
+            );
+            lua.load("assert(bar.visible and changes == 3 and closes == 1)")
+                .exec()?;
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawin_moved_with_margins() -> rlua::Result<()> {
+        let mut server = TestServer::start(LAYER_SHELL_MAX_VERSION);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("moves.trace");
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            screen::init(lua)?;
+            let options = event_trace::Options {
+                input: false,
+                redact: true
+            };
+            event_trace::start(&path, options).unwrap();
+            lua.load("bar = drawin{ x = 10, y = 5, width = 100, height = 20, visible = true }")
+                .exec()?;
+            let bar: Drawin = lua.globals().get("bar")?;
+            let id = bar.state()?.layer_surface.as_ref().unwrap().id();
+            server.roundtrip();
+            server.configure(
+                id,
+                1,
+                Size {
+                    width: 100,
+                    height: 20
+                }
+            );
+            server.roundtrip();
+            scheduler::run_deferred(lua);
+            lua.load("bar.drawable:refresh()").exec()?;
+            server.roundtrip();
+            for moved in &["bar.x = 30", "bar:geometry{ x = 40, y = 60 }"] {
+                lua.load(moved).exec()?;
+                scheduler::run_deferred(lua);
+                server.roundtrip();
+            }
+            event_trace::stop().unwrap().unwrap();
+            let trace = event_trace::format::decode(&fs::read(&path).unwrap()).unwrap();
+            let placement = |message: &str, args: Vec<String>| match message {
+                "attach" | "set_margin" => Some(format!("{}({})", message, args.join(", "))),
+                _ => None
+            };
+            let recorded: Vec<_> = trace
+                .records
+                .iter()
+                .filter(|record| record.direction == Direction::Request)
+                .filter_map(|record| {
+                    let args = record.args.iter().map(|arg| match arg {
+                        Arg::Object(Some(object)) => object.interface.clone(),
+                        Arg::Int(value) => value.to_string(),
+                        arg => format!("{:?}", arg)
+                    });
+                    placement(&record.message, args.collect())
+                })
+                .collect();
+            // The drawin is placed with the margins from the top left
+            // corner, and the buffer stays at the origin of the surface.
+            assert_eq!(
+                recorded,
+                [
+                    "set_margin(5, 0, 0, 10)",
+                    "attach(wl_buffer, 0, 0)",
+                    "attach(wl_buffer, 0, 0)",
+                    "set_margin(5, 0, 0, 30)",
+                    "attach(wl_buffer, 0, 0)",
+                    "set_margin(60, 0, 0, 40)"
+                ]
+            );
+            // That's what the compositor got.
+            let sent: Vec<_> = server
+                .take_requests()
+                .iter()
+                .filter_map(|request| {
+                    let mut args: Vec<_> = request.args.iter().map(i64::to_string).collect();
+                    if request.name == "attach" {
+                        args[0] = "wl_buffer".into();
+                    }
+                    placement(request.name, args)
+                })
+                .collect();
+            assert_eq!(sent, recorded);
+            Ok(())
+        })
+    }
+
+    /// A drawin of the real client against a server of its own, which the
+    /// events of a trace are replayed to. The requests it made are read
+    /// back from a recording of its own, started again each time.
+    struct Session<'s, 'lua> {
+        server: &'s mut TestServer,
+        lua: rlua::Context<'lua>,
+        path: std::path::PathBuf
+    }
+
+    impl<'s, 'lua> Session<'s, 'lua> {
+        /// Records the drawin being shown, which is drawn again whenever
+        /// its surface changes.
+        fn start(
+            server: &'s mut TestServer,
+            lua: rlua::Context<'lua>,
+            path: std::path::PathBuf
+        ) -> rlua::Result<Self> {
+            drawable::init(lua)?;
+            init(lua)?;
+            screen::init(lua)?;
+            let options = event_trace::Options {
+                input: false,
+                redact: true
+            };
+            event_trace::start(&path, options).unwrap();
+            lua.load(
+                r#"
+bar = drawin{ x = 10, y = 5, width = 100, height = 20, visible = true }
+bar.drawable:connect_signal("property::surface", function(d) d:refresh() end)
+bar.drawable:refresh()
+"#
+            )
+            .exec()?;
+            Ok(Session { server, lua, path })
+        }
+
+        /// The trace recorded since the last call.
+        fn recorded(&mut self) -> event_trace::format::Trace {
+            event_trace::stop().unwrap().unwrap();
+            let trace = event_trace::format::decode(&fs::read(&self.path).unwrap()).unwrap();
+            let options = event_trace::Options {
+                input: false,
+                redact: true
+            };
+            event_trace::start(&self.path, options).unwrap();
+            trace
+        }
+    }
+
+    impl<'s, 'lua> Target for Session<'s, 'lua> {
+        fn deliver(&mut self, event: &Record) {
+            let opcode = match (event.object.interface.as_str(), event.message.as_str()) {
+                ("zwlr_layer_surface_v1", "configure") | ("wl_callback", "done") => 0,
+                ("zwlr_layer_surface_v1", "closed") => 1,
+                _ => panic!("{}.{} can't be replayed", event.object.interface, event.message)
+            };
+            let args: Vec<_> = event
+                .args
+                .iter()
+                .map(|arg| match arg {
+                    Arg::Uint(value) => *value as u32,
+                    arg => panic!("{:?} can't be replayed", arg)
+                })
+                .collect();
+            self.server.send_event(event.object.id, opcode, &args);
+            self.server.roundtrip();
+            scheduler::run_deferred(self.lua);
+            self.server.roundtrip();
+        }
+
+        fn requests(&mut self) -> Vec<Record> {
+            self.recorded()
+                .records
+                .into_iter()
+                .filter(|record| record.direction == Direction::Request)
+                .collect()
+        }
+    }
+
+    /// The event `message` to `object`, as the session would record it.
+    fn compositor_event(interface: &str, object: u32, message: &str, args: &[u32]) -> Record {
+        Record {
+            time: 0,
+            direction: Direction::Event,
+            object: ObjectRef {
+                interface: interface.into(),
+                id: object
+            },
+            message: message.into(),
+            args: args.iter().map(|arg| Arg::Uint((*arg).into())).collect()
+        }
+    }
+
+    /// Answers the frame callbacks the session asked for since the last
+    /// call.
+    fn answer_frames(session: &mut Session) {
+        for request in session.server.take_requests() {
+            if request.name == "frame" {
+                let done = compositor_event("wl_callback", request.args[0] as u32, "done", &[0]);
+                session.deliver(&done)
+            }
+        }
+    }
+
+    #[test]
+    fn drawin_trace_replayed_to_client() -> rlua::Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let trace = {
+            let mut server = TestServer::start(LAYER_SHELL_MAX_VERSION);
+            let lua = Lua::new();
+            lua.context(|lua| -> rlua::Result<_> {
+                let mut session = Session::start(&mut server, lua, dir.path().join("recorded.trace"))?;
+                let bar: Drawin = lua.globals().get("bar")?;
+                let id = bar.state()?.layer_surface.as_ref().unwrap().id();
+                session.server.roundtrip();
+                for serial in 1..=2 {
+                    let configure =
+                        compositor_event("zwlr_layer_surface_v1", id, "configure", &[serial, 100, 20]);
+                    session.deliver(&configure);
+                    answer_frames(&mut session);
+                }
+                session.deliver(&compositor_event("zwlr_layer_surface_v1", id, "closed", &[]));
+                let trace = session.recorded();
+                event_trace::stop().unwrap().unwrap();
+                Ok(trace)
+            })?
+        };
+        let replay_to_client = |trace: &event_trace::format::Trace, name: &str| {
+            let mut server = TestServer::start(LAYER_SHELL_MAX_VERSION);
+            let lua = Lua::new();
+            lua.context(|lua| -> rlua::Result<_> {
+                let mut session = Session::start(&mut server, lua, dir.path().join(name))?;
+                let replayed = replay(trace, &mut session, Timing::FastForward);
+                event_trace::stop().unwrap().unwrap();
+                Ok(replayed)
+            })
+        };
+        let messages: Vec<_> = trace
+            .records
+            .iter()
+            .map(|record| {
+                format!(
+                    "{:?} {}.{}",
+                    record.direction, record.object.interface, record.message
+                )
+            })
+            .collect();
+        assert_eq!(
+            messages
+                .iter()
+                .filter(|message| message.starts_with("Event"))
+                .collect::<Vec<_>>(),
+            [
+                "Event zwlr_layer_surface_v1.configure",
+                "Event wl_callback.done",
+                "Event zwlr_layer_surface_v1.configure",
+                "Event zwlr_layer_surface_v1.closed"
+            ]
+        );
+        assert!(trace.records.iter().all(|record| record.object.id != 0));
+
+        // A client of its own replays it without diverging.
+        assert_eq!(replay_to_client(&trace, "replayed.trace")?, Ok(()));
+
+        // Configured with another serial the client acks that one, which is
+        // where the replay diverges.
+        let second = trace
+            .records
+            .iter()
+            .position(|record| record.message == "configure" && record.args[0] == Arg::Uint(2))
+            .unwrap();
+        let mut mutated = trace;
+        mutated.records[second].args[0] = Arg::Uint(7);
+        let divergence = replay_to_client(&mutated, "mutated.trace")?.unwrap_err();
+        assert_eq!(divergence.index, second + 1);
+        assert_eq!(divergence.after, Some(second));
+        assert_eq!(divergence.reason, "argument 1 differs");
+        let found = divergence.found.unwrap();
+        assert_eq!(
+            (found.message.as_str(), found.args),
+            ("ack_configure", vec![Arg::Uint(7)])
+        );
+        assert_eq!(divergence.expected.unwrap().args, [Arg::Uint(2)]);
+        Ok(())
+    }
+
+    #[test]
+    fn drawin_redrawn_at_same_size() -> rlua::Result<()> {
+        let mut server = TestServer::start(LAYER_SHELL_MAX_VERSION);
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            screen::init(lua)?;
+            lua.load("clock = drawin{ width = 4, height = 2, visible = true }")
+                .exec()?;
+            let clock: Drawin = lua.globals().get("clock")?;
+            let id = clock.state()?.layer_surface.as_ref().unwrap().id();
+            server.roundtrip();
+            server.configure(id, 1, Size { width: 4, height: 2 });
+            server.roundtrip();
+            scheduler::run_deferred(lua);
+            server.roundtrip();
+            server.take_frames();
+            // Like a clock ticking, painted over at the same size and
+            // refreshed each time.
+            let mut seen = Vec::new();
+            for &(red, blue) in &[(1.0, 0.0), (0.0, 1.0)] {
+                {
+                    let drawable = clock.drawable()?;
+                    let state = drawable.state()?;
+                    let cr = cairo::Context::new(state.surface.as_ref().unwrap());
+                    cr.set_source_rgb(red, 0.0, blue);
+                    cr.paint();
+                }
+                lua.load("clock.drawable:refresh()").exec()?;
+                scheduler::run_deferred(lua);
+                server.roundtrip();
+                seen.extend(server.take_frames());
+                // The compositor is done with the frame, so the next one
+                // isn't held back.
+                for request in server.take_requests() {
+                    if request.name == "frame" {
+                        server.send_event(request.args[0] as u32, 0, &[0]);
+                    }
+                }
+                server.roundtrip();
+                scheduler::run_deferred(lua);
+            }
+            // What the compositor read from the buffer on each commit.
+            let colors: Vec<Vec<u32>> = seen
+                .iter()
+                .map(|frame| {
+                    frame
+                        .pixels
+                        .chunks(4)
+                        .map(|pixel| u32::from_ne_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]))
+                        .collect()
+                })
+                .collect();
+            assert_eq!(colors, [vec![0xffff_0000; 8], vec![0xff00_00ff; 8]]);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawin_rescaled_between_outputs() -> rlua::Result<()> {
+        // The outputs are given screens in `LUA`.
+        LUA.with(|lua| {
+            let lua = lua.borrow();
+            lua.context(|lua| {
+                drawable::init(lua)?;
+                init(lua)?;
+                screen::init(lua).map(|_| ())
+            })
+        })?;
+        let mut server = TestServer::with_outputs(LAYER_SHELL_MAX_VERSION, &[1, 2]);
+        let (low, high) = (server.outputs[0], server.outputs[1]);
+        let result = LUA.with(|lua| {
+            let lua = lua.borrow();
+            lua.context(|lua| {
+                lua.load("bar = drawin{ width = 100, height = 20, visible = true }")
+                    .exec()?;
+                let bar: Drawin = lua.globals().get("bar")?;
+                let (id, surface) = {
+                    let state = bar.state()?;
+                    let layer_surface = state.layer_surface.as_ref().unwrap();
+                    (layer_surface.id(), layer_surface.wl_surface().as_ref().id())
+                };
+                server.roundtrip();
+                server.configure(
+                    id,
+                    1,
+                    Size {
+                        width: 100,
+                        height: 20
+                    }
+                );
+                server.roundtrip();
+                scheduler::run_deferred(lua);
+                server.roundtrip();
+                server.take_frames();
+                server.take_requests();
+                lua.load(
+                    r#"
+allocated = 0
+bar.drawable:connect_signal("property::surface", function() allocated = allocated + 1 end)
+"#
+                )
+                .exec()?;
+                let mut shown = Vec::new();
+                for &(enter, leave) in &[(low, None), (high, Some(low)), (low, Some(high))] {
+                    server.enter(surface, enter);
+                    if let Some(leave) = leave {
+                        server.leave(surface, leave);
+                    }
+                    server.roundtrip();
+                    scheduler::run_deferred(lua);
+                    // Painted like wibox paints, in logical pixels: a 10x10
+                    // blue square on red.
+                    {
+                        let drawable = bar.drawable()?;
+                        let state = drawable.state()?;
+                        let cr = cairo::Context::new(state.surface.as_ref().unwrap());
+                        cr.set_source_rgb(1.0, 0.0, 0.0);
+                        cr.paint();
+                        cr.set_source_rgb(0.0, 0.0, 1.0);
+                        cr.rectangle(0.0, 0.0, 10.0, 10.0);
+                        cr.fill();
+                    }
+                    lua.load("bar.drawable:refresh()").exec()?;
+                    scheduler::run_deferred(lua);
+                    server.roundtrip();
+                    let requests = server.take_requests();
+                    let scales: Vec<_> = requests
+                        .iter()
+                        .filter(|request| request.name == "set_buffer_scale")
+                        .map(|request| request.args[0])
+                        .collect();
+                    let frame = server.take_frames().pop().expect("the drawin wasn't committed");
+                    let pixel = |x: usize, y: usize| {
+                        let at = y * frame.stride as usize + x * 4;
+                        let pixel = &frame.pixels[at..at + 4];
+                        u32::from_ne_bytes([pixel[0], pixel[1], pixel[2], pixel[3]])
+                    };
+                    // The last pixel of the square, and the first past it.
+                    let edge = frame.size.width as usize / 10 - 1;
+                    shown.push((frame.size, scales, pixel(edge, edge), pixel(edge + 1, edge + 1)));
+                    // The compositor is done with the frame.
+                    for request in requests {
+                        if request.name == "frame" {
+                            server.send_event(request.args[0] as u32, 0, &[0]);
+                        }
+                    }
+                    server.roundtrip();
+                    scheduler::run_deferred(lua);
+                }
+                let size = |width, height| Size { width, height };
+                let (blue, red) = (0xff00_00ff, 0xffff_0000);
+                assert_eq!(
+                    shown,
+                    [
+                        (size(100, 20), vec![], blue, red),
+                        (size(200, 40), vec![2], blue, red),
+                        (size(100, 20), vec![1], blue, red)
+                    ]
+                );
+                // Allocated again on each move between the scales.
+                lua.load("assert(allocated == 2)").exec()
+            })
+        });
+        // What was made with the server goes before it.
+        LUA.with(|lua| *lua.borrow_mut() = Lua::new());
+        result
+    }
+
+    #[test]
+    fn drawin_getters_in_signal_handlers() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            screen::init(lua)?;
+            // Handlers only get the drawin, and read it while it changes.
+            let shown = lua.create_function(|_, drawin: Drawin| {
+                Ok((
+                    drawin.get_visible()?,
+                    drawin.drawable()?.get_geometry()?.size.width
+                ))
+            })?;
+            lua.globals().set("shown", shown)?;
+            lua.load(
+                r#"
+local popup = drawin{}
+local seen = {}
+local function read(d)
+    local visible, width = shown(d)
+    assert(visible == d.visible)
+    seen[#seen + 1] = { visible, width, d:geometry().width, d:struts().top, d.index }
+end
+for _, name in ipairs{ "property::visible", "property::width", "property::geometry", "property::struts" } do
+    popup:connect_signal(name, read)
+end
+popup.visible = true
+popup.width = 20
+popup:struts{ top = 5 }
+popup.visible = false
+assert(#seen == 5, #seen)
+assert(seen[1][1] and seen[1][3] == 0)
+assert(seen[2][3] == 20 and seen[3][3] == 20)
+assert(seen[4][4] == 5 and seen[4][5] == 1)
+assert(not seen[5][1])
+"#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn drawin_hidden_content_not_written() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            screen::init(lua)?;
+            let bar = lua
+                .load("drawin{ x = 10, y = 10, width = 100, height = 20 }")
+                .eval::<Drawin>()?;
+            lua.globals().set("bar", bar.clone())?;
+            // As if it had been shown before, so it has a surface to paint.
+            bar.drawable()?.set_geometry(lua, bar.get_geometry()?)?;
+            // Refreshing it would write the buffer, which needs a compositor.
+            lua.load(
+                r#"
+for _ = 1, 3 do
+    bar.drawable:refresh()
+end
+assert(bar.drawable:frame_stats().frames == 0)
+"#
+            )
+            .exec()?;
+            assert!(bar.state()?.layer_surface.is_none());
+            // Drawins without a size can be toggled for real.
+            lua.load(
+                r#"
+local popup = drawin{}
+for _ = 1, 5 do
+    popup.visible = true
+    popup.drawable:refresh()
+    popup.visible = false
+    popup.drawable:refresh()
+end
+assert(popup.drawable:frame_stats().frames == 0)
+"#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn drawin_toggled_against_compositor() -> rlua::Result<()> {
+        let mut server = TestServer::start(LAYER_SHELL_MAX_VERSION);
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            screen::init(lua)?;
+            lua.load("bar = drawin{ x = 10, y = 10, width = 100, height = 20 }")
+                .exec()?;
+            let bar: Drawin = lua.globals().get("bar")?;
+            let size = Size {
+                width: 100,
+                height: 20
+            };
+            let paint = |color: u32| -> rlua::Result<()> {
+                let drawable = bar.drawable()?;
+                let state = drawable.state()?;
+                let cr = cairo::Context::new(state.surface.as_ref().unwrap());
+                let channel = |shift: u32| f64::from((color >> shift) & 0xff) / 255.0;
+                cr.set_source_rgb(channel(16), channel(8), channel(0));
+                cr.paint();
+                Ok(())
+            };
+            // Shown and drawn once, with the frame answered.
+            let show = |server: &mut TestServer, serial| -> rlua::Result<Vec<Request>> {
+                lua.load("bar.visible = true").exec()?;
+                let id = bar.state()?.layer_surface.as_ref().unwrap().id();
+                server.roundtrip();
+                server.configure(id, serial, size);
+                server.roundtrip();
+                scheduler::run_deferred(lua);
+                server.roundtrip();
+                Ok(server.take_requests())
+            };
+            let answer_frames = |server: &mut TestServer, requests: Vec<Request>| {
+                for request in requests {
+                    if request.name == "frame" {
+                        server.send_event(request.args[0] as u32, 0, &[0]);
+                    }
+                }
+                server.roundtrip();
+                scheduler::run_deferred(lua);
+                server.roundtrip();
+                server.take_requests();
+                server.take_frames();
+            };
+            let mut requests = show(&mut server, 1)?;
+            lua.load("bar.drawable:refresh()").exec()?;
+            server.roundtrip();
+            requests.extend(server.take_requests());
+            assert!(requests.iter().any(|request| request.name == "attach"));
+            answer_frames(&mut server, requests);
+            let (red, blue) = (0xffff_0000, 0xff00_00ff);
+            for (serial, &(color, expected)) in (2..).zip(&[(red, red), (blue, blue), (red, red)]) {
+                // Painted and refreshed while hidden, nothing reaches the
+                // compositor but the destroyed surface.
+                lua.load("bar.visible = false").exec()?;
+                paint(color)?;
+                lua.load("bar.drawable:refresh() bar.drawable:refresh()").exec()?;
+                scheduler::run_deferred(lua);
+                server.roundtrip();
+                let sent: Vec<_> = server
+                    .take_requests()
+                    .iter()
+                    .map(|request| format!("{}.{}", request.interface, request.name))
+                    .collect();
+                assert_eq!(sent, ["zwlr_layer_surface_v1.destroy", "wl_surface.destroy"]);
+                assert!(server.take_frames().is_empty());
+                // Shown again, the content refreshed while hidden is drawn.
+                let requests = show(&mut server, serial)?;
+                let frame = server
+                    .take_frames()
+                    .pop()
+                    .expect("the drawin wasn't drawn when shown");
+                let pixel =
+                    u32::from_ne_bytes([frame.pixels[0], frame.pixels[1], frame.pixels[2], frame.pixels[3]]);
+                assert_eq!(pixel, expected);
+                answer_frames(&mut server, requests);
+            }
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawin_refreshes_wait_for_frame() -> rlua::Result<()> {
+        let mut server = TestServer::start(LAYER_SHELL_MAX_VERSION);
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            screen::init(lua)?;
+            lua.load("bar = drawin{ x = 10, y = 10, width = 100, height = 20, visible = true }")
+                .exec()?;
+            let bar: Drawin = lua.globals().get("bar")?;
+            let id = bar.state()?.layer_surface.as_ref().unwrap().id();
+            server.roundtrip();
+            server.configure(
+                id,
+                1,
+                Size {
+                    width: 100,
+                    height: 20
+                }
+            );
+            server.roundtrip();
+            scheduler::run_deferred(lua);
+            lua.load("bar.drawable:refresh()").exec()?;
+            server.roundtrip();
+            let frames: Vec<_> = server
+                .take_requests()
+                .into_iter()
+                .filter(|request| request.name == "frame")
+                .collect();
+            assert_eq!(frames.len(), 1);
+            server.take_frames();
+            // A widget refreshing in a loop, painting a new colour each time.
+            let paint = |color: u32| -> rlua::Result<()> {
+                let drawable = bar.drawable()?;
+                let state = drawable.state()?;
+                let cr = cairo::Context::new(state.surface.as_ref().unwrap());
+                let channel = |shift: u32| f64::from((color >> shift) & 0xff) / 255.0;
+                cr.set_source_rgb(channel(16), channel(8), channel(0));
+                cr.paint();
+                Ok(())
+            };
+            for color in 0..100 {
+                paint(color)?;
+                lua.load("bar.drawable:refresh()").exec()?;
+                scheduler::run_deferred(lua);
+            }
+            server.roundtrip();
+            assert_eq!(server.take_requests(), []);
+            assert!(server.take_frames().is_empty());
+            lua.load(
+                r#"
+local stats = bar.drawable:frame_stats()
+assert(stats.throttled_frames == 1 and stats.coalesced_frames == 99)
+"#
+            )
+            .exec()?;
+            // Once the compositor is done with the frame the last content
+            // is committed, once.
+            server.send_event(frames[0].args[0] as u32, 0, &[0]);
+            server.roundtrip();
+            scheduler::run_deferred(lua);
+            server.roundtrip();
+            let sent: Vec<_> = server
+                .take_requests()
+                .iter()
+                .map(|request| format!("{}.{}", request.interface, request.name))
+                .collect();
+            assert_eq!(
+                sent,
+                [
+                    "wl_surface.attach",
+                    "wl_surface.damage",
+                    "wl_surface.frame",
+                    "wl_surface.commit"
+                ]
+            );
+            let committed = server.take_frames();
+            assert_eq!(committed.len(), 1);
+            let pixels = &committed[0].pixels;
+            let pixel = u32::from_ne_bytes([pixels[0], pixels[1], pixels[2], pixels[3]]);
+            assert_eq!(pixel, 0xff00_0063);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawin_mapped_buffer_shown() -> rlua::Result<()> {
+        let mut server = TestServer::start(LAYER_SHELL_MAX_VERSION);
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            screen::init(lua)?;
+            lua.load(
+                r#"
+bar = drawin{ x = 10, y = 10, width = 100, height = 20 }
+bar.drawable.buffer_backend = "mapped"
+bar.visible = true
+"#
+            )
+            .exec()?;
+            let bar: Drawin = lua.globals().get("bar")?;
+            let id = bar.state()?.layer_surface.as_ref().unwrap().id();
+            server.roundtrip();
+            server.configure(
+                id,
+                1,
+                Size {
+                    width: 100,
+                    height: 20
+                }
+            );
+            server.roundtrip();
+            scheduler::run_deferred(lua);
+            server.roundtrip();
+            server.take_requests();
+            server.take_frames();
+            // Paints `color` and refreshes, returning the buffer that was
+            // attached, the colour the compositor got and the frame
+            // callback.
+            let refresh = |server: &mut TestServer, color: u32| -> rlua::Result<(i64, u32, u32)> {
+                {
+                    let drawable = bar.drawable()?;
+                    let state = drawable.state()?;
+                    let cr = cairo::Context::new(state.surface.as_ref().unwrap());
+                    let channel = |shift: u32| f64::from((color >> shift) & 0xff) / 255.0;
+                    cr.set_source_rgb(channel(16), channel(8), channel(0));
+                    cr.paint();
+                }
+                lua.load("bar.drawable:refresh()").exec()?;
+                server.roundtrip();
+                let requests = server.take_requests();
+                let attached = requests
+                    .iter()
+                    .find(|request| request.name == "attach")
+                    .unwrap()
+                    .args[0];
+                let callback = requests
+                    .iter()
+                    .find(|request| request.name == "frame")
+                    .unwrap()
+                    .args[0];
+                let frame = server.take_frames().pop().unwrap();
+                let pixels = &frame.pixels;
+                let pixel = u32::from_ne_bytes([pixels[0], pixels[1], pixels[2], pixels[3]]);
+                Ok((attached, pixel, callback as u32))
+            };
+            let stats = || -> rlua::Result<(Option<String>, u64, Option<String>)> {
+                let stats: Table = lua.load("bar.drawable:frame_stats()").eval()?;
+                Ok((
+                    stats.get("buffer_backend")?,
+                    stats.get("last_bytes_copied")?,
+                    stats.get("buffer_fallback")?
+                ))
+            };
+            let (blue, red) = (0xff00_00ff, 0xffff_0000);
+            let (mapped, pixel, callback) = refresh(&mut server, blue)?;
+            assert_eq!(pixel, blue);
+            assert_eq!(stats()?, (Some("mapped".into()), 0, None));
+            // Released by the compositor, the buffer is painted into and
+            // attached again, still without a copy.
+            server.send_event(callback, 0, &[0]);
+            server.send_event(mapped as u32, 0, &[]);
+            server.roundtrip();
+            scheduler::run_deferred(lua);
+            let (attached, pixel, callback) = refresh(&mut server, red)?;
+            assert_eq!((attached, pixel), (mapped, red));
+            assert_eq!(stats()?, (Some("mapped".into()), 0, None));
+            // Not released before the next frame, the content is copied into
+            // a buffer of its own from then on.
+            server.send_event(callback, 0, &[0]);
+            server.roundtrip();
+            scheduler::run_deferred(lua);
+            let (attached, pixel, _) = refresh(&mut server, blue)?;
+            assert_ne!(attached, mapped);
+            assert_eq!(pixel, blue);
+            let (backend, copied, fallback) = stats()?;
+            assert_eq!(backend.as_deref(), Some("shm"));
+            assert_eq!(copied, 100 * 20 * 4);
+            assert!(fallback.unwrap().contains("didn't release the mapped buffer"));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawin_clipped_to_output() -> rlua::Result<()> {
+        // The output is given a screen in `LUA`.
+        LUA.with(|lua| {
+            let lua = lua.borrow();
+            lua.context(|lua| {
+                drawable::init(lua)?;
+                init(lua)?;
+                screen::init(lua).map(|_| ())
+            })
+        })?;
+        let mut server = TestServer::with_outputs(LAYER_SHELL_MAX_VERSION, &[1]);
+        let result = LUA.with(|lua| {
+            let lua = lua.borrow();
+            lua.context(|lua| {
+                lua.load("bar = drawin{ x = 950, y = 10, width = 100, height = 20, visible = true }")
+                    .exec()?;
+                let bar: Drawin = lua.globals().get("bar")?;
+                let id = bar.state()?.layer_surface.as_ref().unwrap().id();
+                // Red on the left half of the content, blue on the right.
+                {
+                    let drawable = bar.drawable()?;
+                    let state = drawable.state()?;
+                    let cr = cairo::Context::new(state.surface.as_ref().unwrap());
+                    cr.set_source_rgb(1.0, 0.0, 0.0);
+                    cr.paint();
+                    cr.set_source_rgb(0.0, 0.0, 1.0);
+                    cr.rectangle(50.0, 0.0, 50.0, 20.0);
+                    cr.fill();
+                }
+                lua.load("bar.drawable:refresh()").exec()?;
+                let (red, blue) = (0xffff_0000, 0xff00_00ff);
+                let mut shown = Vec::new();
+                for (serial, moved) in (1..).zip(&["", "bar.x = -30", "bar.x = 100"]) {
+                    lua.load(moved).exec()?;
+                    scheduler::run_deferred(lua);
+                    server.roundtrip();
+                    let requests = server.take_requests();
+                    let placed: Vec<_> = requests
+                        .iter()
+                        .filter(|request| request.name == "set_size" || request.name == "set_margin")
+                        .map(ToString::to_string)
+                        .collect();
+                    let size = requests
+                        .iter()
+                        .find(|request| request.name == "set_size")
+                        .map(|request| Size {
+                            width: request.args[0] as u32,
+                            height: request.args[1] as u32
+                        })
+                        .unwrap();
+                    // The compositor grants the part that's on the output.
+                    server.configure(id, serial, size);
+                    server.roundtrip();
+                    scheduler::run_deferred(lua);
+                    server.roundtrip();
+                    // Buffers are always attached at the origin.
+                    assert!(server
+                        .take_requests()
+                        .iter()
+                        .filter(|request| request.name == "attach")
+                        .all(|request| request.args[1..] == [0, 0]));
+                    let frame = server.take_frames().pop().expect("the drawin wasn't drawn");
+                    let pixel = |x: usize| {
+                        let pixel = &frame.pixels[x * 4..x * 4 + 4];
+                        u32::from_ne_bytes([pixel[0], pixel[1], pixel[2], pixel[3]])
+                    };
+                    let geometry: (i32, u32) = lua.load("return bar.x, bar.width").eval()?;
+                    shown.push((placed, frame.size, pixel(0), pixel(25), geometry));
+                }
+                let size = |width| Size { width, height: 20 };
+                let placed = |width, left| {
+                    vec![
+                        format!("zwlr_layer_surface_v1.set_size({}, 20)", width),
+                        format!("zwlr_layer_surface_v1.set_margin(10, 0, 0, {})", left),
+                    ]
+                };
+                // Shown only where it's on the output, with the part of the
+                // content that's there, while Lua keeps the whole geometry.
+                assert_eq!(
+                    shown,
+                    [
+                        (placed(50, 950), size(50), red, red, (950, 100)),
+                        (placed(70, 0), size(70), red, blue, (-30, 100)),
+                        (placed(100, 100), size(100), red, red, (100, 100))
+                    ]
+                );
+                Ok(())
+            })
+        });
+        // What was made with the server goes before it.
+        LUA.with(|lua| *lua.borrow_mut() = Lua::new());
+        result
+    }
+
+    #[test]
+    fn drawin_format() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            screen::init(lua)?;
+            lua.load(
+                r#"
+local bar = drawin{ format = "rgb24", width = 100, height = 20 }
+assert(bar.format == "rgb24" and bar.drawable.format == "rgb24")
+bar.format = "argb32"
+assert(bar.drawable.format == "argb32")
+assert(not pcall(function() bar.format = "a8" end))
+assert(drawin{}.format == "argb32")
+"#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn drawin_stacking_order() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            screen::init(lua)?;
+            lua.load(
+                r#"
+local bar, popup, menu = drawin{}, drawin{}, drawin{}
+assert(bar.index == 1 and popup.index == 2 and menu.index == 3)
+assert(drawin.instances() == 3)
+local changes = {}
+for _, d in ipairs({ bar, popup, menu }) do
+    d:connect_signal("property::index", function(d) changes[d] = (changes[d] or 0) + 1 end)
+end
+bar:raise()
+assert(popup.index == 1 and menu.index == 2 and bar.index == 3)
+assert(changes[bar] == 1 and changes[popup] == 1 and changes[menu] == 1)
+bar:raise()
+assert(bar.index == 3 and changes[bar] == 1)
+menu:lower()
+assert(menu.index == 1 and popup.index == 2 and bar.index == 3)
+assert(changes[menu] == 2 and changes[popup] == 2 and changes[bar] == 1)
+-- Hiding and showing it doesn't change its place.
+popup.visible = true
+popup.visible = false
+assert(popup.index == 2)
+popup:remove()
+assert(menu.index == 1 and bar.index == 2)
+local all = drawin.get()
+assert(#all == 2 and all[1] == menu and all[2] == bar and drawin.instances() == 2)
+"#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn drawin_geometry_batched() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            screen::init(lua)?;
+            let redraws = || -> rlua::Result<usize> {
+                let stats = scheduler::scheduler_stats(lua, ())?;
+                stats.get::<_, Table>("redraw")?.get("depth")
+            };
+            let popup: Drawin = lua.load("popup = drawin{} return popup").eval()?;
+            let queued = redraws()?;
+            lua.load(
+                r#"
+signals = {}
+for _, name in ipairs({ "x", "y", "width", "height", "geometry" }) do
+    popup:connect_signal("property::" .. name, function()
+        signals[name] = (signals[name] or 0) + 1
+    end)
+end
+popup.x = 10
+popup.y = 20
+popup.width = 100
+popup.height = 50
+popup.x = 10
+assert(signals.x == 1 and signals.y == 1 and signals.width == 1 and signals.height == 1)
+assert(signals.geometry == 4)
+"#
+            )
+            .exec()?;
+            // The surfaces are resized once, after the Lua code is done.
+            assert!(popup.state()?.update_queued);
+            assert_eq!(redraws()?, queued + 1);
+            lua.load("popup:geometry{ x = 0 }").exec()?;
+            assert_eq!(redraws()?, queued + 1);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawin_removed() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            screen::init(lua)?;
+            lua.load("bar = drawin{ width = 100, height = 20 }").exec()?;
+            let bar: Drawin = lua.globals().get("bar")?;
+            lua.load(
+                r#"
+local id = bar.id
+assert(drawin.by_id(id) == bar)
+local other = drawin{}
+assert(bar.window == id and other.window > id)
+assert(tostring(bar):find("(window " .. id .. ")", 1, true), tostring(bar))
+other:remove()
+bar:remove()
+assert(drawin.by_id(id) == nil)
+assert(not bar.valid and bar.id == id and bar.window == id)
+local ok, err = pcall(function() return bar.visible end)
+assert(not ok and tostring(err):find("was destroyed"), err)
+assert(not pcall(function() bar.visible = true end))
+assert(not pcall(function() bar:geometry() end))
+assert(not pcall(function() bar:remove() end))
+assert(drawin.instances() == 0 and #drawin.get() == 0)
+"#
+            )
+            .exec()?;
+            let drawins = lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)?;
+            assert!(drawins.is_empty());
+            assert_eq!(bar.drawable()?.memory()?.total(), 0);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawin_pinned_to_screen() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            screen::init(lua)?;
+            let mut external = Screen::new(lua)?;
+            external.state_mut()?.geometry = area::Area {
+                origin: area::Origin { x: 1024, y: 0 },
+                size: Size {
+                    width: 1920,
+                    height: 1080
+                }
+            };
+            screen::add_screen(lua, external.clone())?;
+            lua.load(
+                r#"
+bar = drawin{ x = 0, y = 0, width = 1024, height = 24, screen = 2 }
+assert(bar.screen == screen[2] and bar.x == 1024 and bar.width == 1920)
+changes = 0
+bar:connect_signal("property::screen", function() changes = changes + 1 end)
+bar.screen = screen[2]
+assert(changes == 0)
+bar.screen = screen[1]
+assert(bar.screen == screen[1] and bar.x == 0 and bar.width == 1024 and changes == 1)
+assert(not pcall(function() bar.screen = 3 end))
+assert(not pcall(function() bar.screen = drawin{} end))
+assert(not pcall(function() bar.screen = "HDMI-1" end))
+bar.screen = nil
+assert(bar.screen == screen[1] and changes == 2)
+bar.screen = 2
+"#
+            )
+            .exec()?;
+            // Unplugged, it's on any output again.
+            screen::remove_screen(lua, external)?;
+            super::outputs_changed(lua)?;
+            lua.load("assert(changes == 4 and bar.screen == nil)").exec()
+        })
+    }
+
+    #[test]
+    fn drawin_anchor() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            screen::init(lua)?;
+            let mut external = Screen::new(lua)?;
+            external.state_mut()?.geometry = area::Area {
+                origin: area::Origin { x: 1024, y: 0 },
+                size: Size {
+                    width: 1920,
+                    height: 1080
+                }
+            };
+            screen::add_screen(lua, external.clone())?;
+            lua.load(
+                r#"
+bar = drawin{ anchor = { "top", "left", "right" }, x = 4, y = 4, height = 24, screen = 2 }
+assert(#bar.anchor == 3 and bar.anchor[1] == "top" and bar.anchor[3] == "right")
+assert(bar.x == 4 and bar.y == 4 and bar.width == 1912)
+local shown = bar:shown_geometry()
+assert(shown.x == 1028 and shown.y == 4)
+local corner = drawin{ x = 900, y = 700, width = 100, height = 50 }
+local changes = 0
+corner:connect_signal("property::anchor", function() changes = changes + 1 end)
+corner.anchor = { "bottom", "right" }
+assert(corner.x == 24 and corner.y == 18 and changes == 1)
+corner:geometry{ x = 10 }
+assert(corner:shown_geometry().x == 914 and corner:geometry().x == 10)
+corner.anchor = {}
+assert(#corner.anchor == 0 and corner.x == 914 and corner.y == 700 and changes == 2)
+assert(not pcall(function() corner.anchor = { "middle" } end))
+assert(not pcall(function() corner.anchor = "top" end))
+assert(not pcall(function() bar:follow_pointer() end))
+"#
+            )
+            .exec()?;
+            // It's stretched again when the mode of the output changes.
+            external.state_mut()?.geometry.size.width = 2560;
+            super::update_placement(lua)?;
+            lua.load("assert(bar.x == 4 and bar.width == 2552 and bar:shown_geometry().x == 1028)")
+                .exec()
+        })
+    }
+
+    #[test]
+    fn drawin_clamp_to_output() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            screen::init(lua)?;
+            lua.load(
+                r#"
+popup = drawin{ clamp_to_output = true, x = 900, y = 700, width = 300, height = 100 }
+local g = popup:geometry()
+assert(g.x == 724 and g.y == 668 and g.width == 300 and g.height == 100)
+widths = 0
+popup:connect_signal("property::width", function() widths = widths + 1 end)
+popup:geometry{ x = -50, width = 2000 }
+g = popup:geometry()
+assert(g.x == 0 and g.y == 668 and g.width == 1024 and widths == 1)
+popup.clamp_to_output = false
+g = popup:geometry()
+assert(g.x == -50 and g.y == 700 and g.width == 2000 and widths == 2)
+popup.clamp_to_output = true
+assert(popup.x == 0 and popup.width == 1024 and widths == 3)
+"#
+            )
+            .exec()?;
+            // It grows back when the screen does.
+            let mut screens = lua.named_registry_value::<str, Vec<Screen>>(SCREENS_HANDLE)?;
+            screens[0].state_mut()?.geometry.size.width = 2560;
+            super::update_placement(lua)?;
+            lua.load("assert(popup.x == 0 and popup.width == 2000 and popup.y == 668 and widths == 4)")
+                .exec()
+        })
+    }
+
+    #[test]
+    fn drawin_fullscreen() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            screen::init(lua)?;
+            lua.load(
+                r#"
+lock = drawin{ x = 10, y = 20, width = 300, height = 100, fullscreen = true }
+local g = lock:geometry()
+assert(lock.fullscreen and g.x == 0 and g.y == 0 and g.width == 1024 and g.height == 768)
+changes = 0
+lock:connect_signal("property::fullscreen", function() changes = changes + 1 end)
+lock:geometry{ x = 50, width = 10 }
+lock.x = 5
+g = lock:geometry()
+assert(g.x == 0 and g.width == 1024)
+lock.fullscreen = false
+g = lock:geometry()
+assert(not lock.fullscreen and changes == 1)
+assert(g.x == 10 and g.y == 20 and g.width == 300 and g.height == 100)
+lock.fullscreen = true
+"#
+            )
+            .exec()?;
+            // It follows the mode of the output.
+            let mut screens = lua.named_registry_value::<str, Vec<Screen>>(SCREENS_HANDLE)?;
+            screens[0].state_mut()?.geometry.size = Size {
+                width: 2560,
+                height: 1440
+            };
+            super::update_placement(lua)?;
+            lua.load("assert(lock.width == 2560 and lock.height == 1440 and changes == 2)")
+                .exec()
+        })
+    }
+
+    #[test]
+    fn drawin_type_layer() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            screen::init(lua)?;
+            let mut output = Screen::new(lua)?;
+            output.state_mut()?.geometry = Size {
+                width: 1000,
+                height: 800
+            }
+            .into();
+            screen::add_screen(lua, output)?;
+            let mut dock: Drawin = lua
+                .load(
+                    r#"
+assert(drawin{}.type == "normal")
+assert(drawin{ type = "desktop" }.layer == "background")
+assert(drawin{ type = "splash", layer = "bottom" }.layer == "bottom")
+local popup = drawin{ type = "notification" }
+assert(popup.ontop and popup.layer == "overlay")
+local types, layers = 0, 0
+popup:connect_signal("property::type", function() types = types + 1 end)
+popup:connect_signal("property::layer", function() layers = layers + 1 end)
+popup.type = "splash"
+assert(types == 1 and layers == 0)
+popup.type = "tooltip"
+assert(popup.layer == "top" and types == 2 and layers == 1)
+assert(not pcall(function() popup.type = "panel" end))
+dock = drawin{ y = 776, width = 1000, height = 24, type = "dock" }
+assert(dock.layer == "top" and dock:struts().bottom == 0)
+return dock
+"#
+                )
+                .eval()?;
+            let workarea = || -> rlua::Result<area::Area> {
+                let screens = lua.named_registry_value::<str, Vec<Screen>>(SCREENS_HANDLE)?;
+                // After the screen `screen::init` adds.
+                let workarea = screens[1].state()?.workarea;
+                Ok(workarea)
+            };
+            // Shown, a dock keeps the edge it's along clear.
+            dock.state_mut()?.visible = true;
+            update_workareas(lua)?;
+            let kept = workarea()?;
+            assert_eq!((kept.origin.y, kept.size.height), (0, 776));
+            // Unless Lua set its struts.
+            dock.state_mut()?.visible = false;
+            lua.load("dock:struts{ left = 1 }").exec()?;
+            dock.state_mut()?.visible = true;
+            update_workareas(lua)?;
+            assert_eq!(workarea()?.size.height, 800);
+            dock.state_mut()?.visible = false;
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawin_struts_reserve_workarea() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            screen::init(lua)?;
+            let mut output = Screen::new(lua)?;
+            output.state_mut()?.geometry = Size {
+                width: 1000,
+                height: 800
+            }
+            .into();
+            screen::add_screen(lua, output)?;
+            let mut bar: Drawin = lua
+                .load(
+                    r#"
+changes = 0
+bar = drawin{ width = 1000, height = 24 }
+bar:connect_signal("property::struts", function() changes = changes + 1 end)
+local struts = bar:struts{ top = 24, left = 10 }
+assert(struts.top == 24 and struts.left == 10 and struts.bottom == 0 and changes == 1)
+bar:struts{ top = 24, left = 10 }
+assert(changes == 1)
+assert(not pcall(bar.struts, bar, { bottom = -1 }))
+assert(bar:struts().top == 24)
+workareas = {}
+screen[2]:connect_signal("property::workarea", function(_, old)
+    table.insert(workareas, old)
+end)
+return bar
+                    "#
+                )
+                .eval()?;
+            let workarea = || -> rlua::Result<area::Area> {
+                let screens = lua.named_registry_value::<str, Vec<Screen>>(SCREENS_HANDLE)?;
+                // After the screen `screen::init` adds.
+                let workarea = screens[1].state()?.workarea;
+                Ok(workarea)
+            };
+            // Hidden, it keeps nothing clear.
+            update_workareas(lua)?;
+            let free = workarea()?;
+            assert_eq!((free.origin.y, free.size.height), (0, 800));
+            // Shown without a compositor, its strut is kept.
+            bar.state_mut()?.visible = true;
+            update_workareas(lua)?;
+            let kept = workarea()?;
+            assert_eq!((kept.origin.x, kept.origin.y), (0, 24));
+            assert_eq!((kept.size.width, kept.size.height), (1000, 776));
+            // A second bar along the same edge adds to it.
+            let mut other: Drawin = lua
+                .load(
+                    r#"
+local other = drawin{ width = 1000, height = 30 }
+other:struts{ top = 30 }
+return other
+"#
+                )
+                .eval()?;
+            other.state_mut()?.visible = true;
+            update_workareas(lua)?;
+            let stacked = workarea()?;
+            assert_eq!((stacked.origin.y, stacked.size.height), (54, 746));
+            other.state_mut()?.visible = false;
+            bar.state_mut()?.visible = false;
+            lua.load(
+                r#"
+bar:struts{}
+assert(changes == 2 and bar:struts().top == 0)
+-- Told with the workarea from before each change.
+assert(#workareas == 3)
+assert(workareas[1].y == 0 and workareas[1].height == 800)
+assert(workareas[2].y == 24 and workareas[2].height == 776)
+assert(workareas[3].y == 54 and workareas[3].height == 746)
+"#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn drawin_struts_sent_to_compositor() -> rlua::Result<()> {
+        // The output is given a screen in `LUA`.
+        LUA.with(|lua| {
+            let lua = lua.borrow();
+            lua.context(|lua| {
+                drawable::init(lua)?;
+                init(lua)?;
+                screen::init(lua).map(|_| ())
+            })
+        })?;
+        let mut server = TestServer::with_outputs(LAYER_SHELL_MAX_VERSION, &[1]);
+        let result = LUA.with(|lua| {
+            let lua = lua.borrow();
+            lua.context(|lua| {
+                let placement = |requests: Vec<Request>| -> Vec<String> {
+                    requests
+                        .iter()
+                        .filter(|request| request.name != "attach" && request.name != "damage")
+                        .map(ToString::to_string)
+                        .collect()
+                };
+                lua.load("bar = drawin{ width = 1000, height = 24, visible = true }; bar:struts{ top = 24 }")
+                    .exec()?;
+                scheduler::run_deferred(lua);
+                let bar: Drawin = lua.globals().get("bar")?;
+                let id = bar.state()?.layer_surface.as_ref().unwrap().id();
+                server.roundtrip();
+                // Shown along the top edge keeping 24 clear, which is sent
+                // before the commit the compositor configures.
+                assert_eq!(
+                    placement(server.take_requests())[5..],
+                    [
+                        "zwlr_layer_surface_v1.set_size(1000, 24)",
+                        "zwlr_layer_surface_v1.set_anchor(13)",
+                        "zwlr_layer_surface_v1.set_exclusive_zone(24)",
+                        "wl_surface.commit()"
+                    ]
+                );
+                server.configure(
+                    id,
+                    1,
+                    Size {
+                        width: 1000,
+                        height: 24
+                    }
+                );
+                server.roundtrip();
+                scheduler::run_deferred(lua);
+                lua.load("bar.drawable:refresh()").exec()?;
+                server.roundtrip();
+                server.take_requests();
+                lua.load("bar:struts{ top = 30 }").exec()?;
+                scheduler::run_deferred(lua);
+                server.roundtrip();
+                // Only the zone changed, which is committed on its own.
+                assert_eq!(
+                    placement(server.take_requests()),
+                    [
+                        "zwlr_layer_surface_v1.set_exclusive_zone(30)",
+                        "wl_surface.commit()"
+                    ]
+                );
+                // Without struts it's placed again, keeping nothing clear.
+                lua.load("bar:struts{}").exec()?;
+                scheduler::run_deferred(lua);
+                server.roundtrip();
+                assert_eq!(
+                    placement(server.take_requests()),
+                    [
+                        "zwlr_layer_surface_v1.set_size(1000, 24)",
+                        "zwlr_layer_surface_v1.set_anchor(5)",
+                        "zwlr_layer_surface_v1.set_exclusive_zone(0)",
+                        "wl_surface.commit()"
+                    ]
+                );
+                Ok(())
+            })
+        });
+        // What was made with the server goes before it.
+        LUA.with(|lua| *lua.borrow_mut() = Lua::new());
+        result
+    }
+
+    #[test]
+    fn drawin_drag() -> rlua::Result<()> {
+        const BTN_LEFT: u32 = 0x110;
+        const BTN_RIGHT: u32 = 0x111;
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            screen::init(lua)?;
+            POINTER_POSITION.with(|position| position.set(None));
+            lua.load(
+                r#"
+events = {}
+under = drawin{ width = 1000, height = 700 }
+box = drawin{ x = 100, y = 100, width = 200, height = 100 }
+for _, name in ipairs{ "drawin::drag_started", "drawin::drag_stopped", "property::geometry" } do
+    box:connect_signal(name, function() events[#events + 1] = name end)
+end
+-- Where the pointer is isn't known yet.
+assert(not pcall(box.start_move, box, 1))
+"#
+            )
+            .exec()?;
+            let under = lua.globals().get::<_, Drawin>("under")?.id()?;
+            let geometry = || {
+                lua.load("local g = box:geometry() return g.x, g.y, g.width, g.height")
+                    .eval::<(i32, i32, u32, u32)>()
+            };
+            let motion = |x: f64, y: f64| {
+                let event = PointerEvent::Motion { x, y, time: 0.0 };
+                dispatch_pointer_event(lua, event, &mut None)
+            };
+            let release = |button: u32| {
+                let event = PointerEvent::Button {
+                    button,
+                    pressed: false,
+                    time: 0.0
+                };
+                dispatch_pointer_event(lua, event, &mut None)
+            };
+            POINTER_FOCUS.with(|focus| focus.set(Some((under, 0.0, 0.0))));
+            motion(150.0, 150.0)?;
+            lua.load("box:start_move(1) assert(box.dragging and not under.dragging and #events == 1)")
+                .exec()?;
+            // A burst of motion moves it once, at the end of the frame.
+            for i in 0..20 {
+                motion(150.0 + f64::from(i), 140.0)?;
+            }
+            assert_eq!(geometry()?, (100, 100, 200, 100));
+            move_dragged(lua)?;
+            assert_eq!(geometry()?, (119, 90, 200, 100));
+            // Only its own button ends it, moving it where it was released.
+            release(BTN_RIGHT)?;
+            motion(100.0, 100.0)?;
+            release(BTN_LEFT)?;
+            assert_eq!(geometry()?, (50, 50, 200, 100));
+            lua.load(
+                r#"
+assert(not box.dragging and #events == 4)
+assert(events[2] == "property::geometry" and events[4] == "drawin::drag_stopped")
+assert(not pcall(box.start_resize, box, 1, "middle"))
+box:start_resize(nil, "top_left")
+"#
+            )
+            .exec()?;
+            motion(80.0, 90.0)?;
+            move_dragged(lua)?;
+            assert_eq!(geometry()?, (30, 40, 220, 110));
+            // Escape puts it back.
+            let event = KeyboardEvent::Key {
+                keysym: keysyms::KEY_Escape,
+                modifiers: Vec::new(),
+                pressed: true,
+                time: 0.0
+            };
+            keyboard_event(lua, event)?;
+            assert_eq!(geometry()?, (50, 50, 200, 100));
+            lua.load(
+                r#"
+assert(not box.dragging and events[#events] == "drawin::drag_stopped")
+box:follow_pointer()
+assert(not pcall(box.start_move, box))
+box:unfollow_pointer()
+box:start_move()
+box:remove()
+"#
+            )
+            .exec()?;
+            assert_eq!(DRAGS.with(|drags| drags.borrow().dragging()), None);
+            // The tests share the thread.
+            POINTER_FOCUS.with(|focus| focus.set(None));
+            POINTER_POSITION.with(|position| position.set(None));
+            FOLLOWS.with(|follows| follows.replace(Default::default()));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawin_follows_pointer_once_per_frame() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            init(lua)?;
+            screen::init(lua)?;
+            let mut output = Screen::new(lua)?;
+            output.state_mut()?.geometry = Size {
+                width: 1000,
+                height: 800
+            }
+            .into();
+            screen::add_screen(lua, output)?;
+            lua.load(
+                r#"
+events = {}
+tip = drawin{ width = 100, height = 40 }
+under = drawin{ x = 200, y = 100, width = 600, height = 300 }
+for _, name in ipairs{ "drawin::follow_started", "drawin::follow_stopped" } do
+    tip:connect_signal(name, function() events[#events + 1] = name end)
+end
+assert(not pcall(tip.follow_pointer, tip, { constrain = "screen" }))
+tip:follow_pointer{ offset_x = 10, offset_y = 20 }
+tip:follow_pointer{ offset_x = 10, offset_y = 20 }
+assert(tip.following and not under.following and #events == 1)
+                "#
+            )
+            .exec()?;
+            let under = lua.globals().get::<_, Drawin>("under")?.id()?;
+            let tip_x = || lua.load("return tip.x, tip.y").eval::<(i32, i32)>();
+            let moves = || FOLLOWS.with(|follows| follows.borrow().moves());
+            let motion = |x: f64, y: f64| {
+                let event = PointerEvent::Motion { x, y, time: 0.0 };
+                dispatch_pointer_event(lua, event, &mut None)
+            };
+            // The pointer is over another of our drawins, moving in a burst.
+            POINTER_FOCUS.with(|focus| focus.set(Some((under, 0.0, 0.0))));
+            for i in 0..20 {
+                motion(f64::from(i), 5.0)?;
+            }
+            assert_eq!(tip_x()?, (0, 0));
+            move_followers(lua)?;
+            assert_eq!(tip_x()?, (229, 125));
+            // Nothing moved since, so the next frame doesn't move it.
+            move_followers(lua)?;
+            assert_eq!(moves(), 1);
+            // Near the right edge it goes to the left of the pointer.
+            motion(780.0, 5.0)?;
+            motion(781.5, 5.0)?;
+            move_followers(lua)?;
+            assert_eq!(tip_x()?, (871, 125));
+            assert_eq!(moves(), 2);
+            // The pointer leaves for a window that isn't ours, so the tip
+            // waits where it was last seen.
+            FOLLOWS.with(|follows| follows.borrow_mut().pointer_left());
+            POINTER_FOCUS.with(|focus| focus.set(None));
+            motion(10.0, 10.0)?;
+            move_followers(lua)?;
+            assert_eq!((tip_x()?, moves()), ((871, 125), 2));
+            lua.load("assert(drawin.follow_stats().paused)").exec()?;
+            // It comes back over our drawin.
+            POINTER_FOCUS.with(|focus| focus.set(Some((under, 0.0, 0.0))));
+            motion(100.0, 100.0)?;
+            move_followers(lua)?;
+            assert_eq!((tip_x()?, moves()), ((310, 220), 3));
+            lua.load(
+                r#"
+local stats = drawin.follow_stats()
+assert(not stats.paused and stats.moves == 3)
+-- Lua can resize it, but not move it while it follows.
+tip.width = 120
+assert(tip.width == 120 and tip.x == 310)
+assert(not pcall(tip.geometry, tip, { x = 5, y = 5 }))
+                "#
+            )
+            .exec()?;
+            fn following(err: &rlua::Error) -> Option<&Following> {
+                match err {
+                    rlua::Error::CallbackError { cause, .. } => following(cause),
+                    rlua::Error::ExternalError(err) => err.downcast_ref(),
+                    _ => None
+                }
+            }
+            let DrawinId(tip) = lua.globals().get::<_, Drawin>("tip")?.id()?;
+            let err = lua.load("tip.x = 5").exec().unwrap_err();
+            assert_eq!(following(&err), Some(&Following { drawin: tip }));
+            lua.load(
+                r#"
+assert(tip.x == 310)
+tip:unfollow_pointer()
+tip:unfollow_pointer()
+assert(not tip.following and #events == 2 and events[2] == "drawin::follow_stopped")
+tip.x = 5
+assert(tip.x == 5)
+                "#
+            )
+            .exec()?;
+            // Unfollowed, motion doesn't move it anymore.
+            motion(0.0, 0.0)?;
+            move_followers(lua)?;
+            assert_eq!((tip_x()?, moves()), ((5, 220), 3));
+            // The tests share the thread.
+            POINTER_FOCUS.with(|focus| focus.set(None));
+            FOLLOWS.with(|follows| follows.replace(Default::default()));
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,194 @@
+//! The edges of its screen a drawin is anchored to, set with
+//! `drawin.anchor`.
+//!
+//! The layer surface of an anchored drawin is anchored to the same edges,
+//! so the compositor keeps it along them when the output changes mode. `x`
+//! and `y` are then the distance from the anchored edges rather than a
+//! position: anchored to two opposite edges the drawin is stretched between
+//! them with that margin on both sides, and along an axis without anchored
+//! edges it is centered.
+
+use crate::area::{AnchorEdge, AnchorSet, Area, Margin, Origin, Size};
+
+pub const NAMES: &[&str] = &["top", "bottom", "left", "right"];
+
+pub fn from_name(name: &str) -> Option<AnchorEdge> {
+    match name {
+        "top" => Some(AnchorEdge::Top),
+        "bottom" => Some(AnchorEdge::Bottom),
+        "left" => Some(AnchorEdge::Left),
+        "right" => Some(AnchorEdge::Right),
+        _ => None
+    }
+}
+
+pub fn name(edge: AnchorEdge) -> &'static str {
+    match edge {
+        AnchorEdge::Top => "top",
+        AnchorEdge::Bottom => "bottom",
+        AnchorEdge::Left => "left",
+        AnchorEdge::Right => "right"
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Anchor {
+    pub edges: AnchorSet,
+    /// The distance from the anchored edges, which Lua sees as `x` and `y`.
+    pub offset: Origin
+}
+
+impl Default for Anchor {
+    fn default() -> Self {
+        Anchor {
+            edges: AnchorSet::empty(),
+            offset: Origin::default()
+        }
+    }
+}
+
+impl Anchor {
+    /// Anchors a drawin at `geometry` on `screen` to `edges` without
+    /// moving it.
+    pub fn at(edges: AnchorSet, geometry: Area, screen: Area) -> Self {
+        let margin = geometry.margin_within(screen);
+        let offset = |before: AnchorEdge, after: AnchorEdge, from_before: i32, from_after: i32| {
+            if edges.contains(before) {
+                from_before
+            } else if edges.contains(after) {
+                from_after
+            } else {
+                0
+            }
+        };
+        Anchor {
+            edges,
+            offset: Origin {
+                x: offset(AnchorEdge::Left, AnchorEdge::Right, margin.left, margin.right),
+                y: offset(AnchorEdge::Top, AnchorEdge::Bottom, margin.top, margin.bottom)
+            }
+        }
+    }
+
+    pub fn is_anchored(self) -> bool {
+        !self.edges.is_empty()
+    }
+
+    /// The margin of the layer surface from the anchored edges, which is
+    /// grown by `extent` on each side by the shadows of the effects.
+    pub fn margin(self, extent: i32) -> Margin {
+        let side = |edge: AnchorEdge, offset: i32| {
+            if self.edges.contains(edge) {
+                offset - extent
+            } else {
+                0
+            }
+        };
+        let Origin { x, y } = self.offset;
+        Margin {
+            top: side(AnchorEdge::Top, y),
+            right: side(AnchorEdge::Right, x),
+            bottom: side(AnchorEdge::Bottom, y),
+            left: side(AnchorEdge::Left, x)
+        }
+    }
+
+    /// Where a drawin of `size` is on `screen`, which is where the
+    /// compositor places its layer surface.
+    pub fn place(self, size: Size, screen: Area) -> Area {
+        let axis =
+            |before: AnchorEdge, after: AnchorEdge, offset: i32, start: i32, length: u32, span: u32| match (
+                self.edges.contains(before),
+                self.edges.contains(after)
+            ) {
+                (true, true) => (start + offset, (span as i32 - 2 * offset).max(1) as u32),
+                (true, false) => (start + offset, length),
+                (false, true) => (start + span as i32 - offset - length as i32, length),
+                (false, false) => (start + (span as i32 - length as i32) / 2, length)
+            };
+        let (x, width) = axis(
+            AnchorEdge::Left,
+            AnchorEdge::Right,
+            self.offset.x,
+            screen.origin.x,
+            size.width,
+            screen.size.width
+        );
+        let (y, height) = axis(
+            AnchorEdge::Top,
+            AnchorEdge::Bottom,
+            self.offset.y,
+            screen.origin.y,
+            size.height,
+            screen.size.height
+        );
+        Area {
+            origin: Origin { x, y },
+            size: Size { width, height }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn area(x: i32, y: i32, width: u32, height: u32) -> Area {
+        Area {
+            origin: Origin { x, y },
+            size: Size { width, height }
+        }
+    }
+
+    #[test]
+    fn anchor_names() {
+        for name in NAMES {
+            assert_eq!(from_name(name).map(super::name), Some(*name));
+        }
+        assert_eq!(from_name("center"), None);
+    }
+
+    #[test]
+    fn anchor_place() {
+        let screen = area(1920, 0, 1920, 1080);
+        let size = Size {
+            width: 100,
+            height: 24
+        };
+        // A bar along the top, stretched with a margin of 4 on both sides.
+        let bar = Anchor {
+            edges: AnchorEdge::Top | AnchorEdge::Left | AnchorEdge::Right,
+            offset: Origin { x: 4, y: 4 }
+        };
+        assert_eq!(bar.place(size, screen), area(1924, 4, 1912, 24));
+        assert_eq!(
+            bar.margin(2),
+            Margin {
+                top: 2,
+                right: 2,
+                bottom: 0,
+                left: 2
+            }
+        );
+        // In the bottom right corner, from the right and bottom edges.
+        let corner = Anchor {
+            edges: AnchorEdge::Bottom | AnchorEdge::Right,
+            offset: Origin { x: 10, y: 20 }
+        };
+        assert_eq!(corner.place(size, screen), area(3730, 1036, 100, 24));
+        // Centered where no edge is anchored.
+        let top = Anchor {
+            edges: AnchorEdge::Top.into(),
+            offset: Origin { x: 50, y: 0 }
+        };
+        assert_eq!(top.place(size, screen), area(2830, 0, 100, 24));
+        assert_eq!(top.margin(0), Margin::default());
+        // Anchoring doesn't move a drawin.
+        for placed in &[bar, corner, top] {
+            let geometry = placed.place(size, screen);
+            let anchor = Anchor::at(placed.edges, geometry, screen);
+            assert_eq!(anchor.place(geometry.size, screen), geometry);
+        }
+        assert!(!Anchor::default().is_anchored());
+    }
+}
@@ -0,0 +1,496 @@
+//! Drawins described as plain tables, so a config can declare the drawins
+//! it wants and have them created, updated and removed to match:
+//!
+//! ```lua
+//! drawin.reconcile({
+//!     { version = 1, id = "top-bar", x = 0, y = 0, width = 1920, height = 24, visible = true },
+//!     { version = 1, id = "dock", y = 1040, height = 40, effects = { "shadow:8:0.5" } }
+//! })
+//! ```
+//!
+//! A description has the persistent properties of the drawin, under the
+//! names of the properties, and the id the config knows it by. Properties
+//! a description leaves out are left as they are.
+//!
+//! Only the properties that differ from the drawin are set, so applying
+//! the same description again does nothing. The geometry is the one Lua
+//! placed the drawin at, which a drawin moved to another output when its
+//! own went away keeps, so that isn't undone every time the config is
+//! applied.
+
+use rlua::{self, Table, Value};
+
+use crate::area::Area;
+use crate::common::object::{self, Object};
+use crate::objects::drawable::Effect;
+
+use super::keys::DRAWIN_SCHEMA;
+use super::{checked_coordinate, checked_length, Drawin, DRAWINS_HANDLE};
+
+/// The version of the format, which descriptions from newer versions
+/// still apply with a warning.
+pub const VERSION: i64 = 1;
+
+/// Properties that are about debugging the drawin rather than what it is.
+const TRANSIENT: &[&str] = &["trace_input"];
+
+const GEOMETRY: &[&str] = &["x", "y", "width", "height"];
+
+/// What applying a description did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Applied {
+    Created,
+    /// The properties that were changed, if any.
+    Updated(Vec<&'static str>)
+}
+
+/// A checked description, with the values in their canonical form.
+#[derive(Debug)]
+pub struct Description<'lua> {
+    pub id: String,
+    values: Vec<(&'static str, Value<'lua>)>
+}
+
+impl<'lua> Description<'lua> {
+    pub fn parse(lua: rlua::Context<'lua>, desc: Table<'lua>) -> rlua::Result<Self> {
+        match desc.get::<_, Value>("version")? {
+            Value::Nil => {},
+            Value::Integer(version) if version > VERSION => warn!(
+                "drawin description: version {} is newer than {}, ignoring what isn't understood",
+                version, VERSION
+            ),
+            Value::Integer(version) if version >= 1 => {},
+            version => {
+                return Err(rlua::Error::RuntimeError(format!(
+                    "drawin description: invalid version {:?}",
+                    version
+                )))
+            },
+        }
+        let id = match desc.get::<_, Value>("id")? {
+            Value::String(id) => id.to_str()?.to_owned(),
+            _ => {
+                return Err(rlua::Error::RuntimeError(
+                    "drawin description: expected a string id".into()
+                ))
+            },
+        };
+        let mut values = Vec::new();
+        for pair in desc.pairs::<Value, Value>() {
+            let (key, value) = pair?;
+            let key = match key {
+                Value::String(key) => key,
+                _ => continue
+            };
+            let key = key.to_str()?;
+            match described_keys().find(|&name| name == key) {
+                Some(name) => values.push((name, canonical(lua, name, value)?)),
+                None if key == "id" || key == "version" => {},
+                None => warn!("drawin description \"{}\": ignoring unknown key \"{}\"", id, key)
+            }
+        }
+        values.sort_by_key(|&(name, _)| described_keys().position(|key| key == name));
+        Ok(Description { id, values })
+    }
+
+    /// The properties the description has.
+    pub fn keys(&self) -> Vec<&'static str> {
+        self.values.iter().map(|&(key, _)| key).collect()
+    }
+
+    fn get(&self, key: &str) -> Option<&Value<'lua>> {
+        self.values
+            .iter()
+            .find(|&&(name, _)| name == key)
+            .map(|(_, value)| value)
+    }
+}
+
+/// The properties a description can have, in the order they're applied.
+fn described_keys() -> impl Iterator<Item = &'static str> {
+    let mut keys: Vec<_> = DRAWIN_SCHEMA.keys.iter().enumerate().collect();
+    keys.sort_by_key(|&(index, key)| (key.phase, index));
+    keys.into_iter()
+        .map(|(_, key)| key.name)
+        .filter(|name| !TRANSIENT.contains(name))
+}
+
+/// The value as the drawin reports it, e.g. `"dim:0.3"` for `"dim:.30"`.
+fn canonical<'lua>(lua: rlua::Context<'lua>, key: &str, value: Value<'lua>) -> rlua::Result<Value<'lua>> {
+    // Forgetting the preferred output is the only property set to nil.
+    if let ("preferred_output", Value::Nil) = (key, &value) {
+        return Ok(value);
+    }
+    let value: Value = DRAWIN_SCHEMA.check(lua, key, value)?;
+    if key != "effects" {
+        return Ok(value);
+    }
+    let effects: Vec<String> = rlua::FromLua::from_lua(value, lua)?;
+    let effects = effects
+        .iter()
+        .map(|effect| Effect::parse(effect).map(|effect| effect.to_string()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| rlua::Error::RuntimeError(format!("drawin.effects: {}", err)))?;
+    rlua::ToLua::to_lua(effects, lua)
+}
+
+/// The description of `drawin`, with every persistent property.
+pub fn describe<'lua>(lua: rlua::Context<'lua>, drawin: &Drawin<'lua>) -> rlua::Result<Table<'lua>> {
+    let table = lua.create_table()?;
+    table.set("version", VERSION)?;
+    table.set("id", drawin.state()?.declared_id.clone())?;
+    for key in described_keys() {
+        table.set(key, current(lua, drawin, key)?)?;
+    }
+    Ok(table)
+}
+
+/// The value of the property `key` of the drawin, as it's described.
+fn current<'lua>(lua: rlua::Context<'lua>, drawin: &Drawin<'lua>, key: &str) -> rlua::Result<Value<'lua>> {
+    let placed = drawin.state()?.placed;
+    Ok(match key {
+        "x" => Value::Integer(placed.origin.x.into()),
+        "y" => Value::Integer(placed.origin.y.into()),
+        "width" => Value::Integer(placed.size.width.into()),
+        "height" => Value::Integer(placed.size.height.into()),
+        key => object::default_index(lua, (drawin.clone(), rlua::ToLua::to_lua(key, lua)?))?
+    })
+}
+
+/// Creates the drawin with the id of `desc`, or changes the properties of
+/// the existing one that differ from it.
+pub fn apply<'lua>(
+    lua: rlua::Context<'lua>,
+    desc: &Description<'lua>
+) -> rlua::Result<(Drawin<'lua>, Applied)> {
+    let mut drawin = match find_declared(lua, &desc.id)? {
+        Some(drawin) => drawin,
+        None => {
+            let args = lua.create_table()?;
+            for (key, value) in desc.values.iter() {
+                args.set(*key, value.clone())?;
+            }
+            let mut drawin = Drawin::new(lua, args)?;
+            drawin.state_mut()?.declared_id = Some(desc.id.clone());
+            return Ok((drawin, Applied::Created));
+        }
+    };
+    let mut changed = Vec::new();
+    for &(key, ref value) in desc.values.iter() {
+        if !same(&current(lua, &drawin, key)?, value) {
+            changed.push(key);
+        }
+    }
+    let mut geometry_set = false;
+    for &key in changed.iter() {
+        if GEOMETRY.contains(&key) {
+            if !geometry_set {
+                geometry_set = true;
+                let geometry = placed_geometry(desc, drawin.state()?.placed)?;
+                drawin.resize(lua, geometry)?;
+                // Placing the drawin makes the output it's on preferred.
+                if let Some(preferred) = desc.get("preferred_output") {
+                    if !changed.contains(&"preferred_output") {
+                        set(lua, &drawin, "preferred_output", preferred.clone())?;
+                    }
+                }
+            }
+            continue;
+        }
+        let value = desc.get(key).cloned().unwrap_or(Value::Nil);
+        set(lua, &drawin, key, value)?;
+    }
+    Ok((drawin, Applied::Updated(changed)))
+}
+
+/// Applies all of `descs`, then removes the drawins with an id that isn't
+/// in them. Every drawin that's removed is told with "request::remove" and
+/// the reason "reconcile" before any of them is removed.
+///
+/// Returns `(created, updated, removed)`, with the ids of the drawins.
+pub fn reconcile<'lua>(
+    lua: rlua::Context<'lua>,
+    descs: Vec<Table<'lua>>
+) -> rlua::Result<(Vec<String>, Vec<String>, Vec<String>)> {
+    // Nothing is applied unless every description is valid.
+    let mut parsed: Vec<Description> = Vec::new();
+    for desc in descs {
+        let desc = Description::parse(lua, desc)?;
+        if parsed.iter().any(|other| other.id == desc.id) {
+            return Err(rlua::Error::RuntimeError(format!(
+                "drawin.reconcile: the id \"{}\" is described twice",
+                desc.id
+            )));
+        }
+        parsed.push(desc);
+    }
+    let (mut created, mut updated) = (Vec::new(), Vec::new());
+    for desc in parsed.iter() {
+        match apply(lua, desc)?.1 {
+            Applied::Created => created.push(desc.id.clone()),
+            Applied::Updated(ref changed) if !changed.is_empty() => updated.push(desc.id.clone()),
+            Applied::Updated(_) => {}
+        }
+    }
+    let mut removing = Vec::new();
+    for drawin in lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)? {
+        let id = drawin.state()?.declared_id.clone();
+        if let Some(id) = id {
+            if !parsed.iter().any(|desc| desc.id == id) {
+                removing.push((drawin, id));
+            }
+        }
+    }
+    for (drawin, _) in removing.iter() {
+        Object::emit_signal(lua, drawin, "request::remove", "reconcile")?;
+    }
+    let mut removed = Vec::new();
+    for (mut drawin, id) in removing {
+        drawin.remove(lua)?;
+        removed.push(id);
+    }
+    Ok((created, updated, removed))
+}
+
+fn find_declared<'lua>(lua: rlua::Context<'lua>, id: &str) -> rlua::Result<Option<Drawin<'lua>>> {
+    for drawin in lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)? {
+        if drawin.state()?.declared_id.as_ref().map(String::as_str) == Some(id) {
+            return Ok(Some(drawin));
+        }
+    }
+    Ok(None)
+}
+
+fn set<'lua>(
+    lua: rlua::Context<'lua>,
+    drawin: &Drawin<'lua>,
+    key: &str,
+    value: Value<'lua>
+) -> rlua::Result<()> {
+    object::default_newindex(lua, (drawin.clone(), key.into(), value))?;
+    Ok(())
+}
+
+/// The geometry of `desc`, with what it leaves out from `placed`.
+fn placed_geometry(desc: &Description, placed: Area) -> rlua::Result<Area> {
+    let field = |key: &str| match desc.get(key) {
+        Some(&Value::Integer(value)) => Some(value),
+        _ => None
+    };
+    let mut geometry = placed;
+    if let Some(x) = field("x") {
+        geometry.origin.x = checked_coordinate("x", x)?;
+    }
+    if let Some(y) = field("y") {
+        geometry.origin.y = checked_coordinate("y", y)?;
+    }
+    if let Some(width) = field("width") {
+        geometry.size.width = checked_length("width", width)?;
+    }
+    if let Some(height) = field("height") {
+        geometry.size.height = checked_length("height", height)?;
+    }
+    Ok(geometry)
+}
+
+/// Whether two described values are the same, comparing lists by their
+/// items.
+fn same(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Integer(a), Value::Number(b)) | (Value::Number(b), Value::Integer(a)) => *a as f64 == *b,
+        (Value::String(a), Value::String(b)) => a.as_bytes() == b.as_bytes(),
+        (Value::Table(a), Value::Table(b)) => {
+            let a: Vec<Value> = a.clone().sequence_values().filter_map(Result::ok).collect();
+            let b: Vec<Value> = b.clone().sequence_values().filter_map(Result::ok).collect();
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| same(a, b))
+        },
+        (Value::Nil, Value::Nil) => true,
+        (Value::Boolean(a), Value::Boolean(b)) => a == b,
+        (Value::Integer(a), Value::Integer(b)) => a == b,
+        (Value::Number(a), Value::Number(b)) => a == b,
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rlua::{self, Lua};
+
+    use crate::area::Size;
+    use crate::objects::{
+        drawable,
+        drawin::{self, Drawin},
+        screen::SCREENS_HANDLE
+    };
+    use crate::scheduler;
+    use crate::wayland_obj::{test_server::TestServer, LAYER_SHELL_MAX_VERSION};
+
+    fn setup(lua: rlua::Context) -> rlua::Result<()> {
+        drawable::init(lua)?;
+        drawin::init(lua)?;
+        lua.set_named_registry_value(SCREENS_HANDLE, lua.create_table()?)
+    }
+
+    #[test]
+    fn description_applied_twice_is_a_noop() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            setup(lua)?;
+            lua.load(
+                r#"
+local desc = {
+    version = 1, id = "bar", x = 10, y = "20", width = 300, height = 24.0,
+    effects = { "dim:.30" }, content_fit = "crop", migration_policy = "sticky"
+}
+local bar, changed = drawin.apply_description(desc)
+assert(#changed == 7)
+assert(bar.x == 10 and bar.y == 20 and bar.content_fit == "crop")
+local again, changed = drawin.apply_description(desc)
+assert(again == bar and #changed == 0)
+-- The description of a drawin is canonical, so applying it changes nothing.
+local described = drawin.describe(bar)
+assert(described.id == "bar" and described.version == 1)
+assert(described.effects[1] == "dim:0.3")
+assert(described.trace_input == nil)
+local _, changed = drawin.apply_description(described)
+assert(#changed == 0)
+-- Only what differs is set, with the geometry in one go.
+desc.x, desc.width, desc.cursor = 0, 200, "hand1"
+local _, changed = drawin.apply_description(desc)
+assert(#changed == 3 and changed[1] == "x" and changed[2] == "width" and changed[3] == "cursor")
+assert(bar:geometry().width == 200)
+-- Keys from newer versions only warn.
+local _, changed = drawin.apply_description{ version = 2, id = "bar", x = 0, blur = 4 }
+assert(#changed == 0)
+assert(not pcall(drawin.apply_description, { x = 0 }))
+assert(not pcall(drawin.apply_description, { id = "bar", effects = { "glow" } }))
+                "#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn description_applied_twice_sends_nothing() -> rlua::Result<()> {
+        let mut server = TestServer::start(LAYER_SHELL_MAX_VERSION);
+        let lua = Lua::new();
+        lua.context(|lua| {
+            setup(lua)?;
+            lua.load(
+                r#"
+desc = {
+    version = 1, id = "bar", x = 10, y = 20, width = 300, height = 24, visible = true,
+    effects = { "dim:.30" }
+}
+bar = drawin.apply_description(desc)
+"#
+            )
+            .exec()?;
+            let bar: Drawin = lua.globals().get("bar")?;
+            let id = bar.state()?.layer_surface.as_ref().unwrap().id();
+            server.roundtrip();
+            server.configure(
+                id,
+                1,
+                Size {
+                    width: 300,
+                    height: 24
+                }
+            );
+            server.roundtrip();
+            scheduler::run_deferred(lua);
+            lua.load("bar.drawable:refresh()").exec()?;
+            scheduler::run_deferred(lua);
+            server.roundtrip();
+            server.take_requests();
+            // Neither the description nor the drawin's own changes what the
+            // compositor has.
+            lua.load("drawin.apply_description(desc) drawin.apply_description(drawin.describe(bar))")
+                .exec()?;
+            scheduler::run_deferred(lua);
+            server.roundtrip();
+            assert_eq!(server.take_requests(), []);
+            let sent = |server: &TestServer| -> Vec<String> {
+                server
+                    .take_requests()
+                    .iter()
+                    .map(|request| format!("{}.{}", request.interface, request.name))
+                    .collect()
+            };
+            // A move is sent like any other, with the margins and the same
+            // frame again.
+            lua.load("desc.x = 30 drawin.apply_description(desc)").exec()?;
+            scheduler::run_deferred(lua);
+            server.roundtrip();
+            assert_eq!(
+                sent(&server),
+                [
+                    "zwlr_layer_surface_v1.set_size",
+                    "wl_surface.attach",
+                    "wl_surface.damage",
+                    "zwlr_layer_surface_v1.set_margin",
+                    "wl_surface.commit"
+                ]
+            );
+            // Reconciling with a list without it destroys its surface and
+            // its buffer.
+            lua.load("drawin.reconcile{}").exec()?;
+            scheduler::run_deferred(lua);
+            server.roundtrip();
+            assert_eq!(
+                sent(&server),
+                [
+                    "zwlr_layer_surface_v1.destroy",
+                    "wl_surface.destroy",
+                    "wl_buffer.destroy",
+                    "wl_shm_pool.destroy"
+                ]
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn description_reconcile() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            setup(lua)?;
+            lua.load(
+                r#"
+local a = drawin.apply_description{ id = "a", x = 0, width = 100, height = 20 }
+local b = drawin.apply_description{ id = "b", x = 0, width = 100, height = 20 }
+local c = drawin.apply_description{ id = "c", x = 0, width = 100, height = 20 }
+local undeclared = drawin{ width = 10, height = 10 }
+local removing = {}
+for _, d in ipairs{ a, b, c, undeclared } do
+    d:connect_signal("request::remove", function(d, reason)
+        -- Told before any of them is removed.
+        removing[#removing + 1] = reason
+        assert(drawin.describe(c).id == "c")
+    end)
+end
+local result = drawin.reconcile{
+    { id = "a", x = 0, width = 100, height = 20 },
+    { id = "b", x = 5, width = 100, height = 20 },
+    { id = "d", width = 50, height = 50 }
+}
+assert(#result.created == 1 and result.created[1] == "d")
+assert(#result.updated == 1 and result.updated[1] == "b")
+assert(#result.removed == 1 and result.removed[1] == "c")
+assert(#removing == 1 and removing[1] == "reconcile")
+assert(drawin.describe(c).id == nil and b.x == 5)
+-- Drawins without an id are left alone, and applying the list again
+-- does nothing.
+local result = drawin.reconcile{
+    { id = "a", x = 0, width = 100, height = 20 },
+    { id = "b", x = 5, width = 100, height = 20 },
+    { id = "d", width = 50, height = 50 }
+}
+assert(#result.created == 0 and #result.updated == 0 and #result.removed == 0)
+assert(not pcall(drawin.reconcile, { { id = "a" }, { id = "a" } }))
+                "#
+            )
+            .exec()
+        })
+    }
+}
@@ -0,0 +1,261 @@
+//! Moving and resizing drawins with the pointer, like awesome's
+//! `awful.mouse.client.move` does with clients.
+//!
+//! Lua starts a drag while a button is held down, usually from a
+//! "button::press" handler, and the drawin goes with the pointer until the
+//! button is released. Like drawins that follow the pointer, all the motion
+//! of a frame changes its geometry only once. Escape puts it back where it
+//! was.
+
+use crate::area::{Area, Origin, Size, MAX_COORDINATE};
+
+/// The corner of a drawin that's dragged to resize it. The opposite corner
+/// stays where it is.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight
+}
+
+impl Corner {
+    pub const NAMES: [&'static str; 4] = ["top_left", "top_right", "bottom_left", "bottom_right"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "top_left" => Some(Corner::TopLeft),
+            "top_right" => Some(Corner::TopRight),
+            "bottom_left" => Some(Corner::BottomLeft),
+            "bottom_right" => Some(Corner::BottomRight),
+            _ => None
+        }
+    }
+}
+
+impl Default for Corner {
+    fn default() -> Self {
+        Corner::BottomRight
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Mode {
+    Move,
+    Resize(Corner)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Drag {
+    pub drawin: usize,
+    pub mode: Mode,
+    /// The button whose release ends the drag, any if `None`.
+    pub button: Option<u32>,
+    /// Where the drawin was when the drag started.
+    pub from: Area,
+    /// Where the pointer was when the drag started, in the coordinates of
+    /// the screens.
+    pub pointer: (f64, f64)
+}
+
+impl Drag {
+    /// Where the drawin goes with the pointer at `pointer`.
+    pub fn geometry(&self, pointer: (f64, f64)) -> Area {
+        let delta = |to: f64, from: f64| {
+            // Casting an out of range float isn't defined.
+            (to - from)
+                .round()
+                .max(-MAX_COORDINATE as f64)
+                .min(MAX_COORDINATE as f64) as i32
+        };
+        let (dx, dy) = (delta(pointer.0, self.pointer.0), delta(pointer.1, self.pointer.1));
+        let Area { origin, size } = self.from;
+        match self.mode {
+            Mode::Move => Area {
+                origin: Origin {
+                    x: origin.x.saturating_add(dx),
+                    y: origin.y.saturating_add(dy)
+                },
+                size
+            },
+            Mode::Resize(corner) => {
+                let (left, top) = match corner {
+                    Corner::TopLeft => (true, true),
+                    Corner::TopRight => (false, true),
+                    Corner::BottomLeft => (true, false),
+                    Corner::BottomRight => (false, false)
+                };
+                let (x, width) = stretch(origin.x, size.width, dx, left);
+                let (y, height) = stretch(origin.y, size.height, dy, top);
+                Area {
+                    origin: Origin { x, y },
+                    size: Size { width, height }
+                }
+            }
+        }
+    }
+}
+
+/// Moves the start edge of a side at `start`, `length` long, by `delta`
+/// if `start_edge`, else the end edge, keeping it at least 1 long.
+fn stretch(start: i32, length: u32, delta: i32, start_edge: bool) -> (i32, u32) {
+    let (start, length, delta) = (i64::from(start), i64::from(length), i64::from(delta));
+    let stretched = if start_edge {
+        length - delta
+    } else {
+        length + delta
+    };
+    let stretched = stretched.max(1).min(i64::from(MAX_COORDINATE));
+    if start_edge {
+        ((start + length - stretched) as i32, stretched as u32)
+    } else {
+        (start as i32, stretched as u32)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Drags {
+    drag: Option<Drag>,
+    /// Where the pointer was last seen, if the drawin wasn't moved there
+    /// yet.
+    moved: Option<(f64, f64)>,
+    /// Whether moving it is queued for the end of the frame.
+    queued: bool
+}
+
+impl Drags {
+    /// Starts a drag, returning the one it replaces. There's only one
+    /// pointer to drag with.
+    pub fn start(&mut self, drag: Drag) -> Option<Drag> {
+        self.moved = None;
+        self.drag.replace(drag)
+    }
+
+    /// Stops dragging the drawin, returning its drag if it was dragged.
+    pub fn stop(&mut self, drawin: usize) -> Option<Drag> {
+        if self.dragging() != Some(drawin) {
+            return None;
+        }
+        self.moved = None;
+        self.drag.take()
+    }
+
+    /// The drawin that's dragged.
+    pub fn dragging(&self) -> Option<usize> {
+        self.drag.map(|drag| drag.drawin)
+    }
+
+    pub fn drag(&self) -> Option<Drag> {
+        self.drag
+    }
+
+    /// The pointer was seen at `pointer`. Returns whether moving the dragged
+    /// drawin has to be queued.
+    pub fn pointer_moved(&mut self, pointer: (f64, f64)) -> bool {
+        if self.drag.is_none() {
+            return false;
+        }
+        self.moved = Some(pointer);
+        !std::mem::replace(&mut self.queued, true)
+    }
+
+    /// The dragged drawin and where it goes, if the pointer moved since it
+    /// was last moved.
+    pub fn take_move(&mut self) -> Option<(usize, Area)> {
+        self.queued = false;
+        let drag = self.drag?;
+        let pointer = self.moved.take()?;
+        Some((drag.drawin, drag.geometry(pointer)))
+    }
+
+    /// `button` was released. Returns the drag it ends, with where the
+    /// drawin goes if the pointer moved since it was last moved.
+    pub fn released(&mut self, button: u32) -> Option<(Drag, Option<Area>)> {
+        let drag = self.drag?;
+        if drag.button.map_or(false, |other| other != button) {
+            return None;
+        }
+        self.drag = None;
+        let last = self.moved.take().map(|pointer| drag.geometry(pointer));
+        Some((drag, last))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn area(x: i32, y: i32, width: u32, height: u32) -> Area {
+        Area {
+            origin: Origin { x, y },
+            size: Size { width, height }
+        }
+    }
+
+    fn drag(mode: Mode) -> Drag {
+        Drag {
+            drawin: 1,
+            mode,
+            button: Some(1),
+            from: area(100, 100, 200, 100),
+            pointer: (150.0, 150.0)
+        }
+    }
+
+    #[test]
+    fn drag_geometry() {
+        let moved = drag(Mode::Move);
+        assert_eq!(moved.geometry((160.4, 130.6)), area(110, 81, 200, 100));
+        let resized = drag(Mode::Resize(Corner::BottomRight));
+        assert_eq!(resized.geometry((200.0, 120.0)), area(100, 100, 250, 70));
+        // The opposite corner stays where it is.
+        let resized = drag(Mode::Resize(Corner::TopLeft));
+        assert_eq!(resized.geometry((130.0, 170.0)), area(80, 120, 220, 80));
+        let resized = drag(Mode::Resize(Corner::TopRight));
+        assert_eq!(resized.geometry((140.0, 140.0)), area(100, 90, 190, 110));
+        // It doesn't shrink past a pixel, or turn inside out.
+        assert_eq!(resized.geometry((-500.0, 900.0)), area(100, 199, 1, 1));
+        let resized = drag(Mode::Resize(Corner::BottomLeft));
+        assert_eq!(resized.geometry((900.0, -500.0)), area(299, 100, 1, 1));
+        assert_eq!(Corner::NAMES.len(), 4);
+        for name in &Corner::NAMES {
+            assert!(Corner::from_name(name).is_some());
+        }
+        assert_eq!(Corner::from_name("left"), None);
+    }
+
+    #[test]
+    fn drag_motion_batched_per_frame() {
+        let mut drags = Drags::default();
+        assert!(!drags.pointer_moved((0.0, 0.0)));
+        assert_eq!(drags.start(drag(Mode::Move)), None);
+        assert_eq!(drags.take_move(), None);
+        // A burst of motion queues one move, to where the pointer ended up.
+        let queued = (0..20)
+            .filter(|&i| drags.pointer_moved((150.0 + f64::from(i), 150.0)))
+            .count();
+        assert_eq!(queued, 1);
+        assert_eq!(drags.take_move(), Some((1, area(119, 100, 200, 100))));
+        assert_eq!(drags.take_move(), None);
+        // Another button doesn't end it, and releasing its own moves it to
+        // where the pointer went last.
+        assert!(drags.pointer_moved((100.0, 100.0)));
+        assert_eq!(drags.released(3), None);
+        assert_eq!(
+            drags.released(1),
+            Some((drag(Mode::Move), Some(area(50, 50, 200, 100))))
+        );
+        assert_eq!(drags.dragging(), None);
+        assert_eq!(drags.take_move(), None);
+        // Without a button, any ends it.
+        drags.start(Drag {
+            button: None,
+            ..drag(Mode::Move)
+        });
+        assert_eq!(drags.stop(2), None);
+        assert_eq!(drags.released(8).map(|(_, last)| last), Some(None));
+        drags.start(drag(Mode::Move));
+        assert_eq!(drags.stop(1), Some(drag(Mode::Move)));
+        assert_eq!(drags.drag(), None);
+    }
+}
@@ -0,0 +1,257 @@
+//! Arbitration of which drawin owns an edge of an output.
+//!
+//! A drawin with `exclusive_edge_owner` set claims the edge of every output
+//! it is flush against. Only one drawin can own a (output, edge) slot, so
+//! two bars can't end up stacked on the same edge by accident.
+
+use std::fmt;
+
+use crate::area::{AnchorEdge, Area};
+
+/// An edge of an output.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Edge {
+    Top,
+    Bottom,
+    Left,
+    Right
+}
+
+/// A place a drawin can claim: an edge of a screen.
+///
+/// Screens are identified by their id, which isn't given to another screen
+/// once the screen is gone.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Slot {
+    pub screen: usize,
+    pub edge: Edge
+}
+
+/// How a drawin wants to own the edges it is placed on.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Ownership {
+    /// The drawin doesn't claim any edge.
+    None,
+    /// Claiming an edge another drawin owns is an error.
+    Exclusive,
+    /// Claiming an edge another drawin owns takes it away from that drawin.
+    Replace
+}
+
+impl Default for Ownership {
+    fn default() -> Self {
+        Ownership::None
+    }
+}
+
+/// The error when a drawin tries to claim a slot someone else owns.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct AlreadyOwned {
+    pub owner: usize
+}
+
+impl fmt::Display for AlreadyOwned {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "edge already owned by drawin#{}", self.owner)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Claim {
+    pub drawin: usize,
+    pub slot: Slot
+}
+
+/// All of the claimed slots.
+#[derive(Debug, Default)]
+pub struct EdgeClaims {
+    claims: Vec<Claim>
+}
+
+impl Edge {
+    pub fn name(self) -> &'static str {
+        match self {
+            Edge::Top => "top",
+            Edge::Bottom => "bottom",
+            Edge::Left => "left",
+            Edge::Right => "right"
+        }
+    }
+}
+
+impl EdgeClaims {
+    pub fn claims(&self) -> &[Claim] {
+        &self.claims
+    }
+
+    /// Gives the drawin the slots, replacing the slots it had before.
+    ///
+    /// If any of the slots is owned by another drawin nothing changes and an
+    /// error is returned, unless the drawin replaces other owners. In that
+    /// case the claims that were taken away are returned.
+    pub fn claim(
+        &mut self,
+        drawin: usize,
+        slots: &[Slot],
+        ownership: Ownership
+    ) -> Result<Vec<Claim>, AlreadyOwned> {
+        if ownership == Ownership::None {
+            self.release(drawin);
+            return Ok(Vec::new());
+        }
+        let (conflicts, rest): (Vec<Claim>, Vec<Claim>) = self
+            .claims
+            .iter()
+            .filter(|claim| claim.drawin != drawin)
+            .partition(|claim| slots.contains(&claim.slot));
+        if let (Some(conflict), Ownership::Exclusive) = (conflicts.first(), ownership) {
+            return Err(AlreadyOwned {
+                owner: conflict.drawin
+            });
+        }
+        self.claims = rest;
+        self.claims
+            .extend(slots.iter().map(|&slot| Claim { drawin, slot }));
+        Ok(conflicts)
+    }
+
+    /// Like `claim`, but slots owned by other drawins are skipped instead.
+    ///
+    /// Used when the outputs change, when there is no one to report an
+    /// error to.
+    pub fn reclaim(&mut self, drawin: usize, slots: &[Slot]) -> Vec<Slot> {
+        self.release(drawin);
+        let (taken, free): (Vec<Slot>, Vec<Slot>) = slots
+            .iter()
+            .partition(|slot| self.claims.iter().any(|claim| claim.slot == **slot));
+        self.claims
+            .extend(free.into_iter().map(|slot| Claim { drawin, slot }));
+        taken
+    }
+
+    /// Releases all the slots of the drawin.
+    pub fn release(&mut self, drawin: usize) {
+        self.claims.retain(|claim| claim.drawin != drawin);
+    }
+}
+
+/// The edge of the screen the drawin is placed on, if any.
+///
+/// A drawin touching several edges (e.g. a bar in a corner) is placed on
+/// the edge along its longer side, so a wide bar at the top left claims the
+/// top edge and a tall one claims the left edge.
+pub fn placed_edge(drawin: Area, screen: Area) -> Option<Edge> {
+    let anchors = drawin.anchor_edges_for(screen);
+    let horizontal = [(AnchorEdge::Top, Edge::Top), (AnchorEdge::Bottom, Edge::Bottom)];
+    let vertical = [(AnchorEdge::Left, Edge::Left), (AnchorEdge::Right, Edge::Right)];
+    let order = if drawin.size.width >= drawin.size.height {
+        horizontal.iter().chain(vertical.iter())
+    } else {
+        vertical.iter().chain(horizontal.iter())
+    };
+    order
+        .filter(|(anchor, _)| anchors.contains(*anchor))
+        .map(|(_, edge)| *edge)
+        .next()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::area::{Origin, Size};
+
+    fn area(x: i32, y: i32, width: u32, height: u32) -> Area {
+        Area {
+            origin: Origin { x, y },
+            size: Size { width, height }
+        }
+    }
+
+    const TOP: Slot = Slot {
+        screen: 1,
+        edge: Edge::Top
+    };
+    const BOTTOM: Slot = Slot {
+        screen: 1,
+        edge: Edge::Bottom
+    };
+
+    #[test]
+    fn edge_claim_conflict() {
+        let mut claims = EdgeClaims::default();
+        assert_eq!(claims.claim(1, &[TOP], Ownership::Exclusive), Ok(vec![]));
+        assert_eq!(
+            claims.claim(2, &[BOTTOM, TOP], Ownership::Exclusive),
+            Err(AlreadyOwned { owner: 1 })
+        );
+        assert_eq!(claims.claims(), &[Claim { drawin: 1, slot: TOP }]);
+        // Claiming again with the same slots is not a conflict with itself.
+        assert_eq!(claims.claim(1, &[TOP], Ownership::Exclusive), Ok(vec![]));
+    }
+
+    #[test]
+    fn edge_claim_replace() {
+        let mut claims = EdgeClaims::default();
+        claims.claim(1, &[TOP], Ownership::Exclusive).unwrap();
+        assert_eq!(
+            claims.claim(2, &[TOP], Ownership::Replace),
+            Ok(vec![Claim { drawin: 1, slot: TOP }])
+        );
+        assert_eq!(claims.claims(), &[Claim { drawin: 2, slot: TOP }]);
+    }
+
+    #[test]
+    fn edge_claim_release() {
+        let mut claims = EdgeClaims::default();
+        claims.claim(1, &[TOP], Ownership::Exclusive).unwrap();
+        claims.release(1);
+        assert_eq!(claims.claim(2, &[TOP], Ownership::Exclusive), Ok(vec![]));
+        claims.claim(2, &[], Ownership::Exclusive).unwrap();
+        assert!(claims.claims().is_empty());
+    }
+
+    #[test]
+    fn edge_claim_spanning() {
+        let mut claims = EdgeClaims::default();
+        let left_top = Slot {
+            screen: 1,
+            edge: Edge::Top
+        };
+        let right_top = Slot {
+            screen: 2,
+            edge: Edge::Top
+        };
+        claims
+            .claim(1, &[left_top, right_top], Ownership::Exclusive)
+            .unwrap();
+        assert_eq!(
+            claims.claim(2, &[right_top], Ownership::Replace),
+            Ok(vec![Claim {
+                drawin: 1,
+                slot: right_top
+            }])
+        );
+        // The spanning drawin keeps the slot on the other output.
+        assert_eq!(
+            claims.claim(3, &[left_top], Ownership::Exclusive),
+            Err(AlreadyOwned { owner: 1 })
+        );
+        assert_eq!(claims.reclaim(1, &[left_top, right_top]), vec![right_top]);
+    }
+
+    #[test]
+    fn edge_placement() {
+        let screen = area(0, 0, 1920, 1080);
+        assert_eq!(placed_edge(area(0, 0, 1920, 20), screen), Some(Edge::Top));
+        assert_eq!(placed_edge(area(0, 1060, 1920, 20), screen), Some(Edge::Bottom));
+        assert_eq!(placed_edge(area(0, 0, 20, 1080), screen), Some(Edge::Left));
+        assert_eq!(placed_edge(area(1900, 100, 20, 500), screen), Some(Edge::Right));
+        assert_eq!(placed_edge(area(100, 100, 200, 200), screen), None);
+        // A bar spanning two outputs is on the top edge of both.
+        let bar = area(0, 0, 3840, 20);
+        let right = area(1920, 0, 1920, 1080);
+        assert!(bar.intersects(screen) && bar.intersects(right));
+        assert_eq!(placed_edge(bar, right), Some(Edge::Top));
+        assert!(!area(0, 0, 1920, 20).intersects(right));
+    }
+}
@@ -0,0 +1,192 @@
+//! Which drawin has exclusive keyboard focus.
+//!
+//! The compositor gives keyboard focus to the topmost layer surface that
+//! asks for it, so when several drawins ask at once the one that gets it is
+//! a surprise. Instead drawins request focus here with a priority, and only
+//! the drawin holding it asks the compositor.
+//!
+//! Requests are kept on a stack. The most important request holds the
+//! focus, the latest one if several are equally important, and when it's
+//! released the focus goes back to the next one. Requests made while a more
+//! important one holds the focus wait on the stack.
+//!
+//! Lock screens are not on the stack: a locked screen holds the focus until
+//! it's unlocked, whatever is requested in the meantime.
+
+/// How important the keyboard focus of a drawin is.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Priority {
+    Regular,
+    /// A prompt that has to be answered before anything else.
+    Modal,
+    Lockscreen
+}
+
+impl Priority {
+    pub const NAMES: &'static [&'static str] = &["regular", "modal", "lockscreen"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "regular" => Some(Priority::Regular),
+            "modal" => Some(Priority::Modal),
+            "lockscreen" => Some(Priority::Lockscreen),
+            _ => None
+        }
+    }
+}
+
+/// A change of the drawin holding the focus, by id.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Change {
+    Revoked(usize),
+    Granted(usize)
+}
+
+#[derive(Debug, Default)]
+pub struct FocusStack {
+    /// The waiting and holding requests, latest last.
+    requests: Vec<(usize, Priority)>,
+    /// The lock screens, in the order they locked. The first one holds the
+    /// focus.
+    locks: Vec<usize>
+}
+
+impl FocusStack {
+    /// The drawin that holds the focus.
+    pub fn holder(&self) -> Option<usize> {
+        if let Some(&lock) = self.locks.first() {
+            return Some(lock);
+        }
+        // The last of the equally important requests is the latest.
+        self.requests
+            .iter()
+            .max_by_key(|&&(_, priority)| priority)
+            .map(|&(drawin, _)| drawin)
+    }
+
+    /// Whether a lock screen holds the focus.
+    pub fn locked(&self) -> bool {
+        !self.locks.is_empty()
+    }
+
+    /// The drawins with a request, holding or waiting.
+    pub fn drawins(&self) -> Vec<usize> {
+        let requests = self.requests.iter().map(|&(drawin, _)| drawin);
+        self.locks.iter().cloned().chain(requests).collect()
+    }
+
+    /// Requests the focus for the drawin, replacing its earlier request.
+    pub fn request(&mut self, drawin: usize, priority: Priority) -> Vec<Change> {
+        // Locking again mustn't let a later lock screen jump ahead.
+        if priority == Priority::Lockscreen && self.locks.contains(&drawin) {
+            return Vec::new();
+        }
+        let before = self.holder();
+        self.remove(drawin);
+        match priority {
+            Priority::Lockscreen => self.locks.push(drawin),
+            priority => self.requests.push((drawin, priority))
+        }
+        self.changes(before)
+    }
+
+    /// Forgets the request of the drawin, e.g. when it's hidden.
+    pub fn release(&mut self, drawin: usize) -> Vec<Change> {
+        let before = self.holder();
+        self.remove(drawin);
+        self.changes(before)
+    }
+
+    fn remove(&mut self, drawin: usize) {
+        self.requests.retain(|&(requester, _)| requester != drawin);
+        self.locks.retain(|&lock| lock != drawin);
+    }
+
+    /// What changed since `before` held the focus, the revocation first.
+    fn changes(&self, before: Option<usize>) -> Vec<Change> {
+        let after = self.holder();
+        if before == after {
+            return Vec::new();
+        }
+        before
+            .map(Change::Revoked)
+            .into_iter()
+            .chain(after.map(Change::Granted))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Change::*;
+    use super::*;
+
+    const BAR: usize = 1;
+    const RUN_PROMPT: usize = 2;
+    const CONFIRM_PROMPT: usize = 3;
+    const LOCK: usize = 4;
+
+    #[test]
+    fn focus_prompt_over_prompt() {
+        let mut focus = FocusStack::default();
+        assert_eq!(focus.request(BAR, Priority::Regular), vec![Granted(BAR)]);
+        assert_eq!(
+            focus.request(RUN_PROMPT, Priority::Modal),
+            vec![Revoked(BAR), Granted(RUN_PROMPT)]
+        );
+        assert_eq!(
+            focus.request(CONFIRM_PROMPT, Priority::Modal),
+            vec![Revoked(RUN_PROMPT), Granted(CONFIRM_PROMPT)]
+        );
+        // The bar waits for the prompts instead of taking the focus.
+        assert_eq!(focus.request(BAR, Priority::Regular), vec![]);
+        assert_eq!(focus.holder(), Some(CONFIRM_PROMPT));
+        // Dismissing the prompts gives the focus back in order.
+        assert_eq!(
+            focus.release(CONFIRM_PROMPT),
+            vec![Revoked(CONFIRM_PROMPT), Granted(RUN_PROMPT)]
+        );
+        assert_eq!(focus.release(RUN_PROMPT), vec![Revoked(RUN_PROMPT), Granted(BAR)]);
+        assert_eq!(focus.release(BAR), vec![Revoked(BAR)]);
+        assert_eq!(focus.holder(), None);
+    }
+
+    #[test]
+    fn focus_lockscreen_never_yields() {
+        let mut focus = FocusStack::default();
+        focus.request(BAR, Priority::Regular);
+        focus.request(RUN_PROMPT, Priority::Modal);
+        assert_eq!(
+            focus.request(LOCK, Priority::Lockscreen),
+            vec![Revoked(RUN_PROMPT), Granted(LOCK)]
+        );
+        // Prompts opened while locked wait for the unlock.
+        assert_eq!(focus.request(CONFIRM_PROMPT, Priority::Modal), vec![]);
+        assert_eq!(focus.release(RUN_PROMPT), vec![]);
+        assert_eq!(focus.holder(), Some(LOCK));
+        // Unlocking restores the top of the stack as it is now.
+        assert_eq!(focus.release(LOCK), vec![Revoked(LOCK), Granted(CONFIRM_PROMPT)]);
+        assert_eq!(
+            focus.release(CONFIRM_PROMPT),
+            vec![Revoked(CONFIRM_PROMPT), Granted(BAR)]
+        );
+    }
+
+    #[test]
+    fn focus_hidden_waiting_request() {
+        let mut focus = FocusStack::default();
+        focus.request(RUN_PROMPT, Priority::Modal);
+        focus.request(BAR, Priority::Regular);
+        // A waiting request that goes away changes nothing.
+        assert_eq!(focus.release(BAR), vec![]);
+        assert_eq!(focus.release(BAR), vec![]);
+        assert_eq!(focus.release(RUN_PROMPT), vec![Revoked(RUN_PROMPT)]);
+        // Requesting again replaces the earlier request.
+        focus.request(BAR, Priority::Modal);
+        assert_eq!(focus.request(BAR, Priority::Regular), vec![]);
+        assert_eq!(
+            focus.request(RUN_PROMPT, Priority::Regular),
+            vec![Revoked(BAR), Granted(RUN_PROMPT)]
+        );
+    }
+}
@@ -0,0 +1,312 @@
+//! Drawins that follow the pointer, like tooltips and drag previews.
+//!
+//! Motion of the pointer moves them directly, without going through Lua,
+//! and all the motion of a frame moves them only once. We are only told
+//! where the pointer is while it's over one of our own surfaces, so when it
+//! leaves them following pauses where it was last seen and resumes when it
+//! comes back.
+
+use std::{error::Error, fmt};
+
+use crate::area::{Area, Origin, Size};
+
+/// What keeps a following drawin in place.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Constrain {
+    /// It stays on the output the pointer is on, going to the other side of
+    /// the pointer where it doesn't fit.
+    Output,
+    /// It goes wherever the offset puts it.
+    None
+}
+
+impl Constrain {
+    pub const NAMES: [&'static str; 2] = ["output", "none"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "output" => Some(Constrain::Output),
+            "none" => Some(Constrain::None),
+            _ => None
+        }
+    }
+}
+
+impl Default for Constrain {
+    fn default() -> Self {
+        Constrain::Output
+    }
+}
+
+/// How a drawin follows the pointer.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Follow {
+    /// Where its top left corner is from the pointer.
+    pub offset: Origin,
+    pub constrain: Constrain
+}
+
+#[derive(Debug, Default)]
+pub struct Follows {
+    followers: Vec<(usize, Follow)>,
+    /// Where the pointer was last seen, in the coordinates of the screens.
+    pointer: Option<(f64, f64)>,
+    /// Whether the pointer left our surfaces since it was last seen.
+    paused: bool,
+    /// Whether the followers haven't been moved to the pointer yet.
+    moved: bool,
+    /// Whether moving them is queued for the end of the frame.
+    queued: bool,
+    /// How many frames moved the followers.
+    moves: u64
+}
+
+impl Follows {
+    /// Makes the drawin follow the pointer, returning whether it didn't
+    /// already. Returns whether moving the followers has to be queued as
+    /// well, which it does when the pointer is known.
+    pub fn start(&mut self, drawin: usize, follow: Follow) -> (bool, bool) {
+        let started = !self.is_following(drawin);
+        self.followers.retain(|&(id, _)| id != drawin);
+        self.followers.push((drawin, follow));
+        self.moved |= self.pointer.is_some();
+        (started, self.queue())
+    }
+
+    /// Stops the drawin from following the pointer, returning whether it
+    /// did.
+    pub fn stop(&mut self, drawin: usize) -> bool {
+        let following = self.is_following(drawin);
+        self.followers.retain(|&(id, _)| id != drawin);
+        following
+    }
+
+    pub fn is_following(&self, drawin: usize) -> bool {
+        self.followers.iter().any(|&(id, _)| id == drawin)
+    }
+
+    pub fn followers(&self) -> impl Iterator<Item = usize> + '_ {
+        self.followers.iter().map(|&(id, _)| id)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.followers.is_empty()
+    }
+
+    /// Whether the pointer is over surfaces that aren't ours.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn moves(&self) -> u64 {
+        self.moves
+    }
+
+    /// The pointer was seen at `pointer`, on one of our surfaces. Returns
+    /// whether moving the followers has to be queued.
+    pub fn pointer_moved(&mut self, pointer: (f64, f64)) -> bool {
+        self.pointer = Some(pointer);
+        self.paused = false;
+        self.moved = true;
+        self.queue()
+    }
+
+    /// The pointer left our surfaces, so we won't see where it goes.
+    pub fn pointer_left(&mut self) {
+        self.paused = true;
+    }
+
+    fn queue(&mut self) -> bool {
+        if !self.moved || self.queued || self.followers.is_empty() {
+            return false;
+        }
+        self.queued = true;
+        true
+    }
+
+    /// Where the pointer is for the followers to be moved to, and how they
+    /// follow it, if it moved since they were last moved.
+    pub fn take_moves(&mut self) -> Option<((f64, f64), Vec<(usize, Follow)>)> {
+        self.queued = false;
+        let pointer = self.pointer?;
+        if !std::mem::replace(&mut self.moved, false) || self.followers.is_empty() {
+            return None;
+        }
+        self.moves += 1;
+        Some((pointer, self.followers.clone()))
+    }
+}
+
+/// Where a drawin of `size` following the pointer at `pointer` goes, with
+/// `output` being the output the pointer is on.
+///
+/// It keeps its offset from the pointer, but when it's constrained it goes
+/// to the other side of the pointer where that fits and it doesn't, and is
+/// then moved back onto the output as little as possible, like menus are.
+pub fn place(pointer: Origin, follow: Follow, size: Size, output: Option<Area>) -> Origin {
+    let Follow { offset, constrain } = follow;
+    let origin = Origin {
+        x: pointer.x.saturating_add(offset.x),
+        y: pointer.y.saturating_add(offset.y)
+    };
+    let output = match (constrain, output) {
+        (Constrain::Output, Some(output)) => output,
+        _ => return origin
+    };
+    let flip = |pointer: i32, start: i32, offset: i32, length: u32, low: i32, high: i32| {
+        let length = length as i32;
+        let fits = |start: i32| start >= low && start.saturating_add(length) <= high;
+        let flipped = pointer.saturating_sub(offset).saturating_sub(length);
+        if !fits(start) && fits(flipped) {
+            flipped
+        } else {
+            start
+        }
+    };
+    let origin = Origin {
+        x: flip(
+            pointer.x,
+            origin.x,
+            offset.x,
+            size.width,
+            output.origin.x,
+            output.right()
+        ),
+        y: flip(
+            pointer.y,
+            origin.y,
+            offset.y,
+            size.height,
+            output.origin.y,
+            output.bottom()
+        )
+    };
+    Area { origin, size }.clamp_within(output).origin
+}
+
+/// The error raised when Lua moves a drawin that follows the pointer.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Following {
+    pub drawin: usize
+}
+
+impl fmt::Display for Following {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "the drawin #{} follows the pointer, call drawin:unfollow_pointer() before moving it",
+            self.drawin
+        )
+    }
+}
+
+impl Error for Following {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const OUTPUT: Area = Area {
+        origin: Origin { x: 0, y: 0 },
+        size: Size {
+            width: 1000,
+            height: 800
+        }
+    };
+
+    const TOOLTIP: Size = Size {
+        width: 100,
+        height: 40
+    };
+
+    const BELOW_RIGHT: Follow = Follow {
+        offset: Origin { x: 10, y: 20 },
+        constrain: Constrain::Output
+    };
+
+    #[test]
+    fn follow_placement() {
+        let place = |x, y, follow| place(Origin { x, y }, follow, TOOLTIP, Some(OUTPUT));
+        assert_eq!(place(100, 100, BELOW_RIGHT), Origin { x: 110, y: 120 });
+        // Past the right and bottom edges, it goes to the left and above.
+        assert_eq!(place(950, 790, BELOW_RIGHT), Origin { x: 840, y: 730 });
+        assert_eq!(place(950, 100, BELOW_RIGHT), Origin { x: 840, y: 120 });
+        // Where neither side fits, it's kept on the output.
+        let wide = Follow {
+            offset: Origin { x: -600, y: 0 },
+            ..BELOW_RIGHT
+        };
+        assert_eq!(place(500, 100, wide), Origin { x: 0, y: 100 });
+        // A negative offset flips the other way.
+        let above_left = Follow {
+            offset: Origin { x: -110, y: -50 },
+            ..BELOW_RIGHT
+        };
+        assert_eq!(place(50, 30, above_left), Origin { x: 60, y: 40 });
+        let free = Follow {
+            constrain: Constrain::None,
+            ..BELOW_RIGHT
+        };
+        assert_eq!(place(990, 790, free), Origin { x: 1000, y: 810 });
+        assert_eq!(
+            super::place(Origin { x: 990, y: 790 }, BELOW_RIGHT, TOOLTIP, None),
+            Origin { x: 1000, y: 810 }
+        );
+    }
+
+    #[test]
+    fn follow_motion_batched_per_frame() {
+        let mut follows = Follows::default();
+        assert_eq!(follows.start(1, BELOW_RIGHT), (true, false));
+        assert_eq!(follows.take_moves(), None);
+        // A burst of motion queues one move, to where the pointer ended up.
+        let queued: Vec<bool> = (0..20)
+            .map(|i| follows.pointer_moved((f64::from(i), 5.0)))
+            .collect();
+        assert_eq!(queued.iter().filter(|&&queued| queued).count(), 1);
+        assert!(queued[0]);
+        assert_eq!(follows.take_moves(), Some(((19.0, 5.0), vec![(1, BELOW_RIGHT)])));
+        assert_eq!(follows.moves(), 1);
+        // Nothing moved in the next frame.
+        assert_eq!(follows.take_moves(), None);
+        assert!(follows.pointer_moved((30.0, 5.0)));
+        assert!(!follows.pointer_moved((31.0, 5.0)));
+        assert_eq!(
+            follows.take_moves().map(|(pointer, _)| pointer),
+            Some((31.0, 5.0))
+        );
+        assert_eq!(follows.moves(), 2);
+        // Another drawin starts right where the pointer was last seen.
+        assert_eq!(follows.start(2, BELOW_RIGHT), (true, true));
+        assert_eq!(follows.start(2, BELOW_RIGHT), (false, false));
+        let (pointer, followers) = follows.take_moves().unwrap();
+        assert_eq!(pointer, (31.0, 5.0));
+        assert_eq!(followers.len(), 2);
+        assert!(follows.stop(1) && !follows.stop(1));
+        assert!(follows.stop(2));
+        assert!(!follows.pointer_moved((40.0, 5.0)));
+        assert_eq!(follows.take_moves(), None);
+    }
+
+    #[test]
+    fn follow_pauses_off_our_surfaces() {
+        let mut follows = Follows::default();
+        follows.start(1, BELOW_RIGHT);
+        assert!(follows.pointer_moved((10.0, 10.0)));
+        follows.pointer_left();
+        // The move of the frame still goes to where the pointer was seen.
+        assert!(follows.is_paused());
+        assert_eq!(
+            follows.take_moves().map(|(pointer, _)| pointer),
+            Some((10.0, 10.0))
+        );
+        assert_eq!(follows.take_moves(), None);
+        // Back over one of our surfaces, it follows again.
+        assert!(follows.pointer_moved((500.0, 300.0)));
+        assert!(!follows.is_paused());
+        assert_eq!(
+            follows.take_moves().map(|(pointer, _)| pointer),
+            Some((500.0, 300.0))
+        );
+    }
+}
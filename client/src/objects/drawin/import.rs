@@ -0,0 +1,636 @@
+//! Buffers another program draws into, e.g. a GL renderer, which it shows
+//! on a drawin itself instead of having it paint the drawable:
+//!
+//! ```lua
+//! local buffers = {}
+//! for i = 1, 3 do
+//!     buffers[i] = d:import_shm { fd = fd, width = 640, height = 480, stride = 2560, offset = (i - 1) * 1228800 }
+//!     buffers[i]:on_release(function(buffer, info) renderer:reuse(i, info.destroyed) end)
+//! end
+//! renderer:draw_into(1)
+//! buffers[1]:commit()
+//! ```
+//!
+//! The compositor reads a buffer from when it's committed until it
+//! releases it, which it does some time after another buffer is committed.
+//! Until then the buffer is in use, and committing it again fails, so the
+//! program doesn't draw over pixels that may still be shown. A drawin can
+//! have any number of buffers, so the program can draw into one while
+//! another is shown.
+//!
+//! While a buffer is attached, what's drawn on the drawable isn't shown.
+//! Destroying the attached buffer shows the drawable again. When the
+//! drawin is removed the release handlers of its buffers are called once
+//! more, with `destroyed` set, so the program doesn't wait on them.
+
+use std::fmt;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rlua::{self, AnyUserData, Function, Table, UserData, UserDataMethods, Value};
+
+use crate::area::Size;
+use crate::leaks::{self, Created, Finding, Owner};
+
+use super::{find_drawin, Drawin, DrawinId};
+
+/// Table of the handles of imported buffers by their id.
+const IMPORTS_HANDLE: &str = "__imported_buffers";
+
+/// The largest width and height of a buffer.
+const MAX_SIZE: i64 = 1 << 14;
+
+static NEXT_IMPORT_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// Why a buffer couldn't be imported or committed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImportError {
+    /// The arguments don't describe pixels in the file.
+    Invalid(String),
+    /// The compositor couldn't be asked for the buffer.
+    Import(String),
+    /// The compositor still reads the buffer.
+    Busy,
+    /// The drawin has no surface to attach the buffer to.
+    Hidden,
+    /// The buffer or its drawin was destroyed.
+    Destroyed
+}
+
+impl ImportError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ImportError::Invalid(_) => "invalid",
+            ImportError::Import(_) => "import",
+            ImportError::Busy => "busy",
+            ImportError::Hidden => "hidden",
+            ImportError::Destroyed => "destroyed"
+        }
+    }
+
+    /// The error as a table like `{ kind = "busy", message = "..." }`.
+    pub fn to_lua<'lua>(&self, lua: rlua::Context<'lua>) -> rlua::Result<Table<'lua>> {
+        let table = lua.create_table()?;
+        table.set("kind", self.kind())?;
+        table.set("message", self.to_string())?;
+        Ok(table)
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::Invalid(err) => write!(f, "invalid buffer: {}", err),
+            ImportError::Import(err) => write!(f, "could not import the buffer: {}", err),
+            ImportError::Busy => write!(f, "the compositor has not released the buffer yet"),
+            ImportError::Hidden => write!(f, "the drawin is not visible"),
+            ImportError::Destroyed => write!(f, "the buffer was destroyed")
+        }
+    }
+}
+
+/// Where the pixels of a buffer are in its file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub fd: RawFd,
+    pub size: Size,
+    pub stride: i32,
+    pub offset: i32
+}
+
+impl Layout {
+    /// Checks that the rows fit in `file_len` bytes, and that the size
+    /// isn't larger than a surface could be.
+    pub fn new(
+        fd: i64,
+        width: i64,
+        height: i64,
+        stride: i64,
+        offset: i64,
+        file_len: i64
+    ) -> Result<Layout, ImportError> {
+        let invalid = |err: String| Err(ImportError::Invalid(err));
+        if fd < 0 || fd > i64::from(RawFd::max_value()) {
+            return invalid(format!("{} is not a file descriptor", fd));
+        }
+        if width < 1 || height < 1 || width > MAX_SIZE || height > MAX_SIZE {
+            return invalid(format!(
+                "the size must be from 1x1 to {}x{}, got {}x{}",
+                MAX_SIZE, MAX_SIZE, width, height
+            ));
+        }
+        if stride < width * 4 {
+            return invalid(format!(
+                "a stride of {} is too short for {} pixels",
+                stride, width
+            ));
+        }
+        if offset < 0 {
+            return invalid(format!("negative offset {}", offset));
+        }
+        let end = offset + stride * height;
+        if end > file_len || end > i64::from(i32::max_value()) {
+            return invalid(format!(
+                "the pixels end at {}, past the {} bytes of the file",
+                end, file_len
+            ));
+        }
+        Ok(Layout {
+            fd: fd as RawFd,
+            size: Size {
+                width: width as u32,
+                height: height as u32
+            },
+            stride: stride as i32,
+            offset: offset as i32
+        })
+    }
+}
+
+/// The buffers imported to a drawin, and which of them the compositor
+/// holds.
+#[derive(Debug)]
+pub struct Imports<B> {
+    slots: Vec<Slot<B>>,
+    /// The buffer attached to the surface, if any.
+    attached: Option<usize>
+}
+
+#[derive(Debug)]
+struct Slot<B> {
+    id: usize,
+    buffer: B,
+    /// Whether the buffer was committed and not released yet.
+    held: bool
+}
+
+impl<B> Default for Imports<B> {
+    fn default() -> Self {
+        Imports {
+            slots: Vec::new(),
+            attached: None
+        }
+    }
+}
+
+impl<B> Imports<B> {
+    pub fn add(&mut self, id: usize, buffer: B) {
+        self.slots.push(Slot {
+            id,
+            buffer,
+            held: false
+        });
+    }
+
+    /// Whether the compositor holds the buffer, or `None` if it was
+    /// destroyed.
+    pub fn in_use(&self, id: usize) -> Option<bool> {
+        self.slot(id).map(|slot| slot.held)
+    }
+
+    /// Marks the buffer as attached and held, returning it to attach to
+    /// the surface, which is there if `shown`.
+    pub fn commit(&mut self, id: usize, shown: bool) -> Result<&B, ImportError> {
+        let index = match self.slots.iter().position(|slot| slot.id == id) {
+            Some(index) => index,
+            None => return Err(ImportError::Destroyed)
+        };
+        if self.slots[index].held {
+            return Err(ImportError::Busy);
+        }
+        if !shown {
+            return Err(ImportError::Hidden);
+        }
+        self.attached = Some(id);
+        let slot = &mut self.slots[index];
+        slot.held = true;
+        Ok(&slot.buffer)
+    }
+
+    /// Called when the compositor released the buffer, returning whether
+    /// it held it.
+    pub fn release(&mut self, id: usize) -> bool {
+        match self.slots.iter_mut().find(|slot| slot.id == id) {
+            Some(slot) => {
+                let held = slot.held;
+                slot.held = false;
+                held
+            },
+            None => false
+        }
+    }
+
+    /// The buffer attached to the surface, which is shown instead of the
+    /// drawable.
+    pub fn attached(&self) -> Option<&B> {
+        self.attached
+            .and_then(|id| self.slot(id))
+            .map(|slot| &slot.buffer)
+    }
+
+    /// Forgets which buffer is attached, e.g. when the surface was
+    /// destroyed.
+    pub fn detach(&mut self) {
+        self.attached = None;
+    }
+
+    /// Removes the buffer, returning it and whether it was attached.
+    pub fn remove(&mut self, id: usize) -> Option<(B, bool)> {
+        let index = self.slots.iter().position(|slot| slot.id == id)?;
+        let attached = self.attached == Some(id);
+        if attached {
+            self.attached = None;
+        }
+        Some((self.slots.remove(index).buffer, attached))
+    }
+
+    /// Removes every buffer, returning their ids.
+    pub fn drain(&mut self) -> Vec<usize> {
+        self.attached = None;
+        self.slots.drain(..).map(|slot| slot.id).collect()
+    }
+
+    fn slot(&self, id: usize) -> Option<&Slot<B>> {
+        self.slots.iter().find(|slot| slot.id == id)
+    }
+}
+
+/// The Lua side of an imported buffer.
+///
+/// The user value is a table with the drawin and the release handlers.
+pub struct ImportHandle {
+    pub id: usize,
+    drawin: DrawinId,
+    created: Created
+}
+
+impl UserData for ImportHandle {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_function("on_release", on_release);
+        methods.add_function("in_use", in_use);
+        methods.add_function("commit", commit);
+        methods.add_function("destroy", destroy);
+    }
+}
+
+/// A new id for a buffer about to be imported.
+pub fn next_id() -> usize {
+    NEXT_IMPORT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Creates the handle of a buffer imported to `drawin`, which is found by
+/// its id until it's destroyed.
+pub fn register<'lua>(
+    lua: rlua::Context<'lua>,
+    drawin: Drawin<'lua>,
+    id: usize
+) -> rlua::Result<AnyUserData<'lua>> {
+    let handle = lua.create_userdata(ImportHandle {
+        id,
+        drawin: drawin.id()?,
+        created: Created::now(lua)
+    })?;
+    let contents = lua.create_table()?;
+    contents.set("drawin", drawin)?;
+    contents.set("callbacks", lua.create_table()?)?;
+    handle.set_user_value(contents)?;
+    handles(lua)?.set(id, handle.clone())?;
+    Ok(handle)
+}
+
+/// Called on the main loop when the compositor released a buffer.
+pub fn released(lua: rlua::Context, id: usize) -> rlua::Result<()> {
+    let handle = match handles(lua)?.get::<_, Option<AnyUserData>>(id)? {
+        Some(handle) => handle,
+        None => return Ok(())
+    };
+    let drawin = handle.borrow::<ImportHandle>()?.drawin;
+    // Marked as released first, so the handlers can commit it again.
+    if let Some(mut drawin) = find_drawin(lua, drawin)? {
+        drawin.state_mut()?.imports.release(id);
+    }
+    call_handlers(lua, handle, false)
+}
+
+/// Called once the buffers of a drawin were removed with it, so whoever
+/// waits for them to be released doesn't wait forever.
+pub fn destroyed(lua: rlua::Context, ids: Vec<usize>) -> rlua::Result<()> {
+    let handles = handles(lua)?;
+    for id in ids {
+        if let Some(handle) = handles.get::<_, Option<AnyUserData>>(id)? {
+            handles.set(id, Value::Nil)?;
+            call_handlers(lua, handle, true)?;
+        }
+    }
+    Ok(())
+}
+
+/// Destroys the buffers Lua lost without destroying them, and forgets the
+/// handles of buffers that are gone already, see `leaks`.
+///
+/// The compositor owns a buffer while it reads it, it's released later.
+pub fn sweep(lua: rlua::Context) -> rlua::Result<Vec<Finding>> {
+    let handles = handles(lua)?;
+    let mut findings = Vec::new();
+    let mut candidates = Vec::new();
+    for pair in handles.clone().pairs::<usize, AnyUserData>() {
+        let (id, handle) = pair?;
+        let in_use = handle_drawin(&handle)?.state()?.imports.in_use(id);
+        let owner = match in_use {
+            Some(true) => Owner::Client("held by the compositor"),
+            Some(false) => Owner::Lua,
+            None => {
+                findings.push(finding(&handle)?);
+                handles.set(id, Value::Nil)?;
+                continue;
+            }
+        };
+        if owner == Owner::Lua {
+            candidates.push(handle);
+        }
+    }
+    if candidates.is_empty() {
+        return Ok(findings);
+    }
+    let values = candidates.iter().cloned().map(Value::UserData).collect();
+    let reached = leaks::reachable(lua, handles, values)?;
+    for (handle, reached) in candidates.into_iter().zip(reached) {
+        if !reached {
+            findings.push(finding(&handle)?);
+            destroy(lua, handle)?;
+        }
+    }
+    Ok(findings)
+}
+
+fn finding(handle: &AnyUserData) -> rlua::Result<Finding> {
+    let handle = handle.borrow::<ImportHandle>()?;
+    Ok(Finding {
+        category: "imported_buffers",
+        id: handle.id,
+        created: Some(handle.created.clone())
+    })
+}
+
+fn call_handlers<'lua>(
+    lua: rlua::Context<'lua>,
+    handle: AnyUserData<'lua>,
+    destroyed: bool
+) -> rlua::Result<()> {
+    let callbacks = handle.get_user_value::<Table>()?.get::<_, Table>("callbacks")?;
+    for callback in callbacks.sequence_values::<Function>() {
+        let info = lua.create_table()?;
+        info.set("destroyed", destroyed)?;
+        if let Err(err) = callback?.call::<_, ()>((handle.clone(), info)) {
+            warn!("Error in the release handler of an imported buffer: {}", err);
+        }
+    }
+    Ok(())
+}
+
+fn handles(lua: rlua::Context) -> rlua::Result<Table> {
+    match lua.named_registry_value::<str, Option<Table>>(IMPORTS_HANDLE)? {
+        Some(handles) => Ok(handles),
+        None => {
+            let handles = lua.create_table()?;
+            lua.set_named_registry_value(IMPORTS_HANDLE, handles.clone())?;
+            Ok(handles)
+        }
+    }
+}
+
+/// The handle of the buffer `id`, if it's imported to `drawin`.
+pub fn handle<'lua>(
+    lua: rlua::Context<'lua>,
+    drawin: DrawinId,
+    id: usize
+) -> rlua::Result<Option<AnyUserData<'lua>>> {
+    match handles(lua)?.get::<_, Option<AnyUserData>>(id)? {
+        Some(handle) if handle.borrow::<ImportHandle>()?.drawin == drawin => Ok(Some(handle)),
+        _ => Ok(None)
+    }
+}
+
+fn handle_drawin<'lua>(handle: &AnyUserData<'lua>) -> rlua::Result<Drawin<'lua>> {
+    handle.get_user_value::<Table>()?.get::<_, Drawin>("drawin")
+}
+
+/// `buffer:on_release(func)`, which calls `func(buffer, { destroyed =
+/// false })` every time the compositor releases the buffer.
+pub fn on_release<'lua>(
+    _: rlua::Context<'lua>,
+    (handle, func): (AnyUserData<'lua>, Function<'lua>)
+) -> rlua::Result<()> {
+    let callbacks = handle.get_user_value::<Table>()?.get::<_, Table>("callbacks")?;
+    callbacks.set(callbacks.raw_len() + 1, func)
+}
+
+/// `buffer:in_use()`, whether the compositor may still read the buffer.
+fn in_use(_: rlua::Context, handle: AnyUserData) -> rlua::Result<bool> {
+    let id = handle.borrow::<ImportHandle>()?.id;
+    let drawin = handle_drawin(&handle)?;
+    let in_use = drawin.state()?.imports.in_use(id);
+    Ok(in_use.unwrap_or(false))
+}
+
+/// `buffer:commit()`, which shows the buffer on the drawin and returns true
+/// or nil and the error.
+fn commit<'lua>(
+    lua: rlua::Context<'lua>,
+    handle: AnyUserData<'lua>
+) -> rlua::Result<(Value<'lua>, Value<'lua>)> {
+    let id = handle.borrow::<ImportHandle>()?.id;
+    let mut drawin = handle_drawin(&handle)?;
+    match drawin.commit_import(id)? {
+        Ok(()) => Ok((Value::Boolean(true), Value::Nil)),
+        Err(err) => Ok((Value::Nil, Value::Table(err.to_lua(lua)?)))
+    }
+}
+
+/// `buffer:destroy()`, which frees the buffer without calling the release
+/// handlers. The drawable is shown again if the buffer was.
+pub fn destroy(lua: rlua::Context, handle: AnyUserData) -> rlua::Result<()> {
+    let id = handle.borrow::<ImportHandle>()?.id;
+    let mut drawin = handle_drawin(&handle)?;
+    handles(lua)?.set(id, Value::Nil)?;
+    drawin.remove_import(id)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+
+    use rlua::{self, Lua};
+
+    use super::*;
+    use crate::objects::{drawable, drawin};
+
+    /// Releases a committed buffer only once `delay` more buffers were
+    /// committed after it, like a compositor that is some frames behind.
+    struct Compositor {
+        delay: usize,
+        committed: VecDeque<usize>
+    }
+
+    impl Compositor {
+        fn commit(&mut self, imports: &mut Imports<()>, id: usize) -> Result<(), ImportError> {
+            imports.commit(id, true)?;
+            self.committed.push_back(id);
+            // The attached buffer is never released.
+            while self.committed.len() > self.delay + 1 {
+                let released = self.committed.pop_front().unwrap();
+                assert!(imports.release(released));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn import_release_gates_reuse() {
+        let mut imports = Imports::default();
+        for id in 1..=3 {
+            imports.add(id, ());
+        }
+        for delay in 0..2 {
+            let mut compositor = Compositor {
+                delay,
+                committed: VecDeque::new()
+            };
+            for frame in 0..30 {
+                // The producer draws into the first buffer it may reuse.
+                let free = (1..=3).find(|&id| imports.in_use(id) == Some(false));
+                let id = match free {
+                    Some(id) => id,
+                    None => panic!("no buffer free in frame {} with a delay of {}", frame, delay)
+                };
+                compositor.commit(&mut imports, id).unwrap();
+                assert_eq!(imports.attached, Some(id));
+                // Every buffer the compositor holds can't be committed.
+                for &held in &compositor.committed {
+                    assert_eq!(imports.commit(held, true).err(), Some(ImportError::Busy));
+                }
+            }
+            for id in compositor.committed {
+                imports.release(id);
+            }
+        }
+        // Three buffers aren't enough for a compositor two frames behind.
+        let mut compositor = Compositor {
+            delay: 2,
+            committed: VecDeque::new()
+        };
+        for id in 1..=3 {
+            compositor.commit(&mut imports, id).unwrap();
+        }
+        assert!((1..=3).all(|id| imports.in_use(id) == Some(true)));
+        assert_eq!(compositor.commit(&mut imports, 1), Err(ImportError::Busy));
+    }
+
+    #[test]
+    fn import_commit_errors() {
+        let mut imports = Imports::default();
+        imports.add(1, ());
+        assert_eq!(imports.commit(1, false).err(), Some(ImportError::Hidden));
+        assert_eq!(imports.in_use(1), Some(false));
+        assert!(imports.commit(1, true).is_ok());
+        imports.detach();
+        assert!(imports.attached().is_none());
+        // Still held until the compositor releases it.
+        assert_eq!(imports.commit(1, true).err(), Some(ImportError::Busy));
+        assert!(!imports.release(2));
+        assert_eq!(imports.remove(1), Some(((), false)));
+        assert_eq!(imports.commit(1, true).err(), Some(ImportError::Destroyed));
+        assert_eq!(imports.in_use(1), None);
+        assert!(!imports.release(1));
+        imports.add(2, ());
+        imports.add(3, ());
+        imports.commit(3, true).unwrap();
+        assert_eq!(imports.drain(), vec![2, 3]);
+        assert!(imports.attached().is_none());
+    }
+
+    #[test]
+    fn import_layout() {
+        let layout = Layout::new(3, 4, 2, 20, 8, 48).unwrap();
+        assert_eq!(layout.size, Size { width: 4, height: 2 });
+        assert_eq!((layout.stride, layout.offset), (20, 8));
+        let invalid = [
+            (-1, 4, 2, 16, 0, 100),
+            (3, 0, 2, 16, 0, 100),
+            (3, 4, 2, 15, 0, 100),
+            (3, 4, 2, 16, -4, 100),
+            (3, 4, 2, 20, 12, 48),
+            (3, 1 << 15, 2, 1 << 17, 0, 1 << 40),
+            (3, 1 << 14, 1 << 14, 1 << 17, 0, 1 << 40)
+        ];
+        for &(fd, width, height, stride, offset, len) in &invalid {
+            match Layout::new(fd, width, height, stride, offset, len) {
+                Err(ImportError::Invalid(_)) => {},
+                other => panic!("{:?} for {:?}", other, (fd, width, height, stride, offset, len))
+            }
+        }
+    }
+
+    #[test]
+    fn import_destroyed_handlers_fire_once() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            drawin::init(lua)?;
+            let d = lua.load("drawin{}").eval::<Drawin>()?;
+            let handle = register(lua, d, next_id())?;
+            let id = handle.borrow::<ImportHandle>()?.id;
+            lua.globals().set("buffer", handle)?;
+            lua.load(
+                r#"
+calls = {}
+buffer:on_release(function(b, info) table.insert(calls, info.destroyed) end)
+"#
+            )
+            .exec()?;
+            released(lua, id)?;
+            destroyed(lua, vec![id])?;
+            // The handle is forgotten, so later releases don't reach it.
+            destroyed(lua, vec![id])?;
+            released(lua, id)?;
+            lua.load(
+                r#"
+assert(#calls == 2 and calls[1] == false and calls[2] == true)
+assert(not buffer:in_use())
+local ok, err = buffer:commit()
+assert(ok == nil and err.kind == "destroyed", err and err.kind)
+"#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn import_sweep_forgets_destroyed() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            drawable::init(lua)?;
+            drawin::init(lua)?;
+            let d = lua.load("drawin{}").eval::<Drawin>()?;
+            // Handles of buffers the drawin doesn't have, like after an
+            // error halfway through destroying them.
+            let kept = register(lua, d.clone(), next_id())?;
+            let lost = register(lua, d, next_id())?;
+            let lost = lost.borrow::<ImportHandle>()?.id;
+            let findings = sweep(lua)?;
+            assert_eq!(findings.len(), 2);
+            assert!(findings.iter().any(|finding| finding.id == lost));
+            assert!(handles(lua)?.get::<_, Option<AnyUserData>>(lost)?.is_none());
+            // Using a forgotten handle fails like using a destroyed one.
+            lua.globals().set("buffer", kept)?;
+            assert!(sweep(lua)?.is_empty());
+            lua.load(
+                r#"
+local ok, err = buffer:commit()
+assert(ok == nil and err.kind == "destroyed")
+"#
+            )
+            .exec()
+        })
+    }
+}
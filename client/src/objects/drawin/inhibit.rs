@@ -0,0 +1,322 @@
+//! Drawins that ask the compositor for the key presses it would otherwise
+//! act on itself, like a lock screen that mustn't let anyone switch away
+//! from it.
+//!
+//! A drawin inhibits the shortcuts while something wants it to and it
+//! holds the keyboard focus, see `focus`. What wants it is counted: every
+//! `drawin:inhibit_shortcuts(true)` is undone by one
+//! `drawin:inhibit_shortcuts(false)`, and a lock screen wants it while it's
+//! locked, so a modal opened over a lock screen doesn't lift the lock's
+//! inhibitor when it lifts its own.
+//!
+//! The compositor decides whether the shortcuts are actually inhibited, and
+//! can change its mind at any time. It refuses by saying the inhibitor is
+//! inactive before it ever was active. A lock screen the compositor doesn't
+//! inhibit the shortcuts for is weaker than it looks, so that's reported.
+
+/// Something to do or tell Lua.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Action {
+    /// Create the inhibitor of the drawin.
+    Create(usize),
+    /// Destroy the inhibitor of the drawin.
+    Destroy(usize),
+    /// Whether the shortcuts are inhibited for the drawin changed.
+    Inhibited(usize, bool),
+    /// The shortcuts of a lock screen aren't inhibited, because the
+    /// compositor doesn't support it, refused or stopped.
+    LockWeakened(usize, &'static str)
+}
+
+#[derive(Debug, Default)]
+struct Entry {
+    drawin: usize,
+    /// How many times the drawin was asked to inhibit the shortcuts, not
+    /// counting the lock.
+    requests: u32,
+    locked: bool,
+    focused: bool,
+    /// Whether the drawin has an inhibitor.
+    created: bool,
+    /// Whether the compositor said the inhibitor is active.
+    active: bool,
+    /// Whether it ever was, so the compositor didn't refuse it.
+    granted: bool
+}
+
+impl Entry {
+    fn wanted(&self) -> bool {
+        (self.requests > 0 || self.locked) && self.focused
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Inhibits {
+    entries: Vec<Entry>
+}
+
+impl Inhibits {
+    /// Whether the compositor inhibits the shortcuts for the drawin.
+    pub fn inhibited(&self, drawin: usize) -> bool {
+        self.entry(drawin).map(|entry| entry.active).unwrap_or(false)
+    }
+
+    /// How many times Lua asked the drawin to inhibit the shortcuts, not
+    /// counting the lock.
+    pub fn requests(&self, drawin: usize) -> u32 {
+        self.entry(drawin).map(|entry| entry.requests).unwrap_or(0)
+    }
+
+    /// The drawins anything is known about.
+    pub fn drawins(&self) -> Vec<usize> {
+        self.entries.iter().map(|entry| entry.drawin).collect()
+    }
+
+    /// `drawin:inhibit_shortcuts(true)`.
+    pub fn request(&mut self, drawin: usize) -> Vec<Action> {
+        self.update(drawin, |entry| entry.requests += 1)
+    }
+
+    /// `drawin:inhibit_shortcuts(false)`, which undoes one request.
+    pub fn unrequest(&mut self, drawin: usize) -> Vec<Action> {
+        self.update(drawin, |entry| entry.requests = entry.requests.saturating_sub(1))
+    }
+
+    /// Called when the drawin locked or unlocked the screen.
+    pub fn set_locked(&mut self, drawin: usize, locked: bool) -> Vec<Action> {
+        self.update(drawin, |entry| entry.locked = locked)
+    }
+
+    /// Called when the drawin got or lost the keyboard focus.
+    pub fn set_focused(&mut self, drawin: usize, focused: bool) -> Vec<Action> {
+        self.update(drawin, |entry| entry.focused = focused)
+    }
+
+    /// Forgets the drawin, which is destroyed along with its inhibitor.
+    pub fn remove(&mut self, drawin: usize) -> Vec<Action> {
+        let mut actions = self.update(drawin, |entry| {
+            entry.requests = 0;
+            entry.locked = false;
+            entry.focused = false
+        });
+        actions.retain(|action| match action {
+            Action::Inhibited(..) => false,
+            _ => true
+        });
+        actions
+    }
+
+    /// Called when the inhibitor couldn't be created.
+    pub fn unsupported(&mut self, drawin: usize) -> Vec<Action> {
+        match self.entry_mut(drawin) {
+            Some(entry) if entry.created => {
+                entry.created = false;
+                if entry.locked {
+                    vec![Action::LockWeakened(drawin, "unsupported")]
+                } else {
+                    Vec::new()
+                }
+            },
+            _ => Vec::new()
+        }
+    }
+
+    /// Called when the compositor said whether the inhibitor of the drawin
+    /// is active.
+    pub fn set_active(&mut self, drawin: usize, active: bool) -> Vec<Action> {
+        let entry = match self.entry_mut(drawin) {
+            // Events for a destroyed inhibitor may still arrive.
+            Some(entry) if entry.created => entry,
+            _ => return Vec::new()
+        };
+        let was_active = entry.active;
+        entry.active = active;
+        let mut actions = Vec::new();
+        if active != was_active {
+            actions.push(Action::Inhibited(drawin, active));
+        }
+        if active {
+            entry.granted = true;
+        } else if entry.locked {
+            let reason = if entry.granted { "revoked" } else { "refused" };
+            actions.push(Action::LockWeakened(drawin, reason));
+        }
+        actions
+    }
+
+    /// Changes the entry of the drawin, creating or destroying the
+    /// inhibitor if it's wanted now or not anymore.
+    fn update<F: FnOnce(&mut Entry)>(&mut self, drawin: usize, change: F) -> Vec<Action> {
+        if self.entry(drawin).is_none() {
+            self.entries.push(Entry {
+                drawin,
+                ..Entry::default()
+            });
+        }
+        let entry = self.entry_mut(drawin).unwrap();
+        change(entry);
+        let mut actions = Vec::new();
+        match (entry.wanted(), entry.created) {
+            (true, false) => {
+                entry.created = true;
+                entry.granted = false;
+                actions.push(Action::Create(drawin));
+            },
+            (false, true) => {
+                entry.created = false;
+                actions.push(Action::Destroy(drawin));
+                if entry.active {
+                    entry.active = false;
+                    actions.push(Action::Inhibited(drawin, false));
+                }
+            },
+            _ => {}
+        }
+        // Drawins nothing is known about aren't kept.
+        self.entries
+            .retain(|entry| entry.requests > 0 || entry.locked || entry.focused || entry.created);
+        actions
+    }
+
+    fn entry(&self, drawin: usize) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.drawin == drawin)
+    }
+
+    fn entry_mut(&mut self, drawin: usize) -> Option<&mut Entry> {
+        self.entries.iter_mut().find(|entry| entry.drawin == drawin)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::focus::{Change, FocusStack, Priority};
+    use super::Action::*;
+    use super::*;
+
+    const KIOSK: usize = 1;
+    const LOCK: usize = 2;
+    const MODAL: usize = 3;
+
+    /// Passes the focus changes on like `focus_changed` does.
+    fn focus_changed(inhibits: &mut Inhibits, changes: Vec<Change>) -> Vec<Action> {
+        let mut actions = Vec::new();
+        for change in changes {
+            actions.extend(match change {
+                Change::Revoked(drawin) => inhibits.set_focused(drawin, false),
+                Change::Granted(drawin) => inhibits.set_focused(drawin, true)
+            });
+        }
+        actions
+    }
+
+    #[test]
+    fn inhibit_granted_while_focused() {
+        let mut inhibits = Inhibits::default();
+        let mut focus = FocusStack::default();
+        // Nothing is created until the drawin has the focus.
+        assert_eq!(inhibits.request(KIOSK), vec![]);
+        assert_eq!(
+            focus_changed(&mut inhibits, focus.request(KIOSK, Priority::Regular)),
+            vec![Create(KIOSK)]
+        );
+        assert!(!inhibits.inhibited(KIOSK));
+        assert_eq!(inhibits.set_active(KIOSK, true), vec![Inhibited(KIOSK, true)]);
+        assert_eq!(inhibits.set_active(KIOSK, true), vec![]);
+        assert!(inhibits.inhibited(KIOSK));
+        // Losing the focus destroys it, getting it back creates it again.
+        assert_eq!(
+            focus_changed(&mut inhibits, focus.request(MODAL, Priority::Modal)),
+            vec![Destroy(KIOSK), Inhibited(KIOSK, false)]
+        );
+        assert_eq!(inhibits.set_active(KIOSK, true), vec![]);
+        assert_eq!(
+            focus_changed(&mut inhibits, focus.release(MODAL)),
+            vec![Create(KIOSK)]
+        );
+        assert_eq!(inhibits.unrequest(KIOSK), vec![Destroy(KIOSK)]);
+        assert_eq!(inhibits.unrequest(KIOSK), vec![]);
+        assert_eq!(inhibits.requests(KIOSK), 0);
+    }
+
+    #[test]
+    fn inhibit_refused_and_revoked() {
+        let mut inhibits = Inhibits::default();
+        let mut focus = FocusStack::default();
+        // Only a lock screen being refused is worth a warning.
+        inhibits.request(KIOSK);
+        focus_changed(&mut inhibits, focus.request(KIOSK, Priority::Regular));
+        assert_eq!(inhibits.set_active(KIOSK, false), vec![]);
+
+        assert_eq!(inhibits.set_locked(LOCK, true), vec![]);
+        assert_eq!(
+            focus_changed(&mut inhibits, focus.request(LOCK, Priority::Lockscreen)),
+            vec![Destroy(KIOSK), Create(LOCK)]
+        );
+        assert_eq!(
+            inhibits.set_active(LOCK, false),
+            vec![LockWeakened(LOCK, "refused")]
+        );
+        // Granted later after all, then revoked while locked.
+        assert_eq!(inhibits.set_active(LOCK, true), vec![Inhibited(LOCK, true)]);
+        assert_eq!(
+            inhibits.set_active(LOCK, false),
+            vec![Inhibited(LOCK, false), LockWeakened(LOCK, "revoked")]
+        );
+        assert_eq!(inhibits.set_active(LOCK, true), vec![Inhibited(LOCK, true)]);
+        // No protocol at all.
+        let mut inhibits = Inhibits::default();
+        inhibits.set_locked(LOCK, true);
+        assert_eq!(inhibits.set_focused(LOCK, true), vec![Create(LOCK)]);
+        assert_eq!(
+            inhibits.unsupported(LOCK),
+            vec![LockWeakened(LOCK, "unsupported")]
+        );
+        assert_eq!(inhibits.set_active(LOCK, true), vec![]);
+    }
+
+    #[test]
+    fn inhibit_counted_per_surface() {
+        let mut inhibits = Inhibits::default();
+        let mut focus = FocusStack::default();
+        inhibits.set_locked(LOCK, true);
+        focus_changed(&mut inhibits, focus.request(LOCK, Priority::Lockscreen));
+        // A modal part of the lock screen inhibits the same surface.
+        assert_eq!(inhibits.request(LOCK), vec![]);
+        assert_eq!(inhibits.requests(LOCK), 1);
+        assert_eq!(inhibits.unrequest(LOCK), vec![]);
+        inhibits.set_active(LOCK, true);
+        assert!(inhibits.inhibited(LOCK));
+        // A modal over the lock screen waits without taking it away.
+        inhibits.request(MODAL);
+        assert_eq!(
+            focus_changed(&mut inhibits, focus.request(MODAL, Priority::Modal)),
+            vec![]
+        );
+        // Unlocking hands the focus, and with it the inhibitor, over in
+        // order: the old one is gone before the new one is asked for.
+        let mut actions = inhibits.set_locked(LOCK, false);
+        actions.extend(focus_changed(&mut inhibits, focus.release(LOCK)));
+        assert_eq!(
+            actions,
+            vec![Destroy(LOCK), Inhibited(LOCK, false), Create(MODAL)]
+        );
+    }
+
+    #[test]
+    fn inhibit_cleanup_on_remove() {
+        let mut inhibits = Inhibits::default();
+        let mut focus = FocusStack::default();
+        inhibits.set_locked(LOCK, true);
+        focus_changed(&mut inhibits, focus.request(LOCK, Priority::Lockscreen));
+        inhibits.set_active(LOCK, true);
+        // The drawin is removed before the focus moves on, and there is no
+        // one left to tell about it.
+        assert_eq!(inhibits.remove(LOCK), vec![Destroy(LOCK)]);
+        assert_eq!(focus_changed(&mut inhibits, focus.release(LOCK)), vec![]);
+        assert_eq!(inhibits.requests(LOCK), 0);
+        assert!(!inhibits.inhibited(LOCK));
+        // Late events for the destroyed inhibitor are ignored.
+        assert_eq!(inhibits.set_active(LOCK, false), vec![]);
+        assert!(inhibits.entries.is_empty());
+    }
+}
@@ -0,0 +1,246 @@
+//! A record of what happened to recent input events on their way to the
+//! Lua handlers of a drawin, for finding out why a click did nothing.
+//!
+//! Every event is traced through the stages it passes, e.g. whether it hit
+//! the surface of a drawin and how many handlers were called. Only events
+//! of drawins with `trace_input` set are recorded, or all of them with
+//! `drawin.trace_input_all`.
+
+use std::{collections::VecDeque, fmt};
+
+/// How many events are remembered.
+pub const CAPACITY: usize = 256;
+
+/// Something that happened to an input event.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stage {
+    /// The event reached the client, e.g. "button 272 pressed".
+    Received(String),
+    /// The event was on the surface of the drawin.
+    SurfaceMatched { surface: u32 },
+    /// The event was on a surface that isn't shown by a drawin.
+    SurfaceMismatched { surface: u32 },
+    /// The event has no surface, it went to the drawin under the pointer.
+    Focused,
+    /// The event has no surface and the pointer isn't over a drawin.
+    NoFocus,
+    /// Where on the drawin the event happened.
+    Translated { x: f64, y: f64 },
+    /// The button the Lua handlers are given.
+    ButtonMapped { button: u32 },
+    /// The button has no number in Lua, so the event is dropped.
+    ButtonUnmapped { code: u32 },
+    /// The signal was emitted, calling the handlers connected to it.
+    Dispatched { signal: &'static str, handlers: usize },
+    /// A handler raised an error.
+    HandlerErrored(String),
+    /// The drawins following the pointer were told it's at `x`, `y` on the
+    /// screens.
+    Followed { x: f64, y: f64, followers: usize },
+    /// The pointer left our surfaces, so the drawins following it stay
+    /// where it was last seen.
+    FollowPaused
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stage::Received(event) => write!(f, "received {}", event),
+            Stage::SurfaceMatched { surface } => write!(f, "surface-matched {}", surface),
+            Stage::SurfaceMismatched { surface } => write!(f, "surface-mismatched {}", surface),
+            Stage::Focused => write!(f, "focused"),
+            Stage::NoFocus => write!(f, "no-focus"),
+            Stage::Translated { x, y } => write!(f, "translated {},{}", x, y),
+            Stage::ButtonMapped { button } => write!(f, "button {}", button),
+            Stage::ButtonUnmapped { code } => write!(f, "button-unmapped {}", code),
+            Stage::Dispatched { signal, handlers } => {
+                write!(f, "dispatched {} to {} handlers", signal, handlers)
+            },
+            Stage::HandlerErrored(err) => write!(f, "handler-errored {}", err),
+            Stage::Followed { x, y, followers } => write!(f, "followed {},{} by {}", x, y, followers),
+            Stage::FollowPaused => write!(f, "follow-paused")
+        }
+    }
+}
+
+/// The stages of one event.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Entry {
+    /// The id of the drawin the event went to, if any.
+    pub drawin: Option<usize>,
+    /// When the event happened, in milliseconds since the client started.
+    pub time: f64,
+    pub stages: Vec<Stage>
+}
+
+#[derive(Debug, Default)]
+pub struct InputTrace {
+    all: bool,
+    traced: Vec<usize>,
+    entries: VecDeque<Entry>
+}
+
+impl InputTrace {
+    /// Whether any event could be recorded. When it isn't, events shouldn't
+    /// be traced at all.
+    pub fn is_enabled(&self) -> bool {
+        self.all || !self.traced.is_empty()
+    }
+
+    pub fn all(&self) -> bool {
+        self.all
+    }
+
+    pub fn set_all(&mut self, all: bool) {
+        self.all = all;
+    }
+
+    pub fn is_traced(&self, drawin: usize) -> bool {
+        self.traced.contains(&drawin)
+    }
+
+    pub fn set_traced(&mut self, drawin: usize, traced: bool) {
+        self.traced.retain(|id| *id != drawin);
+        if traced {
+            self.traced.push(drawin);
+        }
+    }
+
+    /// Remembers the event if it went to a traced drawin, forgetting the
+    /// oldest event if there are too many.
+    pub fn record(&mut self, entry: Entry) {
+        let traced = match entry.drawin {
+            Some(drawin) => self.is_traced(drawin),
+            None => false
+        };
+        if !self.all && !traced {
+            return;
+        }
+        if self.entries.len() == CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// The remembered events, oldest first.
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter()
+    }
+
+    /// The remembered events of a drawin, oldest first.
+    pub fn entries_of(&self, drawin: usize) -> impl Iterator<Item = &Entry> {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.drawin == Some(drawin))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(drawin: Option<usize>, stages: Vec<Stage>) -> Entry {
+        Entry {
+            drawin,
+            time: 0.0,
+            stages
+        }
+    }
+
+    fn describe(entry: &Entry) -> Vec<String> {
+        entry.stages.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn input_trace_enabling() {
+        let mut trace = InputTrace::default();
+        assert!(!trace.is_enabled());
+        trace.set_traced(1, true);
+        trace.set_traced(1, true);
+        assert!(trace.is_enabled() && trace.is_traced(1));
+        trace.set_traced(1, false);
+        assert!(!trace.is_enabled());
+        trace.set_all(true);
+        assert!(trace.is_enabled() && !trace.is_traced(1));
+    }
+
+    #[test]
+    fn input_trace_records_traced_drawins() {
+        let mut trace = InputTrace::default();
+        trace.set_traced(1, true);
+        trace.record(entry(Some(1), vec![Stage::Received("enter".into())]));
+        trace.record(entry(Some(2), vec![Stage::Received("enter".into())]));
+        trace.record(entry(None, vec![Stage::Received("enter".into())]));
+        assert_eq!(trace.entries().count(), 1);
+        // Events that didn't reach a drawin are only recorded when tracing
+        // everything.
+        trace.set_all(true);
+        trace.record(entry(Some(2), vec![Stage::Received("leave".into())]));
+        trace.record(entry(None, vec![Stage::Received("leave".into())]));
+        assert_eq!(trace.entries().count(), 3);
+        assert_eq!(trace.entries_of(2).count(), 1);
+        for i in 0..CAPACITY {
+            trace.record(entry(Some(1), vec![Stage::Received(format!("motion {}", i))]));
+        }
+        assert_eq!(trace.entries().count(), CAPACITY);
+        let oldest = trace.entries().next().unwrap();
+        assert_eq!(oldest.stages, vec![Stage::Received("motion 0".into())]);
+    }
+
+    #[test]
+    fn input_trace_dispositions() {
+        let clicked = entry(
+            Some(1),
+            vec![
+                Stage::Received("button 272 pressed".into()),
+                Stage::Focused,
+                Stage::Translated { x: 3.0, y: 4.5 },
+                Stage::ButtonMapped { button: 1 },
+                Stage::Dispatched {
+                    signal: "button::press",
+                    handlers: 2
+                },
+                Stage::HandlerErrored("boom".into()),
+            ]
+        );
+        assert_eq!(
+            describe(&clicked),
+            vec![
+                "received button 272 pressed",
+                "focused",
+                "translated 3,4.5",
+                "button 1",
+                "dispatched button::press to 2 handlers",
+                "handler-errored boom",
+            ]
+        );
+        let dropped = entry(
+            Some(1),
+            vec![
+                Stage::Received("button 277 pressed".into()),
+                Stage::Focused,
+                Stage::Translated { x: 0.0, y: 0.0 },
+                Stage::ButtonUnmapped { code: 277 },
+            ]
+        );
+        assert_eq!(describe(&dropped)[3], "button-unmapped 277");
+        let missed = entry(
+            None,
+            vec![
+                Stage::Received("enter".into()),
+                Stage::SurfaceMismatched { surface: 12 },
+            ]
+        );
+        assert_eq!(describe(&missed)[1], "surface-mismatched 12");
+        let entered = entry(
+            Some(1),
+            vec![
+                Stage::Received("enter".into()),
+                Stage::SurfaceMatched { surface: 13 },
+            ]
+        );
+        assert_eq!(describe(&entered)[1], "surface-matched 13");
+        let nowhere = entry(None, vec![Stage::Received("motion".into()), Stage::NoFocus]);
+        assert_eq!(describe(&nowhere)[1], "no-focus");
+    }
+}
@@ -0,0 +1,445 @@
+//! The properties a drawin accepts, in the order they are applied in its
+//! constructor.
+
+use crate::common::{
+    color_profile::Profile,
+    schema::{Key, Kind, Phase, Schema}
+};
+use crate::objects::drawable::{ContentFit, PixelFormat};
+
+use super::{layer::Layer, migration::Policy, window_type::WindowType};
+
+pub const DRAWIN_SCHEMA: Schema = Schema {
+    class: "drawin",
+    keys: &[
+        Key {
+            name: "exclusive_edge_owner",
+            kind: Kind::BooleanOr(&["replace"]),
+            phase: Phase::Backend
+        },
+        Key {
+            name: "trace_input",
+            kind: Kind::Boolean,
+            phase: Phase::Backend
+        },
+        // Before the geometry, so the saved content is restored into the
+        // first surface.
+        Key {
+            name: "persist_content",
+            kind: Kind::Boolean,
+            phase: Phase::Backend
+        },
+        // Before the geometry, so the first surface is placed at the bottom.
+        Key {
+            name: "osk",
+            kind: Kind::Boolean,
+            phase: Phase::Backend
+        },
+        Key {
+            name: "osk_auto",
+            kind: Kind::Boolean,
+            phase: Phase::Backend
+        },
+        Key {
+            name: "migration_policy",
+            kind: Kind::OneOf(Policy::NAMES),
+            phase: Phase::Backend
+        },
+        // Before the geometry, so `x` and `y` are the distance from the
+        // anchored edges.
+        Key {
+            name: "anchor",
+            kind: Kind::Strings,
+            phase: Phase::Backend
+        },
+        // Before the geometry, so it's clamped too.
+        Key {
+            name: "clamp_to_output",
+            kind: Kind::Boolean,
+            phase: Phase::Backend
+        },
+        // Before the geometry, so the first surface is allocated in it.
+        Key {
+            name: "format",
+            kind: Kind::OneOf(PixelFormat::NAMES),
+            phase: Phase::Backend
+        },
+        Key {
+            name: "x",
+            kind: Kind::Integer,
+            phase: Phase::Geometry
+        },
+        Key {
+            name: "y",
+            kind: Kind::Integer,
+            phase: Phase::Geometry
+        },
+        Key {
+            name: "width",
+            kind: Kind::Integer,
+            phase: Phase::Geometry
+        },
+        Key {
+            name: "height",
+            kind: Kind::Integer,
+            phase: Phase::Geometry
+        },
+        // After the rest of the geometry, so a drawin placed elsewhere is
+        // moved onto the screen.
+        Key {
+            name: "screen",
+            kind: Kind::Object("screen"),
+            phase: Phase::Geometry
+        },
+        // After the rest of the geometry, which it goes back to when it's
+        // turned off.
+        Key {
+            name: "fullscreen",
+            kind: Kind::Boolean,
+            phase: Phase::Geometry
+        },
+        // Before `ontop` and `layer`, which choose another layer.
+        Key {
+            name: "type",
+            kind: Kind::OneOf(WindowType::NAMES),
+            phase: Phase::Appearance
+        },
+        Key {
+            name: "ontop",
+            kind: Kind::Boolean,
+            phase: Phase::Appearance
+        },
+        // After `ontop`, which it overrides.
+        Key {
+            name: "layer",
+            kind: Kind::OneOf(Layer::NAMES),
+            phase: Phase::Appearance
+        },
+        Key {
+            name: "cursor",
+            kind: Kind::String,
+            phase: Phase::Appearance
+        },
+        Key {
+            name: "content_fit",
+            kind: Kind::OneOf(ContentFit::NAMES),
+            phase: Phase::Appearance
+        },
+        Key {
+            name: "letterbox_color",
+            kind: Kind::String,
+            phase: Phase::Appearance
+        },
+        Key {
+            name: "input_from_alpha",
+            kind: Kind::Boolean,
+            phase: Phase::Appearance
+        },
+        Key {
+            name: "alpha_threshold",
+            kind: Kind::Integer,
+            phase: Phase::Appearance
+        },
+        Key {
+            name: "input_scan_frames",
+            kind: Kind::Integer,
+            phase: Phase::Appearance
+        },
+        Key {
+            name: "input_passthrough",
+            kind: Kind::Boolean,
+            phase: Phase::Appearance
+        },
+        Key {
+            name: "shape_bounding",
+            kind: Kind::Shape,
+            phase: Phase::Appearance
+        },
+        Key {
+            name: "effects",
+            kind: Kind::Strings,
+            phase: Phase::Appearance
+        },
+        Key {
+            name: "opacity",
+            kind: Kind::Number,
+            phase: Phase::Appearance
+        },
+        Key {
+            name: "color_profile",
+            kind: Kind::OneOf(Profile::NAMES),
+            phase: Phase::Appearance
+        },
+        // After the geometry, so the drawin is exported where it is.
+        Key {
+            name: "accessible",
+            kind: Kind::Table,
+            phase: Phase::Appearance
+        },
+        Key {
+            name: "on_memory_pressure",
+            kind: Kind::Function,
+            phase: Phase::Appearance
+        },
+        // After the geometry, which makes the output the drawin is placed on
+        // its preferred output.
+        Key {
+            name: "preferred_output",
+            kind: Kind::String,
+            phase: Phase::Appearance
+        },
+        Key {
+            name: "visible",
+            kind: Kind::Boolean,
+            phase: Phase::Visibility
+        },
+        // After visible, so it's only asked for once the drawin is shown.
+        Key {
+            name: "keyboard_focus",
+            kind: Kind::Boolean,
+            phase: Phase::Visibility
+        }
+    ]
+};
+
+#[cfg(test)]
+mod test {
+    use rlua::{self, FromLua, Lua, Table, Value};
+
+    use super::DRAWIN_SCHEMA;
+
+    fn check<'lua, T: FromLua<'lua>>(lua: rlua::Context<'lua>, key: &str, value: &str) -> rlua::Result<T> {
+        DRAWIN_SCHEMA.check(lua, key, lua.load(value).eval()?)
+    }
+
+    fn check_error(lua: rlua::Context, key: &str, value: &str) -> String {
+        let value = lua.load(value).eval::<Value>().unwrap();
+        match DRAWIN_SCHEMA.check::<Value>(lua, key, value) {
+            Err(rlua::Error::RuntimeError(err)) => err,
+            Err(err) => panic!("unexpected error for {}: {}", key, err),
+            Ok(_) => panic!("{} accepted a bad value", key)
+        }
+    }
+
+    #[test]
+    fn drawin_bad_values() {
+        let bad = [
+            ("x", "'abc'", r#"drawin.x: expected an integer, got string "abc""#),
+            ("x", "10.5", "drawin.x: expected an integer, got number 10.5"),
+            (
+                "x",
+                "2^63",
+                "drawin.x: expected an integer, got number 9223372036854776000"
+            ),
+            ("y", "true", "drawin.y: expected an integer, got boolean true"),
+            ("y", "nil", "drawin.y: expected an integer, got nil"),
+            ("width", "{}", "drawin.width: expected an integer, got table"),
+            (
+                "width",
+                "'1e'",
+                r#"drawin.width: expected an integer, got string "1e""#
+            ),
+            (
+                "height",
+                "print",
+                "drawin.height: expected an integer, got function"
+            ),
+            (
+                "ontop",
+                "'yes'",
+                r#"drawin.ontop: expected a boolean, got string "yes""#
+            ),
+            ("cursor", "5", "drawin.cursor: expected a string, got number 5"),
+            ("visible", "2", "drawin.visible: expected a boolean, got number 2"),
+            (
+                "visible",
+                "'true'",
+                r#"drawin.visible: expected a boolean, got string "true""#
+            ),
+            (
+                "exclusive_edge_owner",
+                "'always'",
+                r#"drawin.exclusive_edge_owner: expected a boolean or "replace", got string "always""#
+            ),
+            (
+                "content_fit",
+                "'fill'",
+                r#"drawin.content_fit: expected one of "stretch", "letterbox", "crop", "none", got string "fill""#
+            ),
+            (
+                "content_fit",
+                "true",
+                r#"drawin.content_fit: expected one of "stretch", "letterbox", "crop", "none", got boolean true"#
+            ),
+            (
+                "format",
+                "'a8'",
+                r#"drawin.format: expected one of "argb32", "rgb24", got string "a8""#
+            ),
+            (
+                "color_profile",
+                "'pq'",
+                r#"drawin.color_profile: expected one of "srgb", got string "pq""#
+            ),
+            (
+                "screen",
+                "'HDMI-1'",
+                r#"drawin.screen: expected a screen or its index, got string "HDMI-1""#
+            ),
+            (
+                "screen",
+                "1.5",
+                r#"drawin.screen: expected a screen or its index, got number 1.5"#
+            ),
+            (
+                "on_memory_pressure",
+                "'free'",
+                r#"drawin.on_memory_pressure: expected a function, got string "free""#
+            ),
+            (
+                "letterbox_color",
+                "0",
+                "drawin.letterbox_color: expected a string, got number 0"
+            ),
+            (
+                "input_from_alpha",
+                "'yes'",
+                r#"drawin.input_from_alpha: expected a boolean, got string "yes""#
+            ),
+            (
+                "shape_bounding",
+                "true",
+                "drawin.shape_bounding: expected a cairo surface or a list of rectangles, got boolean true"
+            ),
+            (
+                "alpha_threshold",
+                "0.5",
+                "drawin.alpha_threshold: expected an integer, got number 0.5"
+            ),
+            (
+                "trace_input",
+                "'on'",
+                r#"drawin.trace_input: expected a boolean, got string "on""#
+            ),
+            (
+                "migration_policy",
+                "'never'",
+                r#"drawin.migration_policy: expected one of "follow", "sticky", "manual", got string "never""#
+            ),
+            (
+                "persist_content",
+                "'yes'",
+                r#"drawin.persist_content: expected a boolean, got string "yes""#
+            ),
+            (
+                "preferred_output",
+                "1",
+                "drawin.preferred_output: expected a string, got number 1"
+            ),
+            (
+                "osk",
+                "'on'",
+                r#"drawin.osk: expected a boolean, got string "on""#
+            ),
+            ("osk_auto", "{}", "drawin.osk_auto: expected a boolean, got table"),
+            (
+                "effects",
+                "'dim:0.3'",
+                r#"drawin.effects: expected a list of strings, got string "dim:0.3""#
+            ),
+            (
+                "effects",
+                "{ 'dim:0.3', 5 }",
+                "drawin.effects: expected a list of strings, got table"
+            ),
+            (
+                "accessible",
+                "'Main bar'",
+                r#"drawin.accessible: expected a table, got string "Main bar""#
+            ),
+            ("id", "1", r#"drawin: unknown property "id""#)
+        ];
+        Lua::new().context(|lua| {
+            for (key, value, expected) in bad.iter() {
+                assert_eq!(check_error(lua, key, value), *expected);
+            }
+        })
+    }
+
+    #[test]
+    fn drawin_coercion() -> rlua::Result<()> {
+        Lua::new().context(|lua| {
+            assert_eq!(check::<i64>(lua, "x", "' 10 '")?, 10);
+            assert_eq!(check::<i64>(lua, "width", "20.0")?, 20);
+            assert_eq!(check::<i64>(lua, "height", "'30.0'")?, 30);
+            assert!(check::<bool>(lua, "visible", "1")?);
+            assert!(!check::<bool>(lua, "ontop", "0")?);
+            assert!(check::<bool>(lua, "exclusive_edge_owner", "true")?);
+            assert_eq!(
+                check::<String>(lua, "exclusive_edge_owner", "'replace'")?,
+                "replace"
+            );
+            assert_eq!(check::<String>(lua, "content_fit", "'letterbox'")?, "letterbox");
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawin_unknown_arguments() -> rlua::Result<()> {
+        Lua::new().context(|lua| {
+            let args: Table = lua.load("{ x = 1, bg = '#000', [1] = 'positional' }").eval()?;
+            let ordered = DRAWIN_SCHEMA.order(lua, args.clone())?;
+            assert_eq!(ordered.len(), 1);
+            assert_eq!(ordered[0].0, "x");
+            lua.load("awesome = { strict_arguments = true }").exec()?;
+            match DRAWIN_SCHEMA.order(lua, args) {
+                Err(rlua::Error::RuntimeError(err)) => assert_eq!(err, r#"drawin: unknown property "bg""#),
+                _ => panic!("unknown argument accepted in strict mode")
+            }
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drawin_argument_order() -> rlua::Result<()> {
+        // Lua doesn't define the iteration order of tables, so build them
+        // with the keys inserted in different orders.
+        let insertions = [
+            "visible, x, y, width, height, ontop, cursor, exclusive_edge_owner",
+            "exclusive_edge_owner, cursor, ontop, height, width, y, x, visible",
+            "width, visible, exclusive_edge_owner, x, cursor, height, ontop, y",
+            "cursor, visible, ontop, y, exclusive_edge_owner, height, x, width"
+        ];
+        let expected = [
+            "exclusive_edge_owner",
+            "x",
+            "y",
+            "width",
+            "height",
+            "ontop",
+            "cursor",
+            "visible"
+        ];
+        Lua::new().context(|lua| {
+            for insertion in insertions.iter() {
+                let args = lua.create_table()?;
+                for key in insertion.split(", ") {
+                    args.set(key, true)?;
+                }
+                let ordered: Vec<&str> = DRAWIN_SCHEMA
+                    .order(lua, args)?
+                    .into_iter()
+                    .map(|(key, _)| key)
+                    .collect();
+                assert_eq!(ordered, expected);
+            }
+            // Visibility comes after the geometry even when only some of it
+            // is given.
+            let args: Table = lua.load("{ visible = true, height = 20 }").eval()?;
+            let ordered = DRAWIN_SCHEMA.order(lua, args)?;
+            assert_eq!(ordered[0].0, "height");
+            assert_eq!(ordered[1].0, "visible");
+            Ok(())
+        })
+    }
+}
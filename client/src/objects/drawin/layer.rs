@@ -0,0 +1,84 @@
+//! The layer shell layer a drawin is shown on, set with `drawin.layer` or,
+//! as in awesome, `drawin.ontop`.
+//!
+//! Windows are between the bottom and the top layers, so a drawin on the
+//! top layer, which is where drawins are by default, is above them. Only
+//! the overlay layer is above fullscreen windows, which is what `ontop`
+//! asks for.
+
+use crate::wayland_obj;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Layer {
+    Background,
+    Bottom,
+    Top,
+    Overlay
+}
+
+impl Default for Layer {
+    fn default() -> Self {
+        Layer::Top
+    }
+}
+
+impl Layer {
+    pub const NAMES: &'static [&'static str] = &["background", "bottom", "top", "overlay"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "background" => Some(Layer::Background),
+            "bottom" => Some(Layer::Bottom),
+            "top" => Some(Layer::Top),
+            "overlay" => Some(Layer::Overlay),
+            _ => None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Layer::Background => "background",
+            Layer::Bottom => "bottom",
+            Layer::Top => "top",
+            Layer::Overlay => "overlay"
+        }
+    }
+
+    /// The layer `ontop` chooses.
+    pub fn ontop(ontop: bool) -> Self {
+        if ontop {
+            Layer::Overlay
+        } else {
+            Layer::Top
+        }
+    }
+
+    pub fn is_ontop(self) -> bool {
+        self == Layer::Overlay
+    }
+
+    pub fn to_wayland(self) -> wayland_obj::Layer {
+        match self {
+            Layer::Background => wayland_obj::Layer::Background,
+            Layer::Bottom => wayland_obj::Layer::Bottom,
+            Layer::Top => wayland_obj::Layer::Top,
+            Layer::Overlay => wayland_obj::Layer::Overlay
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn layer_names() {
+        for name in Layer::NAMES {
+            assert_eq!(Layer::from_name(name).map(Layer::name), Some(*name));
+        }
+        assert_eq!(Layer::from_name("ontop"), None);
+        assert_eq!(Layer::default(), Layer::ontop(false));
+        assert!(Layer::ontop(true).is_ontop());
+        assert!(!Layer::Bottom.is_ontop());
+    }
+}
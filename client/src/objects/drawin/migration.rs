@@ -0,0 +1,382 @@
+//! What happens to a drawin when its output goes away and comes back, e.g.
+//! when a laptop is undocked and docked again.
+//!
+//! Every drawin remembers the output it was placed on by Lua, which is
+//! where it prefers to be. Its policy decides whether it goes back there
+//! when that output reappears.
+
+use crate::area::{Area, Origin};
+
+/// How a drawin follows its preferred output.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Policy {
+    /// Return to the preferred output whenever it's connected.
+    Follow,
+    /// Stay on the current output, which becomes the preferred one.
+    Sticky,
+    /// Never move, only tell Lua when outputs are lost or available.
+    Manual
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy::Follow
+    }
+}
+
+impl Policy {
+    pub const NAMES: &'static [&'static str] = &["follow", "sticky", "manual"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "follow" => Some(Policy::Follow),
+            "sticky" => Some(Policy::Sticky),
+            "manual" => Some(Policy::Manual),
+            _ => None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Policy::Follow => "follow",
+            Policy::Sticky => "sticky",
+            Policy::Manual => "manual"
+        }
+    }
+}
+
+/// An output as it's recognized across reconnects.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct OutputId {
+    pub name: String,
+    pub make: String,
+    pub model: String
+}
+
+impl OutputId {
+    /// Whether the output is the same monitor, which some docks give a new
+    /// name every time they are connected.
+    fn same_monitor(&self, other: &OutputId) -> bool {
+        !self.make.is_empty() && self.make == other.make && self.model == other.model
+    }
+
+    /// Finds the output in `outputs`, by name or else by make and model.
+    fn find<'a>(&self, outputs: &'a [OutputId]) -> Option<&'a OutputId> {
+        outputs
+            .iter()
+            .find(|output| output.name == self.name)
+            .or_else(|| outputs.iter().find(|output| self.same_monitor(output)))
+    }
+}
+
+/// What to do with a drawin after the outputs changed.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Action {
+    /// Move the drawin to the output.
+    Move(OutputId),
+    /// The output the drawin is on was disconnected.
+    Lost(OutputId),
+    /// The preferred output of the drawin was connected.
+    Available(OutputId)
+}
+
+#[derive(Debug, Default)]
+pub struct Migration {
+    pub policy: Policy,
+    preferred: Option<OutputId>,
+    current: Option<OutputId>,
+    /// Whether `current` was connected when the outputs last changed.
+    current_connected: bool,
+    /// Whether `preferred` was connected when the outputs last changed.
+    preferred_connected: bool
+}
+
+impl Migration {
+    pub fn preferred(&self) -> Option<&OutputId> {
+        self.preferred.as_ref()
+    }
+
+    pub fn set_preferred(&mut self, preferred: Option<OutputId>) {
+        self.preferred_connected = false;
+        self.preferred = preferred;
+    }
+
+    /// Called when Lua placed the drawin on `output`, which becomes its
+    /// preferred output.
+    pub fn placed(&mut self, output: Option<OutputId>) {
+        if let Some(output) = output.as_ref() {
+            self.preferred = Some(output.clone());
+            self.preferred_connected = true;
+        }
+        self.current_connected = output.is_some();
+        self.current = output;
+    }
+
+    /// Decides what happens to the drawin now that `outputs` are connected.
+    ///
+    /// The drawin is assumed to be moved as told, so calling this again
+    /// with the same outputs does nothing.
+    pub fn outputs_changed(&mut self, outputs: &[OutputId]) -> Vec<Action> {
+        let on = self
+            .current
+            .as_ref()
+            .and_then(|current| current.find(outputs))
+            .cloned();
+        let preferred = self
+            .preferred
+            .as_ref()
+            .and_then(|preferred| preferred.find(outputs))
+            .cloned();
+        let was_placed = self.current.is_some();
+        let mut actions = Vec::new();
+        match self.policy {
+            Policy::Follow => match (preferred.as_ref(), on.as_ref()) {
+                (Some(preferred), Some(on)) if preferred == on => {},
+                (Some(preferred), _) => actions.push(Action::Move(preferred.clone())),
+                (None, Some(_)) => {},
+                (None, None) if was_placed => {
+                    if let Some(fallback) = outputs.first() {
+                        actions.push(Action::Move(fallback.clone()));
+                    }
+                },
+                (None, None) => {}
+            },
+            Policy::Sticky => {
+                if on.is_none() && was_placed {
+                    if let Some(fallback) = outputs.first() {
+                        actions.push(Action::Move(fallback.clone()));
+                    }
+                }
+            },
+            Policy::Manual => {
+                if on.is_none() && self.current_connected {
+                    actions.extend(self.current.clone().map(Action::Lost));
+                }
+                if let Some(preferred) = preferred.as_ref() {
+                    if !self.preferred_connected && on.as_ref() != Some(preferred) {
+                        actions.push(Action::Available(preferred.clone()));
+                    }
+                }
+            }
+        }
+        // Outputs that were found by make and model are known by their new
+        // name from now on.
+        let mut current = on;
+        for action in &actions {
+            if let Action::Move(to) = action {
+                current = Some(to.clone());
+            }
+        }
+        if current.is_some() {
+            self.current = current;
+            self.current_connected = true;
+        } else {
+            self.current_connected = false;
+        }
+        if self.policy == Policy::Sticky && self.current_connected {
+            self.preferred = self.current.clone();
+        } else if preferred.is_some() {
+            self.preferred = preferred;
+        }
+        self.preferred_connected = self
+            .preferred
+            .as_ref()
+            .map_or(false, |preferred| preferred.find(outputs).is_some());
+        actions
+    }
+}
+
+/// Where a drawin at `geometry` on an output at `from` goes on an output at
+/// `to`.
+///
+/// It keeps its place relative to the output, sticking to the right and
+/// bottom edges if it was on them. A drawin as wide or as high as the old
+/// output is stretched to the new one, like a bar.
+pub fn relocate(geometry: Area, from: Area, to: Area) -> Area {
+    let mut size = geometry.size;
+    if size.width == from.size.width {
+        size.width = to.size.width;
+    }
+    if size.height == from.size.height {
+        size.height = to.size.height;
+    }
+    let margin = geometry.margin_within(from);
+    let (offset_x, offset_y) = (margin.left, margin.top);
+    let right = margin.right == 0;
+    let bottom = margin.bottom == 0;
+    let x = if right && offset_x > 0 {
+        to.origin.x + to.size.width as i32 - size.width as i32
+    } else {
+        to.origin.x + offset_x
+    };
+    let y = if bottom && offset_y > 0 {
+        to.origin.y + to.size.height as i32 - size.height as i32
+    } else {
+        to.origin.y + offset_y
+    };
+    Area {
+        origin: Origin { x, y },
+        size
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::area::Size;
+
+    fn output(name: &str, model: &str) -> OutputId {
+        OutputId {
+            name: name.into(),
+            make: "Dell".into(),
+            model: model.into()
+        }
+    }
+
+    fn laptop() -> OutputId {
+        OutputId {
+            name: "eDP-1".into(),
+            make: "BOE".into(),
+            model: "0x0747".into()
+        }
+    }
+
+    /// A bar placed on the external monitor of a dock, then undocked,
+    /// docked again and left alone.
+    fn dock_cycle(policy: Policy, redocked: OutputId) -> Vec<Vec<Action>> {
+        let external = output("DP-1", "U2720Q");
+        let mut migration = Migration::default();
+        migration.policy = policy;
+        migration.placed(Some(external.clone()));
+        let docked = vec![laptop(), external];
+        let undocked = vec![laptop()];
+        let redocked = vec![laptop(), redocked];
+        vec![
+            migration.outputs_changed(&docked),
+            migration.outputs_changed(&undocked),
+            migration.outputs_changed(&undocked),
+            migration.outputs_changed(&redocked),
+            migration.outputs_changed(&redocked),
+        ]
+    }
+
+    #[test]
+    fn migration_follow() {
+        let external = output("DP-1", "U2720Q");
+        assert_eq!(
+            dock_cycle(Policy::Follow, external.clone()),
+            vec![
+                vec![],
+                vec![Action::Move(laptop())],
+                vec![],
+                vec![Action::Move(external)],
+                vec![],
+            ]
+        );
+        // Some docks rename the output on every reconnect.
+        let renamed = output("DP-5", "U2720Q");
+        assert_eq!(
+            dock_cycle(Policy::Follow, renamed.clone())[3],
+            vec![Action::Move(renamed)]
+        );
+    }
+
+    #[test]
+    fn migration_sticky() {
+        let external = output("DP-1", "U2720Q");
+        let mut migration = Migration::default();
+        migration.policy = Policy::Sticky;
+        assert_eq!(
+            dock_cycle(Policy::Sticky, external.clone()),
+            vec![vec![], vec![Action::Move(laptop())], vec![], vec![], vec![]]
+        );
+        migration.placed(Some(external));
+        migration.outputs_changed(&[laptop()]);
+        assert_eq!(migration.preferred(), Some(&laptop()));
+    }
+
+    #[test]
+    fn migration_manual() {
+        let external = output("DP-1", "U2720Q");
+        assert_eq!(
+            dock_cycle(Policy::Manual, external.clone()),
+            vec![
+                vec![],
+                vec![Action::Lost(external.clone())],
+                vec![],
+                vec![],
+                vec![],
+            ]
+        );
+        // Lua moved the bar to the laptop when it was lost, so the external
+        // monitor is only available once it's back.
+        let mut migration = Migration::default();
+        migration.policy = Policy::Manual;
+        migration.placed(Some(external.clone()));
+        assert_eq!(
+            migration.outputs_changed(&[laptop()]),
+            vec![Action::Lost(external.clone())]
+        );
+        migration.placed(Some(laptop()));
+        migration.set_preferred(Some(external.clone()));
+        assert_eq!(
+            migration.outputs_changed(&[laptop(), external.clone()]),
+            vec![Action::Available(external.clone())]
+        );
+        assert_eq!(migration.outputs_changed(&[laptop(), external]), vec![]);
+    }
+
+    #[test]
+    fn migration_policy_change() {
+        let external = output("DP-1", "U2720Q");
+        let mut migration = Migration::default();
+        migration.policy = Policy::Manual;
+        migration.placed(Some(external.clone()));
+        migration.outputs_changed(&[laptop()]);
+        migration.placed(Some(laptop()));
+        migration.set_preferred(Some(external.clone()));
+        // The new policy applies on the next hotplug.
+        migration.policy = Policy::Follow;
+        assert_eq!(migration.outputs_changed(&[laptop()]), vec![]);
+        assert_eq!(
+            migration.outputs_changed(&[laptop(), external.clone()]),
+            vec![Action::Move(external)]
+        );
+    }
+
+    #[test]
+    fn migration_unplaced() {
+        let mut migration = Migration::default();
+        assert_eq!(migration.outputs_changed(&[laptop()]), vec![]);
+        assert_eq!(migration.outputs_changed(&[]), vec![]);
+    }
+
+    #[test]
+    fn migration_relocate() {
+        let area = |x, y, width, height| Area {
+            origin: Origin { x, y },
+            size: Size { width, height }
+        };
+        let laptop = area(0, 0, 1920, 1080);
+        let external = area(1920, 0, 3840, 2160);
+        // A bar at the top is stretched.
+        assert_eq!(
+            relocate(area(0, 0, 1920, 24), laptop, external),
+            area(1920, 0, 3840, 24)
+        );
+        // A bar at the bottom stays at the bottom.
+        assert_eq!(
+            relocate(area(0, 1056, 1920, 24), laptop, external),
+            area(1920, 2136, 3840, 24)
+        );
+        // A popup keeps its offset, or its distance from the right edge.
+        assert_eq!(
+            relocate(area(100, 50, 300, 200), laptop, external),
+            area(2020, 50, 300, 200)
+        );
+        assert_eq!(
+            relocate(area(1620, 50, 300, 200), laptop, external),
+            area(5460, 50, 300, 200)
+        );
+    }
+}
@@ -0,0 +1,291 @@
+//! Drawins that are on-screen keyboards.
+//!
+//! An on-screen keyboard sits at the bottom of its screen with windows
+//! kept above it, and types into the window that has the keyboard focus
+//! with a virtual keyboard, so it must never take the focus itself.
+//!
+//! The virtual keyboard needs a keymap that has every character typed with
+//! it. Rather than guessing one up front, keysyms are given a keycode of
+//! their own the first time they are typed and the keymap is sent again.
+
+use xkbcommon::xkb::{self, Keycode, Keysym};
+
+use crate::area::{Area, Margin};
+
+/// The keycode of the first keysym in the keymap. XKB keycodes are the
+/// evdev codes plus 8, and evdev code 0 is reserved.
+const FIRST_KEYCODE: Keycode = 9;
+
+/// The last keycode X11 clients can see.
+const LAST_KEYCODE: Keycode = 255;
+
+/// Something to do with the virtual keyboard.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Action {
+    /// Send this keymap, in the XKB text format, before any further keys.
+    Keymap(String),
+    Key {
+        keycode: Keycode,
+        pressed: bool
+    }
+}
+
+/// The keymap of the virtual keyboard, which grows as keysyms are typed.
+#[derive(Debug, Default)]
+pub struct OskKeymap {
+    /// The keysyms by keycode, from `FIRST_KEYCODE` on.
+    keysyms: Vec<Keysym>,
+    /// Whether the keymap with `keysyms` was sent.
+    sent: bool
+}
+
+impl OskKeymap {
+    /// Presses or releases the key of `keysym`, adding it to the keymap
+    /// first if it isn't in it.
+    ///
+    /// Releasing a key that isn't in the keymap does nothing.
+    pub fn key(&mut self, keysym: Keysym, pressed: bool) -> Vec<Action> {
+        let mut actions = Vec::new();
+        let keycode = match self.keycode(keysym) {
+            Some(keycode) => keycode,
+            None if !pressed => return actions,
+            None => self.add(keysym)
+        };
+        if !self.sent {
+            self.sent = true;
+            actions.push(Action::Keymap(self.keymap()));
+        }
+        actions.push(Action::Key { keycode, pressed });
+        actions
+    }
+
+    /// Types `text` by pressing and releasing the key of each character.
+    pub fn text(&mut self, text: &str) -> Vec<Action> {
+        text.chars()
+            .map(char_keysym)
+            .flat_map(|keysym| {
+                let mut actions = self.key(keysym, true);
+                actions.extend(self.key(keysym, false));
+                actions
+            })
+            .collect()
+    }
+
+    /// Sends the keymap again with the next key, e.g. because sending it
+    /// failed.
+    pub fn resend(&mut self) {
+        self.sent = false;
+    }
+
+    fn keycode(&self, keysym: Keysym) -> Option<Keycode> {
+        self.keysyms
+            .iter()
+            .position(|&known| known == keysym)
+            .map(|index| FIRST_KEYCODE + index as Keycode)
+    }
+
+    /// Gives `keysym` a keycode, starting over with an empty keymap when
+    /// there are no keycodes left.
+    fn add(&mut self, keysym: Keysym) -> Keycode {
+        if FIRST_KEYCODE + self.keysyms.len() as Keycode > LAST_KEYCODE {
+            self.keysyms.clear();
+        }
+        self.keysyms.push(keysym);
+        self.sent = false;
+        FIRST_KEYCODE + self.keysyms.len() as Keycode - 1
+    }
+
+    /// The keymap in the XKB text format. Every key has one level, so no
+    /// modifiers are needed to type any of them.
+    fn keymap(&self) -> String {
+        let keys = || (FIRST_KEYCODE..).zip(self.keysyms.iter());
+        let mut keymap = String::from("xkb_keymap {\nxkb_keycodes \"osk\" {\n");
+        keymap.push_str(&format!("minimum = 8;\nmaximum = {};\n", LAST_KEYCODE));
+        for (keycode, _) in keys() {
+            keymap.push_str(&format!("<K{}> = {};\n", keycode, keycode));
+        }
+        keymap.push_str(
+            "};\nxkb_types \"osk\" {\ntype \"ONE_LEVEL\" {\nmodifiers = none;\n\
+             level_name[Level1] = \"Any\";\n};\n};\n\
+             xkb_compatibility \"osk\" {\n};\nxkb_symbols \"osk\" {\n"
+        );
+        for (keycode, &keysym) in keys() {
+            keymap.push_str(&format!(
+                "key <K{}> {{ [ {} ] }};\n",
+                keycode,
+                xkb::keysym_get_name(keysym)
+            ));
+        }
+        keymap.push_str("};\n};\n");
+        keymap
+    }
+}
+
+/// The keysym that types `c`.
+pub fn char_keysym(c: char) -> Keysym {
+    match c as u32 {
+        // Latin-1 keysyms are the code points.
+        code @ 0x20..=0x7e | code @ 0xa0..=0xff => code,
+        0x08 => xkb::keysyms::KEY_BackSpace,
+        0x09 => xkb::keysyms::KEY_Tab,
+        0x0a | 0x0d => xkb::keysyms::KEY_Return,
+        0x1b => xkb::keysyms::KEY_Escape,
+        code => 0x0100_0000 + code
+    }
+}
+
+/// Where an on-screen keyboard is on its screen, as a layer surface sees
+/// it: anchored to the bottom and both sides.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Placement {
+    pub margin: Margin,
+    /// The height windows are kept out of above the bottom margin.
+    pub exclusive_zone: i32
+}
+
+impl Placement {
+    pub fn new(geometry: Area, screen: Area) -> Self {
+        // Anchored to the bottom, the top margin isn't used.
+        let margin = Margin {
+            top: 0,
+            ..geometry.margin_within(screen)
+        };
+        Placement {
+            margin,
+            exclusive_zone: geometry.size.height as i32
+        }
+    }
+
+    /// How much of the bottom of the screen windows are kept out of. The
+    /// compositor adds the margin to the exclusive zone.
+    pub fn reserved(&self) -> i32 {
+        (self.exclusive_zone + self.margin.bottom).max(0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::area::{Origin, Size};
+
+    /// Types the actions into XKB, the way the compositor would, returning
+    /// what the focused window got.
+    fn typed(actions: &[Action]) -> String {
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let mut state = None;
+        let mut text = String::new();
+        for action in actions {
+            match action {
+                Action::Keymap(keymap) => {
+                    let keymap = xkb::Keymap::new_from_string(
+                        &context,
+                        keymap.clone(),
+                        xkb::KEYMAP_FORMAT_TEXT_V1,
+                        xkb::KEYMAP_COMPILE_NO_FLAGS
+                    )
+                    .expect("the keymap doesn't compile");
+                    state = Some(xkb::State::new(&keymap));
+                },
+                Action::Key { keycode, pressed } => {
+                    let state = state.as_mut().expect("key before the keymap");
+                    if *pressed {
+                        text.push_str(&state.key_get_utf8(*keycode));
+                    }
+                    let direction = if *pressed {
+                        xkb::KeyDirection::Down
+                    } else {
+                        xkb::KeyDirection::Up
+                    };
+                    state.update_key(*keycode, direction);
+                }
+            }
+        }
+        text
+    }
+
+    fn keymaps(actions: &[Action]) -> usize {
+        actions
+            .iter()
+            .filter(|action| match action {
+                Action::Keymap(_) => true,
+                _ => false
+            })
+            .count()
+    }
+
+    #[test]
+    fn osk_text_round_trip() {
+        let mut keymap = OskKeymap::default();
+        let hello = keymap.text("Grüße, ħello");
+        assert_eq!(typed(&hello), "Grüße, ħello");
+        // Each new character sends the keymap again, known ones don't.
+        assert_eq!(keymaps(&hello), 10);
+        let mut again = hello.clone();
+        again.extend(keymap.text("Ħeλλo 😀"));
+        assert_eq!(typed(&again), "Grüße, ħelloĦeλλo 😀");
+        assert_eq!(keymaps(&again), 10 + 3);
+        // Releasing a key that was never pressed has no keycode to release.
+        assert_eq!(keymap.key(xkb::keysyms::KEY_F1, false), vec![]);
+    }
+
+    #[test]
+    fn osk_keymap_full() {
+        let mut keymap = OskKeymap::default();
+        let alphabet: String = (0..300)
+            .map(|i| std::char::from_u32(0x4e00 + i).unwrap())
+            .collect();
+        let actions = keymap.text(&alphabet);
+        assert_eq!(typed(&actions), alphabet);
+        assert_eq!(keymap.keysyms.len(), 300 - 247);
+        assert_eq!(
+            keymap.key(char_keysym('\u{4e00}'), true)[1],
+            Action::Key {
+                keycode: FIRST_KEYCODE + 300 - 247,
+                pressed: true
+            }
+        );
+    }
+
+    #[test]
+    fn osk_exclusive_zone() {
+        let screen = Area {
+            origin: Origin { x: 1920, y: 0 },
+            size: Size {
+                width: 1280,
+                height: 720
+            }
+        };
+        let keyboard = Area {
+            origin: Origin { x: 1920, y: 480 },
+            size: Size {
+                width: 1280,
+                height: 240
+            }
+        };
+        let placement = Placement::new(keyboard, screen);
+        assert_eq!(placement.margin, Margin::default());
+        assert_eq!(placement.exclusive_zone, 240);
+        assert_eq!(placement.reserved(), 240);
+        // Raised above the bottom edge, windows stay above the keyboard.
+        let raised = Area {
+            origin: Origin { x: 2020, y: 400 },
+            ..keyboard
+        };
+        let placement = Placement::new(raised, screen);
+        assert_eq!(
+            (
+                placement.margin.left,
+                placement.margin.right,
+                placement.margin.bottom
+            ),
+            (100, -100, 80)
+        );
+        assert_eq!(placement.reserved(), 320);
+        // Half slid off the bottom, only what is shown is kept clear.
+        let sliding = Area {
+            origin: Origin { x: 1920, y: 600 },
+            ..keyboard
+        };
+        assert_eq!(Placement::new(sliding, screen).reserved(), 120);
+    }
+}
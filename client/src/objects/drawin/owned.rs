@@ -0,0 +1,434 @@
+//! Drawins another program owns through the control socket, see `control`.
+//!
+//! The program creates the drawin, sets its properties, shows its own
+//! buffers on it and is sent the input it gets. Lua can still move it, show
+//! and hide it and keep it in its own tables, but setting anything else
+//! fails, and `drawin:remove` and `drawin:import_shm` need `force = true`.
+//!
+//! When the program disconnects its drawins are kept for a grace period,
+//! in which another connection can adopt them with the token they were
+//! claimed with, e.g. the program after it restarted. The drawins nobody
+//! adopted are removed after that, with "request::remove" and the reason
+//! "disconnected".
+
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::io::RawFd;
+use std::time::Instant;
+
+use rlua::{self, AnyUserData, MultiValue, Table, Value};
+
+use crate::area::Size;
+use crate::common::{
+    object::{self, Object},
+    signal
+};
+use crate::control;
+use crate::json::Json;
+
+use super::import::{self, ImportError, Layout};
+use super::keys::DRAWIN_SCHEMA;
+use super::{find_drawin, Drawin, DrawinId, DrawinState, DRAWINS_HANDLE};
+
+/// What Lua may still set on a drawin it doesn't own.
+const LUA_PROPERTIES: &[&str] = &["x", "y", "visible"];
+
+/// The signals that are sent to the owner of a drawin.
+const INPUT_SIGNALS: &[&str] = &[
+    "mouse::enter",
+    "mouse::leave",
+    "mouse::move",
+    "button::press",
+    "button::release"
+];
+
+#[derive(Debug)]
+pub struct Owner {
+    /// The connection the drawin is owned through.
+    pub connection: u64,
+    /// What the program called itself, like "connection 3" if it didn't.
+    pub name: String,
+    /// Adopts the drawin once the connection was lost.
+    token: String,
+    /// When the drawin is removed, once the connection was lost.
+    expires: Option<Instant>
+}
+
+/// Why a connection can't use a drawin.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OwnerError {
+    /// There's no such drawin, or it was removed.
+    Unknown(usize),
+    /// Lua or another connection owns the drawin.
+    NotOwner(usize),
+    /// The token doesn't adopt the drawin, or it's still owned.
+    NotOrphaned(usize)
+}
+
+impl OwnerError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            OwnerError::Unknown(_) => "unknown_drawin",
+            OwnerError::NotOwner(_) => "not_owner",
+            OwnerError::NotOrphaned(_) => "not_orphaned"
+        }
+    }
+}
+
+impl fmt::Display for OwnerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OwnerError::Unknown(id) => write!(f, "there is no drawin {}", id),
+            OwnerError::NotOwner(id) => write!(f, "drawin {} is not owned by this connection", id),
+            OwnerError::NotOrphaned(id) => write!(f, "drawin {} can't be adopted with this token", id)
+        }
+    }
+}
+
+/// Creates a drawin with `properties` that `connection` owns, returning it
+/// and the token that adopts it.
+pub fn claim<'lua>(
+    lua: rlua::Context<'lua>,
+    connection: u64,
+    name: String,
+    properties: Table<'lua>
+) -> rlua::Result<(Drawin<'lua>, String)> {
+    let token =
+        token().map_err(|err| rlua::Error::RuntimeError(format!("could not make a token: {}", err)))?;
+    let mut drawin = Drawin::new(lua, properties)?;
+    drawin.state_mut()?.owner = Some(Owner {
+        connection,
+        name,
+        token: token.clone(),
+        expires: None
+    });
+    for &name in INPUT_SIGNALS {
+        let forward = lua.create_function(move |_, args: MultiValue| forward_input(name, args))?;
+        signal::connect_signal(lua, drawin.signals()?, name, forward)?;
+    }
+    Ok((drawin, token))
+}
+
+/// The drawin `id`, if `connection` owns it.
+pub fn owned<'lua>(
+    lua: rlua::Context<'lua>,
+    connection: u64,
+    id: usize
+) -> rlua::Result<Result<Drawin<'lua>, OwnerError>> {
+    let drawin = match find_drawin(lua, DrawinId(id))? {
+        Some(drawin) => drawin,
+        None => return Ok(Err(OwnerError::Unknown(id)))
+    };
+    let owned = match drawin.state()?.owner {
+        Some(ref owner) => owner.connection == connection && owner.expires.is_none(),
+        None => false
+    };
+    Ok(if owned {
+        Ok(drawin)
+    } else {
+        Err(OwnerError::NotOwner(id))
+    })
+}
+
+/// Makes `connection` the owner of the drawin `id`, which lost its owner,
+/// if `token` is the one it was claimed with.
+pub fn adopt<'lua>(
+    lua: rlua::Context<'lua>,
+    connection: u64,
+    name: String,
+    id: usize,
+    token: &str
+) -> rlua::Result<Result<Drawin<'lua>, OwnerError>> {
+    let mut drawin = match find_drawin(lua, DrawinId(id))? {
+        Some(drawin) => drawin,
+        None => return Ok(Err(OwnerError::Unknown(id)))
+    };
+    {
+        let mut state = drawin.state_mut()?;
+        match state.owner {
+            Some(ref mut owner) if owner.expires.is_some() && owner.token == token => {
+                owner.connection = connection;
+                owner.name = name;
+                owner.expires = None;
+            },
+            _ => return Ok(Err(OwnerError::NotOrphaned(id)))
+        }
+    }
+    Ok(Ok(drawin))
+}
+
+/// Sets a property like Lua would, but without the restrictions of Lua.
+pub fn set<'lua>(
+    lua: rlua::Context<'lua>,
+    drawin: Drawin<'lua>,
+    property: String,
+    value: Value<'lua>
+) -> rlua::Result<()> {
+    drawin.check_valid()?;
+    object::default_newindex(lua, (drawin, property, value)).map(|_| ())
+}
+
+pub fn get<'lua>(
+    lua: rlua::Context<'lua>,
+    drawin: Drawin<'lua>,
+    property: &str
+) -> rlua::Result<Value<'lua>> {
+    drawin.check_valid()?;
+    object::default_index(lua, (drawin, Value::String(lua.create_string(property)?)))
+}
+
+/// Imports the buffer in `fd` to the drawin, returning its id. Its owner
+/// is told every time it's released.
+pub fn import<'lua>(
+    lua: rlua::Context<'lua>,
+    drawin: Drawin<'lua>,
+    fd: RawFd,
+    size: Size,
+    stride: i64,
+    offset: i64
+) -> rlua::Result<Result<usize, ImportError>> {
+    let file_len = match nix::sys::stat::fstat(fd) {
+        Ok(stat) => stat.st_size,
+        Err(_) => {
+            return Ok(Err(ImportError::Invalid(
+                "the file descriptor is not an open file".into()
+            )))
+        },
+    };
+    let (width, height) = (i64::from(size.width), i64::from(size.height));
+    let layout = match Layout::new(i64::from(fd), width, height, stride, offset, file_len) {
+        Ok(layout) => layout,
+        Err(err) => return Ok(Err(err))
+    };
+    let DrawinId(drawin_id) = drawin.id()?;
+    let handle = match super::import_buffer(lua, drawin, layout)? {
+        Ok(handle) => handle,
+        Err(err) => return Ok(Err(err))
+    };
+    let id = handle.borrow::<import::ImportHandle>()?.id;
+    let released = lua.create_function(move |lua, (_, info): (AnyUserData, Table)| {
+        let destroyed = info.get::<_, bool>("destroyed")?;
+        send(lua, DrawinId(drawin_id), "release", |event| {
+            event.push(("buffer".into(), Json::Integer(id as i64)));
+            event.push(("destroyed".into(), Json::Bool(destroyed)));
+        })
+    })?;
+    import::on_release(lua, (handle, released))?;
+    Ok(Ok(id))
+}
+
+/// Shows the imported buffer `id` on the drawin.
+pub fn commit(lua: rlua::Context, mut drawin: Drawin, id: usize) -> rlua::Result<Result<(), ImportError>> {
+    match import::handle(lua, drawin.id()?, id)? {
+        Some(_) => drawin.commit_import(id),
+        None => Ok(Err(ImportError::Destroyed))
+    }
+}
+
+/// Destroys the imported buffer `id`, false if the drawin has no such
+/// buffer.
+pub fn destroy_buffer(lua: rlua::Context, drawin: &Drawin, id: usize) -> rlua::Result<bool> {
+    match import::handle(lua, drawin.id()?, id)? {
+        Some(handle) => import::destroy(lua, handle).map(|_| true),
+        None => Ok(false)
+    }
+}
+
+/// Removes a drawin its owner doesn't want anymore.
+pub fn release<'lua>(lua: rlua::Context<'lua>, mut drawin: Drawin<'lua>) -> rlua::Result<()> {
+    // It's already gone for the owner.
+    drawin.state_mut()?.owner = None;
+    Object::emit_signal(lua, &drawin, "request::remove", "released")?;
+    drawin.remove(lua)
+}
+
+/// Keeps the drawins of `connection`, which was lost, until `expires`.
+pub fn orphan(lua: rlua::Context, connection: u64, expires: Instant) -> rlua::Result<()> {
+    for mut drawin in lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)? {
+        let mut state = drawin.state_mut()?;
+        if let Some(ref mut owner) = state.owner {
+            if owner.connection == connection && owner.expires.is_none() {
+                owner.expires = Some(expires);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Removes the drawins whose owner was lost and wasn't replaced by `now`,
+/// returning their ids.
+pub fn expire(lua: rlua::Context, now: Instant) -> rlua::Result<Vec<usize>> {
+    let mut expired = Vec::new();
+    for mut drawin in lua.named_registry_value::<str, Vec<Drawin>>(DRAWINS_HANDLE)? {
+        let due = match drawin.state()?.owner {
+            Some(Owner {
+                expires: Some(expires),
+                ..
+            }) => expires <= now,
+            _ => false
+        };
+        if !due {
+            continue;
+        }
+        let DrawinId(id) = drawin.id()?;
+        drawin.state_mut()?.owner = None;
+        Object::emit_signal(lua, &drawin, "request::remove", "disconnected")?;
+        drawin.remove(lua)?;
+        expired.push(id);
+    }
+    Ok(expired)
+}
+
+/// `drawin.owner`, the name of the program that owns the drawin, or nil
+/// if Lua does.
+pub fn owner_name(state: &DrawinState) -> Option<String> {
+    state.owner.as_ref().map(|owner| owner.name.clone())
+}
+
+/// Raises an error if Lua can't set `property` on the drawin.
+pub fn check_lua_write(drawin: &Drawin, property: &str) -> rlua::Result<()> {
+    // Fields Lua keeps on the drawin itself are Lua's.
+    if DRAWIN_SCHEMA.key(property).is_none() || LUA_PROPERTIES.contains(&property) {
+        return Ok(());
+    }
+    let state = drawin.state()?;
+    match state.owner {
+        Some(ref owner) => Err(rlua::Error::RuntimeError(format!(
+            "drawin {} is owned by {:?}, Lua can only move it and show or hide it, not set {}",
+            state.id.0, owner.name, property
+        ))),
+        None => Ok(())
+    }
+}
+
+/// Raises an error if Lua does something to a drawin it doesn't own that
+/// needs `force = true` in `options`.
+pub fn check_force(drawin: &Drawin, options: Option<&Table>, what: &str) -> rlua::Result<()> {
+    let forced = match options {
+        Some(options) => options.get::<_, Option<bool>>("force")?.unwrap_or(false),
+        None => false
+    };
+    let state = drawin.state()?;
+    match state.owner {
+        Some(ref owner) if !forced => Err(rlua::Error::RuntimeError(format!(
+            "drawin {} is owned by {:?}, {} it with force = true",
+            state.id.0, owner.name, what
+        ))),
+        _ => Ok(())
+    }
+}
+
+/// Tells the owner where the drawin is now and whether it's shown.
+pub fn moved(state: &DrawinState) {
+    let owner = match state.owner {
+        Some(ref owner) if owner.expires.is_none() => owner,
+        _ => return
+    };
+    let geometry = state.geometry;
+    control::send(
+        owner.connection,
+        event(state.id, "geometry", |event| {
+            event.push(("x".into(), Json::Integer(geometry.origin.x.into())));
+            event.push(("y".into(), Json::Integer(geometry.origin.y.into())));
+            event.push(("width".into(), Json::Integer(geometry.size.width.into())));
+            event.push(("height".into(), Json::Integer(geometry.size.height.into())));
+            event.push(("visible".into(), Json::Bool(state.visible)));
+        })
+    );
+}
+
+/// Tells the owner the size the compositor gave the drawin's surface.
+pub fn configured(drawin: &Drawin, size: Size) -> rlua::Result<()> {
+    let state = drawin.state()?;
+    if let Some(Owner {
+        connection,
+        expires: None,
+        ..
+    }) = state.owner
+    {
+        control::send(
+            connection,
+            event(state.id, "configure", |event| {
+                event.push(("width".into(), Json::Integer(size.width.into())));
+                event.push(("height".into(), Json::Integer(size.height.into())));
+            })
+        );
+    }
+    Ok(())
+}
+
+/// Tells the owner the drawin was removed, it's Lua's after that.
+pub fn removed(state: &mut DrawinState) {
+    if let Some(owner) = state.owner.take() {
+        if owner.expires.is_none() {
+            control::send(owner.connection, event(state.id, "removed", |_| {}));
+        }
+    }
+}
+
+/// Sends an input signal the drawin got to its owner, with the arguments
+/// of the signal as `args`.
+fn forward_input(name: &str, args: MultiValue) -> rlua::Result<()> {
+    let mut args = args.into_vec().into_iter();
+    let drawin = match args.next() {
+        Some(Value::UserData(drawin)) => Drawin::cast(drawin)?,
+        _ => return Ok(())
+    };
+    let args = args
+        .map(|arg| Json::from_lua(arg).unwrap_or(Json::Null))
+        .collect::<Vec<_>>();
+    let state = drawin.state()?;
+    if let Some(Owner {
+        connection,
+        expires: None,
+        ..
+    }) = state.owner
+    {
+        control::send(
+            connection,
+            event(state.id, name, |event| {
+                event.push(("args".into(), Json::Array(args)))
+            })
+        );
+    }
+    Ok(())
+}
+
+/// Sends an event about the drawin `id` to its owner, if it has one.
+fn send<F>(lua: rlua::Context, id: DrawinId, name: &str, fields: F) -> rlua::Result<()>
+where
+    F: FnOnce(&mut Vec<(String, Json)>)
+{
+    if let Some(drawin) = find_drawin(lua, id)? {
+        if let Some(Owner {
+            connection,
+            expires: None,
+            ..
+        }) = drawin.state()?.owner
+        {
+            control::send(connection, event(id, name, fields));
+        }
+    }
+    Ok(())
+}
+
+/// An event like `{"event":"geometry","drawin":3,"x":0,...}`.
+fn event<F>(DrawinId(id): DrawinId, name: &str, fields: F) -> Json
+where
+    F: FnOnce(&mut Vec<(String, Json)>)
+{
+    let mut event = vec![
+        ("event".into(), Json::String(name.into())),
+        ("drawin".into(), Json::Integer(id as i64)),
+    ];
+    fields(&mut event);
+    Json::Object(event)
+}
+
+/// A random token, as 32 hex digits.
+fn token() -> std::io::Result<String> {
+    let mut bytes = [0; 16];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
@@ -0,0 +1,195 @@
+//! The space along the edges of its screen a drawin keeps windows out of,
+//! set with `drawin:struts{ top = 24 }`.
+//!
+//! A drawin is shown on a layer surface, which keeps windows out with an
+//! exclusive zone along the one edge it's anchored to, so a drawin keeps at
+//! most one edge clear. With struts on several edges it's the edge with the
+//! largest strut, and of equal ones the first of top, bottom, left and
+//! right. The other struts are kept, so `drawin:struts()` returns what Lua
+//! set, but reserve nothing.
+//!
+//! A strut is measured from the edge of the screen, as in awesome, so a bar
+//! 24 pixels high at the top sets `top = 24`. A drawin further from the
+//! edge than its strut keeps nothing clear.
+//!
+//! A drawin of type "dock" without struts keeps the edge it's flush against
+//! clear, see `docked`.
+
+use crate::area::{AnchorEdge, Area, Margin, Size};
+
+/// How a drawin keeping an edge clear is placed, as a layer surface sees it:
+/// anchored to the edge and both sides next to it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Strut {
+    pub edge: AnchorEdge,
+    /// The margins from the screen, without the one opposite the edge.
+    pub margin: Margin,
+    /// What's kept clear past the margin of the edge.
+    pub exclusive_zone: i32
+}
+
+impl Strut {
+    /// How a drawin at `geometry` on `screen` keeps the edge of the largest
+    /// of `struts` clear, if it does.
+    pub fn new(struts: Margin, geometry: Area, screen: Area) -> Option<Strut> {
+        let (edge, strut) = kept_edge(struts)?;
+        let mut margin = geometry.margin_within(screen);
+        // The compositor adds the margin of the edge to the exclusive zone.
+        let exclusive_zone = match edge {
+            AnchorEdge::Top => {
+                margin.bottom = 0;
+                strut - margin.top
+            },
+            AnchorEdge::Bottom => {
+                margin.top = 0;
+                strut - margin.bottom
+            },
+            AnchorEdge::Left => {
+                margin.right = 0;
+                strut - margin.left
+            },
+            AnchorEdge::Right => {
+                margin.left = 0;
+                strut - margin.right
+            }
+        };
+        if exclusive_zone <= 0 {
+            return None;
+        }
+        Some(Strut {
+            edge,
+            margin,
+            exclusive_zone
+        })
+    }
+
+    /// The space along the edges of the screen windows are kept out of.
+    pub fn reserved(&self) -> Margin {
+        let mut reserved = Margin::default();
+        match self.edge {
+            AnchorEdge::Top => reserved.top = self.exclusive_zone + self.margin.top,
+            AnchorEdge::Bottom => reserved.bottom = self.exclusive_zone + self.margin.bottom,
+            AnchorEdge::Left => reserved.left = self.exclusive_zone + self.margin.left,
+            AnchorEdge::Right => reserved.right = self.exclusive_zone + self.margin.right
+        }
+        reserved
+    }
+}
+
+/// The struts of a dock at `geometry`: its thickness along the edge of
+/// `screen` it's flush against, the top or bottom one if it's wider than
+/// high.
+pub fn docked(geometry: Area, screen: Area) -> Margin {
+    let margin = geometry.margin_within(screen);
+    let mut struts = Margin::default();
+    let Size { width, height } = geometry.size;
+    if width >= height {
+        if margin.top <= 0 {
+            struts.top = geometry.bottom() - screen.origin.y;
+        } else if margin.bottom <= 0 {
+            struts.bottom = screen.bottom() - geometry.origin.y;
+        }
+    } else if margin.left <= 0 {
+        struts.left = geometry.right() - screen.origin.x;
+    } else if margin.right <= 0 {
+        struts.right = screen.right() - geometry.origin.x;
+    }
+    struts
+}
+
+/// The edge of the largest strut, and the strut, if there is one.
+fn kept_edge(struts: Margin) -> Option<(AnchorEdge, i32)> {
+    let edges = [
+        (AnchorEdge::Top, struts.top),
+        (AnchorEdge::Bottom, struts.bottom),
+        (AnchorEdge::Left, struts.left),
+        (AnchorEdge::Right, struts.right)
+    ];
+    edges
+        .iter()
+        .cloned()
+        .filter(|&(_, strut)| strut > 0)
+        // The first of the largest.
+        .fold(None, |kept: Option<(AnchorEdge, i32)>, (edge, strut)| match kept {
+            Some((_, largest)) if largest >= strut => kept,
+            _ => Some((edge, strut))
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::area::Origin;
+
+    fn area(x: i32, y: i32, width: u32, height: u32) -> Area {
+        Area {
+            origin: Origin { x, y },
+            size: Size { width, height }
+        }
+    }
+
+    fn struts(top: i32, right: i32, bottom: i32, left: i32) -> Margin {
+        Margin {
+            top,
+            right,
+            bottom,
+            left
+        }
+    }
+
+    #[test]
+    fn struts_keep_one_edge() {
+        let screen = area(1920, 0, 1920, 1080);
+        // A bar across the top of the second screen.
+        let bar = area(1920, 0, 1920, 24);
+        let strut = Strut::new(struts(24, 0, 0, 0), bar, screen).unwrap();
+        assert_eq!(strut.edge, AnchorEdge::Top);
+        assert_eq!(strut.margin, struts(0, 0, 0, 0));
+        assert_eq!(strut.exclusive_zone, 24);
+        assert_eq!(strut.reserved(), struts(24, 0, 0, 0));
+        // Floating 4 pixels below the edge, with gaps on the sides.
+        let floating = area(1930, 4, 1900, 24);
+        let strut = Strut::new(struts(28, 0, 0, 0), floating, screen).unwrap();
+        assert_eq!(strut.margin, struts(4, 10, 0, 10));
+        assert_eq!(strut.exclusive_zone, 24);
+        assert_eq!(strut.reserved(), struts(28, 0, 0, 0));
+        // A dock on the right.
+        let dock = area(3790, 100, 50, 880);
+        let strut = Strut::new(struts(0, 50, 0, 0), dock, screen).unwrap();
+        assert_eq!(strut.edge, AnchorEdge::Right);
+        assert_eq!(strut.margin, struts(100, 0, 100, 0));
+        assert_eq!(strut.reserved(), struts(0, 50, 0, 0));
+        // Nothing to keep, or further from the edge than the strut.
+        assert_eq!(Strut::new(struts(0, 0, 0, 0), bar, screen), None);
+        assert_eq!(Strut::new(struts(-5, 0, 0, 0), bar, screen), None);
+        assert_eq!(
+            Strut::new(struts(24, 0, 0, 0), area(1920, 500, 100, 24), screen),
+            None
+        );
+    }
+
+    #[test]
+    fn struts_of_docks() {
+        let screen = area(1920, 0, 1920, 1080);
+        assert_eq!(docked(area(1920, 0, 1920, 24), screen), struts(24, 0, 0, 0));
+        assert_eq!(docked(area(1920, 1050, 1920, 30), screen), struts(0, 0, 30, 0));
+        assert_eq!(docked(area(3790, 100, 50, 880), screen), struts(0, 50, 0, 0));
+        assert_eq!(docked(area(1920, 0, 40, 1080), screen), struts(0, 0, 0, 40));
+        // Away from the edges it keeps nothing clear.
+        assert_eq!(docked(area(2000, 500, 400, 24), screen), Margin::default());
+    }
+
+    #[test]
+    fn struts_conflicting_edges() {
+        assert_eq!(kept_edge(struts(24, 0, 0, 0)), Some((AnchorEdge::Top, 24)));
+        // The largest strut wins.
+        assert_eq!(kept_edge(struts(24, 0, 0, 48)), Some((AnchorEdge::Left, 48)));
+        assert_eq!(kept_edge(struts(0, 30, 31, 0)), Some((AnchorEdge::Bottom, 31)));
+        // Of equal ones, top, bottom, left, then right.
+        assert_eq!(kept_edge(struts(24, 24, 24, 24)), Some((AnchorEdge::Top, 24)));
+        assert_eq!(kept_edge(struts(0, 24, 24, 24)), Some((AnchorEdge::Bottom, 24)));
+        assert_eq!(kept_edge(struts(0, 24, 0, 24)), Some((AnchorEdge::Left, 24)));
+        assert_eq!(kept_edge(struts(0, 24, 0, 0)), Some((AnchorEdge::Right, 24)));
+        assert_eq!(kept_edge(struts(0, -1, 0, 0)), None);
+    }
+}
@@ -0,0 +1,121 @@
+//! The kind of window a drawin is, set with `drawin.type` as in awesome,
+//! which decides the layer it's shown on.
+//!
+//! A desktop is on the background layer, under everything. Splashes and
+//! notifications are on the overlay layer, above fullscreen windows. The
+//! rest are on the top layer, above windows, and a dock keeps the edge it's
+//! along clear of them unless Lua set its struts, see `struts::docked`.
+//!
+//! `drawin.layer` and `drawin.ontop` set later choose another layer.
+
+use super::layer::Layer;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum WindowType {
+    Desktop,
+    Dock,
+    Splash,
+    Dialog,
+    Menu,
+    Toolbar,
+    Utility,
+    DropdownMenu,
+    PopupMenu,
+    Notification,
+    Combo,
+    Dnd,
+    Normal,
+    Tooltip
+}
+
+impl Default for WindowType {
+    fn default() -> Self {
+        WindowType::Normal
+    }
+}
+
+impl WindowType {
+    pub const NAMES: &'static [&'static str] = &[
+        "desktop",
+        "dock",
+        "splash",
+        "dialog",
+        "menu",
+        "toolbar",
+        "utility",
+        "dropdown_menu",
+        "popup_menu",
+        "notification",
+        "combo",
+        "dnd",
+        "normal",
+        "tooltip"
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "desktop" => Some(WindowType::Desktop),
+            "dock" => Some(WindowType::Dock),
+            "splash" => Some(WindowType::Splash),
+            "dialog" => Some(WindowType::Dialog),
+            "menu" => Some(WindowType::Menu),
+            "toolbar" => Some(WindowType::Toolbar),
+            "utility" => Some(WindowType::Utility),
+            "dropdown_menu" => Some(WindowType::DropdownMenu),
+            "popup_menu" => Some(WindowType::PopupMenu),
+            "notification" => Some(WindowType::Notification),
+            "combo" => Some(WindowType::Combo),
+            "dnd" => Some(WindowType::Dnd),
+            "normal" => Some(WindowType::Normal),
+            "tooltip" => Some(WindowType::Tooltip),
+            _ => None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WindowType::Desktop => "desktop",
+            WindowType::Dock => "dock",
+            WindowType::Splash => "splash",
+            WindowType::Dialog => "dialog",
+            WindowType::Menu => "menu",
+            WindowType::Toolbar => "toolbar",
+            WindowType::Utility => "utility",
+            WindowType::DropdownMenu => "dropdown_menu",
+            WindowType::PopupMenu => "popup_menu",
+            WindowType::Notification => "notification",
+            WindowType::Combo => "combo",
+            WindowType::Dnd => "dnd",
+            WindowType::Normal => "normal",
+            WindowType::Tooltip => "tooltip"
+        }
+    }
+
+    /// The layer windows of the type are shown on.
+    pub fn layer(self) -> Layer {
+        match self {
+            WindowType::Desktop => Layer::Background,
+            WindowType::Splash | WindowType::Notification => Layer::Overlay,
+            _ => Layer::Top
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn window_type_layers() {
+        for name in WindowType::NAMES {
+            assert_eq!(WindowType::from_name(name).map(WindowType::name), Some(*name));
+        }
+        assert_eq!(WindowType::from_name("panel"), None);
+        assert_eq!(WindowType::default().layer(), Layer::default());
+        assert_eq!(WindowType::Desktop.layer(), Layer::Background);
+        assert_eq!(WindowType::Dock.layer(), Layer::Top);
+        assert_eq!(WindowType::Splash.layer(), Layer::Overlay);
+        assert_eq!(WindowType::Notification.layer(), Layer::Overlay);
+        assert_eq!(WindowType::Tooltip.layer(), Layer::Top);
+    }
+}
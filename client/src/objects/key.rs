@@ -10,13 +10,17 @@ use crate::common::{
     object::{self, Object},
     property::Property
 };
-use crate::lua::mods_to_num;
+use crate::lua::{mods_to_num, num_to_mod_names};
 
 #[derive(Clone, Debug, Default)]
 pub struct KeyState {
     modifiers: u32,
     keysym: Keysym,
-    keycode: xkb::Keycode
+    keycode: xkb::Keycode,
+    /// What the binding does, for the hotkeys popup.
+    description: String,
+    /// The group the binding is listed under in the hotkeys popup.
+    group: String
 }
 
 pub type Key<'lua> = Object<'lua, KeyState>;
@@ -58,6 +62,33 @@ impl<'lua> Key<'lua> {
         Ok(())
     }
 
+    pub fn description(&self) -> rlua::Result<String> {
+        Ok(self.state()?.description.clone())
+    }
+
+    pub fn group(&self) -> rlua::Result<String> {
+        Ok(self.state()?.group.clone())
+    }
+
+    /// The key combination as a user would write it, e.g. "Mod4+Shift+Return".
+    ///
+    /// The modifiers `awful.key` ignores (Caps Lock and Num Lock) are left
+    /// out, so all the variants of a binding have the same label.
+    pub fn label(&self) -> rlua::Result<String> {
+        let state = self.state()?;
+        let key = if state.keysym != 0 {
+            xkb::keysym_get_name(state.keysym)
+        } else {
+            format!("#{}", state.keycode + 8)
+        };
+        let mut parts: Vec<&str> = num_to_mod_names(state.modifiers)
+            .into_iter()
+            .filter(|name| *name != "Caps" && *name != "Mod2")
+            .collect();
+        parts.push(&key);
+        Ok(parts.join("+"))
+    }
+
     #[allow(dead_code)]
     pub fn keycode(&self) -> rlua::Result<xkb::Keycode> {
         let state = self.state()?;
@@ -111,6 +142,18 @@ fn property_setup<'lua>(
             Some(lua.create_function(set_modifiers)?),
             Some(lua.create_function(get_modifiers)?),
             Some(lua.create_function(set_modifiers)?)
+        ))?
+        .property(Property::new(
+            "description".into(),
+            Some(lua.create_function(set_description)?),
+            Some(lua.create_function(get_description)?),
+            Some(lua.create_function(set_description)?)
+        ))?
+        .property(Property::new(
+            "group".into(),
+            Some(lua.create_function(set_group)?),
+            Some(lua.create_function(get_group)?),
+            Some(lua.create_function(set_group)?)
         ))
 }
 
@@ -125,6 +168,27 @@ fn set_modifiers<'lua>(
     key.set_modifiers(mods_to_num(mods)?.bits())
 }
 
+fn get_description<'lua>(_: rlua::Context<'lua>, key: Key<'lua>) -> rlua::Result<String> {
+    key.description()
+}
+
+fn set_description<'lua>(
+    _: rlua::Context<'lua>,
+    (mut key, description): (Key<'lua>, String)
+) -> rlua::Result<()> {
+    key.state_mut()?.description = description;
+    Ok(())
+}
+
+fn get_group<'lua>(_: rlua::Context<'lua>, key: Key<'lua>) -> rlua::Result<String> {
+    key.group()
+}
+
+fn set_group<'lua>(_: rlua::Context<'lua>, (mut key, group): (Key<'lua>, String)) -> rlua::Result<()> {
+    key.state_mut()?.group = group;
+    Ok(())
+}
+
 fn get_keysym<'lua>(lua: rlua::Context<'lua>, key: Key<'lua>) -> rlua::Result<Value<'lua>> {
     // TODO Shouldn't this be able to fail?
     xkb::keysym_get_name(key.keysym()?).to_lua(lua)
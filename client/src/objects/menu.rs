@@ -0,0 +1,819 @@
+//! Native version of `awful.menu`, e.g. for right click menus.
+//!
+//! Every menu is shown with a drawin of its own. Clicking an item calls its
+//! callback, hovering over an item with a submenu opens the submenu. While a
+//! menu is shown the keyboard can be used too: Up and Down select an item,
+//! Right and Left open and close submenus, Return activates the selected
+//! item and Escape closes the menu.
+//!
+//! Menus are themed with the `theme` argument, falling back to the
+//! `menu_*` variables of `beautiful`.
+
+mod placement;
+
+use cairo::{Context, ImageSurface};
+use gdk_pixbuf::Pixbuf;
+use rlua::{self, Function, RegistryKey, Table, ToLua, UserData, UserDataMethods, Value};
+
+use crate::area::{Area, Origin, Size};
+use crate::common::{
+    class::{self, Class, ClassBuilder},
+    color::{self, Color},
+    font::{self, Font},
+    object::{self, Object, ObjectBuilder},
+    property::Property,
+    text
+};
+use crate::objects::{
+    client::{self, Client},
+    drawin::Drawin,
+    screen::{Screen, SCREENS_HANDLE}
+};
+
+/// Handle to the menu that has the keyboard while it's shown.
+const MENU_KEYGRABBER: &str = "__menu_keygrabber";
+
+const DEFAULT_FONT: &str = "Sans";
+const DEFAULT_FONT_SIZE: f64 = 10.0;
+/// Space between the edges of an item and its contents.
+const PADDING: f64 = 4.0;
+
+/// How menus look.
+#[derive(Debug, Clone)]
+pub struct MenuTheme {
+    pub width: u32,
+    /// The height of a single item.
+    pub height: u32,
+    pub font: Font,
+    pub bg_normal: Color,
+    pub fg_normal: Color,
+    /// The colors of the selected item.
+    pub bg_focus: Color,
+    pub fg_focus: Color,
+    pub border_color: Color,
+    pub border_width: u32,
+    /// Shown at the end of items with a submenu.
+    pub submenu: String
+}
+
+/// An item of a menu.
+#[derive(Debug)]
+pub struct MenuItem {
+    pub label: String,
+    /// Called with the menu when the item is activated.
+    pub callback: Option<RegistryKey>,
+    pub submenu: Option<RegistryKey>,
+    /// The path of an image shown before the label.
+    pub icon: Option<String>
+}
+
+#[derive(Debug, Default)]
+pub struct MenuState {
+    items: Vec<MenuItem>,
+    width: u32,
+    theme: MenuTheme,
+    x: i32,
+    y: i32,
+    visible: bool,
+    /// The item that is under the pointer or selected with the keyboard.
+    highlighted: Option<usize>,
+    /// The item whose submenu is shown.
+    expanded: Option<usize>
+}
+
+pub type Menu<'lua> = Object<'lua, MenuState>;
+
+impl Default for MenuTheme {
+    fn default() -> Self {
+        MenuTheme {
+            width: 100,
+            height: 16,
+            font: Font {
+                family: DEFAULT_FONT.into(),
+                size: DEFAULT_FONT_SIZE
+            },
+            bg_normal: color::parse_color("#222222").unwrap(),
+            fg_normal: color::parse_color("#aaaaaa").unwrap(),
+            bg_focus: color::parse_color("#535d6c").unwrap(),
+            fg_focus: color::parse_color("#ffffff").unwrap(),
+            border_color: color::parse_color("#000000").unwrap(),
+            border_width: 1,
+            submenu: "▶".into()
+        }
+    }
+}
+
+impl MenuTheme {
+    /// The theme from `beautiful`, where `beautiful.menu_bg_normal` is used
+    /// for `bg_normal` and so on.
+    ///
+    /// The colors and font fall back to the ones used everywhere else, e.g.
+    /// `beautiful.bg_normal`.
+    fn from_beautiful(lua: rlua::Context) -> rlua::Result<MenuTheme> {
+        let loaded = lua
+            .globals()
+            .get::<_, Table>("package")?
+            .get::<_, Table>("loaded")?;
+        let theme = match loaded.get::<_, Option<Table>>("beautiful")? {
+            Some(beautiful) => beautiful,
+            None => return Ok(MenuTheme::default())
+        };
+        let fallbacks = [
+            ("font", "font"),
+            ("bg_normal", "bg_normal"),
+            ("fg_normal", "fg_normal"),
+            ("bg_focus", "bg_focus"),
+            ("fg_focus", "fg_focus"),
+            ("border_color", "border_normal"),
+            ("border_width", "border_width")
+        ];
+        let overrides = lua.create_table()?;
+        for pair in theme.clone().pairs::<Value, Value>() {
+            if let (Value::String(key), value) = pair? {
+                let key = key.to_str()?;
+                if key.starts_with("menu_") {
+                    overrides.set(&key["menu_".len()..], value)?;
+                }
+            }
+        }
+        for &(key, fallback) in fallbacks.iter() {
+            if let Value::Nil = overrides.get::<_, Value>(key)? {
+                overrides.set(key, theme.get::<_, Value>(fallback)?)?;
+            }
+        }
+        MenuTheme::default().with_overrides(overrides)
+    }
+
+    /// Replaces the parts of the theme that are given in `overrides`, e.g.
+    /// `{ width = 200, bg_normal = "#000000" }`.
+    fn with_overrides(mut self, overrides: Table) -> rlua::Result<MenuTheme> {
+        let string = |key: &str| -> rlua::Result<Option<String>> {
+            match overrides.get::<_, Value>(key)? {
+                Value::String(value) => Ok(Some(value.to_str()?.into())),
+                _ => Ok(None)
+            }
+        };
+        let number = |key: &str| -> rlua::Result<Option<u32>> {
+            Ok(match overrides.get::<_, Value>(key)? {
+                Value::Integer(value) if value >= 0 => Some(value as u32),
+                Value::Number(value) if value >= 0.0 => Some(value as u32),
+                Value::String(value) => value.to_str()?.trim().parse().ok(),
+                _ => None
+            })
+        };
+        let color = |key: &str, default: Color| -> rlua::Result<Color> {
+            Ok(string(key)?
+                .and_then(|value| color::parse_color(&value))
+                .unwrap_or(default))
+        };
+        self.width = number("width")?.unwrap_or(self.width);
+        self.height = number("height")?.unwrap_or(self.height);
+        if let Some(value) = string("font")? {
+            self.font = font::parse_font(&value, DEFAULT_FONT_SIZE);
+        }
+        self.bg_normal = color("bg_normal", self.bg_normal)?;
+        self.fg_normal = color("fg_normal", self.fg_normal)?;
+        self.bg_focus = color("bg_focus", self.bg_focus)?;
+        self.fg_focus = color("fg_focus", self.fg_focus)?;
+        self.border_color = color("border_color", self.border_color)?;
+        self.border_width = number("border_width")?.unwrap_or(self.border_width);
+        self.submenu = string("submenu")?.unwrap_or(self.submenu);
+        Ok(self)
+    }
+}
+
+impl UserData for MenuState {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        object::default_add_methods(methods);
+    }
+}
+
+impl<'lua> Menu<'lua> {
+    /// Makes a menu of `items`, which are tables like `{ label, command,
+    /// icon }`.
+    ///
+    /// The command is a function, a command to spawn, or the items of a
+    /// submenu, which has the same theme.
+    pub fn new(lua: rlua::Context<'lua>, items: Table<'lua>, theme: MenuTheme) -> rlua::Result<Menu<'lua>> {
+        let class = class::class_setup(lua, "menu")?;
+        let mut menu = object_setup(lua, Menu::allocate(lua, class)?)?.build();
+        let items = items
+            .sequence_values::<Table>()
+            .map(|item| parse_item(lua, item?, &theme))
+            .collect::<rlua::Result<Vec<MenuItem>>>()?;
+        {
+            let mut state = menu.state_mut()?;
+            state.items = items;
+            state.width = theme.width;
+            state.theme = theme;
+        }
+        Ok(menu)
+    }
+
+    pub fn get_visible(&self) -> rlua::Result<bool> {
+        Ok(self.state()?.visible)
+    }
+
+    fn size(&self) -> rlua::Result<Size> {
+        let state = self.state()?;
+        Ok(Size {
+            width: state.width,
+            height: state.items.len() as u32 * state.theme.height
+        })
+    }
+
+    fn geometry(&self) -> rlua::Result<Area> {
+        let origin = {
+            let state = self.state()?;
+            Origin {
+                x: state.x,
+                y: state.y
+            }
+        };
+        Ok(Area {
+            origin,
+            size: self.size()?
+        })
+    }
+
+    /// Shows the menu with its top left corner at `coords`, or at the top
+    /// left corner of the first screen.
+    ///
+    /// The menu is moved to stay on the screen.
+    pub fn show(&mut self, lua: rlua::Context<'lua>, coords: Option<Origin>) -> rlua::Result<()> {
+        let screens = screens(lua)?;
+        let point = match coords.or_else(|| screens.first().map(|screen| screen.origin)) {
+            Some(point) => point,
+            None => {
+                return Err(rlua::Error::RuntimeError(
+                    "there is no screen to show the menu on".into()
+                ))
+            },
+        };
+        let screen = placement::screen_at(point, &screens).unwrap();
+        let origin = placement::place(point, self.size()?, screen);
+        self.show_at(lua, origin)
+    }
+
+    fn show_at(&mut self, lua: rlua::Context<'lua>, origin: Origin) -> rlua::Result<()> {
+        self.collapse(lua)?;
+        let was_visible = {
+            let mut state = self.state_mut()?;
+            state.x = origin.x;
+            state.y = origin.y;
+            state.highlighted = None;
+            std::mem::replace(&mut state.visible, true)
+        };
+        let mut drawin = self.drawin(lua)?;
+        let Size { width, height } = self.size()?;
+        let geometry = lua.create_table()?;
+        geometry.set("x", origin.x)?;
+        geometry.set("y", origin.y)?;
+        geometry.set("width", width)?;
+        geometry.set("height", height)?;
+        crate::lua_fns::call_method(lua, drawin.clone().to_lua(lua)?, "geometry", geometry)?;
+        drawin.set_visible(lua, true)?;
+        if !was_visible && self.parent()?.is_none() {
+            grab_keyboard(lua, self.clone())?;
+        }
+        self.redraw(lua)
+    }
+
+    /// Hides the menu and its submenus.
+    pub fn hide(&mut self, lua: rlua::Context<'lua>) -> rlua::Result<()> {
+        if !self.get_visible()? {
+            return Ok(());
+        }
+        self.collapse(lua)?;
+        {
+            let mut state = self.state_mut()?;
+            state.visible = false;
+            state.highlighted = None;
+        }
+        if let Some(mut drawin) = self.get_associated_data::<Option<Drawin>>("drawin")? {
+            drawin.set_visible(lua, false)?;
+        }
+        if self.parent()?.is_none() {
+            release_keyboard(lua, self)?;
+        }
+        Ok(())
+    }
+
+    pub fn toggle(&mut self, lua: rlua::Context<'lua>, coords: Option<Origin>) -> rlua::Result<()> {
+        if self.get_visible()? {
+            self.hide(lua)
+        } else {
+            self.show(lua, coords)
+        }
+    }
+
+    /// The menu this is a submenu of.
+    fn parent(&self) -> rlua::Result<Option<Menu<'lua>>> {
+        self.get_associated_data::<Option<Menu>>("parent")
+    }
+
+    /// The menu that isn't a submenu of another one.
+    fn root(&self) -> rlua::Result<Menu<'lua>> {
+        let mut menu = self.clone();
+        while let Some(parent) = menu.parent()? {
+            menu = parent;
+        }
+        Ok(menu)
+    }
+
+    fn submenu(&self, lua: rlua::Context<'lua>, index: usize) -> rlua::Result<Option<Menu<'lua>>> {
+        let state = self.state()?;
+        match state.items.get(index).and_then(|item| item.submenu.as_ref()) {
+            Some(submenu) => Ok(Some(lua.registry_value::<Menu>(submenu)?)),
+            None => Ok(None)
+        }
+    }
+
+    /// The submenu that is shown, if any.
+    fn expanded(&self, lua: rlua::Context<'lua>) -> rlua::Result<Option<Menu<'lua>>> {
+        let expanded = self.state()?.expanded;
+        match expanded {
+            Some(index) => self.submenu(lua, index),
+            None => Ok(None)
+        }
+    }
+
+    /// Shows the submenu of the item at `index` next to it.
+    fn expand(&mut self, lua: rlua::Context<'lua>, index: usize) -> rlua::Result<()> {
+        if self.state()?.expanded == Some(index) {
+            return Ok(());
+        }
+        self.collapse(lua)?;
+        let mut submenu = match self.submenu(lua, index)? {
+            Some(submenu) => submenu,
+            None => return Ok(())
+        };
+        submenu.set_associated_data("parent", self.clone())?;
+        let menu = self.geometry()?;
+        let screen = placement::screen_at(menu.origin, &screens(lua)?).unwrap_or(menu);
+        let item_height = self.state()?.theme.height;
+        let origin = placement::place_submenu(menu, index, item_height, submenu.size()?, screen);
+        self.state_mut()?.expanded = Some(index);
+        submenu.show_at(lua, origin)
+    }
+
+    /// Hides the submenu that is shown, if any.
+    fn collapse(&mut self, lua: rlua::Context<'lua>) -> rlua::Result<()> {
+        if let Some(mut submenu) = self.expanded(lua)? {
+            self.state_mut()?.expanded = None;
+            submenu.hide(lua)?;
+        }
+        Ok(())
+    }
+
+    /// Highlights the item at `index`, opening its submenu if `expand` is
+    /// set and it has one.
+    fn highlight(
+        &mut self,
+        lua: rlua::Context<'lua>,
+        index: Option<usize>,
+        expand: bool
+    ) -> rlua::Result<()> {
+        let changed = {
+            let mut state = self.state_mut()?;
+            std::mem::replace(&mut state.highlighted, index) != index
+        };
+        if changed {
+            self.redraw(lua)?;
+        }
+        match index {
+            Some(index) if expand && self.submenu(lua, index)?.is_some() => self.expand(lua, index),
+            _ => self.collapse(lua)
+        }
+    }
+
+    /// Calls the callback of the item at `index`, or opens its submenu.
+    ///
+    /// The whole menu is hidden afterwards, unless the callback returns
+    /// true.
+    fn activate(&mut self, lua: rlua::Context<'lua>, index: usize) -> rlua::Result<()> {
+        if self.submenu(lua, index)?.is_some() {
+            return self.expand(lua, index);
+        }
+        let callback = {
+            let state = self.state()?;
+            match state.items.get(index).and_then(|item| item.callback.as_ref()) {
+                Some(callback) => Some(lua.registry_value::<Function>(callback)?),
+                None => None
+            }
+        };
+        let keep = match callback {
+            Some(callback) => callback.call::<_, bool>(self.clone())?,
+            None => false
+        };
+        if !keep {
+            self.root()?.hide(lua)?;
+        }
+        Ok(())
+    }
+
+    /// The drawin that shows the menu, which is made the first time the
+    /// menu is shown.
+    fn drawin(&self, lua: rlua::Context<'lua>) -> rlua::Result<Drawin<'lua>> {
+        if let Some(drawin) = self.get_associated_data::<Option<Drawin>>("drawin")? {
+            return Ok(drawin);
+        }
+        let args = lua.create_table()?;
+        args.set("ontop", true)?;
+        let drawin = Drawin::new(lua, args)?;
+        drawin.set_associated_data("menu", self.clone())?;
+        Drawin::connect_signal(lua, &drawin, "mouse::move", lua.create_function(mouse_move)?)?;
+        Drawin::connect_signal(lua, &drawin, "mouse::leave", lua.create_function(mouse_leave)?)?;
+        Drawin::connect_signal(lua, &drawin, "button::press", lua.create_function(button_press)?)?;
+        self.set_associated_data("drawin", drawin.clone())?;
+        Ok(drawin)
+    }
+
+    /// Draws the items into the menu's drawin.
+    fn redraw(&self, lua: rlua::Context<'lua>) -> rlua::Result<()> {
+        let mut drawable = self.drawin(lua)?.drawable()?;
+        {
+            let surface = drawable.state()?;
+            let surface = match surface.surface.as_ref() {
+                Some(surface) => surface,
+                None => return Ok(())
+            };
+            draw(&Context::new(surface), &*self.state()?);
+        }
+        drawable.refresh(lua)
+    }
+}
+
+/// Makes a `MenuItem` from a table like `{ label, command, icon }`.
+fn parse_item<'lua>(
+    lua: rlua::Context<'lua>,
+    item: Table<'lua>,
+    theme: &MenuTheme
+) -> rlua::Result<MenuItem> {
+    let label = item.get::<_, Option<String>>(1)?.unwrap_or_default();
+    let icon = match item.get::<_, Value>(3)? {
+        Value::String(icon) => Some(icon.to_str()?.into()),
+        _ => None
+    };
+    let (callback, submenu) = match item.get::<_, Value>(2)? {
+        Value::Nil => (None, None),
+        Value::Function(callback) => (Some(callback), None),
+        Value::String(command) => {
+            let command = command.to_str()?.to_owned();
+            let spawn = lua.create_function(move |lua, _: Value| spawn(lua, &command))?;
+            (Some(spawn), None)
+        },
+        Value::Table(items) => (None, Some(Menu::new(lua, items, theme.clone())?)),
+        Value::UserData(menu) => (None, Some(Menu::cast(menu)?)),
+        _ => {
+            return Err(rlua::Error::RuntimeError(format!(
+                "menu: the command of \"{}\" must be a function, a command or a list of items",
+                label
+            )))
+        },
+    };
+    Ok(MenuItem {
+        label,
+        callback: callback
+            .map(|callback| lua.create_registry_value(callback))
+            .transpose()?,
+        submenu: submenu
+            .map(|submenu| lua.create_registry_value(submenu))
+            .transpose()?,
+        icon
+    })
+}
+
+fn spawn(lua: rlua::Context, command: &str) -> rlua::Result<()> {
+    let awesome = lua.globals().get::<_, Table>("awesome")?;
+    awesome.get::<_, Function>("spawn")?.call::<_, Value>(command)?;
+    Ok(())
+}
+
+fn screens(lua: rlua::Context) -> rlua::Result<Vec<Area>> {
+    lua.named_registry_value::<str, Vec<Screen>>(SCREENS_HANDLE)?
+        .iter()
+        .map(|screen| Ok(screen.state()?.geometry))
+        .collect()
+}
+
+/// Lets the keyboard control `menu` while it's shown.
+fn grab_keyboard<'lua>(lua: rlua::Context<'lua>, menu: Menu<'lua>) -> rlua::Result<()> {
+    lua.set_named_registry_value(MENU_KEYGRABBER, menu)?;
+    let keygrabber = lua.globals().get::<_, Table>("keygrabber")?;
+    keygrabber
+        .get::<_, Function>("run")?
+        .call(lua.create_function(handle_key)?)
+}
+
+/// Stops the keyboard from controlling `menu`, if it was.
+fn release_keyboard<'lua>(lua: rlua::Context<'lua>, menu: &Menu<'lua>) -> rlua::Result<()> {
+    let grabbing = match lua.named_registry_value::<str, Option<Menu>>(MENU_KEYGRABBER)? {
+        Some(grabbing) => &*grabbing.state()? as *const MenuState == &*menu.state()? as *const MenuState,
+        None => false
+    };
+    if grabbing {
+        lua.set_named_registry_value(MENU_KEYGRABBER, Value::Nil)?;
+        let keygrabber = lua.globals().get::<_, Table>("keygrabber")?;
+        keygrabber.get::<_, Function>("stop")?.call::<_, ()>(())?;
+    }
+    Ok(())
+}
+
+/// The keygrabber callback while a menu is shown.
+///
+/// Keys act on the innermost submenu that is shown.
+fn handle_key<'lua>(
+    lua: rlua::Context<'lua>,
+    (_, key, event): (Value<'lua>, String, String)
+) -> rlua::Result<()> {
+    if event != "press" {
+        return Ok(());
+    }
+    let mut menu = match lua.named_registry_value::<str, Option<Menu>>(MENU_KEYGRABBER)? {
+        Some(menu) => menu,
+        None => return Ok(())
+    };
+    while let Some(submenu) = menu.expanded(lua)? {
+        menu = submenu;
+    }
+    let (highlighted, count) = {
+        let state = menu.state()?;
+        (state.highlighted, state.items.len())
+    };
+    if count == 0 {
+        return menu.root()?.hide(lua);
+    }
+    match (key.as_str(), highlighted) {
+        ("Escape", _) => menu.root()?.hide(lua),
+        ("Up", Some(index)) => menu.highlight(lua, Some((index + count - 1) % count), false),
+        ("Up", None) => menu.highlight(lua, Some(count - 1), false),
+        ("Down", Some(index)) => menu.highlight(lua, Some((index + 1) % count), false),
+        ("Down", None) => menu.highlight(lua, Some(0), false),
+        ("Right", Some(index)) => {
+            menu.expand(lua, index)?;
+            match menu.expanded(lua)? {
+                Some(mut submenu) => submenu.highlight(lua, Some(0), false),
+                None => Ok(())
+            }
+        },
+        ("Left", _) => match menu.parent()? {
+            Some(mut parent) => parent.collapse(lua),
+            None => Ok(())
+        },
+        ("Return", Some(index)) => menu.activate(lua, index),
+        _ => Ok(())
+    }
+}
+
+/// The menu shown with `drawin`.
+fn menu_of<'lua>(drawin: &Drawin<'lua>) -> rlua::Result<Menu<'lua>> {
+    drawin.get_associated_data::<Menu>("menu")
+}
+
+fn mouse_move<'lua>(lua: rlua::Context<'lua>, (drawin, _, y): (Drawin<'lua>, f64, f64)) -> rlua::Result<()> {
+    let mut menu = menu_of(&drawin)?;
+    let index = {
+        let state = menu.state()?;
+        placement::item_at(y, state.theme.height, state.items.len())
+    };
+    menu.highlight(lua, index, true)
+}
+
+/// Nothing stays highlighted once the pointer leaves, unless it's moving
+/// into the submenu of the item.
+fn mouse_leave<'lua>(lua: rlua::Context<'lua>, drawin: Drawin<'lua>) -> rlua::Result<()> {
+    let mut menu = menu_of(&drawin)?;
+    if menu.state()?.expanded.is_some() {
+        return Ok(());
+    }
+    menu.highlight(lua, None, false)
+}
+
+/// The left button activates the item under the pointer, the right button
+/// hides the menu.
+fn button_press<'lua>(
+    lua: rlua::Context<'lua>,
+    (drawin, _, y, button): (Drawin<'lua>, f64, f64, u32)
+) -> rlua::Result<()> {
+    let mut menu = menu_of(&drawin)?;
+    let index = {
+        let state = menu.state()?;
+        placement::item_at(y, state.theme.height, state.items.len())
+    };
+    match (button, index) {
+        (1, Some(index)) => menu.activate(lua, index),
+        (3, _) => menu.root()?.hide(lua),
+        _ => Ok(())
+    }
+}
+
+fn draw(cr: &Context, menu: &MenuState) {
+    let theme = &menu.theme;
+    let width = menu.width as f64;
+    let item_height = theme.height as f64;
+    let set_source = |color: Color| cr.set_source_rgba(color.red, color.green, color.blue, color.alpha);
+    text::select_font(cr, &theme.font);
+    let extents = cr.font_extents();
+    // Leave room for the icons if any item has one.
+    let text_x = if menu.items.iter().any(|item| item.icon.is_some()) {
+        item_height + PADDING
+    } else {
+        PADDING
+    };
+    for (index, item) in menu.items.iter().enumerate() {
+        let y = index as f64 * item_height;
+        let (bg, fg) = if menu.highlighted == Some(index) {
+            (theme.bg_focus, theme.fg_focus)
+        } else {
+            (theme.bg_normal, theme.fg_normal)
+        };
+        cr.save();
+        cr.rectangle(0.0, y, width, item_height);
+        cr.clip();
+        set_source(bg);
+        cr.paint();
+        if let Some(icon) = item.icon.as_ref().and_then(|icon| load_icon(icon)) {
+            cr.save();
+            cr.translate(0.0, y);
+            cr.scale(
+                item_height / icon.get_width() as f64,
+                item_height / icon.get_height() as f64
+            );
+            cr.set_source_surface(&icon, 0.0, 0.0);
+            cr.paint();
+            cr.restore();
+        }
+        set_source(fg);
+        let baseline = y + (item_height - extents.height) / 2.0 + extents.ascent;
+        cr.move_to(text_x, baseline);
+        text::draw_text(cr, &item.label);
+        if item.submenu.is_some() {
+            let indicator_width = cr.text_extents(&theme.submenu).x_advance;
+            cr.move_to(width - PADDING - indicator_width, baseline);
+            text::draw_text(cr, &theme.submenu);
+        }
+        cr.restore();
+    }
+    if theme.border_width > 0 {
+        let border_width = theme.border_width as f64;
+        set_source(theme.border_color);
+        cr.set_line_width(border_width);
+        cr.rectangle(
+            border_width / 2.0,
+            border_width / 2.0,
+            width - border_width,
+            menu.items.len() as f64 * item_height - border_width
+        );
+        cr.stroke();
+    }
+}
+
+fn load_icon(path: &str) -> Option<ImageSurface> {
+    match Pixbuf::new_from_file(path) {
+        Ok(pixbuf) => Some(crate::awesome::load_surface_from_pixbuf(pixbuf)),
+        Err(err) => {
+            warn!("Could not load menu icon {}: {}", path, err);
+            None
+        }
+    }
+}
+
+pub fn init(lua: rlua::Context) -> rlua::Result<Class<MenuState>> {
+    let module = lua.create_table()?;
+    module.set("new", lua.create_function(menu_new)?)?;
+    module.set("client_list", lua.create_function(client_list)?)?;
+    // So `awful.menu { ... }` works when the Lua module can't be loaded.
+    let meta = lua.create_table()?;
+    meta.set(
+        "__call",
+        lua.create_function(|lua, (_, args, parent): (Value, Table, Option<Menu>)| {
+            menu_new(lua, (args, parent))
+        })?
+    )?;
+    module.set_metatable(Some(meta));
+    crate::lua_fns::extend_module(lua, "awful.menu", module)?;
+    property_setup(lua, Class::builder(lua, "menu", None)?)?
+        .save_class("menu")?
+        .build()
+}
+
+fn property_setup<'lua>(
+    lua: rlua::Context<'lua>,
+    builder: ClassBuilder<'lua, MenuState>
+) -> rlua::Result<ClassBuilder<'lua, MenuState>> {
+    builder.property(Property::new(
+        "visible".into(),
+        None,
+        Some(lua.create_function(get_visible)?),
+        None
+    ))
+}
+
+fn object_setup<'lua>(
+    lua: rlua::Context<'lua>,
+    builder: ObjectBuilder<'lua, MenuState>
+) -> rlua::Result<ObjectBuilder<'lua, MenuState>> {
+    let table = lua.create_table()?;
+    table.set("show", lua.create_function(show)?)?;
+    table.set("hide", lua.create_function(hide)?)?;
+    table.set("toggle", lua.create_function(toggle)?)?;
+    builder.add_to_meta(table)
+}
+
+/// `awful.menu.new(args, parent)`, where `args` is the list of items or a
+/// table like `{ items = { ... }, theme = { ... } }`.
+///
+/// Without a theme in the arguments a submenu uses the theme of its parent.
+fn menu_new<'lua>(
+    lua: rlua::Context<'lua>,
+    (args, parent): (Table<'lua>, Option<Menu<'lua>>)
+) -> rlua::Result<Menu<'lua>> {
+    let items = args
+        .get::<_, Option<Table>>("items")?
+        .unwrap_or_else(|| args.clone());
+    let theme = match parent {
+        Some(ref parent) => parent.state()?.theme.clone(),
+        None => MenuTheme::from_beautiful(lua)?
+    };
+    let theme = match args.get::<_, Option<Table>>("theme")? {
+        Some(overrides) => theme.with_overrides(overrides)?,
+        None => theme
+    };
+    let menu = Menu::new(lua, items, theme)?;
+    if let Some(parent) = parent {
+        menu.set_associated_data("parent", parent)?;
+    }
+    Ok(menu)
+}
+
+/// Shows a menu of all clients, which focuses the one that's clicked.
+///
+/// `args` can have a `theme` like `awful.menu.new` and the `coords` to show
+/// the menu at.
+fn client_list<'lua>(lua: rlua::Context<'lua>, args: Option<Table<'lua>>) -> rlua::Result<Menu<'lua>> {
+    let args = match args {
+        Some(args) => args,
+        None => lua.create_table()?
+    };
+    let focus = lua.create_function(|lua, (client, _): (Client, Value)| {
+        client.activate(lua)?;
+        Ok(false)
+    })?;
+    let items = lua.create_table()?;
+    for (index, client) in client::clients(lua)?.into_iter().enumerate() {
+        let item = lua.create_table()?;
+        item.set(1, client.get_name()?)?;
+        item.set(2, focus.bind(client)?)?;
+        items.set(index + 1, item)?;
+    }
+    let theme = MenuTheme::from_beautiful(lua)?;
+    let theme = match args.get::<_, Option<Table>>("theme")? {
+        Some(overrides) => theme.with_overrides(overrides)?,
+        None => theme
+    };
+    let mut menu = Menu::new(lua, items, theme)?;
+    menu.show(lua, coords(&args)?)?;
+    Ok(menu)
+}
+
+/// The `coords` argument of `show` and `toggle`, like `{ x = 10, y = 20 }`.
+fn coords(args: &Table) -> rlua::Result<Option<Origin>> {
+    match args.get::<_, Option<Table>>("coords")? {
+        Some(coords) => Ok(Some(Origin {
+            x: coords.get("x")?,
+            y: coords.get("y")?
+        })),
+        None => Ok(None)
+    }
+}
+
+fn show<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut menu, args): (Menu<'lua>, Option<Table<'lua>>)
+) -> rlua::Result<()> {
+    let coords = match args {
+        Some(ref args) => coords(args)?,
+        None => None
+    };
+    menu.show(lua, coords)
+}
+
+fn hide<'lua>(lua: rlua::Context<'lua>, mut menu: Menu<'lua>) -> rlua::Result<()> {
+    menu.hide(lua)
+}
+
+fn toggle<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut menu, args): (Menu<'lua>, Option<Table<'lua>>)
+) -> rlua::Result<()> {
+    let coords = match args {
+        Some(ref args) => coords(args)?,
+        None => None
+    };
+    menu.toggle(lua, coords)
+}
+
+fn get_visible<'lua>(_: rlua::Context<'lua>, menu: Menu<'lua>) -> rlua::Result<bool> {
+    menu.get_visible()
+}
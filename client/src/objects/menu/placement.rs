@@ -0,0 +1,125 @@
+//! Where menus are placed on the screen, and which item is where.
+
+use crate::area::{Area, Origin, Size};
+
+/// The item `y` pixels from the top of a menu with `count` items.
+pub fn item_at(y: f64, item_height: u32, count: usize) -> Option<usize> {
+    if y < 0.0 || item_height == 0 {
+        return None;
+    }
+    let index = (y / item_height as f64) as usize;
+    if index < count {
+        Some(index)
+    } else {
+        None
+    }
+}
+
+/// The screen `point` is on, or the first screen if it's on none of them.
+pub fn screen_at(point: Origin, screens: &[Area]) -> Option<Area> {
+    screens
+        .iter()
+        .cloned()
+        .find(|screen| screen.contains(point))
+        .or_else(|| screens.first().cloned())
+}
+
+/// Places a menu of `size` with its top left corner at `point`, moving it
+/// as little as possible to keep it on `screen`.
+pub fn place(point: Origin, size: Size, screen: Area) -> Origin {
+    Area { origin: point, size }.clamp_within(screen).origin
+}
+
+/// Places a submenu of `size` next to the item at `index` of `menu`.
+///
+/// It goes on the right of the menu, unless it only fits on the left.
+pub fn place_submenu(menu: Area, index: usize, item_height: u32, size: Size, screen: Area) -> Origin {
+    let right = menu.origin.x + menu.size.width as i32;
+    let x = if right + size.width as i32 > screen.origin.x + screen.size.width as i32 &&
+        menu.origin.x - size.width as i32 >= screen.origin.x
+    {
+        menu.origin.x - size.width as i32
+    } else {
+        right
+    };
+    let y = menu.origin.y + (index as u32 * item_height) as i32;
+    place(Origin { x, y }, size, screen)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SCREEN: Area = Area {
+        origin: Origin { x: 0, y: 0 },
+        size: Size {
+            width: 1000,
+            height: 800
+        }
+    };
+
+    const MENU_SIZE: Size = Size {
+        width: 100,
+        height: 60
+    };
+
+    #[test]
+    fn menu_item_at() {
+        assert_eq!(item_at(0.0, 20, 3), Some(0));
+        assert_eq!(item_at(39.9, 20, 3), Some(1));
+        assert_eq!(item_at(59.0, 20, 3), Some(2));
+        assert_eq!(item_at(60.0, 20, 3), None);
+        assert_eq!(item_at(-1.0, 20, 3), None);
+        assert_eq!(item_at(10.0, 0, 3), None);
+    }
+
+    #[test]
+    fn menu_screen_at() {
+        let second = Area {
+            origin: Origin { x: 1000, y: 0 },
+            ..SCREEN
+        };
+        let screens = [SCREEN, second];
+        assert_eq!(screen_at(Origin { x: 1500, y: 10 }, &screens), Some(second));
+        assert_eq!(screen_at(Origin { x: -5, y: 10 }, &screens), Some(SCREEN));
+        assert_eq!(screen_at(Origin { x: 0, y: 0 }, &[]), None);
+    }
+
+    #[test]
+    fn menu_placement() {
+        assert_eq!(
+            place(Origin { x: 10, y: 20 }, MENU_SIZE, SCREEN),
+            Origin { x: 10, y: 20 }
+        );
+        // Moved back onto the screen at the bottom right corner.
+        assert_eq!(
+            place(Origin { x: 950, y: 790 }, MENU_SIZE, SCREEN),
+            Origin { x: 900, y: 740 }
+        );
+        assert_eq!(
+            place(Origin { x: -10, y: -10 }, MENU_SIZE, SCREEN),
+            Origin { x: 0, y: 0 }
+        );
+    }
+
+    #[test]
+    fn menu_submenu_placement() {
+        let menu = Area {
+            origin: Origin { x: 100, y: 100 },
+            size: MENU_SIZE
+        };
+        assert_eq!(
+            place_submenu(menu, 2, 20, MENU_SIZE, SCREEN),
+            Origin { x: 200, y: 140 }
+        );
+        // No room on the right, so it opens on the left.
+        let menu = Area {
+            origin: Origin { x: 850, y: 100 },
+            ..menu
+        };
+        assert_eq!(
+            place_submenu(menu, 0, 20, MENU_SIZE, SCREEN),
+            Origin { x: 750, y: 100 }
+        );
+    }
+}
@@ -3,9 +3,11 @@ pub mod client;
 pub mod drawable;
 pub mod drawin;
 pub mod key;
+pub mod menu;
 pub mod mouse;
 pub mod screen;
 pub mod tag;
+pub mod timer;
 
 use rlua;
 
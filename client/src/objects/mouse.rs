@@ -7,7 +7,11 @@ use std::default::Default;
 
 use rlua::{self, AnyUserData, MetaMethod, Table, ToLua, UserData, UserDataMethods, Value};
 
-use crate::objects::screen::{Screen, SCREENS_HANDLE};
+use crate::area::Origin;
+use crate::objects::{
+    drawin,
+    screen::{Screen, SCREENS_HANDLE}
+};
 
 const INDEX_MISS_FUNCTION: &'static str = "__index_miss_function";
 const NEWINDEX_MISS_FUNCTION: &'static str = "__newindex_miss_function";
@@ -51,12 +55,22 @@ fn method_setup<'lua>(lua: rlua::Context<'lua>, mouse_table: &Table<'lua>) -> rl
     Ok(())
 }
 
+/// `mouse.coords()`, where the pointer was last seen over one of the
+/// drawins. Clients can't move the pointer on Wayland, so coordinates to
+/// move it to are ignored.
 fn coords<'lua>(
-    _lua: rlua::Context<'lua>,
-    (_coords, _ignore_enter): (Value<'lua>, Value<'lua>)
+    lua: rlua::Context<'lua>,
+    (coords, _ignore_enter): (Option<Table<'lua>>, Value<'lua>)
 ) -> rlua::Result<Table<'lua>> {
-    // TODO Get Cords
-    unimplemented!()
+    if coords.is_some() {
+        warn!("mouse.coords can't move the pointer, the compositor doesn't let clients");
+    }
+    let (x, y) = drawin::pointer_position().unwrap_or_default();
+    let table = lua.create_table()?;
+    table.set("x", x)?;
+    table.set("y", y)?;
+    table.set("buttons", lua.create_table()?)?;
+    Ok(table)
 }
 
 fn set_index_miss<'lua>(lua: rlua::Context<'lua>, func: rlua::Function<'lua>) -> rlua::Result<()> {
@@ -78,17 +92,20 @@ fn index<'lua>(
     let obj_table = mouse.get_user_value::<Table>()?;
     if let Value::String(ref string) = index {
         if string.to_str()? == "screen" {
-            // TODO Get output
-            let output = None; // unimplemented!();
-
             let screens: Vec<Screen> = lua
                 .named_registry_value::<str, Vec<AnyUserData>>(SCREENS_HANDLE)?
                 .into_iter()
                 .map(|obj| Screen::cast(obj.into()).unwrap())
                 .collect();
-            if let Some(output) = output {
+            // The screen the pointer was last seen on.
+            if let Some((x, y)) = drawin::pointer_position() {
+                let point = Origin {
+                    x: x.floor() as i32,
+                    y: y.floor() as i32
+                };
                 for screen in &screens {
-                    if screen.state()?.outputs.contains(&output) {
+                    let state = screen.state()?;
+                    if state.valid && state.geometry.contains(point) {
                         return screen.clone().to_lua(lua);
                     }
                 }
@@ -3,7 +3,10 @@
 //! Note that there isn't a one-to-one mapping between number of outputs,
 //! screens, and the outputs as reported by Way Cooler.
 
-use std::default::Default;
+use std::{
+    default::Default,
+    sync::atomic::{AtomicUsize, Ordering}
+};
 
 use rlua::{self, AnyUserData, MetaMethod, Table, ToLua, UserData, UserDataMethods, Value};
 
@@ -11,12 +14,16 @@ use crate::area::{Area, Origin, Size};
 use crate::common::{
     class::{self, Class, ClassBuilder},
     object::{self, Object},
-    property::Property
+    property::Property,
+    signal
 };
-use crate::wayland_obj::Output;
+use crate::resume::{self, Kind, Removal};
+use crate::wayland_obj::{Output, OutputColor};
 
 pub const SCREENS_HANDLE: &'static str = "__screens";
 
+static NEXT_SCREEN_ID: AtomicUsize = AtomicUsize::new(1);
+
 pub type Screen<'lua> = Object<'lua, ScreenState>;
 
 #[derive(Clone)]
@@ -30,7 +37,11 @@ pub struct ScreenState {
     // The screen outputs information
     pub outputs: Vec<Output>,
     // Some XID identifying this screen
-    pub xid: u32
+    pub xid: u32,
+    // Identifies the screen to Lua, see `screen.by_id`
+    pub id: usize,
+    // When the screen was removed, see `resume`
+    pub removed: Option<Removal>
 }
 
 unsafe impl Send for ScreenState {}
@@ -41,6 +52,7 @@ impl PartialEq for ScreenState {
             self.geometry == other.geometry &&
             self.workarea == other.workarea &&
             self.xid == other.xid &&
+            self.id == other.id &&
             self.outputs == other.outputs
     }
 }
@@ -54,7 +66,9 @@ impl Default for ScreenState {
             geometry: Area::default(),
             workarea: Area::default(),
             outputs: vec![],
-            xid: 0
+            xid: 0,
+            id: NEXT_SCREEN_ID.fetch_add(1, Ordering::Relaxed),
+            removed: None
         }
     }
 }
@@ -74,11 +88,10 @@ impl<'lua> Screen<'lua> {
 
     pub fn init_screens(&mut self, output: Output, outputs: Vec<Output>) -> rlua::Result<()> {
         let mut state = self.state_mut()?;
-        let (width, height) = output.resolution();
-        let resolution = Size { width, height };
+        let geometry = output.geometry();
         state.outputs = outputs;
-        state.geometry = state.geometry.with_size(resolution);
-        state.workarea = state.workarea.with_size(resolution);
+        state.geometry = geometry;
+        state.workarea = geometry;
         Ok(())
     }
 
@@ -95,14 +108,16 @@ impl<'lua> Screen<'lua> {
         Ok(())
     }
 
+    /// Sets the workarea, emitting `property::workarea` with the old one if
+    /// it changed.
     pub fn set_workarea(&mut self, lua: rlua::Context<'lua>, geometry: Area) -> rlua::Result<()> {
-        if self.state()?.workarea != geometry {
+        let old = std::mem::replace(&mut self.state_mut()?.workarea, geometry);
+        if old != geometry {
             let old_area = lua.create_table()?;
-            old_area.set("x", geometry.origin.x)?;
-            old_area.set("y", geometry.origin.y)?;
-            old_area.set("width", geometry.size.width)?;
-            old_area.set("height", geometry.size.height)?;
-            self.state_mut()?.workarea = geometry;
+            old_area.set("x", old.origin.x)?;
+            old_area.set("y", old.origin.y)?;
+            old_area.set("width", old.size.width)?;
+            old_area.set("height", old.size.height)?;
             Object::emit_signal(lua, self, "property::workarea", old_area)?;
         }
         Ok(())
@@ -155,6 +170,27 @@ pub fn get_screen(lua: rlua::Context, output: Output) -> rlua::Result<Screen> {
         .ok_or(rlua::Error::RuntimeError(format!("No screen with output {:?}", output)))
 }
 
+/// Removes the screen of an output that was unplugged.
+///
+/// The handlers of "removed" on the screen and on the class can still use
+/// it, after that using it raises an error, see `resume`.
+pub fn remove_screen<'lua>(lua: rlua::Context<'lua>, mut screen: Screen<'lua>) -> rlua::Result<()> {
+    let id = screen.state()?.id;
+    let mut screens = Vec::new();
+    for screen in lua.named_registry_value::<str, Vec<Screen>>(SCREENS_HANDLE)? {
+        if screen.state()?.id != id {
+            screens.push(screen);
+        }
+    }
+    lua.set_named_registry_value(SCREENS_HANDLE, screens.to_lua(lua)?)?;
+    screen.state_mut()?.valid = false;
+    Object::emit_signal(lua, &screen, "removed", Value::Nil)?;
+    let class = class::class_setup::<ScreenState>(lua, "screen")?;
+    signal::emit_signals(lua, class.signals()?, "removed", screen.clone())?;
+    screen.state_mut()?.removed = Some(resume::removed());
+    Ok(())
+}
+
 pub fn init<'lua>(lua: rlua::Context<'lua>) -> rlua::Result<Class<ScreenState>> {
     let builder = Class::builder(lua, "screen", None)?;
     let res = property_setup(lua, method_setup(lua, builder)?)?
@@ -196,7 +232,8 @@ fn method_setup<'lua>(
     builder
         .method("count".into(), lua.create_function(count)?)?
         .method("__call".into(), lua.create_function(iterate_over_screens)?)?
-        .method("__index".into(), lua.create_function(index)?)
+        .method("__index".into(), lua.create_function(index)?)?
+        .method("by_id".into(), lua.create_function(by_id)?)
 }
 
 fn property_setup<'lua>(
@@ -215,9 +252,72 @@ fn property_setup<'lua>(
             None,
             Some(lua.create_function(get_workarea)?),
             None
+        ))?
+        .property(Property::new(
+            "id".into(),
+            None,
+            Some(lua.create_function(get_id)?),
+            None
+        ))?
+        .property(Property::new(
+            "color".into(),
+            None,
+            Some(lua.create_function(get_color)?),
+            None
         ))
 }
 
+fn get_id<'lua>(_: rlua::Context<'lua>, screen: Screen<'lua>) -> rlua::Result<usize> {
+    Ok(screen.state()?.id)
+}
+
+/// `screen.color`, the color the output shows if the compositor does color
+/// management, or nil. It changes with "property::color".
+fn get_color<'lua>(lua: rlua::Context<'lua>, screen: Screen<'lua>) -> rlua::Result<Value<'lua>> {
+    let color = screen.state()?.outputs.first().and_then(Output::color);
+    match color {
+        Some(color) => color_to_lua(lua, &color).map(Value::Table),
+        None => Ok(Value::Nil)
+    }
+}
+
+/// The color of an output as `screen.color` has it, e.g.
+/// `{ primaries = "bt2020", transfer_function = "st2084_pq", hdr = true,
+/// hdr_metadata = true, target_luminance = { min = 0.005, max = 1000 } }`.
+fn color_to_lua<'lua>(lua: rlua::Context<'lua>, color: &OutputColor) -> rlua::Result<Table<'lua>> {
+    let table = lua.create_table()?;
+    table.set("primaries", color.primaries)?;
+    table.set("transfer_function", color.transfer_function)?;
+    table.set("hdr", color.is_hdr())?;
+    table.set("hdr_metadata", color.has_hdr_metadata())?;
+    if let Some((min, max, reference)) = color.luminances {
+        let luminances = lua.create_table()?;
+        luminances.set("min", min)?;
+        luminances.set("max", max)?;
+        luminances.set("reference", reference)?;
+        table.set("luminances", luminances)?;
+    }
+    if let Some((min, max)) = color.target_luminance {
+        let target = lua.create_table()?;
+        target.set("min", min)?;
+        target.set("max", max)?;
+        table.set("target_luminance", target)?;
+    }
+    table.set("max_cll", color.max_cll)?;
+    table.set("max_fall", color.max_fall)?;
+    Ok(table)
+}
+
+/// `screen.by_id(id)`, the screen with the id, or nil if it was removed.
+fn by_id<'lua>(lua: rlua::Context<'lua>, id: usize) -> rlua::Result<Option<Screen<'lua>>> {
+    for screen in lua.named_registry_value::<str, Vec<Screen>>(SCREENS_HANDLE)? {
+        if screen.state()?.id == id {
+            return Ok(Some(screen));
+        }
+    }
+    Ok(None)
+}
+
 fn get_geometry<'lua>(lua: rlua::Context<'lua>, screen: Screen<'lua>) -> rlua::Result<Table<'lua>> {
     screen.get_geometry(lua)
 }
@@ -270,6 +370,21 @@ fn index<'lua>(
     lua: rlua::Context<'lua>,
     (obj, index): (AnyUserData<'lua>, Value<'lua>)
 ) -> rlua::Result<Value<'lua>> {
+    if let Ok(screen) = Screen::cast(obj.clone()) {
+        // Whether a removed screen is valid, and its id to find it again,
+        // are all that can be used of it.
+        let (id, removed) = {
+            let state = screen.state()?;
+            (state.id, state.removed)
+        };
+        match index {
+            Value::String(ref string) if string.to_str()? == "valid" => {
+                return Ok(Value::Boolean(resume::is_valid(removed)))
+            },
+            Value::String(ref string) if string.to_str()? == "id" => return id.to_lua(lua),
+            _ => resume::check(removed, Kind::Removed, "screen", id as u64)?
+        }
+    }
     let screens: Vec<Screen> = lua.named_registry_value(SCREENS_HANDLE)?;
     match index {
         Value::String(ref string) => {
@@ -41,6 +41,10 @@ impl<'lua> Tag<'lua> {
             .build())
     }
 
+    pub fn selected(&self) -> rlua::Result<bool> {
+        Ok(self.state()?.selected)
+    }
+
     pub fn clients(&self) -> rlua::Result<Vec<Client<'lua>>> {
         self.get_associated_data::<Vec<Client>>("__clients")
     }
@@ -62,7 +66,6 @@ impl<'lua> Tag<'lua> {
         Ok(())
     }
 
-    #[allow(dead_code)]
     pub fn client_index(&self, client: &Client<'lua>) -> rlua::Result<Option<usize>> {
         // TODO: remove the chaining of collect and into_iter
         Ok(self.clients()?.iter().position(|c| *c == *client))
@@ -555,7 +558,9 @@ assert(t:clients()[1] == c, "Pass by value, not by reference")
             .eval()?;
 
             let mut c = globals.get::<_, Client>("c")?;
-            c.state_mut()?.dummy = 1;
+            let mut geometry = c.get_geometry()?;
+            geometry.origin.x = 1;
+            c.set_geometry(ctx, geometry)?;
             ctx.load(
                 r#"
             assert(t:clients()[1] == c, "Tags are not passed by reference")
@@ -563,8 +568,8 @@ assert(t:clients()[1] == c, "Pass by value, not by reference")
             )
             .eval()?;
 
-            let mut c = globals.get::<_, Client>("c")?;
-            assert!(c.state_mut()?.dummy == 1);
+            let c = globals.get::<_, Client>("c")?;
+            assert!(c.get_geometry()?.origin.x == 1);
             Ok(())
         })
     }
@@ -0,0 +1,314 @@
+//! Timers that emit "timeout" from the main loop, like the timer class of
+//! Awesome.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use glib::{Continue, SourceId};
+use rlua::{self, prelude::LuaInteger, Table, UserData, UserDataMethods, Value};
+
+use crate::common::{
+    class::{self, Class, ClassDef},
+    object::{self, Object},
+    schema::{Key, Kind, Phase, Schema}
+};
+use crate::leaks::{Created, Finding};
+use crate::scheduler::{self, Priority};
+
+/// The started timers by id, which keeps them alive while they run.
+const TIMERS_HANDLE: &'static str = "__timers";
+
+static NEXT_TIMER_ID: AtomicUsize = AtomicUsize::new(1);
+
+const TIMER_SCHEMA: Schema = Schema {
+    class: "timer",
+    keys: &[
+        Key {
+            name: "timeout",
+            kind: Kind::Number,
+            phase: Phase::Backend
+        },
+        Key {
+            name: "single_shot",
+            kind: Kind::Boolean,
+            phase: Phase::Backend
+        }
+    ]
+};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct TimerId(usize);
+
+impl Default for TimerId {
+    fn default() -> Self {
+        TimerId(NEXT_TIMER_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TimerState {
+    id: TimerId,
+    /// How long the timer waits, in seconds.
+    timeout: f64,
+    single_shot: bool,
+    /// The main loop source while the timer is started.
+    source: Option<SourceId>,
+    /// Counts the starts, so that a timeout that was queued before the
+    /// timer was stopped is ignored.
+    generation: u64,
+    /// When and where the timer was created, see `leaks`.
+    created: Created
+}
+
+pub type Timer<'lua> = Object<'lua, TimerState>;
+
+impl UserData for TimerState {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        object::default_add_methods(methods);
+        class::add_eq_by_id(methods, |state: &TimerState| state.id);
+    }
+}
+
+impl<'lua> Timer<'lua> {
+    pub fn new(lua: rlua::Context<'lua>, args: Table<'lua>) -> rlua::Result<Timer<'lua>> {
+        let class = class::class_setup(lua, "timer")?;
+        let mut timer = Timer::allocate(lua, class)?
+            .handle_constructor_argument_with_schema(args, &TIMER_SCHEMA)?
+            .build();
+        timer.state_mut()?.created = Created::now(lua);
+        Ok(timer)
+    }
+
+    pub fn started(&self) -> rlua::Result<bool> {
+        Ok(self.state()?.source.is_some())
+    }
+
+    pub fn start(&mut self, lua: rlua::Context<'lua>) -> rlua::Result<()> {
+        if self.started()? {
+            warn!("timer: already started");
+            return Ok(());
+        }
+        let (TimerId(id), generation, timeout) = {
+            let mut state = self.state_mut()?;
+            if !(state.timeout > 0.0) {
+                return Err(rlua::Error::RuntimeError(format!(
+                    "timer: the timeout must be positive, got {}",
+                    state.timeout
+                )));
+            }
+            state.generation += 1;
+            (state.id, state.generation, state.timeout)
+        };
+        let millis = (timeout * 1000.0).round().max(1.0) as u32;
+        let source = glib::timeout_add(millis, move || {
+            scheduler::defer(Priority::Timer, move |lua| timed_out(lua, id, generation));
+            Continue(true)
+        });
+        self.state_mut()?.source = Some(source);
+        let timers = lua.named_registry_value::<str, Table>(TIMERS_HANDLE)?;
+        timers.set(id, self.clone())?;
+        Object::emit_signal(lua, self, "start", Value::Nil)
+    }
+
+    pub fn stop(&mut self, lua: rlua::Context<'lua>) -> rlua::Result<()> {
+        let (TimerId(id), source) = {
+            let mut state = self.state_mut()?;
+            (state.id, state.source.take())
+        };
+        let source = match source {
+            Some(source) => source,
+            None => {
+                warn!("timer: not started");
+                return Ok(());
+            }
+        };
+        glib::source_remove(source);
+        let timers = lua.named_registry_value::<str, Table>(TIMERS_HANDLE)?;
+        timers.set(id, Value::Nil)?;
+        Object::emit_signal(lua, self, "stop", Value::Nil)
+    }
+
+    /// Starts the timer over, whether it was started or not.
+    pub fn again(&mut self, lua: rlua::Context<'lua>) -> rlua::Result<()> {
+        if self.started()? {
+            self.stop(lua)?;
+        }
+        self.start(lua)
+    }
+}
+
+/// Emits "timeout" on the timer, if it's still started the same way as
+/// when the timeout was queued.
+fn timed_out(lua: rlua::Context, id: usize, generation: u64) -> rlua::Result<()> {
+    let timers = lua.named_registry_value::<str, Table>(TIMERS_HANDLE)?;
+    let mut timer = match timers.get::<_, Option<Timer>>(id)? {
+        Some(timer) => timer,
+        None => return Ok(())
+    };
+    let single_shot = {
+        let state = timer.state()?;
+        if state.generation != generation {
+            return Ok(());
+        }
+        state.single_shot
+    };
+    if single_shot {
+        timer.stop(lua)?;
+    }
+    Object::emit_signal(lua, &timer, "timeout", Value::Nil)
+}
+
+/// Forgets the timers that are kept as started but aren't anymore, see
+/// `leaks`. A started timer belongs to the client, it runs until stopped
+/// whether Lua refers to it or not.
+pub fn sweep(lua: rlua::Context) -> rlua::Result<Vec<Finding>> {
+    let timers = lua.named_registry_value::<str, Table>(TIMERS_HANDLE)?;
+    let mut findings = Vec::new();
+    for pair in timers.clone().pairs::<usize, Timer>() {
+        let (id, timer) = pair?;
+        if timer.started()? {
+            continue;
+        }
+        timers.set(id, Value::Nil)?;
+        findings.push(Finding {
+            category: "timers",
+            id,
+            created: Some(timer.state()?.created.clone())
+        });
+    }
+    Ok(findings)
+}
+
+pub fn init(lua: rlua::Context) -> rlua::Result<Class<TimerState>> {
+    lua.set_named_registry_value(TIMERS_HANDLE, lua.create_table()?)?;
+    ClassDef::new(lua, "timer")?
+        .constructor(Timer::new)?
+        .property("timeout", get_timeout, set_timeout)?
+        .property("single_shot", get_single_shot, set_single_shot)?
+        .read_only("started", get_started)?
+        .read_only("id", get_id)?
+        .object_method("start", |lua, mut timer: Timer| timer.start(lua))?
+        .object_method("stop", |lua, mut timer: Timer| timer.stop(lua))?
+        .object_method("again", |lua, mut timer: Timer| timer.again(lua))?
+        .save()
+}
+
+/// Sets the timeout in seconds, which restarts the timer if it's started.
+fn set_timeout<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut timer, timeout): (Timer<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    let timeout: f64 = TIMER_SCHEMA.check(lua, "timeout", timeout)?;
+    if !(timeout > 0.0) {
+        return Err(rlua::Error::RuntimeError(format!(
+            "timer.timeout: expected a positive number, got {}",
+            timeout
+        )));
+    }
+    timer.state_mut()?.timeout = timeout;
+    if timer.started()? {
+        timer.again(lua)?;
+    }
+    Ok(())
+}
+
+fn get_timeout<'lua>(_: rlua::Context<'lua>, timer: Timer<'lua>) -> rlua::Result<f64> {
+    Ok(timer.state()?.timeout)
+}
+
+fn set_single_shot<'lua>(
+    lua: rlua::Context<'lua>,
+    (mut timer, single_shot): (Timer<'lua>, Value<'lua>)
+) -> rlua::Result<()> {
+    timer.state_mut()?.single_shot = TIMER_SCHEMA.check(lua, "single_shot", single_shot)?;
+    Ok(())
+}
+
+fn get_single_shot<'lua>(_: rlua::Context<'lua>, timer: Timer<'lua>) -> rlua::Result<bool> {
+    Ok(timer.state()?.single_shot)
+}
+
+fn get_started<'lua>(_: rlua::Context<'lua>, timer: Timer<'lua>) -> rlua::Result<bool> {
+    timer.started()
+}
+
+fn get_id<'lua>(_: rlua::Context<'lua>, timer: Timer<'lua>) -> rlua::Result<LuaInteger> {
+    let TimerId(id) = timer.state()?.id;
+    Ok(id as LuaInteger)
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::timer;
+    use super::Timer;
+    use rlua::{self, Lua};
+
+    #[test]
+    fn timer_property_signals() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|ctx| {
+            timer::init(ctx)?;
+            ctx.load(
+                r#"
+local t = timer{ timeout = "1.5", single_shot = true }
+assert(t.timeout == 1.5 and t.single_shot and not t.started)
+local changed = {}
+t:connect_signal("property::timeout", function(t) changed[#changed + 1] = t.timeout end)
+t.timeout = 2
+t.single_shot = false
+assert(#changed == 1 and changed[1] == 2)
+assert(not pcall(function() t.timeout = 0 end))
+assert(t.timeout == 2)
+                "#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn timer_start_stop() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|ctx| {
+            timer::init(ctx)?;
+            ctx.load(
+                r#"
+local t = timer{ timeout = 10 }
+local events = {}
+t:connect_signal("start", function() events[#events + 1] = "start" end)
+t:connect_signal("stop", function() events[#events + 1] = "stop" end)
+t:start()
+assert(t.started)
+t:again()
+t:stop()
+assert(not t.started)
+assert(table.concat(events, " ") == "start stop start stop")
+assert(not pcall(function() timer{}:start() end))
+                "#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn timer_sweep_forgets_stopped() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|ctx| {
+            timer::init(ctx)?;
+            let running = ctx.load("timer{ timeout = 10 }").eval::<Timer>()?;
+            let mut lost = ctx.load("timer{ timeout = 10 }").eval::<Timer>()?;
+            ctx.load("local t = ...; t:start()")
+                .call::<_, ()>(running.clone())?;
+            lost.start(ctx)?;
+            // Stopped without being forgotten, like after an error halfway
+            // through stopping it.
+            if let Some(source) = lost.state_mut()?.source.take() {
+                glib::source_remove(source);
+            }
+            let findings = timer::sweep(ctx)?;
+            assert_eq!(findings.len(), 1);
+            assert_eq!(findings[0].id, lost.state()?.id.0);
+            assert!(timer::sweep(ctx)?.is_empty());
+            assert!(running.started()?);
+            Ok(())
+        })
+    }
+}
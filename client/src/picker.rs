@@ -0,0 +1,816 @@
+//! Lets the user pick a rectangle or a point on the screens with the
+//! pointer, e.g. for screenshots and zooming, see `awesome.pick_geometry`.
+//!
+//! While picking every screen is covered by a dimmed drawin on the overlay
+//! layer. Dragging with the left button cuts the rectangle out of the
+//! dimming, optionally with its size next to the pointer, and releasing the
+//! button picks it. The right button and Escape cancel.
+//!
+//! The overlays are removed before the callback runs, so they're gone even
+//! if it errors. Connecting an output cancels the pick, since the overlays
+//! don't cover the new screen.
+
+mod selection;
+
+use std::cell::RefCell;
+
+use cairo::{Context, Operator};
+use rlua::{self, Function, Table, ToLua, Value};
+
+use crate::area::{Area, Origin, Size};
+use crate::common::{color::Color, font::Font, text};
+use crate::l10n::{self, Arg, Args};
+use crate::objects::{
+    drawin::Drawin,
+    screen::{Screen, SCREENS_HANDLE}
+};
+use crate::scheduler::{self, Priority};
+
+use self::selection::{Mode, Options, Outcome, Selection};
+
+const PICKER_CALLBACK: &str = "__picker_callback";
+const PICKER_OVERLAYS: &str = "__picker_overlays";
+
+/// The dimming of the screens outside of the rectangle.
+const BACKDROP: Color = Color {
+    red: 0.0,
+    green: 0.0,
+    blue: 0.0,
+    alpha: 0.5
+};
+const BORDER: Color = Color {
+    red: 1.0,
+    green: 1.0,
+    blue: 1.0,
+    alpha: 0.9
+};
+const BORDER_WIDTH: i32 = 2;
+const LABEL_FONT: &str = "Sans";
+const LABEL_FONT_SIZE: f64 = 11.0;
+/// The box the size is shown in, below and to the right of the pointer.
+const LABEL_SIZE: Size = Size {
+    width: 96,
+    height: 20
+};
+const LABEL_OFFSET: i32 = 16;
+
+thread_local! {
+    static PICK: RefCell<Option<Pick>> = RefCell::new(None);
+}
+
+struct Pick {
+    selection: Selection,
+    /// The geometries of the screens, which the overlays are shown on in
+    /// the same order.
+    screens: Vec<Area>,
+    /// Where the feedback was last drawn on every overlay that's shown, in
+    /// the coordinates of the overlay.
+    drawn: Vec<Option<Area>>,
+    redraw_queued: bool,
+    /// Whether Escape is taken with the keygrabber.
+    grabbing_keyboard: bool
+}
+
+/// `awesome.pick_geometry(options, callback)`.
+///
+/// `options` can have the `mode`, "area" or "point", an `aspect_ratio` of
+/// width to height, the `min_width` and `min_height` of the rectangle and
+/// whether to `show_dimensions`. The callback gets the rectangle, or the
+/// point as `{ x, y }`, and the screen it's on, or nil if it was cancelled.
+pub fn pick_geometry<'lua>(
+    lua: rlua::Context<'lua>,
+    (options, callback): (Option<Table<'lua>>, Function<'lua>)
+) -> rlua::Result<()> {
+    if running() {
+        return Err(rlua::Error::RuntimeError(
+            "awesome.pick_geometry: a pick is already running".into()
+        ));
+    }
+    let options = match options {
+        Some(options) => parse_options(options)?,
+        None => Options::default()
+    };
+    let mut screens = Vec::new();
+    for screen in lua.named_registry_value::<str, Vec<Screen>>(SCREENS_HANDLE)? {
+        let state = screen.state()?;
+        if state.valid {
+            screens.push(state.geometry);
+        }
+    }
+    if screens.is_empty() {
+        return Err(rlua::Error::RuntimeError(
+            "awesome.pick_geometry: there is no screen to pick on".into()
+        ));
+    }
+    begin(lua, options, callback, screens)?;
+    if let Err(err) = show_overlays(lua).and_then(|_| grab_keyboard(lua)) {
+        teardown(lua)?;
+        return Err(err);
+    }
+    Ok(())
+}
+
+fn parse_options(options: Table) -> rlua::Result<Options> {
+    let invalid = |message: String| rlua::Error::RuntimeError(format!("awesome.pick_geometry: {}", message));
+    let mode = match options.get::<_, Option<String>>("mode")? {
+        Some(name) => Mode::from_name(&name).ok_or_else(|| {
+            invalid(format!(
+                "expected the mode to be one of \"{}\", got \"{}\"",
+                Mode::NAMES.join("\", \""),
+                name
+            ))
+        })?,
+        None => Mode::Area
+    };
+    let aspect_ratio = options.get::<_, Option<f64>>("aspect_ratio")?;
+    if let Some(ratio) = aspect_ratio {
+        if !ratio.is_finite() || ratio <= 0.0 {
+            return Err(invalid(format!(
+                "the aspect ratio must be positive, got {}",
+                ratio
+            )));
+        }
+    }
+    Ok(Options {
+        mode,
+        aspect_ratio,
+        min_size: Size {
+            width: options.get::<_, Option<u32>>("min_width")?.unwrap_or(0),
+            height: options.get::<_, Option<u32>>("min_height")?.unwrap_or(0)
+        },
+        show_dimensions: options
+            .get::<_, Option<bool>>("show_dimensions")?
+            .unwrap_or(false)
+    })
+}
+
+fn running() -> bool {
+    PICK.with(|pick| pick.borrow().is_some())
+}
+
+/// Starts picking on `screens`, without showing anything yet.
+fn begin<'lua>(
+    lua: rlua::Context<'lua>,
+    options: Options,
+    callback: Function<'lua>,
+    screens: Vec<Area>
+) -> rlua::Result<()> {
+    lua.set_named_registry_value(PICKER_CALLBACK, callback)?;
+    PICK.with(|pick| {
+        *pick.borrow_mut() = Some(Pick {
+            selection: Selection::new(options),
+            screens,
+            drawn: Vec::new(),
+            redraw_queued: false,
+            grabbing_keyboard: false
+        })
+    });
+    Ok(())
+}
+
+/// Covers every screen with an overlay.
+fn show_overlays(lua: rlua::Context) -> rlua::Result<()> {
+    let screens = PICK.with(|pick| pick.borrow().as_ref().map(|pick| pick.screens.clone()));
+    let mut overlays = Vec::new();
+    for screen in screens.unwrap_or_default() {
+        let mut overlay = create_overlay(lua, screen)?;
+        // Saved first, so it's removed if showing it fails.
+        overlays.push(overlay.clone());
+        lua.set_named_registry_value(PICKER_OVERLAYS, overlays.clone().to_lua(lua)?)?;
+        overlay.set_visible(lua, true)?;
+    }
+    PICK.with(|pick| {
+        if let Some(pick) = pick.borrow_mut().as_mut() {
+            pick.drawn = vec![None; overlays.len()];
+        }
+    });
+    // The first frame dims all of every overlay.
+    for (index, overlay) in overlays.iter().enumerate() {
+        paint(lua, index, overlay, None)?;
+    }
+    Ok(())
+}
+
+/// Makes the overlay that covers `screen`, which is hidden.
+fn create_overlay(lua: rlua::Context, screen: Area) -> rlua::Result<Drawin> {
+    let args = lua.create_table()?;
+    args.set("x", screen.origin.x)?;
+    args.set("y", screen.origin.y)?;
+    args.set("width", screen.size.width)?;
+    args.set("height", screen.size.height)?;
+    args.set("ontop", true)?;
+    args.set("cursor", "cross")?;
+    let overlay = Drawin::new(lua, args)?;
+    Drawin::connect_signal(lua, &overlay, "button::press", lua.create_function(button_press)?)?;
+    Drawin::connect_signal(
+        lua,
+        &overlay,
+        "button::release",
+        lua.create_function(button_release)?
+    )?;
+    Drawin::connect_signal(lua, &overlay, "mouse::move", lua.create_function(mouse_move)?)?;
+    Ok(overlay)
+}
+
+/// Lets Escape cancel, unless Lua is grabbing the keyboard already.
+fn grab_keyboard(lua: rlua::Context) -> rlua::Result<()> {
+    if crate::keygrabber::is_keygrabber_set(lua) {
+        return Ok(());
+    }
+    let keygrabber = lua.globals().get::<_, Table>("keygrabber")?;
+    keygrabber
+        .get::<_, Function>("run")?
+        .call::<_, ()>(lua.create_function(handle_key)?)?;
+    PICK.with(|pick| {
+        if let Some(pick) = pick.borrow_mut().as_mut() {
+            pick.grabbing_keyboard = true;
+        }
+    });
+    Ok(())
+}
+
+/// The keygrabber callback while picking.
+fn handle_key<'lua>(
+    lua: rlua::Context<'lua>,
+    (_, key, event): (Value<'lua>, String, String)
+) -> rlua::Result<()> {
+    if key == "Escape" && event == "press" {
+        finish(lua, None)?;
+    }
+    Ok(())
+}
+
+/// Cancels the pick, since the overlays don't cover the screens anymore.
+pub fn outputs_changed(lua: rlua::Context) -> rlua::Result<()> {
+    if let Err(err) = finish(lua, None) {
+        warn!("The callback of the geometry pick failed: {}", err);
+    }
+    Ok(())
+}
+
+/// Stops picking and calls the callback with what was picked.
+fn finish(lua: rlua::Context, picked: Option<Area>) -> rlua::Result<()> {
+    let (pick, callback) = match teardown(lua)? {
+        Some((pick, Some(callback))) => (pick, callback),
+        _ => return Ok(())
+    };
+    let picked = match picked {
+        Some(area) => {
+            let area = area_table(lua, area, pick.selection.options().mode)?;
+            let screen = screen_of(lua, area_origin(&area)?)?;
+            (area.to_lua(lua)?, screen.to_lua(lua)?)
+        },
+        None => (Value::Nil, Value::Nil)
+    };
+    callback.call(picked)
+}
+
+/// Forgets the pick and removes the overlays, returning it and the
+/// callback.
+fn teardown<'lua>(lua: rlua::Context<'lua>) -> rlua::Result<Option<(Pick, Option<Function<'lua>>)>> {
+    let pick = match PICK.with(|pick| pick.borrow_mut().take()) {
+        Some(pick) => pick,
+        None => return Ok(None)
+    };
+    let callback = lua.named_registry_value::<str, Option<Function>>(PICKER_CALLBACK)?;
+    lua.set_named_registry_value(PICKER_CALLBACK, Value::Nil)?;
+    let overlays = lua.named_registry_value::<str, Option<Vec<Drawin>>>(PICKER_OVERLAYS)?;
+    lua.set_named_registry_value(PICKER_OVERLAYS, Value::Nil)?;
+    for mut overlay in overlays.unwrap_or_default() {
+        if let Err(err) = overlay.remove(lua) {
+            warn!("Could not remove an overlay of the geometry pick: {}", err);
+        }
+    }
+    if pick.grabbing_keyboard {
+        let keygrabber = lua.globals().get::<_, Table>("keygrabber")?;
+        keygrabber.get::<_, Function>("stop")?.call::<_, ()>(())?;
+    }
+    Ok(Some((pick, callback)))
+}
+
+/// The table Lua gets `area` as, which is only a point in the point mode.
+fn area_table(lua: rlua::Context, area: Area, mode: Mode) -> rlua::Result<Table> {
+    let table = lua.create_table()?;
+    table.set("x", area.origin.x)?;
+    table.set("y", area.origin.y)?;
+    if mode == Mode::Area {
+        table.set("width", area.size.width)?;
+        table.set("height", area.size.height)?;
+    }
+    Ok(table)
+}
+
+fn area_origin(area: &Table) -> rlua::Result<Origin> {
+    Ok(Origin {
+        x: area.get("x")?,
+        y: area.get("y")?
+    })
+}
+
+fn screen_of(lua: rlua::Context, point: Origin) -> rlua::Result<Option<Screen>> {
+    for screen in lua.named_registry_value::<str, Vec<Screen>>(SCREENS_HANDLE)? {
+        if screen.state()?.geometry.contains(point) {
+            return Ok(Some(screen));
+        }
+    }
+    Ok(None)
+}
+
+/// Where `(x, y)` on `overlay` is on the screens.
+fn global_point(overlay: &Drawin, x: f64, y: f64) -> rlua::Result<Origin> {
+    let origin = overlay.get_geometry()?.origin;
+    Ok(Origin {
+        x: origin.x + x.floor() as i32,
+        y: origin.y + y.floor() as i32
+    })
+}
+
+fn button_press<'lua>(
+    lua: rlua::Context<'lua>,
+    (overlay, x, y, button): (Drawin<'lua>, f64, f64, u32)
+) -> rlua::Result<()> {
+    press(lua, global_point(&overlay, x, y)?, button)
+}
+
+fn button_release<'lua>(
+    lua: rlua::Context<'lua>,
+    (overlay, x, y, button): (Drawin<'lua>, f64, f64, u32)
+) -> rlua::Result<()> {
+    release(lua, global_point(&overlay, x, y)?, button)
+}
+
+fn mouse_move<'lua>(lua: rlua::Context<'lua>, (overlay, x, y): (Drawin<'lua>, f64, f64)) -> rlua::Result<()> {
+    motion(lua, global_point(&overlay, x, y)?)
+}
+
+fn press(lua: rlua::Context, point: Origin, button: u32) -> rlua::Result<()> {
+    let outcome = PICK.with(|pick| {
+        let mut pick = pick.borrow_mut();
+        let pick = pick.as_mut()?;
+        let screen = pick
+            .screens
+            .iter()
+            .find(|screen| screen.contains(point))
+            .copied()?;
+        Some(pick.selection.press(point, screen, button))
+    });
+    outcome.map_or(Ok(()), |outcome| settle(lua, outcome))
+}
+
+fn release(lua: rlua::Context, point: Origin, button: u32) -> rlua::Result<()> {
+    let outcome = PICK.with(|pick| {
+        let mut pick = pick.borrow_mut();
+        pick.as_mut().map(|pick| pick.selection.release(point, button))
+    });
+    outcome.map_or(Ok(()), |outcome| settle(lua, outcome))
+}
+
+fn motion(lua: rlua::Context, point: Origin) -> rlua::Result<()> {
+    PICK.with(|pick| {
+        if let Some(pick) = pick.borrow_mut().as_mut() {
+            pick.selection.motion(point);
+        }
+    });
+    settle(lua, Outcome::Pending)
+}
+
+fn settle(lua: rlua::Context, outcome: Outcome) -> rlua::Result<()> {
+    match outcome {
+        Outcome::Pending => {
+            queue_redraw();
+            Ok(())
+        },
+        Outcome::Picked(area) => finish(lua, Some(area)),
+        Outcome::Cancelled => finish(lua, None)
+    }
+}
+
+/// Redraws the overlays once the input of this iteration of the main loop
+/// was handled, so a flood of motion only draws once a frame.
+fn queue_redraw() {
+    let queue = PICK.with(|pick| match pick.borrow_mut().as_mut() {
+        Some(pick) if !pick.drawn.is_empty() => !std::mem::replace(&mut pick.redraw_queued, true),
+        _ => false
+    });
+    if queue {
+        scheduler::defer(Priority::Redraw, redraw);
+    }
+}
+
+fn redraw(lua: rlua::Context) -> rlua::Result<()> {
+    let queued = PICK.with(|pick| match pick.borrow_mut().as_mut() {
+        Some(pick) => std::mem::replace(&mut pick.redraw_queued, false),
+        None => false
+    });
+    if !queued {
+        return Ok(());
+    }
+    let overlays = lua.named_registry_value::<str, Option<Vec<Drawin>>>(PICKER_OVERLAYS)?;
+    for (index, overlay) in overlays.unwrap_or_default().iter().enumerate() {
+        let feedback = PICK.with(|pick| {
+            let pick = pick.borrow();
+            let pick = pick.as_ref()?;
+            let screen = *pick.screens.get(index)?;
+            Some((
+                pick.drawn.get(index).copied().flatten(),
+                feedback_bounds(screen, &pick.selection)
+            ))
+        });
+        let (drawn, bounds) = match feedback {
+            Some(feedback) => feedback,
+            None => continue
+        };
+        // Only where the feedback was or is now changes.
+        let damage = match (drawn, bounds) {
+            (Some(drawn), Some(bounds)) => union(drawn, bounds),
+            (Some(area), None) | (None, Some(area)) => area,
+            (None, None) => continue
+        };
+        paint(lua, index, overlay, Some(damage))?;
+    }
+    Ok(())
+}
+
+/// Draws the feedback on the overlay at `index`, within `damage` or all of
+/// it.
+fn paint<'lua>(
+    lua: rlua::Context<'lua>,
+    index: usize,
+    overlay: &Drawin<'lua>,
+    damage: Option<Area>
+) -> rlua::Result<()> {
+    let mut drawable = overlay.drawable()?;
+    let drawn = {
+        let state = drawable.state()?;
+        let surface = match state.surface.as_ref() {
+            Some(surface) => surface,
+            None => return Ok(())
+        };
+        PICK.with(|pick| {
+            let pick = pick.borrow();
+            let pick = pick.as_ref()?;
+            let screen = *pick.screens.get(index)?;
+            let clip = damage.unwrap_or(Area {
+                origin: Origin::default(),
+                size: screen.size
+            });
+            draw(&Context::new(surface), screen, &pick.selection, clip);
+            Some(feedback_bounds(screen, &pick.selection))
+        })
+    };
+    let drawn = match drawn {
+        Some(drawn) => drawn,
+        None => return Ok(())
+    };
+    PICK.with(|pick| {
+        if let Some(slot) = pick
+            .borrow_mut()
+            .as_mut()
+            .and_then(|pick| pick.drawn.get_mut(index))
+        {
+            *slot = drawn;
+        }
+    });
+    if let Some(damage) = damage {
+        drawable.add_damage(damage)?;
+    }
+    drawable.refresh(lua)
+}
+
+/// The part of the overlay of `screen` the feedback for `selection` is
+/// drawn in, in the coordinates of the overlay.
+fn feedback_bounds(screen: Area, selection: &Selection) -> Option<Area> {
+    let rect = selection.rect()?;
+    let local = Origin {
+        x: rect.origin.x - screen.origin.x - BORDER_WIDTH,
+        y: rect.origin.y - screen.origin.y - BORDER_WIDTH
+    };
+    let mut bounds = Area {
+        origin: local,
+        size: Size {
+            width: rect.size.width + 2 * BORDER_WIDTH as u32,
+            height: rect.size.height + 2 * BORDER_WIDTH as u32
+        }
+    };
+    if let Some(label) = label_area(screen, selection) {
+        bounds = union(bounds, label);
+    }
+    bounds.intersection(Area {
+        origin: Origin::default(),
+        size: screen.size
+    })
+}
+
+/// Where the size of the rectangle is shown, if it is.
+fn label_area(screen: Area, selection: &Selection) -> Option<Area> {
+    if !selection.options().show_dimensions || selection.rect().is_none() {
+        return None;
+    }
+    let cursor = selection.cursor()?;
+    Some(Area {
+        origin: Origin {
+            x: cursor.x - screen.origin.x + LABEL_OFFSET,
+            y: cursor.y - screen.origin.y + LABEL_OFFSET
+        },
+        size: LABEL_SIZE
+    })
+}
+
+fn union(first: Area, second: Area) -> Area {
+    let x = first.origin.x.min(second.origin.x);
+    let y = first.origin.y.min(second.origin.y);
+    Area {
+        origin: Origin { x, y },
+        size: Size {
+            width: (first.right().max(second.right()) - x) as u32,
+            height: (first.bottom().max(second.bottom()) - y) as u32
+        }
+    }
+}
+
+/// Draws the dimming of the overlay of `screen` within `clip`, with the
+/// rectangle of `selection` cut out of it.
+fn draw(cr: &Context, screen: Area, selection: &Selection, clip: Area) {
+    let set_source = |color: Color| cr.set_source_rgba(color.red, color.green, color.blue, color.alpha);
+    cr.rectangle(
+        f64::from(clip.origin.x),
+        f64::from(clip.origin.y),
+        f64::from(clip.size.width),
+        f64::from(clip.size.height)
+    );
+    cr.clip();
+    cr.set_operator(Operator::Source);
+    set_source(BACKDROP);
+    cr.paint();
+    let rect = match selection.rect() {
+        Some(rect) => rect,
+        None => return
+    };
+    let (x, y) = (
+        f64::from(rect.origin.x - screen.origin.x),
+        f64::from(rect.origin.y - screen.origin.y)
+    );
+    let (width, height) = (f64::from(rect.size.width), f64::from(rect.size.height));
+    cr.rectangle(x, y, width, height);
+    cr.set_source_rgba(0.0, 0.0, 0.0, 0.0);
+    cr.fill();
+    cr.set_operator(Operator::Over);
+    let border_width = f64::from(BORDER_WIDTH);
+    set_source(BORDER);
+    cr.set_line_width(border_width);
+    cr.rectangle(
+        x - border_width / 2.0,
+        y - border_width / 2.0,
+        width + border_width,
+        height + border_width
+    );
+    cr.stroke();
+    if let Some(label) = label_area(screen, selection) {
+        cr.rectangle(
+            f64::from(label.origin.x),
+            f64::from(label.origin.y),
+            f64::from(label.size.width),
+            f64::from(label.size.height)
+        );
+        set_source(BACKDROP);
+        cr.fill();
+        text::select_font(
+            cr,
+            &Font {
+                family: LABEL_FONT.into(),
+                size: LABEL_FONT_SIZE
+            }
+        );
+        let extents = cr.font_extents();
+        let baseline = (f64::from(LABEL_SIZE.height) - extents.height) / 2.0 + extents.ascent;
+        set_source(BORDER);
+        cr.move_to(
+            f64::from(label.origin.x) + 4.0,
+            f64::from(label.origin.y) + baseline
+        );
+        let dimensions = Args::positional(vec![
+            Arg::Integer(i64::from(rect.size.width)),
+            Arg::Integer(i64::from(rect.size.height)),
+        ]);
+        text::draw_text(cr, &l10n::message("picker.dimensions", &dimensions));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rlua::{self, Lua, MultiValue, Table, ToLua, Value};
+
+    use super::*;
+    use crate::common::signal;
+    use crate::objects::{
+        drawable,
+        drawin::{self, DRAWINS_HANDLE}
+    };
+
+    const LEFT: Area = Area {
+        origin: Origin { x: 0, y: 0 },
+        size: Size {
+            width: 800,
+            height: 600
+        }
+    };
+    const RIGHT: Area = Area {
+        origin: Origin { x: 800, y: 0 },
+        size: Size {
+            width: 1024,
+            height: 768
+        }
+    };
+
+    /// Starts a pick on two screens with hidden overlays, whose callback
+    /// saves what it got in `picked`.
+    fn start<'lua>(lua: rlua::Context<'lua>, options: Options) -> rlua::Result<Vec<Drawin<'lua>>> {
+        lua.load("picked = nil; calls = 0").exec()?;
+        let callback = lua
+            .load("function(area, screen) picked = area; calls = calls + 1 end")
+            .eval::<Function>()?;
+        begin(lua, options, callback, vec![LEFT, RIGHT])?;
+        let overlays = vec![create_overlay(lua, LEFT)?, create_overlay(lua, RIGHT)?];
+        lua.set_named_registry_value(PICKER_OVERLAYS, overlays.clone().to_lua(lua)?)?;
+        Ok(overlays)
+    }
+
+    fn setup(lua: rlua::Context) -> rlua::Result<()> {
+        drawable::init(lua)?;
+        drawin::init(lua)?;
+        lua.set_named_registry_value(SCREENS_HANDLE, lua.create_table()?)
+    }
+
+    /// Emits a pointer signal on `overlay`, at `(x, y)` on it.
+    fn pointer<'lua>(
+        lua: rlua::Context<'lua>,
+        overlay: &Drawin<'lua>,
+        signal: &str,
+        (x, y): (f64, f64),
+        button: Option<u32>
+    ) -> rlua::Result<()> {
+        let mut args = vec![overlay.clone().to_lua(lua)?, Value::Number(x), Value::Number(y)];
+        if let Some(button) = button {
+            args.push(Value::Integer(button.into()));
+            args.push(Value::Table(lua.create_table()?));
+        }
+        args.push(Value::Number(0.0));
+        signal::emit_signals(lua, overlay.signals()?, signal, MultiValue::from_vec(args))
+    }
+
+    /// Whether the pick was forgotten and its overlays were removed.
+    fn cleaned_up(lua: rlua::Context) -> rlua::Result<bool> {
+        let drawins = lua.named_registry_value::<str, Table>(DRAWINS_HANDLE)?;
+        let unset = |key: &str| -> rlua::Result<bool> {
+            Ok(match lua.named_registry_value::<str, Value>(key)? {
+                Value::Nil => true,
+                _ => false
+            })
+        };
+        Ok(!running() && drawins.raw_len() == 0 && unset(PICKER_OVERLAYS)? && unset(PICKER_CALLBACK)?)
+    }
+
+    #[test]
+    fn picker_drags_rectangle() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            setup(lua)?;
+            let overlays = start(lua, Options::default())?;
+            pointer(lua, &overlays[1], "mouse::move", (100.5, 100.5), None)?;
+            pointer(lua, &overlays[1], "button::press", (100.5, 100.5), Some(1))?;
+            pointer(lua, &overlays[1], "mouse::move", (50.0, 50.0), None)?;
+            // The press grabs the pointer, so the overlay the drag started on
+            // keeps getting the events when it's over the other screen.
+            pointer(lua, &overlays[1], "mouse::move", (-100.0, 200.0), None)?;
+            lua.load("assert(calls == 0)").exec()?;
+            pointer(lua, &overlays[1], "button::release", (-100.0, 200.0), Some(1))?;
+            lua.load(
+                r#"
+assert(calls == 1)
+assert(picked.x == 800 and picked.y == 100)
+assert(picked.width == 100 and picked.height == 100)
+                "#
+            )
+            .exec()?;
+            assert!(cleaned_up(lua)?);
+            // The overlays don't react anymore.
+            pointer(lua, &overlays[0], "button::press", (10.0, 10.0), Some(1))?;
+            lua.load("assert(calls == 1)").exec()
+        })
+    }
+
+    #[test]
+    fn picker_point_mode() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            setup(lua)?;
+            let overlays = start(
+                lua,
+                Options {
+                    mode: Mode::Point,
+                    ..Options::default()
+                }
+            )?;
+            pointer(lua, &overlays[0], "button::press", (10.0, 20.0), Some(1))?;
+            lua.load("assert(calls == 1 and picked.x == 10 and picked.y == 20 and picked.width == nil)")
+                .exec()?;
+            assert!(cleaned_up(lua)?);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn picker_cancelled() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            setup(lua)?;
+            let overlays = start(lua, Options::default())?;
+            pointer(lua, &overlays[0], "button::press", (10.0, 20.0), Some(1))?;
+            pointer(lua, &overlays[0], "button::press", (30.0, 40.0), Some(3))?;
+            lua.load("assert(calls == 1 and picked == nil)").exec()?;
+            assert!(cleaned_up(lua)?);
+
+            start(lua, Options::default())?;
+            handle_key(lua, (Value::Nil, "Escape".into(), "release".into()))?;
+            assert!(running());
+            handle_key(lua, (Value::Nil, "Escape".into(), "press".into()))?;
+            lua.load("assert(calls == 1 and picked == nil)").exec()?;
+            assert!(cleaned_up(lua)?);
+
+            // A screen was connected mid-drag.
+            let overlays = start(lua, Options::default())?;
+            pointer(lua, &overlays[0], "button::press", (10.0, 20.0), Some(1))?;
+            outputs_changed(lua)?;
+            lua.load("assert(calls == 1 and picked == nil)").exec()?;
+            assert!(cleaned_up(lua)?);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn picker_callback_errors() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            setup(lua)?;
+            let callback = lua.load("function() error('broken') end").eval::<Function>()?;
+            begin(lua, Options::default(), callback, vec![LEFT])?;
+            let overlay = create_overlay(lua, LEFT)?;
+            lua.set_named_registry_value(PICKER_OVERLAYS, vec![overlay].to_lua(lua)?)?;
+            press(lua, Origin { x: 10, y: 10 }, 1)?;
+            assert!(release(lua, Origin { x: 20, y: 20 }, 1).is_err());
+            assert!(cleaned_up(lua)?);
+            // The same goes for a cancelled pick.
+            let callback = lua.load("function() error('broken') end").eval::<Function>()?;
+            begin(lua, Options::default(), callback, vec![LEFT])?;
+            assert!(press(lua, Origin { x: 10, y: 10 }, 3).is_err());
+            assert!(cleaned_up(lua)?);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn picker_draws_cut_out() {
+        let mut surface = cairo::ImageSurface::create(cairo::Format::ARgb32, 100, 100).unwrap();
+        let screen = Area {
+            origin: Origin { x: 800, y: 0 },
+            size: Size {
+                width: 100,
+                height: 100
+            }
+        };
+        let mut selection = Selection::new(Options {
+            show_dimensions: true,
+            ..Options::default()
+        });
+        selection.press(Origin { x: 810, y: 10 }, screen, 1);
+        selection.motion(Origin { x: 850, y: 50 });
+        draw(
+            &Context::new(&surface),
+            screen,
+            &selection,
+            Area {
+                origin: Origin::default(),
+                size: screen.size
+            }
+        );
+        let stride = surface.get_stride() as usize;
+        let data = surface.get_data().unwrap();
+        let alpha = |x: usize, y: usize| data[y * stride + x * 4 + 3];
+        assert!((0x7f..=0x80).contains(&alpha(0, 0)));
+        assert_eq!(alpha(30, 30), 0);
+        // The border, and the box of the label under the pointer.
+        assert!(alpha(9, 30) > 0x80);
+        assert!(alpha(70, 70) > 0x80);
+        assert!((0x7f..=0x80).contains(&alpha(99, 99)));
+        assert_eq!(
+            feedback_bounds(screen, &selection),
+            Some(Area {
+                origin: Origin { x: 8, y: 8 },
+                size: Size {
+                    width: 92,
+                    height: 78
+                }
+            })
+        );
+    }
+}
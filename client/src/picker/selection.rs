@@ -0,0 +1,304 @@
+//! What the user selected so far while picking, in global coordinates.
+
+use crate::area::{Area, Origin, Size};
+
+/// What a pick selects.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Mode {
+    /// A rectangle dragged out with the pointer.
+    Area,
+    /// The point that was clicked.
+    Point
+}
+
+impl Mode {
+    pub const NAMES: &'static [&'static str] = &["area", "point"];
+
+    pub fn from_name(name: &str) -> Option<Mode> {
+        match name {
+            "area" => Some(Mode::Area),
+            "point" => Some(Mode::Point),
+            _ => None
+        }
+    }
+}
+
+impl Default for Mode {
+    fn default() -> Self {
+        Mode::Area
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Options {
+    pub mode: Mode,
+    /// The width the rectangle is kept at for every unit of height.
+    pub aspect_ratio: Option<f64>,
+    /// The size the rectangle is at least, as far as the screen allows.
+    pub min_size: Size,
+    /// Whether the size of the rectangle is shown next to the pointer.
+    pub show_dimensions: bool
+}
+
+/// How a pointer button changed the pick.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Outcome {
+    Pending,
+    Picked(Area),
+    Cancelled
+}
+
+/// The left and right buttons, as numbered for Lua.
+const BUTTON_LEFT: u32 = 1;
+const BUTTON_RIGHT: u32 = 3;
+
+#[derive(Debug)]
+pub struct Selection {
+    options: Options,
+    /// Where the drag started, and the screen it started on.
+    anchor: Option<(Origin, Area)>,
+    /// Where the pointer was last seen.
+    cursor: Option<Origin>
+}
+
+impl Selection {
+    pub fn new(options: Options) -> Self {
+        Selection {
+            options,
+            anchor: None,
+            cursor: None
+        }
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    pub fn cursor(&self) -> Option<Origin> {
+        self.cursor
+    }
+
+    /// `button` was pressed at `point` on `screen`.
+    ///
+    /// The right button cancels, the left one starts the drag or picks the
+    /// point.
+    pub fn press(&mut self, point: Origin, screen: Area, button: u32) -> Outcome {
+        self.cursor = Some(point);
+        match button {
+            BUTTON_RIGHT => Outcome::Cancelled,
+            BUTTON_LEFT if self.options.mode == Mode::Point => Outcome::Picked(Area {
+                origin: point,
+                size: Size::default()
+            }),
+            BUTTON_LEFT if self.anchor.is_none() => {
+                self.anchor = Some((point, screen));
+                Outcome::Pending
+            },
+            _ => Outcome::Pending
+        }
+    }
+
+    pub fn motion(&mut self, point: Origin) {
+        self.cursor = Some(point);
+    }
+
+    /// `button` was released at `point`, which ends the drag if it's the
+    /// left one.
+    pub fn release(&mut self, point: Origin, button: u32) -> Outcome {
+        self.cursor = Some(point);
+        match (button, self.rect()) {
+            (BUTTON_LEFT, Some(rect)) => Outcome::Picked(rect),
+            _ => Outcome::Pending
+        }
+    }
+
+    /// The rectangle selected so far, if a drag started.
+    ///
+    /// It grows from the anchor towards the pointer, is kept at the aspect
+    /// ratio and at least at the minimum size, and stays on the screen the
+    /// drag started on.
+    pub fn rect(&self) -> Option<Area> {
+        let (anchor, screen) = self.anchor?;
+        let cursor = self.cursor.unwrap_or(anchor);
+        let cursor = Origin {
+            x: cursor.x.max(screen.origin.x).min(screen.right()),
+            y: cursor.y.max(screen.origin.y).min(screen.bottom())
+        };
+        let (dx, dy) = (cursor.x - anchor.x, cursor.y - anchor.y);
+        // The room there is from the anchor to the edge of the screen in the
+        // direction of the drag.
+        let room_x = if dx < 0 {
+            anchor.x - screen.origin.x
+        } else {
+            screen.right() - anchor.x
+        };
+        let room_y = if dy < 0 {
+            anchor.y - screen.origin.y
+        } else {
+            screen.bottom() - anchor.y
+        };
+        let (room_x, room_y) = (f64::from(room_x.max(0)), f64::from(room_y.max(0)));
+        let (width, height) = (f64::from(dx.abs()), f64::from(dy.abs()));
+        let ratio = self.options.aspect_ratio;
+        let (width, height) = match ratio {
+            Some(ratio) => grow_to_ratio(width, height, ratio),
+            None => (width, height)
+        };
+        let width = width.max(f64::from(self.options.min_size.width));
+        let height = height.max(f64::from(self.options.min_size.height));
+        let (width, height) = match ratio {
+            Some(ratio) => {
+                let (width, height) = grow_to_ratio(width, height, ratio);
+                let fit = (room_x / width).min(room_y / height).min(1.0);
+                (width * fit, height * fit)
+            },
+            None => (width.min(room_x), height.min(room_y))
+        };
+        let (width, height) = (width.round() as i32, height.round() as i32);
+        Some(Area {
+            origin: Origin {
+                x: if dx < 0 { anchor.x - width } else { anchor.x },
+                y: if dy < 0 { anchor.y - height } else { anchor.y }
+            },
+            size: Size {
+                width: width as u32,
+                height: height as u32
+            }
+        })
+    }
+}
+
+/// Grows the shorter side of `width` by `height` so they're at `ratio`.
+fn grow_to_ratio(width: f64, height: f64, ratio: f64) -> (f64, f64) {
+    if height == 0.0 || width / height > ratio {
+        (width, width / ratio)
+    } else {
+        (height * ratio, height)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SCREEN: Area = Area {
+        origin: Origin { x: 100, y: 0 },
+        size: Size {
+            width: 800,
+            height: 600
+        }
+    };
+
+    fn area(x: i32, y: i32, width: u32, height: u32) -> Area {
+        Area {
+            origin: Origin { x, y },
+            size: Size { width, height }
+        }
+    }
+
+    fn drag(options: Options, from: (i32, i32), to: (i32, i32)) -> Outcome {
+        let mut selection = Selection::new(options);
+        let pending = selection.press(Origin { x: from.0, y: from.1 }, SCREEN, 1);
+        assert_eq!(pending, Outcome::Pending);
+        selection.motion(Origin {
+            x: (from.0 + to.0) / 2,
+            y: (from.1 + to.1) / 2
+        });
+        selection.motion(Origin { x: to.0, y: to.1 });
+        selection.release(Origin { x: to.0, y: to.1 }, 1)
+    }
+
+    #[test]
+    fn selection_drags_out_rectangle() {
+        let options = Options::default();
+        assert_eq!(
+            drag(options, (200, 100), (300, 150)),
+            Outcome::Picked(area(200, 100, 100, 50))
+        );
+        // Dragging up and to the left grows it the other way.
+        assert_eq!(
+            drag(options, (300, 150), (200, 100)),
+            Outcome::Picked(area(200, 100, 100, 50))
+        );
+        // The pointer leaving the screen doesn't take the rectangle along.
+        assert_eq!(
+            drag(options, (800, 500), (2000, -40)),
+            Outcome::Picked(area(800, 0, 100, 500))
+        );
+    }
+
+    #[test]
+    fn selection_aspect_and_minimum() {
+        let options = Options {
+            aspect_ratio: Some(16.0 / 9.0),
+            ..Options::default()
+        };
+        assert_eq!(
+            drag(options, (200, 100), (360, 110)),
+            Outcome::Picked(area(200, 100, 160, 90))
+        );
+        assert_eq!(
+            drag(options, (200, 100), (210, 190)),
+            Outcome::Picked(area(200, 100, 160, 90))
+        );
+        // Near the edge it shrinks instead, still at the ratio.
+        assert_eq!(
+            drag(options, (740, 510), (900, 600)),
+            Outcome::Picked(area(740, 510, 160, 90))
+        );
+        assert_eq!(
+            drag(options, (820, 510), (900, 600)),
+            Outcome::Picked(area(820, 510, 80, 45))
+        );
+        let options = Options {
+            min_size: Size {
+                width: 64,
+                height: 32
+            },
+            ..Options::default()
+        };
+        assert_eq!(
+            drag(options, (200, 100), (210, 200)),
+            Outcome::Picked(area(200, 100, 64, 100))
+        );
+        assert_eq!(
+            drag(options, (200, 100), (190, 90)),
+            Outcome::Picked(area(136, 68, 64, 32))
+        );
+    }
+
+    #[test]
+    fn selection_point_and_cancel() {
+        let mut selection = Selection::new(Options {
+            mode: Mode::Point,
+            ..Options::default()
+        });
+        selection.motion(Origin { x: 150, y: 10 });
+        assert_eq!(
+            selection.press(Origin { x: 150, y: 20 }, SCREEN, 1),
+            Outcome::Picked(area(150, 20, 0, 0))
+        );
+
+        let mut selection = Selection::new(Options::default());
+        assert_eq!(
+            selection.press(Origin { x: 150, y: 20 }, SCREEN, 1),
+            Outcome::Pending
+        );
+        selection.motion(Origin { x: 250, y: 80 });
+        assert_eq!(selection.rect(), Some(area(150, 20, 100, 60)));
+        // Other buttons do nothing, the right one cancels.
+        assert_eq!(
+            selection.press(Origin { x: 250, y: 80 }, SCREEN, 2),
+            Outcome::Pending
+        );
+        assert_eq!(selection.release(Origin { x: 250, y: 80 }, 2), Outcome::Pending);
+        assert_eq!(
+            selection.press(Origin { x: 250, y: 80 }, SCREEN, 3),
+            Outcome::Cancelled
+        );
+        // Releasing a button before a drag started picks nothing.
+        let mut selection = Selection::new(Options::default());
+        assert_eq!(selection.release(Origin { x: 250, y: 80 }, 1), Outcome::Pending);
+        assert_eq!(selection.rect(), None);
+    }
+}
@@ -0,0 +1,408 @@
+//! Resuming coroutines that waited on something asynchronous, like a
+//! spawned command, see `awesome.resume_safely`.
+//!
+//! A coroutine that captured a screen or a drawin before it waited may find
+//! it removed when it's resumed, e.g. because the output was unplugged in
+//! the meantime. Using a removed object raises an `InvalidObject` error,
+//! except for its `valid` and `id` fields, and `screen.by_id(id)` and
+//! `drawin.by_id(id)` find the object again if it's still there.
+//!
+//! Objects are only invalidated between dispatches. One that's removed while
+//! a coroutine runs under `resume_safely` can still be used until the
+//! coroutine yields or returns, and screens are only removed when Wayland
+//! says their output is gone, which is never while Lua runs.
+//!
+//! When objects were removed since a coroutine last yielded to
+//! `resume_safely`, it's first resumed with `{ retry_with_fresh_objects =
+//! true }`, before any of its code touches them. It finds its objects again
+//! by id and yields, and is then resumed a second time with the results:
+//!
+//! ```lua
+//! local function await(start)
+//!     local co = coroutine.running()
+//!     start(function(...) awesome.resume_safely(co, ...) end)
+//!     local results = table.pack(coroutine.yield())
+//!     while type(results[1]) == "table" and results[1].retry_with_fresh_objects do
+//!         s = screen.by_id(s_id) or screen.primary
+//!         results = table.pack(coroutine.yield())
+//!     end
+//!     return table.unpack(results, 1, results.n)
+//! end
+//! ```
+//!
+//! An invalid object error that escapes the coroutine anyway ends it, and is
+//! returned after the error like `coroutine.resume` does, as a table like
+//! `{ kind = "removed", class = "screen", id = 3, retry_with_fresh_objects =
+//! true }`, so the caller can start over.
+
+use std::{cell::RefCell, error::Error, fmt};
+
+use rlua::{self, MultiValue, Table, Thread, ThreadStatus, Value};
+
+/// The coroutines `resume_safely` saw yield, with how many objects were
+/// removed by then. The keys are weak.
+const RESUMED_HANDLE: &str = "__resumed_coroutines";
+
+thread_local! {
+    static RESUMES: RefCell<Resumes> = RefCell::new(Resumes::default());
+}
+
+/// When an object was removed, which decides whether the coroutine being
+/// resumed still sees it, see `is_valid`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Removal(u64);
+
+/// Why an object can't be used anymore.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Kind {
+    /// The screen's output was unplugged.
+    Removed,
+    /// The drawin was removed.
+    Destroyed
+}
+
+impl Kind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Removed => "removed",
+            Kind::Destroyed => "destroyed"
+        }
+    }
+}
+
+/// The error raised when a removed object is used.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct InvalidObject {
+    pub kind: Kind,
+    pub class: &'static str,
+    pub id: u64
+}
+
+impl fmt::Display for InvalidObject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "the {} #{} was {}, find it again with {}.by_id",
+            self.class,
+            self.id,
+            self.kind.name(),
+            self.class
+        )
+    }
+}
+
+impl Error for InvalidObject {}
+
+#[derive(Debug, Default)]
+struct Resumes {
+    /// Advances when removals take effect, which is right away outside of
+    /// resumes and when the outermost one ends inside of them.
+    epoch: u64,
+    /// The epoch the outermost resume started in.
+    started: u64,
+    /// How many resumes are running, nested.
+    depth: u32,
+    /// How many objects were removed so far.
+    removals: u64
+}
+
+impl Resumes {
+    fn remove(&mut self) -> Removal {
+        let removal = Removal(self.epoch);
+        self.removals += 1;
+        if self.depth == 0 {
+            self.epoch += 1;
+        }
+        removal
+    }
+
+    fn is_valid(&self, removal: Option<Removal>) -> bool {
+        match removal {
+            Some(Removal(epoch)) => self.depth > 0 && epoch >= self.started,
+            None => true
+        }
+    }
+
+    fn enter(&mut self) {
+        if self.depth == 0 {
+            self.started = self.epoch;
+        }
+        self.depth += 1;
+    }
+
+    fn leave(&mut self) {
+        self.depth -= 1;
+        if self.depth == 0 {
+            self.epoch += 1;
+        }
+    }
+}
+
+/// Marks an object as removed, which it is to Lua once the coroutine being
+/// resumed, if any, yields or returns.
+pub fn removed() -> Removal {
+    RESUMES.with(|resumes| resumes.borrow_mut().remove())
+}
+
+/// Whether an object removed at `removal` can still be used.
+pub fn is_valid(removal: Option<Removal>) -> bool {
+    RESUMES.with(|resumes| resumes.borrow().is_valid(removal))
+}
+
+/// Raises an `InvalidObject` error if the object can't be used anymore.
+pub fn check(removal: Option<Removal>, kind: Kind, class: &'static str, id: u64) -> rlua::Result<()> {
+    if is_valid(removal) {
+        return Ok(());
+    }
+    Err(rlua::Error::external(InvalidObject { kind, class, id }))
+}
+
+/// The `InvalidObject` error `err` is or was caused by.
+pub fn invalid_object(err: &rlua::Error) -> Option<&InvalidObject> {
+    match err {
+        rlua::Error::CallbackError { cause, .. } => invalid_object(cause),
+        rlua::Error::ExternalError(err) => err.downcast_ref(),
+        _ => None
+    }
+}
+
+/// `awesome.resume_safely(co, ...)`, which resumes `co` with `...` and
+/// returns what `coroutine.resume` would, see the module documentation.
+pub fn resume_safely<'lua>(
+    lua: rlua::Context<'lua>,
+    (co, args): (Thread<'lua>, MultiValue<'lua>)
+) -> rlua::Result<MultiValue<'lua>> {
+    if co.status() != ThreadStatus::Resumable {
+        return failed(
+            lua,
+            rlua::Error::RuntimeError("cannot resume dead coroutine".into())
+        );
+    }
+    let resumed = resumed_coroutines(lua)?;
+    let removals = RESUMES.with(|resumes| resumes.borrow().removals);
+    let seen: Option<u64> = resumed.get(co.clone())?;
+    if seen.map(|seen| seen < removals).unwrap_or(false) {
+        let retry = lua.create_table()?;
+        retry.set("retry_with_fresh_objects", true)?;
+        match resume(lua, &co, MultiValue::from_vec(vec![Value::Table(retry)]))? {
+            Err(err) => return failed(lua, err),
+            Ok(values) => {
+                if co.status() != ThreadStatus::Resumable {
+                    return succeeded(values);
+                }
+            },
+        }
+    }
+    match resume(lua, &co, args)? {
+        Ok(values) => succeeded(values),
+        Err(err) => failed(lua, err)
+    }
+}
+
+/// Resumes `co`, remembering how many objects were removed by the time it
+/// yielded.
+fn resume<'lua>(
+    lua: rlua::Context<'lua>,
+    co: &Thread<'lua>,
+    args: MultiValue<'lua>
+) -> rlua::Result<Result<MultiValue<'lua>, rlua::Error>> {
+    RESUMES.with(|resumes| resumes.borrow_mut().enter());
+    let result = co.resume::<_, MultiValue>(args);
+    let removals = RESUMES.with(|resumes| {
+        let mut resumes = resumes.borrow_mut();
+        resumes.leave();
+        resumes.removals
+    });
+    let seen = match co.status() {
+        ThreadStatus::Resumable => Value::Integer(removals as _),
+        _ => Value::Nil
+    };
+    resumed_coroutines(lua)?.set(co.clone(), seen)?;
+    Ok(result)
+}
+
+fn succeeded(values: MultiValue) -> rlua::Result<MultiValue> {
+    let mut values = values.into_vec();
+    values.insert(0, Value::Boolean(true));
+    Ok(MultiValue::from_vec(values))
+}
+
+/// `false` and the error, followed by what was invalid if that's why.
+fn failed<'lua>(lua: rlua::Context<'lua>, err: rlua::Error) -> rlua::Result<MultiValue<'lua>> {
+    let mut values = vec![Value::Boolean(false)];
+    values.push(Value::String(lua.create_string(&message(&err))?));
+    if let Some(invalid) = invalid_object(&err) {
+        let info = lua.create_table()?;
+        info.set("kind", invalid.kind.name())?;
+        info.set("class", invalid.class)?;
+        info.set("id", invalid.id)?;
+        info.set("retry_with_fresh_objects", true)?;
+        values.push(Value::Table(info));
+    }
+    Ok(MultiValue::from_vec(values))
+}
+
+/// The message of `err`, with the traceback of the callback it came from.
+fn message(err: &rlua::Error) -> String {
+    match err {
+        rlua::Error::RuntimeError(message) => message.clone(),
+        rlua::Error::CallbackError { traceback, cause } => format!("{}\n{}", message(cause), traceback),
+        rlua::Error::ExternalError(err) => err.to_string(),
+        err => err.to_string()
+    }
+}
+
+fn resumed_coroutines(lua: rlua::Context) -> rlua::Result<Table> {
+    if let Ok(table) = lua.named_registry_value::<str, Table>(RESUMED_HANDLE) {
+        return Ok(table);
+    }
+    let table = lua.create_table()?;
+    let meta = lua.create_table()?;
+    meta.set("__mode", "k")?;
+    table.set_metatable(Some(meta));
+    lua.set_named_registry_value(RESUMED_HANDLE, table.clone())?;
+    Ok(table)
+}
+
+#[cfg(test)]
+mod test {
+    use rlua::Lua;
+
+    use super::*;
+    use crate::area::Size;
+    use crate::objects::{
+        drawable,
+        drawin::{self, Drawin},
+        screen::{self, Screen, SCREENS_HANDLE}
+    };
+
+    fn setup(lua: rlua::Context) -> rlua::Result<()> {
+        drawable::init(lua)?;
+        drawin::init(lua)?;
+        screen::init(lua)?;
+        let awesome = lua.create_table()?;
+        awesome.set("resume_safely", lua.create_function(resume_safely)?)?;
+        lua.globals().set("awesome", awesome)?;
+        // Like unplugging the output of the screen and plugging in another.
+        let unplug = lua.create_function(|lua, id: usize| {
+            for screen in lua.named_registry_value::<str, Vec<Screen>>(SCREENS_HANDLE)? {
+                if screen.state()?.id == id {
+                    screen::remove_screen(lua, screen)?;
+                }
+            }
+            let mut screen = Screen::new(lua)?;
+            screen.state_mut()?.geometry = Size {
+                width: 640,
+                height: 480
+            }
+            .into();
+            screen::add_screen(lua, screen)
+        })?;
+        lua.globals().set("unplug", unplug)?;
+        let remove = lua.create_function(|lua, mut drawin: Drawin| drawin.remove(lua))?;
+        lua.globals().set("remove", remove)
+    }
+
+    #[test]
+    fn resume_safely_after_hotplug() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            setup(lua)?;
+            lua.load(
+                r#"
+local s = screen[1]
+local id = s.id
+assert(screen.by_id(id) == s)
+local removed = {}
+screen.connect_signal("removed", function(s) removed[#removed + 1] = s.geometry.width end)
+local retried = 0
+local co = coroutine.create(function()
+    local before = s.geometry.width
+    local results = table.pack(coroutine.yield("requested"))
+    while type(results[1]) == "table" and results[1].retry_with_fresh_objects do
+        retried = retried + 1
+        s = screen.by_id(id) or screen.primary
+        results = table.pack(coroutine.yield())
+    end
+    return before, s.geometry.width, results[1]
+end)
+local ok, state = awesome.resume_safely(co)
+assert(ok and state == "requested")
+-- The output is unplugged before the request completes.
+unplug(id)
+assert(#removed == 1 and removed[1] == 1024)
+assert(not s.valid and s.id == id and screen.by_id(id) == nil)
+local ok, err = pcall(function() return s.geometry end)
+assert(not ok and tostring(err):find("screen #" .. id .. " was removed"), tostring(err))
+-- It's told to find its screen again before it gets the result.
+local ok, before, after, result = awesome.resume_safely(co, "done")
+assert(ok and retried == 1, retried)
+assert(before == 1024 and after == 640 and result == "done")
+assert(coroutine.status(co) == "dead")
+assert(select('#', awesome.resume_safely(co)) == 2)
+                "#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn resume_safely_classifies_errors() -> rlua::Result<()> {
+        let lua = Lua::new();
+        lua.context(|lua| {
+            setup(lua)?;
+            lua.load(
+                r#"
+local d = drawin{}
+local id = d.id
+assert(drawin.by_id(id) == d)
+local co = coroutine.create(function()
+    remove(d)
+    -- Removed while the coroutine runs, it can be used until it yields.
+    d.x = 10
+    coroutine.yield(d.valid, d.x)
+    return d.x
+end)
+local ok, valid, x = awesome.resume_safely(co)
+assert(ok and valid and x == 10)
+assert(not d.valid and d.id == id and drawin.by_id(id) == nil)
+assert(not pcall(function() d.x = 5 end))
+-- Ignoring the retry, it uses the drawin and fails.
+local ok, err, info = awesome.resume_safely(co)
+assert(not ok and tostring(err):find("drawin #" .. id .. " was destroyed"), tostring(err))
+assert(info.kind == "destroyed" and info.class == "drawin" and info.id == id)
+assert(info.retry_with_fresh_objects)
+-- Other errors are passed on like coroutine.resume does.
+local ok, err, info = awesome.resume_safely(coroutine.create(function() error("boom", 0) end))
+assert(not ok and err:find("^boom") and info == nil)
+local ok, a, b = awesome.resume_safely(coroutine.create(function(a, b) return b, a end), 1, 2)
+assert(ok and a == 2 and b == 1)
+                "#
+            )
+            .exec()
+        })
+    }
+
+    #[test]
+    fn resumes_invalidate_between_dispatches() {
+        let mut resumes = Resumes::default();
+        let before = resumes.remove();
+        assert!(!resumes.is_valid(Some(before)));
+        resumes.enter();
+        assert!(!resumes.is_valid(Some(before)));
+        // Removed while a coroutine runs, it's usable until it yields.
+        let during = resumes.remove();
+        resumes.enter();
+        let nested = resumes.remove();
+        assert!(resumes.is_valid(Some(during)) && resumes.is_valid(Some(nested)));
+        resumes.leave();
+        assert!(resumes.is_valid(Some(during)));
+        resumes.leave();
+        assert!(!resumes.is_valid(Some(during)) && !resumes.is_valid(Some(nested)));
+        resumes.enter();
+        assert!(!resumes.is_valid(Some(during)));
+        assert!(resumes.is_valid(None));
+        resumes.leave();
+        assert_eq!(resumes.removals, 3);
+    }
+}
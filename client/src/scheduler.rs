@@ -0,0 +1,224 @@
+//! Runs callbacks from the main loop by priority, so that a flood of
+//! widget updates can't hold up input.
+//!
+//! Every iteration of the main loop runs all queued input, then as much of
+//! the rest as fits in the frame budget. Work that was passed over for a
+//! few frames in a row runs regardless of the budget.
+
+mod queues;
+
+use std::{
+    cell::RefCell,
+    fmt::{self, Write},
+    time::{Duration, Instant}
+};
+
+use glib::Continue;
+use rlua::{self, Function, RegistryKey, Table};
+
+use crate::lua::LUA;
+
+pub use self::queues::Priority;
+use self::queues::{Frame, Queues};
+
+/// How long the callbacks below input priority can run every frame, by
+/// default.
+const DEFAULT_BUDGET: Duration = Duration::from_millis(8);
+
+thread_local! {
+    static SCHEDULER: RefCell<Scheduler> = RefCell::new(Scheduler::new());
+}
+
+enum Task {
+    Lua(RegistryKey),
+    Native(Box<dyn FnOnce(rlua::Context) -> rlua::Result<()>>)
+}
+
+struct Scheduler {
+    queues: Queues<Task>,
+    budget: Duration,
+    /// Whether the main loop will run a frame.
+    scheduled: bool
+}
+
+impl Scheduler {
+    fn new() -> Self {
+        Scheduler {
+            queues: Queues::default(),
+            budget: DEFAULT_BUDGET,
+            scheduled: false
+        }
+    }
+}
+
+/// Runs `callback` from the main loop, after the work of higher priority.
+pub fn defer<F>(priority: Priority, callback: F)
+where
+    F: FnOnce(rlua::Context) -> rlua::Result<()> + 'static
+{
+    push(priority, Task::Native(Box::new(callback)));
+}
+
+fn push(priority: Priority, task: Task) {
+    let schedule = SCHEDULER.with(|scheduler| {
+        let mut scheduler = scheduler.borrow_mut();
+        scheduler.queues.push(priority, task, Instant::now());
+        !std::mem::replace(&mut scheduler.scheduled, true)
+    });
+    if schedule {
+        glib::idle_add(|| Continue(run_frame()));
+    }
+}
+
+/// Runs the callbacks for one iteration of the main loop, returning whether
+/// any are left for the next one.
+fn run_frame() -> bool {
+    let mut frame = SCHEDULER.with(|scheduler| Frame::new(Instant::now(), scheduler.borrow().budget));
+    loop {
+        // The callbacks can defer more work, so the queues aren't borrowed
+        // while they run.
+        let next = SCHEDULER.with(|scheduler| scheduler.borrow_mut().queues.next(&mut frame, Instant::now()));
+        let (priority, task) = match next {
+            Some(next) => next,
+            None => break
+        };
+        LUA.with(|lua| {
+            lua.borrow().context(|lua| {
+                if let Err(err) = run_task(lua, task) {
+                    warn!("Error in a deferred {} callback: {}", priority.name(), err);
+                }
+            })
+        });
+    }
+    SCHEDULER.with(|scheduler| {
+        let mut scheduler = scheduler.borrow_mut();
+        scheduler.queues.end_frame(frame);
+        scheduler.scheduled = !scheduler.queues.is_empty();
+        scheduler.scheduled
+    })
+}
+
+/// Runs the queued callbacks with `lua` until there are none left, the ones
+/// they queue too, like the main loop would.
+///
+/// Callbacks queued by earlier tests on the thread run too, and fail if
+/// they were for another Lua state.
+#[cfg(test)]
+pub fn run_deferred(lua: rlua::Context) {
+    loop {
+        let next = SCHEDULER.with(|scheduler| {
+            let mut frame = Frame::new(Instant::now(), Duration::from_secs(60));
+            scheduler.borrow_mut().queues.next(&mut frame, Instant::now())
+        });
+        let (priority, task) = match next {
+            Some(next) => next,
+            None => return
+        };
+        if let Err(err) = run_task(lua, task) {
+            warn!("Error in a deferred {} callback: {}", priority.name(), err);
+        }
+    }
+}
+
+fn run_task(lua: rlua::Context, task: Task) -> rlua::Result<()> {
+    match task {
+        Task::Lua(key) => {
+            let callback: Function = lua.registry_value(&key)?;
+            lua.remove_registry_value(key)?;
+            callback.call(())
+        },
+        Task::Native(callback) => callback(lua)
+    }
+}
+
+/// `awesome.defer(callback, priority)`, where the priority is one of
+/// "input", "redraw", "timer" or "idle", the default.
+pub fn defer_lua<'lua>(
+    lua: rlua::Context<'lua>,
+    (callback, priority): (Function<'lua>, Option<String>)
+) -> rlua::Result<()> {
+    let priority = match priority {
+        Some(name) => Priority::from_name(&name).ok_or_else(|| {
+            rlua::Error::RuntimeError(format!(
+                "defer: unknown priority \"{}\", expected one of input, redraw, timer or idle",
+                name
+            ))
+        })?,
+        None => Priority::Idle
+    };
+    push(priority, Task::Lua(lua.create_registry_value(callback)?));
+    Ok(())
+}
+
+/// `awesome.idle_add(callback)`, which runs the callback once there's
+/// nothing more important to do.
+pub fn idle_add<'lua>(lua: rlua::Context<'lua>, callback: Function<'lua>) -> rlua::Result<()> {
+    defer_lua(lua, (callback, None))
+}
+
+/// `awesome.set_frame_budget(milliseconds)`
+pub fn set_frame_budget(_: rlua::Context, millis: f64) -> rlua::Result<()> {
+    if !millis.is_finite() || millis <= 0.0 {
+        return Err(rlua::Error::RuntimeError(format!(
+            "set_frame_budget: the budget must be positive, got {}",
+            millis
+        )));
+    }
+    let budget = Duration::from_micros((millis * 1000.0) as u64);
+    SCHEDULER.with(|scheduler| scheduler.borrow_mut().budget = budget);
+    Ok(())
+}
+
+/// `awesome.scheduler_stats()`, the queue depths and latencies of every
+/// priority, in milliseconds.
+pub fn scheduler_stats(lua: rlua::Context, _: ()) -> rlua::Result<Table> {
+    let millis = |duration: Duration| duration.as_micros() as f64 / 1000.0;
+    let stats = lua.create_table()?;
+    SCHEDULER.with(|scheduler| {
+        let scheduler = scheduler.borrow();
+        for &priority in &Priority::ALL {
+            let class = scheduler.queues.stats(priority);
+            let table = lua.create_table()?;
+            table.set("depth", class.depth)?;
+            table.set("max_depth", class.max_depth)?;
+            table.set("dispatched", class.dispatched)?;
+            let average = if class.dispatched == 0 {
+                0.0
+            } else {
+                millis(class.total_latency) / class.dispatched as f64
+            };
+            table.set("average_latency", average)?;
+            table.set("max_latency", millis(class.max_latency))?;
+            stats.set(priority.name(), table)?;
+        }
+        stats.set("frame_budget", millis(scheduler.budget))
+    })?;
+    Ok(stats)
+}
+
+/// Writes the statistics of every priority for a crash report, a line per
+/// priority.
+pub fn write_stats(out: &mut dyn Write) -> fmt::Result {
+    let millis = |duration: Duration| duration.as_micros() as f64 / 1000.0;
+    SCHEDULER
+        .try_with(|scheduler| {
+            let scheduler = match scheduler.try_borrow() {
+                Ok(scheduler) => scheduler,
+                Err(_) => return writeln!(out, "(in use)")
+            };
+            for &priority in &Priority::ALL {
+                let class = scheduler.queues.stats(priority);
+                writeln!(
+                    out,
+                    "{} depth={} max_depth={} dispatched={} max_latency={:.3}",
+                    priority.name(),
+                    class.depth,
+                    class.max_depth,
+                    class.dispatched,
+                    millis(class.max_latency)
+                )?;
+            }
+            Ok(())
+        })
+        .unwrap_or(Ok(()))
+}
@@ -0,0 +1,282 @@
+//! The queues of the scheduler, one for every priority, and the order in
+//! which a frame takes work from them.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant}
+};
+
+/// How many frames a priority can be passed over while it has work before
+/// it's given one callback regardless of the budget.
+pub const STARVATION_FRAMES: u32 = 4;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Priority {
+    /// Pointer and keyboard events, which are never postponed.
+    Input,
+    /// Configure events and redrawing.
+    Redraw,
+    /// Callbacks of timers and spawned commands.
+    Timer,
+    /// Work that can wait, e.g. from `awesome.idle_add`.
+    Idle
+}
+
+impl Priority {
+    pub const ALL: [Priority; 4] = [Priority::Input, Priority::Redraw, Priority::Timer, Priority::Idle];
+
+    /// The priorities that share the frame budget.
+    const BUDGETED: [Priority; 3] = [Priority::Redraw, Priority::Timer, Priority::Idle];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Priority::ALL
+            .iter()
+            .cloned()
+            .find(|priority| priority.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Priority::Input => "input",
+            Priority::Redraw => "redraw",
+            Priority::Timer => "timer",
+            Priority::Idle => "idle"
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// What a priority went through since the client started.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Stats {
+    /// How many callbacks are waiting.
+    pub depth: usize,
+    pub max_depth: usize,
+    pub dispatched: u64,
+    /// How long callbacks waited in the queue, in total.
+    pub total_latency: Duration,
+    pub max_latency: Duration
+}
+
+/// One iteration of the main loop, in which the queued callbacks are run.
+#[derive(Debug)]
+pub struct Frame {
+    start: Instant,
+    budget: Duration,
+    ran: [bool; 4]
+}
+
+impl Frame {
+    pub fn new(start: Instant, budget: Duration) -> Self {
+        Frame {
+            start,
+            budget,
+            ran: [false; 4]
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Queues<T> {
+    queues: [VecDeque<(T, Instant)>; 4],
+    /// How many frames every priority was passed over in a row.
+    credits: [u32; 4],
+    stats: [Stats; 4]
+}
+
+impl<T> Default for Queues<T> {
+    fn default() -> Self {
+        Queues {
+            queues: [VecDeque::new(), VecDeque::new(), VecDeque::new(), VecDeque::new()],
+            credits: [0; 4],
+            stats: [Stats::default(); 4]
+        }
+    }
+}
+
+impl<T> Queues<T> {
+    pub fn push(&mut self, priority: Priority, item: T, now: Instant) {
+        let queue = &mut self.queues[priority.index()];
+        queue.push_back((item, now));
+        let stats = &mut self.stats[priority.index()];
+        stats.max_depth = stats.max_depth.max(queue.len());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queues.iter().all(VecDeque::is_empty)
+    }
+
+    /// The next callback to run in the frame, if any.
+    ///
+    /// Input always runs. Then a priority that was passed over for too many
+    /// frames gets one callback, and the rest of the budget goes to the
+    /// others in order of priority.
+    pub fn next(&mut self, frame: &mut Frame, now: Instant) -> Option<(Priority, T)> {
+        if let Some(item) = self.pop(Priority::Input, now) {
+            return Some((Priority::Input, item));
+        }
+        for &priority in &Priority::BUDGETED {
+            if !frame.ran[priority.index()] && self.credits[priority.index()] >= STARVATION_FRAMES {
+                if let Some(item) = self.pop(priority, now) {
+                    frame.ran[priority.index()] = true;
+                    return Some((priority, item));
+                }
+            }
+        }
+        if now.duration_since(frame.start) >= frame.budget {
+            return None;
+        }
+        for &priority in &Priority::BUDGETED {
+            if let Some(item) = self.pop(priority, now) {
+                frame.ran[priority.index()] = true;
+                return Some((priority, item));
+            }
+        }
+        None
+    }
+
+    /// Gives credit to the priorities that had work but didn't get to run.
+    pub fn end_frame(&mut self, frame: Frame) {
+        for &priority in &Priority::BUDGETED {
+            let index = priority.index();
+            if frame.ran[index] {
+                self.credits[index] = 0;
+            } else if !self.queues[index].is_empty() {
+                self.credits[index] += 1;
+            }
+        }
+    }
+
+    pub fn stats(&self, priority: Priority) -> Stats {
+        Stats {
+            depth: self.queues[priority.index()].len(),
+            ..self.stats[priority.index()]
+        }
+    }
+
+    fn pop(&mut self, priority: Priority, now: Instant) -> Option<T> {
+        let (item, queued) = self.queues[priority.index()].pop_front()?;
+        let latency = now.duration_since(queued);
+        let stats = &mut self.stats[priority.index()];
+        stats.dispatched += 1;
+        stats.total_latency += latency;
+        stats.max_latency = stats.max_latency.max(latency);
+        Some(item)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+
+    const BUDGET: Duration = Duration::from_millis(8);
+
+    /// A clock that only moves when callbacks take time.
+    struct Clock {
+        start: Instant,
+        elapsed: Cell<Duration>
+    }
+
+    impl Clock {
+        fn new() -> Self {
+            Clock {
+                start: Instant::now(),
+                elapsed: Cell::new(Duration::from_millis(0))
+            }
+        }
+
+        fn now(&self) -> Instant {
+            self.start + self.elapsed.get()
+        }
+
+        fn advance(&self, millis: u64) {
+            self.elapsed
+                .set(self.elapsed.get() + Duration::from_millis(millis));
+        }
+    }
+
+    /// Runs a frame where every callback takes as many milliseconds as it
+    /// says, returning what ran.
+    fn run_frame(queues: &mut Queues<u64>, clock: &Clock) -> Vec<Priority> {
+        let mut frame = Frame::new(clock.now(), BUDGET);
+        let mut ran = Vec::new();
+        while let Some((priority, millis)) = queues.next(&mut frame, clock.now()) {
+            clock.advance(millis);
+            ran.push(priority);
+        }
+        queues.end_frame(frame);
+        // The main loop waits for events between frames.
+        clock.advance(1);
+        ran
+    }
+
+    #[test]
+    fn scheduler_input_under_flood() {
+        let clock = Clock::new();
+        let mut queues = Queues::default();
+        for _ in 0..200 {
+            queues.push(Priority::Timer, 3, clock.now());
+        }
+        let mut frames = 0;
+        while !queues.is_empty() {
+            // A button press arrives during every frame.
+            queues.push(Priority::Input, 0, clock.now());
+            let ran = run_frame(&mut queues, &clock);
+            assert_eq!(ran[0], Priority::Input);
+            frames += 1;
+            assert!(frames < 1000, "the timers never finished");
+        }
+        let input = queues.stats(Priority::Input);
+        assert_eq!(input.dispatched, frames);
+        // Input waits for at most the frame it arrived in.
+        assert!(input.max_latency <= BUDGET + Duration::from_millis(3));
+        let timers = queues.stats(Priority::Timer);
+        assert_eq!((timers.dispatched, timers.depth, timers.max_depth), (200, 0, 200));
+    }
+
+    #[test]
+    fn scheduler_priority_order() {
+        let clock = Clock::new();
+        let mut queues = Queues::default();
+        queues.push(Priority::Idle, 1, clock.now());
+        queues.push(Priority::Timer, 1, clock.now());
+        queues.push(Priority::Redraw, 1, clock.now());
+        queues.push(Priority::Input, 1, clock.now());
+        assert_eq!(
+            run_frame(&mut queues, &clock),
+            vec![Priority::Input, Priority::Redraw, Priority::Timer, Priority::Idle,]
+        );
+    }
+
+    #[test]
+    fn scheduler_starvation_credits() {
+        let clock = Clock::new();
+        let mut queues = Queues::default();
+        queues.push(Priority::Idle, 1, clock.now());
+        let mut frames = 0;
+        loop {
+            // Every frame, more timer work than fits in the budget arrives.
+            for _ in 0..4 {
+                queues.push(Priority::Timer, 5, clock.now());
+            }
+            frames += 1;
+            if run_frame(&mut queues, &clock).contains(&Priority::Idle) {
+                break;
+            }
+            assert!(frames < 100, "idle work was postponed forever");
+        }
+        assert_eq!(frames, STARVATION_FRAMES + 1);
+        // The credits start over once the priority got to run.
+        queues.push(Priority::Idle, 1, clock.now());
+        for _ in 0..STARVATION_FRAMES {
+            queues.push(Priority::Timer, 20, clock.now());
+            assert!(!run_frame(&mut queues, &clock).contains(&Priority::Idle));
+        }
+        queues.push(Priority::Timer, 20, clock.now());
+        assert!(run_frame(&mut queues, &clock).contains(&Priority::Idle));
+    }
+}
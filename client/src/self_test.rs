@@ -0,0 +1,430 @@
+//! `awesome.self_test`, which checks that the compositor does what the
+//! client needs to show drawins. The report is meant to be attached to bug
+//! reports from compositors we can't test on.
+//!
+//! The test shows a layer surface of its own, which is 1x1 and transparent
+//! so it can't be seen. It's destroyed when the test ends, however it ends.
+
+mod report;
+
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc
+};
+
+use glib::Continue;
+use rlua::{self, Function, Table};
+use wayland_client::protocol::wl_shm::Format;
+
+use crate::area::{Origin, Size};
+use crate::l10n::{self, Arg, Args};
+use crate::lua::LUA;
+use crate::wayland_obj::{self, Buffer, LayerSurface};
+
+use self::report::{Global, Report, Status, Step};
+
+/// Handle to the function the report is given to.
+const CALLBACK_HANDLE: &str = "__self_test_callback";
+
+/// How long the compositor has to configure the test surface.
+const CONFIGURE_TIMEOUT_MS: u32 = 1000;
+
+const TEST_SIZE: Size = Size { width: 1, height: 1 };
+
+/// Every step, in the order they are run.
+const STEPS: &[&str] = &[
+    "globals",
+    "layer_surface",
+    "layers",
+    "configure",
+    "commit",
+    "readback",
+    "workarea",
+    "viewport",
+    "fractional_scale",
+    "destroy"
+];
+
+thread_local! {
+    static RUN: RefCell<Option<Run>> = RefCell::new(None);
+    static NEXT_RUN: Cell<usize> = Cell::new(0);
+}
+
+/// A self test that's waiting for the compositor.
+struct Run {
+    /// Tells the callbacks of an earlier run apart from this one.
+    id: usize,
+    report: Report,
+    configured: bool,
+    layer_surface: Option<LayerSurface>,
+    buffer: Option<Buffer>
+}
+
+/// Runs the self test, calling `callback` with the report once it's done.
+pub fn self_test<'lua>(lua: rlua::Context<'lua>, callback: Function<'lua>) -> rlua::Result<()> {
+    if RUN.with(|run| run.borrow().is_some()) {
+        return Err(rlua::Error::RuntimeError("a self test is already running".into()));
+    }
+    lua.set_named_registry_value(CALLBACK_HANDLE, callback)?;
+    let id = NEXT_RUN.with(|next| {
+        next.set(next.get() + 1);
+        next.get()
+    });
+    let globals: Vec<Global> = wayland_obj::globals()
+        .into_iter()
+        .map(|(interface, version)| Global { interface, version })
+        .collect();
+    let mut run = Run {
+        id,
+        report: Report {
+            steps: vec![report::globals_step(&globals)],
+            globals
+        },
+        configured: false,
+        layer_surface: None,
+        buffer: None
+    };
+    if !run.report.passed() {
+        RUN.with(|cell| *cell.borrow_mut() = Some(run));
+        finish_later(id, "the compositor is missing required globals");
+        return Ok(());
+    }
+    match wayland_obj::create_layer_surface(
+        None,
+        wayland_obj::Layer::Top,
+        wayland_obj::Anchor::Top | wayland_obj::Anchor::Left
+    ) {
+        Ok(layer_surface) => {
+            layer_surface.set_size(TEST_SIZE);
+            layer_surface.set_position(Origin::default());
+            layer_surface.on_configure(Rc::new(move |size| configured(id, size)));
+            layer_surface.commit();
+            run.layer_surface = Some(layer_surface);
+            run.report.steps.push(Step::pass("layer_surface"));
+            run.report
+                .steps
+                .push(Step::skip("layers", "drawins are only shown on the top layer"));
+            RUN.with(|cell| *cell.borrow_mut() = Some(run));
+            glib::timeout_add(CONFIGURE_TIMEOUT_MS, move || {
+                timed_out(id);
+                Continue(false)
+            });
+        },
+        Err(()) => {
+            run.report
+                .steps
+                .push(Step::fail("layer_surface", "could not create a layer surface"));
+            RUN.with(|cell| *cell.borrow_mut() = Some(run));
+            finish_later(id, "there is no surface to test");
+        }
+    }
+    Ok(())
+}
+
+/// Called when the compositor configured the test surface.
+///
+/// The surface can't be touched while it's being configured, so the test
+/// continues once the event has been handled.
+fn configured(id: usize, size: Size) {
+    let first = RUN.with(|run| match run.borrow_mut().as_mut() {
+        Some(run) if run.id == id && !run.configured => {
+            run.configured = true;
+            true
+        },
+        _ => false
+    });
+    if first {
+        glib::idle_add(move || {
+            commit_test_pattern(id, size);
+            Continue(false)
+        });
+    }
+}
+
+fn timed_out(id: usize) {
+    let waiting = RUN.with(|run| match run.borrow_mut().as_mut() {
+        Some(run) if run.id == id && !run.configured => {
+            let reason = format!("no configure within {} ms", CONFIGURE_TIMEOUT_MS);
+            run.report.steps.push(Step::fail("configure", reason));
+            true
+        },
+        _ => false
+    });
+    if waiting {
+        finish(id, "the surface wasn't configured");
+    }
+}
+
+fn commit_test_pattern(id: usize, size: Size) {
+    RUN.with(|run| {
+        let mut run = run.borrow_mut();
+        let run = match run.as_mut() {
+            Some(run) if run.id == id => run,
+            _ => return
+        };
+        let steps = &mut run.report.steps;
+        if size == TEST_SIZE {
+            steps.push(Step::pass("configure"));
+        } else {
+            let reason = format!(
+                "asked for {}x{}, got {}x{}",
+                TEST_SIZE.width, TEST_SIZE.height, size.width, size.height
+            );
+            steps.push(Step::fail("configure", reason));
+        }
+        let transparent = vec![0; TEST_SIZE.width as usize * TEST_SIZE.height as usize * 4];
+        let buffer = wayland_obj::create_buffer(TEST_SIZE, Format::Argb8888).and_then(|mut buffer| {
+            buffer
+                .write(&transparent, TEST_SIZE.width as usize * 4, Origin::default())
+                .map_err(wayland_obj::BufferError::File)?;
+            Ok(buffer)
+        });
+        match (buffer, run.layer_surface.as_ref()) {
+            (Ok(buffer), Some(layer_surface)) => {
+                layer_surface.set_buffer(buffer.wl_buffer(), TEST_SIZE, None);
+                layer_surface.commit();
+                run.buffer = Some(buffer);
+                steps.push(Step::pass("commit"));
+            },
+            _ => steps.push(Step::fail("commit", "could not create a shm buffer"))
+        }
+        steps.push(Step::skip(
+            "readback",
+            "the content committed to a surface can't be read back"
+        ));
+        steps.push(Step::skip("workarea", "drawins don't set an exclusive zone"));
+        let globals = &run.report.globals;
+        let viewport = report::unused_protocol_step("viewport", "wp_viewporter", globals);
+        let fractional_scale =
+            report::unused_protocol_step("fractional_scale", "wp_fractional_scale_manager_v1", globals);
+        run.report.steps.push(viewport);
+        run.report.steps.push(fractional_scale);
+    });
+    finish(id, "an earlier step failed");
+}
+
+fn finish_later(id: usize, reason: &'static str) {
+    // Always report asynchronously, so the callback runs at the same point
+    // however far the test got.
+    glib::idle_add(move || {
+        finish(id, reason);
+        Continue(false)
+    });
+}
+
+/// Destroys the test surface and gives the report to the callback.
+///
+/// Steps that weren't reached are skipped with `reason`.
+fn finish(id: usize, reason: &str) {
+    let run = RUN.with(|run| {
+        let mut run = run.borrow_mut();
+        match run.as_ref() {
+            Some(current) if current.id == id => run.take(),
+            _ => None
+        }
+    });
+    let Run {
+        mut report,
+        layer_surface,
+        buffer,
+        ..
+    } = match run {
+        Some(run) => run,
+        None => return
+    };
+    if layer_surface.is_some() {
+        drop(layer_surface);
+        drop(buffer);
+        report.steps.push(Step::pass("destroy"));
+    }
+    report.skip_missing(STEPS, reason);
+    LUA.with(|lua| {
+        let lua = lua.borrow();
+        lua.context(|ctx| {
+            if let Err(err) = report_to_lua(ctx, &report) {
+                warn!("Could not report the self test: {}", err);
+            }
+        })
+    });
+}
+
+/// What the report says, in the language of the user.
+fn summary(report: &Report) -> String {
+    let failed = report
+        .steps
+        .iter()
+        .filter(|step| step.status == Status::Fail)
+        .count();
+    if failed == 0 {
+        return l10n::message("self_test.passed", &Args::default());
+    }
+    let count = Args {
+        positional: Vec::new(),
+        named: vec![("count".into(), Arg::Integer(failed as i64))]
+    };
+    l10n::message("self_test.failed", &count)
+}
+
+fn report_to_lua(lua: rlua::Context, report: &Report) -> rlua::Result<()> {
+    let callback = lua.named_registry_value::<str, Function>(CALLBACK_HANDLE)?;
+    lua.unset_named_registry_value(CALLBACK_HANDLE)?;
+    let table = lua.create_table()?;
+    table.set("passed", report.passed())?;
+    table.set("summary", summary(report))?;
+    let steps = lua.create_table()?;
+    for (i, step) in report.steps.iter().enumerate() {
+        let entry = lua.create_table()?;
+        entry.set("name", step.name)?;
+        entry.set(
+            "label",
+            l10n::message(&format!("self_test.{}", step.name), &Args::default())
+        )?;
+        entry.set("status", step.status.name())?;
+        entry.set("reason", step.reason.clone())?;
+        steps.set(i + 1, entry)?;
+    }
+    table.set("steps", steps)?;
+    let globals = lua.create_table()?;
+    for (i, global) in report.globals.iter().enumerate() {
+        let entry = lua.create_table()?;
+        entry.set("interface", global.interface.clone())?;
+        entry.set("version", global.version)?;
+        globals.set(i + 1, entry)?;
+    }
+    table.set("globals", globals)?;
+    table.set("json", report.to_json())?;
+    callback.call::<Table, ()>(table)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use rlua::Lua;
+
+    use super::*;
+    use crate::wayland_obj::{test_server::TestServer, LAYER_SHELL_MAX_VERSION};
+
+    /// The requests that create an object, whose id is their first
+    /// argument.
+    const CREATES: &[(&str, &str)] = &[
+        ("wl_compositor", "create_surface"),
+        ("zwlr_layer_shell_v1", "get_layer_surface"),
+        ("wl_shm", "create_pool"),
+        ("wl_shm_pool", "create_buffer"),
+        ("wl_surface", "frame")
+    ];
+
+    /// Runs the self test against `server`, which configures the test
+    /// surface to `size`, and returns the statuses of the steps in the
+    /// report and the objects it left behind.
+    fn run_against(server: &mut TestServer, size: Size) -> rlua::Result<(bool, Vec<String>, Vec<u32>)> {
+        LUA.with(|lua| {
+            let lua = lua.borrow();
+            lua.context(|lua| {
+                let callback =
+                    lua.create_function(|lua, report: Table| lua.globals().set("report", report))?;
+                self_test(lua, callback)
+            })
+        })?;
+        server.roundtrip();
+        let requests = server.take_requests();
+        let layer_surface = requests
+            .iter()
+            .find(|request| request.name == "get_layer_surface")
+            .unwrap()
+            .args[0] as u32;
+        server.configure(layer_surface, 1, size);
+        server.roundtrip();
+        let context = glib::MainContext::default().unwrap();
+        let reported = || {
+            LUA.with(|lua| {
+                let lua = lua.borrow();
+                lua.context(|lua| {
+                    lua.globals()
+                        .get::<_, Option<Table>>("report")
+                        .map(|report| report.is_some())
+                })
+            })
+        };
+        while !reported()? {
+            context.iteration(true);
+        }
+        server.roundtrip();
+        let requests: Vec<_> = requests.into_iter().chain(server.take_requests()).collect();
+        let created: HashSet<u32> = requests
+            .iter()
+            .filter(|request| CREATES.contains(&(request.interface, request.name)))
+            .map(|request| request.args[0] as u32)
+            .collect();
+        let destroyed: HashSet<u32> = requests
+            .iter()
+            .filter(|request| request.name == "destroy")
+            .map(|request| request.object)
+            .collect();
+        assert!(created.len() >= 4, "only created {:?}", created);
+        let mut live: Vec<u32> = created.difference(&destroyed).cloned().collect();
+        live.sort();
+        LUA.with(|lua| {
+            let lua = lua.borrow();
+            lua.context(|lua| {
+                let report: Table = lua.globals().get("report")?;
+                lua.globals().set("report", rlua::Value::Nil)?;
+                let mut statuses = Vec::new();
+                for step in report.get::<_, Table>("steps")?.sequence_values::<Table>() {
+                    let step = step?;
+                    statuses.push(format!(
+                        "{} {}",
+                        step.get::<_, String>("name")?,
+                        step.get::<_, String>("status")?
+                    ));
+                }
+                Ok((report.get("passed")?, statuses, live))
+            })
+        })
+    }
+
+    #[test]
+    fn self_test_against_compositor() -> rlua::Result<()> {
+        let mut server = TestServer::start(LAYER_SHELL_MAX_VERSION);
+        wayland_obj::set_globals(
+            server
+                .globals
+                .list()
+                .into_iter()
+                .map(|(_, interface, version)| (interface, version))
+                .collect()
+        );
+        let (passed, statuses, live) = run_against(&mut server, TEST_SIZE)?;
+        assert!(passed);
+        assert_eq!(
+            statuses,
+            [
+                "globals pass",
+                "layer_surface pass",
+                "layers skip",
+                "configure pass",
+                "commit pass",
+                "readback skip",
+                "workarea skip",
+                "viewport skip",
+                "fractional_scale skip",
+                "destroy pass"
+            ]
+        );
+        assert_eq!(live, []);
+
+        // A surface configured to another size fails the test, which still
+        // cleans up after itself.
+        let size = Size { width: 2, height: 2 };
+        let (passed, statuses, live) = run_against(&mut server, size)?;
+        assert!(!passed);
+        assert!(statuses.contains(&"configure fail".to_string()));
+        assert!(statuses.contains(&"destroy pass".to_string()));
+        assert_eq!(live, []);
+
+        wayland_obj::set_globals(Vec::new());
+        LUA.with(|lua| *lua.borrow_mut() = Lua::new());
+        Ok(())
+    }
+}
@@ -0,0 +1,229 @@
+//! The outcome of a self test, in a form that can be attached to a bug
+//! report.
+
+use std::fmt::Write;
+
+/// The globals the client can't work without.
+pub const REQUIRED_GLOBALS: &[&str] = &["wl_compositor", "wl_shm", "zwlr_layer_shell_v1"];
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Status {
+    Pass,
+    Fail,
+    Skip
+}
+
+impl Status {
+    pub fn name(self) -> &'static str {
+        match self {
+            Status::Pass => "pass",
+            Status::Fail => "fail",
+            Status::Skip => "skip"
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Step {
+    pub name: &'static str,
+    pub status: Status,
+    /// Why the step failed or was skipped.
+    pub reason: Option<String>
+}
+
+impl Step {
+    pub fn pass(name: &'static str) -> Self {
+        Step {
+            name,
+            status: Status::Pass,
+            reason: None
+        }
+    }
+
+    pub fn fail<S: Into<String>>(name: &'static str, reason: S) -> Self {
+        Step {
+            name,
+            status: Status::Fail,
+            reason: Some(reason.into())
+        }
+    }
+
+    pub fn skip<S: Into<String>>(name: &'static str, reason: S) -> Self {
+        Step {
+            name,
+            status: Status::Skip,
+            reason: Some(reason.into())
+        }
+    }
+}
+
+/// A global advertised by the compositor.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Global {
+    pub interface: String,
+    pub version: u32
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Report {
+    pub steps: Vec<Step>,
+    pub globals: Vec<Global>
+}
+
+impl Report {
+    /// Whether no step failed.
+    pub fn passed(&self) -> bool {
+        self.steps.iter().all(|step| step.status != Status::Fail)
+    }
+
+    /// Reports the steps that haven't been reached as skipped.
+    pub fn skip_missing(&mut self, steps: &[&'static str], reason: &str) {
+        for name in steps {
+            if self.steps.iter().all(|step| step.name != *name) {
+                self.steps.push(Step::skip(name, reason));
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        write!(json, "{{\"passed\":{},\"steps\":[", self.passed()).unwrap();
+        for (i, step) in self.steps.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            write!(
+                json,
+                "{{\"name\":{},\"status\":{}",
+                quote(step.name),
+                quote(step.status.name())
+            )
+            .unwrap();
+            if let Some(reason) = step.reason.as_ref() {
+                write!(json, ",\"reason\":{}", quote(reason)).unwrap();
+            }
+            json.push('}');
+        }
+        json.push_str("],\"globals\":[");
+        for (i, global) in self.globals.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            write!(
+                json,
+                "{{\"interface\":{},\"version\":{}}}",
+                quote(&global.interface),
+                global.version
+            )
+            .unwrap();
+        }
+        json.push_str("]}");
+        json
+    }
+}
+
+/// Checks that the compositor advertises every required global.
+pub fn globals_step(globals: &[Global]) -> Step {
+    let missing: Vec<&str> = REQUIRED_GLOBALS
+        .iter()
+        .cloned()
+        .filter(|required| globals.iter().all(|global| global.interface != *required))
+        .collect();
+    if missing.is_empty() {
+        Step::pass("globals")
+    } else {
+        Step::fail("globals", format!("missing {}", missing.join(", ")))
+    }
+}
+
+/// The step for a protocol the client doesn't use yet, which can only say
+/// whether the compositor has it.
+pub fn unused_protocol_step(name: &'static str, interface: &str, globals: &[Global]) -> Step {
+    if globals.iter().any(|global| global.interface == interface) {
+        Step::skip(
+            name,
+            format!("{} is advertised but not used by the client", interface)
+        )
+    } else {
+        Step::skip(name, format!("{} is not advertised", interface))
+    }
+}
+
+/// Quotes a string as a JSON string.
+fn quote(string: &str) -> String {
+    let mut quoted = String::with_capacity(string.len() + 2);
+    quoted.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c)
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn global(interface: &str) -> Global {
+        Global {
+            interface: interface.into(),
+            version: 1
+        }
+    }
+
+    #[test]
+    fn self_test_globals() {
+        let mut globals = vec![global("wl_compositor"), global("wl_shm")];
+        assert_eq!(
+            globals_step(&globals),
+            Step::fail("globals", "missing zwlr_layer_shell_v1")
+        );
+        globals.push(global("zwlr_layer_shell_v1"));
+        assert_eq!(globals_step(&globals), Step::pass("globals"));
+        assert_eq!(
+            unused_protocol_step("viewport", "wp_viewporter", &globals),
+            Step::skip("viewport", "wp_viewporter is not advertised")
+        );
+        globals.push(global("wp_viewporter"));
+        assert_eq!(
+            unused_protocol_step("viewport", "wp_viewporter", &globals).reason,
+            Some("wp_viewporter is advertised but not used by the client".into())
+        );
+    }
+
+    #[test]
+    fn self_test_report_json() {
+        let mut report = Report {
+            steps: vec![
+                Step::pass("globals"),
+                Step::skip("readback", "can't read \"committed\" content\n"),
+            ],
+            globals: vec![global("wl_shm")]
+        };
+        assert!(report.passed());
+        assert_eq!(
+            report.to_json(),
+            concat!(
+                r#"{"passed":true,"steps":[{"name":"globals","status":"pass"},"#,
+                r#"{"name":"readback","status":"skip","reason":"can't read \"committed\" content\n"}],"#,
+                r#""globals":[{"interface":"wl_shm","version":1}]}"#
+            )
+        );
+        report.steps.push(Step::fail("configure", "timed out"));
+        assert!(!report.passed());
+        report.skip_missing(&["globals", "commit"], "the surface wasn't configured");
+        assert_eq!(
+            report.steps.last(),
+            Some(&Step::skip("commit", "the surface wasn't configured"))
+        );
+        assert_eq!(report.steps.len(), 4);
+        assert!(report.to_json().starts_with(r#"{"passed":false,"#));
+    }
+}
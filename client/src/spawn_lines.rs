@@ -0,0 +1,348 @@
+//! `awesome.spawn_with_line_callback` and `awesome.spawn_with_input`, which
+//! give the output of a command to Lua line by line, e.g. for a widget
+//! tailing a log file.
+//!
+//! The output is read on background threads and sent to the Lua thread,
+//! which is woken up to call the callbacks from the main loop at timer
+//! priority.
+
+use std::{
+    cell::RefCell,
+    io::{BufRead, BufReader, Read, Write},
+    os::unix::process::ExitStatusExt,
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender}
+    },
+    thread
+};
+
+use glib::Continue;
+use rlua::{self, Function, Table, ToLua, Value};
+
+use crate::awesome;
+use crate::scheduler::{self, Priority};
+
+/// Handle to the table of callbacks, keyed by the id of the command.
+const CALLBACKS_HANDLE: &str = "__spawn_line_callbacks";
+
+/// Whether the Lua thread has been woken up to handle the messages.
+static WOKEN: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static CHANNEL: RefCell<Channel> = RefCell::new(Channel::new());
+}
+
+/// Something a command did, as it's sent to the Lua thread.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Stdout(String),
+    Stderr(String),
+    /// The command exited with the code or was killed by the signal. It's
+    /// sent after every line of output.
+    Exited(ExitReason, i32)
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ExitReason {
+    Exit,
+    Signal
+}
+
+impl ExitReason {
+    fn name(self) -> &'static str {
+        match self {
+            ExitReason::Exit => "exit",
+            ExitReason::Signal => "signal"
+        }
+    }
+}
+
+struct Channel {
+    next_id: usize,
+    sender: Sender<(usize, Message)>,
+    receiver: Receiver<(usize, Message)>
+}
+
+impl Channel {
+    fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Channel {
+            next_id: 0,
+            sender,
+            receiver
+        }
+    }
+}
+
+/// Spawns `cmd`, calling `stdout_callback` and `stderr_callback` with every
+/// line it prints and `exit_callback` with the reason and code once it
+/// exited. Any callback can be nil.
+///
+/// Returns the pid, or an error message if the command couldn't be spawned.
+pub fn spawn_with_line_callback<'lua>(
+    lua: rlua::Context<'lua>,
+    (cmd, stdout_callback, stderr_callback, exit_callback): (
+        Value<'lua>,
+        Option<Function<'lua>>,
+        Option<Function<'lua>>,
+        Option<Function<'lua>>
+    )
+) -> rlua::Result<Value<'lua>> {
+    spawn_lua(lua, cmd, None, (stdout_callback, stderr_callback, exit_callback))
+}
+
+/// Spawns `cmd` with `input` as its standard input, calling
+/// `stdout_callback` with every line it prints.
+pub fn spawn_with_input<'lua>(
+    lua: rlua::Context<'lua>,
+    (cmd, input, stdout_callback): (Value<'lua>, String, Option<Function<'lua>>)
+) -> rlua::Result<Value<'lua>> {
+    spawn_lua(lua, cmd, Some(input), (stdout_callback, None, None))
+}
+
+fn spawn_lua<'lua>(
+    lua: rlua::Context<'lua>,
+    cmd: Value<'lua>,
+    input: Option<String>,
+    (stdout, stderr, exit): (
+        Option<Function<'lua>>,
+        Option<Function<'lua>>,
+        Option<Function<'lua>>
+    )
+) -> rlua::Result<Value<'lua>> {
+    let argv = awesome::command_argv(cmd)?;
+    let (id, sender) = CHANNEL.with(|channel| {
+        let mut channel = channel.borrow_mut();
+        channel.next_id += 1;
+        (channel.next_id, channel.sender.clone())
+    });
+    let pid = match spawn(&argv, input, id, sender, wake) {
+        Ok(pid) => pid,
+        Err(err) => return err.to_lua(lua)
+    };
+    let callbacks = lua.create_table()?;
+    callbacks.set("stdout", stdout)?;
+    callbacks.set("stderr", stderr)?;
+    callbacks.set("exit", exit)?;
+    all_callbacks(lua)?.set(id, callbacks)?;
+    pid.to_lua(lua)
+}
+
+/// Spawns the command, sending what it does as messages tagged with `id`.
+///
+/// `wake` is called after every message, from the thread that sent it.
+pub fn spawn(
+    argv: &[String],
+    input: Option<String>,
+    id: usize,
+    sender: Sender<(usize, Message)>,
+    wake: fn()
+) -> Result<u32, String> {
+    let (program, args) = argv.split_first().ok_or("spawn: empty command")?;
+    trace!("spawn with line callback: {:?}", argv);
+    let stdin = if input.is_some() {
+        Stdio::piped()
+    } else {
+        Stdio::null()
+    };
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(stdin)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| err.to_string())?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        thread::spawn(move || {
+            // The command might not read all of it, which isn't an error.
+            let _ = stdin.write_all(input.as_bytes());
+        });
+    }
+    let stdout = child.stdout.take().map(|stdout| {
+        let sender = sender.clone();
+        thread::spawn(move || read_lines(stdout, id, Message::Stdout, sender, wake))
+    });
+    let stderr = child.stderr.take().map(|stderr| {
+        let sender = sender.clone();
+        thread::spawn(move || read_lines(stderr, id, Message::Stderr, sender, wake))
+    });
+    let pid = child.id();
+    thread::Builder::new()
+        .name(program.clone())
+        .spawn(move || {
+            for reader in stdout.into_iter().chain(stderr) {
+                let _ = reader.join();
+            }
+            let exited = wait(child);
+            if sender.send((id, exited)).is_ok() {
+                wake();
+            }
+        })
+        .expect("Unable to spawn thread");
+    Ok(pid)
+}
+
+fn read_lines<R: Read>(
+    output: R,
+    id: usize,
+    message: fn(String) -> Message,
+    sender: Sender<(usize, Message)>,
+    wake: fn()
+) {
+    for line in BufReader::new(output).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break
+        };
+        if sender.send((id, message(line))).is_err() {
+            break;
+        }
+        wake();
+    }
+}
+
+fn wait(mut child: Child) -> Message {
+    match child.wait() {
+        Ok(status) => match (status.code(), status.signal()) {
+            (Some(code), _) => Message::Exited(ExitReason::Exit, code),
+            (None, Some(signal)) => Message::Exited(ExitReason::Signal, signal),
+            (None, None) => Message::Exited(ExitReason::Exit, -1)
+        },
+        Err(err) => {
+            warn!("Could not wait for a spawned command: {}", err);
+            Message::Exited(ExitReason::Exit, -1)
+        }
+    }
+}
+
+/// Wakes up the Lua thread to handle the messages, unless it already was.
+fn wake() {
+    if !WOKEN.swap(true, Ordering::SeqCst) {
+        glib::idle_add(|| {
+            scheduler::defer(Priority::Timer, dispatch);
+            Continue(false)
+        });
+    }
+}
+
+/// Calls the callbacks for every message that was sent.
+fn dispatch(lua: rlua::Context) -> rlua::Result<()> {
+    WOKEN.store(false, Ordering::SeqCst);
+    let messages: Vec<_> = CHANNEL.with(|channel| channel.borrow().receiver.try_iter().collect());
+    for (id, message) in messages {
+        if let Err(err) = call_callback(lua, id, message) {
+            warn!("Error in a spawn callback: {}", err);
+        }
+    }
+    Ok(())
+}
+
+fn call_callback(lua: rlua::Context, id: usize, message: Message) -> rlua::Result<()> {
+    let all = all_callbacks(lua)?;
+    let callbacks = match all.get::<_, Option<Table>>(id)? {
+        Some(callbacks) => callbacks,
+        None => return Ok(())
+    };
+    match message {
+        Message::Stdout(line) => call(callbacks.get("stdout")?, line),
+        Message::Stderr(line) => call(callbacks.get("stderr")?, line),
+        Message::Exited(reason, code) => {
+            all.set(id, Value::Nil)?;
+            call(callbacks.get("exit")?, (reason.name(), code))
+        }
+    }
+}
+
+fn call<'lua, A: rlua::ToLuaMulti<'lua>>(callback: Option<Function<'lua>>, args: A) -> rlua::Result<()> {
+    match callback {
+        Some(callback) => callback.call(args),
+        None => Ok(())
+    }
+}
+
+fn all_callbacks(lua: rlua::Context) -> rlua::Result<Table> {
+    match lua.named_registry_value::<str, Option<Table>>(CALLBACKS_HANDLE)? {
+        Some(callbacks) => Ok(callbacks),
+        None => {
+            let callbacks = lua.create_table()?;
+            lua.set_named_registry_value(CALLBACKS_HANDLE, callbacks.clone())?;
+            Ok(callbacks)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn no_wake() {}
+
+    fn run(argv: &[&str], input: Option<&str>) -> Vec<Message> {
+        let (sender, receiver) = mpsc::channel();
+        let argv: Vec<String> = argv.iter().map(|arg| arg.to_string()).collect();
+        spawn(&argv, input.map(String::from), 7, sender, no_wake).unwrap();
+        let mut messages = Vec::new();
+        for (id, message) in receiver {
+            assert_eq!(id, 7);
+            let exited = match message {
+                Message::Exited(..) => true,
+                _ => false
+            };
+            messages.push(message);
+            if exited {
+                break;
+            }
+        }
+        messages
+    }
+
+    #[test]
+    fn spawn_lines_output() {
+        let messages = run(&["sh", "-c", "echo one; echo two; echo oops >&2; exit 3"], None);
+        let stdout: Vec<_> = messages
+            .iter()
+            .filter(|message| match message {
+                Message::Stdout(_) => true,
+                _ => false
+            })
+            .collect();
+        assert_eq!(
+            stdout,
+            vec![&Message::Stdout("one".into()), &Message::Stdout("two".into())]
+        );
+        assert!(messages.contains(&Message::Stderr("oops".into())));
+        // The exit comes after all of the output.
+        assert_eq!(messages.last(), Some(&Message::Exited(ExitReason::Exit, 3)));
+        assert_eq!(messages.len(), 4);
+    }
+
+    #[test]
+    fn spawn_lines_input() {
+        let messages = run(
+            &["sh", "-c", "read a; read b; echo \"$b $a\""],
+            Some("world\nhello\n")
+        );
+        assert_eq!(
+            messages,
+            vec![
+                Message::Stdout("hello world".into()),
+                Message::Exited(ExitReason::Exit, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn spawn_lines_signal() {
+        let messages = run(&["sh", "-c", "kill -9 $$"], None);
+        assert_eq!(messages, vec![Message::Exited(ExitReason::Signal, 9)]);
+    }
+
+    #[test]
+    fn spawn_lines_errors() {
+        let (sender, _) = mpsc::channel();
+        assert!(spawn(&[], None, 1, sender.clone(), no_wake).is_err());
+        assert!(spawn(&["/nonexistent/command".into()], None, 1, sender, no_wake).is_err());
+    }
+}
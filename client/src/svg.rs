@@ -0,0 +1,561 @@
+//! `awesome.load_svg` and `drawable:draw_svg`, which render SVG images at
+//! exactly the size they are shown at instead of scaling a raster of them.
+//!
+//! An image is checked once when it's loaded and rendered with the SVG
+//! loader of gdk-pixbuf, which is librsvg. Renderings are cached by image
+//! and size, so drawing the same icon again doesn't render it again.
+//!
+//! Images that refer to anything outside of themselves, like other files
+//! or URLs, are refused: a theme shouldn't be able to make us read
+//! arbitrary files or go on the network.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    fmt, fs,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc
+    }
+};
+
+use cairo::ImageSurface;
+use gdk_pixbuf::{PixbufLoader, PixbufLoaderExt};
+use rlua::{self, Table, ToLua, UserData, UserDataMethods, Value};
+use xml::{
+    common::Position,
+    reader::{EventReader, XmlEvent}
+};
+
+use crate::awesome;
+
+/// How many bytes of renderings are remembered.
+pub const CACHE_BYTES: usize = 32 << 20;
+
+/// The largest width or height an image is rendered at.
+pub const MAX_SIZE: i32 = 1 << 14;
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+thread_local! {
+    static CACHE: RefCell<RenderCache> = RefCell::new(RenderCache::new(CACHE_BYTES));
+}
+
+/// Why an image couldn't be loaded or rendered.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SvgError {
+    /// The file couldn't be read.
+    Io(String),
+    /// The image isn't well-formed XML, at a line and column counting from
+    /// 1.
+    Parse { message: String, line: u64, column: u64 },
+    /// The image refers to something outside of itself.
+    External(String),
+    /// librsvg couldn't render the image.
+    Render(String),
+    /// The size isn't one the image can be rendered at.
+    Size { width: i32, height: i32 }
+}
+
+impl SvgError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SvgError::Io(_) => "io",
+            SvgError::Parse { .. } => "parse",
+            SvgError::External(_) => "external",
+            SvgError::Render(_) => "render",
+            SvgError::Size { .. } => "size"
+        }
+    }
+
+    /// The error as a table like `{ kind = "parse", message = "...", line =
+    /// 3, column = 7 }`. Only parse errors have a line and column.
+    pub fn to_lua<'lua>(&self, lua: rlua::Context<'lua>) -> rlua::Result<Table<'lua>> {
+        let table = lua.create_table()?;
+        table.set("kind", self.kind())?;
+        table.set("message", self.to_string())?;
+        if let SvgError::Parse { line, column, .. } = *self {
+            table.set("line", line)?;
+            table.set("column", column)?;
+        }
+        Ok(table)
+    }
+}
+
+impl fmt::Display for SvgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SvgError::Io(err) => write!(f, "could not read the image: {}", err),
+            SvgError::Parse {
+                message,
+                line,
+                column
+            } => write!(f, "{}:{}: {}", line, column, message),
+            SvgError::External(reference) => {
+                write!(
+                    f,
+                    "refusing to load \"{}\", which is outside of the image",
+                    reference
+                )
+            },
+            SvgError::Render(err) => write!(f, "could not render the image: {}", err),
+            SvgError::Size { width, height } => write!(
+                f,
+                "can't render at {}x{}, the size must be from 1x1 to {}x{}",
+                width, height, MAX_SIZE, MAX_SIZE
+            )
+        }
+    }
+}
+
+/// A loaded image, which is cheap to clone.
+///
+/// Lua's user data has to be `Send`, so the markup is in an `Arc`.
+#[derive(Debug, Clone)]
+pub struct Svg {
+    /// Tells renderings of different images apart in the cache.
+    id: usize,
+    data: Arc<Vec<u8>>,
+    width: i32,
+    height: i32
+}
+
+impl Svg {
+    /// Loads the image in `source`, which is either the markup of the image
+    /// or the path of the file it's in.
+    pub fn load(source: &str) -> Result<Svg, SvgError> {
+        if source.trim_start().starts_with('<') {
+            Svg::parse(source.as_bytes().to_vec())
+        } else {
+            let data = fs::read(source).map_err(|err| SvgError::Io(format!("{}: {}", source, err)))?;
+            Svg::parse(data)
+        }
+    }
+
+    pub fn parse(data: Vec<u8>) -> Result<Svg, SvgError> {
+        check_references(&data)?;
+        let (width, height) = intrinsic_size(&data)?;
+        Ok(Svg {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            data: Arc::new(data),
+            width,
+            height
+        })
+    }
+
+    /// The size the image says it is.
+    pub fn size(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+
+    /// Renders the image stretched to `width` by `height`.
+    pub fn rasterize(&self, width: i32, height: i32) -> Result<ImageSurface, SvgError> {
+        if width < 1 || height < 1 || width > MAX_SIZE || height > MAX_SIZE {
+            return Err(SvgError::Size { width, height });
+        }
+        let loader = svg_loader()?;
+        loader.set_size(width, height);
+        loader
+            .write(&self.data)
+            .and_then(|_| loader.close())
+            .map_err(|err| SvgError::Render(err.to_string()))?;
+        let pixbuf = loader
+            .get_pixbuf()
+            .ok_or_else(|| SvgError::Render("the image is empty".into()))?;
+        Ok(awesome::load_surface_from_pixbuf(pixbuf))
+    }
+}
+
+fn svg_loader() -> Result<PixbufLoader, SvgError> {
+    PixbufLoader::new_with_type("svg").map_err(|err| SvgError::Render(err.to_string()))
+}
+
+/// The size of the image, rendering it as small as possible to find out.
+fn intrinsic_size(data: &[u8]) -> Result<(i32, i32), SvgError> {
+    let loader = svg_loader()?;
+    let size = Rc::new(RefCell::new(None));
+    let prepared = size.clone();
+    loader.connect_size_prepared(move |loader, width, height| {
+        *prepared.borrow_mut() = Some((width, height));
+        loader.set_size(1, 1);
+    });
+    loader
+        .write(data)
+        .and_then(|_| loader.close())
+        .map_err(|err| SvgError::Render(err.to_string()))?;
+    let size = size.borrow_mut().take();
+    size.ok_or_else(|| SvgError::Render("the image has no size".into()))
+}
+
+/// Checks that the image doesn't refer to anything but itself, and that it
+/// is well-formed.
+fn check_references(data: &[u8]) -> Result<(), SvgError> {
+    // Entities are how XML includes other files, and they aren't needed for
+    // anything else in an image.
+    if data.windows(8).any(|window| window == b"<!ENTITY") {
+        return Err(SvgError::External("<!ENTITY".into()));
+    }
+    let mut in_style = false;
+    for event in EventReader::new(data) {
+        let event = event.map_err(|err| SvgError::Parse {
+            message: err.msg().into(),
+            line: err.position().row + 1,
+            column: err.position().column + 1
+        })?;
+        match event {
+            XmlEvent::StartElement { name, attributes, .. } => {
+                in_style = name.local_name == "style";
+                for attribute in attributes {
+                    if attribute.name.local_name == "href" && !is_internal(&attribute.value) {
+                        return Err(SvgError::External(attribute.value));
+                    }
+                    check_css(&attribute.value)?;
+                }
+            },
+            XmlEvent::EndElement { .. } => in_style = false,
+            XmlEvent::Characters(text) | XmlEvent::CData(text) if in_style => check_css(&text)?,
+            XmlEvent::ProcessingInstruction { name, .. } if name == "xml-stylesheet" => {
+                return Err(SvgError::External(format!("<?{}", name)))
+            },
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Checks the `url()`s and `@import`s in a style sheet or attribute.
+fn check_css(css: &str) -> Result<(), SvgError> {
+    if css.contains("@import") {
+        return Err(SvgError::External("@import".into()));
+    }
+    for (start, _) in css.match_indices("url(") {
+        let rest = &css[start + 4..];
+        let url = rest[..rest.find(')').unwrap_or_else(|| rest.len())]
+            .trim()
+            .trim_matches(|c| c == '"' || c == '\'');
+        if !is_internal(url) {
+            return Err(SvgError::External(url.into()));
+        }
+    }
+    Ok(())
+}
+
+/// Whether a reference is to a part of the image or to data in it.
+fn is_internal(reference: &str) -> bool {
+    let reference = reference.trim();
+    reference.starts_with('#') || reference.starts_with("data:")
+}
+
+/// How often renderings were found in the cache.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// The bytes of the renderings that are remembered.
+    pub bytes: usize
+}
+
+/// The most recently used renderings, up to a number of bytes.
+#[derive(Debug)]
+pub struct RenderCache {
+    budget: usize,
+    renderings: HashMap<(usize, i32, i32), ImageSurface>,
+    /// The keys of `renderings`, least recently used first.
+    order: VecDeque<(usize, i32, i32)>,
+    stats: CacheStats
+}
+
+impl RenderCache {
+    pub fn new(budget: usize) -> Self {
+        RenderCache {
+            budget,
+            renderings: HashMap::new(),
+            order: VecDeque::new(),
+            stats: CacheStats::default()
+        }
+    }
+
+    /// The rendering of `svg` at the size, rendering it if it isn't
+    /// remembered.
+    ///
+    /// Renderings bigger than the whole budget are never remembered.
+    pub fn render(&mut self, svg: &Svg, width: i32, height: i32) -> Result<ImageSurface, SvgError> {
+        let key = (svg.id, width, height);
+        if let Some(surface) = self.renderings.get(&key).cloned() {
+            self.stats.hits += 1;
+            self.touch(&key);
+            return Ok(surface);
+        }
+        self.stats.misses += 1;
+        let surface = svg.rasterize(width, height)?;
+        let bytes = surface_bytes(&surface);
+        if bytes <= self.budget {
+            while self.stats.bytes + bytes > self.budget {
+                self.evict_oldest();
+            }
+            self.stats.bytes += bytes;
+            self.renderings.insert(key, surface.clone());
+            self.order.push_back(key);
+        }
+        Ok(surface)
+    }
+
+    /// Forgets the renderings of an image that was dropped.
+    pub fn forget(&mut self, id: usize) {
+        while let Some(index) = self.order.iter().position(|&(other, _, _)| other == id) {
+            let key = self.order.remove(index).unwrap();
+            if let Some(surface) = self.renderings.remove(&key) {
+                self.stats.bytes -= surface_bytes(&surface);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some(oldest) = self.order.pop_front() {
+            if let Some(surface) = self.renderings.remove(&oldest) {
+                self.stats.bytes -= surface_bytes(&surface);
+            }
+        }
+    }
+
+    fn touch(&mut self, key: &(usize, i32, i32)) {
+        if let Some(index) = self.order.iter().position(|other| other == key) {
+            let key = self.order.remove(index).unwrap();
+            self.order.push_back(key);
+        }
+    }
+}
+
+fn surface_bytes(surface: &ImageSurface) -> usize {
+    surface.get_stride() as usize * surface.get_height() as usize
+}
+
+/// Renders `svg` at the size, from the cache if it was rendered at that
+/// size before.
+pub fn render(svg: &Svg, width: i32, height: i32) -> Result<ImageSurface, SvgError> {
+    CACHE.with(|cache| cache.borrow_mut().render(svg, width, height))
+}
+
+/// An image as Lua sees it.
+#[derive(Debug, Clone)]
+pub struct SvgHandle(pub Svg);
+
+impl Drop for SvgHandle {
+    fn drop(&mut self) {
+        // The last handle of the image is the one Lua collects.
+        if Arc::strong_count(&self.0.data) == 1 {
+            CACHE.with(|cache| {
+                if let Ok(mut cache) = cache.try_borrow_mut() {
+                    cache.forget(self.0.id)
+                }
+            })
+        }
+    }
+}
+
+impl UserData for SvgHandle {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        // `handle:render(width, height)`, which returns a cairo surface or
+        // nil and the error.
+        methods.add_method("render", |lua, handle, (width, height): (i32, i32)| match render(
+            &handle.0, width, height
+        ) {
+            Ok(surface) => Ok((awesome::surface_to_lua(lua, surface)?, Value::Nil)),
+            Err(err) => Ok((Value::Nil, Value::Table(err.to_lua(lua)?)))
+        });
+        // `handle:size()`, the width and height the image says it is.
+        methods.add_method("size", |_, handle, ()| Ok(handle.0.size()));
+    }
+}
+
+/// `awesome.load_svg(path_or_markup)`, which returns a handle of the image
+/// or nil and the error.
+pub fn load_svg<'lua>(lua: rlua::Context<'lua>, source: String) -> rlua::Result<(Value<'lua>, Value<'lua>)> {
+    match Svg::load(&source) {
+        Ok(svg) => Ok((SvgHandle(svg).to_lua(lua)?, Value::Nil)),
+        Err(err) => Ok((Value::Nil, Value::Table(err.to_lua(lua)?)))
+    }
+}
+
+/// `awesome.svg_cache_stats()`, which returns `{ hits = ..., misses = ...,
+/// bytes = ... }`.
+pub fn svg_cache_stats(lua: rlua::Context, _: ()) -> rlua::Result<Table> {
+    let stats = CACHE.with(|cache| cache.borrow().stats());
+    let table = lua.create_table()?;
+    table.set("hits", stats.hits)?;
+    table.set("misses", stats.misses)?;
+    table.set("bytes", stats.bytes)?;
+    Ok(table)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A red square in the middle of a 16x16 image.
+    const SQUARE: &str = r##"<svg xmlns="http://www.w3.org/2000/svg" width="16" height="16" viewBox="0 0 16 16">
+        <rect x="4" y="4" width="8" height="8" fill="#ff0000"/>
+    </svg>"##;
+
+    /// A blue circle filling a 16x16 image.
+    const CIRCLE: &str = r##"<svg xmlns="http://www.w3.org/2000/svg" width="16" height="16" viewBox="0 0 16 16">
+        <circle cx="8" cy="8" r="8" fill="#0000ff"/>
+    </svg>"##;
+
+    /// A gradient from black to white, left to right, with a style sheet.
+    const GRADIENT: &str = r##"<svg xmlns="http://www.w3.org/2000/svg" width="32" height="8">
+        <defs>
+            <linearGradient id="ramp"><stop offset="0" stop-color="#000"/><stop offset="1" stop-color="#fff"/></linearGradient>
+            <style>rect { fill: url(#ramp); }</style>
+        </defs>
+        <rect width="32" height="8"/>
+    </svg>"##;
+
+    /// The premultiplied (alpha, red, green, blue) of a pixel.
+    fn pixel(surface: &mut ImageSurface, x: i32, y: i32) -> (u8, u8, u8, u8) {
+        let stride = surface.get_stride() as usize;
+        let data = surface.get_data().unwrap();
+        let i = y as usize * stride + x as usize * 4;
+        (data[i + 3], data[i + 2], data[i + 1], data[i])
+    }
+
+    fn alpha_sum(surface: &mut ImageSurface) -> f64 {
+        let (width, height) = (surface.get_width(), surface.get_height());
+        let mut sum = 0.0;
+        for y in 0..height {
+            for x in 0..width {
+                sum += f64::from(pixel(surface, x, y).0) / 255.0;
+            }
+        }
+        sum
+    }
+
+    #[test]
+    fn svg_square_at_scales() {
+        let svg = Svg::load(SQUARE).unwrap();
+        assert_eq!(svg.size(), (16, 16));
+        for &scale in &[1.0, 1.5, 2.0] {
+            let size = (16.0 * scale) as i32;
+            let mut surface = svg.rasterize(size, size).unwrap();
+            assert_eq!((surface.get_width(), surface.get_height()), (size, size));
+            let (start, end) = ((4.0 * scale) as i32, (12.0 * scale) as i32);
+            for y in 0..size {
+                for x in 0..size {
+                    let inside = x >= start && x < end && y >= start && y < end;
+                    let expected = if inside { (255, 255, 0, 0) } else { (0, 0, 0, 0) };
+                    assert_eq!(pixel(&mut surface, x, y), expected, "{},{} at {}", x, y, scale);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn svg_circle_at_scales() {
+        let svg = Svg::load(CIRCLE).unwrap();
+        for &scale in &[1.0, 1.5, 2.0] {
+            let size = (16.0 * scale) as i32;
+            let mut surface = svg.rasterize(size, size).unwrap();
+            let center = size / 2;
+            assert_eq!(pixel(&mut surface, center, center), (255, 0, 0, 255));
+            assert_eq!(pixel(&mut surface, 0, 0), (0, 0, 0, 0));
+            // Antialiased, the edge is covered as much as the circle is big.
+            let radius = 8.0 * scale;
+            let area = std::f64::consts::PI * radius * radius;
+            let covered = alpha_sum(&mut surface);
+            assert!(
+                (covered - area).abs() < area * 0.02,
+                "{} for {} at {}",
+                covered,
+                area,
+                scale
+            );
+        }
+    }
+
+    #[test]
+    fn svg_gradient_at_scales() {
+        let svg = Svg::load(GRADIENT).unwrap();
+        for &scale in &[1.0, 1.5, 2.0] {
+            let (width, height) = ((32.0 * scale) as i32, (8.0 * scale) as i32);
+            let mut surface = svg.rasterize(width, height).unwrap();
+            let y = height / 2;
+            let mut last = 0;
+            for x in 0..width {
+                let (alpha, red, green, blue) = pixel(&mut surface, x, y);
+                assert_eq!(alpha, 255);
+                assert!(red == green && green == blue, "{},{} isn't grey", x, y);
+                assert!(red >= last, "{},{} is darker than the pixel before", x, y);
+                last = red;
+            }
+            assert!(pixel(&mut surface, 0, y).1 < 16);
+            assert!(pixel(&mut surface, width - 1, y).1 > 239);
+        }
+    }
+
+    #[test]
+    fn svg_errors() {
+        let err = Svg::load("<svg xmlns=\"http://www.w3.org/2000/svg\">\n  <rect></svg>").unwrap_err();
+        match err {
+            SvgError::Parse { line, .. } => assert_eq!(line, 2),
+            _ => panic!("{:?} isn't a parse error", err)
+        }
+        let external = [
+            r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink"><image xlink:href="/etc/passwd"/></svg>"#,
+            r#"<svg xmlns="http://www.w3.org/2000/svg"><image href="https://example.com/a.png"/></svg>"#,
+            r#"<svg xmlns="http://www.w3.org/2000/svg"><rect style="fill: url('file:///a.svg#b')"/></svg>"#,
+            r#"<svg xmlns="http://www.w3.org/2000/svg"><style>@import "a.css";</style></svg>"#,
+            r#"<?xml-stylesheet href="a.css"?><svg xmlns="http://www.w3.org/2000/svg"/>"#,
+            r#"<!DOCTYPE svg [<!ENTITY a SYSTEM "/etc/passwd">]><svg xmlns="http://www.w3.org/2000/svg">&a;</svg>"#
+        ];
+        for markup in &external {
+            match Svg::load(markup) {
+                Err(SvgError::External(_)) => {},
+                other => panic!("{} gave {:?}", markup, other)
+            }
+        }
+        assert_eq!(Svg::load("/nonexistent/icon.svg").unwrap_err().kind(), "io");
+        let svg = Svg::load(SQUARE).unwrap();
+        assert_eq!(
+            svg.rasterize(0, 16).unwrap_err(),
+            SvgError::Size { width: 0, height: 16 }
+        );
+    }
+
+    #[test]
+    fn svg_cache_hits() {
+        let square = Svg::load(SQUARE).unwrap();
+        let circle = Svg::load(CIRCLE).unwrap();
+        // Room for two 16x16 renderings.
+        let mut cache = RenderCache::new(2 * 16 * 16 * 4);
+        for _ in 0..10 {
+            cache.render(&square, 16, 16).unwrap();
+        }
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 9,
+                misses: 1,
+                bytes: 16 * 16 * 4
+            }
+        );
+        cache.render(&circle, 16, 16).unwrap();
+        cache.render(&square, 16, 16).unwrap();
+        // The circle is the least recently used.
+        cache.render(&square, 8, 8).unwrap();
+        cache.render(&square, 16, 16).unwrap();
+        cache.render(&circle, 16, 16).unwrap();
+        assert_eq!((cache.stats().hits, cache.stats().misses), (11, 4));
+        // Too big to be remembered at all.
+        cache.render(&square, 64, 64).unwrap();
+        cache.render(&square, 64, 64).unwrap();
+        assert_eq!((cache.stats().hits, cache.stats().misses), (11, 6));
+        cache.forget(circle.id);
+        cache.forget(square.id);
+        assert_eq!(cache.stats().bytes, 0);
+    }
+}
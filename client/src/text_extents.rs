@@ -0,0 +1,118 @@
+//! `awesome.text_extents`, which measures text the way it's drawn so Lua
+//! can size a drawin before painting it.
+
+use std::{
+    cell::RefCell,
+    time::{Duration, Instant}
+};
+
+use cairo::{Context, Format, ImageSurface};
+use rlua::{self, Table};
+
+use crate::common::{
+    font::{self, Font},
+    text::{self, FontconfigStamp, TextCache, TextExtents}
+};
+
+/// How many measurements are remembered.
+const CACHE_CAPACITY: usize = 1024;
+
+/// How often the fontconfig configuration is checked for changes, which
+/// clears the cache.
+const RECHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The font size when the description has none.
+const DEFAULT_FONT_SIZE: f64 = 10.0;
+
+thread_local! {
+    static CACHE: RefCell<Cache> = RefCell::new(Cache {
+        texts: TextCache::new(CACHE_CAPACITY),
+        stamp: FontconfigStamp::read(),
+        checked: Instant::now()
+    });
+}
+
+struct Cache {
+    texts: TextCache,
+    stamp: FontconfigStamp,
+    checked: Instant
+}
+
+impl Cache {
+    /// Forgets every measurement if the fontconfig configuration changed
+    /// since it was last checked.
+    fn recheck(&mut self) {
+        if self.checked.elapsed() < RECHECK_INTERVAL {
+            return;
+        }
+        self.checked = Instant::now();
+        let stamp = FontconfigStamp::read();
+        if stamp != self.stamp {
+            self.stamp = stamp;
+            self.texts.clear();
+        }
+    }
+}
+
+/// Measures `text` in the font described by `font_desc`, e.g. "Sans 10".
+pub fn text_extents<'lua>(
+    lua: rlua::Context<'lua>,
+    (text, font_desc): (String, String)
+) -> rlua::Result<Table<'lua>> {
+    let extents = measure_all(&[text], &font_desc)?;
+    to_lua(lua, extents[0])
+}
+
+/// Measures every text in `texts` in the same font, which is cheaper than
+/// measuring them one at a time.
+pub fn text_extents_multi<'lua>(
+    lua: rlua::Context<'lua>,
+    (texts, font_desc): (Vec<String>, String)
+) -> rlua::Result<Vec<Table<'lua>>> {
+    measure_all(&texts, &font_desc)?
+        .into_iter()
+        .map(|extents| to_lua(lua, extents))
+        .collect()
+}
+
+fn measure_all(texts: &[String], font_desc: &str) -> rlua::Result<Vec<TextExtents>> {
+    let font = font::parse_font(font_desc, DEFAULT_FONT_SIZE);
+    CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        cache.recheck();
+        // The font is only set up once something isn't cached.
+        let mut cr: Option<Context> = None;
+        let mut all = Vec::with_capacity(texts.len());
+        for text in texts {
+            if let Some(extents) = cache.texts.get(&font, text) {
+                all.push(extents);
+                continue;
+            }
+            if cr.is_none() {
+                cr = Some(measuring_context(&font)?);
+            }
+            let extents = text::measure(cr.as_ref().unwrap(), text).map_err(rlua::Error::RuntimeError)?;
+            cache.texts.insert(&font, text, extents);
+            all.push(extents);
+        }
+        Ok(all)
+    })
+}
+
+fn measuring_context(font: &Font) -> rlua::Result<Context> {
+    let surface = ImageSurface::create(Format::ARgb32, 1, 1)
+        .map_err(|err| rlua::Error::RuntimeError(format!("could not measure text: {:?}", err)))?;
+    let cr = Context::new(&surface);
+    text::select_font(&cr, font);
+    Ok(cr)
+}
+
+fn to_lua(lua: rlua::Context, extents: TextExtents) -> rlua::Result<Table> {
+    let table = lua.create_table()?;
+    table.set("width", extents.width)?;
+    table.set("height", extents.height)?;
+    table.set("ascent", extents.ascent)?;
+    table.set("descent", extents.descent)?;
+    table.set("x_advance", extents.x_advance)?;
+    Ok(table)
+}
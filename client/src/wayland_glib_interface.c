@@ -1,15 +1,17 @@
+#include <errno.h>
 #include <stdlib.h>
 #include <fcntl.h>
 #include <glib.h>
 #include <dbus/dbus.h>
 #include <wayland-client-core.h>
 
-static GSource *session_source = NULL;
-static GSource *system_source = NULL;
+/* The sources watching the session, system and accessibility bus, indexed
+ * by the bus. */
+static GSource *dbus_sources[3] = { NULL, NULL, NULL };
 
 void awesome_refresh(void* wayland_state);
-gboolean dbus_session_refresh(void* data);
-gboolean dbus_system_refresh(void* data);
+void awesome_protocol_error(uint32_t code, const char *interface, uint32_t id);
+gboolean dbus_refresh(void* bus);
 
 /* Instance of an event source that we use to integrate the wayland event queue
  * with GLib's MainLoop.
@@ -59,6 +61,14 @@ static gboolean interface_dispatch(GSource *base, GSourceFunc callback,
 	struct InterfaceEventSource *interface_source
 		= (struct InterfaceEventSource *) base;
 	if (wl_display_roundtrip(interface_source->display) == -1) {
+		if (wl_display_get_error(interface_source->display) == EPROTO) {
+			const struct wl_interface *interface = NULL;
+			uint32_t id = 0;
+			uint32_t code = wl_display_get_protocol_error(
+					interface_source->display, &interface, &id);
+			awesome_protocol_error(code,
+					interface ? interface->name : NULL, id);
+		}
 		exit(0);
 	}
 
@@ -71,27 +81,30 @@ static gboolean interface_dispatch(GSource *base, GSourceFunc callback,
 }
 
 
-static void setup_dbus_callback(int fd, GSourceFunc cb, GSource **source) {
+void remove_dbus_from_glib(int bus) {
+	if (dbus_sources[bus]) {
+		g_source_destroy(dbus_sources[bus]);
+		g_source_unref(dbus_sources[bus]);
+		dbus_sources[bus] = NULL;
+	}
+}
+
+/* Watches the connection to a bus, 0 for the session bus, 1 for the system
+ * bus and 2 for the accessibility bus. It's connected to the first time it's
+ * used.
+ */
+void add_dbus_to_glib(int fd, int bus) {
 	GIOChannel *channel = g_io_channel_unix_new(fd);
-	*source = g_io_create_watch(channel, G_IO_IN);
+
+	remove_dbus_from_glib(bus);
+	dbus_sources[bus] = g_io_create_watch(channel, G_IO_IN);
 	g_io_channel_unref(channel);
-	g_source_set_callback(*source, cb, NULL, NULL);
-	g_source_attach(*source, NULL);
+	g_source_set_callback(dbus_sources[bus], dbus_refresh, GINT_TO_POINTER(bus), NULL);
+	g_source_attach(dbus_sources[bus], NULL);
 
 	fcntl(fd, F_SETFD, FD_CLOEXEC);
 }
 
-void remove_dbus_from_glib() {
-	if (session_source) {
-		g_source_destroy(session_source);
-		session_source = NULL;
-	}
-	if (system_source) {
-		g_source_destroy(system_source);
-		system_source = NULL;
-	}
-}
-
 static GSourceFuncs interface_funcs = {
 	.prepare  = interface_prepare,
 	.check    = interface_check,
@@ -102,7 +115,7 @@ static GSourceFuncs interface_funcs = {
  * integrates the wayland event queue with the GLib main loop.
  */
 void wayland_glib_interface_init(struct wl_display *display,
-		int session_fd, int system_fd, void *wayland_state)
+		void *wayland_state)
 {
 	struct InterfaceEventSource *interface_source;
 	GSource *source = g_source_new(&interface_funcs, sizeof(*interface_source));
@@ -117,8 +130,5 @@ void wayland_glib_interface_init(struct wl_display *display,
 			G_IO_IN | G_IO_ERR | G_IO_HUP);
 	g_source_set_can_recurse(source, TRUE);
 
-	setup_dbus_callback(session_fd, dbus_session_refresh, &session_source);
-	setup_dbus_callback(system_fd, dbus_system_refresh, &system_source);
-
 	g_source_attach(source, NULL);
 }
@@ -0,0 +1,739 @@
+//! Wrapper around the wp_color_manager_v1 global, which tags the surfaces
+//! of drawins with their color profile and reads the color of outputs.
+//!
+//! Compositors take untagged surfaces to be sRGB, but on an HDR output they
+//! may map them to less light than tagged ones, which washes out bars next
+//! to windows that are tagged. So once the global is there, a surface is
+//! tagged with an image description made of the named transfer function
+//! and primaries of its profile, see `description`. That only happens once
+//! the compositor said it supports them and made the description, until
+//! then or if it doesn't the surface stays untagged, as it is without the
+//! global. `Tagging` decides which requests are made, and is tested against
+//! a fake of the compositor.
+//!
+//! The image description of every output is read into an `OutputColor`,
+//! which the screen of the output shows as `screen.color`.
+
+use std::{cell::RefCell, os::unix::io::RawFd};
+
+use nix::unistd;
+use wayland_client::{
+    protocol::{wl_output::WlOutput, wl_surface::WlSurface},
+    GlobalImplementor, NewProxy
+};
+
+pub use self::generated::client::wp_color_manager_v1::WpColorManagerV1;
+use self::generated::client::{
+    wp_color_management_output_v1::{self, WpColorManagementOutputV1},
+    wp_color_management_surface_v1::WpColorManagementSurfaceV1,
+    wp_color_manager_v1,
+    wp_image_description_creator_params_v1::WpImageDescriptionCreatorParamsV1,
+    wp_image_description_info_v1::{self, WpImageDescriptionInfoV1},
+    wp_image_description_v1::{self, WpImageDescriptionV1}
+};
+use crate::common::color_profile::Profile;
+use crate::wayland_obj::output::{self, Output};
+
+/// The minimum version of the wp_color_manager_v1 global to bind to.
+pub const COLOR_MANAGER_VERSION: u32 = 1;
+
+/// The code generated from protocols/color-management-v1.xml, which
+/// wayland-protocols doesn't have.
+mod generated {
+    #![allow(dead_code, non_camel_case_types, unused_unsafe, unused_variables)]
+    #![allow(non_upper_case_globals, non_snake_case, unused_imports)]
+    #![allow(clippy::all)]
+
+    pub mod client {
+        pub(crate) use wayland_client::protocol::{wl_output, wl_surface};
+        pub(crate) use wayland_client::sys;
+        pub(crate) use wayland_client::{AnonymousObject, HandledBy, NewProxy, Proxy, ProxyMap};
+        pub(crate) use wayland_commons::map::{Object, ObjectMetadata};
+        pub(crate) use wayland_commons::wire::{Argument, ArgumentType, Message, MessageDesc};
+        pub(crate) use wayland_commons::{Interface, MessageGroup};
+        include!(concat!(env!("OUT_DIR"), "/color-management-v1_client_api.rs"));
+    }
+}
+
+// The values of the enums of the protocol that are used.
+const FEATURE_PARAMETRIC: u32 = 1;
+const PRIMARIES_SRGB: u32 = 1;
+const TF_GAMMA22: u32 = 2;
+const TF_SRGB: u32 = 9;
+const RENDER_INTENT_PERCEPTUAL: u32 = 0;
+
+const PRIMARIES_NAMES: &[&str] = &[
+    "srgb",
+    "pal_m",
+    "pal",
+    "ntsc",
+    "generic_film",
+    "bt2020",
+    "cie1931_xyz",
+    "dci_p3",
+    "display_p3",
+    "adobe_rgb"
+];
+
+const TF_NAMES: &[&str] = &[
+    "bt1886",
+    "gamma22",
+    "gamma28",
+    "st240",
+    "ext_linear",
+    "log_100",
+    "log_316",
+    "xvycc",
+    "srgb",
+    "ext_srgb",
+    "st2084_pq",
+    "st428",
+    "hlg"
+];
+
+thread_local! {
+    static COLOR_MANAGER: RefCell<Option<WpColorManagerV1>> = RefCell::new(None);
+    static TAGGING: RefCell<Tagging<Compositor>> = RefCell::new(Tagging::new(Compositor {}));
+    /// The color management objects of the surfaces that were tagged.
+    static SURFACES: RefCell<Vec<(WlSurface, WpColorManagementSurfaceV1)>> = RefCell::new(Vec::new());
+    /// The color management objects of the outputs.
+    static OUTPUTS: RefCell<Vec<(WlOutput, WpColorManagementOutputV1)>> = RefCell::new(Vec::new());
+}
+
+pub struct ColorManager {}
+
+impl GlobalImplementor<WpColorManagerV1> for ColorManager {
+    fn new_global(&mut self, new_proxy: NewProxy<WpColorManagerV1>) -> WpColorManagerV1 {
+        let res = new_proxy.implement(ColorManagerEventHandler {}, ());
+
+        COLOR_MANAGER.with(|manager| {
+            *manager.borrow_mut() = Some(res.clone());
+        });
+        // Outputs bound before the global are watched now.
+        for output in output::bound_outputs() {
+            watch_output(&output);
+        }
+
+        res
+    }
+}
+
+struct ColorManagerEventHandler {}
+
+impl wp_color_manager_v1::EventHandler for ColorManagerEventHandler {
+    fn supported_feature(&mut self, _: WpColorManagerV1, feature: u32) {
+        TAGGING.with(|tagging| tagging.borrow_mut().support.features.push(feature));
+    }
+
+    fn supported_tf_named(&mut self, _: WpColorManagerV1, tf: u32) {
+        TAGGING.with(|tagging| tagging.borrow_mut().support.tfs.push(tf));
+    }
+
+    fn supported_primaries_named(&mut self, _: WpColorManagerV1, primaries: u32) {
+        TAGGING.with(|tagging| tagging.borrow_mut().support.primaries.push(primaries));
+    }
+
+    fn done(&mut self, _: WpColorManagerV1) {
+        TAGGING.with(|tagging| tagging.borrow_mut().done());
+    }
+}
+
+/// The parameters an image description is made of.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Description {
+    /// The named transfer function.
+    pub tf: u32,
+    /// The named primaries.
+    pub primaries: u32
+}
+
+/// What the compositor said it supports.
+#[derive(Debug, Default)]
+struct Support {
+    features: Vec<u32>,
+    tfs: Vec<u32>,
+    primaries: Vec<u32>,
+    /// Set once the compositor said everything it supports.
+    done: bool
+}
+
+/// How `profile` is described to a compositor that supports `support`, or
+/// `None` if it can't be.
+///
+/// sRGB is described with the gamma 2.2 of the displays the sRGB standard
+/// was made for, which is how compositors show untagged surfaces, so
+/// tagging doesn't change how they look. The piecewise sRGB curve is only
+/// used by compositors without it.
+fn description(profile: Profile, support: &Support) -> Option<Description> {
+    if !support.features.contains(&FEATURE_PARAMETRIC) {
+        return None;
+    }
+    match profile {
+        Profile::Srgb => {
+            let tf = [TF_GAMMA22, TF_SRGB]
+                .iter()
+                .cloned()
+                .find(|tf| support.tfs.contains(tf))?;
+            if !support.primaries.contains(&PRIMARIES_SRGB) {
+                return None;
+            }
+            Some(Description {
+                tf,
+                primaries: PRIMARIES_SRGB
+            })
+        }
+    }
+}
+
+/// The requests tagging surfaces makes, which the tests fake.
+trait Requests {
+    type Surface: Clone + PartialEq;
+    type Description;
+
+    /// Makes the image description of `profile`, which is ready or failed
+    /// later, or `None` if it can't be made.
+    fn create_description(&mut self, profile: Profile, description: Description)
+        -> Option<Self::Description>;
+
+    /// Sets the image description of a surface, which it has from its next
+    /// commit on.
+    fn tag(&mut self, surface: &Self::Surface, description: &Self::Description);
+}
+
+enum DescriptionState<D> {
+    /// Made, but the compositor didn't say whether it can be used yet.
+    Creating(D),
+    Ready(D),
+    Failed
+}
+
+/// Tags surfaces with their profile once the image description of the
+/// profile is ready.
+struct Tagging<R: Requests> {
+    requests: R,
+    support: Support,
+    /// The image descriptions made so far, by profile.
+    descriptions: Vec<(Profile, DescriptionState<R::Description>)>,
+    /// The surfaces waiting for the description of their profile.
+    waiting: Vec<(R::Surface, Profile)>
+}
+
+impl<R: Requests> Tagging<R> {
+    fn new(requests: R) -> Self {
+        Tagging {
+            requests,
+            support: Support::default(),
+            descriptions: Vec::new(),
+            waiting: Vec::new()
+        }
+    }
+
+    /// Tags `surface` with `profile`, now or once its image description is
+    /// ready.
+    fn tag(&mut self, surface: R::Surface, profile: Profile) {
+        self.waiting.retain(|(waiting, _)| *waiting != surface);
+        if !self.support.done {
+            self.waiting.push((surface, profile));
+            return;
+        }
+        let index = match self.describe(profile) {
+            Some(index) => index,
+            None => return
+        };
+        match &self.descriptions[index].1 {
+            DescriptionState::Ready(description) => self.requests.tag(&surface, description),
+            DescriptionState::Creating(_) => self.waiting.push((surface, profile)),
+            DescriptionState::Failed => {}
+        }
+    }
+
+    /// The compositor said everything it supports.
+    fn done(&mut self) {
+        self.support.done = true;
+        let mut profiles = Vec::new();
+        for &(_, profile) in &self.waiting {
+            if !profiles.contains(&profile) {
+                profiles.push(profile);
+            }
+        }
+        for profile in profiles {
+            if self.describe(profile).is_none() {
+                self.waiting.retain(|&(_, waiting)| waiting != profile);
+            }
+        }
+    }
+
+    /// The image description of `profile` can be used.
+    fn ready(&mut self, profile: Profile) {
+        let state = match self.descriptions.iter_mut().find(|(other, _)| *other == profile) {
+            Some((_, state)) => state,
+            None => return
+        };
+        *state = match std::mem::replace(state, DescriptionState::Failed) {
+            DescriptionState::Creating(description) => DescriptionState::Ready(description),
+            state => state
+        };
+        let description = match state {
+            DescriptionState::Ready(description) => description,
+            _ => return
+        };
+        let requests = &mut self.requests;
+        self.waiting.retain(|(surface, waiting)| {
+            if *waiting != profile {
+                return true;
+            }
+            requests.tag(surface, description);
+            false
+        });
+    }
+
+    /// The compositor couldn't make the image description of `profile`, so
+    /// its surfaces stay untagged.
+    fn failed(&mut self, profile: Profile) {
+        if let Some((_, state)) = self.descriptions.iter_mut().find(|(other, _)| *other == profile) {
+            *state = DescriptionState::Failed;
+        }
+        self.waiting.retain(|&(_, waiting)| waiting != profile);
+    }
+
+    /// The index of the image description of `profile`, which is made the
+    /// first time it's needed, or `None` if the compositor can't show it.
+    fn describe(&mut self, profile: Profile) -> Option<usize> {
+        let index = match self.descriptions.iter().position(|(other, _)| *other == profile) {
+            Some(index) => index,
+            None => {
+                let description = description(profile, &self.support)?;
+                let state = match self.requests.create_description(profile, description) {
+                    Some(description) => DescriptionState::Creating(description),
+                    None => DescriptionState::Failed
+                };
+                self.descriptions.push((profile, state));
+                self.descriptions.len() - 1
+            }
+        };
+        Some(index)
+    }
+}
+
+/// The requests made to the compositor.
+struct Compositor {}
+
+impl Requests for Compositor {
+    type Surface = WlSurface;
+    type Description = WpImageDescriptionV1;
+
+    fn create_description(
+        &mut self,
+        profile: Profile,
+        description: Description
+    ) -> Option<WpImageDescriptionV1> {
+        COLOR_MANAGER.with(|manager| {
+            let manager = manager.borrow();
+            let manager = manager.as_ref()?;
+            let creator: WpImageDescriptionCreatorParamsV1 = manager
+                .create_parametric_creator(NewProxy::implement_dummy)
+                .ok()?;
+            creator.set_tf_named(description.tf);
+            creator.set_primaries_named(description.primaries);
+            creator
+                .create(|new_proxy| new_proxy.implement(DescriptionEventHandler { profile }, ()))
+                .ok()
+        })
+    }
+
+    fn tag(&mut self, surface: &WlSurface, description: &WpImageDescriptionV1) {
+        SURFACES.with(|surfaces| {
+            let mut surfaces = surfaces.borrow_mut();
+            // The objects of destroyed surfaces do nothing anymore.
+            surfaces.retain(|(surface, color_surface)| {
+                let alive = surface.as_ref().is_alive();
+                if !alive {
+                    color_surface.destroy();
+                }
+                alive
+            });
+            if !surfaces.iter().any(|(tagged, _)| tagged == surface) {
+                let color_surface = COLOR_MANAGER.with(|manager| {
+                    let manager = manager.borrow();
+                    manager
+                        .as_ref()?
+                        .get_surface(surface, NewProxy::implement_dummy)
+                        .ok()
+                });
+                match color_surface {
+                    Some(color_surface) => surfaces.push((surface.clone(), color_surface)),
+                    None => return
+                }
+            }
+            if let Some((_, color_surface)) = surfaces.iter().find(|(tagged, _)| tagged == surface) {
+                color_surface.set_image_description(description, RENDER_INTENT_PERCEPTUAL);
+            }
+        })
+    }
+}
+
+// Handle incoming events for the image description of a profile.
+struct DescriptionEventHandler {
+    profile: Profile
+}
+
+impl wp_image_description_v1::EventHandler for DescriptionEventHandler {
+    fn failed(&mut self, object: WpImageDescriptionV1, _: u32, msg: String) {
+        warn!(
+            "The compositor can't show the {} color profile, surfaces are left untagged: {}",
+            self.profile.name(),
+            msg
+        );
+        TAGGING.with(|tagging| tagging.borrow_mut().failed(self.profile));
+        object.destroy();
+    }
+
+    fn ready(&mut self, _: WpImageDescriptionV1, _: u32) {
+        TAGGING.with(|tagging| tagging.borrow_mut().ready(self.profile));
+    }
+}
+
+/// Tags the surface with `profile` from its next commit on, if the
+/// compositor does color management.
+pub fn tag_surface(surface: &WlSurface, profile: Profile) {
+    if COLOR_MANAGER.with(|manager| manager.borrow().is_none()) {
+        return;
+    }
+    TAGGING.with(|tagging| tagging.borrow_mut().tag(surface.clone(), profile));
+}
+
+/// The color an output shows, as the compositor described it.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OutputColor {
+    /// The name of the primaries, `"custom"` if they're given as
+    /// coordinates.
+    pub primaries: Option<&'static str>,
+    /// The name of the transfer function, `"power"` if it's a power curve.
+    pub transfer_function: Option<&'static str>,
+    /// The luminance range and reference white, in cd/m².
+    pub luminances: Option<(f64, f64, f64)>,
+    /// The luminance range of the display, in cd/m².
+    pub target_luminance: Option<(f64, f64)>,
+    /// The maximum content light level, in cd/m².
+    pub max_cll: Option<u32>,
+    /// The maximum frame-average light level, in cd/m².
+    pub max_fall: Option<u32>
+}
+
+impl OutputColor {
+    /// Whether the output shows more than the light of SDR white, which is
+    /// what PQ and HLG are for.
+    pub fn is_hdr(&self) -> bool {
+        match self.transfer_function {
+            Some("st2084_pq") | Some("hlg") => true,
+            _ => false
+        }
+    }
+
+    /// Whether the compositor described the light of the display, which HDR
+    /// content is mapped to.
+    pub fn has_hdr_metadata(&self) -> bool {
+        self.target_luminance.is_some() || self.max_cll.is_some() || self.max_fall.is_some()
+    }
+}
+
+/// The name of the value of an enum, which starts at 1, or "unknown" for a
+/// value newer than this client.
+fn enum_name(names: &'static [&'static str], value: u32) -> &'static str {
+    (value as usize)
+        .checked_sub(1)
+        .and_then(|index| names.get(index))
+        .cloned()
+        .unwrap_or("unknown")
+}
+
+/// Starts reading the color of `output`, now and whenever it changes.
+pub fn watch_output(wl_output: &WlOutput) {
+    let color_output = COLOR_MANAGER.with(|manager| {
+        let manager = manager.borrow();
+        let output = wl_output.clone();
+        manager
+            .as_ref()?
+            .get_output(wl_output, |new_proxy| {
+                new_proxy.implement(ColorOutputEventHandler { output }, ())
+            })
+            .ok()
+    });
+    if let Some(color_output) = color_output {
+        read_output_color(&color_output, wl_output);
+        OUTPUTS.with(|outputs| outputs.borrow_mut().push((wl_output.clone(), color_output)));
+    }
+}
+
+/// Stops reading the color of an output that was unplugged.
+pub fn forget_output(wl_output: &WlOutput) {
+    OUTPUTS.with(|outputs| {
+        outputs.borrow_mut().retain(|(output, color_output)| {
+            if output != wl_output {
+                return true;
+            }
+            color_output.destroy();
+            false
+        })
+    });
+}
+
+fn read_output_color(color_output: &WpColorManagementOutputV1, wl_output: &WlOutput) {
+    let output = wl_output.clone();
+    let description = color_output
+        .get_image_description(|new_proxy| new_proxy.implement(OutputDescriptionEventHandler { output }, ()));
+    if description.is_err() {
+        warn!("Could not read the color of output {}", output_name(wl_output));
+    }
+}
+
+// Handle incoming events for the color management object of an output.
+struct ColorOutputEventHandler {
+    output: WlOutput
+}
+
+impl wp_color_management_output_v1::EventHandler for ColorOutputEventHandler {
+    fn image_description_changed(&mut self, object: WpColorManagementOutputV1) {
+        read_output_color(&object, &self.output);
+    }
+}
+
+// Handle incoming events for the image description of an output, which is
+// only read once.
+struct OutputDescriptionEventHandler {
+    output: WlOutput
+}
+
+impl wp_image_description_v1::EventHandler for OutputDescriptionEventHandler {
+    fn failed(&mut self, object: WpImageDescriptionV1, _: u32, msg: String) {
+        warn!(
+            "Could not read the color of output {}: {}",
+            output_name(&self.output),
+            msg
+        );
+        output::set_color(&self.output, None);
+        object.destroy();
+    }
+
+    fn ready(&mut self, object: WpImageDescriptionV1, _: u32) {
+        let output = self.output.clone();
+        let info = object.get_information(|new_proxy| {
+            new_proxy.implement(InfoEventHandler { output }, RefCell::new(OutputColor::default()))
+        });
+        if info.is_err() {
+            warn!("Could not read the color of output {}", output_name(&self.output));
+        }
+        object.destroy();
+    }
+}
+
+// Handle incoming events for the information about the image description
+// of an output, which is collected until it's done.
+struct InfoEventHandler {
+    output: WlOutput
+}
+
+impl InfoEventHandler {
+    fn update<F: FnOnce(&mut OutputColor)>(object: &WpImageDescriptionInfoV1, f: F) {
+        if let Some(color) = object.as_ref().user_data::<RefCell<OutputColor>>() {
+            f(&mut color.borrow_mut());
+        }
+    }
+}
+
+impl wp_image_description_info_v1::EventHandler for InfoEventHandler {
+    fn done(&mut self, object: WpImageDescriptionInfoV1) {
+        let color = object
+            .as_ref()
+            .user_data::<RefCell<OutputColor>>()
+            .map(|color| color.borrow().clone());
+        output::set_color(&self.output, color);
+    }
+
+    fn icc_file(&mut self, _: WpImageDescriptionInfoV1, icc: RawFd, _: u32) {
+        let _ = unistd::close(icc);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn primaries(
+        &mut self,
+        object: WpImageDescriptionInfoV1,
+        _: i32,
+        _: i32,
+        _: i32,
+        _: i32,
+        _: i32,
+        _: i32,
+        _: i32,
+        _: i32
+    ) {
+        InfoEventHandler::update(&object, |color| color.primaries = Some("custom"));
+    }
+
+    fn primaries_named(&mut self, object: WpImageDescriptionInfoV1, primaries: u32) {
+        InfoEventHandler::update(&object, |color| {
+            color.primaries = Some(enum_name(PRIMARIES_NAMES, primaries))
+        });
+    }
+
+    fn tf_power(&mut self, object: WpImageDescriptionInfoV1, _: u32) {
+        InfoEventHandler::update(&object, |color| color.transfer_function = Some("power"));
+    }
+
+    fn tf_named(&mut self, object: WpImageDescriptionInfoV1, tf: u32) {
+        InfoEventHandler::update(&object, |color| {
+            color.transfer_function = Some(enum_name(TF_NAMES, tf))
+        });
+    }
+
+    fn luminances(
+        &mut self,
+        object: WpImageDescriptionInfoV1,
+        min_lum: u32,
+        max_lum: u32,
+        reference_lum: u32
+    ) {
+        InfoEventHandler::update(&object, |color| {
+            color.luminances = Some((min_luminance(min_lum), max_lum.into(), reference_lum.into()))
+        });
+    }
+
+    fn target_luminance(&mut self, object: WpImageDescriptionInfoV1, min_lum: u32, max_lum: u32) {
+        InfoEventHandler::update(&object, |color| {
+            color.target_luminance = Some((min_luminance(min_lum), max_lum.into()))
+        });
+    }
+
+    fn target_max_cll(&mut self, object: WpImageDescriptionInfoV1, max_cll: u32) {
+        InfoEventHandler::update(&object, |color| color.max_cll = Some(max_cll));
+    }
+
+    fn target_max_fall(&mut self, object: WpImageDescriptionInfoV1, max_fall: u32) {
+        InfoEventHandler::update(&object, |color| color.max_fall = Some(max_fall));
+    }
+}
+
+fn output_name(output: &WlOutput) -> String {
+    Output::from(output.clone()).name()
+}
+
+/// The minimum luminances of the protocol are in 0.0001 cd/m².
+fn min_luminance(min_lum: u32) -> f64 {
+    f64::from(min_lum) / 10_000.0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A compositor that records the requests, and says whether the
+    /// descriptions are ready when the test does.
+    #[derive(Default)]
+    struct Fake {
+        created: Vec<(Profile, Description)>,
+        tagged: Vec<(u32, usize)>
+    }
+
+    impl Requests for Fake {
+        type Surface = u32;
+        type Description = usize;
+
+        fn create_description(&mut self, profile: Profile, description: Description) -> Option<usize> {
+            self.created.push((profile, description));
+            Some(self.created.len())
+        }
+
+        fn tag(&mut self, surface: &u32, description: &usize) {
+            self.tagged.push((*surface, *description));
+        }
+    }
+
+    fn announce(tagging: &mut Tagging<Fake>, features: &[u32], tfs: &[u32], primaries: &[u32]) {
+        tagging.support.features.extend_from_slice(features);
+        tagging.support.tfs.extend_from_slice(tfs);
+        tagging.support.primaries.extend_from_slice(primaries);
+        tagging.done();
+    }
+
+    #[test]
+    fn color_management_tags_srgb() {
+        let mut tagging = Tagging::new(Fake::default());
+        // A surface made before the compositor said what it supports.
+        tagging.tag(1, Profile::Srgb);
+        assert!(tagging.requests.created.is_empty());
+        announce(
+            &mut tagging,
+            &[FEATURE_PARAMETRIC],
+            &[TF_SRGB, TF_GAMMA22, 11],
+            &[PRIMARIES_SRGB, 6]
+        );
+        let srgb = Description {
+            tf: TF_GAMMA22,
+            primaries: PRIMARIES_SRGB
+        };
+        assert_eq!(tagging.requests.created, vec![(Profile::Srgb, srgb)]);
+        // Nothing is tagged with a description that isn't ready.
+        tagging.tag(2, Profile::Srgb);
+        assert!(tagging.requests.tagged.is_empty());
+        tagging.ready(Profile::Srgb);
+        assert_eq!(tagging.requests.tagged, vec![(1, 1), (2, 1)]);
+        // The description is made once.
+        tagging.tag(3, Profile::Srgb);
+        assert_eq!(tagging.requests.created.len(), 1);
+        assert_eq!(tagging.requests.tagged.last(), Some(&(3, 1)));
+        assert!(tagging.waiting.is_empty());
+    }
+
+    #[test]
+    fn color_management_unsupported() {
+        // Without parametric descriptions surfaces stay untagged.
+        let mut tagging = Tagging::new(Fake::default());
+        tagging.tag(1, Profile::Srgb);
+        announce(&mut tagging, &[0], &[TF_SRGB], &[PRIMARIES_SRGB]);
+        tagging.tag(2, Profile::Srgb);
+        assert!(tagging.requests.created.is_empty());
+        assert!(tagging.waiting.is_empty());
+        // The piecewise curve is used without gamma 2.2.
+        let mut tagging = Tagging::new(Fake::default());
+        announce(&mut tagging, &[FEATURE_PARAMETRIC], &[TF_SRGB], &[PRIMARIES_SRGB]);
+        tagging.tag(1, Profile::Srgb);
+        assert_eq!(tagging.requests.created[0].1.tf, TF_SRGB);
+        // A description the compositor can't make leaves them untagged.
+        tagging.tag(2, Profile::Srgb);
+        tagging.failed(Profile::Srgb);
+        tagging.ready(Profile::Srgb);
+        tagging.tag(3, Profile::Srgb);
+        assert!(tagging.requests.tagged.is_empty());
+        assert_eq!(tagging.requests.created.len(), 1);
+        // Neither are surfaces without sRGB primaries.
+        let mut tagging = Tagging::new(Fake::default());
+        announce(&mut tagging, &[FEATURE_PARAMETRIC], &[TF_GAMMA22], &[6]);
+        tagging.tag(1, Profile::Srgb);
+        assert!(tagging.requests.created.is_empty());
+    }
+
+    #[test]
+    fn color_management_output_color() {
+        assert_eq!(enum_name(PRIMARIES_NAMES, 6), "bt2020");
+        assert_eq!(enum_name(TF_NAMES, 11), "st2084_pq");
+        assert_eq!(enum_name(TF_NAMES, 0), "unknown");
+        assert_eq!(enum_name(TF_NAMES, 14), "unknown");
+        let sdr = OutputColor {
+            primaries: Some("srgb"),
+            transfer_function: Some("gamma22"),
+            luminances: Some((0.2, 80.0, 80.0)),
+            ..OutputColor::default()
+        };
+        assert!(!sdr.is_hdr());
+        assert!(!sdr.has_hdr_metadata());
+        let hdr = OutputColor {
+            primaries: Some("bt2020"),
+            transfer_function: Some("st2084_pq"),
+            target_luminance: Some((min_luminance(50), 1000.0)),
+            ..OutputColor::default()
+        };
+        assert!(hdr.is_hdr());
+        assert!(hdr.has_hdr_metadata());
+        assert_eq!(hdr.target_luminance, Some((0.005, 1000.0)));
+    }
+}
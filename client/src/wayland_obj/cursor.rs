@@ -0,0 +1,196 @@
+//! The image of the pointer over our surfaces, loaded from the cursor theme
+//! with libwayland-cursor.
+//!
+//! Like awesome, cursors are named after the glyphs of the X cursor font,
+//! e.g. "hand1". The theme is the one of `XCURSOR_THEME` at the size of
+//! `XCURSOR_SIZE`, as for other clients, and is loaded the first time the
+//! pointer enters one of our surfaces. Only the first frame of animated
+//! cursors is shown.
+
+use std::{cell::RefCell, env};
+
+use wayland_client::{
+    cursor::{self, CursorTheme},
+    protocol::{wl_pointer::WlPointer, wl_surface::WlSurface}
+};
+
+use crate::event_trace::{self, Arg};
+
+use super::{wl_compositor, wl_shm};
+
+/// The cursor shown over surfaces that didn't choose one.
+pub const DEFAULT_CURSOR: &str = "left_ptr";
+
+/// The size of the cursors when `XCURSOR_SIZE` isn't set.
+const DEFAULT_SIZE: u32 = 24;
+
+/// The glyphs of the X cursor font.
+const NAMES: &[&str] = &[
+    "X_cursor",
+    "arrow",
+    "based_arrow_down",
+    "based_arrow_up",
+    "boat",
+    "bogosity",
+    "bottom_left_corner",
+    "bottom_right_corner",
+    "bottom_side",
+    "bottom_tee",
+    "box_spiral",
+    "center_ptr",
+    "circle",
+    "clock",
+    "coffee_mug",
+    "cross",
+    "cross_reverse",
+    "crosshair",
+    "diamond_cross",
+    "dot",
+    "dotbox",
+    "double_arrow",
+    "draft_large",
+    "draft_small",
+    "draped_box",
+    "exchange",
+    "fleur",
+    "gobbler",
+    "gumby",
+    "hand1",
+    "hand2",
+    "heart",
+    "icon",
+    "iron_cross",
+    "left_ptr",
+    "left_side",
+    "left_tee",
+    "leftbutton",
+    "ll_angle",
+    "lr_angle",
+    "man",
+    "middlebutton",
+    "mouse",
+    "pencil",
+    "pirate",
+    "plus",
+    "question_arrow",
+    "right_ptr",
+    "right_side",
+    "right_tee",
+    "rightbutton",
+    "rtl_logo",
+    "sailboat",
+    "sb_down_arrow",
+    "sb_h_double_arrow",
+    "sb_left_arrow",
+    "sb_right_arrow",
+    "sb_up_arrow",
+    "sb_v_double_arrow",
+    "shuttle",
+    "sizing",
+    "spider",
+    "spraycan",
+    "star",
+    "target",
+    "tcross",
+    "top_left_arrow",
+    "top_left_corner",
+    "top_right_corner",
+    "top_side",
+    "top_tee",
+    "trek",
+    "ul_angle",
+    "umbrella",
+    "ur_angle",
+    "watch",
+    "xterm"
+];
+
+thread_local! {
+    /// The theme and the surface the cursor is shown on, once the pointer
+    /// entered one of our surfaces. `None` inside if the theme can't be
+    /// loaded.
+    static CURSOR: RefCell<Option<Option<Cursor>>> = RefCell::new(None);
+}
+
+struct Cursor {
+    theme: CursorTheme,
+    surface: WlSurface
+}
+
+/// Whether `name` is one of the cursors of the X cursor font.
+pub fn is_cursor_name(name: &str) -> bool {
+    NAMES.contains(&name)
+}
+
+/// Shows the cursor `name` for the pointer, which entered a surface with
+/// `serial`. A cursor the theme doesn't have is shown as the default one.
+pub fn show(pointer: &WlPointer, serial: u32, name: &str) {
+    CURSOR.with(|cursor| {
+        let mut cursor = cursor.borrow_mut();
+        let cursor = match cursor.get_or_insert_with(load).as_ref() {
+            Some(cursor) => cursor,
+            None => return
+        };
+        let image = match cursor
+            .theme
+            .get_cursor(name)
+            .or_else(|| cursor.theme.get_cursor(DEFAULT_CURSOR))
+        {
+            Some(image) => image,
+            None => {
+                warn!("The cursor theme has neither {} nor {}", name, DEFAULT_CURSOR);
+                return;
+            }
+        };
+        let (buffer, (width, height, hotspot_x, hotspot_y, _)) =
+            match (image.frame_buffer(0), image.frame_info(0)) {
+                (Some(buffer), Some(info)) => (buffer, info),
+                _ => return
+            };
+        cursor.surface.attach(Some(&buffer), 0, 0);
+        cursor.surface.damage(0, 0, width as i32, height as i32);
+        cursor.surface.commit();
+        event_trace::request(pointer.as_ref(), "set_cursor", || {
+            vec![
+                Arg::Uint(serial.into()),
+                Arg::Object(Some(event_trace::object(cursor.surface.as_ref()))),
+                Arg::Int(i64::from(hotspot_x)),
+                Arg::Int(i64::from(hotspot_y)),
+            ]
+        });
+        pointer.set_cursor(serial, Some(&cursor.surface), hotspot_x as i32, hotspot_y as i32);
+    })
+}
+
+fn load() -> Option<Cursor> {
+    if !cursor::is_available() {
+        warn!("libwayland-cursor isn't available, the pointer keeps the compositor's cursor");
+        return None;
+    }
+    let shm = wl_shm::shm()?;
+    let surface = wl_compositor::create_surface().ok()?;
+    let name = env::var("XCURSOR_THEME").ok();
+    let size = env::var("XCURSOR_SIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(DEFAULT_SIZE);
+    Some(Cursor {
+        theme: cursor::load_theme(name.as_ref().map(String::as_str), size, &shm),
+        surface
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cursor_names() {
+        assert!(is_cursor_name(DEFAULT_CURSOR));
+        assert!(is_cursor_name("hand1"));
+        assert!(is_cursor_name("sb_h_double_arrow"));
+        assert!(!is_cursor_name("pointer"));
+        assert!(!is_cursor_name("Hand1"));
+        assert!(!is_cursor_name(""));
+    }
+}
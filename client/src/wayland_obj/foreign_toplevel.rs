@@ -0,0 +1,156 @@
+//! Wrappers around the zwlr_foreign_toplevel_manager_v1 and the toplevels
+//! it advertises.
+//!
+//! Foreign toplevels are the windows of other Wayland clients. They are what
+//! backs the Awesome client objects.
+
+use std::{cell::RefCell, convert::TryInto, fmt};
+
+use wayland_client::{GlobalImplementor, NewProxy, Proxy};
+use wayland_protocols::wlr::unstable::foreign_toplevel::v1::client::{
+    zwlr_foreign_toplevel_handle_v1::{self, State, ZwlrForeignToplevelHandleV1},
+    zwlr_foreign_toplevel_manager_v1::{self, ZwlrForeignToplevelManagerV1}
+};
+
+use crate::lua::LUA;
+use crate::objects::client;
+use crate::wayland_obj::seat;
+
+/// The minimum version of the zwlr_foreign_toplevel_manager_v1 global to
+/// bind to.
+pub const FOREIGN_TOPLEVEL_MANAGER_VERSION: u32 = 1;
+
+/// Provides the new zwlr_foreign_toplevel_manager_v1 with an implementation.
+pub struct ForeignToplevelManager {}
+
+struct ForeignToplevelManagerHandler {}
+
+/// Wrapper around a zwlr_foreign_toplevel_handle_v1.
+#[derive(Clone, Eq, PartialEq)]
+pub struct ForeignToplevel {
+    proxy: ZwlrForeignToplevelHandleV1
+}
+
+struct ForeignToplevelHandler {}
+
+/// The cached state for the `ForeignToplevel`.
+///
+/// Events update the state directly, it is only passed on to Lua once the
+/// compositor sends the done event.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+struct ForeignToplevelState {
+    title: String,
+    app_id: String,
+    activated: bool
+}
+
+impl GlobalImplementor<ZwlrForeignToplevelManagerV1> for ForeignToplevelManager {
+    fn new_global(
+        &mut self,
+        new_proxy: NewProxy<ZwlrForeignToplevelManagerV1>
+    ) -> ZwlrForeignToplevelManagerV1 {
+        new_proxy.implement(ForeignToplevelManagerHandler {}, ())
+    }
+}
+
+impl zwlr_foreign_toplevel_manager_v1::EventHandler for ForeignToplevelManagerHandler {
+    fn toplevel(
+        &mut self,
+        _object: ZwlrForeignToplevelManagerV1,
+        toplevel: NewProxy<ZwlrForeignToplevelHandleV1>
+    ) {
+        toplevel.implement(
+            ForeignToplevelHandler {},
+            RefCell::new(ForeignToplevelState::default())
+        );
+    }
+
+    fn finished(&mut self, _object: ZwlrForeignToplevelManagerV1) {
+        warn!("Compositor stopped sending us toplevels");
+    }
+}
+
+impl zwlr_foreign_toplevel_handle_v1::EventHandler for ForeignToplevelHandler {
+    fn title(&mut self, object: ZwlrForeignToplevelHandleV1, title: String) {
+        unwrap_state(object.as_ref()).borrow_mut().title = title;
+    }
+
+    fn app_id(&mut self, object: ZwlrForeignToplevelHandleV1, app_id: String) {
+        unwrap_state(object.as_ref()).borrow_mut().app_id = app_id;
+    }
+
+    fn state(&mut self, object: ZwlrForeignToplevelHandleV1, state: Vec<u8>) {
+        // The states are an array of native endian u32s.
+        let activated = state
+            .chunks_exact(4)
+            .filter_map(|chunk| chunk.try_into().ok())
+            .any(|chunk| u32::from_ne_bytes(chunk) == State::Activated as u32);
+        unwrap_state(object.as_ref()).borrow_mut().activated = activated;
+    }
+
+    fn done(&mut self, object: ZwlrForeignToplevelHandleV1) {
+        let toplevel = ForeignToplevel { proxy: object };
+        LUA.with(|lua| {
+            lua.borrow().context(|ctx| {
+                if let Err(err) = client::update_client(ctx, toplevel) {
+                    error!("Could not update client: {}", err);
+                }
+            })
+        });
+    }
+
+    fn closed(&mut self, object: ZwlrForeignToplevelHandleV1) {
+        let toplevel = ForeignToplevel { proxy: object };
+        LUA.with(|lua| {
+            lua.borrow().context(|ctx| {
+                if let Err(err) = client::unmanage_client(ctx, &toplevel) {
+                    error!("Could not unmanage client: {}", err);
+                }
+            })
+        });
+        toplevel.proxy.destroy();
+    }
+}
+
+impl ForeignToplevel {
+    pub fn title(&self) -> String {
+        unwrap_state(self.as_ref()).borrow().title.clone()
+    }
+
+    pub fn app_id(&self) -> String {
+        unwrap_state(self.as_ref()).borrow().app_id.clone()
+    }
+
+    /// Whether the compositor considers this toplevel to be focused.
+    pub fn activated(&self) -> bool {
+        unwrap_state(self.as_ref()).borrow().activated
+    }
+
+    /// Asks the compositor to give the toplevel keyboard focus.
+    ///
+    /// Nothing happens if the compositor didn't advertise a seat.
+    pub fn activate(&self) {
+        match seat::seat() {
+            Some(seat) => self.proxy.activate(&seat),
+            None => warn!("Can't activate {:?}, there is no seat", self)
+        }
+    }
+}
+
+impl fmt::Debug for ForeignToplevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.proxy.as_ref().c_ptr())
+    }
+}
+
+impl AsRef<Proxy<ZwlrForeignToplevelHandleV1>> for ForeignToplevel {
+    fn as_ref(&self) -> &Proxy<ZwlrForeignToplevelHandleV1> {
+        &self.proxy.as_ref()
+    }
+}
+
+fn unwrap_state(proxy: &Proxy<ZwlrForeignToplevelHandleV1>) -> &RefCell<ForeignToplevelState> {
+    proxy
+        .user_data::<RefCell<ForeignToplevelState>>()
+        .expect("User data has not been set yet")
+}
@@ -0,0 +1,268 @@
+//! The few functions of libgbm needed to allocate buffers the CPU can write
+//! into and the compositor can import as dmabufs.
+//!
+//! libgbm is loaded when the first dmabuf buffer is allocated rather than
+//! linked, so the client still starts where it's missing, and buffers are
+//! then allocated in shared memory instead.
+
+use std::{
+    cell::RefCell,
+    ffi::CStr,
+    fs::{self, File, OpenOptions},
+    os::unix::io::{AsRawFd, RawFd},
+    path::PathBuf,
+    rc::Rc,
+    slice
+};
+
+use libc::{c_char, c_int, c_void};
+
+use crate::area::Size;
+
+/// `GBM_FORMAT_ARGB8888`, which is `DRM_FORMAT_ARGB8888`, the same layout as
+/// a Cairo ARGB32 image surface on little endian machines.
+pub const FORMAT_ARGB8888: u32 = 0x3432_5241;
+
+const BO_USE_RENDERING: u32 = 1 << 2;
+const BO_USE_LINEAR: u32 = 1 << 4;
+const BO_TRANSFER_WRITE: u32 = 1 << 1;
+
+/// Overrides which render node buffers are allocated on.
+const RENDER_NODE_VAR: &str = "WAY_COOLER_RENDER_NODE";
+
+thread_local! {
+    /// The device buffers are allocated on, or why there is none. It's only
+    /// opened once, failing to open it won't get better by trying again.
+    static DEVICE: RefCell<Option<Result<Rc<Device>, String>>> = RefCell::new(None);
+}
+
+#[allow(non_camel_case_types)]
+type gbm_device = c_void;
+#[allow(non_camel_case_types)]
+type gbm_bo = c_void;
+
+/// The functions of libgbm, looked up when it's loaded.
+struct Library {
+    handle: *mut c_void,
+    create_device: unsafe extern "C" fn(c_int) -> *mut gbm_device,
+    device_destroy: unsafe extern "C" fn(*mut gbm_device),
+    bo_create: unsafe extern "C" fn(*mut gbm_device, u32, u32, u32, u32) -> *mut gbm_bo,
+    bo_destroy: unsafe extern "C" fn(*mut gbm_bo),
+    bo_get_fd: unsafe extern "C" fn(*mut gbm_bo) -> c_int,
+    bo_get_stride: unsafe extern "C" fn(*mut gbm_bo) -> u32,
+    bo_get_modifier: unsafe extern "C" fn(*mut gbm_bo) -> u64,
+    bo_map:
+        unsafe extern "C" fn(*mut gbm_bo, u32, u32, u32, u32, u32, *mut u32, *mut *mut c_void) -> *mut c_void,
+    bo_unmap: unsafe extern "C" fn(*mut gbm_bo, *mut c_void)
+}
+
+impl Library {
+    fn load() -> Result<Self, String> {
+        unsafe {
+            let handle = libc::dlopen(b"libgbm.so.1\0".as_ptr() as *const c_char, libc::RTLD_NOW);
+            if handle.is_null() {
+                return Err(format!("could not load libgbm: {}", dlerror()));
+            }
+            macro_rules! symbol {
+                ($name:expr) => {{
+                    let symbol = libc::dlsym(handle, concat!($name, "\0").as_ptr() as *const c_char);
+                    if symbol.is_null() {
+                        libc::dlclose(handle);
+                        return Err(format!("libgbm has no {}", $name));
+                    }
+                    std::mem::transmute(symbol)
+                }};
+            }
+            Ok(Library {
+                handle,
+                create_device: symbol!("gbm_create_device"),
+                device_destroy: symbol!("gbm_device_destroy"),
+                bo_create: symbol!("gbm_bo_create"),
+                bo_destroy: symbol!("gbm_bo_destroy"),
+                bo_get_fd: symbol!("gbm_bo_get_fd"),
+                bo_get_stride: symbol!("gbm_bo_get_stride"),
+                bo_get_modifier: symbol!("gbm_bo_get_modifier"),
+                bo_map: symbol!("gbm_bo_map"),
+                bo_unmap: symbol!("gbm_bo_unmap")
+            })
+        }
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        unsafe {
+            libc::dlclose(self.handle);
+        }
+    }
+}
+
+fn dlerror() -> String {
+    unsafe {
+        let error = libc::dlerror();
+        if error.is_null() {
+            "unknown error".into()
+        } else {
+            CStr::from_ptr(error).to_string_lossy().into_owned()
+        }
+    }
+}
+
+/// A GBM device on a render node.
+pub struct Device {
+    library: Library,
+    device: *mut gbm_device,
+    /// Kept open for as long as the device is used.
+    _node: File
+}
+
+impl Device {
+    /// The device buffers are allocated on, opening it the first time.
+    pub fn get() -> Result<Rc<Device>, String> {
+        DEVICE.with(|device| {
+            device
+                .borrow_mut()
+                .get_or_insert_with(|| {
+                    let device = Device::open();
+                    if let Err(ref err) = device {
+                        warn!("Drawables can't use dmabuf buffers, {}", err);
+                    }
+                    device
+                })
+                .clone()
+        })
+    }
+
+    fn open() -> Result<Rc<Device>, String> {
+        let path = render_node().ok_or_else(|| "there is no render node".to_string())?;
+        let node = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .map_err(|err| format!("could not open {}: {}", path.display(), err))?;
+        let library = Library::load()?;
+        let device = unsafe { (library.create_device)(node.as_raw_fd()) };
+        if device.is_null() {
+            return Err(format!("could not create a GBM device on {}", path.display()));
+        }
+        info!("Allocating dmabuf buffers on {}", path.display());
+        Ok(Rc::new(Device {
+            library,
+            device,
+            _node: node
+        }))
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        unsafe { (self.library.device_destroy)(self.device) }
+    }
+}
+
+/// The render node to allocate on: the one the environment names, or else
+/// the first one.
+///
+/// The compositor could say which node it imports from best, but only in
+/// version 4 of zwp_linux_dmabuf_v1, which the client can't bind yet.
+fn render_node() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os(RENDER_NODE_VAR) {
+        return Some(path.into());
+    }
+    let mut nodes: Vec<PathBuf> = fs::read_dir("/dev/dri")
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .map_or(false, |name| name.starts_with("renderD"))
+        })
+        .collect();
+    nodes.sort();
+    nodes.into_iter().next()
+}
+
+/// A linear ARGB8888 buffer object.
+pub struct Bo {
+    device: Rc<Device>,
+    bo: *mut gbm_bo,
+    size: Size
+}
+
+impl Bo {
+    pub fn create(device: Rc<Device>, size: Size) -> Result<Self, String> {
+        let bo = unsafe {
+            (device.library.bo_create)(
+                device.device,
+                size.width,
+                size.height,
+                FORMAT_ARGB8888,
+                BO_USE_LINEAR | BO_USE_RENDERING
+            )
+        };
+        if bo.is_null() {
+            return Err(format!(
+                "could not allocate a {}x{} linear buffer object",
+                size.width, size.height
+            ));
+        }
+        Ok(Bo { device, bo, size })
+    }
+
+    /// A new file descriptor of the dmabuf, which the caller closes.
+    pub fn fd(&self) -> Result<RawFd, String> {
+        match unsafe { (self.device.library.bo_get_fd)(self.bo) } {
+            fd if fd < 0 => Err("could not export the buffer object".into()),
+            fd => Ok(fd)
+        }
+    }
+
+    pub fn stride(&self) -> u32 {
+        unsafe { (self.device.library.bo_get_stride)(self.bo) }
+    }
+
+    pub fn modifier(&self) -> u64 {
+        unsafe { (self.device.library.bo_get_modifier)(self.bo) }
+    }
+
+    /// Maps the buffer for writing, giving `write` its memory and the
+    /// length of its rows in bytes.
+    pub fn map_write<R, F>(&mut self, write: F) -> Result<R, String>
+    where
+        F: FnOnce(&mut [u8], usize) -> R
+    {
+        let Size { width, height } = self.size;
+        let mut stride = 0;
+        let mut map_data = std::ptr::null_mut();
+        let library = &self.device.library;
+        let map = unsafe {
+            (library.bo_map)(
+                self.bo,
+                0,
+                0,
+                width,
+                height,
+                BO_TRANSFER_WRITE,
+                &mut stride,
+                &mut map_data
+            )
+        };
+        if map.is_null() {
+            return Err("could not map the buffer object".into());
+        }
+        let len = stride as usize * height as usize;
+        let result = write(
+            unsafe { slice::from_raw_parts_mut(map as *mut u8, len) },
+            stride as usize
+        );
+        unsafe { (library.bo_unmap)(self.bo, map_data) };
+        Ok(result)
+    }
+}
+
+impl Drop for Bo {
+    fn drop(&mut self) {
+        unsafe { (self.device.library.bo_destroy)(self.bo) }
+    }
+}
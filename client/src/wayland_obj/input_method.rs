@@ -0,0 +1,56 @@
+//! Wrapper around a zwp_input_method_v1.
+//!
+//! The compositor tells the input method when a text field is focused and
+//! unfocused, which is when an on-screen keyboard is needed.
+
+use std::{cell::RefCell, rc::Rc};
+
+use wayland_client::{GlobalImplementor, NewProxy};
+use wayland_protocols::unstable::input_method::v1::client::{
+    zwp_input_method_context_v1::ZwpInputMethodContextV1,
+    zwp_input_method_v1::{self, ZwpInputMethodV1}
+};
+
+/// The minimum version of the zwp_input_method_v1 global to bind to.
+pub const INPUT_METHOD_VERSION: u32 = 1;
+
+thread_local! {
+    /// Called with whether a text field is active.
+    static TEXT_INPUT_HANDLER: RefCell<Option<Rc<dyn Fn(bool)>>> = RefCell::new(None);
+}
+
+pub struct InputMethodManager {}
+
+struct InputMethodHandler {}
+
+impl GlobalImplementor<ZwpInputMethodV1> for InputMethodManager {
+    fn new_global(&mut self, new_proxy: NewProxy<ZwpInputMethodV1>) -> ZwpInputMethodV1 {
+        new_proxy.implement(InputMethodHandler {}, ())
+    }
+}
+
+impl zwp_input_method_v1::EventHandler for InputMethodHandler {
+    fn activate(&mut self, _object: ZwpInputMethodV1, id: NewProxy<ZwpInputMethodContextV1>) {
+        // Text is typed with the virtual keyboard rather than committed
+        // through the context, so none of its events are needed.
+        id.implement_dummy();
+        handle_text_input(true);
+    }
+
+    fn deactivate(&mut self, _object: ZwpInputMethodV1, context: ZwpInputMethodContextV1) {
+        context.destroy();
+        handle_text_input(false);
+    }
+}
+
+/// Sets the function that is called with whether a text field is active
+/// whenever that changes.
+pub fn on_text_input(handler: Rc<dyn Fn(bool)>) {
+    TEXT_INPUT_HANDLER.with(|text_input_handler| *text_input_handler.borrow_mut() = Some(handler));
+}
+
+fn handle_text_input(active: bool) {
+    if let Some(handler) = TEXT_INPUT_HANDLER.with(|handler| handler.borrow().clone()) {
+        handler(active)
+    }
+}
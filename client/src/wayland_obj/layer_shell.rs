@@ -0,0 +1,1566 @@
+//! Wrappers around a zwlr_layer_surface_v1 and the layer shell setup code.
+//!
+//! Layer surfaces are what drawins are displayed with. Unlike xdg surfaces
+//! they can be stacked above and below clients and positioned relative to
+//! the edges of an output.
+//!
+//! A compositor without the layer shell gets xdg toplevels instead, see
+//! `Role`. They're configured, sized and committed the same way, but where
+//! they are is up to the compositor, and nothing that places or stacks
+//! them is sent.
+
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+    rc::Rc
+};
+
+use wayland_client::{
+    protocol::{
+        wl_buffer::WlBuffer,
+        wl_callback::{self, WlCallback},
+        wl_output::WlOutput,
+        wl_surface::WlSurface
+    },
+    GlobalImplementor, NewProxy, Proxy
+};
+
+pub use self::generated::client::zwlr_layer_shell_v1::Layer;
+pub use self::generated::client::zwlr_layer_surface_v1::{Anchor, KeyboardInteractivity};
+use self::generated::client::{
+    zwlr_layer_shell_v1::{self, ZwlrLayerShellV1},
+    zwlr_layer_surface_v1::{self, ZwlrLayerSurfaceV1}
+};
+use crate::area::{AnchorEdge, AnchorSet, Area, Margin, Origin, Size};
+use crate::event_trace::{self, Arg};
+use crate::wayland_obj::{
+    self,
+    xdg_shell::{self, XdgToplevel},
+    Output
+};
+
+/// The minimum version of the zwlr_layer_shell_v1 global to bind to.
+pub const LAYER_SHELL_VERSION: u32 = 1;
+
+/// The newest version of the zwlr_layer_shell_v1 global the client knows.
+pub const LAYER_SHELL_MAX_VERSION: u32 = 4;
+
+/// The namespace given to every layer surface we create.
+const LAYER_NAMESPACE: &str = "way-cooler";
+
+/// The first version of zwlr_layer_surface_v1 that can change the layer of
+/// the surface.
+const SET_LAYER_VERSION: u32 = 2;
+
+/// The first version of zwlr_layer_surface_v1 with on-demand keyboard
+/// interactivity.
+const ON_DEMAND_VERSION: u32 = 4;
+
+/// The first version of wl_surface that only attaches buffers at (0, 0).
+const OFFSET_VERSION: u32 = 5;
+
+/// The code generated from protocols/wlr-layer-shell-unstable-v1.xml, of
+/// which wayland-protocols only has the first version.
+mod generated {
+    #![allow(dead_code, non_camel_case_types, unused_unsafe, unused_variables)]
+    #![allow(non_upper_case_globals, non_snake_case, unused_imports)]
+    #![allow(clippy::all)]
+
+    pub mod client {
+        pub(crate) use wayland_client::protocol::{wl_output, wl_surface};
+        pub(crate) use wayland_client::sys;
+        pub(crate) use wayland_client::{AnonymousObject, HandledBy, NewProxy, Proxy, ProxyMap};
+        pub(crate) use wayland_commons::map::{Object, ObjectMetadata};
+        pub(crate) use wayland_commons::wire::{Argument, ArgumentType, Message, MessageDesc};
+        pub(crate) use wayland_commons::{Interface, MessageGroup};
+        pub(crate) use wayland_protocols::xdg_shell::client::xdg_popup;
+        include!(concat!(
+            env!("OUT_DIR"),
+            "/wlr-layer-shell-unstable-v1_client_api.rs"
+        ));
+    }
+}
+
+thread_local! {
+    /// The layer surface creator.
+    ///
+    /// This should remain local to just this module.
+    static LAYER_SHELL: RefCell<Option<ZwlrLayerShellV1>> = RefCell::new(None);
+    /// The version the layer shell was bound with, which the layer surfaces
+    /// it makes have too.
+    static VERSION: Cell<u32> = Cell::new(0);
+}
+
+/// Provides the new zwlr_layer_shell_v1 with an implementation, and records
+/// the version it was bound with.
+pub struct LayerShellManager {}
+
+/// A wrapper around `ZwlrLayerSurfaceV1` that keeps track of the changes to
+/// the internal state of the layer surface and its `wl_surface`.
+///
+/// This should be used instead of using a layer surface directly as
+/// otherwise you need to roll your own caching scheme.
+pub struct LayerSurface {
+    role: Role,
+    state: Rc<RefCell<LayerSurfaceState>>
+}
+
+/// What the surface is shown as.
+enum Role {
+    Layer(ZwlrLayerSurfaceV1),
+    /// An xdg toplevel, when the compositor doesn't have the layer shell.
+    /// The anchor, margin, exclusive zone, keyboard interactivity and layer
+    /// are kept, but not sent to it.
+    Toplevel(XdgToplevel)
+}
+
+/// The cached state for the `LayerSurface`. Cached state about the
+/// `wl_surface` is also stored in here.
+///
+/// This needs to be stored as the user data in the `LayerSurface` so that it
+/// can be accessed anywhere. It's shared with the layer surface that
+/// replaces it when the layer changes, see `LayerSurface::set_layer`.
+struct LayerSurfaceState {
+    wl_surface: WlSurface,
+    /// The output the surface was put on, or `None` if the compositor
+    /// chose it.
+    output: Option<WlOutput>,
+    layer: Layer,
+    size: Size,
+    /// The size the compositor last configured the surface with.
+    granted_size: Size,
+    /// The edges the surface is anchored to, as the compositor knows them.
+    anchor: Anchor,
+    /// The edges to anchor the surface to with the next commit, if they
+    /// changed.
+    pending_anchor: Option<Anchor>,
+    /// The distance of the surface from the edges it's anchored to, as the
+    /// compositor knows it.
+    margin: Margin,
+    /// The margin to send with the next commit, if it changed.
+    pending_margin: Option<Margin>,
+    /// The height kept clear of other surfaces, if the surface is anchored
+    /// to an edge, as the compositor knows it.
+    exclusive_zone: i32,
+    /// The exclusive zone to send with the next commit, if it changed.
+    pending_exclusive_zone: Option<i32>,
+    /// Set once the first configure has been acked.
+    ///
+    /// Attaching a buffer before that is a protocol error.
+    configured: bool,
+    /// Buffer to attach once the surface has been configured, with its
+    /// size, or once it's the size the surface was configured with.
+    pending_buffer: Option<(WlBuffer, Size)>,
+    /// The buffer set last and its size, attached again to a layer surface
+    /// that replaces this one.
+    buffer: Option<(WlBuffer, Size)>,
+    /// How the surface takes keyboard events, as the compositor knows it.
+    keyboard_interactivity: KeyboardInteractivity,
+    /// The keyboard interactivity to send with the next commit, if it
+    /// changed.
+    pending_keyboard_interactivity: Option<KeyboardInteractivity>,
+    /// How many pixels of the buffer make a unit of the surface.
+    buffer_scale: i32,
+    /// Called with the granted size when it changes.
+    on_configure: Option<Rc<dyn Fn(Size)>>,
+    /// Set from committing a buffer until the compositor says it's a good
+    /// time to draw the next one.
+    frame_pending: bool,
+    /// Called when the frame that was pending is done.
+    on_frame: Option<Rc<dyn Fn()>>,
+    /// Set once the compositor closed the layer surface, after which
+    /// nothing is shown on it.
+    closed: bool,
+    /// Called when the compositor closed the layer surface.
+    on_closed: Option<Rc<dyn Fn()>>
+}
+
+struct LayerSurfaceEventHandler {}
+
+/// Clears the pending frame of the layer surface when the compositor is
+/// done with it.
+struct FrameEventHandler {
+    state: Rc<RefCell<LayerSurfaceState>>,
+    /// The id of the callback, which `done` destroys before it's handled.
+    id: Rc<Cell<u32>>
+}
+
+impl GlobalImplementor<ZwlrLayerShellV1> for LayerShellManager {
+    fn new_global(&mut self, new_proxy: NewProxy<ZwlrLayerShellV1>) -> ZwlrLayerShellV1 {
+        let res = new_proxy.implement(LayerShellEventHandler {}, ());
+
+        LAYER_SHELL.with(|layer_shell| {
+            *layer_shell.borrow_mut() = Some(res.clone());
+        });
+        VERSION.with(|version| version.set(res.as_ref().version()));
+
+        res
+    }
+}
+
+struct LayerShellEventHandler {}
+
+impl zwlr_layer_shell_v1::EventHandler for LayerShellEventHandler {}
+
+impl zwlr_layer_surface_v1::EventHandler for LayerSurfaceEventHandler {
+    fn configure(&mut self, object: ZwlrLayerSurfaceV1, serial: u32, width: u32, height: u32) {
+        event_trace::event(object.as_ref(), "configure", || {
+            vec![
+                Arg::Uint(serial.into()),
+                Arg::Uint(width.into()),
+                Arg::Uint(height.into()),
+            ]
+        });
+        event_trace::request(object.as_ref(), "ack_configure", || {
+            vec![Arg::Uint(serial.into())]
+        });
+        object.ack_configure(serial);
+        configured(&shared_state(object.as_ref()), Some(&object), width, height);
+    }
+
+    fn closed(&mut self, object: ZwlrLayerSurfaceV1) {
+        event_trace::event(object.as_ref(), "closed", Vec::new);
+        warn!("Layer surface was closed by the compositor");
+        let state = shared_state(object.as_ref());
+        state.borrow_mut().closed = true;
+        // The wl_surface is left to the owner, who might have objects of
+        // its own to destroy before it.
+        event_trace::request(object.as_ref(), "destroy", Vec::new);
+        object.destroy();
+        closed(&state);
+    }
+}
+
+/// Applies the configure of the layer surface `proxy`, or of the xdg
+/// toplevel standing in for it if there's none, once it's acked.
+fn configured(
+    state: &RefCell<LayerSurfaceState>,
+    proxy: Option<&ZwlrLayerSurfaceV1>,
+    width: u32,
+    height: u32
+) {
+    let callback = {
+        let mut state = state.borrow_mut();
+        state.configured = true;
+        // A zero dimension means we get to choose it.
+        let granted_size = Size {
+            width: if width == 0 { state.size.width } else { width },
+            height: if height == 0 { state.size.height } else { height }
+        };
+        let size_changed = state.granted_size != granted_size;
+        state.granted_size = granted_size;
+        // A buffer of another size waits for the owner to allocate one
+        // that fits, which it's told to below.
+        match state.pending_buffer.take() {
+            Some((buffer, size)) if fits(&state, size) => attach_buffer(&state, &buffer, None),
+            pending => state.pending_buffer = pending
+        }
+        send_pending(proxy, &mut state);
+        commit_surface(&state.wl_surface);
+        if size_changed {
+            state
+                .on_configure
+                .clone()
+                .map(|callback| (callback, granted_size))
+        } else {
+            None
+        }
+    };
+    // The callback commits the surface again, so the state can't be
+    // borrowed while it runs.
+    if let Some((callback, granted_size)) = callback {
+        callback(granted_size);
+    }
+}
+
+/// Forgets the buffer of a surface the compositor closed, once its role is
+/// destroyed, and tells the owner.
+fn closed(state: &RefCell<LayerSurfaceState>) {
+    let callback = {
+        let mut state = state.borrow_mut();
+        state.pending_buffer = None;
+        state.on_closed.clone()
+    };
+    // The owner drops the layer surface, so the state can't be borrowed
+    // while it runs.
+    if let Some(callback) = callback {
+        callback();
+    }
+}
+
+impl wl_callback::EventHandler for FrameEventHandler {
+    fn done(&mut self, _: WlCallback, time: u32) {
+        event_trace::destructor_event::<WlCallback, _>(self.id.get(), "done", || {
+            vec![Arg::Uint(time.into())]
+        });
+        let callback = {
+            let mut state = self.state.borrow_mut();
+            // The surface could have been destroyed since the frame was
+            // asked for.
+            if !state.wl_surface.as_ref().is_alive() {
+                return;
+            }
+            state.frame_pending = false;
+            state.on_frame.clone()
+        };
+        // The callback commits the surface again, like on configure.
+        if let Some(callback) = callback {
+            callback();
+        }
+    }
+}
+
+impl LayerSurface {
+    /// Sets the size of the surface.
+    ///
+    /// Like all layer surface state this is double buffered, so it will not
+    /// be applied until the next commit.
+    pub fn set_size(&self, size: Size) {
+        let Size { width, height } = size;
+        {
+            let mut state = self.state.borrow_mut();
+            if is_closed(&state, "resize") {
+                return;
+            }
+            state.size = size;
+        }
+        // A toplevel is the size of the buffer attached to it.
+        if let Role::Layer(proxy) = &self.role {
+            event_trace::request(proxy.as_ref(), "set_size", || {
+                vec![Arg::Uint(width.into()), Arg::Uint(height.into())]
+            });
+            proxy.set_size(width, height);
+        }
+    }
+
+    /// Positions the surface relative to the top left corner of its output.
+    ///
+    /// Layer surfaces can't be placed at an absolute position, so the surface
+    /// is anchored to the top and left edges and the position is used as the
+    /// margin from those edges.
+    pub fn set_position(&self, origin: Origin) {
+        let Origin { x, y } = origin;
+        let margin = Margin {
+            top: y,
+            left: x,
+            ..Margin::default()
+        };
+        self.set_anchor(Anchor::Top | Anchor::Left);
+        self.set_margin(margin);
+        let mut state = self.state.borrow_mut();
+        // A surface anchored to a corner can't keep anything clear.
+        queue_exclusive_zone(&mut state, 0);
+    }
+
+    /// Anchors the surface to `edges` of its output, `margin` away from
+    /// them. It's stretched between opposite edges if its size is 0 along
+    /// that axis, and centered between them otherwise.
+    pub fn set_anchors(&self, edges: AnchorSet, margin: Margin) {
+        self.set_anchor(anchor_of(edges));
+        self.set_margin(margin);
+        let mut state = self.state.borrow_mut();
+        queue_exclusive_zone(&mut state, 0);
+    }
+
+    /// Stretches the surface over all of its output, including the space
+    /// other surfaces keep clear. Its size should be 0 so the compositor
+    /// configures it with the size of the output.
+    pub fn set_fullscreen(&self) {
+        let mut state = self.state.borrow_mut();
+        queue_anchor(&mut state, Anchor::all());
+        queue_margin(&mut state, Margin::default());
+        queue_exclusive_zone(&mut state, -1);
+    }
+
+    /// Places the surface along the `edge` of its output, between the
+    /// margins of the edges next to it, keeping `exclusive_zone` past the
+    /// margin of the edge clear of windows and other surfaces.
+    pub fn set_edge_placement(&self, edge: AnchorEdge, margin: Margin, exclusive_zone: i32) {
+        let mut state = self.state.borrow_mut();
+        let anchor = match edge {
+            AnchorEdge::Top => Anchor::Top | Anchor::Left | Anchor::Right,
+            AnchorEdge::Bottom => Anchor::Bottom | Anchor::Left | Anchor::Right,
+            AnchorEdge::Left => Anchor::Left | Anchor::Top | Anchor::Bottom,
+            AnchorEdge::Right => Anchor::Right | Anchor::Top | Anchor::Bottom
+        };
+        queue_anchor(&mut state, anchor);
+        queue_margin(&mut state, margin);
+        queue_exclusive_zone(&mut state, exclusive_zone);
+    }
+
+    /// Anchors the surface to the edges of its output in `anchor`, or
+    /// none of them to center it.
+    ///
+    /// Like the rest of the layer surface state it's double buffered. It's
+    /// sent right before the next commit, and only if it changed.
+    pub fn set_anchor(&self, anchor: Anchor) {
+        queue_anchor(&mut self.state.borrow_mut(), anchor);
+    }
+
+    /// Keeps the surface `margin` away from the edges it's anchored to.
+    /// The margin of an edge it isn't anchored to is ignored.
+    ///
+    /// Margins can be negative, which moves the surface past the edge, e.g.
+    /// to slide a bar out of view. Like the anchor it's sent right before
+    /// the next commit, and only if it changed.
+    pub fn set_margin(&self, margin: Margin) {
+        queue_margin(&mut self.state.borrow_mut(), margin);
+    }
+
+    /// Keeps `exclusive_zone` past the margin of the edge the surface is
+    /// anchored to clear of windows and other surfaces. 0 keeps nothing
+    /// clear but moves the surface out of what others keep clear, and -1
+    /// places it over that too. Anything below -1 is taken as -1.
+    ///
+    /// The compositor only keeps the zone clear for a surface anchored to
+    /// one edge, or to one edge and both next to it.
+    ///
+    /// It's sent right before the next commit, like the anchor. A surface
+    /// that's configured already is committed again to apply it, since
+    /// nothing else might commit it for a while, e.g. when a drawin only
+    /// changes its struts.
+    pub fn set_exclusive_zone(&self, exclusive_zone: i32) {
+        let mut state = self.state.borrow_mut();
+        queue_exclusive_zone(&mut state, exclusive_zone);
+        if let Some(proxy) = self.role.layer() {
+            if state.configured && state.pending_exclusive_zone.is_some() {
+                send_pending(Some(proxy), &mut state);
+                commit_surface(&state.wl_surface);
+            }
+        }
+    }
+
+    /// Set the buffer that is displayed by the surface.
+    ///
+    /// The buffer is always attached at the origin of the surface, the
+    /// position of the surface is controlled with `set_position`.
+    ///
+    /// If the surface has not been configured yet the buffer is attached once
+    /// it is. The contents will not be sent until a wl_surface commit, due to
+    /// Wayland surfaces being double buffered.
+    ///
+    /// `size` is the size of the buffer in pixels, which has to be the
+    /// configured size at the buffer scale. A buffer of another size isn't
+    /// attached, some compositors take that as a protocol error, until the
+    /// surface is configured with its size. The owner is told about the
+    /// size it should be with `on_configure`, and sets another buffer.
+    ///
+    /// `damage` is the parts of the buffer that changed, or `None` if all
+    /// of it might have.
+    pub fn set_buffer(&self, buffer: &WlBuffer, size: Size, damage: Option<&[Area]>) {
+        let mut state = self.state.borrow_mut();
+        if is_closed(&state, "attach a buffer to") {
+            return;
+        }
+        state.buffer = Some((buffer.clone(), size));
+        if state.configured && fits(&state, size) {
+            state.pending_buffer = None;
+            attach_buffer(&state, buffer, damage);
+        } else {
+            if state.configured {
+                debug!(
+                    "Not attaching a {}x{} buffer to a layer surface configured to be {}x{}",
+                    size.width, size.height, state.granted_size.width, state.granted_size.height
+                );
+            }
+            state.pending_buffer = Some((buffer.clone(), size));
+        }
+    }
+
+    /// The size the compositor configured the surface with, or `None` before
+    /// it's configured.
+    pub fn configured_size(&self) -> Option<Size> {
+        let state = self.state.borrow();
+        if state.configured {
+            Some(state.granted_size)
+        } else {
+            None
+        }
+    }
+
+    /// Sets how many pixels of the buffers attached next make a unit of the
+    /// surface, which the compositor scales them down by. Like the buffer,
+    /// it's applied when the surface is committed.
+    pub fn set_buffer_scale(&self, scale: i32) {
+        let mut state = self.state.borrow_mut();
+        if state.buffer_scale != scale {
+            state.buffer_scale = scale;
+            event_trace::request(state.wl_surface.as_ref(), "set_buffer_scale", || {
+                vec![Arg::Int(scale.into())]
+            });
+            state.wl_surface.set_buffer_scale(scale);
+        }
+    }
+
+    /// The largest scale of the outputs the surface is on, or `None` while
+    /// it isn't on any.
+    pub fn output_scale(&self) -> Option<i32> {
+        let wl_surface = self.state.borrow().wl_surface.clone();
+        wayland_obj::surface_outputs(&wl_surface)
+            .into_iter()
+            .map(|output| Output::from(output).scale())
+            .max()
+    }
+
+    /// Sets the function called when the surface entered or left an
+    /// output.
+    pub fn on_outputs_changed(&self, callback: Rc<dyn Fn()>) {
+        let wl_surface = self.state.borrow().wl_surface.clone();
+        wayland_obj::on_surface_outputs_changed(&wl_surface, callback);
+    }
+
+    /// Sets how the surface takes keyboard events from the seat. Before
+    /// version 4 of the layer shell on-demand focus is exclusive instead.
+    ///
+    /// Like the anchor it's sent right before the next commit, and only if
+    /// it changed, so it can be set before the surface is configured.
+    pub fn set_keyboard_interactivity(&self, interactivity: KeyboardInteractivity) {
+        queue_keyboard_interactivity(&mut self.state.borrow_mut(), interactivity);
+    }
+
+    /// Sets the parts of the surface that take pointer input, or `None` for
+    /// all of it.
+    pub fn set_input_region(&self, rects: Option<&[Area]>) {
+        let state = self.state.borrow();
+        match rects {
+            Some(rects) => match wayland_obj::create_region(rects) {
+                Ok(region) => {
+                    event_trace::request(state.wl_surface.as_ref(), "set_input_region", || {
+                        vec![Arg::Object(Some(event_trace::object(region.as_ref())))]
+                    });
+                    state.wl_surface.set_input_region(Some(&region));
+                    region.destroy();
+                },
+                Err(_) => warn!("Could not create the input region of a layer surface")
+            },
+            None => {
+                event_trace::request(state.wl_surface.as_ref(), "set_input_region", || {
+                    vec![Arg::Object(None)]
+                });
+                state.wl_surface.set_input_region(None)
+            }
+        }
+    }
+
+    /// Sets the parts of the surface the compositor doesn't have to draw
+    /// what is below behind. None of it if `rects` is empty.
+    pub fn set_opaque_region(&self, rects: &[Area]) {
+        let state = self.state.borrow();
+        if rects.is_empty() {
+            event_trace::request(state.wl_surface.as_ref(), "set_opaque_region", || {
+                vec![Arg::Object(None)]
+            });
+            state.wl_surface.set_opaque_region(None);
+            return;
+        }
+        match wayland_obj::create_region(rects) {
+            Ok(region) => {
+                event_trace::request(state.wl_surface.as_ref(), "set_opaque_region", || {
+                    vec![Arg::Object(Some(event_trace::object(region.as_ref())))]
+                });
+                state.wl_surface.set_opaque_region(Some(&region));
+                region.destroy();
+            },
+            Err(_) => warn!("Could not create the opaque region of a layer surface")
+        }
+    }
+
+    /// Sets the function called with the size the compositor grants the
+    /// surface whenever that size changes.
+    pub fn on_configure(&self, callback: Rc<dyn Fn(Size)>) {
+        self.state.borrow_mut().on_configure = Some(callback);
+    }
+
+    /// Sets the function called when the compositor closed the surface,
+    /// which should be dropped then.
+    pub fn on_closed(&self, callback: Rc<dyn Fn()>) {
+        self.state.borrow_mut().on_closed = Some(callback);
+    }
+
+    /// Sets the function called when the compositor is done with the frame
+    /// that was pending, see `request_frame`.
+    pub fn on_frame(&self, callback: Rc<dyn Fn()>) {
+        self.state.borrow_mut().on_frame = Some(callback);
+    }
+
+    /// Asks the compositor to say when it's a good time to draw the next
+    /// frame, with the next commit. Until then a frame is pending.
+    ///
+    /// Does nothing while a frame is already pending, before the surface is
+    /// configured, when the buffer isn't attached yet, or once the
+    /// compositor closed it.
+    pub fn request_frame(&self) {
+        let mut state = self.state.borrow_mut();
+        if state.frame_pending || !state.configured || state.closed {
+            return;
+        }
+        let id = Rc::new(Cell::new(0));
+        let handler = FrameEventHandler {
+            state: self.state.clone(),
+            id: id.clone()
+        };
+        match state
+            .wl_surface
+            .frame(|new_proxy| new_proxy.implement(handler, ()))
+        {
+            Ok(callback) => {
+                id.set(callback.as_ref().id());
+                event_trace::request(state.wl_surface.as_ref(), "frame", || {
+                    vec![Arg::NewId(event_trace::object(callback.as_ref()))]
+                });
+                state.frame_pending = true;
+            },
+            Err(_) => warn!("Could not ask for a frame callback of a layer surface")
+        }
+    }
+
+    /// Whether a frame was committed that the compositor isn't done with.
+    pub fn frame_pending(&self) -> bool {
+        self.state.borrow().frame_pending
+    }
+
+    /// Whether this is the layer surface of `wl_surface`.
+    pub fn has_surface(&self, wl_surface: &WlSurface) -> bool {
+        self.state.borrow().wl_surface == *wl_surface
+    }
+
+    /// The surface the layer surface displays.
+    pub fn wl_surface(&self) -> WlSurface {
+        self.state.borrow().wl_surface.clone()
+    }
+
+    /// Moves the surface to `layer`, on top of the surfaces already there.
+    ///
+    /// From version 2 of the layer shell the layer is changed with a commit,
+    /// which this makes. Before that the layer surface is destroyed and
+    /// another is made for the same `wl_surface` on the same output. The new
+    /// one is committed with the state the old one had, and the buffer set
+    /// last is attached again once it's configured. Errors if the new one
+    /// couldn't be made, which leaves the surface without a layer surface.
+    pub fn set_layer(&mut self, layer: Layer) -> Result<(), ()> {
+        let state = self.state.clone();
+        let (wl_surface, output) = {
+            let mut state = state.borrow_mut();
+            if is_closed(&state, "move") {
+                return Err(());
+            }
+            if state.layer == layer {
+                return Ok(());
+            }
+            let proxy = match &self.role {
+                Role::Layer(proxy) => proxy,
+                // A toplevel isn't in a layer.
+                Role::Toplevel(_) => {
+                    state.layer = layer;
+                    return Ok(());
+                }
+            };
+            if version() >= SET_LAYER_VERSION {
+                event_trace::request(proxy.as_ref(), "set_layer", || {
+                    vec![Arg::Uint(layer.to_raw().into())]
+                });
+                proxy.set_layer(layer);
+                state.layer = layer;
+                send_pending(Some(proxy), &mut state);
+                commit_surface(&state.wl_surface);
+                return Ok(());
+            }
+            event_trace::request(proxy.as_ref(), "destroy", Vec::new);
+            proxy.destroy();
+            (state.wl_surface.clone(), state.output.clone())
+        };
+        // A surface that has a buffer can't have a layer surface made for
+        // it.
+        attach(&wl_surface, None, 0, 0);
+        commit_surface(&wl_surface);
+        let proxy = get_layer_surface(&wl_surface, output.as_ref(), layer, state.clone())?;
+        let mut state = state.borrow_mut();
+        state.layer = layer;
+        replay(&proxy, &mut state);
+        send_pending(Some(&proxy), &mut state);
+        self.role = Role::Layer(proxy);
+        commit_surface(&wl_surface);
+        Ok(())
+    }
+
+    /// Commits the surface, with the state set since the last commit.
+    pub fn commit(&self) {
+        let mut state = self.state.borrow_mut();
+        if is_closed(&state, "commit") {
+            return;
+        }
+        send_pending(self.role.layer(), &mut state);
+        commit_surface(&state.wl_surface);
+    }
+
+    /// The id of the zwlr_layer_surface_v1, or of the xdg_toplevel standing
+    /// in for it.
+    #[cfg(test)]
+    pub fn id(&self) -> u32 {
+        match &self.role {
+            Role::Layer(proxy) => proxy.as_ref().id(),
+            Role::Toplevel(toplevel) => toplevel.proxy().id()
+        }
+    }
+}
+
+impl Role {
+    /// The layer surface, if it is one.
+    fn layer(&self) -> Option<&ZwlrLayerSurfaceV1> {
+        match self {
+            Role::Layer(proxy) => Some(proxy),
+            Role::Toplevel(_) => None
+        }
+    }
+}
+
+impl PartialEq for LayerSurface {
+    fn eq(&self, other: &LayerSurface) -> bool {
+        Rc::ptr_eq(&self.state, &other.state)
+    }
+}
+
+impl Eq for LayerSurface {}
+
+impl Drop for LayerSurface {
+    fn drop(&mut self) {
+        let (wl_surface, closed) = {
+            let state = self.state.borrow();
+            (state.wl_surface.clone(), state.closed)
+        };
+        match &self.role {
+            // It was destroyed when the compositor closed it.
+            Role::Layer(_) if closed => {},
+            Role::Layer(proxy) => {
+                event_trace::request(proxy.as_ref(), "destroy", Vec::new);
+                proxy.destroy();
+            },
+            // The compositor only asks for a toplevel to be closed.
+            Role::Toplevel(toplevel) => toplevel.destroy()
+        }
+        event_trace::request(wl_surface.as_ref(), "destroy", Vec::new);
+        wl_surface.destroy();
+    }
+}
+
+/// Creates a new layer surface on the given output and layer, anchored to
+/// the edges in `anchor`.
+///
+/// If no output is given the compositor chooses one. Without a layer shell
+/// it's an xdg toplevel, and errors if there's no xdg shell either.
+///
+/// The surface is not committed, callers should set the size and position
+/// of the surface before the initial commit.
+pub fn create_layer_surface(
+    output: Option<&WlOutput>,
+    layer: Layer,
+    anchor: Anchor
+) -> Result<LayerSurface, ()> {
+    let wl_surface = wayland_obj::create_surface()?;
+    let mut state = LayerSurfaceState {
+        wl_surface: wl_surface.clone(),
+        output: output.cloned(),
+        layer,
+        size: Size::default(),
+        granted_size: Size::default(),
+        anchor: Anchor::empty(),
+        pending_anchor: None,
+        margin: Margin::default(),
+        pending_margin: None,
+        exclusive_zone: 0,
+        pending_exclusive_zone: None,
+        configured: false,
+        pending_buffer: None,
+        buffer: None,
+        keyboard_interactivity: KeyboardInteractivity::None,
+        pending_keyboard_interactivity: None,
+        buffer_scale: 1,
+        on_configure: None,
+        frame_pending: false,
+        on_frame: None,
+        closed: false,
+        on_closed: None
+    };
+    queue_anchor(&mut state, anchor);
+    let state = Rc::new(RefCell::new(state));
+    let role = if has_layer_shell() {
+        Role::Layer(get_layer_surface(&wl_surface, output, layer, state.clone())?)
+    } else {
+        Role::Toplevel(create_toplevel(&wl_surface, &state)?)
+    };
+    Ok(LayerSurface { role, state })
+}
+
+/// Whether the compositor has the layer shell. Layer surfaces are xdg
+/// toplevels if it doesn't.
+pub fn has_layer_shell() -> bool {
+    LAYER_SHELL.with(|layer_shell| layer_shell.borrow().is_some())
+}
+
+/// Makes `wl_surface` an xdg toplevel that stands in for a layer surface
+/// with `state`.
+fn create_toplevel(
+    wl_surface: &WlSurface,
+    state: &Rc<RefCell<LayerSurfaceState>>
+) -> Result<XdgToplevel, ()> {
+    let on_configure = {
+        let state = Rc::downgrade(state);
+        Rc::new(move |size: Size| {
+            if let Some(state) = state.upgrade() {
+                configured(&state, None, size.width, size.height);
+            }
+        })
+    };
+    let on_close = {
+        let state = Rc::downgrade(state);
+        Rc::new(move || {
+            if let Some(state) = state.upgrade() {
+                warn!("Toplevel of a drawin was closed by the compositor");
+                state.borrow_mut().closed = true;
+                closed(&state);
+            }
+        })
+    };
+    xdg_shell::create_xdg_toplevel(wl_surface, on_configure, on_close)
+}
+
+/// Makes a layer surface for `wl_surface` with `state`.
+fn get_layer_surface(
+    wl_surface: &WlSurface,
+    output: Option<&WlOutput>,
+    layer: Layer,
+    state: Rc<RefCell<LayerSurfaceState>>
+) -> Result<ZwlrLayerSurfaceV1, ()> {
+    LAYER_SHELL.with(|layer_shell| {
+        let layer_shell = layer_shell.borrow();
+        let layer_shell = layer_shell.as_ref().expect("Layer shell was not initialized");
+        layer_shell
+            .get_layer_surface(wl_surface, output, layer, LAYER_NAMESPACE.into(), |new_proxy| {
+                new_proxy.implement(LayerSurfaceEventHandler {}, state)
+            })
+            .map(|proxy| {
+                event_trace::request(layer_shell.as_ref(), "get_layer_surface", || {
+                    vec![
+                        Arg::NewId(event_trace::object(proxy.as_ref())),
+                        Arg::Object(Some(event_trace::object(wl_surface.as_ref()))),
+                        Arg::Object(output.map(|output| event_trace::object(output.as_ref()))),
+                        Arg::Uint(layer.to_raw().into()),
+                        Arg::Str(LAYER_NAMESPACE.into()),
+                    ]
+                });
+                proxy
+            })
+    })
+}
+
+/// Sends the state a layer surface had to the new one `proxy` replaces it
+/// with, and has it wait for its configure like the first.
+///
+/// The anchor, margin, exclusive zone and keyboard interactivity are queued
+/// for the next commit, the size doesn't wait for it.
+fn replay(proxy: &ZwlrLayerSurfaceV1, state: &mut LayerSurfaceState) {
+    let anchor = state.pending_anchor.take().unwrap_or(state.anchor);
+    let margin = state.pending_margin.take().unwrap_or(state.margin);
+    let exclusive_zone = state
+        .pending_exclusive_zone
+        .take()
+        .unwrap_or(state.exclusive_zone);
+    let interactivity = state
+        .pending_keyboard_interactivity
+        .take()
+        .unwrap_or(state.keyboard_interactivity);
+    // A new layer surface starts from the defaults of the protocol.
+    state.anchor = Anchor::empty();
+    state.margin = Margin::default();
+    state.exclusive_zone = 0;
+    state.keyboard_interactivity = KeyboardInteractivity::None;
+    queue_anchor(state, anchor);
+    queue_margin(state, margin);
+    queue_exclusive_zone(state, exclusive_zone);
+    queue_keyboard_interactivity(state, interactivity);
+    let Size { width, height } = state.size;
+    event_trace::request(proxy.as_ref(), "set_size", || {
+        vec![Arg::Uint(width.into()), Arg::Uint(height.into())]
+    });
+    proxy.set_size(width, height);
+    state.configured = false;
+    // The owner is told the size it's configured with again, it might not
+    // have seen the last one before the old one went.
+    state.granted_size = Size::default();
+    // The buffer could have been destroyed since, if it was replaced by
+    // one that's still pending.
+    let buffer = state
+        .buffer
+        .clone()
+        .filter(|(buffer, _)| buffer.as_ref().is_alive());
+    state.pending_buffer = state.pending_buffer.take().or(buffer);
+    // The frame callback of the old one may never be done.
+    state.frame_pending = false;
+}
+
+/// The anchor of a surface anchored to `edges`.
+pub fn anchor_of(edges: AnchorSet) -> Anchor {
+    edges.iter().fold(Anchor::empty(), |anchor, edge| {
+        anchor |
+            match edge {
+                AnchorEdge::Top => Anchor::Top,
+                AnchorEdge::Bottom => Anchor::Bottom,
+                AnchorEdge::Left => Anchor::Left,
+                AnchorEdge::Right => Anchor::Right
+            }
+    })
+}
+
+/// Sets the anchor to send with the next commit, none if the compositor
+/// has it already.
+fn queue_anchor(state: &mut LayerSurfaceState, anchor: Anchor) {
+    state.pending_anchor = if anchor == state.anchor {
+        None
+    } else {
+        Some(anchor)
+    };
+}
+
+/// Sets the margin to send with the next commit, none if the compositor
+/// has it already.
+fn queue_margin(state: &mut LayerSurfaceState, margin: Margin) {
+    state.pending_margin = if margin == state.margin {
+        None
+    } else {
+        Some(margin)
+    };
+}
+
+/// Sets the exclusive zone to send with the next commit, none if the
+/// compositor has it already.
+fn queue_exclusive_zone(state: &mut LayerSurfaceState, exclusive_zone: i32) {
+    let exclusive_zone = exclusive_zone.max(-1);
+    state.pending_exclusive_zone = if exclusive_zone == state.exclusive_zone {
+        None
+    } else {
+        Some(exclusive_zone)
+    };
+}
+
+/// Sets the keyboard interactivity to send with the next commit, none if
+/// the compositor has it already.
+fn queue_keyboard_interactivity(state: &mut LayerSurfaceState, interactivity: KeyboardInteractivity) {
+    state.pending_keyboard_interactivity = if interactivity == state.keyboard_interactivity {
+        None
+    } else {
+        Some(interactivity)
+    };
+}
+
+/// Sends the layer surface state that changed since the last commit.
+///
+/// Without a layer surface it's only taken as what the compositor has, an
+/// xdg toplevel has nothing to send it to.
+fn send_pending(proxy: Option<&ZwlrLayerSurfaceV1>, state: &mut LayerSurfaceState) {
+    let proxy = match proxy {
+        Some(proxy) => proxy,
+        None => {
+            state.anchor = state.pending_anchor.take().unwrap_or(state.anchor);
+            state.margin = state.pending_margin.take().unwrap_or(state.margin);
+            state.exclusive_zone = state
+                .pending_exclusive_zone
+                .take()
+                .unwrap_or(state.exclusive_zone);
+            state.keyboard_interactivity = state
+                .pending_keyboard_interactivity
+                .take()
+                .unwrap_or(state.keyboard_interactivity);
+            return;
+        }
+    };
+    if let Some(anchor) = state.pending_anchor.take() {
+        event_trace::request(proxy.as_ref(), "set_anchor", || {
+            vec![Arg::Uint(anchor.bits().into())]
+        });
+        proxy.set_anchor(anchor);
+        state.anchor = anchor;
+    }
+    if let Some(margin) = state.pending_margin.take() {
+        event_trace::request(proxy.as_ref(), "set_margin", || {
+            [margin.top, margin.right, margin.bottom, margin.left]
+                .iter()
+                .map(|side| Arg::Int((*side).into()))
+                .collect()
+        });
+        proxy.set_margin(margin.top, margin.right, margin.bottom, margin.left);
+        state.margin = margin;
+    }
+    if let Some(exclusive_zone) = state.pending_exclusive_zone.take() {
+        event_trace::request(proxy.as_ref(), "set_exclusive_zone", || {
+            vec![Arg::Int(exclusive_zone.into())]
+        });
+        proxy.set_exclusive_zone(exclusive_zone);
+        state.exclusive_zone = exclusive_zone;
+    }
+    if let Some(interactivity) = state.pending_keyboard_interactivity.take() {
+        let sent = keyboard_interactivity_on(interactivity, version());
+        event_trace::request(proxy.as_ref(), "set_keyboard_interactivity", || {
+            vec![Arg::Uint(sent.to_raw().into())]
+        });
+        proxy.set_keyboard_interactivity(sent);
+        state.keyboard_interactivity = interactivity;
+    }
+}
+
+/// What `interactivity` is sent as to a layer surface of `version`.
+fn keyboard_interactivity_on(interactivity: KeyboardInteractivity, version: u32) -> KeyboardInteractivity {
+    match interactivity {
+        KeyboardInteractivity::OnDemand if version < ON_DEMAND_VERSION => KeyboardInteractivity::Exclusive,
+        interactivity => interactivity
+    }
+}
+
+/// The version the layer shell was bound with.
+fn version() -> u32 {
+    VERSION.with(Cell::get)
+}
+
+/// Forgets the layer shell, when the connection of a test is gone.
+#[cfg(test)]
+pub(super) fn unbind() {
+    LAYER_SHELL.with(|layer_shell| *layer_shell.borrow_mut() = None);
+    VERSION.with(|version| version.set(0));
+}
+
+/// Whether a buffer of `size` is the size the surface was configured with,
+/// at the buffer scale.
+fn fits(state: &LayerSurfaceState, size: Size) -> bool {
+    let scale = state.buffer_scale.max(1) as u32;
+    let Size { width, height } = state.granted_size;
+    size.width == width * scale && size.height == height * scale
+}
+
+/// Attaches the buffer to the surface and damages the changed parts, or
+/// all of it.
+///
+/// Only configured surfaces have buffers attached, so the granted size is
+/// the size of the surface. The damage is in the buffer, and is scaled down
+/// to the surface, which is all wl_surface version 3 can take.
+///
+/// Buffers are always attached at (0, 0): the attach offset moves the buffer
+/// relative to the surface rather than positioning the surface, and it must
+/// be zero on newer versions of wl_surface.
+fn attach_buffer(state: &LayerSurfaceState, buffer: &WlBuffer, damage: Option<&[Area]>) {
+    let Size { width, height } = state.granted_size;
+    let wl_surface = &state.wl_surface;
+    attach(wl_surface, Some(buffer), 0, 0);
+    let damage_area = |x: i32, y: i32, width: i32, height: i32| {
+        event_trace::request(wl_surface.as_ref(), "damage", || {
+            [x, y, width, height]
+                .iter()
+                .map(|n| Arg::Int((*n).into()))
+                .collect()
+        });
+        wl_surface.damage(x, y, width, height);
+    };
+    match damage {
+        Some(damage) => {
+            for rect in damage {
+                let Area { origin, size } = rect.scale(1.0 / f64::from(state.buffer_scale));
+                damage_area(origin.x, origin.y, size.width as i32, size.height as i32);
+            }
+        },
+        None => damage_area(0, 0, width as i32, height as i32)
+    }
+}
+
+/// Whether the compositor closed the layer surface, warning that it can't
+/// `action` it if it did.
+fn is_closed(state: &LayerSurfaceState, action: &str) -> bool {
+    if state.closed {
+        warn!("Can't {} a layer surface the compositor closed", action);
+    }
+    state.closed
+}
+
+/// Attaches `buffer`, or none, moved `x` and `y` relative to the surface.
+/// From version 5 of wl_surface on that's a protocol error unless both are
+/// 0, the surface is moved with wl_surface.offset instead.
+fn attach(wl_surface: &WlSurface, buffer: Option<&WlBuffer>, x: i32, y: i32) {
+    debug_assert!(
+        wl_surface.as_ref().version() < OFFSET_VERSION || (x, y) == (0, 0),
+        "attach offset ({}, {}) on wl_surface version {}",
+        x,
+        y,
+        wl_surface.as_ref().version()
+    );
+    event_trace::request(wl_surface.as_ref(), "attach", || {
+        let buffer = buffer.map(|buffer| event_trace::object(buffer.as_ref()));
+        vec![Arg::Object(buffer), Arg::Int(x.into()), Arg::Int(y.into())]
+    });
+    wl_surface.attach(buffer, x, y);
+}
+
+fn commit_surface(wl_surface: &WlSurface) {
+    event_trace::request(wl_surface.as_ref(), "commit", Vec::new);
+    wl_surface.commit();
+}
+
+impl fmt::Debug for LayerSurface {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.role {
+            Role::Layer(proxy) => write!(f, "{:?}", proxy.as_ref().c_ptr()),
+            Role::Toplevel(toplevel) => write!(f, "{:?}", toplevel.proxy().c_ptr())
+        }
+    }
+}
+
+fn shared_state(proxy: &Proxy<ZwlrLayerSurfaceV1>) -> Rc<RefCell<LayerSurfaceState>> {
+    proxy
+        .user_data::<Rc<RefCell<LayerSurfaceState>>>()
+        .expect("User data has not been set yet")
+        .clone()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::wayland_obj::{
+        create_buffer,
+        test_server::{Request, TestServer},
+        Buffer
+    };
+    use wayland_client::protocol::wl_shm::Format;
+
+    /// A layer surface of `size` the server configured, with the requests
+    /// up to that taken.
+    fn configured_surface(server: &mut TestServer, size: Size) -> LayerSurface {
+        let layer_surface = create_layer_surface(None, Layer::Top, Anchor::Top).unwrap();
+        layer_surface.set_size(size);
+        layer_surface.commit();
+        server.roundtrip();
+        server.configure(layer_surface.id(), 1, size);
+        server.roundtrip();
+        server.take_requests();
+        layer_surface
+    }
+
+    /// The requests the server got, without their arguments.
+    fn sent(server: &TestServer) -> Vec<String> {
+        server
+            .take_requests()
+            .iter()
+            .map(|request| format!("{}.{}", request.interface, request.name))
+            .collect()
+    }
+
+    #[test]
+    fn closed_by_compositor() {
+        let mut server = TestServer::start(LAYER_SHELL_MAX_VERSION);
+        let size = Size { width: 10, height: 5 };
+        let buffer = create_buffer(size, Format::Argb8888).unwrap();
+        let layer_surface = configured_surface(&mut server, size);
+        let closes = Rc::new(Cell::new(0));
+        {
+            let closes = closes.clone();
+            layer_surface.on_closed(Rc::new(move || closes.set(closes.get() + 1)));
+        }
+        server.close(layer_surface.id());
+        server.roundtrip();
+        assert_eq!(closes.get(), 1);
+        assert_eq!(sent(&server), ["zwlr_layer_surface_v1.destroy"]);
+        // Nothing is sent for it anymore.
+        layer_surface.set_size(Size { width: 20, height: 5 });
+        layer_surface.set_buffer(buffer.wl_buffer(), size, None);
+        layer_surface.request_frame();
+        layer_surface.commit();
+        server.roundtrip();
+        assert_eq!(sent(&server), Vec::<String>::new());
+        assert_eq!(closes.get(), 1);
+        // Only the wl_surface is left to destroy.
+        drop(layer_surface);
+        server.roundtrip();
+        assert_eq!(sent(&server), ["wl_surface.destroy"]);
+    }
+
+    /// The requests the server got, with `buffer` written as "buffer" where
+    /// it's attached.
+    fn sent_with(server: &TestServer, buffer: &Buffer) -> Vec<String> {
+        let id = i64::from(buffer.wl_buffer().as_ref().id());
+        server
+            .take_requests()
+            .into_iter()
+            .map(|request| match (request.name, request.args.as_slice()) {
+                ("attach", &[buffer, x, y]) if buffer == id => {
+                    format!("wl_surface.attach(buffer, {}, {})", x, y)
+                },
+                _ => request.to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn buffer_of_another_size_waits() {
+        let mut server = TestServer::start(LAYER_SHELL_MAX_VERSION);
+        let small = Size { width: 10, height: 5 };
+        let large = Size { width: 20, height: 5 };
+        let buffer = create_buffer(large, Format::Argb8888).unwrap();
+        server.roundtrip();
+        server.take_requests();
+        let layer_surface = configured_surface(&mut server, small);
+        layer_surface.set_buffer(buffer.wl_buffer(), large, None);
+        layer_surface.commit();
+        server.roundtrip();
+        assert_eq!(sent_with(&server, &buffer), ["wl_surface.commit()"]);
+        // It's attached once the surface is configured with its size.
+        server.configure(layer_surface.id(), 2, large);
+        server.roundtrip();
+        assert_eq!(
+            sent_with(&server, &buffer),
+            [
+                "zwlr_layer_surface_v1.ack_configure(2)",
+                "wl_surface.attach(buffer, 0, 0)",
+                "wl_surface.damage(0, 0, 20, 5)",
+                "wl_surface.commit()"
+            ]
+        );
+        assert_eq!(layer_surface.configured_size(), Some(large));
+    }
+
+    #[test]
+    fn buffer_that_fits_replaces_the_waiting_one() {
+        let mut server = TestServer::start(LAYER_SHELL_MAX_VERSION);
+        let small = Size { width: 10, height: 5 };
+        let large = Size { width: 20, height: 5 };
+        let waiting = create_buffer(large, Format::Argb8888).unwrap();
+        let fitting = create_buffer(small, Format::Argb8888).unwrap();
+        server.roundtrip();
+        server.take_requests();
+        let layer_surface = configured_surface(&mut server, small);
+        layer_surface.set_buffer(waiting.wl_buffer(), large, None);
+        layer_surface.set_buffer(fitting.wl_buffer(), small, None);
+        layer_surface.commit();
+        server.roundtrip();
+        assert_eq!(
+            sent_with(&server, &fitting),
+            [
+                "wl_surface.attach(buffer, 0, 0)",
+                "wl_surface.damage(0, 0, 10, 5)",
+                "wl_surface.commit()"
+            ]
+        );
+        // The one that waited isn't attached anymore.
+        server.configure(layer_surface.id(), 2, large);
+        server.roundtrip();
+        assert_eq!(
+            sent(&server),
+            ["zwlr_layer_surface_v1.ack_configure", "wl_surface.commit"]
+        );
+    }
+
+    #[test]
+    fn configure_without_a_dimension() {
+        let mut server = TestServer::start(LAYER_SHELL_MAX_VERSION);
+        let size = Size { width: 10, height: 5 };
+        let buffer = create_buffer(size, Format::Argb8888).unwrap();
+        let layer_surface = create_layer_surface(None, Layer::Top, Anchor::Top).unwrap();
+        layer_surface.set_size(size);
+        layer_surface.set_buffer(buffer.wl_buffer(), size, None);
+        layer_surface.commit();
+        server.roundtrip();
+        server.take_requests();
+        // The width is left to the client, which asked for 10.
+        server.configure(layer_surface.id(), 1, Size { width: 0, height: 5 });
+        server.roundtrip();
+        assert_eq!(layer_surface.configured_size(), Some(size));
+        assert_eq!(
+            sent_with(&server, &buffer),
+            [
+                "zwlr_layer_surface_v1.ack_configure(1)",
+                "wl_surface.attach(buffer, 0, 0)",
+                "wl_surface.damage(0, 0, 10, 5)",
+                "wl_surface.commit()"
+            ]
+        );
+    }
+
+    #[test]
+    fn exclusive_zone_of_configured_surface() {
+        let mut server = TestServer::start(LAYER_SHELL_MAX_VERSION);
+        let layer_surface = configured_surface(&mut server, Size { width: 10, height: 5 });
+        layer_surface.set_exclusive_zone(30);
+        server.roundtrip();
+        let requests = server.take_requests();
+        let requests: Vec<_> = requests.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            requests,
+            [
+                "zwlr_layer_surface_v1.set_exclusive_zone(30)",
+                "wl_surface.commit()"
+            ]
+        );
+        // Nothing is sent for the zone it keeps already, and below -1 is -1.
+        layer_surface.set_exclusive_zone(30);
+        layer_surface.set_exclusive_zone(-5);
+        server.roundtrip();
+        let requests = server.take_requests();
+        let requests: Vec<_> = requests.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            requests,
+            [
+                "zwlr_layer_surface_v1.set_exclusive_zone(-1)",
+                "wl_surface.commit()"
+            ]
+        );
+    }
+
+    #[test]
+    fn exclusive_zone_before_configure() {
+        let mut server = TestServer::start(LAYER_SHELL_MAX_VERSION);
+        let layer_surface = create_layer_surface(None, Layer::Top, Anchor::Top).unwrap();
+        server.roundtrip();
+        server.take_requests();
+        // It waits for the commit that configures the surface, with the
+        // rest of the placement.
+        layer_surface.set_exclusive_zone(24);
+        server.roundtrip();
+        assert!(server.take_requests().is_empty());
+        layer_surface.commit();
+        server.roundtrip();
+        assert_eq!(
+            sent(&server),
+            [
+                "zwlr_layer_surface_v1.set_anchor",
+                "zwlr_layer_surface_v1.set_exclusive_zone",
+                "wl_surface.commit"
+            ]
+        );
+    }
+
+    #[test]
+    fn set_layer_request() {
+        let mut server = TestServer::start(LAYER_SHELL_MAX_VERSION);
+        let mut layer_surface = configured_surface(&mut server, Size { width: 10, height: 5 });
+        let id = layer_surface.id();
+        layer_surface.set_layer(Layer::Overlay).unwrap();
+        server.roundtrip();
+        let requests = server.take_requests();
+        let requests: Vec<_> = requests.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            requests,
+            ["zwlr_layer_surface_v1.set_layer(3)", "wl_surface.commit()"]
+        );
+        // The layer surface is kept.
+        assert_eq!(layer_surface.id(), id);
+        assert!(layer_surface.configured_size().is_some());
+        layer_surface.set_layer(Layer::Overlay).unwrap();
+        server.roundtrip();
+        assert!(server.take_requests().is_empty());
+    }
+
+    #[test]
+    fn set_layer_recreates_before_version_2() {
+        let mut server = TestServer::start(1);
+        let mut layer_surface = configured_surface(&mut server, Size { width: 10, height: 5 });
+        let id = layer_surface.id();
+        layer_surface.set_layer(Layer::Overlay).unwrap();
+        server.roundtrip();
+        assert_eq!(
+            sent(&server),
+            [
+                "zwlr_layer_surface_v1.destroy",
+                "wl_surface.attach",
+                "wl_surface.commit",
+                "zwlr_layer_shell_v1.get_layer_surface",
+                "zwlr_layer_surface_v1.set_size",
+                "zwlr_layer_surface_v1.set_anchor",
+                "wl_surface.commit"
+            ]
+        );
+        assert_ne!(layer_surface.id(), id);
+        // The new one waits for its own configure.
+        assert_eq!(layer_surface.configured_size(), None);
+        layer_surface.set_layer(Layer::Overlay).unwrap();
+        server.roundtrip();
+        assert!(server.take_requests().is_empty());
+    }
+
+    #[test]
+    fn set_layer_keeps_layer_when_recreation_fails() {
+        let mut server = TestServer::start(LAYER_SHELL_MAX_VERSION);
+        let mut layer_surface = configured_surface(&mut server, Size { width: 10, height: 5 });
+        // A layer shell that can't set the layer, and can't make layer
+        // surfaces either.
+        VERSION.with(|version| version.set(1));
+        LAYER_SHELL.with(|layer_shell| layer_shell.borrow().as_ref().unwrap().destroy());
+        assert_eq!(layer_surface.set_layer(Layer::Overlay), Err(()));
+        assert_eq!(layer_surface.state.borrow().layer, Layer::Top);
+    }
+
+    /// The ids of the xdg_surface and xdg_toplevel made among `requests`.
+    fn toplevel_ids(requests: &[Request]) -> (u32, u32) {
+        let made = |name| {
+            requests
+                .iter()
+                .find(|request| request.name == name)
+                .map(|request| request.args[0] as u32)
+                .unwrap()
+        };
+        (made("get_xdg_surface"), made("get_toplevel"))
+    }
+
+    /// An xdg toplevel standing in for a layer surface of `size`, which the
+    /// server configured, with the ids of its xdg_surface and xdg_toplevel.
+    fn configured_toplevel(server: &mut TestServer, size: Size) -> (LayerSurface, u32, u32) {
+        let layer_surface = create_layer_surface(None, Layer::Top, Anchor::Top).unwrap();
+        layer_surface.set_size(size);
+        layer_surface.commit();
+        server.roundtrip();
+        let (xdg_surface, toplevel) = toplevel_ids(&server.take_requests());
+        assert_eq!(layer_surface.id(), toplevel);
+        server.configure_toplevel(xdg_surface, toplevel, 1, Size::default());
+        server.roundtrip();
+        server.take_requests();
+        (layer_surface, xdg_surface, toplevel)
+    }
+
+    #[test]
+    fn toplevel_without_layer_shell() {
+        let mut server = TestServer::without_layer_shell();
+        let size = Size { width: 10, height: 5 };
+        let buffer = create_buffer(size, Format::Argb8888).unwrap();
+        server.roundtrip();
+        server.take_requests();
+        let mut layer_surface = create_layer_surface(None, Layer::Top, Anchor::Top).unwrap();
+        // What places the surface isn't sent, the compositor places it.
+        layer_surface.set_size(size);
+        layer_surface.set_position(Origin { x: 5, y: 5 });
+        layer_surface.set_keyboard_interactivity(KeyboardInteractivity::Exclusive);
+        layer_surface.set_buffer(buffer.wl_buffer(), size, None);
+        layer_surface.commit();
+        server.roundtrip();
+        let requests = server.take_requests();
+        let (xdg_surface, toplevel) = toplevel_ids(&requests);
+        let names: Vec<_> = requests
+            .iter()
+            .map(|request| format!("{}.{}", request.interface, request.name))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "wl_compositor.create_surface",
+                "xdg_wm_base.get_xdg_surface",
+                "xdg_surface.get_toplevel",
+                "xdg_toplevel.set_app_id",
+                "wl_surface.commit"
+            ]
+        );
+        // A configure that leaves the size to the client gets the buffer.
+        server.configure_toplevel(xdg_surface, toplevel, 1, Size::default());
+        server.roundtrip();
+        assert_eq!(
+            sent_with(&server, &buffer),
+            [
+                "xdg_surface.ack_configure(1)",
+                "wl_surface.attach(buffer, 0, 0)",
+                "wl_surface.damage(0, 0, 10, 5)",
+                "wl_surface.commit()"
+            ]
+        );
+        assert_eq!(layer_surface.configured_size(), Some(size));
+        layer_surface.set_exclusive_zone(24);
+        layer_surface.set_layer(Layer::Overlay).unwrap();
+        server.roundtrip();
+        assert!(server.take_requests().is_empty());
+        drop(layer_surface);
+        server.roundtrip();
+        assert_eq!(
+            sent(&server),
+            [
+                "xdg_toplevel.destroy",
+                "xdg_surface.destroy",
+                "wl_surface.destroy"
+            ]
+        );
+    }
+
+    #[test]
+    fn toplevel_closed_by_compositor() {
+        let mut server = TestServer::without_layer_shell();
+        let size = Size { width: 10, height: 5 };
+        let (layer_surface, _, toplevel) = configured_toplevel(&mut server, size);
+        let closes = Rc::new(Cell::new(0));
+        {
+            let closes = closes.clone();
+            layer_surface.on_closed(Rc::new(move || closes.set(closes.get() + 1)));
+        }
+        server.close_toplevel(toplevel);
+        server.roundtrip();
+        assert_eq!(closes.get(), 1);
+        // The compositor only asked, the toplevel is destroyed with it.
+        assert!(server.take_requests().is_empty());
+        layer_surface.commit();
+        drop(layer_surface);
+        server.roundtrip();
+        assert_eq!(
+            sent(&server),
+            [
+                "xdg_toplevel.destroy",
+                "xdg_surface.destroy",
+                "wl_surface.destroy"
+            ]
+        );
+    }
+
+    #[test]
+    fn toplevel_resized_by_compositor() {
+        let mut server = TestServer::without_layer_shell();
+        let (layer_surface, xdg_surface, toplevel) =
+            configured_toplevel(&mut server, Size { width: 10, height: 5 });
+        let sizes = Rc::new(RefCell::new(Vec::new()));
+        {
+            let sizes = sizes.clone();
+            layer_surface.on_configure(Rc::new(move |size| sizes.borrow_mut().push(size)));
+        }
+        let tiled = Size {
+            width: 300,
+            height: 200
+        };
+        server.configure_toplevel(xdg_surface, toplevel, 2, tiled);
+        server.roundtrip();
+        assert_eq!(*sizes.borrow(), [tiled]);
+        assert_eq!(layer_surface.configured_size(), Some(tiled));
+    }
+
+    #[test]
+    fn keyboard_interactivity_on_demand() {
+        let sent = |interactivity, version| keyboard_interactivity_on(interactivity, version).to_raw();
+        assert_eq!(sent(KeyboardInteractivity::None, 1), 0);
+        assert_eq!(sent(KeyboardInteractivity::Exclusive, 4), 1);
+        assert_eq!(sent(KeyboardInteractivity::OnDemand, 4), 2);
+        // Older versions only know exclusive focus.
+        assert_eq!(sent(KeyboardInteractivity::OnDemand, 3), 1);
+    }
+
+    #[test]
+    fn keyboard_interactivity_by_bound_version() {
+        for &(version, sent) in &[(LAYER_SHELL_MAX_VERSION, 2), (1, 1)] {
+            let mut server = TestServer::start(version);
+            let layer_surface = create_layer_surface(None, Layer::Top, Anchor::Top).unwrap();
+            assert_eq!(layer_surface.role.layer().unwrap().as_ref().version(), version);
+            server.roundtrip();
+            server.take_requests();
+            // It's queued until the first commit.
+            layer_surface.set_keyboard_interactivity(KeyboardInteractivity::OnDemand);
+            server.roundtrip();
+            assert!(server.take_requests().is_empty());
+            layer_surface.commit();
+            server.roundtrip();
+            let requests = server.take_requests();
+            let requests: Vec<_> = requests.iter().map(ToString::to_string).collect();
+            assert_eq!(
+                requests,
+                [
+                    "zwlr_layer_surface_v1.set_anchor(1)".to_string(),
+                    format!("zwlr_layer_surface_v1.set_keyboard_interactivity({})", sent),
+                    "wl_surface.commit()".to_string()
+                ]
+            );
+        }
+    }
+}
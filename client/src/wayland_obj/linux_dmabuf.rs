@@ -0,0 +1,352 @@
+//! Wrapper around the zwp_linux_dmabuf_v1 global, and buffers the
+//! compositor imports as dmabufs instead of reading shared memory.
+//!
+//! The compositor can use such a buffer as a texture or scan it out without
+//! copying it first, which matters for large drawins that are repainted
+//! often. The client still writes the pixels with the CPU, through a
+//! mapping of a linear buffer object, see `gbm`.
+
+use std::{cell::Cell, cell::RefCell, fmt, rc::Rc};
+
+use wayland_client::{protocol::wl_buffer::WlBuffer, GlobalImplementor, NewProxy};
+use wayland_protocols::unstable::linux_dmabuf::v1::client::{
+    zwp_linux_buffer_params_v1::{self, ZwpLinuxBufferParamsV1},
+    zwp_linux_dmabuf_v1
+};
+
+pub use wayland_protocols::unstable::linux_dmabuf::v1::client::zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1;
+
+use crate::area::{Area, Origin, Size};
+use crate::wayland_obj::gbm::{Bo, Device, FORMAT_ARGB8888};
+
+/// The minimum version of the zwp_linux_dmabuf_v1 global to bind to, the
+/// first with `create_immed`.
+pub const LINUX_DMABUF_VERSION: u32 = 2;
+
+/// The newest version the client knows. Version 4 stops advertising
+/// formats with events, in favour of feedback the client can't read yet.
+pub const LINUX_DMABUF_MAX_VERSION: u32 = 3;
+
+/// The layout where rows follow each other, which the CPU can write.
+pub const MOD_LINEAR: u64 = 0;
+
+/// No explicit modifier: the layout is whatever the driver picks by
+/// default, which a linear buffer object also has.
+pub const MOD_INVALID: u64 = 0x00ff_ffff_ffff_ffff;
+
+thread_local! {
+    static LINUX_DMABUF: RefCell<Option<ZwpLinuxDmabufV1>> = RefCell::new(None);
+    /// The formats and modifiers the compositor can import.
+    static FORMATS: RefCell<Vec<(u32, u64)>> = RefCell::new(Vec::new());
+}
+
+pub struct LinuxDmabufManager {}
+
+impl GlobalImplementor<ZwpLinuxDmabufV1> for LinuxDmabufManager {
+    fn new_global(&mut self, new_proxy: NewProxy<ZwpLinuxDmabufV1>) -> ZwpLinuxDmabufV1 {
+        let res = new_proxy.implement(LinuxDmabufEventHandler {}, ());
+
+        LINUX_DMABUF.with(|linux_dmabuf| {
+            *linux_dmabuf.borrow_mut() = Some(res.clone());
+        });
+
+        res
+    }
+}
+
+struct LinuxDmabufEventHandler {}
+
+impl zwp_linux_dmabuf_v1::EventHandler for LinuxDmabufEventHandler {
+    fn format(&mut self, _: ZwpLinuxDmabufV1, format: u32) {
+        FORMATS.with(|formats| formats.borrow_mut().push((format, MOD_INVALID)));
+    }
+
+    fn modifier(&mut self, _: ZwpLinuxDmabufV1, format: u32, modifier_hi: u32, modifier_lo: u32) {
+        let modifier = u64::from(modifier_hi) << 32 | u64::from(modifier_lo);
+        FORMATS.with(|formats| formats.borrow_mut().push((format, modifier)));
+    }
+}
+
+// Handle incoming events for the parameters of a buffer, which only say
+// whether it was imported.
+struct ParamsEventHandler {
+    failed: Rc<Cell<bool>>
+}
+
+impl zwp_linux_buffer_params_v1::EventHandler for ParamsEventHandler {
+    fn failed(&mut self, _: ZwpLinuxBufferParamsV1) {
+        self.failed.set(true);
+    }
+}
+
+/// The modifier to import ARGB8888 buffers with, out of the formats and
+/// modifiers the compositor advertised, if it can import any the CPU can
+/// write.
+///
+/// Linear is preferred. Failing that an implicit modifier is used, since
+/// the buffer object is allocated linear either way.
+pub fn negotiate(advertised: &[(u32, u64)]) -> Option<u64> {
+    let modifiers = advertised
+        .iter()
+        .filter(|&&(format, _)| format == FORMAT_ARGB8888)
+        .map(|&(_, modifier)| modifier);
+    let mut implicit = false;
+    for modifier in modifiers {
+        match modifier {
+            MOD_LINEAR => return Some(MOD_LINEAR),
+            MOD_INVALID => implicit = true,
+            _ => {}
+        }
+    }
+    if implicit {
+        Some(MOD_INVALID)
+    } else {
+        None
+    }
+}
+
+/// A wl_buffer backed by a dmabuf, in the ARGB8888 format like `Buffer`.
+pub struct DmabufBuffer {
+    bo: Bo,
+    /// Kept to be told if the compositor couldn't import the buffer.
+    params: ZwpLinuxBufferParamsV1,
+    buffer: WlBuffer,
+    failed: Rc<Cell<bool>>,
+    size: Size
+}
+
+// Like every object Lua has, the drawable owning it stays on the main
+// thread.
+unsafe impl Send for DmabufBuffer {}
+
+impl DmabufBuffer {
+    /// The wl_buffer to attach to a surface.
+    pub fn wl_buffer(&self) -> &WlBuffer {
+        &self.buffer
+    }
+
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    /// Whether the compositor couldn't import the buffer, which makes it
+    /// useless.
+    pub fn failed(&self) -> bool {
+        self.failed.get()
+    }
+
+    /// Copies `data` into the buffer, as `Buffer::write` does.
+    pub fn write(&mut self, data: &[u8], stride: usize, offset: Origin) -> Result<(), String> {
+        let size = self.size;
+        self.bo
+            .map_write(|dest, dest_stride| copy_all(dest, dest_stride, size, data, stride, offset))
+    }
+
+    /// Copies `rects` of `data` into the buffer, as `Buffer::write_rects`
+    /// does.
+    pub fn write_rects(
+        &mut self,
+        data: &[u8],
+        stride: usize,
+        offset: Origin,
+        rects: &[Area]
+    ) -> Result<(), String> {
+        let size = self.size;
+        self.bo
+            .map_write(|dest, dest_stride| copy_rects(dest, dest_stride, size, data, stride, offset, rects))
+    }
+}
+
+impl Drop for DmabufBuffer {
+    fn drop(&mut self) {
+        self.buffer.destroy();
+        self.params.destroy();
+    }
+}
+
+impl fmt::Debug for DmabufBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "DmabufBuffer {{ size: {:?}, failed: {} }}",
+            self.size,
+            self.failed.get()
+        )
+    }
+}
+
+/// Creates a dmabuf buffer in the given size, or says why it can't.
+///
+/// The compositor is asked to import it right away, and tells whether it
+/// could later, see `DmabufBuffer::failed`.
+pub fn create_dmabuf_buffer(size: Size) -> Result<DmabufBuffer, String> {
+    let linux_dmabuf = LINUX_DMABUF
+        .with(|linux_dmabuf| linux_dmabuf.borrow().clone())
+        .ok_or_else(|| {
+            format!(
+                "the compositor has no zwp_linux_dmabuf_v1 of version {}",
+                LINUX_DMABUF_VERSION
+            )
+        })?;
+    let modifier = FORMATS
+        .with(|formats| negotiate(&formats.borrow()))
+        .ok_or_else(|| "the compositor can't import linear ARGB8888 buffers".to_string())?;
+    let bo = Bo::create(Device::get()?, size)?;
+    if modifier == MOD_LINEAR && bo.modifier() != MOD_LINEAR && bo.modifier() != MOD_INVALID {
+        return Err("the buffer object isn't linear".into());
+    }
+    let failed = Rc::new(Cell::new(false));
+    let handler = ParamsEventHandler {
+        failed: failed.clone()
+    };
+    let fd = bo.fd()?;
+    let params = match linux_dmabuf.create_params(|new_proxy| new_proxy.implement(handler, ())) {
+        Ok(params) => params,
+        Err(()) => {
+            unsafe {
+                libc::close(fd);
+            }
+            return Err("zwp_linux_dmabuf_v1 is gone".into());
+        }
+    };
+    params.add(fd, 0, 0, bo.stride(), (modifier >> 32) as u32, modifier as u32);
+    // The descriptor was duplicated to be sent.
+    unsafe {
+        libc::close(fd);
+    }
+    let buffer = params.create_immed(
+        size.width as i32,
+        size.height as i32,
+        FORMAT_ARGB8888,
+        0,
+        NewProxy::implement_dummy
+    );
+    let buffer = match buffer {
+        Ok(buffer) => buffer,
+        Err(()) => {
+            params.destroy();
+            return Err("zwp_linux_buffer_params_v1 is gone".into());
+        }
+    };
+    Ok(DmabufBuffer {
+        bo,
+        params,
+        buffer,
+        failed,
+        size
+    })
+}
+
+/// Copies `data`, which has rows `stride` bytes long, to `dest`, which has
+/// rows `dest_stride` bytes long and is `size` big, as `Buffer::write` does.
+fn copy_all(dest: &mut [u8], dest_stride: usize, size: Size, data: &[u8], stride: usize, offset: Origin) {
+    let row_len = size.width as usize * 4;
+    for row in dest.chunks_mut(dest_stride).take(size.height as usize) {
+        for byte in row[..row_len].iter_mut() {
+            *byte = 0;
+        }
+    }
+    copy_rects(dest, dest_stride, size, data, stride, offset, &[size.into()]);
+}
+
+/// Copies the parts of `data` at `rects` to `dest`, as
+/// `Buffer::write_rects` does.
+fn copy_rects(
+    dest: &mut [u8],
+    dest_stride: usize,
+    size: Size,
+    data: &[u8],
+    stride: usize,
+    offset: Origin,
+    rects: &[Area]
+) {
+    let bounds: Area = size.into();
+    let src_height = if stride == 0 { 0 } else { data.len() / stride } as i64;
+    let src_width = (stride / 4) as i64;
+    for rect in rects.iter().filter_map(|rect| rect.intersection(bounds)) {
+        for y in rect.origin.y as i64..rect.origin.y as i64 + rect.size.height as i64 {
+            let src_y = y + offset.y as i64;
+            let start = (rect.origin.x as i64 + offset.x as i64).max(0).min(src_width);
+            let end = (rect.origin.x as i64 + offset.x as i64 + rect.size.width as i64)
+                .max(0)
+                .min(src_width);
+            if src_y < 0 || src_y >= src_height || start >= end {
+                continue;
+            }
+            let dest_start = y as usize * dest_stride + (start - offset.x as i64) as usize * 4;
+            let src = src_y as usize * stride;
+            let len = (end - start) as usize * 4;
+            dest[dest_start..dest_start + len]
+                .copy_from_slice(&data[src + start as usize * 4..src + end as usize * 4]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PNG: u32 = 0x2020_474e;
+    const XRGB8888: u32 = 0x3432_5258;
+    const TILED: u64 = 0x0100_0000_0000_0001;
+
+    #[test]
+    fn dmabuf_format_negotiation() {
+        let linear = [
+            (XRGB8888, MOD_LINEAR),
+            (FORMAT_ARGB8888, TILED),
+            (FORMAT_ARGB8888, MOD_LINEAR)
+        ];
+        assert_eq!(negotiate(&linear), Some(MOD_LINEAR));
+        // The format events of version 1 and 2 only imply a modifier.
+        let implicit = [(FORMAT_ARGB8888, MOD_INVALID), (FORMAT_ARGB8888, TILED)];
+        assert_eq!(negotiate(&implicit), Some(MOD_INVALID));
+        // Tiled layouts can't be written by the CPU, and other formats
+        // don't have alpha, so the drawable falls back to shared memory.
+        assert_eq!(
+            negotiate(&[(FORMAT_ARGB8888, TILED), (XRGB8888, MOD_LINEAR)]),
+            None
+        );
+        assert_eq!(negotiate(&[(PNG, MOD_LINEAR)]), None);
+        assert_eq!(negotiate(&[]), None);
+    }
+
+    #[test]
+    fn dmabuf_mapped_writes() {
+        // A mapped buffer object has rows padded to its stride.
+        let size = Size { width: 3, height: 2 };
+        let dest_stride = 16;
+        let mut dest = vec![0xaa; dest_stride * 2];
+        let data: Vec<u8> = (0..4 * 4 * 3).map(|byte| byte as u8).collect();
+        let stride = 16;
+        copy_all(&mut dest, dest_stride, size, &data, stride, Origin { x: 1, y: 1 });
+        assert_eq!(&dest[..12], &data[20..32]);
+        assert_eq!(&dest[16..28], &data[36..48]);
+        // The padding is left alone.
+        assert_eq!(&dest[12..16], &[0xaa; 4]);
+        assert_eq!(&dest[28..32], &[0xaa; 4]);
+        // Past the content is cleared.
+        copy_all(&mut dest, dest_stride, size, &data, stride, Origin { x: 2, y: 2 });
+        assert_eq!(&dest[..8], &data[40..48]);
+        assert_eq!(&dest[8..12], &[0; 4]);
+        assert_eq!(&dest[16..28], &[0; 12]);
+        // Only the rects are written.
+        let mut dest = vec![0xaa; dest_stride * 2];
+        let rect = Area {
+            origin: Origin { x: 1, y: 1 },
+            size: Size { width: 5, height: 5 }
+        };
+        copy_rects(
+            &mut dest,
+            dest_stride,
+            size,
+            &data,
+            stride,
+            Origin::default(),
+            &[rect]
+        );
+        assert_eq!(&dest[..20], &[0xaa; 20][..]);
+        assert_eq!(&dest[20..28], &data[20..28]);
+        assert_eq!(&dest[28..32], &[0xaa; 4]);
+    }
+}
@@ -1,13 +1,76 @@
 //! Wrappers around Wayland objects
 
+mod color_management;
+mod cursor;
+mod foreign_toplevel;
+#[cfg(feature = "dmabuf")]
+mod gbm;
+mod input_method;
+mod layer_shell;
+#[cfg(feature = "dmabuf")]
+mod linux_dmabuf;
 mod output;
+mod seat;
+mod shortcuts_inhibit;
+#[cfg(test)]
+pub mod test_server;
+mod virtual_keyboard;
 mod wl_compositor;
 mod wl_shm;
 mod xdg_shell;
 
+use std::cell::RefCell;
+
+#[cfg(feature = "dmabuf")]
+pub use self::linux_dmabuf::{
+    create_dmabuf_buffer, DmabufBuffer, LinuxDmabufManager, ZwpLinuxDmabufV1, LINUX_DMABUF_MAX_VERSION,
+    LINUX_DMABUF_VERSION
+};
 pub use self::{
-    output::{Output, WlOutputManager, WL_OUTPUT_VERSION},
-    wl_compositor::{create_surface, WlCompositorManager, WL_COMPOSITOR_VERSION},
-    wl_shm::{create_buffer, WlShmManager, WL_SHM_VERSION},
-    xdg_shell::{create_xdg_toplevel, xdg_shell_init, XdgToplevel, XDG_WM_BASE_VERSION}
+    color_management::{tag_surface, ColorManager, OutputColor, WpColorManagerV1, COLOR_MANAGER_VERSION},
+    cursor::{is_cursor_name, DEFAULT_CURSOR},
+    foreign_toplevel::{ForeignToplevel, ForeignToplevelManager, FOREIGN_TOPLEVEL_MANAGER_VERSION},
+    input_method::{on_text_input, InputMethodManager, INPUT_METHOD_VERSION},
+    layer_shell::{
+        anchor_of, create_layer_surface, has_layer_shell, Anchor, KeyboardInteractivity, Layer,
+        LayerShellManager, LayerSurface, LAYER_SHELL_MAX_VERSION, LAYER_SHELL_VERSION
+    },
+    output::{binding_output, output_removed, Output, WlOutputManager, WL_OUTPUT_VERSION},
+    seat::{
+        on_keyboard_event, on_pointer_event, set_cursor, KeyboardEvent, PointerEvent, WlSeatManager,
+        WL_SEAT_VERSION
+    },
+    shortcuts_inhibit::{
+        create_input_inhibitor, create_shortcuts_inhibitor, InputInhibitManager, InputInhibitor,
+        ShortcutsInhibitManager, ShortcutsInhibitor, ZwlrInputInhibitManagerV1,
+        ZwpKeyboardShortcutsInhibitManagerV1, INPUT_INHIBIT_MANAGER_VERSION,
+        SHORTCUTS_INHIBIT_MANAGER_VERSION
+    },
+    virtual_keyboard::{
+        create_virtual_keyboard, VirtualKeyboard, VirtualKeyboardManager, ZwpVirtualKeyboardManagerV1,
+        VIRTUAL_KEYBOARD_MANAGER_VERSION
+    },
+    wl_compositor::{
+        create_region, create_surface, on_surface_outputs_changed, surface_outputs, WlCompositorManager,
+        WL_COMPOSITOR_VERSION
+    },
+    wl_shm::{
+        create_buffer, create_mapped_buffer, import_buffer, on_buffer_release, supports_format, Buffer,
+        BufferError, ImportedBuffer, MappedBuffer, Mapping, WlShmManager, WL_SHM_VERSION
+    },
+    xdg_shell::{XdgWmBaseManager, XDG_WM_BASE_VERSION}
 };
+
+thread_local! {
+    /// The interface and version of the globals the compositor advertised
+    /// when the client connected.
+    static GLOBALS: RefCell<Vec<(String, u32)>> = RefCell::new(Vec::new());
+}
+
+pub fn set_globals(globals: Vec<(String, u32)>) {
+    GLOBALS.with(|cell| *cell.borrow_mut() = globals);
+}
+
+pub fn globals() -> Vec<(String, u32)> {
+    GLOBALS.with(|globals| globals.borrow().clone())
+}
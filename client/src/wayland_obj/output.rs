@@ -1,19 +1,37 @@
 //! Wrapper around a wl_output
 
-use std::{cell::RefCell, fmt};
+use std::{
+    cell::{Cell, RefCell},
+    fmt
+};
 
+use rlua::Value;
 use wayland_client::{
     protocol::wl_output::{self, WlOutput},
     GlobalImplementor, NewProxy, Proxy
 };
 
-use crate::area::{Area, Origin, Size};
+use crate::area::{Area, Origin, Size, Transform};
+use crate::common::object::Object;
+use crate::event_trace::{self, Arg};
 use crate::lua::LUA;
-use crate::objects::screen::{self, Screen};
+use crate::objects::{
+    drawin,
+    screen::{self, Screen}
+};
+use crate::picker;
+use crate::wayland_obj::color_management::{self, OutputColor};
 
 /// The minimum version of the wl_output global to bind to.
 pub const WL_OUTPUT_VERSION: u32 = 2;
 
+thread_local! {
+    /// The name of the wl_output global that's being bound.
+    static BINDING: Cell<u32> = Cell::new(0);
+    /// The bound outputs, by the name of their global.
+    static OUTPUTS: RefCell<Vec<(u32, WlOutput)>> = RefCell::new(Vec::new());
+}
+
 /// Wrapper around WlOutput.
 #[derive(Clone, Eq, PartialEq)]
 pub struct Output {
@@ -27,25 +45,87 @@ pub struct WlOutputManager {}
 pub struct WlOutputEventHandler {}
 
 /// The cached state for the WlOutput.
-#[derive(Debug, Default, Clone, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq)]
 struct OutputState {
     name: String,
-    resolution: (u32, u32)
+    make: String,
+    model: String,
+    /// Where the output is in the compositor's coordinates, across all
+    /// outputs.
+    position: Origin,
+    /// The size of the current mode, in pixels.
+    resolution: Size,
+    transform: Transform,
+    scale: i32,
+    /// The color the output shows, if the compositor said.
+    color: Option<OutputColor>
+}
+
+impl OutputState {
+    /// The size of the output in the compositor's coordinates, which is its
+    /// mode turned and scaled down.
+    fn size(&self) -> Size {
+        let pixels: Area = self.resolution.into();
+        pixels
+            .transform(self.transform, self.resolution)
+            .scale(1.0 / self.scale.max(1) as f64)
+            .size
+    }
+
+    fn geometry(&self) -> Area {
+        Area {
+            origin: self.position,
+            size: self.size()
+        }
+    }
 }
 
 impl Output {
-    pub fn resolution(&self) -> (u32, u32) {
-        unwrap_state(self.as_ref()).borrow().resolution
+    /// Where the output is across all outputs, and its size.
+    pub fn geometry(&self) -> Area {
+        unwrap_state(self.as_ref()).borrow().geometry()
     }
 
     pub fn name(&self) -> String {
         unwrap_state(self.as_ref()).borrow().name.clone()
     }
+
+    pub fn make(&self) -> String {
+        unwrap_state(self.as_ref()).borrow().make.clone()
+    }
+
+    pub fn model(&self) -> String {
+        unwrap_state(self.as_ref()).borrow().model.clone()
+    }
+
+    /// How many pixels of the output make a unit of the compositor's
+    /// coordinates, at least 1.
+    pub fn scale(&self) -> i32 {
+        unwrap_state(self.as_ref()).borrow().scale.max(1)
+    }
+
+    /// The color the output shows, see `color_management`.
+    pub fn color(&self) -> Option<OutputColor> {
+        unwrap_state(self.as_ref()).borrow().color.clone()
+    }
+
+    pub fn wl_output(&self) -> &WlOutput {
+        &self.output
+    }
+}
+
+impl From<WlOutput> for Output {
+    fn from(output: WlOutput) -> Self {
+        Output { output }
+    }
 }
 
 impl GlobalImplementor<WlOutput> for WlOutputManager {
     fn new_global(&mut self, new_proxy: NewProxy<WlOutput>) -> WlOutput {
         let res = new_proxy.implement(WlOutputEventHandler {}, RefCell::new(OutputState::default()));
+        let global = BINDING.with(Cell::get);
+        OUTPUTS.with(|outputs| outputs.borrow_mut().push((global, res.clone())));
+        color_management::watch_output(&res);
 
         LUA.with(|lua| {
             lua.borrow().context(|ctx| {
@@ -76,35 +156,49 @@ impl wl_output::EventHandler for WlOutputEventHandler {
         model: String,
         transform: wl_output::Transform
     ) {
-        unwrap_state(object.as_ref()).borrow_mut().name = format!("{} ({})", make, model);
+        event_trace::event(object.as_ref(), "geometry", || {
+            vec![
+                Arg::Int(x.into()),
+                Arg::Int(y.into()),
+                Arg::Int(physical_width.into()),
+                Arg::Int(physical_height.into()),
+                Arg::Uint(subpixel.to_raw().into()),
+                Arg::Str(make.clone()),
+                Arg::Str(model.clone()),
+                Arg::Uint(transform.to_raw().into()),
+            ]
+        });
+        {
+            let mut state = unwrap_state(object.as_ref()).borrow_mut();
+            state.name = format!("{} ({})", make, model);
+            state.make = make;
+            state.model = model;
+            state.position = Origin { x, y };
+            state.transform = Transform::from_raw(transform.to_raw());
+        }
+        update_geometry(object);
     }
 
     #[allow(unused)]
     fn mode(&mut self, object: WlOutput, flags: wl_output::Mode, width: i32, height: i32, refresh: i32) {
-        unwrap_state(object.as_ref()).borrow_mut().resolution = (width as u32, height as u32);
-        let geometry = Area {
-            origin: Origin { x: 0, y: 0 },
-            size: Size {
-                width: width as u32,
-                height: height as u32
-            }
-        };
-        LUA.with(|lua| {
-            lua.borrow().context(|ctx| {
-                if let Ok(mut screen) = screen::get_screen(ctx, Output { output: object }) {
-                    screen
-                        .set_geometry(ctx, geometry)
-                        .expect("could not set geometry");
-                    screen
-                        .set_workarea(ctx, geometry)
-                        .expect("could not set workarea ");
-                }
-            });
+        event_trace::event(object.as_ref(), "mode", || {
+            vec![
+                Arg::Uint(flags.bits().into()),
+                Arg::Int(width.into()),
+                Arg::Int(height.into()),
+                Arg::Int(refresh.into()),
+            ]
         });
+        unwrap_state(object.as_ref()).borrow_mut().resolution = Size {
+            width: width.max(0) as u32,
+            height: height.max(0) as u32
+        };
+        update_geometry(object);
     }
 
     #[allow(unused)]
     fn done(&mut self, object: WlOutput) {
+        event_trace::event(object.as_ref(), "done", Vec::new);
         // TODO We may not always want to add a new screen
         // see how awesome does it and fix this.
         LUA.with(|lua| {
@@ -115,14 +209,107 @@ impl wl_output::EventHandler for WlOutputEventHandler {
                     .init_screens(output.clone(), vec![output])
                     .expect("Could not initilize new output with a screen");
                 screen::add_screen(ctx, screen).expect("Could not add screen to the list of screens");
+                drawin::update_edge_claims(ctx).expect("Could not update the edges owned by drawins");
+                drawin::outputs_changed(ctx).expect("Could not migrate drawins to the outputs");
+                picker::outputs_changed(ctx).expect("Could not cancel the geometry pick");
             });
         });
     }
 
-    #[allow(unused)]
     fn scale(&mut self, object: WlOutput, factor: i32) {
-        // TODO
+        event_trace::event(object.as_ref(), "scale", || vec![Arg::Int(factor.into())]);
+        unwrap_state(object.as_ref()).borrow_mut().scale = factor;
+        update_geometry(object);
+    }
+}
+
+/// Tells the output that's bound next which global it is.
+pub fn binding_output(global: u32) {
+    BINDING.with(|binding| binding.set(global));
+}
+
+/// Forgets the outputs, when the connection of a test is gone.
+#[cfg(test)]
+pub(super) fn unbind() {
+    OUTPUTS.with(|outputs| outputs.borrow_mut().clear());
+}
+
+/// Removes the screen of the output of the global, which was unplugged.
+pub fn output_removed(global: u32) {
+    let output = OUTPUTS.with(|outputs| {
+        let mut outputs = outputs.borrow_mut();
+        let index = outputs.iter().position(|&(other, _)| other == global)?;
+        Some(outputs.remove(index).1)
+    });
+    let output = match output {
+        Some(output) => Output { output },
+        None => return
+    };
+    color_management::forget_output(&output.output);
+    LUA.with(|lua| {
+        lua.borrow().context(|ctx| {
+            while let Ok(screen) = screen::get_screen(ctx, output.clone()) {
+                screen::remove_screen(ctx, screen).expect("Could not remove the screen");
+            }
+            drawin::update_workareas(ctx).expect("Could not update the workareas");
+            drawin::update_edge_claims(ctx).expect("Could not update the edges owned by drawins");
+            drawin::outputs_changed(ctx).expect("Could not migrate drawins to the outputs");
+            picker::outputs_changed(ctx).expect("Could not cancel the geometry pick");
+        });
+    });
+}
+
+/// The outputs that are bound.
+pub fn bound_outputs() -> Vec<WlOutput> {
+    OUTPUTS.with(|outputs| {
+        outputs
+            .borrow()
+            .iter()
+            .map(|(_, output)| output.clone())
+            .collect()
+    })
+}
+
+/// Sets the color the output shows, and tells its screen if it changed.
+pub fn set_color(object: &WlOutput, color: Option<OutputColor>) {
+    {
+        let mut state = unwrap_state(object.as_ref()).borrow_mut();
+        if state.color == color {
+            return;
+        }
+        state.color = color;
     }
+    LUA.with(|lua| {
+        lua.borrow().context(|ctx| {
+            if let Ok(screen) = screen::get_screen(
+                ctx,
+                Output {
+                    output: object.clone()
+                }
+            ) {
+                Object::emit_signal(ctx, &screen, "property::color", Value::Nil)
+                    .expect("Could not emit property::color");
+            }
+        });
+    });
+}
+
+/// Sets the geometry of the screen of the output to the current position
+/// and size of the output.
+fn update_geometry(object: WlOutput) {
+    let geometry = unwrap_state(object.as_ref()).borrow().geometry();
+    LUA.with(|lua| {
+        lua.borrow().context(|ctx| {
+            if let Ok(mut screen) = screen::get_screen(ctx, Output { output: object }) {
+                screen
+                    .set_geometry(ctx, geometry)
+                    .expect("could not set geometry");
+            }
+            drawin::update_placement(ctx).expect("Could not place the drawins on the screen again");
+            drawin::update_workareas(ctx).expect("Could not update the workareas");
+            drawin::update_edge_claims(ctx).expect("Could not update the edges owned by drawins");
+        });
+    });
 }
 
 impl fmt::Debug for Output {
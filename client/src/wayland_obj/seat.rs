@@ -0,0 +1,401 @@
+//! Wrapper around a wl_seat.
+
+use std::{
+    cell::{Cell, RefCell},
+    fs::File,
+    io::Read,
+    os::unix::io::{FromRawFd, RawFd},
+    rc::Rc
+};
+
+use wayland_client::{
+    protocol::{
+        wl_keyboard::{self, KeyState, KeymapFormat, WlKeyboard},
+        wl_pointer::{self, ButtonState, WlPointer},
+        wl_seat::{self, Capability, WlSeat},
+        wl_surface::WlSurface
+    },
+    GlobalImplementor, NewProxy
+};
+use xkbcommon::xkb::{self, keysyms, Keysym};
+
+use crate::clock;
+use crate::event_trace::{self, Arg};
+
+use super::cursor;
+
+/// The minimum version of the wl_seat global to bind to.
+pub const WL_SEAT_VERSION: u32 = 1;
+
+thread_local! {
+    static WL_SEAT: RefCell<Option<WlSeat>> = RefCell::new(None);
+    static WL_POINTER: RefCell<Option<WlPointer>> = RefCell::new(None);
+    /// The serial of the last time the pointer entered one of our surfaces,
+    /// which setting its cursor needs.
+    static ENTER_SERIAL: Cell<Option<u32>> = Cell::new(None);
+    /// Called with everything the pointer does.
+    static POINTER_HANDLER: RefCell<Option<Rc<dyn Fn(PointerEvent)>>> = RefCell::new(None);
+    static WL_KEYBOARD: RefCell<Option<WlKeyboard>> = RefCell::new(None);
+    /// The state of the keyboard in the keymap the compositor sent last.
+    static XKB_STATE: RefCell<Option<xkb::State>> = RefCell::new(None);
+    /// Called with everything the keyboard does.
+    static KEYBOARD_HANDLER: RefCell<Option<Rc<dyn Fn(KeyboardEvent)>>> = RefCell::new(None);
+}
+
+/// The modifiers Lua knows, by their name in XKB, as the keysyms Lua's
+/// lists of modifiers are made from.
+const MODIFIERS: &[(&str, Keysym)] = &[
+    (xkb::MOD_NAME_SHIFT, keysyms::KEY_Shift_L),
+    (xkb::MOD_NAME_CAPS, keysyms::KEY_Caps_Lock),
+    (xkb::MOD_NAME_CTRL, keysyms::KEY_Control_L),
+    (xkb::MOD_NAME_ALT, keysyms::KEY_Alt_L),
+    (xkb::MOD_NAME_NUM, keysyms::KEY_Meta_L),
+    (xkb::MOD_NAME_LOGO, keysyms::KEY_Super_L)
+];
+
+/// Something the pointer did over one of our surfaces.
+///
+/// Positions are relative to the top left corner of the surface the pointer
+/// is over. Times are in milliseconds since the client started, see
+/// `clock`. The compositor doesn't say when the pointer entered or left a
+/// surface, so that is when we were told.
+#[derive(Clone)]
+pub enum PointerEvent {
+    Enter {
+        surface: WlSurface,
+        x: f64,
+        y: f64,
+        time: f64
+    },
+    Leave {
+        surface: WlSurface,
+        time: f64
+    },
+    Motion {
+        x: f64,
+        y: f64,
+        time: f64
+    },
+    /// A button, as a Linux input event code, was pressed or released.
+    Button {
+        button: u32,
+        pressed: bool,
+        time: f64
+    }
+}
+
+impl PointerEvent {
+    pub fn time(&self) -> f64 {
+        match *self {
+            PointerEvent::Enter { time, .. } |
+            PointerEvent::Leave { time, .. } |
+            PointerEvent::Motion { time, .. } |
+            PointerEvent::Button { time, .. } => time
+        }
+    }
+}
+
+/// Something the keyboard did while one of our surfaces had its focus.
+/// Times are like those of `PointerEvent`.
+#[derive(Clone)]
+pub enum KeyboardEvent {
+    Enter {
+        surface: WlSurface,
+        time: f64
+    },
+    Leave {
+        surface: WlSurface,
+        time: f64
+    },
+    /// The key with the keysym was pressed or released, while the
+    /// modifiers were held down.
+    Key {
+        keysym: Keysym,
+        modifiers: Vec<Keysym>,
+        pressed: bool,
+        time: f64
+    }
+}
+
+pub struct WlSeatManager {}
+
+struct SeatEventHandler {}
+
+struct PointerEventHandler {}
+
+struct KeyboardEventHandler {}
+
+impl GlobalImplementor<WlSeat> for WlSeatManager {
+    fn new_global(&mut self, new_proxy: NewProxy<WlSeat>) -> WlSeat {
+        let res = new_proxy.implement(SeatEventHandler {}, ());
+
+        WL_SEAT.with(|wl_seat| {
+            *wl_seat.borrow_mut() = Some(res.clone());
+        });
+
+        res
+    }
+}
+
+impl wl_seat::EventHandler for SeatEventHandler {
+    fn capabilities(&mut self, object: WlSeat, capabilities: Capability) {
+        WL_POINTER.with(|wl_pointer| {
+            let mut wl_pointer = wl_pointer.borrow_mut();
+            if !capabilities.contains(Capability::Pointer) {
+                // The pointer can't be released before version 3 of wl_seat,
+                // the compositor will stop sending events for it though.
+                *wl_pointer = None;
+            } else if wl_pointer.is_none() {
+                *wl_pointer = object
+                    .get_pointer(|new_proxy| new_proxy.implement(PointerEventHandler {}, ()))
+                    .ok();
+            }
+        });
+        WL_KEYBOARD.with(|wl_keyboard| {
+            let mut wl_keyboard = wl_keyboard.borrow_mut();
+            if !capabilities.contains(Capability::Keyboard) {
+                *wl_keyboard = None;
+                XKB_STATE.with(|state| *state.borrow_mut() = None);
+            } else if wl_keyboard.is_none() {
+                *wl_keyboard = object
+                    .get_keyboard(|new_proxy| new_proxy.implement(KeyboardEventHandler {}, ()))
+                    .ok();
+            }
+        })
+    }
+}
+
+impl wl_pointer::EventHandler for PointerEventHandler {
+    fn enter(&mut self, object: WlPointer, serial: u32, surface: WlSurface, surface_x: f64, surface_y: f64) {
+        event_trace::input_event(object.as_ref(), "enter", || {
+            vec![
+                Arg::Uint(serial.into()),
+                Arg::Object(Some(event_trace::object(surface.as_ref()))),
+                Arg::Fixed(surface_x),
+                Arg::Fixed(surface_y),
+            ]
+        });
+        ENTER_SERIAL.with(|enter_serial| enter_serial.set(Some(serial)));
+        handle_pointer_event(PointerEvent::Enter {
+            surface,
+            x: surface_x,
+            y: surface_y,
+            time: clock::now()
+        })
+    }
+
+    fn leave(&mut self, object: WlPointer, serial: u32, surface: WlSurface) {
+        event_trace::input_event(object.as_ref(), "leave", || {
+            vec![
+                Arg::Uint(serial.into()),
+                Arg::Object(Some(event_trace::object(surface.as_ref()))),
+            ]
+        });
+        ENTER_SERIAL.with(|enter_serial| enter_serial.set(None));
+        handle_pointer_event(PointerEvent::Leave {
+            surface,
+            time: clock::now()
+        })
+    }
+
+    fn motion(&mut self, object: WlPointer, time: u32, surface_x: f64, surface_y: f64) {
+        event_trace::input_event(object.as_ref(), "motion", || {
+            vec![
+                Arg::Uint(time.into()),
+                Arg::Fixed(surface_x),
+                Arg::Fixed(surface_y),
+            ]
+        });
+        handle_pointer_event(PointerEvent::Motion {
+            x: surface_x,
+            y: surface_y,
+            time: clock::event_time(time)
+        })
+    }
+
+    fn button(&mut self, object: WlPointer, serial: u32, time: u32, button: u32, state: ButtonState) {
+        event_trace::input_event(object.as_ref(), "button", || {
+            vec![
+                Arg::Uint(serial.into()),
+                Arg::Uint(time.into()),
+                Arg::Uint(button.into()),
+                Arg::Uint(state.to_raw().into()),
+            ]
+        });
+        handle_pointer_event(PointerEvent::Button {
+            button,
+            pressed: state == ButtonState::Pressed,
+            time: clock::event_time(time)
+        })
+    }
+}
+
+impl wl_keyboard::EventHandler for KeyboardEventHandler {
+    fn keymap(&mut self, object: WlKeyboard, format: KeymapFormat, fd: RawFd, size: u32) {
+        event_trace::input_event(object.as_ref(), "keymap", || {
+            vec![
+                Arg::Uint(format.to_raw().into()),
+                Arg::Int(i64::from(fd)),
+                Arg::Uint(size.into()),
+            ]
+        });
+        // The file is closed when it's dropped.
+        let file = unsafe { File::from_raw_fd(fd) };
+        let state = match format {
+            KeymapFormat::XkbV1 => load_keymap(file, size),
+            _ => None
+        };
+        if state.is_none() {
+            warn!("Could not load the keymap of the keyboard, key presses are ignored");
+        }
+        XKB_STATE.with(|xkb_state| *xkb_state.borrow_mut() = state);
+    }
+
+    fn enter(&mut self, object: WlKeyboard, serial: u32, surface: WlSurface, _: Vec<u8>) {
+        event_trace::input_event(object.as_ref(), "enter", || {
+            vec![
+                Arg::Uint(serial.into()),
+                Arg::Object(Some(event_trace::object(surface.as_ref()))),
+            ]
+        });
+        handle_keyboard_event(KeyboardEvent::Enter {
+            surface,
+            time: clock::now()
+        })
+    }
+
+    fn leave(&mut self, object: WlKeyboard, serial: u32, surface: WlSurface) {
+        event_trace::input_event(object.as_ref(), "leave", || {
+            vec![
+                Arg::Uint(serial.into()),
+                Arg::Object(Some(event_trace::object(surface.as_ref()))),
+            ]
+        });
+        handle_keyboard_event(KeyboardEvent::Leave {
+            surface,
+            time: clock::now()
+        })
+    }
+
+    fn key(&mut self, object: WlKeyboard, serial: u32, time: u32, key: u32, state: KeyState) {
+        event_trace::input_event(object.as_ref(), "key", || {
+            vec![
+                Arg::Uint(serial.into()),
+                Arg::Uint(time.into()),
+                Arg::Uint(key.into()),
+                Arg::Uint(state.to_raw().into()),
+            ]
+        });
+        let pressed = XKB_STATE.with(|xkb_state| {
+            xkb_state.borrow().as_ref().map(|xkb_state| {
+                // XKB keycodes are the evdev ones plus 8.
+                let keysym = xkb_state.key_get_one_sym(key + 8);
+                let modifiers = MODIFIERS
+                    .iter()
+                    .filter(|(name, _)| xkb_state.mod_name_is_active(*name, xkb::STATE_MODS_EFFECTIVE))
+                    .map(|&(_, keysym)| keysym)
+                    .collect();
+                (keysym, modifiers)
+            })
+        });
+        if let Some((keysym, modifiers)) = pressed {
+            handle_keyboard_event(KeyboardEvent::Key {
+                keysym,
+                modifiers,
+                pressed: state == KeyState::Pressed,
+                time: clock::event_time(time)
+            })
+        }
+    }
+
+    fn modifiers(
+        &mut self,
+        object: WlKeyboard,
+        serial: u32,
+        mods_depressed: u32,
+        mods_latched: u32,
+        mods_locked: u32,
+        group: u32
+    ) {
+        event_trace::input_event(object.as_ref(), "modifiers", || {
+            vec![
+                Arg::Uint(serial.into()),
+                Arg::Uint(mods_depressed.into()),
+                Arg::Uint(mods_latched.into()),
+                Arg::Uint(mods_locked.into()),
+                Arg::Uint(group.into()),
+            ]
+        });
+        XKB_STATE.with(|xkb_state| {
+            if let Some(xkb_state) = xkb_state.borrow_mut().as_mut() {
+                xkb_state.update_mask(mods_depressed, mods_latched, mods_locked, 0, 0, group);
+            }
+        })
+    }
+}
+
+/// Reads the keymap the compositor sent, which is `size` bytes of the
+/// file ending with a nul.
+fn load_keymap(mut file: File, size: u32) -> Option<xkb::State> {
+    let mut keymap = Vec::with_capacity(size as usize);
+    file.by_ref()
+        .take(u64::from(size))
+        .read_to_end(&mut keymap)
+        .ok()?;
+    let end = keymap
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or_else(|| keymap.len());
+    keymap.truncate(end);
+    let keymap = String::from_utf8(keymap).ok()?;
+    let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+    let keymap = xkb::Keymap::new_from_string(
+        &context,
+        keymap,
+        xkb::KEYMAP_FORMAT_TEXT_V1,
+        xkb::KEYMAP_COMPILE_NO_FLAGS
+    )?;
+    Some(xkb::State::new(&keymap))
+}
+
+/// Get the seat the compositor advertised, if there is one.
+pub fn seat() -> Option<WlSeat> {
+    WL_SEAT.with(|wl_seat| wl_seat.borrow().clone())
+}
+
+/// Shows the cursor `name` while the pointer is over the surface it
+/// entered last, see `cursor`.
+pub fn set_cursor(name: &str) {
+    let serial = match ENTER_SERIAL.with(Cell::get) {
+        Some(serial) => serial,
+        None => return
+    };
+    WL_POINTER.with(|wl_pointer| {
+        if let Some(wl_pointer) = wl_pointer.borrow().as_ref() {
+            cursor::show(wl_pointer, serial, name)
+        }
+    })
+}
+
+/// Sets the function that is called with everything the pointer does.
+pub fn on_pointer_event(handler: Rc<dyn Fn(PointerEvent)>) {
+    POINTER_HANDLER.with(|pointer_handler| *pointer_handler.borrow_mut() = Some(handler));
+}
+
+/// Sets the function that is called with everything the keyboard does.
+pub fn on_keyboard_event(handler: Rc<dyn Fn(KeyboardEvent)>) {
+    KEYBOARD_HANDLER.with(|keyboard_handler| *keyboard_handler.borrow_mut() = Some(handler));
+}
+
+fn handle_keyboard_event(event: KeyboardEvent) {
+    if let Some(handler) = KEYBOARD_HANDLER.with(|keyboard_handler| keyboard_handler.borrow().clone()) {
+        handler(event)
+    }
+}
+
+fn handle_pointer_event(event: PointerEvent) {
+    // The handler is cloned so it can set a new handler while it runs.
+    if let Some(handler) = POINTER_HANDLER.with(|pointer_handler| pointer_handler.borrow().clone()) {
+        handler(event)
+    }
+}
@@ -0,0 +1,162 @@
+//! Wrappers around the zwp_keyboard_shortcuts_inhibit_manager_v1 and the
+//! zwlr_input_inhibit_manager_v1.
+//!
+//! A shortcuts inhibitor asks the compositor to send the key presses it
+//! would act on itself, like switching windows, to a surface while it has
+//! keyboard focus. The compositor may not allow it, or stop allowing it at
+//! any time, and says so with the `active` and `inactive` events.
+//!
+//! An input inhibitor sends all input to this client, whatever surface has
+//! focus, which is what a lock screen wants. Only one client can have one,
+//! the compositor disconnects a second one.
+
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use wayland_client::{GlobalImplementor, NewProxy, Proxy};
+use wayland_protocols::{
+    unstable::keyboard_shortcuts_inhibit::v1::client::{
+        zwp_keyboard_shortcuts_inhibit_manager_v1,
+        zwp_keyboard_shortcuts_inhibitor_v1::{self, ZwpKeyboardShortcutsInhibitorV1}
+    },
+    wlr::unstable::input_inhibitor::v1::client::{
+        zwlr_input_inhibit_manager_v1, zwlr_input_inhibitor_v1::ZwlrInputInhibitorV1
+    }
+};
+
+pub use self::zwlr_input_inhibit_manager_v1::ZwlrInputInhibitManagerV1;
+pub use self::zwp_keyboard_shortcuts_inhibit_manager_v1::ZwpKeyboardShortcutsInhibitManagerV1;
+use crate::wayland_obj::{seat, LayerSurface};
+
+/// The minimum version of the zwp_keyboard_shortcuts_inhibit_manager_v1
+/// global to bind to.
+pub const SHORTCUTS_INHIBIT_MANAGER_VERSION: u32 = 1;
+
+/// The minimum version of the zwlr_input_inhibit_manager_v1 global to bind
+/// to.
+pub const INPUT_INHIBIT_MANAGER_VERSION: u32 = 1;
+
+thread_local! {
+    static SHORTCUTS_INHIBIT_MANAGER: RefCell<Option<ZwpKeyboardShortcutsInhibitManagerV1>> =
+        RefCell::new(None);
+    static INPUT_INHIBIT_MANAGER: RefCell<Option<ZwlrInputInhibitManagerV1>> = RefCell::new(None);
+}
+
+pub struct ShortcutsInhibitManager {}
+
+pub struct InputInhibitManager {}
+
+impl GlobalImplementor<ZwpKeyboardShortcutsInhibitManagerV1> for ShortcutsInhibitManager {
+    fn new_global(
+        &mut self,
+        new_proxy: NewProxy<ZwpKeyboardShortcutsInhibitManagerV1>
+    ) -> ZwpKeyboardShortcutsInhibitManagerV1 {
+        let res = new_proxy.implement_dummy();
+
+        SHORTCUTS_INHIBIT_MANAGER.with(|manager| {
+            *manager.borrow_mut() = Some(res.clone());
+        });
+
+        res
+    }
+}
+
+impl GlobalImplementor<ZwlrInputInhibitManagerV1> for InputInhibitManager {
+    fn new_global(&mut self, new_proxy: NewProxy<ZwlrInputInhibitManagerV1>) -> ZwlrInputInhibitManagerV1 {
+        let res = new_proxy.implement_dummy();
+
+        INPUT_INHIBIT_MANAGER.with(|manager| {
+            *manager.borrow_mut() = Some(res.clone());
+        });
+
+        res
+    }
+}
+
+// Handle incoming events for ZwpKeyboardShortcutsInhibitorV1, whose user
+// data is the function told about them.
+struct ShortcutsInhibitorEventHandler {}
+
+impl zwp_keyboard_shortcuts_inhibitor_v1::EventHandler for ShortcutsInhibitorEventHandler {
+    fn active(&mut self, object: ZwpKeyboardShortcutsInhibitorV1) {
+        notify(object.as_ref(), true)
+    }
+
+    fn inactive(&mut self, object: ZwpKeyboardShortcutsInhibitorV1) {
+        notify(object.as_ref(), false)
+    }
+}
+
+fn notify(proxy: &Proxy<ZwpKeyboardShortcutsInhibitorV1>, active: bool) {
+    let callback = proxy
+        .user_data::<Rc<dyn Fn(bool)>>()
+        .expect("User data has not been set yet")
+        .clone();
+    callback(active)
+}
+
+/// Inhibits the shortcuts of the compositor for a surface until dropped.
+pub struct ShortcutsInhibitor {
+    proxy: ZwpKeyboardShortcutsInhibitorV1
+}
+
+impl Drop for ShortcutsInhibitor {
+    fn drop(&mut self) {
+        self.proxy.destroy();
+    }
+}
+
+impl fmt::Debug for ShortcutsInhibitor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.proxy.as_ref().c_ptr())
+    }
+}
+
+/// Sends all input to this client until dropped.
+pub struct InputInhibitor {
+    proxy: ZwlrInputInhibitorV1
+}
+
+impl Drop for InputInhibitor {
+    fn drop(&mut self) {
+        self.proxy.destroy();
+    }
+}
+
+impl fmt::Debug for InputInhibitor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.proxy.as_ref().c_ptr())
+    }
+}
+
+/// Asks the compositor to send its shortcuts to the layer surface while it
+/// has keyboard focus on the seat. `callback` is called with whether the
+/// compositor does so whenever that changes.
+///
+/// Fails if the compositor doesn't have the protocol or a seat.
+pub fn create_shortcuts_inhibitor(
+    layer_surface: &LayerSurface,
+    callback: Rc<dyn Fn(bool)>
+) -> Result<ShortcutsInhibitor, ()> {
+    let seat = seat::seat().ok_or(())?;
+    SHORTCUTS_INHIBIT_MANAGER.with(|manager| {
+        let manager = manager.borrow();
+        let manager = manager.as_ref().ok_or(())?;
+        manager
+            .inhibit_shortcuts(&layer_surface.wl_surface(), &seat, |new_proxy| {
+                new_proxy.implement(ShortcutsInhibitorEventHandler {}, callback)
+            })
+            .map(|proxy| ShortcutsInhibitor { proxy })
+    })
+}
+
+/// Sends all input to this client, if the compositor has the protocol.
+pub fn create_input_inhibitor() -> Option<InputInhibitor> {
+    INPUT_INHIBIT_MANAGER.with(|manager| {
+        let manager = manager.borrow();
+        manager
+            .as_ref()?
+            .get_inhibitor(NewProxy::implement_dummy)
+            .ok()
+            .map(|proxy| InputInhibitor { proxy })
+    })
+}
@@ -0,0 +1,635 @@
+//! A compositor of a test's own, which answers just enough of the protocol
+//! for layer surfaces, or the xdg toplevels that stand in for them, with
+//! shared memory buffers, and records the requests it gets so tests can
+//! check what was sent. It can have outputs of different scales, which
+//! tests move surfaces between.
+//!
+//! The shm files the client sends are kept like a compositor keeps them,
+//! until the pool and its buffers are destroyed, and the pixels of each
+//! buffer committed to a surface are read from them.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    fs::File,
+    io::Write,
+    iter,
+    net::Shutdown,
+    os::unix::{
+        fs::FileExt,
+        io::{AsRawFd, FromRawFd, IntoRawFd, RawFd},
+        net::UnixStream
+    },
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle}
+};
+
+use nix::{
+    errno::Errno,
+    sys::{
+        socket::{recvmsg, CmsgSpace, ControlMessage, MsgFlags},
+        uio::IoVec
+    }
+};
+use wayland_client::{
+    protocol::wl_output::WlOutput, Display, EventQueue, GlobalEvent, GlobalImplementor, GlobalManager,
+    Interface
+};
+
+use crate::area::Size;
+use crate::wayland_obj::{
+    binding_output, layer_shell, output, wl_compositor, wl_shm, xdg_shell, LayerShellManager,
+    WlCompositorManager, WlOutputManager, WlShmManager, XdgWmBaseManager, LAYER_SHELL_MAX_VERSION,
+    LAYER_SHELL_VERSION, WL_COMPOSITOR_VERSION, WL_OUTPUT_VERSION, WL_SHM_VERSION, XDG_WM_BASE_VERSION
+};
+
+/// The version of wl_compositor the server has, which makes wl_surfaces of
+/// that version.
+const WL_COMPOSITOR_SERVER_VERSION: u32 = 4;
+
+/// The size of every output in the compositor's coordinates, its mode is
+/// as many times larger as its scale.
+const OUTPUT_SIZE: Size = Size {
+    width: 1000,
+    height: 800
+};
+
+/// A request the server got.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub interface: &'static str,
+    pub object: u32,
+    pub name: &'static str,
+    /// The numbers among the arguments, strings and file descriptors are
+    /// left out.
+    pub args: Vec<i64>
+}
+
+/// The pixels of a shm buffer as they were when it was committed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub surface: u32,
+    pub size: Size,
+    pub stride: u32,
+    pub pixels: Vec<u8>
+}
+
+/// A connection to a server on a thread of its own, that's closed when
+/// dropped.
+///
+/// The wl_compositor, wl_shm and zwlr_layer_shell_v1 or xdg_wm_base globals
+/// are bound like the client binds them, and forgotten again when the
+/// server is dropped.
+/// Anything made with them has to be dropped before the server.
+pub struct TestServer {
+    pub globals: GlobalManager,
+    /// The wl_outputs the client bound, in the order of their scales.
+    pub outputs: Vec<u32>,
+    event_queue: EventQueue,
+    _display: Display,
+    stream: Arc<Mutex<UnixStream>>,
+    requests: Arc<Mutex<Vec<Request>>>,
+    frames: Arc<Mutex<Vec<Frame>>>,
+    thread: Option<JoinHandle<()>>
+}
+
+impl TestServer {
+    /// Starts the server with a layer shell of `layer_shell_version` and
+    /// connects to it.
+    ///
+    /// Panics if libwayland-client can't be loaded, the tests that need a
+    /// compositor can't run without it.
+    pub fn start(layer_shell_version: u32) -> Self {
+        TestServer::with_shell(("zwlr_layer_shell_v1", layer_shell_version), Vec::new())
+    }
+
+    /// Starts a server without the layer shell, which has the xdg shell
+    /// instead, and connects to it.
+    pub fn without_layer_shell() -> Self {
+        TestServer::with_shell(("xdg_wm_base", XDG_WM_BASE_VERSION), Vec::new())
+    }
+
+    /// Starts the server with a layer shell and an output for each of
+    /// `scales`, side by side, and connects to it.
+    ///
+    /// The outputs are bound like the client binds them, which gives each
+    /// a screen in `LUA`, so it has to be set up for screens first.
+    pub fn with_outputs(layer_shell_version: u32, scales: &[i32]) -> Self {
+        TestServer::with_shell(("zwlr_layer_shell_v1", layer_shell_version), scales.to_vec())
+    }
+
+    fn with_shell(shell: (&'static str, u32), scales: Vec<i32>) -> Self {
+        let (client, server) = UnixStream::pair().unwrap();
+        let stream = Arc::new(Mutex::new(server.try_clone().unwrap()));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        let shared = [
+            ("wl_compositor", WL_COMPOSITOR_SERVER_VERSION),
+            ("wl_shm", WL_SHM_VERSION),
+            shell
+        ];
+        let thread = {
+            let stream = stream.clone();
+            let requests = requests.clone();
+            let frames = frames.clone();
+            thread::spawn(move || serve(server, &stream, &requests, &frames, &shared, &scales))
+        };
+        let (display, event_queue) = match unsafe { Display::from_fd(client.into_raw_fd()) } {
+            Ok(connection) => connection,
+            Err(err) => {
+                stream.lock().unwrap().shutdown(Shutdown::Both).ok();
+                thread.join().ok();
+                panic!("Could not connect to the test server: {:?}", err);
+            }
+        };
+        let globals = GlobalManager::new_with_cb(&display, |event, registry| match event {
+            GlobalEvent::New {
+                id, ref interface, ..
+            } if interface == WlOutput::NAME => {
+                binding_output(id);
+                registry
+                    .bind(WL_OUTPUT_VERSION, id, |new_proxy| {
+                        WlOutputManager {}.new_global(new_proxy)
+                    })
+                    .unwrap();
+            },
+            _ => {}
+        });
+        let mut server = TestServer {
+            globals,
+            outputs: Vec::new(),
+            event_queue,
+            _display: display,
+            stream,
+            requests,
+            frames,
+            thread: Some(thread)
+        };
+        server.roundtrip();
+        server
+            .globals
+            .instantiate_exact(WL_COMPOSITOR_VERSION, |new_proxy| {
+                WlCompositorManager {}.new_global(new_proxy)
+            })
+            .unwrap();
+        server
+            .globals
+            .instantiate_exact(WL_SHM_VERSION, |new_proxy| WlShmManager {}.new_global(new_proxy))
+            .unwrap();
+        if shell.0 == "xdg_wm_base" {
+            server
+                .globals
+                .instantiate_exact(XDG_WM_BASE_VERSION, |new_proxy| {
+                    XdgWmBaseManager {}.new_global(new_proxy)
+                })
+                .unwrap();
+        } else {
+            server
+                .globals
+                .instantiate_range(LAYER_SHELL_VERSION, LAYER_SHELL_MAX_VERSION, |new_proxy| {
+                    LayerShellManager {}.new_global(new_proxy)
+                })
+                .unwrap();
+        }
+        server.roundtrip();
+        server.outputs = server
+            .take_requests()
+            .iter()
+            .filter(|request| request.name == "bind" && request.args[0] as usize > shared.len())
+            .map(|request| request.args[2] as u32)
+            .collect();
+        server
+    }
+
+    /// Sends the requests made so far and dispatches the events the server
+    /// sent back, then sends the requests the events were answered with. The
+    /// server has recorded all of them once this returns.
+    pub fn roundtrip(&mut self) {
+        self.event_queue.sync_roundtrip().unwrap();
+        self.event_queue.sync_roundtrip().unwrap();
+    }
+
+    /// The requests the server got since the last call, without the ones of
+    /// the roundtrips.
+    pub fn take_requests(&self) -> Vec<Request> {
+        let mut requests = self.requests.lock().unwrap();
+        requests
+            .drain(..)
+            .filter(|request| request.interface != "wl_display")
+            .collect()
+    }
+
+    /// The buffers committed since the last call, with the pixels they had
+    /// when the server got the commit.
+    pub fn take_frames(&self) -> Vec<Frame> {
+        self.frames.lock().unwrap().drain(..).collect()
+    }
+
+    /// Sends the event with `opcode` to `object`, with `args` as its
+    /// arguments.
+    pub fn send_event(&self, object: u32, opcode: u16, args: &[u32]) {
+        send(&self.stream, object, opcode, args);
+    }
+
+    /// Sends zwlr_layer_surface_v1.configure to `layer_surface`.
+    pub fn configure(&self, layer_surface: u32, serial: u32, size: Size) {
+        self.send_event(layer_surface, 0, &[serial, size.width, size.height]);
+    }
+
+    /// Sends zwlr_layer_surface_v1.closed to `layer_surface`.
+    pub fn close(&self, layer_surface: u32) {
+        self.send_event(layer_surface, 1, &[]);
+    }
+
+    /// Sends xdg_toplevel.configure with `size` and no states to
+    /// `toplevel`, then the xdg_surface.configure that applies it to
+    /// `xdg_surface`.
+    pub fn configure_toplevel(&self, xdg_surface: u32, toplevel: u32, serial: u32, size: Size) {
+        self.send_event(toplevel, 0, &[size.width, size.height, 0]);
+        self.send_event(xdg_surface, 0, &[serial]);
+    }
+
+    /// Sends xdg_toplevel.close to `toplevel`.
+    pub fn close_toplevel(&self, toplevel: u32) {
+        self.send_event(toplevel, 1, &[]);
+    }
+
+    /// Sends wl_surface.enter to `surface`, which is then on `output`.
+    pub fn enter(&self, surface: u32, output: u32) {
+        self.send_event(surface, 0, &[output]);
+    }
+
+    /// Sends wl_surface.leave to `surface`, which isn't on `output` anymore.
+    pub fn leave(&self, surface: u32, output: u32) {
+        self.send_event(surface, 1, &[output]);
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        layer_shell::unbind();
+        xdg_shell::unbind();
+        output::unbind();
+        wl_shm::unbind();
+        wl_compositor::unbind();
+        self.stream.lock().unwrap().shutdown(Shutdown::Both).ok();
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+/// Written like `wl_surface.attach(7, 0, 0)`, since object ids vary.
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let args: Vec<_> = self.args.iter().map(i64::to_string).collect();
+        write!(f, "{}.{}({})", self.interface, self.name, args.join(", "))
+    }
+}
+
+/// The bytes and file descriptors the client sent, which are read as the
+/// messages need them.
+struct Incoming {
+    stream: UnixStream,
+    bytes: VecDeque<u8>,
+    fds: VecDeque<File>
+}
+
+impl Incoming {
+    /// The next `len` bytes, or `None` once the client is gone.
+    fn read(&mut self, len: usize) -> Option<Vec<u8>> {
+        while self.bytes.len() < len {
+            let mut buffer = [0; 4096];
+            let read = {
+                let iov = [IoVec::from_mut_slice(&mut buffer)];
+                let mut space: CmsgSpace<[RawFd; 28]> = CmsgSpace::new();
+                match recvmsg(self.stream.as_raw_fd(), &iov, Some(&mut space), MsgFlags::empty()) {
+                    Ok(message) => {
+                        for cmsg in message.cmsgs() {
+                            if let ControlMessage::ScmRights(received) = cmsg {
+                                let files = received.iter().map(|fd| unsafe { File::from_raw_fd(*fd) });
+                                self.fds.extend(files);
+                            }
+                        }
+                        message.bytes
+                    },
+                    Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                    Err(_) => 0
+                }
+            };
+            if read == 0 {
+                return None;
+            }
+            self.bytes.extend(&buffer[..read]);
+        }
+        Some(self.bytes.drain(..len).collect())
+    }
+}
+
+/// A wl_buffer made from a shm pool.
+struct ShmBuffer {
+    file: Arc<File>,
+    offset: u32,
+    size: Size,
+    stride: u32
+}
+
+impl ShmBuffer {
+    /// What's in the buffer now.
+    fn pixels(&self) -> Vec<u8> {
+        let mut pixels = vec![0; (self.stride * self.size.height) as usize];
+        self.file
+            .read_exact_at(&mut pixels, u64::from(self.offset))
+            .expect("the buffer is past the end of its pool");
+        pixels
+    }
+}
+
+/// Records the requests that come in on `server` until the client goes,
+/// answering the ones that need it.
+fn serve(
+    server: UnixStream,
+    stream: &Mutex<UnixStream>,
+    requests: &Mutex<Vec<Request>>,
+    frames: &Mutex<Vec<Frame>>,
+    globals: &[(&'static str, u32)],
+    scales: &[i32]
+) {
+    let mut incoming = Incoming {
+        stream: server,
+        bytes: VecDeque::new(),
+        fds: VecDeque::new()
+    };
+    let mut objects = HashMap::new();
+    objects.insert(1, "wl_display");
+    let mut pools = HashMap::new();
+    let mut buffers = HashMap::new();
+    // The buffer attached to each surface since its last commit.
+    let mut attached = HashMap::new();
+    loop {
+        let header = match incoming.read(8) {
+            Some(header) => header,
+            None => return
+        };
+        let object = word(&header[..4]);
+        let size_opcode = word(&header[4..]);
+        let body = match incoming.read((size_opcode >> 16) as usize - header.len()) {
+            Some(body) => body,
+            None => return
+        };
+        let interface = objects.get(&object).cloned().unwrap_or("unknown");
+        let (name, signature, creates) = signatures(interface)
+            .get((size_opcode & 0xffff) as usize)
+            .cloned()
+            .unwrap_or(("unknown", "", None));
+        let mut words = body.chunks(4).map(word);
+        let mut args = Vec::new();
+        let mut strings = Vec::new();
+        let mut new_id = None;
+        let mut fd = None;
+        for kind in signature.chars() {
+            match kind {
+                // File descriptors come out of band, in the order of the
+                // messages.
+                'h' => fd = incoming.fds.pop_front(),
+                's' => {
+                    let len = words.next().unwrap_or(0) as usize;
+                    let bytes: Vec<u8> = words
+                        .by_ref()
+                        .take((len + 3) / 4)
+                        .flat_map(|word| word.to_ne_bytes().to_vec())
+                        .take(len.saturating_sub(1))
+                        .collect();
+                    strings.push(String::from_utf8_lossy(&bytes).into_owned());
+                },
+                'i' => args.push(i64::from(words.next().unwrap_or(0) as i32)),
+                'n' => {
+                    let id = words.next().unwrap_or(0);
+                    new_id = Some(id);
+                    args.push(i64::from(id));
+                },
+                _ => args.push(i64::from(words.next().unwrap_or(0)))
+            }
+        }
+        // Which objects are bound is told by name.
+        let creates = if name == "bind" {
+            strings.first().map(|name| interface_name(name))
+        } else {
+            creates
+        };
+        if let (Some(id), Some(created)) = (new_id, creates) {
+            objects.insert(id, created);
+        }
+        match (interface, name, args.as_slice()) {
+            ("wl_shm", "create_pool", &[pool, _]) => {
+                let file = fd.expect("wl_shm.create_pool without a file descriptor");
+                pools.insert(pool as u32, Arc::new(file));
+            },
+            ("wl_shm_pool", "create_buffer", &[id, offset, width, height, stride, _]) => {
+                let buffer = ShmBuffer {
+                    file: pools[&object].clone(),
+                    offset: offset as u32,
+                    size: Size {
+                        width: width as u32,
+                        height: height as u32
+                    },
+                    stride: stride as u32
+                };
+                buffers.insert(id as u32, buffer);
+            },
+            // The file is kept open by the buffers made from it.
+            ("wl_shm_pool", "destroy", _) => {
+                pools.remove(&object);
+            },
+            ("wl_buffer", "destroy", _) => {
+                buffers.remove(&object);
+            },
+            ("wl_registry", "bind", &[global, _, output]) if creates == Some("wl_output") => {
+                let index = global as usize - globals.len() - 1;
+                send_output(stream, output as u32, index as i32, scales[index]);
+            },
+            ("wl_surface", "attach", &[buffer, _, _]) => {
+                attached.insert(object, buffer as u32);
+            },
+            ("wl_surface", "commit", _) => {
+                if let Some(buffer) = attached.remove(&object).and_then(|buffer| buffers.get(&buffer)) {
+                    frames.lock().unwrap().push(Frame {
+                        surface: object,
+                        size: buffer.size,
+                        stride: buffer.stride,
+                        pixels: buffer.pixels()
+                    });
+                }
+            },
+            _ => {}
+        }
+        requests.lock().unwrap().push(Request {
+            interface,
+            object,
+            name,
+            args
+        });
+        match (interface, name, new_id) {
+            ("wl_display", "sync", Some(callback)) => {
+                send(stream, callback, 0, &[0]);
+                send(stream, 1, 1, &[callback]);
+            },
+            ("wl_display", "get_registry", Some(registry)) => {
+                // The outputs come after the other globals.
+                let outputs = scales.iter().map(|_| ("wl_output", WL_OUTPUT_VERSION));
+                for (index, (name, version)) in globals.iter().cloned().chain(outputs).enumerate() {
+                    let args: Vec<_> = iter::once(index as u32 + 1)
+                        .chain(string(name))
+                        .chain(iter::once(version))
+                        .collect();
+                    send(stream, registry, 0, &args);
+                }
+            },
+            ("wl_registry", "bind", Some(shm)) if creates == Some("wl_shm") => {
+                for format in &[0, 1] {
+                    send(stream, shm, 0, &[*format]);
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
+/// The name, the argument types and the interface of the object it makes,
+/// of the requests of `interface` by opcode.
+fn signatures(interface: &str) -> &'static [(&'static str, &'static str, Option<&'static str>)] {
+    match interface {
+        "wl_display" => &[
+            ("sync", "n", Some("wl_callback")),
+            ("get_registry", "n", Some("wl_registry"))
+        ],
+        "wl_registry" => &[("bind", "usun", None)],
+        "wl_compositor" => &[
+            ("create_surface", "n", Some("wl_surface")),
+            ("create_region", "n", Some("wl_region"))
+        ],
+        "wl_surface" => &[
+            ("destroy", "", None),
+            ("attach", "oii", None),
+            ("damage", "iiii", None),
+            ("frame", "n", Some("wl_callback")),
+            ("set_opaque_region", "o", None),
+            ("set_input_region", "o", None),
+            ("commit", "", None),
+            ("set_buffer_transform", "i", None),
+            ("set_buffer_scale", "i", None),
+            ("damage_buffer", "iiii", None),
+            ("offset", "ii", None)
+        ],
+        "wl_region" => &[
+            ("destroy", "", None),
+            ("add", "iiii", None),
+            ("subtract", "iiii", None)
+        ],
+        "wl_shm" => &[("create_pool", "nhi", Some("wl_shm_pool"))],
+        "wl_shm_pool" => &[
+            ("create_buffer", "niiiiu", Some("wl_buffer")),
+            ("destroy", "", None),
+            ("resize", "i", None)
+        ],
+        "wl_buffer" => &[("destroy", "", None)],
+        "zwlr_layer_shell_v1" => &[
+            ("get_layer_surface", "noous", Some("zwlr_layer_surface_v1")),
+            ("destroy", "", None)
+        ],
+        "xdg_wm_base" => &[
+            ("destroy", "", None),
+            ("create_positioner", "n", Some("xdg_positioner")),
+            ("get_xdg_surface", "no", Some("xdg_surface")),
+            ("pong", "u", None)
+        ],
+        "xdg_surface" => &[
+            ("destroy", "", None),
+            ("get_toplevel", "n", Some("xdg_toplevel")),
+            ("get_popup", "noo", Some("xdg_popup")),
+            ("set_window_geometry", "iiii", None),
+            ("ack_configure", "u", None)
+        ],
+        "xdg_toplevel" => &[
+            ("destroy", "", None),
+            ("set_parent", "o", None),
+            ("set_title", "s", None),
+            ("set_app_id", "s", None)
+        ],
+        "zwlr_layer_surface_v1" => &[
+            ("set_size", "uu", None),
+            ("set_anchor", "u", None),
+            ("set_exclusive_zone", "i", None),
+            ("set_margin", "iiii", None),
+            ("set_keyboard_interactivity", "u", None),
+            ("get_popup", "o", None),
+            ("ack_configure", "u", None),
+            ("destroy", "", None),
+            ("set_layer", "u", None)
+        ],
+        _ => &[]
+    }
+}
+
+/// Sends what the client needs to know of the output at `index` from the
+/// left, whose scale is `scale`.
+fn send_output(stream: &Mutex<UnixStream>, output: u32, index: i32, scale: i32) {
+    let x = index * OUTPUT_SIZE.width as i32;
+    let geometry: Vec<_> = [x as u32, 0, 0, 0, 0]
+        .iter()
+        .cloned()
+        .chain(string("test"))
+        .chain(string(&format!("output {}", index)))
+        .chain(iter::once(0))
+        .collect();
+    send(stream, output, 0, &geometry);
+    // The current and preferred mode.
+    let mode = [
+        0x3,
+        OUTPUT_SIZE.width * scale as u32,
+        OUTPUT_SIZE.height * scale as u32,
+        60_000
+    ];
+    send(stream, output, 1, &mode);
+    send(stream, output, 3, &[scale as u32]);
+    send(stream, output, 2, &[]);
+}
+
+/// The interface called `name`, if the server knows it.
+fn interface_name(name: &str) -> &'static str {
+    [
+        "wl_compositor",
+        "wl_shm",
+        "zwlr_layer_shell_v1",
+        "xdg_wm_base",
+        "wl_output"
+    ]
+    .iter()
+    .find(|interface| **interface == name)
+    .cloned()
+    .unwrap_or("unknown")
+}
+
+/// Sends the event with `opcode` to `object`, unless the client is gone.
+fn send(stream: &Mutex<UnixStream>, object: u32, opcode: u16, args: &[u32]) {
+    let size = 8 + 4 * args.len() as u32;
+    let message: Vec<u8> = [object, size << 16 | u32::from(opcode)]
+        .iter()
+        .chain(args)
+        .flat_map(|word| word.to_ne_bytes().to_vec())
+        .collect();
+    stream.lock().unwrap().write_all(&message).ok();
+}
+
+/// The words of a string argument.
+fn string(string: &str) -> Vec<u32> {
+    let mut bytes = string.as_bytes().to_vec();
+    bytes.push(0);
+    let len = bytes.len() as u32;
+    bytes.resize((bytes.len() + 3) / 4 * 4, 0);
+    iter::once(len).chain(bytes.chunks(4).map(word)).collect()
+}
+
+fn word(bytes: &[u8]) -> u32 {
+    u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
@@ -0,0 +1,129 @@
+//! Wrappers around the zwp_virtual_keyboard_manager_v1 and the virtual
+//! keyboards it creates.
+//!
+//! A virtual keyboard types into whatever has the keyboard focus as if its
+//! keys were pressed on a real keyboard, with a keymap of its own.
+
+use std::{
+    cell::RefCell,
+    fmt,
+    io::{self, Write},
+    os::unix::io::AsRawFd
+};
+
+use wayland_client::{
+    protocol::wl_keyboard::{KeyState, KeymapFormat},
+    GlobalImplementor, NewProxy
+};
+
+pub use self::generated::client::zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1;
+use self::generated::client::zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1;
+use crate::clock;
+use crate::wayland_obj::seat;
+
+/// The minimum version of the zwp_virtual_keyboard_manager_v1 global to
+/// bind to.
+pub const VIRTUAL_KEYBOARD_MANAGER_VERSION: u32 = 1;
+
+/// The code generated from protocols/virtual-keyboard-unstable-v1.xml,
+/// which wayland-protocols doesn't have.
+mod generated {
+    #![allow(dead_code, non_camel_case_types, unused_unsafe, unused_variables)]
+    #![allow(non_upper_case_globals, non_snake_case, unused_imports)]
+    #![allow(clippy::all)]
+
+    pub mod client {
+        pub(crate) use wayland_client::protocol::wl_seat;
+        pub(crate) use wayland_client::sys;
+        pub(crate) use wayland_client::{AnonymousObject, HandledBy, NewProxy, Proxy, ProxyMap};
+        pub(crate) use wayland_commons::map::{Object, ObjectMetadata};
+        pub(crate) use wayland_commons::wire::{Argument, ArgumentType, Message, MessageDesc};
+        pub(crate) use wayland_commons::{Interface, MessageGroup};
+        include!(concat!(
+            env!("OUT_DIR"),
+            "/virtual-keyboard-unstable-v1_client_api.rs"
+        ));
+    }
+}
+
+thread_local! {
+    static VIRTUAL_KEYBOARD_MANAGER: RefCell<Option<ZwpVirtualKeyboardManagerV1>> = RefCell::new(None);
+}
+
+pub struct VirtualKeyboardManager {}
+
+impl GlobalImplementor<ZwpVirtualKeyboardManagerV1> for VirtualKeyboardManager {
+    fn new_global(
+        &mut self,
+        new_proxy: NewProxy<ZwpVirtualKeyboardManagerV1>
+    ) -> ZwpVirtualKeyboardManagerV1 {
+        let res = new_proxy.implement_dummy();
+
+        VIRTUAL_KEYBOARD_MANAGER.with(|manager| {
+            *manager.borrow_mut() = Some(res.clone());
+        });
+
+        res
+    }
+}
+
+/// A virtual keyboard on the seat.
+///
+/// A keymap has to be set before any key is pressed.
+pub struct VirtualKeyboard {
+    proxy: ZwpVirtualKeyboardV1
+}
+
+impl VirtualKeyboard {
+    /// Sets the keymap, in the XKB text format, that the keycodes of `key`
+    /// are in.
+    pub fn set_keymap(&self, keymap: &str) -> io::Result<()> {
+        let mut file = tempfile::tempfile()?;
+        // The keymap is read as a C string.
+        file.write_all(keymap.as_bytes())?;
+        file.write_all(&[0])?;
+        file.flush()?;
+        let size = keymap.len() as u32 + 1;
+        self.proxy
+            .keymap(KeymapFormat::XkbV1.to_raw(), file.as_raw_fd(), size);
+        Ok(())
+    }
+
+    /// Presses or releases the key with the XKB keycode.
+    pub fn key(&self, keycode: u32, pressed: bool) {
+        // Any clock would do, but with the compositor's the keys are typed
+        // when they say they are.
+        let time = clock::timestamp();
+        let state = if pressed {
+            KeyState::Pressed
+        } else {
+            KeyState::Released
+        };
+        // The protocol takes evdev codes, which XKB keycodes are offset from.
+        self.proxy.key(time, keycode - 8, state.to_raw());
+    }
+}
+
+impl Drop for VirtualKeyboard {
+    fn drop(&mut self) {
+        self.proxy.destroy();
+    }
+}
+
+impl fmt::Debug for VirtualKeyboard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.proxy.as_ref().c_ptr())
+    }
+}
+
+/// Creates a virtual keyboard on the seat, if the compositor lets us.
+pub fn create_virtual_keyboard() -> Result<VirtualKeyboard, ()> {
+    let seat = seat::seat().ok_or(())?;
+    VIRTUAL_KEYBOARD_MANAGER.with(|manager| {
+        let manager = manager.borrow();
+        let manager = manager.as_ref().ok_or(())?;
+        manager
+            .create_virtual_keyboard(&seat, NewProxy::implement_dummy)
+            .map(|proxy| VirtualKeyboard { proxy })
+    })
+}
@@ -1,10 +1,18 @@
 //! Wrapper around a wl_compositor.
 
-use std::cell::RefCell;
+use std::{cell::RefCell, rc::Rc};
+
+use crate::area::Area;
+use crate::event_trace::{self, Arg};
 
 use wayland_client::{
-    protocol::{wl_compositor::WlCompositor, wl_surface::WlSurface},
-    GlobalImplementor, NewProxy
+    protocol::{
+        wl_compositor::WlCompositor,
+        wl_output::WlOutput,
+        wl_region::WlRegion,
+        wl_surface::{self, WlSurface}
+    },
+    GlobalImplementor, NewProxy, Proxy
 };
 
 /// The minimum version of the wl_compositor global to bind to.
@@ -16,6 +24,17 @@ thread_local! {
 
 pub struct WlCompositorManager {}
 
+/// The outputs a surface is on, kept as the user data of the surface.
+#[derive(Default)]
+struct SurfaceState {
+    outputs: Vec<WlOutput>,
+    /// Called when the surface entered or left an output.
+    on_outputs_changed: Option<Rc<dyn Fn()>>
+}
+
+// Handle incoming events for WlSurface.
+struct WlSurfaceEventHandler {}
+
 impl GlobalImplementor<WlCompositor> for WlCompositorManager {
     fn new_global(&mut self, new_proxy: NewProxy<WlCompositor>) -> WlCompositor {
         let res = new_proxy.implement_dummy();
@@ -28,10 +47,86 @@ impl GlobalImplementor<WlCompositor> for WlCompositorManager {
     }
 }
 
+impl wl_surface::EventHandler for WlSurfaceEventHandler {
+    fn enter(&mut self, object: WlSurface, output: WlOutput) {
+        event_trace::event(object.as_ref(), "enter", || {
+            vec![Arg::Object(Some(event_trace::object(output.as_ref())))]
+        });
+        let callback = {
+            let mut state = unwrap_state(object.as_ref()).borrow_mut();
+            if !state.outputs.contains(&output) {
+                state.outputs.push(output);
+            }
+            state.on_outputs_changed.clone()
+        };
+        if let Some(callback) = callback {
+            callback();
+        }
+    }
+
+    fn leave(&mut self, object: WlSurface, output: WlOutput) {
+        event_trace::event(object.as_ref(), "leave", || {
+            vec![Arg::Object(Some(event_trace::object(output.as_ref())))]
+        });
+        let callback = {
+            let mut state = unwrap_state(object.as_ref()).borrow_mut();
+            state.outputs.retain(|entered| *entered != output);
+            state.on_outputs_changed.clone()
+        };
+        if let Some(callback) = callback {
+            callback();
+        }
+    }
+}
+
+/// Forgets the wl_compositor global, when the connection of a test is gone.
+#[cfg(test)]
+pub(super) fn unbind() {
+    WL_COMPOSITOR.with(|wl_compositor| *wl_compositor.borrow_mut() = None);
+}
+
+/// Creates a surface, which keeps track of the outputs it's on.
 pub fn create_surface() -> Result<WlSurface, ()> {
     WL_COMPOSITOR.with(|wl_compositor| {
         let wl_compositor = wl_compositor.borrow();
         let wl_compositor = wl_compositor.as_ref().expect("WL_COMPOSITOR was not initilized");
-        wl_compositor.create_surface(NewProxy::implement_dummy)
+        let surface = wl_compositor.create_surface(|new_proxy| {
+            new_proxy.implement(WlSurfaceEventHandler {}, RefCell::new(SurfaceState::default()))
+        })?;
+        event_trace::request(wl_compositor.as_ref(), "create_surface", || {
+            vec![Arg::NewId(event_trace::object(surface.as_ref()))]
+        });
+        Ok(surface)
     })
 }
+
+/// The outputs some part of the surface is on.
+pub fn surface_outputs(surface: &WlSurface) -> Vec<WlOutput> {
+    unwrap_state(surface.as_ref()).borrow().outputs.clone()
+}
+
+/// Sets the function called when the surface entered or left an output.
+pub fn on_surface_outputs_changed(surface: &WlSurface, callback: Rc<dyn Fn()>) {
+    unwrap_state(surface.as_ref()).borrow_mut().on_outputs_changed = Some(callback);
+}
+
+/// Creates a region of `rects`, which can be destroyed once it's set on a
+/// surface.
+pub fn create_region(rects: &[Area]) -> Result<WlRegion, ()> {
+    let region = WL_COMPOSITOR.with(|wl_compositor| {
+        let wl_compositor = wl_compositor.borrow();
+        let wl_compositor = wl_compositor.as_ref().expect("WL_COMPOSITOR was not initilized");
+        wl_compositor.create_region(NewProxy::implement_dummy)
+    })?;
+    for rect in rects {
+        let Area { origin, size } = *rect;
+        region.add(origin.x, origin.y, size.width as i32, size.height as i32);
+    }
+    Ok(region)
+}
+
+fn unwrap_state(proxy: &Proxy<WlSurface>) -> &RefCell<SurfaceState> {
+    proxy
+        .user_data::<RefCell<SurfaceState>>()
+        .expect("User data has not been set yet")
+}
@@ -1,30 +1,75 @@
 //! Wrapper around a wl_shm.
 
-use std::{cell::RefCell, os::unix::io::RawFd};
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+    fs::File,
+    io::{self, Seek, SeekFrom, Write},
+    os::unix::io::{AsRawFd, RawFd},
+    rc::Rc
+};
 
 use wayland_client::{
     self,
     protocol::{
-        wl_buffer::WlBuffer,
-        wl_shm::{self, WlShm}
+        wl_buffer::{self, WlBuffer},
+        wl_shm::{self, WlShm},
+        wl_shm_pool::WlShmPool
     },
     NewProxy
 };
 
-use crate::area::Size;
+use crate::area::{Area, Origin, Size};
+use crate::event_trace::{self, Arg};
 
 /// The minimum version of the wl_shm global to bind to.
 pub const WL_SHM_VERSION: u32 = 1;
 
 thread_local! {
     static WL_SHM: RefCell<Option<WlShm>> = RefCell::new(None);
+    /// The formats the compositor advertised besides the ones every
+    /// compositor supports.
+    static FORMATS: RefCell<Vec<wl_shm::Format>> = RefCell::new(Vec::new());
+    /// Called with the id of an imported buffer the compositor released.
+    static RELEASE_HANDLER: RefCell<Option<Rc<dyn Fn(usize)>>> = RefCell::new(None);
 }
 
 pub struct WlShmManager {}
 
+/// Why a shared memory buffer couldn't be created or resized.
+#[derive(Debug)]
+pub enum BufferError {
+    /// A pool can't be as large as the buffer would be.
+    TooLarge(Size),
+    /// The file of the buffer couldn't be created or grown, e.g. because
+    /// `XDG_RUNTIME_DIR` is full.
+    File(io::Error),
+    /// The file of the buffer couldn't be mapped into the client.
+    Map(io::Error),
+    /// There's no wl_shm to ask for the buffer, e.g. in the tests.
+    NoShm,
+    /// The request for the pool or the buffer couldn't be sent, because
+    /// the connection is gone.
+    Request
+}
+
+impl fmt::Display for BufferError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BufferError::TooLarge(Size { width, height }) => {
+                write!(f, "a {}x{} buffer is too large for a pool", width, height)
+            },
+            BufferError::File(err) => write!(f, "could not create its file: {}", err),
+            BufferError::Map(err) => write!(f, "could not map its file: {}", err),
+            BufferError::NoShm => write!(f, "the compositor has no wl_shm"),
+            BufferError::Request => write!(f, "the compositor could not be asked for it")
+        }
+    }
+}
+
 impl wayland_client::GlobalImplementor<WlShm> for WlShmManager {
     fn new_global(&mut self, new_proxy: NewProxy<WlShm>) -> WlShm {
-        let res = new_proxy.implement_dummy();
+        let res = new_proxy.implement(WlShmEventHandler {}, ());
 
         WL_SHM.with(|wl_shm| {
             *wl_shm.borrow_mut() = Some(res.clone());
@@ -34,26 +79,716 @@ impl wayland_client::GlobalImplementor<WlShm> for WlShmManager {
     }
 }
 
-/// Create a buffer from the raw file descriptor in the given size.
+struct WlShmEventHandler {}
+
+impl wl_shm::EventHandler for WlShmEventHandler {
+    fn format(&mut self, object: WlShm, format: wl_shm::Format) {
+        event_trace::event(object.as_ref(), "format", || {
+            vec![Arg::Uint(format.to_raw().into())]
+        });
+        FORMATS.with(|formats| {
+            let mut formats = formats.borrow_mut();
+            if !formats.contains(&format) {
+                formats.push(format)
+            }
+        });
+    }
+}
+
+/// The wl_shm global, if the compositor advertised it.
+pub fn shm() -> Option<WlShm> {
+    WL_SHM.with(|wl_shm| wl_shm.borrow().clone())
+}
+
+/// Forgets the wl_shm global and its formats, when the connection of a test
+/// is gone.
+#[cfg(test)]
+pub(super) fn unbind() {
+    WL_SHM.with(|wl_shm| *wl_shm.borrow_mut() = None);
+    FORMATS.with(|formats| formats.borrow_mut().clear());
+}
+
+/// Whether buffers can be created in `format`. ARGB8888 and XRGB8888 are
+/// supported by every compositor, the others if it advertised them.
+pub fn supports_format(format: wl_shm::Format) -> bool {
+    match format {
+        wl_shm::Format::Argb8888 | wl_shm::Format::Xrgb8888 => true,
+        format => FORMATS.with(|formats| formats.borrow().contains(&format))
+    }
+}
+
+// Handle incoming events for imported buffers, whose user data is their id.
+struct ImportedBufferEventHandler {}
+
+impl wl_buffer::EventHandler for ImportedBufferEventHandler {
+    fn release(&mut self, object: WlBuffer) {
+        event_trace::event(object.as_ref(), "release", Vec::new);
+        let id = *object
+            .as_ref()
+            .user_data::<usize>()
+            .expect("User data has not been set yet");
+        // The handler is cloned so it can set a new handler while it runs.
+        if let Some(handler) = RELEASE_HANDLER.with(|handler| handler.borrow().clone()) {
+            handler(id)
+        }
+    }
+}
+
+// Handle incoming events for mapped buffers.
+struct MappedBufferEventHandler {
+    busy: Rc<Cell<bool>>
+}
+
+impl wl_buffer::EventHandler for MappedBufferEventHandler {
+    fn release(&mut self, object: WlBuffer) {
+        event_trace::event(object.as_ref(), "release", Vec::new);
+        self.busy.set(false);
+    }
+}
+
+/// A wl_buffer backed by a shared memory file.
+///
+/// The buffer is in the ARGB8888 or XRGB8888 format, which are the same
+/// layout as Cairo ARGB32 and RGB24 image surfaces on little endian
+/// machines.
+///
+/// The file and its pool are kept for the life of the buffer, so resizing
+/// it only makes a new wl_buffer over them, growing them if they're too
+/// small.
+pub struct Buffer {
+    temp_file: File,
+    pool: WlShmPool,
+    buffer: WlBuffer,
+    size: Size,
+    format: wl_shm::Format,
+    /// The length of the file and the pool, in bytes.
+    capacity: usize
+}
+
+impl Buffer {
+    /// The wl_buffer to attach to a surface.
+    pub fn wl_buffer(&self) -> &WlBuffer {
+        &self.buffer
+    }
+
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    pub fn format(&self) -> wl_shm::Format {
+        self.format
+    }
+
+    /// The memory the file of the buffer takes, which can be more than its
+    /// size needs once it shrank.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Whether `resize` keeps the file for `size`, rather than the buffer
+    /// being allocated again so it doesn't keep memory it doesn't use.
+    pub fn can_resize(&self, size: Size) -> bool {
+        keeps(self.capacity, size)
+    }
+
+    /// Makes the buffer `size`, in the same file, returning how many bytes
+    /// the file grew by. What was written is left as it is, in the layout of
+    /// the old size.
+    pub fn resize(&mut self, size: Size) -> Result<usize, BufferError> {
+        let (width, height, stride, len) = layout(size)?;
+        let grown = len.saturating_sub(self.capacity);
+        if grown > 0 {
+            self.temp_file.set_len(len as u64).map_err(BufferError::File)?;
+            event_trace::request(self.pool.as_ref(), "resize", || vec![Arg::Int(len as i64)]);
+            self.pool.resize(len as i32);
+            self.capacity = len;
+        }
+        let buffer = self
+            .pool
+            .create_buffer(0, width, height, stride, self.format, NewProxy::implement_dummy)
+            .map_err(|_| BufferError::Request)?;
+        record_buffer(&self.pool, &buffer, 0, size, stride);
+        self.buffer.destroy();
+        self.buffer = buffer;
+        self.size = size;
+        Ok(grown)
+    }
+
+    /// Copies `data`, which has rows `stride` bytes long, into the buffer.
+    ///
+    /// The `offset` shifts the content within the buffer: the pixel at
+    /// (`offset.x`, `offset.y`) in `data` ends up in the top left corner.
+    /// Any part of the buffer not covered by `data` is cleared.
+    pub fn write(&mut self, data: &[u8], stride: usize, offset: Origin) -> io::Result<()> {
+        write_pixels(&mut self.temp_file, self.size, data, stride, offset)?;
+        self.temp_file.flush()
+    }
+
+    /// Copies the parts of `data` at `rects` into the buffer, leaving the
+    /// rest as it is. `data` and `offset` are as in `write`, and `rects`
+    /// are in the coordinates of the buffer.
+    pub fn write_rects(
+        &mut self,
+        data: &[u8],
+        stride: usize,
+        offset: Origin,
+        rects: &[Area]
+    ) -> io::Result<()> {
+        write_pixel_rects(&mut self.temp_file, self.size, data, stride, offset, rects)?;
+        self.temp_file.flush()
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        self.buffer.destroy();
+        event_trace::request(self.pool.as_ref(), "destroy", Vec::new);
+        self.pool.destroy();
+    }
+}
+
+impl fmt::Debug for Buffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Buffer {{ size: {:?}, capacity: {} }}",
+            self.size, self.capacity
+        )
+    }
+}
+
+/// A wl_buffer over shared memory another program draws into, which tells
+/// `on_buffer_release` when the compositor is done reading it.
+pub struct ImportedBuffer {
+    buffer: WlBuffer,
+    size: Size
+}
+
+impl ImportedBuffer {
+    /// The wl_buffer to attach to a surface.
+    pub fn wl_buffer(&self) -> &WlBuffer {
+        &self.buffer
+    }
+
+    pub fn size(&self) -> Size {
+        self.size
+    }
+}
+
+impl Drop for ImportedBuffer {
+    fn drop(&mut self) {
+        self.buffer.destroy();
+    }
+}
+
+impl fmt::Debug for ImportedBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ImportedBuffer {{ size: {:?} }}", self.size)
+    }
+}
+
+/// Memory mapped from a file, shared with whatever else maps it. It's
+/// unmapped once the last reference to it is dropped.
+pub struct Mapping {
+    ptr: *mut u8,
+    len: usize
+}
+
+impl Mapping {
+    /// Maps the first `len` bytes of `file`, which has to be at least that
+    /// long, to read and write them.
+    pub fn new(file: &File, len: usize) -> io::Result<Mapping> {
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping {
+            ptr: ptr as *mut u8,
+            len
+        })
+    }
+
+    pub fn as_mut_ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+impl fmt::Debug for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Mapping {{ len: {} }}", self.len)
+    }
+}
+
+/// A shared memory buffer whose memory is mapped into the client, so its
+/// content can be painted right where the compositor reads it rather than
+/// copied there.
+///
+/// The compositor may read the buffer from when it's committed until it
+/// releases it, and nothing should be painted in between. The mapping
+/// outlives the buffer for as long as something holds on to it.
+pub struct MappedBuffer {
+    pool: WlShmPool,
+    buffer: WlBuffer,
+    mapping: Rc<Mapping>,
+    size: Size,
+    format: wl_shm::Format,
+    /// Set from attaching the buffer until the compositor released it.
+    busy: Rc<Cell<bool>>
+}
+
+// Only used from the thread of the event loop, like the `DmabufBuffer`.
+unsafe impl Send for MappedBuffer {}
+
+impl MappedBuffer {
+    /// The wl_buffer to attach to a surface.
+    pub fn wl_buffer(&self) -> &WlBuffer {
+        &self.buffer
+    }
+
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    /// The length of the rows of pixels, in bytes.
+    pub fn stride(&self) -> usize {
+        self.size.width as usize * 4
+    }
+
+    /// The memory of the buffer, which is unmapped once every clone is
+    /// dropped.
+    pub fn mapping(&self) -> Rc<Mapping> {
+        self.mapping.clone()
+    }
+
+    /// Records that the buffer was attached, so the compositor may read it
+    /// until it releases it.
+    pub fn attached(&self) {
+        self.busy.set(true);
+    }
+
+    /// Whether the compositor released the buffer since it was last
+    /// attached, or it never was.
+    pub fn is_released(&self) -> bool {
+        !self.busy.get()
+    }
+}
+
+impl Drop for MappedBuffer {
+    fn drop(&mut self) {
+        self.buffer.destroy();
+        event_trace::request(self.pool.as_ref(), "destroy", Vec::new);
+        self.pool.destroy();
+    }
+}
+
+impl fmt::Debug for MappedBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "MappedBuffer {{ size: {:?}, format: {:?}, busy: {} }}",
+            self.size,
+            self.format,
+            self.busy.get()
+        )
+    }
+}
+
+/// Copies `data` into `file`, which holds a buffer of `size`, as
+/// `Buffer::write` does.
+///
+/// The rows of the buffer are `size.width * 4` bytes long, whatever the
+/// stride of `data` is, so each row is copied on its own.
+fn write_pixels<F: Write + Seek>(
+    file: &mut F,
+    size: Size,
+    data: &[u8],
+    stride: usize,
+    offset: Origin
+) -> io::Result<()> {
+    let Size { width, height } = size;
+    let row_len = width as usize * 4;
+    let src_height = if stride == 0 { 0 } else { data.len() / stride } as i64;
+    let src_width = (stride / 4) as i64;
+    let mut row = vec![0u8; row_len];
+    file.seek(SeekFrom::Start(0))?;
+    for y in 0..height as i64 {
+        for byte in row.iter_mut() {
+            *byte = 0;
+        }
+        let src_y = y + offset.y as i64;
+        if src_y >= 0 && src_y < src_height {
+            // Clip the visible columns to the source image.
+            let start = (offset.x as i64).max(0).min(src_width);
+            let end = (offset.x as i64 + width as i64).max(0).min(src_width);
+            if start < end {
+                let src = src_y as usize * stride;
+                let dest = ((start - offset.x as i64) * 4) as usize;
+                let len = ((end - start) * 4) as usize;
+                row[dest..dest + len]
+                    .copy_from_slice(&data[src + start as usize * 4..src + end as usize * 4]);
+            }
+        }
+        file.write_all(&row)?;
+    }
+    Ok(())
+}
+
+/// Copies the parts of `data` at `rects` into `file`, which holds a buffer
+/// of `size`, as `Buffer::write_rects` does.
+fn write_pixel_rects<F: Write + Seek>(
+    file: &mut F,
+    size: Size,
+    data: &[u8],
+    stride: usize,
+    offset: Origin,
+    rects: &[Area]
+) -> io::Result<()> {
+    let bounds: Area = size.into();
+    let src_height = if stride == 0 { 0 } else { data.len() / stride } as i64;
+    let src_width = (stride / 4) as i64;
+    for rect in rects.iter().filter_map(|rect| rect.intersection(bounds)) {
+        for y in rect.origin.y as i64..rect.origin.y as i64 + rect.size.height as i64 {
+            let src_y = y + offset.y as i64;
+            let start = (rect.origin.x as i64 + offset.x as i64).max(0).min(src_width);
+            let end = (rect.origin.x as i64 + offset.x as i64 + rect.size.width as i64)
+                .max(0)
+                .min(src_width);
+            if src_y < 0 || src_y >= src_height || start >= end {
+                continue;
+            }
+            let dest = (y * size.width as i64 + start - offset.x as i64) * 4;
+            let src = src_y as usize * stride;
+            file.seek(SeekFrom::Start(dest as u64))?;
+            file.write_all(&data[src + start as usize * 4..src + end as usize * 4])?;
+        }
+    }
+    Ok(())
+}
+
+/// Create a new shared memory buffer in the given size and format, which
+/// has 4 bytes a pixel.
 ///
 /// This should be called from a shell and generally should not be used
 /// directly by the Awesome objects.
-pub fn create_buffer(fd: RawFd, size: Size) -> Result<WlBuffer, ()> {
-    let Size { width, height } = size;
-    let width = width as i32;
-    let height = height as i32;
+pub fn create_buffer(size: Size, format: wl_shm::Format) -> Result<Buffer, BufferError> {
+    let (width, height, stride, len) = layout(size)?;
+    let temp_file = tempfile::tempfile().map_err(BufferError::File)?;
+    temp_file.set_len(len as u64).map_err(BufferError::File)?;
+    WL_SHM.with(|wl_shm| {
+        let wl_shm = wl_shm.borrow();
+        let wl_shm = wl_shm.as_ref().ok_or(BufferError::NoShm)?;
+        let pool = wl_shm
+            .create_pool(temp_file.as_raw_fd(), len as i32, NewProxy::implement_dummy)
+            .map_err(|_| BufferError::Request)?;
+        record_pool(wl_shm, &pool, len as i32);
+        let buffer = match pool.create_buffer(0, width, height, stride, format, NewProxy::implement_dummy) {
+            Ok(buffer) => buffer,
+            Err(_) => {
+                pool.destroy();
+                return Err(BufferError::Request);
+            }
+        };
+        record_buffer(&pool, &buffer, 0, size, stride);
+        Ok(Buffer {
+            temp_file,
+            pool,
+            buffer,
+            size,
+            format,
+            capacity: len
+        })
+    })
+}
+
+/// Creates a shared memory buffer in the given size and format, which has
+/// 4 bytes a pixel, with its memory mapped into the client.
+///
+/// The file is closed once it's mapped, the pool and the mapping keep the
+/// memory.
+pub fn create_mapped_buffer(size: Size, format: wl_shm::Format) -> Result<MappedBuffer, BufferError> {
+    let (width, height, stride, len) = layout(size)?;
+    let temp_file = tempfile::tempfile().map_err(BufferError::File)?;
+    temp_file.set_len(len as u64).map_err(BufferError::File)?;
+    let mapping = Mapping::new(&temp_file, len).map_err(BufferError::Map)?;
     WL_SHM.with(|wl_shm| {
         let wl_shm = wl_shm.borrow();
-        let wl_shm = wl_shm.as_ref().expect("WL_SHM was not initilized");
-        let pool = wl_shm.create_pool(fd, width * height * 4, NewProxy::implement_dummy)?;
-        // TODO ARb32 instead
-        pool.create_buffer(
-            0,
-            width,
+        let wl_shm = wl_shm.as_ref().ok_or(BufferError::NoShm)?;
+        let pool = wl_shm
+            .create_pool(temp_file.as_raw_fd(), len as i32, NewProxy::implement_dummy)
+            .map_err(|_| BufferError::Request)?;
+        record_pool(wl_shm, &pool, len as i32);
+        let busy = Rc::new(Cell::new(false));
+        let handler = MappedBufferEventHandler { busy: busy.clone() };
+        let buffer = match pool.create_buffer(0, width, height, stride, format, |new_proxy| {
+            new_proxy.implement(handler, ())
+        }) {
+            Ok(buffer) => buffer,
+            Err(_) => {
+                pool.destroy();
+                return Err(BufferError::Request);
+            }
+        };
+        record_buffer(&pool, &buffer, 0, size, stride);
+        Ok(MappedBuffer {
+            pool,
+            buffer,
+            mapping: Rc::new(mapping),
+            size,
+            format,
+            busy
+        })
+    })
+}
+
+/// The width, height, stride and length in bytes of a buffer of `size`,
+/// which a pool can only have if they fit in an `i32`.
+fn layout(size: Size) -> Result<(i32, i32, i32, usize), BufferError> {
+    let width = size.width as i32;
+    let height = size.height as i32;
+    if width < 0 || height < 0 {
+        return Err(BufferError::TooLarge(size));
+    }
+    let stride = width.checked_mul(4).ok_or(BufferError::TooLarge(size))?;
+    let len = stride.checked_mul(height).ok_or(BufferError::TooLarge(size))?;
+    Ok((width, height, stride, len as usize))
+}
+
+/// Whether a file of `capacity` bytes is kept for a buffer of `size`. It
+/// grows as needed, but isn't kept when most of it would go unused.
+fn keeps(capacity: usize, size: Size) -> bool {
+    let len = u64::from(size.width) * u64::from(size.height) * 4;
+    len >= capacity as u64 / 4
+}
+
+/// Creates a buffer over the ARGB8888 pixels another program put in `fd`,
+/// with rows `stride` bytes long starting `offset` bytes in.
+///
+/// The file stays open, it belongs to the caller. `id` is passed to the
+/// handler of `on_buffer_release`.
+pub fn import_buffer(
+    fd: RawFd,
+    offset: i32,
+    stride: i32,
+    size: Size,
+    id: usize
+) -> Result<ImportedBuffer, ()> {
+    let height = size.height as i32;
+    let pool_size = stride
+        .checked_mul(height)
+        .and_then(|len| len.checked_add(offset))
+        .ok_or(())?;
+    let buffer = WL_SHM.with(|wl_shm| {
+        let wl_shm = wl_shm.borrow();
+        // Programs can ask before there's a compositor, e.g. in the tests.
+        let wl_shm = wl_shm.as_ref().ok_or(())?;
+        let pool = wl_shm.create_pool(fd, pool_size, NewProxy::implement_dummy)?;
+        let buffer = pool.create_buffer(
+            offset,
+            size.width as i32,
             height,
-            width * 4,
+            stride,
             wl_shm::Format::Argb8888,
-            NewProxy::implement_dummy
-        )
-    })
+            |new_proxy| new_proxy.implement(ImportedBufferEventHandler {}, id)
+        );
+        record_pool(wl_shm, &pool, pool_size);
+        if let Ok(buffer) = buffer.as_ref() {
+            record_buffer(&pool, buffer, offset, size, stride);
+        }
+        // The buffer keeps the memory mapped, the pool is only used once.
+        event_trace::request(pool.as_ref(), "destroy", Vec::new);
+        pool.destroy();
+        buffer
+    })?;
+    Ok(ImportedBuffer { buffer, size })
+}
+
+/// Records the request that made a pool. Its file isn't part of the trace,
+/// just its size.
+fn record_pool(wl_shm: &WlShm, pool: &WlShmPool, pool_size: i32) {
+    event_trace::request(wl_shm.as_ref(), "create_pool", || {
+        vec![
+            Arg::NewId(event_trace::object(pool.as_ref())),
+            Arg::Int(pool_size.into()),
+        ]
+    });
+}
+
+/// Records the request that made a buffer in `pool`.
+fn record_buffer(pool: &WlShmPool, buffer: &WlBuffer, offset: i32, size: Size, stride: i32) {
+    event_trace::request(pool.as_ref(), "create_buffer", || {
+        vec![
+            Arg::NewId(event_trace::object(buffer.as_ref())),
+            Arg::Int(offset.into()),
+            Arg::Int(size.width.into()),
+            Arg::Int(size.height.into()),
+            Arg::Int(stride.into()),
+        ]
+    });
+}
+
+/// Sets the function called with the id of an imported buffer when the
+/// compositor is done reading it.
+pub fn on_buffer_release(handler: Rc<dyn Fn(usize)>) {
+    RELEASE_HANDLER.with(|release_handler| *release_handler.borrow_mut() = Some(handler));
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use cairo::{Format, ImageSurface};
+
+    use super::*;
+
+    /// A cairo surface `width` wide with every pixel a different color,
+    /// and its pixels.
+    fn gradient(width: i32, height: i32) -> (usize, Vec<u8>) {
+        let mut surface = ImageSurface::create(Format::ARgb32, width, height).unwrap();
+        let stride = surface.get_stride() as usize;
+        {
+            let mut data = surface.get_data().unwrap();
+            for (i, pixel) in data.chunks_mut(4).enumerate() {
+                let (x, y) = ((i % (stride / 4)) as u32, (i / (stride / 4)) as u32);
+                pixel.copy_from_slice(&[x as u8, (x >> 8) as u8, y as u8, 0xff]);
+            }
+        }
+        let data = surface.get_data().unwrap().to_vec();
+        (stride, data)
+    }
+
+    /// The pixel at (`x`, `y`) of a buffer `width` wide.
+    fn pixel(buffer: &[u8], width: u32, x: u32, y: u32) -> &[u8] {
+        let at = ((y * width + x) * 4) as usize;
+        &buffer[at..at + 4]
+    }
+
+    #[test]
+    fn buffer_rows_of_odd_widths() {
+        for &width in &[131, 257, 1, 3] {
+            let (stride, data) = gradient(width, 5);
+            let size = Size {
+                width: width as u32,
+                height: 5
+            };
+            let mut file = Cursor::new(Vec::new());
+            write_pixels(&mut file, size, &data, stride, Origin::default()).unwrap();
+            let buffer = file.into_inner();
+            // As long as the buffer, not sheared or overrun.
+            assert_eq!(buffer.len(), width as usize * 5 * 4);
+            for y in 0..5 {
+                for &x in &[0, width as u32 / 2, width as u32 - 1] {
+                    assert_eq!(
+                        pixel(&buffer, size.width, x, y),
+                        &[x as u8, (x >> 8) as u8, y as u8, 0xff]
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn buffer_rows_of_padded_strides() {
+        // Rows padded past the width, as other formats have.
+        let (width, height, stride) = (131u32, 4u32, 131 * 4 + 12);
+        let mut data = vec![0xee; stride * height as usize];
+        for y in 0..height {
+            for x in 0..width {
+                let at = y as usize * stride + x as usize * 4;
+                data[at..at + 4].copy_from_slice(&[x as u8, y as u8, 1, 0xff]);
+            }
+        }
+        let size = Size { width, height };
+        let mut file = Cursor::new(Vec::new());
+        write_pixels(&mut file, size, &data, stride, Origin::default()).unwrap();
+        let mut buffer = file.into_inner();
+        assert_eq!(buffer.len(), (width * height * 4) as usize);
+        assert_eq!(pixel(&buffer, width, 130, 3), &[130, 3, 1, 0xff]);
+        // Shifted by the offset, and cleared past the content.
+        let mut file = Cursor::new(Vec::new());
+        write_pixels(&mut file, size, &data, stride, Origin { x: 0, y: 1 }).unwrap();
+        let shifted = file.into_inner();
+        assert_eq!(pixel(&shifted, width, 0, 0), &[0, 1, 1, 0xff]);
+        assert_eq!(pixel(&shifted, width, 130, 2), &[130, 3, 1, 0xff]);
+        assert_eq!(pixel(&shifted, width, 0, 3), &[0, 0, 0, 0]);
+        // Only the damage is copied, at the same place in every row.
+        for byte in buffer.iter_mut() {
+            *byte = 0;
+        }
+        let damage = Area {
+            origin: Origin { x: 127, y: 1 },
+            size: Size { width: 10, height: 2 }
+        };
+        let mut file = Cursor::new(buffer);
+        write_pixel_rects(&mut file, size, &data, stride, Origin::default(), &[damage]).unwrap();
+        let buffer = file.into_inner();
+        assert_eq!(buffer.len(), (width * height * 4) as usize);
+        assert_eq!(pixel(&buffer, width, 127, 1), &[127, 1, 1, 0xff]);
+        assert_eq!(pixel(&buffer, width, 130, 2), &[130, 2, 1, 0xff]);
+        assert_eq!(pixel(&buffer, width, 126, 1), &[0, 0, 0, 0]);
+        assert_eq!(pixel(&buffer, width, 130, 3), &[0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn shm_formats() {
+        assert!(supports_format(wl_shm::Format::Argb8888));
+        assert!(supports_format(wl_shm::Format::Xrgb8888));
+        assert!(!supports_format(wl_shm::Format::Rgb565));
+        FORMATS.with(|formats| formats.borrow_mut().push(wl_shm::Format::Rgb565));
+        assert!(supports_format(wl_shm::Format::Rgb565));
+        FORMATS.with(|formats| formats.borrow_mut().clear());
+    }
+
+    #[test]
+    fn buffer_file_kept() {
+        let size = |width, height| Size { width, height };
+        let capacity = 3840 * 30 * 4;
+        // A clock that changes width keeps its file.
+        assert!(keeps(capacity, size(3840, 30)));
+        assert!(keeps(capacity, size(3000, 30)));
+        assert!(keeps(capacity, size(1000, 30)));
+        // Growing is done in place.
+        assert!(keeps(capacity, size(7680, 60)));
+        // Most of it would go unused.
+        assert!(!keeps(capacity, size(900, 30)));
+        assert!(!keeps(capacity, size(0, 0)));
+        assert!(layout(size(100, 20)).is_ok());
+        assert!(layout(size(1 << 15, 1 << 15)).is_err());
+        assert!(layout(size(u32::max_value(), 1)).is_err());
+    }
+
+    #[test]
+    fn buffer_errors() {
+        let size = Size {
+            width: 1 << 15,
+            height: 1 << 15
+        };
+        let err = create_buffer(size, wl_shm::Format::Argb8888).unwrap_err();
+        assert_eq!(err.to_string(), "a 32768x32768 buffer is too large for a pool");
+        // There's no compositor in the tests.
+        let size = Size {
+            width: 10,
+            height: 10
+        };
+        match create_buffer(size, wl_shm::Format::Argb8888) {
+            Err(BufferError::NoShm) => {},
+            other => panic!("expected no wl_shm, got {:?}", other.map(|_| ()))
+        }
+    }
 }
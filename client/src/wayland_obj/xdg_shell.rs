@@ -1,201 +1,176 @@
-//! Wrappers around a xdg_surface and xdg_shell setup code.
-
-use std::{cell::RefCell, fmt, os::unix::io::AsRawFd};
-
-use wayland_client::{
-    protocol::{wl_buffer::WlBuffer, wl_surface::WlSurface},
-    NewProxy, Proxy
+//! Wrappers around an xdg_toplevel and the xdg shell setup code.
+//!
+//! Drawins are shown with xdg toplevels when the compositor doesn't have
+//! the layer shell, see `LayerSurface`. The compositor places them like any
+//! other window then, so they can't be positioned or stacked.
+
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc
 };
+
+use wayland_client::{protocol::wl_surface::WlSurface, GlobalImplementor, NewProxy, Proxy};
 use wayland_protocols::xdg_shell::client::{
     xdg_surface::{self, XdgSurface},
     xdg_toplevel,
     xdg_wm_base::{self, XdgWmBase}
 };
 
-use crate::area::{Area, Origin, Size};
-use crate::wayland_obj;
+use crate::area::Size;
+use crate::event_trace::{self, Arg};
 
 /// The minimum version of the xdg_wm_base global to bind to.
-pub const XDG_WM_BASE_VERSION: u32 = 2;
+pub const XDG_WM_BASE_VERSION: u32 = 1;
+
+/// The app id given to every toplevel we create.
+const APP_ID: &str = "way-cooler";
 
 thread_local! {
-    /// The XDG surface creator.
+    /// The xdg surface creator.
     ///
     /// This should remain local to just this module.
-    static XDG_SHELL_CREATOR: RefCell<Option<XdgWmBase>> = RefCell::new(None);
+    static XDG_WM_BASE: RefCell<Option<XdgWmBase>> = RefCell::new(None);
 }
 
-/// A wrapper around `xdg_toplevel::Xdgtoplevel` that keeps track of the changes to the
-/// internal state of the `xdg_toplevel`, `xdg_surface, and `wl_surface`.
-///
-/// This should be used instead of using a xdg_toplevel directly as
-/// otherwise you need to roll your own caching scheme.
-#[derive(Clone, Eq, PartialEq)]
-pub struct XdgToplevel {
-    proxy: xdg_toplevel::XdgToplevel
-}
+/// Provides the new xdg_wm_base with an implementation.
+pub struct XdgWmBaseManager {}
 
-/// The cached state for the 'XdgToplevel. Cached state about the `xdg_surface`
-/// and the `wl_surface` is also stored in here.
+/// An xdg_toplevel and the xdg_surface it was made from.
 ///
-/// This needs to be stored as the user data in the `XdgToplevel` so that it
-/// can be accessed anywhere.
+/// Neither is destroyed when it's dropped, the owner destroys them with
+/// `destroy` before the wl_surface.
 #[derive(Eq, PartialEq)]
-struct XdgToplevelState {
-    // TODO These should have wrappers around the proxies as well,
-    // so that their cached state is transparent to this cached state.
-    wl_surface: WlSurface,
+pub struct XdgToplevel {
     xdg_surface: XdgSurface,
-    buffer: Option<WlBuffer>,
-    size: Size
+    toplevel: xdg_toplevel::XdgToplevel
 }
 
-struct XdgToplevelHandler {}
-
-impl XdgToplevelHandler {}
+struct XdgWmBaseEventHandler {}
 
-impl xdg_toplevel::EventHandler for XdgToplevelHandler {
-    #[allow(unused)]
-    fn configure(&mut self, object: xdg_toplevel::XdgToplevel, width: i32, height: i32, states: Vec<u8>) {
-        // TODO Fill in
-        let state = unwrap_state(object.as_ref()).borrow_mut();
-        state
-            .xdg_surface
-            .set_window_geometry(0, 0, state.size.width as i32, state.size.height as i32);
-    }
-
-    fn close(&mut self, _object: xdg_toplevel::XdgToplevel) {
-        // TODO We should probably do what the compositor wants here.
-    }
+/// Acks the configures of the xdg_surface and tells the owner the size the
+/// toplevel was configured with.
+struct XdgSurfaceEventHandler {
+    /// The size of the last xdg_toplevel.configure, 0 where the client
+    /// chooses it.
+    size: Rc<Cell<Size>>,
+    on_configure: Rc<dyn Fn(Size)>
 }
 
-impl XdgToplevel {
-    pub fn set_size(&self, size: Size) {
-        let Size { width, height, .. } = size;
-        // TODO Is there ever a need for us to set the x and y?
-        unwrap_state(self.as_ref())
-            .borrow()
-            .xdg_surface
-            .set_window_geometry(0, 0, width as i32, height as i32);
-    }
-
-    /// Set the backing storage of the xdg shell surface.
-    ///
-    /// The contents will not be sent until a wl_surface commit, due to
-    /// Wayland surfaces being double buffered.
-    pub fn set_surface<FD>(&mut self, fd: &FD, size: Size) -> Result<(), ()>
-    where
-        FD: AsRawFd
-    {
-        let buffer = wayland_obj::create_buffer(fd.as_raw_fd(), size)?;
-        let mut state = unwrap_state(self.as_ref()).borrow_mut();
-        state.wl_surface.attach(Some(&buffer), 0, 0);
-        state.size = size;
-        state.buffer = Some(buffer);
-
-        Ok(())
-    }
-
-    #[allow(dead_code)]
-    pub fn commit(&self) {
-        unwrap_state(self.as_ref()).borrow().wl_surface.commit();
-    }
+struct XdgToplevelEventHandler {
+    size: Rc<Cell<Size>>,
+    on_close: Rc<dyn Fn()>
 }
 
-impl Drop for XdgToplevel {
-    fn drop(&mut self) {
-        self.proxy.destroy();
-    }
-}
+impl GlobalImplementor<XdgWmBase> for XdgWmBaseManager {
+    fn new_global(&mut self, new_proxy: NewProxy<XdgWmBase>) -> XdgWmBase {
+        let res = new_proxy.implement(XdgWmBaseEventHandler {}, ());
 
-pub fn xdg_shell_init(new_proxy: NewProxy<XdgWmBase>) -> XdgWmBase {
-    let xwm_base = new_proxy.implement(XdgWmBaseHandler {}, ());
+        XDG_WM_BASE.with(|xdg_wm_base| {
+            *xdg_wm_base.borrow_mut() = Some(res.clone());
+        });
 
-    XDG_SHELL_CREATOR.with(|shell_creator| {
-        *shell_creator.borrow_mut() = Some(xwm_base.clone());
-    });
-
-    xwm_base
+        res
+    }
 }
 
-pub struct XdgWmBaseHandler {}
-
-impl xdg_wm_base::EventHandler for XdgWmBaseHandler {
+impl xdg_wm_base::EventHandler for XdgWmBaseEventHandler {
     fn ping(&mut self, object: XdgWmBase, serial: u32) {
+        event_trace::event(object.as_ref(), "ping", || vec![Arg::Uint(serial.into())]);
+        event_trace::request(object.as_ref(), "pong", || vec![Arg::Uint(serial.into())]);
         object.pong(serial);
     }
 }
 
-pub fn create_xdg_toplevel<G>(geometry: G) -> Result<XdgToplevel, ()>
-where
-    G: Into<Option<Area>>
-{
-    let wl_surface = wayland_obj::create_surface()?;
-    let xdg_surface = create_xdg_surface(&wl_surface)?;
-    if let Some(geometry) = geometry.into() {
-        let Origin { x, y } = geometry.origin;
-        let Size { width, height } = geometry.size;
-        xdg_surface.set_window_geometry(x, y, width as i32, height as i32);
+impl xdg_surface::EventHandler for XdgSurfaceEventHandler {
+    fn configure(&mut self, object: XdgSurface, serial: u32) {
+        event_trace::event(object.as_ref(), "configure", || vec![Arg::Uint(serial.into())]);
+        event_trace::request(object.as_ref(), "ack_configure", || {
+            vec![Arg::Uint(serial.into())]
+        });
+        object.ack_configure(serial);
+        (self.on_configure)(self.size.get());
     }
-
-    xdg_surface
-        .get_toplevel(|new_toplevel| {
-            let state = XdgToplevelState {
-                size: Size::default(),
-                wl_surface: wl_surface.clone(),
-                xdg_surface: xdg_surface.clone(),
-                buffer: None
-            };
-
-            let res = new_toplevel.implement(XdgToplevelHandler {}, RefCell::new(state));
-
-            wl_surface.commit();
-
-            res
-        })
-        .map(|toplevel| XdgToplevel { proxy: toplevel })
 }
 
-struct XdgSurfaceHandler {
-    wlsurface: WlSurface
-}
+impl xdg_toplevel::EventHandler for XdgToplevelEventHandler {
+    fn configure(&mut self, object: xdg_toplevel::XdgToplevel, width: i32, height: i32, _states: Vec<u8>) {
+        event_trace::event(object.as_ref(), "configure", || {
+            vec![Arg::Int(width.into()), Arg::Int(height.into())]
+        });
+        // It's applied with the xdg_surface.configure that follows.
+        self.size.set(Size {
+            width: width.max(0) as u32,
+            height: height.max(0) as u32
+        });
+    }
 
-impl xdg_surface::EventHandler for XdgSurfaceHandler {
-    fn configure(&mut self, object: XdgSurface, serial: u32) {
-        object.ack_configure(serial);
-        self.wlsurface.commit();
+    fn close(&mut self, object: xdg_toplevel::XdgToplevel) {
+        event_trace::event(object.as_ref(), "close", Vec::new);
+        (self.on_close)();
     }
 }
 
-fn create_xdg_surface(surface: &WlSurface) -> Result<XdgSurface, ()> {
-    XDG_SHELL_CREATOR.with(|shell_creator| {
-        let shell_creator = shell_creator.borrow();
-        let shell_creator = shell_creator.as_ref().expect("XDG Shell creator not initilized");
-        shell_creator.get_xdg_surface(surface, |new_proxy| {
-            new_proxy.implement(
-                XdgSurfaceHandler {
-                    wlsurface: surface.clone()
-                },
-                ()
-            )
-        })
-    })
-}
+impl XdgToplevel {
+    /// The xdg_toplevel.
+    pub fn proxy(&self) -> &Proxy<xdg_toplevel::XdgToplevel> {
+        self.toplevel.as_ref()
+    }
 
-impl fmt::Debug for XdgToplevel {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self.proxy.as_ref().c_ptr())
+    /// Destroys the xdg_toplevel and the xdg_surface, in the order the
+    /// protocol wants.
+    pub fn destroy(&self) {
+        event_trace::request(self.toplevel.as_ref(), "destroy", Vec::new);
+        self.toplevel.destroy();
+        event_trace::request(self.xdg_surface.as_ref(), "destroy", Vec::new);
+        self.xdg_surface.destroy();
     }
 }
 
-impl AsRef<Proxy<xdg_toplevel::XdgToplevel>> for XdgToplevel {
-    fn as_ref(&self) -> &Proxy<xdg_toplevel::XdgToplevel> {
-        &self.proxy.as_ref()
-    }
+/// Forgets the xdg shell, when the connection of a test is gone.
+#[cfg(test)]
+pub(super) fn unbind() {
+    XDG_WM_BASE.with(|xdg_wm_base| *xdg_wm_base.borrow_mut() = None);
 }
 
-fn unwrap_state(proxy: &Proxy<xdg_toplevel::XdgToplevel>) -> &RefCell<XdgToplevelState> {
-    proxy
-        .user_data::<RefCell<XdgToplevelState>>()
-        .expect("User data has not been set yet")
+/// Makes `wl_surface` an xdg toplevel.
+///
+/// `on_configure` is called once the toplevel is configured, with the size
+/// the compositor chose or 0 where it left it to the client, and
+/// `on_close` when the compositor asks to close it.
+///
+/// Like a layer surface it's shown once it's committed, configured, and
+/// then committed with a buffer.
+pub fn create_xdg_toplevel(
+    wl_surface: &WlSurface,
+    on_configure: Rc<dyn Fn(Size)>,
+    on_close: Rc<dyn Fn()>
+) -> Result<XdgToplevel, ()> {
+    XDG_WM_BASE.with(|xdg_wm_base| {
+        let xdg_wm_base = xdg_wm_base.borrow();
+        let xdg_wm_base = xdg_wm_base.as_ref().ok_or(())?;
+        let size = Rc::new(Cell::new(Size::default()));
+        let handler = XdgSurfaceEventHandler {
+            size: size.clone(),
+            on_configure
+        };
+        let xdg_surface =
+            xdg_wm_base.get_xdg_surface(wl_surface, |new_proxy| new_proxy.implement(handler, ()))?;
+        event_trace::request(xdg_wm_base.as_ref(), "get_xdg_surface", || {
+            vec![
+                Arg::NewId(event_trace::object(xdg_surface.as_ref())),
+                Arg::Object(Some(event_trace::object(wl_surface.as_ref()))),
+            ]
+        });
+        let handler = XdgToplevelEventHandler { size, on_close };
+        let toplevel = xdg_surface.get_toplevel(|new_proxy| new_proxy.implement(handler, ()))?;
+        event_trace::request(xdg_surface.as_ref(), "get_toplevel", || {
+            vec![Arg::NewId(event_trace::object(toplevel.as_ref()))]
+        });
+        event_trace::request(toplevel.as_ref(), "set_app_id", || vec![Arg::Str(APP_ID.into())]);
+        toplevel.set_app_id(APP_ID.into());
+        Ok(XdgToplevel {
+            xdg_surface,
+            toplevel
+        })
+    })
 }
@@ -29,6 +29,17 @@ if awesome.startup_errors then
                      text = awesome.startup_errors })
 end
 
+-- Tell where the report is if the last session crashed
+do
+    local report = awesome.last_crash_report and awesome.last_crash_report()
+    if report then
+        naughty.notify({ preset = naughty.config.presets.critical,
+                         title = "The last session crashed",
+                         text = (report.message or report.reason) ..
+                                "\nThe crash report is in " .. report.path })
+    end
+end
+
 -- Handle runtime errors after startup
 do
     local in_error = false